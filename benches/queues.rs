@@ -0,0 +1,119 @@
+// benches/queues.rs
+//
+// Frames of 100 streams at 240 fps handed from their reader tasks to one
+// writer, through a tokio mpsc channel and through the per-stream rings of
+// pmu::queue. First a paced second of traffic for each, printing the
+// latency percentiles from push to pop, then the throughput of both.
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, Criterion};
+use pmu::queue::Rings;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+
+const STREAMS: usize = 100;
+const RATE: u64 = 240;
+const FRAME_SIZE: usize = 52;
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn frame() -> Bytes {
+    Bytes::from(vec![0xAA; FRAME_SIZE])
+}
+
+// Frames per stream, every 1/RATE s when paced or as fast as possible.
+async fn mpsc_handoff(frames: u64, paced: bool) -> Vec<Duration> {
+    let (tx, mut rx) = mpsc::channel::<(Instant, Bytes)>(1024 * STREAMS);
+    for _ in 0..STREAMS {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_micros(1_000_000 / RATE));
+            for _ in 0..frames {
+                if paced {
+                    interval.tick().await;
+                }
+                if tx.send((Instant::now(), frame())).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    drop(tx);
+    let mut latencies = Vec::with_capacity(STREAMS * frames as usize);
+    while let Some((sent, _)) = rx.recv().await {
+        latencies.push(sent.elapsed());
+    }
+    latencies
+}
+
+async fn ring_handoff(frames: u64, paced: bool) -> Vec<Duration> {
+    let mut rings = Rings::<(Instant, Bytes)>::new();
+    for _ in 0..STREAMS {
+        let mut producer = rings.add(1024);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_micros(1_000_000 / RATE));
+            for _ in 0..frames {
+                if paced {
+                    interval.tick().await;
+                }
+                while producer.slots() == 0 {
+                    tokio::task::yield_now().await;
+                }
+                producer.push((Instant::now(), frame()));
+            }
+        });
+    }
+    let mut latencies = Vec::with_capacity(STREAMS * frames as usize);
+    while let Some((sent, _)) = rings.pop().await {
+        latencies.push(sent.elapsed());
+    }
+    latencies
+}
+
+fn report(name: &str, mut latencies: Vec<Duration>) {
+    latencies.sort();
+    let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+    println!(
+        "{}: {} frames, p50 {:?}, p99 {:?}, p99.9 {:?}, max {:?}",
+        name,
+        latencies.len(),
+        percentile(0.5),
+        percentile(0.99),
+        percentile(0.999),
+        latencies[latencies.len() - 1]
+    );
+}
+
+fn benchmark_tail_latency(_c: &mut Criterion) {
+    let runtime = runtime();
+    report(
+        "mpsc, 100 streams at 240 fps",
+        runtime.block_on(mpsc_handoff(RATE, true)),
+    );
+    report(
+        "rings, 100 streams at 240 fps",
+        runtime.block_on(ring_handoff(RATE, true)),
+    );
+}
+
+fn benchmark_throughput(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("handoff_100_streams_1s");
+    group.sample_size(20);
+    group.bench_function("mpsc", |b| {
+        b.iter(|| runtime.block_on(mpsc_handoff(RATE, false)))
+    });
+    group.bench_function("rings", |b| {
+        b.iter(|| runtime.block_on(ring_handoff(RATE, false)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_tail_latency, benchmark_throughput);
+criterion_main!(benches);
@@ -0,0 +1,792 @@
+// Collects raw data frames per stream and turns them into Arrow RecordBatches.
+//
+// Frames are kept in their wire format until a flush, which keeps the
+// per-row cost equal to the data frame size. A MemoryBudget bounds how much
+// each stream (and all streams together) may hold before a flush is forced.
+//
+// Optionally, short gaps in a stream are filled with synthesized frames
+// (previous values held, or interpolated) and frames with a bad CHK are kept
+// (lenient parsing). Either adds a quality column to every batch of the
+// stream, flagging the rows that were synthesized or not verified. Streams of
+// devices computing CHK their own way are checked with their CrcMode, and
+// the frames failing the check are counted in CrcStats. Salvage keeps only
+// the frames with a bad CHK whose structure still looks sane.
+//
+// Frames whose size or FRAMESIZE disagrees with the configuration are
+// rejected, or repaired for streams with a lenient FrameSizePolicy
+// (framesize), and counted in FrameSizeStats.
+//
+// In the long layout, sparse channels (arrow_utils::SparseChannels) only get
+// a row when their value changes since the last row given, in this batch or
+// an earlier one of the stream.
+//
+// A FlushPolicy decides how large batches get: after a number of rows or
+// bytes, after some wall time, or at timestamp boundaries. Sinks that want
+// different batch sizes are fed by accumulators with their own policy.
+//
+// Row timestamps are taken at the stream's TIME_BASE and kept increasing
+// across the SOC rollover of 2106. A frame whose FRACSEC wrapped to 0 before
+// its SOC was incremented, a second behind the frame expected next, gets the
+// expected timestamp; such repairs are counted per stream.
+use crate::arrow_utils::{
+    append_quality_column, build_record_batch_at, drop_unchanged, frame_timestamp_micros_with,
+    to_long_format, unwrap_soc_rollover, ArrowLayout, ArrowOptions, SparseState, QUALITY_BAD_CRC,
+    QUALITY_HELD, QUALITY_INTERPOLATED,
+};
+use crate::budget::{total_usage, MemoryBudget, MemoryUsage};
+use crate::frames::{
+    calculate_crc, ChannelDataType, ChannelInfo, ConfigurationFrame1and2_2011, CrcMode,
+};
+use crate::framesize::{
+    check_frame_size, repair_frame_size, FrameSizeIssue, FrameSizePolicy, FrameSizeStats,
+};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::f64::consts::PI;
+use std::time::{Duration, Instant};
+
+// Farthest a salvaged frame's timestamp may be from the previous frame's.
+const MAX_SALVAGE_JUMP_US: i64 = 60_000_000;
+
+#[derive(Debug)]
+pub enum AccumulatorError {
+    UnknownStream(u16),
+    InvalidCrc(u16),
+    InvalidFrameSize { expected: usize, actual: usize },
+    Arrow(ArrowError),
+}
+
+impl From<ArrowError> for AccumulatorError {
+    fn from(e: ArrowError) -> Self {
+        AccumulatorError::Arrow(e)
+    }
+}
+
+// CHK checks of a stream's frames.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CrcStats {
+    pub checked: u64,
+    pub mismatches: u64,
+    pub passed_by: BTreeMap<CrcMode, u64>, // Mismatched frames another mode passes
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapFill {
+    Hold,        // Repeat the frame before the gap
+    Interpolate, // Linear between the frames around the gap, digitals held
+}
+
+// When a stream's buffered rows are turned into a batch. Any limit that is
+// reached triggers the flush, no limit set means batches are only flushed
+// explicitly or by the memory budget.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlushPolicy {
+    pub max_rows: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub max_age: Option<Duration>, // Wall time since the first row of the batch
+    pub boundary: Option<Duration>, // Batches never span a multiple of this in UTC (e.g. each minute)
+}
+
+impl FlushPolicy {
+    pub fn with_max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = Some(max_rows);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_boundary(mut self, boundary: Duration) -> Self {
+        self.boundary = Some(boundary);
+        self
+    }
+
+    fn is_full(&self, usage: &MemoryUsage) -> bool {
+        MemoryBudget {
+            max_bytes: self.max_bytes,
+            max_rows: self.max_rows,
+        }
+        .is_reached(usage)
+    }
+
+    fn is_expired(&self, started: Option<Instant>, now: Instant) -> bool {
+        match (self.max_age, started) {
+            (Some(max_age), Some(started)) => now.duration_since(started) >= max_age,
+            _ => false,
+        }
+    }
+
+    // True when a frame at timestamp_us belongs after the boundary following first_us.
+    fn crosses_boundary(&self, first_us: Option<i64>, timestamp_us: i64) -> bool {
+        let (Some(boundary), Some(first_us)) = (self.boundary, first_us) else {
+            return false;
+        };
+        let boundary_us = (boundary.as_micros() as i64).max(1);
+        timestamp_us.div_euclid(boundary_us) > first_us.div_euclid(boundary_us)
+    }
+}
+
+struct StreamBuffer {
+    channel_map: HashMap<String, ChannelInfo>,
+    options: ArrowOptions,
+    interpolator: FrameInterpolator,
+    frame_size: usize,
+    time_base: u32,
+    period_us: f64,
+    frames: Vec<u8>,
+    rows: usize,
+    timestamps: Vec<i64>,         // Per row
+    newest_us: Option<i64>,       // Newest timestamp pushed, kept across batches
+    time_repairs: u64,            // Frames given the timestamp expected at a FRACSEC wrap
+    first_us: Option<i64>,        // Timestamp of the first buffered frame
+    started: Option<Instant>,     // When the first buffered frame was pushed
+    quality: Option<Vec<u8>>,     // Per row, when quality is tracked
+    last: Option<(i64, Vec<u8>)>, // Timestamp and bytes of the newest frame
+    sparse: SparseState,          // Last rows of the sparse channels, long layout
+}
+
+impl StreamBuffer {
+    fn usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            rows: self.rows,
+            bytes: self.frames.len(),
+        }
+    }
+
+    fn take_batch(&mut self) -> Result<Option<RecordBatch>, AccumulatorError> {
+        if self.rows == 0 {
+            return Ok(None);
+        }
+        // The quality column is added to the wide batch before it is reshaped
+        let wide = self.options.clone().with_layout(ArrowLayout::Wide);
+        let timestamps = std::mem::take(&mut self.timestamps);
+        let mut batch = build_record_batch_at(
+            &self.frames,
+            self.frame_size,
+            &self.channel_map,
+            &wide,
+            timestamps,
+        )?;
+        if let Some(quality) = self.quality.as_mut() {
+            batch = append_quality_column(&batch, quality)?;
+            quality.clear();
+        }
+        if self.options.layout == ArrowLayout::Long {
+            batch = to_long_format(&batch)?;
+            if let Some(sparse) = &self.options.sparse {
+                batch = drop_unchanged(&batch, sparse, &mut self.sparse)?;
+            }
+        }
+        self.frames.clear();
+        self.rows = 0;
+        self.first_us = None;
+        self.started = None;
+        // Sparse channels alone may leave nothing to write
+        Ok((batch.num_rows() > 0).then_some(batch))
+    }
+
+    fn push(&mut self, frame: &[u8], quality: u8, timestamp_us: i64) {
+        if self.rows == 0 {
+            self.first_us = Some(timestamp_us);
+            self.started = Some(Instant::now());
+        }
+        self.frames.extend_from_slice(frame);
+        self.timestamps.push(timestamp_us);
+        self.newest_us = Some(self.newest_us.map_or(timestamp_us, |n| n.max(timestamp_us)));
+        self.rows += 1;
+        if let Some(flags) = self.quality.as_mut() {
+            flags.push(quality);
+        }
+    }
+
+    // Timestamp of a frame, past the SOC rollover when the newest one is.
+    fn timestamp_us(&self, frame: &[u8]) -> i64 {
+        let timestamp_us = frame_timestamp_micros_with(frame, self.time_base).unwrap_or_default();
+        self.newest_us.map_or(timestamp_us, |newest| {
+            unwrap_soc_rollover(timestamp_us, newest)
+        })
+    }
+
+    // Timestamp of the row of a frame. A frame at the start of a second that
+    // is a second behind the frame expected next had its FRACSEC wrap to 0
+    // without SOC being carried, and is given the expected timestamp.
+    fn row_timestamp_us(&mut self, frame: &[u8]) -> i64 {
+        let timestamp_us = self.timestamp_us(frame);
+        let Some(newest_us) = self.newest_us else {
+            return timestamp_us;
+        };
+        let period_us = self.period_us.round() as i64;
+        let expected_us = newest_us + period_us;
+        let at_wrap = timestamp_us.rem_euclid(1_000_000) < (period_us / 2).max(1);
+        if at_wrap && (timestamp_us + 1_000_000 - expected_us).abs() <= period_us / 2 {
+            self.time_repairs += 1;
+            return expected_us;
+        }
+        timestamp_us
+    }
+
+    // Insert frames for the reporting instants missing between the previous
+    // frame and this one, up to max_frames of them.
+    fn fill_gap(&mut self, frame: &[u8], timestamp_us: i64, mode: GapFill, max_frames: usize) {
+        let Some((last_us, last)) = self.last.take() else {
+            return;
+        };
+        let missing = ((timestamp_us - last_us) as f64 / self.period_us).round() as i64 - 1;
+        if missing >= 1 && missing as usize <= max_frames {
+            for k in 1..=missing {
+                let fraction = k as f64 / (missing + 1) as f64;
+                let mut filled = match mode {
+                    GapFill::Hold => last.clone(),
+                    GapFill::Interpolate => self.interpolator.interpolate(&last, frame, fraction),
+                };
+                let filled_us = last_us + (k as f64 * self.period_us).round() as i64;
+                self.interpolator.set_timestamp(&mut filled, filled_us);
+                let flag = match mode {
+                    GapFill::Hold => QUALITY_HELD,
+                    GapFill::Interpolate => QUALITY_INTERPOLATED,
+                };
+                self.push(&filled, flag, filled_us);
+            }
+        }
+        self.last = Some((last_us, last));
+    }
+
+    // Whether a data frame with a bad CHK can still be trusted to be one:
+    // SYNC, frame type and FRAMESIZE as configured, FRACSEC within the time
+    // base, a timestamp near the previous frame's and finite float values.
+    fn looks_sane(&self, frame: &[u8]) -> bool {
+        if frame[0] != 0xAA
+            || (frame[1] >> 4) & 0x07 != 0
+            || u16::from_be_bytes([frame[2], frame[3]]) as usize != self.frame_size
+        {
+            return false;
+        }
+        let fracsec = u32::from_be_bytes([frame[10], frame[11], frame[12], frame[13]]);
+        if fracsec & 0x00FF_FFFF >= self.time_base.max(1) {
+            return false;
+        }
+        let timestamp_us = self.timestamp_us(frame);
+        if self
+            .last
+            .as_ref()
+            .is_some_and(|(last_us, _)| (timestamp_us - last_us).abs() > MAX_SALVAGE_JUMP_US)
+        {
+            return false;
+        }
+        self.channel_map.values().all(|info| match info.data_type {
+            ChannelDataType::PhasorFloat => {
+                read_f32(frame, info.offset).is_finite()
+                    && read_f32(frame, info.offset + 4).is_finite()
+            }
+            ChannelDataType::AnalogFloat
+            | ChannelDataType::FreqFloat
+            | ChannelDataType::DfreqFloat => read_f32(frame, info.offset).is_finite(),
+            _ => true,
+        })
+    }
+}
+
+// Synthesizes data frames of a stream between two received ones, for gap
+// filling and frame rate conversion.
+#[derive(Debug, Clone)]
+pub struct FrameInterpolator {
+    channel_map: HashMap<String, ChannelInfo>,
+    polar_offsets: HashSet<usize>, // Offsets of polar phasor channels
+    time_base: u32,
+}
+
+impl FrameInterpolator {
+    pub fn new(config: &ConfigurationFrame1and2_2011) -> Self {
+        let channel_map = config.get_channel_map();
+        let polar_offsets = channel_map
+            .values()
+            .filter(|info| {
+                info.polar
+                    && matches!(
+                        info.data_type,
+                        ChannelDataType::PhasorFixed | ChannelDataType::PhasorFloat
+                    )
+            })
+            .map(|info| info.offset)
+            .collect();
+        FrameInterpolator {
+            channel_map,
+            polar_offsets,
+            time_base: config.time_base,
+        }
+    }
+
+    // Values a fraction of the way from frame a to frame b. STAT and digital
+    // words are taken from a.
+    pub fn interpolate(&self, a: &[u8], b: &[u8], fraction: f64) -> Vec<u8> {
+        let mut frame = a.to_vec();
+        let lerp = |x: f64, y: f64| x + (y - x) * fraction;
+        for info in self.channel_map.values() {
+            let offset = info.offset;
+            let polar = self.polar_offsets.contains(&offset);
+            match info.data_type {
+                ChannelDataType::PhasorFloat => {
+                    let x = read_f32(a, offset);
+                    let y = read_f32(b, offset);
+                    write_f32(&mut frame, offset, lerp(x, y));
+                    let x = read_f32(a, offset + 4);
+                    let y = read_f32(b, offset + 4);
+                    let angle = if polar {
+                        x + shortest_turn(x, y, PI) * fraction
+                    } else {
+                        lerp(x, y)
+                    };
+                    write_f32(&mut frame, offset + 4, angle);
+                }
+                ChannelDataType::PhasorFixed => {
+                    let x = read_i16(a, offset);
+                    let y = read_i16(b, offset);
+                    if polar {
+                        // Unsigned magnitude, angle in radians x 10^4
+                        let x = u16::from_be_bytes([a[offset], a[offset + 1]]) as f64;
+                        let y = u16::from_be_bytes([b[offset], b[offset + 1]]) as f64;
+                        let magnitude = lerp(x, y).round() as u16;
+                        frame[offset..offset + 2].copy_from_slice(&magnitude.to_be_bytes());
+                        let x = read_i16(a, offset + 2);
+                        let y = read_i16(b, offset + 2);
+                        let angle = x + shortest_turn(x, y, PI * 10_000.0) * fraction;
+                        let angle =
+                            (angle + PI * 10_000.0).rem_euclid(2.0 * PI * 10_000.0) - PI * 10_000.0;
+                        write_i16(&mut frame, offset + 2, angle);
+                    } else {
+                        write_i16(&mut frame, offset, lerp(x, y));
+                        let x = read_i16(a, offset + 2);
+                        let y = read_i16(b, offset + 2);
+                        write_i16(&mut frame, offset + 2, lerp(x, y));
+                    }
+                }
+                ChannelDataType::AnalogFloat
+                | ChannelDataType::FreqFloat
+                | ChannelDataType::DfreqFloat => {
+                    write_f32(
+                        &mut frame,
+                        offset,
+                        lerp(read_f32(a, offset), read_f32(b, offset)),
+                    );
+                }
+                ChannelDataType::AnalogFixed
+                | ChannelDataType::FreqFixed
+                | ChannelDataType::DfreqFixed => {
+                    write_i16(
+                        &mut frame,
+                        offset,
+                        lerp(read_i16(a, offset), read_i16(b, offset)),
+                    );
+                }
+                ChannelDataType::Digital | ChannelDataType::Stat => {}
+            }
+        }
+        frame
+    }
+
+    // Rewrite SOC/FRACSEC, keeping the time quality flags, and the CHK.
+    pub fn set_timestamp(&self, frame: &mut [u8], timestamp_us: i64) {
+        let soc = timestamp_us.div_euclid(1_000_000) as u32;
+        let fraction = timestamp_us.rem_euclid(1_000_000) as u64;
+        let fracsec =
+            ((fraction * self.time_base as u64 + 500_000) / 1_000_000) as u32 & 0x00FF_FFFF;
+        let flags = u32::from_be_bytes([frame[10], frame[11], frame[12], frame[13]]) & 0xFF00_0000;
+        frame[6..10].copy_from_slice(&soc.to_be_bytes());
+        frame[10..14].copy_from_slice(&(flags | fracsec).to_be_bytes());
+        let len = frame.len();
+        let crc = calculate_crc(&frame[..len - 2]);
+        frame[len - 2..].copy_from_slice(&crc.to_be_bytes());
+    }
+}
+
+fn log_crc_mismatches(idcode: u16, mode: CrcMode, stats: &CrcStats) {
+    let passed: Vec<String> = stats
+        .passed_by
+        .iter()
+        .map(|(mode, count)| format!("{} pass {:?}", count, mode))
+        .collect();
+    println!(
+        "Stream {}: {} of {} frames failed the {:?} CHK check{}{}",
+        idcode,
+        stats.mismatches,
+        stats.checked,
+        mode,
+        if passed.is_empty() { "" } else { ", " },
+        passed.join(", ")
+    );
+}
+
+fn log_frame_size_issue(idcode: u16, issue: &FrameSizeIssue, stats: &FrameSizeStats) {
+    println!(
+        "Stream {}: {} frames of the wrong size, the last {}",
+        idcode,
+        stats.total(),
+        issue
+    );
+}
+
+fn read_f32(frame: &[u8], offset: usize) -> f64 {
+    f32::from_be_bytes(frame[offset..offset + 4].try_into().unwrap()) as f64
+}
+
+fn write_f32(frame: &mut [u8], offset: usize, value: f64) {
+    frame[offset..offset + 4].copy_from_slice(&(value as f32).to_be_bytes());
+}
+
+fn read_i16(frame: &[u8], offset: usize) -> f64 {
+    i16::from_be_bytes([frame[offset], frame[offset + 1]]) as f64
+}
+
+fn write_i16(frame: &mut [u8], offset: usize, value: f64) {
+    let value = value.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+    frame[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+// Change from angle x to angle y the short way round, half_turn being pi in the angle's units.
+fn shortest_turn(x: f64, y: f64, half_turn: f64) -> f64 {
+    (y - x + half_turn).rem_euclid(2.0 * half_turn) - half_turn
+}
+
+pub struct BatchAccumulator {
+    streams: HashMap<u16, StreamBuffer>,
+    stream_budget: MemoryBudget, // Applied to each stream individually
+    total_budget: MemoryBudget,  // Applied to the sum of all streams
+    gap_fill: Option<(GapFill, usize)>,
+    lenient: bool,
+    salvage: bool,
+    crc_modes: HashMap<u16, (CrcMode, bool)>, // Check and leniency of single streams
+    crc_stats: HashMap<u16, CrcStats>,
+    frame_size_policies: HashMap<u16, FrameSizePolicy>, // Streams not strict
+    frame_size_stats: HashMap<u16, FrameSizeStats>,
+    options: ArrowOptions,
+    flush_policy: FlushPolicy,
+}
+
+impl BatchAccumulator {
+    pub fn new(stream_budget: MemoryBudget) -> Self {
+        Self {
+            streams: HashMap::new(),
+            stream_budget,
+            total_budget: MemoryBudget::unlimited(),
+            gap_fill: None,
+            lenient: false,
+            salvage: false,
+            crc_modes: HashMap::new(),
+            crc_stats: HashMap::new(),
+            frame_size_policies: HashMap::new(),
+            frame_size_stats: HashMap::new(),
+            options: ArrowOptions::default(),
+            flush_policy: FlushPolicy::default(),
+        }
+    }
+
+    pub fn with_total_budget(mut self, total_budget: MemoryBudget) -> Self {
+        self.total_budget = total_budget;
+        self
+    }
+
+    // Fill gaps of up to max_frames missing frames. Longer gaps (e.g. an outage)
+    // are left as they are.
+    pub fn with_gap_fill(mut self, mode: GapFill, max_frames: usize) -> Self {
+        self.gap_fill = Some((mode, max_frames));
+        self
+    }
+
+    // Keep frames whose CHK does not match instead of rejecting them, flagged
+    // with QUALITY_BAD_CRC.
+    pub fn with_lenient_parsing(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    // Keep the frames with a bad CHK that still look like frames of their
+    // stream, flagged with QUALITY_BAD_CRC, and reject the others. Lenient
+    // parsing keeps them all.
+    pub fn with_salvage(mut self, salvage: bool) -> Self {
+        self.salvage = salvage;
+        self
+    }
+
+    // Check the CHK of a stream's frames with mode, keeping the frames that
+    // fail it when lenient, instead of the standard check and the lenient
+    // setting of all streams. The stream's frames are always checked, call
+    // this before add_stream.
+    pub fn set_crc_mode(&mut self, idcode: u16, mode: CrcMode, lenient: bool) {
+        self.crc_modes.insert(idcode, (mode, lenient));
+    }
+
+    pub fn crc_stats(&self, idcode: u16) -> Option<&CrcStats> {
+        self.crc_stats.get(&idcode)
+    }
+
+    // Repair or reject the frames of a stream whose size or FRAMESIZE is not
+    // the configured one. Streams are strict unless set otherwise.
+    pub fn set_frame_size_policy(&mut self, idcode: u16, policy: FrameSizePolicy) {
+        self.frame_size_policies.insert(idcode, policy);
+    }
+
+    pub fn frame_size_stats(&self, idcode: u16) -> Option<&FrameSizeStats> {
+        self.frame_size_stats.get(&idcode)
+    }
+
+    // Frames of a stream given the expected timestamp at a FRACSEC wrap.
+    pub fn time_repairs(&self, idcode: u16) -> u64 {
+        self.streams
+            .get(&idcode)
+            .map_or(0, |stream| stream.time_repairs)
+    }
+
+    // Layout of the batches of streams added after this call.
+    pub fn with_arrow_options(mut self, options: ArrowOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_flush_policy(mut self, flush_policy: FlushPolicy) -> Self {
+        self.flush_policy = flush_policy;
+        self
+    }
+
+    fn tracks_quality(&self) -> bool {
+        self.gap_fill.is_some() || self.lenient || self.salvage
+    }
+
+    // Register (or replace) a stream using its configuration frame.
+    // Any rows buffered under a previous configuration are discarded.
+    pub fn add_stream(&mut self, config: &ConfigurationFrame1and2_2011) {
+        let channel_map = config.get_channel_map();
+        let period_us = if config.data_rate > 0 {
+            1_000_000.0 / config.data_rate as f64
+        } else {
+            -(config.data_rate as f64) * 1_000_000.0
+        };
+        self.streams.insert(
+            config.prefix.idcode,
+            StreamBuffer {
+                channel_map,
+                options: self.options.clone(),
+                interpolator: FrameInterpolator::new(config),
+                frame_size: config.calc_data_frame_size(),
+                time_base: config.time_base,
+                period_us,
+                frames: Vec::new(),
+                rows: 0,
+                timestamps: Vec::new(),
+                newest_us: None,
+                time_repairs: 0,
+                first_us: None,
+                started: None,
+                quality: (self.tracks_quality()
+                    || self.crc_modes.contains_key(&config.prefix.idcode))
+                .then(Vec::new),
+                last: None,
+                sparse: SparseState::default(),
+            },
+        );
+    }
+
+    pub fn remove_stream(&mut self, idcode: u16) -> Result<Option<RecordBatch>, AccumulatorError> {
+        match self.streams.remove(&idcode) {
+            Some(mut stream) => stream.take_batch(),
+            None => Err(AccumulatorError::UnknownStream(idcode)),
+        }
+    }
+
+    // Append a raw data frame to its stream, the stream is taken from the frame IDCODE.
+    // A frame not of the configured size, or with another FRAMESIZE and a CHK
+    // that checks, is rejected with InvalidFrameSize unless the stream's
+    // policy is lenient and the frame can be repaired.
+    // When gaps are filled the CHK is verified too, frames with a bad one are
+    // rejected unless parsing is lenient.
+    // Returns a flushed batch when adding the frame reached a memory budget
+    // or the flush policy. When the total budget is reached the largest stream
+    // is flushed, which is not necessarily the stream of this frame.
+    // A frame past a timestamp boundary flushes the rows before it and starts
+    // the next batch. Frames filled in for a gap across the boundary go with
+    // the frame after the gap.
+    pub fn push_frame(
+        &mut self,
+        frame: &[u8],
+    ) -> Result<Option<(u16, RecordBatch)>, AccumulatorError> {
+        if frame.len() < 6 {
+            return Err(AccumulatorError::InvalidFrameSize {
+                expected: 6,
+                actual: frame.len(),
+            });
+        }
+        let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+        let stream = self
+            .streams
+            .get_mut(&idcode)
+            .ok_or(AccumulatorError::UnknownStream(idcode))?;
+
+        let mode = self
+            .crc_modes
+            .get(&idcode)
+            .map_or(CrcMode::Standard, |(mode, _)| *mode);
+        let frame = match check_frame_size(frame, stream.frame_size) {
+            None => Cow::Borrowed(frame),
+            // A FRAMESIZE failing CHK too is damage, left to the CHK check
+            Some(FrameSizeIssue::DeclaredMismatch { .. }) if !mode.check(frame) => {
+                Cow::Borrowed(frame)
+            }
+            Some(issue) => {
+                let stats = self.frame_size_stats.entry(idcode).or_default();
+                stats.count(&issue);
+                // Logged at 1, 10, 100, ... frames
+                if stats.total() == 10u64.pow(stats.total().ilog10()) {
+                    log_frame_size_issue(idcode, &issue, stats);
+                }
+                let repaired = match self.frame_size_policies.get(&idcode) {
+                    Some(FrameSizePolicy::Lenient) => {
+                        repair_frame_size(frame, stream.frame_size, mode)
+                    }
+                    _ => None,
+                };
+                match repaired {
+                    Some(repaired) => {
+                        stats.repaired += 1;
+                        Cow::Owned(repaired)
+                    }
+                    None => {
+                        let actual = match issue {
+                            FrameSizeIssue::DeclaredMismatch { declared, .. } => declared,
+                            _ => frame.len(),
+                        };
+                        return Err(AccumulatorError::InvalidFrameSize {
+                            expected: stream.frame_size,
+                            actual,
+                        });
+                    }
+                }
+            }
+        };
+        let frame = frame.as_ref();
+        let timestamp_us = stream.row_timestamp_us(frame);
+        let mut ready = None;
+        if self
+            .flush_policy
+            .crosses_boundary(stream.first_us, timestamp_us)
+        {
+            ready = stream.take_batch()?;
+        }
+        let mut quality = 0;
+        if stream.quality.is_some() {
+            let (mode, lenient) = self
+                .crc_modes
+                .get(&idcode)
+                .copied()
+                .unwrap_or((CrcMode::Standard, self.lenient));
+            let stats = self.crc_stats.entry(idcode).or_default();
+            if mode != CrcMode::Skip {
+                stats.checked += 1;
+            }
+            if !mode.check(frame) {
+                stats.mismatches += 1;
+                if let Some(other) = CrcMode::detect(frame) {
+                    *stats.passed_by.entry(other).or_default() += 1;
+                }
+                // Logged at 1, 10, 100, ... mismatches
+                if stats.mismatches == 10u64.pow(stats.mismatches.ilog10()) {
+                    log_crc_mismatches(idcode, mode, stats);
+                }
+                let salvaged = self.salvage && stream.looks_sane(frame);
+                if !lenient && !salvaged {
+                    return Err(AccumulatorError::InvalidCrc(idcode));
+                }
+                quality |= QUALITY_BAD_CRC;
+            }
+            if let Some((mode, max_frames)) = self.gap_fill {
+                stream.fill_gap(frame, timestamp_us, mode, max_frames);
+            }
+            // Out of order frames are not used as the gap reference
+            if stream
+                .last
+                .as_ref()
+                .is_none_or(|(last_us, _)| timestamp_us > *last_us)
+            {
+                stream.last = Some((timestamp_us, frame.to_vec()));
+            }
+        }
+        stream.push(frame, quality, timestamp_us);
+
+        if let Some(batch) = ready {
+            return Ok(Some((idcode, batch)));
+        }
+        if self.stream_budget.is_reached(&stream.usage())
+            || self.flush_policy.is_full(&stream.usage())
+            || self.flush_policy.is_expired(stream.started, Instant::now())
+        {
+            return Ok(stream.take_batch()?.map(|batch| (idcode, batch)));
+        }
+
+        if self.total_budget.is_reached(&self.total_usage()) {
+            let largest = self
+                .streams
+                .iter()
+                .max_by_key(|(_, stream)| stream.frames.len())
+                .map(|(idcode, _)| *idcode);
+            if let Some(largest) = largest {
+                return self.flush(largest).map(|batch| batch.map(|b| (largest, b)));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn flush(&mut self, idcode: u16) -> Result<Option<RecordBatch>, AccumulatorError> {
+        self.streams
+            .get_mut(&idcode)
+            .ok_or(AccumulatorError::UnknownStream(idcode))?
+            .take_batch()
+    }
+
+    // Flush the streams whose batch is older than the policy's max_age. Streams
+    // that stop receiving frames are only flushed on age by calling this
+    // periodically.
+    pub fn flush_expired(&mut self) -> Result<Vec<(u16, RecordBatch)>, AccumulatorError> {
+        let now = Instant::now();
+        let mut batches = Vec::new();
+        for (idcode, stream) in self.streams.iter_mut() {
+            if self.flush_policy.is_expired(stream.started, now) {
+                if let Some(batch) = stream.take_batch()? {
+                    batches.push((*idcode, batch));
+                }
+            }
+        }
+        Ok(batches)
+    }
+
+    pub fn flush_all(&mut self) -> Result<Vec<(u16, RecordBatch)>, AccumulatorError> {
+        let mut batches = Vec::new();
+        for (idcode, stream) in self.streams.iter_mut() {
+            if let Some(batch) = stream.take_batch()? {
+                batches.push((*idcode, batch));
+            }
+        }
+        Ok(batches)
+    }
+
+    pub fn usage(&self, idcode: u16) -> Option<MemoryUsage> {
+        self.streams.get(&idcode).map(|stream| stream.usage())
+    }
+
+    pub fn usage_by_stream(&self) -> HashMap<u16, MemoryUsage> {
+        self.streams
+            .iter()
+            .map(|(idcode, stream)| (*idcode, stream.usage()))
+            .collect()
+    }
+
+    pub fn total_usage(&self) -> MemoryUsage {
+        total_usage(&self.usage_by_stream())
+    }
+}
@@ -0,0 +1,777 @@
+// Time alignment of several PMU streams, as a PDC does.
+//
+// Frames of the registered streams are grouped by timestamp. A group is
+// emitted once every stream has delivered its frame, or once the watermark
+// (newest timestamp seen minus the allowed lateness) has passed it, in which
+// case the streams that did not make it are missing from the group.
+//
+// A frame whose group has already been emitted is late. It is never added to
+// a later group; it goes to the LateData handler instead, which drops it,
+// writes it to a separate sink or patches it into a historian.
+//
+// checkpoint() and restore() carry the streams and the groups still waiting
+// across a restart, so they are completed rather than emitted partial.
+//
+// Streams registered with add_stream_from() are tied to their source. Two
+// sources presenting the same IDCODE with the same configuration are taken as
+// redundant feeds of one stream: a frame repeated by the second source is
+// dropped. A different configuration, or different data for the same
+// timestamp, is a conflict that the ConflictPolicy resolves instead of the
+// streams being silently merged. Sources and the resolutions are not part of
+// a checkpoint, they are made again when the sources register after restore.
+//
+// With with_arrival_fallback a stream whose SOC has not advanced for the
+// given time (longer than a second) is taken to have a stuck clock and is
+// aligned by arrival time instead, less the latency it had while its clock
+// worked, to the nearest frame of its rate. Its frames are rewritten with
+// that time and downgraded: STAT gets the PMU sync error and sorted by
+// arrival bits, the time quality of FRACSEC says clock failure. Frames until
+// the clock is found stuck keep their own time, and are usually late. Once
+// the SOC moves again the stream is aligned by its timestamps.
+use crate::arrow_utils::{build_record_batch, frame_timestamp_micros_with, unwrap_soc_rollover};
+use crate::checkpoint::{AggregatorState, Frame};
+use crate::frames::{calculate_crc, ChannelDataType, ChannelInfo, ConfigurationFrame1and2_2011};
+use crate::historian::{Historian, HistorianError};
+use crate::latency::now_micros;
+use crate::remap::set_idcode;
+use crate::sinks::BatchSink;
+use arrow::error::ArrowError;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum AggregatorError {
+    UnknownStream(u16),
+    InvalidFrameSize { expected: usize, actual: usize },
+    Arrow(ArrowError),
+    Io(io::Error),
+    Historian(HistorianError),
+    Conflict(IdcodeConflict),
+}
+
+impl From<ArrowError> for AggregatorError {
+    fn from(e: ArrowError) -> Self {
+        AggregatorError::Arrow(e)
+    }
+}
+
+impl From<io::Error> for AggregatorError {
+    fn from(e: io::Error) -> Self {
+        AggregatorError::Io(e)
+    }
+}
+
+impl From<HistorianError> for AggregatorError {
+    fn from(e: HistorianError) -> Self {
+        AggregatorError::Historian(e)
+    }
+}
+
+// What happens to frames that arrive after their group was emitted.
+pub enum LateData {
+    Drop,
+    Sink(Box<dyn BatchSink + Send>), // One single row batch per late frame
+    Historian(Arc<Mutex<Historian>>), // Stored in timestamp order with Historian::patch
+}
+
+// What happens when a second source presents an IDCODE that is taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    #[default]
+    PreferFirst, // The first source keeps the IDCODE, frames of the other are dropped
+    Reject, // As PreferFirst, but the conflict is returned as an error
+    // The other source becomes a stream of its own under a free IDCODE, with
+    // "_<n>" appended to its station names to keep the column names apart
+    Suffix,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    Config, // Different configuration
+    Data,   // Same configuration, different data for the same timestamp
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdcodeConflict {
+    pub idcode: u16,
+    pub source: String, // The source that lost the IDCODE
+    pub kind: ConflictKind,
+    pub assigned: Option<u16>, // IDCODE the source is aggregated under, Suffix only
+    pub dropped: u64,          // Frames of the source dropped since
+}
+
+// Frames of all streams for one timestamp, by IDCODE.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlignedFrames {
+    pub timestamp_us: i64,
+    pub frames: BTreeMap<u16, Vec<u8>>,
+}
+
+impl AlignedFrames {
+    // Registered streams with no frame in this group.
+    pub fn missing<'a>(&self, idcodes: impl IntoIterator<Item = &'a u16>) -> Vec<u16> {
+        idcodes
+            .into_iter()
+            .filter(|idcode| !self.frames.contains_key(idcode))
+            .copied()
+            .collect()
+    }
+}
+
+struct AlignedStream {
+    config: Frame,
+    channel_map: HashMap<String, ChannelInfo>,
+    frame_size: usize,
+    time_base: u32,
+    data_rate: i16,
+    late: u64,
+    sources: Vec<String>, // First is the owner, empty when added without source
+    clock: StreamClock,
+}
+
+// What the arrival fallback knows of the clock of a stream.
+#[derive(Default)]
+struct StreamClock {
+    soc: Option<u32>,
+    since_us: i64,   // Arrival of the first frame with soc
+    latency_us: i64, // Arrival less timestamp while the clock worked
+    stuck: bool,
+    by_arrival: u64, // Frames aligned by arrival time
+}
+
+impl AlignedStream {
+    // Time to align a frame at when the clock of the stream is stuck.
+    fn arrival_timestamp(
+        &mut self,
+        idcode: u16,
+        frame: &[u8],
+        timestamp_us: i64,
+        arrival_us: i64,
+        frozen_us: i64,
+    ) -> Option<i64> {
+        let clock = &mut self.clock;
+        let soc = u32::from_be_bytes([frame[6], frame[7], frame[8], frame[9]]);
+        if clock.soc != Some(soc) {
+            if clock.stuck {
+                println!("Clock of stream {} moves again", idcode);
+            }
+            clock.soc = Some(soc);
+            clock.since_us = arrival_us;
+            clock.stuck = false;
+        }
+        let frozen_for = arrival_us - clock.since_us;
+        if !clock.stuck && frozen_for >= frozen_us {
+            println!(
+                "Clock of stream {} stuck at SOC {}, aligning it by arrival time",
+                idcode, soc
+            );
+            clock.stuck = true;
+        }
+        if !clock.stuck {
+            // Not when the clock may be stuck already
+            if frozen_for < 1_000_000 {
+                clock.latency_us = arrival_us - timestamp_us;
+            }
+            return None;
+        }
+        clock.by_arrival += 1;
+        Some(nearest_frame_time(
+            arrival_us - clock.latency_us,
+            self.data_rate,
+        ))
+    }
+
+    // The frame at timestamp_us, flagged as aligned by arrival time.
+    fn downgrade(&self, frame: &[u8], timestamp_us: i64) -> Vec<u8> {
+        let mut frame = frame.to_vec();
+        let soc = timestamp_us.div_euclid(1_000_000) as u32;
+        let fraction_us = timestamp_us.rem_euclid(1_000_000);
+        let fracsec =
+            (fraction_us as f64 * self.time_base as f64 / 1_000_000.0).round() as u32 & 0x00FF_FFFF;
+        // Time quality 0xF: clock failure, time not reliable
+        let quality = (frame[10] & 0xF0) | 0x0F;
+        frame[6..10].copy_from_slice(&soc.to_be_bytes());
+        frame[10..14].copy_from_slice(&(fracsec | (quality as u32) << 24).to_be_bytes());
+        for info in self.channel_map.values() {
+            if matches!(info.data_type, ChannelDataType::Stat) && info.offset + 2 <= frame.len() {
+                let stat = u16::from_be_bytes([frame[info.offset], frame[info.offset + 1]]);
+                // PMU sync error, data sorted by arrival
+                let stat = stat | 0x2000 | 0x1000;
+                frame[info.offset..info.offset + 2].copy_from_slice(&stat.to_be_bytes());
+            }
+        }
+        let len = frame.len();
+        let crc = calculate_crc(&frame[..len - 2]);
+        frame[len - 2..].copy_from_slice(&crc.to_be_bytes());
+        frame
+    }
+}
+
+pub struct Aggregator {
+    streams: HashMap<u16, AlignedStream>,
+    lateness_us: i64,
+    late_data: LateData,
+    pending: BTreeMap<i64, BTreeMap<u16, Vec<u8>>>,
+    newest_us: Option<i64>,  // Newest timestamp seen on any stream
+    emitted_us: Option<i64>, // Timestamp of the last group emitted
+    conflict_policy: ConflictPolicy,
+    // IDCODE each (source, IDCODE) is aggregated under, None when dropped
+    routes: HashMap<(String, u16), Option<u16>>,
+    // Source of the pending frames of streams with several sources
+    pending_sources: HashMap<(i64, u16), String>,
+    conflicts: Vec<IdcodeConflict>,
+    suffixes: HashMap<u16, usize>, // Station name suffix of each suffixed stream
+    frozen_us: Option<i64>,        // SOC unchanged this long falls back to arrival time
+}
+
+impl Aggregator {
+    // Groups wait for missing frames until a frame lateness past their
+    // timestamp has been seen.
+    pub fn new(lateness: Duration) -> Self {
+        Self {
+            streams: HashMap::new(),
+            lateness_us: lateness.as_micros() as i64,
+            late_data: LateData::Drop,
+            pending: BTreeMap::new(),
+            newest_us: None,
+            emitted_us: None,
+            conflict_policy: ConflictPolicy::default(),
+            routes: HashMap::new(),
+            pending_sources: HashMap::new(),
+            conflicts: Vec::new(),
+            suffixes: HashMap::new(),
+            frozen_us: None,
+        }
+    }
+
+    pub fn with_late_data(mut self, late_data: LateData) -> Self {
+        self.late_data = late_data;
+        self
+    }
+
+    pub fn with_conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.conflict_policy = policy;
+        self
+    }
+
+    // Align a stream by arrival time once its SOC has not changed for frozen.
+    pub fn with_arrival_fallback(mut self, frozen: Duration) -> Self {
+        self.frozen_us = Some(frozen.as_micros() as i64);
+        self
+    }
+
+    // Conflicts found so far, one per source and IDCODE.
+    pub fn conflicts(&self) -> &[IdcodeConflict] {
+        &self.conflicts
+    }
+
+    // Register (or replace) a stream using its configuration frame.
+    pub fn add_stream(&mut self, config: &ConfigurationFrame1and2_2011) {
+        self.insert_stream(config, Vec::new());
+    }
+
+    fn insert_stream(&mut self, config: &ConfigurationFrame1and2_2011, sources: Vec<String>) {
+        self.streams.insert(
+            config.prefix.idcode,
+            AlignedStream {
+                config: Frame::from(config),
+                channel_map: config.get_channel_map(),
+                frame_size: config.calc_data_frame_size(),
+                time_base: config.time_base,
+                data_rate: config.data_rate,
+                late: 0,
+                sources,
+                clock: StreamClock::default(),
+            },
+        );
+    }
+
+    // Register the stream of a source. Returns the IDCODE its frames are
+    // aggregated under, None when they are dropped for a conflict.
+    pub fn add_stream_from(
+        &mut self,
+        source: &str,
+        config: &ConfigurationFrame1and2_2011,
+    ) -> Result<Option<u16>, AggregatorError> {
+        let idcode = config.prefix.idcode;
+        let key = (source.to_string(), idcode);
+        match self.routes.get(&key).copied().flatten() {
+            // A configuration change of a suffixed source
+            Some(routed) if routed != idcode => {
+                let sources = vec![source.to_string()];
+                self.insert_stream(&self.suffixed(config, routed), sources);
+                return Ok(Some(routed));
+            }
+            // A configuration change of the owner
+            Some(_) if self.streams[&idcode].sources.first() == Some(&key.0) => {
+                let sources = self.streams[&idcode].sources.clone();
+                self.insert_stream(config, sources);
+                return Ok(Some(idcode));
+            }
+            Some(_) => self.leave(source, idcode),
+            None => {}
+        }
+        let Some(stream) = self.streams.get_mut(&idcode) else {
+            self.insert_stream(config, vec![source.to_string()]);
+            self.routes.insert(key, Some(idcode));
+            return Ok(Some(idcode));
+        };
+        if stream.sources.is_empty() {
+            // Added without source or restored from a checkpoint
+            let late = stream.late;
+            self.insert_stream(config, vec![source.to_string()]);
+            self.streams.get_mut(&idcode).unwrap().late = late;
+        } else if same_layout(&stream.config.config()?, config) {
+            stream.sources.push(source.to_string());
+        } else {
+            return self.resolve(source, config, ConflictKind::Config);
+        }
+        self.routes.insert(key, Some(idcode));
+        Ok(Some(idcode))
+    }
+
+    // Add a raw data frame of a source registered with add_stream_from.
+    // Frames of other sources are added as with push_frame.
+    pub fn push_frame_from(
+        &mut self,
+        source: &str,
+        frame: &[u8],
+    ) -> Result<Vec<AlignedFrames>, AggregatorError> {
+        if frame.len() < 14 {
+            return self.push_frame(frame);
+        }
+        let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+        let route = self.routes.get(&(source.to_string(), idcode)).copied();
+        match route {
+            None => self.push_frame(frame),
+            Some(None) => {
+                if let Some(conflict) = self.conflict_mut(source, idcode) {
+                    conflict.dropped += 1;
+                }
+                Ok(Vec::new())
+            }
+            Some(Some(routed)) if routed != idcode => {
+                let mut frame = frame.to_vec();
+                set_idcode(&mut frame, routed);
+                self.push_frame(&frame)
+            }
+            Some(Some(_)) => self.push_shared(source, idcode, frame),
+        }
+    }
+
+    // Frames of streams with several sources must agree per timestamp.
+    fn push_shared(
+        &mut self,
+        source: &str,
+        idcode: u16,
+        frame: &[u8],
+    ) -> Result<Vec<AlignedFrames>, AggregatorError> {
+        let Some(stream) = self.streams.get(&idcode) else {
+            return self.push_frame(frame);
+        };
+        if stream.sources.len() < 2 || frame.len() != stream.frame_size {
+            return self.push_frame(frame);
+        }
+        let owner = stream.sources[0].clone();
+        let timestamp_us = timestamp_us(frame, stream.time_base, self.newest_us);
+        let pending = self
+            .pending
+            .get(&timestamp_us)
+            .and_then(|group| group.get(&idcode));
+        let Some(pending) = pending else {
+            let ready = self.push_frame(frame)?;
+            if self.pending.contains_key(&timestamp_us) {
+                self.pending_sources
+                    .insert((timestamp_us, idcode), source.to_string());
+            }
+            return Ok(ready);
+        };
+        if pending.as_slice() == frame {
+            return Ok(Vec::new()); // The same frame from a redundant source
+        }
+        let pending_source = self
+            .pending_sources
+            .get(&(timestamp_us, idcode))
+            .cloned()
+            .unwrap_or_else(|| owner.clone());
+        if pending_source == source {
+            return self.push_frame(frame);
+        }
+        // The owner's frame stays, the frame of the other source goes with it
+        let (loser, frame) = if source != owner && pending_source == owner {
+            (source.to_string(), frame.to_vec())
+        } else {
+            let replaced = self
+                .pending
+                .get_mut(&timestamp_us)
+                .and_then(|group| group.insert(idcode, frame.to_vec()))
+                .unwrap_or_default();
+            self.pending_sources
+                .insert((timestamp_us, idcode), source.to_string());
+            (pending_source, replaced)
+        };
+        let config = self.streams[&idcode].config.config()?;
+        self.leave(&loser, idcode);
+        let routed = self.resolve(&loser, &config, ConflictKind::Data);
+        match routed {
+            Ok(Some(routed)) => {
+                let mut frame = frame;
+                set_idcode(&mut frame, routed);
+                self.push_frame(&frame)
+            }
+            Ok(None) => Ok(self.take_ready()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Remove a source from the sources of a stream.
+    fn leave(&mut self, source: &str, idcode: u16) {
+        if let Some(stream) = self.streams.get_mut(&idcode) {
+            stream.sources.retain(|s| s != source);
+        }
+        self.routes.remove(&(source.to_string(), idcode));
+    }
+
+    fn conflict_mut(&mut self, source: &str, idcode: u16) -> Option<&mut IdcodeConflict> {
+        self.conflicts
+            .iter_mut()
+            .find(|c| c.source == source && c.idcode == idcode)
+    }
+
+    // Apply the conflict policy to a source that lost its IDCODE.
+    fn resolve(
+        &mut self,
+        source: &str,
+        config: &ConfigurationFrame1and2_2011,
+        kind: ConflictKind,
+    ) -> Result<Option<u16>, AggregatorError> {
+        let idcode = config.prefix.idcode;
+        let assigned = match self.conflict_policy {
+            ConflictPolicy::Suffix => {
+                let suffix = self
+                    .conflicts
+                    .iter()
+                    .filter(|c| c.idcode == idcode)
+                    .filter_map(|c| c.assigned.and_then(|routed| self.suffixes.get(&routed)))
+                    .max()
+                    .map_or(2, |suffix| suffix + 1);
+                let mut renamed = suffix_stations(config, suffix);
+                let routed = self.free_idcode(idcode, &renamed)?;
+                renamed.prefix.idcode = routed;
+                self.insert_stream(&renamed, vec![source.to_string()]);
+                self.suffixes.insert(routed, suffix);
+                Some(routed)
+            }
+            ConflictPolicy::PreferFirst | ConflictPolicy::Reject => None,
+        };
+        println!(
+            "IDCODE {} of {} conflicts with another source ({:?}), {}",
+            idcode,
+            source,
+            kind,
+            match assigned {
+                Some(routed) => format!("aggregated as {}", routed),
+                None => "dropping its frames".to_string(),
+            }
+        );
+        self.routes.insert((source.to_string(), idcode), assigned);
+        let conflict = IdcodeConflict {
+            idcode,
+            source: source.to_string(),
+            kind,
+            assigned,
+            dropped: 0,
+        };
+        self.conflicts
+            .retain(|c| c.source != source || c.idcode != idcode);
+        self.conflicts.push(conflict.clone());
+        if self.conflict_policy == ConflictPolicy::Reject {
+            return Err(AggregatorError::Conflict(conflict));
+        }
+        Ok(assigned)
+    }
+
+    // The configuration a suffixed source is aggregated with.
+    fn suffixed(
+        &self,
+        config: &ConfigurationFrame1and2_2011,
+        routed: u16,
+    ) -> ConfigurationFrame1and2_2011 {
+        let suffix = self.suffixes.get(&routed).copied().unwrap_or(2);
+        let mut config = suffix_stations(config, suffix);
+        config.prefix.idcode = routed;
+        config
+    }
+
+    // An IDCODE for a suffixed stream: one restored without source for the
+    // same configuration, else the next unused after the original.
+    fn free_idcode(
+        &self,
+        idcode: u16,
+        config: &ConfigurationFrame1and2_2011,
+    ) -> Result<u16, AggregatorError> {
+        for (candidate, stream) in &self.streams {
+            if *candidate != idcode
+                && stream.sources.is_empty()
+                && same_layout(&stream.config.config()?, config)
+            {
+                return Ok(*candidate);
+            }
+        }
+        (1..=u16::MAX)
+            .map(|offset| idcode.wrapping_add(offset))
+            .find(|candidate| !self.streams.contains_key(candidate))
+            .ok_or(AggregatorError::UnknownStream(idcode))
+    }
+
+    pub fn idcodes(&self) -> impl Iterator<Item = &u16> {
+        self.streams.keys()
+    }
+
+    // Groups at or before this timestamp are emitted, None before the first frame.
+    pub fn watermark(&self) -> Option<i64> {
+        self.newest_us.map(|newest| newest - self.lateness_us)
+    }
+
+    // Number of late frames received from a stream.
+    pub fn late_frames(&self, idcode: u16) -> Option<u64> {
+        self.streams.get(&idcode).map(|stream| stream.late)
+    }
+
+    // Whether a stream is aligned by arrival time, its clock being stuck.
+    pub fn is_by_arrival(&self, idcode: u16) -> Option<bool> {
+        self.streams.get(&idcode).map(|stream| stream.clock.stuck)
+    }
+
+    // Number of frames of a stream aligned by arrival time.
+    pub fn arrival_frames(&self, idcode: u16) -> Option<u64> {
+        self.streams
+            .get(&idcode)
+            .map(|stream| stream.clock.by_arrival)
+    }
+
+    // Add a raw data frame. Returns the groups that are ready, oldest first.
+    pub fn push_frame(&mut self, frame: &[u8]) -> Result<Vec<AlignedFrames>, AggregatorError> {
+        self.push_frame_at(frame, now_micros())
+    }
+
+    // Add a raw data frame received at arrival_us, the time the arrival
+    // fallback aligns it at when the clock of its stream is stuck.
+    pub fn push_frame_at(
+        &mut self,
+        frame: &[u8],
+        arrival_us: i64,
+    ) -> Result<Vec<AlignedFrames>, AggregatorError> {
+        if frame.len() < 14 {
+            return Err(AggregatorError::InvalidFrameSize {
+                expected: 14,
+                actual: frame.len(),
+            });
+        }
+        let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+        let stream = self
+            .streams
+            .get_mut(&idcode)
+            .ok_or(AggregatorError::UnknownStream(idcode))?;
+        if frame.len() != stream.frame_size {
+            return Err(AggregatorError::InvalidFrameSize {
+                expected: stream.frame_size,
+                actual: frame.len(),
+            });
+        }
+        let mut timestamp_us = timestamp_us(frame, stream.time_base, self.newest_us);
+        let downgraded;
+        let mut frame = frame;
+        if let Some(frozen_us) = self.frozen_us {
+            if let Some(assigned) =
+                stream.arrival_timestamp(idcode, frame, timestamp_us, arrival_us, frozen_us)
+            {
+                downgraded = stream.downgrade(frame, assigned);
+                frame = &downgraded;
+                timestamp_us = assigned;
+            }
+        }
+
+        if self
+            .emitted_us
+            .is_some_and(|emitted| timestamp_us <= emitted)
+        {
+            stream.late += 1;
+            self.handle_late(idcode, frame)?;
+            return Ok(self.take_ready());
+        }
+
+        self.pending
+            .entry(timestamp_us)
+            .or_default()
+            .insert(idcode, frame.to_vec());
+        self.newest_us = Some(self.newest_us.map_or(timestamp_us, |n| n.max(timestamp_us)));
+        Ok(self.take_ready())
+    }
+
+    // Streams and pending groups, to save in a Checkpoint.
+    pub fn checkpoint(&self) -> AggregatorState {
+        let mut idcodes: Vec<&u16> = self.streams.keys().collect();
+        idcodes.sort();
+        AggregatorState {
+            configs: idcodes
+                .iter()
+                .map(|idcode| self.streams[idcode].config.clone())
+                .collect(),
+            pending: self
+                .pending
+                .values()
+                .flat_map(|frames| frames.values().map(|frame| Frame(frame.clone())))
+                .collect(),
+            newest_us: self.newest_us,
+            emitted_us: self.emitted_us,
+            late: self
+                .streams
+                .iter()
+                .map(|(idcode, stream)| (*idcode, stream.late))
+                .collect(),
+        }
+    }
+
+    // Take back the streams and pending groups of a checkpoint. Groups are
+    // not emitted until the next frame arrives.
+    pub fn restore(&mut self, state: &AggregatorState) -> Result<(), AggregatorError> {
+        for config in &state.configs {
+            self.add_stream(&config.config()?);
+        }
+        for (idcode, late) in &state.late {
+            if let Some(stream) = self.streams.get_mut(idcode) {
+                stream.late = *late;
+            }
+        }
+        for Frame(frame) in &state.pending {
+            if frame.len() < 14 {
+                return Err(AggregatorError::InvalidFrameSize {
+                    expected: 14,
+                    actual: frame.len(),
+                });
+            }
+            let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+            let stream = self
+                .streams
+                .get(&idcode)
+                .ok_or(AggregatorError::UnknownStream(idcode))?;
+            let timestamp_us = timestamp_us(frame, stream.time_base, state.newest_us);
+            self.pending
+                .entry(timestamp_us)
+                .or_default()
+                .insert(idcode, frame.clone());
+        }
+        self.newest_us = state.newest_us;
+        self.emitted_us = state.emitted_us;
+        Ok(())
+    }
+
+    // Emit every pending group, complete or not.
+    pub fn flush(&mut self) -> Vec<AlignedFrames> {
+        let groups = std::mem::take(&mut self.pending);
+        self.pending_sources.clear();
+        if let Some(last) = groups.keys().next_back() {
+            self.emitted_us = Some(*last);
+        }
+        groups
+            .into_iter()
+            .map(|(timestamp_us, frames)| AlignedFrames {
+                timestamp_us,
+                frames,
+            })
+            .collect()
+    }
+
+    fn take_ready(&mut self) -> Vec<AlignedFrames> {
+        let watermark = self.watermark();
+        let mut ready = Vec::new();
+        while let Some(entry) = self.pending.first_entry() {
+            let complete = self
+                .streams
+                .keys()
+                .all(|idcode| entry.get().contains_key(idcode));
+            if !complete && watermark.is_none_or(|w| *entry.key() > w) {
+                break;
+            }
+            let (timestamp_us, frames) = entry.remove_entry();
+            self.emitted_us = Some(timestamp_us);
+            if !self.pending_sources.is_empty() {
+                self.pending_sources.retain(|(t, _), _| *t > timestamp_us);
+            }
+            ready.push(AlignedFrames {
+                timestamp_us,
+                frames,
+            });
+        }
+        ready
+    }
+
+    fn handle_late(&mut self, idcode: u16, frame: &[u8]) -> Result<(), AggregatorError> {
+        match &mut self.late_data {
+            LateData::Drop => {}
+            LateData::Sink(sink) => {
+                let stream = &self.streams[&idcode];
+                let batch = build_record_batch(frame, stream.frame_size, &stream.channel_map)?;
+                sink.write_batch(&batch)?;
+            }
+            LateData::Historian(historian) => {
+                historian
+                    .lock()
+                    .map_err(|_| io::Error::other("Historian lock poisoned"))?
+                    .patch(frame)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Configurations describing the same stream, regardless of when they were
+// sent and under which IDCODE.
+fn same_layout(a: &ConfigurationFrame1and2_2011, b: &ConfigurationFrame1and2_2011) -> bool {
+    a.time_base == b.time_base && a.data_rate == b.data_rate && a.pmu_configs == b.pmu_configs
+}
+
+// "_<suffix>" appended to every station name, cutting the name to fit.
+fn suffix_stations(
+    config: &ConfigurationFrame1and2_2011,
+    suffix: usize,
+) -> ConfigurationFrame1and2_2011 {
+    let mut config = config.clone();
+    let suffix = format!("_{}", suffix);
+    for pmu in &mut config.pmu_configs {
+        let station = String::from_utf8_lossy(&pmu.stn).trim().to_string();
+        let keep = station.len().min(16 - suffix.len());
+        let mut name = station.as_bytes()[..keep].to_vec();
+        name.extend_from_slice(suffix.as_bytes());
+        name.resize(16, b' ');
+        pmu.stn.copy_from_slice(&name);
+    }
+    config
+}
+
+// The frame time of a stream at data_rate nearest to timestamp_us. Frames
+// are at whole multiples of the period from the top of each second.
+fn nearest_frame_time(timestamp_us: i64, data_rate: i16) -> i64 {
+    let second = timestamp_us.div_euclid(1_000_000) * 1_000_000;
+    let fraction_us = timestamp_us.rem_euclid(1_000_000) as f64;
+    if data_rate > 0 {
+        let rate = data_rate as f64;
+        let index = (fraction_us * rate / 1e6).round();
+        second + (index * 1e6 / rate).round() as i64
+    } else {
+        // One frame every -data_rate seconds
+        let period = -(data_rate.min(-1) as i64) * 1_000_000;
+        ((timestamp_us as f64 / period as f64).round() as i64) * period
+    }
+}
+
+// Timestamp of a frame, past the SOC rollover when the newest one is.
+fn timestamp_us(frame: &[u8], time_base: u32, newest_us: Option<i64>) -> i64 {
+    let timestamp_us = frame_timestamp_micros_with(frame, time_base).unwrap_or_default();
+    newest_us.map_or(timestamp_us, |newest| {
+        unwrap_soc_rollover(timestamp_us, newest)
+    })
+}
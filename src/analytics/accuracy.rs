@@ -0,0 +1,224 @@
+// Accuracy of PMU measurements against a reference, per IEEE C37.118.1.
+//
+// TVE (total vector error) is the magnitude of the difference between the
+// measured and reference phasor relative to the reference magnitude. FE and
+// RFE are the absolute frequency and ROCOF differences. A device stream is
+// compared to a reference stream frame by frame, matched on timestamp.
+use crate::frame_parser::parse_data_frames;
+use crate::frames::{
+    calculate_crc, soc_fracsec_micros, ConfigurationFrame1and2_2011, PMUConfigurationFrame2011,
+    PMUData, PMUFrameType,
+};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Phasor {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Phasor {
+    pub fn new(re: f64, im: f64) -> Self {
+        Phasor { re, im }
+    }
+
+    // Angle in radians
+    pub fn from_polar(magnitude: f64, angle: f64) -> Self {
+        Phasor {
+            re: magnitude * angle.cos(),
+            im: magnitude * angle.sin(),
+        }
+    }
+
+    pub fn magnitude(&self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn angle(&self) -> f64 {
+        self.im.atan2(self.re)
+    }
+}
+
+// Total vector error in percent. Infinite when the reference is zero.
+pub fn tve(reference: Phasor, measured: Phasor) -> f64 {
+    let error = (measured.re - reference.re).hypot(measured.im - reference.im);
+    let magnitude = reference.magnitude();
+    if magnitude == 0.0 {
+        return if error == 0.0 { 0.0 } else { f64::INFINITY };
+    }
+    error / magnitude * 100.0
+}
+
+// Frequency error in Hz.
+pub fn frequency_error(reference_hz: f64, measured_hz: f64) -> f64 {
+    (measured_hz - reference_hz).abs()
+}
+
+// ROCOF error in Hz/s.
+pub fn rocof_error(reference: f64, measured: f64) -> f64 {
+    (measured - reference).abs()
+}
+
+// One PMU's phasors, frequency and ROCOF in engineering units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PmuMeasurement {
+    pub timestamp_us: i64,
+    pub stat: u16,
+    pub phasors: Vec<Phasor>,
+    pub frequency: f64, // Hz
+    pub rocof: f64,     // Hz/s
+}
+
+impl PmuMeasurement {
+    pub fn from_block(
+        timestamp_us: i64,
+        block: &PMUFrameType,
+        config: &PMUConfigurationFrame2011,
+    ) -> Self {
+        let polar = config.is_phasor_polar();
+        let phasors = block
+            .phasor_values(config)
+            .into_iter()
+            .map(|(first, second)| {
+                if polar {
+                    Phasor::from_polar(first, second)
+                } else {
+                    Phasor::new(first, second)
+                }
+            })
+            .collect();
+
+        PmuMeasurement {
+            timestamp_us,
+            stat: block.stat(),
+            phasors,
+            frequency: block.frequency(config),
+            rocof: block.rocof(),
+        }
+    }
+}
+
+// Measurements of every PMU in a data frame. Frames of the wrong size or
+// with a bad CHK are refused.
+pub fn frame_measurements(
+    frame: &[u8],
+    config: &ConfigurationFrame1and2_2011,
+) -> Result<Vec<PmuMeasurement>, String> {
+    if frame.len() != config.calc_data_frame_size() {
+        return Err(format!(
+            "Frame is {} bytes, configuration expects {}",
+            frame.len(),
+            config.calc_data_frame_size()
+        ));
+    }
+    let len = frame.len();
+    if calculate_crc(&frame[..len - 2]) != u16::from_be_bytes([frame[len - 2], frame[len - 1]]) {
+        return Err("Invalid CRC".to_string());
+    }
+    let data = parse_data_frames(frame, config).map_err(|e| format!("{:?}", e))?;
+
+    let timestamp_us = soc_fracsec_micros(data.prefix.soc, data.prefix.fracsec, config.time_base);
+    Ok(data
+        .data
+        .iter()
+        .zip(&config.pmu_configs)
+        .map(|(block, pmu_config)| PmuMeasurement::from_block(timestamp_us, block, pmu_config))
+        .collect())
+}
+
+// Measurements of PMU number pmu from a sequence of data frames, skipping
+// frames that do not parse.
+pub fn stream_measurements(
+    frames: &[Vec<u8>],
+    config: &ConfigurationFrame1and2_2011,
+    pmu: usize,
+) -> Vec<PmuMeasurement> {
+    frames
+        .iter()
+        .filter_map(|frame| frame_measurements(frame, config).ok())
+        .filter_map(|mut measurements| {
+            (pmu < measurements.len()).then(|| measurements.swap_remove(pmu))
+        })
+        .collect()
+}
+
+// Errors of one measurement against the reference at the same instant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccuracyErrors {
+    pub timestamp_us: i64,
+    pub tve: Vec<f64>, // Percent, per phasor
+    pub fe: f64,       // Hz
+    pub rfe: f64,      // Hz/s
+}
+
+impl AccuracyErrors {
+    pub fn max_tve(&self) -> f64 {
+        self.tve.iter().copied().fold(0.0, f64::max)
+    }
+}
+
+pub fn compare(reference: &PmuMeasurement, measured: &PmuMeasurement) -> AccuracyErrors {
+    AccuracyErrors {
+        timestamp_us: measured.timestamp_us,
+        tve: reference
+            .phasors
+            .iter()
+            .zip(&measured.phasors)
+            .map(|(reference, measured)| tve(*reference, *measured))
+            .collect(),
+        fe: frequency_error(reference.frequency, measured.frequency),
+        rfe: rocof_error(reference.rocof, measured.rocof),
+    }
+}
+
+// Compare measurements to the reference with the same timestamp. Measurements
+// without a reference are left out.
+pub fn compare_streams(
+    reference: &[PmuMeasurement],
+    measured: &[PmuMeasurement],
+) -> Vec<AccuracyErrors> {
+    let by_time: HashMap<i64, &PmuMeasurement> = reference
+        .iter()
+        .map(|measurement| (measurement.timestamp_us, measurement))
+        .collect();
+    measured
+        .iter()
+        .filter_map(|measurement| {
+            by_time
+                .get(&measurement.timestamp_us)
+                .map(|reference| compare(reference, measurement))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AccuracySummary {
+    pub count: usize,
+    pub max_tve: f64,
+    pub mean_tve: f64,
+    pub max_fe: f64,
+    pub max_rfe: f64,
+}
+
+pub fn summarize(errors: &[AccuracyErrors]) -> AccuracySummary {
+    if errors.is_empty() {
+        return AccuracySummary::default();
+    }
+    let mut summary = AccuracySummary {
+        count: errors.len(),
+        ..Default::default()
+    };
+    let mut tve_sum = 0.0;
+    let mut tve_count = 0;
+    for error in errors {
+        summary.max_tve = summary.max_tve.max(error.max_tve());
+        summary.max_fe = summary.max_fe.max(error.fe);
+        summary.max_rfe = summary.max_rfe.max(error.rfe);
+        tve_sum += error.tve.iter().sum::<f64>();
+        tve_count += error.tve.len();
+    }
+    if tve_count > 0 {
+        summary.mean_tve = tve_sum / tve_count as f64;
+    }
+    summary
+}
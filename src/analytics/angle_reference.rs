@@ -0,0 +1,237 @@
+// Phase angles relative to a common reference.
+//
+// Each PMU reports angles against its own nominal-frequency rotating
+// reference, so absolute angles drift with the system frequency and only
+// differences between phasors mean anything. An AngleReferencer takes the
+// measurements of all PMUs at one timestamp and subtracts the angle of the
+// reference phasor from every angle.
+//
+// The references are tried in order of preference. A reference is usable at
+// a timestamp when its PMU is present with valid data (STAT bits 15-14 clear)
+// and a finite, non-zero phasor. When none is usable the angles are left
+// against the nominal rotating reference, i.e. as reported. Every switch of
+// reference is published on the event bus, so consumers can tell that the
+// angles jumped because the reference changed. With an AreaMap the angles
+// are also averaged per area and compared between areas.
+use crate::analytics::accuracy::PmuMeasurement;
+use crate::areas::AreaMap;
+use crate::events::{Event, EventBus, EventKind, Severity};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AngleReference {
+    // Phasor number phasor of the PMU measurements are keyed with station
+    Phasor {
+        station: String,
+        #[serde(default)]
+        phasor: usize,
+    },
+    // Rotating at nominal frequency, the angles as the PMUs report them
+    Nominal,
+}
+
+impl AngleReference {
+    pub fn phasor(station: &str, phasor: usize) -> Self {
+        AngleReference::Phasor {
+            station: station.to_string(),
+            phasor,
+        }
+    }
+
+    // Angle of the reference in radians, None when it is not usable.
+    fn angle(&self, measurements: &HashMap<String, PmuMeasurement>) -> Option<f64> {
+        match self {
+            AngleReference::Nominal => Some(0.0),
+            AngleReference::Phasor { station, phasor } => {
+                let measurement = measurements.get(station)?;
+                if measurement.stat & 0xC000 != 0 {
+                    return None;
+                }
+                let phasor = measurement.phasors.get(*phasor)?;
+                let magnitude = phasor.magnitude();
+                (magnitude.is_finite() && magnitude > 0.0).then(|| phasor.angle())
+            }
+        }
+    }
+}
+
+impl fmt::Display for AngleReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AngleReference::Phasor { station, phasor } => {
+                write!(f, "{} phasor {}", station, phasor)
+            }
+            AngleReference::Nominal => write!(f, "nominal rotating reference"),
+        }
+    }
+}
+
+// Angles of one timestamp against the reference used for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferencedAngles {
+    pub timestamp_us: i64,
+    pub reference: AngleReference,
+    pub angles: HashMap<String, Vec<f64>>, // Radians in -pi..pi, per phasor of each station
+}
+
+impl ReferencedAngles {
+    // Angle of the first phasor of each station, as LineOutageDetector takes them.
+    pub fn first_phasors(&self) -> HashMap<String, f64> {
+        self.angles
+            .iter()
+            .filter_map(|(station, angles)| angles.first().map(|angle| (station.clone(), *angle)))
+            .collect()
+    }
+
+    // Mean angle of the first phasors of each area's stations, those of the
+    // areas below included. Averaged as unit vectors, so angles either side
+    // of +-pi do not cancel out.
+    pub fn area_angles(&self, areas: &AreaMap) -> BTreeMap<String, f64> {
+        let first = self.first_phasors();
+        areas
+            .group(
+                first
+                    .iter()
+                    .map(|(station, angle)| (station.as_str(), *angle)),
+            )
+            .into_iter()
+            .map(|(area, angles)| {
+                let (sin, cos) = angles.iter().fold((0.0, 0.0), |(s, c), angle| {
+                    (s + angle.sin(), c + angle.cos())
+                });
+                (area, sin.atan2(cos))
+            })
+            .collect()
+    }
+
+    // Angle differences between the areas, each pair once as (a, b, angle of
+    // a minus angle of b) with a before b by name.
+    pub fn area_differences(&self, areas: &AreaMap) -> Vec<(String, String, f64)> {
+        let angles: Vec<(String, f64)> = self.area_angles(areas).into_iter().collect();
+        let mut differences = Vec::new();
+        for (i, (a, angle_a)) in angles.iter().enumerate() {
+            for (b, angle_b) in &angles[i + 1..] {
+                differences.push((a.clone(), b.clone(), wrap(angle_a - angle_b)));
+            }
+        }
+        differences
+    }
+}
+
+pub struct AngleReferencer {
+    references: Vec<AngleReference>, // In order of preference
+    current: Option<AngleReference>,
+    switches: u64,
+    bus: Option<EventBus>,
+}
+
+impl AngleReferencer {
+    pub fn new(references: Vec<AngleReference>) -> Self {
+        AngleReferencer {
+            references,
+            current: None,
+            switches: 0,
+            bus: None,
+        }
+    }
+
+    pub fn with_event_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    // Reference of the last timestamp, None before the first.
+    pub fn current(&self) -> Option<&AngleReference> {
+        self.current.as_ref()
+    }
+
+    // Times the reference changed after the first timestamp.
+    pub fn switches(&self) -> u64 {
+        self.switches
+    }
+
+    // Re-reference the measurements of all stations at one timestamp, keyed
+    // by station.
+    pub fn push(
+        &mut self,
+        timestamp_us: i64,
+        measurements: &HashMap<String, PmuMeasurement>,
+    ) -> ReferencedAngles {
+        let (reference, offset) = self
+            .references
+            .iter()
+            .find_map(|reference| {
+                reference
+                    .angle(measurements)
+                    .map(|angle| (reference.clone(), angle))
+            })
+            .unwrap_or((AngleReference::Nominal, 0.0));
+
+        if let Some(previous) = self
+            .current
+            .as_ref()
+            .filter(|current| **current != reference)
+        {
+            self.switches += 1;
+            self.publish(timestamp_us, previous, &reference);
+        }
+        self.current = Some(reference.clone());
+
+        let angles = measurements
+            .iter()
+            .map(|(station, measurement)| {
+                let angles = measurement
+                    .phasors
+                    .iter()
+                    .map(|phasor| wrap(phasor.angle() - offset))
+                    .collect();
+                (station.clone(), angles)
+            })
+            .collect();
+        ReferencedAngles {
+            timestamp_us,
+            reference,
+            angles,
+        }
+    }
+
+    fn publish(&self, timestamp_us: i64, previous: &AngleReference, next: &AngleReference) {
+        let Some(bus) = &self.bus else {
+            return;
+        };
+        // Falling back to a less preferred reference is worth a look
+        let rank = |reference: &AngleReference| {
+            self.references
+                .iter()
+                .position(|r| r == reference)
+                .unwrap_or(self.references.len())
+        };
+        let severity = if rank(next) > rank(previous) {
+            Severity::Warning
+        } else {
+            Severity::Info
+        };
+        let source = match next {
+            AngleReference::Phasor { station, .. } => station.as_str(),
+            AngleReference::Nominal => "nominal",
+        };
+        bus.publish(
+            Event::new(
+                timestamp_us,
+                EventKind::ReferenceChanged,
+                source,
+                format!("Angle reference changed from {} to {}", previous, next),
+            )
+            .with_severity(severity),
+        );
+    }
+}
+
+// Angle in radians to -pi..pi.
+fn wrap(angle: f64) -> f64 {
+    (angle + PI).rem_euclid(2.0 * PI) - PI
+}
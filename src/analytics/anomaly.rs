@@ -0,0 +1,429 @@
+// Statistical data quality checks per channel.
+//
+// Unlike threshold alarms these look at how a channel behaves compared to its
+// own recent past. The residual of each sample is its change from the
+// previous one; a robust z-score of the residual against the median and MAD
+// (median absolute deviation) of the last window residuals flags outliers.
+// An outlier that returns to the previous level on the next sample is a
+// spike, one that stays is a jump, so outliers are classified one sample
+// late. Runs of identical values are stuck values and gaps of more than a
+// few reporting intervals, or non-finite values, are dropouts.
+//
+// Outliers are kept out of the window so a burst of them does not widen the
+// spread they are judged against.
+//
+// Anomalies covered by a suppressing annotation (maintenance, known bad data)
+// are counted as suppressed and not published.
+//
+// Phasor angles given with push_angle are not checked for anomalies but for
+// their jitter (analytics::jitter), reported with the counts.
+use crate::analytics::jitter::{AngleJitter, JitterStats};
+use crate::annotations::AnnotationStore;
+use crate::events::{Event, EventBus, EventKind, Severity};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyKind {
+    Stuck,   // Value unchanged for stuck_samples samples
+    Spike,   // Single outlier that returns to the previous level
+    Dropout, // Missing samples or a non-finite value
+    Jump,    // Outlier change to a new level
+}
+
+impl AnomalyKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnomalyKind::Stuck => "stuck",
+            AnomalyKind::Spike => "spike",
+            AnomalyKind::Dropout => "dropout",
+            AnomalyKind::Jump => "jump",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnomalyConfig {
+    pub sample_rate: f64,       // Expected samples per second
+    pub window: usize,          // Residuals the median and MAD are taken over
+    pub threshold: f64,         // Robust z-score that is an outlier
+    pub stuck_samples: usize,   // Identical values in a row that are a stuck value
+    pub dropout_intervals: f64, // Gap, in reporting intervals, that is a dropout
+    pub jitter_window: usize,   // Angles the jitter is taken over
+}
+
+impl AnomalyConfig {
+    // 2 s of residuals at the given rate, outliers beyond 6 sigma.
+    pub fn new(sample_rate: f64) -> Self {
+        AnomalyConfig {
+            sample_rate,
+            window: (2.0 * sample_rate).round().max(10.0) as usize,
+            threshold: 6.0,
+            stuck_samples: sample_rate.round().max(2.0) as usize,
+            dropout_intervals: 2.5,
+            jitter_window: sample_rate.round().max(10.0) as usize,
+        }
+    }
+
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window.max(3);
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_stuck_samples(mut self, stuck_samples: usize) -> Self {
+        self.stuck_samples = stuck_samples.max(2);
+        self
+    }
+
+    pub fn with_dropout_intervals(mut self, dropout_intervals: f64) -> Self {
+        self.dropout_intervals = dropout_intervals;
+        self
+    }
+
+    pub fn with_jitter_window(mut self, jitter_window: usize) -> Self {
+        self.jitter_window = jitter_window.max(3);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    pub channel: String,
+    pub kind: AnomalyKind,
+    pub timestamp_us: i64, // The outlier, the first stuck value or the sample after the gap
+    pub value: f64,
+    pub score: f64, // Robust z-score, stuck samples or missing intervals
+}
+
+impl Anomaly {
+    pub fn to_event(&self, source: &str) -> Event {
+        let detail = match self.kind {
+            AnomalyKind::Stuck => format!("{:.0} identical samples", self.score),
+            AnomalyKind::Dropout => format!("{:.1} intervals missing", self.score),
+            _ => format!("robust z-score {:.1}", self.score),
+        };
+        Event::new(
+            self.timestamp_us,
+            EventKind::DataQuality,
+            source,
+            format!("{} on {}, {}", self.kind.name(), self.channel, detail),
+        )
+        .with_severity(Severity::Warning)
+        .with_value("value", self.value)
+        .with_value("score", self.score)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelQuality {
+    pub samples: usize,
+    pub stuck: usize,
+    pub spikes: usize,
+    pub dropouts: usize,
+    pub jumps: usize,
+    pub suppressed: usize, // Anomalies within annotated periods, not counted above
+}
+
+impl ChannelQuality {
+    pub fn anomalies(&self) -> usize {
+        self.stuck + self.spikes + self.dropouts + self.jumps
+    }
+
+    fn count(&mut self, kind: AnomalyKind) {
+        match kind {
+            AnomalyKind::Stuck => self.stuck += 1,
+            AnomalyKind::Spike => self.spikes += 1,
+            AnomalyKind::Dropout => self.dropouts += 1,
+            AnomalyKind::Jump => self.jumps += 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QualityReport {
+    pub channels: BTreeMap<String, ChannelQuality>,
+    pub angle_jitter: BTreeMap<String, JitterStats>, // By phasor channel
+}
+
+impl QualityReport {
+    // One line per channel with its anomaly counts.
+    pub fn render(&self) -> String {
+        let mut out = String::from("Data quality report\n");
+        for (channel, quality) in &self.channels {
+            out.push_str(&format!(
+                "{:<24} samples={} stuck={} spikes={} dropouts={} jumps={} suppressed={}\n",
+                channel,
+                quality.samples,
+                quality.stuck,
+                quality.spikes,
+                quality.dropouts,
+                quality.jumps,
+                quality.suppressed,
+            ));
+        }
+        for (channel, jitter) in &self.angle_jitter {
+            let rad = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{:.2e}", v));
+            out.push_str(&format!(
+                "{:<24} angle jitter latest={} mean={} max={} rad\n",
+                channel,
+                rad(jitter.latest),
+                rad(jitter.mean()),
+                rad(jitter.max),
+            ));
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "channels": self.channels.iter().map(|(channel, quality)| json!({
+                "name": channel,
+                "samples": quality.samples,
+                "anomalies": quality.anomalies(),
+                "stuck": quality.stuck,
+                "spikes": quality.spikes,
+                "dropouts": quality.dropouts,
+                "jumps": quality.jumps,
+                "suppressed": quality.suppressed,
+            })).collect::<Vec<_>>(),
+            "angle_jitter": self.angle_jitter.iter().map(|(channel, jitter)| json!({
+                "name": channel,
+                "samples": jitter.samples,
+                "latest_rad": jitter.latest,
+                "mean_rad": jitter.mean(),
+                "max_rad": jitter.max,
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+struct Outlier {
+    timestamp_us: i64,
+    value: f64,
+    previous: f64, // Level before the outlier
+    score: f64,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    residuals: VecDeque<f64>,
+    last: Option<(i64, f64)>,
+    outlier: Option<Outlier>,
+    stuck_since: Option<(i64, usize)>, // First identical sample and the run length
+    stuck_reported: bool,
+}
+
+pub struct AnomalyDetector {
+    config: AnomalyConfig,
+    source: String,
+    bus: Option<EventBus>,
+    annotations: Option<Arc<Mutex<AnnotationStore>>>,
+    channels: BTreeMap<String, ChannelState>,
+    jitter: BTreeMap<String, AngleJitter>,
+    report: QualityReport,
+}
+
+impl AnomalyDetector {
+    pub fn new(config: AnomalyConfig, source: &str) -> Self {
+        AnomalyDetector {
+            config,
+            source: source.to_string(),
+            bus: None,
+            annotations: None,
+            channels: BTreeMap::new(),
+            jitter: BTreeMap::new(),
+            report: QualityReport::default(),
+        }
+    }
+
+    // Publish each anomaly on a bus as well.
+    pub fn with_event_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    // Suppress anomalies the annotations cover. Annotations of a stream apply
+    // when the source of the detector is the stream IDCODE.
+    pub fn with_annotations(mut self, annotations: Arc<Mutex<AnnotationStore>>) -> Self {
+        self.annotations = Some(annotations);
+        self
+    }
+
+    // Counts per channel since the detector was created.
+    pub fn report(&self) -> &QualityReport {
+        &self.report
+    }
+
+    // Add a sample of a channel. Returns the anomalies it completes, which for
+    // spikes and jumps belong to the sample before, except suppressed ones.
+    pub fn push(&mut self, channel: &str, timestamp_us: i64, value: f64) -> Vec<Anomaly> {
+        let state = self.channels.entry(channel.to_string()).or_default();
+        let mut anomalies = check(&self.config, channel, state, timestamp_us, value);
+
+        let quality = self.report.channels.entry(channel.to_string()).or_default();
+        quality.samples += 1;
+        if let Some(annotations) = self.annotations.as_ref().filter(|_| !anomalies.is_empty()) {
+            let stream = self.source.parse().ok();
+            let annotations = annotations.lock().unwrap();
+            anomalies.retain(|anomaly| {
+                let suppressed = annotations
+                    .suppressing(stream, channel, anomaly.timestamp_us)
+                    .is_some();
+                quality.suppressed += suppressed as usize;
+                !suppressed
+            });
+        }
+        for anomaly in &anomalies {
+            quality.count(anomaly.kind);
+            if let Some(bus) = &self.bus {
+                bus.publish(anomaly.to_event(&self.source));
+            }
+        }
+        anomalies
+    }
+
+    // Add an angle in radians of a phasor channel. Returns its jitter once
+    // half the jitter window is filled.
+    pub fn push_angle(&mut self, channel: &str, timestamp_us: i64, angle: f64) -> Option<f64> {
+        let window = self.config.jitter_window;
+        let jitter = self
+            .jitter
+            .entry(channel.to_string())
+            .or_insert_with(|| AngleJitter::new(window))
+            .push(timestamp_us, angle);
+        self.report
+            .angle_jitter
+            .entry(channel.to_string())
+            .or_default()
+            .record(jitter);
+        jitter
+    }
+}
+
+fn check(
+    config: &AnomalyConfig,
+    channel: &str,
+    state: &mut ChannelState,
+    timestamp_us: i64,
+    value: f64,
+) -> Vec<Anomaly> {
+    let anomaly = |kind, timestamp_us, value, score| Anomaly {
+        channel: channel.to_string(),
+        kind,
+        timestamp_us,
+        value,
+        score,
+    };
+    let mut anomalies = Vec::new();
+
+    if let Some((last_us, _)) = state.last {
+        let intervals = (timestamp_us - last_us) as f64 / 1e6 * config.sample_rate;
+        if intervals > config.dropout_intervals {
+            anomalies.push(anomaly(
+                AnomalyKind::Dropout,
+                timestamp_us,
+                value,
+                intervals - 1.0,
+            ));
+        }
+    }
+    if !value.is_finite() {
+        anomalies.push(anomaly(AnomalyKind::Dropout, timestamp_us, value, 1.0));
+        // Judge the next sample against the last good one
+        state.last = state.last.map(|(_, last)| (timestamp_us, last));
+        return anomalies;
+    }
+    let Some((last_us, last)) = state.last.replace((timestamp_us, value)) else {
+        return anomalies;
+    };
+
+    // Stuck values
+    if value == last {
+        let (since, run) = state.stuck_since.get_or_insert((last_us, 1));
+        *run += 1;
+        if *run >= config.stuck_samples && !state.stuck_reported {
+            state.stuck_reported = true;
+            anomalies.push(anomaly(AnomalyKind::Stuck, *since, value, *run as f64));
+        }
+    } else {
+        state.stuck_since = None;
+        state.stuck_reported = false;
+    }
+
+    // Spikes return to the level before them, anything else is a jump
+    let residual = value - last;
+    if let Some(outlier) = state.outlier.take() {
+        let back = robust_z(&state.residuals, value - outlier.previous).abs();
+        let kind = if back < config.threshold {
+            AnomalyKind::Spike
+        } else {
+            AnomalyKind::Jump
+        };
+        anomalies.push(anomaly(
+            kind,
+            outlier.timestamp_us,
+            outlier.value,
+            outlier.score,
+        ));
+        if kind == AnomalyKind::Spike {
+            return anomalies;
+        }
+    }
+
+    let enough = state.residuals.len() >= config.window / 2;
+    let score = robust_z(&state.residuals, residual);
+    if enough && score.abs() > config.threshold {
+        state.outlier = Some(Outlier {
+            timestamp_us,
+            value,
+            previous: last,
+            score,
+        });
+        return anomalies;
+    }
+    // A stuck value would otherwise fill the window with zeros and collapse
+    // the spread, those are covered by the stuck check
+    if residual != 0.0 {
+        state.residuals.push_back(residual);
+    }
+    if state.residuals.len() > config.window {
+        state.residuals.pop_front();
+    }
+    anomalies
+}
+
+// Robust z-score of a residual, 0 when the window has no spread.
+fn robust_z(residuals: &VecDeque<f64>, residual: f64) -> f64 {
+    if residuals.is_empty() {
+        return 0.0;
+    }
+    let mut sorted: Vec<f64> = residuals.iter().copied().collect();
+    let center = median(&mut sorted);
+    let mut deviations: Vec<f64> = sorted.iter().map(|r| (r - center).abs()).collect();
+    let mut scale = 1.4826 * median(&mut deviations);
+    if scale <= 0.0 {
+        // Mostly identical residuals, e.g. a quantized signal; fall back to
+        // the mean absolute deviation
+        scale = 1.2533 * deviations.iter().sum::<f64>() / deviations.len() as f64;
+    }
+    if scale <= 0.0 {
+        return 0.0;
+    }
+    (residual - center) / scale
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
@@ -0,0 +1,264 @@
+// Grouping of stations by the coherency of their phase angles.
+//
+// Generators that swing together keep the angles of the stations near them
+// moving together. Over a rolling window the angle of each station is
+// unwrapped and the correlation of every pair of stations computed over the
+// timestamps both have. Stations are then clustered hierarchically, average
+// linkage on 1 - correlation, merging groups while their distance stays
+// within 1 - min_correlation.
+//
+// The clustering is redone every interval once the window holds enough
+// samples. When stations move between groups the new groups are returned
+// and published as a CoherencyChange event; the first clustering is not a
+// change.
+use crate::events::{Event, EventBus, EventKind, Severity};
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoherencyConfig {
+    pub window: f64,          // Seconds of angles correlated
+    pub interval: f64,        // Seconds between clusterings
+    pub min_correlation: f64, // Of the stations in a group, on average
+    pub min_samples: usize,   // Common timestamps a pair needs to be compared
+}
+
+impl Default for CoherencyConfig {
+    fn default() -> Self {
+        CoherencyConfig {
+            window: 10.0,
+            interval: 1.0,
+            min_correlation: 0.9,
+            min_samples: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoherentGroups {
+    pub timestamp_us: i64,
+    pub groups: Vec<Vec<String>>, // Stations sorted by name, groups by their first
+}
+
+impl CoherentGroups {
+    pub fn group_of(&self, station: &str) -> Option<&[String]> {
+        self.groups
+            .iter()
+            .find(|group| group.iter().any(|s| s == station))
+            .map(Vec::as_slice)
+    }
+
+    // Stations whose group has other members than before, by name.
+    pub fn moved_from(&self, previous: &CoherentGroups) -> Vec<String> {
+        let mut moved: Vec<String> = self
+            .groups
+            .iter()
+            .flatten()
+            .filter(|station| previous.group_of(station) != self.group_of(station))
+            .cloned()
+            .collect();
+        moved.sort();
+        moved
+    }
+
+    pub fn to_event(&self, previous: &CoherentGroups) -> Event {
+        let moved = self.moved_from(previous);
+        Event::new(
+            self.timestamp_us,
+            EventKind::CoherencyChange,
+            &moved.join(","),
+            format!(
+                "Coherent groups changed from {} to {}",
+                describe(&previous.groups),
+                describe(&self.groups)
+            ),
+        )
+        .with_severity(Severity::Info)
+        .with_value("groups", self.groups.len() as f64)
+        .with_value("moved", moved.len() as f64)
+    }
+}
+
+// Groups as [A, B] [C].
+fn describe(groups: &[Vec<String>]) -> String {
+    groups
+        .iter()
+        .map(|group| format!("[{}]", group.join(", ")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub struct CoherencyClusterer {
+    config: CoherencyConfig,
+    rows: VecDeque<(i64, HashMap<String, f64>)>, // Unwrapped angles in the window
+    last: HashMap<String, (f64, f64)>,           // Last angle as given and unwrapped
+    next_us: Option<i64>,                        // Time of the next clustering
+    current: Option<CoherentGroups>,
+    changes: u64,
+    bus: Option<EventBus>,
+}
+
+impl CoherencyClusterer {
+    pub fn new(config: CoherencyConfig) -> Self {
+        CoherencyClusterer {
+            config,
+            rows: VecDeque::new(),
+            last: HashMap::new(),
+            next_us: None,
+            current: None,
+            changes: 0,
+            bus: None,
+        }
+    }
+
+    pub fn with_event_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    // Groups of the last clustering, None before the first.
+    pub fn current(&self) -> Option<&CoherentGroups> {
+        self.current.as_ref()
+    }
+
+    // Times stations moved between groups.
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    // Add the angles (radians) of the stations at one timestamp, e.g.
+    // ReferencedAngles::first_phasors. Returns the new groups when stations
+    // moved between groups.
+    pub fn push(
+        &mut self,
+        timestamp_us: i64,
+        angles: &HashMap<String, f64>,
+    ) -> Option<CoherentGroups> {
+        let mut row = HashMap::with_capacity(angles.len());
+        for (station, angle) in angles {
+            if !angle.is_finite() {
+                continue;
+            }
+            let unwrapped = match self.last.get(station) {
+                Some((previous, unwrapped)) => unwrapped + wrap(angle - previous),
+                None => *angle,
+            };
+            self.last.insert(station.clone(), (*angle, unwrapped));
+            row.insert(station.clone(), unwrapped);
+        }
+        self.rows.push_back((timestamp_us, row));
+        let window_us = (self.config.window * 1e6) as i64;
+        while self
+            .rows
+            .front()
+            .is_some_and(|(t, _)| *t <= timestamp_us - window_us)
+        {
+            self.rows.pop_front();
+        }
+
+        let next_us = *self.next_us.get_or_insert(timestamp_us + window_us);
+        if timestamp_us < next_us {
+            return None;
+        }
+        self.next_us = Some(timestamp_us + (self.config.interval * 1e6).max(1.0) as i64);
+        let groups = CoherentGroups {
+            timestamp_us,
+            groups: self.cluster(),
+        };
+        let previous = self.current.replace(groups.clone());
+        let previous = previous.filter(|previous| !groups.moved_from(previous).is_empty())?;
+        self.changes += 1;
+        if let Some(bus) = &self.bus {
+            bus.publish(groups.to_event(&previous));
+        }
+        Some(groups)
+    }
+
+    // Average linkage clustering of the stations in the window.
+    fn cluster(&self) -> Vec<Vec<String>> {
+        let mut stations: Vec<&String> = self.rows.iter().flat_map(|(_, row)| row.keys()).collect();
+        stations.sort();
+        stations.dedup();
+        let n = stations.len();
+        // Distance 1 - correlation, 2 for pairs that cannot be compared
+        let mut distance = vec![vec![0.0; n]; n];
+        for i in 0..n {
+            for j in i + 1..n {
+                let d = self
+                    .correlation(stations[i], stations[j])
+                    .map_or(2.0, |r| 1.0 - r);
+                distance[i][j] = d;
+                distance[j][i] = d;
+            }
+        }
+        let mut groups: Vec<Vec<usize>> = (0..n).map(|i| vec![i]).collect();
+        let max_distance = 1.0 - self.config.min_correlation;
+        loop {
+            let mut closest: Option<(f64, usize, usize)> = None;
+            for a in 0..groups.len() {
+                for b in a + 1..groups.len() {
+                    let total: f64 = groups[a]
+                        .iter()
+                        .flat_map(|i| groups[b].iter().map(|j| distance[*i][*j]))
+                        .sum();
+                    let d = total / (groups[a].len() * groups[b].len()) as f64;
+                    if closest.is_none_or(|(closest, _, _)| d < closest) {
+                        closest = Some((d, a, b));
+                    }
+                }
+            }
+            match closest {
+                Some((d, a, b)) if d <= max_distance => {
+                    let merged = groups.remove(b);
+                    groups[a].extend(merged);
+                }
+                _ => break,
+            }
+        }
+        let mut groups: Vec<Vec<String>> = groups
+            .into_iter()
+            .map(|group| {
+                let mut group: Vec<String> = group.iter().map(|i| stations[*i].clone()).collect();
+                group.sort();
+                group
+            })
+            .collect();
+        groups.sort();
+        groups
+    }
+
+    // Correlation of the angles of two stations over the timestamps of the
+    // window both have, None with too few of them. Two stations with steady
+    // angles are coherent, one steady and one moving are not.
+    fn correlation(&self, a: &str, b: &str) -> Option<f64> {
+        let pairs: Vec<(f64, f64)> = self
+            .rows
+            .iter()
+            .filter_map(|(_, row)| Some((*row.get(a)?, *row.get(b)?)))
+            .collect();
+        if pairs.len() < self.config.min_samples.max(2) {
+            return None;
+        }
+        let n = pairs.len() as f64;
+        let mean_a = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_b = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let (mut sab, mut saa, mut sbb) = (0.0, 0.0, 0.0);
+        for (x, y) in &pairs {
+            sab += (x - mean_a) * (y - mean_b);
+            saa += (x - mean_a) * (x - mean_a);
+            sbb += (y - mean_b) * (y - mean_b);
+        }
+        // Variance of less than about a millidegree is no movement
+        let steady = 1e-10 * n;
+        Some(match (saa < steady, sbb < steady) {
+            (true, true) => 1.0,
+            (true, false) | (false, true) => 0.0,
+            _ => sab / (saa * sbb).sqrt(),
+        })
+    }
+}
+
+// Angle in radians to -pi..pi.
+fn wrap(angle: f64) -> f64 {
+    (angle + PI).rem_euclid(2.0 * PI) - PI
+}
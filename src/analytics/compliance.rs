@@ -0,0 +1,442 @@
+// Compliance of a PMU with the C37.118.1 (2011, amended 2014) accuracy limits.
+//
+// A device is run through the standard test conditions (off-nominal
+// frequency, magnitude and phase steps, frequency ramp) as simulator
+// scenarios. Its frames are compared to an ideal simulator running the same
+// scenario and the errors checked against the P-class or M-class limits.
+// Measurements within the response time after a step are left out, the
+// standard tests those separately.
+use crate::analytics::accuracy::{
+    compare_streams, stream_measurements, summarize, AccuracySummary,
+};
+use crate::frame_parser::parse_config_frame_1and2;
+use crate::frames::ConfigurationFrame1and2_2011;
+use crate::recorder::CaptureReader;
+use crate::simulator::{NoiseModel, Scenario, ScenarioEvent, Simulator};
+use serde_json::{json, Value};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerformanceClass {
+    P, // Protection, fast response
+    M, // Measurement, more filtering
+}
+
+impl PerformanceClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PerformanceClass::P => "P",
+            PerformanceClass::M => "M",
+        }
+    }
+
+    // Seconds after a step before the measurement must be within limits
+    pub fn response_time(&self, nominal_hz: f64) -> f64 {
+        match self {
+            PerformanceClass::P => 2.0 / nominal_hz,
+            PerformanceClass::M => 7.0 / nominal_hz,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConditionKind {
+    SteadyState,
+    Step,
+    Ramp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ComplianceLimits {
+    pub max_tve: f64, // Percent
+    pub max_fe: f64,  // Hz
+    pub max_rfe: f64, // Hz/s
+}
+
+impl ComplianceLimits {
+    pub fn for_condition(kind: ConditionKind, class: PerformanceClass) -> Self {
+        match (kind, class) {
+            (ConditionKind::Ramp, PerformanceClass::P) => ComplianceLimits {
+                max_tve: 1.0,
+                max_fe: 0.01,
+                max_rfe: 0.4,
+            },
+            (ConditionKind::Ramp, PerformanceClass::M) => ComplianceLimits {
+                max_tve: 1.0,
+                max_fe: 0.01,
+                max_rfe: 0.2,
+            },
+            // Away from the step, steady state limits apply
+            (_, PerformanceClass::P) => ComplianceLimits {
+                max_tve: 1.0,
+                max_fe: 0.005,
+                max_rfe: 0.4,
+            },
+            (_, PerformanceClass::M) => ComplianceLimits {
+                max_tve: 1.0,
+                max_fe: 0.005,
+                max_rfe: 0.1,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestCondition {
+    pub name: String,
+    pub kind: ConditionKind,
+    pub scenario: Scenario,
+    // Seconds since start at which the signal changes abruptly
+    pub transitions: Vec<f64>,
+}
+
+impl TestCondition {
+    fn new(name: &str, kind: ConditionKind, duration: f64, events: Vec<ScenarioEvent>) -> Self {
+        let mut transitions = Vec::new();
+        for event in &events {
+            match *event {
+                ScenarioEvent::FrequencyRamp { at, duration, .. } => {
+                    transitions.extend([at, at + duration]);
+                }
+                ScenarioEvent::VoltageSag { at, duration, .. } => {
+                    transitions.extend([at, at + duration]);
+                }
+                _ => transitions.push(event.at()),
+            }
+        }
+        TestCondition {
+            name: name.to_string(),
+            kind,
+            scenario: Scenario {
+                start_soc: Some(START_SOC),
+                events,
+                duration: Some(duration),
+                ..Default::default()
+            },
+            transitions,
+        }
+    }
+
+    fn excluded(&self, elapsed: f64, response_time: f64) -> bool {
+        self.transitions
+            .iter()
+            .any(|at| elapsed >= *at && elapsed < at + response_time)
+    }
+}
+
+// SOC of the first frame of every standard condition
+const START_SOC: u32 = 1_700_000_000;
+
+// Test conditions of C37.118.1 covered by the simulator.
+pub fn standard_conditions(class: PerformanceClass) -> Vec<TestCondition> {
+    // Off-nominal range, reached with a one second ramp
+    let offset = match class {
+        PerformanceClass::P => 2.0,
+        PerformanceClass::M => 5.0,
+    };
+    let ramp = |rate: f64| ScenarioEvent::FrequencyRamp {
+        at: 0.0,
+        duration: 1.0,
+        rate,
+    };
+    vec![
+        TestCondition::new(
+            "steady_state_nominal",
+            ConditionKind::SteadyState,
+            3.0,
+            vec![],
+        ),
+        TestCondition::new(
+            "frequency_offset_high",
+            ConditionKind::SteadyState,
+            4.0,
+            vec![ramp(offset)],
+        ),
+        TestCondition::new(
+            "frequency_offset_low",
+            ConditionKind::SteadyState,
+            4.0,
+            vec![ramp(-offset)],
+        ),
+        TestCondition::new(
+            "magnitude_step",
+            ConditionKind::Step,
+            4.0,
+            vec![ScenarioEvent::VoltageSag {
+                at: 2.0,
+                duration: 10.0,
+                depth: 0.1,
+            }],
+        ),
+        TestCondition::new(
+            "phase_step",
+            ConditionKind::Step,
+            4.0,
+            vec![ScenarioEvent::PhaseJump {
+                at: 2.0,
+                degrees: 10.0,
+            }],
+        ),
+        TestCondition::new(
+            "frequency_ramp",
+            ConditionKind::Ramp,
+            5.0,
+            vec![ScenarioEvent::FrequencyRamp {
+                at: 1.0,
+                duration: 3.0,
+                rate: 1.0,
+            }],
+        ),
+    ]
+}
+
+// A PMU under test: reports its frames for the signal described by a scenario.
+pub trait DeviceUnderTest {
+    fn measure(
+        &mut self,
+        scenario: &Scenario,
+    ) -> Result<(ConfigurationFrame1and2_2011, Vec<Vec<u8>>), String>;
+}
+
+// The simulator with optional measurement noise standing in for a device.
+pub struct SimulatedDevice {
+    config: ConfigurationFrame1and2_2011,
+    noise: Option<NoiseModel>,
+}
+
+impl SimulatedDevice {
+    pub fn new(config: ConfigurationFrame1and2_2011) -> Self {
+        SimulatedDevice {
+            config,
+            noise: None,
+        }
+    }
+
+    pub fn with_noise(mut self, noise: NoiseModel) -> Self {
+        self.noise = Some(noise);
+        self
+    }
+}
+
+impl DeviceUnderTest for SimulatedDevice {
+    fn measure(
+        &mut self,
+        scenario: &Scenario,
+    ) -> Result<(ConfigurationFrame1and2_2011, Vec<Vec<u8>>), String> {
+        if scenario.duration.is_none() {
+            return Err("Scenario has no duration".to_string());
+        }
+        let scenario = Scenario {
+            noise: self.noise.clone(),
+            ..scenario.clone()
+        };
+        let mut simulator = Simulator::new(self.config.clone(), scenario);
+        let mut frames = Vec::new();
+        while !simulator.is_finished() {
+            frames.extend(simulator.next_tick().frames);
+        }
+        Ok((self.config.clone(), frames))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConditionResult {
+    pub name: String,
+    pub limits: ComplianceLimits,
+    pub summary: AccuracySummary,
+    pub excluded: usize, // Measurements within the response time of a step
+    pub failures: Vec<String>,
+}
+
+impl ConditionResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+// Check the frames a device reported for a condition. Only the given PMU of
+// the stream is evaluated.
+pub fn evaluate(
+    condition: &TestCondition,
+    class: PerformanceClass,
+    config: &ConfigurationFrame1and2_2011,
+    frames: &[Vec<u8>],
+    pmu: usize,
+) -> ConditionResult {
+    let limits = ComplianceLimits::for_condition(condition.kind, class);
+    let mut result = ConditionResult {
+        name: condition.name.clone(),
+        limits,
+        summary: AccuracySummary::default(),
+        excluded: 0,
+        failures: Vec::new(),
+    };
+
+    let measured = stream_measurements(frames, config, pmu);
+    let (Some(first), Some(last)) = (measured.first(), measured.last()) else {
+        result.failures.push("No valid data frames".to_string());
+        return result;
+    };
+
+    // The ideal signal, starting at the second of the first measurement
+    let start_us = first.timestamp_us.div_euclid(1_000_000) * 1_000_000;
+    let scenario = Scenario {
+        start_soc: Some((start_us / 1_000_000) as u32),
+        noise: None,
+        duration: None,
+        ..condition.scenario.clone()
+    };
+    let mut simulator = Simulator::new(config.clone(), scenario);
+    let mut reference_frames = Vec::new();
+    while simulator.next_time_us() <= last.timestamp_us {
+        reference_frames.extend(simulator.next_tick().frames);
+    }
+    let reference = stream_measurements(&reference_frames, config, pmu);
+
+    let nominal = match config.pmu_configs.get(pmu) {
+        Some(pmu_config) if pmu_config.fnom & 0x0001 != 0 => 50.0,
+        _ => 60.0,
+    };
+    let response_time = class.response_time(nominal);
+    let (excluded, errors): (Vec<_>, Vec<_>) = compare_streams(&reference, &measured)
+        .into_iter()
+        .partition(|error| {
+            condition.excluded(
+                (error.timestamp_us - start_us) as f64 / 1_000_000.0,
+                response_time,
+            )
+        });
+    result.excluded = excluded.len();
+    result.summary = summarize(&errors);
+
+    if errors.is_empty() {
+        result
+            .failures
+            .push("No measurements match the reference".to_string());
+    }
+    if result.summary.max_tve > limits.max_tve {
+        result.failures.push(format!(
+            "TVE {:.3}% exceeds {}%",
+            result.summary.max_tve, limits.max_tve
+        ));
+    }
+    if result.summary.max_fe > limits.max_fe {
+        result.failures.push(format!(
+            "FE {:.4} Hz exceeds {} Hz",
+            result.summary.max_fe, limits.max_fe
+        ));
+    }
+    if result.summary.max_rfe > limits.max_rfe {
+        result.failures.push(format!(
+            "RFE {:.3} Hz/s exceeds {} Hz/s",
+            result.summary.max_rfe, limits.max_rfe
+        ));
+    }
+    result
+}
+
+// Check a recording of a device under a condition. The configuration is
+// taken from the capture when not given.
+pub fn evaluate_capture(
+    path: impl AsRef<Path>,
+    config: Option<&ConfigurationFrame1and2_2011>,
+    condition: &TestCondition,
+    class: PerformanceClass,
+) -> Result<ConditionResult, String> {
+    let mut reader = CaptureReader::open(path).map_err(|e| e.to_string())?;
+    let mut captured_config = None;
+    let mut frames = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        match record.frame.get(..2) {
+            Some([0xAA, 0x01]) => frames.push(record.frame),
+            Some([0xAA, 0x21]) | Some([0xAA, 0x31]) if captured_config.is_none() => {
+                captured_config = parse_config_frame_1and2(&record.frame).ok();
+            }
+            _ => {}
+        }
+    }
+    let config = match (config, &captured_config) {
+        (Some(config), _) => config,
+        (None, Some(config)) => config,
+        (None, None) => return Err("No configuration frame in capture".to_string()),
+    };
+    Ok(evaluate(condition, class, config, &frames, 0))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComplianceReport {
+    pub class: PerformanceClass,
+    pub results: Vec<ConditionResult>,
+}
+
+impl ComplianceReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.passed())
+    }
+
+    // One line per condition and an overall verdict.
+    pub fn render(&self) -> String {
+        let mut out = format!("Compliance report, {} class\n", self.class.name());
+        for result in &self.results {
+            out.push_str(&format!(
+                "{:<24} {} samples={} TVE={:.3}% FE={:.4}Hz RFE={:.3}Hz/s\n",
+                result.name,
+                if result.passed() { "PASS" } else { "FAIL" },
+                result.summary.count,
+                result.summary.max_tve,
+                result.summary.max_fe,
+                result.summary.max_rfe,
+            ));
+            for failure in &result.failures {
+                out.push_str(&format!("    {}\n", failure));
+            }
+        }
+        out.push_str(if self.passed() { "PASS\n" } else { "FAIL\n" });
+        out
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "class": self.class.name(),
+            "passed": self.passed(),
+            "conditions": self.results.iter().map(|result| json!({
+                "name": result.name,
+                "passed": result.passed(),
+                "samples": result.summary.count,
+                "excluded": result.excluded,
+                "max_tve": result.summary.max_tve,
+                "mean_tve": result.summary.mean_tve,
+                "max_fe": result.summary.max_fe,
+                "max_rfe": result.summary.max_rfe,
+                "limits": {
+                    "max_tve": result.limits.max_tve,
+                    "max_fe": result.limits.max_fe,
+                    "max_rfe": result.limits.max_rfe,
+                },
+                "failures": result.failures,
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+// Run a device through every standard condition of the class.
+pub fn run_compliance(
+    device: &mut impl DeviceUnderTest,
+    class: PerformanceClass,
+) -> ComplianceReport {
+    let results = standard_conditions(class)
+        .iter()
+        .map(|condition| match device.measure(&condition.scenario) {
+            Ok((config, frames)) => evaluate(condition, class, &config, &frames, 0),
+            Err(e) => ConditionResult {
+                name: condition.name.clone(),
+                limits: ComplianceLimits::for_condition(condition.kind, class),
+                summary: AccuracySummary::default(),
+                excluded: 0,
+                failures: vec![format!("Device failed: {}", e)],
+            },
+        })
+        .collect();
+    ComplianceReport { class, results }
+}
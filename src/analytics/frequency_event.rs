@@ -0,0 +1,290 @@
+// Classification of frequency excursions (generator trips, load losses).
+//
+// The classifier keeps the mean frequency of the last pre_event seconds
+// (value A). When the frequency leaves it by more than the trigger, an event
+// starts: the initial ROCOF is fitted over the first rocof_window seconds,
+// the nadir is the furthest point reached and the settling frequency
+// (value B) is the mean between settling_start and settling_end seconds
+// after the start. The event size follows from the system frequency
+// response constant: MW = |B - A| / 0.1 Hz x response.
+//
+// Losses reported to the classifier (report_loss) within loss_window seconds
+// of an event's start give its measured frequency response instead
+// (analytics::frequency_response), published with the event.
+use crate::analytics::frequency_response::FrequencyResponse;
+use crate::analytics::least_squares_slope;
+use crate::events::{Event, EventBus, EventKind, Severity};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrequencyEventConfig {
+    pub response_mw_per_0_1hz: f64, // System frequency response, MW per 0.1 Hz
+    pub trigger_hz: f64,            // Deviation from value A that starts an event
+    pub pre_event: f64,             // Seconds averaged for value A
+    pub rocof_window: f64,          // Seconds after the start fitted for the initial ROCOF
+    pub settling_start: f64,        // Seconds after the start
+    pub settling_end: f64,          // Seconds after the start
+    pub alarm_mw: Option<f64>,      // Events at least this large are alarms
+    pub loss_window: f64,           // Seconds between a reported loss and the event's start
+}
+
+impl FrequencyEventConfig {
+    // Value A over 16 s and value B from 20 to 52 s after the start, as in
+    // NERC BAL-003.
+    pub fn new(response_mw_per_0_1hz: f64) -> Self {
+        FrequencyEventConfig {
+            response_mw_per_0_1hz,
+            trigger_hz: 0.04,
+            pre_event: 16.0,
+            rocof_window: 0.5,
+            settling_start: 20.0,
+            settling_end: 52.0,
+            alarm_mw: None,
+            loss_window: 10.0,
+        }
+    }
+
+    pub fn with_trigger(mut self, trigger_hz: f64) -> Self {
+        self.trigger_hz = trigger_hz;
+        self
+    }
+
+    pub fn with_settling_window(mut self, start: f64, end: f64) -> Self {
+        self.settling_start = start;
+        self.settling_end = end.max(start);
+        self
+    }
+
+    pub fn with_alarm(mut self, alarm_mw: f64) -> Self {
+        self.alarm_mw = Some(alarm_mw);
+        self
+    }
+
+    pub fn with_loss_window(mut self, seconds: f64) -> Self {
+        self.loss_window = seconds;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrequencyEvent {
+    pub kind: EventKind,
+    pub start_us: i64,
+    pub pre_event_hz: f64, // Value A
+    pub nadir_hz: f64,     // Lowest (trip) or highest (load loss) frequency
+    pub nadir_us: i64,
+    pub settling_hz: f64,   // Value B
+    pub initial_rocof: f64, // Hz/s
+    pub size_mw: f64,
+    pub response: Option<FrequencyResponse>, // With a reported loss
+}
+
+impl FrequencyEvent {
+    pub fn to_event(&self, source: &str, severity: Severity) -> Event {
+        let what = match self.kind {
+            EventKind::GeneratorTrip => "Generation loss",
+            _ => "Load loss",
+        };
+        let mut message = format!(
+            "{} of about {:.0} MW, nadir {:.3} Hz",
+            what, self.size_mw, self.nadir_hz
+        );
+        if let Some(response) = &self.response {
+            message.push_str(&format!(", frequency response of {}", response));
+        }
+        let event = Event::new(self.start_us, self.kind, source, message)
+            .with_severity(severity)
+            .with_value("pre_event_hz", self.pre_event_hz)
+            .with_value("nadir_hz", self.nadir_hz)
+            .with_value("settling_hz", self.settling_hz)
+            .with_value("initial_rocof_hz_per_s", self.initial_rocof)
+            .with_value("size_mw", self.size_mw);
+        match &self.response {
+            Some(response) => event
+                .with_value("delta_p_mw", response.delta_p_mw)
+                .with_value("nadir_beta_mw_per_0_1hz", response.nadir_beta)
+                .with_value("settling_beta_mw_per_0_1hz", response.settling_beta),
+            None => event,
+        }
+    }
+}
+
+struct Excursion {
+    start_us: i64,
+    pre_event_hz: f64,
+    samples: Vec<(i64, f64)>,
+}
+
+pub struct FrequencyEventClassifier {
+    config: FrequencyEventConfig,
+    source: String,
+    bus: Option<EventBus>,
+    history: VecDeque<(i64, f64)>, // Last pre_event seconds while no event is running
+    excursion: Option<Excursion>,
+    losses: Vec<(i64, f64)>, // Reported, not yet matched to an event
+}
+
+impl FrequencyEventClassifier {
+    pub fn new(config: FrequencyEventConfig, source: &str) -> Self {
+        FrequencyEventClassifier {
+            config,
+            source: source.to_string(),
+            bus: None,
+            history: VecDeque::new(),
+            excursion: None,
+            losses: Vec::new(),
+        }
+    }
+
+    // Publish each classified event on a bus as well.
+    pub fn with_event_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    // A loss of mw of generation or load reported at a time, for the event
+    // starting closest to it within the loss window.
+    pub fn report_loss(&mut self, timestamp_us: i64, mw: f64) {
+        self.losses.push((timestamp_us, mw));
+    }
+
+    // Add a frequency sample, returns the event once its settling window has passed.
+    pub fn push(&mut self, timestamp_us: i64, frequency: f64) -> Option<FrequencyEvent> {
+        if let Some(excursion) = self.excursion.as_mut() {
+            excursion.samples.push((timestamp_us, frequency));
+            let elapsed = (timestamp_us - excursion.start_us) as f64 / 1e6;
+            if elapsed < self.config.settling_end {
+                return None;
+            }
+            let excursion = self.excursion.take()?;
+            let mut event = self.classify(&excursion);
+            if let Some(event) = event.as_mut() {
+                self.estimate_response(event);
+            }
+            // The settled frequency is the reference for the next event
+            let keep_from = timestamp_us - (self.config.pre_event * 1e6) as i64;
+            self.history = excursion
+                .samples
+                .into_iter()
+                .filter(|(t, _)| *t >= keep_from)
+                .collect();
+            if let (Some(event), Some(bus)) = (event, &self.bus) {
+                bus.publish(event.to_event(&self.source, self.severity(&event)));
+            }
+            return event;
+        }
+
+        let span = match (self.history.front(), self.history.back()) {
+            (Some((first, _)), Some((last, _))) => (last - first) as f64 / 1e6,
+            _ => 0.0,
+        };
+        // Wait for half the pre-event window before triggering
+        if span >= self.config.pre_event / 2.0 {
+            let mean = mean(self.history.iter().map(|(_, f)| *f));
+            if (frequency - mean).abs() > self.config.trigger_hz {
+                self.excursion = Some(Excursion {
+                    start_us: timestamp_us,
+                    pre_event_hz: mean,
+                    samples: vec![(timestamp_us, frequency)],
+                });
+                return None;
+            }
+        }
+        self.history.push_back((timestamp_us, frequency));
+        let keep_from = timestamp_us - (self.config.pre_event * 1e6) as i64;
+        while self.history.front().is_some_and(|(t, _)| *t < keep_from) {
+            self.history.pop_front();
+        }
+        // Too early for any event yet to start
+        let window_us = (self.config.loss_window * 1e6) as i64;
+        self.losses.retain(|(t, _)| *t >= timestamp_us - window_us);
+        None
+    }
+
+    // Match the event to the closest reported loss and drop the losses
+    // reported before its window.
+    fn estimate_response(&mut self, event: &mut FrequencyEvent) {
+        let window_us = (self.config.loss_window * 1e6) as i64;
+        let closest = self
+            .losses
+            .iter()
+            .enumerate()
+            .filter(|(_, (t, _))| (t - event.start_us).abs() <= window_us)
+            .min_by_key(|(_, (t, _))| (t - event.start_us).abs())
+            .map(|(index, _)| index);
+        if let Some(index) = closest {
+            let (_, mw) = self.losses.remove(index);
+            event.response = FrequencyResponse::estimate(event, mw);
+            if let Some(response) = &event.response {
+                println!("Frequency response at {}: {}", self.source, response);
+            }
+        }
+        self.losses.retain(|(t, _)| *t > event.start_us - window_us);
+    }
+
+    fn severity(&self, event: &FrequencyEvent) -> Severity {
+        match self.config.alarm_mw {
+            Some(alarm) if event.size_mw >= alarm => Severity::Alarm,
+            _ => Severity::Warning,
+        }
+    }
+
+    fn classify(&self, excursion: &Excursion) -> Option<FrequencyEvent> {
+        let since = |t: i64| (t - excursion.start_us) as f64 / 1e6;
+        let settling: Vec<f64> = excursion
+            .samples
+            .iter()
+            .filter(|(t, _)| {
+                since(*t) >= self.config.settling_start && since(*t) <= self.config.settling_end
+            })
+            .map(|(_, f)| *f)
+            .collect();
+        if settling.is_empty() {
+            return None;
+        }
+        let settling_hz = mean(settling.into_iter());
+        let first = excursion.samples.first()?.1;
+        let drop = first < excursion.pre_event_hz;
+        let nadir = excursion.samples.iter().copied().reduce(|a, b| {
+            let further = if drop { b.1 < a.1 } else { b.1 > a.1 };
+            if further {
+                b
+            } else {
+                a
+            }
+        })?;
+
+        let rocof_samples: Vec<(f64, f64)> = excursion
+            .samples
+            .iter()
+            .filter(|(t, _)| since(*t) <= self.config.rocof_window)
+            .map(|(t, f)| (since(*t), *f))
+            .collect();
+
+        Some(FrequencyEvent {
+            kind: if drop {
+                EventKind::GeneratorTrip
+            } else {
+                EventKind::LoadLoss
+            },
+            start_us: excursion.start_us,
+            pre_event_hz: excursion.pre_event_hz,
+            nadir_hz: nadir.1,
+            nadir_us: nadir.0,
+            settling_hz,
+            initial_rocof: least_squares_slope(&rocof_samples).unwrap_or(0.0),
+            size_mw: (settling_hz - excursion.pre_event_hz).abs() / 0.1
+                * self.config.response_mw_per_0_1hz,
+            response: None,
+        })
+    }
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0usize), |(s, c), v| (s + v, c + 1));
+    if count == 0 {
+        0.0
+    } else {
+        sum / count as f64
+    }
+}
@@ -0,0 +1,63 @@
+// System frequency response (beta) of frequency events.
+//
+// Beta relates the MW lost or gained in an event to the frequency change it
+// caused, in MW per 0.1 Hz:
+//
+// - nadir-based: delta P over value A - nadir, the response arresting the
+//   excursion (inertia and the fastest governors);
+// - settling-based: delta P over value A - value B, the response once
+//   primary control has settled, as in NERC BAL-003.
+//
+// Delta P, the size of the resource or load lost, is not something the
+// frequency tells: it is reported for the event, e.g. by the balancing
+// authority or the tripped unit's telemetry. FrequencyEventClassifier
+// matches the losses reported to it to the events it classifies on the area
+// frequency and estimates the response of each.
+use crate::analytics::frequency_event::FrequencyEvent;
+use std::fmt;
+
+// Frequency changes smaller than this give no beta.
+const MIN_DELTA_HZ: f64 = 0.001;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrequencyResponse {
+    pub delta_p_mw: f64,        // Resource or load lost
+    pub nadir_delta_hz: f64,    // |value A - nadir|
+    pub settling_delta_hz: f64, // |value A - value B|
+    pub nadir_beta: f64,        // MW per 0.1 Hz
+    pub settling_beta: f64,     // MW per 0.1 Hz
+}
+
+impl FrequencyResponse {
+    // Response of an event to a loss of delta_p_mw, None when the frequency
+    // did not settle away from value A.
+    pub fn estimate(event: &FrequencyEvent, delta_p_mw: f64) -> Option<Self> {
+        let delta_p_mw = delta_p_mw.abs();
+        let nadir_delta_hz = (event.pre_event_hz - event.nadir_hz).abs();
+        let settling_delta_hz = (event.pre_event_hz - event.settling_hz).abs();
+        if settling_delta_hz < MIN_DELTA_HZ || delta_p_mw == 0.0 {
+            return None;
+        }
+        Some(FrequencyResponse {
+            delta_p_mw,
+            nadir_delta_hz,
+            settling_delta_hz,
+            nadir_beta: delta_p_mw / nadir_delta_hz.max(settling_delta_hz) * 0.1,
+            settling_beta: delta_p_mw / settling_delta_hz * 0.1,
+        })
+    }
+}
+
+impl fmt::Display for FrequencyResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.0} MW: {:.0} MW/0.1 Hz settling ({:.3} Hz), {:.0} MW/0.1 Hz at the nadir ({:.3} Hz)",
+            self.delta_p_mw,
+            self.settling_beta,
+            self.settling_delta_hz,
+            self.nadir_beta,
+            self.nadir_delta_hz
+        )
+    }
+}
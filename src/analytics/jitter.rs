@@ -0,0 +1,295 @@
+// Angle jitter of phasor channels, a data quality indicator.
+//
+// A PMU reports angles against a reference rotating at nominal frequency, so
+// off nominal the angle of a clean phasor turns at 2π(f - f0) rad/s. Over a
+// short window that rotation is a straight line in the unwrapped angle. The
+// jitter of a channel is the standard deviation of the angle around the
+// least squares line through the last window samples, what is left once the
+// rotation is removed. Clean channels show a few 10^-4 rad; noisy instrument
+// transformers, bad burdens or a loose connection show much more.
+//
+// A gap of more than a few reporting intervals makes the unwrapping
+// ambiguous, so the window starts over after it.
+//
+// JitterMonitor follows the phasors of whole batches, keeps statistics per
+// channel and sets the pmu_angle_jitter_rad gauge of a Metrics registry.
+use crate::analytics::least_squares_slope;
+use crate::arrow_utils::{
+    COMPLEX_COMPONENT, META_CHANNEL, META_COMPONENT, META_SCALE, META_STATION,
+};
+use crate::metrics::Metrics;
+use arrow::array::{Array, FixedSizeListArray, Float64Array, TimestampMicrosecondArray};
+use arrow::compute::cast;
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+pub const JITTER_GAUGE: &str = "pmu_angle_jitter_rad";
+const GAP_INTERVALS: f64 = 3.0; // Gap, in intervals, after which the window starts over
+
+// Angle jitter followed by the pipeline.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JitterConfig {
+    // Samples the jitter is taken over
+    #[serde(default = "default_window")]
+    pub window: usize,
+}
+
+fn default_window() -> usize {
+    30
+}
+
+impl Default for JitterConfig {
+    fn default() -> Self {
+        JitterConfig {
+            window: default_window(),
+        }
+    }
+}
+
+// Rolling angle jitter of one channel.
+#[derive(Debug, Clone)]
+pub struct AngleJitter {
+    window: usize,
+    samples: VecDeque<(i64, f64)>, // Timestamp and unwrapped angle
+    last_angle: f64,               // As received, to unwrap the next one
+}
+
+impl AngleJitter {
+    pub fn new(window: usize) -> Self {
+        AngleJitter {
+            window: window.max(3),
+            samples: VecDeque::new(),
+            last_angle: 0.0,
+        }
+    }
+
+    // Add an angle in radians. Returns the jitter in radians once half the
+    // window is filled.
+    pub fn push(&mut self, timestamp_us: i64, angle: f64) -> Option<f64> {
+        if !angle.is_finite() {
+            return None;
+        }
+        let interval = match self.samples.len() {
+            0 | 1 => None,
+            n => Some(self.samples[n - 1].0 - self.samples[n - 2].0),
+        };
+        match self.samples.back().copied() {
+            Some((last_us, unwrapped)) => {
+                let gap = timestamp_us - last_us;
+                let too_long = interval.is_some_and(|i| gap as f64 > GAP_INTERVALS * i as f64);
+                if gap <= 0 || too_long {
+                    self.samples.clear();
+                    self.samples.push_back((timestamp_us, angle));
+                } else {
+                    let step = wrap(angle - self.last_angle);
+                    self.samples.push_back((timestamp_us, unwrapped + step));
+                }
+            }
+            None => self.samples.push_back((timestamp_us, angle)),
+        }
+        self.last_angle = angle;
+        if self.samples.len() > self.window {
+            self.samples.pop_front();
+        }
+        self.jitter()
+    }
+
+    // Standard deviation of the angles around their least squares line.
+    pub fn jitter(&self) -> Option<f64> {
+        if self.samples.len() < (self.window / 2).max(3) {
+            return None;
+        }
+        let start_us = self.samples[0].0;
+        let points: Vec<(f64, f64)> = self
+            .samples
+            .iter()
+            .map(|(t, angle)| ((t - start_us) as f64 / 1e6, *angle))
+            .collect();
+        let n = points.len() as f64;
+        let mt = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+        let ma = points.iter().map(|(_, a)| a).sum::<f64>() / n;
+        let slope = least_squares_slope(&points).unwrap_or(0.0);
+        let squares: f64 = points
+            .iter()
+            .map(|(t, a)| (a - ma - slope * (t - mt)).powi(2))
+            .sum();
+        Some((squares / (n - 2.0)).sqrt())
+    }
+}
+
+// Angle difference into (-π, π].
+fn wrap(angle: f64) -> f64 {
+    let wrapped = (angle + PI).rem_euclid(2.0 * PI) - PI;
+    if wrapped == -PI {
+        PI
+    } else {
+        wrapped
+    }
+}
+
+// Jitter of a channel over the samples seen.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct JitterStats {
+    pub samples: usize,
+    pub latest: Option<f64>, // Radians, of the last window
+    pub max: Option<f64>,
+    sum: f64,
+    windows: usize,
+}
+
+impl JitterStats {
+    pub fn record(&mut self, jitter: Option<f64>) {
+        self.samples += 1;
+        if let Some(jitter) = jitter {
+            self.latest = Some(jitter);
+            self.max = Some(self.max.map_or(jitter, |max| max.max(jitter)));
+            self.sum += jitter;
+            self.windows += 1;
+        }
+    }
+
+    // Mean of the jitter over every full window.
+    pub fn mean(&self) -> Option<f64> {
+        (self.windows > 0).then(|| self.sum / self.windows as f64)
+    }
+}
+
+// Jitter of every phasor of a stream, published as metrics.
+pub struct JitterMonitor {
+    window: usize,
+    source: String,
+    metrics: Option<Arc<Metrics>>,
+    channels: BTreeMap<String, (AngleJitter, JitterStats)>,
+}
+
+impl JitterMonitor {
+    pub fn new(window: usize, source: &str) -> Self {
+        JitterMonitor {
+            window,
+            source: source.to_string(),
+            metrics: None,
+            channels: BTreeMap::new(),
+        }
+    }
+
+    // Set the pmu_angle_jitter_rad gauge of each channel, labeled with the
+    // source and channel.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        metrics.describe(
+            JITTER_GAUGE,
+            "Standard deviation of the detrended phasor angle, radians",
+        );
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn push(&mut self, channel: &str, timestamp_us: i64, angle: f64) -> Option<f64> {
+        let window = self.window;
+        let (rolling, stats) = self
+            .channels
+            .entry(channel.to_string())
+            .or_insert_with(|| (AngleJitter::new(window), JitterStats::default()));
+        let jitter = rolling.push(timestamp_us, angle);
+        stats.record(jitter);
+        if let (Some(metrics), Some(jitter)) = (&self.metrics, jitter) {
+            let labels = [("source", self.source.as_str()), ("channel", channel)];
+            metrics.set_gauge(JITTER_GAUGE, &labels, jitter);
+        }
+        jitter
+    }
+
+    // Follow every phasor of a batch.
+    pub fn push_batch(&mut self, batch: &RecordBatch) {
+        let Some(timestamps) = batch
+            .column_by_name("timestamp")
+            .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+        else {
+            return;
+        };
+        let timestamps = timestamps.values().to_vec();
+        for (channel, _, angles) in phasor_angles(batch) {
+            for (timestamp_us, angle) in timestamps.iter().zip(angles) {
+                self.push(&channel, *timestamp_us, angle);
+            }
+        }
+    }
+
+    pub fn stats(&self, channel: &str) -> Option<&JitterStats> {
+        self.channels.get(channel).map(|(_, stats)| stats)
+    }
+
+    // Statistics by channel.
+    pub fn report(&self) -> BTreeMap<String, JitterStats> {
+        self.channels
+            .iter()
+            .map(|(channel, (_, stats))| (channel.clone(), *stats))
+            .collect()
+    }
+}
+
+// Angles in radians of the phasors of a batch, with the channel and station
+// of each, whether held as magnitude and angle, real and imaginary parts or
+// complex columns.
+pub fn phasor_angles(batch: &RecordBatch) -> Vec<(String, String, Vec<f64>)> {
+    let schema = batch.schema();
+    let mut parts: BTreeMap<String, (String, HashMap<String, Vec<f64>>)> = BTreeMap::new();
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        let meta = field.metadata();
+        let Some(component) = meta.get(META_COMPONENT) else {
+            continue;
+        };
+        let channel = meta.get(META_CHANNEL).unwrap_or(field.name()).clone();
+        let station = meta.get(META_STATION).cloned().unwrap_or_default();
+        let (component, values) = if component == COMPLEX_COMPONENT {
+            // [real, imaginary] pairs, scaled
+            let Some(list) = column.as_any().downcast_ref::<FixedSizeListArray>() else {
+                continue;
+            };
+            let Some(pairs) = list.values().as_any().downcast_ref::<Float64Array>() else {
+                continue;
+            };
+            let angles = pairs
+                .values()
+                .chunks_exact(2)
+                .map(|pair| pair[1].atan2(pair[0]))
+                .collect();
+            ("angle".to_string(), angles)
+        } else {
+            let Ok(values) = cast(column, &DataType::Float64) else {
+                continue;
+            };
+            let Some(values) = values.as_any().downcast_ref::<Float64Array>() else {
+                continue;
+            };
+            let scale = meta
+                .get(META_SCALE)
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(1.0);
+            let values = values.values().iter().map(|v| v * scale).collect();
+            (component.clone(), values)
+        };
+        parts
+            .entry(channel)
+            .or_insert_with(|| (station, HashMap::new()))
+            .1
+            .insert(component, values);
+    }
+    parts
+        .into_iter()
+        .filter_map(|(channel, (station, parts))| {
+            let angles = match (parts.get("real"), parts.get("imaginary")) {
+                (Some(real), Some(imaginary)) => real
+                    .iter()
+                    .zip(imaginary)
+                    .map(|(x, y)| y.atan2(*x))
+                    .collect(),
+                _ => parts.get("angle")?.clone(),
+            };
+            Some((channel, station, angles))
+        })
+        .collect()
+}
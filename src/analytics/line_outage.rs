@@ -0,0 +1,202 @@
+// Line outage detection from phase angle differences between stations.
+//
+// For each configured pair of stations the angle difference is followed over
+// a short window. A change of more than the threshold within the window is
+// a step, which a slow drift in loading does not produce but a change in
+// network topology does. Steps on several pairs close together in time are
+// reported as a single probable topology change.
+use crate::events::{Event, EventBus, EventKind, Severity};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StationPair {
+    pub from: String,
+    pub to: String,
+}
+
+impl StationPair {
+    pub fn new(from: &str, to: &str) -> Self {
+        StationPair {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        format!("{}-{}", self.from, self.to)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineOutageConfig {
+    pub threshold_deg: f64, // Angle difference change that is a step
+    pub window: f64,        // Seconds the change has to happen within
+    pub correlation: f64,   // Seconds after a step that steps on other pairs are grouped with it
+}
+
+impl Default for LineOutageConfig {
+    fn default() -> Self {
+        LineOutageConfig {
+            threshold_deg: 5.0,
+            window: 0.5,
+            correlation: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AngleStep {
+    pub pair: String,
+    pub timestamp_us: i64,
+    pub before_deg: f64, // Angle difference at the start of the window
+    pub after_deg: f64,
+}
+
+impl AngleStep {
+    pub fn change_deg(&self) -> f64 {
+        wrap_deg(self.after_deg - self.before_deg)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopologyChange {
+    pub timestamp_us: i64, // First step
+    pub steps: Vec<AngleStep>,
+}
+
+impl TopologyChange {
+    pub fn to_event(&self) -> Event {
+        let pairs: Vec<String> = self.steps.iter().map(|s| s.pair.clone()).collect();
+        let largest = self
+            .steps
+            .iter()
+            .map(|s| s.change_deg().abs())
+            .fold(0.0, f64::max);
+        Event::new(
+            self.timestamp_us,
+            EventKind::TopologyChange,
+            &pairs.join(","),
+            format!(
+                "Angle step on {} pair(s) {}, largest {:.1} deg",
+                self.steps.len(),
+                pairs.join(", "),
+                largest
+            ),
+        )
+        .with_severity(Severity::Warning)
+        .with_value("pairs", self.steps.len() as f64)
+        .with_value("largest_step_deg", largest)
+    }
+}
+
+struct PairState {
+    pair: StationPair,
+    history: VecDeque<(i64, f64)>, // Angle difference in degrees
+    quiet_until: i64,              // No new step before this, after a step
+}
+
+pub struct LineOutageDetector {
+    config: LineOutageConfig,
+    pairs: Vec<PairState>,
+    pending: Option<TopologyChange>,
+    bus: Option<EventBus>,
+}
+
+impl LineOutageDetector {
+    pub fn new(config: LineOutageConfig, pairs: Vec<StationPair>) -> Self {
+        LineOutageDetector {
+            config,
+            pairs: pairs
+                .into_iter()
+                .map(|pair| PairState {
+                    pair,
+                    history: VecDeque::new(),
+                    quiet_until: i64::MIN,
+                })
+                .collect(),
+            pending: None,
+            bus: None,
+        }
+    }
+
+    pub fn with_event_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    // Add the angles (radians) of the stations at one timestamp. Pairs with a
+    // station missing are skipped. Returns a topology change once the
+    // correlation time after its first step has passed.
+    pub fn push(
+        &mut self,
+        timestamp_us: i64,
+        angles: &HashMap<String, f64>,
+    ) -> Option<TopologyChange> {
+        let window_us = (self.config.window * 1e6) as i64;
+        for state in self.pairs.iter_mut() {
+            let (Some(from), Some(to)) = (angles.get(&state.pair.from), angles.get(&state.pair.to))
+            else {
+                continue;
+            };
+            let difference = wrap_deg((from - to).to_degrees());
+            while state
+                .history
+                .front()
+                .is_some_and(|(t, _)| *t < timestamp_us - window_us)
+            {
+                state.history.pop_front();
+            }
+            let before = state.history.front().map(|(_, d)| *d);
+            state.history.push_back((timestamp_us, difference));
+            let Some(before) = before else {
+                continue;
+            };
+            if timestamp_us < state.quiet_until
+                || wrap_deg(difference - before).abs() <= self.config.threshold_deg
+            {
+                continue;
+            }
+            let step = AngleStep {
+                pair: state.pair.name(),
+                timestamp_us,
+                before_deg: before,
+                after_deg: difference,
+            };
+            // Let the window fill with the new level before looking again
+            state.quiet_until = timestamp_us + window_us;
+            state.history.clear();
+            state.history.push_back((timestamp_us, difference));
+            self.pending
+                .get_or_insert_with(|| TopologyChange {
+                    timestamp_us,
+                    steps: Vec::new(),
+                })
+                .steps
+                .push(step);
+        }
+
+        let correlation_us = (self.config.correlation * 1e6) as i64;
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|change| timestamp_us - change.timestamp_us >= correlation_us)
+        {
+            return self.flush();
+        }
+        None
+    }
+
+    // Report the pending topology change, if any, without waiting.
+    pub fn flush(&mut self) -> Option<TopologyChange> {
+        let change = self.pending.take()?;
+        if let Some(bus) = &self.bus {
+            bus.publish(change.to_event());
+        }
+        Some(change)
+    }
+}
+
+// Angle in degrees to -180..180.
+fn wrap_deg(angle: f64) -> f64 {
+    (angle + 180.0).rem_euclid(360.0) - 180.0
+}
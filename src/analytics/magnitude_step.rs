@@ -0,0 +1,205 @@
+// Step changes in phasor magnitudes.
+//
+// Capacitor banks switching and transformer taps changing move a voltage
+// magnitude by a fixed amount within a cycle or two, and it stays there.
+// Unlike sag and swell limits, which look at the level, a step is a change
+// of level: the mean magnitude over a post window against the mean over a
+// pre window, with a short gap between them left for the switching
+// transient. A step is reported when the change is at least min_step of the
+// pre level and both windows are steady, their standard deviation at most
+// steadiness times the change. Windows are only steady on both sides of a
+// step that falls in the gap, which times the step, and never on a ramp or
+// an oscillation, whose windows move as much as their means.
+//
+// After a step the pre window fills with the new level before the channel
+// is looked at again. A missing or non-finite value starts the windows over.
+use crate::arrow_utils::{channel_values, META_CHANNEL, META_COMPONENT};
+use crate::events::{Event, EventBus, EventKind};
+use arrow::array::{Array, TimestampMicrosecondArray};
+use arrow::record_batch::RecordBatch;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MagnitudeStepConfig {
+    pub pre: usize,      // Samples averaged before the step
+    pub gap: usize,      // Samples left out for the transient
+    pub post: usize,     // Samples averaged after the step
+    pub min_step: f64,   // Change relative to the pre level that is a step
+    pub steadiness: f64, // Largest window standard deviation, relative to the change
+}
+
+impl MagnitudeStepConfig {
+    // Windows of 0.5 s around a gap of 0.1 s at the given rate, steps of
+    // 0.5% and more.
+    pub fn new(sample_rate: f64) -> Self {
+        MagnitudeStepConfig {
+            pre: (0.5 * sample_rate).round().max(3.0) as usize,
+            gap: (0.1 * sample_rate).round().max(1.0) as usize,
+            post: (0.5 * sample_rate).round().max(3.0) as usize,
+            min_step: 0.005,
+            steadiness: 0.1,
+        }
+    }
+
+    pub fn with_windows(mut self, pre: usize, gap: usize, post: usize) -> Self {
+        self.pre = pre.max(2);
+        self.gap = gap;
+        self.post = post.max(2);
+        self
+    }
+
+    pub fn with_min_step(mut self, min_step: f64) -> Self {
+        self.min_step = min_step;
+        self
+    }
+
+    pub fn with_steadiness(mut self, steadiness: f64) -> Self {
+        self.steadiness = steadiness;
+        self
+    }
+
+    fn span(&self) -> usize {
+        self.pre + self.gap + self.post
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MagnitudeStep {
+    pub channel: String,
+    pub timestamp_us: i64, // First sample of the post window
+    pub before: f64,       // Mean of the pre window
+    pub after: f64,        // Mean of the post window
+}
+
+impl MagnitudeStep {
+    pub fn change(&self) -> f64 {
+        self.after - self.before
+    }
+
+    // Change relative to the level before, 0.01 = 1%.
+    pub fn relative(&self) -> f64 {
+        self.change() / self.before.abs()
+    }
+
+    pub fn to_event(&self, source: &str) -> Event {
+        Event::new(
+            self.timestamp_us,
+            EventKind::MagnitudeStep,
+            source,
+            format!(
+                "Magnitude step on {} of {:+.2}% ({:.1} to {:.1})",
+                self.channel,
+                self.relative() * 100.0,
+                self.before,
+                self.after
+            ),
+        )
+        .with_value("step", self.change())
+        .with_value("step_percent", self.relative() * 100.0)
+        .with_value("before", self.before)
+        .with_value("after", self.after)
+    }
+}
+
+pub struct MagnitudeStepDetector {
+    config: MagnitudeStepConfig,
+    source: String,
+    bus: Option<EventBus>,
+    channels: BTreeMap<String, VecDeque<(i64, f64)>>,
+}
+
+impl MagnitudeStepDetector {
+    pub fn new(config: MagnitudeStepConfig, source: &str) -> Self {
+        MagnitudeStepDetector {
+            config,
+            source: source.to_string(),
+            bus: None,
+            channels: BTreeMap::new(),
+        }
+    }
+
+    // Publish each step on a bus as well.
+    pub fn with_event_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    // Add a magnitude of a channel. Returns the step whose post window it
+    // completes, if any.
+    pub fn push(&mut self, channel: &str, timestamp_us: i64, value: f64) -> Option<MagnitudeStep> {
+        let config = self.config;
+        let history = self.channels.entry(channel.to_string()).or_default();
+        if !value.is_finite() {
+            history.clear();
+            return None;
+        }
+        history.push_back((timestamp_us, value));
+        if history.len() > config.span() {
+            history.pop_front();
+        }
+        if history.len() < config.span() {
+            return None;
+        }
+
+        let values: Vec<f64> = history.iter().map(|(_, value)| *value).collect();
+        let (before, pre_spread) = mean_and_deviation(&values[..config.pre]);
+        let (after, post_spread) = mean_and_deviation(&values[config.pre + config.gap..]);
+        let change = (after - before).abs();
+        if change < config.min_step * before.abs()
+            || pre_spread > config.steadiness * change
+            || post_spread > config.steadiness * change
+        {
+            return None;
+        }
+        let step = MagnitudeStep {
+            channel: channel.to_string(),
+            timestamp_us: history[config.pre + config.gap].0,
+            before,
+            after,
+        };
+        // The post window is the new level to look for the next step from
+        history.drain(..config.pre + config.gap);
+        if let Some(bus) = &self.bus {
+            bus.publish(step.to_event(&self.source));
+        }
+        Some(step)
+    }
+
+    // Follow the magnitudes of every phasor of a batch.
+    pub fn push_batch(&mut self, batch: &RecordBatch) -> Vec<MagnitudeStep> {
+        let Some(timestamps) = batch
+            .column_by_name("timestamp")
+            .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+        else {
+            return Vec::new();
+        };
+        let schema = batch.schema();
+        let phasors: BTreeSet<&String> = schema
+            .fields()
+            .iter()
+            .filter(|field| field.metadata().contains_key(META_COMPONENT))
+            .filter_map(|field| field.metadata().get(META_CHANNEL))
+            .collect();
+        let mut steps = Vec::new();
+        for channel in phasors {
+            let Some(magnitudes) = channel_values(batch, channel) else {
+                continue;
+            };
+            for row in 0..batch.num_rows() {
+                let value = match magnitudes.is_valid(row) {
+                    true => magnitudes.value(row),
+                    false => f64::NAN,
+                };
+                steps.extend(self.push(channel, timestamps.value(row), value));
+            }
+        }
+        steps
+    }
+}
+
+fn mean_and_deviation(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    (mean, variance.sqrt())
+}
@@ -0,0 +1,52 @@
+// Evaluation of PMU measurements.
+//
+// accuracy and reference, which the simulator and virtual PMUs build on,
+// are always there, the rest needs the analytics feature.
+pub mod accuracy;
+#[cfg(feature = "analytics")]
+pub mod angle_reference;
+#[cfg(feature = "analytics")]
+pub mod anomaly;
+#[cfg(feature = "analytics")]
+pub mod coherency;
+#[cfg(feature = "analytics")]
+pub mod compliance;
+#[cfg(feature = "analytics")]
+pub mod frequency_event;
+#[cfg(feature = "analytics")]
+pub mod frequency_response;
+#[cfg(feature = "analytics")]
+pub mod jitter;
+#[cfg(feature = "analytics")]
+pub mod line_outage;
+#[cfg(feature = "analytics")]
+pub mod magnitude_step;
+#[cfg(feature = "analytics")]
+pub mod oscillation;
+pub mod reference;
+#[cfg(feature = "analytics")]
+pub mod spectrogram;
+#[cfg(feature = "analytics")]
+pub mod time_skew;
+#[cfg(feature = "analytics")]
+pub mod trigger;
+#[cfg(feature = "analytics")]
+pub mod ufls;
+#[cfg(feature = "analytics")]
+pub mod voltage_stability;
+
+pub use accuracy::{frequency_error, rocof_error, tve, Phasor};
+
+// Least squares slope of y over x, None without spread in x.
+#[cfg(feature = "analytics")]
+pub(crate) fn least_squares_slope(points: &[(f64, f64)]) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mx = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let my = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let sxy: f64 = points.iter().map(|(x, y)| (x - mx) * (y - my)).sum();
+    let sxx: f64 = points.iter().map(|(x, _)| (x - mx) * (x - mx)).sum();
+    (sxx > 1e-12 * n).then(|| sxy / sxx)
+}
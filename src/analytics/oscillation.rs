@@ -0,0 +1,362 @@
+// Electromechanical oscillation modes and their damping.
+//
+// Modes are identified per window with Prony's method: a linear prediction
+// model is fitted to the detrended samples, the roots of its characteristic
+// polynomial give each mode's frequency and damping, and a least squares fit
+// of the samples to those roots gives its amplitude. Modes outside the
+// configured band or too small to matter are discarded.
+//
+// The ModeTracker follows modes from one window to the next by nearest
+// frequency, giving a time series of frequency and damping per mode, and
+// raises an alarm when a mode's damping ratio drops below a threshold.
+use rustfft::num_complex::Complex;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+type C64 = Complex<f64>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OscillationConfig {
+    pub window: usize,      // Samples per identification
+    pub step: usize,        // Samples between identifications
+    pub order: usize,       // Linear prediction order, at least twice the expected modes
+    pub min_frequency: f64, // Hz
+    pub max_frequency: f64, // Hz
+    pub min_amplitude: f64, // In channel units
+}
+
+impl Default for OscillationConfig {
+    // 20 s windows every 5 s at 30 frames/s, inter-area and local modes.
+    fn default() -> Self {
+        OscillationConfig {
+            window: 600,
+            step: 150,
+            order: 8,
+            min_frequency: 0.1,
+            max_frequency: 2.5,
+            min_amplitude: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mode {
+    pub frequency: f64, // Hz
+    pub damping: f64,   // Damping ratio, negative when growing
+    pub amplitude: f64, // Initial amplitude in the window, channel units
+}
+
+// Modes in one window of samples taken at sample_rate, largest first.
+pub fn identify_modes(samples: &[f64], sample_rate: f64, config: &OscillationConfig) -> Vec<Mode> {
+    let p = config.order.max(2);
+    let n = samples.len();
+    if n < 2 * p + 1 {
+        return Vec::new();
+    }
+    let mean = samples.iter().sum::<f64>() / n as f64;
+    let x: Vec<f64> = samples.iter().map(|v| v - mean).collect();
+
+    // x[k] = -(a1 x[k-1] + ... + ap x[k-p]), least squares over the window
+    let mut normal = vec![vec![C64::default(); p]; p];
+    let mut rhs = vec![C64::default(); p];
+    for k in p..n {
+        for i in 0..p {
+            for j in 0..p {
+                normal[i][j] += x[k - 1 - i] * x[k - 1 - j];
+            }
+            rhs[i] -= x[k - 1 - i] * x[k];
+        }
+    }
+    let Some(coefficients) = solve(normal, rhs) else {
+        return Vec::new();
+    };
+    let roots = polynomial_roots(&coefficients);
+
+    // Amplitudes: x[k] = sum b_i z_i^k
+    let mut normal = vec![vec![C64::default(); p]; p];
+    let mut rhs = vec![C64::default(); p];
+    let mut powers = vec![C64::new(1.0, 0.0); p];
+    for value in &x {
+        for i in 0..p {
+            for j in 0..p {
+                normal[i][j] += powers[i].conj() * powers[j];
+            }
+            rhs[i] += powers[i].conj() * value;
+        }
+        for (power, root) in powers.iter_mut().zip(&roots) {
+            *power *= root;
+        }
+    }
+    let Some(residues) = solve(normal, rhs) else {
+        return Vec::new();
+    };
+
+    let mut modes: Vec<Mode> = roots
+        .iter()
+        .zip(&residues)
+        .filter(|(root, _)| root.im > 0.0)
+        .map(|(root, residue)| {
+            let s = root.ln() * sample_rate;
+            Mode {
+                frequency: s.im / (2.0 * PI),
+                damping: -s.re / s.norm(),
+                // Conjugate pair
+                amplitude: 2.0 * residue.norm(),
+            }
+        })
+        .filter(|mode| {
+            mode.frequency >= config.min_frequency
+                && mode.frequency <= config.max_frequency
+                && mode.amplitude >= config.min_amplitude
+        })
+        .collect();
+    modes.sort_by(|a, b| b.amplitude.total_cmp(&a.amplitude));
+    modes
+}
+
+// Gaussian elimination with partial pivoting, None when singular.
+fn solve(mut a: Vec<Vec<C64>>, mut b: Vec<C64>) -> Option<Vec<C64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|i, j| a[*i][col].norm().total_cmp(&a[*j][col].norm()))?;
+        if a[pivot][col].norm() < 1e-300 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..n {
+            let factor = a[row][col] / a[col][col];
+            let (upper, lower) = a.split_at_mut(row);
+            for (target, value) in lower[0][col..].iter_mut().zip(&upper[col][col..]) {
+                *target -= factor * value;
+            }
+            let value = b[col];
+            b[row] -= factor * value;
+        }
+    }
+    let mut x = vec![C64::default(); n];
+    for row in (0..n).rev() {
+        let mut sum = b[row];
+        for k in row + 1..n {
+            sum -= a[row][k] * x[k];
+        }
+        x[row] = sum / a[row][row];
+    }
+    Some(x)
+}
+
+// Roots of z^p + c1 z^(p-1) + ... + cp (Durand-Kerner).
+fn polynomial_roots(coefficients: &[C64]) -> Vec<C64> {
+    let p = coefficients.len();
+    let eval = |z: C64| {
+        coefficients
+            .iter()
+            .fold(C64::new(1.0, 0.0), |acc, c| acc * z + c)
+    };
+    let seed = C64::new(0.4, 0.9);
+    let mut roots: Vec<C64> = (0..p).map(|k| seed.powu(k as u32)).collect();
+    for _ in 0..1000 {
+        let mut change: f64 = 0.0;
+        for i in 0..p {
+            let mut denominator = C64::new(1.0, 0.0);
+            for j in 0..p {
+                if i != j {
+                    denominator *= roots[i] - roots[j];
+                }
+            }
+            let delta = eval(roots[i]) / denominator;
+            roots[i] -= delta;
+            change = change.max(delta.norm());
+        }
+        if change < 1e-14 {
+            break;
+        }
+    }
+    roots
+}
+
+// Identifies modes over a sliding window of a channel.
+pub struct OscillationDetector {
+    config: OscillationConfig,
+    sample_rate: f64,
+    samples: VecDeque<(i64, f64)>,
+    until_next: usize,
+}
+
+impl OscillationDetector {
+    pub fn new(config: OscillationConfig, sample_rate: f64) -> Self {
+        OscillationDetector {
+            config,
+            sample_rate,
+            samples: VecDeque::with_capacity(config.window),
+            until_next: config.window.max(1),
+        }
+    }
+
+    // Add a sample. Each step once the window is full, returns the timestamp
+    // of the window's last sample and the modes identified in it.
+    pub fn push(&mut self, timestamp_us: i64, value: f64) -> Option<(i64, Vec<Mode>)> {
+        if self.samples.len() == self.config.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((timestamp_us, value));
+        self.until_next -= 1;
+        if self.until_next > 0 {
+            return None;
+        }
+        self.until_next = self.config.step.max(1);
+        let values: Vec<f64> = self.samples.iter().map(|(_, v)| *v).collect();
+        Some((
+            timestamp_us,
+            identify_modes(&values, self.sample_rate, &self.config),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModePoint {
+    pub timestamp_us: i64,
+    pub frequency: f64,
+    pub damping: f64,
+    pub amplitude: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeTrack {
+    pub id: u32,
+    pub points: Vec<ModePoint>,
+    missed: usize,     // Windows in a row without a matching mode
+    low_damping: bool, // Alarm raised and not yet cleared
+}
+
+impl ModeTrack {
+    pub fn last(&self) -> &ModePoint {
+        // Tracks are created with a point
+        self.points.last().unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DampingAlarm {
+    pub mode_id: u32,
+    pub timestamp_us: i64,
+    pub frequency: f64,
+    pub damping: f64,
+}
+
+pub struct ModeTracker {
+    tolerance_hz: f64,  // Largest frequency change between windows for the same mode
+    alarm_damping: f64, // Damping ratio below which a mode alarms
+    max_missed: usize,  // Windows a mode may go unseen before its track ends
+    next_id: u32,
+    tracks: Vec<ModeTrack>,
+    finished: Vec<ModeTrack>,
+}
+
+impl ModeTracker {
+    pub fn new(tolerance_hz: f64, alarm_damping: f64) -> Self {
+        ModeTracker {
+            tolerance_hz,
+            alarm_damping,
+            max_missed: 2,
+            next_id: 0,
+            tracks: Vec::new(),
+            finished: Vec::new(),
+        }
+    }
+
+    pub fn with_max_missed(mut self, max_missed: usize) -> Self {
+        self.max_missed = max_missed;
+        self
+    }
+
+    // Modes followed at the moment.
+    pub fn tracks(&self) -> &[ModeTrack] {
+        &self.tracks
+    }
+
+    // Tracks that ended because their mode was no longer seen.
+    pub fn finished(&self) -> &[ModeTrack] {
+        &self.finished
+    }
+
+    // Match the modes of a window to the current tracks, closest frequencies
+    // first. Returns an alarm for each mode whose damping fell below the
+    // threshold; it is raised again only after the damping has recovered.
+    pub fn update(&mut self, timestamp_us: i64, modes: &[Mode]) -> Vec<DampingAlarm> {
+        let mut pairs = Vec::new();
+        for (t, track) in self.tracks.iter().enumerate() {
+            for (m, mode) in modes.iter().enumerate() {
+                let distance = (track.last().frequency - mode.frequency).abs();
+                if distance <= self.tolerance_hz {
+                    pairs.push((distance, t, m));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut track_matched = vec![false; self.tracks.len()];
+        let mut mode_matched = vec![None; modes.len()];
+        for (_, t, m) in pairs {
+            if !track_matched[t] && mode_matched[m].is_none() {
+                track_matched[t] = true;
+                mode_matched[m] = Some(t);
+            }
+        }
+
+        for (m, mode) in modes.iter().enumerate() {
+            let point = ModePoint {
+                timestamp_us,
+                frequency: mode.frequency,
+                damping: mode.damping,
+                amplitude: mode.amplitude,
+            };
+            match mode_matched[m] {
+                Some(t) => {
+                    self.tracks[t].points.push(point);
+                    self.tracks[t].missed = 0;
+                }
+                None => {
+                    self.tracks.push(ModeTrack {
+                        id: self.next_id,
+                        points: vec![point],
+                        missed: 0,
+                        low_damping: false,
+                    });
+                    self.next_id += 1;
+                    track_matched.push(true);
+                }
+            }
+        }
+
+        let mut alarms = Vec::new();
+        for (track, matched) in self.tracks.iter_mut().zip(&track_matched) {
+            if !matched {
+                track.missed += 1;
+                continue;
+            }
+            let last = *track.last();
+            if last.damping < self.alarm_damping {
+                if !track.low_damping {
+                    track.low_damping = true;
+                    alarms.push(DampingAlarm {
+                        mode_id: track.id,
+                        timestamp_us,
+                        frequency: last.frequency,
+                        damping: last.damping,
+                    });
+                }
+            } else {
+                track.low_damping = false;
+            }
+        }
+
+        let (ended, active): (Vec<_>, Vec<_>) = self
+            .tracks
+            .drain(..)
+            .partition(|track| track.missed > self.max_missed);
+        self.tracks = active;
+        self.finished.extend(ended);
+        alarms
+    }
+}
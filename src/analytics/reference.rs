@@ -0,0 +1,171 @@
+// Exact reference phasors for calibration, per the C37.118.1 test signals.
+//
+// A ReferenceSignal describes the signal in closed form relative to a
+// nominal-frequency rotating reference: magnitude in per unit, angle in
+// radians, frequency deviation and ROCOF. Off-nominal frequency, amplitude
+// and phase modulation, frequency ramps and magnitude/phase steps can be
+// combined. Values are computed at the nominal reporting instants, so they
+// can be compared directly with a device's frames, or fed to the simulator
+// through Scenario::signal.
+use crate::analytics::accuracy::{Phasor, PmuMeasurement};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+fn default_magnitude() -> f64 {
+    1.0
+}
+
+// Cosine modulation of depth k at frequency Hz.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Modulation {
+    pub depth: f64,
+    pub frequency: f64,
+}
+
+// Linear frequency change of rate Hz/s from at, for duration seconds (forever when not set).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrequencyRamp {
+    pub at: f64,
+    pub rate: f64,
+    #[serde(default)]
+    pub duration: Option<f64>,
+}
+
+// Step of size applied from at onwards.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Step {
+    pub at: f64,
+    pub size: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReferenceSignal {
+    #[serde(default = "default_magnitude")]
+    pub magnitude: f64, // Per unit
+    #[serde(default)]
+    pub phase_deg: f64,
+    #[serde(default)]
+    pub frequency_offset: f64, // Hz from nominal
+    // Magnitude is multiplied by 1 + depth cos(wt)
+    #[serde(default)]
+    pub amplitude_modulation: Option<Modulation>,
+    // Angle changes by depth cos(wt - pi), depth in radians
+    #[serde(default)]
+    pub phase_modulation: Option<Modulation>,
+    #[serde(default)]
+    pub ramp: Option<FrequencyRamp>,
+    // Relative magnitude step (0.1 = +10%)
+    #[serde(default)]
+    pub magnitude_step: Option<Step>,
+    // Angle step in degrees
+    #[serde(default)]
+    pub phase_step: Option<Step>,
+}
+
+impl Default for ReferenceSignal {
+    fn default() -> Self {
+        ReferenceSignal {
+            magnitude: 1.0,
+            phase_deg: 0.0,
+            frequency_offset: 0.0,
+            amplitude_modulation: None,
+            phase_modulation: None,
+            ramp: None,
+            magnitude_step: None,
+            phase_step: None,
+        }
+    }
+}
+
+// The signal at one instant.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReferenceValue {
+    pub magnitude: f64,        // Per unit
+    pub angle: f64,            // Radians, not wrapped
+    pub frequency_offset: f64, // Hz from nominal
+    pub rocof: f64,            // Hz/s
+}
+
+impl ReferenceValue {
+    pub fn phasor(&self, magnitude: f64) -> Phasor {
+        Phasor::from_polar(self.magnitude * magnitude, self.angle)
+    }
+}
+
+impl ReferenceSignal {
+    // Value t seconds after the start of the signal.
+    pub fn at(&self, t: f64) -> ReferenceValue {
+        let mut value = ReferenceValue {
+            magnitude: self.magnitude,
+            angle: self.phase_deg.to_radians() + 2.0 * PI * self.frequency_offset * t,
+            frequency_offset: self.frequency_offset,
+            rocof: 0.0,
+        };
+
+        if let Some(Modulation { depth, frequency }) = self.amplitude_modulation {
+            value.magnitude *= 1.0 + depth * (2.0 * PI * frequency * t).cos();
+        }
+        if let Some(Modulation { depth, frequency }) = self.phase_modulation {
+            let w = 2.0 * PI * frequency;
+            value.angle += depth * (w * t - PI).cos();
+            value.frequency_offset -= depth * w / (2.0 * PI) * (w * t - PI).sin();
+            value.rocof -= depth * w * w / (2.0 * PI) * (w * t - PI).cos();
+        }
+        if let Some(ramp) = self.ramp {
+            let elapsed = (t - ramp.at).max(0.0);
+            let ramping = ramp
+                .duration
+                .map_or(elapsed, |duration| elapsed.min(duration));
+            // Angle is the integral of the frequency deviation
+            value.angle += PI * ramp.rate * ramping * ramping
+                + 2.0 * PI * ramp.rate * ramping * (elapsed - ramping);
+            value.frequency_offset += ramp.rate * ramping;
+            if t >= ramp.at && ramp.duration.is_none_or(|duration| elapsed < duration) {
+                value.rocof += ramp.rate;
+            }
+        }
+        if let Some(step) = self.magnitude_step.filter(|step| t >= step.at) {
+            value.magnitude *= 1.0 + step.size;
+        }
+        if let Some(step) = self.phase_step.filter(|step| t >= step.at) {
+            value.angle += step.size.to_radians();
+        }
+        value
+    }
+
+    // Measurements at count reporting instants from start_us, with phasors
+    // sets of three phases 120 degrees apart as the simulator sends them.
+    pub fn measurements(
+        &self,
+        start_us: i64,
+        data_rate: i16,
+        count: usize,
+        nominal_hz: f64,
+        magnitude: f64,
+        phasors: usize,
+    ) -> Vec<PmuMeasurement> {
+        let period_us = if data_rate > 0 {
+            1_000_000.0 / data_rate as f64
+        } else {
+            -(data_rate as f64) * 1_000_000.0
+        };
+        (0..count)
+            .map(|k| {
+                let elapsed_us = (k as f64 * period_us).round() as i64;
+                let value = self.at(elapsed_us as f64 / 1_000_000.0);
+                PmuMeasurement {
+                    timestamp_us: start_us + elapsed_us,
+                    stat: 0,
+                    phasors: (0..phasors)
+                        .map(|p| {
+                            let angle = value.angle - (p % 3) as f64 * 2.0 * PI / 3.0;
+                            Phasor::from_polar(value.magnitude * magnitude, angle)
+                        })
+                        .collect(),
+                    frequency: nominal_hz + value.frequency_offset,
+                    rocof: value.rocof,
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,220 @@
+// Rolling FFT spectrograms of a channel (frequency, phasor magnitude, ...).
+//
+// Samples are taken in windows of a fixed number of reports, successive
+// windows overlapping by a configurable number of samples. Each window is
+// detrended (its mean removed), tapered and transformed; the result is the
+// one sided power per frequency bin, in mean square units of the channel
+// (a sine of amplitude A in a single bin shows A^2/2).
+//
+// Spectrograms are written as Arrow batches with one row per window and bin,
+// (time, freq_bin, power), time being the centre of the window.
+use crate::analytics::accuracy::PmuMeasurement;
+use arrow::array::{ArrayRef, Float64Array, TimestampMicrosecondArray};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// Schema metadata key naming the channel of a spectrogram batch.
+pub const META_SPECTROGRAM_CHANNEL: &str = "pmu.channel";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WindowFunction {
+    Rectangular,
+    #[default]
+    Hann,
+}
+
+impl WindowFunction {
+    fn coefficients(&self, len: usize) -> Vec<f64> {
+        match self {
+            WindowFunction::Rectangular => vec![1.0; len],
+            WindowFunction::Hann => (0..len)
+                .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f64 / len as f64).cos())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpectrogramConfig {
+    pub window: usize,  // Samples per FFT
+    pub overlap: usize, // Samples shared by successive windows, less than window
+    pub window_function: WindowFunction,
+}
+
+impl SpectrogramConfig {
+    pub fn new(window: usize, overlap: usize) -> Self {
+        SpectrogramConfig {
+            window: window.max(2),
+            overlap: overlap.min(window.max(2) - 1),
+            window_function: WindowFunction::default(),
+        }
+    }
+
+    pub fn with_window_function(mut self, window_function: WindowFunction) -> Self {
+        self.window_function = window_function;
+        self
+    }
+
+    fn hop(&self) -> usize {
+        self.window - self.overlap
+    }
+}
+
+// Power spectrum of one window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpectrumColumn {
+    pub timestamp_us: i64, // Centre of the window
+    pub power: Vec<f64>,   // Bins 0 to window / 2
+}
+
+// Streaming spectrogram of one channel sampled at a fixed rate.
+pub struct RollingSpectrogram {
+    config: SpectrogramConfig,
+    sample_rate: f64,
+    coefficients: Vec<f64>,
+    fft: Arc<dyn Fft<f64>>,
+    samples: VecDeque<(i64, f64)>,
+    until_next: usize, // Samples to add before the next window is complete
+}
+
+impl RollingSpectrogram {
+    pub fn new(config: SpectrogramConfig, sample_rate: f64) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(config.window);
+        RollingSpectrogram {
+            config,
+            sample_rate,
+            coefficients: config.window_function.coefficients(config.window),
+            fft,
+            samples: VecDeque::with_capacity(config.window),
+            until_next: config.window,
+        }
+    }
+
+    // Centre frequency of each bin in Hz.
+    pub fn bin_frequencies(&self) -> Vec<f64> {
+        let resolution = self.sample_rate / self.config.window as f64;
+        (0..=self.config.window / 2)
+            .map(|k| k as f64 * resolution)
+            .collect()
+    }
+
+    // Add a sample, returns a column each time a window is complete.
+    pub fn push(&mut self, timestamp_us: i64, value: f64) -> Option<SpectrumColumn> {
+        if self.samples.len() == self.config.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((timestamp_us, value));
+        self.until_next -= 1;
+        if self.until_next > 0 {
+            return None;
+        }
+        self.until_next = self.config.hop();
+        Some(self.transform())
+    }
+
+    fn transform(&self) -> SpectrumColumn {
+        let n = self.config.window;
+        let mean = self.samples.iter().map(|(_, v)| v).sum::<f64>() / n as f64;
+        let mut buffer: Vec<Complex<f64>> = self
+            .samples
+            .iter()
+            .zip(&self.coefficients)
+            .map(|((_, v), w)| Complex::new((v - mean) * w, 0.0))
+            .collect();
+        self.fft.process(&mut buffer);
+
+        let gain: f64 = self.coefficients.iter().sum();
+        let power = buffer[..=n / 2]
+            .iter()
+            .enumerate()
+            .map(|(k, x)| {
+                // Energy of the negative frequencies folded in, except DC and Nyquist
+                let one_sided = if k == 0 || 2 * k == n { 1.0 } else { 2.0 };
+                one_sided * x.norm_sqr() / (gain * gain)
+            })
+            .collect();
+
+        let first = self.samples.front().map_or(0, |(t, _)| *t);
+        let last = self.samples.back().map_or(0, |(t, _)| *t);
+        SpectrumColumn {
+            timestamp_us: first + (last - first) / 2,
+            power,
+        }
+    }
+}
+
+// Spectrogram of a whole series of (timestamp, value) samples.
+pub fn spectrogram(
+    samples: &[(i64, f64)],
+    sample_rate: f64,
+    config: SpectrogramConfig,
+) -> (Vec<f64>, Vec<SpectrumColumn>) {
+    let mut rolling = RollingSpectrogram::new(config, sample_rate);
+    let columns = samples
+        .iter()
+        .filter_map(|(t, v)| rolling.push(*t, *v))
+        .collect();
+    (rolling.bin_frequencies(), columns)
+}
+
+// Frequency of a PMU's measurements, in Hz.
+pub fn frequency_series(measurements: &[PmuMeasurement]) -> Vec<(i64, f64)> {
+    measurements
+        .iter()
+        .map(|m| (m.timestamp_us, m.frequency))
+        .collect()
+}
+
+// Magnitude of one phasor of a PMU's measurements.
+pub fn magnitude_series(measurements: &[PmuMeasurement], phasor: usize) -> Vec<(i64, f64)> {
+    measurements
+        .iter()
+        .filter_map(|m| Some((m.timestamp_us, m.phasors.get(phasor)?.magnitude())))
+        .collect()
+}
+
+pub fn spectrogram_schema(channel: &str) -> Schema {
+    Schema::new(vec![
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            false,
+        ),
+        Field::new("freq_bin", DataType::Float64, false),
+        Field::new("power", DataType::Float64, false),
+    ])
+    .with_metadata(HashMap::from([(
+        META_SPECTROGRAM_CHANNEL.to_string(),
+        channel.to_string(),
+    )]))
+}
+
+// One row per column and bin, ready for any BatchSink.
+pub fn spectrogram_batch(
+    channel: &str,
+    bins: &[f64],
+    columns: &[SpectrumColumn],
+) -> Result<RecordBatch, ArrowError> {
+    let mut times = Vec::new();
+    let mut freqs = Vec::new();
+    let mut powers = Vec::new();
+    for column in columns {
+        for (freq, power) in bins.iter().zip(&column.power) {
+            times.push(column.timestamp_us);
+            freqs.push(*freq);
+            powers.push(*power);
+        }
+    }
+    let arrays: Vec<ArrayRef> = vec![
+        Arc::new(TimestampMicrosecondArray::from(times)),
+        Arc::new(Float64Array::from(freqs)),
+        Arc::new(Float64Array::from(powers)),
+    ];
+    RecordBatch::try_new(Arc::new(spectrogram_schema(channel)), arrays)
+}
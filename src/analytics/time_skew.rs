@@ -0,0 +1,160 @@
+// Residual time skew between the clocks of two streams.
+//
+// PMUs of one interconnection see the same system frequency, so the
+// frequency of two streams should move together at the same timestamps. A
+// clock off by some time shifts one stream's frequency against the other's.
+// estimate_skew resamples both series onto a common grid at the faster
+// reporting interval, correlates their sample to sample changes (the level
+// and slow drift say nothing about timing) for lags up to max_lag_secs, and
+// refines the lag of the peak with a parabola through it and its
+// neighbours, resolving well below a reporting interval.
+//
+// A positive skew means the second stream's timestamps are late: it reports
+// a change that much after the first. The peak correlation tells how much to
+// trust it; a calm period gives little to correlate. Stations far apart
+// also see electromechanical delays of tens of milliseconds, which show up
+// as skew, so pairs of nearby stations give the cleanest clock check.
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeSkewConfig {
+    pub max_lag_secs: f64,
+    pub min_correlation: f64, // Peaks below this are not estimates
+}
+
+impl Default for TimeSkewConfig {
+    fn default() -> Self {
+        TimeSkewConfig {
+            max_lag_secs: 0.5,
+            min_correlation: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SkewEstimate {
+    pub skew_us: f64,
+    pub correlation: f64, // At the peak
+    pub interval_us: f64, // Of the common grid
+    pub samples: usize,   // Grid points compared
+}
+
+// Skew of series b against series a, both (timestamp, frequency) in time
+// order. None when they overlap too little or the peak is too weak.
+pub fn estimate_skew(
+    config: &TimeSkewConfig,
+    a: &[(i64, f64)],
+    b: &[(i64, f64)],
+) -> Option<SkewEstimate> {
+    let interval_us = median_interval(a)?.min(median_interval(b)?);
+    let start = a.first()?.0.max(b.first()?.0);
+    let end = a.last()?.0.min(b.last()?.0);
+    if end <= start {
+        return None;
+    }
+    let points = ((end - start) as f64 / interval_us) as usize + 1;
+    let grid: Vec<f64> = (0..points)
+        .map(|n| start as f64 + n as f64 * interval_us)
+        .collect();
+    let a = normalize(&differences(&resample(a, &grid, interval_us)))?;
+    let b = normalize(&differences(&resample(b, &grid, interval_us)))?;
+
+    let max_lag = ((config.max_lag_secs * 1e6 / interval_us).round() as usize).min(a.len() / 2);
+    let correlations: Vec<f64> = (-(max_lag as i64)..=max_lag as i64)
+        .map(|lag| correlation(&a, &b, lag))
+        .collect();
+    let (peak, &best) = correlations
+        .iter()
+        .enumerate()
+        .max_by(|x, y| x.1.total_cmp(y.1))?;
+    if !best.is_finite() || best < config.min_correlation {
+        return None;
+    }
+    // Parabola through the peak and its neighbours
+    let offset = match (
+        correlations.get(peak.wrapping_sub(1)),
+        correlations.get(peak + 1),
+    ) {
+        (Some(before), Some(after)) => {
+            let curvature = before - 2.0 * best + after;
+            if curvature < 0.0 {
+                (0.5 * (before - after) / curvature).clamp(-0.5, 0.5)
+            } else {
+                0.0
+            }
+        }
+        _ => 0.0,
+    };
+    let lag = peak as f64 - max_lag as f64 + offset;
+    Some(SkewEstimate {
+        skew_us: lag * interval_us,
+        correlation: best,
+        interval_us,
+        samples: points,
+    })
+}
+
+fn median_interval(series: &[(i64, f64)]) -> Option<f64> {
+    let mut intervals: Vec<i64> = series
+        .windows(2)
+        .map(|pair| pair[1].0 - pair[0].0)
+        .filter(|interval| *interval > 0)
+        .collect();
+    if intervals.is_empty() {
+        return None;
+    }
+    intervals.sort_unstable();
+    Some(intervals[intervals.len() / 2] as f64)
+}
+
+// Linear interpolation at the grid times, NaN across gaps of more than a few
+// intervals.
+fn resample(series: &[(i64, f64)], grid: &[f64], interval_us: f64) -> Vec<f64> {
+    let mut next = 0;
+    grid.iter()
+        .map(|t| {
+            while next < series.len() && (series[next].0 as f64) < *t {
+                next += 1;
+            }
+            match (next.checked_sub(1).map(|i| series[i]), series.get(next)) {
+                (_, Some((t1, v1))) if *t1 as f64 == *t => *v1,
+                (Some((t0, v0)), Some((t1, v1))) if ((t1 - t0) as f64) < 3.0 * interval_us => {
+                    v0 + (v1 - v0) * (t - t0 as f64) / (t1 - t0) as f64
+                }
+                _ => f64::NAN,
+            }
+        })
+        .collect()
+}
+
+fn differences(values: &[f64]) -> Vec<f64> {
+    values.windows(2).map(|pair| pair[1] - pair[0]).collect()
+}
+
+// Zero mean and unit variance over the finite values. None without variation.
+fn normalize(values: &[f64]) -> Option<Vec<f64>> {
+    let finite: Vec<f64> = values.iter().copied().filter(|v| v.is_finite()).collect();
+    if finite.len() < 3 {
+        return None;
+    }
+    let n = finite.len() as f64;
+    let mean = finite.iter().sum::<f64>() / n;
+    let std = (finite.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt();
+    (std > 0.0).then(|| values.iter().map(|v| (v - mean) / std).collect())
+}
+
+// Mean product of a[n] and b[n + lag] over the points both have.
+fn correlation(a: &[f64], b: &[f64], lag: i64) -> f64 {
+    let (sum, count) = (0..a.len() as i64)
+        .filter_map(|n| {
+            let (x, y) = (a[n as usize], *b.get(usize::try_from(n + lag).ok()?)?);
+            (x.is_finite() && y.is_finite()).then_some(x * y)
+        })
+        .fold((0.0, 0usize), |(sum, count), product| {
+            (sum + product, count + 1)
+        });
+    if count == 0 {
+        return 0.0;
+    }
+    sum / count as f64
+}
@@ -0,0 +1,520 @@
+// Composite trigger conditions over live channel values.
+//
+// A TriggerDefinition names a condition tree: comparisons of a channel, or
+// the ratio of two channels, against a threshold, combined with all (AND)
+// and any (OR). Any node can carry a time qualifier, for_ms, and is true only
+// once its own condition has held for that long, e.g. ROCOF above 0.5 Hz/s
+// for 100 ms and V2/V1 above 0.02:
+//
+//   {"name": "islanding", "severity": "alarm", "condition": {"all": [
+//       {"channel": "Station A_7734_DFREQ", "abs": true, "above": 0.5, "for_ms": 100},
+//       {"ratio": ["Station A_7734_V2", "Station A_7734_V1"], "above": 0.02}
+//   ]}}
+//
+// Channels are named as for the DNP3 outstation: a column or a phasor
+// channel, which is its magnitude, so sequence quantities come from the
+// PMU's sequence phasor channels. The engine keeps the latest value of every
+// channel it needs; values older than STALE_US at the time of a row are
+// missing, and a comparison of a missing value is false.
+//
+// A trigger fires once when its condition becomes true and again only after
+// it was false. Each firing is an Event of kind Trigger from the trigger's
+// name, with the values compared, published on the bus if there is one.
+use crate::accumulator::{BatchAccumulator, FlushPolicy};
+use crate::arrow_utils::channel_value;
+use crate::budget::MemoryBudget;
+use crate::events::{Event, EventBus, EventKind, Severity};
+use crate::frames::ConfigurationFrame1and2_2011;
+use crate::sinks::BatchSink;
+use arrow::array::{Array, TimestampMicrosecondArray};
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+
+const STALE_US: i64 = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Signal {
+    Channel { channel: String },
+    Ratio { ratio: [String; 2] }, // Numerator and denominator
+}
+
+impl Signal {
+    fn channels(&self) -> Vec<&str> {
+        match self {
+            Signal::Channel { channel } => vec![channel],
+            Signal::Ratio { ratio } => vec![&ratio[0], &ratio[1]],
+        }
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Signal::Channel { channel } => write!(f, "{}", channel),
+            Signal::Ratio { ratio } => write!(f, "{}/{}", ratio[0], ratio[1]),
+        }
+    }
+}
+
+// A signal above and/or below thresholds (within the band with both).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Comparison {
+    #[serde(flatten)]
+    pub signal: Signal,
+    // Compare the absolute value
+    #[serde(default)]
+    pub abs: bool,
+    #[serde(default)]
+    pub above: Option<f64>,
+    #[serde(default)]
+    pub below: Option<f64>,
+    #[serde(default)]
+    pub for_ms: u64,
+}
+
+impl Comparison {
+    pub fn channel(channel: &str) -> Self {
+        Comparison::new(Signal::Channel {
+            channel: channel.to_string(),
+        })
+    }
+
+    pub fn ratio(numerator: &str, denominator: &str) -> Self {
+        Comparison::new(Signal::Ratio {
+            ratio: [numerator.to_string(), denominator.to_string()],
+        })
+    }
+
+    fn new(signal: Signal) -> Self {
+        Comparison {
+            signal,
+            abs: false,
+            above: None,
+            below: None,
+            for_ms: 0,
+        }
+    }
+
+    pub fn absolute(mut self) -> Self {
+        self.abs = true;
+        self
+    }
+
+    pub fn above(mut self, threshold: f64) -> Self {
+        self.above = Some(threshold);
+        self
+    }
+
+    pub fn below(mut self, threshold: f64) -> Self {
+        self.below = Some(threshold);
+        self
+    }
+
+    pub fn for_ms(mut self, for_ms: u64) -> Self {
+        self.for_ms = for_ms;
+        self
+    }
+
+    fn holds(&self, value: f64) -> bool {
+        let value = if self.abs { value.abs() } else { value };
+        self.above.is_none_or(|above| value > above) && self.below.is_none_or(|below| value < below)
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let signal = if self.abs {
+            format!("|{}|", self.signal)
+        } else {
+            self.signal.to_string()
+        };
+        match (self.above, self.below) {
+            (Some(above), Some(below)) => write!(f, "{} < {} < {}", above, signal, below)?,
+            (Some(above), None) => write!(f, "{} > {}", signal, above)?,
+            (None, Some(below)) => write!(f, "{} < {}", signal, below)?,
+            (None, None) => write!(f, "{}", signal)?,
+        }
+        if self.for_ms > 0 {
+            write!(f, " for {} ms", self.for_ms)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Condition {
+    All {
+        all: Vec<Condition>,
+        #[serde(default)]
+        for_ms: u64,
+    },
+    Any {
+        any: Vec<Condition>,
+        #[serde(default)]
+        for_ms: u64,
+    },
+    Compare(Comparison),
+}
+
+impl Condition {
+    pub fn all(conditions: Vec<Condition>) -> Self {
+        Condition::All {
+            all: conditions,
+            for_ms: 0,
+        }
+    }
+
+    pub fn any(conditions: Vec<Condition>) -> Self {
+        Condition::Any {
+            any: conditions,
+            for_ms: 0,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Condition::All {
+                all: conditions, ..
+            }
+            | Condition::Any {
+                any: conditions, ..
+            } => {
+                if conditions.is_empty() {
+                    return Err("empty all/any".to_string());
+                }
+                conditions.iter().try_for_each(Condition::validate)
+            }
+            Condition::Compare(comparison) => {
+                if comparison.signal.channels().iter().any(|c| c.is_empty()) {
+                    return Err("empty channel name".to_string());
+                }
+                if comparison.above.is_none() && comparison.below.is_none() {
+                    return Err(format!("no threshold for {}", comparison.signal));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn comparisons(&self) -> Vec<&Comparison> {
+        match self {
+            Condition::All {
+                all: conditions, ..
+            }
+            | Condition::Any {
+                any: conditions, ..
+            } => conditions.iter().flat_map(Condition::comparisons).collect(),
+            Condition::Compare(comparison) => vec![comparison],
+        }
+    }
+}
+
+impl From<Comparison> for Condition {
+    fn from(comparison: Comparison) -> Self {
+        Condition::Compare(comparison)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (conditions, join, for_ms) = match self {
+            Condition::All { all, for_ms } => (all, " and ", *for_ms),
+            Condition::Any { any, for_ms } => (any, " or ", *for_ms),
+            Condition::Compare(comparison) => return write!(f, "{}", comparison),
+        };
+        let parts: Vec<String> = conditions
+            .iter()
+            .map(|condition| match condition {
+                Condition::Compare(comparison) => comparison.to_string(),
+                nested => format!("({})", nested),
+            })
+            .collect();
+        write!(f, "{}", parts.join(join))?;
+        if for_ms > 0 {
+            write!(f, ", for {} ms", for_ms)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TriggerDefinition {
+    pub name: String,
+    pub condition: Condition,
+    #[serde(default = "default_severity")]
+    pub severity: Severity,
+}
+
+fn default_severity() -> Severity {
+    Severity::Warning
+}
+
+impl TriggerDefinition {
+    pub fn new(name: &str, condition: impl Into<Condition>) -> Self {
+        TriggerDefinition {
+            name: name.to_string(),
+            condition: condition.into(),
+            severity: default_severity(),
+        }
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+}
+
+// A condition with the time its own condition started holding.
+struct Node {
+    kind: NodeKind,
+    for_us: i64,
+    since: Option<i64>,
+}
+
+enum NodeKind {
+    All(Vec<Node>),
+    Any(Vec<Node>),
+    Compare(Comparison),
+}
+
+type Values = HashMap<String, (i64, f64)>; // Latest time and value per channel
+
+impl Node {
+    fn new(condition: &Condition) -> Self {
+        let (kind, for_ms) = match condition {
+            Condition::All { all, for_ms } => {
+                (NodeKind::All(all.iter().map(Node::new).collect()), *for_ms)
+            }
+            Condition::Any { any, for_ms } => {
+                (NodeKind::Any(any.iter().map(Node::new).collect()), *for_ms)
+            }
+            Condition::Compare(comparison) => {
+                (NodeKind::Compare(comparison.clone()), comparison.for_ms)
+            }
+        };
+        Node {
+            kind,
+            for_us: for_ms as i64 * 1000,
+            since: None,
+        }
+    }
+
+    // Every node is evaluated, so the timers of the nested ones keep running.
+    fn evaluate(&mut self, timestamp_us: i64, values: &Values) -> bool {
+        let holds = match &mut self.kind {
+            NodeKind::All(nodes) => {
+                let results: Vec<bool> = nodes
+                    .iter_mut()
+                    .map(|node| node.evaluate(timestamp_us, values))
+                    .collect();
+                results.iter().all(|holds| *holds)
+            }
+            NodeKind::Any(nodes) => {
+                let results: Vec<bool> = nodes
+                    .iter_mut()
+                    .map(|node| node.evaluate(timestamp_us, values))
+                    .collect();
+                results.iter().any(|holds| *holds)
+            }
+            NodeKind::Compare(comparison) => signal_value(&comparison.signal, timestamp_us, values)
+                .is_some_and(|value| comparison.holds(value)),
+        };
+        if !holds {
+            self.since = None;
+            return false;
+        }
+        let since = *self.since.get_or_insert(timestamp_us);
+        timestamp_us - since >= self.for_us
+    }
+}
+
+fn signal_value(signal: &Signal, timestamp_us: i64, values: &Values) -> Option<f64> {
+    let value = |channel: &String| {
+        values
+            .get(channel)
+            .filter(|(at, _)| timestamp_us - at <= STALE_US)
+            .map(|(_, value)| *value)
+    };
+    match signal {
+        Signal::Channel { channel } => value(channel),
+        Signal::Ratio { ratio } => {
+            let denominator = value(&ratio[1]).filter(|d| *d != 0.0)?;
+            Some(value(&ratio[0])? / denominator)
+        }
+    }
+}
+
+struct TriggerState {
+    definition: TriggerDefinition,
+    root: Node,
+    active: bool,
+    fired: u64,
+}
+
+pub struct TriggerEngine {
+    triggers: Vec<TriggerState>,
+    channels: Vec<String>, // Referenced by the conditions
+    values: Values,
+    accumulator: BatchAccumulator, // One row batches of the frames pushed
+    bus: Option<EventBus>,
+}
+
+impl TriggerEngine {
+    pub fn new(definitions: Vec<TriggerDefinition>) -> io::Result<Self> {
+        let mut channels = Vec::new();
+        for definition in &definitions {
+            let invalid = |message: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid trigger {:?}: {}", definition.name, message),
+                )
+            };
+            if definition.name.is_empty() {
+                return Err(invalid("empty name".to_string()));
+            }
+            definition.condition.validate().map_err(invalid)?;
+            for comparison in definition.condition.comparisons() {
+                for channel in comparison.signal.channels() {
+                    if !channels.iter().any(|known| known == channel) {
+                        channels.push(channel.to_string());
+                    }
+                }
+            }
+        }
+        Ok(TriggerEngine {
+            triggers: definitions
+                .into_iter()
+                .map(|definition| TriggerState {
+                    root: Node::new(&definition.condition),
+                    definition,
+                    active: false,
+                    fired: 0,
+                })
+                .collect(),
+            channels,
+            values: HashMap::new(),
+            accumulator: BatchAccumulator::new(MemoryBudget::unlimited())
+                .with_flush_policy(FlushPolicy::default().with_max_rows(1)),
+            bus: None,
+        })
+    }
+
+    // Publish each firing on a bus as well.
+    pub fn with_event_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    // Channels the conditions use.
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    // Whether a trigger's condition holds, as of the last row.
+    pub fn is_active(&self, name: &str) -> bool {
+        self.triggers
+            .iter()
+            .any(|trigger| trigger.definition.name == name && trigger.active)
+    }
+
+    // Times each trigger fired.
+    pub fn fired(&self) -> BTreeMap<String, u64> {
+        self.triggers
+            .iter()
+            .map(|trigger| (trigger.definition.name.clone(), trigger.fired))
+            .collect()
+    }
+
+    // Register (or replace) a stream whose data frames are pushed.
+    pub fn add_stream(&mut self, config: &ConfigurationFrame1and2_2011) {
+        self.accumulator.add_stream(config);
+    }
+
+    // Evaluate the triggers on a data frame of a registered stream.
+    pub fn push_frame(&mut self, frame: &[u8]) -> io::Result<Vec<Event>> {
+        match self.accumulator.push_frame(frame) {
+            Ok(Some((_, batch))) => self.push_batch(&batch),
+            Ok(None) => Ok(Vec::new()),
+            Err(e) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?}", e),
+            )),
+        }
+    }
+
+    // Evaluate the triggers row by row. Returns the firings.
+    pub fn push_batch(&mut self, batch: &RecordBatch) -> io::Result<Vec<Event>> {
+        let timestamps = batch
+            .column_by_name("timestamp")
+            .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Batch without timestamp column")
+            })?;
+        let mut events = Vec::new();
+        for row in 0..batch.num_rows() {
+            let timestamp_us = timestamps.value(row);
+            let mut updated = Vec::new();
+            for channel in &self.channels {
+                if let Some(value) = channel_value(batch, channel, row) {
+                    self.values.insert(channel.clone(), (timestamp_us, value));
+                    updated.push(channel.as_str());
+                }
+            }
+            if updated.is_empty() {
+                continue;
+            }
+            for trigger in &mut self.triggers {
+                let comparisons = trigger.definition.condition.comparisons();
+                let uses_row = comparisons
+                    .iter()
+                    .any(|c| c.signal.channels().iter().any(|ch| updated.contains(ch)));
+                if !uses_row {
+                    continue;
+                }
+                let holds = trigger.root.evaluate(timestamp_us, &self.values);
+                if holds && !trigger.active {
+                    trigger.fired += 1;
+                    let definition = &trigger.definition;
+                    let mut event = Event::new(
+                        timestamp_us,
+                        EventKind::Trigger,
+                        &definition.name,
+                        format!("{}: {}", definition.name, definition.condition),
+                    )
+                    .with_severity(definition.severity);
+                    for comparison in comparisons {
+                        let signal = &comparison.signal;
+                        if let Some(value) = signal_value(signal, timestamp_us, &self.values) {
+                            event = event.with_value(&signal.to_string(), value);
+                        }
+                    }
+                    if let Some(bus) = &self.bus {
+                        bus.publish(event.clone());
+                    }
+                    events.push(event);
+                }
+                trigger.active = holds;
+            }
+        }
+        Ok(events)
+    }
+}
+
+impl BatchSink for TriggerEngine {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        self.push_batch(batch).map(|_| ())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
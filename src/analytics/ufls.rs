@@ -0,0 +1,360 @@
+// Under-frequency load shedding what-if.
+//
+// A UFLS scheme sheds load in stages, each a relay setting: a frequency
+// pickup, a ROCOF pickup (df/dt relays, negative) or both, the ROCOF then
+// supervising the frequency element, and a time delay. UflsSimulation runs
+// the stages over a frequency trajectory, recorded or live, and reports
+// which stages would have tripped and when, for checking settings against
+// real disturbances. It only evaluates: the trajectory is taken as measured,
+// without the recovery the shed load would have brought.
+//
+// A stage picks up while every element it has is at or below its pickup and
+// trips once picked up for delay_secs, measured from the first sample picked
+// up; dropping out resets the timer. A stage trips at most once. ROCOF is the
+// least squares slope of the frequency over the last rocof_window_secs, as
+// relays filter it, rather than the noisy ROCOF the PMUs report.
+use crate::analytics::accuracy::frame_measurements;
+use crate::analytics::least_squares_slope;
+use crate::events::{Event, EventBus, EventKind, Severity};
+use crate::frame_parser::parse_config_frame_1and2;
+use crate::frames::ConfigurationFrame1and2_2011;
+use crate::reports::format_time;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UflsStage {
+    pub name: String,
+    // Picks up at or below this frequency
+    #[serde(default)]
+    pub pickup_hz: Option<f64>,
+    // Picks up at or below this ROCOF, e.g. -0.5
+    #[serde(default)]
+    pub rocof_hz_per_s: Option<f64>,
+    #[serde(default)]
+    pub delay_secs: f64,
+    // Load shed when the stage trips, percent
+    #[serde(default)]
+    pub shed_percent: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UflsConfig {
+    pub stages: Vec<UflsStage>,
+    #[serde(default = "default_rocof_window")]
+    pub rocof_window_secs: f64,
+}
+
+fn default_rocof_window() -> f64 {
+    0.2
+}
+
+impl UflsConfig {
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        serde_json::from_str(json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    // Every stage needs a pickup and a delay that is not negative.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        if self.stages.is_empty() {
+            return invalid("No UFLS stages".to_string());
+        }
+        for stage in &self.stages {
+            if stage.pickup_hz.is_none() && stage.rocof_hz_per_s.is_none() {
+                return invalid(format!("Stage {} has no pickup", stage.name));
+            }
+            if stage.delay_secs < 0.0 || !stage.delay_secs.is_finite() {
+                return invalid(format!("Stage {} has a negative delay", stage.name));
+            }
+        }
+        if self.rocof_window_secs <= 0.0 {
+            return invalid("rocof_window_secs must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageTrip {
+    pub stage: String,
+    pub pickup_us: i64,
+    pub trip_us: i64,
+    pub frequency_hz: f64, // At the trip
+    pub rocof: Option<f64>,
+    pub shed_percent: f64,
+}
+
+impl StageTrip {
+    pub fn to_event(&self, source: &str) -> Event {
+        let mut event = Event::new(
+            self.trip_us,
+            EventKind::LoadShed,
+            source,
+            format!(
+                "UFLS stage {} would trip at {:.3} Hz, shedding {}% of load",
+                self.stage, self.frequency_hz, self.shed_percent
+            ),
+        )
+        .with_severity(Severity::Warning)
+        .with_value("frequency_hz", self.frequency_hz)
+        .with_value("shed_percent", self.shed_percent);
+        if let Some(rocof) = self.rocof {
+            event = event.with_value("rocof_hz_per_s", rocof);
+        }
+        event
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UflsReport {
+    pub stages: Vec<String>, // In configuration order
+    pub trips: Vec<StageTrip>,
+    pub samples: usize,
+    pub nadir: Option<(i64, f64)>,
+    pub min_rocof: Option<f64>,
+}
+
+impl UflsReport {
+    pub fn shed_percent(&self) -> f64 {
+        self.trips.iter().map(|trip| trip.shed_percent).sum()
+    }
+
+    pub fn trip(&self, stage: &str) -> Option<&StageTrip> {
+        self.trips.iter().find(|trip| trip.stage == stage)
+    }
+
+    // One line per stage, then the trajectory.
+    pub fn render(&self) -> String {
+        let mut out = String::from("UFLS evaluation\n");
+        for stage in &self.stages {
+            match self.trip(stage) {
+                Some(trip) => out.push_str(&format!(
+                    "{:<16} tripped at {} (picked up {:.3} s before), {:.3} Hz{}, shed {}%\n",
+                    stage,
+                    format_time(trip.trip_us),
+                    (trip.trip_us - trip.pickup_us) as f64 / 1e6,
+                    trip.frequency_hz,
+                    trip.rocof
+                        .map_or(String::new(), |rocof| format!(", {:.3} Hz/s", rocof)),
+                    trip.shed_percent
+                )),
+                None => out.push_str(&format!("{:<16} not tripped\n", stage)),
+            }
+        }
+        if let Some((timestamp_us, frequency)) = self.nadir {
+            out.push_str(&format!(
+                "Nadir {:.3} Hz at {}\n",
+                frequency,
+                format_time(timestamp_us)
+            ));
+        }
+        if let Some(rocof) = self.min_rocof {
+            out.push_str(&format!("Steepest ROCOF {:.3} Hz/s\n", rocof));
+        }
+        out.push_str(&format!(
+            "{} samples, {} of {} stages tripped, {}% of load shed\n",
+            self.samples,
+            self.trips.len(),
+            self.stages.len(),
+            self.shed_percent()
+        ));
+        out
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "samples": self.samples,
+            "shed_percent": self.shed_percent(),
+            "nadir_hz": self.nadir.map(|(_, frequency)| frequency),
+            "nadir_us": self.nadir.map(|(timestamp_us, _)| timestamp_us),
+            "min_rocof_hz_per_s": self.min_rocof,
+            "stages": self.stages.iter().map(|stage| {
+                let trip = self.trip(stage);
+                json!({
+                    "name": stage,
+                    "tripped": trip.is_some(),
+                    "pickup_us": trip.map(|trip| trip.pickup_us),
+                    "trip_us": trip.map(|trip| trip.trip_us),
+                    "frequency_hz": trip.map(|trip| trip.frequency_hz),
+                    "rocof_hz_per_s": trip.and_then(|trip| trip.rocof),
+                    "shed_percent": trip.map(|trip| trip.shed_percent),
+                })
+            }).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[derive(Default)]
+struct StageState {
+    picked_up_us: Option<i64>,
+    tripped: bool,
+}
+
+pub struct UflsSimulation {
+    config: UflsConfig,
+    source: String,
+    bus: Option<EventBus>,
+    states: Vec<StageState>,
+    history: VecDeque<(i64, f64)>, // Last rocof_window_secs of frequency
+    report: UflsReport,
+}
+
+impl UflsSimulation {
+    pub fn new(config: UflsConfig, source: &str) -> io::Result<Self> {
+        config.validate()?;
+        let report = UflsReport {
+            stages: config
+                .stages
+                .iter()
+                .map(|stage| stage.name.clone())
+                .collect(),
+            ..Default::default()
+        };
+        Ok(UflsSimulation {
+            states: config
+                .stages
+                .iter()
+                .map(|_| StageState::default())
+                .collect(),
+            config,
+            source: source.to_string(),
+            bus: None,
+            history: VecDeque::new(),
+            report,
+        })
+    }
+
+    // Publish each trip on a bus as well.
+    pub fn with_event_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    pub fn report(&self) -> &UflsReport {
+        &self.report
+    }
+
+    // Add a frequency sample. Returns the stages it trips.
+    pub fn push(&mut self, timestamp_us: i64, frequency: f64) -> Vec<StageTrip> {
+        if !frequency.is_finite() {
+            return Vec::new();
+        }
+        self.report.samples += 1;
+        if self.report.nadir.is_none_or(|(_, nadir)| frequency < nadir) {
+            self.report.nadir = Some((timestamp_us, frequency));
+        }
+        let rocof = self.rocof(timestamp_us, frequency);
+        if let Some(rocof) = rocof {
+            if self.report.min_rocof.is_none_or(|min| rocof < min) {
+                self.report.min_rocof = Some(rocof);
+            }
+        }
+
+        let mut trips = Vec::new();
+        for (stage, state) in self.config.stages.iter().zip(self.states.iter_mut()) {
+            if state.tripped {
+                continue;
+            }
+            let frequency_low = stage.pickup_hz.is_none_or(|pickup| frequency <= pickup);
+            let rocof_low = match stage.rocof_hz_per_s {
+                Some(pickup) => rocof.is_some_and(|rocof| rocof <= pickup),
+                None => true,
+            };
+            if !(frequency_low && rocof_low) {
+                state.picked_up_us = None;
+                continue;
+            }
+            let pickup_us = *state.picked_up_us.get_or_insert(timestamp_us);
+            if (timestamp_us - pickup_us) as f64 / 1e6 < stage.delay_secs {
+                continue;
+            }
+            state.tripped = true;
+            trips.push(StageTrip {
+                stage: stage.name.clone(),
+                pickup_us,
+                trip_us: timestamp_us,
+                frequency_hz: frequency,
+                rocof,
+                shed_percent: stage.shed_percent,
+            });
+        }
+        for trip in &trips {
+            if let Some(bus) = &self.bus {
+                bus.publish(trip.to_event(&self.source));
+            }
+        }
+        self.report.trips.extend(trips.iter().cloned());
+        trips
+    }
+
+    // Slope of the frequency over the ROCOF window, once the window is full.
+    fn rocof(&mut self, timestamp_us: i64, frequency: f64) -> Option<f64> {
+        self.history.push_back((timestamp_us, frequency));
+        let window_us = (self.config.rocof_window_secs * 1e6) as i64;
+        while self
+            .history
+            .get(1)
+            .is_some_and(|(t, _)| timestamp_us - t >= window_us)
+        {
+            self.history.pop_front();
+        }
+        let (first_us, _) = *self.history.front()?;
+        if timestamp_us - first_us < window_us {
+            return None;
+        }
+        let points: Vec<(f64, f64)> = self
+            .history
+            .iter()
+            .map(|(t, f)| ((t - first_us) as f64 / 1e6, *f))
+            .collect();
+        least_squares_slope(&points)
+    }
+}
+
+// Evaluate the stages over the frequency of PMU number pmu in captured
+// frames, configuration frames included or given.
+pub fn evaluate_frames(
+    config: UflsConfig,
+    stream_config: Option<ConfigurationFrame1and2_2011>,
+    frames: &[Vec<u8>],
+    pmu: usize,
+) -> io::Result<UflsReport> {
+    let mut simulation = UflsSimulation::new(config, "ufls")?;
+    let mut stream_config = stream_config;
+    for frame in frames.iter().filter(|frame| frame.len() >= 4) {
+        match (frame[1] >> 4) & 0x07 {
+            2 | 3 => match parse_config_frame_1and2(frame) {
+                Ok(config) => stream_config = Some(config),
+                Err(e) => println!("Invalid configuration frame: {:?}", e),
+            },
+            0 => {
+                let Some(config) = &stream_config else {
+                    continue;
+                };
+                let Ok(measurements) = frame_measurements(frame, config) else {
+                    continue;
+                };
+                if let Some(measurement) = measurements.get(pmu) {
+                    simulation.push(measurement.timestamp_us, measurement.frequency);
+                }
+            }
+            _ => {}
+        }
+    }
+    if stream_config.is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No configuration frame for the data frames",
+        ));
+    }
+    Ok(simulation.report)
+}
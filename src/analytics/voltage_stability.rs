@@ -0,0 +1,199 @@
+// Voltage stability indicators per bus.
+//
+// Over a rolling window, the bus voltage magnitude is regressed on two proxies
+// of loading: the angle of the bus relative to a reference bus (dV/dangle)
+// and the active power of a monitored branch, P = Re(V I*) (dV/dP). Near the
+// nose of the PV curve the voltage becomes more and more sensitive to both,
+// so a growing negative slope is a warning sign. Slopes are left out when the
+// window holds too little variation in the proxy to fit them.
+//
+// Indicators are emitted at a reduced rate, every interval seconds, as rows
+// that can be turned into an Arrow batch for the analytics output.
+use crate::analytics::{least_squares_slope, Phasor};
+use arrow::array::{ArrayRef, Float64Array, StringArray, TimestampMicrosecondArray};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BusSample {
+    pub voltage: Phasor,         // Positive sequence or phase voltage, volts
+    pub current: Option<Phasor>, // Current of a branch leaving the bus, amperes
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoltageStabilityConfig {
+    pub buses: Vec<String>,
+    pub reference: Option<String>, // Bus the angles are taken against
+    pub nominal_voltage: f64,      // Volts, for the per unit voltage
+    pub window: f64,               // Seconds regressed
+    pub interval: f64,             // Seconds between emitted rows
+}
+
+impl VoltageStabilityConfig {
+    pub fn new(buses: &[&str], nominal_voltage: f64) -> Self {
+        VoltageStabilityConfig {
+            buses: buses.iter().map(|b| b.to_string()).collect(),
+            reference: None,
+            nominal_voltage,
+            window: 30.0,
+            interval: 1.0,
+        }
+    }
+
+    pub fn with_reference(mut self, reference: &str) -> Self {
+        self.reference = Some(reference.to_string());
+        self
+    }
+
+    pub fn with_window(mut self, window: f64, interval: f64) -> Self {
+        self.window = window;
+        self.interval = interval;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StabilityIndex {
+    pub timestamp_us: i64,
+    pub bus: String,
+    pub voltage_pu: f64,        // Mean over the window
+    pub dv_dangle: Option<f64>, // Per unit per degree
+    pub dv_dp: Option<f64>,     // Per unit per MW
+}
+
+#[derive(Clone, Copy)]
+struct WindowSample {
+    timestamp_us: i64,
+    voltage_pu: f64,
+    angle_deg: Option<f64>,
+    power_mw: Option<f64>,
+}
+
+pub struct VoltageStabilityMonitor {
+    config: VoltageStabilityConfig,
+    windows: HashMap<String, VecDeque<WindowSample>>,
+    next_output_us: Option<i64>,
+}
+
+impl VoltageStabilityMonitor {
+    pub fn new(config: VoltageStabilityConfig) -> Self {
+        VoltageStabilityMonitor {
+            config,
+            windows: HashMap::new(),
+            next_output_us: None,
+        }
+    }
+
+    // Add the samples of all buses at one timestamp. Returns one row per bus
+    // with samples each time an interval has passed.
+    pub fn push(
+        &mut self,
+        timestamp_us: i64,
+        samples: &HashMap<String, BusSample>,
+    ) -> Vec<StabilityIndex> {
+        let reference_angle = self
+            .config
+            .reference
+            .as_ref()
+            .and_then(|reference| samples.get(reference))
+            .map(|sample| sample.voltage.angle());
+        let window_us = (self.config.window * 1e6) as i64;
+
+        for bus in &self.config.buses {
+            let Some(sample) = samples.get(bus) else {
+                continue;
+            };
+            let angle_deg = reference_angle.map(|reference| {
+                let difference = (sample.voltage.angle() - reference).to_degrees();
+                (difference + 180.0).rem_euclid(360.0) - 180.0
+            });
+            let power_mw = sample.current.map(|current| {
+                // Re(V I*), single phase quantities
+                (sample.voltage.re * current.re + sample.voltage.im * current.im) / 1e6
+            });
+            let window = self.windows.entry(bus.clone()).or_default();
+            window.push_back(WindowSample {
+                timestamp_us,
+                voltage_pu: sample.voltage.magnitude() / self.config.nominal_voltage,
+                angle_deg,
+                power_mw,
+            });
+            while window
+                .front()
+                .is_some_and(|s| s.timestamp_us <= timestamp_us - window_us)
+            {
+                window.pop_front();
+            }
+        }
+
+        let next = *self.next_output_us.get_or_insert(timestamp_us);
+        if timestamp_us < next {
+            return Vec::new();
+        }
+        self.next_output_us = Some(next + (self.config.interval * 1e6) as i64);
+        self.config
+            .buses
+            .iter()
+            .filter_map(|bus| {
+                let window = self.windows.get(bus)?;
+                (!window.is_empty()).then(|| index(timestamp_us, bus, window))
+            })
+            .collect()
+    }
+}
+
+fn index(timestamp_us: i64, bus: &str, window: &VecDeque<WindowSample>) -> StabilityIndex {
+    let voltage_pu = window.iter().map(|s| s.voltage_pu).sum::<f64>() / window.len() as f64;
+    let against = |proxy: fn(&WindowSample) -> Option<f64>| {
+        let points: Vec<(f64, f64)> = window
+            .iter()
+            .filter_map(|s| Some((proxy(s)?, s.voltage_pu)))
+            .collect();
+        least_squares_slope(&points)
+    };
+    StabilityIndex {
+        timestamp_us,
+        bus: bus.to_string(),
+        voltage_pu,
+        dv_dangle: against(|s| s.angle_deg),
+        dv_dp: against(|s| s.power_mw),
+    }
+}
+
+pub fn stability_schema() -> Schema {
+    Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            false,
+        ),
+        Field::new("bus", DataType::Utf8, false),
+        Field::new("voltage_pu", DataType::Float64, false),
+        Field::new("dv_dangle", DataType::Float64, true),
+        Field::new("dv_dp", DataType::Float64, true),
+    ])
+}
+
+pub fn stability_batch(rows: &[StabilityIndex]) -> Result<RecordBatch, ArrowError> {
+    let arrays: Vec<ArrayRef> = vec![
+        Arc::new(TimestampMicrosecondArray::from(
+            rows.iter().map(|r| r.timestamp_us).collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            rows.iter().map(|r| r.bus.as_str()).collect::<Vec<_>>(),
+        )),
+        Arc::new(Float64Array::from(
+            rows.iter().map(|r| r.voltage_pu).collect::<Vec<_>>(),
+        )),
+        Arc::new(Float64Array::from(
+            rows.iter().map(|r| r.dv_dangle).collect::<Vec<_>>(),
+        )),
+        Arc::new(Float64Array::from(
+            rows.iter().map(|r| r.dv_dp).collect::<Vec<_>>(),
+        )),
+    ];
+    RecordBatch::try_new(Arc::new(stability_schema()), arrays)
+}
@@ -0,0 +1,264 @@
+// Operator annotations of streams and time ranges.
+//
+// Operators mark maintenance windows and periods known to carry bad data, so
+// that the alarms and quality figures of those periods are not taken at face
+// value. The analytics and reports consult the store: anomalies and events
+// covered by a suppressing annotation are counted as suppressed instead of
+// being raised, and compliance reports leave maintenance out of the frames
+// expected.
+//
+// An annotation covers [start, end) of one stream (by IDCODE) or of all of
+// them, and optionally only a source: a station, or a channel named
+// STATION_..., as events and column names carry them. Streams and sources
+// each have key-value metadata as well (location, owner, ticket).
+//
+// The store is a JSON file replaced in one step on every change, so a crash
+// while saving leaves the previous version.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnotationKind {
+    Maintenance, // Planned work, outages and odd data are expected
+    KnownBad,    // Data known to be wrong, e.g. a failed CT or a wrong ratio
+    Note,        // Information only, suppresses nothing
+}
+
+impl AnnotationKind {
+    pub fn suppresses(&self) -> bool {
+        !matches!(self, AnnotationKind::Note)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotation {
+    #[serde(default)]
+    pub id: u64, // Assigned by the store
+    pub kind: AnnotationKind,
+    #[serde(default)]
+    pub stream: Option<u16>, // None for all streams
+    #[serde(default)]
+    pub source: Option<String>, // Station or channel, None for the whole stream
+    pub start_us: i64,
+    pub end_us: i64, // Exclusive
+    #[serde(default)]
+    pub note: String,
+}
+
+impl Annotation {
+    pub fn new(kind: AnnotationKind, start_us: i64, end_us: i64) -> Self {
+        Annotation {
+            id: 0,
+            kind,
+            stream: None,
+            source: None,
+            start_us,
+            end_us,
+            note: String::new(),
+        }
+    }
+
+    pub fn with_stream(mut self, stream: u16) -> Self {
+        self.stream = Some(stream);
+        self
+    }
+
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.note = note.to_string();
+        self
+    }
+
+    // Whether the annotation applies to a source of a stream. Annotations of
+    // a stream only apply where the stream is known. A source matches itself
+    // and the channels named after it.
+    pub fn applies_to(&self, stream: Option<u16>, source: &str) -> bool {
+        if self.stream.is_some() && self.stream != stream {
+            return false;
+        }
+        match &self.source {
+            None => true,
+            Some(prefix) => {
+                source == prefix
+                    || source
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with('_'))
+            }
+        }
+    }
+
+    pub fn overlaps(&self, start_us: i64, end_us: i64) -> bool {
+        self.start_us < end_us && start_us < self.end_us
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct StoreFile {
+    next_id: u64,
+    annotations: Vec<Annotation>,
+    metadata: BTreeMap<String, BTreeMap<String, String>>, // Per stream or source
+}
+
+#[derive(Debug, Default)]
+pub struct AnnotationStore {
+    path: Option<PathBuf>, // None keeps the store in memory only
+    data: StoreFile,
+}
+
+impl AnnotationStore {
+    // Open the store of a file, empty when the file does not exist yet.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let data = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => StoreFile::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(AnnotationStore {
+            path: Some(path),
+            data,
+        })
+    }
+
+    pub fn in_memory() -> Self {
+        AnnotationStore::default()
+    }
+
+    // Add an annotation, returns the id assigned to it.
+    pub fn add(&mut self, mut annotation: Annotation) -> io::Result<u64> {
+        if annotation.end_us <= annotation.start_us {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Annotation ends before it starts",
+            ));
+        }
+        self.data.next_id += 1;
+        annotation.id = self.data.next_id;
+        self.data.annotations.push(annotation);
+        self.save()?;
+        Ok(self.data.next_id)
+    }
+
+    // Returns false if there was no annotation with the id.
+    pub fn remove(&mut self, id: u64) -> io::Result<bool> {
+        let count = self.data.annotations.len();
+        self.data.annotations.retain(|a| a.id != id);
+        if self.data.annotations.len() == count {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.data.annotations
+    }
+
+    // Annotations of a source overlapping [start, end).
+    pub fn find(
+        &self,
+        stream: Option<u16>,
+        source: &str,
+        start_us: i64,
+        end_us: i64,
+    ) -> Vec<&Annotation> {
+        self.data
+            .annotations
+            .iter()
+            .filter(|a| a.applies_to(stream, source) && a.overlaps(start_us, end_us))
+            .collect()
+    }
+
+    // The annotation suppressing alarms of a source at a time, if any.
+    pub fn suppressing(
+        &self,
+        stream: Option<u16>,
+        source: &str,
+        timestamp_us: i64,
+    ) -> Option<&Annotation> {
+        self.data.annotations.iter().find(|a| {
+            a.kind.suppresses()
+                && a.applies_to(stream, source)
+                && a.overlaps(timestamp_us, timestamp_us + 1)
+        })
+    }
+
+    // Time of [start, end) covered by suppressing annotations of the whole
+    // stream, overlaps counted once.
+    pub fn suppressed_us(&self, stream: u16, start_us: i64, end_us: i64) -> i64 {
+        let mut periods: Vec<(i64, i64)> = self
+            .data
+            .annotations
+            .iter()
+            .filter(|a| {
+                a.kind.suppresses()
+                    && a.source.is_none()
+                    && a.stream.is_none_or(|s| s == stream)
+                    && a.overlaps(start_us, end_us)
+            })
+            .map(|a| (a.start_us.max(start_us), a.end_us.min(end_us)))
+            .collect();
+        periods.sort();
+        let mut covered = 0;
+        let mut until = start_us;
+        for (start, end) in periods {
+            if end > until {
+                covered += end - start.max(until);
+                until = end;
+            }
+        }
+        covered
+    }
+
+    // Key-value metadata of a stream ("7734") or source.
+    pub fn metadata(&self, key: &str) -> Option<&BTreeMap<String, String>> {
+        self.data.metadata.get(key)
+    }
+
+    // Set a value, or remove it with None.
+    pub fn set_metadata(&mut self, key: &str, name: &str, value: Option<&str>) -> io::Result<()> {
+        match value {
+            Some(value) => {
+                self.data
+                    .metadata
+                    .entry(key.to_string())
+                    .or_default()
+                    .insert(name.to_string(), value.to_string());
+            }
+            None => {
+                if let Some(values) = self.data.metadata.get_mut(key) {
+                    values.remove(name);
+                    if values.is_empty() {
+                        self.data.metadata.remove(key);
+                    }
+                }
+            }
+        }
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(&self.data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let temporary = path.with_extension("tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+}
@@ -0,0 +1,226 @@
+// Grouping of stations into areas, and of areas into larger ones.
+//
+// An AreaConfig (JSON) names the areas, the stations of each and optionally
+// the area it is part of, e.g. substations in control areas in regions:
+//
+//   {"areas": [
+//     {"name": "WEST"},
+//     {"name": "NORTH", "parent": "WEST", "stations": ["SUB_A", "SUB_B"]},
+//     {"name": "SOUTH", "parent": "WEST", "stations": ["SUB_C"]}
+//   ]}
+//
+// A station is listed in one area and through it belongs to the areas above.
+// Station names are matched without their padding. The analytics (area
+// angles), the buffer server (/areas) and the quality reports all group
+// stations with an AreaMap.
+use crate::arrow_utils::META_STATION;
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Area {
+    pub name: String,
+    // Stations listed in this area, not those of the areas below
+    #[serde(default)]
+    pub stations: Vec<String>,
+    #[serde(default)]
+    pub parent: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AreaConfig {
+    pub areas: Vec<Area>,
+}
+
+impl AreaConfig {
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        serde_json::from_str(json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AreaMap {
+    areas: Vec<Area>,                      // In configuration order
+    station_areas: HashMap<String, usize>, // Area listing each station
+}
+
+impl AreaMap {
+    // Check the configuration: unique area names, known parents without
+    // cycles, and every station in one area.
+    pub fn new(config: AreaConfig) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut map = AreaMap::default();
+        for (index, area) in config.areas.iter().enumerate() {
+            if config.areas[..index].iter().any(|a| a.name == area.name) {
+                return Err(invalid(format!("Area {} defined twice", area.name)));
+            }
+            for station in &area.stations {
+                let station = station.trim().to_string();
+                if let Some(other) = map.station_areas.insert(station.clone(), index) {
+                    return Err(invalid(format!(
+                        "Station {} is in areas {} and {}",
+                        station, config.areas[other].name, area.name
+                    )));
+                }
+            }
+        }
+        map.areas = config.areas;
+        for area in &map.areas {
+            if let Some(parent) = &area.parent {
+                if map.get(parent).is_none() {
+                    return Err(invalid(format!(
+                        "Parent {} of area {} is not defined",
+                        parent, area.name
+                    )));
+                }
+            }
+            if map.ancestors(&area.name).contains(&area.name.as_str()) {
+                return Err(invalid(format!("Area {} is its own parent", area.name)));
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(AreaConfig::from_file(path)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.areas.is_empty()
+    }
+
+    pub fn areas(&self) -> &[Area] {
+        &self.areas
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Area> {
+        self.areas.iter().find(|area| area.name == name)
+    }
+
+    // The area listing the station.
+    pub fn area_of(&self, station: &str) -> Option<&str> {
+        self.station_areas
+            .get(station.trim())
+            .map(|index| self.areas[*index].name.as_str())
+    }
+
+    // The station's area, then the areas above it up to the top.
+    pub fn path_of(&self, station: &str) -> Vec<&str> {
+        let Some(area) = self.area_of(station) else {
+            return Vec::new();
+        };
+        let mut path = vec![area];
+        path.extend(self.ancestors(area));
+        path
+    }
+
+    // Areas with this one as parent.
+    pub fn children(&self, name: &str) -> Vec<&str> {
+        self.areas
+            .iter()
+            .filter(|area| area.parent.as_deref() == Some(name))
+            .map(|area| area.name.as_str())
+            .collect()
+    }
+
+    // Stations of the area and of the areas below it.
+    pub fn stations(&self, name: &str) -> Vec<&str> {
+        let mut stations: Vec<&str> = self
+            .station_areas
+            .keys()
+            .filter(|station| self.contains(name, station))
+            .map(String::as_str)
+            .collect();
+        stations.sort();
+        stations
+    }
+
+    // Whether the station is in the area or one below it.
+    pub fn contains(&self, name: &str, station: &str) -> bool {
+        self.path_of(station).contains(&name)
+    }
+
+    // Values of stations by area, each value in its station's area and the
+    // areas above. Stations of no area are left out.
+    pub fn group<'a, T: Clone>(
+        &self,
+        values: impl IntoIterator<Item = (&'a str, T)>,
+    ) -> BTreeMap<String, Vec<T>> {
+        let mut groups: BTreeMap<String, Vec<T>> = BTreeMap::new();
+        for (station, value) in values {
+            for area in self.path_of(station) {
+                groups
+                    .entry(area.to_string())
+                    .or_default()
+                    .push(value.clone());
+            }
+        }
+        groups
+    }
+
+    // Description of an area as served at /areas/{name}: parent, areas
+    // below and all stations.
+    pub fn area_json(&self, name: &str) -> Option<Value> {
+        let area = self.get(name)?;
+        Some(json!({
+            "name": area.name,
+            "parent": area.parent,
+            "children": self.children(name),
+            "stations": self.stations(name),
+        }))
+    }
+
+    // All areas as served at /areas.
+    pub fn to_json(&self) -> Value {
+        Value::Array(
+            self.areas
+                .iter()
+                .filter_map(|area| self.area_json(&area.name))
+                .collect(),
+        )
+    }
+
+    // The timestamp and the channel columns of the area's stations.
+    pub fn select(&self, batch: &RecordBatch, name: &str) -> Result<RecordBatch, ArrowError> {
+        let schema = batch.schema();
+        let indices: Vec<usize> = schema
+            .fields()
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| {
+                field.name() == "timestamp"
+                    || field
+                        .metadata()
+                        .get(META_STATION)
+                        .is_some_and(|station| self.contains(name, station))
+            })
+            .map(|(index, _)| index)
+            .collect();
+        batch.project(&indices)
+    }
+
+    // Parents of an area, nearest first. Stops at a cycle.
+    fn ancestors(&self, name: &str) -> Vec<&str> {
+        let mut ancestors = Vec::new();
+        let mut current = self.get(name);
+        while let Some(parent) = current.and_then(|area| area.parent.as_deref()) {
+            if parent == name || ancestors.contains(&parent) {
+                ancestors.push(parent);
+                break;
+            }
+            ancestors.push(parent);
+            current = self.get(parent);
+        }
+        ancestors
+    }
+}
@@ -1,10 +1,228 @@
+pub use crate::frames::soc_fracsec_micros;
 use crate::frames::{ChannelDataType, ChannelInfo};
-use arrow::array::{ArrayRef, Float32Array, Int16Array, UInt16Array};
+use arrow::array::{
+    Array, ArrayRef, BooleanArray, FixedSizeListArray, Float32Array, Float64Array, Int16Array,
+    StringArray, TimestampMicrosecondArray, UInt16Array, UInt8Array,
+};
+use arrow::compute::{binary, cast, filter_record_batch, unary};
 use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
 use std::collections::HashMap;
 use std::sync::Arc;
 
+// Per-row quality bitmask, added by the accumulator when it can modify data.
+// Rows with no bit set are as received.
+pub const QUALITY_COLUMN: &str = "quality";
+pub const QUALITY_HELD: u8 = 0x01; // Missing frame, values repeated from the previous frame
+pub const QUALITY_INTERPOLATED: u8 = 0x02; // Missing frame, values interpolated
+pub const QUALITY_BAD_CRC: u8 = 0x04; // CHK did not match, kept by lenient parsing or salvage
+
+// Field metadata keys describing each channel column.
+pub const META_STATION: &str = "pmu.station";
+pub const META_IDCODE: &str = "pmu.idcode";
+pub const META_CHANNEL: &str = "pmu.channel";
+pub const META_UNIT: &str = "pmu.unit";
+pub const META_SCALE: &str = "pmu.scale"; // Engineering units per raw value
+pub const META_OFFSET: &str = "pmu.offset"; // Added after scaling (fixed point FREQ)
+pub const META_KIND: &str = "pmu.kind";
+pub const META_COMPONENT: &str = "pmu.component"; // Part of a phasor held by the column
+pub const META_NOMINAL_FREQUENCY: &str = "pmu.nominal_frequency";
+pub const META_FILTER: &str = "pmu.filter"; // Filter chain applied to the values
+pub const META_GROUP_DELAY_US: &str = "pmu.group_delay_us"; // Delay of the values behind the timestamp
+pub const META_EXPRESSION: &str = "pmu.expression"; // Expression of a derived channel
+pub const META_BUS: &str = "pmu.bus"; // Network model elements of the channel (topology)
+pub const META_BRANCH: &str = "pmu.branch";
+pub const META_MRID: &str = "pmu.mrid";
+
+// pmu.component of a phasor held as one complex column.
+pub const COMPLEX_COMPONENT: &str = "complex";
+
+// Columns emitted for each PMU's STAT word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatColumns {
+    Raw,     // STAT as received
+    Decoded, // One column per flag
+    #[default]
+    Both,
+}
+
+// Shape of the batches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArrowLayout {
+    #[default]
+    Wide, // One row per frame, one column per channel value
+    Long, // One row per frame and channel value, see build_long_schema
+}
+
+// Columns emitted for each phasor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PhasorColumns {
+    #[default]
+    Components, // Two raw columns as received, _X/_Y or _magnitude/_angle
+    Complex, // One FixedSizeList<Float64, 2> column of [real, imaginary], scaled; not for CSV
+}
+
+// Channels of the long layout given a row only when their value changes,
+// e.g. analogs updating far slower than the reporting rate. A row is also
+// given when the quality changes, and every heartbeat_us when set, so a
+// constant value still shows the channel alive.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SparseChannels {
+    pub kinds: Vec<String>,    // pmu.kind of the channels, e.g. rms or digital
+    pub channels: Vec<String>, // Channel names as in the long layout
+    pub deadband: f64,         // Largest change still taken as no change
+    pub heartbeat_us: Option<i64>,
+}
+
+impl SparseChannels {
+    pub fn with_kinds(mut self, kinds: &[&str]) -> Self {
+        self.kinds = kinds.iter().map(|kind| kind.to_string()).collect();
+        self
+    }
+
+    pub fn with_channels(mut self, channels: &[&str]) -> Self {
+        self.channels = channels.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    pub fn with_deadband(mut self, deadband: f64) -> Self {
+        self.deadband = deadband;
+        self
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat: std::time::Duration) -> Self {
+        self.heartbeat_us = Some(heartbeat.as_micros() as i64);
+        self
+    }
+
+    // Whether a channel of the long layout is sparse. The type of a phasor
+    // component (voltage_real) matches the kind of its phasor (voltage).
+    pub fn matches(&self, channel: &str, kind: &str) -> bool {
+        self.channels.iter().any(|name| name == channel)
+            || self.kinds.iter().any(|k| {
+                kind == k
+                    || kind
+                        .strip_prefix(k.as_str())
+                        .is_some_and(|rest| rest.starts_with('_'))
+            })
+    }
+}
+
+// Last row given for each sparse channel, by idcode, channel and type. Kept
+// across batches, e.g. by the accumulator for each stream.
+#[derive(Debug, Clone, Default)]
+pub struct SparseState {
+    last: HashMap<(u16, String, String), (i64, f64, u8)>, // Timestamp, value, quality
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArrowOptions {
+    pub stat_columns: StatColumns,
+    pub layout: ArrowLayout,
+    pub phasor_columns: PhasorColumns,
+    pub sparse: Option<SparseChannels>, // Long layout only
+}
+
+impl ArrowOptions {
+    pub fn with_stat_columns(mut self, stat_columns: StatColumns) -> Self {
+        self.stat_columns = stat_columns;
+        self
+    }
+
+    pub fn with_layout(mut self, layout: ArrowLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn with_phasor_columns(mut self, phasor_columns: PhasorColumns) -> Self {
+        self.phasor_columns = phasor_columns;
+        self
+    }
+
+    pub fn with_sparse(mut self, sparse: SparseChannels) -> Self {
+        self.sparse = Some(sparse);
+        self
+    }
+}
+
+type StatFlag = (&'static str, fn(u16) -> bool);
+
+// Flags decoded from STAT, column suffix and value. Booleans are true when
+// the condition is present, PMU_SYNC when the PMU is in sync.
+const STAT_FLAGS: [StatFlag; 6] = [
+    ("DATA_VALID", |stat| stat & 0xC000 == 0),
+    ("PMU_SYNC", |stat| stat & 0x2000 == 0),
+    ("SORTED_BY_ARRIVAL", |stat| stat & 0x1000 != 0),
+    ("TRIGGER", |stat| stat & 0x0800 != 0),
+    ("CONFIG_CHANGE", |stat| stat & 0x0400 != 0),
+    ("DATA_MODIFIED", |stat| stat & 0x0200 != 0),
+];
+pub(crate) const STAT_TIME_QUALITY: &str = "TIME_QUALITY"; // Bits 8-6, 0 when not used
+
+// Name of a decoded STAT column, from the STAT channel name.
+pub(crate) fn stat_column_name(stat_name: &str, flag: &str) -> String {
+    let base = stat_name.strip_suffix("STAT").unwrap_or(stat_name);
+    format!("{}{}", base, flag)
+}
+
+fn channel_field(
+    name: String,
+    data_type: DataType,
+    channel: &str,
+    info: &ChannelInfo,
+    component: Option<&str>,
+) -> Field {
+    let mut metadata = HashMap::from([
+        (META_STATION.to_string(), info.station.clone()),
+        (META_IDCODE.to_string(), info.idcode.to_string()),
+        (META_CHANNEL.to_string(), channel.to_string()),
+        (
+            META_NOMINAL_FREQUENCY.to_string(),
+            info.nominal_hz.to_string(),
+        ),
+    ]);
+    let mut unit = info.unit;
+    let mut scale = info.scale;
+    if let Some(component) = component {
+        metadata.insert(META_COMPONENT.to_string(), component.to_string());
+        if component == COMPLEX_COMPONENT {
+            // Scaled when extracted
+            scale = 1.0;
+        } else if component == "angle" {
+            // Radians, fixed point in 10^-4 rad
+            unit = "rad";
+            scale = if data_type == DataType::Int16 {
+                1e-4
+            } else {
+                1.0
+            };
+        }
+    }
+    if !unit.is_empty() {
+        metadata.insert(META_UNIT.to_string(), unit.to_string());
+    }
+    if !info.kind.is_empty() {
+        metadata.insert(META_KIND.to_string(), info.kind.to_string());
+    }
+    metadata.insert(META_SCALE.to_string(), scale.to_string());
+    if let ChannelDataType::FreqFixed = info.data_type {
+        metadata.insert(META_OFFSET.to_string(), info.nominal_hz.to_string());
+    }
+    Field::new(name, data_type, false).with_metadata(metadata)
+}
+
 pub fn build_arrow_schema(channel_map: &HashMap<String, ChannelInfo>) -> Schema {
+    build_arrow_schema_with(channel_map, &ArrowOptions::default())
+}
+
+pub fn build_arrow_schema_with(
+    channel_map: &HashMap<String, ChannelInfo>,
+    options: &ArrowOptions,
+) -> Schema {
+    if options.layout == ArrowLayout::Long {
+        return build_long_schema();
+    }
     let mut fields = vec![Field::new(
         "timestamp",
         DataType::Timestamp(TimeUnit::Microsecond, None),
@@ -12,35 +230,122 @@ pub fn build_arrow_schema(channel_map: &HashMap<String, ChannelInfo>) -> Schema
     )];
 
     for (name, info) in channel_map {
+        let (first, second) = if info.polar {
+            ("magnitude", "angle")
+        } else {
+            ("real", "imaginary")
+        };
+        let phasor = matches!(
+            info.data_type,
+            ChannelDataType::PhasorFloat | ChannelDataType::PhasorFixed
+        );
+        if phasor && options.phasor_columns == PhasorColumns::Complex {
+            fields.push(channel_field(
+                name.clone(),
+                complex_data_type(),
+                name,
+                info,
+                Some(COMPLEX_COMPONENT),
+            ));
+            continue;
+        }
         match info.data_type {
             ChannelDataType::PhasorFloat => {
-                fields.push(Field::new(
-                    &format!("{}_magnitude", name),
+                fields.push(channel_field(
+                    format!("{}_magnitude", name),
                     DataType::Float32,
-                    false,
+                    name,
+                    info,
+                    Some(first),
                 ));
-                fields.push(Field::new(
-                    &format!("{}_angle", name),
+                fields.push(channel_field(
+                    format!("{}_angle", name),
                     DataType::Float32,
-                    false,
+                    name,
+                    info,
+                    Some(second),
                 ));
             }
             ChannelDataType::PhasorFixed => {
-                fields.push(Field::new(&format!("{}_X", name), DataType::Int16, false));
-                fields.push(Field::new(&format!("{}_Y", name), DataType::Int16, false));
+                // Polar magnitudes are unsigned
+                fields.push(channel_field(
+                    format!("{}_X", name),
+                    if info.polar {
+                        DataType::UInt16
+                    } else {
+                        DataType::Int16
+                    },
+                    name,
+                    info,
+                    Some(first),
+                ));
+                fields.push(channel_field(
+                    format!("{}_Y", name),
+                    DataType::Int16,
+                    name,
+                    info,
+                    Some(second),
+                ));
             }
             ChannelDataType::AnalogFloat
             | ChannelDataType::FreqFloat
             | ChannelDataType::DfreqFloat => {
-                fields.push(Field::new(name, DataType::Float32, false));
+                fields.push(channel_field(
+                    name.clone(),
+                    DataType::Float32,
+                    name,
+                    info,
+                    None,
+                ));
             }
             ChannelDataType::AnalogFixed
             | ChannelDataType::FreqFixed
             | ChannelDataType::DfreqFixed => {
-                fields.push(Field::new(name, DataType::Int16, false));
+                fields.push(channel_field(
+                    name.clone(),
+                    DataType::Int16,
+                    name,
+                    info,
+                    None,
+                ));
             }
             ChannelDataType::Digital => {
-                fields.push(Field::new(name, DataType::UInt16, false));
+                fields.push(channel_field(
+                    name.clone(),
+                    DataType::UInt16,
+                    name,
+                    info,
+                    None,
+                ));
+            }
+            ChannelDataType::Stat => {
+                if options.stat_columns != StatColumns::Decoded {
+                    fields.push(channel_field(
+                        name.clone(),
+                        DataType::UInt16,
+                        name,
+                        info,
+                        None,
+                    ));
+                }
+                if options.stat_columns != StatColumns::Raw {
+                    for (flag, _) in STAT_FLAGS {
+                        fields.push(channel_field(
+                            stat_column_name(name, flag),
+                            DataType::Boolean,
+                            name,
+                            info,
+                            None,
+                        ));
+                    }
+                    fields.push(channel_field(
+                        stat_column_name(name, STAT_TIME_QUALITY),
+                        DataType::UInt8,
+                        name,
+                        info,
+                        None,
+                    ));
+                }
             }
         }
     }
@@ -111,11 +416,90 @@ fn extract_uint16_values(
     UInt16Array::from(values)
 }
 
+fn complex_data_type() -> DataType {
+    DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float64, false)), 2)
+}
+
+// Phasors as [real, imaginary] pairs in engineering units, polar phasors
+// converted to rectangular.
+fn extract_complex_values(
+    buffer: &[u8],
+    frame_size: usize,
+    channel_info: &ChannelInfo,
+) -> FixedSizeListArray {
+    let mut values = Vec::new();
+    for frame in buffer.chunks(frame_size) {
+        if frame.len() < frame_size {
+            break;
+        }
+        let data_start = channel_info.offset;
+        let data_end = data_start + channel_info.size;
+        if data_end > frame.len() {
+            continue;
+        }
+        let data = &frame[data_start..data_end];
+        let (first, second, angle_scale) = match channel_info.data_type {
+            ChannelDataType::PhasorFloat => (
+                f32::from_be_bytes(data[..4].try_into().unwrap()) as f64,
+                f32::from_be_bytes(data[4..].try_into().unwrap()) as f64,
+                1.0,
+            ),
+            // Polar magnitudes are unsigned, angles in 10^-4 rad
+            _ if channel_info.polar => (
+                u16::from_be_bytes(data[..2].try_into().unwrap()) as f64,
+                i16::from_be_bytes(data[2..].try_into().unwrap()) as f64,
+                1e-4,
+            ),
+            _ => (
+                i16::from_be_bytes(data[..2].try_into().unwrap()) as f64,
+                i16::from_be_bytes(data[2..].try_into().unwrap()) as f64,
+                1.0,
+            ),
+        };
+        if channel_info.polar {
+            let magnitude = first * channel_info.scale;
+            let angle = second * angle_scale;
+            values.push(magnitude * angle.cos());
+            values.push(magnitude * angle.sin());
+        } else {
+            values.push(first * channel_info.scale);
+            values.push(second * channel_info.scale);
+        }
+    }
+    FixedSizeListArray::new(
+        Arc::new(Field::new("item", DataType::Float64, false)),
+        2,
+        Arc::new(Float64Array::from(values)),
+        None,
+    )
+}
+
 pub fn extract_channel_values(
     buffer: &[u8],
     frame_size: usize,
     channel_info: &ChannelInfo,
 ) -> Vec<ArrayRef> {
+    extract_channel_values_with(buffer, frame_size, channel_info, &ArrowOptions::default())
+}
+
+pub fn extract_channel_values_with(
+    buffer: &[u8],
+    frame_size: usize,
+    channel_info: &ChannelInfo,
+    options: &ArrowOptions,
+) -> Vec<ArrayRef> {
+    if options.phasor_columns == PhasorColumns::Complex
+        && matches!(
+            channel_info.data_type,
+            ChannelDataType::PhasorFloat | ChannelDataType::PhasorFixed
+        )
+    {
+        return vec![Arc::new(extract_complex_values(
+            buffer,
+            frame_size,
+            channel_info,
+        ))];
+    }
     match channel_info.data_type {
         ChannelDataType::PhasorFloat => {
             let mut values = Vec::new();
@@ -151,15 +535,29 @@ pub fn extract_channel_values(
                 let data_end = data_start + channel_info.size;
                 if data_end <= frame.len() {
                     let data = &frame[data_start..data_end];
-                    let magnitude = i16::from_be_bytes(data[..2].try_into().unwrap());
-                    let angle = i16::from_be_bytes(data[2..].try_into().unwrap());
-                    values.push((magnitude, angle));
+                    let first = [data[0], data[1]];
+                    let second = i16::from_be_bytes(data[2..].try_into().unwrap());
+                    values.push((first, second));
                 }
             }
-            vec![
+            // Real part or, polar, the unsigned magnitude
+            let first: ArrayRef = if channel_info.polar {
+                Arc::new(UInt16Array::from(
+                    values
+                        .iter()
+                        .map(|(first, _)| u16::from_be_bytes(*first))
+                        .collect::<Vec<_>>(),
+                ))
+            } else {
                 Arc::new(Int16Array::from(
-                    values.iter().map(|(m, _)| *m).collect::<Vec<_>>(),
-                )),
+                    values
+                        .iter()
+                        .map(|(first, _)| i16::from_be_bytes(*first))
+                        .collect::<Vec<_>>(),
+                ))
+            };
+            vec![
+                first,
                 Arc::new(Int16Array::from(
                     values.iter().map(|(_, a)| *a).collect::<Vec<_>>(),
                 )),
@@ -186,5 +584,450 @@ pub fn extract_channel_values(
                 channel_info,
             ))]
         }
+        ChannelDataType::Stat => {
+            let stat = extract_uint16_values(buffer, frame_size, channel_info);
+            let mut arrays: Vec<ArrayRef> = Vec::new();
+            if options.stat_columns != StatColumns::Decoded {
+                arrays.push(Arc::new(stat.clone()));
+            }
+            if options.stat_columns != StatColumns::Raw {
+                for (_, decode) in STAT_FLAGS {
+                    arrays.push(Arc::new(BooleanArray::from(
+                        stat.values().iter().map(|s| decode(*s)).collect::<Vec<_>>(),
+                    )));
+                }
+                arrays.push(Arc::new(UInt8Array::from(
+                    stat.values()
+                        .iter()
+                        .map(|s| ((s >> 6) & 0x07) as u8)
+                        .collect::<Vec<_>>(),
+                )));
+            }
+            arrays
+        }
+    }
+}
+
+// SOC wraps after 2^32 seconds, on 2106-02-07T06:28:16Z.
+pub const SOC_ROLLOVER_US: i64 = (1 << 32) * 1_000_000;
+
+// timestamp_us moved by whole SOC rollovers to be nearest to reference_us,
+// e.g. the previous frame's, so times after 2106 follow those before.
+pub fn unwrap_soc_rollover(timestamp_us: i64, reference_us: i64) -> i64 {
+    let eras = ((reference_us - timestamp_us) as f64 / SOC_ROLLOVER_US as f64).round() as i64;
+    timestamp_us + eras * SOC_ROLLOVER_US
+}
+
+// Timestamp of a raw frame in microseconds, read from the SOC and FRACSEC
+// prefix fields with a time base of 10^6, for frames without their
+// configuration at hand.
+pub fn frame_timestamp_micros(frame: &[u8]) -> Option<i64> {
+    frame_timestamp_micros_with(frame, 1_000_000)
+}
+
+pub fn frame_timestamp_micros_with(frame: &[u8], time_base: u32) -> Option<i64> {
+    if frame.len() < 14 {
+        return None;
+    }
+    let soc = u32::from_be_bytes([frame[6], frame[7], frame[8], frame[9]]);
+    let fracsec = u32::from_be_bytes([frame[10], frame[11], frame[12], frame[13]]);
+    Some(soc_fracsec_micros(soc, fracsec, time_base))
+}
+
+// Timestamps of back to back data frames at the time base of their channels,
+// each unwrapped against the one before across the SOC rollover.
+pub fn frame_timestamps(
+    buffer: &[u8],
+    frame_size: usize,
+    channel_map: &HashMap<String, ChannelInfo>,
+) -> Vec<i64> {
+    let time_base = channel_map
+        .values()
+        .next()
+        .map_or(1_000_000, |info| info.time_base);
+    let mut previous: Option<i64> = None;
+    buffer
+        .chunks(frame_size)
+        .filter(|frame| frame.len() == frame_size)
+        .filter_map(|frame| frame_timestamp_micros_with(frame, time_base))
+        .map(|timestamp| {
+            let timestamp = previous.map_or(timestamp, |p| unwrap_soc_rollover(timestamp, p));
+            previous = Some(timestamp);
+            timestamp
+        })
+        .collect()
+}
+
+// Convert a buffer of back to back data frames into a single RecordBatch,
+// one row per frame and columns laid out by build_arrow_schema.
+pub fn build_record_batch(
+    buffer: &[u8],
+    frame_size: usize,
+    channel_map: &HashMap<String, ChannelInfo>,
+) -> Result<RecordBatch, ArrowError> {
+    build_record_batch_with(buffer, frame_size, channel_map, &ArrowOptions::default())
+}
+
+pub fn build_record_batch_with(
+    buffer: &[u8],
+    frame_size: usize,
+    channel_map: &HashMap<String, ChannelInfo>,
+    options: &ArrowOptions,
+) -> Result<RecordBatch, ArrowError> {
+    let timestamps = frame_timestamps(buffer, frame_size, channel_map);
+    build_record_batch_at(buffer, frame_size, channel_map, options, timestamps)
+}
+
+// Same with the timestamps of the frames given, one per frame, e.g. kept by
+// the caller across batches.
+pub fn build_record_batch_at(
+    buffer: &[u8],
+    frame_size: usize,
+    channel_map: &HashMap<String, ChannelInfo>,
+    options: &ArrowOptions,
+    timestamps: Vec<i64>,
+) -> Result<RecordBatch, ArrowError> {
+    // Long batches are converted from the wide one
+    let wide = ArrowOptions {
+        layout: ArrowLayout::Wide,
+        ..options.clone()
+    };
+    let schema = Arc::new(build_arrow_schema_with(channel_map, &wide));
+    let mut arrays: Vec<ArrayRef> = Vec::new();
+    arrays.push(Arc::new(TimestampMicrosecondArray::from(timestamps)));
+
+    // Same map, same iteration order as the schema.
+    for info in channel_map.values() {
+        arrays.extend(extract_channel_values_with(buffer, frame_size, info, &wide));
+    }
+
+    let batch = RecordBatch::try_new(schema, arrays)?;
+    match (options.layout, &options.sparse) {
+        (ArrowLayout::Wide, _) => Ok(batch),
+        (ArrowLayout::Long, None) => to_long_format(&batch),
+        (ArrowLayout::Long, Some(sparse)) => {
+            let long = to_long_format(&batch)?;
+            drop_unchanged(&long, sparse, &mut SparseState::default())
+        }
+    }
+}
+
+// Schema of the long layout. Values are in engineering units (raw value times
+// pmu.scale plus pmu.offset), flags are 0/1. Type is the channel kind, with the
+// phasor component appended (e.g. voltage_real). Quality is the quality column
+// of the wide batch, 0 when it has none.
+pub fn build_long_schema() -> Schema {
+    Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            false,
+        ),
+        Field::new("station", DataType::Utf8, false),
+        Field::new("idcode", DataType::UInt16, false),
+        Field::new("channel", DataType::Utf8, false),
+        Field::new("type", DataType::Utf8, false),
+        Field::new("value", DataType::Float64, false),
+        Field::new(QUALITY_COLUMN, DataType::UInt8, false),
+    ])
+}
+
+// Convert a wide batch into the long layout, one row per frame and channel
+// column, frame by frame in schema order. Columns without channel metadata
+// (timestamp, quality) are not values.
+pub fn to_long_format(batch: &RecordBatch) -> Result<RecordBatch, ArrowError> {
+    let schema = batch.schema();
+    let timestamps = batch
+        .column_by_name("timestamp")
+        .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+        .ok_or_else(|| ArrowError::SchemaError("Missing timestamp column".to_string()))?;
+    let quality = batch
+        .column_by_name(QUALITY_COLUMN)
+        .and_then(|c| c.as_any().downcast_ref::<UInt8Array>());
+
+    struct ValueColumn {
+        station: String,
+        idcode: u16,
+        channel: String,
+        kind: String,
+        scale: f64,
+        offset: f64,
+        values: Float64Array,
+    }
+    let mut columns = Vec::new();
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        let meta = field.metadata();
+        let (Some(station), Some(idcode)) = (meta.get(META_STATION), meta.get(META_IDCODE)) else {
+            continue;
+        };
+        let prefix = format!("{}_{}_", station, idcode);
+        let component = meta.get(META_COMPONENT);
+        // Phasor components share the channel name, other columns are named after it
+        let name = match component {
+            Some(_) => meta.get(META_CHANNEL).unwrap_or(field.name()),
+            None => field.name(),
+        };
+        let kind = meta.get(META_KIND).cloned().unwrap_or_default();
+        let number = |key: &str, default: f64| {
+            meta.get(key)
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(default)
+        };
+        let cast_error = || ArrowError::CastError(field.name().to_string());
+        // Complex columns give a real and an imaginary value per row
+        let parts = if component.is_some_and(|c| c == COMPLEX_COMPONENT) {
+            let pairs = column
+                .as_any()
+                .downcast_ref::<FixedSizeListArray>()
+                .ok_or_else(cast_error)?;
+            let flat = pairs.values().as_any().downcast_ref::<Float64Array>();
+            let flat = flat.ok_or_else(cast_error)?.values();
+            vec![
+                (
+                    format!("{}_real", kind),
+                    flat.iter().step_by(2).copied().collect(),
+                ),
+                (
+                    format!("{}_imaginary", kind),
+                    flat.iter().skip(1).step_by(2).copied().collect(),
+                ),
+            ]
+        } else {
+            let values = cast(column, &DataType::Float64)?;
+            let values = values
+                .as_any()
+                .downcast_ref::<Float64Array>()
+                .cloned()
+                .ok_or_else(cast_error)?;
+            let kind = match component {
+                Some(component) => format!("{}_{}", kind, component),
+                None => kind,
+            };
+            vec![(kind, values)]
+        };
+        for (kind, values) in parts {
+            columns.push(ValueColumn {
+                station: station.clone(),
+                idcode: idcode.parse().unwrap_or(0),
+                channel: name.strip_prefix(&prefix).unwrap_or(name).to_string(),
+                kind,
+                scale: number(META_SCALE, 1.0),
+                offset: number(META_OFFSET, 0.0),
+                values,
+            });
+        }
+    }
+
+    let rows = batch.num_rows() * columns.len();
+    let mut out_timestamps = Vec::with_capacity(rows);
+    let mut stations = Vec::with_capacity(rows);
+    let mut idcodes = Vec::with_capacity(rows);
+    let mut channels = Vec::with_capacity(rows);
+    let mut kinds = Vec::with_capacity(rows);
+    let mut values = Vec::with_capacity(rows);
+    let mut qualities = Vec::with_capacity(rows);
+    for row in 0..batch.num_rows() {
+        for column in &columns {
+            out_timestamps.push(timestamps.value(row));
+            stations.push(column.station.as_str());
+            idcodes.push(column.idcode);
+            channels.push(column.channel.as_str());
+            kinds.push(column.kind.as_str());
+            values.push(column.values.value(row) * column.scale + column.offset);
+            qualities.push(quality.map_or(0, |q| q.value(row)));
+        }
+    }
+
+    RecordBatch::try_new(
+        Arc::new(build_long_schema()),
+        vec![
+            Arc::new(TimestampMicrosecondArray::from(out_timestamps)),
+            Arc::new(StringArray::from(stations)),
+            Arc::new(UInt16Array::from(idcodes)),
+            Arc::new(StringArray::from(channels)),
+            Arc::new(StringArray::from(kinds)),
+            Arc::new(Float64Array::from(values)),
+            Arc::new(UInt8Array::from(qualities)),
+        ],
+    )
+}
+
+// Drop the rows of sparse channels from a long batch that repeat the last
+// row given for the channel, within the deadband and with the same quality,
+// until the heartbeat is due. Rows of other channels are kept.
+pub fn drop_unchanged(
+    batch: &RecordBatch,
+    sparse: &SparseChannels,
+    state: &mut SparseState,
+) -> Result<RecordBatch, ArrowError> {
+    let column = |name: &str| {
+        batch
+            .column_by_name(name)
+            .ok_or_else(|| ArrowError::SchemaError(format!("Missing {} column", name)))
+    };
+    let cast_error = |name: &str| ArrowError::CastError(name.to_string());
+    let timestamps = column("timestamp")?;
+    let timestamps = timestamps
+        .as_any()
+        .downcast_ref::<TimestampMicrosecondArray>()
+        .ok_or_else(|| cast_error("timestamp"))?;
+    let idcodes = column("idcode")?;
+    let idcodes = idcodes
+        .as_any()
+        .downcast_ref::<UInt16Array>()
+        .ok_or_else(|| cast_error("idcode"))?;
+    let channels = column("channel")?;
+    let channels = channels
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .ok_or_else(|| cast_error("channel"))?;
+    let kinds = column("type")?;
+    let kinds = kinds
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .ok_or_else(|| cast_error("type"))?;
+    let values = column("value")?;
+    let values = values
+        .as_any()
+        .downcast_ref::<Float64Array>()
+        .ok_or_else(|| cast_error("value"))?;
+    let quality = column(QUALITY_COLUMN)?;
+    let quality = quality
+        .as_any()
+        .downcast_ref::<UInt8Array>()
+        .ok_or_else(|| cast_error(QUALITY_COLUMN))?;
+
+    let mut keep = Vec::with_capacity(batch.num_rows());
+    for row in 0..batch.num_rows() {
+        let (channel, kind) = (channels.value(row), kinds.value(row));
+        if !sparse.matches(channel, kind) {
+            keep.push(true);
+            continue;
+        }
+        let (timestamp, value, flags) =
+            (timestamps.value(row), values.value(row), quality.value(row));
+        let key = (idcodes.value(row), channel.to_string(), kind.to_string());
+        let changed = match state.last.get(&key) {
+            None => true,
+            Some((last_us, last, last_flags)) => {
+                let moved =
+                    (value - last).abs() > sparse.deadband || value.is_nan() != last.is_nan();
+                let due = sparse
+                    .heartbeat_us
+                    .is_some_and(|heartbeat| timestamp - last_us >= heartbeat);
+                moved || flags != *last_flags || due
+            }
+        };
+        if changed {
+            state.last.insert(key, (timestamp, value, flags));
+        }
+        keep.push(changed);
+    }
+    filter_record_batch(batch, &BooleanArray::from(keep))
+}
+
+// Add the quality column, one value per row, to a batch.
+pub fn append_quality_column(
+    batch: &RecordBatch,
+    quality: &[u8],
+) -> Result<RecordBatch, ArrowError> {
+    let mut fields: Vec<Field> = batch
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.as_ref().clone())
+        .collect();
+    fields.push(Field::new(QUALITY_COLUMN, DataType::UInt8, false));
+    let mut columns = batch.columns().to_vec();
+    columns.push(Arc::new(UInt8Array::from(quality.to_vec())));
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+}
+
+// Value of a column, or magnitude of a phasor channel, in engineering units.
+// None when the batch lacks the channel or the value is missing.
+pub fn channel_value(batch: &RecordBatch, channel: &str, row: usize) -> Option<f64> {
+    let schema = batch.schema();
+    let mut components = Vec::new();
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        let meta = field.metadata();
+        let exact = field.name() == channel;
+        if !exact && meta.get(META_CHANNEL).map(String::as_str) != Some(channel) {
+            continue;
+        }
+        let values = cast(column, &DataType::Float64).ok()?;
+        let values = values.as_any().downcast_ref::<Float64Array>()?;
+        if !values.is_valid(row) {
+            return None;
+        }
+        let number = |key: &str, default: f64| {
+            meta.get(key)
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(default)
+        };
+        let value = values.value(row) * number(META_SCALE, 1.0) + number(META_OFFSET, 0.0);
+        if exact {
+            return Some(value).filter(|v| v.is_finite());
+        }
+        components.push((meta.get(META_COMPONENT).cloned(), value));
+    }
+    let component = |name: &str| {
+        components
+            .iter()
+            .find(|(component, _)| component.as_deref() == Some(name))
+            .map(|(_, value)| *value)
+    };
+    let value = match (component("real"), component("imaginary")) {
+        (Some(x), Some(y)) => x.hypot(y),
+        _ => component("magnitude").or_else(|| {
+            components
+                .iter()
+                .find(|(component, _)| component.is_none())
+                .map(|(_, value)| *value)
+        })?,
+    };
+    Some(value).filter(|v| v.is_finite())
+}
+
+// Values of a column, or magnitudes of a phasor channel, in engineering
+// units, as channel_value for all rows. None when the batch lacks the channel.
+pub fn channel_values(batch: &RecordBatch, channel: &str) -> Option<Float64Array> {
+    let schema = batch.schema();
+    let mut components = Vec::new();
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        let meta = field.metadata();
+        let exact = field.name() == channel;
+        if !exact && meta.get(META_CHANNEL).map(String::as_str) != Some(channel) {
+            continue;
+        }
+        let values = cast(column, &DataType::Float64).ok()?;
+        let values = values.as_any().downcast_ref::<Float64Array>()?;
+        let number = |key: &str, default: f64| {
+            meta.get(key)
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(default)
+        };
+        let (scale, offset) = (number(META_SCALE, 1.0), number(META_OFFSET, 0.0));
+        let values: Float64Array = unary(values, |value| value * scale + offset);
+        if exact {
+            return Some(values);
+        }
+        components.push((meta.get(META_COMPONENT).cloned(), values));
+    }
+    let component = |name: &str| {
+        components
+            .iter()
+            .find(|(component, _)| component.as_deref() == Some(name))
+            .map(|(_, values)| values)
+    };
+    match (component("real"), component("imaginary")) {
+        (Some(x), Some(y)) => binary(x, y, f64::hypot).ok(),
+        _ => component("magnitude")
+            .or_else(|| {
+                components
+                    .iter()
+                    .find(|(component, _)| component.is_none())
+                    .map(|(_, values)| values)
+            })
+            .cloned(),
     }
 }
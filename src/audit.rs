@@ -0,0 +1,159 @@
+// Audit trail of command frames.
+//
+// Every command sent by the PDC client and every command received by the mock
+// server is recorded as one JSON object per line, with the time it was seen, the
+// stream idcode, the SOC/FRACSEC carried in the frame and the addresses of both
+// ends. Lines are written and flushed one at a time so the log survives a crash.
+use crate::frames::{now_micros, CommandFrame2011};
+use serde_json::{json, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditDirection {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditOutcome {
+    Sent,
+    Accepted,
+    Flagged(String),  // Accepted but suspicious, with the finding
+    Rejected(String), // Reason
+}
+
+pub fn command_name(command: u16) -> &'static str {
+    match command {
+        1 => "turn_off_transmission",
+        2 => "turn_on_transmission",
+        3 => "send_header_frame",
+        4 => "send_config_frame1",
+        5 => "send_config_frame2",
+        6 => "send_config_frame3",
+        8 => "extended_frame",
+        _ => "unknown",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub timestamp_us: i64, // Wall clock time the command was sent or received
+    pub direction: AuditDirection,
+    pub idcode: u16,
+    pub command: u16,
+    pub soc: u32,
+    pub fracsec: u32,
+    pub local: Option<SocketAddr>,
+    pub peer: Option<SocketAddr>,
+    pub outcome: AuditOutcome,
+}
+
+impl AuditEntry {
+    pub fn from_command(
+        direction: AuditDirection,
+        cmd: &CommandFrame2011,
+        local: Option<SocketAddr>,
+        peer: Option<SocketAddr>,
+        outcome: AuditOutcome,
+    ) -> Self {
+        AuditEntry {
+            timestamp_us: now_micros(),
+            direction,
+            idcode: cmd.prefix.idcode,
+            command: cmd.command,
+            soc: cmd.prefix.soc,
+            fracsec: cmd.prefix.fracsec,
+            local,
+            peer,
+            outcome,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let (outcome, reason) = match &self.outcome {
+            AuditOutcome::Sent => ("sent", None),
+            AuditOutcome::Accepted => ("accepted", None),
+            AuditOutcome::Flagged(reason) => ("flagged", Some(reason.as_str())),
+            AuditOutcome::Rejected(reason) => ("rejected", Some(reason.as_str())),
+        };
+        json!({
+            "timestamp_us": self.timestamp_us,
+            "direction": match self.direction {
+                AuditDirection::Sent => "sent",
+                AuditDirection::Received => "received",
+            },
+            "idcode": self.idcode,
+            "command": self.command,
+            "command_name": command_name(self.command),
+            "soc": self.soc,
+            "fracsec": self.fracsec,
+            "local": self.local.map(|addr| addr.to_string()),
+            "peer": self.peer.map(|addr| addr.to_string()),
+            "outcome": outcome,
+            "reason": reason,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum AuditTarget {
+    File(File),
+    Memory(Vec<String>),
+}
+
+// Shared between connections, wrap in an Arc.
+#[derive(Debug)]
+pub struct AuditLog {
+    target: Mutex<AuditTarget>,
+}
+
+impl AuditLog {
+    // Append to a JSONL file, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            target: Mutex::new(AuditTarget::File(file)),
+        })
+    }
+
+    // Keep the lines in memory, read back with lines().
+    pub fn in_memory() -> Self {
+        AuditLog {
+            target: Mutex::new(AuditTarget::Memory(Vec::new())),
+        }
+    }
+
+    pub fn record(&self, entry: &AuditEntry) -> io::Result<()> {
+        let line = entry.to_json().to_string();
+        let mut target = self.target.lock().unwrap();
+        match &mut *target {
+            AuditTarget::File(file) => {
+                file.write_all(format!("{}\n", line).as_bytes())?;
+                file.flush()
+            }
+            AuditTarget::Memory(lines) => {
+                lines.push(line);
+                Ok(())
+            }
+        }
+    }
+
+    // Record an entry, logging instead of failing so auditing never stops the data path.
+    pub fn record_or_log(&self, entry: &AuditEntry) {
+        if let Err(e) = self.record(entry) {
+            println!("Failed to write audit entry: {}", e);
+        }
+    }
+
+    // Lines recorded by an in-memory log. Empty for file logs.
+    pub fn lines(&self) -> Vec<String> {
+        match &*self.target.lock().unwrap() {
+            AuditTarget::File(_) => Vec::new(),
+            AuditTarget::Memory(lines) => lines.clone(),
+        }
+    }
+}
@@ -0,0 +1,320 @@
+// Long-term normal ranges per channel.
+//
+// The Baseliner summarizes each channel per day and hour of day: count, min,
+// max, mean and a few percentiles. Percentiles are taken over a uniform
+// subsample of the hour, at most max_samples values, so memory stays bounded
+// at high reporting rates. Closed hours are kept for retention_days days and
+// saved to a JSON file, so the baseline survives restarts.
+//
+// The Baseline for an hour of day combines the days seen: the extremes of
+// the mins and maxes and the median of the daily percentiles. It gives
+// adaptive alarm limits and a check of new values against the normal range.
+use chrono::{DateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const MICROS_PER_HOUR: i64 = 3_600_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HourSummary {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub p01: f64,
+    pub p05: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Baseline {
+    pub days: usize, // Days the hour was seen on
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub p01: f64,
+    pub p05: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+impl Baseline {
+    // Alarm limits: the 1st to 99th percentile range widened by margin times
+    // its width on both sides.
+    pub fn limits(&self, margin: f64) -> (f64, f64) {
+        let width = self.p99 - self.p01;
+        (self.p01 - margin * width, self.p99 + margin * width)
+    }
+
+    pub fn contains(&self, value: f64, margin: f64) -> bool {
+        let (low, high) = self.limits(margin);
+        value >= low && value <= high
+    }
+}
+
+// Samples of the hour being accumulated.
+struct OpenHour {
+    local_hour: i64, // Local hours since the epoch
+    count: usize,
+    min: f64,
+    max: f64,
+    sum: f64,
+    kept: Vec<f64>, // Every stride-th sample
+    stride: usize,
+}
+
+impl OpenHour {
+    fn new(local_hour: i64) -> Self {
+        OpenHour {
+            local_hour,
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+            kept: Vec::new(),
+            stride: 1,
+        }
+    }
+
+    fn push(&mut self, value: f64, max_samples: usize) {
+        if self.count.is_multiple_of(self.stride) {
+            self.kept.push(value);
+            if self.kept.len() > max_samples {
+                // Keep every other sample and halve the rate from here on
+                self.kept = self.kept.iter().step_by(2).copied().collect();
+                self.stride *= 2;
+            }
+        }
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+    }
+
+    fn summary(mut self) -> HourSummary {
+        self.kept.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| percentile(&self.kept, p);
+        HourSummary {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: self.sum / self.count as f64,
+            p01: percentile(1.0),
+            p05: percentile(5.0),
+            p50: percentile(50.0),
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+        }
+    }
+}
+
+// Nearest rank percentile of sorted values.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+// Closed hours by channel, then by local date (YYYY-MM-DD), 24 per day.
+type Summaries = BTreeMap<String, BTreeMap<String, Vec<Option<HourSummary>>>>;
+
+pub struct Baseliner {
+    path: Option<PathBuf>,
+    timezone: Option<Tz>,
+    retention_days: usize,
+    max_samples: usize,
+    summaries: Summaries,
+    open: HashMap<String, OpenHour>,
+}
+
+impl Baseliner {
+    // Load the baselines saved at path, or start empty if there is no file yet.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let summaries = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Summaries::new(),
+            Err(e) => return Err(e),
+        };
+        let mut baseliner = Baseliner::in_memory();
+        baseliner.path = Some(path);
+        baseliner.summaries = summaries;
+        Ok(baseliner)
+    }
+
+    // Keep the baselines in memory only, save() does nothing.
+    pub fn in_memory() -> Self {
+        Baseliner {
+            path: None,
+            timezone: None,
+            retention_days: 30,
+            max_samples: 4096,
+            summaries: Summaries::new(),
+            open: HashMap::new(),
+        }
+    }
+
+    // Hours of day in this time zone instead of UTC.
+    pub fn with_timezone(mut self, timezone: Tz) -> Self {
+        self.timezone = Some(timezone);
+        self
+    }
+
+    pub fn with_retention_days(mut self, retention_days: usize) -> Self {
+        self.retention_days = retention_days.max(1);
+        self
+    }
+
+    // Samples per hour the percentiles are taken over.
+    pub fn with_max_samples(mut self, max_samples: usize) -> Self {
+        self.max_samples = max_samples.max(2);
+        self
+    }
+
+    pub fn channels(&self) -> Vec<String> {
+        self.summaries.keys().cloned().collect()
+    }
+
+    // Local hours since the epoch.
+    fn local_hour(&self, timestamp_us: i64) -> i64 {
+        let local_us = match self.timezone {
+            Some(tz) => {
+                let utc = DateTime::from_timestamp_micros(timestamp_us)
+                    .unwrap_or_default()
+                    .naive_utc();
+                let offset = tz.offset_from_utc_datetime(&utc).fix().local_minus_utc();
+                timestamp_us + offset as i64 * 1_000_000
+            }
+            None => timestamp_us,
+        };
+        local_us.div_euclid(MICROS_PER_HOUR)
+    }
+
+    // Add a sample. Non-finite values are ignored. The previous hour of the
+    // channel is summarized once a sample of a later hour arrives.
+    pub fn push(&mut self, channel: &str, timestamp_us: i64, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        let local_hour = self.local_hour(timestamp_us);
+        let previous = match self.open.get(channel) {
+            Some(open) if local_hour > open.local_hour => self.open.remove(channel),
+            // Late samples are counted in the open hour
+            _ => None,
+        };
+        if let Some(previous) = previous {
+            self.close_hour(channel, previous);
+        }
+        self.open
+            .entry(channel.to_string())
+            .or_insert_with(|| OpenHour::new(local_hour))
+            .push(value, self.max_samples);
+    }
+
+    fn close_hour(&mut self, channel: &str, open: OpenHour) {
+        let start = DateTime::from_timestamp(open.local_hour * 3600, 0)
+            .unwrap_or_default()
+            .naive_utc();
+        let date = start.format("%Y-%m-%d").to_string();
+        let hour = open.local_hour.rem_euclid(24) as usize;
+        let days = self.summaries.entry(channel.to_string()).or_default();
+        days.entry(date).or_insert_with(|| vec![None; 24])[hour] = Some(open.summary());
+        while days.len() > self.retention_days {
+            days.pop_first();
+        }
+    }
+
+    // Summarize the hours still open, e.g. before shutting down.
+    pub fn close_open_hours(&mut self) {
+        let open: Vec<(String, OpenHour)> = self.open.drain().collect();
+        for (channel, hour) in open {
+            self.close_hour(&channel, hour);
+        }
+    }
+
+    // Summary of one hour of one day, date as YYYY-MM-DD.
+    pub fn hour_summary(&self, channel: &str, date: &str, hour: usize) -> Option<HourSummary> {
+        *self.summaries.get(channel)?.get(date)?.get(hour)?
+    }
+
+    // Baseline of a channel for an hour of day (0-23), over the retained days.
+    pub fn baseline(&self, channel: &str, hour: usize) -> Option<Baseline> {
+        let hours: Vec<HourSummary> = self
+            .summaries
+            .get(channel)?
+            .values()
+            .filter_map(|day| *day.get(hour)?)
+            .collect();
+        if hours.is_empty() {
+            return None;
+        }
+        let count: usize = hours.iter().map(|h| h.count).sum();
+        let median_of = |field: fn(&HourSummary) -> f64| median(hours.iter().map(field).collect());
+        Some(Baseline {
+            days: hours.len(),
+            count,
+            min: hours.iter().map(|h| h.min).fold(f64::INFINITY, f64::min),
+            max: hours
+                .iter()
+                .map(|h| h.max)
+                .fold(f64::NEG_INFINITY, f64::max),
+            mean: hours.iter().map(|h| h.mean * h.count as f64).sum::<f64>() / count as f64,
+            p01: median_of(|h| h.p01),
+            p05: median_of(|h| h.p05),
+            p50: median_of(|h| h.p50),
+            p95: median_of(|h| h.p95),
+            p99: median_of(|h| h.p99),
+        })
+    }
+
+    // Baseline for the hour of day a timestamp falls in.
+    pub fn baseline_at(&self, channel: &str, timestamp_us: i64) -> Option<Baseline> {
+        let hour = self.local_hour(timestamp_us).rem_euclid(24) as usize;
+        self.baseline(channel, hour)
+    }
+
+    // Whether a value is within the normal range for its hour, None without
+    // a baseline for the channel at that hour.
+    pub fn validate(
+        &self,
+        channel: &str,
+        timestamp_us: i64,
+        value: f64,
+        margin: f64,
+    ) -> Option<bool> {
+        Some(
+            self.baseline_at(channel, timestamp_us)?
+                .contains(value, margin),
+        )
+    }
+
+    // Write the closed hours to the file, replacing it in one step.
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_string(&self.summaries)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, json)?;
+        fs::rename(&temporary, path)
+    }
+}
@@ -0,0 +1,93 @@
+// Collect C37.118 streams into Parquet files and serve metrics.
+//
+// An example of the library end to end: the streams of a pipeline
+// configuration (pipeline::PipelineConfig) are written as Parquet files,
+// whatever format its sink names, while the pipeline's metrics are served
+// for Prometheus at GET /metrics, e.g. against pmu-sim:
+//
+//   pdc-collector collector.json --metrics-port 9100
+//
+// with collector.json
+//
+//   {"streams": [{"host": "127.0.0.1", "port": 4712}],
+//    "sink": {"format": "parquet", "dir": "data"}}
+use clap::Parser;
+use pmu::metrics::{self, Metrics};
+use pmu::pipeline::{Pipeline, PipelineConfig, SinkFormat};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io;
+use tokio::time;
+
+#[derive(Debug, Parser)]
+#[command(name = "pdc-collector")]
+#[command(about = "Collect C37.118 streams into Parquet files", long_about = None)]
+struct Cli {
+    // JSON pipeline configuration
+    config: PathBuf,
+    // Directory of the Parquet files, in place of the sink's
+    #[arg(long)]
+    out: Option<PathBuf>,
+    // Serve the metrics over HTTP on this port
+    #[arg(long, default_value_t = 9100)]
+    metrics_port: u16,
+    // Stop after this many seconds, running until interrupted when not set
+    #[arg(long)]
+    duration: Option<u64>,
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let args = Cli::parse();
+    let mut config = PipelineConfig::from_file(&args.config)?;
+    if config.sink.format != SinkFormat::Parquet {
+        println!("Writing Parquet files in place of {:?}", config.sink.format);
+        config.sink.format = SinkFormat::Parquet;
+    }
+    if let Some(dir) = args.out {
+        config.sink.dir = dir;
+    }
+    println!(
+        "Collecting {} streams into {}",
+        config.streams.len(),
+        config.sink.dir.display()
+    );
+
+    let metrics = Arc::new(Metrics::new());
+    let served = metrics.clone();
+    let addr = SocketAddr::from(([0, 0, 0, 0], args.metrics_port));
+    tokio::spawn(async move {
+        if let Err(e) = metrics::serve(served, addr).await {
+            println!("Metrics failed: {}", e);
+        }
+    });
+
+    let pipeline = Pipeline::new(config).with_metrics(metrics);
+    let run = pipeline.run();
+    tokio::pin!(run);
+    let stopped = async {
+        match args.duration {
+            Some(seconds) => time::sleep(Duration::from_secs(seconds)).await,
+            None => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    };
+    let stats = tokio::select! {
+        stats = &mut run => stats,
+        _ = stopped => {
+            println!("Shutting down...");
+            pipeline.stop();
+            run.await
+        }
+    }?;
+    for (shard, stats) in stats.iter().enumerate() {
+        println!(
+            "Shard {}: {} streams, {} frames, {} rows in {} batches, {} errors",
+            shard, stats.streams, stats.frames, stats.rows, stats.batches, stats.errors
+        );
+    }
+    Ok(())
+}
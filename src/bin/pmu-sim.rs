@@ -0,0 +1,84 @@
+// Serve synthetic PMUs as one C37.118 stream.
+//
+// An example of the library end to end: N simulated PMUs, each with three
+// voltage phasors a few degrees apart, are served as a PDC stream by the
+// mock server, for pdc-collector or any other PDC client to connect to, e.g.
+//
+//   pmu-sim --pmus 8 --rate 60 --port 4712
+use clap::Parser;
+use pmu::idcodes;
+use pmu::pdc_server::{run_mock_server, Protocol, ServerConfig};
+use pmu::simulator::{Scenario, SimulatedPmu, StreamLayout};
+use tokio::io;
+
+#[derive(Debug, Parser)]
+#[command(name = "pmu-sim")]
+#[command(about = "Serve N synthetic PMUs as a C37.118 stream", long_about = None)]
+struct Cli {
+    // Simulated PMUs in the stream
+    #[arg(long, default_value_t = 4)]
+    pmus: u16,
+    // Frames per second
+    #[arg(long, default_value_t = 30)]
+    rate: i16,
+    #[arg(long, default_value = "127.0.0.1")]
+    ip: String,
+    #[arg(long, default_value_t = 4712)]
+    port: u16,
+    // IDCODEs of the stream and its PMUs, first-last
+    #[arg(long, default_value = "1-65534")]
+    idcodes: String,
+    // Analog values of every PMU
+    #[arg(long, default_value_t = 0)]
+    analogs: u16,
+    // Digital status words of every PMU
+    #[arg(long, default_value_t = 0)]
+    digitals: u16,
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let args = Cli::parse();
+    if args.pmus == 0 || args.rate <= 0 {
+        return Err(io::Error::other("--pmus and --rate must be positive"));
+    }
+    let pmus = (0..args.pmus)
+        .map(|n| SimulatedPmu {
+            station: format!("PMU {}", n + 1),
+            idcode: 0,
+            polar: false,
+            float_phasors: false,
+            float_analogs: false,
+            float_freq: false,
+            phasors: 3,
+            analogs: args.analogs,
+            digitals: args.digitals,
+            data_rate: None,
+            nominal_50hz: false,
+            // Power flows away from the first PMU
+            angle: -2.5 * n as f64,
+        })
+        .collect();
+    let mut layout = StreamLayout {
+        idcode: 0,
+        data_rate: args.rate,
+        time_base: 1_000_000,
+        pmus,
+    };
+    let range = idcodes::parse_range(&args.idcodes).map_err(io::Error::other)?;
+    idcodes::assign_layouts(std::slice::from_mut(&mut layout), range)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    println!(
+        "Simulating stream {} of {} PMUs at {} frames per second",
+        layout.idcode, args.pmus, args.rate
+    );
+
+    let scenario = Scenario {
+        stream: Some(layout),
+        ..Default::default()
+    };
+    let server_config = ServerConfig::new(args.ip, args.port, Protocol::TCP, args.rate as f64)
+        .map_err(io::Error::other)?
+        .with_scenario(scenario);
+    run_mock_server(server_config).await
+}
@@ -0,0 +1,62 @@
+// Memory limits shared by the batch accumulator and the historian.
+//
+// High channel count deployments can buffer a lot of data per stream,
+// so both components accept a budget in bytes and/or rows and report
+// their current usage per stream.
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryBudget {
+    pub max_bytes: Option<usize>,
+    pub max_rows: Option<usize>,
+}
+
+impl MemoryBudget {
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn with_max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = Some(max_rows);
+        self
+    }
+
+    // True when usage has reached or gone past either limit.
+    pub fn is_reached(&self, usage: &MemoryUsage) -> bool {
+        self.max_bytes.is_some_and(|max| usage.bytes >= max)
+            || self.max_rows.is_some_and(|max| usage.rows >= max)
+    }
+
+    // True when usage is strictly over either limit.
+    pub fn is_exceeded(&self, usage: &MemoryUsage) -> bool {
+        self.max_bytes.is_some_and(|max| usage.bytes > max)
+            || self.max_rows.is_some_and(|max| usage.rows > max)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub rows: usize,
+    pub bytes: usize,
+}
+
+impl MemoryUsage {
+    pub fn add(&mut self, other: &MemoryUsage) {
+        self.rows += other.rows;
+        self.bytes += other.bytes;
+    }
+}
+
+// Sum of the usage of every stream.
+pub fn total_usage(usage_by_stream: &HashMap<u16, MemoryUsage>) -> MemoryUsage {
+    let mut total = MemoryUsage::default();
+    for usage in usage_by_stream.values() {
+        total.add(usage);
+    }
+    total
+}
@@ -0,0 +1,334 @@
+// Event bundles for operator review.
+//
+// An EventBundler takes classified events from the event bus (generator
+// trips, load losses and topology changes by default) and, once the
+// historian holds the post window of an event, writes a directory with what
+// an operator needs to review it:
+//
+//   <dir>/<event time>-<kind>-<source>/
+//     event.json   the event, its window, the streams and files of the bundle
+//     record.cfg   COMTRADE record of all channels of the window (and .dat)
+//     plot.csv     frequency, ROCOF and phasor magnitudes on a time grid,
+//                  ready to plot, with plot_channels.csv describing them
+//     chart.svg    quick-look chart of the window (plot feature)
+//
+// The window runs from pre_secs before to post_secs after the event, in
+// frame time. Every stream of the historian with data in the window is
+// included. Events still waiting for their post window are written by
+// finish, with the data there is.
+use crate::arrow_utils::{META_COMPONENT, META_KIND};
+use crate::events::{Event, EventBus, EventKind, Severity};
+use crate::historian::Historian;
+use crate::matrix::{MatrixExport, MatrixStats};
+use arrow::record_batch::RecordBatch;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundleConfig {
+    pub dir: PathBuf,
+    #[serde(default = "default_window_secs")]
+    pub pre_secs: f64,
+    #[serde(default = "default_window_secs")]
+    pub post_secs: f64,
+    // Events bundled
+    #[serde(default = "default_kinds")]
+    pub kinds: Vec<EventKind>,
+    #[serde(default = "default_min_severity")]
+    pub min_severity: Severity,
+}
+
+fn default_window_secs() -> f64 {
+    10.0
+}
+
+fn default_kinds() -> Vec<EventKind> {
+    vec![
+        EventKind::GeneratorTrip,
+        EventKind::LoadLoss,
+        EventKind::TopologyChange,
+    ]
+}
+
+fn default_min_severity() -> Severity {
+    Severity::Info
+}
+
+impl BundleConfig {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        BundleConfig {
+            dir: dir.as_ref().to_path_buf(),
+            pre_secs: default_window_secs(),
+            post_secs: default_window_secs(),
+            kinds: default_kinds(),
+            min_severity: default_min_severity(),
+        }
+    }
+
+    pub fn with_windows(mut self, pre_secs: f64, post_secs: f64) -> Self {
+        self.pre_secs = pre_secs.max(0.0);
+        self.post_secs = post_secs.max(0.0);
+        self
+    }
+
+    pub fn with_kinds(mut self, kinds: &[EventKind]) -> Self {
+        self.kinds = kinds.to_vec();
+        self
+    }
+
+    pub fn with_min_severity(mut self, min_severity: Severity) -> Self {
+        self.min_severity = min_severity;
+        self
+    }
+
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        serde_json::from_str(json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+pub struct EventBundler {
+    config: BundleConfig,
+    events: Option<broadcast::Receiver<Event>>,
+    pending: Vec<Event>, // Waiting for their post window
+    written: Vec<PathBuf>,
+}
+
+impl EventBundler {
+    pub fn new(config: BundleConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        Ok(EventBundler {
+            config,
+            events: None,
+            pending: Vec::new(),
+            written: Vec::new(),
+        })
+    }
+
+    pub fn with_events(mut self, bus: &EventBus) -> Self {
+        self.events = Some(bus.subscribe());
+        self
+    }
+
+    // Bundle directories written so far.
+    pub fn bundles(&self) -> &[PathBuf] {
+        &self.written
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Queue an event when it is of a bundled kind and severity.
+    pub fn offer(&mut self, event: &Event) -> bool {
+        let wanted =
+            self.config.kinds.contains(&event.kind) && event.severity >= self.config.min_severity;
+        if wanted {
+            self.pending.push(event.clone());
+        }
+        wanted
+    }
+
+    // Take the events of the bus and write the bundles whose post window the
+    // historian holds. Returns the directories written.
+    pub fn poll(&mut self, historian: &Historian) -> io::Result<Vec<PathBuf>> {
+        self.take_events();
+        let newest = historian
+            .idcodes()
+            .into_iter()
+            .filter_map(|idcode| historian.time_range(idcode))
+            .map(|(_, last)| last)
+            .max();
+        let post_us = (self.config.post_secs * 1e6) as i64;
+        let (due, waiting) = self
+            .pending
+            .drain(..)
+            .partition(|event| newest.is_some_and(|newest| newest >= event.timestamp_us + post_us));
+        self.pending = waiting;
+        self.write_all(historian, due)
+    }
+
+    // Write the bundles of all events taken, e.g. when stopping.
+    pub fn finish(&mut self, historian: &Historian) -> io::Result<Vec<PathBuf>> {
+        self.take_events();
+        let due = std::mem::take(&mut self.pending);
+        self.write_all(historian, due)
+    }
+
+    fn take_events(&mut self) {
+        let mut events = Vec::new();
+        if let Some(receiver) = self.events.as_mut() {
+            loop {
+                match receiver.try_recv() {
+                    Ok(event) => events.push(event),
+                    Err(broadcast::error::TryRecvError::Lagged(missed)) => {
+                        println!("Event bundler missed {} events", missed);
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+        for event in &events {
+            self.offer(event);
+        }
+    }
+
+    fn write_all(&mut self, historian: &Historian, events: Vec<Event>) -> io::Result<Vec<PathBuf>> {
+        let mut written = Vec::new();
+        for event in events {
+            let dir = self.write(historian, &event)?;
+            self.written.push(dir.clone());
+            written.push(dir);
+        }
+        Ok(written)
+    }
+
+    // Write the bundle of an event now, with the data the historian holds.
+    pub fn write(&self, historian: &Historian, event: &Event) -> io::Result<PathBuf> {
+        let start_us = event.timestamp_us - (self.config.pre_secs * 1e6) as i64;
+        let end_us = event.timestamp_us + (self.config.post_secs * 1e6) as i64;
+        let mut record = MatrixExport::new();
+        let mut plot = MatrixExport::new();
+        #[cfg(feature = "plot")]
+        let mut chart =
+            crate::plot::QuickLook::for_event(event, self.config.pre_secs, self.config.post_secs);
+        let mut streams = Vec::new();
+        let mut stations = Vec::new();
+        let mut rate_hz: f64 = 0.0;
+        for idcode in historian.idcodes() {
+            // The window includes the frame at its end
+            let batch = historian
+                .query(idcode, start_us, end_us + 1)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+            let Some(batch) = batch else {
+                continue;
+            };
+            let config = historian.config(idcode);
+            let station = config
+                .as_ref()
+                .and_then(|config| config.pmu_configs.first())
+                .map(|pmu| String::from_utf8_lossy(&pmu.stn).trim().to_string())
+                .unwrap_or_default();
+            // DATA_RATE is frames per second, or seconds per frame when negative
+            rate_hz = rate_hz.max(match config.map_or(0, |config| config.data_rate) {
+                rate if rate > 0 => rate as f64,
+                rate if rate < 0 => -1.0 / rate as f64,
+                _ => 0.0,
+            });
+            record.add_batch(&batch)?;
+            #[cfg(feature = "plot")]
+            chart.add_batch(&batch)?;
+            plot.add_batch(&plot_columns(&batch)?)?;
+            streams.push(json!({
+                "idcode": idcode,
+                "station": station,
+                "rows": batch.num_rows(),
+            }));
+            stations.push(station);
+        }
+
+        let dir = self.dir_for(event);
+        fs::create_dir_all(&dir)?;
+        let mut files = vec!["event.json"];
+        let mut record_stats = MatrixStats::default();
+        if !streams.is_empty() {
+            let rate_hz = rate_hz.max(1.0);
+            record = record.with_rate(rate_hz);
+            plot = plot.with_rate(rate_hz);
+            record_stats = record.write_comtrade(
+                dir.join("record.cfg"),
+                &stations.join(" "),
+                Some(event.timestamp_us),
+            )?;
+            plot.write_csv(dir.join("plot.csv"))?;
+            files.extend(["record.cfg", "record.dat", "plot.csv", "plot_channels.csv"]);
+            #[cfg(feature = "plot")]
+            {
+                chart.render(dir.join("chart.svg"))?;
+                files.push("chart.svg");
+            }
+        }
+        let description = json!({
+            "event": event,
+            "window": {
+                "start_us": start_us,
+                "end_us": end_us,
+                "pre_secs": self.config.pre_secs,
+                "post_secs": self.config.post_secs,
+            },
+            "streams": streams,
+            "record": {
+                "rows": record_stats.rows,
+                "channels": record_stats.columns,
+                "gaps": record_stats.gaps,
+            },
+            "files": files,
+        });
+        let text = serde_json::to_string_pretty(&description)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(dir.join("event.json"), text)?;
+        println!(
+            "Event bundle {} written: {} streams, {} rows",
+            dir.display(),
+            streams.len(),
+            record_stats.rows
+        );
+        Ok(dir)
+    }
+
+    // <dir>/<time>-<kind>-<source>, numbered when taken.
+    fn dir_for(&self, event: &Event) -> PathBuf {
+        let time = DateTime::from_timestamp_micros(event.timestamp_us)
+            .unwrap_or_default()
+            .format("%Y%m%dT%H%M%S%.3f");
+        let kind = serde_json::to_value(event.kind)
+            .ok()
+            .and_then(|kind| kind.as_str().map(String::from))
+            .unwrap_or_default();
+        let source: String = event
+            .source
+            .chars()
+            .take(40)
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let stem = format!("{}-{}-{}", time, kind, source);
+        let mut dir = self.config.dir.join(&stem);
+        let mut n = 1;
+        while dir.exists() {
+            dir = self.config.dir.join(format!("{}-{}", stem, n));
+            n += 1;
+        }
+        dir
+    }
+}
+
+// The timestamp, frequency, ROCOF and phasor magnitude columns.
+fn plot_columns(batch: &RecordBatch) -> io::Result<RecordBatch> {
+    let schema = batch.schema();
+    let indices: Vec<usize> = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| {
+            let meta = field.metadata();
+            field.name() == "timestamp"
+                || matches!(
+                    meta.get(META_KIND).map(String::as_str),
+                    Some("frequency" | "rocof")
+                )
+                || meta.get(META_COMPONENT).map(String::as_str) == Some("magnitude")
+        })
+        .map(|(index, _)| index)
+        .collect();
+    batch
+        .project(&indices)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
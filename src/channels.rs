@@ -0,0 +1,140 @@
+// Channel names interned to integer IDs.
+//
+// Channels are known by name (Station A_7734_VA) in configurations, queries
+// and expressions, but code that runs for every frame or sample should not
+// hash those names each time. ChannelRegistry interns names to ChannelIds,
+// dense and stable: a name keeps its ID for as long as the registry lives,
+// so IDs can index Vecs. Names are looked up once, when a query, filter or
+// expression is set up, and the hot path works with IDs.
+//
+// ChannelIndex is the channel map of a configuration (get_channel_map) in
+// that form: the channels in offset order, each with its ID, name and
+// ChannelInfo, found by name through the registry.
+use crate::frames::ChannelInfo;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChannelId(pub u32);
+
+impl ChannelId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChannelRegistry {
+    ids: HashMap<Arc<str>, ChannelId>,
+    names: Vec<Arc<str>>, // By ID
+}
+
+impl ChannelRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The ID of name, a new one the first time it is seen.
+    pub fn intern(&mut self, name: &str) -> ChannelId {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = ChannelId(self.names.len() as u32);
+        let name: Arc<str> = Arc::from(name);
+        self.names.push(name.clone());
+        self.ids.insert(name, id);
+        id
+    }
+
+    pub fn id(&self, name: &str) -> Option<ChannelId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: ChannelId) -> Option<&str> {
+        self.names.get(id.index()).map(|name| name.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    // Names in ID order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(|name| name.as_ref())
+    }
+}
+
+// The channels of a configuration by ID, IDs given in offset order.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelIndex {
+    registry: ChannelRegistry,
+    channels: Vec<ChannelInfo>, // By ID
+}
+
+impl ChannelIndex {
+    pub fn new(channel_map: &HashMap<String, ChannelInfo>) -> Self {
+        let mut channels: Vec<(&String, &ChannelInfo)> = channel_map.iter().collect();
+        channels.sort_by(|a, b| a.1.offset.cmp(&b.1.offset).then(a.0.cmp(b.0)));
+        let mut registry = ChannelRegistry::new();
+        let channels = channels
+            .into_iter()
+            .map(|(name, info)| {
+                registry.intern(name);
+                info.clone()
+            })
+            .collect();
+        ChannelIndex { registry, channels }
+    }
+
+    pub fn id(&self, name: &str) -> Option<ChannelId> {
+        self.registry.id(name)
+    }
+
+    pub fn name(&self, id: ChannelId) -> Option<&str> {
+        self.registry.name(id)
+    }
+
+    pub fn info(&self, id: ChannelId) -> Option<&ChannelInfo> {
+        self.channels.get(id.index())
+    }
+
+    // Offset of the channel in a data frame.
+    pub fn offset(&self, id: ChannelId) -> Option<usize> {
+        self.info(id).map(|info| info.offset)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ChannelInfo> {
+        self.id(name).and_then(|id| self.info(id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    // (ID, name, info) in offset order.
+    pub fn iter(&self) -> impl Iterator<Item = (ChannelId, &str, &ChannelInfo)> {
+        self.registry
+            .names()
+            .zip(&self.channels)
+            .enumerate()
+            .map(|(id, (name, info))| (ChannelId(id as u32), name, info))
+    }
+
+    // The channel map of the channels given, by name.
+    pub fn channel_map<'a>(
+        &self,
+        ids: impl IntoIterator<Item = &'a ChannelId>,
+    ) -> HashMap<String, ChannelInfo> {
+        ids.into_iter()
+            .filter_map(|id| Some((self.name(*id)?.to_string(), self.info(*id)?.clone())))
+            .collect()
+    }
+}
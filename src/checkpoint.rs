@@ -0,0 +1,197 @@
+// Snapshots of collector state, so a restart picks up where it left off.
+//
+// A checkpoint holds what a collector would otherwise have to rebuild after
+// a restart: the configuration frame and newest timestamp of every stream,
+// the groups an Aggregator is still waiting to complete and the frames a
+// Historian holds. Frames are kept raw, hex encoded in a JSON file that is
+// replaced in one step, so a crash while saving leaves the previous one.
+//
+// The components build their part with checkpoint() and take it back with
+// restore(). Checkpointer decides when the next periodic save is due.
+use crate::frame_parser::parse_config_frame_1and2;
+use crate::frames::ConfigurationFrame1and2_2011;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// A raw frame, hex encoded in the file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Frame(#[serde(with = "hex")] pub Vec<u8>);
+
+impl Frame {
+    pub fn config(&self) -> io::Result<ConfigurationFrame1and2_2011> {
+        if self.0.len() < 14 {
+            return Err(invalid_data("Configuration frame too short"));
+        }
+        parse_config_frame_1and2(&self.0)
+            .map_err(|e| invalid_data(&format!("Invalid configuration frame: {:?}", e)))
+    }
+}
+
+impl From<&ConfigurationFrame1and2_2011> for Frame {
+    fn from(config: &ConfigurationFrame1and2_2011) -> Self {
+        Frame(config.to_hex())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamState {
+    pub source: Option<String>, // host:port the stream was read from
+    pub config: Frame,
+    pub last_timestamp_us: Option<i64>, // Newest data frame seen
+}
+
+// Groups an Aggregator has not emitted yet.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AggregatorState {
+    pub configs: Vec<Frame>,
+    pub pending: Vec<Frame>,
+    pub newest_us: Option<i64>,
+    pub emitted_us: Option<i64>,
+    pub late: BTreeMap<u16, u64>,
+}
+
+// Frames held by a Historian, oldest first per stream.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistorianState {
+    pub configs: Vec<Frame>,
+    pub frames: Vec<Frame>,
+    pub evicted: BTreeMap<u16, u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub saved_us: i64,
+    #[serde(default)]
+    pub streams: BTreeMap<u16, StreamState>, // By IDCODE
+    #[serde(default)]
+    pub aggregator: Option<AggregatorState>,
+    #[serde(default)]
+    pub historian: Option<HistorianState>,
+}
+
+impl Checkpoint {
+    // None when there is no checkpoint yet.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Replace the checkpoint in one step.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let temporary = path.with_extension("tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+
+    // The stream read from a source, if it was checkpointed.
+    pub fn stream_from(&self, source: &str) -> Option<&StreamState> {
+        self.streams
+            .values()
+            .find(|stream| stream.source.as_deref() == Some(source))
+    }
+}
+
+// Every checkpoint of a directory merged into one, for collectors that save
+// one file per shard. Newer checkpoints win for streams found in several.
+pub fn load_dir(dir: impl AsRef<Path>) -> io::Result<Checkpoint> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    paths.sort();
+    let mut checkpoints = Vec::new();
+    for path in paths {
+        match Checkpoint::load(&path) {
+            Ok(Some(checkpoint)) => checkpoints.push(checkpoint),
+            Ok(None) => {}
+            Err(e) => println!("Ignoring checkpoint {}: {}", path.display(), e),
+        }
+    }
+    checkpoints.sort_by_key(|checkpoint| checkpoint.saved_us);
+    let mut merged = Checkpoint::default();
+    for checkpoint in checkpoints {
+        merged.saved_us = checkpoint.saved_us;
+        merged.streams.extend(checkpoint.streams);
+        merged.aggregator = checkpoint.aggregator.or(merged.aggregator);
+        merged.historian = checkpoint.historian.or(merged.historian);
+    }
+    Ok(merged)
+}
+
+// Decides when the next periodic checkpoint is due.
+pub struct Checkpointer {
+    path: PathBuf,
+    interval: Duration,
+    last: Instant,
+}
+
+impl Checkpointer {
+    pub fn new(path: impl AsRef<Path>, interval: Duration) -> Self {
+        Checkpointer {
+            path: path.as_ref().to_path_buf(),
+            interval,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.last.elapsed() >= self.interval
+    }
+
+    pub fn save(&mut self, checkpoint: &Checkpoint) -> io::Result<()> {
+        self.last = Instant::now();
+        checkpoint.save(&self.path)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+mod hex {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let text: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        serializer.serialize_str(&text)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        if !text.len().is_multiple_of(2) {
+            return Err(D::Error::custom("Odd number of hex digits"));
+        }
+        text.as_bytes()
+            .chunks(2)
+            .map(|pair| {
+                let pair = std::str::from_utf8(pair).map_err(D::Error::custom)?;
+                u8::from_str_radix(pair, 16).map_err(D::Error::custom)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,203 @@
+// CIM (IEC 61970) RDF/XML export of the measurement catalog.
+//
+// EMS model management tools import measurements as CIM: an Analog or
+// Discrete per measured quantity with its type and unit, attached to the
+// Terminal of the network model it is measured at. The catalog of a
+// validation (pipeline::StreamCheck) is rendered as such a document, one
+// measurement per channel and two, magnitude and angle, per phasor. STAT
+// words are left out.
+//
+// Channels mapped to an mRID in a topology file (topology::Topology) are
+// attached to that Terminal, which the document lists by reference
+// (rdf:about) with its bus and branch as description. The others are left
+// for the modeler to attach.
+//
+// The mRIDs of the measurements are derived from the channel names, so an
+// export of the same catalog gives the same identifiers and importing it
+// again updates the measurements rather than adding them twice.
+use crate::pipeline::{CatalogChannel, StreamCheck};
+use crate::topology::{ModelElement, Topology};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const CIM_NAMESPACE: &str = "http://iec.ch/TC57/2013/CIM-schema-cim16#";
+const RDF_NAMESPACE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CimMeasurement {
+    pub mrid: String,
+    pub name: String,
+    pub description: String,
+    pub class: &'static str, // Analog or Discrete
+    pub measurement_type: &'static str,
+    pub unit: &'static str, // UnitSymbol
+    pub terminal: Option<String>,
+}
+
+// Measurements of a channel, none for STAT words.
+pub fn measurements(channel: &CatalogChannel, topology: &Topology) -> Vec<CimMeasurement> {
+    let description = format!("{}, PMU {}", channel.station, channel.idcode);
+    let terminal = topology
+        .element(&channel.name)
+        .and_then(|element| element.mrid.clone());
+    let measurement = |suffix: &str, class, measurement_type, unit| {
+        let name = format!("{}{}", channel.name, suffix);
+        CimMeasurement {
+            mrid: mrid(&name),
+            name,
+            description: description.clone(),
+            class,
+            measurement_type,
+            unit,
+            terminal: terminal.clone(),
+        }
+    };
+    match channel.kind.as_str() {
+        "stat" => Vec::new(),
+        "voltage" => vec![
+            measurement("_magnitude", "Analog", "PhaseVoltage", "V"),
+            measurement("_angle", "Analog", "PhaseAngle", "rad"),
+        ],
+        "current" => vec![
+            measurement("_magnitude", "Analog", "LineCurrent", "A"),
+            measurement("_angle", "Analog", "PhaseAngle", "rad"),
+        ],
+        "frequency" => vec![measurement("", "Analog", "Frequency", "Hz")],
+        // CIM16 has no unit for Hz/s
+        "rocof" => vec![measurement("", "Analog", "RateOfChangeOfFrequency", "none")],
+        "digital" => vec![measurement("", "Discrete", "Status", "none")],
+        _ => vec![measurement(
+            "",
+            "Analog",
+            "Analog",
+            unit_symbol(&channel.unit),
+        )],
+    }
+}
+
+fn unit_symbol(unit: &str) -> &'static str {
+    match unit {
+        "V" => "V",
+        "A" => "A",
+        "Hz" => "Hz",
+        "rad" => "rad",
+        _ => "none",
+    }
+}
+
+// A UUID formatted mRID from a name, the same for the same name.
+pub fn mrid(name: &str) -> String {
+    let hash = |seed: u64| {
+        name.bytes().fold(seed, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+        })
+    };
+    let (high, low) = (hash(0xCBF2_9CE4_8422_2325), hash(0x6C62_272E_07BB_0142));
+    // Version 8 (custom) and the RFC 4122 variant
+    let high = (high & !0xF000) | 0x8000;
+    let low = (low & !(0b11 << 62)) | (0b10 << 62);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xFFFF,
+        high & 0xFFFF,
+        low >> 48,
+        low & 0xFFFF_FFFF_FFFF
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// The catalog of the streams that answered as a CIM RDF/XML document.
+pub fn catalog_xml(streams: &[StreamCheck], topology: &Topology) -> String {
+    let mut xml = String::new();
+    let _ = writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        xml,
+        r#"<rdf:RDF xmlns:rdf="{}" xmlns:cim="{}">"#,
+        RDF_NAMESPACE, CIM_NAMESPACE
+    );
+    // Terminals referred to, by mRID
+    let mut terminals: BTreeMap<String, &ModelElement> = BTreeMap::new();
+    for channel in streams.iter().flat_map(|stream| &stream.channels) {
+        for measurement in measurements(channel, topology) {
+            let class = measurement.class;
+            let _ = writeln!(xml, r#"  <cim:{} rdf:ID="_{}">"#, class, measurement.mrid);
+            let _ = writeln!(
+                xml,
+                "    <cim:IdentifiedObject.mRID>{}</cim:IdentifiedObject.mRID>",
+                measurement.mrid
+            );
+            let _ = writeln!(
+                xml,
+                "    <cim:IdentifiedObject.name>{}</cim:IdentifiedObject.name>",
+                escape(&measurement.name)
+            );
+            let _ = writeln!(
+                xml,
+                "    <cim:IdentifiedObject.description>{}</cim:IdentifiedObject.description>",
+                escape(&measurement.description)
+            );
+            let _ = writeln!(
+                xml,
+                "    <cim:Measurement.measurementType>{}</cim:Measurement.measurementType>",
+                measurement.measurement_type
+            );
+            let _ = writeln!(
+                xml,
+                r#"    <cim:Measurement.unitSymbol rdf:resource="{}UnitSymbol.{}"/>"#,
+                CIM_NAMESPACE, measurement.unit
+            );
+            let _ = writeln!(
+                xml,
+                r#"    <cim:Measurement.unitMultiplier rdf:resource="{}UnitMultiplier.none"/>"#,
+                CIM_NAMESPACE
+            );
+            if let Some(terminal) = &measurement.terminal {
+                let _ = writeln!(
+                    xml,
+                    r##"    <cim:Measurement.Terminal rdf:resource="#{}"/>"##,
+                    escape(terminal)
+                );
+                if let Some(element) = topology.element(&channel.name) {
+                    terminals.insert(terminal.clone(), element);
+                }
+            }
+            let _ = writeln!(xml, "  </cim:{}>", class);
+        }
+    }
+    for (mrid, element) in terminals {
+        let description = [("bus", &element.bus), ("branch", &element.branch)]
+            .into_iter()
+            .filter_map(|(label, value)| value.as_ref().map(|value| format!("{} {}", label, value)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let _ = writeln!(xml, r##"  <cim:Terminal rdf:about="#{}">"##, escape(&mrid));
+        if !description.is_empty() {
+            let _ = writeln!(
+                xml,
+                "    <cim:IdentifiedObject.description>{}</cim:IdentifiedObject.description>",
+                escape(&description)
+            );
+        }
+        let _ = writeln!(xml, "  </cim:Terminal>");
+    }
+    let _ = writeln!(xml, "</rdf:RDF>");
+    xml
+}
+
+pub fn write_catalog(
+    path: impl AsRef<Path>,
+    streams: &[StreamCheck],
+    topology: &Topology,
+) -> io::Result<()> {
+    fs::write(path, catalog_xml(streams, topology))
+}
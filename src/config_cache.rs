@@ -0,0 +1,98 @@
+// Configuration frames cached on disk, so a restarted collector can parse a
+// stream's data before the PDC has answered for its configuration.
+//
+// Frames are kept raw, one file per source (host:port) and idcode sent in
+// the commands, as <dir>/<host>_<port>-<idcode>.cfg. CFG-1, CFG-2 and CFG-3
+// frames are accepted; a file is only replaced, in one step, when the frame
+// differs from the one cached.
+//
+// A cached configuration may be stale: the pipeline uses it for commanded
+// UDP streams only and asks the PDC for the current one once streaming
+// (PDCClient::with_config_refresh), which then replaces it here.
+use crate::frame_parser::parse_config_frame_1and2;
+use crate::frames::ConfigurationFrame1and2_2011;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigCache {
+    dir: PathBuf,
+}
+
+impl ConfigCache {
+    pub fn new(dir: impl AsRef<Path>) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(ConfigCache {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn path(&self, source: &str, idcode: u16) -> PathBuf {
+        let name: String = source
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        self.dir.join(format!("{}-{}.cfg", name, idcode))
+    }
+
+    // Cache a configuration frame. False when it is the one cached already.
+    pub fn store(&self, source: &str, idcode: u16, frame: &[u8]) -> io::Result<bool> {
+        if frame.len() < 16 || frame[0] != 0xAA || !matches!((frame[1] >> 4) & 0x07, 2 | 3 | 5) {
+            return Err(invalid_data("Not a configuration frame"));
+        }
+        if u16::from_be_bytes([frame[2], frame[3]]) as usize != frame.len() {
+            return Err(invalid_data("FRAMESIZE does not match the frame"));
+        }
+        let path = self.path(source, idcode);
+        if self.load(source, idcode)?.as_deref() == Some(frame) {
+            return Ok(false);
+        }
+        let temporary = path.with_extension("tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(frame)?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)?;
+        Ok(true)
+    }
+
+    // The frame cached for a source, None when there is none.
+    pub fn load(&self, source: &str, idcode: u16) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(source, idcode)) {
+            Ok(frame) => Ok(Some(frame)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // The cached CFG-1 or CFG-2 of a source, parsed.
+    pub fn load_config(
+        &self,
+        source: &str,
+        idcode: u16,
+    ) -> io::Result<Option<ConfigurationFrame1and2_2011>> {
+        let Some(frame) = self.load(source, idcode)? else {
+            return Ok(None);
+        };
+        if frame.len() < 14 || !matches!((frame[1] >> 4) & 0x07, 2 | 3) {
+            return Err(invalid_data("Cached frame is not a CFG-1 or CFG-2"));
+        }
+        parse_config_frame_1and2(&frame)
+            .map(Some)
+            .map_err(|e| invalid_data(&format!("Invalid cached configuration: {:?}", e)))
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,293 @@
+// Synthetic datasets for benchmarking and validating analytics.
+//
+// A DatasetConfig (JSON) describes streams of simulated PMUs, a time span and
+// the events to inject. The simulators are run as fast as they go, without a
+// server or sockets, and their frames are turned into RecordBatches by an
+// accumulator, the same way collected data is. Hours of data take seconds.
+// Streams and PMUs given no IDCODE are assigned free ones, and IDCODEs used
+// twice are refused (idcodes::assign_layouts).
+//
+// Every injected event is also returned as an EventLabel with its stream and
+// time span, the ground truth to score detectors against. write() saves the
+// batches with the pipeline's sinks and the labels to labels.json next to them.
+use crate::accumulator::{AccumulatorError, BatchAccumulator, FlushPolicy};
+use crate::budget::MemoryBudget;
+use crate::frame_parser::parse_config_frame_1and2;
+use crate::frames::calculate_crc;
+use crate::idcodes::{self, IdcodeRange};
+use crate::pipeline::{SinkConfig, SinkFormat};
+use crate::simulator::{NoiseModel, Scenario, ScenarioEvent, Simulator, StreamLayout};
+use crate::sinks::BatchSink;
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatasetStream {
+    pub layout: StreamLayout,
+    // Events of this stream only, on top of the dataset's events
+    #[serde(default)]
+    pub events: Vec<ScenarioEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatasetConfig {
+    pub streams: Vec<DatasetStream>,
+    pub start_soc: u32,
+    pub duration_secs: f64,
+    // Events seen by every stream, e.g. a grid wide frequency ramp
+    #[serde(default)]
+    pub events: Vec<ScenarioEvent>,
+    // Noise of every stream, each stream seeded differently
+    #[serde(default)]
+    pub noise: Option<NoiseModel>,
+    #[serde(default = "default_batch_rows")]
+    pub batch_rows: usize,
+    #[serde(default)]
+    pub format: SinkFormat,
+    // IDCODEs assigned to the streams and PMUs that have none
+    #[serde(default)]
+    pub idcodes: Option<IdcodeRange>,
+}
+
+fn default_batch_rows() -> usize {
+    1800
+}
+
+impl DatasetConfig {
+    // Parse a configuration, checking the IDCODEs of its streams and
+    // assigning those left at 0.
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        let mut config: DatasetConfig = serde_json::from_str(json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let range = config
+            .idcodes
+            .map_or(1..=u16::MAX - 1, |idcodes| idcodes.range());
+        let mut layouts: Vec<StreamLayout> = config
+            .streams
+            .iter()
+            .map(|stream| stream.layout.clone())
+            .collect();
+        idcodes::assign_layouts(&mut layouts, range)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        for (stream, layout) in config.streams.iter_mut().zip(layouts) {
+            stream.layout = layout;
+        }
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    fn scenario(&self, index: usize, stream: &DatasetStream) -> Scenario {
+        Scenario {
+            start_soc: Some(self.start_soc),
+            events: self.events.iter().chain(&stream.events).cloned().collect(),
+            stream: Some(stream.layout.clone()),
+            duration: Some(self.duration_secs),
+            noise: self.noise.clone().map(|noise| NoiseModel {
+                seed: noise.seed.wrapping_add(index as u64),
+                ..noise
+            }),
+            ..Default::default()
+        }
+    }
+
+    // Ground truth: every event injected, by stream, oldest first.
+    pub fn labels(&self) -> Vec<EventLabel> {
+        let start_us = self.start_soc as i64 * 1_000_000;
+        let mut labels: Vec<EventLabel> = self
+            .streams
+            .iter()
+            .flat_map(|stream| {
+                let period_us = 1_000_000.0 / stream.layout.data_rate.max(1) as f64;
+                self.events.iter().chain(&stream.events).map(move |event| {
+                    EventLabel::new(stream.layout.idcode, event, start_us, period_us)
+                })
+            })
+            .collect();
+        labels.sort_by_key(|label| (label.start_us, label.idcode));
+        labels
+    }
+}
+
+// An injected event and the time span it affects.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventLabel {
+    pub idcode: u16,
+    pub kind: String, // Scenario event type, e.g. frequency_ramp
+    pub start_us: i64,
+    pub end_us: i64, // Equal to start_us for steps
+}
+
+impl EventLabel {
+    fn new(idcode: u16, event: &ScenarioEvent, start_us: i64, period_us: f64) -> Self {
+        let (kind, span_secs) = match event {
+            ScenarioEvent::FrequencyRamp { duration, .. } => ("frequency_ramp", *duration),
+            ScenarioEvent::PhaseJump { .. } => ("phase_jump", 0.0),
+            ScenarioEvent::VoltageSag { duration, .. } => ("voltage_sag", *duration),
+            ScenarioEvent::DropFrames { count, .. } => {
+                ("drop_frames", *count as f64 * period_us / 1_000_000.0)
+            }
+            ScenarioEvent::CorruptCrc { count, .. } => {
+                ("corrupt_crc", *count as f64 * period_us / 1_000_000.0)
+            }
+            ScenarioEvent::ConfigChange { .. } => ("config_change", 0.0),
+            ScenarioEvent::ClockJump { .. } => ("clock_jump", 0.0),
+        };
+        let start = start_us + (event.at() * 1_000_000.0).round() as i64;
+        EventLabel {
+            idcode,
+            kind: kind.to_string(),
+            start_us: start,
+            end_us: start + (span_secs * 1_000_000.0).round() as i64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatasetStats {
+    pub frames: u64,
+    pub rows: u64,
+    pub batches: u64,
+    pub rejected: u64, // Frames with a corrupted CHK, left out of the batches
+}
+
+// Batches of a dataset in time order of their frames, as (stream idcode, batch).
+pub struct DatasetGenerator {
+    simulators: Vec<Simulator>,
+    accumulator: BatchAccumulator,
+    ready: VecDeque<(u16, RecordBatch)>,
+    stats: DatasetStats,
+    finished: bool,
+}
+
+impl DatasetGenerator {
+    pub fn new(config: &DatasetConfig) -> Self {
+        let simulators = config
+            .streams
+            .iter()
+            .enumerate()
+            .map(|(index, stream)| {
+                Simulator::from_layout(&stream.layout, config.scenario(index, stream))
+            })
+            .collect();
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited())
+            .with_flush_policy(FlushPolicy::default().with_max_rows(config.batch_rows.max(1)));
+        for stream in &config.streams {
+            accumulator.add_stream(&stream.layout.to_config());
+        }
+        DatasetGenerator {
+            simulators,
+            accumulator,
+            ready: VecDeque::new(),
+            stats: DatasetStats::default(),
+            finished: false,
+        }
+    }
+
+    pub fn stats(&self) -> &DatasetStats {
+        &self.stats
+    }
+
+    // Run the simulator with the earliest next frame until a batch is ready.
+    fn advance(&mut self) -> Result<(), AccumulatorError> {
+        while self.ready.is_empty() {
+            let next = self
+                .simulators
+                .iter_mut()
+                .filter(|simulator| !simulator.is_finished())
+                .min_by_key(|simulator| simulator.next_time_us());
+            let Some(simulator) = next else {
+                let mut rest = self.accumulator.flush_all()?;
+                rest.sort_by_key(|(idcode, _)| *idcode);
+                self.ready.extend(rest);
+                self.finished = true;
+                return Ok(());
+            };
+            for frame in simulator.next_tick().frames {
+                match (frame[1] >> 4) & 0x07 {
+                    2 | 3 => {
+                        // Rows of the old configuration go in their own batch
+                        if let Ok(config) = parse_config_frame_1and2(&frame) {
+                            let idcode = config.prefix.idcode;
+                            if let Ok(Some(batch)) = self.accumulator.flush(idcode) {
+                                self.ready.push_back((idcode, batch));
+                            }
+                            self.accumulator.add_stream(&config);
+                        }
+                    }
+                    _ => {
+                        // A receiver drops frames with a bad CHK, so do they here
+                        self.stats.frames += 1;
+                        let len = frame.len();
+                        let chk = u16::from_be_bytes([frame[len - 2], frame[len - 1]]);
+                        if calculate_crc(&frame[..len - 2]) != chk {
+                            self.stats.rejected += 1;
+                            continue;
+                        }
+                        if let Some(batch) = self.accumulator.push_frame(&frame)? {
+                            self.ready.push_back(batch);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Generate the whole dataset into dir, one file (or parquet prefix) per stream
+// as the pipeline names them, and the labels to labels.json.
+pub fn write(config: &DatasetConfig, dir: impl AsRef<Path>) -> io::Result<DatasetStats> {
+    let sink = SinkConfig {
+        format: config.format,
+        dir: dir.as_ref().to_path_buf(),
+        rate_hz: None,
+        decimation_ms: None,
+        spool: None,
+        parquet: None,
+        names: Default::default(),
+    };
+    fs::create_dir_all(&sink.dir)?;
+    let labels = serde_json::to_string_pretty(&config.labels())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(sink.dir.join("labels.json"), labels)?;
+
+    let mut generator = DatasetGenerator::new(config);
+    let mut sinks: HashMap<u16, Box<dyn BatchSink + Send>> = HashMap::new();
+    for next in generator.by_ref() {
+        let (idcode, batch) = next.map_err(|e| io::Error::other(format!("{:?}", e)))?;
+        let stream_sink = match sinks.entry(idcode) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(sink.open(idcode)?),
+        };
+        stream_sink.write_batch(&batch)?;
+    }
+    for sink in sinks.values_mut() {
+        sink.close()?;
+    }
+    Ok(generator.stats().clone())
+}
+
+impl Iterator for DatasetGenerator {
+    type Item = Result<(u16, RecordBatch), AccumulatorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ready.is_empty() && !self.finished {
+            if let Err(e) = self.advance() {
+                self.finished = true;
+                return Some(Err(e));
+            }
+        }
+        let (idcode, batch) = self.ready.pop_front()?;
+        self.stats.batches += 1;
+        self.stats.rows += batch.num_rows() as u64;
+        Some(Ok((idcode, batch)))
+    }
+}
@@ -0,0 +1,240 @@
+// Dead-letter capture of frames that fail to parse.
+//
+// Frames the pipeline cannot use, e.g. configuration frames that do not
+// parse, data frames of another size than configured or with a bad CHK,
+// are written as received to a capture file (recorder format, readable by
+// dump and replay) with a JSON lines sidecar giving the context of each:
+// source, arrival time, reason and error. Vendors can be sent the file as a
+// reproducer.
+//
+// A single damaged frame on a noisy link is not worth keeping, so frames of
+// a source are written once the source failed min_failures times for the
+// same reason, the earlier ones included, and at most max_frames per source
+// and reason. Every failure is counted in the metrics.
+//
+// Files are <dir>/<name>-<start time>.pmucap and .jsonl, created with the
+// first frame written.
+use crate::metrics::Metrics;
+use crate::recorder::{CaptureCompression, CaptureWriter};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const FRAMES_METRIC: &str = "pmu_dead_letter_frames_total";
+pub const WRITTEN_METRIC: &str = "pmu_dead_letter_written_total";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetterConfig {
+    pub dir: PathBuf,
+    #[serde(default = "default_min_failures")]
+    pub min_failures: u64,
+    #[serde(default = "default_max_frames")]
+    pub max_frames: u64,
+}
+
+fn default_min_failures() -> u64 {
+    3
+}
+
+fn default_max_frames() -> u64 {
+    100
+}
+
+impl DeadLetterConfig {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        DeadLetterConfig {
+            dir: dir.as_ref().to_path_buf(),
+            min_failures: default_min_failures(),
+            max_frames: default_max_frames(),
+        }
+    }
+
+    pub fn with_limits(mut self, min_failures: u64, max_frames: u64) -> Self {
+        self.min_failures = min_failures.max(1);
+        self.max_frames = max_frames;
+        self
+    }
+}
+
+// A failed frame with its context.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub frame: Vec<u8>,
+    pub arrival_us: i64,
+    pub source: String,
+    pub reason: String, // Short, a metric label: config, frame_size, crc...
+    pub error: String,
+}
+
+#[derive(Default)]
+struct Failures {
+    count: u64,
+    written: u64,
+    held: Vec<DeadLetter>, // Before min_failures is reached
+}
+
+struct Files {
+    capture: CaptureWriter,
+    context: BufWriter<File>,
+    path: PathBuf,
+    frames: u64,
+}
+
+pub struct DeadLetterQueue {
+    config: DeadLetterConfig,
+    name: String,
+    failures: HashMap<(String, String), Failures>, // By source and reason
+    files: Option<Files>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl DeadLetterQueue {
+    pub fn new(config: DeadLetterConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        Ok(DeadLetterQueue {
+            config,
+            name: "deadletter".to_string(),
+            failures: HashMap::new(),
+            files: None,
+            metrics: None,
+        })
+    }
+
+    // Prefix of the file names, to tell queues sharing a directory apart.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        metrics.describe(FRAMES_METRIC, "Frames that failed to parse");
+        metrics.describe(
+            WRITTEN_METRIC,
+            "Failed frames written to the dead-letter capture",
+        );
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // The capture file, once a frame was written.
+    pub fn path(&self) -> Option<&Path> {
+        self.files.as_ref().map(|files| files.path.as_path())
+    }
+
+    // Frames written to the capture.
+    pub fn written(&self) -> u64 {
+        self.files.as_ref().map_or(0, |files| files.frames)
+    }
+
+    // Failures of a source for a reason, written or not.
+    pub fn failures(&self, source: &str, reason: &str) -> u64 {
+        self.failures
+            .get(&(source.to_string(), reason.to_string()))
+            .map_or(0, |failures| failures.count)
+    }
+
+    // Count a failed frame and write it when its source keeps failing.
+    // Returns the number of frames written.
+    pub fn push(&mut self, letter: DeadLetter) -> io::Result<usize> {
+        if let Some(metrics) = &self.metrics {
+            metrics.increment(
+                FRAMES_METRIC,
+                &[("source", &letter.source), ("reason", &letter.reason)],
+            );
+        }
+        let key = (letter.source.clone(), letter.reason.clone());
+        let failures = self.failures.entry(key).or_default();
+        failures.count += 1;
+        if failures.written >= self.config.max_frames {
+            return Ok(0);
+        }
+        failures.held.push(letter);
+        if failures.count < self.config.min_failures {
+            return Ok(0);
+        }
+        let letters = std::mem::take(&mut failures.held);
+        let room = (self.config.max_frames - failures.written) as usize;
+        failures.written += letters.len().min(room) as u64;
+        let mut written = 0;
+        for letter in letters.into_iter().take(room) {
+            self.write(&letter)?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    fn write(&mut self, letter: &DeadLetter) -> io::Result<()> {
+        if self.files.is_none() {
+            self.files = Some(self.create(letter.arrival_us)?);
+        }
+        let Some(files) = self.files.as_mut() else {
+            return Ok(());
+        };
+        files
+            .capture
+            .write_frame_at(&letter.frame, letter.arrival_us)?;
+        let context = json!({
+            "frame": files.frames,
+            "arrival_us": letter.arrival_us,
+            "time": DateTime::from_timestamp_micros(letter.arrival_us).map(|t| t.to_rfc3339()),
+            "source": letter.source,
+            "reason": letter.reason,
+            "error": letter.error,
+            "size": letter.frame.len(),
+        });
+        writeln!(files.context, "{}", context)?;
+        files.context.flush()?;
+        files.frames += 1;
+        if files.frames == 1 {
+            println!(
+                "Writing frames that fail to parse to {}",
+                files.path.display()
+            );
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.increment(WRITTEN_METRIC, &[("reason", &letter.reason)]);
+        }
+        Ok(())
+    }
+
+    // <dir>/<name>-<time>.pmucap, numbered when taken.
+    fn create(&self, timestamp_us: i64) -> io::Result<Files> {
+        let time = DateTime::from_timestamp_micros(timestamp_us)
+            .unwrap_or_default()
+            .format("%Y%m%dT%H%M%S");
+        let stem = format!("{}-{}", self.name, time);
+        let mut path = self.config.dir.join(format!("{}.pmucap", stem));
+        let mut n = 1;
+        while path.exists() {
+            path = self.config.dir.join(format!("{}-{}.pmucap", stem, n));
+            n += 1;
+        }
+        // A block per frame, each one durable as written
+        let capture = CaptureWriter::create(&path, CaptureCompression::None)?.with_block_frames(1);
+        let context = BufWriter::new(File::create(path.with_extension("jsonl"))?);
+        Ok(Files {
+            capture,
+            context,
+            path,
+            frames: 0,
+        })
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(mut files) = self.files.take() {
+            files.capture.finish()?;
+            files.context.flush()?;
+            println!(
+                "Dead-letter capture {} written, {} frames",
+                files.path.display(),
+                files.frames
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+// Exact duplicates of data frames, e.g. retransmitted by a PDC after a
+// reconnect.
+//
+// FrameDeduplicator remembers the (IDCODE, SOC, FRACSEC) of the last
+// capacity data frames it let through, the least recently seen forgotten
+// first, and reports a frame whose key it holds as a duplicate. A frame
+// arriving late but not seen before passes, unlike the check against the
+// newest timestamp of a checkpoint. Only frames whose CHK checks are
+// remembered, so a corrupted frame sent again intact still gets through.
+// Duplicates are counted by stream, and in the metrics as
+// pmu_duplicate_frames_total{stream="<idcode>"}.
+use crate::metrics::Metrics;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+pub const DUPLICATES_METRIC: &str = "pmu_duplicate_frames_total";
+
+// About half a minute of a stream at 30 frames per second.
+pub const DEFAULT_DEDUP_CAPACITY: usize = 1024;
+
+// IDCODE, SOC and FRACSEC as sent.
+pub type FrameKey = (u16, u32, u32);
+
+// Key of a data frame, None for other frames.
+pub fn frame_key(frame: &[u8]) -> Option<FrameKey> {
+    if frame.len() < 14 || (frame[1] >> 4) & 0x07 != 0 {
+        return None;
+    }
+    Some((
+        u16::from_be_bytes([frame[4], frame[5]]),
+        u32::from_be_bytes([frame[6], frame[7], frame[8], frame[9]]),
+        u32::from_be_bytes([frame[10], frame[11], frame[12], frame[13]]),
+    ))
+}
+
+pub struct FrameDeduplicator {
+    capacity: usize,
+    seen: HashMap<FrameKey, u64>, // Key -> when last seen
+    order: BTreeMap<u64, FrameKey>,
+    clock: u64,
+    duplicates: HashMap<u16, u64>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl FrameDeduplicator {
+    pub fn new(capacity: usize) -> Self {
+        FrameDeduplicator {
+            capacity: capacity.max(1),
+            seen: HashMap::new(),
+            order: BTreeMap::new(),
+            clock: 0,
+            duplicates: HashMap::new(),
+            metrics: None,
+        }
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        metrics.describe(
+            DUPLICATES_METRIC,
+            "Data frames dropped as exact duplicates of one received before",
+        );
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // Whether a data frame was seen before, counting it if so. A frame
+    // with a valid CHK is remembered, as the most recent one if seen before.
+    pub fn is_duplicate(&mut self, frame: &[u8], crc_ok: bool) -> bool {
+        let Some(key) = frame_key(frame) else {
+            return false;
+        };
+        self.clock += 1;
+        if let Some(last) = self.seen.get_mut(&key) {
+            self.order.remove(last);
+            *last = self.clock;
+            self.order.insert(self.clock, key);
+            *self.duplicates.entry(key.0).or_default() += 1;
+            if let Some(metrics) = &self.metrics {
+                metrics.increment(DUPLICATES_METRIC, &[("stream", &key.0.to_string())]);
+            }
+            return true;
+        }
+        if !crc_ok {
+            return false;
+        }
+        self.seen.insert(key, self.clock);
+        self.order.insert(self.clock, key);
+        while self.seen.len() > self.capacity {
+            match self.order.pop_first() {
+                Some((_, oldest)) => self.seen.remove(&oldest),
+                None => break,
+            };
+        }
+        false
+    }
+
+    // Duplicates dropped of a stream so far.
+    pub fn duplicates(&self, idcode: u16) -> u64 {
+        self.duplicates.get(&idcode).copied().unwrap_or(0)
+    }
+
+    pub fn total_duplicates(&self) -> u64 {
+        self.duplicates.values().sum()
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+impl Default for FrameDeduplicator {
+    fn default() -> Self {
+        FrameDeduplicator::new(DEFAULT_DEDUP_CAPACITY)
+    }
+}
@@ -0,0 +1,731 @@
+// Derived channels: expressions over the channels of a batch.
+//
+// A DerivedChannel names an expression such as `abs(VA)` or
+// `(VA - VB) / VN`, given in the pipeline configuration. Expressions are
+// parsed once and evaluated per batch with Arrow compute kernels, each adding
+// a Float64 column to the batch.
+//
+// Expressions are numbers, channel names, + - * / with the usual precedence,
+// unary minus, parentheses and the functions abs, sqrt, min and max. Names
+// with characters other than letters, digits, '_' and '.' are quoted with
+// backticks or double quotes, e.g. `Station A_7734_VA`.
+//
+// A name is the column or channel of that name, phasors giving their
+// magnitude (arrow_utils::channel_values), or else the channel of that name
+// of the one station in the batch having it: VA is Station A_7734_VA in a
+// batch of Station A. A derived channel whose inputs belong to one station is
+// named after it the same way, Station A_7734_<name>.
+//
+// Derived channels are evaluated in order and may use the ones before them.
+// Those with inputs missing from a batch are left out of it; missing values
+// give null rows.
+//
+// Instead of an expression a derived channel can be a WeightedMean of
+// channels, e.g. the frequency of an area as the mean of its PMUs'
+// frequencies weighted by the inertia behind each. Per row, values further
+// than max_deviation from the median of the row are rejected as outliers, and
+// the mean is taken over the inputs left, null when fewer than min_inputs.
+use crate::arrow_utils::{
+    channel_values, META_CHANNEL, META_EXPRESSION, META_IDCODE, META_KIND, META_STATION, META_UNIT,
+};
+use crate::channels::{ChannelId, ChannelRegistry};
+use arrow::array::{Array, ArrayRef, AsArray, Datum, Float64Array};
+use arrow::compute::kernels::numeric;
+use arrow::compute::{binary, unary};
+use arrow::datatypes::{DataType, Field, Float64Type, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DerivedChannel {
+    pub name: String,
+    // Empty for a weighted mean
+    #[serde(default)]
+    pub expression: String,
+    #[serde(default)]
+    pub weighted_mean: Option<WeightedMean>,
+    #[serde(default)]
+    pub unit: Option<String>,
+}
+
+impl DerivedChannel {
+    pub fn new(name: &str, expression: &str) -> Self {
+        DerivedChannel {
+            name: name.to_string(),
+            expression: expression.to_string(),
+            weighted_mean: None,
+            unit: None,
+        }
+    }
+
+    pub fn weighted_mean(name: &str, weighted_mean: WeightedMean) -> Self {
+        DerivedChannel {
+            name: name.to_string(),
+            expression: String::new(),
+            weighted_mean: Some(weighted_mean),
+            unit: None,
+        }
+    }
+
+    pub fn with_unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.to_string());
+        self
+    }
+}
+
+// Mean of channels by weight, e.g. inertia weighted area frequency.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WeightedMean {
+    // Channel name (resolved as in expressions) and its weight
+    pub weights: BTreeMap<String, f64>,
+    // Values further than this from the median of their row are left out
+    #[serde(default)]
+    pub max_deviation: Option<f64>,
+    // Rows with fewer valid inputs are null
+    #[serde(default = "default_min_inputs")]
+    pub min_inputs: usize,
+}
+
+fn default_min_inputs() -> usize {
+    1
+}
+
+impl WeightedMean {
+    pub fn new(weights: &[(&str, f64)]) -> Self {
+        WeightedMean {
+            weights: weights
+                .iter()
+                .map(|(name, weight)| (name.to_string(), *weight))
+                .collect(),
+            max_deviation: None,
+            min_inputs: 1,
+        }
+    }
+
+    pub fn with_max_deviation(mut self, max_deviation: f64) -> Self {
+        self.max_deviation = Some(max_deviation);
+        self
+    }
+
+    pub fn with_min_inputs(mut self, min_inputs: usize) -> Self {
+        self.min_inputs = min_inputs.max(1);
+        self
+    }
+
+    // Description kept in the field metadata, e.g. weighted_mean(A * 2, B * 1).
+    pub fn describe(&self) -> String {
+        let terms: Vec<String> = self
+            .weights
+            .iter()
+            .map(|(name, weight)| format!("{} * {}", name, weight))
+            .collect();
+        format!("weighted_mean({})", terms.join(", "))
+    }
+
+    // The mean over the inputs present, rows long.
+    pub fn evaluate(&self, inputs: &HashMap<String, Float64Array>, rows: usize) -> Float64Array {
+        let inputs: Vec<(f64, &Float64Array)> = self
+            .weights
+            .iter()
+            .filter(|(_, weight)| **weight > 0.0)
+            .filter_map(|(name, weight)| inputs.get(name).map(|values| (*weight, values)))
+            .collect();
+        (0..rows)
+            .map(|row| {
+                let mut values: Vec<(f64, f64)> = inputs
+                    .iter()
+                    .filter(|(_, values)| values.is_valid(row))
+                    .map(|(weight, values)| (*weight, values.value(row)))
+                    .filter(|(_, value)| value.is_finite())
+                    .collect();
+                if let (Some(max_deviation), Some(median)) = (self.max_deviation, median(&values)) {
+                    values.retain(|(_, value)| (value - median).abs() <= max_deviation);
+                }
+                if values.len() < self.min_inputs || values.is_empty() {
+                    return None;
+                }
+                let total: f64 = values.iter().map(|(weight, _)| weight).sum();
+                Some(
+                    values
+                        .iter()
+                        .map(|(weight, value)| weight * value)
+                        .sum::<f64>()
+                        / total,
+                )
+            })
+            .collect()
+    }
+}
+
+// Median of the values of a row, the mean of the middle two for an even count.
+fn median(values: &[(f64, f64)]) -> Option<f64> {
+    let mut sorted: Vec<f64> = values.iter().map(|(_, value)| *value).collect();
+    sorted.sort_by(f64::total_cmp);
+    let n = sorted.len();
+    match n {
+        0 => None,
+        _ if n % 2 == 1 => Some(sorted[n / 2]),
+        _ => Some((sorted[n / 2 - 1] + sorted[n / 2]) / 2.0),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Operator {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            Operator::Add => a + b,
+            Operator::Subtract => a - b,
+            Operator::Multiply => a * b,
+            Operator::Divide => a / b,
+        }
+    }
+
+    fn kernel(self, a: &dyn Datum, b: &dyn Datum) -> Result<ArrayRef, ArrowError> {
+        match self {
+            Operator::Add => numeric::add(a, b),
+            Operator::Subtract => numeric::sub(a, b),
+            Operator::Multiply => numeric::mul(a, b),
+            Operator::Divide => numeric::div(a, b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Abs,
+    Sqrt,
+    Min,
+    Max,
+}
+
+impl Function {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "abs" => Some(Function::Abs),
+            "sqrt" => Some(Function::Sqrt),
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            _ => None,
+        }
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Function::Abs | Function::Sqrt => 1,
+            Function::Min | Function::Max => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Channel(String),
+    Negate(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> io::Result<Expr> {
+        let invalid = |message: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid expression '{}': {}", text, message),
+            )
+        };
+        let mut parser = Parser {
+            tokens: tokenize(text).map_err(invalid)?,
+            position: 0,
+        };
+        let expr = parser.expr().map_err(invalid)?;
+        match parser.next() {
+            None => Ok(expr),
+            Some(token) => Err(invalid(format!("unexpected {:?}", token))),
+        }
+    }
+
+    // Names used, in order of appearance.
+    pub fn channels(&self) -> Vec<&str> {
+        let mut channels = Vec::new();
+        self.collect_channels(&mut channels);
+        channels
+    }
+
+    fn collect_channels<'a>(&'a self, channels: &mut Vec<&'a str>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Channel(name) => {
+                if !channels.contains(&name.as_str()) {
+                    channels.push(name);
+                }
+            }
+            Expr::Negate(expr) => expr.collect_channels(channels),
+            Expr::Binary(_, a, b) => {
+                a.collect_channels(channels);
+                b.collect_channels(channels);
+            }
+            Expr::Call(_, args) => {
+                for arg in args {
+                    arg.collect_channels(channels);
+                }
+            }
+        }
+    }
+
+    // Evaluate over the values of the channels, rows long.
+    pub fn evaluate(
+        &self,
+        inputs: &HashMap<String, Float64Array>,
+        rows: usize,
+    ) -> Result<Float64Array, ArrowError> {
+        match self.value(inputs)? {
+            Value::Array(values) => Ok(values),
+            Value::Scalar(value) => Ok(Float64Array::from_value(value, rows)),
+        }
+    }
+
+    fn value(&self, inputs: &HashMap<String, Float64Array>) -> Result<Value, ArrowError> {
+        Ok(match self {
+            Expr::Number(value) => Value::Scalar(*value),
+            Expr::Channel(name) => Value::Array(
+                inputs
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| ArrowError::InvalidArgumentError(name.clone()))?,
+            ),
+            Expr::Negate(expr) => match expr.value(inputs)? {
+                Value::Scalar(value) => Value::Scalar(-value),
+                Value::Array(values) => Value::from(numeric::neg(&values)?),
+            },
+            Expr::Binary(operator, a, b) => match (a.value(inputs)?, b.value(inputs)?) {
+                (Value::Scalar(a), Value::Scalar(b)) => Value::Scalar(operator.apply(a, b)),
+                (Value::Array(a), Value::Array(b)) => Value::from(operator.kernel(&a, &b)?),
+                (Value::Array(a), Value::Scalar(b)) => {
+                    Value::from(operator.kernel(&a, &Float64Array::new_scalar(b))?)
+                }
+                (Value::Scalar(a), Value::Array(b)) => {
+                    Value::from(operator.kernel(&Float64Array::new_scalar(a), &b)?)
+                }
+            },
+            Expr::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.value(inputs))
+                    .collect::<Result<Vec<_>, _>>()?;
+                call(*function, &args)?
+            }
+        })
+    }
+}
+
+enum Value {
+    Scalar(f64),
+    Array(Float64Array),
+}
+
+impl From<ArrayRef> for Value {
+    fn from(array: ArrayRef) -> Self {
+        Value::Array(array.as_primitive::<Float64Type>().clone())
+    }
+}
+
+fn call(function: Function, args: &[Value]) -> Result<Value, ArrowError> {
+    let single = |f: fn(f64) -> f64| match &args[0] {
+        Value::Scalar(value) => Value::Scalar(f(*value)),
+        Value::Array(values) => Value::Array(unary(values, f)),
+    };
+    let pair = |f: fn(f64, f64) -> f64| -> Result<Value, ArrowError> {
+        Ok(match (&args[0], &args[1]) {
+            (Value::Scalar(a), Value::Scalar(b)) => Value::Scalar(f(*a, *b)),
+            (Value::Array(a), Value::Array(b)) => Value::Array(binary(a, b, f)?),
+            (Value::Array(a), Value::Scalar(b)) => Value::Array(unary(a, |a| f(a, *b))),
+            (Value::Scalar(a), Value::Array(b)) => Value::Array(unary(b, |b| f(*a, b))),
+        })
+    };
+    match function {
+        Function::Abs => Ok(single(f64::abs)),
+        Function::Sqrt => Ok(single(f64::sqrt)),
+        Function::Min => pair(f64::min),
+        Function::Max => pair(f64::max),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Quoted(String),
+    Operator(char),
+    Open,
+    Close,
+    Comma,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        i += 1;
+        match c {
+            _ if c.is_whitespace() => {}
+            '+' | '-' | '*' | '/' => tokens.push(Token::Operator(c)),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            ',' => tokens.push(Token::Comma),
+            '`' | '"' => {
+                let Some(end) = chars[i..].iter().position(|&q| q == c) else {
+                    return Err(format!("unterminated name at {}", start));
+                };
+                tokens.push(Token::Quoted(chars[i..i + end].iter().collect()));
+                i += end + 1;
+            }
+            _ if c.is_ascii_digit() || c == '.' => {
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                // Exponent, e.g. 1e-3
+                if i < chars.len() && matches!(chars[i], 'e' | 'E') {
+                    let mut j = i + 1;
+                    if j < chars.len() && matches!(chars[j], '+' | '-') {
+                        j += 1;
+                    }
+                    if j < chars.len() && chars[j].is_ascii_digit() {
+                        i = j;
+                        while i < chars.len() && chars[i].is_ascii_digit() {
+                            i += 1;
+                        }
+                    }
+                }
+                let number: String = chars[start..i].iter().collect();
+                let value = number
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid number {}", number))?;
+                tokens.push(Token::Number(value));
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+                {
+                    i += 1;
+                }
+                tokens.push(Token::Name(chars[start..i].iter().collect()));
+            }
+            _ => return Err(format!("unexpected '{}' at {}", c, start)),
+        }
+    }
+    Ok(tokens)
+}
+
+// Recursive descent, one level per precedence.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected {:?}, found {:?}", expected, token)),
+            None => Err(format!("expected {:?} at the end", expected)),
+        }
+    }
+
+    // Sums and differences of terms
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.term()?;
+        while let Some(Token::Operator(c @ ('+' | '-'))) = self.peek() {
+            let operator = if *c == '+' {
+                Operator::Add
+            } else {
+                Operator::Subtract
+            };
+            self.position += 1;
+            expr = Expr::Binary(operator, Box::new(expr), Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+
+    // Products and quotients of factors
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut expr = self.factor()?;
+        while let Some(Token::Operator(c @ ('*' | '/'))) = self.peek() {
+            let operator = if *c == '*' {
+                Operator::Multiply
+            } else {
+                Operator::Divide
+            };
+            self.position += 1;
+            expr = Expr::Binary(operator, Box::new(expr), Box::new(self.factor()?));
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Operator('-')) => Ok(Expr::Negate(Box::new(self.factor()?))),
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Quoted(name)) => Ok(Expr::Channel(name)),
+            Some(Token::Name(name)) if self.peek() == Some(&Token::Open) => {
+                let function =
+                    Function::parse(&name).ok_or_else(|| format!("unknown function {}", name))?;
+                self.position += 1;
+                let mut args = vec![self.expr()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.position += 1;
+                    args.push(self.expr()?);
+                }
+                self.expect(Token::Close)?;
+                if args.len() != function.arity() {
+                    return Err(format!(
+                        "{} takes {} arguments, not {}",
+                        name,
+                        function.arity(),
+                        args.len()
+                    ));
+                }
+                Ok(Expr::Call(function, args))
+            }
+            Some(Token::Name(name)) => Ok(Expr::Channel(name)),
+            Some(Token::Open) => {
+                let expr = self.expr()?;
+                self.expect(Token::Close)?;
+                Ok(expr)
+            }
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+}
+
+// Station name and idcode of a column.
+type Station = (String, String);
+
+fn station_of(field: &Field) -> Option<Station> {
+    let meta = field.metadata();
+    Some((
+        meta.get(META_STATION)?.clone(),
+        meta.get(META_IDCODE)?.clone(),
+    ))
+}
+
+// Values of a name in a batch, with the station they belong to.
+fn resolve(batch: &RecordBatch, name: &str) -> Option<(Float64Array, Option<Station>)> {
+    let schema = batch.schema();
+    if let Some(values) = channel_values(batch, name) {
+        let station = schema
+            .fields()
+            .iter()
+            .find(|field| {
+                field.name() == name
+                    || field.metadata().get(META_CHANNEL).map(String::as_str) == Some(name)
+            })
+            .and_then(|field| station_of(field));
+        return Some((values, station));
+    }
+    let mut stations: Vec<Station> = schema
+        .fields()
+        .iter()
+        .filter_map(|field| {
+            let (station, idcode) = station_of(field)?;
+            let full = format!("{}_{}_{}", station, idcode, name);
+            let channel = field.metadata().get(META_CHANNEL);
+            (field.name() == &full || channel == Some(&full)).then_some((station, idcode))
+        })
+        .collect();
+    stations.dedup();
+    match stations.as_slice() {
+        [(station, idcode)] => {
+            let values = channel_values(batch, &format!("{}_{}_{}", station, idcode, name))?;
+            Some((values, Some((station.clone(), idcode.clone()))))
+        }
+        _ => None,
+    }
+}
+
+// What a derived channel computes.
+#[derive(Debug, Clone)]
+enum Formula {
+    Expression(Expr),
+    WeightedMean(WeightedMean),
+}
+
+impl Formula {
+    fn channels(&self) -> Vec<&str> {
+        match self {
+            Formula::Expression(expr) => expr.channels(),
+            Formula::WeightedMean(mean) => mean.weights.keys().map(String::as_str).collect(),
+        }
+    }
+
+    // Whether the inputs found are enough: all of an expression's, at least
+    // min_inputs of a mean's.
+    fn is_complete(&self, found: usize) -> bool {
+        match self {
+            Formula::Expression(expr) => found == expr.channels().len(),
+            Formula::WeightedMean(mean) => found >= mean.min_inputs.max(1),
+        }
+    }
+
+    fn evaluate(
+        &self,
+        inputs: &HashMap<String, Float64Array>,
+        rows: usize,
+    ) -> Result<Float64Array, ArrowError> {
+        match self {
+            Formula::Expression(expr) => expr.evaluate(inputs, rows),
+            Formula::WeightedMean(mean) => Ok(mean.evaluate(inputs, rows)),
+        }
+    }
+}
+
+// Compiled derived channels, applied to each batch. The names of their
+// inputs are interned, so a batch resolves each one once however many
+// derived channels use it.
+#[derive(Debug, Clone, Default)]
+pub struct DerivedChannels {
+    channels: Vec<(DerivedChannel, Formula, Vec<ChannelId>)>, // With their inputs
+    inputs: ChannelRegistry,
+}
+
+impl DerivedChannels {
+    pub fn new(definitions: Vec<DerivedChannel>) -> io::Result<Self> {
+        let mut names = HashSet::new();
+        let mut channels = Vec::with_capacity(definitions.len());
+        let mut inputs = ChannelRegistry::new();
+        for definition in definitions {
+            if definition.name.is_empty() || !names.insert(definition.name.clone()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Derived channel names must be unique: '{}'",
+                        definition.name
+                    ),
+                ));
+            }
+            let formula = match &definition.weighted_mean {
+                Some(_) if !definition.expression.is_empty() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "Derived channel '{}' has both an expression and a weighted mean",
+                            definition.name
+                        ),
+                    ));
+                }
+                Some(mean) if mean.weights.is_empty() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Weighted mean '{}' has no inputs", definition.name),
+                    ));
+                }
+                Some(mean) => Formula::WeightedMean(mean.clone()),
+                None => Formula::Expression(Expr::parse(&definition.expression)?),
+            };
+            let ids = formula
+                .channels()
+                .into_iter()
+                .map(|name| inputs.intern(name))
+                .collect();
+            channels.push((definition, formula, ids));
+        }
+        Ok(DerivedChannels { channels, inputs })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    // The batch with a column per derived channel whose inputs it has.
+    pub fn apply(&self, batch: &RecordBatch) -> Result<RecordBatch, ArrowError> {
+        let mut batch = batch.clone();
+        // Inputs found in the batch so far, by ID
+        let mut resolved: Vec<Option<(Float64Array, Option<Station>)>> =
+            vec![None; self.inputs.len()];
+        for (definition, formula, ids) in &self.channels {
+            let mut inputs = HashMap::new();
+            let mut stations = HashSet::new();
+            for &id in ids {
+                let Some(name) = self.inputs.name(id) else {
+                    continue;
+                };
+                if resolved[id.index()].is_none() {
+                    resolved[id.index()] = resolve(&batch, name);
+                }
+                if let Some((values, station)) = &resolved[id.index()] {
+                    inputs.insert(name.to_string(), values.clone());
+                    stations.insert(station.clone());
+                }
+            }
+            if !formula.is_complete(inputs.len()) {
+                continue;
+            }
+            let values = formula.evaluate(&inputs, batch.num_rows())?;
+
+            let expression = match &definition.weighted_mean {
+                Some(mean) => mean.describe(),
+                None => definition.expression.clone(),
+            };
+            let mut metadata = HashMap::from([(META_EXPRESSION.to_string(), expression)]);
+            metadata.insert(META_KIND.to_string(), "derived".to_string());
+            if let Some(unit) = &definition.unit {
+                metadata.insert(META_UNIT.to_string(), unit.clone());
+            }
+            let name = match stations.into_iter().collect::<Vec<_>>().as_slice() {
+                [Some((station, idcode))] => {
+                    metadata.insert(META_STATION.to_string(), station.clone());
+                    metadata.insert(META_IDCODE.to_string(), idcode.clone());
+                    format!("{}_{}_{}", station, idcode, definition.name)
+                }
+                _ => definition.name.clone(),
+            };
+            metadata.insert(META_CHANNEL.to_string(), name.clone());
+            // The new column is what its names resolve to from now on
+            for added in [&name, &definition.name] {
+                if let Some(id) = self.inputs.id(added) {
+                    resolved[id.index()] = None;
+                }
+            }
+
+            let schema = batch.schema();
+            let mut fields: Vec<Field> = schema
+                .fields()
+                .iter()
+                .map(|field| field.as_ref().clone())
+                .collect();
+            fields.push(Field::new(name, DataType::Float64, true).with_metadata(metadata));
+            let mut columns = batch.columns().to_vec();
+            columns.push(Arc::new(values) as ArrayRef);
+            batch = RecordBatch::try_new(
+                Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone())),
+                columns,
+            )?;
+        }
+        Ok(batch)
+    }
+}
@@ -0,0 +1,289 @@
+// Detection of the frame format of a capture of unknown origin.
+//
+// Scans a byte blob for SYNC words (0xAA, then a frame type of 0-5 and a
+// version of 1-3) whose FRAMESIZE fits and whose CHK matches, and reports
+// what the valid frames tell: the protocol version, where the first frame
+// starts, the frame types, stream IDCODEs and the frame rate. The rate is
+// DATA_RATE of a configuration frame when there is one, else counted from
+// the data frames per second of SOC.
+//
+// Candidates whose CHK does not match but which are followed by another
+// SYNC word at FRAMESIZE are counted apart: many of them point at frames of
+// another checksum (BPA PDCstream) or bytes altered on the way. Hex text is
+// decoded first; recorder files are recognized by their magic.
+use crate::frame_parser::parse_config_frame_1and2;
+use crate::frames::calculate_crc;
+use crate::recorder::CAPTURE_MAGIC;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+const SYNC: u8 = 0xAA;
+const MIN_FRAME_SIZE: usize = 16; // Prefix and CHK
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Binary,
+    HexText,
+    Recorder, // recorder::CaptureWriter file, frames possibly compressed
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateSource {
+    Config,     // DATA_RATE of a configuration frame
+    Timestamps, // Data frames counted per second
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Detection {
+    pub encoding: Encoding,
+    pub bytes: usize,          // Scanned, after decoding hex text
+    pub offset: Option<usize>, // First valid frame
+    pub version: Option<u8>,   // Most frequent SYNC version of the valid frames
+    pub frames: usize,
+    pub frame_types: BTreeMap<&'static str, usize>,
+    pub crc_failures: usize, // Chained candidates with a CHK that does not match
+    pub coverage: f64,       // Share of the bytes in valid frames
+    pub idcodes: Vec<u16>,
+    pub data_frame_size: Option<usize>, // Most frequent
+    pub time_base: Option<u32>,
+    pub data_rate: Option<f64>, // Frames per second
+    pub rate_source: Option<RateSource>,
+    pub first_soc: Option<u32>,
+    pub last_soc: Option<u32>,
+}
+
+impl Detection {
+    // Name of the protocol of the detected version.
+    pub fn protocol(&self) -> &'static str {
+        match self.version {
+            Some(1) => "IEEE C37.118-2005",
+            Some(2) => "IEEE C37.118.2-2011",
+            Some(3) => "IEEE C37.118.2-2024",
+            _ => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let encoding = match self.encoding {
+            Encoding::Binary => "binary",
+            Encoding::HexText => "hex text",
+            Encoding::Recorder => "recorder capture",
+        };
+        writeln!(f, "Encoding: {}, {} bytes", encoding, self.bytes)?;
+        let Some(offset) = self.offset else {
+            writeln!(f, "No C37.118 frame found")?;
+            if self.crc_failures > 0 {
+                writeln!(
+                    f,
+                    "{} frame-like candidates with a bad CHK, another checksum or altered bytes?",
+                    self.crc_failures
+                )?;
+            }
+            return Ok(());
+        };
+        writeln!(
+            f,
+            "Protocol: {} (version {})",
+            self.protocol(),
+            self.version.unwrap_or(0)
+        )?;
+        writeln!(f, "First frame at byte {} (0x{:X})", offset, offset)?;
+        let types: Vec<String> = self
+            .frame_types
+            .iter()
+            .map(|(kind, count)| format!("{} {}", count, kind))
+            .collect();
+        writeln!(
+            f,
+            "Frames: {} ({}), {:.1}% of the bytes",
+            self.frames,
+            types.join(", "),
+            self.coverage * 100.0
+        )?;
+        if self.crc_failures > 0 {
+            writeln!(f, "Candidates with a bad CHK: {}", self.crc_failures)?;
+        }
+        let idcodes: Vec<String> = self.idcodes.iter().map(u16::to_string).collect();
+        writeln!(f, "IDCODEs: {}", idcodes.join(", "))?;
+        if let Some(size) = self.data_frame_size {
+            writeln!(f, "Data frame size: {} bytes", size)?;
+        }
+        if let Some(time_base) = self.time_base {
+            writeln!(f, "Time base: {}", time_base)?;
+        }
+        match (self.data_rate, self.rate_source) {
+            (Some(rate), Some(RateSource::Config)) => {
+                writeln!(f, "Frame rate: {} frames/s (configuration)", rate)?
+            }
+            (Some(rate), _) => writeln!(f, "Frame rate: about {} frames/s (timestamps)", rate)?,
+            _ => writeln!(f, "Frame rate: unknown")?,
+        }
+        if let (Some(first), Some(last)) = (self.first_soc, self.last_soc) {
+            let time = |soc: u32| {
+                chrono::DateTime::from_timestamp(soc as i64, 0)
+                    .map_or_else(|| soc.to_string(), |t| t.to_rfc3339())
+            };
+            writeln!(f, "Time span: {} to {}", time(first), time(last))?;
+        }
+        Ok(())
+    }
+}
+
+fn frame_type_name(frame_type: u8) -> &'static str {
+    match frame_type {
+        0 => "data",
+        1 => "header",
+        2 => "configuration 1",
+        3 => "configuration 2",
+        4 => "command",
+        _ => "configuration 3",
+    }
+}
+
+// Whether SYNC, frame type and version are plausible at the start of bytes.
+fn is_sync(bytes: &[u8]) -> bool {
+    bytes.len() >= 2 && bytes[0] == SYNC && (bytes[1] >> 4) & 0x07 <= 5 && {
+        let version = bytes[1] & 0x0F;
+        (1..=3).contains(&version)
+    }
+}
+
+// Hex text (digits and whitespace only, an even number of digits) decoded.
+fn decode_hex(bytes: &[u8]) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = bytes
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    if digits.is_empty()
+        || !digits.len().is_multiple_of(2)
+        || !digits.iter().all(u8::is_ascii_hexdigit)
+    {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+// The most frequent value, the smallest of equally frequent ones.
+fn most_frequent<T: Copy + Ord + std::hash::Hash>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts: HashMap<T, usize> = HashMap::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by(|(a, n), (b, m)| n.cmp(m).then(b.cmp(a)))
+        .map(|(value, _)| value)
+}
+
+pub fn detect(bytes: &[u8]) -> Detection {
+    let mut detection = Detection::default();
+    let decoded;
+    let bytes = if bytes.starts_with(CAPTURE_MAGIC) {
+        detection.encoding = Encoding::Recorder;
+        bytes
+    } else if let Some(hex) = decode_hex(bytes) {
+        detection.encoding = Encoding::HexText;
+        decoded = hex;
+        &decoded
+    } else {
+        bytes
+    };
+    detection.bytes = bytes.len();
+
+    let mut versions = Vec::new();
+    let mut data_sizes = Vec::new();
+    let mut data_socs: HashMap<u16, Vec<u32>> = HashMap::new();
+    let mut frame_bytes = 0;
+    let mut pos = 0;
+    while pos + MIN_FRAME_SIZE <= bytes.len() {
+        let rest = &bytes[pos..];
+        if !is_sync(rest) {
+            pos += 1;
+            continue;
+        }
+        let size = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+        if size < MIN_FRAME_SIZE || size > rest.len() {
+            pos += 1;
+            continue;
+        }
+        let chk = u16::from_be_bytes([rest[size - 2], rest[size - 1]]);
+        if calculate_crc(&rest[..size - 2]) != chk {
+            // Another frame right after makes it a frame with a different CHK
+            if size == rest.len() || is_sync(&rest[size..]) {
+                detection.crc_failures += 1;
+            }
+            pos += 1;
+            continue;
+        }
+        let frame = &rest[..size];
+        let frame_type = (frame[1] >> 4) & 0x07;
+        let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+        let soc = u32::from_be_bytes([frame[6], frame[7], frame[8], frame[9]]);
+        detection.offset.get_or_insert(pos);
+        detection.frames += 1;
+        *detection
+            .frame_types
+            .entry(frame_type_name(frame_type))
+            .or_default() += 1;
+        versions.push(frame[1] & 0x0F);
+        if !detection.idcodes.contains(&idcode) {
+            detection.idcodes.push(idcode);
+        }
+        match frame_type {
+            0 => {
+                data_sizes.push(size);
+                data_socs.entry(idcode).or_default().push(soc);
+                detection.first_soc = Some(detection.first_soc.map_or(soc, |s| s.min(soc)));
+                detection.last_soc = Some(detection.last_soc.map_or(soc, |s| s.max(soc)));
+            }
+            2 | 3 if detection.data_rate.is_none() => {
+                if let Ok(config) = parse_config_frame_1and2(frame) {
+                    detection.time_base = Some(config.time_base & 0x00FF_FFFF);
+                    // DATA_RATE is frames per second, or seconds per frame when negative
+                    detection.data_rate = match config.data_rate {
+                        rate if rate > 0 => Some(rate as f64),
+                        rate if rate < 0 => Some(-1.0 / rate as f64),
+                        _ => None,
+                    };
+                    detection.rate_source = detection.data_rate.map(|_| RateSource::Config);
+                }
+            }
+            _ => {}
+        }
+        frame_bytes += size;
+        pos += size;
+    }
+    detection.version = most_frequent(versions.into_iter());
+    detection.data_frame_size = most_frequent(data_sizes.into_iter());
+    detection.coverage = match bytes.len() {
+        0 => 0.0,
+        len => frame_bytes as f64 / len as f64,
+    };
+    if detection.data_rate.is_none() {
+        // Frames per whole second of SOC, the first and last may be partial
+        let counts = data_socs.values().flat_map(|socs| {
+            let mut per_second: BTreeMap<u32, usize> = BTreeMap::new();
+            for soc in socs {
+                *per_second.entry(*soc).or_default() += 1;
+            }
+            let len = per_second.len();
+            let inner: Vec<usize> = match len {
+                0..=2 => per_second.into_values().collect(),
+                _ => per_second.into_values().skip(1).take(len - 2).collect(),
+            };
+            inner
+        });
+        if let Some(count) = most_frequent(counts) {
+            detection.data_rate = Some(count as f64);
+            detection.rate_source = Some(RateSource::Timestamps);
+        }
+    }
+    detection
+}
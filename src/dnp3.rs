@@ -0,0 +1,709 @@
+// DNP3 outstation serving selected channels as analog inputs.
+//
+// Many control centers poll DNP3 rather than take synchrophasors, so a
+// Dnp3Config maps channels to analog input points (group 30) of an
+// outstation on TCP. A Dnp3Gateway, a BatchSink, keeps the points up to date
+// from the batches of a stream, at most once per decimation interval; the
+// outstation answers the master's polls with the latest values.
+//
+// A point names a column (Station A_7734_FREQ) or a phasor channel
+// (Station A_7734_VA), which is served as its magnitude. Values are in
+// engineering units. Points are ONLINE once they have a value, RESTART
+// before and COMM_LOST when no value arrived within the stale time.
+//
+// Only what polling analog inputs needs is implemented: link resets and
+// status, reads of class 0 (g60v1) and g30 in any variation, class 1-3
+// event polls (there are no events) and clearing the restart IIN (g80v1).
+// Other function codes are answered with IIN2 "function not supported".
+// Responses are a single fragment of up to 2048 bytes, some 400 points.
+use crate::arrow_utils::channel_value;
+use crate::sinks::BatchSink;
+use arrow::array::{Array, TimestampMicrosecondArray};
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+const MAX_FRAGMENT: usize = 2048;
+const MAX_SEGMENT: usize = 249; // Application bytes per link frame
+
+// Link layer function codes, primary (from the master) and secondary.
+const LINK_RESET: u8 = 0;
+const LINK_TEST: u8 = 2;
+const LINK_CONFIRMED_DATA: u8 = 3;
+const LINK_UNCONFIRMED_DATA: u8 = 4;
+const LINK_REQUEST_STATUS: u8 = 9;
+const LINK_ACK: u8 = 0;
+const LINK_STATUS: u8 = 11;
+
+// Application function codes.
+const FC_CONFIRM: u8 = 0;
+const FC_READ: u8 = 1;
+const FC_WRITE: u8 = 2;
+const FC_DISABLE_UNSOLICITED: u8 = 21;
+const FC_RESPONSE: u8 = 0x81;
+
+// Internal indications.
+pub const IIN1_DEVICE_RESTART: u8 = 0x80;
+pub const IIN2_NO_FUNCTION: u8 = 0x01;
+pub const IIN2_OBJECT_UNKNOWN: u8 = 0x02;
+pub const IIN2_PARAMETER_ERROR: u8 = 0x04;
+
+// Flags of an analog input.
+pub const FLAG_ONLINE: u8 = 0x01;
+pub const FLAG_RESTART: u8 = 0x02;
+pub const FLAG_COMM_LOST: u8 = 0x04;
+pub const FLAG_OVER_RANGE: u8 = 0x20;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnalogPoint {
+    pub index: u16,
+    pub channel: String, // Column or phasor channel name
+    #[serde(default = "default_variation")]
+    pub variation: u8, // Of g30 in class 0 responses, 5 is float with flag
+}
+
+fn default_variation() -> u8 {
+    5
+}
+
+impl AnalogPoint {
+    pub fn new(index: u16, channel: &str) -> Self {
+        AnalogPoint {
+            index,
+            channel: channel.to_string(),
+            variation: default_variation(),
+        }
+    }
+
+    pub fn with_variation(mut self, variation: u8) -> Self {
+        self.variation = variation;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dnp3Config {
+    #[serde(default = "default_outstation")]
+    pub outstation: u16, // Link address of the outstation
+    #[serde(default)]
+    pub master: Option<u16>, // Only this master is answered, None for any
+    pub points: Vec<AnalogPoint>,
+    #[serde(default)]
+    pub decimation_ms: Option<u64>,
+    #[serde(default)]
+    pub stale_ms: Option<u64>,
+}
+
+fn default_outstation() -> u16 {
+    10
+}
+
+impl Dnp3Config {
+    pub fn new(points: Vec<AnalogPoint>) -> Self {
+        Dnp3Config {
+            outstation: default_outstation(),
+            master: None,
+            points,
+            decimation_ms: None,
+            stale_ms: None,
+        }
+    }
+
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        let config: Dnp3Config = serde_json::from_str(json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        let mut indexes = std::collections::HashSet::new();
+        for point in &self.points {
+            if !(1..=6).contains(&point.variation) {
+                return invalid(format!(
+                    "Point {}: no variation {}",
+                    point.index, point.variation
+                ));
+            }
+            if !indexes.insert(point.index) {
+                return invalid(format!("Point {} defined twice", point.index));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PointState {
+    channel: String,
+    variation: u8,
+    value: Option<(f64, Instant)>,
+}
+
+// Latest values of the points, shared by gateways and the outstation.
+#[derive(Debug, Clone)]
+pub struct AnalogPoints {
+    points: Arc<Mutex<BTreeMap<u16, PointState>>>,
+    stale: Option<Duration>,
+}
+
+impl AnalogPoints {
+    pub fn new(points: &[AnalogPoint]) -> Self {
+        let points = points
+            .iter()
+            .map(|point| {
+                let state = PointState {
+                    channel: point.channel.clone(),
+                    variation: point.variation,
+                    value: None,
+                };
+                (point.index, state)
+            })
+            .collect();
+        AnalogPoints {
+            points: Arc::new(Mutex::new(points)),
+            stale: None,
+        }
+    }
+
+    // Points without a new value for this long are COMM_LOST.
+    pub fn with_stale_after(mut self, stale: Duration) -> Self {
+        self.stale = Some(stale);
+        self
+    }
+
+    // Returns false if there is no point with the index.
+    pub fn set(&self, index: u16, value: f64) -> bool {
+        match self.points.lock().unwrap().get_mut(&index) {
+            Some(point) => {
+                point.value = Some((value, Instant::now()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Value and flags of a point.
+    pub fn get(&self, index: u16) -> Option<(f64, u8)> {
+        let points = self.points.lock().unwrap();
+        let point = points.get(&index)?;
+        Some(match point.value {
+            None => (0.0, FLAG_RESTART),
+            Some((value, updated)) => {
+                let stale = self.stale.is_some_and(|stale| updated.elapsed() > stale);
+                (value, if stale { FLAG_COMM_LOST } else { FLAG_ONLINE })
+            }
+        })
+    }
+
+    // Indexes and channels of the points.
+    pub fn channels(&self) -> Vec<(u16, String)> {
+        let points = self.points.lock().unwrap();
+        points
+            .iter()
+            .map(|(index, point)| (*index, point.channel.clone()))
+            .collect()
+    }
+
+    // Indexes in [start, stop] with their class 0 variation.
+    fn range(&self, start: u16, stop: u16) -> Vec<(u16, u8)> {
+        let points = self.points.lock().unwrap();
+        points
+            .range(start..=stop)
+            .map(|(index, point)| (*index, point.variation))
+            .collect()
+    }
+}
+
+// Keeps the points up to date from the batches of a stream.
+pub struct Dnp3Gateway {
+    points: AnalogPoints,
+    interval_us: i64,
+    last_interval: Option<i64>,
+}
+
+impl Dnp3Gateway {
+    pub fn new(points: AnalogPoints) -> Self {
+        Dnp3Gateway {
+            points,
+            interval_us: 0,
+            last_interval: None,
+        }
+    }
+
+    // Take only the first row of every interval, 0 takes all rows.
+    pub fn with_decimation(mut self, interval_us: i64) -> Self {
+        self.interval_us = interval_us.max(0);
+        self
+    }
+}
+
+impl BatchSink for Dnp3Gateway {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let timestamps = batch
+            .column_by_name("timestamp")
+            .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Batch without timestamp column")
+            })?;
+        // Last row kept after decimation, the earlier ones would be overwritten
+        let mut row = None;
+        for n in 0..batch.num_rows() {
+            if self.interval_us > 0 {
+                let interval = timestamps.value(n).div_euclid(self.interval_us);
+                if self.last_interval == Some(interval) {
+                    continue;
+                }
+                self.last_interval = Some(interval);
+            }
+            row = Some(n);
+        }
+        let Some(row) = row else {
+            return Ok(());
+        };
+        for (index, channel) in self.points.channels() {
+            if let Some(value) = channel_value(batch, &channel, row) {
+                self.points.set(index, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// CRC of DNP3 link frames, stored little endian.
+pub fn crc(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA6BC
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkFrame {
+    pub control: u8,
+    pub destination: u16,
+    pub source: u16,
+    pub data: Vec<u8>, // At most 250 bytes
+}
+
+impl LinkFrame {
+    pub fn function(&self) -> u8 {
+        self.control & 0x0F
+    }
+
+    // Header, then the data in blocks of 16 bytes, each followed by its CRC.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut frame = vec![0x05, 0x64, 5 + self.data.len() as u8, self.control];
+        frame.extend_from_slice(&self.destination.to_le_bytes());
+        frame.extend_from_slice(&self.source.to_le_bytes());
+        frame.extend_from_slice(&crc(&frame).to_le_bytes());
+        for block in self.data.chunks(16) {
+            frame.extend_from_slice(block);
+            frame.extend_from_slice(&crc(block).to_le_bytes());
+        }
+        frame
+    }
+
+    // A frame at the start of a buffer and its length in bytes, None when
+    // the buffer does not hold all of it yet. Invalid frames are errors.
+    pub fn decode(buffer: &[u8]) -> io::Result<Option<(LinkFrame, usize)>> {
+        let invalid = |message: &str| {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                message.to_string(),
+            ))
+        };
+        if buffer.len() < 10 {
+            return Ok(None);
+        }
+        if buffer[0..2] != [0x05, 0x64] {
+            return invalid("No DNP3 start bytes");
+        }
+        if u16::from_le_bytes([buffer[8], buffer[9]]) != crc(&buffer[..8]) {
+            return invalid("DNP3 header CRC mismatch");
+        }
+        let length = buffer[2] as usize;
+        if length < 5 {
+            return invalid("DNP3 frame too short");
+        }
+        let data_length = length - 5;
+        let total = 10 + data_length + 2 * data_length.div_ceil(16);
+        if buffer.len() < total {
+            return Ok(None);
+        }
+        let mut data = Vec::with_capacity(data_length);
+        for block in buffer[10..total].chunks(18) {
+            let (block, check) = block.split_at(block.len() - 2);
+            if u16::from_le_bytes([check[0], check[1]]) != crc(block) {
+                return invalid("DNP3 data CRC mismatch");
+            }
+            data.extend_from_slice(block);
+        }
+        let frame = LinkFrame {
+            control: buffer[3],
+            destination: u16::from_le_bytes([buffer[4], buffer[5]]),
+            source: u16::from_le_bytes([buffer[6], buffer[7]]),
+            data,
+        };
+        Ok(Some((frame, total)))
+    }
+}
+
+pub struct Dnp3Outstation {
+    listener: TcpListener,
+    config: Dnp3Config,
+    points: AnalogPoints,
+    restart: Arc<AtomicBool>, // IIN1.7 until a master clears it
+}
+
+impl Dnp3Outstation {
+    pub async fn bind(address: &str, config: Dnp3Config) -> io::Result<Self> {
+        config.validate()?;
+        let mut points = AnalogPoints::new(&config.points);
+        if let Some(stale_ms) = config.stale_ms {
+            points = points.with_stale_after(Duration::from_millis(stale_ms));
+        }
+        let listener = TcpListener::bind(address).await?;
+        println!(
+            "DNP3 outstation {} listening on {} with {} analog inputs",
+            config.outstation,
+            listener.local_addr()?,
+            config.points.len()
+        );
+        Ok(Dnp3Outstation {
+            listener,
+            config,
+            points,
+            restart: Arc::new(AtomicBool::new(true)),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn points(&self) -> AnalogPoints {
+        self.points.clone()
+    }
+
+    // Gateway updating the points, with the decimation of the config.
+    pub fn gateway(&self) -> Dnp3Gateway {
+        let interval_us = self.config.decimation_ms.unwrap_or(0) as i64 * 1000;
+        Dnp3Gateway::new(self.points()).with_decimation(interval_us)
+    }
+
+    // Serve masters until stopped.
+    pub async fn run(self, mut stop: watch::Receiver<bool>) -> io::Result<()> {
+        loop {
+            let (socket, address) = tokio::select! {
+                accepted = self.listener.accept() => accepted?,
+                _ = stop.wait_for(|stop| *stop) => return Ok(()),
+            };
+            println!("DNP3 master connected from {}", address);
+            let mut session = Session {
+                config: self.config.clone(),
+                points: self.points.clone(),
+                restart: self.restart.clone(),
+                fragment: Vec::new(),
+                transport_sequence: 0,
+            };
+            let mut stop = stop.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    result = session.serve(socket) => {
+                        if let Err(e) = result {
+                            println!("DNP3 master {} disconnected: {}", address, e);
+                        }
+                    }
+                    _ = stop.wait_for(|stop| *stop) => {}
+                }
+            });
+        }
+    }
+}
+
+struct Session {
+    config: Dnp3Config,
+    points: AnalogPoints,
+    restart: Arc<AtomicBool>,
+    fragment: Vec<u8>, // Request being reassembled
+    transport_sequence: u8,
+}
+
+impl Session {
+    async fn serve(&mut self, mut socket: TcpStream) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        let mut read = [0u8; 1024];
+        loop {
+            let n = socket.read(&mut read).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            buffer.extend_from_slice(&read[..n]);
+            while let Some((frame, length)) = LinkFrame::decode(&buffer)? {
+                buffer.drain(..length);
+                for reply in self.handle(&frame) {
+                    socket.write_all(&reply.encode()).await?;
+                }
+            }
+        }
+    }
+
+    // Frames answering a link frame.
+    fn handle(&mut self, frame: &LinkFrame) -> Vec<LinkFrame> {
+        let from_master = frame.control & 0xC0 == 0xC0; // DIR and PRM
+        let broadcast = frame.destination >= 0xFFFD;
+        if !from_master
+            || (frame.destination != self.config.outstation && !broadcast)
+            || self
+                .config
+                .master
+                .is_some_and(|master| master != frame.source)
+        {
+            return Vec::new();
+        }
+        let reply = |function: u8| LinkFrame {
+            control: function,
+            destination: frame.source,
+            source: self.config.outstation,
+            data: Vec::new(),
+        };
+        let mut replies = Vec::new();
+        match frame.function() {
+            LINK_RESET | LINK_TEST if !broadcast => replies.push(reply(LINK_ACK)),
+            LINK_REQUEST_STATUS if !broadcast => replies.push(reply(LINK_STATUS)),
+            function @ (LINK_CONFIRMED_DATA | LINK_UNCONFIRMED_DATA) => {
+                if function == LINK_CONFIRMED_DATA && !broadcast {
+                    replies.push(reply(LINK_ACK));
+                }
+                let Some((&transport, segment)) = frame.data.split_first() else {
+                    return replies;
+                };
+                if transport & 0x40 != 0 {
+                    self.fragment.clear();
+                }
+                self.fragment.extend_from_slice(segment);
+                if transport & 0x80 != 0 && !broadcast {
+                    let request = std::mem::take(&mut self.fragment);
+                    if let Some(response) = self.respond(&request) {
+                        replies.extend(self.segments(frame.source, &response));
+                    }
+                }
+            }
+            _ => {}
+        }
+        replies
+    }
+
+    // Application response to a request fragment, None when none is due.
+    fn respond(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let (&control, rest) = request.split_first()?;
+        let (&function, mut objects) = rest.split_first()?;
+        let sequence = control & 0x0F;
+        let mut iin2 = 0;
+        let mut body = Vec::new();
+        match function {
+            FC_CONFIRM => return None,
+            FC_READ => {
+                while !objects.is_empty() {
+                    let Some((group, variation, range, rest)) = object_header(objects) else {
+                        iin2 |= IIN2_PARAMETER_ERROR;
+                        break;
+                    };
+                    objects = rest;
+                    match (group, variation) {
+                        (60, 1) => self.analog_inputs(&mut body, 0, u16::MAX, None),
+                        (60, 2..=4) => {} // No events
+                        (30, 0..=6) => {
+                            let (start, stop) = range.unwrap_or((0, u16::MAX));
+                            let variation = (variation != 0).then_some(variation);
+                            self.analog_inputs(&mut body, start, stop, variation);
+                        }
+                        _ => iin2 |= IIN2_OBJECT_UNKNOWN,
+                    }
+                }
+            }
+            FC_WRITE => match object_header(objects) {
+                // IIN bits, only clearing the restart bit is allowed
+                Some((80, 1, Some((start, 7)), values)) if start <= 7 && !values.is_empty() => {
+                    if values[0] >> (7 - start) & 1 == 0 {
+                        self.restart.store(false, Ordering::Relaxed);
+                    } else {
+                        iin2 |= IIN2_PARAMETER_ERROR;
+                    }
+                }
+                Some(_) => iin2 |= IIN2_OBJECT_UNKNOWN,
+                None => iin2 |= IIN2_PARAMETER_ERROR,
+            },
+            FC_DISABLE_UNSOLICITED => {} // Never sent anyway
+            _ => iin2 |= IIN2_NO_FUNCTION,
+        }
+        if body.len() + 4 > MAX_FRAGMENT {
+            body.clear();
+            iin2 |= IIN2_PARAMETER_ERROR;
+        }
+        let iin1 = if self.restart.load(Ordering::Relaxed) {
+            IIN1_DEVICE_RESTART
+        } else {
+            0
+        };
+        let mut response = vec![0xC0 | sequence, FC_RESPONSE, iin1, iin2];
+        response.extend(body);
+        Some(response)
+    }
+
+    // Analog inputs in [start, stop], each in its class 0 variation unless
+    // one is requested, as start-stop ranges of consecutive indexes.
+    fn analog_inputs(&self, body: &mut Vec<u8>, start: u16, stop: u16, requested: Option<u8>) {
+        let points = self.points.range(start, stop);
+        let mut n = 0;
+        while n < points.len() {
+            let variation = requested.unwrap_or(points[n].1);
+            let first = points[n].0;
+            let mut values = Vec::new();
+            let last = loop {
+                let index = points[n].0;
+                let (value, flags) = self.points.get(index).unwrap_or((0.0, FLAG_RESTART));
+                encode_analog(&mut values, variation, value, flags);
+                n += 1;
+                match points.get(n) {
+                    Some((next, class0))
+                        if index.checked_add(1) == Some(*next)
+                            && requested.unwrap_or(*class0) == variation => {}
+                    _ => break index,
+                }
+            };
+            body.extend_from_slice(&[30, variation, 0x01]);
+            body.extend_from_slice(&first.to_le_bytes());
+            body.extend_from_slice(&last.to_le_bytes());
+            body.extend(values);
+        }
+    }
+
+    // Link frames carrying a response, split into transport segments.
+    fn segments(&mut self, master: u16, response: &[u8]) -> Vec<LinkFrame> {
+        let count = response.chunks(MAX_SEGMENT).len();
+        response
+            .chunks(MAX_SEGMENT)
+            .enumerate()
+            .map(|(n, segment)| {
+                let mut transport = self.transport_sequence & 0x3F;
+                self.transport_sequence = self.transport_sequence.wrapping_add(1);
+                if n == 0 {
+                    transport |= 0x40;
+                }
+                if n + 1 == count {
+                    transport |= 0x80;
+                }
+                let mut data = vec![transport];
+                data.extend_from_slice(segment);
+                LinkFrame {
+                    control: 0x40 | LINK_UNCONFIRMED_DATA, // PRM, from the outstation
+                    destination: master,
+                    source: self.config.outstation,
+                    data,
+                }
+            })
+            .collect()
+    }
+}
+
+// Group, variation and index range (None for all) of an object header, and
+// what follows it.
+#[allow(clippy::type_complexity)]
+fn object_header(objects: &[u8]) -> Option<(u8, u8, Option<(u16, u16)>, &[u8])> {
+    let (&group, rest) = objects.split_first()?;
+    let (&variation, rest) = rest.split_first()?;
+    let (&qualifier, rest) = rest.split_first()?;
+    let (range, rest) = match qualifier {
+        0x06 => (None, rest),
+        0x00 if rest.len() >= 2 => (Some((rest[0] as u16, rest[1] as u16)), &rest[2..]),
+        0x01 if rest.len() >= 4 => (
+            Some((
+                u16::from_le_bytes([rest[0], rest[1]]),
+                u16::from_le_bytes([rest[2], rest[3]]),
+            )),
+            &rest[4..],
+        ),
+        // The first count points
+        0x07 if !rest.is_empty() && rest[0] > 0 => (Some((0, rest[0] as u16 - 1)), &rest[1..]),
+        0x08 if rest.len() >= 2 => {
+            let count = u16::from_le_bytes([rest[0], rest[1]]);
+            (Some((0, count.checked_sub(1)?)), &rest[2..])
+        }
+        _ => return None,
+    };
+    Some((group, variation, range, rest))
+}
+
+// An analog input in a g30 variation: 1 and 2 are 32 and 16 bit integers
+// with flags, 3 and 4 without, 5 and 6 single and double floats with flags.
+fn encode_analog(values: &mut Vec<u8>, variation: u8, value: f64, flags: u8) {
+    let integer = |min: f64, max: f64| {
+        let rounded = value.round();
+        if rounded < min || rounded > max {
+            (rounded.clamp(min, max), flags | FLAG_OVER_RANGE)
+        } else {
+            (rounded, flags)
+        }
+    };
+    match variation {
+        1 => {
+            let (value, flags) = integer(i32::MIN as f64, i32::MAX as f64);
+            values.push(flags);
+            values.extend_from_slice(&(value as i32).to_le_bytes());
+        }
+        2 => {
+            let (value, flags) = integer(i16::MIN as f64, i16::MAX as f64);
+            values.push(flags);
+            values.extend_from_slice(&(value as i16).to_le_bytes());
+        }
+        3 => {
+            let (value, _) = integer(i32::MIN as f64, i32::MAX as f64);
+            values.extend_from_slice(&(value as i32).to_le_bytes());
+        }
+        4 => {
+            let (value, _) = integer(i16::MIN as f64, i16::MAX as f64);
+            values.extend_from_slice(&(value as i16).to_le_bytes());
+        }
+        5 => {
+            values.push(flags);
+            values.extend_from_slice(&(value as f32).to_le_bytes());
+        }
+        _ => {
+            values.push(flags);
+            values.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
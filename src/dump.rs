@@ -0,0 +1,607 @@
+// Annotated hex dumps of C37.118 frames, for debugging interop problems.
+//
+// dissect splits the bytes of a frame into its fields, each with the byte
+// offset, name and decoded value, like the detail pane of Wireshark; dump
+// renders them as a hex dump:
+//
+//   0000  AA 01                     SYNC       data frame, version 1 (C37.118-2005)
+//   0002  00 34                     FRAMESIZE  52
+//   ...
+//
+// Data frames need the configuration of the stream to be decoded, without
+// it their body is shown as is. Truncated and inconsistent frames are
+// dumped as far as they go. The frame structs have a pretty_print built on
+// this and a one-line Display.
+use crate::audit::command_name;
+use crate::frame_parser::Frame;
+use crate::frames::{
+    calculate_crc, CommandFrame2011, ConfigurationFrame1and2_2011, DataFrame2011, FormatFlags,
+    PMUConfigurationFrame2011, PrefixFrame2011,
+};
+use chrono::DateTime;
+use std::fmt::{self, Write as _};
+
+const BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpField {
+    pub offset: usize,
+    pub length: usize, // 0 for headings
+    pub name: String,
+    pub value: String,
+}
+
+fn frame_type_name(sync: u16) -> &'static str {
+    match (sync >> 4) & 0x7 {
+        0 => "data frame",
+        1 => "header frame",
+        2 => "configuration frame 1",
+        3 => "configuration frame 2",
+        4 => "command frame",
+        5 => "configuration frame 3",
+        _ => "unknown frame",
+    }
+}
+
+fn version_name(version: u16) -> &'static str {
+    match version {
+        1 => "C37.118-2005",
+        2 => "C37.118.2-2011",
+        3 => "C37.118.2-2024",
+        _ => "unknown",
+    }
+}
+
+fn format_soc(soc: u32) -> String {
+    DateTime::from_timestamp(soc as i64, 0)
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_default()
+}
+
+// Time quality byte of FRACSEC.
+fn time_quality(quality: u8) -> String {
+    let mut flags = Vec::new();
+    if quality & 0x10 != 0 {
+        flags.push("leap second pending");
+    }
+    if quality & 0x20 != 0 {
+        flags.push("leap second occurred");
+    }
+    if quality & 0x10 != 0 || quality & 0x20 != 0 {
+        flags.push(if quality & 0x40 != 0 {
+            "deleted"
+        } else {
+            "added"
+        });
+    }
+    let code = quality & 0x0F;
+    let mut text = match code {
+        0 => "locked".to_string(),
+        0x0F => "fault, unreliable".to_string(),
+        code => format!("within 10^{} s", code as i32 - 10),
+    };
+    for flag in flags {
+        text.push_str(", ");
+        text.push_str(flag);
+    }
+    text
+}
+
+fn stat_flags(stat: u16) -> String {
+    let mut flags = vec![match stat >> 14 {
+        0 => "data valid",
+        1 => "PMU error",
+        2 => "test mode",
+        _ => "data invalid",
+    }];
+    for (bit, name) in [
+        (0x2000, "sync lost"),
+        (0x1000, "sorted by arrival"),
+        (0x0800, "trigger"),
+        (0x0400, "config change"),
+        (0x0200, "data modified"),
+    ] {
+        if stat & bit != 0 {
+            flags.push(name);
+        }
+    }
+    let mut text = flags.join(", ");
+    let quality = (stat >> 6) & 0x7;
+    if quality != 0 {
+        let _ = write!(text, ", time quality {}", quality);
+    }
+    let unlocked = (stat >> 4) & 0x3;
+    if unlocked != 0 {
+        let _ = write!(
+            text,
+            ", unlocked {}",
+            ["", "10 s", "100 s", "1000 s"][unlocked as usize]
+        );
+    }
+    text
+}
+
+fn ascii(bytes: &[u8]) -> String {
+    let text: String = bytes
+        .iter()
+        .map(|b| {
+            if b.is_ascii_graphic() || *b == b' ' {
+                *b as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+    format!("\"{}\"", text.trim_end())
+}
+
+fn be16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn f32_at(bytes: &[u8]) -> f32 {
+    f32::from_bits(be32(bytes))
+}
+
+struct Dissector<'a> {
+    frame: &'a [u8],
+    end: usize, // Start of CHK
+    offset: usize,
+    fields: Vec<DumpField>,
+    truncated: bool,
+}
+
+impl<'a> Dissector<'a> {
+    // The next field, None (and the rest of the body as truncated) when the
+    // frame ends before it.
+    fn field(
+        &mut self,
+        length: usize,
+        name: &str,
+        value: impl FnOnce(&'a [u8]) -> String,
+    ) -> Option<&'a [u8]> {
+        if self.truncated {
+            return None;
+        }
+        if self.offset + length > self.end {
+            self.truncated = true;
+            if self.offset < self.end {
+                self.fields.push(DumpField {
+                    offset: self.offset,
+                    length: self.end - self.offset,
+                    name: name.to_string(),
+                    value: "(truncated)".to_string(),
+                });
+                self.offset = self.end;
+            }
+            return None;
+        }
+        let frame: &'a [u8] = self.frame;
+        let bytes = &frame[self.offset..self.offset + length];
+        self.fields.push(DumpField {
+            offset: self.offset,
+            length,
+            name: name.to_string(),
+            value: value(bytes),
+        });
+        self.offset += length;
+        Some(bytes)
+    }
+
+    fn heading(&mut self, name: String) {
+        self.fields.push(DumpField {
+            offset: self.offset,
+            length: 0,
+            name,
+            value: String::new(),
+        });
+    }
+
+    fn prefix(&mut self, time_base: Option<u32>) {
+        self.field(2, "SYNC", |b| {
+            let sync = be16(b);
+            format!(
+                "{}, version {} ({})",
+                frame_type_name(sync),
+                sync & 0xF,
+                version_name(sync & 0xF)
+            )
+        });
+        self.field(2, "FRAMESIZE", |b| be16(b).to_string());
+        self.field(2, "IDCODE", |b| be16(b).to_string());
+        self.field(4, "SOC", |b| {
+            format!("{} ({})", be32(b), format_soc(be32(b)))
+        });
+        self.field(4, "FRACSEC", |b| {
+            let fraction = be32(b) & 0x00FF_FFFF;
+            let seconds = match time_base {
+                Some(base) if base > 0 => format!(" = {:.6} s", fraction as f64 / base as f64),
+                _ => String::new(),
+            };
+            format!("{}{}, {}", fraction, seconds, time_quality(b[0]))
+        });
+    }
+
+    fn data(&mut self, config: &ConfigurationFrame1and2_2011) {
+        for (n, pmu) in config.pmu_configs.iter().enumerate() {
+            let station = String::from_utf8_lossy(&pmu.stn).trim().to_string();
+            self.heading(format!("PMU {}: {} ({})", n + 1, station, pmu.idcode));
+            self.field(2, "STAT", |b| {
+                format!("0x{:04X} {}", be16(b), stat_flags(be16(b)))
+            });
+            let names = channel_names(pmu);
+            for (k, name) in names.iter().take(pmu.phnmr as usize).enumerate() {
+                let unit = pmu.phunit.get(k).copied().unwrap_or(0);
+                let symbol = if unit >> 24 == 1 { "A" } else { "V" };
+                let scale = (unit & 0x00FF_FFFF) as f64 / 100_000.0;
+                self.field(pmu.phasor_size(), name, |b| {
+                    let (first, second) = if pmu.format.phasor_float {
+                        (f32_at(&b[0..4]) as f64, f32_at(&b[4..8]) as f64)
+                    } else if pmu.format.polar {
+                        // Unsigned magnitude
+                        (be16(&b[0..2]) as f64, be16(&b[2..4]) as i16 as f64)
+                    } else {
+                        (be16(&b[0..2]) as i16 as f64, be16(&b[2..4]) as i16 as f64)
+                    };
+                    if pmu.format.polar {
+                        // Fixed point angles are in 10^-4 rad
+                        let magnitude = if pmu.format.phasor_float {
+                            first
+                        } else {
+                            first * scale
+                        };
+                        let angle = if pmu.format.phasor_float {
+                            second
+                        } else {
+                            second * 1e-4
+                        };
+                        format!("{:.3} {}, {:.2} deg", magnitude, symbol, angle.to_degrees())
+                    } else {
+                        let (x, y) = if pmu.format.phasor_float {
+                            (first, second)
+                        } else {
+                            (first * scale, second * scale)
+                        };
+                        format!(
+                            "{:.3} {:+.3}j {} = {:.3} {}, {:.2} deg",
+                            x,
+                            y,
+                            symbol,
+                            x.hypot(y),
+                            symbol,
+                            y.atan2(x).to_degrees()
+                        )
+                    }
+                });
+            }
+            let nominal = if pmu.fnom & 0x1 != 0 { 50.0 } else { 60.0 };
+            let float = pmu.format.freq_float;
+            self.field(pmu.freq_dfreq_size(), "FREQ", |b| {
+                if float {
+                    format!("{:.4} Hz", f32_at(b))
+                } else {
+                    let deviation = be16(b) as i16;
+                    format!(
+                        "{:+} mHz = {:.4} Hz",
+                        deviation,
+                        nominal + deviation as f64 / 1000.0
+                    )
+                }
+            });
+            self.field(pmu.freq_dfreq_size(), "DFREQ", |b| {
+                if float {
+                    format!("{:.4} Hz/s", f32_at(b))
+                } else {
+                    format!("{:.2} Hz/s", be16(b) as i16 as f64 / 100.0)
+                }
+            });
+            for k in 0..pmu.annmr as usize {
+                let unit = pmu.anunit.get(k).copied().unwrap_or(1);
+                let scale = ((unit << 8) as i32 >> 8) as f64;
+                let name = &names[pmu.phnmr as usize + k];
+                self.field(pmu.analog_size(), name, |b| {
+                    if pmu.format.analog_float {
+                        format!("{}", f32_at(b))
+                    } else {
+                        let raw = be16(b) as i16;
+                        format!("{} x {} = {}", raw, scale, raw as f64 * scale)
+                    }
+                });
+            }
+            for k in 0..pmu.dgnmr as usize {
+                let name = &names[pmu.phnmr as usize + pmu.annmr as usize + 16 * k];
+                self.field(2, name, |b| format!("0x{:04X} {:016b}", be16(b), be16(b)));
+            }
+        }
+    }
+
+    fn configuration(&mut self) -> Option<()> {
+        let num_pmu = self
+            .field(2, "NUM_PMU", |b| be16(b).to_string())
+            .map(be16)?;
+        for n in 0..num_pmu {
+            self.heading(format!("PMU {}", n + 1));
+            self.field(16, "STN", ascii)?;
+            self.field(2, "IDCODE", |b| be16(b).to_string())?;
+            let format = self
+                .field(2, "FORMAT", |b| {
+                    format!("0x{:04X} {}", be16(b), FormatFlags::from(be16(b)))
+                })
+                .map(|b| FormatFlags::from(be16(b)))?;
+            let phnmr = self.field(2, "PHNMR", |b| be16(b).to_string()).map(be16)? as usize;
+            let annmr = self.field(2, "ANNMR", |b| be16(b).to_string()).map(be16)? as usize;
+            let dgnmr = self.field(2, "DGNMR", |b| be16(b).to_string()).map(be16)? as usize;
+            for _ in 0..phnmr + annmr + 16 * dgnmr {
+                self.field(16, "CHNAM", ascii)?;
+            }
+            for _ in 0..phnmr {
+                self.field(4, "PHUNIT", |b| {
+                    let unit = be32(b);
+                    let kind = if b[0] == 1 { "current" } else { "voltage" };
+                    if format.phasor_float {
+                        format!("{}, float (scale ignored)", kind)
+                    } else {
+                        format!(
+                            "{}, {} per bit",
+                            kind,
+                            (unit & 0x00FF_FFFF) as f64 / 100_000.0
+                        )
+                    }
+                })?;
+            }
+            for _ in 0..annmr {
+                self.field(4, "ANUNIT", |b| {
+                    let kind = match b[0] {
+                        0 => "point on wave",
+                        1 => "rms",
+                        2 => "peak",
+                        _ => "user defined",
+                    };
+                    format!("{}, scale {}", kind, (be32(b) << 8) as i32 >> 8)
+                })?;
+            }
+            for _ in 0..dgnmr {
+                self.field(4, "DIGUNIT", |b| {
+                    format!(
+                        "normal 0x{:04X}, valid 0x{:04X}",
+                        be16(&b[0..2]),
+                        be16(&b[2..4])
+                    )
+                })?;
+            }
+            self.field(2, "FNOM", |b| {
+                let hz = if be16(b) & 0x1 != 0 { 50 } else { 60 };
+                format!("{} Hz", hz)
+            })?;
+            self.field(2, "CFGCNT", |b| be16(b).to_string())?;
+        }
+        self.field(2, "DATA_RATE", |b| {
+            let rate = be16(b) as i16;
+            if rate >= 0 {
+                format!("{} frames/s", rate)
+            } else {
+                format!("1 frame per {} s", -rate)
+            }
+        })?;
+        Some(())
+    }
+
+    // The rest of the body, undecoded.
+    fn rest(&mut self, name: &str, value: &str) {
+        let length = self.end.saturating_sub(self.offset);
+        if length > 0 {
+            self.field(length, name, |_| value.to_string());
+        }
+    }
+}
+
+fn channel_names(pmu: &PMUConfigurationFrame2011) -> Vec<String> {
+    let mut names: Vec<String> = pmu
+        .chnam
+        .chunks(16)
+        .map(|chunk| String::from_utf8_lossy(chunk).trim().to_string())
+        .collect();
+    // Names for frames that do not match their configuration
+    let needed = pmu.phnmr as usize + pmu.annmr as usize + 16 * pmu.dgnmr as usize;
+    while names.len() < needed {
+        names.push(format!("CHANNEL{}", names.len() + 1));
+    }
+    names
+}
+
+// The fields of a frame. A configuration decodes data frames, the time base
+// of FRACSEC is taken from it as well.
+pub fn dissect(frame: &[u8], config: Option<&ConfigurationFrame1and2_2011>) -> Vec<DumpField> {
+    let has_chk = frame.len() >= 16;
+    let mut dissector = Dissector {
+        frame,
+        end: if has_chk {
+            frame.len() - 2
+        } else {
+            frame.len()
+        },
+        offset: 0,
+        fields: Vec::new(),
+        truncated: false,
+    };
+    let sync = if frame.len() >= 2 { be16(frame) } else { 0 };
+    let frame_type = (sync >> 4) & 0x7;
+    // Configuration frames carry their own time base
+    let time_base = if (frame_type == 2 || frame_type == 3) && frame.len() >= 18 {
+        Some(be32(&frame[14..18]) & 0x00FF_FFFF)
+    } else {
+        config.map(|c| c.time_base & 0x00FF_FFFF)
+    };
+    dissector.prefix(time_base);
+    match frame_type {
+        0 => match config {
+            Some(config) => dissector.data(config),
+            None => dissector.rest("DATA", "(configuration needed to decode)"),
+        },
+        1 => dissector.rest(
+            "DATA",
+            &ascii(frame.get(14..dissector.end).unwrap_or_default()),
+        ),
+        2 | 3 => {
+            dissector.field(4, "TIME_BASE", |b| {
+                format!("{} (flags 0x{:02X})", be32(b) & 0x00FF_FFFF, b[0])
+            });
+            dissector.configuration();
+        }
+        4 => {
+            dissector.field(2, "CMD", |b| {
+                format!("0x{:04X} {}", be16(b), command_name(be16(b)))
+            });
+            dissector.rest("EXTFRAME", "");
+        }
+        _ => {}
+    }
+    dissector.rest("(unexpected)", "bytes beyond the fields");
+    if has_chk {
+        dissector.end = frame.len();
+        dissector.truncated = false;
+        dissector.field(2, "CHK", |b| {
+            let expected = calculate_crc(&frame[..frame.len() - 2]);
+            if be16(b) == expected {
+                format!("0x{:04X} (ok)", expected)
+            } else {
+                format!("0x{:04X} (expected 0x{:04X})", be16(b), expected)
+            }
+        });
+    }
+    dissector.fields
+}
+
+// A frame as an annotated hex dump, one field per line and up to eight
+// bytes per line.
+pub fn dump(frame: &[u8], config: Option<&ConfigurationFrame1and2_2011>) -> String {
+    let fields = dissect(frame, config);
+    let width = fields
+        .iter()
+        .filter(|f| f.length > 0)
+        .map(|f| f.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    for field in &fields {
+        if field.length == 0 {
+            let _ = writeln!(out, "      -- {} --", field.name);
+            continue;
+        }
+        let bytes = &frame[field.offset..field.offset + field.length];
+        for (n, line) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+            let hex: Vec<String> = line.iter().map(|b| format!("{:02X}", b)).collect();
+            let hex = hex.join(" ");
+            let offset = field.offset + n * BYTES_PER_LINE;
+            if n == 0 {
+                let _ = writeln!(
+                    out,
+                    "{:04X}  {:<24}  {:<width$}  {}",
+                    offset,
+                    hex,
+                    field.name,
+                    field.value,
+                    width = width
+                );
+            } else {
+                let _ = writeln!(out, "{:04X}  {}", offset, hex);
+            }
+        }
+    }
+    out
+}
+
+// Dumps of frames one after another, e.g. a capture. Configuration frames
+// found on the way decode the data frames after them.
+pub fn dump_all(bytes: &[u8], mut config: Option<ConfigurationFrame1and2_2011>) -> String {
+    let mut out = String::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let rest = &bytes[offset..];
+        let size = if rest.len() >= 4 && rest[0] == 0xAA {
+            (be16(&rest[2..4]) as usize).clamp(4, rest.len())
+        } else {
+            // Not at a frame, skip to the next sync byte
+            let skip = rest[1..]
+                .iter()
+                .position(|b| *b == 0xAA)
+                .map_or(rest.len(), |p| p + 1);
+            let _ = writeln!(out, "{} bytes without a frame at 0x{:X}\n", skip, offset);
+            offset += skip;
+            continue;
+        };
+        let frame = &rest[..size];
+        let frame_type = (frame[1] >> 4) & 0x7;
+        if frame_type == 2 || frame_type == 3 {
+            if let Ok(parsed) = crate::frame_parser::parse_config_frame_1and2(frame) {
+                config = Some(parsed);
+            }
+        }
+        let _ = writeln!(out, "Frame at 0x{:X}, {} bytes", offset, size);
+        out.push_str(&dump(frame, config.as_ref()));
+        out.push('\n');
+        offset += size;
+    }
+    out
+}
+
+impl fmt::Display for PrefixFrame2011 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} v{}, IDCODE {}, {} bytes, SOC {} ({}), FRACSEC 0x{:08X}",
+            frame_type_name(self.sync),
+            self.sync & 0xF,
+            self.idcode,
+            self.framesize,
+            self.soc,
+            format_soc(self.soc),
+            self.fracsec
+        )
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Frame::Header(header) => write!(f, "{}", header.prefix),
+            Frame::Prefix(prefix) => write!(f, "{}", prefix),
+            Frame::Configuration(config) => write!(
+                f,
+                "{}, {} PMUs at {} frames/s",
+                config.prefix,
+                config.pmu_configs.len(),
+                config.data_rate
+            ),
+            Frame::Data(data) => write!(f, "{}, {} PMUs", data.prefix, data.data.len()),
+            Frame::Command(command) => write!(
+                f,
+                "{}, command {}",
+                command.prefix,
+                command_name(command.command)
+            ),
+        }
+    }
+}
+
+impl ConfigurationFrame1and2_2011 {
+    pub fn pretty_print(&self) -> String {
+        dump(&self.to_hex(), Some(self))
+    }
+}
+
+impl DataFrame2011 {
+    pub fn pretty_print(&self, config: &ConfigurationFrame1and2_2011) -> String {
+        dump(&self.to_hex(), Some(config))
+    }
+}
+
+impl CommandFrame2011 {
+    pub fn pretty_print(&self) -> String {
+        dump(&self.to_hex(), None)
+    }
+}
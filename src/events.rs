@@ -0,0 +1,245 @@
+// Events detected by the analytics, published on a bus.
+//
+// Detectors publish Events on an EventBus; any number of subscribers (alarm
+// handlers, loggers, sinks) receive every event published after they
+// subscribed. Subscribers that fall more than the bus capacity behind lose
+// the oldest events, publishers never block.
+//
+// Stations under maintenance can be taken out of alerting: while a configured
+// window or a live toggle is on, Warning and Alarm events of the station are
+// counted instead of published. Info events and the data are unaffected.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    GeneratorTrip,    // Loss of generation, frequency drops
+    LoadLoss,         // Loss of load, frequency rises
+    TopologyChange,   // Angle steps between stations, e.g. a line outage
+    DataQuality,      // Anomalous measurements: stuck values, spikes, dropouts, jumps
+    StreamStalled,    // No data from a stream for longer than its stall limit
+    StreamResumed,    // Data again after a stall
+    ReferenceChanged, // Angles are now relative to another reference phasor
+    Trigger,          // A configured trigger condition held (analytics::trigger)
+    CoherencyChange,  // Stations moved between coherent groups (analytics::coherency)
+    MagnitudeStep,    // Phasor magnitude changed level, e.g. a capacitor bank or tap change
+    LoadShed,         // A UFLS stage would have tripped (analytics::ufls)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Warning,
+    Alarm,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event {
+    pub timestamp_us: i64, // Start of the event, from the measurements
+    pub kind: EventKind,
+    pub severity: Severity,
+    pub source: String, // Station or channel the event was detected on
+    pub message: String,
+    pub values: BTreeMap<String, f64>, // Measured quantities, named with their unit
+}
+
+impl Event {
+    pub fn new(timestamp_us: i64, kind: EventKind, source: &str, message: String) -> Self {
+        Event {
+            timestamp_us,
+            kind,
+            severity: Severity::Info,
+            source: source.to_string(),
+            message,
+            values: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    pub fn with_value(mut self, name: &str, value: f64) -> Self {
+        self.values.insert(name.to_string(), value);
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+    maintenance: Option<Maintenance>,
+}
+
+impl EventBus {
+    // Subscribers can lag up to capacity events behind.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        EventBus {
+            sender,
+            maintenance: None,
+        }
+    }
+
+    // Suppress alarms of stations under maintenance.
+    pub fn with_maintenance(mut self, maintenance: Maintenance) -> Self {
+        self.maintenance = Some(maintenance);
+        self
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    // Returns the number of subscribers the event was sent to, 0 when it was
+    // suppressed.
+    pub fn publish(&self, event: Event) -> usize {
+        if let Some(maintenance) = &self.maintenance {
+            if maintenance.suppress(&event) {
+                return 0;
+            }
+        }
+        self.sender.send(event).unwrap_or(0)
+    }
+}
+
+// A configured maintenance period of a station, [start, end).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    pub station: String,
+    pub start_us: i64,
+    pub end_us: i64,
+}
+
+// Alarms suppressed for a station.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SuppressedAlarms {
+    pub count: u64,
+    pub by_kind: BTreeMap<String, u64>,
+    pub first_us: Option<i64>,
+    pub last_us: Option<i64>,
+}
+
+#[derive(Default)]
+struct MaintenanceState {
+    windows: Vec<MaintenanceWindow>,
+    toggled: BTreeMap<String, bool>, // Live toggles, win over the windows
+    suppressed: BTreeMap<String, SuppressedAlarms>,
+}
+
+// Maintenance state shared by the buses and whoever toggles it, e.g. the
+// operator API.
+#[derive(Clone, Default)]
+pub struct Maintenance {
+    state: Arc<Mutex<MaintenanceState>>,
+}
+
+impl Maintenance {
+    pub fn new(windows: Vec<MaintenanceWindow>) -> Self {
+        let maintenance = Maintenance::default();
+        maintenance.state.lock().unwrap().windows = windows;
+        maintenance
+    }
+
+    pub fn add_window(&self, window: MaintenanceWindow) {
+        self.state.lock().unwrap().windows.push(window);
+    }
+
+    // Put a station in or out of maintenance regardless of the windows.
+    // Taking it out prints the alarms suppressed meanwhile.
+    pub fn set(&self, station: &str, on: bool) {
+        let mut state = self.state.lock().unwrap();
+        let was_on = state.toggled.insert(station.to_string(), on);
+        if was_on == Some(true) && !on {
+            if let Some(suppressed) = state.suppressed.get(station) {
+                println!(
+                    "Maintenance of {} ended, {} alarms suppressed",
+                    station, suppressed.count
+                );
+            }
+        }
+    }
+
+    // Back to the configured windows for the station.
+    pub fn clear(&self, station: &str) {
+        self.state.lock().unwrap().toggled.remove(station);
+    }
+
+    pub fn is_active(&self, station: &str, timestamp_us: i64) -> bool {
+        self.state.lock().unwrap().is_active(station, timestamp_us)
+    }
+
+    // Alarms suppressed per station so far.
+    pub fn suppressed(&self) -> BTreeMap<String, SuppressedAlarms> {
+        self.state.lock().unwrap().suppressed.clone()
+    }
+
+    // The counts so far, starting over.
+    pub fn take_suppressed(&self) -> BTreeMap<String, SuppressedAlarms> {
+        std::mem::take(&mut self.state.lock().unwrap().suppressed)
+    }
+
+    // Count the event if it is an alarm of a station under maintenance.
+    fn suppress(&self, event: &Event) -> bool {
+        if event.severity < Severity::Warning {
+            return false;
+        }
+        let mut state = self.state.lock().unwrap();
+        let Some(station) = state.station_of(&event.source, event.timestamp_us) else {
+            return false;
+        };
+        let suppressed = state.suppressed.entry(station).or_default();
+        suppressed.count += 1;
+        let kind = serde_json::to_value(event.kind)
+            .ok()
+            .and_then(|kind| kind.as_str().map(str::to_string))
+            .unwrap_or_default();
+        *suppressed.by_kind.entry(kind).or_insert(0) += 1;
+        suppressed.first_us.get_or_insert(event.timestamp_us);
+        suppressed.last_us = Some(event.timestamp_us);
+        true
+    }
+}
+
+impl MaintenanceState {
+    fn is_active(&self, station: &str, timestamp_us: i64) -> bool {
+        match self.toggled.get(station) {
+            Some(on) => *on,
+            None => self.windows.iter().any(|window| {
+                window.station == station
+                    && window.start_us <= timestamp_us
+                    && timestamp_us < window.end_us
+            }),
+        }
+    }
+
+    // The station under maintenance an event source belongs to: the station
+    // itself or a channel named STATION_...
+    fn station_of(&self, source: &str, timestamp_us: i64) -> Option<String> {
+        let stations = self
+            .toggled
+            .keys()
+            .chain(self.windows.iter().map(|window| &window.station));
+        for station in stations {
+            let matches = source == station
+                || source
+                    .strip_prefix(station.as_str())
+                    .is_some_and(|rest| rest.starts_with('_'));
+            if matches && self.is_active(station, timestamp_us) {
+                return Some(station.clone());
+            }
+        }
+        None
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new(1024)
+    }
+}
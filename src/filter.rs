@@ -0,0 +1,408 @@
+// Per-channel DSP filters applied before analytics and sinks.
+//
+// A FilterChain runs samples through filters in order: FIR, IIR biquads,
+// moving average and median. Each filter reports its group delay at DC in
+// samples; the chain's delay is their sum. The filtered values lag the
+// timestamps they are reported with by that delay, so it is carried along
+// as metadata for consumers that need to line the signal up again.
+//
+// Filters start from the first sample as if it had been there forever, so a
+// signal far from zero (60 Hz) does not begin with a transient.
+use crate::arrow_utils::{META_FILTER, META_GROUP_DELAY_US, META_OFFSET, META_SCALE};
+use crate::channels::{ChannelId, ChannelRegistry};
+use arrow::array::{ArrayRef, Float64Array};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+pub trait Filter: Send {
+    fn process(&mut self, x: f64) -> f64;
+
+    // Delay at DC in samples.
+    fn group_delay(&self) -> f64;
+
+    // Short description for metadata, e.g. "median(5)".
+    fn describe(&self) -> String;
+
+    // Forget the past samples.
+    fn reset(&mut self);
+}
+
+// Finite impulse response filter.
+pub struct Fir {
+    taps: Vec<f64>,
+    history: VecDeque<f64>, // Newest first
+}
+
+impl Fir {
+    pub fn new(taps: Vec<f64>) -> Self {
+        Fir {
+            taps,
+            history: VecDeque::new(),
+        }
+    }
+}
+
+impl Filter for Fir {
+    fn process(&mut self, x: f64) -> f64 {
+        if self.history.is_empty() {
+            self.history.resize(self.taps.len(), x);
+        }
+        self.history.pop_back();
+        self.history.push_front(x);
+        self.taps
+            .iter()
+            .zip(&self.history)
+            .map(|(h, x)| h * x)
+            .sum()
+    }
+
+    fn group_delay(&self) -> f64 {
+        let sum: f64 = self.taps.iter().sum();
+        if sum.abs() < 1e-12 {
+            return 0.0;
+        }
+        let moment: f64 = self
+            .taps
+            .iter()
+            .enumerate()
+            .map(|(k, h)| k as f64 * h)
+            .sum();
+        moment / sum
+    }
+
+    fn describe(&self) -> String {
+        format!("fir({})", self.taps.len())
+    }
+
+    fn reset(&mut self) {
+        self.history.clear();
+    }
+}
+
+// Second order IIR section, transposed direct form II, a0 normalized to 1.
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2], // a1, a2
+    name: String,
+    state: Option<[f64; 2]>,
+}
+
+impl Biquad {
+    pub fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Biquad {
+            b: [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            a: [a[1] / a[0], a[2] / a[0]],
+            name: "biquad".to_string(),
+            state: None,
+        }
+    }
+
+    // q = 1/sqrt(2) is a Butterworth response. Coefficients from the Audio
+    // EQ Cookbook (R. Bristow-Johnson).
+    pub fn lowpass(cutoff_hz: f64, sample_rate: f64, q: f64) -> Self {
+        let (cos, alpha) = Biquad::prewarp(cutoff_hz, sample_rate, q);
+        let mut biquad = Biquad::new(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        );
+        biquad.name = format!("lowpass({}Hz)", cutoff_hz);
+        biquad
+    }
+
+    pub fn highpass(cutoff_hz: f64, sample_rate: f64, q: f64) -> Self {
+        let (cos, alpha) = Biquad::prewarp(cutoff_hz, sample_rate, q);
+        let mut biquad = Biquad::new(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        );
+        biquad.name = format!("highpass({}Hz)", cutoff_hz);
+        biquad
+    }
+
+    fn prewarp(cutoff_hz: f64, sample_rate: f64, q: f64) -> (f64, f64) {
+        let w0 = 2.0 * PI * cutoff_hz / sample_rate;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    fn dc_gain(&self) -> f64 {
+        self.b.iter().sum::<f64>() / (1.0 + self.a[0] + self.a[1])
+    }
+}
+
+impl Filter for Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let [b0, b1, b2] = self.b;
+        let [a1, a2] = self.a;
+        let gain = self.dc_gain();
+        let [s1, s2] = *self.state.get_or_insert_with(|| {
+            // Steady state for a constant input x
+            let y = x * gain;
+            let s2 = b2 * x - a2 * y;
+            [b1 * x - a1 * y + s2, s2]
+        });
+        let y = b0 * x + s1;
+        self.state = Some([b1 * x - a1 * y + s2, b2 * x - a2 * y]);
+        y
+    }
+
+    fn group_delay(&self) -> f64 {
+        // d/dw of the phase at w = 0 for B(z)/A(z)
+        let weighted = |c: &[f64]| -> Option<f64> {
+            let sum: f64 = c.iter().sum();
+            let moment: f64 = c.iter().enumerate().map(|(k, v)| k as f64 * v).sum();
+            (sum.abs() > 1e-12).then(|| moment / sum)
+        };
+        let a = [1.0, self.a[0], self.a[1]];
+        match (weighted(&self.b), weighted(&a)) {
+            (Some(b), Some(a)) => b - a,
+            // No response at DC (high pass), no meaningful delay
+            _ => 0.0,
+        }
+    }
+
+    fn describe(&self) -> String {
+        self.name.clone()
+    }
+
+    fn reset(&mut self) {
+        self.state = None;
+    }
+}
+
+pub struct MovingAverage {
+    length: usize,
+    window: VecDeque<f64>,
+    sum: f64,
+}
+
+impl MovingAverage {
+    pub fn new(length: usize) -> Self {
+        MovingAverage {
+            length: length.max(1),
+            window: VecDeque::new(),
+            sum: 0.0,
+        }
+    }
+}
+
+impl Filter for MovingAverage {
+    fn process(&mut self, x: f64) -> f64 {
+        if self.window.is_empty() {
+            self.window.resize(self.length, x);
+            self.sum = x * self.length as f64;
+        }
+        self.sum += x - self.window.pop_front().unwrap_or(0.0);
+        self.window.push_back(x);
+        self.sum / self.length as f64
+    }
+
+    fn group_delay(&self) -> f64 {
+        (self.length - 1) as f64 / 2.0
+    }
+
+    fn describe(&self) -> String {
+        format!("moving_average({})", self.length)
+    }
+
+    fn reset(&mut self) {
+        self.window.clear();
+        self.sum = 0.0;
+    }
+}
+
+// Running median, removes spikes without smearing steps.
+pub struct Median {
+    length: usize,
+    window: VecDeque<f64>,
+}
+
+impl Median {
+    pub fn new(length: usize) -> Self {
+        Median {
+            length: length.max(1),
+            window: VecDeque::new(),
+        }
+    }
+}
+
+impl Filter for Median {
+    fn process(&mut self, x: f64) -> f64 {
+        if self.window.is_empty() {
+            self.window.resize(self.length, x);
+        }
+        self.window.pop_front();
+        self.window.push_back(x);
+        let mut sorted: Vec<f64> = self.window.iter().copied().collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let mid = sorted.len() / 2;
+        if sorted.len().is_multiple_of(2) {
+            (sorted[mid - 1] + sorted[mid]) / 2.0
+        } else {
+            sorted[mid]
+        }
+    }
+
+    fn group_delay(&self) -> f64 {
+        (self.length - 1) as f64 / 2.0
+    }
+
+    fn describe(&self) -> String {
+        format!("median({})", self.length)
+    }
+
+    fn reset(&mut self) {
+        self.window.clear();
+    }
+}
+
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn Filter>>,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        FilterChain::default()
+    }
+
+    pub fn with(mut self, filter: impl Filter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        self.filters
+            .iter_mut()
+            .fold(x, |x, filter| filter.process(x))
+    }
+
+    // Samples the output lags the input.
+    pub fn group_delay(&self) -> f64 {
+        self.filters.iter().map(|filter| filter.group_delay()).sum()
+    }
+
+    pub fn describe(&self) -> String {
+        let names: Vec<String> = self.filters.iter().map(|f| f.describe()).collect();
+        names.join(" -> ")
+    }
+
+    pub fn reset(&mut self) {
+        for filter in self.filters.iter_mut() {
+            filter.reset();
+        }
+    }
+}
+
+// Filter chains by column name, for one stream at a fixed reporting rate.
+// Columns are interned, per sample code looks its column up once with
+// channel_id and filters with process_channel.
+pub struct ChannelFilters {
+    sample_rate: f64,
+    channels: ChannelRegistry,
+    chains: Vec<FilterChain>, // By ChannelId
+}
+
+impl ChannelFilters {
+    pub fn new(sample_rate: f64) -> Self {
+        ChannelFilters {
+            sample_rate,
+            channels: ChannelRegistry::new(),
+            chains: Vec::new(),
+        }
+    }
+
+    pub fn with_chain(mut self, column: &str, chain: FilterChain) -> Self {
+        let id = self.channels.intern(column);
+        if id.index() < self.chains.len() {
+            self.chains[id.index()] = chain;
+        } else {
+            self.chains.push(chain);
+        }
+        self
+    }
+
+    // ID of a filtered column.
+    pub fn channel_id(&self, column: &str) -> Option<ChannelId> {
+        self.channels.id(column)
+    }
+
+    // Filter one sample, unfiltered columns pass through.
+    pub fn process(&mut self, column: &str, value: f64) -> f64 {
+        match self.channel_id(column) {
+            Some(id) => self.process_channel(id, value),
+            None => value,
+        }
+    }
+
+    pub fn process_channel(&mut self, id: ChannelId, value: f64) -> f64 {
+        match self.chains.get_mut(id.index()) {
+            Some(chain) => chain.process(value),
+            None => value,
+        }
+    }
+
+    // Microseconds the column's values lag their timestamps.
+    pub fn group_delay_us(&self, column: &str) -> f64 {
+        self.channel_id(column)
+            .and_then(|id| self.chains.get(id.index()))
+            .map_or(0.0, |chain| chain.group_delay() / self.sample_rate * 1e6)
+    }
+
+    // Filter the configured columns of a wide batch. Filtered columns become
+    // Float64 in engineering units (scale 1, no offset) and carry the chain
+    // and its group delay in their metadata. Chains keep their state from
+    // one batch to the next.
+    pub fn filter_batch(&mut self, batch: &RecordBatch) -> Result<RecordBatch, ArrowError> {
+        let schema = batch.schema();
+        let mut fields = Vec::with_capacity(batch.num_columns());
+        let mut columns: Vec<ArrayRef> = Vec::with_capacity(batch.num_columns());
+        for (field, column) in schema.fields().iter().zip(batch.columns()) {
+            let delay_us = self.group_delay_us(field.name());
+            let chain = self
+                .channels
+                .id(field.name())
+                .and_then(|id| self.chains.get_mut(id.index()));
+            let Some(chain) = chain else {
+                fields.push(field.as_ref().clone());
+                columns.push(column.clone());
+                continue;
+            };
+            let mut metadata = field.metadata().clone();
+            let number = |key: &str, default: f64| {
+                metadata
+                    .get(key)
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .unwrap_or(default)
+            };
+            let (scale, offset) = (number(META_SCALE, 1.0), number(META_OFFSET, 0.0));
+            let raw = cast(column, &DataType::Float64)?;
+            let raw = raw
+                .as_any()
+                .downcast_ref::<Float64Array>()
+                .ok_or_else(|| ArrowError::CastError(field.name().to_string()))?;
+            let filtered: Float64Array = raw
+                .iter()
+                .map(|value| value.map(|value| chain.process(value * scale + offset)))
+                .collect();
+
+            metadata.insert(META_SCALE.to_string(), "1".to_string());
+            metadata.remove(META_OFFSET);
+            metadata.insert(META_FILTER.to_string(), chain.describe());
+            metadata.insert(META_GROUP_DELAY_US.to_string(), delay_us.to_string());
+            fields.push(
+                Field::new(field.name(), DataType::Float64, field.is_nullable())
+                    .with_metadata(metadata),
+            );
+            columns.push(Arc::new(filtered));
+        }
+        RecordBatch::try_new(
+            Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone())),
+            columns,
+        )
+    }
+}
@@ -0,0 +1,203 @@
+// Test fixtures captured from live devices.
+//
+// A fixture is one configuration frame and a handful of data frames of a
+// device, written as hex text in the format of tests/test_data: uppercase
+// hex digits without separators, read back with read_hex_file. The data
+// frames go into one file, back to back, as a frame buffer for
+// build_record_batch.
+//
+// Captures are anonymized before they are written unless asked not to be:
+//
+//   - the stream is given IDCODE 7734 and its PMUs 7734, 7735, ...
+//   - stations are renamed Station A, Station B, ...
+//   - phasors are renamed V1, V2, ... or I1, I2, ... after their type,
+//     analogs A1, A2, ... and the bits of digital words D1_0 to D1_15, ...
+//     (names left blank stay blank)
+//   - timestamps are shifted so the first data frame is at the SOC of the
+//     sample data frame, 2006-06-06 08:00:00 UTC, keeping FRACSEC and the
+//     spacing of the frames
+//
+// The measured values are kept, they are what a regression test checks. The
+// configuration frame is rebuilt with the standard CHK, the data frames get
+// theirs recalculated the way it was computed.
+use crate::frame_parser::parse_config_frame_1and2;
+use crate::frames::{ConfigurationFrame1and2_2011, CrcMode};
+use crate::pdc_client::{ControlMessage, PDCClient};
+use crate::queue::Rings;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const FIXTURE_IDCODE: u16 = 7734;
+pub const FIXTURE_SOC: u32 = 1_149_580_800; // 2006-06-06 08:00:00 UTC
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixture {
+    pub config: Vec<u8>,
+    pub frames: Vec<Vec<u8>>, // Data frames, as received
+}
+
+impl Fixture {
+    pub fn new(config: Vec<u8>, frames: Vec<Vec<u8>>) -> Self {
+        Fixture { config, frames }
+    }
+
+    // The fixture with identities replaced, see the top of the file.
+    pub fn anonymize(&self) -> io::Result<Fixture> {
+        let mut config = parse_config_frame_1and2(&self.config).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid configuration frame: {:?}", e),
+            )
+        })?;
+        let shift = self
+            .frames
+            .first()
+            .and_then(|frame| frame.get(6..10))
+            .map_or(0, |soc| {
+                FIXTURE_SOC as i64 - u32::from_be_bytes(soc.try_into().unwrap()) as i64
+            });
+        anonymize_config(&mut config, shift);
+        let frames = self
+            .frames
+            .iter()
+            .map(|frame| {
+                let mut frame = frame.clone();
+                rewrite(&mut frame, |frame| {
+                    frame[4..6].copy_from_slice(&FIXTURE_IDCODE.to_be_bytes());
+                    shift_soc(frame, shift);
+                });
+                frame
+            })
+            .collect();
+        Ok(Fixture {
+            config: config.to_hex(),
+            frames,
+        })
+    }
+
+    // Write <name>_config.bin and <name>_data.bin to dir, returning their
+    // paths.
+    pub fn write(&self, dir: impl AsRef<Path>, name: &str) -> io::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let config = dir.join(format!("{}_config.bin", name));
+        let data = dir.join(format!("{}_data.bin", name));
+        fs::write(&config, to_hex_text(&self.config))?;
+        fs::write(&data, to_hex_text(&self.frames.concat()))?;
+        Ok(vec![config, data])
+    }
+}
+
+// Bytes as in tests/test_data.
+pub fn to_hex_text(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn anonymize_config(config: &mut ConfigurationFrame1and2_2011, shift: i64) {
+    config.prefix.idcode = FIXTURE_IDCODE;
+    config.prefix.soc = (config.prefix.soc as i64 + shift).clamp(0, u32::MAX as i64) as u32;
+    for (index, pmu) in config.pmu_configs.iter_mut().enumerate() {
+        pmu.idcode = FIXTURE_IDCODE.wrapping_add(index as u16);
+        pmu.stn = pad(&format!("Station {}", station_letter(index)));
+        let mut names = Vec::new();
+        let (mut voltages, mut currents) = (0, 0);
+        for unit in &pmu.phunit {
+            if unit >> 24 == 1 {
+                currents += 1;
+                names.push(format!("I{}", currents));
+            } else {
+                voltages += 1;
+                names.push(format!("V{}", voltages));
+            }
+        }
+        names.extend((1..=pmu.annmr).map(|analog| format!("A{}", analog)));
+        for word in 1..=pmu.dgnmr {
+            names.extend((0..16).map(|bit| format!("D{}_{}", word, bit)));
+        }
+        for (chunk, name) in pmu.chnam.chunks_mut(16).zip(names) {
+            if chunk.iter().any(|byte| *byte != b' ' && *byte != 0) {
+                chunk.copy_from_slice(&pad(&name));
+            }
+        }
+    }
+}
+
+// A, B, ..., Z, AA, AB, ...
+fn station_letter(index: usize) -> String {
+    let letter = (b'A' + (index % 26) as u8) as char;
+    match index / 26 {
+        0 => letter.to_string(),
+        prefix => format!("{}{}", station_letter(prefix - 1), letter),
+    }
+}
+
+fn pad(name: &str) -> [u8; 16] {
+    let mut padded = [b' '; 16];
+    let len = name.len().min(16);
+    padded[..len].copy_from_slice(&name.as_bytes()[..len]);
+    padded
+}
+
+fn shift_soc(frame: &mut [u8], shift: i64) {
+    let soc = u32::from_be_bytes(frame[6..10].try_into().unwrap()) as i64;
+    let soc = (soc + shift).clamp(0, u32::MAX as i64) as u32;
+    frame[6..10].copy_from_slice(&soc.to_be_bytes());
+}
+
+// Change a frame, recalculating a valid CHK the way it was computed.
+fn rewrite(frame: &mut [u8], change: impl FnOnce(&mut [u8])) {
+    if frame.len() < 16 {
+        return;
+    }
+    let len = frame.len();
+    let mode = CrcMode::detect(frame);
+    change(frame);
+    if let Some(chk) = mode.and_then(|mode| mode.calculate(frame)) {
+        frame[len - 2..].copy_from_slice(&chk.to_be_bytes());
+    }
+}
+
+// The configuration and the first data frames of a device, within timeout.
+pub async fn capture(
+    host: &str,
+    port: u16,
+    idcode: u16,
+    frames: usize,
+    timeout: Duration,
+) -> io::Result<Fixture> {
+    let (client, _, _) = PDCClient::new(host, port, idcode, Duration::from_secs(1)).await?;
+    let config = client
+        .config
+        .as_ref()
+        .map(|config| config.to_hex())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No configuration"))?;
+    let mut rings = Rings::new();
+    let mut client = client.with_frame_queue(rings.add(frames.max(64)));
+    let control = client.get_control_sender();
+    let stream = tokio::spawn(async move { client.start_stream().await });
+
+    let mut captured = Vec::with_capacity(frames);
+    let deadline = tokio::time::Instant::now() + timeout;
+    while captured.len() < frames && tokio::time::Instant::now() < deadline {
+        match rings.try_pop() {
+            Some(frame) => captured.push(frame.to_vec()),
+            None => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+    let _ = control.send(ControlMessage::Stop).await;
+    let _ = tokio::time::timeout(Duration::from_secs(1), stream).await;
+    if captured.len() < frames {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "{} of {} data frames within {:?}",
+                captured.len(),
+                frames,
+                timeout
+            ),
+        ));
+    }
+    Ok(Fixture::new(config, captured))
+}
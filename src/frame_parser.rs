@@ -4,6 +4,7 @@ use crate::frames::{
     PMUConfigurationFrame2011, PMUDataFrameFixedFreq2011, PMUDataFrameFloatFreq2011, PMUFrameType,
     PrefixFrame2011,
 };
+use bytes::Bytes;
 
 // Define constants
 const PREFIX_SIZE: usize = 14; // Size of HeaderFrame2011 in bytes
@@ -27,12 +28,15 @@ pub enum Frame {
     Command(CommandFrame2011),
 }
 
+// Header frames are not parsed yet.
 pub fn parse_header(buffer: &[u8]) -> Result<HeaderFrame2011, ParseError> {
-    todo!("Impement Header Frame parsing")
+    Err(ParseError::NotImplemented)
 }
 
 pub fn parse_command_frame(buffer: &[u8]) -> Result<Frame, ParseError> {
-    // TODO/skip
+    if buffer.len() < PREFIX_SIZE + 4 {
+        return Err(ParseError::InsufficientData);
+    }
     let prefix_slice: &[u8; PREFIX_SIZE] = buffer[..PREFIX_SIZE].try_into().unwrap();
     let prefix = PrefixFrame2011::from_hex(prefix_slice).map_err(|_| ParseError::InvalidHeader)?;
 
@@ -58,13 +62,22 @@ pub fn parse_data_frames(
     buffer: &[u8],
     config: &ConfigurationFrame1and2_2011,
 ) -> Result<DataFrame2011, ParseError> {
-    // data frame parsing here.
-    // TODO
-
     // First get prefix frame
+    if buffer.len() < PREFIX_SIZE + 2 {
+        return Err(ParseError::InsufficientData);
+    }
     let prefix_slice: &[u8; PREFIX_SIZE] = buffer[..PREFIX_SIZE].try_into().unwrap();
     let prefix = PrefixFrame2011::from_hex(prefix_slice).map_err(|_| ParseError::InvalidHeader)?;
 
+    // FRAMESIZE must be the size the configuration gives before any field is read
+    let expected_size = config.calc_data_frame_size();
+    if prefix.framesize as usize != expected_size {
+        return Err(ParseError::InvalidFrameSize);
+    }
+    if buffer.len() < expected_size {
+        return Err(ParseError::InsufficientData);
+    }
+
     let mut data: Vec<PMUFrameType> = Vec::new();
 
     // Make an offset variable and
@@ -86,7 +99,8 @@ pub fn parse_data_frames(
         let analog_datum_usize = config.analog_size();
         let freq_dfreq_usize = config.freq_dfreq_size();
 
-        // phasor should be vec<u8> with length 2*phasor_datum_usize*phnmr
+        // phasor should be vec<u8> with length phasor_datum_usize*phnmr
+        // (phasor_size already covers both components)
         // analog should be vec<u8> with length analog_datum_usize*annmr
         let pmu_frame = if freq_dfreq_usize == 2 {
             let stat = u16::from_be_bytes([buffer[offset], buffer[offset + 1]]);
@@ -119,8 +133,8 @@ pub fn parse_data_frames(
             let stat = u16::from_be_bytes([buffer[offset], buffer[offset + 1]]);
             offset += 2;
 
-            let phasors = buffer[offset..offset + 2 * phasor_datum_usize * phnmr as usize].to_vec();
-            offset += 2 * phasor_datum_usize * phnmr as usize;
+            let phasors = buffer[offset..offset + phasor_datum_usize * phnmr as usize].to_vec();
+            offset += phasor_datum_usize * phnmr as usize;
 
             let freq = f32::from_be_bytes([
                 buffer[offset],
@@ -156,14 +170,187 @@ pub fn parse_data_frames(
         data.push(pmu_frame);
     }
     // Read the CRC (chk) from the last two bytes of the buffer
-    let chk = u16::from_be_bytes([buffer[buffer.len() - 2], buffer[buffer.len() - 1]]);
+    let chk = u16::from_be_bytes([buffer[expected_size - 2], buffer[expected_size - 1]]);
 
     Ok(DataFrame2011 { prefix, data, chk })
 }
 
+// Fields of one PMU in a data frame, slices sharing the frame's bytes.
+#[derive(Debug, Clone)]
+pub struct PmuDataView {
+    pub stat: u16,
+    pub phasors: Bytes,
+    pub freq: Bytes, // 2 or 4 bytes, as the PMU's FORMAT says
+    pub dfreq: Bytes,
+    pub analogs: Bytes,
+    pub digitals: Bytes,
+}
+
+// A data frame parsed without copying its fields.
+#[derive(Debug, Clone)]
+pub struct DataFrameView {
+    pub prefix: PrefixFrame2011,
+    pub pmus: Vec<PmuDataView>,
+    pub chk: u16,
+    pub frame: Bytes,
+}
+
+// Like parse_data_frames, but the PMU fields are views into the frame, e.g. a
+// Bytes from a FramePool, instead of copies.
+pub fn parse_data_frame_view(
+    frame: Bytes,
+    config: &ConfigurationFrame1and2_2011,
+) -> Result<DataFrameView, ParseError> {
+    if frame.len() < PREFIX_SIZE + 2 {
+        return Err(ParseError::InsufficientData);
+    }
+    let prefix_slice: &[u8; PREFIX_SIZE] = frame[..PREFIX_SIZE].try_into().unwrap();
+    let prefix = PrefixFrame2011::from_hex(prefix_slice).map_err(|_| ParseError::InvalidHeader)?;
+    let expected_size = config.calc_data_frame_size();
+    if prefix.framesize as usize != expected_size {
+        return Err(ParseError::InvalidFrameSize);
+    }
+    if frame.len() < expected_size {
+        return Err(ParseError::InsufficientData);
+    }
+
+    let mut pmus = Vec::with_capacity(config.pmu_configs.len());
+    let mut offset = PREFIX_SIZE;
+    for pmu in &config.pmu_configs {
+        let freq_size = pmu.freq_dfreq_size();
+        let sizes = [
+            2,
+            pmu.phasor_size() * pmu.phnmr as usize,
+            freq_size,
+            freq_size,
+            pmu.analog_size() * pmu.annmr as usize,
+            2 * pmu.dgnmr as usize,
+        ];
+        let mut next = |size: usize| {
+            let field = frame.slice(offset..offset + size);
+            offset += size;
+            field
+        };
+        let stat = next(sizes[0]);
+        pmus.push(PmuDataView {
+            stat: u16::from_be_bytes([stat[0], stat[1]]),
+            phasors: next(sizes[1]),
+            freq: next(sizes[2]),
+            dfreq: next(sizes[3]),
+            analogs: next(sizes[4]),
+            digitals: next(sizes[5]),
+        });
+    }
+    // CHK ends the frame at FRAMESIZE, bytes past it belong to whatever follows
+    let chk = u16::from_be_bytes([frame[expected_size - 2], frame[expected_size - 1]]);
+    Ok(DataFrameView {
+        prefix,
+        pmus,
+        chk,
+        frame: frame.slice(..expected_size),
+    })
+}
+
+// Frames stored back to back, as in a capture or archive file. A frame is
+// taken when SYNC is 0xAA with version 1 or 2, FRAMESIZE fits in the buffer
+// and CHK matches. Anything else is skipped byte by byte up to the next frame
+// (resync), reported as one error per run of skipped bytes.
+pub struct FrameSequence<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+    skipped: usize,
+}
+
+impl<'a> FrameSequence<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        FrameSequence {
+            buffer,
+            offset: 0,
+            skipped: 0,
+        }
+    }
+
+    // Position of the next frame in the buffer.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    // Bytes skipped between frames so far.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    // Size of the frame at the start of bytes, or why there is none.
+    fn frame_at(bytes: &[u8]) -> Result<usize, ParseError> {
+        if bytes.len() < PREFIX_SIZE + 2 {
+            return Err(ParseError::InsufficientData);
+        }
+        if bytes[0] != 0xAA || !matches!(bytes[1] & 0x0F, 1 | 2) {
+            return Err(ParseError::InvalidHeader);
+        }
+        let size = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+        if size < PREFIX_SIZE + 2 || size > bytes.len() {
+            return Err(ParseError::InvalidFrameSize);
+        }
+        let chk = u16::from_be_bytes([bytes[size - 2], bytes[size - 1]]);
+        if calculate_crc(&bytes[..size - 2]) != chk {
+            return Err(ParseError::InvalidCRC);
+        }
+        Ok(size)
+    }
+}
+
+impl<'a> Iterator for FrameSequence<'a> {
+    type Item = Result<&'a [u8], ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.buffer[self.offset.min(self.buffer.len())..];
+        if rest.is_empty() {
+            return None;
+        }
+        match Self::frame_at(rest) {
+            Ok(size) => {
+                self.offset += size;
+                Some(Ok(&rest[..size]))
+            }
+            Err(e) => {
+                // Resync at the next frame, or the end of the buffer
+                let skip = (1..rest.len())
+                    .find(|&start| Self::frame_at(&rest[start..]).is_ok())
+                    .unwrap_or(rest.len());
+                self.offset += skip;
+                self.skipped += skip;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+pub fn frame_sequence(buffer: &[u8]) -> FrameSequence<'_> {
+    FrameSequence::new(buffer)
+}
+
+// The data frames of a buffer of back to back frames of one configuration,
+// e.g. read from a file. Frames of other types are passed over; damaged bytes
+// give an error and parsing resumes at the next frame.
+pub fn parse_frame_sequence<'a>(
+    buffer: &'a [u8],
+    config: &'a ConfigurationFrame1and2_2011,
+) -> impl Iterator<Item = Result<DataFrame2011, ParseError>> + 'a {
+    let size = config.calc_data_frame_size();
+    frame_sequence(buffer).filter_map(move |frame| match frame {
+        Ok(frame) if (frame[1] >> 4) & 0x07 != 0 => None,
+        Ok(frame) if frame.len() != size => Some(Err(ParseError::InvalidFrameSize)),
+        Ok(frame) => Some(parse_data_frames(frame, config)),
+        Err(e) => Some(Err(e)),
+    })
+}
+
 pub fn parse_config_frame_1and2(buffer: &[u8]) -> Result<ConfigurationFrame1and2_2011, ParseError> {
     // get the header frame struct using the parse_header_frame function
-
+    if buffer.len() < PREFIX_SIZE + 6 {
+        return Err(ParseError::InsufficientData);
+    }
     let prefix_slice: &[u8; PREFIX_SIZE] = buffer[..PREFIX_SIZE].try_into().unwrap();
     let common_header =
         PrefixFrame2011::from_hex(prefix_slice).map_err(|_| ParseError::InvalidHeader)?;
@@ -214,7 +401,7 @@ pub fn parse_config_frame_1and2(buffer: &[u8]) -> Result<ConfigurationFrame1and2
         let mut pmu_config = PMUConfigurationFrame2011 {
             stn,
             idcode,
-            format,
+            format: format.into(),
             phnmr,
             annmr,
             dgnmr,
@@ -227,10 +414,15 @@ pub fn parse_config_frame_1and2(buffer: &[u8]) -> Result<ConfigurationFrame1and2
         };
 
         // determine the next length of bytes to read populate the chnam field.
-        let chnam_bytes_len = 16 * (phnmr + annmr + 16 * dgnmr) as usize;
+        let chnam_bytes_len = 16 * (phnmr as usize + annmr as usize + 16 * dgnmr as usize);
+        // CHNAM, the units, FNOM and CFGCNT must all be there
+        let units_len = 4 * (phnmr as usize + annmr as usize + dgnmr as usize);
+        if offset + chnam_bytes_len + units_len + 4 > buffer.len() {
+            return Err(ParseError::InsufficientData);
+        }
         // read from offset to chname_bytes_len into a vec<u8> variable.
         let chnam = buffer[offset..offset + chnam_bytes_len].to_vec();
-        offset += chnam_bytes_len as usize;
+        offset += chnam_bytes_len;
         pmu_config.chnam = chnam;
 
         // read from offset to 4*phnmr into a vec<u32> variable.
@@ -268,6 +460,9 @@ pub fn parse_config_frame_1and2(buffer: &[u8]) -> Result<ConfigurationFrame1and2
 
         pmu_configs.push(pmu_config);
     }
+    if offset + 4 > buffer.len() {
+        return Err(ParseError::InsufficientData);
+    }
     // Generate the configuration frame 1 and 2 based on the variables throughout this function.
     let config_frame = ConfigurationFrame1and2_2011 {
         prefix: common_header,
@@ -281,9 +476,9 @@ pub fn parse_config_frame_1and2(buffer: &[u8]) -> Result<ConfigurationFrame1and2
     Ok(config_frame)
 }
 
+// Configuration frames 3 are not parsed yet.
 pub fn parse_config_frame_3(buffer: &[u8]) -> Result<Frame, ParseError> {
-    // TODO
-    todo!("Implement Config Frame type 3 parsing.")
+    Err(ParseError::NotImplemented)
 }
 
 pub fn parse_frame(
@@ -297,6 +492,9 @@ pub fn parse_frame(
     // if bits 3-0 == 0010, use IEEE standard from 2011
     // if bits 3-0 do not equal 0010, throw ParseError:VersionNotSupported
     println!("Reading Frame Prefix");
+    if buffer.len() < 4 {
+        return Err(ParseError::InsufficientData);
+    }
     let sync = u16::from_be_bytes([buffer[0], buffer[1]]);
     if sync >> 8 != 0xAA {
         println!("Invalid Sync value");
@@ -0,0 +1,88 @@
+// Receive buffers for frames, reused instead of allocated per frame.
+//
+// A source reads each frame into the pool's BytesMut and cuts it off as an
+// immutable Bytes. Frames cut from one chunk share its allocation, so there
+// is one allocation per chunk rather than one per frame. Once every frame of
+// the chunk has been dropped downstream, the next reserve reclaims the chunk
+// in place instead of allocating a new one.
+//
+// Frames are cheap to clone and slice (parse_data_frame_view), so they can
+// be buffered, forwarded and parsed without copying the bytes again.
+use bytes::{Bytes, BytesMut};
+
+// Frames of the largest expected size a chunk holds.
+const DEFAULT_CHUNK_FRAMES: usize = 64;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FramePoolStats {
+    pub frames: u64,      // Frames handed out
+    pub allocations: u64, // Chunks allocated
+    pub reclaimed: u64,   // Chunks reused after their frames were dropped
+}
+
+pub struct FramePool {
+    buffer: BytesMut,
+    chunk_frames: usize,
+    start: usize, // Address of the current chunk, to tell reuse from allocation
+    stats: FramePoolStats,
+}
+
+impl Default for FramePool {
+    fn default() -> Self {
+        FramePool::new()
+    }
+}
+
+impl FramePool {
+    pub fn new() -> Self {
+        FramePool {
+            buffer: BytesMut::new(),
+            chunk_frames: DEFAULT_CHUNK_FRAMES,
+            start: 0,
+            stats: FramePoolStats::default(),
+        }
+    }
+
+    // Frames per chunk. Larger chunks allocate less often but stay alive as
+    // long as any of their frames is.
+    pub fn with_chunk_frames(mut self, chunk_frames: usize) -> Self {
+        self.chunk_frames = chunk_frames.max(1);
+        self
+    }
+
+    pub fn stats(&self) -> FramePoolStats {
+        self.stats
+    }
+
+    // Zeroed space for a frame of up to len bytes, to read into.
+    pub fn buffer(&mut self, len: usize) -> &mut [u8] {
+        self.buffer.clear();
+        if self.buffer.capacity() < len {
+            self.buffer.reserve(len * self.chunk_frames);
+            let start = self.buffer.as_ptr() as usize;
+            if start == self.start {
+                self.stats.reclaimed += 1;
+            } else {
+                self.start = start;
+                self.stats.allocations += 1;
+            }
+        }
+        self.buffer.resize(len, 0);
+        &mut self.buffer[..]
+    }
+
+    // The first n bytes read into the buffer as a frame.
+    pub fn freeze(&mut self, n: usize) -> Bytes {
+        self.stats.frames += 1;
+        let n = n.min(self.buffer.len());
+        let frame = self.buffer.split_to(n).freeze();
+        self.buffer.clear();
+        frame
+    }
+
+    // Copy a frame read elsewhere into the pool.
+    pub fn copy(&mut self, data: &[u8]) -> Bytes {
+        self.buffer(data.len()).copy_from_slice(data);
+        self.freeze(data.len())
+    }
+}
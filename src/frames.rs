@@ -1,4 +1,6 @@
 #![allow(unused)]
+use crate::channels::ChannelIndex;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 // GOAL: Turn Sequence of Bytes in TCP packets into IEEE C37.118.2 formatted structs.
 // Define structures common to all frames
@@ -24,7 +26,89 @@ pub fn calculate_crc(buffer: &[u8]) -> u16 {
     crc
 }
 
-#[derive(Debug, Clone)]
+// Microseconds since the epoch of SOC and FRACSEC counted in time_base per
+// second, rounded to the nearest microsecond with integers, so any time base
+// works. The time quality flags of FRACSEC are left out. A FRACSEC of the time
+// base or more, a wrap the device did not carry into SOC, counts into the
+// following second.
+pub fn soc_fracsec_micros(soc: u32, fracsec: u32, time_base: u32) -> i64 {
+    let time_base = time_base.max(1) as u64;
+    let fraction = (fracsec & 0x00FF_FFFF) as u64;
+    let fraction_us = (fraction * 1_000_000 + time_base / 2) / time_base;
+    soc as i64 * 1_000_000 + fraction_us as i64
+}
+
+// Wall clock in microseconds since the epoch, 0 for a clock set before it.
+pub fn now_micros() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_micros() as i64)
+}
+
+// The same CRC with the polynomial reflected (0x8408), bits taken LSB first.
+pub fn calculate_crc_reflected(buffer: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in buffer {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            if (crc & 0x0001) != 0 {
+                crc = (crc >> 1) ^ 0x8408;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    crc
+}
+
+// How the CHK of a device's frames is checked. A few devices compute it over
+// a different range or with the reflected polynomial.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum CrcMode {
+    #[default]
+    Standard, // CRC-CCITT from SYNC up to CHK
+    ExcludeSync, // CRC-CCITT from FRAMESIZE up to CHK
+    Reflected,   // Reflected CRC-CCITT from SYNC up to CHK
+    Skip,        // CHK not checked
+}
+
+impl CrcMode {
+    // The modes that check CHK.
+    pub const CHECKED: [CrcMode; 3] = [CrcMode::Standard, CrcMode::ExcludeSync, CrcMode::Reflected];
+
+    // CHK the frame should have, None for Skip or a frame too short to have one.
+    pub fn calculate(self, frame: &[u8]) -> Option<u16> {
+        if frame.len() < 4 {
+            return None;
+        }
+        let body = &frame[..frame.len() - 2];
+        match self {
+            CrcMode::Standard => Some(calculate_crc(body)),
+            CrcMode::ExcludeSync => Some(calculate_crc(&body[2..])),
+            CrcMode::Reflected => Some(calculate_crc_reflected(body)),
+            CrcMode::Skip => None,
+        }
+    }
+
+    pub fn check(self, frame: &[u8]) -> bool {
+        if self == CrcMode::Skip {
+            return true;
+        }
+        let len = frame.len();
+        self.calculate(frame)
+            .is_some_and(|crc| crc == u16::from_be_bytes([frame[len - 2], frame[len - 1]]))
+    }
+
+    // The first mode that checks CHK and passes the frame.
+    pub fn detect(frame: &[u8]) -> Option<CrcMode> {
+        CrcMode::CHECKED.into_iter().find(|mode| mode.check(frame))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct PrefixFrame2011 {
     pub sync: u16, // Leading byte = AA hex,
     // second byte: Frame type and version
@@ -122,10 +206,14 @@ impl CommandFrame2011 {
         Self::new_command(idcode, 8)
     }
 
-    // TODO, decide whether to fill in the value now,
-    // or wait until the client sends over TCP.
-    // Second option is most precise.
-    // e.g. get time in seconds and fracsec, calc crc, to_hex, send.
+    // User data of an extended frame, in 16-bit words.
+    pub fn with_extframe(mut self, extframe: Vec<u8>) -> Self {
+        self.extframe = Some(extframe);
+        self
+    }
+
+    // SOC/FRACSEC are left at zero here and filled in by the sender with
+    // stamp_now just before the frame goes out, which is the most precise.
     fn new_command(idcode: u16, command: u16) -> Self {
         let prefix = PrefixFrame2011 {
             sync: 0xAA41,  // Command frame sync
@@ -141,9 +229,25 @@ impl CommandFrame2011 {
             chk: 0,
         }
     }
+    // Set SOC/FRACSEC to the current time. Command frames carry no TIME_BASE,
+    // the fraction of second is written in microseconds with time quality 0.
+    pub fn stamp_now(&mut self) {
+        let now = now_micros();
+        self.prefix.soc = (now / 1_000_000) as u32;
+        self.prefix.fracsec = (now % 1_000_000) as u32;
+    }
+
+    // Timestamp of the command in microseconds, see stamp_now.
+    pub fn timestamp_micros(&self) -> i64 {
+        self.prefix.soc as i64 * 1_000_000 + (self.prefix.fracsec & 0x00FF_FFFF) as i64
+    }
+
+    // Serialize, FRAMESIZE counting the extended frame data.
     pub fn to_hex(&self) -> Vec<u8> {
+        let mut prefix = self.prefix.clone();
+        prefix.framesize = (18 + self.extframe.as_ref().map_or(0, |e| e.len())) as u16;
         let mut result = Vec::new();
-        result.extend_from_slice(&self.prefix.to_hex());
+        result.extend_from_slice(&prefix.to_hex());
         result.extend_from_slice(&self.command.to_be_bytes());
         if let Some(extframe) = &self.extframe {
             result.extend_from_slice(extframe);
@@ -154,19 +258,43 @@ impl CommandFrame2011 {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum PMUFrameType {
     Floating(PMUDataFrameFloatFreq2011),
     Fixed(PMUDataFrameFixedFreq2011),
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct DataFrame2011 {
     pub prefix: PrefixFrame2011,
     pub data: Vec<PMUFrameType>, // Length of Vec is based on num phasors.
     pub chk: u16,
 }
 
+impl DataFrame2011 {
+    // Serialize back to a data frame, framesize and CHK are recalculated.
+    pub fn to_hex(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for pmu in &self.data {
+            match pmu {
+                PMUFrameType::Fixed(pmu) => {
+                    pmu.write_fields(&mut body, &pmu.freq.to_be_bytes(), &pmu.dfreq.to_be_bytes())
+                }
+                PMUFrameType::Floating(pmu) => {
+                    pmu.write_fields(&mut body, &pmu.freq.to_be_bytes(), &pmu.dfreq.to_be_bytes())
+                }
+            }
+        }
+        let mut prefix = self.prefix.clone();
+        prefix.framesize = (14 + body.len() + 2) as u16;
+        let mut result = prefix.to_hex().to_vec();
+        result.extend_from_slice(&body);
+        let crc = calculate_crc(&result);
+        result.extend_from_slice(&crc.to_be_bytes());
+        result
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum PMUValues {
     Float(Vec<f32>),
@@ -182,7 +310,7 @@ impl PMUValues {
 }
 // This frame is repeated for each PMU available.
 // We leave the phasor, analog and digital fields as variable length byte arrays to be parsed later based on the format.
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct PMUDataFrame<T> {
     // Header frame above plus the following
     // Each Vec<u8> field needs to be converted based on the per-field format determined by the configuration.
@@ -199,12 +327,21 @@ pub struct PMUDataFrame<T> {
                           // The number of values is determined by the DGNMR field in configuration 1, 2, and 3 frames.
 }
 impl<T> PMUDataFrame<T> {
+    fn write_fields(&self, out: &mut Vec<u8>, freq: &[u8], dfreq: &[u8]) {
+        out.extend_from_slice(&self.stat.to_be_bytes());
+        out.extend_from_slice(&self.phasors);
+        out.extend_from_slice(freq);
+        out.extend_from_slice(dfreq);
+        out.extend_from_slice(&self.analog);
+        out.extend_from_slice(&self.digital);
+    }
+
     pub fn parse_phasors(&self, config: &PMUConfigurationFrame2011) -> Vec<PMUValues> {
         let mut values = Vec::new();
         let chunk_size = config.phasor_size();
 
         for chunk in self.phasors.chunks(chunk_size) {
-            if config.format & 0x0002 != 0 {
+            if config.format.phasor_float {
                 // Parse as floating point
                 let float_values: Vec<f32> = chunk
                     .chunks(4)
@@ -223,7 +360,7 @@ impl<T> PMUDataFrame<T> {
         values
     }
     pub fn parse_analogs(&self, config: &PMUConfigurationFrame2011) -> PMUValues {
-        if config.format & 0x0004 != 0 {
+        if config.format.analog_float {
             // Parse as floating point
             let float_values: Vec<f32> = self
                 .analog
@@ -252,6 +389,157 @@ impl<T> PMUDataFrame<T> {
 pub type PMUDataFrameFixedFreq2011 = PMUDataFrame<i16>;
 pub type PMUDataFrameFloatFreq2011 = PMUDataFrame<f32>;
 
+// FREQ and DFREQ as sent: 16 bit integers (deviation from nominal in mHz,
+// ROCOF in hundredths of Hz/s) or floating point in Hz and Hz/s.
+pub trait FreqField: Copy {
+    fn frequency_hz(self, nominal_hz: f64) -> f64;
+    fn rocof_hz_s(self) -> f64;
+}
+
+impl FreqField for i16 {
+    fn frequency_hz(self, nominal_hz: f64) -> f64 {
+        nominal_hz + self as f64 / 1000.0
+    }
+
+    fn rocof_hz_s(self) -> f64 {
+        self as f64 / 100.0
+    }
+}
+
+impl FreqField for f32 {
+    fn frequency_hz(self, _nominal_hz: f64) -> f64 {
+        self as f64
+    }
+
+    fn rocof_hz_s(self) -> f64 {
+        self as f64
+    }
+}
+
+// Values of a PMU's block of a data frame in engineering units, whatever
+// the FORMAT they were sent in. Fixed point values are scaled by PHUNIT and
+// ANUNIT of the configuration.
+pub trait PMUData {
+    fn stat(&self) -> u16;
+    // (real, imaginary) or, for polar phasors, (magnitude, angle in radians)
+    fn phasor_values(&self, config: &PMUConfigurationFrame2011) -> Vec<(f64, f64)>;
+    fn frequency(&self, config: &PMUConfigurationFrame2011) -> f64; // Hz
+    fn rocof(&self) -> f64; // Hz/s
+    fn analog_values(&self, config: &PMUConfigurationFrame2011) -> Vec<f64>;
+    fn digital_words(&self) -> Vec<u16>;
+}
+
+impl<T: FreqField> PMUData for PMUDataFrame<T> {
+    fn stat(&self) -> u16 {
+        self.stat
+    }
+
+    fn phasor_values(&self, config: &PMUConfigurationFrame2011) -> Vec<(f64, f64)> {
+        let polar = config.is_phasor_polar();
+        self.phasors
+            .chunks_exact(config.phasor_size())
+            .enumerate()
+            .map(|(k, chunk)| {
+                if config.format.phasor_float {
+                    let first = f32::from_be_bytes(chunk[0..4].try_into().unwrap());
+                    let second = f32::from_be_bytes(chunk[4..8].try_into().unwrap());
+                    return (first as f64, second as f64);
+                }
+                // PHUNIT: 10^-5 V or A per bit
+                let scale = config
+                    .phunit
+                    .get(k)
+                    .map_or(1.0, |unit| (unit & 0x00FF_FFFF) as f64 / 100_000.0);
+                if polar {
+                    // Unsigned magnitude, angle in radians x 10^4
+                    let magnitude = u16::from_be_bytes([chunk[0], chunk[1]]) as f64;
+                    let angle = i16::from_be_bytes([chunk[2], chunk[3]]) as f64 / 10_000.0;
+                    (magnitude * scale, angle)
+                } else {
+                    let re = i16::from_be_bytes([chunk[0], chunk[1]]) as f64;
+                    let im = i16::from_be_bytes([chunk[2], chunk[3]]) as f64;
+                    (re * scale, im * scale)
+                }
+            })
+            .collect()
+    }
+
+    fn frequency(&self, config: &PMUConfigurationFrame2011) -> f64 {
+        let nominal_hz = if config.fnom & 0x0001 != 0 {
+            50.0
+        } else {
+            60.0
+        };
+        self.freq.frequency_hz(nominal_hz)
+    }
+
+    fn rocof(&self) -> f64 {
+        self.dfreq.rocof_hz_s()
+    }
+
+    fn analog_values(&self, config: &PMUConfigurationFrame2011) -> Vec<f64> {
+        if config.format.analog_float {
+            return self
+                .analog
+                .chunks_exact(4)
+                .map(|bytes| f32::from_be_bytes(bytes.try_into().unwrap()) as f64)
+                .collect();
+        }
+        self.analog
+            .chunks_exact(2)
+            .enumerate()
+            .map(|(k, bytes)| {
+                // ANUNIT: signed 24 bit user defined scale
+                let scale = config
+                    .anunit
+                    .get(k)
+                    .map_or(1.0, |unit| ((unit << 8) as i32 >> 8) as f64);
+                i16::from_be_bytes([bytes[0], bytes[1]]) as f64 * scale
+            })
+            .collect()
+    }
+
+    fn digital_words(&self) -> Vec<u16> {
+        self.parse_digitals()
+    }
+}
+
+// The block as either encoding, so callers need not match on it.
+impl PMUData for PMUFrameType {
+    fn stat(&self) -> u16 {
+        self.as_data().stat()
+    }
+
+    fn phasor_values(&self, config: &PMUConfigurationFrame2011) -> Vec<(f64, f64)> {
+        self.as_data().phasor_values(config)
+    }
+
+    fn frequency(&self, config: &PMUConfigurationFrame2011) -> f64 {
+        self.as_data().frequency(config)
+    }
+
+    fn rocof(&self) -> f64 {
+        self.as_data().rocof()
+    }
+
+    fn analog_values(&self, config: &PMUConfigurationFrame2011) -> Vec<f64> {
+        self.as_data().analog_values(config)
+    }
+
+    fn digital_words(&self) -> Vec<u16> {
+        self.as_data().digital_words()
+    }
+}
+
+impl PMUFrameType {
+    pub fn as_data(&self) -> &dyn PMUData {
+        match self {
+            PMUFrameType::Fixed(data) => data,
+            PMUFrameType::Floating(data) => data,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum ChannelDataType {
     PhasorFloat, // 8 bytes (magnitude + angle as f32)
@@ -263,15 +551,25 @@ pub enum ChannelDataType {
     FreqFixed,   // 2 bytes (i16)
     DfreqFloat,  // 4 bytes (f32)
     DfreqFixed,  // 2 bytes (i16)
+    Stat,        // 2 bytes (u16), one per PMU
 }
 #[derive(Debug, Clone)]
 pub struct ChannelInfo {
     pub data_type: ChannelDataType,
     pub offset: usize, // Offset from start of PMU data section
     pub size: usize,   // Size in bytes
+    // Description of the channel, from the PMU configuration
+    pub station: String,
+    pub idcode: u16,
+    pub unit: &'static str, // V, A, Hz, Hz/s, empty when user defined
+    pub scale: f64,         // Engineering units per raw count, 1 for floating point
+    pub kind: &'static str, // voltage/current for phasors, rms/peak/point_on_wave for analogs
+    pub polar: bool,        // Phasors are magnitude and angle
+    pub nominal_hz: f64,
+    pub time_base: u32, // TIME_BASE of the stream, FRACSEC counts per second
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ConfigurationFrame1and2_2011 {
     pub prefix: PrefixFrame2011,
     pub time_base: u32, // Resolution of
@@ -312,42 +610,126 @@ impl ConfigurationFrame1and2_2011 {
 
         total_size
     }
+    // Serialize back to a configuration frame, framesize and CHK are recalculated.
+    pub fn to_hex(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.time_base.to_be_bytes());
+        body.extend_from_slice(&(self.pmu_configs.len() as u16).to_be_bytes());
+        for pmu_config in &self.pmu_configs {
+            body.extend_from_slice(&pmu_config.stn);
+            body.extend_from_slice(&pmu_config.idcode.to_be_bytes());
+            body.extend_from_slice(&u16::from(pmu_config.format).to_be_bytes());
+            body.extend_from_slice(&pmu_config.phnmr.to_be_bytes());
+            body.extend_from_slice(&pmu_config.annmr.to_be_bytes());
+            body.extend_from_slice(&pmu_config.dgnmr.to_be_bytes());
+            body.extend_from_slice(&pmu_config.chnam);
+            for unit in pmu_config
+                .phunit
+                .iter()
+                .chain(&pmu_config.anunit)
+                .chain(&pmu_config.digunit)
+            {
+                body.extend_from_slice(&unit.to_be_bytes());
+            }
+            body.extend_from_slice(&pmu_config.fnom.to_be_bytes());
+            body.extend_from_slice(&pmu_config.cfgcnt.to_be_bytes());
+        }
+        body.extend_from_slice(&self.data_rate.to_be_bytes());
+
+        let mut prefix = self.prefix.clone();
+        prefix.framesize = (14 + body.len() + 2) as u16;
+        let mut result = prefix.to_hex().to_vec();
+        result.extend_from_slice(&body);
+        let crc = calculate_crc(&result);
+        result.extend_from_slice(&crc.to_be_bytes());
+        result
+    }
+
+    // The channel map by ChannelId, for lookups in per-frame code.
+    pub fn channel_index(&self) -> ChannelIndex {
+        ChannelIndex::new(&self.get_channel_map())
+    }
+
     pub fn get_channel_map(&self) -> HashMap<String, ChannelInfo> {
         let mut channel_map = HashMap::new();
-        let mut current_offset = 2; // Start after STAT
+        let mut current_offset = 0;
         let prefix_offset = 14;
 
         for pmu_config in &self.pmu_configs {
             let station_name = String::from_utf8_lossy(&pmu_config.stn).trim().to_string();
             let channel_names = pmu_config.get_column_names();
             let id_code = pmu_config.idcode;
+            let base = ChannelInfo {
+                data_type: ChannelDataType::Digital,
+                offset: 0,
+                size: 0,
+                station: station_name.clone(),
+                idcode: id_code,
+                unit: "",
+                scale: 1.0,
+                kind: "",
+                polar: pmu_config.is_phasor_polar(),
+                nominal_hz: if pmu_config.fnom & 0x0001 != 0 {
+                    50.0
+                } else {
+                    60.0
+                },
+                time_base: self.time_base,
+            };
+            channel_map.insert(
+                format!("{}_{}_STAT", station_name, id_code),
+                ChannelInfo {
+                    data_type: ChannelDataType::Stat,
+                    offset: current_offset + prefix_offset,
+                    size: 2,
+                    kind: "stat",
+                    ..base.clone()
+                },
+            );
+            current_offset += 2;
+
             // Add frequency and DFREQ channels
-            let freq_type = if pmu_config.format & 0x0008 != 0 {
+            let freq_type = if pmu_config.format.freq_float {
                 ChannelDataType::FreqFloat
             } else {
                 ChannelDataType::FreqFixed
             };
-            let dfreq_type = if pmu_config.format & 0x0008 != 0 {
+            let dfreq_type = if pmu_config.format.freq_float {
                 ChannelDataType::DfreqFloat
             } else {
                 ChannelDataType::DfreqFixed
             };
 
             // Add phasor channels
-            let phasor_type = if pmu_config.format & 0x0002 != 0 {
+            let phasor_type = if pmu_config.format.phasor_float {
                 ChannelDataType::PhasorFloat
             } else {
                 ChannelDataType::PhasorFixed
             };
 
             let phasor_size = pmu_config.phasor_size();
-            for name in channel_names.iter().take(pmu_config.phnmr as usize) {
+            for (k, name) in channel_names
+                .iter()
+                .take(pmu_config.phnmr as usize)
+                .enumerate()
+            {
+                // PHUNIT: type in the top byte, 10^-5 V or A per bit below
+                let phunit = pmu_config.phunit.get(k).copied().unwrap_or(0);
+                let current = phunit >> 24 == 1;
                 channel_map.insert(
                     name.clone(),
                     ChannelInfo {
                         data_type: phasor_type.clone(),
                         offset: current_offset + prefix_offset,
                         size: phasor_size,
+                        unit: if current { "A" } else { "V" },
+                        scale: if pmu_config.format.phasor_float {
+                            1.0
+                        } else {
+                            (phunit & 0x00FF_FFFF) as f64 / 100_000.0
+                        },
+                        kind: if current { "current" } else { "voltage" },
+                        ..base.clone()
                     },
                 );
                 current_offset += phasor_size;
@@ -360,6 +742,11 @@ impl ConfigurationFrame1and2_2011 {
                     data_type: freq_type,
                     offset: current_offset + prefix_offset,
                     size: freq_size,
+                    unit: "Hz",
+                    // Fixed point is the deviation from nominal in mHz
+                    scale: if freq_size == 2 { 0.001 } else { 1.0 },
+                    kind: "frequency",
+                    ..base.clone()
                 },
             );
             current_offset += freq_size;
@@ -370,38 +757,59 @@ impl ConfigurationFrame1and2_2011 {
                     data_type: dfreq_type,
                     offset: current_offset + prefix_offset,
                     size: freq_size,
+                    unit: "Hz/s",
+                    scale: if freq_size == 2 { 0.01 } else { 1.0 },
+                    kind: "rocof",
+                    ..base.clone()
                 },
             );
             current_offset += freq_size;
 
             // Add analog channels
-            let analog_type = if pmu_config.format & 0x0004 != 0 {
+            let analog_type = if pmu_config.format.analog_float {
                 ChannelDataType::AnalogFloat
             } else {
                 ChannelDataType::AnalogFixed
             };
 
             let analog_size = pmu_config.analog_size();
-            for name in channel_names
+            for (k, name) in channel_names
                 .iter()
                 .skip(pmu_config.phnmr as usize) // skip the freq/dfreq values and the number of phasors
                 .take(pmu_config.annmr as usize)
+                .enumerate()
             {
+                // ANUNIT: type in the top byte, signed 24 bit user defined scale below
+                let anunit = pmu_config.anunit.get(k).copied().unwrap_or(1);
+                let scale = ((anunit << 8) as i32 >> 8) as f64;
                 channel_map.insert(
                     name.clone(),
                     ChannelInfo {
                         data_type: analog_type.clone(),
                         offset: current_offset + prefix_offset,
                         size: analog_size,
+                        scale: if pmu_config.format.analog_float {
+                            1.0
+                        } else {
+                            scale
+                        },
+                        kind: match anunit >> 24 {
+                            0 => "point_on_wave",
+                            1 => "rms",
+                            2 => "peak",
+                            _ => "",
+                        },
+                        ..base.clone()
                     },
                 );
                 current_offset += analog_size;
             }
 
-            // Add digital channels
+            // Add digital channels, named after the first of the 16 bit names of each word
             for name in channel_names
                 .iter()
                 .skip(pmu_config.phnmr as usize + pmu_config.annmr as usize)
+                .step_by(16)
                 .take(pmu_config.dgnmr as usize)
             {
                 channel_map.insert(
@@ -410,6 +818,8 @@ impl ConfigurationFrame1and2_2011 {
                         data_type: ChannelDataType::Digital,
                         offset: current_offset + prefix_offset,
                         size: 2,
+                        kind: "digital",
+                        ..base.clone()
                     },
                 );
                 current_offset += 2;
@@ -419,23 +829,97 @@ impl ConfigurationFrame1and2_2011 {
         channel_map
     }
 }
+// FORMAT of a PMU's block in data frames, the same 16 bit field in 2011 and
+// 2024 configuration frames.
+// Bits 15-4: unused
+// Bit 3: 0=Freq/DFREQ 16-bit integer 1=Floating point
+// Bit 2: 0 = analogs 16-bit integer, 1=floating point
+// Bit 1: phasors 16-bit ineger, 1=floating point
+// Bit 0: phasor real and imaginary (rectangular), 1=magnitude and angle (polar)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FormatFlags {
+    pub freq_float: bool,
+    pub analog_float: bool,
+    pub phasor_float: bool,
+    pub polar: bool,
+    pub reserved: u16, // Bits 15-4 as received, kept so frames serialize back the same
+}
+
+impl FormatFlags {
+    pub fn freq_dfreq_size(&self) -> usize {
+        if self.freq_float {
+            4
+        } else {
+            2
+        }
+    }
+
+    pub fn analog_size(&self) -> usize {
+        if self.analog_float {
+            4
+        } else {
+            2
+        }
+    }
+
+    // Both components of a phasor
+    pub fn phasor_size(&self) -> usize {
+        if self.phasor_float {
+            8
+        } else {
+            4
+        }
+    }
+}
+
+impl From<u16> for FormatFlags {
+    fn from(format: u16) -> Self {
+        FormatFlags {
+            freq_float: format & 0x0008 != 0,
+            analog_float: format & 0x0004 != 0,
+            phasor_float: format & 0x0002 != 0,
+            polar: format & 0x0001 != 0,
+            reserved: format & 0xFFF0,
+        }
+    }
+}
+
+impl From<FormatFlags> for u16 {
+    fn from(format: FormatFlags) -> Self {
+        (format.reserved & 0xFFF0)
+            | (format.freq_float as u16) << 3
+            | (format.analog_float as u16) << 2
+            | (format.phasor_float as u16) << 1
+            | format.polar as u16
+    }
+}
+
+impl std::fmt::Display for FormatFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let kind = |float: bool| if float { "float" } else { "16 bit" };
+        write!(
+            f,
+            "FREQ/DFREQ {}, analogs {}, phasors {} {}",
+            kind(self.freq_float),
+            kind(self.analog_float),
+            kind(self.phasor_float),
+            if self.polar { "polar" } else { "rectangular" }
+        )
+    }
+}
+
 // This struct is repeated NUM_PMU times.
 // For parsing entire configuration frame, need to take into account num_pmu.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PMUConfigurationFrame2011 {
-    pub stn: [u8; 16], // Station Name 16 bytes ASCII
-    pub idcode: u16,   // Data source ID number, identifies source of each data block.
-    pub format: u16,   // Data format within the data frame
-    // 16-bit flag.
-    // Bits 15-4: unused
-    // Bit 3: 0=Freq/DFREQ 16-bit integer 1=Floating point
-    // Bit 2: 0 = analogs 16-bit integer, 1=floating point
-    // Bit 1: phasors 16-bit ineger, 1=floating point
-    // Bit 0: phasor real and imaginary (rectangular), 1=magnitude and angle (polar)
-    pub phnmr: u16,     // Number of phasors - 2 byte integer
-    pub annmr: u16,     // Number of analog values -  2 byte integer
-    pub dgnmr: u16,     // number of digital status words - 2 byte integer
-    pub chnam: Vec<u8>, // Length = 16 x (PHNMR+ANNMR + 16 x DGNMR)
+    pub stn: [u8; 16],       // Station Name 16 bytes ASCII
+    pub idcode: u16,         // Data source ID number, identifies source of each data block.
+    pub format: FormatFlags, // Data format within the data frame
+    pub phnmr: u16,          // Number of phasors - 2 byte integer
+    pub annmr: u16,          // Number of analog values -  2 byte integer
+    pub dgnmr: u16,          // number of digital status words - 2 byte integer
+    pub chnam: Vec<u8>,      // Length = 16 x (PHNMR+ANNMR + 16 x DGNMR)
     // Phasor and channel names, 16 bytes for each phasor analog and each digital channel.
     pub phunit: Vec<u32>, // length = 4 x PHNMR, Conversion factor for phasor channels
     pub anunit: Vec<u32>, // length = 4 x ANNMR, Conversion factor for Analog Channels
@@ -445,31 +929,19 @@ pub struct PMUConfigurationFrame2011 {
 }
 impl PMUConfigurationFrame2011 {
     pub fn freq_dfreq_size(&self) -> usize {
-        if self.format & 0x0008 != 0 {
-            4 // Floating point (4 bytes)
-        } else {
-            2 // 16-bit integer (2 bytes)
-        }
+        self.format.freq_dfreq_size()
     }
 
     pub fn analog_size(&self) -> usize {
-        if self.format & 0x0004 != 0 {
-            4 // Floating point (4 bytes)
-        } else {
-            2 // 16-bit integer (2 bytes)
-        }
+        self.format.analog_size()
     }
 
     pub fn phasor_size(&self) -> usize {
-        if self.format & 0x0002 != 0 {
-            8 // Floating point (8 bytes)
-        } else {
-            4 // Fixed point (4 bytes)
-        }
+        self.format.phasor_size()
     }
 
     pub fn is_phasor_polar(&self) -> bool {
-        self.format & 0x0001 != 0
+        self.format.polar
     }
     pub fn get_column_names(&self) -> Vec<String> {
         let mut channel_names = Vec::new();
@@ -1,3 +1,5 @@
+use crate::frames::FormatFlags;
+
 #[derive(Debug)]
 pub struct HeaderFrame2024 {
     pub sync: [u8; 2], // Synchronization bytes, using a u8[2] array here since the first and second byte are read separately.
@@ -37,7 +39,7 @@ pub struct PMUConfigurationFrame2024 {
     pub pmu_id: u16,        // 1-65534, 0 and 65535 are reserved
     pub pmu_version: u16,   // Bits 15-4 Reserved =0, Bits 3-0 Version Number from the SYNC word.
     pub g_pmu_id: [u32; 4], // Global PMU ID, Uses RFC 4122 big endian byte encoding.
-    pub format: u16, // Bits 15-4 Reserved=0, Bit3=FREQ/DFREQ 0=16bity integer, 1=floating point
+    pub format: FormatFlags, // Bits 15-4 Reserved=0, Bit3=FREQ/DFREQ 0=16bity integer, 1=floating point
     // bit2 Analog 0=16bit int, 1 = floating point
     // bit1 Phasor (format) 0=int, 1=floating point
     // bit0 Phasor (encoding) 0=real and imaginary, 1=magnitude and angle (polar)
@@ -0,0 +1,116 @@
+// Data frames whose FRAMESIZE disagrees with their stream's configuration.
+//
+// Some firmware gets FRAMESIZE wrong: it counts a field twice or leaves one
+// out, pads frames with trailing bytes, or sends frames too short for the
+// channels it configured. Against the size the configuration gives, a frame
+// is either
+//
+// - too short: fewer bytes than the channels need, never usable;
+// - trailing bytes: more bytes than the channels need, the configured frame
+//   followed by garbage;
+// - declared mismatch: of the configured size, FRAMESIZE saying otherwise.
+//
+// The strict policy rejects all three. The lenient one repairs the last two
+// when CHK still vouches for the bytes, either where the configuration puts
+// it or where a FRAMESIZE no smaller than that does: the frame is cut to the
+// configured size and gets the configured FRAMESIZE and a new CHK, so it
+// parses like any other.
+use crate::frames::CrcMode;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameSizePolicy {
+    #[default]
+    Strict, // Reject frames not of the configured size
+    Lenient, // Repair frames with trailing bytes or a wrong FRAMESIZE
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameSizeIssue {
+    TooShort { expected: usize, actual: usize },
+    TrailingBytes { expected: usize, actual: usize },
+    DeclaredMismatch { expected: usize, declared: usize },
+}
+
+impl fmt::Display for FrameSizeIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameSizeIssue::TooShort { expected, actual } => write!(
+                f,
+                "{} bytes, too short for the {} the configuration gives",
+                actual, expected
+            ),
+            FrameSizeIssue::TrailingBytes { expected, actual } => write!(
+                f,
+                "{} bytes after the {} the configuration gives",
+                actual - expected,
+                expected
+            ),
+            FrameSizeIssue::DeclaredMismatch { expected, declared } => write!(
+                f,
+                "FRAMESIZE {} in a frame of the {} bytes the configuration gives",
+                declared, expected
+            ),
+        }
+    }
+}
+
+// Frames of a stream found with each issue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameSizeStats {
+    pub too_short: u64,
+    pub trailing_bytes: u64,
+    pub declared_mismatch: u64,
+    pub repaired: u64,
+}
+
+impl FrameSizeStats {
+    pub fn count(&mut self, issue: &FrameSizeIssue) {
+        match issue {
+            FrameSizeIssue::TooShort { .. } => self.too_short += 1,
+            FrameSizeIssue::TrailingBytes { .. } => self.trailing_bytes += 1,
+            FrameSizeIssue::DeclaredMismatch { .. } => self.declared_mismatch += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.too_short + self.trailing_bytes + self.declared_mismatch
+    }
+}
+
+// How a frame's size disagrees with the expected one, None when it doesn't.
+pub fn check_frame_size(frame: &[u8], expected: usize) -> Option<FrameSizeIssue> {
+    let actual = frame.len();
+    if actual < expected || actual < 4 {
+        return Some(FrameSizeIssue::TooShort { expected, actual });
+    }
+    if actual > expected {
+        return Some(FrameSizeIssue::TrailingBytes { expected, actual });
+    }
+    let declared = u16::from_be_bytes([frame[2], frame[3]]) as usize;
+    (declared != expected).then_some(FrameSizeIssue::DeclaredMismatch { expected, declared })
+}
+
+// The frame cut to the expected size with FRAMESIZE and CHK set, when CHK
+// checks with mode at the expected size or at a declared one at least as
+// large. None for a frame too short or failing both checks: a CHK checking at
+// a smaller declared size vouches for fewer bytes than the frame keeps.
+pub fn repair_frame_size(frame: &[u8], expected: usize, mode: CrcMode) -> Option<Vec<u8>> {
+    if frame.len() < expected || expected < 16 {
+        return None;
+    }
+    let declared = u16::from_be_bytes([frame[2], frame[3]]) as usize;
+    let vouched = mode.check(&frame[..expected])
+        || (declared >= expected && declared <= frame.len() && mode.check(&frame[..declared]));
+    if !vouched {
+        return None;
+    }
+    let mut repaired = frame[..expected].to_vec();
+    repaired[2..4].copy_from_slice(&(expected as u16).to_be_bytes());
+    if let Some(crc) = mode.calculate(&repaired) {
+        repaired[expected - 2..].copy_from_slice(&crc.to_be_bytes());
+    }
+    Some(repaired)
+}
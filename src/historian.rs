@@ -0,0 +1,849 @@
+// In-memory ring buffer historian.
+//
+// Keeps the most recent raw data frames per stream, ordered by arrival, and
+// converts time ranges back into Arrow RecordBatches on request. Retention is
+// bounded by a MemoryBudget, the oldest frames are evicted first. The frames
+// held can be carried across a restart with checkpoint() and restore().
+//
+// Queries cover [start, end). query_page() returns at most max_rows rows of
+// the channels asked for, with a Continuation to fetch the rows after them,
+// so a large range is read a page at a time.
+//
+// With a SegmentStore (with_segments) evicted frames are spilled to disk
+// segments instead of dropped, and queries read disk and memory alike. Only
+// frames the store deletes to stay within its size count as evicted then.
+//
+// Each stream also keeps rollup tiers (see rollup) of min/mean/max per
+// channel. query_downsampled() answers from the raw frames when the range
+// has few enough of them, from the finest tier that fits otherwise.
+//
+// snapshot_diff() compares every stream at two times, for "what changed"
+// displays: the phasor magnitudes and angles and the frequencies of the last
+// frames at or before each time, one row per channel and quantity.
+use crate::arrow_utils::{
+    build_record_batch, channel_value, frame_timestamp_micros_with, unwrap_soc_rollover,
+    META_CHANNEL, META_COMPONENT, META_SCALE,
+};
+use crate::budget::{total_usage, MemoryBudget, MemoryUsage};
+use crate::channels::ChannelIndex;
+use crate::checkpoint::{Frame, HistorianState};
+use crate::frames::{ChannelDataType, ChannelInfo, ConfigurationFrame1and2_2011};
+use crate::rollup::{default_tiers, RollupTier, Rollups};
+use crate::segments::SegmentStore;
+use arrow::array::{
+    Array, ArrayRef, Float64Array, Float64Builder, StringBuilder, TimestampMicrosecondBuilder,
+    UInt16Builder,
+};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::mem::size_of;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Bookkeeping cost of each stored frame on top of the frame bytes.
+const FRAME_OVERHEAD: usize = size_of::<(i64, Vec<u8>)>();
+
+// Rows of a page when neither the query nor the historian limit them.
+pub const DEFAULT_MAX_QUERY_ROWS: usize = 100_000;
+
+#[derive(Debug)]
+pub enum HistorianError {
+    UnknownStream(u16),
+    UnknownChannel(String),
+    UnknownRollup(i64), // No tier of this interval in microseconds
+    InvalidContinuation(String),
+    InvalidFrameSize { expected: usize, actual: usize },
+    Arrow(ArrowError),
+    Io(std::io::Error),
+}
+
+impl From<ArrowError> for HistorianError {
+    fn from(e: ArrowError) -> Self {
+        HistorianError::Arrow(e)
+    }
+}
+
+impl From<std::io::Error> for HistorianError {
+    fn from(e: std::io::Error) -> Self {
+        HistorianError::Io(e)
+    }
+}
+
+// Where a page ended: the timestamp of the next row and how many rows with
+// that timestamp were returned already. Passed around as "<timestamp>.<skip>".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Continuation {
+    pub timestamp_us: i64,
+    pub skip: usize,
+}
+
+impl fmt::Display for Continuation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.timestamp_us, self.skip)
+    }
+}
+
+impl FromStr for Continuation {
+    type Err = HistorianError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || HistorianError::InvalidContinuation(s.to_string());
+        let (timestamp_us, skip) = s.split_once('.').ok_or_else(invalid)?;
+        Ok(Continuation {
+            timestamp_us: timestamp_us.parse().map_err(|_| invalid())?,
+            skip: skip.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+// Rows of a stream with start_us <= timestamp < end_us.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistorianQuery {
+    pub idcode: u16,
+    pub start_us: i64,
+    pub end_us: i64,
+    pub max_rows: Option<usize>,       // Within the historian's own limit
+    pub channels: Option<Vec<String>>, // Channel names, all when None
+    pub after: Option<Continuation>,
+}
+
+impl HistorianQuery {
+    pub fn new(idcode: u16, start_us: i64, end_us: i64) -> Self {
+        HistorianQuery {
+            idcode,
+            start_us,
+            end_us,
+            max_rows: None,
+            channels: None,
+            after: None,
+        }
+    }
+
+    pub fn with_max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = Some(max_rows.max(1));
+        self
+    }
+
+    pub fn with_channels(mut self, channels: &[&str]) -> Self {
+        self.channels = Some(channels.iter().map(|c| c.to_string()).collect());
+        self
+    }
+
+    // Continue after the page that returned next.
+    pub fn after(mut self, next: Continuation) -> Self {
+        self.after = Some(next);
+        self
+    }
+}
+
+// Where query_downsampled() reads a range from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Raw,
+    Rollup(i64), // Interval of the tier in microseconds
+}
+
+// A page of a query, next is set when rows are left.
+#[derive(Debug)]
+pub struct QueryPage {
+    pub batch: Option<RecordBatch>,
+    pub next: Option<Continuation>,
+}
+
+struct HistorianStream {
+    config: Frame,
+    channel_map: HashMap<String, ChannelInfo>,
+    index: ChannelIndex, // Channels by ID, for queries
+    frame_size: usize,
+    time_base: u32,
+    newest_us: Option<i64>, // Newest frame timestamp, to unwrap the SOC rollover
+    frames: VecDeque<(i64, Vec<u8>)>, // (timestamp in microseconds, raw frame)
+    bytes: usize,
+    evicted: u64,
+    rate_hz: f64,
+    rollups: Rollups,
+}
+
+impl HistorianStream {
+    // Timestamp of a frame at the stream's TIME_BASE, past the SOC rollover
+    // when the newest one is.
+    fn timestamp_us(&self, frame: &[u8]) -> i64 {
+        let timestamp_us = frame_timestamp_micros_with(frame, self.time_base).unwrap_or_default();
+        self.newest_us.map_or(timestamp_us, |newest| {
+            unwrap_soc_rollover(timestamp_us, newest)
+        })
+    }
+
+    fn usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            rows: self.frames.len(),
+            bytes: self.bytes,
+        }
+    }
+
+    // Drop the oldest frame from memory, spilled to the segments when kept.
+    fn evict_oldest(
+        &mut self,
+        idcode: u16,
+        segments: Option<&mut SegmentStore>,
+    ) -> Result<bool, HistorianError> {
+        let Some((timestamp, frame)) = self.frames.pop_front() else {
+            return Ok(false);
+        };
+        self.bytes -= frame.len() + FRAME_OVERHEAD;
+        let spilled = match segments {
+            Some(segments) => segments.append(idcode, timestamp, &frame)?,
+            None => false,
+        };
+        if !spilled {
+            self.evicted += 1;
+        }
+        Ok(true)
+    }
+
+    // Frames with start <= timestamp < end in memory and on disk, in
+    // timestamp order.
+    fn rows<'a>(
+        &'a self,
+        idcode: u16,
+        segments: Option<&'a SegmentStore>,
+        start_us: i64,
+        end_us: i64,
+    ) -> Vec<(i64, &'a [u8])> {
+        let mut rows = segments.map_or_else(Vec::new, |segments| {
+            segments.range(idcode, start_us, end_us)
+        });
+        rows.extend(
+            self.frames
+                .iter()
+                .filter(|(timestamp, _)| *timestamp >= start_us && *timestamp < end_us)
+                .map(|(timestamp, frame)| (*timestamp, frame.as_slice())),
+        );
+        rows.sort_by_key(|(timestamp, _)| *timestamp);
+        rows
+    }
+}
+
+pub struct Historian {
+    streams: HashMap<u16, HistorianStream>,
+    stream_budget: MemoryBudget,
+    total_budget: MemoryBudget,
+    max_query_rows: usize,
+    segments: Option<SegmentStore>,
+    rollup_tiers: Vec<RollupTier>,
+}
+
+impl Historian {
+    pub fn new(stream_budget: MemoryBudget) -> Self {
+        Self {
+            streams: HashMap::new(),
+            stream_budget,
+            total_budget: MemoryBudget::unlimited(),
+            max_query_rows: DEFAULT_MAX_QUERY_ROWS,
+            segments: None,
+            rollup_tiers: default_tiers(),
+        }
+    }
+
+    pub fn with_total_budget(mut self, total_budget: MemoryBudget) -> Self {
+        self.total_budget = total_budget;
+        self
+    }
+
+    // Most rows a page of query_page() returns, whatever the query asks.
+    pub fn with_max_query_rows(mut self, max_query_rows: usize) -> Self {
+        self.max_query_rows = max_query_rows.max(1);
+        self
+    }
+
+    // Spill evicted frames to disk segments. Call this before add_stream,
+    // which takes up the segments a stream left in the store's directory.
+    pub fn with_segments(mut self, segments: SegmentStore) -> Self {
+        self.segments = Some(segments);
+        self
+    }
+
+    // Rollup tiers of the streams added after this call, none to keep only
+    // the raw frames.
+    pub fn with_rollups(mut self, tiers: Vec<RollupTier>) -> Self {
+        self.rollup_tiers = tiers;
+        self
+    }
+
+    pub fn segments(&self) -> Option<&SegmentStore> {
+        self.segments.as_ref()
+    }
+
+    // Register (or replace) a stream. Frames stored under a previous configuration are dropped.
+    pub fn add_stream(&mut self, config: &ConfigurationFrame1and2_2011) {
+        if let Some(segments) = self.segments.as_mut() {
+            let frame_size = config.calc_data_frame_size();
+            if let Err(e) = segments.add_stream(config.prefix.idcode, &config.to_hex(), frame_size)
+            {
+                println!(
+                    "Historian stream {} not spilled to disk: {}",
+                    config.prefix.idcode, e
+                );
+            }
+        }
+        let channel_map = config.get_channel_map();
+        let frame_size = config.calc_data_frame_size();
+        // DATA_RATE is frames per second, or seconds per frame when negative
+        let rate_hz = match config.data_rate {
+            rate if rate > 0 => rate as f64,
+            rate if rate < 0 => -1.0 / rate as f64,
+            _ => 0.0,
+        };
+        self.streams.insert(
+            config.prefix.idcode,
+            HistorianStream {
+                config: Frame::from(config),
+                rollups: Rollups::new(&channel_map, frame_size, &self.rollup_tiers),
+                index: ChannelIndex::new(&channel_map),
+                channel_map,
+                frame_size,
+                time_base: config.time_base,
+                newest_us: None,
+                frames: VecDeque::new(),
+                bytes: 0,
+                evicted: 0,
+                rate_hz,
+            },
+        );
+    }
+
+    pub fn remove_stream(&mut self, idcode: u16) -> bool {
+        if let Some(segments) = self.segments.as_mut() {
+            if let Err(e) = segments.remove_stream(idcode) {
+                println!("Failed to delete segments of stream {}: {}", idcode, e);
+            }
+        }
+        self.streams.remove(&idcode).is_some()
+    }
+
+    // Store a raw data frame, evicting the oldest frames while over budget.
+    pub fn insert(&mut self, frame: &[u8]) -> Result<(), HistorianError> {
+        if frame.len() < 14 {
+            return Err(HistorianError::InvalidFrameSize {
+                expected: 14,
+                actual: frame.len(),
+            });
+        }
+        let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+        let stream = self
+            .streams
+            .get_mut(&idcode)
+            .ok_or(HistorianError::UnknownStream(idcode))?;
+        if frame.len() != stream.frame_size {
+            return Err(HistorianError::InvalidFrameSize {
+                expected: stream.frame_size,
+                actual: frame.len(),
+            });
+        }
+        let timestamp = stream.timestamp_us(frame);
+        stream.newest_us = Some(stream.newest_us.map_or(timestamp, |t| t.max(timestamp)));
+
+        stream.frames.push_back((timestamp, frame.to_vec()));
+        stream.bytes += frame.len() + FRAME_OVERHEAD;
+        stream.rollups.push(timestamp, frame);
+        self.enforce_budgets(idcode)
+    }
+
+    // Store a frame that arrived late at its place in timestamp order, replacing
+    // a frame already held for the same timestamp.
+    pub fn patch(&mut self, frame: &[u8]) -> Result<(), HistorianError> {
+        if frame.len() < 14 {
+            return Err(HistorianError::InvalidFrameSize {
+                expected: 14,
+                actual: frame.len(),
+            });
+        }
+        let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+        let stream = self
+            .streams
+            .get_mut(&idcode)
+            .ok_or(HistorianError::UnknownStream(idcode))?;
+        if frame.len() != stream.frame_size {
+            return Err(HistorianError::InvalidFrameSize {
+                expected: stream.frame_size,
+                actual: frame.len(),
+            });
+        }
+        let timestamp = stream.timestamp_us(frame);
+        stream.newest_us = Some(stream.newest_us.map_or(timestamp, |t| t.max(timestamp)));
+
+        stream.rollups.push(timestamp, frame);
+        let position = stream.frames.partition_point(|(t, _)| *t < timestamp);
+        match stream.frames.get_mut(position) {
+            Some((t, existing)) if *t == timestamp => *existing = frame.to_vec(),
+            _ => {
+                stream.frames.insert(position, (timestamp, frame.to_vec()));
+                stream.bytes += frame.len() + FRAME_OVERHEAD;
+            }
+        }
+        self.enforce_budgets(idcode)
+    }
+
+    fn enforce_budgets(&mut self, idcode: u16) -> Result<(), HistorianError> {
+        if let Some(stream) = self.streams.get_mut(&idcode) {
+            while self.stream_budget.is_exceeded(&stream.usage())
+                && stream.evict_oldest(idcode, self.segments.as_mut())?
+            {}
+        }
+
+        // Over the total budget, take frames from whichever stream holds the most.
+        while self.total_budget.is_exceeded(&self.total_usage()) {
+            let largest = self
+                .streams
+                .iter_mut()
+                .max_by_key(|(_, stream)| stream.bytes);
+            let evicted = match largest {
+                Some((idcode, stream)) => stream.evict_oldest(*idcode, self.segments.as_mut())?,
+                None => false,
+            };
+            if !evicted {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // All frames of a stream with start <= timestamp < end, as a RecordBatch.
+    pub fn query(
+        &self,
+        idcode: u16,
+        start_us: i64,
+        end_us: i64,
+    ) -> Result<Option<RecordBatch>, HistorianError> {
+        let stream = self
+            .streams
+            .get(&idcode)
+            .ok_or(HistorianError::UnknownStream(idcode))?;
+
+        let mut buffer = Vec::new();
+        for (_, frame) in stream.rows(idcode, self.segments.as_ref(), start_us, end_us) {
+            buffer.extend_from_slice(frame);
+        }
+        if buffer.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(build_record_batch(
+            &buffer,
+            stream.frame_size,
+            &stream.channel_map,
+        )?))
+    }
+
+    // A page of a query: at most max_rows rows of the channels asked for,
+    // in timestamp order, starting after the page before when continued.
+    pub fn query_page(&self, query: &HistorianQuery) -> Result<QueryPage, HistorianError> {
+        let stream = self
+            .streams
+            .get(&query.idcode)
+            .ok_or(HistorianError::UnknownStream(query.idcode))?;
+        let channel_map = match &query.channels {
+            Some(channels) => {
+                let ids = channels
+                    .iter()
+                    .map(|name| {
+                        stream
+                            .index
+                            .id(name)
+                            .ok_or_else(|| HistorianError::UnknownChannel(name.clone()))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                stream.index.channel_map(&ids)
+            }
+            None => stream.channel_map.clone(),
+        };
+        let max_rows = query
+            .max_rows
+            .map_or(self.max_query_rows, |rows| rows.min(self.max_query_rows));
+
+        let rows = stream.rows(
+            query.idcode,
+            self.segments.as_ref(),
+            query.start_us,
+            query.end_us,
+        );
+        let first = match query.after {
+            Some(after) => {
+                rows.partition_point(|(timestamp, _)| *timestamp < after.timestamp_us) + after.skip
+            }
+            None => 0,
+        };
+        let page = rows.get(first..).unwrap_or_default();
+        let taken = &page[..page.len().min(max_rows)];
+
+        // Rows at the next timestamp already returned are skipped next time
+        let end = first + taken.len();
+        let next = rows.get(end).map(|(timestamp_us, _)| Continuation {
+            timestamp_us: *timestamp_us,
+            skip: end - rows.partition_point(|(t, _)| t < timestamp_us),
+        });
+        if taken.is_empty() {
+            return Ok(QueryPage { batch: None, next });
+        }
+        let mut buffer = Vec::with_capacity(taken.len() * stream.frame_size);
+        for (_, frame) in taken {
+            buffer.extend_from_slice(frame);
+        }
+        Ok(QueryPage {
+            batch: Some(build_record_batch(
+                &buffer,
+                stream.frame_size,
+                &channel_map,
+            )?),
+            next,
+        })
+    }
+
+    // Rows of a rollup tier with start <= row start < end, as a RecordBatch
+    // of timestamp, frames and <channel>_min, _mean and _max columns.
+    pub fn query_rollup(
+        &self,
+        idcode: u16,
+        interval: Duration,
+        start_us: i64,
+        end_us: i64,
+    ) -> Result<Option<RecordBatch>, HistorianError> {
+        let stream = self
+            .streams
+            .get(&idcode)
+            .ok_or(HistorianError::UnknownStream(idcode))?;
+        let interval_us = interval.as_micros() as i64;
+        let rows = stream
+            .rollups
+            .rows(interval_us, start_us, end_us)
+            .ok_or(HistorianError::UnknownRollup(interval_us))?;
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(stream.rollups.to_batch(&rows)?))
+    }
+
+    // Raw when the range has at most max_rows frames at the stream's rate,
+    // else the finest tier with at most max_rows rows in it, else the
+    // coarsest.
+    pub fn resolution(
+        &self,
+        idcode: u16,
+        start_us: i64,
+        end_us: i64,
+        max_rows: usize,
+    ) -> Resolution {
+        let Some(stream) = self.streams.get(&idcode) else {
+            return Resolution::Raw;
+        };
+        let span_us = (end_us - start_us).max(0) as f64;
+        if span_us * stream.rate_hz / 1e6 <= max_rows as f64 {
+            return Resolution::Raw;
+        }
+        let intervals = stream.rollups.intervals();
+        intervals
+            .iter()
+            .find(|interval_us| span_us / **interval_us as f64 <= max_rows as f64)
+            .or(intervals.last())
+            .map_or(Resolution::Raw, |interval_us| {
+                Resolution::Rollup(*interval_us)
+            })
+    }
+
+    // A range at the resolution() for max_rows, with the schema of query() or
+    // of query_rollup().
+    pub fn query_downsampled(
+        &self,
+        idcode: u16,
+        start_us: i64,
+        end_us: i64,
+        max_rows: usize,
+    ) -> Result<(Resolution, Option<RecordBatch>), HistorianError> {
+        let resolution = self.resolution(idcode, start_us, end_us, max_rows);
+        let batch = match resolution {
+            Resolution::Raw => self.query(idcode, start_us, end_us)?,
+            Resolution::Rollup(interval_us) => self.query_rollup(
+                idcode,
+                Duration::from_micros(interval_us as u64),
+                start_us,
+                end_us,
+            )?,
+        };
+        Ok((resolution, batch))
+    }
+
+    // What changed between t1_us and t2_us across all streams: for every
+    // stream with a frame at or before both, the magnitude and angle of each
+    // phasor and each frequency in the last of those frames. One row per
+    // channel and quantity (magnitude, angle, frequency) with the timestamps
+    // of the two frames, both values and the change, angles in degrees and
+    // their change wrapped to -180..180. None when no stream has both frames.
+    pub fn snapshot_diff(
+        &self,
+        t1_us: i64,
+        t2_us: i64,
+    ) -> Result<Option<RecordBatch>, HistorianError> {
+        let mut diff = SnapshotDiff::default();
+        for idcode in self.idcodes() {
+            let stream = &self.streams[&idcode];
+            let at = |t: i64| {
+                stream
+                    .rows(
+                        idcode,
+                        self.segments.as_ref(),
+                        i64::MIN,
+                        t.saturating_add(1),
+                    )
+                    .last()
+                    .map(|(timestamp, frame)| (*timestamp, frame.to_vec()))
+            };
+            let (Some((time_1, frame_1)), Some((time_2, frame_2))) = (at(t1_us), at(t2_us)) else {
+                continue;
+            };
+            let batch_1 = build_record_batch(&frame_1, stream.frame_size, &stream.channel_map)?;
+            let batch_2 = build_record_batch(&frame_2, stream.frame_size, &stream.channel_map)?;
+            let mut channels: Vec<(&String, &ChannelInfo)> = stream.channel_map.iter().collect();
+            channels.sort_by(|a, b| a.0.cmp(b.0));
+            for (channel, info) in channels {
+                let mut row = |quantity, unit, value: &dyn Fn(&RecordBatch) -> Option<f64>| {
+                    diff.push(
+                        (time_1, time_2),
+                        info,
+                        channel,
+                        quantity,
+                        unit,
+                        (value(&batch_1), value(&batch_2)),
+                    )
+                };
+                match info.data_type {
+                    ChannelDataType::PhasorFloat | ChannelDataType::PhasorFixed => {
+                        row("magnitude", info.unit, &|batch| {
+                            channel_value(batch, channel, 0)
+                        });
+                        row("angle", "deg", &|batch| phasor_angle(batch, channel));
+                    }
+                    ChannelDataType::FreqFloat | ChannelDataType::FreqFixed => {
+                        row("frequency", info.unit, &|batch| {
+                            channel_value(batch, channel, 0)
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+        diff.finish()
+    }
+
+    // Frames of a stream left out of the rollups for arriving too late.
+    pub fn rollup_late(&self, idcode: u16) -> Option<u64> {
+        self.streams
+            .get(&idcode)
+            .map(|stream| stream.rollups.late())
+    }
+
+    // Streams held, in IDCODE order.
+    pub fn idcodes(&self) -> Vec<u16> {
+        let mut idcodes: Vec<u16> = self.streams.keys().copied().collect();
+        idcodes.sort();
+        idcodes
+    }
+
+    pub fn config(&self, idcode: u16) -> Option<ConfigurationFrame1and2_2011> {
+        self.streams.get(&idcode)?.config.config().ok()
+    }
+
+    // Oldest and newest timestamps held for a stream, on disk or in memory.
+    pub fn time_range(&self, idcode: u16) -> Option<(i64, i64)> {
+        let stream = self.streams.get(&idcode)?;
+        let memory = stream
+            .frames
+            .front()
+            .zip(stream.frames.back())
+            .map(|(first, last)| (first.0, last.0));
+        let disk = self
+            .segments
+            .as_ref()
+            .and_then(|segments| segments.time_range(idcode));
+        match (memory, disk) {
+            (Some((a, b)), Some((c, d))) => Some((a.min(c), b.max(d))),
+            (range, None) | (None, range) => range,
+        }
+    }
+
+    // Number of frames dropped from a stream because of the memory budget, or
+    // of the disk size with segments.
+    pub fn evicted(&self, idcode: u16) -> Option<u64> {
+        let deleted = self
+            .segments
+            .as_ref()
+            .map_or(0, |segments| segments.deleted(idcode));
+        self.streams
+            .get(&idcode)
+            .map(|stream| stream.evicted + deleted)
+    }
+
+    // Number of frames of a stream spilled to disk and still held there.
+    pub fn disk_frames(&self, idcode: u16) -> usize {
+        self.segments
+            .as_ref()
+            .map_or(0, |segments| segments.frames(idcode))
+    }
+
+    pub fn usage(&self, idcode: u16) -> Option<MemoryUsage> {
+        self.streams.get(&idcode).map(|stream| stream.usage())
+    }
+
+    pub fn usage_by_stream(&self) -> HashMap<u16, MemoryUsage> {
+        self.streams
+            .iter()
+            .map(|(idcode, stream)| (*idcode, stream.usage()))
+            .collect()
+    }
+
+    // Streams and frames held, to save in a Checkpoint.
+    pub fn checkpoint(&self) -> HistorianState {
+        let mut idcodes: Vec<&u16> = self.streams.keys().collect();
+        idcodes.sort();
+        let mut state = HistorianState::default();
+        for idcode in idcodes {
+            let stream = &self.streams[idcode];
+            state.configs.push(stream.config.clone());
+            state
+                .frames
+                .extend(stream.frames.iter().map(|(_, frame)| Frame(frame.clone())));
+            state.evicted.insert(*idcode, stream.evicted);
+        }
+        state
+    }
+
+    // Take back the streams and frames of a checkpoint, within the budgets.
+    pub fn restore(&mut self, state: &HistorianState) -> Result<(), HistorianError> {
+        for config in &state.configs {
+            self.add_stream(&config.config()?);
+        }
+        for (idcode, evicted) in &state.evicted {
+            if let Some(stream) = self.streams.get_mut(idcode) {
+                stream.evicted = *evicted;
+            }
+        }
+        for Frame(frame) in &state.frames {
+            self.insert(frame)?;
+        }
+        Ok(())
+    }
+
+    pub fn total_usage(&self) -> MemoryUsage {
+        total_usage(&self.usage_by_stream())
+    }
+}
+
+// Angle of a phasor in the first row of a batch, degrees.
+fn phasor_angle(batch: &RecordBatch, channel: &str) -> Option<f64> {
+    let schema = batch.schema();
+    let mut components = HashMap::new();
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        let meta = field.metadata();
+        if meta.get(META_CHANNEL).map(String::as_str) != Some(channel) {
+            continue;
+        }
+        let (Some(component), Ok(values)) =
+            (meta.get(META_COMPONENT), cast(column, &DataType::Float64))
+        else {
+            continue;
+        };
+        let values = values.as_any().downcast_ref::<Float64Array>()?;
+        if values.is_empty() || !values.is_valid(0) {
+            return None;
+        }
+        let scale = meta
+            .get(META_SCALE)
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or(1.0);
+        components.insert(component.as_str(), values.value(0) * scale);
+    }
+    let radians = match (components.get("real"), components.get("imaginary")) {
+        (Some(x), Some(y)) => y.atan2(*x),
+        _ => *components.get("angle")?,
+    };
+    Some(radians.to_degrees()).filter(|v| v.is_finite())
+}
+
+// Rows of Historian::snapshot_diff.
+#[derive(Default)]
+struct SnapshotDiff {
+    time_1: TimestampMicrosecondBuilder,
+    time_2: TimestampMicrosecondBuilder,
+    station: StringBuilder,
+    idcode: UInt16Builder,
+    channel: StringBuilder,
+    quantity: StringBuilder,
+    unit: StringBuilder,
+    value_1: Float64Builder,
+    value_2: Float64Builder,
+    delta: Float64Builder,
+    rows: usize,
+}
+
+impl SnapshotDiff {
+    fn push(
+        &mut self,
+        (time_1, time_2): (i64, i64),
+        info: &ChannelInfo,
+        channel: &str,
+        quantity: &str,
+        unit: &str,
+        (value_1, value_2): (Option<f64>, Option<f64>),
+    ) {
+        let delta = value_1.zip(value_2).map(|(a, b)| match quantity {
+            "angle" => (b - a + 180.0).rem_euclid(360.0) - 180.0,
+            _ => b - a,
+        });
+        self.time_1.append_value(time_1);
+        self.time_2.append_value(time_2);
+        self.station.append_value(&info.station);
+        self.idcode.append_value(info.idcode);
+        self.channel.append_value(channel);
+        self.quantity.append_value(quantity);
+        self.unit.append_value(unit);
+        self.value_1.append_option(value_1);
+        self.value_2.append_option(value_2);
+        self.delta.append_option(delta);
+        self.rows += 1;
+    }
+
+    fn finish(mut self) -> Result<Option<RecordBatch>, HistorianError> {
+        if self.rows == 0 {
+            return Ok(None);
+        }
+        let timestamp = DataType::Timestamp(TimeUnit::Microsecond, None);
+        let schema = Schema::new(vec![
+            Field::new("timestamp_1", timestamp.clone(), false),
+            Field::new("timestamp_2", timestamp, false),
+            Field::new("station", DataType::Utf8, false),
+            Field::new("idcode", DataType::UInt16, false),
+            Field::new("channel", DataType::Utf8, false),
+            Field::new("quantity", DataType::Utf8, false),
+            Field::new("unit", DataType::Utf8, false),
+            Field::new("value_1", DataType::Float64, true),
+            Field::new("value_2", DataType::Float64, true),
+            Field::new("delta", DataType::Float64, true),
+        ]);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.time_1.finish()),
+            Arc::new(self.time_2.finish()),
+            Arc::new(self.station.finish()),
+            Arc::new(self.idcode.finish()),
+            Arc::new(self.channel.finish()),
+            Arc::new(self.quantity.finish()),
+            Arc::new(self.unit.finish()),
+            Arc::new(self.value_1.finish()),
+            Arc::new(self.value_2.finish()),
+            Arc::new(self.delta.finish()),
+        ];
+        Ok(Some(RecordBatch::try_new(Arc::new(schema), columns)?))
+    }
+}
@@ -0,0 +1,186 @@
+// IDCODE allocation for simulated streams and PMUs.
+//
+// Every stream and every PMU a server or simulator presents needs an IDCODE
+// that no other one has, and neither 0 nor 65535, which C37.118.2 reserves.
+// IdcodeRegistry records who holds each IDCODE, refuses reserved values and
+// collisions with the holder named, and hands out the lowest free IDCODE of
+// its range to those that have none.
+//
+// Stream and PMU IDCODEs are checked separately, as a PDC stream carrying a
+// single PMU usually has the PMU's IDCODE. assign_layouts checks a set of
+// stream layouts: IDCODEs given are registered first, then the ones left at
+// 0 are assigned, a stream of a single PMU taking the PMU's IDCODE when it
+// is free.
+use crate::simulator::StreamLayout;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+pub const RESERVED_IDCODES: [u16; 2] = [0, u16::MAX];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdcodeError {
+    Reserved(u16),
+    Taken { idcode: u16, owner: String },
+    Exhausted { first: u16, last: u16 },
+}
+
+impl fmt::Display for IdcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdcodeError::Reserved(idcode) => write!(f, "IDCODE {} is reserved", idcode),
+            IdcodeError::Taken { idcode, owner } => {
+                write!(f, "IDCODE {} is already used by {}", idcode, owner)
+            }
+            IdcodeError::Exhausted { first, last } => {
+                write!(f, "No free IDCODE left in {}-{}", first, last)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdcodeRegistry {
+    range: RangeInclusive<u16>, // Assigned from, without the reserved values
+    owners: BTreeMap<u16, String>,
+}
+
+impl Default for IdcodeRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdcodeRegistry {
+    pub fn new() -> Self {
+        IdcodeRegistry {
+            range: 1..=u16::MAX - 1,
+            owners: BTreeMap::new(),
+        }
+    }
+
+    // Assign IDCODEs from first to last. Registering ones outside the range
+    // is still allowed.
+    pub fn with_range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.range = (*range.start()).max(1)..=(*range.end()).min(u16::MAX - 1);
+        self
+    }
+
+    pub fn range(&self) -> &RangeInclusive<u16> {
+        &self.range
+    }
+
+    pub fn len(&self) -> usize {
+        self.owners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.owners.is_empty()
+    }
+
+    pub fn owner(&self, idcode: u16) -> Option<&str> {
+        self.owners.get(&idcode).map(String::as_str)
+    }
+
+    pub fn is_free(&self, idcode: u16) -> bool {
+        !RESERVED_IDCODES.contains(&idcode) && !self.owners.contains_key(&idcode)
+    }
+
+    // Record owner as the holder of idcode.
+    pub fn register(&mut self, idcode: u16, owner: &str) -> Result<(), IdcodeError> {
+        if RESERVED_IDCODES.contains(&idcode) {
+            return Err(IdcodeError::Reserved(idcode));
+        }
+        if let Some(holder) = self.owners.get(&idcode) {
+            return Err(IdcodeError::Taken {
+                idcode,
+                owner: holder.clone(),
+            });
+        }
+        self.owners.insert(idcode, owner.to_string());
+        Ok(())
+    }
+
+    // The lowest free IDCODE of the range, registered to owner.
+    pub fn allocate(&mut self, owner: &str) -> Result<u16, IdcodeError> {
+        let idcode = self
+            .range
+            .clone()
+            .find(|idcode| !self.owners.contains_key(idcode))
+            .ok_or(IdcodeError::Exhausted {
+                first: *self.range.start(),
+                last: *self.range.end(),
+            })?;
+        self.owners.insert(idcode, owner.to_string());
+        Ok(idcode)
+    }
+
+    pub fn release(&mut self, idcode: u16) -> Option<String> {
+        self.owners.remove(&idcode)
+    }
+}
+
+// Range of IDCODEs to assign, as configured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdcodeRange {
+    pub first: u16,
+    pub last: u16,
+}
+
+impl IdcodeRange {
+    pub fn range(&self) -> RangeInclusive<u16> {
+        self.first..=self.last
+    }
+}
+
+// Parse a range given as first-last.
+pub fn parse_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+    let (first, last) = text
+        .split_once('-')
+        .ok_or_else(|| format!("Invalid IDCODE range {:?}, expected first-last", text))?;
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<u16>()
+            .map_err(|e| format!("Invalid IDCODE range {:?}: {}", text, e))
+    };
+    let (first, last) = (parse(first)?, parse(last)?);
+    if first > last {
+        return Err(format!("Invalid IDCODE range {:?}, first after last", text));
+    }
+    Ok(first..=last)
+}
+
+// Check the IDCODEs of layouts and assign those left at 0, from range.
+pub fn assign_layouts(
+    layouts: &mut [StreamLayout],
+    range: RangeInclusive<u16>,
+) -> Result<(), IdcodeError> {
+    let mut streams = IdcodeRegistry::new().with_range(range.clone());
+    let mut pmus = IdcodeRegistry::new().with_range(range);
+    for (index, layout) in layouts.iter().enumerate() {
+        if layout.idcode != 0 {
+            streams.register(layout.idcode, &format!("stream {}", index + 1))?;
+        }
+        for pmu in layout.pmus.iter().filter(|pmu| pmu.idcode != 0) {
+            pmus.register(pmu.idcode, &format!("PMU {}", pmu.station))?;
+        }
+    }
+    for (index, layout) in layouts.iter_mut().enumerate() {
+        for pmu in layout.pmus.iter_mut().filter(|pmu| pmu.idcode == 0) {
+            pmu.idcode = pmus.allocate(&format!("PMU {}", pmu.station))?;
+        }
+        if layout.idcode == 0 {
+            let owner = format!("stream {}", index + 1);
+            layout.idcode = match layout.pmus.as_slice() {
+                [pmu] if streams.is_free(pmu.idcode) => {
+                    streams.register(pmu.idcode, &owner)?;
+                    pmu.idcode
+                }
+                _ => streams.allocate(&owner)?,
+            };
+        }
+    }
+    Ok(())
+}
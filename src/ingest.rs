@@ -0,0 +1,172 @@
+// Runtime control of what the pipeline ingests.
+//
+// IngestControl is shared by the shards of a pipeline (Pipeline::
+// ingest_control) and may be changed while it runs: data frames of a
+// disabled stream are dropped and its sinks closed, disabled channels are
+// left out of the batches written. Streams are given by idcode after
+// remapping, channels by name as in the channel map, e.g. "Station A_1_VA"
+// for all its columns, or by column name. Rows buffered when the settings
+// change are written as they were before.
+//
+// router() serves the control over HTTP:
+//
+//   GET  /ingest                                 the settings
+//   POST /ingest/streams/{idcode}/enable|disable
+//   POST /ingest/channels/{name}/enable|disable
+//
+// Every request answers with the settings after it.
+use crate::arrow_utils::META_CHANNEL;
+use crate::sinks::to_io_error;
+use arrow::record_batch::RecordBatch;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IngestSettings {
+    #[serde(default)]
+    pub disabled_streams: BTreeSet<u16>,
+    #[serde(default)]
+    pub disabled_channels: BTreeSet<String>,
+}
+
+impl IngestSettings {
+    pub fn is_stream_enabled(&self, idcode: u16) -> bool {
+        !self.disabled_streams.contains(&idcode)
+    }
+
+    // The batch without the columns of disabled channels. The timestamp stays.
+    pub fn select(&self, batch: &RecordBatch) -> io::Result<RecordBatch> {
+        if self.disabled_channels.is_empty() {
+            return Ok(batch.clone());
+        }
+        let schema = batch.schema();
+        let columns: Vec<usize> = schema
+            .fields()
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| {
+                let channel = field.metadata().get(META_CHANNEL);
+                field.name() == "timestamp"
+                    || !(self.disabled_channels.contains(field.name())
+                        || channel.is_some_and(|c| self.disabled_channels.contains(c)))
+            })
+            .map(|(index, _)| index)
+            .collect();
+        if columns.len() == schema.fields().len() {
+            return Ok(batch.clone());
+        }
+        batch.project(&columns).map_err(to_io_error)
+    }
+}
+
+// Shared handle to the settings, cloned into every shard.
+#[derive(Clone)]
+pub struct IngestControl {
+    sender: Arc<watch::Sender<IngestSettings>>,
+}
+
+impl Default for IngestControl {
+    fn default() -> Self {
+        IngestControl::new(IngestSettings::default())
+    }
+}
+
+impl IngestControl {
+    pub fn new(settings: IngestSettings) -> Self {
+        IngestControl {
+            sender: Arc::new(watch::channel(settings).0),
+        }
+    }
+
+    pub fn settings(&self) -> IngestSettings {
+        self.sender.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<IngestSettings> {
+        self.sender.subscribe()
+    }
+
+    // Returns false when the stream already was as asked.
+    pub fn set_stream(&self, idcode: u16, enabled: bool) -> bool {
+        self.sender.send_if_modified(|settings| {
+            if enabled {
+                settings.disabled_streams.remove(&idcode)
+            } else {
+                settings.disabled_streams.insert(idcode)
+            }
+        })
+    }
+
+    pub fn set_channel(&self, name: &str, enabled: bool) -> bool {
+        self.sender.send_if_modified(|settings| {
+            if enabled {
+                settings.disabled_channels.remove(name)
+            } else {
+                settings.disabled_channels.insert(name.to_string())
+            }
+        })
+    }
+
+    pub fn set(&self, settings: IngestSettings) {
+        self.sender.send_if_modified(|current| {
+            let changed = *current != settings;
+            *current = settings;
+            changed
+        });
+    }
+}
+
+fn enabled(action: &str) -> Result<bool, StatusCode> {
+    match action {
+        "enable" => Ok(true),
+        "disable" => Ok(false),
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn get_settings(State(control): State<IngestControl>) -> Json<IngestSettings> {
+    Json(control.settings())
+}
+
+async fn post_stream(
+    State(control): State<IngestControl>,
+    Path((idcode, action)): Path<(u16, String)>,
+) -> Result<Json<IngestSettings>, StatusCode> {
+    if control.set_stream(idcode, enabled(&action)?) {
+        println!("Ingestion of stream {}: {}d", idcode, action);
+    }
+    Ok(Json(control.settings()))
+}
+
+async fn post_channel(
+    State(control): State<IngestControl>,
+    Path((name, action)): Path<(String, String)>,
+) -> Result<Json<IngestSettings>, StatusCode> {
+    if control.set_channel(&name, enabled(&action)?) {
+        println!("Ingestion of channel {}: {}d", name, action);
+    }
+    Ok(Json(control.settings()))
+}
+
+pub fn router(control: IngestControl) -> Router {
+    Router::new()
+        .route("/ingest", get(get_settings))
+        .route("/ingest/streams/:idcode/:action", post(post_stream))
+        .route("/ingest/channels/:name/:action", post(post_channel))
+        .with_state(control)
+}
+
+// Serve the control until the task is dropped.
+pub async fn serve(control: IngestControl, addr: SocketAddr) -> io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    println!("Ingest control listening on {}", listener.local_addr()?);
+    axum::serve(listener, router(control)).await
+}
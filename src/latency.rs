@@ -0,0 +1,214 @@
+// Receive timestamps and the latency and jitter of data frames.
+//
+// By default a frame's arrival time is taken in userspace when the read
+// returns, which includes scheduling delays of the collector itself. On Linux
+// a UDP socket can instead report when the kernel, or the NIC when it
+// supports hardware timestamping, received the datagram (SO_TIMESTAMPING).
+// The timestamp comes with each datagram as ancillary data of recvmsg.
+//
+// Latency is the arrival time minus the frame's SOC/FRACSEC. Jitter is the
+// smoothed variation of the latency from one frame to the next, as the
+// interarrival jitter of RFC 3550.
+//
+// Receiving timestamped datagrams needs the client feature.
+pub use crate::frames::now_micros;
+#[cfg(feature = "client")]
+use std::io;
+#[cfg(feature = "client")]
+use std::net::SocketAddr;
+#[cfg(feature = "client")]
+use tokio::net::UdpSocket;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampSource {
+    #[default]
+    Userspace, // Wall clock when the read returned
+    Kernel,   // Software timestamp of the network stack
+    Hardware, // Timestamp of the NIC
+}
+
+// Ask the kernel to timestamp received datagrams, and the NIC as well when
+// hardware is set. Only available on Linux.
+#[cfg(feature = "client")]
+pub fn enable_rx_timestamps(socket: &UdpSocket, hardware: bool) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        sys::enable(socket, hardware)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (socket, hardware);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Receive timestamps need Linux",
+        ))
+    }
+}
+
+// Receive a datagram with its arrival time in microseconds since the epoch.
+// Falls back to the userspace clock when the datagram has no timestamp.
+#[cfg(feature = "client")]
+pub async fn recv_timestamped(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, i64, TimestampSource)> {
+    #[cfg(target_os = "linux")]
+    {
+        let (n, from, stamp) = socket
+            .async_io(tokio::io::Interest::READABLE, || sys::recv(socket, buf))
+            .await?;
+        let (arrival_us, source) = stamp.unwrap_or((now_micros(), TimestampSource::Userspace));
+        Ok((n, from, arrival_us, source))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let (n, from) = socket.recv_from(buf).await?;
+        Ok((n, from, now_micros(), TimestampSource::Userspace))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "client"))]
+mod sys {
+    use super::TimestampSource;
+    use std::io;
+    use std::mem;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+    use std::os::fd::AsRawFd;
+    use tokio::net::UdpSocket;
+
+    pub fn enable(socket: &UdpSocket, hardware: bool) -> io::Result<()> {
+        let mut flags = libc::SOF_TIMESTAMPING_RX_SOFTWARE | libc::SOF_TIMESTAMPING_SOFTWARE;
+        if hardware {
+            flags |= libc::SOF_TIMESTAMPING_RX_HARDWARE | libc::SOF_TIMESTAMPING_RAW_HARDWARE;
+        }
+        let flags = flags as libc::c_int;
+        // The option value is a plain int
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_TIMESTAMPING,
+                &flags as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Length, sender and the arrival time if the datagram had one.
+    type Received = (usize, SocketAddr, Option<(i64, TimestampSource)>);
+
+    // Non-blocking recvmsg, WouldBlock when nothing is waiting.
+    pub fn recv(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<Received> {
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        // u64 for the alignment of cmsghdr
+        let mut control = [0u64; 64];
+        // All zeroes is a valid sockaddr_storage and msghdr
+        let mut address: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut message: libc::msghdr = unsafe { mem::zeroed() };
+        message.msg_name = &mut address as *mut libc::sockaddr_storage as *mut libc::c_void;
+        message.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        message.msg_controllen = mem::size_of_val(&control) as _;
+
+        // The buffers outlive the call and their lengths are set above
+        let n = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut message, libc::MSG_DONTWAIT) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut stamp = None;
+        // Walk the control messages recvmsg filled in, within msg_controllen
+        unsafe {
+            let mut header = libc::CMSG_FIRSTHDR(&message);
+            while !header.is_null() {
+                if (*header).cmsg_level == libc::SOL_SOCKET
+                    && (*header).cmsg_type == libc::SCM_TIMESTAMPING
+                {
+                    // Software, deprecated, raw hardware
+                    let times = std::ptr::read_unaligned(
+                        libc::CMSG_DATA(header) as *const [libc::timespec; 3]
+                    );
+                    // time_t and c_long are 32 bits on some targets
+                    #[allow(clippy::unnecessary_cast)]
+                    let micros = |t: &libc::timespec| {
+                        (t.tv_sec != 0 || t.tv_nsec != 0)
+                            .then(|| t.tv_sec as i64 * 1_000_000 + t.tv_nsec as i64 / 1_000)
+                    };
+                    stamp = micros(&times[2])
+                        .map(|us| (us, TimestampSource::Hardware))
+                        .or_else(|| micros(&times[0]).map(|us| (us, TimestampSource::Kernel)));
+                }
+                header = libc::CMSG_NXTHDR(&message, header);
+            }
+        }
+        Ok((n as usize, socket_address(&address)?, stamp))
+    }
+
+    fn socket_address(address: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+        // The family tells which sockaddr the storage holds
+        match address.ss_family as libc::c_int {
+            libc::AF_INET => {
+                let v4 = unsafe { &*(address as *const _ as *const libc::sockaddr_in) };
+                Ok(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(v4.sin_addr.s_addr)),
+                    u16::from_be(v4.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                let v6 = unsafe { &*(address as *const _ as *const libc::sockaddr_in6) };
+                Ok(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(v6.sin6_addr.s6_addr),
+                    u16::from_be(v6.sin6_port),
+                    v6.sin6_flowinfo,
+                    v6.sin6_scope_id,
+                )))
+            }
+            family => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unexpected address family {}", family),
+            )),
+        }
+    }
+}
+
+// Latency and jitter of a stream's frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LatencyTracker {
+    pub frames: u64,
+    pub last_us: i64, // Latency of the last frame
+    pub min_us: i64,
+    pub max_us: i64,
+    pub jitter_us: f64,
+    pub source: TimestampSource, // Of the last arrival time
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        LatencyTracker::default()
+    }
+
+    pub fn record(&mut self, frame_us: i64, arrival_us: i64, source: TimestampSource) {
+        let latency = arrival_us - frame_us;
+        if self.frames == 0 {
+            self.min_us = latency;
+            self.max_us = latency;
+        } else {
+            let change = (latency - self.last_us).abs() as f64;
+            self.jitter_us += (change - self.jitter_us) / 16.0;
+            self.min_us = self.min_us.min(latency);
+            self.max_us = self.max_us.max(latency);
+        }
+        self.last_us = latency;
+        self.source = source;
+        self.frames += 1;
+    }
+}
@@ -1,8 +1,110 @@
 // everything public in this file can be used in testing with pmu::...?
+#[cfg(feature = "arrow")]
+pub mod accumulator;
+#[cfg(feature = "sinks")]
+pub mod aggregator;
+pub mod analytics;
+pub mod annotations;
+#[cfg(feature = "arrow")]
+pub mod areas;
+#[cfg(feature = "arrow")]
 pub mod arrow_utils;
+pub mod audit;
+#[cfg(feature = "analytics")]
+pub mod baseline;
+pub mod budget;
+#[cfg(feature = "analytics")]
+pub mod bundle;
+pub mod channels;
+pub mod checkpoint;
+#[cfg(all(feature = "analytics", feature = "server"))]
+pub mod cim;
+pub mod config_cache;
+#[cfg(all(feature = "analytics", feature = "server"))]
+pub mod dataset;
+#[cfg(feature = "sinks")]
+pub mod deadletter;
+pub mod dedup;
+#[cfg(feature = "arrow")]
+pub mod derived;
+#[cfg(feature = "sinks")]
+pub mod detect;
+#[cfg(feature = "dnp3")]
+pub mod dnp3;
+pub mod dump;
+#[cfg(any(feature = "sinks", feature = "client"))]
+pub mod events;
+#[cfg(feature = "arrow")]
+pub mod filter;
+#[cfg(feature = "client")]
+pub mod fixture;
 pub mod frame_buffer;
 pub mod frame_parser;
+pub mod frame_pool;
 pub mod frames;
+pub mod framesize;
+#[cfg(feature = "arrow")]
+pub mod historian;
+pub mod idcodes;
+#[cfg(feature = "server")]
+pub mod ingest;
+#[cfg(feature = "arrow")]
+pub mod latency;
+#[cfg(feature = "arrow")]
+pub mod matrix;
+pub mod metrics;
+#[cfg(feature = "modbus")]
+pub mod modbus;
+#[cfg(feature = "client")]
+pub mod multicast;
+pub mod mutation;
+#[cfg(feature = "analytics")]
+pub mod notify;
+#[cfg(feature = "arrow")]
+pub mod openpdc;
+#[cfg(feature = "arrow")]
+pub mod pdat;
+#[cfg(feature = "server")]
 pub mod pdc_buffer_server;
+#[cfg(feature = "client")]
 pub mod pdc_client;
+#[cfg(feature = "server")]
 pub mod pdc_server;
+#[cfg(all(feature = "analytics", feature = "server"))]
+pub mod pipeline;
+#[cfg(feature = "plot")]
+pub mod plot;
+#[cfg(feature = "client")]
+pub mod queue;
+#[cfg(feature = "arrow")]
+pub mod rate_conversion;
+#[cfg(feature = "sinks")]
+pub mod recorder;
+#[cfg(feature = "arrow")]
+pub mod regenerate;
+pub mod remap;
+#[cfg(all(feature = "client", feature = "parquet"))]
+pub mod replay;
+#[cfg(feature = "analytics")]
+pub mod reports;
+#[cfg(feature = "arrow")]
+pub mod rollup;
+#[cfg(feature = "client")]
+pub mod scada;
+#[cfg(feature = "arrow")]
+pub mod segments;
+pub mod simulator;
+#[cfg(feature = "sinks")]
+pub mod sinks;
+#[cfg(feature = "sinks")]
+pub mod snapshot;
+#[cfg(all(feature = "analytics", feature = "server"))]
+pub mod soak;
+pub mod strict;
+#[cfg(feature = "arrow")]
+pub mod topology;
+#[cfg(feature = "arrow")]
+pub mod trace;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod virtual_pmu;
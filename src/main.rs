@@ -1,12 +1,37 @@
-mod arrow_utils;
-mod frame_parser;
-mod frames;
-mod pdc_buffer_server;
-mod pdc_client;
-mod pdc_server;
+use arrow::record_batch::RecordBatch;
 use clap::{Parser, Subcommand};
 //use log::info;
-use pdc_server::{run_mock_server, Protocol, ServerConfig};
+use pmu::analytics::ufls::{self, UflsConfig};
+use pmu::annotations::{Annotation, AnnotationKind, AnnotationStore};
+use pmu::audit::AuditLog;
+use pmu::cim;
+use pmu::dataset::{self, DatasetConfig};
+use pmu::detect;
+use pmu::dump;
+use pmu::fixture;
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::idcodes;
+use pmu::ingest;
+use pmu::matrix::{self, MatrixExport};
+use pmu::openpdc::{MeasurementMap, OpenPdcReader};
+use pmu::pdat::{self, PdatReader};
+use pmu::pdc_buffer_server;
+use pmu::pdc_server::{
+    run_mock_server, ClientPermission, CommandPolicy, CommandReplayGuard, Protocol, ReplayAction,
+    ServerConfig,
+};
+use pmu::pipeline::{Pipeline, PipelineConfig, SinkConfig, SinkFormat};
+use pmu::recorder::{CaptureReader, CAPTURE_MAGIC};
+use pmu::replay::PlaybackOptions;
+use pmu::simulator::Scenario;
+use pmu::soak::{self, SoakConfig};
+use pmu::topology::Topology;
+use pmu::virtual_pmu::VirtualStreamConfig;
+use std::collections::hash_map::{Entry, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::io;
 #[derive(Debug, Parser)] // requires `derive` feature
 #[command(name = "pmu")]
@@ -23,6 +48,44 @@ enum Commands {
         ip: String,
         #[arg(default_value_t = 8123)]
         port: u16,
+        // JSON pipeline configuration whose server section sets the client permissions
+        #[arg(long)]
+        config: Option<PathBuf>,
+        // Clients allowed to turn transmission on/off, over the configured permissions.
+        // Without a configuration, unlisted clients are refused when any client is listed.
+        #[arg(long)]
+        control: Vec<IpAddr>,
+        // Clients allowed to request header and configuration frames only
+        #[arg(long)]
+        read_only: Vec<IpAddr>,
+        // JSONL file receiving an audit entry for every command
+        #[arg(long)]
+        audit_log: Option<PathBuf>,
+        // Reject commands whose SOC/FRACSEC is further than this from the server clock
+        // or not newer than the previous command from the same client
+        #[arg(long)]
+        replay_window_ms: Option<u64>,
+        // JSON scenario file, streams simulated frames with scripted events
+        #[arg(long)]
+        scenario: Option<PathBuf>,
+        // Playback speed of the simulated stream, 0.1 to 100
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+        // Restart the scenario when its duration has been played
+        #[arg(long = "loop")]
+        looping: bool,
+        // IDCODEs assigned to the scenario's stream and PMUs that have none,
+        // first-last
+        #[arg(long, default_value = "1-65534")]
+        idcodes: String,
+        // Serve the simulated stream at this rate, frames per second: decimated,
+        // or upsampled with interpolated frames flagged as modified in STAT
+        #[arg(long)]
+        output_rate: Option<i16>,
+        // JSON file of virtual PMUs (sequence components, angle differences,
+        // area frequency) to serve in place of the source stream
+        #[arg(long)]
+        virtual_pmus: Option<PathBuf>,
     },
     //#[command(arg_required_else_help = true)]
     Client {
@@ -35,6 +98,275 @@ enum Commands {
         #[arg(default_value_t = 120)]
         duration: u16,
     },
+    // Collect the streams of a JSON pipeline configuration into files
+    Pipeline {
+        config: PathBuf,
+        // Serve the ingest control (enable/disable streams and channels) over
+        // HTTP on this local port
+        #[arg(long)]
+        control_port: Option<u16>,
+    },
+    // Check a pipeline configuration before deployment: connect to the streams,
+    // request their configurations, check the sinks and list the channels,
+    // without collecting
+    Validate {
+        config: PathBuf,
+        // Write the channel catalog as CIM RDF/XML measurements to this file
+        #[arg(long)]
+        cim: Option<PathBuf>,
+    },
+    // Write a synthetic dataset of a JSON dataset configuration, without a server
+    Generate {
+        config: PathBuf,
+        out: PathBuf,
+    },
+    // Print frames of a capture (binary or hex text) as annotated hex dumps
+    Dump {
+        file: PathBuf,
+        // Configuration frame of the stream, when the capture has none
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+    // Guess the frame format, frame rate and streams of a capture of unknown origin
+    Detect {
+        file: PathBuf,
+    },
+    // Export captures (recorder files, .pdat archives, binary or hex text) as
+    // one time-aligned matrix for MATLAB/Octave: CSV, or .mat with the mat
+    // feature. With the hdf5 feature a .h5 file holds each stream as recorded
+    // instead.
+    Export {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        #[arg(long)]
+        out: PathBuf,
+        // Rows per second, the highest rate of the streams by default
+        #[arg(long)]
+        rate: Option<f64>,
+        // Configuration frame of the stream, when the captures have none
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+    // Evaluate UFLS relay settings (JSON) against the frequency of captures,
+    // listing the stages that would have tripped and when
+    Ufls {
+        settings: PathBuf,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        // Configuration frame of the stream, when the captures have none
+        #[arg(long)]
+        config: Option<PathBuf>,
+        // PMU of the stream whose frequency is used
+        #[arg(long, default_value_t = 0)]
+        pmu: usize,
+    },
+    // Read an openPDC/openHistorian CSV export, one stream per device, or a
+    // .pdat/.dst archive of C37.118 frames into the files of a sink
+    Import {
+        file: PathBuf,
+        out: PathBuf,
+        // parquet, csv, json or sqlite
+        #[arg(long, default_value = "parquet", value_parser = parse_format)]
+        format: SinkFormat,
+        // CSV export of the ActiveMeasurements table, to map measurement keys
+        // and point tags
+        #[arg(long)]
+        measurements: Option<PathBuf>,
+    },
+    // Live terminal view of PDC streams, each [name=]host:port/idcode.
+    // Client logs go to stdout, redirect it to keep the screen clean.
+    #[cfg(feature = "tui")]
+    Monitor {
+        #[arg(required = true)]
+        streams: Vec<String>,
+    },
+    // Annotate streams and time ranges in an annotation store (JSON file)
+    Annotate {
+        store: PathBuf,
+        #[command(subcommand)]
+        action: AnnotateAction,
+    },
+    // Regression fixtures in the format of tests/test_data
+    Fixture {
+        #[command(subcommand)]
+        action: FixtureAction,
+    },
+    // Run the pipeline against a simulated stream for a long time, tracking
+    // memory, file descriptors and latency, and check their growth at the end
+    Soak {
+        // Directory the sink writes to
+        out: PathBuf,
+        // JSON soak configuration: duration, bounds, scenario
+        #[arg(long)]
+        config: Option<PathBuf>,
+        // Overrides the configured duration
+        #[arg(long)]
+        duration_secs: Option<f64>,
+        #[arg(long)]
+        port: Option<u16>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum FixtureAction {
+    // Record the configuration and a few data frames of a live device,
+    // anonymized, as <name>_config.bin and <name>_data.bin
+    Capture {
+        host: String,
+        port: u16,
+        #[arg(default_value_t = 1)]
+        idcode: u16,
+        #[arg(long, default_value_t = 5)]
+        frames: usize,
+        #[arg(long, default_value = "tests/test_data")]
+        out: PathBuf,
+        #[arg(long, default_value = "device")]
+        name: String,
+        // Seconds to wait for the frames
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+        // Keep the idcodes, names and timestamps of the device
+        #[arg(long)]
+        keep_identity: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum AnnotateAction {
+    // Add an annotation, times in RFC 3339 (2024-01-01T06:00:00Z)
+    Add {
+        #[arg(value_parser = parse_kind)]
+        kind: AnnotationKind,
+        start: String,
+        end: String,
+        #[arg(long)]
+        stream: Option<u16>,
+        // Station or channel, the whole stream when not given
+        #[arg(long)]
+        source: Option<String>,
+        #[arg(long, default_value = "")]
+        note: String,
+    },
+    List,
+    Remove {
+        id: u64,
+    },
+}
+
+fn parse_kind(kind: &str) -> Result<AnnotationKind, String> {
+    serde_json::from_value(serde_json::Value::String(kind.to_string()))
+        .map_err(|_| "Expected maintenance, known_bad or note".to_string())
+}
+
+fn parse_format(format: &str) -> Result<SinkFormat, String> {
+    serde_json::from_value(serde_json::Value::String(format.to_string()))
+        .map_err(|_| "Expected parquet, csv, json or sqlite".to_string())
+}
+
+// Bytes of a capture file, hex text (whitespace ignored) or binary.
+fn read_capture(path: &PathBuf) -> io::Result<Vec<u8>> {
+    let bytes = std::fs::read(path)?;
+    let hex: Vec<u8> = bytes
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return Ok(bytes);
+    }
+    Ok(hex
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect())
+}
+
+// Frames of a capture: a recorder file, a .pdat/.dst archive (pdat), or frames
+// back to back.
+fn capture_frames(path: &PathBuf) -> io::Result<Vec<Vec<u8>>> {
+    let bytes = read_capture(path)?;
+    if bytes.starts_with(CAPTURE_MAGIC) {
+        return CaptureReader::open(path)?
+            .records()
+            .map(|record| record.map(|record| record.frame))
+            .collect();
+    }
+    if path
+        .extension()
+        .is_some_and(|ext| ext == "pdat" || ext == "dst")
+    {
+        let (frames, _) = pdat::scan_frames(&bytes);
+        return Ok(frames.into_iter().map(<[u8]>::to_vec).collect());
+    }
+    let mut frames = Vec::new();
+    let mut rest = &bytes[..];
+    while rest.len() >= 4 {
+        let size = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+        if rest[0] != 0xAA || size < 4 || size > rest.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: no frame at byte {}",
+                    path.display(),
+                    bytes.len() - rest.len()
+                ),
+            ));
+        }
+        frames.push(rest[..size].to_vec());
+        rest = &rest[size..];
+    }
+    Ok(frames)
+}
+
+// Write the frames of the captures to an HDF5 file, stream by stream.
+#[cfg(feature = "hdf5")]
+fn export_hdf5(files: &[PathBuf], config: Option<PathBuf>, out: &PathBuf) -> io::Result<()> {
+    use pmu::accumulator::BatchAccumulator;
+    use pmu::budget::MemoryBudget;
+    use pmu::sinks::hdf5::Hdf5Sink;
+    use pmu::sinks::BatchSink;
+
+    fn invalid<E: std::fmt::Debug>(e: E) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))
+    }
+    let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited());
+    let mut sink = Hdf5Sink::new(out)?;
+    let mut frames = Vec::new();
+    if let Some(path) = config {
+        frames.push(read_capture(&path)?);
+    }
+    for file in files {
+        frames.extend(capture_frames(file)?);
+    }
+    for frame in frames.iter().filter(|frame| frame.len() >= 4) {
+        match (frame[1] >> 4) & 0x07 {
+            2 | 3 => {
+                let config = parse_config_frame_1and2(frame).map_err(invalid)?;
+                if let Some(batch) = accumulator
+                    .remove_stream(config.prefix.idcode)
+                    .map_err(invalid)?
+                {
+                    sink.write_batch(&batch)?;
+                }
+                accumulator.add_stream(&config);
+            }
+            0 => {
+                if let Some((_, batch)) = accumulator.push_frame(frame).map_err(invalid)? {
+                    sink.write_batch(&batch)?;
+                }
+            }
+            _ => {}
+        }
+    }
+    for (_, batch) in accumulator.flush_all().map_err(invalid)? {
+        sink.write_batch(&batch)?;
+    }
+    sink.close()
+}
+
+fn parse_time(time: &str) -> io::Result<i64> {
+    chrono::DateTime::parse_from_rfc3339(time)
+        .map(|time| time.timestamp_micros())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", time, e)))
 }
 
 #[tokio::main]
@@ -45,9 +377,80 @@ async fn main() -> io::Result<()> {
     let args = Cli::parse();
 
     match args.command {
-        Commands::Server { ip, port } => {
+        Commands::Server {
+            ip,
+            port,
+            config,
+            control,
+            read_only,
+            audit_log,
+            replay_window_ms,
+            scenario,
+            speed,
+            looping,
+            idcodes,
+            output_rate,
+            virtual_pmus,
+        } => {
             println!("Using {ip} and port {port}");
-            let server_config = ServerConfig::new(ip, port, Protocol::TCP, 30.0).unwrap();
+            let mut policy = match config {
+                Some(path) => {
+                    let config = PipelineConfig::from_file(&path)
+                        .expect("Failed to read pipeline configuration");
+                    config.server.unwrap_or_default().permissions
+                }
+                None if !control.is_empty() || !read_only.is_empty() => {
+                    CommandPolicy::new(ClientPermission::Denied)
+                }
+                None => CommandPolicy::default(),
+            };
+            for client in read_only {
+                policy = policy.with_client(client, ClientPermission::ReadOnly);
+            }
+            for client in control {
+                policy = policy.with_client(client, ClientPermission::Control);
+            }
+            let mut server_config = ServerConfig::new(ip, port, Protocol::TCP, 30.0)
+                .unwrap()
+                .with_policy(policy);
+            if let Some(path) = audit_log {
+                let audit = AuditLog::open(&path).expect("Failed to open audit log");
+                server_config = server_config.with_audit(Arc::new(audit));
+            }
+            if let Some(window) = replay_window_ms {
+                let guard =
+                    CommandReplayGuard::new(Duration::from_millis(window), ReplayAction::Reject);
+                server_config = server_config.with_replay_guard(Arc::new(guard));
+            }
+            if let Some(path) = scenario {
+                let mut scenario =
+                    Scenario::from_file(&path).expect("Failed to read scenario file");
+                let range = idcodes::parse_range(&idcodes).expect("Invalid --idcodes");
+                if let Some(layout) = scenario.stream.as_mut() {
+                    if let Err(e) = idcodes::assign_layouts(std::slice::from_mut(layout), range) {
+                        panic!("Invalid scenario: {}", e);
+                    }
+                    println!(
+                        "Simulating stream {} ({} PMUs)",
+                        layout.idcode,
+                        layout.pmus.len()
+                    );
+                }
+                server_config = server_config.with_scenario(scenario);
+            }
+            if let Some(rate) = output_rate {
+                server_config = server_config.with_output_rate(rate);
+            }
+            if let Some(path) = virtual_pmus {
+                let virtual_stream =
+                    VirtualStreamConfig::from_file(&path).expect("Failed to read virtual PMUs");
+                server_config = server_config.with_virtual_stream(virtual_stream);
+            }
+            server_config = server_config.with_playback(
+                PlaybackOptions::default()
+                    .with_speed(speed)
+                    .with_looping(looping),
+            );
 
             run_mock_server(server_config)
                 .await
@@ -61,9 +464,9 @@ async fn main() -> io::Result<()> {
         } => {
             // Start the pdc buffer server
             std::env::set_var("PDC_HOST", &ip);
-            std::env::set_var("PDC_PORT", &pdc_port.to_string());
-            std::env::set_var("SERVER_PORT", &http_port.to_string());
-            std::env::set_var("BUFFER_DURATION_SECS", &duration.to_string());
+            std::env::set_var("PDC_PORT", pdc_port.to_string());
+            std::env::set_var("SERVER_PORT", http_port.to_string());
+            std::env::set_var("BUFFER_DURATION_SECS", duration.to_string());
 
             let buffer_server_handle = tokio::spawn(async move {
                 if let Err(e) = pdc_buffer_server::run().await {
@@ -77,6 +480,306 @@ async fn main() -> io::Result<()> {
             println!("Shutting down...");
             buffer_server_handle.abort();
         }
+        Commands::Pipeline {
+            config,
+            control_port,
+        } => {
+            let config =
+                PipelineConfig::from_file(&config).expect("Failed to read pipeline config");
+            let pipeline = Pipeline::new(config);
+            if let Some(port) = control_port {
+                let control = pipeline.ingest_control();
+                tokio::spawn(async move {
+                    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+                    if let Err(e) = ingest::serve(control, addr).await {
+                        println!("Ingest control failed: {}", e);
+                    }
+                });
+            }
+            let run = pipeline.run();
+            tokio::pin!(run);
+            let stats = tokio::select! {
+                stats = &mut run => stats,
+                _ = tokio::signal::ctrl_c() => {
+                    println!("Shutting down...");
+                    pipeline.stop();
+                    run.await
+                }
+            }?;
+            for (shard, stats) in stats.iter().enumerate() {
+                println!(
+                    "Shard {}: {} streams, {} frames, {} rows in {} batches, {} errors",
+                    shard, stats.streams, stats.frames, stats.rows, stats.batches, stats.errors
+                );
+            }
+            if let Some(tracer) = pipeline.tracer() {
+                print!("{}", tracer.summary());
+            }
+        }
+        Commands::Validate { config, cim } => {
+            let config =
+                PipelineConfig::from_file(&config).expect("Failed to read pipeline config");
+            let validation = Pipeline::validate(&config).await;
+            validation.print();
+            if let Some(path) = cim {
+                let topology = match &config.topology {
+                    Some(topology) => Topology::from_file(topology)?,
+                    None => Topology::default(),
+                };
+                cim::write_catalog(&path, &validation.streams, &topology)?;
+                println!(
+                    "Wrote {} channels as CIM measurements to {}",
+                    validation.channels(),
+                    path.display()
+                );
+            }
+            if !validation.is_ok() {
+                return Err(io::Error::other("Invalid pipeline configuration"));
+            }
+        }
+        Commands::Generate { config, out } => {
+            let config = DatasetConfig::from_file(&config).expect("Failed to read dataset config");
+            let stats = dataset::write(&config, &out)?;
+            println!(
+                "{} frames, {} rows in {} batches, {} rejected, {} labels written to {}",
+                stats.frames,
+                stats.rows,
+                stats.batches,
+                stats.rejected,
+                config.labels().len(),
+                out.display()
+            );
+        }
+        Commands::Dump { file, config } => {
+            let config = match config {
+                Some(path) => {
+                    let bytes = read_capture(&path)?;
+                    Some(parse_config_frame_1and2(&bytes).map_err(|e| {
+                        io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))
+                    })?)
+                }
+                None => None,
+            };
+            print!("{}", dump::dump_all(&read_capture(&file)?, config));
+        }
+        Commands::Detect { file } => {
+            print!("{}", detect::detect(&std::fs::read(&file)?));
+        }
+        Commands::Export {
+            files,
+            out,
+            rate,
+            config,
+        } => {
+            if out
+                .extension()
+                .is_some_and(|ext| ext == "h5" || ext == "hdf5")
+            {
+                #[cfg(feature = "hdf5")]
+                {
+                    return export_hdf5(&files, config, &out);
+                }
+                #[cfg(not(feature = "hdf5"))]
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "HDF5 output needs the hdf5 feature",
+                    ));
+                }
+            }
+            let mut export = MatrixExport::new();
+            if let Some(rate) = rate {
+                export = export.with_rate(rate);
+            }
+            if let Some(path) = config {
+                export.push_frame(&read_capture(&path)?)?;
+            }
+            for file in &files {
+                for frame in capture_frames(file)? {
+                    export.push_frame(&frame)?;
+                }
+            }
+            let stats = if out.extension().is_some_and(|ext| ext == "mat") {
+                #[cfg(feature = "mat")]
+                {
+                    export.write_mat(&out)?
+                }
+                #[cfg(not(feature = "mat"))]
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        ".mat output needs the mat feature",
+                    ));
+                }
+            } else {
+                export.write_csv(&out)?
+            };
+            println!(
+                "{} rows of {} channels ({} gaps) written to {}, channels in {}",
+                stats.rows,
+                stats.columns,
+                stats.gaps,
+                out.display(),
+                matrix::sidecar_path(&out).display()
+            );
+        }
+        Commands::Ufls {
+            settings,
+            files,
+            config,
+            pmu,
+        } => {
+            let settings = UflsConfig::from_file(&settings)?;
+            let config =
+                match config {
+                    Some(path) => Some(parse_config_frame_1and2(&read_capture(&path)?).map_err(
+                        |e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)),
+                    )?),
+                    None => None,
+                };
+            let mut frames = Vec::new();
+            for file in &files {
+                frames.extend(capture_frames(file)?);
+            }
+            print!(
+                "{}",
+                ufls::evaluate_frames(settings, config, &frames, pmu)?.render()
+            );
+        }
+        Commands::Import {
+            file,
+            out,
+            format,
+            measurements,
+        } => {
+            let config = SinkConfig {
+                format,
+                dir: out.clone(),
+                rate_hz: None,
+                decimation_ms: None,
+                spool: None,
+                parquet: None,
+                names: Default::default(),
+            };
+            let mut sinks = HashMap::new();
+            let write = |idcode, batch: RecordBatch| {
+                let sink = match sinks.entry(idcode) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(config.open(idcode)?),
+                };
+                sink.write_batch(&batch)
+            };
+            let archive = file
+                .extension()
+                .is_some_and(|ext| ext == "pdat" || ext == "dst");
+            let (rows, skipped) = if archive {
+                let stats = PdatReader::new().read(&std::fs::read(&file)?, write)?;
+                (stats.rows, format!("{} bytes skipped", stats.skipped_bytes))
+            } else {
+                let mut reader = OpenPdcReader::new();
+                if let Some(path) = measurements {
+                    reader = reader.with_measurements(MeasurementMap::from_file(path)?);
+                }
+                let file = std::io::BufReader::new(std::fs::File::open(&file)?);
+                let stats = reader.read(file, write)?;
+                let skipped = stats.skipped_columns.len();
+                (stats.rows, format!("{} columns skipped", skipped))
+            };
+            for sink in sinks.values_mut() {
+                sink.close()?;
+            }
+            println!(
+                "{} rows of {} streams written to {}, {}",
+                rows,
+                sinks.len(),
+                out.display(),
+                skipped
+            );
+        }
+        #[cfg(feature = "tui")]
+        Commands::Monitor { streams } => {
+            let targets = streams
+                .iter()
+                .map(|stream| pmu::tui::StreamTarget::parse(stream))
+                .collect::<io::Result<Vec<_>>>()?;
+            pmu::tui::run(targets).await?;
+        }
+        Commands::Fixture {
+            action:
+                FixtureAction::Capture {
+                    host,
+                    port,
+                    idcode,
+                    frames,
+                    out,
+                    name,
+                    timeout,
+                    keep_identity,
+                },
+        } => {
+            let mut captured =
+                fixture::capture(&host, port, idcode, frames, Duration::from_secs(timeout)).await?;
+            if !keep_identity {
+                captured = captured.anonymize()?;
+            }
+            for path in captured.write(&out, &name)? {
+                println!("Wrote {}", path.display());
+            }
+        }
+        Commands::Soak {
+            out,
+            config,
+            duration_secs,
+            port,
+        } => {
+            let mut config = match config {
+                Some(path) => SoakConfig::from_file(&path)?,
+                None => SoakConfig::default(),
+            };
+            if let Some(duration) = duration_secs {
+                config.duration_secs = duration;
+            }
+            if let Some(port) = port {
+                config.port = port;
+            }
+            std::fs::create_dir_all(&out)?;
+            let report = soak::run_soak(&config, &out).await?;
+            report.print();
+            if !report.is_ok() {
+                return Err(io::Error::other("Soak test bounds exceeded"));
+            }
+        }
+        Commands::Annotate { store, action } => {
+            let mut store = AnnotationStore::open(&store)?;
+            match action {
+                AnnotateAction::Add {
+                    kind,
+                    start,
+                    end,
+                    stream,
+                    source,
+                    note,
+                } => {
+                    let mut annotation =
+                        Annotation::new(kind, parse_time(&start)?, parse_time(&end)?)
+                            .with_note(&note);
+                    annotation.stream = stream;
+                    annotation.source = source;
+                    let id = store.add(annotation)?;
+                    println!("Added annotation {}", id);
+                }
+                AnnotateAction::List => {
+                    for annotation in store.annotations() {
+                        println!("{}", serde_json::to_string(annotation)?);
+                    }
+                }
+                AnnotateAction::Remove { id } => {
+                    if !store.remove(id)? {
+                        println!("No annotation {}", id);
+                    }
+                }
+            }
+        }
     }
     Ok(())
 }
@@ -0,0 +1,644 @@
+// Time-aligned matrix export for MATLAB/Octave.
+//
+// A MatrixExport collects the batches of one or more streams and writes them
+// as a single wide CSV on a uniform time grid: one row per grid point, one
+// column per channel, NaN where a channel has no sample within half a period
+// of the grid point. The first two columns are the time since the first row
+// and the POSIX time, both in seconds:
+//
+//   time_s,posix_s,Station_A_7734_FREQ,Station_A_7734_VA_X,...
+//
+// which readmatrix/readtable (MATLAB) and csvread/dlmread (Octave) load as is;
+// datetime(posix_s, 'ConvertFrom', 'posixtime') gives the timestamps.
+//
+// Values are in engineering units. Phasors are written as their two
+// components, as received. Column headers are valid MATLAB identifiers; the
+// channel of every column with its station, idcode, kind and unit is written
+// to a <stem>_channels.csv sidecar. Decoded STAT flags and the quality column
+// are left out, the raw STAT is kept.
+//
+// The grid rate defaults to the highest reporting rate of the configuration
+// frames seen. With the mat feature the same matrix can be written as a
+// MATLAB v5 .mat file instead (write_mat), or as an ASCII COMTRADE record
+// (IEEE C37.111-1999, write_comtrade) for fault recorder tools.
+//
+// Samples are held in memory until written, so this is for exports of
+// recorded periods rather than continuous collection.
+use crate::accumulator::BatchAccumulator;
+use crate::arrow_utils::{
+    META_CHANNEL, META_COMPONENT, META_IDCODE, META_KIND, META_NOMINAL_FREQUENCY, META_OFFSET,
+    META_SCALE, META_STATION, META_UNIT, QUALITY_COLUMN,
+};
+use crate::budget::MemoryBudget;
+use crate::frame_parser::parse_config_frame_1and2;
+use arrow::array::{Array, Float64Array, TimestampMicrosecondArray};
+use arrow::compute::cast;
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use chrono::DateTime;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Longest MATLAB variable name.
+const MAX_VARIABLE_LEN: usize = 63;
+
+// Largest COMTRADE ASCII sample, 99999 marks a missing one.
+const COMTRADE_RANGE: f64 = 99998.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixColumn {
+    pub name: String,     // Column of the batches
+    pub variable: String, // Header, a MATLAB identifier
+    pub channel: String,
+    pub station: String,
+    pub idcode: String,
+    pub kind: String,
+    pub unit: String,
+    pub component: String,
+    samples: Vec<(i64, f64)>,
+}
+
+impl MatrixColumn {
+    pub fn samples(&self) -> usize {
+        self.samples.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatrixStats {
+    pub rows: usize,
+    pub columns: usize, // Channels, without the time columns
+    pub gaps: usize,    // NaN values
+}
+
+// The matrix on its grid, for writing.
+pub struct Matrix {
+    pub times_us: Vec<i64>,
+    pub columns: Vec<Vec<f64>>, // One per channel, as long as times_us
+}
+
+pub struct MatrixExport {
+    rate_hz: Option<f64>,
+    config_rate_hz: f64, // Highest rate of the configurations seen
+    nominal_hz: Option<f64>,
+    columns: Vec<MatrixColumn>,
+    index: HashMap<String, usize>,
+    accumulator: BatchAccumulator,
+}
+
+impl Default for MatrixExport {
+    fn default() -> Self {
+        MatrixExport::new()
+    }
+}
+
+impl MatrixExport {
+    pub fn new() -> Self {
+        MatrixExport {
+            rate_hz: None,
+            config_rate_hz: 0.0,
+            nominal_hz: None,
+            columns: Vec::new(),
+            index: HashMap::new(),
+            accumulator: BatchAccumulator::new(MemoryBudget::unlimited()),
+        }
+    }
+
+    // Rows of the grid per second, instead of the rate of the streams.
+    pub fn with_rate(mut self, rate_hz: f64) -> Self {
+        self.rate_hz = Some(rate_hz).filter(|rate| *rate > 0.0);
+        self
+    }
+
+    pub fn rate_hz(&self) -> Option<f64> {
+        self.rate_hz
+            .or(Some(self.config_rate_hz).filter(|rate| *rate > 0.0))
+    }
+
+    // Columns in the order written.
+    pub fn columns(&self) -> Vec<&MatrixColumn> {
+        let mut columns: Vec<&MatrixColumn> = self.columns.iter().collect();
+        columns.sort_by(|a, b| a.name.cmp(&b.name));
+        columns
+    }
+
+    // Take a raw frame: configuration frames register their stream, data
+    // frames are accumulated into batches.
+    pub fn push_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        if frame.len() < 4 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Truncated frame",
+            ));
+        }
+        match (frame[1] >> 4) & 0x07 {
+            2 | 3 => {
+                let config = parse_config_frame_1and2(frame)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+                // DATA_RATE is frames per second, or seconds per frame when negative
+                let rate = match config.data_rate {
+                    rate if rate > 0 => rate as f64,
+                    rate if rate < 0 => -1.0 / rate as f64,
+                    _ => 0.0,
+                };
+                self.config_rate_hz = self.config_rate_hz.max(rate);
+                if let Some(batch) = self
+                    .accumulator
+                    .remove_stream(config.prefix.idcode)
+                    .ok()
+                    .flatten()
+                {
+                    self.add_batch(&batch)?;
+                }
+                self.accumulator.add_stream(&config);
+            }
+            0 => {
+                let batch = self
+                    .accumulator
+                    .push_frame(frame)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+                if let Some((_, batch)) = batch {
+                    self.add_batch(&batch)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Take a wide batch with a timestamp column.
+    pub fn add_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let timestamps = batch
+            .column_by_name("timestamp")
+            .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Batch without timestamp column")
+            })?;
+        let schema = batch.schema();
+        for (field, column) in schema.fields().iter().zip(batch.columns()) {
+            if field.name() == "timestamp"
+                || field.name() == QUALITY_COLUMN
+                || *field.data_type() == DataType::Boolean
+            {
+                continue;
+            }
+            let Ok(values) = cast(column, &DataType::Float64) else {
+                continue; // Complex phasor columns
+            };
+            let Some(values) = values.as_any().downcast_ref::<Float64Array>() else {
+                continue;
+            };
+            let meta = field.metadata();
+            let text = |key: &str| meta.get(key).cloned().unwrap_or_default();
+            let number = |key: &str, default: f64| {
+                meta.get(key)
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .unwrap_or(default)
+            };
+            let (scale, offset) = (number(META_SCALE, 1.0), number(META_OFFSET, 0.0));
+            if self.nominal_hz.is_none() {
+                self.nominal_hz = meta
+                    .get(META_NOMINAL_FREQUENCY)
+                    .and_then(|v| v.parse::<f64>().ok());
+            }
+            let index = match self.index.get(field.name()) {
+                Some(index) => *index,
+                None => {
+                    self.columns.push(MatrixColumn {
+                        name: field.name().clone(),
+                        variable: String::new(),
+                        channel: meta
+                            .get(META_CHANNEL)
+                            .cloned()
+                            .unwrap_or_else(|| field.name().clone()),
+                        station: text(META_STATION),
+                        idcode: text(META_IDCODE),
+                        kind: text(META_KIND),
+                        unit: text(META_UNIT),
+                        component: text(META_COMPONENT),
+                        samples: Vec::new(),
+                    });
+                    self.index
+                        .insert(field.name().clone(), self.columns.len() - 1);
+                    self.columns.len() - 1
+                }
+            };
+            let samples = &mut self.columns[index].samples;
+            for row in 0..batch.num_rows() {
+                if timestamps.is_valid(row) && values.is_valid(row) {
+                    samples.push((timestamps.value(row), values.value(row) * scale + offset));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The samples on the grid, with the rows still accumulated.
+    pub fn matrix(&mut self) -> io::Result<Matrix> {
+        let batches = self
+            .accumulator
+            .flush_all()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+        for (_, batch) in batches {
+            self.add_batch(&batch)?;
+        }
+        let rate_hz = self.rate_hz().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No grid rate: no configuration frame and no rate given",
+            )
+        })?;
+        assign_variables(&mut self.columns);
+        for column in self.columns.iter_mut() {
+            if !column.samples.is_sorted_by_key(|(timestamp, _)| *timestamp) {
+                column.samples.sort_by_key(|(timestamp, _)| *timestamp);
+            }
+        }
+
+        let bounds = self
+            .columns
+            .iter()
+            .filter_map(|column| Some((column.samples.first()?.0, column.samples.last()?.0)));
+        let (Some(start), Some(end)) = (
+            bounds.clone().map(|(first, _)| first).min(),
+            bounds.map(|(_, last)| last).max(),
+        ) else {
+            return Ok(Matrix {
+                times_us: Vec::new(),
+                columns: vec![Vec::new(); self.columns.len()],
+            });
+        };
+        let period_us = 1e6 / rate_hz;
+        let times_us: Vec<i64> = (0..)
+            .map(|k| start + (k as f64 * period_us).round() as i64)
+            .take_while(|t| *t <= end)
+            .collect();
+
+        // The nearest sample within half a period of each grid point
+        let tolerance_us = period_us / 2.0;
+        let columns = self
+            .columns()
+            .iter()
+            .map(|column| {
+                let samples = &column.samples;
+                let mut next = 0;
+                times_us
+                    .iter()
+                    .map(|t| {
+                        while next < samples.len()
+                            && (samples[next].0 as f64) < *t as f64 - tolerance_us
+                        {
+                            next += 1;
+                        }
+                        let candidates = samples[next..].iter().take_while(|(timestamp, _)| {
+                            (*timestamp as f64) <= *t as f64 + tolerance_us
+                        });
+                        candidates
+                            .min_by_key(|(timestamp, _)| (timestamp - t).abs())
+                            .map_or(f64::NAN, |(_, value)| *value)
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(Matrix { times_us, columns })
+    }
+
+    // Write the CSV matrix and its <stem>_channels.csv sidecar.
+    pub fn write_csv(&mut self, path: impl AsRef<Path>) -> io::Result<MatrixStats> {
+        let path = path.as_ref();
+        let matrix = self.matrix()?;
+        let columns = self.columns();
+        let mut out = BufWriter::new(File::create(path)?);
+        let mut header = vec!["time_s".to_string(), "posix_s".to_string()];
+        header.extend(columns.iter().map(|column| column.variable.clone()));
+        writeln!(out, "{}", header.join(","))?;
+        let start = matrix.times_us.first().copied().unwrap_or(0);
+        let mut line = String::new();
+        for (row, t) in matrix.times_us.iter().enumerate() {
+            line.clear();
+            line.push_str(&format!(
+                "{:.6},{:.6}",
+                (t - start) as f64 / 1e6,
+                *t as f64 / 1e6
+            ));
+            for column in &matrix.columns {
+                line.push(',');
+                line.push_str(&number(column[row]));
+            }
+            writeln!(out, "{}", line)?;
+        }
+        out.flush()?;
+        write_sidecar(path, &columns, &matrix)?;
+        Ok(stats(&matrix))
+    }
+
+    // Write time_s and posix_s (rows x 1), data (rows x channels) and the
+    // variables, channels and units (1 x channels cells) to a MATLAB v5 file,
+    // and the sidecar next to it.
+    #[cfg(feature = "mat")]
+    pub fn write_mat(&mut self, path: impl AsRef<Path>) -> io::Result<MatrixStats> {
+        let path = path.as_ref();
+        let matrix = self.matrix()?;
+        let columns = self.columns();
+        let start = matrix.times_us.first().copied().unwrap_or(0);
+        let rows = matrix.times_us.len();
+        let mut out = BufWriter::new(File::create(path)?);
+        mat::write_header(&mut out)?;
+        let time_s: Vec<f64> = matrix
+            .times_us
+            .iter()
+            .map(|t| (t - start) as f64 / 1e6)
+            .collect();
+        let posix_s: Vec<f64> = matrix.times_us.iter().map(|t| *t as f64 / 1e6).collect();
+        mat::write_doubles(&mut out, "time_s", rows, 1, &time_s)?;
+        mat::write_doubles(&mut out, "posix_s", rows, 1, &posix_s)?;
+        // Column major, as MATLAB holds it
+        let data: Vec<f64> = matrix.columns.concat();
+        mat::write_doubles(&mut out, "data", rows, columns.len(), &data)?;
+        let texts = |f: fn(&MatrixColumn) -> &str| -> Vec<String> {
+            columns.iter().map(|column| f(column).to_string()).collect()
+        };
+        mat::write_strings(&mut out, "variables", &texts(|c| &c.variable))?;
+        mat::write_strings(&mut out, "channels", &texts(|c| &c.channel))?;
+        mat::write_strings(&mut out, "units", &texts(|c| &c.unit))?;
+        out.flush()?;
+        write_sidecar(path, &columns, &matrix)?;
+        Ok(stats(&matrix))
+    }
+
+    // Write an ASCII COMTRADE record: <stem>.cfg and <stem>.dat next to
+    // path. Samples are integers scaled per channel to the channel's range;
+    // gaps are 99999. The trigger time defaults to the first row.
+    pub fn write_comtrade(
+        &mut self,
+        path: impl AsRef<Path>,
+        station: &str,
+        trigger_us: Option<i64>,
+    ) -> io::Result<MatrixStats> {
+        let path = path.as_ref();
+        let matrix = self.matrix()?;
+        let rate_hz = self.rate_hz().unwrap_or_default();
+        let columns = self.columns();
+        let start = matrix.times_us.first().copied().unwrap_or(0);
+        // No commas in the fields
+        let field = |text: &str| text.replace(',', " ");
+        let time = |timestamp_us: i64| {
+            DateTime::from_timestamp_micros(timestamp_us)
+                .unwrap_or_default()
+                .format("%d/%m/%Y,%H:%M:%S%.6f")
+                .to_string()
+        };
+        // Value = a * sample + b, the range of the channel on +-COMTRADE_RANGE
+        let scales: Vec<(f64, f64)> = matrix
+            .columns
+            .iter()
+            .map(|values| {
+                let finite = values.iter().filter(|v| v.is_finite());
+                let min = finite.clone().fold(f64::INFINITY, |a, b| a.min(*b));
+                let max = finite.fold(f64::NEG_INFINITY, |a, b| a.max(*b));
+                if min < max {
+                    ((max - min) / (2.0 * COMTRADE_RANGE), (max + min) / 2.0)
+                } else if min.is_finite() {
+                    (1.0, min)
+                } else {
+                    (1.0, 0.0)
+                }
+            })
+            .collect();
+
+        let mut cfg = BufWriter::new(File::create(path.with_extension("cfg"))?);
+        writeln!(cfg, "{},pmu,1999", field(station))?;
+        writeln!(cfg, "{},{}A,0D", columns.len(), columns.len())?;
+        for (n, (column, (a, b))) in columns.iter().zip(&scales).enumerate() {
+            writeln!(
+                cfg,
+                "{},{},{},{},{},{},{},0,{},{},1,1,P",
+                n + 1,
+                column.variable,
+                column.component,
+                field(&column.station),
+                field(&column.unit),
+                a,
+                b,
+                -COMTRADE_RANGE,
+                COMTRADE_RANGE
+            )?;
+        }
+        writeln!(cfg, "{}", self.nominal_hz.unwrap_or(60.0))?;
+        writeln!(cfg, "1")?;
+        writeln!(cfg, "{},{}", rate_hz, matrix.times_us.len())?;
+        writeln!(cfg, "{}", time(start))?;
+        writeln!(cfg, "{}", time(trigger_us.unwrap_or(start)))?;
+        writeln!(cfg, "ASCII")?;
+        writeln!(cfg, "1")?;
+        cfg.flush()?;
+
+        let mut dat = BufWriter::new(File::create(path.with_extension("dat"))?);
+        let mut line = String::new();
+        for (row, t) in matrix.times_us.iter().enumerate() {
+            line.clear();
+            line.push_str(&format!("{},{}", row + 1, t - start));
+            for (values, (a, b)) in matrix.columns.iter().zip(&scales) {
+                let value = values[row];
+                let sample = if value.is_finite() {
+                    ((value - b) / a)
+                        .round()
+                        .clamp(-COMTRADE_RANGE, COMTRADE_RANGE) as i64
+                } else {
+                    99999
+                };
+                line.push_str(&format!(",{}", sample));
+            }
+            writeln!(dat, "{}", line)?;
+        }
+        dat.flush()?;
+        Ok(stats(&matrix))
+    }
+}
+
+fn stats(matrix: &Matrix) -> MatrixStats {
+    MatrixStats {
+        rows: matrix.times_us.len(),
+        columns: matrix.columns.len(),
+        gaps: matrix
+            .columns
+            .iter()
+            .map(|column| column.iter().filter(|v| v.is_nan()).count())
+            .sum(),
+    }
+}
+
+// As MATLAB reads them back.
+fn number(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+// Unique MATLAB identifiers for the column names, in the written order.
+fn assign_variables(columns: &mut [MatrixColumn]) {
+    let mut order: Vec<usize> = (0..columns.len()).collect();
+    order.sort_by(|a, b| columns[*a].name.cmp(&columns[*b].name));
+    let mut taken: HashSet<String> = ["time_s", "posix_s"].map(String::from).into();
+    for index in order {
+        let mut base: String = columns[index]
+            .name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        if !base.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            base.insert(0, 'x');
+        }
+        base.truncate(MAX_VARIABLE_LEN);
+        let mut variable = base.clone();
+        let mut n = 2;
+        while taken.contains(&variable) {
+            let suffix = format!("_{}", n);
+            let mut stem = base.clone();
+            stem.truncate(MAX_VARIABLE_LEN - suffix.len());
+            variable = stem + &suffix;
+            n += 1;
+        }
+        taken.insert(variable.clone());
+        columns[index].variable = variable;
+    }
+}
+
+// <stem>_channels.csv next to the matrix.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}_channels.csv", stem))
+}
+
+fn write_sidecar(path: &Path, columns: &[&MatrixColumn], matrix: &Matrix) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(sidecar_path(path))?);
+    writeln!(
+        out,
+        "column,variable,channel,station,idcode,kind,unit,component,samples,gaps"
+    )?;
+    // Quoted as CSV, names may hold commas
+    let quote = |text: &str| {
+        if text.contains([',', '"']) {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        } else {
+            text.to_string()
+        }
+    };
+    for (n, (column, values)) in columns.iter().zip(&matrix.columns).enumerate() {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{}",
+            n + 3, // 1-based, after time_s and posix_s
+            column.variable,
+            quote(&column.channel),
+            quote(&column.station),
+            column.idcode,
+            column.kind,
+            quote(&column.unit),
+            column.component,
+            column.samples.len(),
+            values.iter().filter(|v| v.is_nan()).count()
+        )?;
+    }
+    out.flush()
+}
+
+// Level 5 MAT-file writing, little endian, uncompressed.
+#[cfg(feature = "mat")]
+mod mat {
+    use std::io::{self, Write};
+
+    const MI_INT8: u32 = 1;
+    const MI_UINT16: u32 = 4;
+    const MI_INT32: u32 = 5;
+    const MI_UINT32: u32 = 6;
+    const MI_DOUBLE: u32 = 9;
+    const MI_MATRIX: u32 = 14;
+    const MX_CELL_CLASS: u32 = 1;
+    const MX_CHAR_CLASS: u32 = 4;
+    const MX_DOUBLE_CLASS: u32 = 6;
+
+    pub fn write_header(out: &mut impl Write) -> io::Result<()> {
+        let mut text = format!(
+            "MATLAB 5.0 MAT-file, Platform: {}, Created by: pmu {}",
+            std::env::consts::OS,
+            env!("CARGO_PKG_VERSION")
+        )
+        .into_bytes();
+        text.resize(116, b' ');
+        out.write_all(&text)?;
+        out.write_all(&[0; 8])?; // No subsystem data
+        out.write_all(&0x0100u16.to_le_bytes())?;
+        out.write_all(b"IM")
+    }
+
+    // Tag and data, padded to 8 bytes.
+    fn element(data_type: u32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + data.len() + 7);
+        bytes.extend_from_slice(&data_type.to_le_bytes());
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes.resize(bytes.len().next_multiple_of(8), 0);
+        bytes
+    }
+
+    fn matrix(class: u32, name: &str, rows: usize, cols: usize, data: &[u8]) -> Vec<u8> {
+        let mut flags = class.to_le_bytes().to_vec();
+        flags.extend_from_slice(&[0; 4]);
+        let mut dims = (rows as i32).to_le_bytes().to_vec();
+        dims.extend_from_slice(&(cols as i32).to_le_bytes());
+        let mut body = element(MI_UINT32, &flags);
+        body.extend(element(MI_INT32, &dims));
+        body.extend(element(MI_INT8, name.as_bytes()));
+        body.extend_from_slice(data);
+        let mut bytes = MI_MATRIX.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    fn char_matrix(name: &str, text: &str) -> Vec<u8> {
+        let units: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let len = units.len() / 2;
+        matrix(
+            MX_CHAR_CLASS,
+            name,
+            if len == 0 { 0 } else { 1 },
+            len,
+            &element(MI_UINT16, &units),
+        )
+    }
+
+    // rows x cols doubles, column major.
+    pub fn write_doubles(
+        out: &mut impl Write,
+        name: &str,
+        rows: usize,
+        cols: usize,
+        values: &[f64],
+    ) -> io::Result<()> {
+        let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        out.write_all(&matrix(
+            MX_DOUBLE_CLASS,
+            name,
+            rows,
+            cols,
+            &element(MI_DOUBLE, &data),
+        ))
+    }
+
+    // 1 x n cell of char arrays.
+    pub fn write_strings(out: &mut impl Write, name: &str, texts: &[String]) -> io::Result<()> {
+        let cells: Vec<u8> = texts
+            .iter()
+            .flat_map(|text| char_matrix("", text))
+            .collect();
+        out.write_all(&matrix(MX_CELL_CLASS, name, 1, texts.len(), &cells))
+    }
+}
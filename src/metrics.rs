@@ -0,0 +1,161 @@
+// Process metrics.
+//
+// A small registry of named counters and gauges with optional labels,
+// rendered in the Prometheus text exposition format. Shared between tasks,
+// wrap in an Arc. router() serves them over HTTP at GET /metrics (server
+// feature).
+#[cfg(feature = "server")]
+use axum::{extract::State, routing::get, Router};
+use std::collections::BTreeMap;
+#[cfg(feature = "server")]
+use std::io;
+#[cfg(feature = "server")]
+use std::net::SocketAddr;
+#[cfg(feature = "server")]
+use std::sync::Arc;
+use std::sync::Mutex;
+
+pub type Labels<'a> = &'a [(&'a str, &'a str)];
+
+// Series key in exposition format, e.g. name{reason="stale"}.
+pub fn series_key(name: &str, labels: Labels) -> String {
+    if labels.is_empty() {
+        return name.to_string();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, value.replace('"', "\\\"")))
+        .collect();
+    format!("{}{{{}}}", name, labels.join(","))
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<String, BTreeMap<String, u64>>>, // name -> series -> value
+    gauges: Mutex<BTreeMap<String, BTreeMap<String, f64>>>,
+    help: Mutex<BTreeMap<String, String>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Help text shown for a metric family.
+    pub fn describe(&self, name: &str, help: &str) {
+        self.help
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), help.to_string());
+    }
+
+    pub fn increment(&self, name: &str, labels: Labels) {
+        self.add(name, labels, 1);
+    }
+
+    pub fn add(&self, name: &str, labels: Labels, value: u64) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .entry(series_key(name, labels))
+            .or_default() += value;
+    }
+
+    pub fn counter(&self, name: &str, labels: Labels) -> u64 {
+        self.counters
+            .lock()
+            .unwrap()
+            .get(name)
+            .and_then(|series| series.get(&series_key(name, labels)))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // Sum of every series of a metric family.
+    pub fn counter_total(&self, name: &str) -> u64 {
+        self.counters
+            .lock()
+            .unwrap()
+            .get(name)
+            .map_or(0, |series| series.values().sum())
+    }
+
+    // Set a gauge, replacing its last value.
+    pub fn set_gauge(&self, name: &str, labels: Labels, value: f64) {
+        self.gauges
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .insert(series_key(name, labels), value);
+    }
+
+    pub fn gauge(&self, name: &str, labels: Labels) -> Option<f64> {
+        self.gauges
+            .lock()
+            .unwrap()
+            .get(name)
+            .and_then(|series| series.get(&series_key(name, labels)))
+            .copied()
+    }
+
+    // Every series of a gauge family by series key, e.g. for the worst of them.
+    pub fn gauge_series(&self, name: &str) -> BTreeMap<String, f64> {
+        self.gauges
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let counters = self.counters.lock().unwrap();
+        let gauges = self.gauges.lock().unwrap();
+        let help = self.help.lock().unwrap();
+        let mut output = String::new();
+        for (name, series) in counters.iter() {
+            if let Some(text) = help.get(name) {
+                output.push_str(&format!("# HELP {} {}\n", name, text));
+            }
+            output.push_str(&format!("# TYPE {} counter\n", name));
+            for (key, value) in series {
+                output.push_str(&format!("{} {}\n", key, value));
+            }
+        }
+        for (name, series) in gauges.iter() {
+            if let Some(text) = help.get(name) {
+                output.push_str(&format!("# HELP {} {}\n", name, text));
+            }
+            output.push_str(&format!("# TYPE {} gauge\n", name));
+            for (key, value) in series {
+                output.push_str(&format!("{} {}\n", key, value));
+            }
+        }
+        output
+    }
+}
+
+#[cfg(feature = "server")]
+async fn get_metrics(State(metrics): State<Arc<Metrics>>) -> String {
+    metrics.render()
+}
+
+#[cfg(feature = "server")]
+pub fn router(metrics: Arc<Metrics>) -> Router {
+    Router::new()
+        .route("/metrics", get(get_metrics))
+        .with_state(metrics)
+}
+
+// Serve the metrics until the task is dropped.
+#[cfg(feature = "server")]
+pub async fn serve(metrics: Arc<Metrics>, addr: SocketAddr) -> io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    println!("Metrics listening on {}", listener.local_addr()?);
+    axum::serve(listener, router(metrics)).await
+}
@@ -0,0 +1,418 @@
+// Modbus TCP server exposing selected channels as holding registers.
+//
+// For simple industrial equipment a ModbusConfig maps channels to register
+// addresses, each value scaled into a 16 or 32 bit integer or a float: the
+// register holds (value - offset) * scale, so FREQ with offset 60 and scale
+// 1000 is the deviation in mHz. 32 bit values take two registers, high word
+// first unless word_swap is set. A ModbusGateway, a BatchSink, updates the
+// registers from the batches of a stream at most once per update interval.
+//
+// Channels are named as for the DNP3 outstation: a column or a phasor
+// channel, which is served as its magnitude. Integer registers are 0 and
+// floats NaN until a value arrives, and integers saturate at their range.
+//
+// Read holding registers (3) and read input registers (4) both read the
+// map, at most 125 registers at a time; unmapped registers in a range read
+// as 0, a range without any mapped register is an illegal data address.
+// The map is read-only, writes are answered as an illegal function.
+use crate::arrow_utils::channel_value;
+use crate::sinks::BatchSink;
+use arrow::array::{Array, TimestampMicrosecondArray};
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+const READ_HOLDING_REGISTERS: u8 = 3;
+const READ_INPUT_REGISTERS: u8 = 4;
+const MAX_REGISTERS: u16 = 125; // Per read
+
+// Exception codes.
+pub const ILLEGAL_FUNCTION: u8 = 1;
+pub const ILLEGAL_DATA_ADDRESS: u8 = 2;
+pub const ILLEGAL_DATA_VALUE: u8 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegisterFormat {
+    #[default]
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+}
+
+impl RegisterFormat {
+    pub fn registers(&self) -> u16 {
+        match self {
+            RegisterFormat::I16 | RegisterFormat::U16 => 1,
+            _ => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisterMap {
+    pub address: u16,    // First register, from 0
+    pub channel: String, // Column or phasor channel name
+    #[serde(default)]
+    pub format: RegisterFormat,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: f64,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl RegisterMap {
+    pub fn new(address: u16, channel: &str, format: RegisterFormat) -> Self {
+        RegisterMap {
+            address,
+            channel: channel.to_string(),
+            format,
+            scale: default_scale(),
+            offset: 0.0,
+        }
+    }
+
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_offset(mut self, offset: f64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    // Register words of a value, high word first.
+    fn words(&self, value: Option<f64>) -> Vec<u16> {
+        let Some(value) = value else {
+            return match self.format {
+                RegisterFormat::F32 => split(f32::NAN.to_bits()),
+                format => vec![0; format.registers() as usize],
+            };
+        };
+        let scaled = (value - self.offset) * self.scale;
+        let integer = |min: f64, max: f64| scaled.round().clamp(min, max);
+        match self.format {
+            RegisterFormat::I16 => vec![integer(i16::MIN as f64, i16::MAX as f64) as i16 as u16],
+            RegisterFormat::U16 => vec![integer(0.0, u16::MAX as f64) as u16],
+            RegisterFormat::I32 => split(integer(i32::MIN as f64, i32::MAX as f64) as i32 as u32),
+            RegisterFormat::U32 => split(integer(0.0, u32::MAX as f64) as u32),
+            RegisterFormat::F32 => split((scaled as f32).to_bits()),
+        }
+    }
+}
+
+fn split(value: u32) -> Vec<u16> {
+    vec![(value >> 16) as u16, value as u16]
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModbusConfig {
+    #[serde(default)]
+    pub unit: Option<u8>, // Only requests to this unit are answered, None for any
+    pub registers: Vec<RegisterMap>,
+    #[serde(default)]
+    pub word_swap: bool, // Low word first for 32 bit values
+    #[serde(default)]
+    pub update_ms: Option<u64>,
+}
+
+impl ModbusConfig {
+    pub fn new(registers: Vec<RegisterMap>) -> Self {
+        ModbusConfig {
+            unit: None,
+            registers,
+            word_swap: false,
+            update_ms: None,
+        }
+    }
+
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        let config: ModbusConfig = serde_json::from_str(json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    // Registers of the map must not overlap.
+    pub fn validate(&self) -> io::Result<()> {
+        let mut used = HashSet::new();
+        for map in &self.registers {
+            for n in 0..map.format.registers() {
+                let taken = map
+                    .address
+                    .checked_add(n)
+                    .is_none_or(|address| !used.insert(address));
+                if taken {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Register {} of {} overlaps", map.address + n, map.channel),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Register values, shared by gateways and the server.
+#[derive(Debug, Clone)]
+pub struct Registers {
+    maps: Arc<Vec<RegisterMap>>,
+    word_swap: bool,
+    values: Arc<Mutex<BTreeMap<u16, u16>>>,
+}
+
+impl Registers {
+    pub fn new(config: &ModbusConfig) -> Self {
+        let registers = Registers {
+            maps: Arc::new(config.registers.clone()),
+            word_swap: config.word_swap,
+            values: Arc::new(Mutex::new(BTreeMap::new())),
+        };
+        for map in registers.maps.iter() {
+            registers.store(map, None);
+        }
+        registers
+    }
+
+    // Set the value of a channel. Returns false if no register maps it.
+    pub fn set(&self, channel: &str, value: f64) -> bool {
+        let mut found = false;
+        for map in self.maps.iter().filter(|map| map.channel == channel) {
+            self.store(map, Some(value));
+            found = true;
+        }
+        found
+    }
+
+    // count registers from start, None when none of them is mapped.
+    pub fn read(&self, start: u16, count: u16) -> Option<Vec<u16>> {
+        let values = self.values.lock().unwrap();
+        let end = start.checked_add(count.checked_sub(1)?)?;
+        values.range(start..=end).next()?;
+        Some(
+            (start..=end)
+                .map(|address| values.get(&address).copied().unwrap_or(0))
+                .collect(),
+        )
+    }
+
+    pub fn channels(&self) -> Vec<String> {
+        let mut channels: Vec<String> = self.maps.iter().map(|map| map.channel.clone()).collect();
+        channels.sort();
+        channels.dedup();
+        channels
+    }
+
+    fn store(&self, map: &RegisterMap, value: Option<f64>) {
+        let mut words = map.words(value);
+        if self.word_swap {
+            words.reverse();
+        }
+        let mut values = self.values.lock().unwrap();
+        for (address, word) in (map.address..).zip(words) {
+            values.insert(address, word);
+        }
+    }
+}
+
+// Keeps the registers up to date from the batches of a stream.
+pub struct ModbusGateway {
+    registers: Registers,
+    interval_us: i64,
+    last_interval: Option<i64>,
+}
+
+impl ModbusGateway {
+    pub fn new(registers: Registers) -> Self {
+        ModbusGateway {
+            registers,
+            interval_us: 0,
+            last_interval: None,
+        }
+    }
+
+    // Take only the first row of every interval, 0 takes all rows.
+    pub fn with_update_interval(mut self, interval_us: i64) -> Self {
+        self.interval_us = interval_us.max(0);
+        self
+    }
+}
+
+impl BatchSink for ModbusGateway {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let timestamps = batch
+            .column_by_name("timestamp")
+            .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Batch without timestamp column")
+            })?;
+        // Last row kept, the earlier ones would be overwritten
+        let mut row = None;
+        for n in 0..batch.num_rows() {
+            if self.interval_us > 0 {
+                let interval = timestamps.value(n).div_euclid(self.interval_us);
+                if self.last_interval == Some(interval) {
+                    continue;
+                }
+                self.last_interval = Some(interval);
+            }
+            row = Some(n);
+        }
+        let Some(row) = row else {
+            return Ok(());
+        };
+        for channel in self.registers.channels() {
+            if let Some(value) = channel_value(batch, &channel, row) {
+                self.registers.set(&channel, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct ModbusServer {
+    listener: TcpListener,
+    config: ModbusConfig,
+    registers: Registers,
+}
+
+impl ModbusServer {
+    pub async fn bind(address: &str, config: ModbusConfig) -> io::Result<Self> {
+        config.validate()?;
+        let registers = Registers::new(&config);
+        let listener = TcpListener::bind(address).await?;
+        println!(
+            "Modbus TCP server listening on {} with {} mapped channels",
+            listener.local_addr()?,
+            config.registers.len()
+        );
+        Ok(ModbusServer {
+            listener,
+            config,
+            registers,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.registers.clone()
+    }
+
+    // Gateway updating the registers, at the update rate of the config.
+    pub fn gateway(&self) -> ModbusGateway {
+        let interval_us = self.config.update_ms.unwrap_or(0) as i64 * 1000;
+        ModbusGateway::new(self.registers()).with_update_interval(interval_us)
+    }
+
+    // Serve clients until stopped.
+    pub async fn run(self, mut stop: watch::Receiver<bool>) -> io::Result<()> {
+        loop {
+            let (socket, address) = tokio::select! {
+                accepted = self.listener.accept() => accepted?,
+                _ = stop.wait_for(|stop| *stop) => return Ok(()),
+            };
+            println!("Modbus client connected from {}", address);
+            let registers = self.registers.clone();
+            let unit = self.config.unit;
+            let mut stop = stop.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    result = serve(socket, registers, unit) => {
+                        if let Err(e) = result {
+                            println!("Modbus client {} disconnected: {}", address, e);
+                        }
+                    }
+                    _ = stop.wait_for(|stop| *stop) => {}
+                }
+            });
+        }
+    }
+}
+
+// Requests of one client: MBAP header (transaction, protocol 0, length, unit)
+// and the PDU, answered with the same header.
+async fn serve(mut socket: TcpStream, registers: Registers, unit: Option<u8>) -> io::Result<()> {
+    let mut header = [0u8; 7];
+    loop {
+        match socket.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if header[2..4] != [0, 0] || !(2..=254).contains(&length) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a Modbus TCP request",
+            ));
+        }
+        let mut pdu = vec![0u8; length - 1];
+        socket.read_exact(&mut pdu).await?;
+        if unit.is_some_and(|unit| unit != header[6]) {
+            continue;
+        }
+        let response = respond(&registers, &pdu);
+        let mut frame = header[..4].to_vec();
+        frame.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+        frame.push(header[6]);
+        frame.extend(response);
+        socket.write_all(&frame).await?;
+    }
+}
+
+fn respond(registers: &Registers, pdu: &[u8]) -> Vec<u8> {
+    let function = pdu[0];
+    let exception = |code: u8| vec![function | 0x80, code];
+    match function {
+        READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+            if pdu.len() != 5 {
+                return exception(ILLEGAL_DATA_VALUE);
+            }
+            let start = u16::from_be_bytes([pdu[1], pdu[2]]);
+            let count = u16::from_be_bytes([pdu[3], pdu[4]]);
+            if !(1..=MAX_REGISTERS).contains(&count) {
+                return exception(ILLEGAL_DATA_VALUE);
+            }
+            let Some(values) = registers.read(start, count) else {
+                return exception(ILLEGAL_DATA_ADDRESS);
+            };
+            let mut response = vec![function, (count * 2) as u8];
+            for value in values {
+                response.extend_from_slice(&value.to_be_bytes());
+            }
+            response
+        }
+        _ => exception(ILLEGAL_FUNCTION),
+    }
+}
@@ -0,0 +1,279 @@
+// Sockets receiving UDP data from multicast groups.
+//
+// Substation LANs often carry the streams of several PMUs on multicast
+// groups sharing a port. Each stream gets a socket of its own, bound to its
+// group's address and port so datagrams sent to the other groups are not
+// delivered to it, and with source-specific multicast (SSM) joined for the
+// PMUs it lists only (IGMPv3 source filtering), so the switches and the
+// kernel drop the senders it does not want. Without sources the group is
+// joined for any source.
+//
+// The sockets allow several to bind the same port (SO_REUSEADDR) unless
+// configured otherwise, and may ask for a larger receive buffer than the
+// system's default for high rate groups.
+//
+// SSM, the receive buffer and binding the group address need Linux. IPv6
+// groups are joined for any source on the default interface.
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MulticastConfig {
+    pub group: IpAddr,
+    // Address of the interface to join on, the system chooses by default
+    #[serde(default)]
+    pub interface: Option<Ipv4Addr>,
+    // Senders to join for (SSM), any when empty
+    #[serde(default)]
+    pub sources: Vec<IpAddr>,
+    // SO_RCVBUF, the system's default when not set
+    #[serde(default)]
+    pub recv_buffer_bytes: Option<usize>,
+    #[serde(default = "default_reuse_address")]
+    pub reuse_address: bool,
+}
+
+fn default_reuse_address() -> bool {
+    true
+}
+
+impl MulticastConfig {
+    pub fn new(group: IpAddr) -> Self {
+        MulticastConfig {
+            group,
+            interface: None,
+            sources: Vec::new(),
+            recv_buffer_bytes: None,
+            reuse_address: true,
+        }
+    }
+
+    pub fn with_interface(mut self, interface: Ipv4Addr) -> Self {
+        self.interface = Some(interface);
+        self
+    }
+
+    pub fn with_sources(mut self, sources: Vec<IpAddr>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    pub fn with_recv_buffer(mut self, bytes: usize) -> Self {
+        self.recv_buffer_bytes = Some(bytes);
+        self
+    }
+
+    // Problems with the configuration, empty when it can be joined.
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.group.is_multicast() {
+            problems.push(format!("{} is not a multicast group", self.group));
+        }
+        for source in &self.sources {
+            if source.is_multicast() || source.is_unspecified() {
+                problems.push(format!("{} is not a source address", source));
+            }
+            if source.is_ipv4() != self.group.is_ipv4() {
+                problems.push(format!(
+                    "Source {} is not of the family of group {}",
+                    source, self.group
+                ));
+            }
+        }
+        if self.group.is_ipv6() && !self.sources.is_empty() {
+            problems.push("Source-specific joins of IPv6 groups are not supported".to_string());
+        }
+        problems
+    }
+
+    // Whether a datagram from this sender belongs to the stream: one of the
+    // sources, or any sender without sources.
+    pub fn accepts(&self, from: IpAddr) -> bool {
+        self.sources.is_empty() || self.sources.contains(&from)
+    }
+}
+
+// A socket bound to the group on port and joined as configured.
+pub fn bind(config: &MulticastConfig, port: u16) -> io::Result<tokio::net::UdpSocket> {
+    let problems = config.check();
+    if !problems.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            problems.join("; "),
+        ));
+    }
+    let socket = sys::bind(config, SocketAddr::new(config.group, port))?;
+    match config.group {
+        IpAddr::V4(group) => {
+            let interface = config.interface.unwrap_or(Ipv4Addr::UNSPECIFIED);
+            if config.sources.is_empty() {
+                socket.join_multicast_v4(&group, &interface)?;
+            }
+            for source in &config.sources {
+                if let IpAddr::V4(source) = source {
+                    sys::join_source_v4(&socket, group, interface, *source)?;
+                }
+            }
+        }
+        IpAddr::V6(group) => socket.join_multicast_v6(&group, 0)?,
+    }
+    socket.set_nonblocking(true)?;
+    tokio::net::UdpSocket::from_std(socket)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::MulticastConfig;
+    use std::io;
+    use std::mem;
+    use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    fn set_option<T>(socket: &UdpSocket, level: i32, name: i32, value: &T) -> io::Result<()> {
+        // The option value is read as a T of its size
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                value as *const T as *const libc::c_void,
+                mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn in_addr(address: Ipv4Addr) -> libc::in_addr {
+        libc::in_addr {
+            s_addr: u32::from_ne_bytes(address.octets()),
+        }
+    }
+
+    // The options have to be set between creating the socket and binding it.
+    pub fn bind(config: &MulticastConfig, address: SocketAddr) -> io::Result<UdpSocket> {
+        let family = match address {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        let fd = unsafe { libc::socket(family, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The descriptor was just created and is owned by the socket from here
+        let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+        if config.reuse_address {
+            set_option(
+                &socket,
+                libc::SOL_SOCKET,
+                libc::SO_REUSEADDR,
+                &1 as &libc::c_int,
+            )?;
+        }
+        if let Some(bytes) = config.recv_buffer_bytes {
+            let bytes = bytes.min(libc::c_int::MAX as usize) as libc::c_int;
+            set_option(&socket, libc::SOL_SOCKET, libc::SO_RCVBUF, &bytes)?;
+        }
+        let result = match address {
+            SocketAddr::V4(address) => {
+                // Only the groups joined on this socket, not those of others
+                set_option(
+                    &socket,
+                    libc::IPPROTO_IP,
+                    libc::IP_MULTICAST_ALL,
+                    &0 as &libc::c_int,
+                )?;
+                let address = libc::sockaddr_in {
+                    sin_family: libc::AF_INET as libc::sa_family_t,
+                    sin_port: address.port().to_be(),
+                    sin_addr: in_addr(*address.ip()),
+                    sin_zero: [0; 8],
+                };
+                unsafe {
+                    libc::bind(
+                        fd,
+                        &address as *const libc::sockaddr_in as *const libc::sockaddr,
+                        mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                    )
+                }
+            }
+            SocketAddr::V6(address) => {
+                let address = libc::sockaddr_in6 {
+                    sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                    sin6_port: address.port().to_be(),
+                    sin6_flowinfo: 0,
+                    sin6_addr: libc::in6_addr {
+                        s6_addr: address.ip().octets(),
+                    },
+                    sin6_scope_id: 0,
+                };
+                unsafe {
+                    libc::bind(
+                        fd,
+                        &address as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                        mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                    )
+                }
+            }
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+
+    // IP_ADD_SOURCE_MEMBERSHIP: the group, from this source only.
+    pub fn join_source_v4(
+        socket: &UdpSocket,
+        group: Ipv4Addr,
+        interface: Ipv4Addr,
+        source: Ipv4Addr,
+    ) -> io::Result<()> {
+        let membership = libc::ip_mreq_source {
+            imr_multiaddr: in_addr(group),
+            imr_interface: in_addr(interface),
+            imr_sourceaddr: in_addr(source),
+        };
+        set_option(
+            socket,
+            libc::IPPROTO_IP,
+            libc::IP_ADD_SOURCE_MEMBERSHIP,
+            &membership,
+        )
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use super::MulticastConfig;
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+    // Bound to the port on any address, without the socket options.
+    pub fn bind(config: &MulticastConfig, address: SocketAddr) -> io::Result<UdpSocket> {
+        if config.recv_buffer_bytes.is_some() {
+            return Err(unsupported("Setting the receive buffer needs Linux"));
+        }
+        let any = match address.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        UdpSocket::bind(SocketAddr::new(any, address.port()))
+    }
+
+    pub fn join_source_v4(
+        _socket: &UdpSocket,
+        _group: Ipv4Addr,
+        _interface: Ipv4Addr,
+        _source: Ipv4Addr,
+    ) -> io::Result<()> {
+        Err(unsupported("Source-specific multicast needs Linux"))
+    }
+
+    fn unsupported(message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::Unsupported, message.to_string())
+    }
+}
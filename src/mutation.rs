@@ -0,0 +1,445 @@
+// Damaged copies of frames, for fault-injection tests.
+//
+// A Mutation damages a frame the way links and firmware do:
+//
+// - a bit of CHK flipped;
+// - the frame cut short;
+// - FRAMESIZE set to something else;
+// - the frame type in SYNC set to another one;
+// - FORMAT of a PMU of a configuration frame set to something else.
+//
+// A Fault is a mutation left as is, the damage done on the wire with CHK no
+// longer checking, or resealed: CHK recomputed, and FRAMESIZE set to the new
+// length of a cut frame, as firmware building a frame wrong would. Only the
+// structural checks can catch a resealed fault. faults() gives every fault of
+// a frame, both ways.
+//
+// check_parser drives every fault of a frame through parse_frame and
+// check_accumulator through BatchAccumulator::push_frame, which the pipeline
+// feeds (arrow feature). Each trial ends in a typed error, the frame taken as
+// the original was, a batch row flagged in its quality column, the frame
+// taken with other values, or a panic, which is caught. A Report's failures
+// are the panics and the faults done on the wire taken with other values:
+// silent bad values. A resealed fault is a valid frame saying something
+// else, taking it is no failure.
+use crate::frame_parser::{parse_frame, Frame};
+use crate::frames::{calculate_crc, ConfigurationFrame1and2_2011};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+#[cfg(feature = "arrow")]
+use crate::accumulator::BatchAccumulator;
+#[cfg(feature = "arrow")]
+use crate::arrow_utils::QUALITY_COLUMN;
+#[cfg(feature = "arrow")]
+use arrow::array::{Array, UInt8Array};
+#[cfg(feature = "arrow")]
+use arrow::record_batch::RecordBatch;
+
+const PREFIX_SIZE: usize = 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mutation {
+    FlipChk(u8),                        // Flip a bit of CHK, 0 the least significant
+    Truncate(usize),                    // Keep the first bytes
+    FrameSize(u16),                     // Set FRAMESIZE
+    FrameType(u8),                      // Set the frame type, bits 4-6 of SYNC
+    Format { pmu: usize, format: u16 }, // Set FORMAT of a PMU of a configuration frame
+}
+
+impl Mutation {
+    // The damaged frame, None when the mutation does not apply to it.
+    pub fn apply(&self, frame: &[u8]) -> Option<Vec<u8>> {
+        let mut damaged = frame.to_vec();
+        match *self {
+            Mutation::FlipChk(bit) => {
+                if bit >= 16 || frame.len() < 2 {
+                    return None;
+                }
+                let at = frame.len() - 1 - bit as usize / 8;
+                damaged[at] ^= 1 << (bit % 8);
+            }
+            Mutation::Truncate(len) => {
+                if len >= frame.len() {
+                    return None;
+                }
+                damaged.truncate(len);
+            }
+            Mutation::FrameSize(size) => {
+                if frame.len() < 4 {
+                    return None;
+                }
+                damaged[2..4].copy_from_slice(&size.to_be_bytes());
+            }
+            Mutation::FrameType(frame_type) => {
+                if frame_type > 0b111 || frame.len() < 2 {
+                    return None;
+                }
+                damaged[1] = (frame[1] & 0x8F) | (frame_type << 4);
+            }
+            Mutation::Format { pmu, format } => {
+                let at = *format_offsets(frame).get(pmu)?;
+                damaged[at..at + 2].copy_from_slice(&format.to_be_bytes());
+            }
+        }
+        (damaged != frame).then_some(damaged)
+    }
+}
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mutation::FlipChk(bit) => write!(f, "CHK bit {} flipped", bit),
+            Mutation::Truncate(len) => write!(f, "cut to {} bytes", len),
+            Mutation::FrameSize(size) => write!(f, "FRAMESIZE {}", size),
+            Mutation::FrameType(frame_type) => write!(f, "frame type {:#05b}", frame_type),
+            Mutation::Format { pmu, format } => {
+                write!(f, "FORMAT {:#06x} of PMU {}", format, pmu)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub mutation: Mutation,
+    pub resealed: bool, // CHK recomputed, FRAMESIZE following a cut
+}
+
+impl Fault {
+    pub fn apply(&self, frame: &[u8]) -> Option<Vec<u8>> {
+        let mut damaged = self.mutation.apply(frame)?;
+        if self.resealed {
+            if damaged.len() < 4 {
+                return None;
+            }
+            if let Mutation::Truncate(len) = self.mutation {
+                damaged[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+            }
+            let len = damaged.len();
+            let crc = calculate_crc(&damaged[..len - 2]);
+            damaged[len - 2..].copy_from_slice(&crc.to_be_bytes());
+        }
+        // Resealing a flipped CHK gives the frame back
+        (damaged != frame).then_some(damaged)
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mutation)?;
+        if self.resealed {
+            write!(f, ", resealed")?;
+        }
+        Ok(())
+    }
+}
+
+// Offsets of the FORMAT field of every PMU of a configuration frame, as far
+// as the frame goes. None for other frames.
+fn format_offsets(frame: &[u8]) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    if frame.len() < PREFIX_SIZE + 6 || !matches!((frame[1] >> 4) & 0b111, 2 | 3) {
+        return offsets;
+    }
+    let num_pmu = u16::from_be_bytes([frame[PREFIX_SIZE + 4], frame[PREFIX_SIZE + 5]]);
+    let mut offset = PREFIX_SIZE + 6;
+    for _ in 0..num_pmu {
+        if offset + 26 > frame.len() {
+            break;
+        }
+        offsets.push(offset + 18);
+        let count = |at: usize| u16::from_be_bytes([frame[at], frame[at + 1]]) as usize;
+        let (phnmr, annmr, dgnmr) = (count(offset + 20), count(offset + 22), count(offset + 24));
+        offset += 26 + 16 * (phnmr + annmr + 16 * dgnmr) + 4 * (phnmr + annmr + dgnmr) + 4;
+    }
+    offsets
+}
+
+// Every mutation of a frame: each bit of CHK, every shorter length, FRAMESIZE
+// off by one or two, zero, at and around the prefix, doubled and at its
+// maximum, every other frame type, and for configuration frames each of the 16 FORMAT flag
+// combinations but the PMU's own and one with the reserved bits set.
+pub fn mutations(frame: &[u8]) -> Vec<Mutation> {
+    let mut mutations: Vec<Mutation> = (0..16).map(Mutation::FlipChk).collect();
+    mutations.extend((0..frame.len()).map(Mutation::Truncate));
+    let len = frame.len() as i64;
+    let sizes = [
+        0,
+        1,
+        4,
+        13,
+        14,
+        15,
+        len - 2,
+        len - 1,
+        len + 1,
+        len + 2,
+        2 * len,
+    ];
+    let mut sizes: Vec<u16> = sizes
+        .iter()
+        .filter_map(|size| u16::try_from(*size).ok())
+        .chain([u16::MAX])
+        .collect();
+    sizes.sort();
+    sizes.dedup();
+    mutations.extend(sizes.into_iter().map(Mutation::FrameSize));
+    if let Some(sync) = frame.get(1) {
+        let own = (sync >> 4) & 0b111;
+        mutations.extend((0..=0b111).filter(|t| *t != own).map(Mutation::FrameType));
+    }
+    for (pmu, at) in format_offsets(frame).into_iter().enumerate() {
+        let own = u16::from_be_bytes([frame[at], frame[at + 1]]);
+        mutations.extend(
+            (0..16)
+                .chain([0xFFF0 | own])
+                .filter(|format| *format != own)
+                .map(|format| Mutation::Format { pmu, format }),
+        );
+    }
+    mutations
+}
+
+// Every mutation of a frame, as is and resealed.
+pub fn faults(frame: &[u8]) -> Vec<Fault> {
+    mutations(frame)
+        .into_iter()
+        .flat_map(|mutation| [false, true].map(|resealed| Fault { mutation, resealed }))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Rejected(String), // Typed error, as Debug
+    Unchanged,        // Taken as the original was
+    Flagged,          // Taken with the row flagged in the quality column
+    Changed,          // Taken with other values
+    Panicked(String), // Message of the panic
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Rejected(error) => write!(f, "rejected with {}", error),
+            Outcome::Unchanged => write!(f, "taken unchanged"),
+            Outcome::Flagged => write!(f, "taken and flagged"),
+            Outcome::Changed => write!(f, "taken with other values"),
+            Outcome::Panicked(message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Config, // The configuration frame was damaged
+    Data,   // The data frame was damaged
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trial {
+    pub target: Target,
+    pub fault: Fault,
+    pub outcome: Outcome,
+}
+
+impl Trial {
+    // A panic, or a fault done on the wire taken with other values.
+    pub fn is_failure(&self) -> bool {
+        match self.outcome {
+            Outcome::Panicked(_) => true,
+            Outcome::Changed => !self.fault.resealed,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Trial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let target = match self.target {
+            Target::Config => "configuration frame",
+            Target::Data => "data frame",
+        };
+        write!(f, "{} {}: {}", target, self.fault, self.outcome)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub trials: Vec<Trial>,
+}
+
+impl Report {
+    pub fn failures(&self) -> Vec<&Trial> {
+        self.trials.iter().filter(|t| t.is_failure()).collect()
+    }
+
+    pub fn count(&self, matches: impl Fn(&Outcome) -> bool) -> usize {
+        self.trials.iter().filter(|t| matches(&t.outcome)).count()
+    }
+
+    fn push(&mut self, target: Target, fault: Fault, outcome: Outcome) {
+        self.trials.push(Trial {
+            target,
+            fault,
+            outcome,
+        });
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} faults: {} rejected, {} unchanged, {} flagged, {} changed, {} panicked",
+            self.trials.len(),
+            self.count(|o| matches!(o, Outcome::Rejected(_))),
+            self.count(|o| *o == Outcome::Unchanged),
+            self.count(|o| *o == Outcome::Flagged),
+            self.count(|o| *o == Outcome::Changed),
+            self.count(|o| matches!(o, Outcome::Panicked(_))),
+        )?;
+        for trial in self.failures() {
+            write!(f, "\n  {}", trial)?;
+        }
+        Ok(())
+    }
+}
+
+// Run f, a panic caught as an Err with its message.
+fn catch<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string())
+    })
+}
+
+fn target_of(frame: &[u8]) -> Target {
+    match frame.get(1).map(|b| (b >> 4) & 0b111) {
+        Some(2 | 3) => Target::Config,
+        _ => Target::Data,
+    }
+}
+
+// Whether two parsed frames say the same, CHK aside.
+fn same_frame(a: &Frame, b: &Frame) -> bool {
+    match (a, b) {
+        (Frame::Data(a), Frame::Data(b)) => a.prefix == b.prefix && a.data == b.data,
+        (Frame::Configuration(a), Frame::Configuration(b)) => {
+            ConfigurationFrame1and2_2011 {
+                chk: 0,
+                ..a.clone()
+            } == ConfigurationFrame1and2_2011 {
+                chk: 0,
+                ..b.clone()
+            }
+        }
+        _ => format!("{:?}", a) == format!("{:?}", b),
+    }
+}
+
+// Every fault of a frame through parse_frame, data frames parsed with config.
+// The frame itself must parse.
+pub fn check_parser(frame: &[u8], config: Option<&ConfigurationFrame1and2_2011>) -> Report {
+    let original = parse_frame(frame, config.cloned()).expect("Undamaged frame must parse");
+    let target = target_of(frame);
+    let mut report = Report::default();
+    for fault in faults(frame) {
+        let Some(damaged) = fault.apply(frame) else {
+            continue;
+        };
+        let outcome = match catch(|| parse_frame(&damaged, config.cloned())) {
+            Ok(Ok(parsed)) if same_frame(&parsed, &original) => Outcome::Unchanged,
+            Ok(Ok(_)) => Outcome::Changed,
+            Ok(Err(e)) => Outcome::Rejected(format!("{:?}", e)),
+            Err(message) => Outcome::Panicked(message),
+        };
+        report.push(target, fault, outcome);
+    }
+    report
+}
+
+// Every fault of a configuration frame and of one of its data frames through
+// an accumulator from new_accumulator. Batches are compared by column name,
+// the order of the channels varies. A damaged configuration frame is
+// parsed with parse_frame and, when taken, its stream given the data frame.
+// Both frames must be taken undamaged.
+#[cfg(feature = "arrow")]
+pub fn check_accumulator(
+    config_frame: &[u8],
+    data_frame: &[u8],
+    new_accumulator: impl Fn() -> BatchAccumulator,
+) -> Report {
+    let config = match parse_frame(config_frame, None) {
+        Ok(Frame::Configuration(config)) => config,
+        other => panic!("Undamaged configuration frame must parse, got {:?}", other),
+    };
+    let accumulate = |config: &ConfigurationFrame1and2_2011, frame: &[u8]| {
+        let mut accumulator = new_accumulator();
+        accumulator.add_stream(config);
+        accumulator
+            .push_frame(frame)
+            .and_then(|_| accumulator.flush(config.prefix.idcode))
+            .map_err(|e| format!("{:?}", e))
+    };
+    let original = accumulate(&config, data_frame)
+        .expect("Undamaged data frame must be taken")
+        .expect("Undamaged data frame must give a batch");
+    let outcome = |result: Result<Result<Option<RecordBatch>, String>, String>| match result {
+        Ok(Ok(Some(batch))) if same_batch(&batch, &original) => Outcome::Unchanged,
+        Ok(Ok(Some(batch))) if flagged(&batch) => Outcome::Flagged,
+        Ok(Ok(Some(_))) => Outcome::Changed,
+        Ok(Ok(None)) => Outcome::Rejected("no batch".to_string()),
+        Ok(Err(e)) => Outcome::Rejected(e),
+        Err(message) => Outcome::Panicked(message),
+    };
+
+    let mut report = Report::default();
+    for fault in faults(config_frame) {
+        let Some(damaged) = fault.apply(config_frame) else {
+            continue;
+        };
+        let result = catch(|| match parse_frame(&damaged, None) {
+            Ok(Frame::Configuration(config)) => accumulate(&config, data_frame),
+            Ok(other) => Err(format!("not a configuration frame: {:?}", other)),
+            Err(e) => Err(format!("{:?}", e)),
+        });
+        report.push(Target::Config, fault, outcome(result));
+    }
+    for fault in faults(data_frame) {
+        let Some(damaged) = fault.apply(data_frame) else {
+            continue;
+        };
+        let result = catch(|| accumulate(&config, &damaged));
+        report.push(Target::Data, fault, outcome(result));
+    }
+    report
+}
+
+// Whether two batches have the same columns, in any order.
+#[cfg(feature = "arrow")]
+fn same_batch(a: &RecordBatch, b: &RecordBatch) -> bool {
+    let a_schema = a.schema();
+    a.num_rows() == b.num_rows()
+        && a.num_columns() == b.num_columns()
+        && a_schema
+            .fields()
+            .iter()
+            .zip(a.columns())
+            .all(|(field, column)| {
+                b.column_by_name(field.name())
+                    .is_some_and(|other| other.as_ref() == column.as_ref())
+            })
+}
+
+// Whether a batch flags a row in its quality column.
+#[cfg(feature = "arrow")]
+fn flagged(batch: &RecordBatch) -> bool {
+    batch
+        .column_by_name(QUALITY_COLUMN)
+        .and_then(|column| column.as_any().downcast_ref::<UInt8Array>())
+        .is_some_and(|quality| quality.iter().any(|q| q.unwrap_or(0) != 0))
+}
@@ -0,0 +1,502 @@
+// Alert delivery by email (SMTP) and webhooks, with rate limits and digests.
+//
+// A Notifier subscribes to the event bus and passes events of at least a
+// severity to its channels. The first event after a quiet period is sent at
+// once; events following within the digest interval are collected and sent
+// as one digest when the interval is over, so an oscillation raising an
+// event per second sends two messages a minute rather than sixty. A channel
+// also sends at most max_per_hour messages; beyond that events wait for the
+// next digest allowed (the oldest are dropped past MAX_PENDING and counted).
+//
+// SMTP goes to a relay without TLS or authentication, e.g. the local MTA,
+// which can forward to email-to-SMS gateways. Webhooks are POSTed as JSON
+// over plain HTTP in the shape of the target: a Slack message, a Teams
+// MessageCard, the events as they are, or a custom template in which the
+// strings {subject}, {text}, {count} and {severity} are replaced and a
+// string "{events}" becomes the list of events, for SMS gateways and the
+// like.
+use crate::events::{Event, EventBus, Severity};
+use crate::reports::{format_time, post_json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, watch};
+
+const HOUR_US: i64 = 3_600_000_000;
+const MAX_PENDING: usize = 1000; // Events waiting per channel
+const DIGEST_LINES: usize = 50; // Events listed in a digest
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmtpTarget {
+    pub server: String, // host:port of the relay
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default = "default_subject_prefix")]
+    pub subject_prefix: String,
+}
+
+fn default_subject_prefix() -> String {
+    "[PMU]".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    Slack,
+    Teams,
+    Events,
+    Template(Value),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotifyTarget {
+    Smtp(SmtpTarget),
+    Webhook { url: String, format: WebhookFormat }, // url is http://host[:port]/path
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NotifyChannel {
+    pub name: String,
+    pub target: NotifyTarget,
+    #[serde(default = "default_min_severity")]
+    pub min_severity: Severity,
+    #[serde(default = "default_digest_secs")]
+    pub digest_secs: u64,
+    #[serde(default)]
+    pub max_per_hour: Option<usize>,
+}
+
+fn default_min_severity() -> Severity {
+    Severity::Warning
+}
+
+fn default_digest_secs() -> u64 {
+    60
+}
+
+impl NotifyChannel {
+    pub fn new(name: &str, target: NotifyTarget) -> Self {
+        NotifyChannel {
+            name: name.to_string(),
+            target,
+            min_severity: default_min_severity(),
+            digest_secs: default_digest_secs(),
+            max_per_hour: None,
+        }
+    }
+
+    pub fn with_min_severity(mut self, severity: Severity) -> Self {
+        self.min_severity = severity;
+        self
+    }
+
+    pub fn with_digest(mut self, interval: Duration) -> Self {
+        self.digest_secs = interval.as_secs();
+        self
+    }
+
+    pub fn with_max_per_hour(mut self, max: usize) -> Self {
+        self.max_per_hour = Some(max);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NotifyConfig {
+    pub channels: Vec<NotifyChannel>,
+}
+
+impl NotifyConfig {
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        serde_json::from_str(json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+// A notification of one channel, a single event or a digest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub channel: String,
+    pub subject: String,
+    pub text: String,
+    pub events: Vec<Event>,
+    pub dropped: usize, // Events left out for the pending limit
+}
+
+impl Message {
+    fn new(channel: &str, events: Vec<Event>, dropped: usize) -> Self {
+        let total = events.len() + dropped;
+        let worst = events
+            .iter()
+            .map(|e| e.severity)
+            .max()
+            .unwrap_or(Severity::Info);
+        let subject = match events.as_slice() {
+            [event] if dropped == 0 => format!(
+                "{}: {} at {}",
+                severity_name(event.severity),
+                kind_name(event),
+                event.source
+            ),
+            _ => {
+                let mut sources: Vec<&str> = events.iter().map(|e| e.source.as_str()).collect();
+                sources.sort();
+                sources.dedup();
+                let more = if sources.len() > 3 { ", ..." } else { "" };
+                sources.truncate(3);
+                format!(
+                    "{} events, worst {}, from {}{}",
+                    total,
+                    severity_name(worst),
+                    sources.join(", "),
+                    more
+                )
+            }
+        };
+        let mut text = String::new();
+        for event in events.iter().take(DIGEST_LINES) {
+            text.push_str(&format!(
+                "{} {} {} {}: {}",
+                format_time(event.timestamp_us),
+                severity_name(event.severity),
+                kind_name(event),
+                event.source,
+                event.message
+            ));
+            for (name, value) in &event.values {
+                text.push_str(&format!(" {}={}", name, value));
+            }
+            text.push('\n');
+        }
+        if total > DIGEST_LINES {
+            text.push_str(&format!("... and {} more\n", total - DIGEST_LINES));
+        }
+        Message {
+            channel: channel.to_string(),
+            subject,
+            text,
+            events,
+            dropped,
+        }
+    }
+
+    fn severity(&self) -> Severity {
+        self.events
+            .iter()
+            .map(|e| e.severity)
+            .max()
+            .unwrap_or(Severity::Info)
+    }
+
+    // JSON body of a webhook in a format.
+    pub fn webhook_body(&self, format: &WebhookFormat) -> Value {
+        match format {
+            WebhookFormat::Slack => json!({ "text": format!("*{}*\n{}", self.subject, self.text) }),
+            WebhookFormat::Teams => json!({
+                "@type": "MessageCard",
+                "@context": "https://schema.org/extensions",
+                "summary": self.subject,
+                "title": self.subject,
+                "text": self.text.replace('\n', "\n\n"),
+            }),
+            WebhookFormat::Events => json!({
+                "subject": self.subject,
+                "count": self.events.len() + self.dropped,
+                "events": self.events,
+            }),
+            WebhookFormat::Template(template) => self.fill(template),
+        }
+    }
+
+    fn fill(&self, template: &Value) -> Value {
+        match template {
+            Value::String(text) if text == "{events}" => json!(self.events),
+            Value::String(text) => Value::String(
+                text.replace("{subject}", &self.subject)
+                    .replace("{text}", &self.text)
+                    .replace("{count}", &(self.events.len() + self.dropped).to_string())
+                    .replace("{severity}", severity_name(self.severity())),
+            ),
+            Value::Array(values) => Value::Array(values.iter().map(|v| self.fill(v)).collect()),
+            Value::Object(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(key, value)| (key.clone(), self.fill(value)))
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+}
+
+fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Info => "Info",
+        Severity::Warning => "Warning",
+        Severity::Alarm => "Alarm",
+    }
+}
+
+fn kind_name(event: &Event) -> String {
+    json!(event.kind).as_str().unwrap_or_default().to_string()
+}
+
+struct ChannelState {
+    channel: NotifyChannel,
+    pending: VecDeque<Event>,
+    dropped: usize,
+    last_sent_us: Option<i64>,
+    sent_us: VecDeque<i64>, // Messages of the last hour
+}
+
+impl ChannelState {
+    fn digest_us(&self) -> i64 {
+        self.channel.digest_secs as i64 * 1_000_000
+    }
+
+    // Whether the rate limit allows another message now.
+    fn allowed(&mut self, now_us: i64) -> bool {
+        while self.sent_us.front().is_some_and(|t| *t <= now_us - HOUR_US) {
+            self.sent_us.pop_front();
+        }
+        self.channel
+            .max_per_hour
+            .is_none_or(|max| self.sent_us.len() < max)
+    }
+
+    // The message due now, if any.
+    fn due(&mut self, now_us: i64) -> Option<Message> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let quiet = self
+            .last_sent_us
+            .is_none_or(|last| now_us - last >= self.digest_us());
+        if !quiet || !self.allowed(now_us) {
+            return None;
+        }
+        let events: Vec<Event> = self.pending.drain(..).collect();
+        let message = Message::new(&self.channel.name, events, self.dropped);
+        self.dropped = 0;
+        self.last_sent_us = Some(now_us);
+        self.sent_us.push_back(now_us);
+        Some(message)
+    }
+}
+
+pub struct Notifier {
+    channels: Vec<ChannelState>,
+    events: Option<broadcast::Receiver<Event>>,
+    sent: u64,
+    failures: u64,
+}
+
+impl Notifier {
+    pub fn new(config: NotifyConfig) -> io::Result<Self> {
+        for channel in &config.channels {
+            let valid = match &channel.target {
+                NotifyTarget::Smtp(smtp) => !smtp.to.is_empty() && smtp.server.contains(':'),
+                NotifyTarget::Webhook { url, .. } => url.starts_with("http://"),
+            };
+            if !valid {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid target of notification channel {}", channel.name),
+                ));
+            }
+        }
+        let channels = config
+            .channels
+            .into_iter()
+            .map(|channel| ChannelState {
+                channel,
+                pending: VecDeque::new(),
+                dropped: 0,
+                last_sent_us: None,
+                sent_us: VecDeque::new(),
+            })
+            .collect();
+        Ok(Notifier {
+            channels,
+            events: None,
+            sent: 0,
+            failures: 0,
+        })
+    }
+
+    pub fn with_events(mut self, bus: &EventBus) -> Self {
+        self.events = Some(bus.subscribe());
+        self
+    }
+
+    // Messages delivered.
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    // Messages that could not be delivered.
+    pub fn failures(&self) -> u64 {
+        self.failures
+    }
+
+    // Queue an event for the channels it is severe enough for.
+    pub fn offer(&mut self, event: &Event) {
+        for state in &mut self.channels {
+            if event.severity < state.channel.min_severity {
+                continue;
+            }
+            if state.pending.len() == MAX_PENDING {
+                state.pending.pop_front();
+                state.dropped += 1;
+            }
+            state.pending.push_back(event.clone());
+        }
+    }
+
+    // Messages due at now_us, without delivering them.
+    pub fn due(&mut self, now_us: i64) -> Vec<Message> {
+        self.channels
+            .iter_mut()
+            .filter_map(|state| state.due(now_us))
+            .collect()
+    }
+
+    // Take the events published since the last call, then deliver the
+    // messages due. Returns the messages, delivered or not.
+    pub async fn deliver_due(&mut self, now_us: i64) -> Vec<Message> {
+        let mut received = Vec::new();
+        if let Some(events) = self.events.as_mut() {
+            loop {
+                match events.try_recv() {
+                    Ok(event) => received.push(event),
+                    Err(broadcast::error::TryRecvError::Lagged(missed)) => {
+                        println!("Notifier missed {} events", missed);
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+        for event in &received {
+            self.offer(event);
+        }
+        let messages = self.due(now_us);
+        for message in &messages {
+            let state = self
+                .channels
+                .iter()
+                .find(|state| state.channel.name == message.channel);
+            let Some(state) = state else {
+                continue;
+            };
+            match send(&state.channel.target, message).await {
+                Ok(()) => self.sent += 1,
+                Err(e) => {
+                    println!("Failed to notify {}: {}", message.channel, e);
+                    self.failures += 1;
+                }
+            }
+        }
+        messages
+    }
+
+    // Deliver notifications as they fall due until stopped.
+    pub async fn run(mut self, mut stop: watch::Receiver<bool>) {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+                _ = stop.wait_for(|stop| *stop) => return,
+            }
+            self.deliver_due(now_us()).await;
+        }
+    }
+}
+
+fn now_us() -> i64 {
+    chrono::Utc::now().timestamp_micros()
+}
+
+async fn send(target: &NotifyTarget, message: &Message) -> io::Result<()> {
+    match target {
+        NotifyTarget::Smtp(smtp) => send_mail(smtp, message).await,
+        NotifyTarget::Webhook { url, format } => {
+            post_json(url, &message.webhook_body(format)).await
+        }
+    }
+}
+
+// Send a plain text mail through an SMTP relay.
+async fn send_mail(smtp: &SmtpTarget, message: &Message) -> io::Result<()> {
+    let exchange = async {
+        let stream = TcpStream::connect(&smtp.server).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        expect(&mut reader, 220).await?;
+        let mut commands = vec!["EHLO pmu".to_string(), format!("MAIL FROM:<{}>", smtp.from)];
+        commands.extend(smtp.to.iter().map(|to| format!("RCPT TO:<{}>", to)));
+        for command in commands {
+            writer
+                .write_all(format!("{}\r\n", command).as_bytes())
+                .await?;
+            expect(&mut reader, 250).await?;
+        }
+        writer.write_all(b"DATA\r\n").await?;
+        expect(&mut reader, 354).await?;
+        let mut mail = format!(
+            "From: <{}>\r\nTo: {}\r\nSubject: {} {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            smtp.from,
+            smtp.to.iter().map(|to| format!("<{}>", to)).collect::<Vec<_>>().join(", "),
+            smtp.subject_prefix,
+            message.subject,
+            chrono::Utc::now().to_rfc2822()
+        );
+        for line in message.text.lines() {
+            // Lines starting with a dot are doubled (RFC 5321 4.5.2)
+            if line.starts_with('.') {
+                mail.push('.');
+            }
+            mail.push_str(line);
+            mail.push_str("\r\n");
+        }
+        mail.push_str(".\r\n");
+        writer.write_all(mail.as_bytes()).await?;
+        expect(&mut reader, 250).await?;
+        writer.write_all(b"QUIT\r\n").await?;
+        Ok::<_, io::Error>(())
+    };
+    tokio::time::timeout(Duration::from_secs(30), exchange)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "SMTP timed out"))?
+}
+
+// Read a reply, all lines of a multiline one, and check its code.
+async fn expect(
+    reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    code: u16,
+) -> io::Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "SMTP server closed the connection",
+            ));
+        }
+        if line.get(..3).and_then(|c| c.parse::<u16>().ok()) != Some(code) {
+            return Err(io::Error::other(format!("SMTP: {}", line.trim_end())));
+        }
+        // 250-... continues, 250 ... is the last line
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,520 @@
+// Reader of openPDC and openHistorian CSV exports.
+//
+// An export has a Timestamp column followed by one column per measurement,
+// headed by its signal reference (SHELBY-FQ, SHELBY-PM1), or by its
+// measurement key (PPA:12) or point tag when the ActiveMeasurements table of
+// the openPDC is given as a MeasurementMap. Each device becomes a stream
+// with the columns and field metadata a configuration frame would give it,
+// so the batches go through the same sinks and analytics as live streams:
+//
+//   FQ         <station>_<idcode>_FREQ, Hz
+//   DF         <station>_<idcode>_DFREQ, Hz/s
+//   SF         <station>_<idcode>_STAT, UInt16
+//   PMn, PAn   <station>_<idcode>_<phasor>_magnitude and _angle, in degrees
+//              scaled to radians
+//   AVn        <station>_<idcode>_AN<n>, or the label of the measurement
+//   DVn        <station>_<idcode>_DG<n>, UInt16
+//
+// The station is the device acronym. Phasors are named PH<n> unless the map
+// labels them. Values are exported already scaled, so the scale is 1 but
+// for angles. Empty and NaN cells are nulls.
+use crate::arrow_utils::{
+    META_CHANNEL, META_COMPONENT, META_IDCODE, META_KIND, META_NOMINAL_FREQUENCY, META_SCALE,
+    META_STATION, META_UNIT,
+};
+use arrow::array::{ArrayRef, Float64Array, TimestampMicrosecondArray, UInt16Array};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use chrono::NaiveDateTime;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+// .NET ticks (100 ns since 0001-01-01) at the Unix epoch
+const UNIX_EPOCH_TICKS: i64 = 621_355_968_000_000_000;
+
+// One row of the ActiveMeasurements table.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Measurement {
+    pub key: String, // PPA:12
+    pub point_tag: String,
+    pub signal_reference: String, // SHELBY-PM1
+    pub device: String,
+    pub signal_type: String, // VPHM, IPHA, FREQ, DFDT, ALOG, DIGI, FLAG, ...
+    pub unit: String,
+    pub label: String, // Channel name, optional
+}
+
+// Measurements by key, point tag and signal reference.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeasurementMap {
+    measurements: Vec<Measurement>,
+    index: HashMap<String, usize>,
+}
+
+impl MeasurementMap {
+    // CSV export of ActiveMeasurements: a header naming the columns, of which
+    // ID, PointTag, SignalReference, Device, SignalType, EngineeringUnits and
+    // Label are read, in any order and case. Missing columns are empty.
+    pub fn from_csv(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        let header: Vec<String> = split_csv_line(lines.next().ok_or("Empty measurement table")?)
+            .iter()
+            .map(|name| name.to_ascii_lowercase())
+            .collect();
+        let column = |name: &str| header.iter().position(|h| h == name);
+        let columns = [
+            column("id"),
+            column("pointtag"),
+            column("signalreference"),
+            column("device"),
+            column("signaltype"),
+            column("engineeringunits"),
+            column("label"),
+        ];
+        if columns[..3].iter().all(Option::is_none) {
+            return Err("Measurement table without ID, PointTag or SignalReference".into());
+        }
+        let mut map = MeasurementMap::default();
+        for line in lines {
+            let fields = split_csv_line(line);
+            let [key, point_tag, signal_reference, device, signal_type, unit, label] =
+                columns.map(|c| {
+                    c.and_then(|c| fields.get(c))
+                        .map(|f| f.trim().to_string())
+                        .unwrap_or_default()
+                });
+            map.insert(Measurement {
+                key,
+                point_tag,
+                signal_reference,
+                device,
+                signal_type,
+                unit,
+                label,
+            });
+        }
+        Ok(map)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_csv(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn insert(&mut self, measurement: Measurement) {
+        let position = self.measurements.len();
+        for name in [
+            &measurement.key,
+            &measurement.point_tag,
+            &measurement.signal_reference,
+        ] {
+            if !name.is_empty() {
+                self.index.insert(name.clone(), position);
+            }
+        }
+        self.measurements.push(measurement);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Measurement> {
+        self.index.get(name).map(|&i| &self.measurements[i])
+    }
+
+    pub fn len(&self) -> usize {
+        self.measurements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.measurements.is_empty()
+    }
+}
+
+// Where a column of the export goes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelMapping {
+    pub device: String,
+    pub channel: String,                 // Name without the station and idcode
+    pub component: Option<&'static str>, // magnitude or angle of a phasor
+    pub kind: String,
+    pub unit: String,
+    pub digital: bool, // UInt16 words: STAT and digitals
+}
+
+// Split a signal reference into device, suffix and index: SHELBY-PA1 gives
+// ("SHELBY", "PA", 1), SHELBY-FQ ("SHELBY", "FQ", 0).
+pub fn parse_signal_reference(reference: &str) -> Option<(&str, &str, u32)> {
+    let (device, signal) = reference.rsplit_once('-')?;
+    let digits = signal.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    let suffix = &signal[..signal.len() - digits.len()];
+    if device.is_empty() || suffix.is_empty() {
+        return None;
+    }
+    let index = match digits {
+        "" => 0,
+        digits => digits.parse().ok()?,
+    };
+    Some((device, suffix, index))
+}
+
+// Mapping of a signal reference, with what the measurement table adds.
+fn map_signal(reference: &str, measurement: Option<&Measurement>) -> Option<ChannelMapping> {
+    let (device, suffix, index) = parse_signal_reference(reference)?;
+    let measurement = measurement.cloned().unwrap_or_default();
+    let device = match measurement.device.is_empty() {
+        true => device.to_string(),
+        false => measurement.device.clone(),
+    };
+    let label = |default: String| match measurement.label.as_str() {
+        "" => default,
+        label => label.to_string(),
+    };
+    let current = measurement.signal_type.starts_with('I');
+    let phasor = |component| ChannelMapping {
+        device: device.clone(),
+        channel: label(format!("PH{}", index)),
+        component: Some(component),
+        kind: match (measurement.signal_type.as_str(), current) {
+            ("", _) => String::new(),
+            (_, true) => "current".into(),
+            (_, false) => "voltage".into(),
+        },
+        unit: match component {
+            "angle" => "rad".into(),
+            _ => measurement.unit.clone(),
+        },
+        digital: false,
+    };
+    let single = |channel: String, kind: &str, unit: &str, digital| ChannelMapping {
+        device: device.clone(),
+        channel,
+        component: None,
+        kind: kind.to_string(),
+        unit: match measurement.unit.as_str() {
+            "" => unit.to_string(),
+            unit => unit.to_string(),
+        },
+        digital,
+    };
+    Some(match suffix {
+        "PM" => phasor("magnitude"),
+        "PA" => phasor("angle"),
+        "FQ" => single("FREQ".into(), "frequency", "Hz", false),
+        "DF" => single("DFREQ".into(), "rocof", "Hz/s", false),
+        "SF" => single("STAT".into(), "stat", "", true),
+        "AV" => single(label(format!("AN{}", index)), "", "", false),
+        "DV" => single(label(format!("DG{}", index)), "digital", "", true),
+        suffix => single(label(format!("{}{}", suffix, index)), "", "", false),
+    })
+}
+
+// Rows read from an export.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportStats {
+    pub rows: usize,
+    pub batches: usize,
+    pub streams: usize,
+    pub skipped_columns: Vec<String>, // Headers that map to no channel
+}
+
+struct Column {
+    field: Field,
+    digital: bool,
+    values: Vec<Option<f64>>,
+}
+
+struct Stream {
+    idcode: u16,
+    columns: Vec<(usize, Column)>, // By position in the export
+}
+
+impl Stream {
+    fn take_batch(&mut self, timestamps: &[i64]) -> io::Result<RecordBatch> {
+        let mut fields = vec![Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            false,
+        )];
+        let mut arrays: Vec<ArrayRef> = vec![Arc::new(TimestampMicrosecondArray::from(
+            timestamps.to_vec(),
+        ))];
+        for (_, column) in &mut self.columns {
+            let values = std::mem::take(&mut column.values);
+            fields.push(column.field.clone());
+            arrays.push(if column.digital {
+                Arc::new(UInt16Array::from_iter(
+                    values.into_iter().map(|v| v.map(|v| v as u16)),
+                ))
+            } else {
+                Arc::new(Float64Array::from(values))
+            });
+        }
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OpenPdcReader {
+    measurements: MeasurementMap,
+    idcodes: HashMap<String, u16>,
+    nominal_hz: f64,
+    batch_rows: usize,
+}
+
+impl Default for OpenPdcReader {
+    fn default() -> Self {
+        OpenPdcReader {
+            measurements: MeasurementMap::default(),
+            idcodes: HashMap::new(),
+            nominal_hz: 60.0,
+            batch_rows: 10_000,
+        }
+    }
+}
+
+impl OpenPdcReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_measurements(mut self, measurements: MeasurementMap) -> Self {
+        self.measurements = measurements;
+        self
+    }
+
+    // IDCODE of the stream of a device. Devices without one are numbered
+    // from 1 in the order they appear, skipping the IDCODEs given.
+    pub fn with_idcode(mut self, device: &str, idcode: u16) -> Self {
+        self.idcodes.insert(device.to_string(), idcode);
+        self
+    }
+
+    pub fn with_nominal_hz(mut self, nominal_hz: f64) -> Self {
+        self.nominal_hz = nominal_hz;
+        self
+    }
+
+    // Rows per batch, at least 1.
+    pub fn with_batch_rows(mut self, batch_rows: usize) -> Self {
+        self.batch_rows = batch_rows.max(1);
+        self
+    }
+
+    // Channel of a column header, None when it maps to none.
+    pub fn map_column(&self, header: &str) -> Option<ChannelMapping> {
+        let measurement = self.measurements.get(header);
+        let reference = match measurement {
+            Some(m) if !m.signal_reference.is_empty() => m.signal_reference.as_str(),
+            _ => header,
+        };
+        map_signal(reference, measurement)
+    }
+
+    // Read an export, handing each batch to emit with the IDCODE of its stream.
+    pub fn read(
+        &self,
+        input: impl BufRead,
+        mut emit: impl FnMut(u16, RecordBatch) -> io::Result<()>,
+    ) -> io::Result<ImportStats> {
+        let invalid = |line: usize, message: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Line {}: {}", line, message),
+            )
+        };
+        let mut lines = input.lines().enumerate();
+        let header = loop {
+            match lines.next() {
+                Some((_, line)) if line.as_ref().is_ok_and(|l| l.trim().is_empty()) => continue,
+                Some((_, line)) => break split_csv_line(&line?),
+                None => return Err(invalid(1, "No header".into())),
+            }
+        };
+        if header.len() < 2 || !header[0].trim().eq_ignore_ascii_case("timestamp") {
+            return Err(invalid(
+                1,
+                "Expected a Timestamp column followed by measurements".into(),
+            ));
+        }
+
+        let mut stats = ImportStats::default();
+        let mut streams: Vec<Stream> = Vec::new();
+        let mut devices: HashMap<String, usize> = HashMap::new();
+        let mut seen = HashMap::new();
+        let mut next_idcode = 1u16;
+        for (position, name) in header.iter().enumerate().skip(1) {
+            let name = name.trim();
+            let Some(mapping) = self.map_column(name) else {
+                stats.skipped_columns.push(name.to_string());
+                continue;
+            };
+            let stream = *devices.entry(mapping.device.clone()).or_insert_with(|| {
+                let idcode = match self.idcodes.get(&mapping.device) {
+                    Some(&idcode) => idcode,
+                    None => {
+                        while self.idcodes.values().any(|&i| i == next_idcode) {
+                            next_idcode = next_idcode.wrapping_add(1);
+                        }
+                        next_idcode = next_idcode.wrapping_add(1);
+                        next_idcode - 1
+                    }
+                };
+                streams.push(Stream {
+                    idcode,
+                    columns: Vec::new(),
+                });
+                streams.len() - 1
+            });
+            let idcode = streams[stream].idcode;
+            let channel = format!("{}_{}_{}", mapping.device, idcode, mapping.channel);
+            let column_name = match mapping.component {
+                Some(component) => format!("{}_{}", channel, component),
+                None => channel.clone(),
+            };
+            if seen.insert(column_name.clone(), name.to_string()).is_some() {
+                stats.skipped_columns.push(name.to_string());
+                continue;
+            }
+            let mut metadata = HashMap::from([
+                (META_STATION.to_string(), mapping.device.clone()),
+                (META_IDCODE.to_string(), idcode.to_string()),
+                (META_CHANNEL.to_string(), channel),
+                (
+                    META_NOMINAL_FREQUENCY.to_string(),
+                    self.nominal_hz.to_string(),
+                ),
+            ]);
+            let scale = match mapping.component {
+                Some("angle") => std::f64::consts::PI / 180.0,
+                _ => 1.0,
+            };
+            metadata.insert(META_SCALE.to_string(), scale.to_string());
+            if let Some(component) = mapping.component {
+                metadata.insert(META_COMPONENT.to_string(), component.to_string());
+            }
+            if !mapping.unit.is_empty() {
+                metadata.insert(META_UNIT.to_string(), mapping.unit.clone());
+            }
+            if !mapping.kind.is_empty() {
+                metadata.insert(META_KIND.to_string(), mapping.kind.clone());
+            }
+            let data_type = match mapping.digital {
+                true => DataType::UInt16,
+                false => DataType::Float64,
+            };
+            streams[stream].columns.push((
+                position,
+                Column {
+                    field: Field::new(column_name, data_type, true).with_metadata(metadata),
+                    digital: mapping.digital,
+                    values: Vec::new(),
+                },
+            ));
+        }
+        for name in &stats.skipped_columns {
+            println!("openPDC import: no channel for column {}", name);
+        }
+        stats.streams = streams.len();
+
+        let mut timestamps = Vec::new();
+        let mut flush = |streams: &mut Vec<Stream>,
+                         timestamps: &mut Vec<i64>,
+                         stats: &mut ImportStats|
+         -> io::Result<()> {
+            if timestamps.is_empty() {
+                return Ok(());
+            }
+            for stream in streams.iter_mut() {
+                emit(stream.idcode, stream.take_batch(timestamps)?)?;
+                stats.batches += 1;
+            }
+            timestamps.clear();
+            Ok(())
+        };
+        for (number, line) in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields = split_csv_line(&line);
+            let timestamp = parse_timestamp(fields[0].trim())
+                .ok_or_else(|| invalid(number + 1, format!("Invalid timestamp {:?}", fields[0])))?;
+            for stream in &mut streams {
+                for (position, column) in &mut stream.columns {
+                    let value = match fields.get(*position).map(|f| f.trim()) {
+                        None | Some("") => None,
+                        Some(text) => Some(text.parse::<f64>().map_err(|_| {
+                            invalid(number + 1, format!("Invalid value {:?}", text))
+                        })?),
+                    };
+                    column.values.push(value.filter(|v| !v.is_nan()));
+                }
+            }
+            timestamps.push(timestamp);
+            stats.rows += 1;
+            if timestamps.len() >= self.batch_rows {
+                flush(&mut streams, &mut timestamps, &mut stats)?;
+            }
+        }
+        flush(&mut streams, &mut timestamps, &mut stats)?;
+        Ok(stats)
+    }
+
+    // All batches of an export file.
+    pub fn read_file(&self, path: impl AsRef<Path>) -> io::Result<Vec<(u16, RecordBatch)>> {
+        let mut batches = Vec::new();
+        self.read(BufReader::new(File::open(path)?), |idcode, batch| {
+            batches.push((idcode, batch));
+            Ok(())
+        })?;
+        Ok(batches)
+    }
+}
+
+// Microseconds since the Unix epoch of an export timestamp: RFC 3339,
+// 2024-01-01 06:00:00.033 or 01/01/2024 06:00:00.033 in UTC, .NET ticks or
+// Unix seconds.
+pub fn parse_timestamp(text: &str) -> Option<i64> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(text) {
+        return Some(time.timestamp_micros());
+    }
+    for format in [
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%m/%d/%Y %H:%M:%S%.f",
+    ] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(text, format) {
+            return Some(time.and_utc().timestamp_micros());
+        }
+    }
+    if let Ok(ticks) = text.parse::<i64>() {
+        if ticks > UNIX_EPOCH_TICKS / 2 {
+            return Some((ticks - UNIX_EPOCH_TICKS) / 10);
+        }
+    }
+    let seconds = text.parse::<f64>().ok().filter(|s| s.is_finite())?;
+    Some((seconds * 1e6).round() as i64)
+}
+
+// Fields of a CSV line, with double quoted fields unquoted.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
@@ -0,0 +1,147 @@
+// Reader of vendor PMU archives (.pdat, .dst) that hold C37.118 frames.
+//
+// PDC archives such as .pdat files (BPA, PhasorPoint exports) store the stream as
+// received: a vendor file header, then the configuration frame and the data
+// frames, possibly with per record headers in between. Without a published
+// specification of those headers the reader supports this subset:
+//
+//   - frames are IEEE C37.118-2011 frames, SYNC 0xAA, version 1 or 2, stored
+//     whole and big endian as on the wire
+//   - a CFG-2 (or CFG-1) frame comes before the data frames it describes,
+//     a later one replaces it (the stream is flushed first)
+//   - anything between frames is skipped: a SYNC word only starts a frame
+//     when FRAMESIZE fits in the file and CHK matches
+//
+// Header and command frames are skipped, as are data frames of no known
+// configuration. Archives with no configuration frame, e.g. BPA PDCstream
+// .dst files, which carry their own packet format, are rejected.
+use crate::accumulator::{AccumulatorError, BatchAccumulator, FlushPolicy};
+use crate::arrow_utils::ArrowOptions;
+use crate::budget::MemoryBudget;
+use crate::frame_parser::{frame_sequence, parse_config_frame_1and2};
+use arrow::record_batch::RecordBatch;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// What a scan of an archive found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveStats {
+    pub configs: usize,
+    pub data_frames: usize,   // Turned into rows
+    pub other_frames: usize,  // Header and command frames
+    pub unconfigured: usize,  // Data frames before a configuration of their stream
+    pub skipped_bytes: usize, // Vendor headers and damaged frames
+    pub rows: usize,
+}
+
+// The frames of an archive in file order, and the number of bytes skipped
+// around them.
+pub fn scan_frames(bytes: &[u8]) -> (Vec<&[u8]>, usize) {
+    let mut sequence = frame_sequence(bytes);
+    let frames = sequence.by_ref().filter_map(Result::ok).collect();
+    (frames, sequence.skipped())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PdatReader {
+    options: ArrowOptions,
+    batch_rows: Option<usize>,
+}
+
+impl PdatReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_arrow_options(mut self, options: ArrowOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // Rows per batch, by default a batch per configuration of a stream.
+    pub fn with_batch_rows(mut self, batch_rows: usize) -> Self {
+        self.batch_rows = Some(batch_rows.max(1));
+        self
+    }
+
+    // Read the archive bytes, handing each batch to emit with the IDCODE of
+    // its stream.
+    pub fn read(
+        &self,
+        bytes: &[u8],
+        mut emit: impl FnMut(u16, RecordBatch) -> io::Result<()>,
+    ) -> io::Result<ArchiveStats> {
+        let invalid =
+            |e: AccumulatorError| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e));
+        let mut policy = FlushPolicy::default();
+        if let Some(rows) = self.batch_rows {
+            policy = policy.with_max_rows(rows);
+        }
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited())
+            .with_arrow_options(self.options.clone())
+            .with_flush_policy(policy);
+        let mut known = Vec::new();
+        let (frames, skipped_bytes) = scan_frames(bytes);
+        let mut stats = ArchiveStats {
+            skipped_bytes,
+            ..ArchiveStats::default()
+        };
+        let mut emit = |idcode, batch: RecordBatch, stats: &mut ArchiveStats| {
+            stats.rows += batch.num_rows();
+            emit(idcode, batch)
+        };
+        for frame in frames {
+            let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+            match (frame[1] >> 4) & 0x07 {
+                2 | 3 => {
+                    let config = parse_config_frame_1and2(frame).map_err(|e| {
+                        io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))
+                    })?;
+                    if known.contains(&idcode) {
+                        if let Some(batch) = accumulator.remove_stream(idcode).map_err(invalid)? {
+                            emit(idcode, batch, &mut stats)?;
+                        }
+                    } else {
+                        known.push(idcode);
+                    }
+                    accumulator.add_stream(&config);
+                    stats.configs += 1;
+                }
+                0 if known.contains(&idcode) => match accumulator.push_frame(frame) {
+                    Ok(batch) => {
+                        stats.data_frames += 1;
+                        if let Some((idcode, batch)) = batch {
+                            emit(idcode, batch, &mut stats)?;
+                        }
+                    }
+                    // Not of the size of the configuration, CHK matched though
+                    Err(AccumulatorError::InvalidFrameSize { .. }) => stats.unconfigured += 1,
+                    Err(e) => return Err(invalid(e)),
+                },
+                0 => stats.unconfigured += 1,
+                _ => stats.other_frames += 1,
+            }
+        }
+        if stats.configs == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "No C37.118 configuration frame in archive",
+            ));
+        }
+        for (idcode, batch) in accumulator.flush_all().map_err(invalid)? {
+            emit(idcode, batch, &mut stats)?;
+        }
+        Ok(stats)
+    }
+
+    // All batches of an archive file.
+    pub fn read_file(&self, path: impl AsRef<Path>) -> io::Result<Vec<(u16, RecordBatch)>> {
+        let mut batches = Vec::new();
+        self.read(&fs::read(path)?, |idcode, batch| {
+            batches.push((idcode, batch));
+            Ok(())
+        })?;
+        Ok(batches)
+    }
+}
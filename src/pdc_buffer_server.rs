@@ -11,18 +11,27 @@
 //
 // It should return that data as arrow dataframes over IPC.
 //
+// With an area configuration (AREAS_FILE, see areas) it also serves the
+// areas at /areas and /areas/{name}, and the buffer data of an area's
+// stations at /areas/{name}/data.
+//
 // What it shouldn't do. (for now)
 // Send configuration commands to the upstream pdc server.
 //
 //#![allow(unused)]
-use crate::arrow_utils::{build_arrow_schema, extract_channel_values};
+use crate::areas::AreaMap;
+use crate::arrow_utils::build_record_batch;
 use crate::frames::ConfigurationFrame1and2_2011;
 use crate::pdc_client::{ControlMessage, PDCClient};
-use arrow::array::ArrayRef;
-use arrow::array::TimestampMicrosecondArray;
 use arrow::ipc::writer::FileWriter;
 use arrow::record_batch::RecordBatch;
-use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Router};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
 use bytes::Bytes;
 use std::env;
 use std::net::SocketAddr;
@@ -39,6 +48,7 @@ struct Config {
     pdc_idcode: u16,
     buffer_duration: Duration,
     server_port: u16,
+    areas_file: Option<String>,
 }
 
 impl Config {
@@ -63,6 +73,7 @@ impl Config {
                 .unwrap_or_else(|_| "8080".to_string())
                 .parse()
                 .map_err(|_| "Invalid SERVER_PORT")?,
+            areas_file: env::var("AREAS_FILE").ok(),
         })
     }
 }
@@ -74,6 +85,7 @@ struct AppState {
     //data_rx: mpsc::Receiver<Vec<u8>>,
     config: ConfigurationFrame1and2_2011,
     frame_size: usize,
+    areas: Arc<AreaMap>,
 }
 
 // Response for configuration endpoint
@@ -94,11 +106,11 @@ struct AppState {
 //StatusCode::NOT_IMPLEMENTED
 //}
 
-// Response for buffer data endpoint
-async fn get_buffer_data(
-    State(state): State<AppState>,
+// The whole buffer as a RecordBatch.
+async fn buffer_batch(
+    state: &AppState,
     data_rx: Arc<Mutex<mpsc::Receiver<Vec<u8>>>>,
-) -> Result<impl IntoResponse, StatusCode> {
+) -> Result<RecordBatch, StatusCode> {
     // Send request for buffer
     state
         .control_tx
@@ -116,42 +128,21 @@ async fn get_buffer_data(
     // Get channel map from config
     let channel_map = state.config.get_channel_map();
 
-    // Build Arrow schema
-    let schema = Arc::new(build_arrow_schema(&channel_map));
-
-    // Create arrays for each channel
-    let mut arrays: Vec<ArrayRef> = Vec::new();
-
-    // Add timestamp array
-    let mut timestamps = Vec::new();
-
-    for frame in buffer.chunks(state.frame_size) {
-        if frame.len() >= 14 {
-            let soc = u32::from_be_bytes([frame[6], frame[7], frame[8], frame[9]]);
-            let fracsec = u32::from_be_bytes([frame[10], frame[11], frame[12], frame[13]]);
-            timestamps.push((soc as i64) * 1_000_000 + (fracsec as i64));
-        }
-    }
-    arrays.push(Arc::new(TimestampMicrosecondArray::from(timestamps)));
-
-    // Extract values for each channel
-    for (_, info) in &channel_map {
-        let channel_arrays = extract_channel_values(&buffer, state.frame_size, info);
-        arrays.extend(channel_arrays);
-    }
-
     // Create RecordBatch
-    let record_batch = RecordBatch::try_new(schema.clone(), arrays)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    build_record_batch(&buffer, state.frame_size, &channel_map)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
 
-    // Serialize to Arrow IPC format
+// Serialize to Arrow IPC format
+fn ipc_response(record_batch: &RecordBatch) -> Result<impl IntoResponse, StatusCode> {
+    let schema = record_batch.schema();
     let mut buf = Vec::new();
     {
         let mut writer = FileWriter::try_new(&mut buf, &schema)
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
         writer
-            .write(&record_batch)
+            .write(record_batch)
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
         writer
@@ -166,9 +157,54 @@ async fn get_buffer_data(
     ))
 }
 
+// Response for buffer data endpoint
+async fn get_buffer_data(
+    State(state): State<AppState>,
+    data_rx: Arc<Mutex<mpsc::Receiver<Vec<u8>>>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let record_batch = buffer_batch(&state, data_rx).await?;
+    ipc_response(&record_batch)
+}
+
+async fn get_areas(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(state.areas.to_json())
+}
+
+async fn get_area(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    state
+        .areas
+        .area_json(&name)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+// Buffer data of the stations in an area
+async fn get_area_data(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    data_rx: Arc<Mutex<mpsc::Receiver<Vec<u8>>>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if state.areas.get(&name).is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let record_batch = buffer_batch(&state, data_rx).await?;
+    let selected = state
+        .areas
+        .select(&record_batch, &name)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    ipc_response(&selected)
+}
+
 pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
     // Load configuration
     let config = Config::from_env()?;
+    let areas = match &config.areas_file {
+        Some(path) => AreaMap::from_file(path)?,
+        None => AreaMap::default(),
+    };
 
     // Initialize PDC client
     let (mut pdc_client, control_tx, data_rx) = match PDCClient::new(
@@ -206,6 +242,7 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
         control_tx,
         config: pdc_config,
         frame_size,
+        areas: Arc::new(areas),
     };
 
     // Create a shared data receiver
@@ -219,6 +256,12 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
             "/data",
             get(move |state| get_buffer_data(state, data_rx_clone.clone())),
         )
+        .route("/areas", get(get_areas))
+        .route("/areas/:name", get(get_area))
+        .route(
+            "/areas/:name/data",
+            get(move |state, name| get_area_data(state, name, data_rx.clone())),
+        )
         .with_state(app_state);
 
     // Start server
@@ -5,22 +5,45 @@
 //
 // It uses a background thread to constantly read from the PDC server,
 // allowing the main thread to grab copies of the buffer when needed.
+//
+// The counters of the session (stats) are a snapshot cheap enough to take
+// on every refresh of a display, without the metrics endpoint.
+//
+// With a deduplicator, data frames already received, e.g. retransmitted by
+// the PDC after a reconnect, are dropped before they are stored or queued
+// (dedup::FrameDeduplicator).
 #![allow(unused)]
 use crate::{
+    arrow_utils::frame_timestamp_micros_with,
+    audit::{AuditDirection, AuditEntry, AuditLog, AuditOutcome},
+    config_cache::ConfigCache,
+    dedup::FrameDeduplicator,
+    events::{Event, EventBus, EventKind, Severity},
     frame_parser::parse_config_frame_1and2,
-    frames::{calculate_crc, CommandFrame2011, ConfigurationFrame1and2_2011, PrefixFrame2011},
+    frame_pool::FramePool,
+    frames::{CommandFrame2011, ConfigurationFrame1and2_2011, CrcMode, PrefixFrame2011},
+    framesize::{check_frame_size, repair_frame_size, FrameSizePolicy},
+    latency::{self, LatencyTracker, TimestampSource},
+    multicast::{self, MulticastConfig},
+    queue::RingProducer,
+    trace::LatencyTracer,
 };
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use std::collections::VecDeque;
 use std::io;
-use std::time::{Duration, SystemTime};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::mpsc; // For efficient byte management
+use tokio::sync::{mpsc, watch}; // For efficient byte management
+
+const STAT_CONFIG_CHANGE: u16 = 0x0400;
 
 // Define an enum to represent different buffer types
+// The stack variant is intentionally large, it is the fixed 30KB ring buffer.
+#[allow(clippy::large_enum_variant)]
 enum BufferType {
-    Stack([u8; 30 * 1024]),                // 30KB stack buffer
-    Heap(VecDeque<(SystemTime, Vec<u8>)>), // Heap buffer with timestamps
+    Stack([u8; 30 * 1024]),              // 30KB stack buffer
+    Heap(VecDeque<(SystemTime, Bytes)>), // Heap buffer with timestamps
 }
 
 pub enum ControlMessage {
@@ -29,8 +52,106 @@ pub enum ControlMessage {
     //GetBufferDuration(Duration),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientState {
+    Configured, // Configuration received, not streaming
+    Streaming,
+    Stalled, // Streaming, but no data for longer than the stall limit
+    Stopped,
+}
+
+// What the client is doing, updated from the stream loop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientStatus {
+    pub state: ClientState,
+    pub frames_received: u64,
+    pub stalls: u32,                 // Times the stream stalled
+    pub turn_on_resends: u32,        // Turn-on commands sent again because of a stall
+    pub config_change_pending: bool, // Last data frame had the STAT config change bit set
+    pub latency: LatencyTracker,     // Arrival time minus SOC/FRACSEC of the data frames
+}
+
+// Counters of the connection, cheap to copy for a health display. Frames
+// are counted by type as received, whole frames only; CRC failures are
+// counted for every frame and the frame is still passed on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    pub data_frames: u64,
+    pub config_frames: u64,
+    pub header_frames: u64,
+    pub other_frames: u64,
+    pub discarded: u64, // Partial reads, datagrams of the wrong size or from elsewhere
+    pub crc_failures: u64,
+    pub duplicates: u64, // Data frames received before, dropped
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub commands_sent: u64,
+    pub reconnects: u32,
+    pub last_command_us: Option<i64>, // Wall clock the last command was sent
+    pub last_response_us: Option<i64>, // Last configuration or header frame received
+    pub last_data_us: Option<i64>,    // Last data frame received
+}
+
+impl SessionStats {
+    fn count_frame(&mut self, frame: &[u8], crc_ok: bool, now_us: i64) {
+        self.bytes_received += frame.len() as u64;
+        if !crc_ok {
+            self.crc_failures += 1;
+        }
+        match frame.get(1).map(|byte| (byte >> 4) & 0x07) {
+            Some(0) => {
+                self.data_frames += 1;
+                self.last_data_us = Some(now_us);
+            }
+            Some(1) => {
+                self.header_frames += 1;
+                self.last_response_us = Some(now_us);
+            }
+            Some(2 | 3 | 5) => {
+                self.config_frames += 1;
+                self.last_response_us = Some(now_us);
+            }
+            _ => self.other_frames += 1,
+        }
+    }
+}
+
+// Silence longer than intervals reporting intervals is a stall. Optionally
+// the turn-on command is sent again, once per stall limit, up to max_resends
+// times per stall. Silence is checked after every read, at least once a
+// second because of the read timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StallPolicy {
+    pub intervals: u32, // 0 disables stall detection
+    pub resend_turn_on: bool,
+    pub max_resends: u32,
+}
+
+impl StallPolicy {
+    pub fn new(intervals: u32) -> Self {
+        StallPolicy {
+            intervals,
+            resend_turn_on: false,
+            max_resends: 0,
+        }
+    }
+
+    pub fn with_resend_turn_on(mut self, max_resends: u32) -> Self {
+        self.resend_turn_on = max_resends > 0;
+        self.max_resends = max_resends;
+        self
+    }
+}
+
+impl Default for StallPolicy {
+    fn default() -> Self {
+        StallPolicy::new(30)
+    }
+}
+
 pub struct PDCClient {
     stream: tokio::net::TcpStream,
+    address: String, // host:port connected to
     //allocate 30 kB to the stack to serve as a ring buffer.
     buffer: BufferType,
     duration: Duration,
@@ -43,6 +164,24 @@ pub struct PDCClient {
     control_rx: mpsc::Receiver<ControlMessage>,
     data_tx: mpsc::Sender<Vec<u8>>,
     pub config: Option<ConfigurationFrame1and2_2011>,
+    audit: Option<Arc<AuditLog>>, // Records every sent command
+    stall_policy: StallPolicy,
+    status: watch::Sender<ClientStatus>,
+    stats: watch::Sender<SessionStats>,
+    events: Option<EventBus>, // StreamStalled and StreamResumed are published here
+    udp: Option<tokio::net::UdpSocket>, // Commanded UDP: data arrives here, commands on stream
+    multicast: Option<MulticastConfig>, // Group udp joined, if any
+    rx_timestamps: bool,      // Kernel/NIC receive timestamps enabled on udp
+    arrival: Option<(i64, TimestampSource)>, // Of the frame read last
+    frames: Option<RingProducer>, // Every data frame is also pushed here
+    pool: FramePool,          // Receive buffers of the data frames
+    crc_mode: CrcMode,        // Check of the configuration frame's CHK
+    cache: Option<(Arc<ConfigCache>, String)>, // With the source the client is cached as
+    refresh: bool,            // Configuration requested again, answer not read yet
+    command_buf: Vec<u8>,     // Read from the TCP stream while refreshing
+    tracer: Option<Arc<LatencyTracer>>, // Stamps the arrival of sampled data frames
+    frame_size_policy: FrameSizePolicy, // For datagrams not of the configured size
+    dedup: Option<FrameDeduplicator>, // Data frames received recently
 }
 
 impl PDCClient {
@@ -52,6 +191,63 @@ impl PDCClient {
         port: u16,
         idcode: u16,
         duration: Duration,
+    ) -> Result<(Self, mpsc::Sender<ControlMessage>, mpsc::Receiver<Vec<u8>>), std::io::Error> {
+        Self::new_with_audit(host, port, idcode, duration, None).await
+    }
+
+    // Same as new, recording every command sent to the server in the audit log,
+    // including the initial configuration request.
+    pub async fn new_with_audit(
+        host: &str,
+        port: u16,
+        idcode: u16,
+        duration: Duration,
+        audit: Option<Arc<AuditLog>>,
+    ) -> Result<(Self, mpsc::Sender<ControlMessage>, mpsc::Receiver<Vec<u8>>), std::io::Error> {
+        Self::connect(host, port, idcode, duration, audit, None, CrcMode::Standard).await
+    }
+
+    // Same as new with a configuration known from before, e.g. a checkpoint,
+    // instead of requesting it from the server.
+    pub async fn new_with_config(
+        host: &str,
+        port: u16,
+        idcode: u16,
+        duration: Duration,
+        config: ConfigurationFrame1and2_2011,
+    ) -> Result<(Self, mpsc::Sender<ControlMessage>, mpsc::Receiver<Vec<u8>>), std::io::Error> {
+        Self::connect(
+            host,
+            port,
+            idcode,
+            duration,
+            None,
+            Some(config),
+            CrcMode::Standard,
+        )
+        .await
+    }
+
+    // Same as new for a device computing the CHK of its configuration frame
+    // another way.
+    pub async fn new_with_crc_mode(
+        host: &str,
+        port: u16,
+        idcode: u16,
+        duration: Duration,
+        crc_mode: CrcMode,
+    ) -> Result<(Self, mpsc::Sender<ControlMessage>, mpsc::Receiver<Vec<u8>>), std::io::Error> {
+        Self::connect(host, port, idcode, duration, None, None, crc_mode).await
+    }
+
+    async fn connect(
+        host: &str,
+        port: u16,
+        idcode: u16,
+        duration: Duration,
+        audit: Option<Arc<AuditLog>>,
+        config: Option<ConfigurationFrame1and2_2011>,
+        crc_mode: CrcMode,
     ) -> Result<(Self, mpsc::Sender<ControlMessage>, mpsc::Receiver<Vec<u8>>), std::io::Error> {
         println!("Attempting to connect to {}:{}", host, port);
         let addr = format!("{}:{}", host, port);
@@ -72,6 +268,7 @@ impl PDCClient {
         let max_buffer_size = STACK_SIZE - (STACK_SIZE % frame_size);
         let mut client = PDCClient {
             stream,
+            address: addr,
             buffer: BufferType::Stack([0; 30 * 1024]),
             duration,
             max_buffer_size,
@@ -82,22 +279,274 @@ impl PDCClient {
             control_rx,
             data_tx,
             config: None,
+            audit,
+            stall_policy: StallPolicy::default(),
+            status: watch::channel(ClientStatus {
+                state: ClientState::Stopped,
+                frames_received: 0,
+                stalls: 0,
+                turn_on_resends: 0,
+                config_change_pending: false,
+                latency: LatencyTracker::new(),
+            })
+            .0,
+            stats: watch::channel(SessionStats::default()).0,
+            events: None,
+            udp: None,
+            multicast: None,
+            rx_timestamps: false,
+            arrival: None,
+            frames: None,
+            pool: FramePool::new(),
+            crc_mode,
+            cache: None,
+            refresh: false,
+            command_buf: Vec::new(),
+            tracer: None,
+            frame_size_policy: FrameSizePolicy::Strict,
+            dedup: None,
         };
 
         // Get initial configuration
-        println!("Getting configuration");
-        let config = client.get_config_frame().await.unwrap();
+        let config = match config {
+            Some(config) => {
+                println!("Using known configuration");
+                client.frame_size = config.calc_data_frame_size();
+                config
+            }
+            None => {
+                println!("Getting configuration");
+                client.get_config_frame().await?
+            }
+        };
         client.config = Some(config);
         println!("Got Configuration: {} PMUs", 1);
         client.initialize_buffer()?;
+        client.set_state(ClientState::Configured);
 
         Ok((client, control_tx, data_rx))
     }
 
+    pub fn with_stall_policy(mut self, stall_policy: StallPolicy) -> Self {
+        self.stall_policy = stall_policy;
+        self
+    }
+
+    // Commanded UDP: keep sending commands over TCP but receive the data
+    // frames by UDP on this port. Datagrams from other hosts or with another
+    // idcode than the configuration are dropped.
+    pub async fn with_udp_data(mut self, port: u16) -> io::Result<Self> {
+        let local = self.stream.local_addr()?;
+        let socket = tokio::net::UdpSocket::bind((local.ip(), port)).await?;
+        println!("Receiving data by UDP on {}", socket.local_addr()?);
+        self.udp = Some(socket);
+        Ok(self)
+    }
+
+    // Same as with_udp_data for data sent to a multicast group. With sources
+    // only their datagrams are taken, joined source-specific, otherwise the
+    // server's like with_udp_data.
+    pub async fn with_multicast_data(
+        mut self,
+        port: u16,
+        config: &MulticastConfig,
+    ) -> io::Result<Self> {
+        let socket = multicast::bind(config, port)?;
+        println!(
+            "Receiving data from multicast group {} on port {}",
+            config.group, port
+        );
+        self.udp = Some(socket);
+        self.multicast = Some(config.clone());
+        Ok(self)
+    }
+
+    // Use the kernel's receive timestamps of the UDP data, or the NIC's when
+    // hardware is set and the interface supports it, as the arrival time of
+    // frames instead of the time the read returned. Needs with_udp_data and
+    // Linux. Hardware timestamps are in the NIC's clock, which must be
+    // synchronized to UTC (e.g. by PTP) for the latency to be meaningful.
+    pub fn with_rx_timestamps(mut self, hardware: bool) -> io::Result<Self> {
+        let Some(udp) = &self.udp else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Receive timestamps need UDP data, call with_udp_data first",
+            ));
+        };
+        latency::enable_rx_timestamps(udp, hardware)?;
+        self.rx_timestamps = true;
+        Ok(self)
+    }
+
+    // Save the configuration to the cache under source (host:port), now and
+    // whenever it is refreshed.
+    pub fn with_config_cache(mut self, cache: Arc<ConfigCache>, source: &str) -> Self {
+        self.cache = Some((cache, source.to_string()));
+        self.cache_config();
+        self
+    }
+
+    // Request the configuration again once streaming and switch to it when
+    // it arrives, for a configuration known from before that may be stale.
+    // Until then the data frames are parsed with the known one. Needs
+    // with_udp_data, with data on TCP the answer would be mixed with them.
+    pub fn with_config_refresh(mut self) -> io::Result<Self> {
+        if self.udp.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Refreshing the configuration needs UDP data, call with_udp_data first",
+            ));
+        }
+        self.refresh = true;
+        Ok(self)
+    }
+
+    fn cache_config(&self) {
+        let (Some((cache, source)), Some(config)) = (&self.cache, &self.config) else {
+            return;
+        };
+        match cache.store(source, self.idcode, &config.to_hex()) {
+            Ok(true) => println!("Cached configuration of {}", source),
+            Ok(false) => {}
+            Err(e) => println!("Failed to cache configuration of {}: {}", source, e),
+        }
+    }
+
+    // Push every data frame to this queue as it arrives. Frames are dropped
+    // rather than waited for when the queue is full, so a slow consumer does
+    // not hold up reading the socket.
+    pub fn with_frame_queue(mut self, frames: RingProducer) -> Self {
+        self.frames = Some(frames);
+        self
+    }
+
+    // Start the latency traces of data frames (trace::LatencyTracer).
+    pub fn with_tracer(mut self, tracer: Arc<LatencyTracer>) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    // Repair the datagrams with trailing bytes or a wrong FRAMESIZE instead of
+    // discarding them, when their CHK still checks (framesize).
+    pub fn with_frame_size_policy(mut self, policy: FrameSizePolicy) -> Self {
+        self.frame_size_policy = policy;
+        self
+    }
+
+    // Drop the data frames this deduplicator has seen before.
+    pub fn with_dedup(mut self, dedup: FrameDeduplicator) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
+
+    pub fn with_event_bus(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub fn status(&self) -> ClientStatus {
+        *self.status.borrow()
+    }
+
+    // Follow the status while start_stream runs in another task.
+    pub fn status_receiver(&self) -> watch::Receiver<ClientStatus> {
+        self.status.subscribe()
+    }
+
+    // Counters of the session so far, across reconnects.
+    pub fn stats(&self) -> SessionStats {
+        *self.stats.borrow()
+    }
+
+    // Follow the counters while start_stream runs in another task.
+    pub fn stats_receiver(&self) -> watch::Receiver<SessionStats> {
+        self.stats.subscribe()
+    }
+
+    fn count_frame(&self, frame: &[u8]) {
+        let crc_ok = self.crc_mode.check(frame);
+        let now_us = latency::now_micros();
+        self.stats
+            .send_modify(|stats| stats.count_frame(frame, crc_ok, now_us));
+    }
+
+    fn count_discarded(&self, bytes: usize) {
+        self.stats.send_modify(|stats| {
+            stats.discarded += 1;
+            stats.bytes_received += bytes as u64;
+        });
+    }
+
+    // Connect to the server again after the connection was lost or the
+    // stream stopped, and request the configuration again. Buffers, status
+    // and statistics are kept; UDP data needs with_udp_data again. Call
+    // start_stream to resume.
+    pub async fn reconnect(&mut self) -> io::Result<()> {
+        println!("Reconnecting to {}", self.address);
+        self.stream = tokio::net::TcpStream::connect(&self.address)
+            .await
+            .map_err(|e| {
+                println!("Failed to reconnect to PDC server: {}", e);
+                io::Error::new(io::ErrorKind::ConnectionRefused, e)
+            })?;
+        self.stats.send_modify(|stats| stats.reconnects += 1);
+        self.command_buf.clear();
+        let config = self.get_config_frame().await?;
+        if self.config.as_ref() != Some(&config) {
+            println!("Configuration changed while disconnected");
+            self.config = Some(config);
+            self.write_offset = 0;
+            self.initialize_buffer()?;
+        }
+        self.set_state(ClientState::Configured);
+        Ok(())
+    }
+
+    fn set_state(&self, state: ClientState) {
+        self.status.send_modify(|status| status.state = state);
+    }
+
+    // Time between data frames from the configured data rate: frames per
+    // second when positive, seconds per frame when negative.
+    fn reporting_interval(&self) -> Duration {
+        match self.config.as_ref().map_or(0, |config| config.data_rate) {
+            rate if rate > 0 => Duration::from_secs_f64(1.0 / rate as f64),
+            rate if rate < 0 => Duration::from_secs(rate.unsigned_abs() as u64),
+            _ => Duration::from_secs_f64(1.0 / 30.0),
+        }
+    }
+
+    fn stall_limit(&self) -> Option<Duration> {
+        (self.stall_policy.intervals > 0)
+            .then(|| self.reporting_interval() * self.stall_policy.intervals)
+    }
+
+    fn station(&self) -> String {
+        self.config
+            .as_ref()
+            .and_then(|config| config.pmu_configs.first())
+            .map(|pmu| String::from_utf8_lossy(&pmu.stn).trim().to_string())
+            .unwrap_or_else(|| self.idcode.to_string())
+    }
+
+    fn publish(&self, kind: EventKind, severity: Severity, message: String, silence: Duration) {
+        let Some(events) = &self.events else {
+            return;
+        };
+        let now_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as i64);
+        events.publish(
+            Event::new(now_us, kind, &self.station(), message)
+                .with_severity(severity)
+                .with_value("silence_s", silence.as_secs_f64()),
+        );
+    }
+
     fn calculate_frame_size(&self) -> usize {
         // Calculate frame size based on configuration
         // This will depend on your specific PMU configuration
-        let frame_size = if let Some(ref config) = self.config {
+        if let Some(ref config) = self.config {
             // Start with the common frame size (prefix + chk)
             let mut size = 14 + 2;
 
@@ -120,9 +569,7 @@ impl PDCClient {
             size
         } else {
             0
-        };
-
-        frame_size
+        }
     }
 
     fn initialize_buffer(&mut self) -> Result<(), std::io::Error> {
@@ -151,7 +598,7 @@ impl PDCClient {
         Ok(())
     }
     pub fn get_config(&mut self) -> Option<ConfigurationFrame1and2_2011> {
-        return self.config.clone();
+        self.config.clone()
     }
 
     pub async fn get_config_frame(&mut self) -> io::Result<ConfigurationFrame1and2_2011> {
@@ -159,11 +606,10 @@ impl PDCClient {
 
         // Create command frame for config request
         let cmd_frame = CommandFrame2011::new_send_config_frame1(self.idcode);
-        let cmd_bytes = cmd_frame.to_hex();
 
         // Send command
         println!("Sending config request command...");
-        self.stream.write_all(&cmd_bytes).await?;
+        self.send_command(cmd_frame).await?;
         println!("Config request command sent");
 
         // Read response
@@ -183,14 +629,15 @@ impl PDCClient {
             complete_frame.extend_from_slice(&header_buf);
             complete_frame.extend_from_slice(&config_buf);
 
+            self.count_frame(&complete_frame);
             // Verify CRC
-            let calculated_crc = calculate_crc(&complete_frame[..complete_frame.len() - 2]);
+            let calculated_crc = self.crc_mode.calculate(&complete_frame).unwrap_or(0);
             let frame_crc = u16::from_be_bytes([
                 complete_frame[prefix.framesize as usize - 2],
                 complete_frame[prefix.framesize as usize - 1],
             ]);
 
-            if calculated_crc != frame_crc {
+            if !self.crc_mode.check(&complete_frame) {
                 println!(
                     "CRC mismatch: calculated={:04x}, received={:04x}",
                     calculated_crc, frame_crc
@@ -227,10 +674,13 @@ impl PDCClient {
         }
     }
 
-    async fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
-        let mut buf = vec![0u8; self.frame_size];
+    async fn read_frame(&mut self) -> io::Result<Option<Bytes>> {
+        if self.udp.is_some() {
+            return self.read_datagram().await;
+        }
+        let buf = self.pool.buffer(self.frame_size);
 
-        match tokio::time::timeout(Duration::from_secs(1), self.stream.read(&mut buf)).await {
+        match tokio::time::timeout(Duration::from_secs(1), self.stream.read(buf)).await {
             Ok(Ok(n)) => {
                 if n == 0 {
                     println!("Connection closed by server");
@@ -243,9 +693,11 @@ impl PDCClient {
                 }
                 if n == self.frame_size {
                     //println!("Successfully read complete frame of size {}", n);
-                    Ok(Some(buf))
+                    self.arrival = Some((latency::now_micros(), TimestampSource::Userspace));
+                    Ok(Some(self.pool.freeze(n)))
                 } else {
                     println!("Partial read: {} bytes of expected {}", n, self.frame_size);
+                    self.count_discarded(n);
                     // You might want to handle partial reads differently
                     Ok(None)
                 }
@@ -261,6 +713,159 @@ impl PDCClient {
         }
     }
 
+    async fn read_datagram(&mut self) -> io::Result<Option<Bytes>> {
+        let Some(udp) = &self.udp else {
+            return Ok(None);
+        };
+        let rx_timestamps = self.rx_timestamps;
+        // One byte more than a frame, so longer datagrams show as such, or
+        // all of them when they may be repaired
+        let lenient = self.frame_size_policy == FrameSizePolicy::Lenient;
+        let buf = if lenient {
+            self.pool.buffer(u16::MAX as usize)
+        } else {
+            self.pool.buffer(self.frame_size + 1)
+        };
+        let receive = async {
+            if rx_timestamps {
+                latency::recv_timestamped(udp, buf).await
+            } else {
+                let (n, from) = udp.recv_from(buf).await?;
+                Ok((n, from, latency::now_micros(), TimestampSource::Userspace))
+            }
+        };
+        // While refreshing the answer is read from the TCP stream meanwhile
+        let stream = &mut self.stream;
+        let command_buf = &mut self.command_buf;
+        let refresh = self.refresh;
+        let command = async {
+            if refresh {
+                stream.read_buf(command_buf).await
+            } else {
+                std::future::pending().await
+            }
+        };
+        let received = tokio::select! {
+            received = tokio::time::timeout(Duration::from_secs(1), receive) => received,
+            read = command => {
+                match read {
+                    Ok(0) => {
+                        println!("Connection closed by server");
+                        return Err(io::Error::new(
+                            io::ErrorKind::ConnectionAborted,
+                            "Server closed connection",
+                        ));
+                    }
+                    Ok(_) => self.take_refreshed_config(),
+                    Err(e) => {
+                        println!("Error reading from stream: {}", e);
+                        return Err(e);
+                    }
+                }
+                return Ok(None);
+            }
+        };
+        let (n, from, arrival_us, source) = match received {
+            Ok(Ok(received)) => received,
+            Ok(Err(e)) => {
+                println!("Error reading UDP datagram: {}", e);
+                return Err(e);
+            }
+            Err(_) => {
+                println!("Timeout reading frame");
+                return Ok(None);
+            }
+        };
+        let server = self.stream.peer_addr().ok().map(|addr| addr.ip());
+        let accepted = match &self.multicast {
+            Some(config) if !config.sources.is_empty() => config.accepts(from.ip()),
+            _ => server == Some(from.ip()),
+        };
+        if !accepted {
+            println!("Ignoring datagram from {}", from);
+            self.count_discarded(n);
+            return Ok(None);
+        }
+        if n != self.frame_size && !lenient {
+            println!("Datagram of {} bytes, expected {}", n, self.frame_size);
+            self.count_discarded(n);
+            return Ok(None);
+        }
+        let mut frame = self.pool.freeze(n);
+        if lenient {
+            if let Some(issue) = check_frame_size(&frame, self.frame_size) {
+                match repair_frame_size(&frame, self.frame_size, self.crc_mode) {
+                    Some(repaired) => frame = Bytes::from(repaired),
+                    None => {
+                        println!("Discarded datagram: {}", issue);
+                        self.count_discarded(n);
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+        let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+        let expected = self.config.as_ref().map(|config| config.prefix.idcode);
+        if expected.is_some_and(|expected| expected != idcode) {
+            println!("Ignoring frame with idcode {}", idcode);
+            self.count_discarded(n);
+            return Ok(None);
+        }
+        self.arrival = Some((arrival_us, source));
+        Ok(Some(frame))
+    }
+
+    // Once the frame requested by with_config_refresh has been read, use it
+    // from now on and hand it to the frame queue before the data frames
+    // following it. Other frames on the stream are skipped.
+    fn take_refreshed_config(&mut self) {
+        while self.command_buf.len() >= 4 {
+            let framesize = u16::from_be_bytes([self.command_buf[2], self.command_buf[3]]) as usize;
+            if framesize < 4 {
+                self.command_buf.clear();
+                return;
+            }
+            if self.command_buf.len() < framesize {
+                return;
+            }
+            let frame: Vec<u8> = self.command_buf.drain(..framesize).collect();
+            self.count_frame(&frame);
+            if !matches!((frame[1] >> 4) & 0x07, 2 | 3) {
+                continue;
+            }
+            if !self.crc_mode.check(&frame) {
+                println!("CRC mismatch in the refreshed configuration frame");
+                continue;
+            }
+            let config = match parse_config_frame_1and2(&frame) {
+                Ok(config) => config,
+                Err(e) => {
+                    println!("Failed to parse the refreshed configuration: {:?}", e);
+                    continue;
+                }
+            };
+            if self.config.as_ref() == Some(&config) {
+                println!("Configuration unchanged");
+            } else {
+                println!("Configuration changed, switching to the one received");
+                self.config = Some(config);
+                self.write_offset = 0;
+                if let Err(e) = self.initialize_buffer() {
+                    println!("Failed to resize the buffer: {}", e);
+                }
+                if let Some(frames) = &mut self.frames {
+                    if !frames.push(Bytes::from(frame)) {
+                        println!("Frame queue full, dropping configuration frame");
+                    }
+                }
+                self.cache_config();
+            }
+            self.refresh = false;
+            self.command_buf.clear();
+            return;
+        }
+    }
+
     pub async fn start_stream(&mut self) {
         println!("PDC client stream starting...");
         let control_tx = self.control_tx.clone();
@@ -268,15 +873,28 @@ impl PDCClient {
         // Send command to start data transmission
         println!("Sending command to start data transmission...");
         let cmd_frame = CommandFrame2011::new_turn_on_transmission(self.idcode);
-        if let Err(e) = self.stream.write_all(&cmd_frame.to_hex()).await {
+        if let Err(e) = self.send_command(cmd_frame).await {
             println!("Failed to send start transmission command: {}", e);
             self.shutdown().await;
             return;
         }
+        if self.refresh {
+            println!("Requesting the configuration again...");
+            let cmd_frame = CommandFrame2011::new_send_config_frame1(self.idcode);
+            if let Err(e) = self.send_command(cmd_frame).await {
+                println!("Failed to request the configuration: {}", e);
+                self.refresh = false;
+            }
+        }
 
         let data_tx = self.data_tx.clone();
         let mut control_rx = std::mem::replace(&mut self.control_rx, mpsc::channel(32).1);
 
+        self.set_state(ClientState::Streaming);
+        let mut last_frame = Instant::now();
+        let mut last_turn_on = Instant::now();
+        let mut resends = 0; // In the current stall
+
         let mut consecutive_errors = 0;
         const MAX_CONSECUTIVE_ERRORS: u32 = 10;
         loop {
@@ -313,7 +931,34 @@ impl PDCClient {
                         Ok(Some(frame)) => {
                             consecutive_errors = 0; //reset error cnt
                             //println!("PDC client received frame of size {}", frame.len());
-                            self.store_frame(&frame);
+                            if self.status().state == ClientState::Stalled {
+                                let silence = last_frame.elapsed();
+                                println!("Stream resumed after {:.1} s", silence.as_secs_f64());
+                                self.set_state(ClientState::Streaming);
+                                self.publish(
+                                    EventKind::StreamResumed,
+                                    Severity::Info,
+                                    format!("Data resumed after {:.1} s", silence.as_secs_f64()),
+                                    silence,
+                                );
+                            }
+                            last_frame = Instant::now();
+                            resends = 0;
+                            let arrival = self
+                                .arrival
+                                .take()
+                                .unwrap_or((latency::now_micros(), TimestampSource::Userspace));
+                            self.frame_received(&frame, arrival);
+                            if self.is_duplicate(&frame) {
+                                self.stats.send_modify(|stats| stats.duplicates += 1);
+                            } else {
+                                self.store_frame(&frame, arrival.0);
+                                if let Some(frames) = &mut self.frames {
+                                    if !frames.push(frame) {
+                                        println!("Frame queue full, dropping frame");
+                                    }
+                                }
+                            }
                         }
                         Ok(None) => {
                             consecutive_errors += 1;
@@ -324,6 +969,42 @@ impl PDCClient {
                             consecutive_errors +=1 ;
                         }
                     }
+                    if let Some(limit) = self.stall_limit() {
+                        let silence = last_frame.elapsed();
+                        if silence >= limit && self.status().state == ClientState::Streaming {
+                            println!("Stream stalled, no data for {:.1} s", silence.as_secs_f64());
+                            self.status.send_modify(|status| {
+                                status.state = ClientState::Stalled;
+                                status.stalls += 1;
+                            });
+                            self.publish(
+                                EventKind::StreamStalled,
+                                Severity::Warning,
+                                format!(
+                                    "No data for {:.1} s ({} reporting intervals)",
+                                    silence.as_secs_f64(),
+                                    self.stall_policy.intervals
+                                ),
+                                silence,
+                            );
+                        }
+                        if silence >= limit
+                            && self.stall_policy.resend_turn_on
+                            && resends < self.stall_policy.max_resends
+                            && last_turn_on.elapsed() >= limit
+                        {
+                            println!("Sending turn-on command again");
+                            let cmd_frame = CommandFrame2011::new_turn_on_transmission(self.idcode);
+                            if let Err(e) = self.send_command(cmd_frame).await {
+                                println!("Failed to resend start transmission command: {}", e);
+                            }
+                            last_turn_on = Instant::now();
+                            resends += 1;
+                            self.status.send_modify(|status| status.turn_on_resends += 1);
+                            // Give the server time to answer before giving up
+                            consecutive_errors = 0;
+                        }
+                    }
                     if consecutive_errors >= MAX_CONSECUTIVE_ERRORS{
                         println!("Too many consecutive errors, shutting down");
                         break;
@@ -333,16 +1014,70 @@ impl PDCClient {
         }
         self.shutdown().await;
         self.control_rx = control_rx;
+        self.set_state(ClientState::Stopped);
         println!("PDC client stream ending...");
     }
 
+    // Count the frame, follow the config change bit of the first PMU's STAT
+    // and track the latency from its timestamp to its arrival.
+    fn frame_received(&self, frame: &[u8], (arrival_us, source): (i64, TimestampSource)) {
+        self.count_frame(frame);
+        let config_change = frame.len() >= 16
+            && u16::from_be_bytes([frame[14], frame[15]]) & STAT_CONFIG_CHANGE != 0;
+        if config_change && !self.status().config_change_pending {
+            println!("PDC flagged a configuration change");
+        }
+        let time_base = self
+            .config
+            .as_ref()
+            .map_or(1_000_000, |config| config.time_base);
+        if let Some(tracer) = &self.tracer {
+            tracer.received(frame, time_base, arrival_us);
+        }
+        self.status.send_modify(|status| {
+            status.frames_received += 1;
+            status.config_change_pending = config_change;
+            if let Some(frame_us) = frame_timestamp_micros_with(frame, time_base) {
+                status.latency.record(frame_us, arrival_us, source);
+            }
+        });
+    }
+
+    fn is_duplicate(&mut self, frame: &[u8]) -> bool {
+        let crc_ok = self.crc_mode.check(frame);
+        self.dedup
+            .as_mut()
+            .is_some_and(|dedup| dedup.is_duplicate(frame, crc_ok))
+    }
+
+    async fn send_command(&mut self, mut cmd_frame: CommandFrame2011) -> io::Result<()> {
+        cmd_frame.stamp_now();
+        let bytes = cmd_frame.to_hex();
+        self.stream.write_all(&bytes).await?;
+        self.stats.send_modify(|stats| {
+            stats.commands_sent += 1;
+            stats.bytes_sent += bytes.len() as u64;
+            stats.last_command_us = Some(cmd_frame.timestamp_micros());
+        });
+        if let Some(audit) = &self.audit {
+            audit.record_or_log(&AuditEntry::from_command(
+                AuditDirection::Sent,
+                &cmd_frame,
+                self.stream.local_addr().ok(),
+                self.stream.peer_addr().ok(),
+                AuditOutcome::Sent,
+            ));
+        }
+        Ok(())
+    }
+
     pub fn get_control_sender(&self) -> mpsc::Sender<ControlMessage> {
         self.control_tx.clone()
     }
 
     // End of Receive loop.
     //
-    fn store_frame(&mut self, frame_data: &[u8]) {
+    fn store_frame(&mut self, frame_data: &Bytes, arrival_us: i64) {
         //println!("Storing data frame");
         match &mut self.buffer {
             BufferType::Stack(buffer) => {
@@ -360,12 +1095,12 @@ impl PDCClient {
                 self.write_offset += self.frame_size;
             }
             BufferType::Heap(buffer) => {
-                let now = SystemTime::now();
-                buffer.push_back((now, frame_data.to_vec()));
+                let now = UNIX_EPOCH + Duration::from_micros(arrival_us.max(0) as u64);
+                buffer.push_back((now, frame_data.clone()));
 
                 // Remove old frames based on buffer duration
                 while let Some((timestamp, _)) = buffer.front() {
-                    if now.duration_since(*timestamp).unwrap() > self.duration {
+                    if now.duration_since(*timestamp).unwrap_or_default() > self.duration {
                         buffer.pop_front();
                     } else {
                         break;
@@ -402,7 +1137,7 @@ impl PDCClient {
 
         // Send stop command to PDC server
         let cmd_frame = CommandFrame2011::new_turn_off_transmission(self.idcode);
-        if let Err(e) = self.stream.write_all(&cmd_frame.to_hex()).await {
+        if let Err(e) = self.send_command(cmd_frame).await {
             println!("Failed to send stop transmission command: {}", e);
         }
 
@@ -410,6 +1145,10 @@ impl PDCClient {
         if let Err(e) = self.stream.shutdown().await {
             println!("Error shutting down stream: {}", e);
         }
+        if self.udp.take().is_some() {
+            println!("Closed UDP data socket");
+        }
+        self.multicast = None;
 
         // Clear the buffer
         match &mut self.buffer {
@@ -7,7 +7,7 @@
 use std::error::Error;
 
 use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, UdpSocket};
 use tokio::time::{self, Duration};
 
 #[derive(Debug, Clone)]
@@ -16,9 +16,178 @@ pub enum Protocol {
     UDP,
 }
 
-use crate::frame_parser::{parse_frame, Frame};
+use crate::audit::{command_name, AuditDirection, AuditEntry, AuditLog, AuditOutcome};
+use crate::frame_parser::{parse_config_frame_1and2, parse_frame, Frame};
+use crate::frames::{now_micros, CommandFrame2011, ConfigurationFrame1and2_2011};
+use crate::metrics::Metrics;
+use crate::rate_conversion::RateConverter;
+use crate::replay::PlaybackOptions;
+use crate::simulator::{Scenario, Simulator};
+use crate::virtual_pmu::{VirtualStream, VirtualStreamConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientPermission {
+    Denied,   // Connection is refused
+    ReadOnly, // May request header and configuration frames
+    Control,  // May also turn transmission on/off and send extended frames
+}
+
+impl ClientPermission {
+    // Permission a client needs to issue a command frame command.
+    pub fn required_for(command: u16) -> ClientPermission {
+        match command {
+            3..=6 => ClientPermission::ReadOnly,
+            _ => ClientPermission::Control,
+        }
+    }
+}
+
+// Per-client permissions, keyed by source address. C37.118 has no authentication of
+// its own, clients are identified by the address they connect from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommandPolicy {
+    // Permission of the clients not listed
+    pub default: ClientPermission,
+    // Permission by client address, e.g. {"10.0.0.5": "control"}
+    pub clients: HashMap<IpAddr, ClientPermission>,
+}
+
+impl Default for CommandPolicy {
+    // Every client has control, the behaviour of a server without a policy.
+    fn default() -> Self {
+        CommandPolicy::new(ClientPermission::Control)
+    }
+}
+
+// Server section of a pipeline configuration.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    // Who may issue which command frames
+    pub permissions: CommandPolicy,
+}
+
+impl CommandPolicy {
+    pub fn new(default: ClientPermission) -> Self {
+        CommandPolicy {
+            default,
+            clients: HashMap::new(),
+        }
+    }
+
+    pub fn with_client(mut self, ip: IpAddr, permission: ClientPermission) -> Self {
+        self.clients.insert(ip, permission);
+        self
+    }
+
+    pub fn permission(&self, ip: &IpAddr) -> ClientPermission {
+        self.clients.get(ip).copied().unwrap_or(self.default)
+    }
+
+    pub fn authorize(&self, ip: &IpAddr, command: u16) -> bool {
+        self.permission(ip) >= ClientPermission::required_for(command)
+    }
+}
+
+// Checks the user data of an extended frame, Err with the reason to reject it.
+pub type ExtendedFrameHook = Arc<dyn Fn(&[u8]) -> Result<(), String> + Send + Sync>;
+
+// What the server takes in extended frames (command 8), whose content the
+// standard leaves to the user. They are denied unless allowed; allowed ones
+// must be whole 16-bit words, no longer than max_bytes, and pass every hook.
+// Data after the command word of any other command is rejected either way.
+#[derive(Clone)]
+pub struct ExtendedFramePolicy {
+    pub allow: bool,
+    pub max_bytes: usize,
+    hooks: Vec<(String, ExtendedFrameHook)>, // By name, for the reason logged
+}
+
+impl Default for ExtendedFramePolicy {
+    fn default() -> Self {
+        ExtendedFramePolicy::deny()
+    }
+}
+
+impl std::fmt::Debug for ExtendedFramePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hooks: Vec<&str> = self.hooks.iter().map(|(name, _)| name.as_str()).collect();
+        f.debug_struct("ExtendedFramePolicy")
+            .field("allow", &self.allow)
+            .field("max_bytes", &self.max_bytes)
+            .field("hooks", &hooks)
+            .finish()
+    }
+}
+
+impl ExtendedFramePolicy {
+    pub fn deny() -> Self {
+        ExtendedFramePolicy {
+            allow: false,
+            max_bytes: 0,
+            hooks: Vec::new(),
+        }
+    }
+
+    pub fn allow(max_bytes: usize) -> Self {
+        ExtendedFramePolicy {
+            allow: true,
+            max_bytes,
+            hooks: Vec::new(),
+        }
+    }
+
+    pub fn with_hook(
+        mut self,
+        name: &str,
+        hook: impl Fn(&[u8]) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.push((name.to_string(), Arc::new(hook)));
+        self
+    }
+
+    pub fn check(&self, cmd: &CommandFrame2011) -> Result<(), String> {
+        let data = cmd.extframe.as_deref().unwrap_or(&[]);
+        if cmd.command != 8 {
+            if !data.is_empty() {
+                return Err(format!(
+                    "{} bytes of extended data after command {}",
+                    data.len(),
+                    cmd.command
+                ));
+            }
+            return Ok(());
+        }
+        if !self.allow {
+            return Err("extended frames are denied".to_string());
+        }
+        if data.len() > self.max_bytes {
+            return Err(format!(
+                "{} bytes of extended data, at most {}",
+                data.len(),
+                self.max_bytes
+            ));
+        }
+        if !data.len().is_multiple_of(2) {
+            return Err(format!(
+                "{} bytes of extended data, not whole words",
+                data.len()
+            ));
+        }
+        for (name, hook) in &self.hooks {
+            hook(data).map_err(|reason| format!("{}: {}", name, reason))?;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
@@ -27,6 +196,17 @@ pub struct ServerConfig {
     pub protocol: Protocol,
     pub address: String,
     pub data_rate: f64, // Hz
+    pub policy: CommandPolicy,
+    pub extended_frames: ExtendedFramePolicy, // Denied by default
+    pub audit: Option<Arc<AuditLog>>,         // Records every received command
+    pub replay_guard: Option<Arc<CommandReplayGuard>>,
+    pub metrics: Option<Arc<Metrics>>,
+    pub scenario: Option<Scenario>, // Stream simulated frames with scripted events
+    pub playback: PlaybackOptions,  // Speed and looping of the simulated stream
+    pub udp_data_port: Option<u16>, // Commanded UDP: data to this port of the client
+    pub udp_data_group: Option<IpAddr>, // Sent to this multicast group instead
+    pub output_rate: Option<i16>,   // Frames per second served, converted from the simulated rate
+    pub virtual_stream: Option<VirtualStreamConfig>, // Served in place of the source stream
 }
 
 impl ServerConfig {
@@ -41,8 +221,226 @@ impl ServerConfig {
             protocol,
             address,
             data_rate,
+            policy: CommandPolicy::default(),
+            extended_frames: ExtendedFramePolicy::default(),
+            audit: None,
+            replay_guard: None,
+            metrics: None,
+            scenario: None,
+            playback: PlaybackOptions::default(),
+            udp_data_port: None,
+            udp_data_group: None,
+            output_rate: None,
+            virtual_stream: None,
         })
     }
+
+    pub fn with_policy(mut self, policy: CommandPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_extended_frames(mut self, policy: ExtendedFramePolicy) -> Self {
+        self.extended_frames = policy;
+        self
+    }
+
+    pub fn with_audit(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    pub fn with_replay_guard(mut self, guard: Arc<CommandReplayGuard>) -> Self {
+        self.replay_guard = Some(guard);
+        self
+    }
+
+    pub fn with_scenario(mut self, scenario: Scenario) -> Self {
+        self.scenario = Some(scenario);
+        self
+    }
+
+    pub fn with_playback(mut self, playback: PlaybackOptions) -> Self {
+        self.playback = playback;
+        self
+    }
+
+    // Commanded UDP: commands and configuration stay on TCP, data frames are
+    // sent by UDP to this port at the client's address.
+    pub fn with_udp_data(mut self, port: u16) -> Self {
+        self.udp_data_port = Some(port);
+        self
+    }
+
+    // Commanded UDP with the data frames sent to a multicast group on port,
+    // from an address of the interface the system routes the group through.
+    pub fn with_multicast_data(mut self, group: IpAddr, port: u16) -> Self {
+        self.udp_data_port = Some(port);
+        self.udp_data_group = Some(group);
+        self
+    }
+
+    // Serve the simulated stream at another rate (see rate_conversion):
+    // decimated, or upsampled with interpolated frames flagged as modified
+    // data in STAT. The sample files carry one timestamp and are not converted.
+    pub fn with_output_rate(mut self, frames_per_second: i16) -> Self {
+        self.output_rate = Some(frames_per_second);
+        self
+    }
+
+    // Serve virtual PMUs computed from the source stream (see virtual_pmu)
+    // instead of the source stream itself.
+    pub fn with_virtual_stream(mut self, virtual_stream: VirtualStreamConfig) -> Self {
+        self.virtual_stream = Some(virtual_stream);
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        metrics.describe(
+            "pmu_server_commands_total",
+            "Command frames received by the server",
+        );
+        metrics.describe(
+            "pmu_server_command_replays_total",
+            "Command frames with a stale, future or repeated timestamp",
+        );
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // Authorization and replay checks for a received command.
+    fn check_command(&self, cmd: &CommandFrame2011, peer: SocketAddr) -> AuditOutcome {
+        if !self.policy.authorize(&peer.ip(), cmd.command) {
+            let required = ClientPermission::required_for(cmd.command);
+            return AuditOutcome::Rejected(format!("requires {:?} permission", required));
+        }
+        if let Err(reason) = self.extended_frames.check(cmd) {
+            return AuditOutcome::Rejected(format!("extended frame: {}", reason));
+        }
+        let Some(guard) = &self.replay_guard else {
+            return AuditOutcome::Accepted;
+        };
+        match guard.check(peer.ip(), cmd.timestamp_micros(), now_micros()) {
+            None => AuditOutcome::Accepted,
+            Some(finding) => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.increment(
+                        "pmu_server_command_replays_total",
+                        &[("reason", finding.reason())],
+                    );
+                }
+                let reason = format!("replay: {}", finding.describe());
+                match guard.action {
+                    ReplayAction::Reject => AuditOutcome::Rejected(reason),
+                    ReplayAction::Flag => AuditOutcome::Flagged(reason),
+                }
+            }
+        }
+    }
+
+    fn record_command(
+        &self,
+        cmd: &CommandFrame2011,
+        local: Option<SocketAddr>,
+        peer: SocketAddr,
+        outcome: &AuditOutcome,
+    ) {
+        if let Some(metrics) = &self.metrics {
+            let outcome = match outcome {
+                AuditOutcome::Rejected(_) => "rejected",
+                AuditOutcome::Flagged(_) => "flagged",
+                _ => "accepted",
+            };
+            metrics.increment(
+                "pmu_server_commands_total",
+                &[("command", command_name(cmd.command)), ("outcome", outcome)],
+            );
+        }
+        if let Some(audit) = &self.audit {
+            audit.record_or_log(&AuditEntry::from_command(
+                AuditDirection::Received,
+                cmd,
+                local,
+                Some(peer),
+                outcome.clone(),
+            ));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayAction {
+    Reject, // Drop the command
+    Flag,   // Execute the command but report it
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayFinding {
+    Stale { age_us: i64 },      // Older than the window
+    Future { ahead_us: i64 },   // Further ahead of the server clock than the window
+    Duplicate { last_us: i64 }, // Not newer than the last command from the same source
+}
+
+impl ReplayFinding {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ReplayFinding::Stale { .. } => "stale",
+            ReplayFinding::Future { .. } => "future",
+            ReplayFinding::Duplicate { .. } => "duplicate",
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            ReplayFinding::Stale { age_us } => format!("stale by {} us", age_us),
+            ReplayFinding::Future { ahead_us } => format!("{} us in the future", ahead_us),
+            ReplayFinding::Duplicate { last_us } => {
+                format!("not newer than previous command at {} us", last_us)
+            }
+        }
+    }
+}
+
+// Tracks the SOC/FRACSEC of the last command from each source address. Commands
+// must be stamped within the window of the server clock and strictly newer than
+// the previous command from the same source. Shared by all connections.
+#[derive(Debug)]
+pub struct CommandReplayGuard {
+    pub window: Duration,
+    pub action: ReplayAction,
+    last_seen: Mutex<HashMap<IpAddr, i64>>,
+}
+
+impl CommandReplayGuard {
+    pub fn new(window: Duration, action: ReplayAction) -> Self {
+        CommandReplayGuard {
+            window,
+            action,
+            last_seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn check(&self, source: IpAddr, timestamp_us: i64, now_us: i64) -> Option<ReplayFinding> {
+        let window_us = self.window.as_micros() as i64;
+        if now_us - timestamp_us > window_us {
+            return Some(ReplayFinding::Stale {
+                age_us: now_us - timestamp_us,
+            });
+        }
+        if timestamp_us - now_us > window_us {
+            return Some(ReplayFinding::Future {
+                ahead_us: timestamp_us - now_us,
+            });
+        }
+        let mut last_seen = self.last_seen.lock().unwrap();
+        match last_seen.get(&source) {
+            Some(&last_us) if timestamp_us <= last_us => Some(ReplayFinding::Duplicate { last_us }),
+            _ => {
+                last_seen.insert(source, timestamp_us);
+                None
+            }
+        }
+    }
 }
 
 fn read_test_file(file_name: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
@@ -66,50 +464,79 @@ fn read_test_file(file_name: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Syn
     Ok(bytes)
 }
 
-async fn handle_client(mut socket: tokio::net::TcpStream, config: ServerConfig) -> io::Result<()> {
+async fn handle_client(
+    mut socket: tokio::net::TcpStream,
+    peer: SocketAddr,
+    config: ServerConfig,
+) -> io::Result<()> {
     println!("Handling client");
+    let local = socket.local_addr().ok();
     let mut is_streaming = false;
-    let stream_interval = Duration::from_secs_f64(1.0 / config.data_rate);
+    let mut stream_interval = Duration::from_secs_f64(1.0 / config.data_rate);
+    // Frames go out on a fixed schedule: sleeping a period after each one
+    // would fall behind their timestamps by the time taken to send
+    let mut next_send = time::Instant::now();
+
+    // With a scenario, frames come from the simulator instead of the sample files
+    let mut simulator = config.scenario.as_ref().and_then(|scenario| {
+        if let Some(layout) = &scenario.stream {
+            return Some(Simulator::from_layout(layout, scenario.clone()));
+        }
+        match read_test_file("config_message.bin").map(|data| parse_config_frame_1and2(&data)) {
+            Ok(Ok(base_config)) => Some(Simulator::new(base_config, scenario.clone())),
+            _ => {
+                println!("Error loading base configuration for simulator");
+                None
+            }
+        }
+    });
+    if let Some(simulator) = &simulator {
+        stream_interval = simulator.period().div_f64(config.playback.speed);
+    }
+    let mut served = match &simulator {
+        Some(simulator) => ServedStream::new(simulator.config(), true, &config),
+        None => {
+            match read_test_file("config_message.bin").map(|data| parse_config_frame_1and2(&data)) {
+                Ok(Ok(source)) => ServedStream::new(&source, false, &config),
+                _ => ServedStream::default(),
+            }
+        }
+    };
 
     // Buffer for reading commands
     let mut buf = vec![0u8; 1024];
 
+    let udp_data = match config.udp_data_port {
+        Some(port) => {
+            let socket = match config.udp_data_group {
+                Some(IpAddr::V4(_)) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?,
+                Some(IpAddr::V6(_)) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await?,
+                None => UdpSocket::bind((config.ip.as_str(), 0)).await?,
+            };
+            let destination = SocketAddr::new(config.udp_data_group.unwrap_or(peer.ip()), port);
+            println!("Sending data by UDP to {}", destination);
+            Some((socket, destination))
+        }
+        None => None,
+    };
+
     loop {
         tokio::select! {
             read_result = socket.read(&mut buf) => {
                 match read_result {
                     Ok(n) if n > 0 => {
-
-                        // Parse command frame
-                        if let Ok(frame) = parse_frame(&buf[..n], None) {
-                            match frame {
-                                Frame::Command(cmd) => {
-                                    match cmd.command {
-                                        4 => { // Send config frame
-                                            println!("Received command: Send configuration frame");
-                                            match read_test_file("config_message.bin") {
-                                                Ok(config_data) => {
-                                                    socket.write_all(&config_data).await?;
-                                                },
-                                                Err(e) => {
-                                                    println!("Error reading config file: {}", e);
-                                                }
-                                            }
-                                        },
-                                        2 => { // Start data transmission
-                                            println!("Received command: Start data transmission");
-                                            is_streaming = true;
-                                        },
-                                        1 => { // Stop data transmission
-                                            println!("Received command: Stop data transmission");
-                                            is_streaming = false;
-                                        },
-                                        _ => {
-                                            println!("Received unknown command: {}", cmd.command);
-                                        }
+                        // Commands sent back to back may arrive in one read
+                        for command in split_frames(&buf[..n]) {
+                            let simulator = simulator.as_ref();
+                            match handle_command(&mut socket, command, peer, local, &config, &served, simulator).await? {
+                                Some(true) => {
+                                    if !is_streaming {
+                                        next_send = time::Instant::now();
                                     }
-                                },
-                                _ => println!("Received non-command frame"),
+                                    is_streaming = true;
+                                }
+                                Some(false) => is_streaming = false,
+                                None => {}
                             }
                         }
                     },
@@ -127,11 +554,38 @@ async fn handle_client(mut socket: tokio::net::TcpStream, config: ServerConfig)
                     }
                 }
             }
-            _ = time::sleep(stream_interval), if is_streaming => {
+            _ = time::sleep_until(next_send), if is_streaming && simulator.is_some() => {
+                let Some(simulator) = simulator.as_mut() else { continue };
+                if simulator.is_finished() {
+                    if !config.playback.looping {
+                        println!("Scenario finished");
+                        is_streaming = false;
+                        continue;
+                    }
+                    simulator.rewind();
+                }
+                let tick = simulator.next_tick();
+                if tick.config_changed {
+                    served = ServedStream::new(simulator.config(), true, &config);
+                }
+                let frames: Vec<Vec<u8>> = tick.frames.iter().flat_map(|frame| served.frames(frame)).collect();
+                for frame in &frames {
+                    if let Err(e) = send_data(&mut socket, &udp_data, frame).await {
+                        println!("Error sending simulated frame: {}", e);
+                        return Ok(());
+                    }
+                }
+                stream_interval = simulator.period().div_f64(config.playback.speed);
+                next_send += stream_interval;
+            }
+            _ = time::sleep_until(next_send), if is_streaming && simulator.is_none() => {
+                next_send += stream_interval;
                 if let Ok(data_frame) = read_test_file("data_message.bin") {
-                    if let Err(e) = socket.write_all(&data_frame).await {
-                        println!("Error sending data frame: {}", e);
-                        break;
+                    for frame in served.frames(&data_frame) {
+                        if let Err(e) = send_data(&mut socket, &udp_data, &frame).await {
+                            println!("Error sending data frame: {}", e);
+                            return Ok(());
+                        }
                     }
                 }
             }
@@ -141,16 +595,182 @@ async fn handle_client(mut socket: tokio::net::TcpStream, config: ServerConfig)
     Ok(())
 }
 
+// Answers one command frame of a client. Returns Some(true) when data
+// transmission should start, Some(false) when it should stop.
+async fn handle_command(
+    socket: &mut tokio::net::TcpStream,
+    command: &[u8],
+    peer: SocketAddr,
+    local: Option<SocketAddr>,
+    config: &ServerConfig,
+    served: &ServedStream,
+    simulator: Option<&Simulator>,
+) -> io::Result<Option<bool>> {
+    // Clients only send commands, any other frame type is dropped unparsed
+    if !is_command_frame(command) {
+        println!("Dropped a frame from {} that is not a command", peer);
+        return Ok(None);
+    }
+    let cmd = match parse_frame(command, None) {
+        Ok(Frame::Command(cmd)) => cmd,
+        Ok(_) => {
+            println!("Received non-command frame");
+            return Ok(None);
+        }
+        Err(_) => return Ok(None),
+    };
+    let outcome = config.check_command(&cmd, peer);
+    config.record_command(&cmd, local, peer, &outcome);
+    if let AuditOutcome::Rejected(reason) = outcome {
+        println!("Rejected command {} from {}: {}", cmd.command, peer, reason);
+        return Ok(None);
+    }
+    match cmd.command {
+        4 => {
+            // Send config frame
+            println!("Received command: Send configuration frame");
+            if let Some(config_frame) = served.config_frame() {
+                socket.write_all(&config_frame).await?;
+            } else if let Some(simulator) = simulator {
+                socket.write_all(&simulator.config_frame()).await?;
+            } else {
+                match read_test_file("config_message.bin") {
+                    Ok(config_data) => socket.write_all(&config_data).await?,
+                    Err(e) => println!("Error reading config file: {}", e),
+                }
+            }
+            Ok(None)
+        }
+        2 => {
+            // Start data transmission
+            println!("Received command: Start data transmission");
+            Ok(Some(true))
+        }
+        1 => {
+            // Stop data transmission
+            println!("Received command: Stop data transmission");
+            Ok(Some(false))
+        }
+        8 => {
+            // Extended frame, passed the policy
+            let length = cmd.extframe.as_ref().map_or(0, |data| data.len());
+            println!("Received extended frame of {} bytes", length);
+            Ok(None)
+        }
+        _ => {
+            println!("Received unknown command: {}", cmd.command);
+            Ok(None)
+        }
+    }
+}
+
+// What a client is sent of the source stream: the virtual PMUs in its place
+// when configured, at the output rate when one is set and the stream is
+// simulated.
+#[derive(Default)]
+struct ServedStream {
+    virtual_stream: Option<VirtualStream>,
+    converter: Option<RateConverter>,
+}
+
+impl ServedStream {
+    fn new(source: &ConfigurationFrame1and2_2011, simulated: bool, config: &ServerConfig) -> Self {
+        let virtual_stream = config.virtual_stream.clone().and_then(|virtual_stream| {
+            VirtualStream::new(virtual_stream, source)
+                .map_err(|e| println!("Serving the source stream: {}", e))
+                .ok()
+        });
+        let served = virtual_stream.as_ref().map_or(source, |v| v.config());
+        let converter = match config.output_rate {
+            Some(rate) if simulated => RateConverter::new(served, rate)
+                .map_err(|e| println!("Serving the simulated rate: {}", e))
+                .ok(),
+            _ => None,
+        };
+        ServedStream {
+            virtual_stream,
+            converter,
+        }
+    }
+
+    // Configuration frame describing the frames sent, None when it is the source's.
+    fn config_frame(&self) -> Option<Vec<u8>> {
+        match (&self.converter, &self.virtual_stream) {
+            (Some(converter), _) => Some(converter.output_config().to_hex()),
+            (None, Some(virtual_stream)) => Some(virtual_stream.config().to_hex()),
+            (None, None) => None,
+        }
+    }
+
+    // Frames to send for a data frame of the source.
+    fn frames(&mut self, frame: &[u8]) -> Vec<Vec<u8>> {
+        let frame = match &self.virtual_stream {
+            Some(virtual_stream) => match virtual_stream.data_frame(frame) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    println!("No virtual PMU frame: {}", e);
+                    return Vec::new();
+                }
+            },
+            None => frame.to_vec(),
+        };
+        match self.converter.as_mut() {
+            Some(converter) => converter.push(&frame),
+            None => vec![frame],
+        }
+    }
+}
+
+// The frames of a read by their FRAMESIZE, the rest as one when it is not
+// a whole frame.
+fn split_frames(mut buf: &[u8]) -> Vec<&[u8]> {
+    let mut frames = Vec::new();
+    while buf.len() >= 4 {
+        let size = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        if size < 4 || size > buf.len() {
+            break;
+        }
+        let (frame, rest) = buf.split_at(size);
+        frames.push(frame);
+        buf = rest;
+    }
+    if !buf.is_empty() {
+        frames.push(buf);
+    }
+    frames
+}
+
+// SYNC bits 4-6 of a command frame are 0b100.
+fn is_command_frame(frame: &[u8]) -> bool {
+    frame.len() >= 2 && frame[0] == 0xAA && (frame[1] >> 4) & 0b111 == 0b100
+}
+
+// Data frames go over the client's TCP connection, or by UDP in commanded UDP mode.
+async fn send_data(
+    socket: &mut tokio::net::TcpStream,
+    udp_data: &Option<(UdpSocket, SocketAddr)>,
+    frame: &[u8],
+) -> io::Result<()> {
+    match udp_data {
+        Some((udp, destination)) => udp.send_to(frame, destination).await.map(|_| ()),
+        None => socket.write_all(frame).await,
+    }
+}
+
 pub async fn run_mock_server(server_config: ServerConfig) -> io::Result<()> {
     let listener = TcpListener::bind(&server_config.address).await?;
     println!("Mock PDC server listening on {}", server_config.address);
     println!("Data rate configured to {} Hz", server_config.data_rate);
 
     while let Ok((socket, addr)) = listener.accept().await {
+        if server_config.policy.permission(&addr.ip()) == ClientPermission::Denied {
+            println!("Refused client {}: not authorized", addr);
+            continue;
+        }
         println!("New client connected: {}", addr);
         let config = server_config.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_client(socket, config).await {
+            if let Err(e) = handle_client(socket, addr, config).await {
                 println!("Client handler error: {}", e);
             }
         });
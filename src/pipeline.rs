@@ -0,0 +1,1719 @@
+// Collector pipeline: PDC streams in, record batches out to sinks.
+//
+// A PipelineConfig (JSON) lists the streams to connect to, where their batches
+// are written and how the work is spread over threads:
+//
+// - shared: the streams run as tasks on the caller's runtime and hand their
+//   frames to a single writer, which holds the accumulator and the sinks.
+//   Fine for tens of streams.
+// - sharded: the streams are spread round robin over shards. Each shard is a
+//   dedicated thread with its own single threaded runtime, queues,
+//   accumulator and sinks, so a frame never leaves the thread that read it
+//   and hundreds of streams don't contend on one writer.
+//
+// Each stream hands its frames to the writer through a lock-free queue of
+// its own (queue::Rings). Data frames its PDC sends again, e.g. after a
+// reconnect, are dropped by the client first (dedup::FrameDeduplicator) and
+// counted in the pipeline's metrics.
+//
+// Sinks are opened per stream, named after its idcode, and belong to the
+// writer of the shard handling the stream. Besides the sink, further sinks
+// get the same batches, each decimated to its own rate, e.g. Parquet at the
+// full rate and SQLite at 1 Hz. A sink may spool its batches to disk while
+// it is down and catch up once it is back. Idcodes must be unique, after the
+// optional remapping table (remap::Remap) is applied to every frame.
+//
+// With a checkpoint directory every shard saves the configuration and newest
+// timestamp of its streams there periodically and when stopping. A restart
+// reconnects without requesting the configurations again and skips frames
+// that are not newer than the checkpoint, e.g. replayed by a buffering PDC.
+//
+// A stream's data may come by UDP, sent to the collector or to a multicast
+// group joined for the stream's PMUs only (multicast::MulticastConfig).
+//
+// With a config_cache directory the configuration of every stream is also
+// cached there as received (config_cache::ConfigCache). A commanded UDP
+// stream without a checkpoint starts from its cached configuration, so its
+// data is parsed right away, and requests the current one meanwhile.
+//
+// Derived channels (derived::DerivedChannels) are computed for every batch
+// before it is written, so they can be stored alongside the measured ones.
+//
+// Triggers (analytics::trigger) are evaluated by every shard on each data
+// frame of its streams and publish on the pipeline's event bus, so the
+// channels of a trigger must belong to streams of the same shard.
+//
+// With a snapshot section every shard also keeps the last frames of its
+// streams and writes them around events on the pipeline's event bus, or
+// manual triggers, to capture files (snapshot::SnapshotRecorder).
+//
+// With a dead_letter section the frames that keep failing to parse are
+// written as received, with the reason, to a capture file per shard
+// (deadletter::DeadLetterQueue) and counted in the pipeline's metrics.
+//
+// Streams and channels can be disabled and enabled again while running
+// through the pipeline's ingest control (ingest::IngestControl).
+//
+// With a scada section the points of external SCADA sources are joined to
+// every batch as extra columns (scada::ScadaJoin), after the derived
+// channels. The sources are read for as long as the pipeline runs.
+//
+// A topology file (topology::Topology) maps channels to buses, branches and
+// CIM mRIDs of a network model, attached to their columns as metadata.
+//
+// With an angle_jitter section every shard follows the jitter of the phasor
+// angles of its streams (analytics::jitter) in the pipeline's metrics.
+//
+// With metrics every shard also sets the latency of each stream's last data
+// frame, from its SOC/FRACSEC to the shard's writer taking it off the queue,
+// as pmu_pipeline_latency_us{stream="<idcode>"}. A backlog in the queues
+// shows up there as latency growing.
+//
+// With a trace section a sample of the data frames is followed from their
+// arrival at the client to their batch written to the sinks
+// (trace::LatencyTracer), and the time spent in every stage summed up in
+// histograms, also set as gauges in the pipeline's metrics.
+//
+// In strict mode frames using reserved or invalid field values
+// (strict::StrictChecker) are rejected to the dead-letter queue with the
+// violations found, and validation reports those of the configurations.
+//
+// The server section is not used by the collector itself: it sets which
+// clients may send which commands (pdc_server::CommandPolicy) when the same
+// deployment also serves streams.
+//
+// Pipeline::validate checks a configuration before deployment without
+// collecting anything: it connects to every stream and requests its
+// configuration, checks that the directories can be written and the programs
+// of the sinks run, and lists the channels the sinks would get.
+use crate::accumulator::{AccumulatorError, BatchAccumulator, FlushPolicy};
+use crate::analytics::jitter::{JitterConfig, JitterMonitor};
+use crate::analytics::trigger::{TriggerDefinition, TriggerEngine};
+use crate::arrow_utils::{frame_timestamp_micros_with, unwrap_soc_rollover};
+use crate::budget::MemoryBudget;
+use crate::checkpoint::{self, Checkpoint, Checkpointer, Frame, StreamState};
+use crate::config_cache::ConfigCache;
+use crate::deadletter::{DeadLetter, DeadLetterConfig, DeadLetterQueue};
+use crate::dedup::{FrameDeduplicator, DEFAULT_DEDUP_CAPACITY};
+use crate::derived::{DerivedChannel, DerivedChannels};
+use crate::events::EventBus;
+use crate::frame_parser::parse_config_frame_1and2;
+use crate::frames::{ConfigurationFrame1and2_2011, CrcMode};
+use crate::framesize::FrameSizePolicy;
+use crate::ingest::{IngestControl, IngestSettings};
+use crate::latency::now_micros;
+use crate::metrics::Metrics;
+use crate::multicast::MulticastConfig;
+use crate::pdc_client::{ControlMessage, PDCClient};
+use crate::pdc_server::ServerSettings;
+use crate::queue::{RingProducer, Rings};
+use crate::remap::Remap;
+use crate::scada::{self, ScadaConfig, ScadaJoin, ScadaSource};
+use crate::sinks::csv::CsvSink;
+use crate::sinks::decimate::{DecimatedSink, Decimator};
+use crate::sinks::json::JsonSink;
+use crate::sinks::naming::{NameStyle, RenamedSink};
+use crate::sinks::parquet::{ParquetOptions, ParquetSink};
+use crate::sinks::spool::{SpoolConfig, SpooledSink};
+use crate::sinks::sqlite::SqliteSink;
+use crate::sinks::{to_io_error, BatchSink};
+use crate::snapshot::{SnapshotConfig, SnapshotRecorder, SnapshotTrigger};
+use crate::strict::{self, StrictChecker};
+use crate::topology::Topology;
+use crate::trace::{LatencyTracer, TraceConfig};
+use arrow::array::TimestampMicrosecondArray;
+use arrow::record_batch::RecordBatch;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+// Frames a stream's queue holds before its client starts dropping them.
+const FRAME_QUEUE: usize = 1024;
+
+// Time a stream has to answer when validating.
+const VALIDATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub const LATENCY_METRIC: &str = "pmu_pipeline_latency_us";
+
+// A PDC stream to collect.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamSource {
+    pub host: String,
+    pub port: u16,
+    // Idcode sent in the commands to the PDC
+    #[serde(default = "default_command_idcode")]
+    pub idcode: u16,
+    // Receive the data frames by UDP on this port, commands stay on TCP
+    #[serde(default)]
+    pub udp_port: Option<u16>,
+    // Sent to this multicast group on udp_port instead
+    #[serde(default)]
+    pub multicast: Option<MulticastConfig>,
+    // Check of the stream's CHK, for devices computing it another way
+    #[serde(default)]
+    pub crc_mode: CrcMode,
+    // Keep frames failing the check, flagged in the quality column
+    #[serde(default)]
+    pub lenient: bool,
+    // Repair data frames with trailing bytes or a wrong FRAMESIZE, for
+    // devices getting it wrong, instead of rejecting them
+    #[serde(default)]
+    pub frame_size: FrameSizePolicy,
+    // Data frames remembered to drop those the PDC sends again
+    // (dedup::FrameDeduplicator), 0 to keep duplicates
+    #[serde(default = "default_dedup_frames")]
+    pub dedup_frames: usize,
+}
+
+fn default_command_idcode() -> u16 {
+    1
+}
+
+fn default_dedup_frames() -> usize {
+    DEFAULT_DEDUP_CAPACITY
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkFormat {
+    #[default]
+    Parquet,
+    Csv,
+    Json,
+    Sqlite, // Through the sqlite3 shell, see sinks::sqlite
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SinkConfig {
+    #[serde(default)]
+    pub format: SinkFormat,
+    pub dir: PathBuf,
+    // Keep one row per interval (sinks::decimate), given as a rate or an interval
+    #[serde(default)]
+    pub rate_hz: Option<f64>,
+    #[serde(default)]
+    pub decimation_ms: Option<u64>,
+    // Spool the batches to disk while the sink fails and catch up on them at
+    // a limited rate once it is back (sinks::spool)
+    #[serde(default)]
+    pub spool: Option<SpoolConfig>,
+    // Writer options of parquet sinks (sinks::parquet::ParquetOptions)
+    #[serde(default)]
+    pub parquet: Option<ParquetOptions>,
+    // Style of the column names (sinks::naming), mapping kept in
+    // <dir>/_<idcode>.columns.json
+    #[serde(default)]
+    pub names: NameStyle,
+}
+
+impl SinkConfig {
+    // Sink for one stream: <dir>/<idcode>-NNNNNN.parquet, <dir>/<idcode>.csv,
+    // <dir>/<idcode>.json or <dir>/<idcode>.sqlite. A restart continues after
+    // the last durable batch. Spooled to <spool dir>/<idcode> and decimated
+    // to the rate of the sink, if any. Columns are renamed in the sink's
+    // style, if any.
+    pub fn open(&self, idcode: u16) -> io::Result<Box<dyn BatchSink + Send>> {
+        let mut sink: Box<dyn BatchSink + Send> = match self.format {
+            SinkFormat::Parquet => {
+                let sink = ParquetSink::new(&self.dir, &idcode.to_string())?;
+                match &self.parquet {
+                    Some(options) => Box::new(sink.with_options(options)),
+                    None => Box::new(sink),
+                }
+            }
+            SinkFormat::Csv => Box::new(CsvSink::resume(self.dir.join(format!("{}.csv", idcode)))?),
+            SinkFormat::Json => {
+                Box::new(JsonSink::resume(self.dir.join(format!("{}.json", idcode)))?)
+            }
+            SinkFormat::Sqlite => Box::new(
+                SqliteSink::new(self.dir.join(format!("{}.sqlite", idcode)))?.with_stream(idcode),
+            ),
+        };
+        if self.names != NameStyle::Canonical {
+            let path = self.dir.join(format!("_{}.columns.json", idcode));
+            sink = Box::new(RenamedSink::new(sink, self.names, path)?);
+        }
+        if let Some(spool) = &self.spool {
+            let config = SpoolConfig {
+                dir: spool.dir.join(idcode.to_string()),
+                ..spool.clone()
+            };
+            sink = Box::new(SpooledSink::new(sink, config)?);
+        }
+        let decimator = self.decimator();
+        Ok(match decimator.interval_us() {
+            0 => sink,
+            _ => Box::new(DecimatedSink::new(sink, decimator)),
+        })
+    }
+
+    // rate_hz wins over decimation_ms; neither keeps every row.
+    pub fn decimator(&self) -> Decimator {
+        match (self.rate_hz, self.decimation_ms) {
+            (Some(rate_hz), _) => Decimator::from_rate(rate_hz),
+            (None, Some(ms)) => Decimator::new(ms as i64 * 1000),
+            (None, None) => Decimator::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum ExecutionMode {
+    #[default]
+    Shared,
+    // One shard per available core when shards is not set
+    Sharded {
+        #[serde(default)]
+        shards: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineConfig {
+    pub streams: Vec<StreamSource>,
+    pub sink: SinkConfig,
+    // More sinks written with the same batches
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    #[serde(default)]
+    pub execution: ExecutionMode,
+    // Rows per batch written to the sinks
+    #[serde(default = "default_batch_rows")]
+    pub batch_rows: usize,
+    #[serde(default)]
+    pub checkpoint: Option<CheckpointConfig>,
+    // Directory caching the configuration frame of every stream
+    #[serde(default)]
+    pub config_cache: Option<PathBuf>,
+    // CSV table renaming stations and channels and remapping idcodes
+    #[serde(default)]
+    pub remap: Option<PathBuf>,
+    // Channels computed from expressions, added to every batch having their inputs
+    #[serde(default)]
+    pub derived: Vec<DerivedChannel>,
+    // Composite conditions raising events
+    #[serde(default)]
+    pub triggers: Vec<TriggerDefinition>,
+    // Capture the raw frames around events and manual triggers
+    #[serde(default)]
+    pub snapshot: Option<SnapshotConfig>,
+    // Keep data frames with a bad CHK whose structure looks sane, flagged
+    // in the quality column, rather than dropping them
+    #[serde(default)]
+    pub salvage: bool,
+    // Write the frames that keep failing to parse to a capture file
+    #[serde(default)]
+    pub dead_letter: Option<DeadLetterConfig>,
+    // SCADA points joined to every batch as extra columns
+    #[serde(default)]
+    pub scada: Option<ScadaConfig>,
+    // CSV table mapping channels to network model elements
+    #[serde(default)]
+    pub topology: Option<PathBuf>,
+    // Reject frames using reserved or invalid field values, when
+    // commissioning devices
+    #[serde(default)]
+    pub strict: bool,
+    // Standard deviation of the detrended phasor angles, set as gauges in
+    // the metrics
+    #[serde(default)]
+    pub angle_jitter: Option<JitterConfig>,
+    // Follow a sample of the data frames through the stages of the pipeline
+    #[serde(default)]
+    pub trace: Option<TraceConfig>,
+    // Command permissions of the clients when serving streams
+    #[serde(default)]
+    pub server: Option<ServerSettings>,
+}
+
+fn default_batch_rows() -> usize {
+    1800
+}
+
+// Where and how often the shards save their stream state.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckpointConfig {
+    pub dir: PathBuf,
+    #[serde(default = "default_checkpoint_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_checkpoint_secs() -> u64 {
+    10
+}
+
+impl CheckpointConfig {
+    fn shard_path(&self, shard: usize) -> PathBuf {
+        self.dir.join(format!("shard-{}.json", shard))
+    }
+}
+
+impl PipelineConfig {
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        serde_json::from_str(json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    // The sink, then the further sinks.
+    pub fn all_sinks(&self) -> Vec<SinkConfig> {
+        let mut sinks = vec![self.sink.clone()];
+        sinks.extend(self.sinks.iter().cloned());
+        sinks
+    }
+
+    // Number of shards the streams are spread over, 1 when shared.
+    pub fn shards(&self) -> usize {
+        match self.execution {
+            ExecutionMode::Shared => 1,
+            ExecutionMode::Sharded { shards } => shards
+                .unwrap_or_else(|| {
+                    std::thread::available_parallelism().map_or(1, |cores| cores.get())
+                })
+                .clamp(1, self.streams.len().max(1)),
+        }
+    }
+
+    // Streams of each shard, round robin in configuration order.
+    pub fn shard_streams(&self) -> Vec<Vec<StreamSource>> {
+        let count = self.shards();
+        let mut shards = vec![Vec::new(); count];
+        for (index, source) in self.streams.iter().enumerate() {
+            shards[index % count].push(source.clone());
+        }
+        shards
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShardStats {
+    pub streams: usize,
+    pub frames: u64,
+    pub batches: u64,
+    pub rows: u64,
+    pub errors: u64,   // Frames the accumulator rejected and failed sink writes
+    pub dropped: u64,  // Frames dropped because the writer fell behind
+    pub stale: u64,    // Frames not newer than the checkpoint of their stream
+    pub disabled: u64, // Frames of streams disabled by the ingest control
+}
+
+// A channel of a stream as the sinks get it, after remapping.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CatalogChannel {
+    pub name: String,
+    pub station: String,
+    pub idcode: u16,
+    pub kind: String,
+    pub unit: String,
+}
+
+// What a stream answered when validating.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamCheck {
+    pub source: String,
+    pub idcode: Option<u16>, // Stream idcode after remapping
+    pub data_rate: Option<i16>,
+    pub channels: Vec<CatalogChannel>, // By name
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Validation {
+    pub streams: Vec<StreamCheck>,
+    pub problems: Vec<String>, // Including the streams that failed
+}
+
+impl Validation {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn channels(&self) -> usize {
+        self.streams
+            .iter()
+            .map(|stream| stream.channels.len())
+            .sum()
+    }
+
+    // The channel catalog of each stream, then the problems found.
+    pub fn print(&self) {
+        for stream in &self.streams {
+            match (&stream.error, stream.idcode) {
+                (Some(error), _) => println!("Stream {}: {}", stream.source, error),
+                (None, Some(idcode)) => {
+                    println!(
+                        "Stream {}: idcode {}, {} frames/s, {} channels",
+                        stream.source,
+                        idcode,
+                        stream.data_rate.unwrap_or(0),
+                        stream.channels.len()
+                    );
+                    for channel in &stream.channels {
+                        println!(
+                            "  {:<32} {:<16} {:<10} {}",
+                            channel.name, channel.station, channel.kind, channel.unit
+                        );
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+        if self.is_ok() {
+            println!(
+                "Configuration valid: {} streams, {} channels",
+                self.streams.len(),
+                self.channels()
+            );
+        } else {
+            for problem in &self.problems {
+                println!("Problem: {}", problem);
+            }
+            println!("Configuration has {} problems", self.problems.len());
+        }
+    }
+}
+
+pub struct Pipeline {
+    config: PipelineConfig,
+    stop: watch::Sender<bool>,
+    events: EventBus, // Trigger events, triggering snapshots
+    snapshot_trigger: SnapshotTrigger,
+    ingest: IngestControl,
+    metrics: Option<Arc<Metrics>>,
+    tracer: Option<Arc<LatencyTracer>>,
+}
+
+impl Pipeline {
+    pub fn new(config: PipelineConfig) -> Self {
+        let tracer = config
+            .trace
+            .clone()
+            .map(|trace| Arc::new(LatencyTracer::new(trace)));
+        Pipeline {
+            config,
+            tracer,
+            stop: watch::channel(false).0,
+            events: EventBus::new(256),
+            snapshot_trigger: SnapshotTrigger::new(),
+            ingest: IngestControl::default(),
+            metrics: None,
+        }
+    }
+
+    // Use another event bus, e.g. shared with detectors and notifiers.
+    pub fn with_events(mut self, bus: EventBus) -> Self {
+        self.events = bus;
+        self
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    // Count the frames failing to parse and set the latency, stage latency and
+    // angle jitter gauges, e.g. in the metrics of the server.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        metrics.describe(
+            LATENCY_METRIC,
+            "Microseconds from the timestamp of a stream's last data frame to the writer",
+        );
+        self.tracer = self
+            .config
+            .trace
+            .clone()
+            .map(|trace| Arc::new(LatencyTracer::new(trace).with_metrics(metrics.clone())));
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // The tracer of the sampled frames, with a trace section.
+    pub fn tracer(&self) -> Option<Arc<LatencyTracer>> {
+        self.tracer.clone()
+    }
+
+    // Manual snapshot triggers, reaching every shard while running.
+    pub fn snapshot_trigger(&self) -> SnapshotTrigger {
+        self.snapshot_trigger.clone()
+    }
+
+    // Streams and channels to ingest, changeable while running.
+    pub fn ingest_control(&self) -> IngestControl {
+        self.ingest.clone()
+    }
+
+    fn snapshot_recorder(&self, name: &str) -> io::Result<Option<SnapshotRecorder>> {
+        let Some(config) = &self.config.snapshot else {
+            return Ok(None);
+        };
+        let recorder = SnapshotRecorder::new(config.clone())?
+            .with_name(name)
+            .with_events(&self.events)
+            .with_triggers(&self.snapshot_trigger);
+        Ok(Some(recorder))
+    }
+
+    fn dead_letter_queue(&self, name: &str) -> io::Result<Option<DeadLetterQueue>> {
+        let Some(config) = &self.config.dead_letter else {
+            return Ok(None);
+        };
+        let mut queue = DeadLetterQueue::new(config.clone())?.with_name(name);
+        if let Some(metrics) = &self.metrics {
+            queue = queue.with_metrics(metrics.clone());
+        }
+        Ok(Some(queue))
+    }
+
+    fn trigger_engine(&self) -> io::Result<Option<TriggerEngine>> {
+        if self.config.triggers.is_empty() {
+            return Ok(None);
+        }
+        let engine = TriggerEngine::new(self.config.triggers.clone())?;
+        Ok(Some(engine.with_event_bus(self.events.clone())))
+    }
+
+    // Check a configuration without collecting: the streams are connected to
+    // and asked for their configuration, but never turned on, and nothing is
+    // written besides the directories created.
+    pub async fn validate(config: &PipelineConfig) -> Validation {
+        let mut validation = Validation::default();
+        let problems = &mut validation.problems;
+        let remap = match &config.remap {
+            Some(path) => Remap::from_file(path).unwrap_or_else(|e| {
+                problems.push(format!("Remap {}: {}", path.display(), e));
+                Remap::default()
+            }),
+            None => Remap::default(),
+        };
+        if let Some(path) = &config.topology {
+            if let Err(e) = Topology::from_file(path) {
+                problems.push(format!("Topology {}: {}", path.display(), e));
+            }
+        }
+        if let Err(e) = DerivedChannels::new(config.derived.clone()) {
+            problems.push(format!("Derived channels: {}", e));
+        }
+        if !config.triggers.is_empty() {
+            if let Err(e) = TriggerEngine::new(config.triggers.clone()) {
+                problems.push(format!("Triggers: {}", e));
+            }
+        }
+        if let Some(scada) = &config.scada {
+            if scada.points.is_empty() {
+                problems.push("SCADA: no points to join".to_string());
+            }
+            for source in &scada.sources {
+                if let ScadaSource::Csv { path, .. } = source {
+                    if !path.is_file() {
+                        problems.push(format!("SCADA source {}: no such file", path.display()));
+                    }
+                }
+            }
+        }
+
+        let mut dirs = Vec::new();
+        for sink in config.all_sinks() {
+            dirs.push(("Sink", sink.dir.clone()));
+            if let Some(spool) = &sink.spool {
+                dirs.push(("Spool", spool.dir.clone()));
+            }
+            if let Some(options) = &sink.parquet {
+                if sink.format != SinkFormat::Parquet {
+                    problems.push(format!(
+                        "Sink {}: parquet options on a {:?} sink",
+                        sink.dir.display(),
+                        sink.format
+                    ));
+                }
+                for problem in options.check() {
+                    problems.push(format!("Sink {}: {}", sink.dir.display(), problem));
+                }
+            }
+            if sink.format == SinkFormat::Sqlite {
+                if let Err(e) = check_program("sqlite3", "-version") {
+                    problems.push(format!("Sink {}: {}", sink.dir.display(), e));
+                }
+            }
+        }
+        if let Some(checkpoint) = &config.checkpoint {
+            dirs.push(("Checkpoint", checkpoint.dir.clone()));
+        }
+        if let Some(dir) = &config.config_cache {
+            dirs.push(("Configuration cache", dir.clone()));
+        }
+        if let Some(snapshot) = &config.snapshot {
+            dirs.push(("Snapshot", snapshot.dir.clone()));
+        }
+        if let Some(dead_letter) = &config.dead_letter {
+            dirs.push(("Dead-letter", dead_letter.dir.clone()));
+        }
+        for (name, dir) in dirs {
+            if let Err(e) = check_dir(&dir) {
+                problems.push(format!("{} directory {}: {}", name, dir.display(), e));
+            }
+        }
+
+        for source in &config.streams {
+            let Some(multicast) = &source.multicast else {
+                continue;
+            };
+            if source.udp_port.is_none() {
+                problems.push(format!(
+                    "Stream {}: multicast needs a udp_port",
+                    source.address()
+                ));
+            }
+            for problem in multicast.check() {
+                problems.push(format!("Stream {}: {}", source.address(), problem));
+            }
+        }
+
+        // The streams all at once, each within the timeout
+        let checks: Vec<_> = config
+            .streams
+            .iter()
+            .map(|source| {
+                let source = source.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(VALIDATE_TIMEOUT, connect(&source, None)).await {
+                        Ok(Ok(client)) => client.config.ok_or_else(|| "No configuration".into()),
+                        Ok(Err(e)) => Err(e.to_string()),
+                        Err(_) => Err("No configuration within the timeout".to_string()),
+                    }
+                })
+            })
+            .collect();
+        let mut idcodes: HashMap<u16, String> = HashMap::new();
+        for (source, check) in config.streams.iter().zip(checks) {
+            let source = source.address();
+            let result = check
+                .await
+                .unwrap_or_else(|e| Err(format!("Failed to connect: {}", e)));
+            let mut stream = StreamCheck {
+                source: source.clone(),
+                idcode: None,
+                data_rate: None,
+                channels: Vec::new(),
+                error: None,
+            };
+            match result {
+                Ok(mut received) => {
+                    if config.strict {
+                        for violation in strict::check_config(&received) {
+                            problems.push(format!("Stream {}: {}", source, violation));
+                        }
+                    }
+                    remap.apply_config(&mut received);
+                    let idcode = received.prefix.idcode;
+                    if let Some(other) = idcodes.insert(idcode, source.clone()) {
+                        problems.push(format!(
+                            "Streams {} and {} both have idcode {}",
+                            other, source, idcode
+                        ));
+                    }
+                    let mut channels: Vec<CatalogChannel> = received
+                        .get_channel_map()
+                        .into_iter()
+                        .map(|(name, info)| CatalogChannel {
+                            name,
+                            station: info.station,
+                            idcode: info.idcode,
+                            kind: info.kind.to_string(),
+                            unit: info.unit.to_string(),
+                        })
+                        .collect();
+                    channels.sort_by(|a, b| a.name.cmp(&b.name));
+                    stream.idcode = Some(idcode);
+                    stream.data_rate = Some(received.data_rate);
+                    stream.channels = channels;
+                }
+                Err(e) => {
+                    problems.push(format!("Stream {}: {}", source, e));
+                    stream.error = Some(e);
+                }
+            }
+            validation.streams.push(stream);
+        }
+        validation
+    }
+
+    // Stop the streams; run returns once the batches are written.
+    pub fn stop(&self) {
+        self.stop.send_replace(true);
+    }
+
+    // Collect until stopped or all streams have ended. Returns the statistics
+    // of each shard.
+    pub async fn run(&self) -> io::Result<Vec<ShardStats>> {
+        let shards = self.config.shard_streams();
+        let restored = Arc::new(match &self.config.checkpoint {
+            Some(checkpoint) => checkpoint::load_dir(&checkpoint.dir)?,
+            None => Checkpoint::default(),
+        });
+        let remap = Arc::new(match &self.config.remap {
+            Some(path) => Remap::from_file(path)?,
+            None => Remap::default(),
+        });
+        let topology = Arc::new(match &self.config.topology {
+            Some(path) => Topology::from_file(path)?,
+            None => Topology::default(),
+        });
+        let derived = Arc::new(DerivedChannels::new(self.config.derived.clone())?);
+        let config_cache = match &self.config.config_cache {
+            Some(dir) => Some(Arc::new(ConfigCache::new(dir)?)),
+            None => None,
+        };
+        let mut sources = AbortOnDrop::default();
+        let scada = self.config.scada.as_ref().map(|config| {
+            let (store, tasks) = scada::start(config);
+            sources.0 = tasks;
+            Arc::new(ScadaJoin::new(config, store))
+        });
+        match self.config.execution {
+            ExecutionMode::Shared => {
+                let sources = shards.into_iter().flatten().collect();
+                let mut shard = Shard::new(
+                    &self.config,
+                    0,
+                    sources,
+                    &restored,
+                    remap.clone(),
+                    self.stop.subscribe(),
+                );
+                shard.derived = derived.clone();
+                shard.scada = scada.clone();
+                shard.topology = topology.clone();
+                shard.config_cache = config_cache.clone();
+                shard.ingest = Some(self.ingest.subscribe());
+                shard.snapshots = self.snapshot_recorder("snapshot")?;
+                shard.dead_letters = self.dead_letter_queue("dead-letter")?;
+                shard.triggers = self.trigger_engine()?;
+                shard.metrics = self.metrics.clone();
+                shard.tracer = self.tracer.clone();
+                Ok(vec![run_shard(shard).await?])
+            }
+            ExecutionMode::Sharded { .. } => {
+                println!(
+                    "Running {} streams on {} shards",
+                    self.config.streams.len(),
+                    shards.len()
+                );
+                let mut threads = Vec::with_capacity(shards.len());
+                for (index, sources) in shards.into_iter().enumerate() {
+                    let mut shard = Shard::new(
+                        &self.config,
+                        index,
+                        sources,
+                        &restored,
+                        remap.clone(),
+                        self.stop.subscribe(),
+                    );
+                    shard.derived = derived.clone();
+                    shard.scada = scada.clone();
+                    shard.topology = topology.clone();
+                    shard.config_cache = config_cache.clone();
+                    shard.ingest = Some(self.ingest.subscribe());
+                    shard.snapshots = self.snapshot_recorder(&format!("shard-{}", index))?;
+                    shard.dead_letters =
+                        self.dead_letter_queue(&format!("dead-letter-shard-{}", index))?;
+                    shard.triggers = self.trigger_engine()?;
+                    shard.metrics = self.metrics.clone();
+                    shard.tracer = self.tracer.clone();
+                    let thread = std::thread::Builder::new()
+                        .name(format!("pmu-shard-{}", index))
+                        .spawn(move || {
+                            let runtime = tokio::runtime::Builder::new_current_thread()
+                                .enable_all()
+                                .build()?;
+                            runtime.block_on(run_shard(shard))
+                        })?;
+                    threads.push(thread);
+                }
+                // Join without blocking the caller's runtime
+                tokio::task::spawn_blocking(move || {
+                    threads
+                        .into_iter()
+                        .map(|thread| {
+                            thread
+                                .join()
+                                .map_err(|_| io::Error::other("Shard thread panicked"))?
+                        })
+                        .collect()
+                })
+                .await
+                .map_err(to_io_error)?
+            }
+        }
+    }
+}
+
+// Tasks stopped when the pipeline returns, however it does.
+#[derive(Default)]
+struct AbortOnDrop(Vec<tokio::task::JoinHandle<()>>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.iter().for_each(|task| task.abort());
+    }
+}
+
+// Everything one shard needs to run.
+struct Shard {
+    sinks: Vec<SinkConfig>,
+    batch_rows: usize,
+    salvage: bool,
+    strict: bool,
+    angle_jitter: Option<JitterConfig>,
+    sources: Vec<StreamSource>,
+    stop: watch::Receiver<bool>,
+    checkpointer: Option<Checkpointer>,
+    restored: Vec<StreamState>, // Checkpointed state of the shard's streams
+    config_cache: Option<Arc<ConfigCache>>,
+    remap: Arc<Remap>,
+    derived: Arc<DerivedChannels>,
+    scada: Option<Arc<ScadaJoin>>,
+    topology: Arc<Topology>,
+    ingest: Option<watch::Receiver<IngestSettings>>,
+    snapshots: Option<SnapshotRecorder>,
+    dead_letters: Option<DeadLetterQueue>,
+    triggers: Option<TriggerEngine>,
+    metrics: Option<Arc<Metrics>>,
+    tracer: Option<Arc<LatencyTracer>>,
+}
+
+impl Shard {
+    fn new(
+        config: &PipelineConfig,
+        index: usize,
+        sources: Vec<StreamSource>,
+        restored: &Checkpoint,
+        remap: Arc<Remap>,
+        stop: watch::Receiver<bool>,
+    ) -> Self {
+        let restored = sources
+            .iter()
+            .filter_map(|source| restored.stream_from(&source.address()).cloned())
+            .collect();
+        Shard {
+            sinks: config.all_sinks(),
+            batch_rows: config.batch_rows,
+            salvage: config.salvage,
+            strict: config.strict,
+            angle_jitter: config.angle_jitter.clone(),
+            sources,
+            stop,
+            checkpointer: config.checkpoint.as_ref().map(|checkpoint| {
+                Checkpointer::new(
+                    checkpoint.shard_path(index),
+                    Duration::from_secs(checkpoint.interval_secs),
+                )
+            }),
+            restored,
+            config_cache: None,
+            remap,
+            derived: Arc::new(DerivedChannels::default()),
+            scada: None,
+            topology: Arc::new(Topology::default()),
+            ingest: None,
+            snapshots: None,
+            dead_letters: None,
+            triggers: None,
+            metrics: None,
+            tracer: None,
+        }
+    }
+}
+
+impl StreamSource {
+    // host:port, as recorded in checkpoints.
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+// Run the streams of one shard and write their batches on the current runtime.
+async fn run_shard(shard: Shard) -> io::Result<ShardStats> {
+    // One lock-free queue per stream, all drained by the shard's writer
+    let mut queues = Rings::new();
+    let addresses = Arc::new(Mutex::new(HashMap::new()));
+    for source in &shard.sources {
+        let frames = queues.add(FRAME_QUEUE);
+        let config = shard
+            .restored
+            .iter()
+            .find(|stream| stream.source.as_deref() == Some(&source.address()))
+            .and_then(|stream| stream.config.config().ok());
+        // A cached configuration is refreshed once streaming
+        let cached = match (&config, &shard.config_cache, source.udp_port) {
+            (None, Some(cache), Some(_)) => cache
+                .load_config(&source.address(), source.idcode)
+                .unwrap_or_else(|e| {
+                    println!(
+                        "Ignoring cached configuration of {}: {}",
+                        source.address(),
+                        e
+                    );
+                    None
+                }),
+            _ => None,
+        };
+        let refresh = cached.is_some();
+        tokio::spawn(run_stream(
+            source.clone(),
+            config.or(cached),
+            (shard.config_cache.clone(), refresh),
+            frames,
+            addresses.clone(),
+            (shard.tracer.clone(), shard.metrics.clone()),
+            shard.stop.clone(),
+        ));
+    }
+
+    let mut writer = ShardWriter::new(shard.sinks, shard.batch_rows)
+        .with_remap(shard.remap)
+        .with_derived(shard.derived)
+        .with_scada(shard.scada)
+        .with_topology(shard.topology)
+        .with_ingest(shard.ingest)
+        .with_checkpoints(shard.checkpointer, &shard.restored, addresses)
+        .with_crc_modes(&shard.sources)
+        .with_salvage(shard.salvage)
+        .with_strict(shard.strict)
+        .with_snapshots(shard.snapshots)
+        .with_dead_letters(shard.dead_letters)
+        .with_triggers(shard.triggers)
+        .with_latency(shard.metrics.clone())
+        .with_tracer(shard.tracer)
+        .with_jitter(shard.angle_jitter, shard.metrics);
+    writer.stats.streams = shard.sources.len();
+    while let Some(frame) = queues.pop().await {
+        writer.push(&frame);
+    }
+    writer.stats.dropped = queues.dropped();
+    writer.finish()
+}
+
+// Connect to a stream and forward its configuration and data frames until
+// stopped or the client gives up. A configuration from a checkpoint or the
+// cache is used instead of requesting it, and requested meanwhile when
+// refresh is set.
+async fn run_stream(
+    source: StreamSource,
+    config: Option<ConfigurationFrame1and2_2011>,
+    (cache, refresh): (Option<Arc<ConfigCache>>, bool),
+    mut frames: RingProducer,
+    addresses: Arc<Mutex<HashMap<u16, String>>>,
+    (tracer, metrics): (Option<Arc<LatencyTracer>>, Option<Arc<Metrics>>),
+    mut stop: watch::Receiver<bool>,
+) {
+    let client = match connect(&source, config).await {
+        Ok(client) if refresh => match client.with_config_refresh() {
+            Ok(client) => client,
+            Err(e) => {
+                println!(
+                    "Failed to refresh the configuration of {}: {}",
+                    source.address(),
+                    e
+                );
+                return;
+            }
+        },
+        Ok(client) => client,
+        Err(e) => {
+            println!(
+                "Failed to connect to {}:{}: {}",
+                source.host, source.port, e
+            );
+            return;
+        }
+    };
+    let Some(config) = client
+        .config
+        .as_ref()
+        .map(|config| Bytes::from(config.to_hex()))
+    else {
+        return;
+    };
+    if let Some(idcode) = client.config.as_ref().map(|config| config.prefix.idcode) {
+        if let Ok(mut addresses) = addresses.lock() {
+            addresses.insert(idcode, source.address());
+        }
+    }
+    frames.push(config);
+    let mut client = client.with_frame_queue(frames);
+    if source.dedup_frames > 0 {
+        let mut dedup = FrameDeduplicator::new(source.dedup_frames);
+        if let Some(metrics) = metrics {
+            dedup = dedup.with_metrics(metrics);
+        }
+        client = client.with_dedup(dedup);
+    }
+    if let Some(cache) = cache {
+        client = client.with_config_cache(cache, &source.address());
+    }
+    if let Some(tracer) = tracer {
+        client = client.with_tracer(tracer);
+    }
+    let control_tx = client.get_control_sender();
+    let stopper = async move {
+        let _ = stop.wait_for(|stop| *stop).await;
+        let _ = control_tx.send(ControlMessage::Stop).await;
+        std::future::pending::<()>().await
+    };
+    tokio::select! {
+        _ = client.start_stream() => {}
+        _ = stopper => {}
+    }
+}
+
+async fn connect(
+    source: &StreamSource,
+    config: Option<ConfigurationFrame1and2_2011>,
+) -> io::Result<PDCClient> {
+    let timeout = Duration::from_secs(1);
+    let (client, _, _) = match config {
+        Some(config) => {
+            PDCClient::new_with_config(&source.host, source.port, source.idcode, timeout, config)
+                .await?
+        }
+        None => {
+            PDCClient::new_with_crc_mode(
+                &source.host,
+                source.port,
+                source.idcode,
+                timeout,
+                source.crc_mode,
+            )
+            .await?
+        }
+    };
+    let client = client.with_frame_size_policy(source.frame_size);
+    match (source.udp_port, &source.multicast) {
+        (Some(port), Some(multicast)) => client.with_multicast_data(port, multicast).await,
+        (Some(port), None) => client.with_udp_data(port).await,
+        (None, Some(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Multicast data needs a udp_port",
+        )),
+        (None, None) => Ok(client),
+    }
+}
+
+// Create the directory if needed and write a file to it.
+fn check_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(".pmu-validate");
+    fs::write(&probe, b"")?;
+    fs::remove_file(probe)
+}
+
+fn check_program(program: &str, arg: &str) -> io::Result<()> {
+    let output = std::process::Command::new(program)
+        .arg(arg)
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("{} {} failed", program, arg)));
+    }
+    Ok(())
+}
+
+// Accumulator and sinks of one shard.
+struct ShardWriter {
+    sink_configs: Vec<SinkConfig>,
+    accumulator: BatchAccumulator,
+    sinks: HashMap<(usize, u16), Box<dyn BatchSink + Send>>, // By sink and stream
+    stats: ShardStats,
+    checkpointer: Option<Checkpointer>,
+    streams: HashMap<u16, (StreamState, u32)>, // With the time base of the stream
+    addresses: Arc<Mutex<HashMap<u16, String>>>, // Source of each idcode
+    crc_modes: HashMap<String, (CrcMode, bool)>, // CHK check and leniency by source
+    frame_size_policies: HashMap<String, FrameSizePolicy>, // Lenient sources
+    remap: Arc<Remap>,
+    derived: Arc<DerivedChannels>,
+    scada: Option<Arc<ScadaJoin>>,
+    topology: Arc<Topology>,
+    strict: Option<StrictChecker>,
+    ingest: Option<watch::Receiver<IngestSettings>>,
+    settings: IngestSettings, // As last received from ingest
+    snapshots: Option<SnapshotRecorder>,
+    dead_letters: Option<DeadLetterQueue>,
+    triggers: Option<TriggerEngine>,
+    jitter: Option<(JitterConfig, Arc<Metrics>)>,
+    jitter_monitors: HashMap<u16, JitterMonitor>, // By stream
+    latency: Option<Arc<Metrics>>,
+    tracer: Option<Arc<LatencyTracer>>,
+}
+
+impl ShardWriter {
+    fn new(sink_configs: Vec<SinkConfig>, batch_rows: usize) -> Self {
+        ShardWriter {
+            sink_configs,
+            accumulator: BatchAccumulator::new(MemoryBudget::unlimited())
+                .with_flush_policy(FlushPolicy::default().with_max_rows(batch_rows.max(1))),
+            sinks: HashMap::new(),
+            stats: ShardStats::default(),
+            checkpointer: None,
+            streams: HashMap::new(),
+            addresses: Arc::new(Mutex::new(HashMap::new())),
+            crc_modes: HashMap::new(),
+            frame_size_policies: HashMap::new(),
+            remap: Arc::new(Remap::default()),
+            derived: Arc::new(DerivedChannels::default()),
+            scada: None,
+            topology: Arc::new(Topology::default()),
+            strict: None,
+            ingest: None,
+            settings: IngestSettings::default(),
+            snapshots: None,
+            dead_letters: None,
+            triggers: None,
+            jitter: None,
+            jitter_monitors: HashMap::new(),
+            latency: None,
+            tracer: None,
+        }
+    }
+
+    fn with_remap(mut self, remap: Arc<Remap>) -> Self {
+        self.remap = remap;
+        self
+    }
+
+    // Save checkpoints with the checkpointer, starting from the restored state.
+    // Streams are known by their remapped idcode, so set the remap first.
+    fn with_checkpoints(
+        mut self,
+        checkpointer: Option<Checkpointer>,
+        restored: &[StreamState],
+        addresses: Arc<Mutex<HashMap<u16, String>>>,
+    ) -> Self {
+        for stream in restored {
+            if let Ok(config) = stream.config.config() {
+                let idcode = self.remap.stream_idcode(config.prefix.idcode);
+                self.streams
+                    .insert(idcode, (stream.clone(), config.time_base));
+            }
+        }
+        self.checkpointer = checkpointer;
+        self.addresses = addresses;
+        self
+    }
+
+    // CHK check of the sources that do not use the standard one strictly.
+    fn with_crc_modes(mut self, sources: &[StreamSource]) -> Self {
+        for source in sources {
+            if source.lenient || source.crc_mode != CrcMode::Standard {
+                self.crc_modes
+                    .insert(source.address(), (source.crc_mode, source.lenient));
+            }
+            if source.frame_size != FrameSizePolicy::Strict {
+                self.frame_size_policies
+                    .insert(source.address(), source.frame_size);
+            }
+        }
+        self
+    }
+
+    fn with_salvage(mut self, salvage: bool) -> Self {
+        self.accumulator = self.accumulator.with_salvage(salvage);
+        self
+    }
+
+    fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict.then(StrictChecker::new);
+        self
+    }
+
+    fn with_derived(mut self, derived: Arc<DerivedChannels>) -> Self {
+        self.derived = derived;
+        self
+    }
+
+    fn with_scada(mut self, scada: Option<Arc<ScadaJoin>>) -> Self {
+        self.scada = scada;
+        self
+    }
+
+    fn with_topology(mut self, topology: Arc<Topology>) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    fn with_ingest(mut self, mut ingest: Option<watch::Receiver<IngestSettings>>) -> Self {
+        if let Some(ingest) = ingest.as_mut() {
+            self.settings = ingest.borrow_and_update().clone();
+        }
+        self.ingest = ingest;
+        self
+    }
+
+    fn with_snapshots(mut self, snapshots: Option<SnapshotRecorder>) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    fn with_dead_letters(mut self, dead_letters: Option<DeadLetterQueue>) -> Self {
+        self.dead_letters = dead_letters;
+        self
+    }
+
+    fn with_triggers(mut self, triggers: Option<TriggerEngine>) -> Self {
+        self.triggers = triggers;
+        self
+    }
+
+    // Set the latency gauges of the streams.
+    fn with_latency(mut self, metrics: Option<Arc<Metrics>>) -> Self {
+        self.latency = metrics;
+        self
+    }
+
+    // Stamp the later stages of the traced frames.
+    fn with_tracer(mut self, tracer: Option<Arc<LatencyTracer>>) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
+    // Follow the angle jitter of the streams, when there are metrics to set.
+    fn with_jitter(mut self, config: Option<JitterConfig>, metrics: Option<Arc<Metrics>>) -> Self {
+        self.jitter = config.zip(metrics);
+        self
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        let addresses = self.addresses.lock().map(|a| a.clone()).unwrap_or_default();
+        Checkpoint {
+            saved_us: now_micros(),
+            streams: self
+                .streams
+                .iter()
+                .map(|(idcode, (stream, _))| {
+                    // Addresses are recorded by the idcode as received
+                    let mut stream = stream.clone();
+                    if let Some((_, address)) = addresses
+                        .iter()
+                        .find(|(received, _)| self.remap.stream_idcode(**received) == *idcode)
+                    {
+                        stream.source = Some(address.clone());
+                    }
+                    (*idcode, stream)
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn save_checkpoint(&mut self) {
+        let checkpoint = self.checkpoint();
+        if let Some(checkpointer) = self.checkpointer.as_mut() {
+            if let Err(e) = checkpointer.save(&checkpoint) {
+                println!("Failed to save checkpoint: {}", e);
+                self.stats.errors += 1;
+            }
+        }
+    }
+
+    // False for a data frame that is not newer than the last one of its
+    // stream, which is remembered otherwise.
+    fn is_new(&mut self, frame: &[u8]) -> bool {
+        if frame.len() < 14 {
+            return true;
+        }
+        let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+        let Some((stream, time_base)) = self.streams.get_mut(&idcode) else {
+            return true;
+        };
+        let Some(mut timestamp) = frame_timestamp_micros_with(frame, *time_base) else {
+            return true;
+        };
+        // Past the SOC rollover of 2106 when the last one is
+        if let Some(last) = stream.last_timestamp_us {
+            timestamp = unwrap_soc_rollover(timestamp, last);
+        }
+        if stream
+            .last_timestamp_us
+            .is_some_and(|last| timestamp <= last)
+        {
+            return false;
+        }
+        stream.last_timestamp_us = Some(timestamp);
+        true
+    }
+
+    // Configuration frames register their stream, data frames are accumulated.
+    // Both are remapped first; checkpoints keep the configuration as received,
+    // which is what the client expects on reconnect.
+    // Count a frame that failed to parse and hand it to the dead-letter queue.
+    fn reject(&mut self, received: &[u8], reason: &str, error: String) {
+        self.stats.errors += 1;
+        let Some(queue) = self.dead_letters.as_mut() else {
+            return;
+        };
+        let source = match received.get(4..6) {
+            Some(idcode) => {
+                let idcode = u16::from_be_bytes([idcode[0], idcode[1]]);
+                self.addresses
+                    .lock()
+                    .ok()
+                    .and_then(|addresses| addresses.get(&idcode).cloned())
+                    .unwrap_or_else(|| format!("idcode {}", idcode))
+            }
+            None => "unknown".to_string(),
+        };
+        let letter = DeadLetter {
+            frame: received.to_vec(),
+            arrival_us: now_micros(),
+            source,
+            reason: reason.to_string(),
+            error,
+        };
+        if let Err(e) = queue.push(letter) {
+            println!("Failed to write dead letter: {}", e);
+        }
+    }
+
+    // Take up changed ingest settings. Rows buffered so far are written with
+    // the settings before; the sinks of streams disabled are closed and
+    // opened again once they are enabled.
+    fn update_ingest(&mut self) {
+        let Some(ingest) = self.ingest.as_mut() else {
+            return;
+        };
+        if !ingest.has_changed().unwrap_or(false) {
+            return;
+        }
+        let settings = ingest.borrow_and_update().clone();
+        let streams: Vec<u16> = if settings.disabled_channels != self.settings.disabled_channels {
+            self.streams.keys().copied().collect()
+        } else {
+            settings
+                .disabled_streams
+                .difference(&self.settings.disabled_streams)
+                .copied()
+                .collect()
+        };
+        for idcode in streams {
+            match self.accumulator.flush(idcode) {
+                Ok(Some(batch)) => self.write(idcode, &batch),
+                Ok(None) => {}
+                Err(e) => {
+                    println!("Failed to flush stream {}: {:?}", idcode, e);
+                    self.stats.errors += 1;
+                }
+            }
+        }
+        for &idcode in settings
+            .disabled_streams
+            .difference(&self.settings.disabled_streams)
+        {
+            println!("Stream {} disabled", idcode);
+            let keys: Vec<(usize, u16)> = self
+                .sinks
+                .keys()
+                .filter(|(_, stream)| *stream == idcode)
+                .copied()
+                .collect();
+            for key in keys {
+                if let Some(mut sink) = self.sinks.remove(&key) {
+                    if let Err(e) = sink.close() {
+                        println!("Failed to close sink of stream {}: {}", idcode, e);
+                        self.stats.errors += 1;
+                    }
+                }
+            }
+        }
+        self.settings = settings;
+    }
+
+    fn push(&mut self, received: &[u8]) {
+        self.update_ingest();
+        if received.len() < 2 {
+            self.reject(received, "short", format!("{} bytes", received.len()));
+            return;
+        }
+        // Snapshots hold the frames as received
+        if let Some(snapshots) = self.snapshots.as_mut() {
+            if let Err(e) = snapshots.push(received, now_micros()) {
+                println!("Failed to write snapshot: {}", e);
+                self.stats.errors += 1;
+            }
+        }
+        // Strict mode checks the fields as the device sent them
+        if let Some(strict) = self.strict.as_mut() {
+            let violations = strict.check(received);
+            if !violations.is_empty() {
+                let violations: Vec<String> = violations
+                    .iter()
+                    .map(|violation| violation.to_string())
+                    .collect();
+                println!("Rejected frame: {}", violations.join("; "));
+                self.reject(received, "strict", violations.join("; "));
+                return;
+            }
+        }
+        let remap = self.remap.clone();
+        let frame = match remap.apply(received) {
+            Ok(frame) => frame,
+            Err(e) => {
+                println!("Failed to remap frame: {}", e);
+                self.reject(received, "remap", e.to_string());
+                return;
+            }
+        };
+        let frame = frame.as_ref();
+        match (frame[1] >> 4) & 0x07 {
+            2 | 3 => match parse_config_frame_1and2(frame) {
+                Ok(config) => {
+                    let received_idcode = u16::from_be_bytes([received[4], received[5]]);
+                    let address = self
+                        .addresses
+                        .lock()
+                        .ok()
+                        .and_then(|addresses| addresses.get(&received_idcode).cloned());
+                    if let Some(address) = address {
+                        if let Some((mode, lenient)) = self.crc_modes.get(&address) {
+                            self.accumulator
+                                .set_crc_mode(config.prefix.idcode, *mode, *lenient);
+                        }
+                        if let Some(policy) = self.frame_size_policies.get(&address) {
+                            self.accumulator
+                                .set_frame_size_policy(config.prefix.idcode, *policy);
+                        }
+                    }
+                    self.accumulator.add_stream(&config);
+                    if let Some(triggers) = self.triggers.as_mut() {
+                        triggers.add_stream(&config);
+                    }
+                    let idcode = config.prefix.idcode;
+                    let last_timestamp_us = self
+                        .streams
+                        .get(&idcode)
+                        .and_then(|(stream, _)| stream.last_timestamp_us);
+                    let stream = StreamState {
+                        source: None,
+                        config: Frame(received.to_vec()),
+                        last_timestamp_us,
+                    };
+                    self.streams.insert(idcode, (stream, config.time_base));
+                }
+                Err(e) => {
+                    println!("Invalid configuration frame: {:?}", e);
+                    self.reject(received, "config", format!("{:?}", e));
+                }
+            },
+            0 if !self
+                .settings
+                .is_stream_enabled(u16::from_be_bytes([frame[4], frame[5]])) =>
+            {
+                self.stats.disabled += 1
+            }
+            0 if !self.is_new(frame) => self.stats.stale += 1,
+            0 => {
+                self.stats.frames += 1;
+                self.record_latency(frame);
+                if let Some(triggers) = self.triggers.as_mut() {
+                    if let Err(e) = triggers.push_frame(frame) {
+                        println!("Failed to evaluate triggers: {}", e);
+                    }
+                }
+                let result = self.accumulator.push_frame(frame);
+                if result.is_ok() {
+                    self.trace_parsed(received, frame);
+                }
+                match result {
+                    Ok(Some((idcode, batch))) => self.write(idcode, &batch),
+                    Ok(None) => {}
+                    Err(e) => {
+                        println!("Dropped frame: {:?}", e);
+                        let reason = match &e {
+                            AccumulatorError::UnknownStream(_) => "unknown_stream",
+                            AccumulatorError::InvalidCrc(_) => "crc",
+                            AccumulatorError::InvalidFrameSize { .. } => "frame_size",
+                            AccumulatorError::Arrow(_) => "arrow",
+                        };
+                        self.reject(received, reason, format!("{:?}", e));
+                    }
+                }
+            }
+            _ => {}
+        }
+        if self.checkpointer.as_ref().is_some_and(|c| c.is_due()) {
+            self.save_checkpoint();
+        }
+    }
+
+    fn record_latency(&self, frame: &[u8]) {
+        let Some(metrics) = &self.latency else {
+            return;
+        };
+        // Frames too short for SOC/FRACSEC have no latency to report
+        if frame.len() < 14 {
+            return;
+        }
+        let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+        let Some((_, time_base)) = self.streams.get(&idcode) else {
+            return;
+        };
+        if let Some(frame_us) = frame_timestamp_micros_with(frame, *time_base) {
+            let latency_us = now_micros() - frame_us;
+            let stream = idcode.to_string();
+            metrics.set_gauge(LATENCY_METRIC, &[("stream", &stream)], latency_us as f64);
+        }
+    }
+
+    fn trace_parsed(&self, received: &[u8], frame: &[u8]) {
+        let Some(tracer) = &self.tracer else {
+            return;
+        };
+        if frame.len() < 14 {
+            return;
+        }
+        let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+        let Some((_, time_base)) = self.streams.get(&idcode) else {
+            return;
+        };
+        if let Some(frame_us) = frame_timestamp_micros_with(frame, *time_base) {
+            let received_idcode = u16::from_be_bytes([received[4], received[5]]);
+            tracer.parsed(received_idcode, idcode, frame_us, now_micros());
+        }
+    }
+
+    fn write(&mut self, idcode: u16, batch: &RecordBatch) {
+        let aligned_us = now_micros();
+        if let Some((config, metrics)) = &self.jitter {
+            self.jitter_monitors
+                .entry(idcode)
+                .or_insert_with(|| {
+                    JitterMonitor::new(config.window, &idcode.to_string())
+                        .with_metrics(metrics.clone())
+                })
+                .push_batch(batch);
+        }
+        let derived;
+        let batch = if self.derived.is_empty() {
+            batch
+        } else {
+            match self.derived.apply(batch) {
+                Ok(batch) => {
+                    derived = batch;
+                    &derived
+                }
+                Err(e) => {
+                    println!("Failed to derive channels of stream {}: {}", idcode, e);
+                    self.stats.errors += 1;
+                    batch
+                }
+            }
+        };
+        let joined;
+        let batch = match self.scada.as_ref().map(|scada| scada.apply(batch)) {
+            Some(Ok(batch)) => {
+                joined = batch;
+                &joined
+            }
+            Some(Err(e)) => {
+                println!("Failed to join SCADA points to stream {}: {}", idcode, e);
+                self.stats.errors += 1;
+                batch
+            }
+            None => batch,
+        };
+        let mapped;
+        let batch = if self.topology.is_empty() {
+            batch
+        } else {
+            match self.topology.apply(batch) {
+                Ok(batch) => {
+                    mapped = batch;
+                    &mapped
+                }
+                Err(e) => {
+                    println!("Failed to map channels of stream {}: {}", idcode, e);
+                    self.stats.errors += 1;
+                    batch
+                }
+            }
+        };
+        let selected;
+        let batch = if self.settings.disabled_channels.is_empty() {
+            batch
+        } else {
+            match self.settings.select(batch) {
+                Ok(batch) => {
+                    selected = batch;
+                    &selected
+                }
+                Err(e) => {
+                    println!("Failed to select channels of stream {}: {}", idcode, e);
+                    self.stats.errors += 1;
+                    return;
+                }
+            }
+        };
+        let mut written = false;
+        for (index, config) in self.sink_configs.iter().enumerate() {
+            let sink = match self.sinks.entry((index, idcode)) {
+                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                std::collections::hash_map::Entry::Vacant(entry) => match config.open(idcode) {
+                    Ok(sink) => entry.insert(sink),
+                    Err(e) => {
+                        println!("Failed to open sink for stream {}: {}", idcode, e);
+                        self.stats.errors += 1;
+                        continue;
+                    }
+                },
+            };
+            match sink.write_batch(batch) {
+                Ok(()) => written = true,
+                Err(e) => {
+                    println!("Failed to write batch of stream {}: {}", idcode, e);
+                    self.stats.errors += 1;
+                }
+            }
+        }
+        if written {
+            self.stats.batches += 1;
+            self.stats.rows += batch.num_rows() as u64;
+        }
+        if let Some(tracer) = &self.tracer {
+            let last_us = batch
+                .column_by_name("timestamp")
+                .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+                .and_then(arrow::compute::max);
+            if let Some(last_us) = last_us {
+                tracer.batch_written(idcode, last_us, aligned_us, now_micros());
+            }
+        }
+    }
+
+    // Write the rows still buffered and close the sinks.
+    fn finish(mut self) -> io::Result<ShardStats> {
+        let batches = self
+            .accumulator
+            .flush_all()
+            .map_err(|e| io::Error::other(format!("{:?}", e)))?;
+        for (idcode, batch) in batches {
+            self.write(idcode, &batch);
+        }
+        for sink in self.sinks.values_mut() {
+            sink.close()?;
+        }
+        if let Some(snapshots) = self.snapshots.as_mut() {
+            snapshots.finish()?;
+        }
+        if let Some(dead_letters) = self.dead_letters.as_mut() {
+            dead_letters.finish()?;
+        }
+        self.save_checkpoint();
+        Ok(self.stats)
+    }
+}
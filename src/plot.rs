@@ -0,0 +1,422 @@
+// Quick-look charts of a time range or an event window.
+//
+// A QuickLook takes wide batches and renders stacked panels over a shared
+// time axis (seconds from the start of the range):
+//
+//   - frequency, every frequency column
+//   - magnitudes, the phasor magnitudes selected by channel name, by default
+//     those of the voltage phasors
+//   - angle differences, the first voltage angle of each station against
+//     that of the reference station, in degrees within -180..180
+//
+// Panels without data are left out. Charts are SVG or PNG by the extension of
+// the path; render_svg gives the SVG text for embedding in reports. Text is
+// left to the SVG viewer, PNGs carry the lines and axes without labels.
+use crate::arrow_utils::{
+    META_CHANNEL, META_COMPONENT, META_KIND, META_OFFSET, META_SCALE, META_STATION,
+};
+use crate::events::Event;
+use arrow::array::{Array, Float64Array, TimestampMicrosecondArray};
+use arrow::compute::cast;
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChartStats {
+    pub panels: usize,
+    pub series: usize,
+    pub points: usize,
+}
+
+#[derive(Default)]
+struct Panel {
+    title: &'static str,
+    unit: &'static str,
+    series: BTreeMap<String, Vec<(i64, f64)>>,
+}
+
+// Components of a phasor channel by name, scaled.
+struct PhasorParts {
+    kind: String,
+    station: String,
+    parts: HashMap<String, Vec<f64>>,
+}
+
+pub struct QuickLook {
+    width: u32,
+    height: u32,
+    title: String,
+    magnitudes: Vec<String>, // Phasor channels, empty for the voltage phasors
+    reference: Option<String>, // Station, by default the first by name
+    range: Option<(i64, i64)>,
+    marker_us: Option<i64>, // Event time, a vertical line
+    frequency: BTreeMap<String, Vec<(i64, f64)>>,
+    magnitude: BTreeMap<String, Vec<(i64, f64)>>,
+    angles: BTreeMap<String, Vec<(i64, f64)>>, // Radians by station
+}
+
+impl Default for QuickLook {
+    fn default() -> Self {
+        QuickLook::new()
+    }
+}
+
+impl QuickLook {
+    pub fn new() -> Self {
+        QuickLook {
+            width: 1024,
+            height: 768,
+            title: String::new(),
+            magnitudes: Vec::new(),
+            reference: None,
+            range: None,
+            marker_us: None,
+            frequency: BTreeMap::new(),
+            magnitude: BTreeMap::new(),
+            angles: BTreeMap::new(),
+        }
+    }
+
+    // Window of an event, with the event time marked.
+    pub fn for_event(event: &Event, pre_secs: f64, post_secs: f64) -> Self {
+        QuickLook::new()
+            .with_title(&format!("{:?} {}", event.kind, event.source))
+            .with_range(
+                event.timestamp_us - (pre_secs * 1e6) as i64,
+                event.timestamp_us + (post_secs * 1e6) as i64,
+            )
+            .with_marker(event.timestamp_us)
+    }
+
+    pub fn with_size(mut self, width: u32, height: u32) -> Self {
+        self.width = width.max(100);
+        self.height = height.max(100);
+        self
+    }
+
+    pub fn with_title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    pub fn with_magnitudes(mut self, channels: &[&str]) -> Self {
+        self.magnitudes = channels.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    pub fn with_reference(mut self, station: &str) -> Self {
+        self.reference = Some(station.trim().to_string());
+        self
+    }
+
+    // Samples outside start..=end are left out.
+    pub fn with_range(mut self, start_us: i64, end_us: i64) -> Self {
+        self.range = Some((start_us, end_us));
+        self
+    }
+
+    pub fn with_marker(mut self, timestamp_us: i64) -> Self {
+        self.marker_us = Some(timestamp_us);
+        self
+    }
+
+    // Take a wide batch with a timestamp column. Phasors may be polar or
+    // rectangular.
+    pub fn add_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let timestamps = batch
+            .column_by_name("timestamp")
+            .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Batch without timestamp column")
+            })?;
+        let rows: Vec<Option<i64>> = (0..batch.num_rows())
+            .map(|row| {
+                let timestamp = timestamps.value(row);
+                let in_range = self
+                    .range
+                    .is_none_or(|(start, end)| (start..=end).contains(&timestamp));
+                (timestamps.is_valid(row) && in_range).then_some(timestamp)
+            })
+            .collect();
+        // Components of the phasors by channel, with kind and station
+        let mut phasors: BTreeMap<String, PhasorParts> = BTreeMap::new();
+        let schema = batch.schema();
+        for (field, column) in schema.fields().iter().zip(batch.columns()) {
+            let meta = field.metadata();
+            let text = |key: &str| meta.get(key).cloned().unwrap_or_default();
+            let (kind, component) = (text(META_KIND), text(META_COMPONENT));
+            if kind != "frequency"
+                && !["magnitude", "angle", "real", "imaginary"].contains(&component.as_str())
+            {
+                continue;
+            }
+            let Ok(values) = cast(column, &DataType::Float64) else {
+                continue;
+            };
+            let Some(values) = values.as_any().downcast_ref::<Float64Array>() else {
+                continue;
+            };
+            let number = |key: &str, default: f64| {
+                meta.get(key)
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .unwrap_or(default)
+            };
+            let (scale, offset) = (number(META_SCALE, 1.0), number(META_OFFSET, 0.0));
+            let values: Vec<f64> = (0..values.len())
+                .map(|row| match values.is_valid(row) {
+                    true => values.value(row) * scale + offset,
+                    false => f64::NAN,
+                })
+                .collect();
+            if kind == "frequency" {
+                push(
+                    self.frequency.entry(field.name().clone()).or_default(),
+                    &rows,
+                    values,
+                );
+                continue;
+            }
+            let channel = meta.get(META_CHANNEL).unwrap_or(field.name()).clone();
+            let station = text(META_STATION).trim().to_string();
+            phasors
+                .entry(channel)
+                .or_insert_with(|| PhasorParts {
+                    kind,
+                    station,
+                    parts: HashMap::new(),
+                })
+                .parts
+                .insert(component, values);
+        }
+        let mut stations_seen = Vec::new();
+        for (
+            channel,
+            PhasorParts {
+                kind,
+                station,
+                parts,
+            },
+        ) in phasors
+        {
+            let (magnitudes, angles) = match (parts.get("real"), parts.get("imaginary")) {
+                (Some(real), Some(imaginary)) => (
+                    real.iter()
+                        .zip(imaginary)
+                        .map(|(x, y)| x.hypot(*y))
+                        .collect(),
+                    real.iter()
+                        .zip(imaginary)
+                        .map(|(x, y)| y.atan2(*x))
+                        .collect(),
+                ),
+                _ => (
+                    parts.get("magnitude").cloned().unwrap_or_default(),
+                    parts.get("angle").cloned().unwrap_or_default(),
+                ),
+            };
+            let selected = match self.magnitudes.is_empty() {
+                true => kind == "voltage",
+                false => self.magnitudes.contains(&channel),
+            };
+            if selected && !magnitudes.is_empty() {
+                push(
+                    self.magnitude.entry(channel).or_default(),
+                    &rows,
+                    magnitudes,
+                );
+            }
+            // The first voltage phasor of the station by channel name
+            if kind == "voltage" && !angles.is_empty() && !stations_seen.contains(&station) {
+                stations_seen.push(station.clone());
+                push(self.angles.entry(station).or_default(), &rows, angles);
+            }
+        }
+        Ok(())
+    }
+
+    // Render to an .svg or .png file.
+    pub fn render(&self, path: impl AsRef<Path>) -> io::Result<ChartStats> {
+        let path = path.as_ref();
+        let size = (self.width, self.height);
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("svg") => self.draw(SVGBackend::new(path, size).into_drawing_area(), true),
+            Some("png") => self.draw(BitMapBackend::new(path, size).into_drawing_area(), false),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Charts are .svg or .png: {}", path.display()),
+            )),
+        }
+    }
+
+    pub fn render_svg(&self) -> io::Result<String> {
+        let mut svg = String::new();
+        let size = (self.width, self.height);
+        self.draw(
+            SVGBackend::with_string(&mut svg, size).into_drawing_area(),
+            true,
+        )?;
+        Ok(svg)
+    }
+
+    fn panels(&self) -> Vec<Panel> {
+        let mut panels = Vec::new();
+        let mut add = |title, unit, series: &BTreeMap<String, Vec<(i64, f64)>>| {
+            let series: BTreeMap<String, Vec<(i64, f64)>> = series
+                .iter()
+                .filter(|(_, samples)| !samples.is_empty())
+                .map(|(name, samples)| (name.clone(), samples.clone()))
+                .collect();
+            if !series.is_empty() {
+                panels.push(Panel {
+                    title,
+                    unit,
+                    series,
+                });
+            }
+        };
+        add("Frequency", "Hz", &self.frequency);
+        add("Magnitude", "", &self.magnitude);
+        add("Angle difference", "deg", &self.angle_differences());
+        panels
+    }
+
+    // Angles of the stations against the reference at its timestamps.
+    fn angle_differences(&self) -> BTreeMap<String, Vec<(i64, f64)>> {
+        let reference = match &self.reference {
+            Some(station) => station.clone(),
+            None => match self.angles.keys().next() {
+                Some(station) => station.clone(),
+                None => return BTreeMap::new(),
+            },
+        };
+        let Some(reference_angles) = self.angles.get(&reference) else {
+            return BTreeMap::new();
+        };
+        let reference_angles: HashMap<i64, f64> = reference_angles.iter().copied().collect();
+        self.angles
+            .iter()
+            .filter(|(station, _)| **station != reference)
+            .map(|(station, samples)| {
+                let differences = samples
+                    .iter()
+                    .filter_map(|(timestamp, angle)| {
+                        let difference = angle - reference_angles.get(timestamp)?;
+                        let wrapped = (difference + PI).rem_euclid(2.0 * PI) - PI;
+                        Some((*timestamp, wrapped.to_degrees()))
+                    })
+                    .collect();
+                (format!("{} - {}", station, reference), differences)
+            })
+            .collect()
+    }
+
+    // Without text for backends that would need a font to draw it.
+    fn draw<DB: DrawingBackend>(
+        &self,
+        root: DrawingArea<DB, Shift>,
+        text: bool,
+    ) -> io::Result<ChartStats> {
+        let error = |e: DrawingAreaErrorKind<DB::ErrorType>| io::Error::other(e.to_string());
+        let panels = self.panels();
+        let mut stats = ChartStats {
+            panels: panels.len(),
+            ..ChartStats::default()
+        };
+        root.fill(&WHITE).map_err(error)?;
+        let area = if self.title.is_empty() || !text {
+            root.clone()
+        } else {
+            root.titled(&self.title, ("sans-serif", 20))
+                .map_err(error)?
+        };
+        let times = panels
+            .iter()
+            .flat_map(|panel| panel.series.values().flatten().map(|(t, _)| *t));
+        let (start, end) = match self.range {
+            Some(range) => range,
+            None => (
+                times.clone().min().unwrap_or_default(),
+                times.max().unwrap_or_default(),
+            ),
+        };
+        let seconds = |timestamp: i64| (timestamp - start) as f64 / 1e6;
+        let span = seconds(end).max(1e-3);
+        let mut color = 0;
+        for (panel, area) in panels
+            .iter()
+            .zip(area.split_evenly((panels.len().max(1), 1)))
+        {
+            let values = panel.series.values().flatten().map(|(_, v)| *v);
+            let low = values.clone().fold(f64::INFINITY, f64::min);
+            let high = values.fold(f64::NEG_INFINITY, f64::max);
+            let margin = ((high - low) * 0.05).max(1e-6);
+            let mut builder = ChartBuilder::on(&area);
+            builder.margin(8);
+            if text {
+                builder
+                    .caption(panel.title, ("sans-serif", 16))
+                    .x_label_area_size(30)
+                    .y_label_area_size(60);
+            }
+            let mut chart = builder
+                .build_cartesian_2d(0.0..span, low - margin..high + margin)
+                .map_err(error)?;
+            chart
+                .configure_mesh()
+                .x_desc("s")
+                .y_desc(panel.unit)
+                .draw()
+                .map_err(error)?;
+            for (name, samples) in &panel.series {
+                let style = Palette99::pick(color).stroke_width(1);
+                color += 1;
+                chart
+                    .draw_series(LineSeries::new(
+                        samples.iter().map(|(t, v)| (seconds(*t), *v)),
+                        style,
+                    ))
+                    .map_err(error)?
+                    .label(name.as_str())
+                    .legend(move |(x, y)| PathElement::new([(x, y), (x + 16, y)], style));
+                stats.series += 1;
+                stats.points += samples.len();
+            }
+            if let Some(marker) = self.marker_us {
+                let x = seconds(marker);
+                chart
+                    .draw_series(LineSeries::new(
+                        [(x, low - margin), (x, high + margin)],
+                        RED.stroke_width(2),
+                    ))
+                    .map_err(error)?;
+            }
+            if !text {
+                continue;
+            }
+            chart
+                .configure_series_labels()
+                .background_style(WHITE.mix(0.8))
+                .border_style(BLACK)
+                .draw()
+                .map_err(error)?;
+        }
+        root.present().map_err(error)?;
+        Ok(stats)
+    }
+}
+
+// Values at the timestamps of their rows, those out of range or not a
+// number left out.
+fn push(samples: &mut Vec<(i64, f64)>, rows: &[Option<i64>], values: Vec<f64>) {
+    for (timestamp, value) in rows.iter().zip(values) {
+        if let Some(timestamp) = timestamp.filter(|_| value.is_finite()) {
+            samples.push((timestamp, value));
+        }
+    }
+}
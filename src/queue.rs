@@ -0,0 +1,131 @@
+// Lock-free handoff of frames between pipeline stages.
+//
+// Every stream gets its own single producer, single consumer ring buffer
+// (rtrb): the reader task of the stream pushes, the writer of its shard pops.
+// Pushing and popping are a few atomic operations, no lock and no
+// allocation. The writer takes frames from the rings in turn, so a busy
+// stream can't starve the others.
+//
+// The producers wake the waiting writer through a shared Notify, which only
+// takes its lock when the writer is actually asleep; while frames keep coming
+// the writer never sleeps and the handoff stays lock-free. A full ring drops
+// the frame rather than blocking the reader.
+use bytes::Bytes;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+// Shared between the rings of one consumer and their producers.
+#[derive(Default)]
+struct Shared {
+    wake: Notify,
+    dropped: AtomicU64,
+}
+
+pub struct RingProducer<T = Bytes> {
+    ring: rtrb::Producer<T>,
+    // Dropped after ring, so the consumer wakes up to an abandoned ring
+    shared: WakeOnDrop,
+}
+
+struct WakeOnDrop(Arc<Shared>);
+
+impl Drop for WakeOnDrop {
+    fn drop(&mut self) {
+        self.0.wake.notify_one();
+    }
+}
+
+impl<T> RingProducer<T> {
+    // Returns false when the ring was full and the value was dropped.
+    pub fn push(&mut self, value: T) -> bool {
+        let pushed = self.ring.push(value).is_ok();
+        if !pushed {
+            self.shared.0.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.shared.0.wake.notify_one();
+        pushed
+    }
+
+    // Free slots left in the ring.
+    pub fn slots(&self) -> usize {
+        self.ring.slots()
+    }
+}
+
+// Consumer side of the rings of one shard.
+pub struct Rings<T = Bytes> {
+    rings: Vec<rtrb::Consumer<T>>,
+    shared: Arc<Shared>,
+    next: usize,
+}
+
+impl<T> Default for Rings<T> {
+    fn default() -> Self {
+        Rings {
+            rings: Vec::new(),
+            shared: Arc::new(Shared::default()),
+            next: 0,
+        }
+    }
+}
+
+impl<T> Rings<T> {
+    pub fn new() -> Self {
+        Rings::default()
+    }
+
+    // A new ring holding up to capacity values, and its producer.
+    pub fn add(&mut self, capacity: usize) -> RingProducer<T> {
+        let (producer, consumer) = rtrb::RingBuffer::new(capacity.max(1));
+        self.rings.push(consumer);
+        RingProducer {
+            ring: producer,
+            shared: WakeOnDrop(self.shared.clone()),
+        }
+    }
+
+    // Values dropped because a ring was full.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    // Values waiting in all rings.
+    pub fn len(&self) -> usize {
+        self.rings.iter().map(|ring| ring.slots()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rings.iter().all(|ring| ring.is_empty())
+    }
+
+    // The next value of the next ring that has one.
+    pub fn try_pop(&mut self) -> Option<T> {
+        for _ in 0..self.rings.len() {
+            let index = self.next % self.rings.len();
+            self.next = index + 1;
+            if let Ok(value) = self.rings[index].pop() {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    // Wait for the next value. None once every producer is gone and the
+    // rings are empty.
+    pub async fn pop(&mut self) -> Option<T> {
+        loop {
+            if let Some(value) = self.try_pop() {
+                return Some(value);
+            }
+            // Abandoned rings are empty at this point, forget them
+            self.rings
+                .retain(|ring| !ring.is_abandoned() || !ring.is_empty());
+            if self.rings.is_empty() {
+                return None;
+            }
+            // A push since try_pop left a permit, this returns at once then
+            self.shared.wake.notified().await;
+        }
+    }
+}
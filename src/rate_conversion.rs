@@ -0,0 +1,155 @@
+// Frame rate conversion of a data stream, to serve it at another rate.
+//
+// A RateConverter turns the data frames of a stream at its configured rate
+// into frames at an output rate. Output instants are the multiples of the
+// output period within each second, where a PMU reporting at that rate would
+// send. An instant on the timestamp of an input frame gets that frame as it
+// is, so decimating to a rate that divides the input rate only drops frames.
+// An instant between two input frames, when upsampling, gets values
+// interpolated between them (accumulator::FrameInterpolator) with STAT bit 9
+// (data modified) set for every PMU, so consumers can tell them from
+// measurements. Interpolation waits for the input frame after the instant:
+// interpolated frames go out one input period late.
+//
+// Instants between frames more than two input periods apart are skipped
+// rather than interpolated across the gap. Clients must be sent the
+// configuration at the output rate, output_config().
+use crate::accumulator::FrameInterpolator;
+use crate::arrow_utils::frame_timestamp_micros_with;
+use crate::frames::{ChannelDataType, ConfigurationFrame1and2_2011};
+use std::io;
+
+const STAT_DATA_MODIFIED: u16 = 0x0200;
+const MAX_GAP_PERIODS: f64 = 2.0; // Input frames further apart are not interpolated between
+
+pub struct RateConverter {
+    interpolator: FrameInterpolator,
+    config: ConfigurationFrame1and2_2011, // At the output rate
+    stat_offsets: Vec<usize>,
+    frame_size: usize,
+    input_period_us: f64,
+    tolerance_us: i64,            // Of FRACSEC rounding, when matching instants
+    last: Option<(i64, Vec<u8>)>, // Timestamp and bytes of the newest input frame
+    interpolated: u64,
+}
+
+impl RateConverter {
+    // Both rates in frames per second.
+    pub fn new(config: &ConfigurationFrame1and2_2011, output_rate: i16) -> io::Result<Self> {
+        if config.data_rate <= 0 || output_rate <= 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Rate conversion needs frames per second, not {} to {}",
+                    config.data_rate, output_rate
+                ),
+            ));
+        }
+        let mut output = config.clone();
+        output.data_rate = output_rate;
+        let stat_offsets = config
+            .get_channel_map()
+            .values()
+            .filter(|info| matches!(info.data_type, ChannelDataType::Stat))
+            .map(|info| info.offset)
+            .collect();
+        Ok(RateConverter {
+            interpolator: FrameInterpolator::new(config),
+            config: output,
+            stat_offsets,
+            frame_size: config.calc_data_frame_size(),
+            input_period_us: 1_000_000.0 / config.data_rate as f64,
+            tolerance_us: (1_000_000 / config.time_base.max(1) as i64).max(1),
+            last: None,
+            interpolated: 0,
+        })
+    }
+
+    // The configuration to send clients, DATA_RATE being the output rate.
+    pub fn output_config(&self) -> &ConfigurationFrame1and2_2011 {
+        &self.config
+    }
+
+    pub fn output_rate(&self) -> i16 {
+        self.config.data_rate
+    }
+
+    // Frames interpolated so far.
+    pub fn interpolated(&self) -> u64 {
+        self.interpolated
+    }
+
+    // Add an input data frame. Returns the output frames due, in time order.
+    pub fn push(&mut self, frame: &[u8]) -> Vec<Vec<u8>> {
+        if frame.len() != self.frame_size {
+            return Vec::new();
+        }
+        let Some(timestamp_us) = frame_timestamp_micros_with(frame, self.config.time_base) else {
+            return Vec::new();
+        };
+        let tolerance_us = self.tolerance_us;
+        let mut output = Vec::new();
+        match self.last.take() {
+            // Repeated or out of order
+            Some((last_us, last)) if timestamp_us <= last_us => {
+                self.last = Some((last_us, last));
+                return output;
+            }
+            Some((last_us, last))
+                if ((timestamp_us - last_us) as f64)
+                    <= MAX_GAP_PERIODS * self.input_period_us + tolerance_us as f64 =>
+            {
+                for instant in self.instants(last_us + tolerance_us, timestamp_us + tolerance_us) {
+                    if (instant - timestamp_us).abs() <= tolerance_us {
+                        output.push(frame.to_vec());
+                        continue;
+                    }
+                    let fraction = (instant - last_us) as f64 / (timestamp_us - last_us) as f64;
+                    let mut interpolated = self.interpolator.interpolate(&last, frame, fraction);
+                    for offset in &self.stat_offsets {
+                        let stat =
+                            u16::from_be_bytes([interpolated[*offset], interpolated[offset + 1]]);
+                        interpolated[*offset..offset + 2]
+                            .copy_from_slice(&(stat | STAT_DATA_MODIFIED).to_be_bytes());
+                    }
+                    self.interpolator.set_timestamp(&mut interpolated, instant);
+                    self.interpolated += 1;
+                    output.push(interpolated);
+                }
+            }
+            // The first frame, or the first after a gap
+            _ => {
+                let on_instant = !self
+                    .instants(timestamp_us - tolerance_us - 1, timestamp_us + tolerance_us)
+                    .is_empty();
+                if on_instant {
+                    output.push(frame.to_vec());
+                }
+            }
+        }
+        self.last = Some((timestamp_us, frame.to_vec()));
+        output
+    }
+
+    // Output instants after after_us up to until_us.
+    fn instants(&self, after_us: i64, until_us: i64) -> Vec<i64> {
+        let rate = self.config.data_rate as i64;
+        let mut second = after_us.div_euclid(1_000_000);
+        let mut k = after_us.rem_euclid(1_000_000) * rate / 1_000_000;
+        let mut instants = Vec::new();
+        loop {
+            if k == rate {
+                second += 1;
+                k = 0;
+            }
+            let instant = second * 1_000_000 + (k as f64 * 1e6 / rate as f64).round() as i64;
+            if instant > until_us {
+                return instants;
+            }
+            if instant > after_us {
+                instants.push(instant);
+            }
+            k += 1;
+        }
+    }
+}
@@ -0,0 +1,443 @@
+// Raw frame recorder.
+//
+// Captures frames exactly as received so they can be replayed or re-parsed
+// later. Frames are grouped into blocks that are optionally zstd compressed,
+// and an index of block time ranges at the end of the file allows seeking by
+// timestamp without decompressing the whole capture.
+//
+// File layout (all integers big endian):
+//   header:  magic "PMUCAP01" (8) | flags u16 (bit 0 = zstd) | reserved u16
+//   block:   first_ts i64 | last_ts i64 | frame_count u32 | stored_len u32 | raw_len u32
+//            payload (stored_len bytes, zstd frame when compressed)
+//   record:  arrival_us i64 | frame_len u16 | frame bytes      (inside a payload)
+//   index:   per block: first_ts i64 | last_ts i64 | offset u64 | frame_count u32
+//   footer:  index_offset u64 | block_count u32 | magic "PMUIDX01"
+//
+// Block timestamps are the min/max frame timestamps (SOC/FRACSEC) of the block.
+// A capture that was not finished has no index, the reader then scans the
+// block headers and ignores a trailing partial block.
+//
+// Every block is synced and recorded in a sidecar manifest (sinks::manifest)
+// when it is written. CaptureWriter::resume cuts an interrupted capture back
+// to its last recorded block, dropping a torn block and the old index, and
+// appends from there.
+use crate::arrow_utils::frame_timestamp_micros;
+use crate::frames::now_micros;
+use crate::sinks::manifest::{DurableFile, Manifest};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const CAPTURE_MAGIC: &[u8; 8] = b"PMUCAP01";
+const INDEX_MAGIC: &[u8; 8] = b"PMUIDX01";
+const FLAG_ZSTD: u16 = 0x0001;
+const FILE_HEADER_SIZE: u64 = 12;
+const BLOCK_HEADER_SIZE: usize = 28;
+const INDEX_ENTRY_SIZE: usize = 28;
+const FOOTER_SIZE: u64 = 20;
+const DEFAULT_BLOCK_FRAMES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureCompression {
+    None,
+    Zstd(i32), // Compression level
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockIndexEntry {
+    pub first_timestamp: i64,
+    pub last_timestamp: i64,
+    pub offset: u64,
+    pub frame_count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    pub arrival_us: i64, // Wall clock receive time in microseconds since the epoch
+    pub frame: Vec<u8>,
+}
+
+impl CaptureRecord {
+    // Timestamp carried in the frame, falling back to arrival time for truncated frames.
+    pub fn timestamp_us(&self) -> i64 {
+        frame_timestamp_micros(&self.frame).unwrap_or(self.arrival_us)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub struct CaptureWriter {
+    file: DurableFile,
+    compression: CaptureCompression,
+    block_frames: usize,
+    block: Vec<u8>,
+    block_count: u32,
+    block_range: Option<(i64, i64)>,
+    offset: u64,
+    index: Vec<BlockIndexEntry>,
+    finished: bool,
+}
+
+impl CaptureWriter {
+    pub fn create(path: impl AsRef<Path>, compression: CaptureCompression) -> io::Result<Self> {
+        let mut file = DurableFile::create(path)?;
+        let flags = match compression {
+            CaptureCompression::None => 0,
+            CaptureCompression::Zstd(_) => FLAG_ZSTD,
+        };
+        file.write_all(CAPTURE_MAGIC)?;
+        file.write_all(&flags.to_be_bytes())?;
+        file.write_all(&[0, 0])?;
+        file.commit(0, 0)?;
+        Ok(Self::with_file(file, compression, Vec::new()))
+    }
+
+    // Append to a capture after its last durable block. Starts a new capture
+    // when there is nothing to resume.
+    pub fn resume(path: impl AsRef<Path>, compression: CaptureCompression) -> io::Result<Self> {
+        let path = path.as_ref();
+        if Manifest::load(Manifest::sidecar(path))?.is_none_or(|m| m.offset < FILE_HEADER_SIZE) {
+            return Self::create(path, compression);
+        }
+        let file = DurableFile::resume(path)?;
+        let reader = CaptureReader::open(path)?;
+        if reader.is_compressed() != matches!(compression, CaptureCompression::Zstd(_)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Capture compression differs from the existing file",
+            ));
+        }
+        println!(
+            "Resuming capture {} after {} blocks",
+            path.display(),
+            reader.index().len()
+        );
+        Ok(Self::with_file(file, compression, reader.index().to_vec()))
+    }
+
+    fn with_file(
+        file: DurableFile,
+        compression: CaptureCompression,
+        index: Vec<BlockIndexEntry>,
+    ) -> Self {
+        Self {
+            offset: file.offset(),
+            file,
+            compression,
+            block_frames: DEFAULT_BLOCK_FRAMES,
+            block: Vec::new(),
+            block_count: 0,
+            block_range: None,
+            index,
+            finished: false,
+        }
+    }
+
+    // Frames per block. Larger blocks compress better, smaller blocks seek faster.
+    pub fn with_block_frames(mut self, block_frames: usize) -> Self {
+        self.block_frames = block_frames.max(1);
+        self
+    }
+
+    pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.write_frame_at(frame, now_micros())
+    }
+
+    pub fn write_frame_at(&mut self, frame: &[u8], arrival_us: i64) -> io::Result<()> {
+        if frame.len() > u16::MAX as usize {
+            return Err(invalid_data("Frame larger than 65535 bytes"));
+        }
+        let timestamp = frame_timestamp_micros(frame).unwrap_or(arrival_us);
+        self.block_range = Some(match self.block_range {
+            Some((first, last)) => (first.min(timestamp), last.max(timestamp)),
+            None => (timestamp, timestamp),
+        });
+        self.block.extend_from_slice(&arrival_us.to_be_bytes());
+        self.block
+            .extend_from_slice(&(frame.len() as u16).to_be_bytes());
+        self.block.extend_from_slice(frame);
+        self.block_count += 1;
+
+        if self.block_count as usize >= self.block_frames {
+            self.flush_block()?;
+        }
+        Ok(())
+    }
+
+    fn flush_block(&mut self) -> io::Result<()> {
+        let Some((first, last)) = self.block_range.take() else {
+            return Ok(());
+        };
+        let raw_len = self.block.len() as u32;
+        let payload = match self.compression {
+            CaptureCompression::None => std::mem::take(&mut self.block),
+            CaptureCompression::Zstd(level) => zstd::encode_all(&self.block[..], level)?,
+        };
+
+        self.file.write_all(&first.to_be_bytes())?;
+        self.file.write_all(&last.to_be_bytes())?;
+        self.file.write_all(&self.block_count.to_be_bytes())?;
+        self.file.write_all(&(payload.len() as u32).to_be_bytes())?;
+        self.file.write_all(&raw_len.to_be_bytes())?;
+        self.file.write_all(&payload)?;
+
+        self.index.push(BlockIndexEntry {
+            first_timestamp: first,
+            last_timestamp: last,
+            offset: self.offset,
+            frame_count: self.block_count,
+        });
+        self.offset += (BLOCK_HEADER_SIZE + payload.len()) as u64;
+        self.file.commit(1, self.block_count as u64)?;
+        self.block.clear();
+        self.block_count = 0;
+        Ok(())
+    }
+
+    // Write out the last block and the index. Called automatically on drop.
+    pub fn finish(&mut self) -> io::Result<Vec<BlockIndexEntry>> {
+        if self.finished {
+            return Ok(self.index.clone());
+        }
+        self.flush_block()?;
+        let index_offset = self.offset;
+        for entry in &self.index {
+            self.file.write_all(&entry.first_timestamp.to_be_bytes())?;
+            self.file.write_all(&entry.last_timestamp.to_be_bytes())?;
+            self.file.write_all(&entry.offset.to_be_bytes())?;
+            self.file.write_all(&entry.frame_count.to_be_bytes())?;
+        }
+        self.file.write_all(&index_offset.to_be_bytes())?;
+        self.file
+            .write_all(&(self.index.len() as u32).to_be_bytes())?;
+        self.file.write_all(INDEX_MAGIC)?;
+        // Not recorded in the manifest, a resume writes a new index
+        self.file.sync()?;
+        self.finished = true;
+        Ok(self.index.clone())
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            println!("Failed to finish capture file: {}", e);
+        }
+    }
+}
+
+pub struct CaptureReader {
+    file: File,
+    compressed: bool,
+    index: Vec<BlockIndexEntry>,
+    indexed: bool,
+}
+
+impl CaptureReader {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut header = [0u8; FILE_HEADER_SIZE as usize];
+        file.read_exact(&mut header)?;
+        if &header[..8] != CAPTURE_MAGIC {
+            return Err(invalid_data("Not a PMU capture file"));
+        }
+        let flags = u16::from_be_bytes([header[8], header[9]]);
+
+        let mut reader = Self {
+            file,
+            compressed: flags & FLAG_ZSTD != 0,
+            index: Vec::new(),
+            indexed: false,
+        };
+        match reader.read_index()? {
+            Some(index) => {
+                reader.index = index;
+                reader.indexed = true;
+            }
+            None => {
+                println!("Capture has no index, scanning blocks");
+                reader.index = reader.scan_blocks()?;
+            }
+        }
+        Ok(reader)
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    // False when the capture was not finished and the index was rebuilt by scanning.
+    pub fn is_indexed(&self) -> bool {
+        self.indexed
+    }
+
+    pub fn index(&self) -> &[BlockIndexEntry] {
+        &self.index
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.index
+            .iter()
+            .map(|entry| entry.frame_count as u64)
+            .sum()
+    }
+
+    fn read_index(&mut self) -> io::Result<Option<Vec<BlockIndexEntry>>> {
+        let len = self.file.metadata()?.len();
+        if len < FILE_HEADER_SIZE + FOOTER_SIZE {
+            return Ok(None);
+        }
+        let mut footer = [0u8; FOOTER_SIZE as usize];
+        self.file.seek(SeekFrom::Start(len - FOOTER_SIZE))?;
+        self.file.read_exact(&mut footer)?;
+        if &footer[12..] != INDEX_MAGIC {
+            return Ok(None);
+        }
+        let index_offset = u64::from_be_bytes(footer[..8].try_into().unwrap());
+        let block_count = u32::from_be_bytes(footer[8..12].try_into().unwrap()) as usize;
+
+        let mut entries = vec![0u8; block_count * INDEX_ENTRY_SIZE];
+        self.file.seek(SeekFrom::Start(index_offset))?;
+        self.file.read_exact(&mut entries)?;
+        Ok(Some(
+            entries
+                .chunks(INDEX_ENTRY_SIZE)
+                .map(|entry| BlockIndexEntry {
+                    first_timestamp: i64::from_be_bytes(entry[..8].try_into().unwrap()),
+                    last_timestamp: i64::from_be_bytes(entry[8..16].try_into().unwrap()),
+                    offset: u64::from_be_bytes(entry[16..24].try_into().unwrap()),
+                    frame_count: u32::from_be_bytes(entry[24..].try_into().unwrap()),
+                })
+                .collect(),
+        ))
+    }
+
+    fn scan_blocks(&mut self) -> io::Result<Vec<BlockIndexEntry>> {
+        let len = self.file.metadata()?.len();
+        let mut offset = FILE_HEADER_SIZE;
+        let mut index = Vec::new();
+        while offset + BLOCK_HEADER_SIZE as u64 <= len {
+            let mut header = [0u8; BLOCK_HEADER_SIZE];
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.read_exact(&mut header)?;
+            let stored_len = u32::from_be_bytes(header[20..24].try_into().unwrap()) as u64;
+            if offset + BLOCK_HEADER_SIZE as u64 + stored_len > len {
+                println!("Ignoring partial block at offset {}", offset);
+                break;
+            }
+            index.push(BlockIndexEntry {
+                first_timestamp: i64::from_be_bytes(header[..8].try_into().unwrap()),
+                last_timestamp: i64::from_be_bytes(header[8..16].try_into().unwrap()),
+                offset,
+                frame_count: u32::from_be_bytes(header[16..20].try_into().unwrap()),
+            });
+            offset += BLOCK_HEADER_SIZE as u64 + stored_len;
+        }
+        Ok(index)
+    }
+
+    // Decode every record of one block.
+    pub fn read_block(&mut self, block: usize) -> io::Result<Vec<CaptureRecord>> {
+        let entry = *self
+            .index
+            .get(block)
+            .ok_or_else(|| invalid_data("Block index out of range"))?;
+        let mut header = [0u8; BLOCK_HEADER_SIZE];
+        self.file.seek(SeekFrom::Start(entry.offset))?;
+        self.file.read_exact(&mut header)?;
+        let stored_len = u32::from_be_bytes(header[20..24].try_into().unwrap()) as usize;
+        let raw_len = u32::from_be_bytes(header[24..28].try_into().unwrap()) as usize;
+
+        let mut payload = vec![0u8; stored_len];
+        self.file.read_exact(&mut payload)?;
+        if self.compressed {
+            payload = zstd::decode_all(&payload[..])?;
+        }
+        if payload.len() != raw_len {
+            return Err(invalid_data("Block length mismatch"));
+        }
+
+        let mut records = Vec::with_capacity(entry.frame_count as usize);
+        let mut pos = 0;
+        while pos + 10 <= payload.len() {
+            let arrival_us = i64::from_be_bytes(payload[pos..pos + 8].try_into().unwrap());
+            let frame_len = u16::from_be_bytes([payload[pos + 8], payload[pos + 9]]) as usize;
+            pos += 10;
+            if pos + frame_len > payload.len() {
+                return Err(invalid_data("Truncated record in block"));
+            }
+            records.push(CaptureRecord {
+                arrival_us,
+                frame: payload[pos..pos + frame_len].to_vec(),
+            });
+            pos += frame_len;
+        }
+        Ok(records)
+    }
+
+    // Iterate over every record in the capture.
+    pub fn records(&mut self) -> CaptureIter<'_> {
+        CaptureIter {
+            reader: self,
+            next_block: 0,
+            pending: VecDeque::new(),
+            min_timestamp: None,
+        }
+    }
+
+    // Iterate from the first record whose frame timestamp is at or after timestamp_us.
+    // Blocks that end before the timestamp are skipped without being read.
+    pub fn seek(&mut self, timestamp_us: i64) -> CaptureIter<'_> {
+        let next_block = self
+            .index
+            .iter()
+            .position(|entry| entry.last_timestamp >= timestamp_us)
+            .unwrap_or(self.index.len());
+        CaptureIter {
+            reader: self,
+            next_block,
+            pending: VecDeque::new(),
+            min_timestamp: Some(timestamp_us),
+        }
+    }
+}
+
+pub struct CaptureIter<'a> {
+    reader: &'a mut CaptureReader,
+    next_block: usize,
+    pending: VecDeque<CaptureRecord>,
+    min_timestamp: Option<i64>,
+}
+
+impl Iterator for CaptureIter<'_> {
+    type Item = io::Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.pending.pop_front() {
+                if let Some(min) = self.min_timestamp {
+                    if record.timestamp_us() < min {
+                        continue;
+                    }
+                    // Records are only filtered until the seek target is reached.
+                    self.min_timestamp = None;
+                }
+                return Some(Ok(record));
+            }
+            if self.next_block >= self.reader.index.len() {
+                return None;
+            }
+            match self.reader.read_block(self.next_block) {
+                Ok(records) => self.pending.extend(records),
+                Err(e) => {
+                    self.next_block = self.reader.index.len();
+                    return Some(Err(e));
+                }
+            }
+            self.next_block += 1;
+        }
+    }
+}
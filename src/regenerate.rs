@@ -0,0 +1,351 @@
+// Data frames regenerated from record batches, the reverse of
+// arrow_utils::build_record_batch, so archived data can be sent out again as
+// C37.118 frames to test downstream systems (replay::BatchSource).
+//
+// The configuration of the archived stream gives the layout of the frames.
+// Columns are found by the names build_arrow_schema gives them or, for
+// phasor components, by their pmu.channel and pmu.component metadata, so
+// reordered and extra columns (derived channels, quality) are fine. Phasors
+// may be held as raw components or as one complex column, STAT as received
+// or as the decoded flags, the bits without a column left 0. Rows held or
+// interpolated by the accumulator get STAT's data modified bit.
+//
+// Fixed point values are rounded to the nearest count and saturate at the
+// limits of their type. FRACSEC carries no time quality. Long format batches
+// cannot be converted.
+use crate::arrow_utils::{
+    stat_column_name, COMPLEX_COMPONENT, META_CHANNEL, META_COMPONENT, QUALITY_COLUMN,
+    QUALITY_HELD, QUALITY_INTERPOLATED, SOC_ROLLOVER_US, STAT_TIME_QUALITY,
+};
+use crate::frame_parser::parse_data_frames;
+use crate::frames::{
+    calculate_crc, ChannelDataType, ChannelInfo, ConfigurationFrame1and2_2011, DataFrame2011,
+};
+use arrow::array::{Array, ArrayRef, FixedSizeListArray, Float64Array, TimestampMicrosecondArray};
+use arrow::compute::cast;
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use std::fmt;
+
+const STAT_DATA_MODIFIED: u16 = 0x0200;
+
+// Decoded STAT columns, the bits set for them and the value that sets them.
+const STAT_BITS: [(&str, u16, bool); 6] = [
+    ("DATA_VALID", 0x4000, false), // PMU error, no information about the data
+    ("PMU_SYNC", 0x2000, false),
+    ("SORTED_BY_ARRIVAL", 0x1000, true),
+    ("TRIGGER", 0x0800, true),
+    ("CONFIG_CHANGE", 0x0400, true),
+    ("DATA_MODIFIED", STAT_DATA_MODIFIED, true),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegenerateError {
+    NoTimestamp,
+    MissingColumn(String),
+    InvalidColumn(String),
+    InvalidFrame(String),
+}
+
+impl fmt::Display for RegenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegenerateError::NoTimestamp => write!(f, "Batch has no microsecond timestamp column"),
+            RegenerateError::MissingColumn(column) => write!(f, "No column {}", column),
+            RegenerateError::InvalidColumn(column) => {
+                write!(f, "Column {} does not hold numbers", column)
+            }
+            RegenerateError::InvalidFrame(reason) => {
+                write!(f, "Regenerated frame does not parse: {}", reason)
+            }
+        }
+    }
+}
+
+pub struct FrameRegenerator {
+    config: ConfigurationFrame1and2_2011,
+    channels: Vec<(String, ChannelInfo)>, // In frame order
+    frame_size: usize,
+}
+
+impl FrameRegenerator {
+    pub fn new(config: &ConfigurationFrame1and2_2011) -> Self {
+        let mut channels: Vec<(String, ChannelInfo)> =
+            config.get_channel_map().into_iter().collect();
+        channels.sort_by_key(|(_, info)| info.offset);
+        FrameRegenerator {
+            config: config.clone(),
+            channels,
+            frame_size: config.calc_data_frame_size(),
+        }
+    }
+
+    pub fn config(&self) -> &ConfigurationFrame1and2_2011 {
+        &self.config
+    }
+
+    // One data frame per row, with FRAMESIZE and CHK.
+    pub fn frames(&self, batch: &RecordBatch) -> Result<Vec<Vec<u8>>, RegenerateError> {
+        let timestamps = batch
+            .column_by_name("timestamp")
+            .and_then(|column| column.as_any().downcast_ref::<TimestampMicrosecondArray>())
+            .ok_or(RegenerateError::NoTimestamp)?;
+        let mut frames: Vec<Vec<u8>> = timestamps
+            .values()
+            .iter()
+            .map(|timestamp| self.empty_frame(*timestamp))
+            .collect();
+
+        for (name, info) in &self.channels {
+            self.write_channel(batch, name, info, &mut frames)?;
+        }
+
+        let quality = batch
+            .column_by_name(QUALITY_COLUMN)
+            .map(|column| numbers(QUALITY_COLUMN, column))
+            .transpose()?;
+        for (row, frame) in frames.iter_mut().enumerate() {
+            let modified = quality.as_ref().is_some_and(|quality| {
+                quality.value(row) as u8 & (QUALITY_HELD | QUALITY_INTERPOLATED) != 0
+            });
+            if modified {
+                for (_, info) in &self.channels {
+                    if let ChannelDataType::Stat = info.data_type {
+                        let stat = read_u16(frame, info.offset) | STAT_DATA_MODIFIED;
+                        frame[info.offset..info.offset + 2].copy_from_slice(&stat.to_be_bytes());
+                    }
+                }
+            }
+            let end = frame.len() - 2;
+            let crc = calculate_crc(&frame[..end]);
+            frame[end..].copy_from_slice(&crc.to_be_bytes());
+        }
+        Ok(frames)
+    }
+
+    // The frames parsed, as received from a PMU.
+    pub fn data_frames(&self, batch: &RecordBatch) -> Result<Vec<DataFrame2011>, RegenerateError> {
+        self.frames(batch)?
+            .iter()
+            .map(|frame| {
+                parse_data_frames(frame, &self.config)
+                    .map_err(|e| RegenerateError::InvalidFrame(format!("{:?}", e)))
+            })
+            .collect()
+    }
+
+    // Prefix of a data frame at a timestamp, the rest zero.
+    fn empty_frame(&self, timestamp_us: i64) -> Vec<u8> {
+        let time_base = self.config.time_base.max(1) as i64;
+        let timestamp_us = timestamp_us.rem_euclid(SOC_ROLLOVER_US);
+        let mut soc = timestamp_us / 1_000_000;
+        let mut fracsec = ((timestamp_us % 1_000_000) * time_base + 500_000) / 1_000_000;
+        if fracsec >= time_base {
+            soc += 1;
+            fracsec -= time_base;
+        }
+        // Data frame of the configuration's version
+        let sync = 0xAA00 | (self.config.prefix.sync & 0x000F);
+        let mut frame = vec![0u8; self.frame_size];
+        frame[0..2].copy_from_slice(&sync.to_be_bytes());
+        frame[2..4].copy_from_slice(&(self.frame_size as u16).to_be_bytes());
+        frame[4..6].copy_from_slice(&self.config.prefix.idcode.to_be_bytes());
+        frame[6..10].copy_from_slice(&(soc as u32).to_be_bytes());
+        frame[10..14].copy_from_slice(&(fracsec as u32).to_be_bytes());
+        frame
+    }
+
+    fn write_channel(
+        &self,
+        batch: &RecordBatch,
+        name: &str,
+        info: &ChannelInfo,
+        frames: &mut [Vec<u8>],
+    ) -> Result<(), RegenerateError> {
+        let offset = info.offset;
+        match info.data_type {
+            ChannelDataType::PhasorFloat | ChannelDataType::PhasorFixed => {
+                let (first, second) = phasor_values(batch, name, info)?;
+                let float = matches!(info.data_type, ChannelDataType::PhasorFloat);
+                for (row, frame) in frames.iter_mut().enumerate() {
+                    let (x, y) = (first[row], second[row]);
+                    if float {
+                        frame[offset..offset + 4].copy_from_slice(&(x as f32).to_be_bytes());
+                        frame[offset + 4..offset + 8].copy_from_slice(&(y as f32).to_be_bytes());
+                    } else {
+                        // Polar magnitudes are unsigned
+                        let x = if info.polar {
+                            (x.round() as u16).to_be_bytes()
+                        } else {
+                            (x.round() as i16).to_be_bytes()
+                        };
+                        frame[offset..offset + 2].copy_from_slice(&x);
+                        frame[offset + 2..offset + 4]
+                            .copy_from_slice(&(y.round() as i16).to_be_bytes());
+                    }
+                }
+            }
+            ChannelDataType::AnalogFloat
+            | ChannelDataType::FreqFloat
+            | ChannelDataType::DfreqFloat => {
+                let values = scalar_values(batch, name)?;
+                for (row, frame) in frames.iter_mut().enumerate() {
+                    let value = values.value(row) as f32;
+                    frame[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+                }
+            }
+            ChannelDataType::AnalogFixed
+            | ChannelDataType::FreqFixed
+            | ChannelDataType::DfreqFixed => {
+                let values = scalar_values(batch, name)?;
+                for (row, frame) in frames.iter_mut().enumerate() {
+                    let value = values.value(row).round() as i16;
+                    frame[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+                }
+            }
+            ChannelDataType::Digital => {
+                let values = scalar_values(batch, name)?;
+                for (row, frame) in frames.iter_mut().enumerate() {
+                    let value = values.value(row).round() as u16;
+                    frame[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+                }
+            }
+            ChannelDataType::Stat => {
+                let values = stat_values(batch, name)?;
+                for (row, frame) in frames.iter_mut().enumerate() {
+                    frame[offset..offset + 2].copy_from_slice(&values[row].to_be_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_u16(frame: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([frame[offset], frame[offset + 1]])
+}
+
+fn numbers(name: &str, column: &ArrayRef) -> Result<Float64Array, RegenerateError> {
+    cast(column, &DataType::Float64)
+        .ok()
+        .and_then(|values| values.as_any().downcast_ref::<Float64Array>().cloned())
+        .ok_or_else(|| RegenerateError::InvalidColumn(name.to_string()))
+}
+
+fn scalar_values(batch: &RecordBatch, name: &str) -> Result<Float64Array, RegenerateError> {
+    let column = batch
+        .column_by_name(name)
+        .ok_or_else(|| RegenerateError::MissingColumn(name.to_string()))?;
+    numbers(name, column)
+}
+
+// Column holding a component of a phasor, by metadata or by its default name.
+fn component_column<'a>(
+    batch: &'a RecordBatch,
+    channel: &str,
+    component: &str,
+    name: &str,
+) -> Option<&'a ArrayRef> {
+    let schema = batch.schema_ref();
+    schema
+        .fields()
+        .iter()
+        .zip(batch.columns())
+        .find(|(field, _)| {
+            let meta = field.metadata();
+            let tagged = meta.get(META_CHANNEL).map(String::as_str) == Some(channel)
+                && meta.get(META_COMPONENT).map(String::as_str) == Some(component);
+            tagged || field.name() == name
+        })
+        .map(|(_, column)| column)
+}
+
+// Raw values of a phasor's two fields, from its components or its complex
+// column in engineering units.
+fn phasor_values(
+    batch: &RecordBatch,
+    name: &str,
+    info: &ChannelInfo,
+) -> Result<(Vec<f64>, Vec<f64>), RegenerateError> {
+    let (first, second) = if info.polar {
+        ("magnitude", "angle")
+    } else {
+        ("real", "imaginary")
+    };
+    let (first_name, second_name) = match info.data_type {
+        ChannelDataType::PhasorFloat => (format!("{}_magnitude", name), format!("{}_angle", name)),
+        _ => (format!("{}_X", name), format!("{}_Y", name)),
+    };
+    let components = (
+        component_column(batch, name, first, &first_name),
+        component_column(batch, name, second, &second_name),
+    );
+    if let (Some(x), Some(y)) = components {
+        let x = numbers(&first_name, x)?;
+        let y = numbers(&second_name, y)?;
+        return Ok((x.values().to_vec(), y.values().to_vec()));
+    }
+
+    let column = component_column(batch, name, COMPLEX_COMPONENT, name)
+        .ok_or_else(|| RegenerateError::MissingColumn(first_name.clone()))?;
+    let pairs = column
+        .as_any()
+        .downcast_ref::<FixedSizeListArray>()
+        .filter(|list| list.value_length() == 2)
+        .and_then(|list| list.values().as_any().downcast_ref::<Float64Array>())
+        .ok_or_else(|| RegenerateError::InvalidColumn(name.to_string()))?;
+    let float = matches!(info.data_type, ChannelDataType::PhasorFloat);
+    let scale = if float || info.scale == 0.0 {
+        1.0
+    } else {
+        info.scale
+    };
+    // Fixed point angles in 10^-4 rad
+    let angle_scale = if float { 1.0 } else { 1e4 };
+    let mut xs = Vec::with_capacity(pairs.len() / 2);
+    let mut ys = Vec::with_capacity(pairs.len() / 2);
+    for pair in pairs.values().chunks_exact(2) {
+        let (real, imaginary) = (pair[0], pair[1]);
+        if info.polar {
+            xs.push(real.hypot(imaginary) / scale);
+            ys.push(imaginary.atan2(real) * angle_scale);
+        } else {
+            xs.push(real / scale);
+            ys.push(imaginary / scale);
+        }
+    }
+    Ok((xs, ys))
+}
+
+// STAT as received or put together from the decoded flags.
+fn stat_values(batch: &RecordBatch, name: &str) -> Result<Vec<u16>, RegenerateError> {
+    if let Some(column) = batch.column_by_name(name) {
+        let values = numbers(name, column)?;
+        return Ok(values.values().iter().map(|v| v.round() as u16).collect());
+    }
+    let mut stat = vec![0u16; batch.num_rows()];
+    let mut decoded = false;
+    for (flag, bit, when) in STAT_BITS {
+        let column_name = stat_column_name(name, flag);
+        let Some(column) = batch.column_by_name(&column_name) else {
+            continue;
+        };
+        decoded = true;
+        for (row, value) in numbers(&column_name, column)?.values().iter().enumerate() {
+            if (*value != 0.0) == when {
+                stat[row] |= bit;
+            }
+        }
+    }
+    let column_name = stat_column_name(name, STAT_TIME_QUALITY);
+    if let Some(column) = batch.column_by_name(&column_name) {
+        decoded = true;
+        for (row, value) in numbers(&column_name, column)?.values().iter().enumerate() {
+            stat[row] |= ((*value as u16) & 0x07) << 6;
+        }
+    }
+    if !decoded {
+        return Err(RegenerateError::MissingColumn(name.to_string()));
+    }
+    Ok(stat)
+}
@@ -0,0 +1,200 @@
+// Renaming of stations and channels and remapping of idcodes at ingestion.
+//
+// Devices in the field are often configured with wrong or duplicate names and
+// idcodes, which then end up in column names and file names downstream. A
+// remapping table, a CSV file with one rule per line, corrects them as the
+// frames come in:
+//
+//   kind,stream,from,to
+//   stream,,7734,1001          # IDCODE of a stream (data and configuration frames)
+//   pmu,7734,7734,2001         # IDCODE of a PMU in a configuration frame
+//   station,7734,Station A,SUB_NORTH
+//   channel,,VA,VA_BUS1
+//
+// The stream column limits a rule to the stream with that (original) IDCODE,
+// empty applies it to every stream; rules of the stream win over the general
+// ones. Names are matched without their padding and must fit in 16 bytes.
+// Lines starting with # and a kind,... header are skipped.
+use crate::frame_parser::parse_config_frame_1and2;
+use crate::frames::{ConfigurationFrame1and2_2011, CrcMode};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hash;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Remap {
+    streams: HashMap<u16, u16>,
+    pmus: HashMap<(Option<u16>, u16), u16>,
+    stations: HashMap<(Option<u16>, String), String>,
+    channels: HashMap<(Option<u16>, String), String>,
+}
+
+impl Remap {
+    pub fn from_csv(text: &str) -> Result<Self, String> {
+        let mut remap = Remap::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() || line.starts_with("kind,") {
+                continue;
+            }
+            remap
+                .add_rule(line)
+                .map_err(|e| format!("Line {}: {}", number + 1, e))?;
+        }
+        Ok(remap)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_csv(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn add_rule(&mut self, line: &str) -> Result<(), String> {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [kind, stream, from, to] = fields[..] else {
+            return Err(format!("Expected kind,stream,from,to, got {:?}", line));
+        };
+        let stream = match stream {
+            "" => None,
+            stream => Some(parse_idcode(stream)?),
+        };
+        match kind {
+            "stream" => {
+                self.streams.insert(parse_idcode(from)?, parse_idcode(to)?);
+            }
+            "pmu" => {
+                self.pmus
+                    .insert((stream, parse_idcode(from)?), parse_idcode(to)?);
+            }
+            "station" => {
+                self.stations
+                    .insert((stream, from.to_string()), parse_name(to)?);
+            }
+            "channel" => {
+                self.channels
+                    .insert((stream, from.to_string()), parse_name(to)?);
+            }
+            kind => return Err(format!("Unknown kind {:?}", kind)),
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+            && self.pmus.is_empty()
+            && self.stations.is_empty()
+            && self.channels.is_empty()
+    }
+
+    // IDCODE a stream is known by after remapping.
+    pub fn stream_idcode(&self, idcode: u16) -> u16 {
+        self.streams.get(&idcode).copied().unwrap_or(idcode)
+    }
+
+    // Apply the rules to a configuration. Returns true if anything changed.
+    pub fn apply_config(&self, config: &mut ConfigurationFrame1and2_2011) -> bool {
+        let stream = config.prefix.idcode;
+        let mut changed = false;
+        for pmu in &mut config.pmu_configs {
+            if let Some(idcode) = self.rule(&self.pmus, stream, pmu.idcode) {
+                changed |= pmu.idcode != *idcode;
+                pmu.idcode = *idcode;
+            }
+            let station = String::from_utf8_lossy(&pmu.stn).trim().to_string();
+            if let Some(name) = self.rule(&self.stations, stream, station) {
+                let padded = pad(name);
+                changed |= pmu.stn != padded;
+                pmu.stn = padded;
+            }
+            for chunk in pmu.chnam.chunks_mut(16) {
+                let channel = String::from_utf8_lossy(chunk).trim().to_string();
+                if let Some(name) = self.rule(&self.channels, stream, channel) {
+                    let padded = pad(name);
+                    changed |= chunk != padded;
+                    chunk.copy_from_slice(&padded);
+                }
+            }
+        }
+        let idcode = self.stream_idcode(stream);
+        changed |= idcode != stream;
+        config.prefix.idcode = idcode;
+        changed
+    }
+
+    // The rule of the stream, else the one of every stream.
+    fn rule<'a, K: Clone + Eq + Hash, V>(
+        &self,
+        rules: &'a HashMap<(Option<u16>, K), V>,
+        stream: u16,
+        key: K,
+    ) -> Option<&'a V> {
+        rules
+            .get(&(Some(stream), key.clone()))
+            .or_else(|| rules.get(&(None, key)))
+    }
+
+    // A frame as it looks after remapping, borrowed when no rule applies.
+    // Configuration frames are rebuilt, data frames get their IDCODE rewritten
+    // with set_idcode. Other frames pass unchanged.
+    pub fn apply<'a>(&self, frame: &'a [u8]) -> Result<Cow<'a, [u8]>, String> {
+        if self.is_empty() || frame.len() < 16 {
+            return Ok(Cow::Borrowed(frame));
+        }
+        match (frame[1] >> 4) & 0x07 {
+            2 | 3 => {
+                let mut config = parse_config_frame_1and2(frame)
+                    .map_err(|e| format!("Invalid configuration frame: {:?}", e))?;
+                if !self.apply_config(&mut config) {
+                    return Ok(Cow::Borrowed(frame));
+                }
+                Ok(Cow::Owned(config.to_hex()))
+            }
+            0 => {
+                let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+                let remapped = self.stream_idcode(idcode);
+                if remapped == idcode {
+                    return Ok(Cow::Borrowed(frame));
+                }
+                let mut frame = frame.to_vec();
+                set_idcode(&mut frame, remapped);
+                Ok(Cow::Owned(frame))
+            }
+            _ => Ok(Cow::Borrowed(frame)),
+        }
+    }
+}
+
+// Rewrite the IDCODE of a frame. A valid CHK is recalculated the way it was
+// computed (see CrcMode), a bad one stays bad.
+pub fn set_idcode(frame: &mut [u8], idcode: u16) {
+    if frame.len() < 8 {
+        return;
+    }
+    let len = frame.len();
+    let mode = CrcMode::detect(frame);
+    frame[4..6].copy_from_slice(&idcode.to_be_bytes());
+    if let Some(chk) = mode.and_then(|mode| mode.calculate(frame)) {
+        frame[len - 2..].copy_from_slice(&chk.to_be_bytes());
+    }
+}
+
+fn parse_idcode(text: &str) -> Result<u16, String> {
+    text.parse()
+        .map_err(|_| format!("Invalid IDCODE {:?}", text))
+}
+
+fn parse_name(text: &str) -> Result<String, String> {
+    if text.is_empty() || text.len() > 16 || !text.is_ascii() {
+        return Err(format!("Name {:?} is not 1 to 16 ASCII characters", text));
+    }
+    Ok(text.to_string())
+}
+
+fn pad(name: &str) -> [u8; 16] {
+    let mut padded = [b' '; 16];
+    padded[..name.len()].copy_from_slice(name.as_bytes());
+    padded
+}
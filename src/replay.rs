@@ -0,0 +1,379 @@
+// Paced playback of recorded or simulated frames.
+//
+// A Player pulls frames from a FrameSource (a capture file or the simulator)
+// and sends them on a channel with the original spacing, scaled by a speed
+// between x0.1 and x100. While running it accepts PlaybackCommands to change
+// speed, pause/resume, seek to a timestamp and toggle looping.
+//
+// Captures are paced by the arrival time of each frame, so the replay has the
+// timing the recorder saw. Seeking uses the timestamp carried in the frames.
+//
+// A BatchSource plays archived record batches, e.g. parquet files written by
+// the pipeline, as the data frames regenerated from them, paced by their
+// timestamps.
+use crate::arrow_utils::frame_timestamp_micros_with;
+use crate::frames::ConfigurationFrame1and2_2011;
+use crate::recorder::{CaptureReader, CaptureRecord};
+use crate::regenerate::FrameRegenerator;
+use crate::simulator::Simulator;
+use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use arrow::record_batch::RecordBatch;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
+
+pub const MIN_SPEED: f64 = 0.1;
+pub const MAX_SPEED: f64 = 100.0;
+
+// Gaps longer than this in a capture (e.g. between recording sessions) are shortened.
+const MAX_GAP: Duration = Duration::from_secs(5);
+
+pub fn clamp_speed(speed: f64) -> f64 {
+    if speed.is_nan() {
+        return 1.0;
+    }
+    speed.clamp(MIN_SPEED, MAX_SPEED)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackCommand {
+    SetSpeed(f64),
+    Pause,
+    Resume,
+    Seek(i64), // Timestamp in microseconds
+    SetLooping(bool),
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaybackOptions {
+    pub speed: f64,
+    pub looping: bool, // Start over at the end of the source
+}
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        PlaybackOptions {
+            speed: 1.0,
+            looping: false,
+        }
+    }
+}
+
+impl PlaybackOptions {
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        self.speed = clamp_speed(speed);
+        self
+    }
+
+    pub fn with_looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+}
+
+pub trait FrameSource {
+    // Frames due next and the source time since the previous ones, None at the end.
+    fn next_frames(&mut self) -> io::Result<Option<(Duration, Vec<Vec<u8>>)>>;
+    // Continue from the first frame at or after timestamp_us.
+    fn seek(&mut self, timestamp_us: i64) -> io::Result<()>;
+    // Continue from the first frame.
+    fn rewind(&mut self) -> io::Result<()>;
+}
+
+// Frames of a capture file written by the recorder.
+pub struct CaptureSource {
+    reader: CaptureReader,
+    next_block: usize,
+    pending: VecDeque<CaptureRecord>,
+    last_arrival_us: Option<i64>,
+}
+
+impl CaptureSource {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(CaptureSource {
+            reader: CaptureReader::open(path)?,
+            next_block: 0,
+            pending: VecDeque::new(),
+            last_arrival_us: None,
+        })
+    }
+
+    fn next_record(&mut self) -> io::Result<Option<CaptureRecord>> {
+        while self.pending.is_empty() {
+            if self.next_block >= self.reader.index().len() {
+                return Ok(None);
+            }
+            self.pending = self.reader.read_block(self.next_block)?.into();
+            self.next_block += 1;
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+impl FrameSource for CaptureSource {
+    fn next_frames(&mut self) -> io::Result<Option<(Duration, Vec<Vec<u8>>)>> {
+        let Some(record) = self.next_record()? else {
+            return Ok(None);
+        };
+        let gap_us = self
+            .last_arrival_us
+            .map_or(0, |last| (record.arrival_us - last).max(0));
+        self.last_arrival_us = Some(record.arrival_us);
+        let delay = Duration::from_micros(gap_us as u64).min(MAX_GAP);
+        Ok(Some((delay, vec![record.frame])))
+    }
+
+    fn seek(&mut self, timestamp_us: i64) -> io::Result<()> {
+        self.next_block = self
+            .reader
+            .index()
+            .iter()
+            .position(|entry| entry.last_timestamp >= timestamp_us)
+            .unwrap_or(self.reader.index().len());
+        self.pending.clear();
+        self.last_arrival_us = None;
+        // Drop the records of the first block that come before the target
+        while let Some(record) = self.next_record()? {
+            if record.timestamp_us() >= timestamp_us {
+                self.pending.push_front(record);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> io::Result<()> {
+        self.next_block = 0;
+        self.pending.clear();
+        self.last_arrival_us = None;
+        Ok(())
+    }
+}
+
+// Data frames regenerated from record batches of a stream.
+pub struct BatchSource {
+    config: ConfigurationFrame1and2_2011,
+    frames: Vec<(i64, Vec<u8>)>, // By timestamp
+    next: usize,
+}
+
+impl BatchSource {
+    pub fn new(config: &ConfigurationFrame1and2_2011, batches: &[RecordBatch]) -> io::Result<Self> {
+        let regenerator = FrameRegenerator::new(config);
+        let mut frames = Vec::new();
+        for batch in batches {
+            let regenerated = regenerator
+                .frames(batch)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            frames.extend(regenerated.into_iter().map(|frame| {
+                (
+                    frame_timestamp_micros_with(&frame, config.time_base).unwrap_or_default(),
+                    frame,
+                )
+            }));
+        }
+        frames.sort_by_key(|(timestamp, _)| *timestamp);
+        Ok(BatchSource {
+            config: config.clone(),
+            frames,
+            next: 0,
+        })
+    }
+
+    // The batches of parquet files, in the order given.
+    pub fn open_parquet(
+        config: &ConfigurationFrame1and2_2011,
+        paths: &[impl AsRef<Path>],
+    ) -> io::Result<Self> {
+        let mut batches = Vec::new();
+        for path in paths {
+            let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+                .and_then(|builder| builder.build())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            for batch in reader {
+                batches.push(batch.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?);
+            }
+        }
+        Self::new(config, &batches)
+    }
+
+    // Configuration frame to serve with the frames.
+    pub fn config(&self) -> &ConfigurationFrame1and2_2011 {
+        &self.config
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+impl FrameSource for BatchSource {
+    fn next_frames(&mut self) -> io::Result<Option<(Duration, Vec<Vec<u8>>)>> {
+        let Some((timestamp, frame)) = self.frames.get(self.next) else {
+            return Ok(None);
+        };
+        let gap_us = match self.next {
+            0 => 0,
+            next => (timestamp - self.frames[next - 1].0).max(0),
+        };
+        self.next += 1;
+        let delay = Duration::from_micros(gap_us as u64).min(MAX_GAP);
+        Ok(Some((delay, vec![frame.clone()])))
+    }
+
+    fn seek(&mut self, timestamp_us: i64) -> io::Result<()> {
+        self.next = self
+            .frames
+            .partition_point(|(timestamp, _)| *timestamp < timestamp_us);
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> io::Result<()> {
+        self.next = 0;
+        Ok(())
+    }
+}
+
+impl FrameSource for Simulator {
+    fn next_frames(&mut self) -> io::Result<Option<(Duration, Vec<Vec<u8>>)>> {
+        if self.is_finished() {
+            return Ok(None);
+        }
+        let period = self.period();
+        Ok(Some((period, self.next_tick().frames)))
+    }
+
+    fn seek(&mut self, timestamp_us: i64) -> io::Result<()> {
+        Simulator::seek(self, timestamp_us);
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> io::Result<()> {
+        Simulator::rewind(self);
+        Ok(())
+    }
+}
+
+pub struct Player<S: FrameSource> {
+    source: S,
+    options: PlaybackOptions,
+    paused: bool,
+    control_tx: mpsc::Sender<PlaybackCommand>,
+    control_rx: mpsc::Receiver<PlaybackCommand>,
+}
+
+impl<S: FrameSource> Player<S> {
+    pub fn new(source: S, options: PlaybackOptions) -> Self {
+        let (control_tx, control_rx) = mpsc::channel(32);
+        Player {
+            source,
+            options: options.with_speed(options.speed),
+            paused: false,
+            control_tx,
+            control_rx,
+        }
+    }
+
+    pub fn control_sender(&self) -> mpsc::Sender<PlaybackCommand> {
+        self.control_tx.clone()
+    }
+
+    pub fn options(&self) -> PlaybackOptions {
+        self.options
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn into_source(self) -> S {
+        self.source
+    }
+
+    // Returns true on Stop.
+    fn apply(&mut self, command: PlaybackCommand) -> io::Result<bool> {
+        match command {
+            PlaybackCommand::SetSpeed(speed) => self.options.speed = clamp_speed(speed),
+            PlaybackCommand::Pause => self.paused = true,
+            PlaybackCommand::Resume => self.paused = false,
+            PlaybackCommand::Seek(timestamp_us) => self.source.seek(timestamp_us)?,
+            PlaybackCommand::SetLooping(looping) => self.options.looping = looping,
+            PlaybackCommand::Stop => return Ok(true),
+        }
+        Ok(false)
+    }
+
+    // Play until the source ends (and is not looping), Stop is received or the
+    // output channel closes. Returns the number of frames sent.
+    pub async fn run(&mut self, output: mpsc::Sender<Vec<u8>>) -> io::Result<u64> {
+        let mut sent = 0;
+        let mut due = Instant::now();
+        let mut pending: Option<Vec<Vec<u8>>> = None;
+        let mut empty_rewinds = 0;
+
+        loop {
+            if self.paused {
+                // Always Some, the player holds a sender.
+                let Some(command) = self.control_rx.recv().await else {
+                    break;
+                };
+                if let PlaybackCommand::Seek(_) = command {
+                    pending = None;
+                }
+                if self.apply(command)? {
+                    break;
+                }
+                due = Instant::now();
+                continue;
+            }
+
+            if pending.is_none() {
+                match self.source.next_frames()? {
+                    Some((delay, frames)) => {
+                        empty_rewinds = 0;
+                        due =
+                            (due + delay.div_f64(self.options.speed)).max(Instant::now() - MAX_GAP);
+                        pending = Some(frames);
+                    }
+                    None if self.options.looping && empty_rewinds == 0 => {
+                        println!("Playback reached the end, looping");
+                        self.source.rewind()?;
+                        empty_rewinds += 1;
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+
+            tokio::select! {
+                _ = time::sleep_until(due) => {
+                    for frame in pending.take().unwrap_or_default() {
+                        if output.send(frame).await.is_err() {
+                            return Ok(sent);
+                        }
+                        sent += 1;
+                    }
+                }
+                Some(command) = self.control_rx.recv() => {
+                    if let PlaybackCommand::Seek(_) = command {
+                        pending = None;
+                        due = Instant::now();
+                    }
+                    if self.apply(command)? {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(sent)
+    }
+}
@@ -0,0 +1,798 @@
+// Scheduled reports over historian data, for unattended operation.
+//
+// A ReportConfig (JSON) lists jobs, each a kind of report, a schedule and
+// where the report goes. Jobs run at fixed times of day, hourly at a minute
+// past the hour or daily at a local time, and cover the period since the
+// previous run (the last hour or the last day):
+//
+// - quality: anomaly counts per channel (analytics::anomaly) of the
+//   frequency, ROCOF, analog and phasor magnitude channels in the historian,
+//   and the angle jitter of the phasor channels (analytics::jitter), and the
+//   time skew between the clocks of every pair of streams, from their
+//   frequency (analytics::time_skew)
+// - events: digest of the events published on the bus, counts by kind and
+//   severity and the events themselves
+// - compliance: reporting per stream, frames received against the configured
+//   rate, the longest gap, timestamps off the rate grid and frames with STAT
+//   errors
+//
+// With an annotation store, anomalies and events in annotated periods are
+// counted as suppressed and maintenance windows of a stream are left out of
+// the frames expected from it. With an AreaMap, channels and streams carry
+// the areas of their stations and the reports add totals per area.
+//
+// Reports are written as JSON and text to a directory, named after the job
+// and the end of the period, and/or POSTed as JSON to an http:// webhook.
+use crate::analytics::anomaly::{AnomalyConfig, AnomalyDetector};
+use crate::analytics::jitter::phasor_angles;
+use crate::analytics::time_skew::{estimate_skew, TimeSkewConfig};
+use crate::annotations::AnnotationStore;
+use crate::areas::AreaMap;
+use crate::arrow_utils::{
+    channel_values, META_COMPONENT, META_KIND, META_OFFSET, META_SCALE, META_STATION,
+};
+use crate::events::{Event, EventBus};
+use crate::historian::Historian;
+use arrow::array::{Array, Float64Array, TimestampMicrosecondArray, UInt16Array};
+use arrow::compute::cast;
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use chrono::{DateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, watch};
+
+const MICROS_PER_MINUTE: i64 = 60_000_000;
+const MICROS_PER_HOUR: i64 = 60 * MICROS_PER_MINUTE;
+const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+
+// Events listed in a digest, the counts cover all of them.
+const DIGEST_EVENTS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportKind {
+    Quality,
+    Events,
+    Compliance,
+}
+
+impl ReportKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReportKind::Quality => "quality",
+            ReportKind::Events => "events",
+            ReportKind::Compliance => "compliance",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "every", rename_all = "snake_case")]
+pub enum Schedule {
+    Hourly {
+        #[serde(default)]
+        minute: u32,
+    },
+    Daily {
+        hour: u32,
+        #[serde(default)]
+        minute: u32,
+    },
+}
+
+impl Schedule {
+    // Length of the period a run covers.
+    pub fn period_us(&self) -> i64 {
+        match self {
+            Schedule::Hourly { .. } => MICROS_PER_HOUR,
+            Schedule::Daily { .. } => MICROS_PER_DAY,
+        }
+    }
+
+    // First run strictly after a time, in local time of the timezone (UTC
+    // when not set).
+    pub fn next_after(&self, after_us: i64, timezone: Option<Tz>) -> i64 {
+        let offset = |utc_us: i64| utc_offset_us(utc_us, timezone);
+        let local = after_us + offset(after_us);
+        let (start, at) = match *self {
+            Schedule::Hourly { minute } => (
+                local.div_euclid(MICROS_PER_HOUR) * MICROS_PER_HOUR,
+                minute as i64 % 60 * MICROS_PER_MINUTE,
+            ),
+            Schedule::Daily { hour, minute } => (
+                local.div_euclid(MICROS_PER_DAY) * MICROS_PER_DAY,
+                (hour as i64 % 24 * 60 + minute as i64 % 60) * MICROS_PER_MINUTE,
+            ),
+        };
+        let mut next = start + at;
+        loop {
+            // Back to UTC with the offset in force at that time
+            let utc = next - offset(next - offset(next));
+            if utc > after_us {
+                return utc;
+            }
+            next += self.period_us();
+        }
+    }
+}
+
+fn utc_offset_us(utc_us: i64, timezone: Option<Tz>) -> i64 {
+    let Some(tz) = timezone else {
+        return 0;
+    };
+    let utc = DateTime::from_timestamp_micros(utc_us)
+        .unwrap_or_default()
+        .naive_utc();
+    tz.offset_from_utc_datetime(&utc).fix().local_minus_utc() as i64 * 1_000_000
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportOutput {
+    Dir(PathBuf),
+    Webhook(String), // http://host[:port]/path
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportJob {
+    pub name: String,
+    pub kind: ReportKind,
+    pub schedule: Schedule,
+    pub outputs: Vec<ReportOutput>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportConfig {
+    pub jobs: Vec<ReportJob>,
+    // IANA name, e.g. Europe/Berlin, for the times of day. UTC when not set.
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
+
+impl ReportConfig {
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        serde_json::from_str(json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub job: String,
+    pub kind: ReportKind,
+    pub start_us: i64, // Period covered, start inclusive
+    pub end_us: i64,   // End exclusive
+    pub content: Value,
+}
+
+impl Report {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "job": self.job,
+            "kind": self.kind.name(),
+            "start_us": self.start_us,
+            "end_us": self.end_us,
+            "content": self.content,
+        })
+    }
+
+    // Header with the period, then the content one line per entry.
+    pub fn render(&self) -> String {
+        let mut out = format!(
+            "{} report {} ({} to {})\n",
+            self.kind.name(),
+            self.job,
+            format_time(self.start_us),
+            format_time(self.end_us)
+        );
+        if let Value::Object(sections) = &self.content {
+            for (section, value) in sections {
+                match value {
+                    Value::Array(entries) => {
+                        out.push_str(&format!("{}:\n", section));
+                        for entry in entries {
+                            out.push_str(&format!("  {}\n", entry));
+                        }
+                    }
+                    value => out.push_str(&format!("{}: {}\n", section, value)),
+                }
+            }
+        }
+        out
+    }
+
+    // File name without extension, e.g. hourly-quality-20240101T1300Z.
+    pub fn file_stem(&self) -> String {
+        let end = DateTime::from_timestamp_micros(self.end_us).unwrap_or_default();
+        format!("{}-{}", self.job, end.format("%Y%m%dT%H%MZ"))
+    }
+}
+
+pub(crate) fn format_time(timestamp_us: i64) -> String {
+    DateTime::from_timestamp_micros(timestamp_us)
+        .unwrap_or_default()
+        .format("%Y-%m-%d %H:%M:%S UTC")
+        .to_string()
+}
+
+struct ScheduledJob {
+    job: ReportJob,
+    next_us: i64,
+}
+
+pub struct ReportScheduler {
+    historian: Arc<Mutex<Historian>>,
+    jobs: Vec<ScheduledJob>,
+    timezone: Option<Tz>,
+    events: Option<broadcast::Receiver<Event>>,
+    annotations: Option<Arc<Mutex<AnnotationStore>>>,
+    areas: Option<Arc<AreaMap>>,
+    recent: VecDeque<Event>, // Events of the longest period, oldest first
+    failures: u64,
+}
+
+impl ReportScheduler {
+    // Jobs are first run at their next time after now_us.
+    pub fn new(
+        config: ReportConfig,
+        historian: Arc<Mutex<Historian>>,
+        now_us: i64,
+    ) -> io::Result<Self> {
+        let timezone = match &config.timezone {
+            Some(name) => Some(name.parse::<Tz>().map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", name, e))
+            })?),
+            None => None,
+        };
+        let jobs = config
+            .jobs
+            .into_iter()
+            .map(|job| ScheduledJob {
+                next_us: job.schedule.next_after(now_us, timezone),
+                job,
+            })
+            .collect();
+        Ok(ReportScheduler {
+            historian,
+            jobs,
+            timezone,
+            events: None,
+            annotations: None,
+            areas: None,
+            recent: VecDeque::new(),
+            failures: 0,
+        })
+    }
+
+    // Collect the events published from now on for the event digests.
+    pub fn with_events(mut self, bus: &EventBus) -> Self {
+        self.events = Some(bus.subscribe());
+        self
+    }
+
+    pub fn with_annotations(mut self, annotations: Arc<Mutex<AnnotationStore>>) -> Self {
+        self.annotations = Some(annotations);
+        self
+    }
+
+    pub fn with_areas(mut self, areas: Arc<AreaMap>) -> Self {
+        self.areas = Some(areas);
+        self
+    }
+
+    // Time of the next job run, None without jobs.
+    pub fn next_run(&self) -> Option<i64> {
+        self.jobs.iter().map(|job| job.next_us).min()
+    }
+
+    // Reports that could not be delivered to one of their outputs.
+    pub fn failures(&self) -> u64 {
+        self.failures
+    }
+
+    // Run and deliver the jobs due at now_us. A job that missed several runs
+    // runs once, for the last period.
+    pub async fn run_due(&mut self, now_us: i64) -> Vec<Report> {
+        self.collect_events();
+        let mut reports = Vec::new();
+        for index in 0..self.jobs.len() {
+            let scheduled = &self.jobs[index];
+            if scheduled.next_us > now_us {
+                continue;
+            }
+            let schedule = scheduled.job.schedule;
+            let mut end_us = scheduled.next_us;
+            while schedule.next_after(end_us, self.timezone) <= now_us {
+                end_us = schedule.next_after(end_us, self.timezone);
+            }
+            self.jobs[index].next_us = schedule.next_after(end_us, self.timezone);
+
+            let job = self.jobs[index].job.clone();
+            let report = self.build(&job, end_us - schedule.period_us(), end_us);
+            for output in &job.outputs {
+                if let Err(e) = deliver(&report, output).await {
+                    println!("Failed to deliver report {}: {}", report.file_stem(), e);
+                    self.failures += 1;
+                }
+            }
+            reports.push(report);
+        }
+        reports
+    }
+
+    // Run jobs as they fall due until stopped.
+    pub async fn run(mut self, mut stop: watch::Receiver<bool>) {
+        while let Some(next_us) = self.next_run() {
+            let wait_us = (next_us - now_us()).max(0) as u64;
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_micros(wait_us)) => {}
+                _ = stop.wait_for(|stop| *stop) => return,
+            }
+            self.run_due(now_us()).await;
+        }
+    }
+
+    fn collect_events(&mut self) {
+        if let Some(events) = self.events.as_mut() {
+            loop {
+                match events.try_recv() {
+                    Ok(event) => self.recent.push_back(event),
+                    Err(broadcast::error::TryRecvError::Lagged(missed)) => {
+                        println!("Report scheduler missed {} events", missed);
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+        // Nothing older than the longest period is reported again
+        let longest = self
+            .jobs
+            .iter()
+            .map(|job| job.job.schedule.period_us())
+            .max()
+            .unwrap_or(0);
+        let oldest = self.jobs.iter().map(|job| job.next_us).min().unwrap_or(0) - longest;
+        while self.recent.front().is_some_and(|e| e.timestamp_us < oldest) {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn build(&self, job: &ReportJob, start_us: i64, end_us: i64) -> Report {
+        let content = match job.kind {
+            ReportKind::Quality => self.quality(start_us, end_us),
+            ReportKind::Events => self.event_digest(start_us, end_us),
+            ReportKind::Compliance => self.compliance(start_us, end_us),
+        };
+        Report {
+            job: job.name.clone(),
+            kind: job.kind,
+            start_us,
+            end_us,
+            content,
+        }
+    }
+
+    // Stations of a stream, from its configuration.
+    fn stations(&self, idcode: u16) -> Vec<String> {
+        let Ok(historian) = self.historian.lock() else {
+            return Vec::new();
+        };
+        historian.config(idcode).map_or_else(Vec::new, |config| {
+            config
+                .pmu_configs
+                .iter()
+                .map(|pmu| String::from_utf8_lossy(&pmu.stn).trim().to_string())
+                .collect()
+        })
+    }
+
+    // Batches of every stream in the period with the stream's reporting rate.
+    fn batches(&self, start_us: i64, end_us: i64) -> Vec<(u16, f64, Option<RecordBatch>)> {
+        let Ok(historian) = self.historian.lock() else {
+            return Vec::new();
+        };
+        historian
+            .idcodes()
+            .into_iter()
+            .map(|idcode| {
+                let rate = historian.config(idcode).map_or(0.0, |config| {
+                    if config.data_rate > 0 {
+                        config.data_rate as f64
+                    } else {
+                        -1.0 / config.data_rate as f64
+                    }
+                });
+                let batch = historian.query(idcode, start_us, end_us).ok().flatten();
+                (idcode, rate, batch)
+            })
+            .collect()
+    }
+
+    fn quality(&self, start_us: i64, end_us: i64) -> Value {
+        let mut channels = Vec::new();
+        let mut angle_jitter = Vec::new();
+        let mut frequencies = Vec::new();
+        for (idcode, rate, batch) in self.batches(start_us, end_us) {
+            let Some(batch) = batch else {
+                continue;
+            };
+            let mut detector = AnomalyDetector::new(AnomalyConfig::new(rate), &idcode.to_string());
+            if let Some(annotations) = &self.annotations {
+                detector = detector.with_annotations(annotations.clone());
+            }
+            let timestamps = timestamps(&batch);
+            if let Some(frequency) = frequency_column(&batch) {
+                let series: Vec<(i64, f64)> = timestamps.iter().copied().zip(frequency).collect();
+                frequencies.push((idcode, series));
+            }
+            let mut stations = HashMap::new();
+            for (name, station, values) in measured_columns(&batch) {
+                for (timestamp_us, value) in timestamps.iter().zip(values) {
+                    detector.push(&name, *timestamp_us, value);
+                }
+                stations.insert(name, station);
+            }
+            for (name, _, angles) in phasor_angles(&batch) {
+                for (timestamp_us, angle) in timestamps.iter().zip(angles) {
+                    detector.push_angle(&name, *timestamp_us, angle);
+                }
+            }
+            if let Value::Object(mut report) = detector.report().to_json() {
+                if let Some(Value::Array(stream_jitter)) = report.remove("angle_jitter") {
+                    angle_jitter.extend(stream_jitter);
+                }
+                if let Some(Value::Array(stream_channels)) = report.remove("channels") {
+                    channels.extend(stream_channels.into_iter().map(|mut channel| {
+                        if self.areas.is_some() {
+                            let station = channel["name"]
+                                .as_str()
+                                .and_then(|name| stations.get(name))
+                                .cloned()
+                                .unwrap_or_default();
+                            channel["station"] = json!(station);
+                        }
+                        channel
+                    }));
+                }
+            }
+        }
+        let anomalies: u64 = channels
+            .iter()
+            .filter_map(|channel| channel["anomalies"].as_u64())
+            .sum();
+        let skew_config = TimeSkewConfig::default();
+        let mut time_skew = Vec::new();
+        for (index, (first, a)) in frequencies.iter().enumerate() {
+            for (second, b) in &frequencies[index + 1..] {
+                let estimate = estimate_skew(&skew_config, a, b);
+                time_skew.push(json!({
+                    "streams": [first, second],
+                    "skew_us": estimate.map(|e| e.skew_us.round()),
+                    "correlation": estimate.map(|e| e.correlation),
+                }));
+            }
+        }
+        let Some(areas) = &self.areas else {
+            return json!({
+                "anomalies": anomalies,
+                "channels": channels,
+                "angle_jitter": angle_jitter,
+                "time_skew": time_skew,
+            });
+        };
+        for channel in &mut channels {
+            let station = channel["station"].as_str().unwrap_or_default();
+            channel["area"] = json!(areas.area_of(station));
+        }
+        let grouped = areas.group(channels.iter().map(|channel| {
+            let station = channel["station"].as_str().unwrap_or_default();
+            (station, channel["anomalies"].as_u64().unwrap_or(0))
+        }));
+        let area_totals: Vec<Value> = grouped
+            .into_iter()
+            .map(|(area, anomalies)| {
+                json!({
+                    "area": area,
+                    "channels": anomalies.len(),
+                    "anomalies": anomalies.iter().sum::<u64>(),
+                })
+            })
+            .collect();
+        json!({
+            "anomalies": anomalies,
+            "areas": area_totals,
+            "channels": channels,
+            "angle_jitter": angle_jitter,
+            "time_skew": time_skew,
+        })
+    }
+
+    fn event_digest(&self, start_us: i64, end_us: i64) -> Value {
+        let (suppressed, events): (Vec<&Event>, Vec<&Event>) = self
+            .recent
+            .iter()
+            .filter(|e| e.timestamp_us >= start_us && e.timestamp_us < end_us)
+            .partition(|e| {
+                self.annotations.as_ref().is_some_and(|annotations| {
+                    let annotations = annotations.lock().unwrap();
+                    let stream = e.source.parse().ok();
+                    annotations
+                        .suppressing(stream, &e.source, e.timestamp_us)
+                        .is_some()
+                })
+            });
+        let mut by_kind = BTreeMap::new();
+        let mut by_severity = BTreeMap::new();
+        for event in &events {
+            let name = |value: Value| value.as_str().unwrap_or_default().to_string();
+            *by_kind.entry(name(json!(event.kind))).or_insert(0u64) += 1;
+            *by_severity
+                .entry(name(json!(event.severity)))
+                .or_insert(0u64) += 1;
+        }
+        json!({
+            "count": events.len(),
+            "suppressed": suppressed.len(),
+            "by_kind": by_kind,
+            "by_severity": by_severity,
+            "events": events.iter().take(DIGEST_EVENTS).collect::<Vec<_>>(),
+        })
+    }
+
+    fn compliance(&self, start_us: i64, end_us: i64) -> Value {
+        let streams: Vec<Value> = self
+            .batches(start_us, end_us)
+            .into_iter()
+            .map(|(idcode, rate, batch)| {
+                let annotated_us = self.annotations.as_ref().map_or(0, |annotations| {
+                    let annotations = annotations.lock().unwrap();
+                    annotations.suppressed_us(idcode, start_us, end_us)
+                });
+                let expected =
+                    ((end_us - start_us - annotated_us) as f64 / 1e6 * rate).round() as u64;
+                let timestamps = batch.as_ref().map(timestamps).unwrap_or_default();
+                let period_us = if rate > 0.0 { 1e6 / rate } else { 0.0 };
+                // Gaps from the start, between frames and to the end of the period
+                let edges = std::iter::once(start_us - period_us.round() as i64)
+                    .chain(timestamps.iter().copied())
+                    .chain(std::iter::once(end_us));
+                let longest_gap_us = edges
+                    .clone()
+                    .zip(edges.skip(1))
+                    .map(|(a, b)| b - a)
+                    .max()
+                    .unwrap_or(0);
+                let off_grid = timestamps
+                    .iter()
+                    .filter(|t| {
+                        let position = (t.rem_euclid(1_000_000)) as f64 * rate / 1e6;
+                        (position - position.round()).abs() * period_us > 100.0
+                    })
+                    .count();
+                let stat_errors = batch.as_ref().map_or(0, stat_errors);
+                let received = timestamps.len() as u64;
+                let mut stream = Map::new();
+                stream.insert("idcode".to_string(), json!(idcode));
+                if let Some(areas) = &self.areas {
+                    let mut stream_areas: Vec<&str> = Vec::new();
+                    for station in self.stations(idcode) {
+                        if let Some(area) = areas.area_of(&station) {
+                            if !stream_areas.contains(&area) {
+                                stream_areas.push(area);
+                            }
+                        }
+                    }
+                    stream.insert("areas".to_string(), json!(stream_areas));
+                }
+                stream.insert("expected".to_string(), json!(expected));
+                stream.insert("received".to_string(), json!(received));
+                stream.insert(
+                    "completeness".to_string(),
+                    json!(if expected > 0 {
+                        received as f64 / expected as f64
+                    } else {
+                        0.0
+                    }),
+                );
+                stream.insert(
+                    "longest_gap_s".to_string(),
+                    json!(longest_gap_us as f64 / 1e6),
+                );
+                stream.insert("annotated_s".to_string(), json!(annotated_us as f64 / 1e6));
+                stream.insert("off_grid".to_string(), json!(off_grid));
+                stream.insert("stat_errors".to_string(), json!(stat_errors));
+                Value::Object(stream)
+            })
+            .collect();
+        let Some(areas) = &self.areas else {
+            return json!({ "streams": streams });
+        };
+        // A stream counts in the areas of all its stations
+        let mut totals: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        for stream in &streams {
+            let idcode = stream["idcode"].as_u64().unwrap_or_default() as u16;
+            let stations = self.stations(idcode);
+            let grouped = areas.group(stations.iter().map(|station| (station.as_str(), ())));
+            for area in grouped.into_keys() {
+                let total = totals.entry(area).or_default();
+                total.0 += stream["expected"].as_u64().unwrap_or(0);
+                total.1 += stream["received"].as_u64().unwrap_or(0);
+            }
+        }
+        let area_totals: Vec<Value> = totals
+            .into_iter()
+            .map(|(area, (expected, received))| {
+                json!({
+                    "area": area,
+                    "expected": expected,
+                    "received": received,
+                    "completeness": if expected > 0 {
+                        received as f64 / expected as f64
+                    } else {
+                        0.0
+                    },
+                })
+            })
+            .collect();
+        json!({ "areas": area_totals, "streams": streams })
+    }
+}
+
+fn now_us() -> i64 {
+    chrono::Utc::now().timestamp_micros()
+}
+
+fn timestamps(batch: &RecordBatch) -> Vec<i64> {
+    batch
+        .column_by_name("timestamp")
+        .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+        .map(|c| c.values().to_vec())
+        .unwrap_or_default()
+}
+
+// Frequency, ROCOF, analog and phasor magnitude columns in engineering units,
+// with the station of each.
+fn measured_columns(batch: &RecordBatch) -> Vec<(String, String, Vec<f64>)> {
+    let schema = batch.schema();
+    let mut columns = Vec::new();
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        let meta = field.metadata();
+        let kind = meta.get(META_KIND).map(String::as_str);
+        let component = meta.get(META_COMPONENT).map(String::as_str);
+        let measured = match component {
+            Some(component) => component == "magnitude",
+            None => matches!(
+                kind,
+                Some("frequency" | "rocof" | "rms" | "peak" | "point_on_wave")
+            ),
+        };
+        if !measured {
+            continue;
+        }
+        let Ok(values) = cast(column, &DataType::Float64) else {
+            continue;
+        };
+        let Some(values) = values.as_any().downcast_ref::<Float64Array>() else {
+            continue;
+        };
+        let number = |key: &str, default: f64| {
+            meta.get(key)
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(default)
+        };
+        let (scale, offset) = (number(META_SCALE, 1.0), number(META_OFFSET, 0.0));
+        let values = values.values().iter().map(|v| v * scale + offset).collect();
+        let station = meta.get(META_STATION).cloned().unwrap_or_default();
+        columns.push((field.name().to_string(), station, values));
+    }
+    columns
+}
+
+// Values in Hz of the first frequency column.
+fn frequency_column(batch: &RecordBatch) -> Option<Vec<f64>> {
+    let schema = batch.schema();
+    let field = schema
+        .fields()
+        .iter()
+        .find(|field| field.metadata().get(META_KIND).map(String::as_str) == Some("frequency"))?;
+    let values = channel_values(batch, field.name())?;
+    Some(values.values().to_vec())
+}
+
+// Frames with an error flagged in the STAT of any PMU (bits 15-14).
+fn stat_errors(batch: &RecordBatch) -> usize {
+    let stats: Vec<&UInt16Array> = batch
+        .schema()
+        .fields()
+        .iter()
+        .zip(batch.columns())
+        .filter(|(field, _)| field.name().ends_with("_STAT"))
+        .filter_map(|(_, column)| column.as_any().downcast_ref::<UInt16Array>())
+        .collect();
+    (0..batch.num_rows())
+        .filter(|row| stats.iter().any(|stat| stat.value(*row) & 0xC000 != 0))
+        .count()
+}
+
+async fn deliver(report: &Report, output: &ReportOutput) -> io::Result<()> {
+    match output {
+        ReportOutput::Dir(dir) => {
+            fs::create_dir_all(dir)?;
+            let stem = report.file_stem();
+            let json = serde_json::to_string_pretty(&report.to_json())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            fs::write(dir.join(format!("{}.json", stem)), json)?;
+            fs::write(dir.join(format!("{}.txt", stem)), report.render())
+        }
+        ReportOutput::Webhook(url) => post_json(url, &report.to_json()).await,
+    }
+}
+
+// POST a JSON body over plain HTTP/1.1, failing on a non-2xx status.
+pub(crate) async fn post_json(url: &str, body: &Value) -> io::Result<()> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid URL {}", url));
+    let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, "/"),
+    };
+    if authority.is_empty() {
+        return Err(invalid());
+    }
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    let body = body.to_string();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        authority,
+        body.len(),
+        body
+    );
+    let exchange = async {
+        let mut stream = TcpStream::connect(&address).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        let mut buffer = [0u8; 512];
+        // The status line is all that is needed
+        while !response.windows(2).any(|w| w == b"\r\n") {
+            let n = stream.read(&mut buffer).await?;
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buffer[..n]);
+        }
+        Ok::<_, io::Error>(response)
+    };
+    let response = tokio::time::timeout(Duration::from_secs(10), exchange)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Webhook timed out"))??;
+    let status_line = String::from_utf8_lossy(&response);
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok());
+    match status {
+        Some(code) if (200..300).contains(&code) => Ok(()),
+        _ => Err(io::Error::other(format!(
+            "Webhook answered {:?}",
+            status_line.lines().next().unwrap_or_default()
+        ))),
+    }
+}
@@ -0,0 +1,329 @@
+// Downsampled rollup tiers of the historian.
+//
+// Alongside the raw frames, each stream keeps rows of min/mean/max per
+// channel (in engineering units, magnitude for phasors) over fixed
+// intervals, by default 1 second for 6 hours and 1 minute for 7 days. The
+// first tier is summarized from the frames of its interval once a frame of a
+// later interval arrives, each coarser tier is merged from the rows of the
+// tier before it. Rows outlive the raw frames they were made of, so long
+// ranges are read from a tier instead of scanning full-rate data.
+//
+// The row still being filled is returned too: the first tier's from its
+// frames so far, a coarser tier's from the rows of the finer tier closed so
+// far. Frames arriving after their interval was summarized are counted as
+// late and left out.
+use crate::arrow_utils::{build_record_batch, channel_values, META_CHANNEL};
+use crate::frames::{ChannelDataType, ChannelInfo};
+use arrow::array::{ArrayRef, Float64Array, TimestampMicrosecondArray, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollupTier {
+    pub interval_us: i64,
+    pub max_rows: usize, // Oldest rows dropped past this
+}
+
+impl RollupTier {
+    pub fn new(interval: Duration, retention: Duration) -> Self {
+        let interval_us = (interval.as_micros() as i64).max(1);
+        RollupTier {
+            interval_us,
+            max_rows: (retention.as_micros() as i64 / interval_us).max(1) as usize,
+        }
+    }
+}
+
+// 1 second for 6 hours and 1 minute for 7 days.
+pub fn default_tiers() -> Vec<RollupTier> {
+    vec![
+        RollupTier::new(Duration::from_secs(1), Duration::from_secs(6 * 3600)),
+        RollupTier::new(Duration::from_secs(60), Duration::from_secs(7 * 86_400)),
+    ]
+}
+
+// Finite values of a channel over a row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub min: f64,
+    pub max: f64,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Summary {
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Summary {
+    fn add(&mut self, value: f64) {
+        if value.is_finite() {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+            self.sum += value;
+            self.count += 1;
+        }
+    }
+
+    fn merge(&mut self, other: &Summary) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
+
+    // min, mean or max, None without values.
+    pub fn statistic(&self, name: &str) -> Option<f64> {
+        match name {
+            "min" if self.count > 0 => Some(self.min),
+            "max" if self.count > 0 => Some(self.max),
+            "mean" => self.mean(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollupRow {
+    pub start_us: i64,
+    pub frames: u64,
+    pub channels: Vec<Summary>, // In the order of Rollups::channels
+}
+
+impl RollupRow {
+    fn merge(&mut self, other: &RollupRow) {
+        self.frames += other.frames;
+        for (summary, other) in self.channels.iter_mut().zip(&other.channels) {
+            summary.merge(other);
+        }
+    }
+}
+
+struct Tier {
+    interval_us: i64,
+    max_rows: usize,
+    rows: VecDeque<RollupRow>,
+    open: Option<RollupRow>, // Coarser tiers, merged from the finer one
+}
+
+impl Tier {
+    fn bucket(&self, timestamp_us: i64) -> i64 {
+        timestamp_us.div_euclid(self.interval_us) * self.interval_us
+    }
+}
+
+pub struct Rollups {
+    channel_map: HashMap<String, ChannelInfo>,
+    channels: Vec<String>,
+    frame_size: usize,
+    tiers: Vec<Tier>, // Finest first
+    frames: Vec<u8>,  // Of the first tier's open interval
+    open_us: Option<i64>,
+    late: u64,
+}
+
+impl Rollups {
+    pub fn new(
+        channel_map: &HashMap<String, ChannelInfo>,
+        frame_size: usize,
+        tiers: &[RollupTier],
+    ) -> Self {
+        let mut channels: Vec<(&String, &ChannelInfo)> = channel_map
+            .iter()
+            .filter(|(_, info)| {
+                !matches!(
+                    info.data_type,
+                    ChannelDataType::Stat | ChannelDataType::Digital
+                )
+            })
+            .collect();
+        channels.sort_by_key(|(_, info)| info.offset);
+        let mut tiers: Vec<Tier> = tiers
+            .iter()
+            .map(|tier| Tier {
+                interval_us: tier.interval_us,
+                max_rows: tier.max_rows,
+                rows: VecDeque::new(),
+                open: None,
+            })
+            .collect();
+        tiers.sort_by_key(|tier| tier.interval_us);
+        Rollups {
+            channel_map: channel_map.clone(),
+            channels: channels.into_iter().map(|(name, _)| name.clone()).collect(),
+            frame_size,
+            tiers,
+            frames: Vec::new(),
+            open_us: None,
+            late: 0,
+        }
+    }
+
+    // Channels summarized, in offset order.
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    pub fn intervals(&self) -> Vec<i64> {
+        self.tiers.iter().map(|tier| tier.interval_us).collect()
+    }
+
+    // Frames left out for arriving after their interval was summarized.
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+
+    pub fn push(&mut self, timestamp_us: i64, frame: &[u8]) {
+        let Some(first) = self.tiers.first() else {
+            return;
+        };
+        let bucket = first.bucket(timestamp_us);
+        match self.open_us {
+            Some(open) if bucket < open => {
+                self.late += 1;
+                return;
+            }
+            Some(open) if bucket > open => {
+                if let Some(row) = self.summarize() {
+                    self.add(0, row);
+                }
+                self.frames.clear();
+                self.open_us = Some(bucket);
+            }
+            Some(_) => {}
+            None => self.open_us = Some(bucket),
+        }
+        self.frames.extend_from_slice(frame);
+    }
+
+    // Row of the frames of the first tier's open interval.
+    fn summarize(&self) -> Option<RollupRow> {
+        let start_us = self.open_us?;
+        if self.frames.is_empty() {
+            return None;
+        }
+        let batch = build_record_batch(&self.frames, self.frame_size, &self.channel_map).ok()?;
+        let channels = self
+            .channels
+            .iter()
+            .map(|name| {
+                let mut summary = Summary::default();
+                if let Some(values) = channel_values(&batch, name) {
+                    values.iter().flatten().for_each(|value| summary.add(value));
+                }
+                summary
+            })
+            .collect();
+        Some(RollupRow {
+            start_us,
+            frames: batch.num_rows() as u64,
+            channels,
+        })
+    }
+
+    // A closed row of the tier before level, or of the frames for level 0.
+    fn add(&mut self, level: usize, row: RollupRow) {
+        let Some(tier) = self.tiers.get_mut(level) else {
+            return;
+        };
+        let closed = if level == 0 {
+            Some(row)
+        } else {
+            let bucket = tier.bucket(row.start_us);
+            match tier.open.as_mut() {
+                Some(open) if open.start_us == bucket => {
+                    open.merge(&row);
+                    None
+                }
+                Some(open) if open.start_us > bucket => None,
+                _ => tier.open.replace(RollupRow {
+                    start_us: bucket,
+                    ..row
+                }),
+            }
+        };
+        if let Some(closed) = closed {
+            tier.rows.push_back(closed.clone());
+            while tier.rows.len() > tier.max_rows {
+                tier.rows.pop_front();
+            }
+            self.add(level + 1, closed);
+        }
+    }
+
+    // Rows of the tier of interval_us with start <= row start < end, the open
+    // one included. None without such a tier.
+    pub fn rows(&self, interval_us: i64, start_us: i64, end_us: i64) -> Option<Vec<RollupRow>> {
+        let level = self
+            .tiers
+            .iter()
+            .position(|tier| tier.interval_us == interval_us)?;
+        let tier = &self.tiers[level];
+        let open = if level == 0 {
+            self.summarize()
+        } else {
+            tier.open.clone()
+        };
+        Some(
+            tier.rows
+                .iter()
+                .cloned()
+                .chain(open)
+                .filter(|row| row.start_us >= start_us && row.start_us < end_us)
+                .collect(),
+        )
+    }
+
+    // Rows as a batch: timestamp (start of the row), frames, then
+    // <channel>_min, _mean and _max for each channel.
+    pub fn to_batch(&self, rows: &[RollupRow]) -> Result<RecordBatch, ArrowError> {
+        let mut fields = vec![
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Microsecond, None),
+                false,
+            ),
+            Field::new("frames", DataType::UInt64, false),
+        ];
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(TimestampMicrosecondArray::from(
+                rows.iter().map(|row| row.start_us).collect::<Vec<_>>(),
+            )),
+            Arc::new(UInt64Array::from(
+                rows.iter().map(|row| row.frames).collect::<Vec<_>>(),
+            )),
+        ];
+        for (index, name) in self.channels.iter().enumerate() {
+            let summaries: Vec<&Summary> = rows.iter().map(|row| &row.channels[index]).collect();
+            for statistic in ["min", "mean", "max"] {
+                let metadata = HashMap::from([(META_CHANNEL.to_string(), name.clone())]);
+                fields.push(
+                    Field::new(format!("{}_{}", name, statistic), DataType::Float64, true)
+                        .with_metadata(metadata),
+                );
+                columns.push(Arc::new(Float64Array::from(
+                    summaries
+                        .iter()
+                        .map(|summary| summary.statistic(statistic))
+                        .collect::<Vec<_>>(),
+                )));
+            }
+        }
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+    }
+}
@@ -0,0 +1,479 @@
+// Time join of PMU batches with external SCADA point data.
+//
+// SCADA points (tap positions, breaker states, ...) arrive at a low rate
+// from a CSV file or, with the mqtt feature, an MQTT broker, and are kept
+// per point in a ScadaStore for retention_secs. ScadaJoin adds a column per
+// configured point to a batch holding, for every row, the point's value
+// nearest in time within tolerance_ms (or the last one at or before the row
+// with JoinMode::Previous), null without one.
+//
+// CSV sources are followed as they grow, a line per value:
+//
+//   timestamp,point,value
+//   2024-05-01T12:00:00.250Z,TX1_TAP,7
+//   1714564800250000,CB12_STATE,1
+//
+// timestamps in RFC 3339 or in microseconds since the epoch. MQTT payloads
+// are a number, the point being the topic and the time of arrival its
+// timestamp, or a JSON object {"point": ..., "value": ..., "timestamp": ...}
+// with the point and timestamp optional.
+use crate::arrow_utils::{META_CHANNEL, META_KIND};
+use arrow::array::{ArrayRef, Float64Array, TimestampMicrosecondArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScadaPoint {
+    pub point: String,
+    pub timestamp_us: i64,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JoinMode {
+    #[default]
+    Nearest,
+    Previous, // Last value at or before the row, e.g. for states
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScadaSource {
+    Csv {
+        path: PathBuf,
+        #[serde(default = "default_poll_ms")]
+        poll_ms: u64,
+    },
+    Mqtt {
+        host: String,
+        #[serde(default = "default_mqtt_port")]
+        port: u16,
+        topics: Vec<String>,
+    },
+}
+
+fn default_poll_ms() -> u64 {
+    1000
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScadaConfig {
+    pub sources: Vec<ScadaSource>,
+    // Points joined, a column each in this order
+    pub points: Vec<String>,
+    #[serde(default)]
+    pub mode: JoinMode,
+    #[serde(default = "default_tolerance_ms")]
+    pub tolerance_ms: u64,
+    #[serde(default = "default_retention_secs")]
+    pub retention_secs: u64,
+}
+
+fn default_tolerance_ms() -> u64 {
+    10_000
+}
+
+fn default_retention_secs() -> u64 {
+    3600
+}
+
+impl ScadaConfig {
+    pub fn new(sources: Vec<ScadaSource>, points: &[&str]) -> Self {
+        ScadaConfig {
+            sources,
+            points: points.iter().map(|p| p.to_string()).collect(),
+            mode: JoinMode::default(),
+            tolerance_ms: default_tolerance_ms(),
+            retention_secs: default_retention_secs(),
+        }
+    }
+}
+
+// Timestamps and values of a point.
+type PointValues = VecDeque<(i64, f64)>;
+
+// Values of every point, in timestamp order, shared by the sources writing
+// to it and the joins reading it.
+#[derive(Clone)]
+pub struct ScadaStore {
+    points: Arc<Mutex<HashMap<String, PointValues>>>,
+    retention_us: i64,
+}
+
+impl ScadaStore {
+    pub fn new(retention: Duration) -> Self {
+        ScadaStore {
+            points: Arc::new(Mutex::new(HashMap::new())),
+            retention_us: retention.as_micros() as i64,
+        }
+    }
+
+    // Keep a value, dropping those of the point older than the retention
+    // before its newest.
+    pub fn push(&self, point: ScadaPoint) {
+        let Ok(mut points) = self.points.lock() else {
+            return;
+        };
+        let values = points.entry(point.point).or_default();
+        let position = values.partition_point(|(t, _)| *t <= point.timestamp_us);
+        values.insert(position, (point.timestamp_us, point.value));
+        if let Some(&(newest, _)) = values.back() {
+            while values
+                .front()
+                .is_some_and(|(t, _)| *t < newest - self.retention_us)
+            {
+                values.pop_front();
+            }
+        }
+    }
+
+    pub fn len(&self, point: &str) -> usize {
+        self.points
+            .lock()
+            .map_or(0, |points| points.get(point).map_or(0, PointValues::len))
+    }
+
+    // Value of a point for each timestamp, None without one within tolerance.
+    pub fn lookup(
+        &self,
+        point: &str,
+        timestamps: &[i64],
+        mode: JoinMode,
+        tolerance_us: i64,
+    ) -> Vec<Option<f64>> {
+        let Ok(points) = self.points.lock() else {
+            return vec![None; timestamps.len()];
+        };
+        let Some(values) = points.get(point) else {
+            return vec![None; timestamps.len()];
+        };
+        timestamps
+            .iter()
+            .map(|&timestamp| {
+                let after = values.partition_point(|(t, _)| *t <= timestamp);
+                let before = after.checked_sub(1).and_then(|i| values.get(i));
+                let nearest = match mode {
+                    JoinMode::Previous => before,
+                    JoinMode::Nearest => match (before, values.get(after)) {
+                        (Some(b), Some(a)) if a.0 - timestamp < timestamp - b.0 => Some(a),
+                        (None, a) => a,
+                        (b, _) => b,
+                    },
+                };
+                nearest
+                    .filter(|(t, _)| (t - timestamp).abs() <= tolerance_us)
+                    .map(|(_, value)| *value)
+            })
+            .collect()
+    }
+}
+
+pub struct ScadaJoin {
+    store: ScadaStore,
+    points: Vec<String>,
+    mode: JoinMode,
+    tolerance_us: i64,
+}
+
+impl ScadaJoin {
+    pub fn new(config: &ScadaConfig, store: ScadaStore) -> Self {
+        ScadaJoin {
+            store,
+            points: config.points.clone(),
+            mode: config.mode,
+            tolerance_us: config.tolerance_ms as i64 * 1000,
+        }
+    }
+
+    // The batch with a Float64 column per point, named as the point.
+    pub fn apply(&self, batch: &RecordBatch) -> Result<RecordBatch, ArrowError> {
+        let timestamps = batch
+            .column_by_name("timestamp")
+            .and_then(|column| column.as_any().downcast_ref::<TimestampMicrosecondArray>())
+            .ok_or_else(|| ArrowError::SchemaError("No timestamp column".to_string()))?;
+        let timestamps: Vec<i64> = timestamps.values().to_vec();
+        let schema = batch.schema();
+        let mut fields: Vec<Field> = schema
+            .fields()
+            .iter()
+            .map(|field| field.as_ref().clone())
+            .collect();
+        let mut columns = batch.columns().to_vec();
+        for point in &self.points {
+            let values = self
+                .store
+                .lookup(point, &timestamps, self.mode, self.tolerance_us);
+            let metadata = HashMap::from([
+                (META_CHANNEL.to_string(), point.clone()),
+                (META_KIND.to_string(), "scada".to_string()),
+            ]);
+            fields.push(Field::new(point, DataType::Float64, true).with_metadata(metadata));
+            columns.push(Arc::new(Float64Array::from(values)) as ArrayRef);
+        }
+        RecordBatch::try_new(
+            Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone())),
+            columns,
+        )
+    }
+}
+
+fn parse_timestamp(text: &str) -> Option<i64> {
+    text.parse::<i64>().ok().or_else(|| {
+        DateTime::parse_from_rfc3339(text)
+            .ok()
+            .map(|t| t.timestamp_micros())
+    })
+}
+
+// A CSV line as timestamp,point,value. None for the header and blank or
+// malformed lines.
+pub fn parse_csv_line(line: &str) -> Option<ScadaPoint> {
+    let mut fields = line.trim().splitn(3, ',');
+    let timestamp_us = parse_timestamp(fields.next()?.trim())?;
+    let point = fields.next()?.trim().to_string();
+    let value = fields.next()?.trim().parse().ok()?;
+    Some(ScadaPoint {
+        point,
+        timestamp_us,
+        value,
+    })
+}
+
+// A payload received on topic, at now_us.
+pub fn parse_payload(topic: &str, payload: &[u8], now_us: i64) -> Option<ScadaPoint> {
+    let text = std::str::from_utf8(payload).ok()?.trim();
+    if let Ok(value) = text.parse::<f64>() {
+        return Some(ScadaPoint {
+            point: topic.to_string(),
+            timestamp_us: now_us,
+            value,
+        });
+    }
+    let json: serde_json::Value = serde_json::from_str(text).ok()?;
+    let value = match &json["value"] {
+        serde_json::Value::Bool(state) => *state as u8 as f64,
+        value => value.as_f64()?,
+    };
+    let timestamp_us = match &json["timestamp"] {
+        serde_json::Value::String(text) => parse_timestamp(text)?,
+        serde_json::Value::Number(number) => number.as_i64()?,
+        _ => now_us,
+    };
+    Some(ScadaPoint {
+        point: json["point"].as_str().unwrap_or(topic).to_string(),
+        timestamp_us,
+        value,
+    })
+}
+
+// Follows a CSV file from offset, returning the points of the lines
+// completed since and the offset after them. A file shorter than offset was
+// replaced and is read from the start.
+pub fn read_csv_from(path: &Path, offset: u64) -> io::Result<(Vec<ScadaPoint>, u64)> {
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let offset = if len < offset { 0 } else { offset };
+    file.seek(SeekFrom::Start(offset))?;
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+    // An incomplete last line is read again next time
+    let complete = text.rfind('\n').map_or(0, |i| i + 1);
+    let points = text[..complete]
+        .lines()
+        .filter_map(parse_csv_line)
+        .collect();
+    Ok((points, offset + complete as u64))
+}
+
+// Feed the store from a CSV file until the task is dropped.
+pub async fn follow_csv(path: PathBuf, poll: Duration, store: ScadaStore) {
+    let mut offset = 0;
+    let mut failing = false;
+    loop {
+        match read_csv_from(&path, offset) {
+            Ok((points, next)) => {
+                failing = false;
+                offset = next;
+                points.into_iter().for_each(|point| store.push(point));
+            }
+            Err(e) if !failing => {
+                println!("Failed to read SCADA points from {}: {}", path.display(), e);
+                failing = true;
+            }
+            Err(_) => {}
+        }
+        tokio::time::sleep(poll).await;
+    }
+}
+
+// Feed the store from a source until the task is dropped.
+pub async fn run_source(source: ScadaSource, store: ScadaStore) {
+    match source {
+        ScadaSource::Csv { path, poll_ms } => {
+            follow_csv(path, Duration::from_millis(poll_ms.max(1)), store).await
+        }
+        #[cfg(feature = "mqtt")]
+        ScadaSource::Mqtt { host, port, topics } => {
+            mqtt::subscribe(&format!("{}:{}", host, port), &topics, store).await
+        }
+        #[cfg(not(feature = "mqtt"))]
+        ScadaSource::Mqtt { host, port, .. } => {
+            println!(
+                "SCADA source mqtt://{}:{} needs the mqtt feature, ignored",
+                host, port
+            );
+        }
+    }
+}
+
+// Start the sources of a configuration, returning the store they feed and
+// their tasks.
+pub fn start(config: &ScadaConfig) -> (ScadaStore, Vec<tokio::task::JoinHandle<()>>) {
+    let store = ScadaStore::new(Duration::from_secs(config.retention_secs));
+    let tasks = config
+        .sources
+        .iter()
+        .map(|source| tokio::spawn(run_source(source.clone(), store.clone())))
+        .collect();
+    (store, tasks)
+}
+
+// MQTT 3.1.1 subscriber, QoS 0 over plain TCP.
+#[cfg(feature = "mqtt")]
+pub mod mqtt {
+    use super::{parse_payload, ScadaStore};
+    use crate::latency::now_micros;
+    use std::io;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    const KEEP_ALIVE_SECS: u16 = 60;
+    const RETRY: Duration = Duration::from_secs(5);
+
+    fn remaining_length(mut length: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let mut byte = (length % 128) as u8;
+            length /= 128;
+            if length > 0 {
+                byte |= 0x80;
+            }
+            bytes.push(byte);
+            if length == 0 {
+                return bytes;
+            }
+        }
+    }
+
+    fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+        let mut packet = vec![header];
+        packet.extend(remaining_length(body.len()));
+        packet.extend_from_slice(body);
+        packet
+    }
+
+    fn string(text: &str) -> Vec<u8> {
+        let mut bytes = (text.len() as u16).to_be_bytes().to_vec();
+        bytes.extend_from_slice(text.as_bytes());
+        bytes
+    }
+
+    pub fn connect_packet(client_id: &str) -> Vec<u8> {
+        let mut body = string("MQTT");
+        body.push(4); // Protocol level 3.1.1
+        body.push(0x02); // Clean session
+        body.extend_from_slice(&KEEP_ALIVE_SECS.to_be_bytes());
+        body.extend(string(client_id));
+        packet(0x10, &body)
+    }
+
+    pub fn subscribe_packet(packet_id: u16, topics: &[String]) -> Vec<u8> {
+        let mut body = packet_id.to_be_bytes().to_vec();
+        for topic in topics {
+            body.extend(string(topic));
+            body.push(0); // QoS 0
+        }
+        packet(0x82, &body)
+    }
+
+    // Type and flags, then the body of the next packet.
+    async fn read_packet(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+        let header = stream.read_u8().await?;
+        let mut length = 0usize;
+        for shift in 0..4 {
+            let byte = stream.read_u8().await?;
+            length |= ((byte & 0x7F) as usize) << (7 * shift);
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await?;
+        Ok((header, body))
+    }
+
+    // Topic and payload of a PUBLISH body.
+    pub fn parse_publish(flags: u8, body: &[u8]) -> Option<(String, &[u8])> {
+        let length = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+        let topic = std::str::from_utf8(body.get(2..2 + length)?).ok()?;
+        // A packet identifier follows the topic from QoS 1 on
+        let start = 2 + length + if flags & 0x06 != 0 { 2 } else { 0 };
+        Some((topic.to_string(), body.get(start..)?))
+    }
+
+    async fn session(address: &str, topics: &[String], store: &ScadaStore) -> io::Result<()> {
+        let mut stream = TcpStream::connect(address).await?;
+        let client_id = format!("pmu-{}", std::process::id());
+        stream.write_all(&connect_packet(&client_id)).await?;
+        let (header, body) = read_packet(&mut stream).await?;
+        if header != 0x20 || body.get(1) != Some(&0) {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("CONNACK {:?}", body),
+            ));
+        }
+        stream.write_all(&subscribe_packet(1, topics)).await?;
+        println!("Subscribed to SCADA points {:?} on {}", topics, address);
+        let mut ping = tokio::time::interval(Duration::from_secs(KEEP_ALIVE_SECS as u64 / 2));
+        loop {
+            tokio::select! {
+                packet = read_packet(&mut stream) => {
+                    let (header, body) = packet?;
+                    if header >> 4 == 3 {
+                        let point = parse_publish(header & 0x0F, &body)
+                            .and_then(|(topic, payload)| parse_payload(&topic, payload, now_micros()));
+                        if let Some(point) = point {
+                            store.push(point);
+                        }
+                    }
+                }
+                _ = ping.tick() => stream.write_all(&[0xC0, 0x00]).await?,
+            }
+        }
+    }
+
+    // Keep a subscription, reconnecting after failures, until the task is
+    // dropped.
+    pub async fn subscribe(address: &str, topics: &[String], store: ScadaStore) {
+        loop {
+            if let Err(e) = session(address, topics, &store).await {
+                println!("SCADA MQTT {}: {}", address, e);
+            }
+            tokio::time::sleep(RETRY).await;
+        }
+    }
+}
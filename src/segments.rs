@@ -0,0 +1,405 @@
+// Disk segments of the historian.
+//
+// Frames the historian evicts from memory are appended to segment files so
+// the retained window is bounded by disk instead of RAM. Each file holds the
+// frames of one stream in timestamp order as fixed size records, behind a
+// header giving the stream, the frame size, a digest of its configuration
+// and the number of records written:
+//
+//   header  "PMUSEG01" | idcode u16 | config digest u16 | frame size u32 |
+//           records u64 | 8 reserved bytes
+//   record  timestamp in microseconds i64 | frame
+//
+// all big endian. A segment is created at its full size and memory mapped,
+// records are written into the map and found by binary search on their
+// timestamps. Segments of a stream still in the directory are taken up again
+// when it is added with the same configuration, segments of another
+// configuration are deleted. Past max_bytes the oldest segments go first.
+use crate::frames::calculate_crc;
+use memmap2::MmapMut;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"PMUSEG01";
+const HEADER_SIZE: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SegmentConfig {
+    pub dir: PathBuf,
+    #[serde(default = "default_segment_bytes")]
+    pub segment_bytes: u64,
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64, // All segments of all streams
+}
+
+fn default_segment_bytes() -> u64 {
+    64 << 20
+}
+
+fn default_max_bytes() -> u64 {
+    4 << 30
+}
+
+impl SegmentConfig {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        SegmentConfig {
+            dir: dir.as_ref().to_path_buf(),
+            segment_bytes: default_segment_bytes(),
+            max_bytes: default_max_bytes(),
+        }
+    }
+
+    pub fn with_limits(mut self, segment_bytes: u64, max_bytes: u64) -> Self {
+        self.segment_bytes = segment_bytes;
+        self.max_bytes = max_bytes;
+        self
+    }
+}
+
+// Digest of a configuration frame, without its prefix (SOC changes with every
+// frame sent) and CHK.
+pub fn config_digest(config_frame: &[u8]) -> u16 {
+    if config_frame.len() < 16 {
+        return 0;
+    }
+    calculate_crc(&config_frame[14..config_frame.len() - 2])
+}
+
+struct Segment {
+    path: PathBuf,
+    map: MmapMut,
+    record_size: usize,
+    capacity: usize,
+    records: usize,
+}
+
+impl Segment {
+    fn create(
+        path: PathBuf,
+        idcode: u16,
+        digest: u16,
+        frame_size: usize,
+        capacity: usize,
+    ) -> io::Result<Self> {
+        let record_size = 8 + frame_size;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        file.set_len((HEADER_SIZE + capacity * record_size) as u64)?;
+        // Safety: the file is this segment's own, no one else resizes it
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        map[..8].copy_from_slice(MAGIC);
+        map[8..10].copy_from_slice(&idcode.to_be_bytes());
+        map[10..12].copy_from_slice(&digest.to_be_bytes());
+        map[12..16].copy_from_slice(&(frame_size as u32).to_be_bytes());
+        map[16..24].copy_from_slice(&0u64.to_be_bytes());
+        Ok(Segment {
+            path,
+            map,
+            record_size,
+            capacity,
+            records: 0,
+        })
+    }
+
+    // A segment left in the directory, None when it is not one of this
+    // stream and configuration.
+    fn open(
+        path: PathBuf,
+        idcode: u16,
+        digest: u16,
+        frame_size: usize,
+    ) -> io::Result<Option<Self>> {
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let len = file.metadata()?.len() as usize;
+        let record_size = 8 + frame_size;
+        if len < HEADER_SIZE + record_size {
+            return Ok(None);
+        }
+        // Safety: as in create
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        let matches = &map[..8] == MAGIC
+            && map[8..10] == idcode.to_be_bytes()
+            && map[10..12] == digest.to_be_bytes()
+            && map[12..16] == (frame_size as u32).to_be_bytes();
+        if !matches {
+            return Ok(None);
+        }
+        let capacity = (len - HEADER_SIZE) / record_size;
+        let records = u64::from_be_bytes(map[16..24].try_into().unwrap()) as usize;
+        Ok(Some(Segment {
+            path,
+            map,
+            record_size,
+            capacity,
+            records: records.min(capacity),
+        }))
+    }
+
+    fn is_full(&self) -> bool {
+        self.records >= self.capacity
+    }
+
+    fn size(&self) -> u64 {
+        self.map.len() as u64
+    }
+
+    fn append(&mut self, timestamp_us: i64, frame: &[u8]) {
+        let start = HEADER_SIZE + self.records * self.record_size;
+        self.map[start..start + 8].copy_from_slice(&timestamp_us.to_be_bytes());
+        self.map[start + 8..start + self.record_size].copy_from_slice(frame);
+        self.records += 1;
+        self.map[16..24].copy_from_slice(&(self.records as u64).to_be_bytes());
+    }
+
+    fn timestamp(&self, record: usize) -> i64 {
+        let start = HEADER_SIZE + record * self.record_size;
+        i64::from_be_bytes(self.map[start..start + 8].try_into().unwrap())
+    }
+
+    fn frame(&self, record: usize) -> &[u8] {
+        let start = HEADER_SIZE + record * self.record_size + 8;
+        &self.map[start..start + self.record_size - 8]
+    }
+
+    // First record at or after timestamp_us.
+    fn position(&self, timestamp_us: i64) -> usize {
+        let (mut low, mut high) = (0, self.records);
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.timestamp(middle) < timestamp_us {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+}
+
+struct StreamSegments {
+    digest: u16,
+    frame_size: usize,
+    segments: Vec<Segment>, // Oldest first, the last one written to
+    deleted: u64,
+}
+
+pub struct SegmentStore {
+    config: SegmentConfig,
+    streams: HashMap<u16, StreamSegments>,
+}
+
+impl SegmentStore {
+    pub fn open(config: SegmentConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        Ok(SegmentStore {
+            config,
+            streams: HashMap::new(),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.config.dir
+    }
+
+    // Take up the segments of a stream left in the directory, deleting those
+    // of another configuration. Returns the number of frames they hold.
+    pub fn add_stream(
+        &mut self,
+        idcode: u16,
+        config_frame: &[u8],
+        frame_size: usize,
+    ) -> io::Result<usize> {
+        self.streams.remove(&idcode);
+        let digest = config_digest(config_frame);
+        let prefix = format!("{}-", idcode);
+        let mut paths: Vec<PathBuf> = fs::read_dir(&self.config.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension().is_some_and(|e| e == "seg")
+                    && path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.starts_with(&prefix))
+            })
+            .collect();
+        // Named by sequence number, zero padded
+        paths.sort();
+        let mut segments = Vec::new();
+        for path in paths {
+            match Segment::open(path.clone(), idcode, digest, frame_size)? {
+                Some(segment) if segment.records > 0 => segments.push(segment),
+                _ => fs::remove_file(&path)?,
+            }
+        }
+        let frames = segments.iter().map(|segment| segment.records).sum();
+        if frames > 0 {
+            println!(
+                "Historian stream {}: {} frames in {} segments taken up from {}",
+                idcode,
+                frames,
+                segments.len(),
+                self.config.dir.display()
+            );
+        }
+        self.streams.insert(
+            idcode,
+            StreamSegments {
+                digest,
+                frame_size,
+                segments,
+                deleted: 0,
+            },
+        );
+        Ok(frames)
+    }
+
+    // Forget a stream, deleting its segments.
+    pub fn remove_stream(&mut self, idcode: u16) -> io::Result<()> {
+        if let Some(stream) = self.streams.remove(&idcode) {
+            for segment in stream.segments {
+                fs::remove_file(&segment.path)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Append a frame evicted from memory. Returns false for a stream that
+    // was not added.
+    pub fn append(&mut self, idcode: u16, timestamp_us: i64, frame: &[u8]) -> io::Result<bool> {
+        let segment_bytes = self.config.segment_bytes;
+        let Some(stream) = self.streams.get_mut(&idcode) else {
+            return Ok(false);
+        };
+        if frame.len() != stream.frame_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Frame of {} bytes, segments hold {}",
+                    frame.len(),
+                    stream.frame_size
+                ),
+            ));
+        }
+        // Out of order frames would break the search, they start a new segment
+        let in_order = stream.segments.last().is_none_or(|segment| {
+            segment.records == 0 || segment.timestamp(segment.records - 1) <= timestamp_us
+        });
+        if !in_order || stream.segments.last().is_none_or(Segment::is_full) {
+            let sequence = stream
+                .segments
+                .last()
+                .and_then(|segment| sequence_of(&segment.path))
+                .map_or(0, |n| n + 1);
+            let path = self
+                .config
+                .dir
+                .join(format!("{}-{:010}.seg", idcode, sequence));
+            let record_size = 8 + stream.frame_size;
+            let capacity =
+                ((segment_bytes as usize).saturating_sub(HEADER_SIZE) / record_size).max(1);
+            if let Some(last) = stream.segments.last() {
+                last.map.flush_async()?;
+            }
+            stream.segments.push(Segment::create(
+                path,
+                idcode,
+                stream.digest,
+                stream.frame_size,
+                capacity,
+            )?);
+        }
+        if let Some(segment) = stream.segments.last_mut() {
+            segment.append(timestamp_us, frame);
+        }
+        self.enforce_max_bytes()?;
+        Ok(true)
+    }
+
+    // Delete the oldest segment of any stream while over max_bytes, never the
+    // one a stream writes to.
+    fn enforce_max_bytes(&mut self) -> io::Result<()> {
+        while self.size() > self.config.max_bytes {
+            let oldest = self
+                .streams
+                .values_mut()
+                .filter(|stream| stream.segments.len() > 1)
+                .min_by_key(|stream| stream.segments[0].timestamp(0));
+            let Some(stream) = oldest else {
+                break;
+            };
+            let segment = stream.segments.remove(0);
+            stream.deleted += segment.records as u64;
+            fs::remove_file(&segment.path)?;
+        }
+        Ok(())
+    }
+
+    // Frames of a stream deleted to stay within max_bytes.
+    pub fn deleted(&self, idcode: u16) -> u64 {
+        self.streams.get(&idcode).map_or(0, |stream| stream.deleted)
+    }
+
+    // Bytes of all segment files.
+    pub fn size(&self) -> u64 {
+        self.streams
+            .values()
+            .flat_map(|stream| &stream.segments)
+            .map(Segment::size)
+            .sum()
+    }
+
+    pub fn frames(&self, idcode: u16) -> usize {
+        self.streams.get(&idcode).map_or(0, |stream| {
+            stream.segments.iter().map(|segment| segment.records).sum()
+        })
+    }
+
+    // Oldest and newest timestamps on disk for a stream.
+    pub fn time_range(&self, idcode: u16) -> Option<(i64, i64)> {
+        let segments = self.streams.get(&idcode)?.segments.iter();
+        segments
+            .filter(|segment| segment.records > 0)
+            .map(|segment| (segment.timestamp(0), segment.timestamp(segment.records - 1)))
+            .reduce(|(a, b), (c, d)| (a.min(c), b.max(d)))
+    }
+
+    // Frames of a stream with start_us <= timestamp < end_us, by segment.
+    pub fn range(&self, idcode: u16, start_us: i64, end_us: i64) -> Vec<(i64, &[u8])> {
+        let Some(stream) = self.streams.get(&idcode) else {
+            return Vec::new();
+        };
+        let mut frames = Vec::new();
+        for segment in &stream.segments {
+            for record in segment.position(start_us)..segment.position(end_us) {
+                frames.push((segment.timestamp(record), segment.frame(record)));
+            }
+        }
+        frames
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        for segment in self.streams.values().flat_map(|stream| &stream.segments) {
+            segment.map.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SegmentStore {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            println!("Failed to flush historian segments: {}", e);
+        }
+    }
+}
+
+// Sequence number of a segment from its name, <idcode>-<sequence>.seg.
+fn sequence_of(path: &Path) -> Option<u64> {
+    path.file_stem()?.to_str()?.split_once('-')?.1.parse().ok()
+}
@@ -0,0 +1,741 @@
+// PMU data stream simulator.
+//
+// Generates C37.118-2011 data frames for a configuration, with nominal
+// phasors rotating at the frequency deviation, and applies scripted events
+// from a scenario: frequency ramps, phase jumps, voltage sags, dropped frames,
+// CRC corruption, configuration changes and clock jumps. Output depends only
+// on the configuration and the scenario, so runs are repeatable. Measurement
+// noise, when configured, comes from a generator seeded by the scenario.
+//
+// Event times are seconds since the first frame, on the simulator's schedule
+// (clock jumps shift the SOC/FRACSEC written to the frames, not the schedule).
+use crate::analytics::reference::ReferenceSignal;
+use crate::frame_parser::parse_config_frame_1and2;
+use crate::frames::{
+    calculate_crc, now_micros, ConfigurationFrame1and2_2011, FormatFlags,
+    PMUConfigurationFrame2011, PrefixFrame2011,
+};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+// STAT bit 10 is set this long before a configuration change takes effect.
+const CONFIG_CHANGE_NOTICE_SECS: f64 = 1.0;
+const STAT_CONFIG_CHANGE: u16 = 0x0400;
+const STAT_DATA_MODIFIED: u16 = 0x0200;
+// Phasor magnitude in raw counts when fixed point phasors have no magnitude set.
+const DEFAULT_FIXED_COUNTS: f64 = 20_000.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScenarioEvent {
+    // Frequency changes at rate Hz/s for duration seconds, then holds.
+    FrequencyRamp {
+        at: f64,
+        duration: f64,
+        rate: f64,
+    },
+    // Step in every phasor angle.
+    PhaseJump {
+        at: f64,
+        degrees: f64,
+    },
+    // Phasor magnitudes drop by depth (0.3 = 70% of nominal) for duration seconds.
+    VoltageSag {
+        at: f64,
+        duration: f64,
+        depth: f64,
+    },
+    // The next count frames are not sent.
+    DropFrames {
+        at: f64,
+        count: u32,
+    },
+    // The next count frames are sent with an invalid CHK.
+    CorruptCrc {
+        at: f64,
+        count: u32,
+    },
+    // CFGCNT is incremented and optionally the data rate changed. A new
+    // configuration frame is sent ahead of the first frame using it.
+    ConfigChange {
+        at: f64,
+        #[serde(default)]
+        data_rate: Option<i16>,
+    },
+    // SOC/FRACSEC of this and later frames shift by offset_ms.
+    ClockJump {
+        at: f64,
+        offset_ms: i64,
+    },
+}
+
+impl ScenarioEvent {
+    pub fn at(&self) -> f64 {
+        match self {
+            ScenarioEvent::FrequencyRamp { at, .. }
+            | ScenarioEvent::PhaseJump { at, .. }
+            | ScenarioEvent::VoltageSag { at, .. }
+            | ScenarioEvent::DropFrames { at, .. }
+            | ScenarioEvent::CorruptCrc { at, .. }
+            | ScenarioEvent::ConfigChange { at, .. }
+            | ScenarioEvent::ClockJump { at, .. } => *at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    // SOC of the first frame, the current time when not set.
+    #[serde(default)]
+    pub start_soc: Option<u32>,
+    // Nominal phasor magnitude in engineering units. Defaults to 1.0 for
+    // floating point phasors and 20000 counts for fixed point phasors.
+    #[serde(default)]
+    pub magnitude: Option<f64>,
+    #[serde(default)]
+    pub events: Vec<ScenarioEvent>,
+    // PMUs of the simulated stream. The sample configuration is used when not set.
+    #[serde(default)]
+    pub stream: Option<StreamLayout>,
+    // Seconds after which the simulation ends, it runs forever when not set.
+    #[serde(default)]
+    pub duration: Option<f64>,
+    // Signal applied under the events, from the analytics reference generator.
+    #[serde(default)]
+    pub signal: Option<ReferenceSignal>,
+    // Measurement error added to every sample. Ideal values when not set.
+    #[serde(default)]
+    pub noise: Option<NoiseModel>,
+}
+
+// Measurement error of the simulated PMUs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NoiseModel {
+    #[serde(default)]
+    pub seed: u64,
+    // Standard deviation of the phasor magnitude, relative (0.001 = 0.1%)
+    #[serde(default)]
+    pub magnitude_std: f64,
+    // Standard deviation of the phasor angle, degrees
+    #[serde(default)]
+    pub angle_std_deg: f64,
+    // Standard deviation of the reported frequency, Hz
+    #[serde(default)]
+    pub frequency_std: f64,
+    // Standard deviation of the reported ROCOF, Hz/s
+    #[serde(default)]
+    pub rocof_std: f64,
+    // Every phasor is off by this total vector error, percent, in a random
+    // direction. Replaces magnitude_std and angle_std_deg when set.
+    #[serde(default)]
+    pub tve_percent: Option<f64>,
+    // Effective resolution of fixed point values: raw counts are rounded to a
+    // multiple of 2^(16 - bits). Full 16 bit resolution when not set.
+    #[serde(default)]
+    pub quantization_bits: Option<u8>,
+}
+
+// Deterministic generator for the noise (SplitMix64).
+#[derive(Debug, Clone)]
+struct NoiseRng {
+    state: u64,
+}
+
+impl NoiseRng {
+    fn new(seed: u64) -> Self {
+        NoiseRng { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in (0, 1]
+    fn uniform(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    // Normal with mean 0 and standard deviation std (Box-Muller)
+    fn gaussian(&mut self, std: f64) -> f64 {
+        if std <= 0.0 {
+            return 0.0;
+        }
+        let radius = (-2.0 * self.uniform().ln()).sqrt();
+        radius * (2.0 * PI * self.uniform()).cos() * std
+    }
+}
+
+impl Scenario {
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        serde_json::from_str(json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+fn default_phasors() -> u16 {
+    3
+}
+
+// One PMU of a simulated PDC stream.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimulatedPmu {
+    pub station: String,
+    // 0 to have one assigned (idcodes::assign_layouts)
+    #[serde(default)]
+    pub idcode: u16,
+    #[serde(default)]
+    pub polar: bool,
+    #[serde(default)]
+    pub float_phasors: bool,
+    #[serde(default)]
+    pub float_analogs: bool,
+    #[serde(default)]
+    pub float_freq: bool,
+    #[serde(default = "default_phasors")]
+    pub phasors: u16,
+    #[serde(default)]
+    pub analogs: u16,
+    #[serde(default)]
+    pub digitals: u16, // Status words of 16 bits each
+    // Reporting rate of this PMU, the stream rate when not set. Between its own
+    // samples the PMU repeats the last one with STAT bit 9 (data modified) set.
+    #[serde(default)]
+    pub data_rate: Option<i16>,
+    #[serde(default)]
+    pub nominal_50hz: bool,
+    // Angle of the first phasor relative to the reference, degrees
+    #[serde(default)]
+    pub angle: f64,
+}
+
+impl SimulatedPmu {
+    pub fn format(&self) -> FormatFlags {
+        FormatFlags {
+            freq_float: self.float_freq,
+            analog_float: self.float_analogs,
+            phasor_float: self.float_phasors,
+            polar: self.polar,
+            reserved: 0,
+        }
+    }
+
+    fn to_config(&self) -> PMUConfigurationFrame2011 {
+        let mut names = Vec::new();
+        for k in 0..self.phasors {
+            names.push(match k {
+                0 => "VA".to_string(),
+                1 => "VB".to_string(),
+                2 => "VC".to_string(),
+                _ => format!("PH{}", k + 1),
+            });
+        }
+        for k in 0..self.analogs {
+            names.push(format!("AN{}", k + 1));
+        }
+        for word in 0..self.digitals {
+            for bit in 0..16 {
+                names.push(format!("DG{}_{}", word + 1, bit));
+            }
+        }
+        let mut chnam = Vec::new();
+        for name in names {
+            chnam.extend_from_slice(&pad16(&name));
+        }
+
+        // Fixed point phasors use ~9.16 V per count, floating point ones no scaling.
+        let phunit = if self.float_phasors { 0 } else { 915_527 };
+        PMUConfigurationFrame2011 {
+            stn: pad16(&self.station),
+            idcode: self.idcode,
+            format: self.format(),
+            phnmr: self.phasors,
+            annmr: self.analogs,
+            dgnmr: self.digitals,
+            chnam,
+            phunit: vec![phunit; self.phasors as usize],
+            anunit: vec![1; self.analogs as usize],
+            digunit: vec![0x0000_FFFF; self.digitals as usize],
+            fnom: self.nominal_50hz as u16,
+            cfgcnt: 0,
+        }
+    }
+}
+
+fn pad16(name: &str) -> [u8; 16] {
+    let mut padded = [b' '; 16];
+    for (dst, src) in padded.iter_mut().zip(name.bytes()) {
+        *dst = src;
+    }
+    padded
+}
+
+// A PDC stream carrying several PMUs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamLayout {
+    // 0 to have one assigned (idcodes::assign_layouts)
+    #[serde(default)]
+    pub idcode: u16,
+    pub data_rate: i16,
+    #[serde(default = "default_time_base")]
+    pub time_base: u32,
+    pub pmus: Vec<SimulatedPmu>,
+}
+
+fn default_time_base() -> u32 {
+    1_000_000
+}
+
+impl StreamLayout {
+    // Configuration frame 2 describing the stream.
+    pub fn to_config(&self) -> ConfigurationFrame1and2_2011 {
+        let config = ConfigurationFrame1and2_2011 {
+            prefix: PrefixFrame2011 {
+                sync: 0xAA31,
+                framesize: 0,
+                idcode: self.idcode,
+                soc: 0,
+                fracsec: 0,
+            },
+            time_base: self.time_base,
+            num_pmu: self.pmus.len() as u16,
+            pmu_configs: self.pmus.iter().map(|pmu| pmu.to_config()).collect(),
+            data_rate: self.data_rate,
+            chk: 0,
+        };
+        // Fill in framesize and CHK
+        parse_config_frame_1and2(&config.to_hex()).unwrap_or(config)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatorTick {
+    pub time_us: i64,         // Scheduled time of the frame, without clock jumps
+    pub frames: Vec<Vec<u8>>, // Empty when the frame was dropped
+    pub config_changed: bool, // frames starts with a new configuration frame
+}
+
+pub struct Simulator {
+    base_config: ConfigurationFrame1and2_2011,
+    config: ConfigurationFrame1and2_2011,
+    scenario: Scenario,
+    start_us: i64,
+    segment_start_us: i64, // Time of the last data rate change since start
+    segment_frames: u64,   // Frames sent since the last data rate change
+    clock_offset_us: i64,
+    phase: f64,                        // Angle offset from nominal rotation, radians
+    applied: Vec<u32>,                 // Per event, frames affected or 1 once applied
+    pmu_rates: Vec<Option<i16>>,       // Per PMU reporting rate
+    angle_offsets: Vec<f64>,           // Per PMU angle, radians
+    held: Vec<Option<(i64, Vec<u8>)>>, // Per PMU last sample index and block
+    rng: NoiseRng,
+    signal_angle: f64, // Reference signal angle of the current frame, radians
+}
+
+impl Simulator {
+    pub fn new(config: ConfigurationFrame1and2_2011, scenario: Scenario) -> Self {
+        let start_us = match scenario.start_soc {
+            Some(soc) => soc as i64 * 1_000_000,
+            None => now_micros() / 1_000_000 * 1_000_000,
+        };
+        let applied = vec![0; scenario.events.len()];
+        let pmus = config.pmu_configs.len();
+        let seed = scenario.noise.as_ref().map_or(0, |noise| noise.seed);
+        Simulator {
+            rng: NoiseRng::new(seed),
+            signal_angle: 0.0,
+            pmu_rates: vec![None; pmus],
+            angle_offsets: vec![0.0; pmus],
+            held: vec![None; pmus],
+            base_config: config.clone(),
+            config,
+            scenario,
+            start_us,
+            segment_start_us: 0,
+            segment_frames: 0,
+            clock_offset_us: 0,
+            phase: 0.0,
+            applied,
+        }
+    }
+
+    pub fn from_layout(layout: &StreamLayout, scenario: Scenario) -> Self {
+        let mut simulator = Self::new(layout.to_config(), scenario);
+        simulator.pmu_rates = layout
+            .pmus
+            .iter()
+            .map(|pmu| pmu.data_rate.filter(|rate| *rate != layout.data_rate))
+            .collect();
+        simulator.angle_offsets = layout
+            .pmus
+            .iter()
+            .map(|pmu| pmu.angle.to_radians())
+            .collect();
+        simulator
+    }
+
+    pub fn config(&self) -> &ConfigurationFrame1and2_2011 {
+        &self.config
+    }
+
+    pub fn config_frame(&self) -> Vec<u8> {
+        self.config.to_hex()
+    }
+
+    // Time between frames at the current data rate.
+    pub fn period(&self) -> Duration {
+        Duration::from_micros(self.period_us() as u64)
+    }
+
+    fn period_us(&self) -> f64 {
+        if self.config.data_rate > 0 {
+            1_000_000.0 / self.config.data_rate as f64
+        } else {
+            // Negative rates are seconds per frame
+            -(self.config.data_rate as f64) * 1_000_000.0
+        }
+    }
+
+    // Scheduled time of the next frame.
+    pub fn next_time_us(&self) -> i64 {
+        self.start_us + self.elapsed_us()
+    }
+
+    // True once the scenario duration has been played.
+    pub fn is_finished(&self) -> bool {
+        self.scenario
+            .duration
+            .is_some_and(|duration| self.elapsed_us() as f64 >= duration * 1_000_000.0)
+    }
+
+    // Start the scenario again from the first frame.
+    pub fn rewind(&mut self) {
+        self.config = self.base_config.clone();
+        self.segment_start_us = 0;
+        self.segment_frames = 0;
+        self.clock_offset_us = 0;
+        self.phase = 0.0;
+        self.applied.iter_mut().for_each(|applied| *applied = 0);
+        self.held.iter_mut().for_each(|held| *held = None);
+        self.rng = NoiseRng::new(self.scenario.noise.as_ref().map_or(0, |noise| noise.seed));
+    }
+
+    // Skip to the first frame scheduled at or after timestamp_us. Events in
+    // between are applied so the state matches a run that got there normally.
+    pub fn seek(&mut self, timestamp_us: i64) {
+        if timestamp_us < self.next_time_us() {
+            self.rewind();
+        }
+        while self.next_time_us() < timestamp_us && !self.is_finished() {
+            self.next_tick();
+        }
+    }
+
+    // Microseconds since start of the next frame.
+    fn elapsed_us(&self) -> i64 {
+        self.segment_start_us + (self.segment_frames as f64 * self.period_us()).round() as i64
+    }
+
+    // Produce the next frame and advance the schedule.
+    pub fn next_tick(&mut self) -> SimulatorTick {
+        let elapsed_us = self.elapsed_us();
+        let t = elapsed_us as f64 / 1_000_000.0;
+        let dt = self.period_us() / 1_000_000.0;
+
+        let mut config_changed = false;
+        let mut drop = false;
+        let mut corrupt = false;
+        let mut stat = 0u16;
+        let mut freq_offset = 0.0;
+        let mut rocof = 0.0;
+        let mut magnitude_factor = 1.0;
+
+        for (event, applied) in self.scenario.events.iter().zip(self.applied.iter_mut()) {
+            match *event {
+                ScenarioEvent::FrequencyRamp { at, duration, rate } => {
+                    freq_offset += rate * (t - at).clamp(0.0, duration);
+                    if t >= at && t < at + duration {
+                        rocof += rate;
+                    }
+                }
+                ScenarioEvent::PhaseJump { at, degrees } if t >= at && *applied == 0 => {
+                    self.phase += degrees.to_radians();
+                    *applied = 1;
+                }
+                ScenarioEvent::VoltageSag {
+                    at,
+                    duration,
+                    depth,
+                } if t >= at && t < at + duration => {
+                    magnitude_factor *= 1.0 - depth;
+                }
+                ScenarioEvent::DropFrames { at, count } if t >= at && *applied < count => {
+                    drop = true;
+                    *applied += 1;
+                }
+                ScenarioEvent::CorruptCrc { at, count } if t >= at && *applied < count => {
+                    corrupt = true;
+                    *applied += 1;
+                }
+                ScenarioEvent::ConfigChange { at, data_rate } => {
+                    if t >= at && *applied == 0 {
+                        for pmu_config in &mut self.config.pmu_configs {
+                            pmu_config.cfgcnt = pmu_config.cfgcnt.wrapping_add(1);
+                        }
+                        if let Some(data_rate) = data_rate {
+                            self.config.data_rate = data_rate;
+                        }
+                        config_changed = true;
+                        *applied = 1;
+                    } else if *applied == 0 && t >= at - CONFIG_CHANGE_NOTICE_SECS {
+                        stat |= STAT_CONFIG_CHANGE;
+                    }
+                }
+                ScenarioEvent::ClockJump { at, offset_ms } if t >= at && *applied == 0 => {
+                    self.clock_offset_us += offset_ms * 1000;
+                    *applied = 1;
+                }
+                _ => {}
+            }
+        }
+
+        // The reference signal is closed form, so its angle is not accumulated
+        let mut reported_offset = freq_offset;
+        self.signal_angle = 0.0;
+        if let Some(signal) = &self.scenario.signal {
+            let value = signal.at(t);
+            magnitude_factor *= value.magnitude;
+            reported_offset += value.frequency_offset;
+            rocof += value.rocof;
+            self.signal_angle = value.angle;
+        }
+
+        let mut frames = Vec::new();
+        if config_changed {
+            // A new data rate starts a new segment of the schedule
+            self.segment_start_us = elapsed_us;
+            self.segment_frames = 0;
+            frames.push(self.config_frame());
+        }
+        if !drop {
+            let timestamp_us = self.start_us + elapsed_us + self.clock_offset_us;
+            let mut frame = self.data_frame(
+                timestamp_us,
+                elapsed_us,
+                stat,
+                reported_offset,
+                rocof,
+                magnitude_factor,
+            );
+            if corrupt {
+                let len = frame.len();
+                frame[len - 1] ^= 0xFF;
+            }
+            frames.push(frame);
+        }
+
+        // Rotation relative to nominal over one period
+        self.phase = (self.phase + 2.0 * PI * freq_offset * dt).rem_euclid(2.0 * PI);
+        self.segment_frames += 1;
+
+        SimulatorTick {
+            time_us: self.start_us + elapsed_us,
+            frames,
+            config_changed,
+        }
+    }
+
+    fn data_frame(
+        &mut self,
+        timestamp_us: i64,
+        elapsed_us: i64,
+        stat: u16,
+        freq_offset: f64,
+        rocof: f64,
+        magnitude_factor: f64,
+    ) -> Vec<u8> {
+        let soc = timestamp_us.div_euclid(1_000_000) as u32;
+        let fraction = timestamp_us.rem_euclid(1_000_000) as u64;
+        let fracsec = ((fraction * self.config.time_base as u64 + 500_000) / 1_000_000) as u32;
+
+        let mut frame = Vec::with_capacity(self.config.calc_data_frame_size());
+        frame.extend_from_slice(&0xAA01u16.to_be_bytes());
+        frame.extend_from_slice(&(self.config.calc_data_frame_size() as u16).to_be_bytes());
+        frame.extend_from_slice(&self.config.prefix.idcode.to_be_bytes());
+        frame.extend_from_slice(&soc.to_be_bytes());
+        frame.extend_from_slice(&(fracsec & 0x00FF_FFFF).to_be_bytes());
+
+        for i in 0..self.config.pmu_configs.len() {
+            // PMUs reporting slower than the stream repeat their last sample
+            // in between, flagged as modified by the PDC.
+            let sample = self.pmu_rates[i].map(|rate| sample_index(elapsed_us, rate));
+            let held = match (&self.held[i], sample) {
+                (Some((held_sample, block)), Some(sample)) if *held_sample == sample => {
+                    Some(block.clone())
+                }
+                _ => None,
+            };
+            let block = match held {
+                Some(block) => {
+                    frame.extend_from_slice(&(stat | STAT_DATA_MODIFIED).to_be_bytes());
+                    block
+                }
+                None => {
+                    frame.extend_from_slice(&stat.to_be_bytes());
+                    let block = self.pmu_block(i, freq_offset, rocof, magnitude_factor);
+                    if let Some(sample) = sample {
+                        self.held[i] = Some((sample, block.clone()));
+                    }
+                    block
+                }
+            };
+            frame.extend_from_slice(&block);
+        }
+
+        let crc = calculate_crc(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        frame
+    }
+
+    // Measurements of one PMU after its STAT word.
+    fn pmu_block(
+        &mut self,
+        i: usize,
+        freq_offset: f64,
+        rocof: f64,
+        magnitude_factor: f64,
+    ) -> Vec<u8> {
+        let pmu_config = &self.config.pmu_configs[i];
+        let noise = self.scenario.noise.clone().unwrap_or_default();
+        // Raw counts of fixed point values are multiples of this
+        let step = noise.quantization_bits.map_or(1.0, |bits| {
+            (1u32 << 16u8.saturating_sub(bits.max(1))) as f64
+        });
+        let mut block = Vec::new();
+
+        let float_phasors = pmu_config.format.phasor_float;
+        for k in 0..pmu_config.phnmr as usize {
+            let scale = pmu_config
+                .phunit
+                .get(k)
+                .map(|unit| (unit & 0x00FF_FFFF) as f64 * 1e-5)
+                .filter(|scale| *scale > 0.0)
+                .unwrap_or(1.0);
+            let nominal = match self.scenario.magnitude {
+                Some(magnitude) => magnitude,
+                None if float_phasors => 1.0,
+                None => DEFAULT_FIXED_COUNTS * scale,
+            };
+            let mut magnitude = nominal * magnitude_factor;
+            // Three phase sets rotate 120 degrees apart
+            let mut angle = wrap_angle(
+                self.phase + self.signal_angle + self.angle_offsets[i]
+                    - (k % 3) as f64 * 2.0 * PI / 3.0,
+            );
+            match noise.tve_percent {
+                Some(tve) => {
+                    let direction = 2.0 * PI * self.rng.uniform();
+                    let error = magnitude * tve / 100.0;
+                    let re = magnitude * angle.cos() + error * direction.cos();
+                    let im = magnitude * angle.sin() + error * direction.sin();
+                    magnitude = re.hypot(im);
+                    angle = im.atan2(re);
+                }
+                None => {
+                    magnitude *= 1.0 + self.rng.gaussian(noise.magnitude_std);
+                    angle = wrap_angle(angle + self.rng.gaussian(noise.angle_std_deg).to_radians());
+                }
+            }
+
+            match (float_phasors, pmu_config.is_phasor_polar()) {
+                (true, true) => {
+                    block.extend_from_slice(&(magnitude as f32).to_be_bytes());
+                    block.extend_from_slice(&(angle as f32).to_be_bytes());
+                }
+                (true, false) => {
+                    block.extend_from_slice(&((magnitude * angle.cos()) as f32).to_be_bytes());
+                    block.extend_from_slice(&((magnitude * angle.sin()) as f32).to_be_bytes());
+                }
+                (false, true) => {
+                    let counts = quantize(magnitude / scale, step).clamp(0.0, u16::MAX as f64);
+                    block.extend_from_slice(&(counts as u16).to_be_bytes());
+                    block
+                        .extend_from_slice(&to_i16(quantize(angle * 10_000.0, step)).to_be_bytes());
+                }
+                (false, false) => {
+                    let re = quantize(magnitude * angle.cos() / scale, step);
+                    let im = quantize(magnitude * angle.sin() / scale, step);
+                    block.extend_from_slice(&to_i16(re).to_be_bytes());
+                    block.extend_from_slice(&to_i16(im).to_be_bytes());
+                }
+            }
+        }
+
+        let freq_offset = freq_offset + self.rng.gaussian(noise.frequency_std);
+        let rocof = rocof + self.rng.gaussian(noise.rocof_std);
+        if pmu_config.format.freq_float {
+            let nominal = if pmu_config.fnom & 0x0001 != 0 {
+                50.0
+            } else {
+                60.0
+            };
+            block.extend_from_slice(&((nominal + freq_offset) as f32).to_be_bytes());
+            block.extend_from_slice(&(rocof as f32).to_be_bytes());
+        } else {
+            // Deviation from nominal in mHz, ROCOF in hundredths of Hz/s
+            block.extend_from_slice(&to_i16(quantize(freq_offset * 1000.0, step)).to_be_bytes());
+            block.extend_from_slice(&to_i16(quantize(rocof * 100.0, step)).to_be_bytes());
+        }
+
+        for _ in 0..pmu_config.annmr {
+            if pmu_config.format.analog_float {
+                block.extend_from_slice(&0f32.to_be_bytes());
+            } else {
+                block.extend_from_slice(&0i16.to_be_bytes());
+            }
+        }
+        for _ in 0..pmu_config.dgnmr {
+            block.extend_from_slice(&0u16.to_be_bytes());
+        }
+        block
+    }
+}
+
+// Index of the latest sample of a PMU reporting at rate, at elapsed_us.
+fn sample_index(elapsed_us: i64, rate: i16) -> i64 {
+    let period_us = if rate > 0 {
+        1_000_000.0 / rate as f64
+    } else {
+        -(rate as f64) * 1_000_000.0
+    };
+    // Half a microsecond of slack for schedules rounded to whole microseconds
+    ((elapsed_us as f64 + 0.5) / period_us).floor() as i64
+}
+
+fn wrap_angle(angle: f64) -> f64 {
+    let wrapped = (angle + PI).rem_euclid(2.0 * PI) - PI;
+    if wrapped <= -PI {
+        wrapped + 2.0 * PI
+    } else {
+        wrapped
+    }
+}
+
+// Round raw counts to a multiple of step.
+fn quantize(counts: f64, step: f64) -> f64 {
+    (counts / step).round() * step
+}
+
+fn to_i16(value: f64) -> i16 {
+    value.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+}
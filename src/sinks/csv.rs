@@ -0,0 +1,103 @@
+// CSV file sink.
+//
+// Writes every batch to one file, header line first. Batches with the same
+// fields are written in the column order of the first one. When the channel
+// layout changes a new header line is written before the next rows.
+use super::manifest::DurableFile;
+use super::{align_to_schema, same_fields, to_io_error, BatchSink};
+use arrow::csv::WriterBuilder;
+use arrow::datatypes::SchemaRef;
+use arrow::record_batch::RecordBatch;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub struct CsvSink {
+    path: PathBuf,
+    file: Option<DurableFile>,
+    schema: Option<SchemaRef>,
+    rows: usize,
+}
+
+impl CsvSink {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open(path, false)
+    }
+
+    // Continue the file of a previous run after its last durable batch.
+    // The first batch after resuming starts with a header line again.
+    pub fn resume(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open(path, true)
+    }
+
+    fn open(path: impl AsRef<Path>, resume: bool) -> io::Result<Self> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = if resume {
+            DurableFile::resume(path.as_ref())?
+        } else {
+            DurableFile::create(path.as_ref())?
+        };
+        println!("Opened CSV file {}", path.as_ref().display());
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            rows: file.manifest().rows as usize,
+            file: Some(file),
+            schema: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Rows in the file, including those of earlier runs when resumed.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+}
+
+impl BatchSink for CsvSink {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let file = self
+            .file
+            .as_mut()
+            .ok_or_else(|| io::Error::other("CSV sink is closed"))?;
+        let (batch, header) = match &self.schema {
+            Some(current) if same_fields(current, &batch.schema()) => {
+                (align_to_schema(batch, current)?, false)
+            }
+            _ => {
+                self.schema = Some(batch.schema());
+                (batch.clone(), true)
+            }
+        };
+
+        let mut buffer = Vec::new();
+        WriterBuilder::new()
+            .with_header(header)
+            .build(&mut buffer)
+            .write(&batch)
+            .map_err(to_io_error)?;
+        // Durable once this returns
+        file.write_all(&buffer)?;
+        file.commit(1, batch.num_rows() as u64)?;
+        self.rows += batch.num_rows();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        match self.file.take() {
+            Some(mut file) => file.sync(),
+            None => Ok(()),
+        }
+    }
+}
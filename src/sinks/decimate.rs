@@ -0,0 +1,111 @@
+// Decimation of the batches written to a sink.
+//
+// A Decimator keeps the first row of every interval of the timestamps, the
+// intervals being aligned on multiples of their length: at 1 Hz the row at
+// or after the start of each second. Rows are picked rather than averaged,
+// which would not work for angles. It keeps its place from one batch to the
+// next, so a sink sees the same rows however the stream is batched.
+//
+// DecimatedSink puts a decimator in front of any sink, letting each sink of
+// a pipeline have its own rate.
+use super::BatchSink;
+use arrow::array::{Array, TimestampMicrosecondArray, UInt32Array};
+use arrow::compute::take_record_batch;
+use arrow::record_batch::RecordBatch;
+use std::io;
+
+#[derive(Debug, Clone, Default)]
+pub struct Decimator {
+    interval_us: i64,
+    last_interval: Option<i64>,
+}
+
+impl Decimator {
+    // Keep the first row of every interval, 0 keeps all rows.
+    pub fn new(interval_us: i64) -> Self {
+        Decimator {
+            interval_us: interval_us.max(0),
+            last_interval: None,
+        }
+    }
+
+    // Rows at about rate_hz.
+    pub fn from_rate(rate_hz: f64) -> Self {
+        if rate_hz > 0.0 {
+            Decimator::new((1e6 / rate_hz).round() as i64)
+        } else {
+            Decimator::new(0)
+        }
+    }
+
+    pub fn interval_us(&self) -> i64 {
+        self.interval_us
+    }
+
+    // Whether the row at timestamp_us is kept, given in time order.
+    pub fn keep(&mut self, timestamp_us: i64) -> bool {
+        if self.interval_us == 0 {
+            return true;
+        }
+        let interval = timestamp_us.div_euclid(self.interval_us);
+        if self.last_interval == Some(interval) {
+            return false;
+        }
+        self.last_interval = Some(interval);
+        true
+    }
+
+    // The rows of the batch that are kept, None when there are none.
+    pub fn decimate(&mut self, batch: &RecordBatch) -> io::Result<Option<RecordBatch>> {
+        if self.interval_us == 0 {
+            return Ok(Some(batch.clone()).filter(|batch| batch.num_rows() > 0));
+        }
+        let timestamps = batch
+            .column_by_name("timestamp")
+            .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Batch without timestamp column")
+            })?;
+        let rows: Vec<u32> = (0..batch.num_rows())
+            .filter(|&row| timestamps.is_valid(row) && self.keep(timestamps.value(row)))
+            .map(|row| row as u32)
+            .collect();
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        if rows.len() == batch.num_rows() {
+            return Ok(Some(batch.clone()));
+        }
+        take_record_batch(batch, &UInt32Array::from(rows))
+            .map(Some)
+            .map_err(super::to_io_error)
+    }
+}
+
+pub struct DecimatedSink {
+    sink: Box<dyn BatchSink + Send>,
+    decimator: Decimator,
+}
+
+impl DecimatedSink {
+    pub fn new(sink: Box<dyn BatchSink + Send>, decimator: Decimator) -> Self {
+        DecimatedSink { sink, decimator }
+    }
+}
+
+impl BatchSink for DecimatedSink {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        match self.decimator.decimate(batch)? {
+            Some(batch) => self.sink.write_batch(&batch),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.sink.close()
+    }
+}
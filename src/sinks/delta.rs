@@ -0,0 +1,287 @@
+// Delta Lake table sink.
+//
+// Each batch is written as one Parquet data file in the table directory and
+// committed with a new JSON entry in _delta_log, following the Delta transaction
+// protocol (reader version 1, writer version 2). A log entry is written and
+// synced to a temporary file first and then hard linked to its version, so it
+// appears whole or not at all and two writers can never claim the same version;
+// the writer that loses reads the log again and commits its file on top, or
+// fails when the table schema is no longer the one of its batch.
+//
+// Only what an append-only writer needs of the protocol is implemented: no
+// checkpoints are written, the table has no table features (column mapping,
+// deletion vectors, ...), and metaData is only rewritten for a new schema.
+// Readers that need more should go through delta-rs or Spark.
+//
+// Delta has no unsigned integer types, so UInt16 columns (digitals) are stored
+// as integer and the microsecond timestamp as a UTC timestamp.
+use super::{to_io_error, BatchSink};
+use crate::frames::now_micros;
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::basic::Compression;
+use ::parquet::file::properties::WriterProperties;
+use arrow::array::ArrayRef;
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const LOG_DIR: &str = "_delta_log";
+
+// Arrow type stored in the data files and its Delta schema name.
+fn delta_type(data_type: &DataType) -> io::Result<(DataType, &'static str)> {
+    let mapped = match data_type {
+        DataType::Boolean => (DataType::Boolean, "boolean"),
+        DataType::Int8 => (DataType::Int8, "byte"),
+        DataType::Int16 => (DataType::Int16, "short"),
+        DataType::Int32 | DataType::UInt16 => (DataType::Int32, "integer"),
+        DataType::Int64 | DataType::UInt32 => (DataType::Int64, "long"),
+        DataType::Float32 => (DataType::Float32, "float"),
+        DataType::Float64 => (DataType::Float64, "double"),
+        DataType::Utf8 => (DataType::Utf8, "string"),
+        DataType::Timestamp(TimeUnit::Microsecond, _) => (
+            DataType::Timestamp(TimeUnit::Microsecond, Some("+00:00".into())),
+            "timestamp",
+        ),
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("No Delta type for {:?}", other),
+            ))
+        }
+    };
+    Ok(mapped)
+}
+
+// Cast a batch to Delta compatible column types, returning it with its schema string.
+fn to_delta_batch(batch: &RecordBatch) -> io::Result<(RecordBatch, String)> {
+    let mut fields = Vec::new();
+    let mut columns: Vec<ArrayRef> = Vec::new();
+    let mut schema_fields = Vec::new();
+
+    for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+        let (arrow_type, delta_name) = delta_type(field.data_type())?;
+        let column = if &arrow_type == field.data_type() {
+            column.clone()
+        } else {
+            cast(column, &arrow_type).map_err(to_io_error)?
+        };
+        fields.push(Field::new(field.name(), arrow_type, field.is_nullable()));
+        columns.push(column);
+        schema_fields.push(json!({
+            "name": field.name(),
+            "type": delta_name,
+            "nullable": field.is_nullable(),
+            "metadata": {},
+        }));
+    }
+
+    let schema_string = json!({ "type": "struct", "fields": schema_fields }).to_string();
+    let batch =
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).map_err(to_io_error)?;
+    Ok((batch, schema_string))
+}
+
+#[derive(Default)]
+struct LogState {
+    version: Option<i64>,
+    schema_string: Option<String>,
+    table_id: Option<String>,
+}
+
+// Latest committed version and table metadata found in the table log.
+fn read_log_state(log_dir: &Path) -> io::Result<LogState> {
+    let mut versions = Vec::new();
+    for entry in fs::read_dir(log_dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if let Some(version) = name.strip_suffix(".json") {
+            if let Ok(version) = version.parse::<i64>() {
+                versions.push(version);
+            }
+        }
+    }
+    versions.sort_unstable();
+
+    let mut state = LogState {
+        version: versions.last().copied(),
+        ..Default::default()
+    };
+    for version in &versions {
+        let content = fs::read_to_string(log_dir.join(format!("{:020}.json", version)))?;
+        for line in content.lines() {
+            let action: Value = serde_json::from_str(line).map_err(to_io_error)?;
+            if let Some(schema_string) = action["metaData"]["schemaString"].as_str() {
+                state.schema_string = Some(schema_string.to_string());
+            }
+            if let Some(id) = action["metaData"]["id"].as_str() {
+                state.table_id = Some(id.to_string());
+            }
+        }
+    }
+    Ok(state)
+}
+
+fn write_synced(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+pub struct DeltaSink {
+    table_dir: PathBuf,
+    table_id: String,
+    version: Option<i64>,
+    schema_string: Option<String>,
+    properties: WriterProperties,
+}
+
+impl DeltaSink {
+    // Open a table directory, creating it if needed. Existing tables are appended to.
+    pub fn open(table_dir: impl AsRef<Path>) -> io::Result<Self> {
+        let table_dir = table_dir.as_ref().to_path_buf();
+        let log_dir = table_dir.join(LOG_DIR);
+        fs::create_dir_all(&log_dir)?;
+        let state = read_log_state(&log_dir)?;
+        Ok(Self {
+            table_dir,
+            table_id: state
+                .table_id
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            version: state.version,
+            schema_string: state.schema_string,
+            properties: WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build(),
+        })
+    }
+
+    // Last committed table version, None for an empty table.
+    pub fn version(&self) -> Option<i64> {
+        self.version
+    }
+
+    fn write_data_file(&self, batch: &RecordBatch) -> io::Result<(String, u64)> {
+        let name = format!("part-{}.snappy.parquet", uuid::Uuid::new_v4());
+        let path = self.table_dir.join(&name);
+        let file = File::create(&path)?;
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(self.properties.clone()))
+            .map_err(to_io_error)?;
+        writer.write(batch).map_err(to_io_error)?;
+        writer.close().map_err(to_io_error)?;
+        Ok((name, fs::metadata(&path)?.len()))
+    }
+
+    // Write the actions as the next log entry. When another writer took that
+    // version, the log is read again and only the added files are committed on
+    // top of it, as long as the table schema is still schema_string.
+    fn commit(&mut self, mut actions: Vec<Value>, schema_string: &str) -> io::Result<i64> {
+        let log_dir = self.table_dir.join(LOG_DIR);
+        loop {
+            let version = self.version.map_or(0, |v| v + 1);
+            let path = log_dir.join(format!("{:020}.json", version));
+            let temp_path = log_dir.join(format!(".{}.json.tmp", uuid::Uuid::new_v4()));
+            let mut content = String::new();
+            for action in &actions {
+                content.push_str(&action.to_string());
+                content.push('\n');
+            }
+            let claimed = write_synced(&temp_path, content.as_bytes())
+                .and_then(|()| fs::hard_link(&temp_path, &path));
+            let _ = fs::remove_file(&temp_path);
+            match claimed {
+                Ok(()) => {
+                    File::open(&log_dir)?.sync_all()?;
+                    self.version = Some(version);
+                    return Ok(version);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    println!(
+                        "Delta version {} already committed, reading the log",
+                        version
+                    );
+                    // Taken up either way, so the next batch is committed
+                    // against the table as it is now.
+                    let state = read_log_state(&log_dir)?;
+                    self.version = state.version;
+                    self.schema_string = state.schema_string;
+                    if let Some(table_id) = state.table_id {
+                        self.table_id = table_id;
+                    }
+                    if self.schema_string.as_deref() != Some(schema_string) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "Delta table schema differs from the batch at version {}",
+                                self.version.unwrap_or(version)
+                            ),
+                        ));
+                    }
+                    actions.retain(|action| action.get("add").is_some());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl BatchSink for DeltaSink {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+        let (batch, schema_string) = to_delta_batch(batch)?;
+        let (path, size) = self.write_data_file(&batch)?;
+        let now = now_micros() / 1000;
+
+        let mut actions = Vec::new();
+        if self.version.is_none() {
+            actions.push(json!({ "protocol": { "minReaderVersion": 1, "minWriterVersion": 2 } }));
+        }
+        // New table or a changed channel map, the latest metaData defines the schema.
+        if self.schema_string.as_deref() != Some(schema_string.as_str()) {
+            actions.push(json!({
+                "metaData": {
+                    "id": self.table_id,
+                    "format": { "provider": "parquet", "options": {} },
+                    "schemaString": schema_string,
+                    "partitionColumns": [],
+                    "configuration": {},
+                    "createdTime": now,
+                }
+            }));
+        }
+        actions.push(json!({
+            "add": {
+                "path": path,
+                "partitionValues": {},
+                "size": size,
+                "modificationTime": now,
+                "dataChange": true,
+            }
+        }));
+        actions.push(json!({
+            "commitInfo": {
+                "timestamp": now,
+                "operation": "WRITE",
+                "operationParameters": { "mode": "Append" },
+            }
+        }));
+
+        self.commit(actions, &schema_string)?;
+        self.schema_string = Some(schema_string);
+        Ok(())
+    }
+
+    // Every batch is its own committed transaction, nothing is buffered.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,457 @@
+// HDF5 file sink, for research tooling that reads HDF5.
+//
+// Every channel becomes a dataset /<station>/<idcode>/<channel> of float64
+// values as received, with the attributes unit, scale, offset (engineering
+// value = value * scale + offset), kind, component and channel where known.
+// Each <idcode> group also holds a timestamp dataset of int64 microseconds
+// since the Unix epoch, one per row of its channels. Channels appearing
+// after the first rows, e.g. after a configuration change, are NaN before.
+// Decoded STAT flags, complex phasor columns and columns of no station are
+// left out.
+//
+// The file is written by close, in the HDF5 file format (superblock
+// version 2, version 2 object headers, groups with compact links and
+// contiguous datasets) without the HDF5 library; HDF5 1.8 and later read
+// it. Rows are held in memory until then, so this is for exports rather
+// than continuous collection.
+use super::BatchSink;
+use crate::arrow_utils::{
+    META_BRANCH, META_BUS, META_CHANNEL, META_COMPONENT, META_IDCODE, META_KIND, META_MRID,
+    META_OFFSET, META_SCALE, META_STATION, META_UNIT,
+};
+use arrow::array::{Array, Float64Array, TimestampMicrosecondArray};
+use arrow::compute::cast;
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const SIGNATURE: &[u8; 8] = b"\x89HDF\r\n\x1a\n";
+const UNDEFINED: u64 = u64::MAX;
+const SUPERBLOCK_SIZE: usize = 48;
+
+// Object header message types
+const MSG_DATASPACE: u8 = 0x01;
+const MSG_LINK_INFO: u8 = 0x02;
+const MSG_DATATYPE: u8 = 0x03;
+const MSG_FILL_VALUE: u8 = 0x05;
+const MSG_LINK: u8 = 0x06;
+const MSG_LAYOUT: u8 = 0x08;
+const MSG_GROUP_INFO: u8 = 0x0A;
+const MSG_ATTRIBUTE: u8 = 0x0C;
+
+#[derive(Debug, Clone, PartialEq)]
+enum AttributeValue {
+    Text(String),
+    Number(f64),
+}
+
+#[derive(Default)]
+struct Dataset {
+    attributes: Vec<(String, AttributeValue)>,
+    values: Vec<f64>,
+}
+
+#[derive(Default)]
+struct Stream {
+    timestamps: Vec<i64>,
+    channels: BTreeMap<String, Dataset>,
+}
+
+pub struct Hdf5Sink {
+    path: PathBuf,
+    streams: BTreeMap<(String, String), Stream>, // By station and idcode
+    closed: bool,
+}
+
+impl Hdf5Sink {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(Hdf5Sink {
+            path: path.as_ref().to_path_buf(),
+            streams: BTreeMap::new(),
+            closed: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Rows held, over all stations.
+    pub fn rows(&self) -> usize {
+        self.streams.values().map(|s| s.timestamps.len()).sum()
+    }
+
+    // The file contents for the rows held.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut file = FileBuilder::new();
+        let mut stations = Vec::new();
+        let mut by_station: BTreeMap<&str, Vec<(&str, &Stream)>> = BTreeMap::new();
+        for ((station, idcode), stream) in &self.streams {
+            by_station
+                .entry(station)
+                .or_default()
+                .push((idcode, stream));
+        }
+        for (station, streams) in by_station {
+            let mut groups = Vec::new();
+            for (idcode, stream) in streams {
+                let rows = stream.timestamps.len();
+                let mut links = Vec::new();
+                let data: Vec<u8> = stream
+                    .timestamps
+                    .iter()
+                    .flat_map(|t| t.to_le_bytes())
+                    .collect();
+                let attributes = [(
+                    "unit".to_string(),
+                    AttributeValue::Text("microseconds since 1970-01-01 UTC".to_string()),
+                )];
+                links.push((
+                    "timestamp".to_string(),
+                    file.dataset(&int64_type(), rows, &data, &attributes),
+                ));
+                for (name, dataset) in &stream.channels {
+                    let mut values = dataset.values.clone();
+                    values.resize(rows, f64::NAN);
+                    let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+                    links.push((
+                        name.clone(),
+                        file.dataset(&float64_type(), rows, &data, &dataset.attributes),
+                    ));
+                }
+                groups.push((idcode.to_string(), file.group(&links)));
+            }
+            stations.push((station.to_string(), file.group(&groups)));
+        }
+        let root = file.group(&stations);
+        file.finish(root)
+    }
+}
+
+// A name usable as an HDF5 link name.
+fn link_name(name: &str) -> String {
+    match name.replace('/', "_") {
+        name if name.is_empty() || name == "." => format!("_{}", name),
+        name => name,
+    }
+}
+
+impl BatchSink for Hdf5Sink {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        if self.closed {
+            return Err(io::Error::other("HDF5 sink is closed"));
+        }
+        let timestamps = batch
+            .column_by_name("timestamp")
+            .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Batch without timestamp column")
+            })?;
+        let schema = batch.schema();
+        let mut extended = Vec::new();
+        for (field, column) in schema.fields().iter().zip(batch.columns()) {
+            let meta = field.metadata();
+            let (Some(station), Some(idcode)) = (meta.get(META_STATION), meta.get(META_IDCODE))
+            else {
+                continue;
+            };
+            if *field.data_type() == DataType::Boolean {
+                continue;
+            }
+            let Ok(values) = cast(column, &DataType::Float64) else {
+                continue;
+            };
+            let Some(values) = values.as_any().downcast_ref::<Float64Array>() else {
+                continue;
+            };
+            let key = (station.clone(), idcode.clone());
+            let stream = self.streams.entry(key.clone()).or_default();
+            if !extended.contains(&key) {
+                stream.timestamps.extend(timestamps.values().iter());
+                extended.push(key);
+            }
+            let start = stream.timestamps.len() - batch.num_rows();
+            // Channels are named after the station and idcode, already in the path
+            let prefix = format!("{}_{}_", station, idcode);
+            let name = link_name(field.name().strip_prefix(&prefix).unwrap_or(field.name()));
+            let dataset = stream.channels.entry(name).or_insert_with(|| {
+                let mut attributes = Vec::new();
+                for (key, attribute) in [
+                    (META_UNIT, "unit"),
+                    (META_KIND, "kind"),
+                    (META_COMPONENT, "component"),
+                    (META_CHANNEL, "channel"),
+                    (META_BUS, "bus"),
+                    (META_BRANCH, "branch"),
+                    (META_MRID, "mrid"),
+                ] {
+                    if let Some(text) = meta.get(key) {
+                        attributes
+                            .push((attribute.to_string(), AttributeValue::Text(text.clone())));
+                    }
+                }
+                for (key, attribute, default) in
+                    [(META_SCALE, "scale", 1.0), (META_OFFSET, "offset", 0.0)]
+                {
+                    let number = meta
+                        .get(key)
+                        .and_then(|v| v.parse::<f64>().ok())
+                        .unwrap_or(default);
+                    attributes.push((attribute.to_string(), AttributeValue::Number(number)));
+                }
+                Dataset {
+                    attributes,
+                    values: Vec::new(),
+                }
+            });
+            dataset.values.resize(start, f64::NAN);
+            dataset
+                .values
+                .extend((0..values.len()).map(|row| match values.is_valid(row) {
+                    true => values.value(row),
+                    false => f64::NAN,
+                }));
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        fs::write(&self.path, self.to_bytes())?;
+        self.closed = true;
+        println!(
+            "Wrote HDF5 file {}, {} rows",
+            self.path.display(),
+            self.rows()
+        );
+        Ok(())
+    }
+}
+
+// Datatype message bodies
+fn float64_type() -> Vec<u8> {
+    // IEEE little endian: sign at bit 63, 11 bit exponent at 52, bias 1023
+    let mut bytes = vec![0x11, 0x20, 63, 0];
+    bytes.extend_from_slice(&8u32.to_le_bytes());
+    bytes.extend_from_slice(&0u16.to_le_bytes());
+    bytes.extend_from_slice(&64u16.to_le_bytes());
+    bytes.extend_from_slice(&[52, 11, 0, 52]);
+    bytes.extend_from_slice(&1023u32.to_le_bytes());
+    bytes
+}
+
+fn int64_type() -> Vec<u8> {
+    // Signed, little endian
+    let mut bytes = vec![0x10, 0x08, 0, 0];
+    bytes.extend_from_slice(&8u32.to_le_bytes());
+    bytes.extend_from_slice(&0u16.to_le_bytes());
+    bytes.extend_from_slice(&64u16.to_le_bytes());
+    bytes
+}
+
+fn string_type(len: usize) -> Vec<u8> {
+    // Null padded UTF-8
+    let mut bytes = vec![0x13, 0x11, 0, 0];
+    bytes.extend_from_slice(&(len as u32).to_le_bytes());
+    bytes
+}
+
+// Dataspace message bodies, version 2
+fn scalar_space() -> Vec<u8> {
+    vec![2, 0, 0, 0]
+}
+
+fn simple_space(len: usize) -> Vec<u8> {
+    let mut bytes = vec![2, 1, 0, 1];
+    bytes.extend_from_slice(&(len as u64).to_le_bytes());
+    bytes
+}
+
+fn attribute(name: &str, value: &AttributeValue) -> Vec<u8> {
+    let (datatype, data) = match value {
+        AttributeValue::Text(text) => {
+            let mut data = text.as_bytes().to_vec();
+            if data.is_empty() {
+                data.push(0);
+            }
+            (string_type(data.len()), data)
+        }
+        AttributeValue::Number(number) => (float64_type(), number.to_le_bytes().to_vec()),
+    };
+    let space = scalar_space();
+    let mut bytes = vec![3, 0];
+    bytes.extend_from_slice(&(name.len() as u16 + 1).to_le_bytes());
+    bytes.extend_from_slice(&(datatype.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(&(space.len() as u16).to_le_bytes());
+    bytes.push(1); // UTF-8 name
+    bytes.extend_from_slice(name.as_bytes());
+    bytes.push(0);
+    bytes.extend(datatype);
+    bytes.extend(space);
+    bytes.extend(data);
+    bytes
+}
+
+// Lays the file out front to back, children before the groups linking them.
+struct FileBuilder {
+    bytes: Vec<u8>,
+}
+
+impl FileBuilder {
+    fn new() -> Self {
+        FileBuilder {
+            bytes: vec![0; SUPERBLOCK_SIZE],
+        }
+    }
+
+    fn address(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    // A version 2 object header holding the messages, returns its address.
+    fn object(&mut self, messages: &[(u8, Vec<u8>)]) -> u64 {
+        let address = self.address();
+        let size: usize = messages.iter().map(|(_, body)| 4 + body.len()).sum();
+        let mut header = b"OHDR".to_vec();
+        header.push(2); // Version
+        header.push(0x02); // Chunk size in 4 bytes
+        header.extend_from_slice(&(size as u32).to_le_bytes());
+        for (kind, body) in messages {
+            header.push(*kind);
+            header.extend_from_slice(&(body.len() as u16).to_le_bytes());
+            header.push(0); // Flags
+            header.extend_from_slice(body);
+        }
+        let checksum = lookup3(&header);
+        header.extend_from_slice(&checksum.to_le_bytes());
+        self.bytes.extend(header);
+        address
+    }
+
+    fn dataset(
+        &mut self,
+        datatype: &[u8],
+        len: usize,
+        data: &[u8],
+        attributes: &[(String, AttributeValue)],
+    ) -> u64 {
+        let data_address = if data.is_empty() {
+            UNDEFINED
+        } else {
+            self.address()
+        };
+        self.bytes.extend_from_slice(data);
+        let mut layout = vec![3, 1]; // Version 3, contiguous
+        layout.extend_from_slice(&data_address.to_le_bytes());
+        layout.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        let mut messages = vec![
+            (MSG_DATASPACE, simple_space(len)),
+            (MSG_DATATYPE, datatype.to_vec()),
+            // Version 3, allocated late, fill value written if set, none set
+            (MSG_FILL_VALUE, vec![3, 0x0A]),
+            (MSG_LAYOUT, layout),
+        ];
+        for (name, value) in attributes {
+            messages.push((MSG_ATTRIBUTE, attribute(name, value)));
+        }
+        self.object(&messages)
+    }
+
+    // A group with compact storage of its links.
+    fn group(&mut self, links: &[(String, u64)]) -> u64 {
+        let mut link_info = vec![0, 0];
+        link_info.extend_from_slice(&UNDEFINED.to_le_bytes()); // No fractal heap
+        link_info.extend_from_slice(&UNDEFINED.to_le_bytes()); // No name index
+        let mut messages = vec![(MSG_LINK_INFO, link_info), (MSG_GROUP_INFO, vec![0, 0])];
+        for (name, address) in links {
+            let name = name.as_bytes();
+            // Hard link with a UTF-8 name, its length in 1 or 2 bytes
+            let mut link = vec![1];
+            if name.len() <= u8::MAX as usize {
+                link.extend_from_slice(&[0x10, 1, name.len() as u8]);
+            } else {
+                link.extend_from_slice(&[0x11, 1]);
+                link.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            }
+            link.extend_from_slice(name);
+            link.extend_from_slice(&address.to_le_bytes());
+            messages.push((MSG_LINK, link));
+        }
+        self.object(&messages)
+    }
+
+    // Write the superblock pointing at the root group.
+    fn finish(mut self, root: u64) -> Vec<u8> {
+        let end = self.address();
+        let mut superblock = SIGNATURE.to_vec();
+        superblock.extend_from_slice(&[2, 8, 8, 0]); // Version, offset and length sizes, flags
+        superblock.extend_from_slice(&0u64.to_le_bytes()); // Base address
+        superblock.extend_from_slice(&UNDEFINED.to_le_bytes()); // No extension
+        superblock.extend_from_slice(&end.to_le_bytes());
+        superblock.extend_from_slice(&root.to_le_bytes());
+        let checksum = lookup3(&superblock);
+        superblock.extend_from_slice(&checksum.to_le_bytes());
+        self.bytes[..SUPERBLOCK_SIZE].copy_from_slice(&superblock);
+        self.bytes
+    }
+}
+
+// Bob Jenkins' lookup3 hashlittle with an initial value of 0, the checksum
+// of HDF5 metadata.
+pub fn lookup3(key: &[u8]) -> u32 {
+    let init = 0xdead_beef_u32.wrapping_add(key.len() as u32);
+    let (mut a, mut b, mut c) = (init, init, init);
+    let word = |bytes: &[u8]| {
+        let mut word = [0; 4];
+        word[..bytes.len()].copy_from_slice(bytes);
+        u32::from_le_bytes(word)
+    };
+    let mut rest = key;
+    while rest.len() > 12 {
+        a = a.wrapping_add(word(&rest[0..4]));
+        b = b.wrapping_add(word(&rest[4..8]));
+        c = c.wrapping_add(word(&rest[8..12]));
+        // mix
+        a = a.wrapping_sub(c) ^ c.rotate_left(4);
+        c = c.wrapping_add(b);
+        b = b.wrapping_sub(a) ^ a.rotate_left(6);
+        a = a.wrapping_add(c);
+        c = c.wrapping_sub(b) ^ b.rotate_left(8);
+        b = b.wrapping_add(a);
+        a = a.wrapping_sub(c) ^ c.rotate_left(16);
+        c = c.wrapping_add(b);
+        b = b.wrapping_sub(a) ^ a.rotate_left(19);
+        a = a.wrapping_add(c);
+        c = c.wrapping_sub(b) ^ b.rotate_left(4);
+        b = b.wrapping_add(a);
+        rest = &rest[12..];
+    }
+    if rest.is_empty() {
+        return c;
+    }
+    let mut last = [0u8; 12];
+    last[..rest.len()].copy_from_slice(rest);
+    a = a.wrapping_add(word(&last[0..4]));
+    b = b.wrapping_add(word(&last[4..8]));
+    c = c.wrapping_add(word(&last[8..12]));
+    // final
+    c = (c ^ b).wrapping_sub(b.rotate_left(14));
+    a = (a ^ c).wrapping_sub(c.rotate_left(11));
+    b = (b ^ a).wrapping_sub(a.rotate_left(25));
+    c = (c ^ b).wrapping_sub(b.rotate_left(16));
+    a = (a ^ c).wrapping_sub(c.rotate_left(4));
+    b = (b ^ a).wrapping_sub(a.rotate_left(14));
+    (c ^ b).wrapping_sub(b.rotate_left(24))
+}
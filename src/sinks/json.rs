@@ -0,0 +1,81 @@
+// Line delimited JSON file sink, one object per row keyed by column name.
+use super::manifest::DurableFile;
+use super::{to_io_error, BatchSink};
+use arrow::json::LineDelimitedWriter;
+use arrow::record_batch::RecordBatch;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub struct JsonSink {
+    path: PathBuf,
+    file: Option<DurableFile>,
+    rows: usize,
+}
+
+impl JsonSink {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open(path, false)
+    }
+
+    // Continue the file of a previous run after its last durable batch.
+    pub fn resume(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open(path, true)
+    }
+
+    fn open(path: impl AsRef<Path>, resume: bool) -> io::Result<Self> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = if resume {
+            DurableFile::resume(path.as_ref())?
+        } else {
+            DurableFile::create(path.as_ref())?
+        };
+        println!("Opened JSON file {}", path.as_ref().display());
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            rows: file.manifest().rows as usize,
+            file: Some(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Rows in the file, including those of earlier runs when resumed.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+}
+
+impl BatchSink for JsonSink {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let file = self
+            .file
+            .as_mut()
+            .ok_or_else(|| io::Error::other("JSON sink is closed"))?;
+        let mut writer = LineDelimitedWriter::new(&mut *file);
+        writer.write(batch).map_err(to_io_error)?;
+        writer.finish().map_err(to_io_error)?;
+        // Durable once this returns
+        file.commit(1, batch.num_rows() as u64)?;
+        self.rows += batch.num_rows();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        match self.file.take() {
+            Some(mut file) => file.sync(),
+            None => Ok(()),
+        }
+    }
+}
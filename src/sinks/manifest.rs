@@ -0,0 +1,164 @@
+// Sidecar manifests recording what a sink has made durable.
+//
+// A crash can stop a sink halfway through a batch. The data file may then
+// end in a torn row or block, and a parquet file has no footer at all. The
+// manifest next to the data records the state after the last batch that was
+// synced to disk: the byte offset it ends at, the batches and rows up to it,
+// and for parquet the files that were completed. It is replaced in one step
+// (write, sync, rename), so it always describes a consistent state.
+//
+// On restart a sink resumes from the manifest: appending sinks cut the file
+// back to the recorded offset, parquet drops the unfinished .tmp file. Every
+// batch counted in the manifest is in the data exactly once, and nothing
+// after it is.
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub offset: u64,  // Bytes of the data file that are durable
+    pub batches: u64, // Batches durable so far
+    pub rows: u64,
+    #[serde(default)]
+    pub files: Vec<String>, // Completed files, parquet only
+}
+
+impl Manifest {
+    // Manifest of a data file, "dir/_<name>.manifest.json". The underscore
+    // keeps dataset readers from taking it for data.
+    pub fn sidecar(data: impl AsRef<Path>) -> PathBuf {
+        let data = data.as_ref();
+        let name = data
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        data.with_file_name(format!("_{}.manifest.json", name))
+    }
+
+    // None when there is no manifest yet.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Replace the manifest in one step.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_string(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let temporary = path.with_extension("tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+}
+
+// An append-only data file whose durable prefix is recorded in its manifest.
+pub struct DurableFile {
+    file: BufWriter<File>,
+    manifest_path: PathBuf,
+    manifest: Manifest,
+    offset: u64, // Bytes written, durable or not
+}
+
+impl DurableFile {
+    // Start a new, empty file.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::create(path.as_ref())?;
+        let manifest_path = Manifest::sidecar(&path);
+        let manifest = Manifest::default();
+        manifest.save(&manifest_path)?;
+        Ok(Self {
+            file: BufWriter::new(file),
+            manifest_path,
+            manifest,
+            offset: 0,
+        })
+    }
+
+    // Continue a file after its last durable batch, dropping anything written
+    // after it. A file without a manifest can't be trusted and starts over.
+    pub fn resume(path: impl AsRef<Path>) -> io::Result<Self> {
+        let manifest_path = Manifest::sidecar(&path);
+        let Some(manifest) = Manifest::load(&manifest_path)? else {
+            return Self::create(path);
+        };
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path.as_ref())?;
+        let len = file.metadata()?.len();
+        if len < manifest.offset {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is shorter than its manifest, {} of {} bytes",
+                    path.as_ref().display(),
+                    len,
+                    manifest.offset
+                ),
+            ));
+        }
+        if len > manifest.offset {
+            println!(
+                "Dropping {} bytes after the last durable batch of {}",
+                len - manifest.offset,
+                path.as_ref().display()
+            );
+            file.set_len(manifest.offset)?;
+        }
+        file.seek(SeekFrom::Start(manifest.offset))?;
+        Ok(Self {
+            file: BufWriter::new(file),
+            manifest_path,
+            offset: manifest.offset,
+            manifest,
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    // Sync everything written so far and record it as durable, along with the
+    // batches and rows it completes.
+    pub fn commit(&mut self, batches: u64, rows: u64) -> io::Result<()> {
+        self.sync()?;
+        self.manifest.offset = self.offset;
+        self.manifest.batches += batches;
+        self.manifest.rows += rows;
+        self.manifest.save(&self.manifest_path)
+    }
+
+    // Sync without recording, for trailers that a resume rewrites anyway.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()
+    }
+}
+
+impl Write for DurableFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,64 @@
+// Destinations for the RecordBatches produced by the accumulator.
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use std::io;
+
+pub mod csv;
+pub mod decimate;
+#[cfg(feature = "delta")]
+pub mod delta;
+#[cfg(feature = "hdf5")]
+pub mod hdf5;
+pub mod json;
+pub mod manifest;
+pub mod naming;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(any(feature = "redis", feature = "nats"))]
+mod pmu_json;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod spool;
+pub mod sqlite;
+#[cfg(feature = "timescale")]
+pub mod timescale;
+
+pub trait BatchSink {
+    // Write one batch. Sinks may buffer internally until flush or close.
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+    // Finish any open files. The sink should not be written to afterwards.
+    fn close(&mut self) -> io::Result<()>;
+}
+
+pub(crate) fn to_io_error<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+// Schemas built from two separate channel maps hold the same fields in a different
+// order, so compare them by name and type only.
+pub(crate) fn same_fields(a: &Schema, b: &Schema) -> bool {
+    a.fields().len() == b.fields().len()
+        && a.fields().iter().all(|field| {
+            b.field_with_name(field.name())
+                .map(|other| other.data_type() == field.data_type())
+                .unwrap_or(false)
+        })
+}
+
+// Reorder the columns of a batch to match a schema with the same fields.
+pub(crate) fn align_to_schema(batch: &RecordBatch, schema: &SchemaRef) -> io::Result<RecordBatch> {
+    let mut columns = Vec::with_capacity(schema.fields().len());
+    for field in schema.fields() {
+        let column = batch.column_by_name(field.name()).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Missing column {}", field.name()),
+            )
+        })?;
+        columns.push(column.clone());
+    }
+    RecordBatch::try_new(schema.clone(), columns).map_err(to_io_error)
+}
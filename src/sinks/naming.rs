@@ -0,0 +1,270 @@
+// Column names of the batches as a sink wants them.
+//
+// Channel columns are named canonically, <station>_<idcode>_<channel> with
+// the component or flag appended, e.g. "Station A_7734_VA_X", which not every
+// sink takes as an identifier. A NameStyle maps every column name to one:
+//
+// - canonical: as is;
+// - snake_case: lower case, runs of anything but letters and digits as one
+//   underscore, e.g. station_a_7734_va_x;
+// - dotted: the station, idcode and rest of the name, each in snake_case,
+//   joined by dots, e.g. station_a.7734.va_x;
+// - prometheus: every character a Prometheus name does not take replaced by
+//   an underscore, case kept, e.g. Station_A_7734_VA_X.
+// A name starting with a digit gets an underscore in front, except dotted.
+//
+// Distinct names may map to the same one, so a NameMapping records the name
+// given to every column: the first column gets the mapped name, later ones a
+// _2, _3, ... suffix. The mapping is saved next to the sink's data whenever
+// it grows and loaded again on restart, so a column keeps its name and every
+// name maps back to the canonical one.
+//
+// RenamedSink puts a mapping in front of any sink.
+use super::{to_io_error, BatchSink};
+use crate::arrow_utils::{META_IDCODE, META_STATION};
+use arrow::datatypes::{Field, Schema};
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameStyle {
+    #[default]
+    Canonical,
+    SnakeCase,
+    Dotted,
+    Prometheus,
+}
+
+impl NameStyle {
+    // Name of a column in this style, before collisions are resolved.
+    pub fn apply(&self, field: &Field) -> String {
+        let name = field.name();
+        match self {
+            NameStyle::Canonical => name.clone(),
+            NameStyle::SnakeCase => leading_digit(snake_case(name)),
+            NameStyle::Dotted => {
+                let meta = field.metadata();
+                let parts = meta.get(META_STATION).zip(meta.get(META_IDCODE));
+                let rest = parts.and_then(|(station, idcode)| {
+                    let rest = name.strip_prefix(&format!("{}_{}_", station, idcode))?;
+                    Some((station, idcode, rest))
+                });
+                match rest {
+                    Some((station, idcode, rest)) => {
+                        format!("{}.{}.{}", snake_case(station), idcode, snake_case(rest))
+                    }
+                    None => leading_digit(snake_case(name)),
+                }
+            }
+            NameStyle::Prometheus => leading_digit(
+                name.chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            snake.push(c.to_ascii_lowercase());
+        } else if !snake.is_empty() && !snake.ends_with('_') {
+            snake.push('_');
+        }
+    }
+    snake.trim_end_matches('_').to_string()
+}
+
+fn leading_digit(name: String) -> String {
+    match name.chars().next() {
+        Some(c) if !c.is_ascii_digit() => name,
+        _ => format!("_{}", name),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnName {
+    pub canonical: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MappingFile {
+    style: NameStyle,
+    columns: Vec<ColumnName>,
+}
+
+// Names given to the columns of a sink, in the order they were given.
+#[derive(Debug, Clone, Default)]
+pub struct NameMapping {
+    style: NameStyle,
+    columns: Vec<ColumnName>,
+    by_canonical: HashMap<String, usize>,
+    by_name: HashMap<String, usize>,
+}
+
+impl NameMapping {
+    pub fn new(style: NameStyle) -> Self {
+        NameMapping {
+            style,
+            ..Default::default()
+        }
+    }
+
+    // The mapping saved at path, or a new one when there is none. A mapping
+    // saved for another style is an error, the names would change.
+    pub fn load(path: impl AsRef<Path>, style: NameStyle) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file: MappingFile = match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(NameMapping::new(style)),
+            Err(e) => return Err(e),
+        };
+        if file.style != style {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} maps the column names to {:?}, not {:?}",
+                    path.display(),
+                    file.style,
+                    style
+                ),
+            ));
+        }
+        let mut mapping = NameMapping::new(style);
+        for column in file.columns {
+            mapping.insert(column.canonical, column.name);
+        }
+        Ok(mapping)
+    }
+
+    // Replace the saved mapping in one step.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let file = MappingFile {
+            style: self.style,
+            columns: self.columns.clone(),
+        };
+        let json = serde_json::to_string_pretty(&file)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, json)?;
+        fs::rename(&temporary, path)
+    }
+
+    pub fn style(&self) -> NameStyle {
+        self.style
+    }
+
+    pub fn columns(&self) -> &[ColumnName] {
+        &self.columns
+    }
+
+    pub fn name(&self, canonical: &str) -> Option<&str> {
+        let index = *self.by_canonical.get(canonical)?;
+        Some(&self.columns[index].name)
+    }
+
+    pub fn canonical(&self, name: &str) -> Option<&str> {
+        let index = *self.by_name.get(name)?;
+        Some(&self.columns[index].canonical)
+    }
+
+    // Name of a column, given one first when it has none yet.
+    pub fn name_field(&mut self, field: &Field) -> &str {
+        let index = match self.by_canonical.get(field.name()) {
+            Some(index) => *index,
+            None => {
+                let styled = self.style.apply(field);
+                let mut name = styled.clone();
+                let mut n = 1;
+                while self.by_name.contains_key(&name) {
+                    n += 1;
+                    name = format!("{}_{}", styled, n);
+                }
+                self.insert(field.name().clone(), name)
+            }
+        };
+        &self.columns[index].name
+    }
+
+    fn insert(&mut self, canonical: String, name: String) -> usize {
+        let index = self.columns.len();
+        self.by_canonical.insert(canonical.clone(), index);
+        self.by_name.insert(name.clone(), index);
+        self.columns.push(ColumnName { canonical, name });
+        index
+    }
+
+    // The batch with its columns renamed, metadata kept.
+    pub fn rename(&mut self, batch: &RecordBatch) -> io::Result<RecordBatch> {
+        let schema = batch.schema();
+        let fields: Vec<Field> = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                let name = self.name_field(field).to_string();
+                field.as_ref().clone().with_name(name)
+            })
+            .collect();
+        let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+        RecordBatch::try_new(Arc::new(schema), batch.columns().to_vec()).map_err(to_io_error)
+    }
+}
+
+pub struct RenamedSink {
+    sink: Box<dyn BatchSink + Send>,
+    mapping: NameMapping,
+    path: PathBuf, // Where the mapping is saved
+}
+
+impl RenamedSink {
+    // Rename the columns of the batches written to sink in style, with the
+    // mapping saved at path, e.g. dir/_<idcode>.columns.json.
+    pub fn new(
+        sink: Box<dyn BatchSink + Send>,
+        style: NameStyle,
+        path: impl Into<PathBuf>,
+    ) -> io::Result<Self> {
+        let path = path.into();
+        let mapping = NameMapping::load(&path, style)?;
+        Ok(RenamedSink {
+            sink,
+            mapping,
+            path,
+        })
+    }
+
+    pub fn mapping(&self) -> &NameMapping {
+        &self.mapping
+    }
+}
+
+impl BatchSink for RenamedSink {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let known = self.mapping.columns().len();
+        let renamed = self.mapping.rename(batch)?;
+        // Saved before the data using the new names
+        if self.mapping.columns().len() > known {
+            self.mapping.save(&self.path)?;
+        }
+        self.sink.write_batch(&renamed)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.sink.close()
+    }
+}
@@ -0,0 +1,453 @@
+// NATS publisher, with JetStream persistence when configured.
+//
+// Every row is split by PMU and each PMU's part is published as one JSON
+// payload (see pmu_json) on the subject <prefix>.<station>.<IDCODE>, the
+// station with everything but letters, digits, - and _ replaced by _. So
+// pmu.*.7734 follows one PMU and pmu.SUB_A.> one station.
+//
+// Without JetStream messages are fire and forget; a PING after every batch
+// makes sure the server took them. With a JetStreamConfig the stream is
+// created (or updated) for <prefix>.> on connecting, every message is
+// published with a reply subject and the batch succeeds once all were
+// acknowledged as stored.
+//
+// Plain TCP only, the address is host:port or nats://host:port.
+use super::pmu_json::pmu_payloads;
+use super::BatchSink;
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_PORT: u16 = 4222;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// JetStream error code of a stream name already in use.
+const STREAM_NAME_IN_USE: u64 = 10058;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Storage {
+    #[default]
+    File,
+    Memory,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JetStreamConfig {
+    pub stream: String,
+    #[serde(default)]
+    pub storage: Storage,
+    #[serde(default)]
+    pub max_age_secs: Option<u64>, // Messages older than this are removed
+    #[serde(default)]
+    pub max_msgs: Option<i64>,
+    #[serde(default)]
+    pub max_bytes: Option<i64>,
+    #[serde(default = "default_replicas")]
+    pub replicas: usize,
+}
+
+fn default_replicas() -> usize {
+    1
+}
+
+impl JetStreamConfig {
+    pub fn new(stream: &str) -> Self {
+        JetStreamConfig {
+            stream: stream.to_string(),
+            storage: Storage::File,
+            max_age_secs: None,
+            max_msgs: None,
+            max_bytes: None,
+            replicas: default_replicas(),
+        }
+    }
+
+    pub fn with_storage(mut self, storage: Storage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age_secs = Some(max_age.as_secs());
+        self
+    }
+
+    pub fn with_max_msgs(mut self, max_msgs: i64) -> Self {
+        self.max_msgs = Some(max_msgs);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: i64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn with_replicas(mut self, replicas: usize) -> Self {
+        self.replicas = replicas.max(1);
+        self
+    }
+
+    // Stream configuration of the JetStream API, limits of -1 are unlimited.
+    fn to_json(&self, subjects: &str) -> Value {
+        json!({
+            "name": self.stream,
+            "subjects": [subjects],
+            "retention": "limits",
+            "storage": self.storage,
+            "max_age": self.max_age_secs.map_or(0, |secs| secs * 1_000_000_000),
+            "max_msgs": self.max_msgs.unwrap_or(-1),
+            "max_bytes": self.max_bytes.unwrap_or(-1),
+            "num_replicas": self.replicas,
+        })
+    }
+}
+
+enum Auth {
+    None,
+    Token(String),
+    User(String, String),
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    inbox: String, // Reply subjects are <inbox>.<n>
+    next_reply: u64,
+}
+
+pub struct NatsPublisher {
+    address: String, // host:port
+    prefix: String,
+    auth: Auth,
+    jetstream: Option<JetStreamConfig>,
+    connection: Option<Connection>,
+    messages: usize,
+}
+
+impl NatsPublisher {
+    // The connection is opened with the first batch.
+    pub fn new(address: &str) -> io::Result<Self> {
+        let host = address.strip_prefix("nats://").unwrap_or(address);
+        if host.is_empty() || host.contains('/') || host.contains('@') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Expected host:port or nats://host:port, got {}", address),
+            ));
+        }
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:{}", host, DEFAULT_PORT)
+        };
+        Ok(NatsPublisher {
+            address,
+            prefix: "pmu".to_string(),
+            auth: Auth::None,
+            jetstream: None,
+            connection: None,
+            messages: 0,
+        })
+    }
+
+    // Subjects are <prefix>.<station>.<IDCODE>, pmu by default.
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.auth = Auth::Token(token.to_string());
+        self
+    }
+
+    pub fn with_user(mut self, user: &str, password: &str) -> Self {
+        self.auth = Auth::User(user.to_string(), password.to_string());
+        self
+    }
+
+    pub fn with_jetstream(mut self, jetstream: JetStreamConfig) -> Self {
+        self.jetstream = Some(jetstream);
+        self
+    }
+
+    // Messages published (and stored, with JetStream) since the publisher
+    // was opened.
+    pub fn messages(&self) -> usize {
+        self.messages
+    }
+
+    pub fn subject(&self, station: &str, idcode: u16) -> String {
+        let station: String = station
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let station = if station.is_empty() { "_" } else { &station };
+        format!("{}.{}.{}", self.prefix, station, idcode)
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        let stream = TcpStream::connect(&self.address)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let mut connection = Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            inbox: format!("_INBOX.pmu{}x{:x}", std::process::id(), nanos),
+            next_reply: 0,
+        };
+
+        // The server starts with INFO
+        let mut line = String::new();
+        connection.reader.read_line(&mut line)?;
+        if !line.starts_with("INFO ") {
+            return Err(io::Error::other(format!(
+                "Expected INFO from NATS, got {:?}",
+                line.trim_end()
+            )));
+        }
+        let mut options = json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "pmu",
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+            "protocol": 1,
+        });
+        match &self.auth {
+            Auth::None => {}
+            Auth::Token(token) => options["auth_token"] = json!(token),
+            Auth::User(user, password) => {
+                options["user"] = json!(user);
+                options["pass"] = json!(password);
+            }
+        }
+        let mut setup = format!("CONNECT {}\r\n", options);
+        if self.jetstream.is_some() {
+            setup.push_str(&format!("SUB {}.* 1\r\n", connection.inbox));
+        }
+        connection.writer.write_all(setup.as_bytes())?;
+        ping(&mut connection)?;
+
+        if let Some(jetstream) = &self.jetstream {
+            let config = jetstream.to_json(&format!("{}.>", self.prefix)).to_string();
+            let create = format!("$JS.API.STREAM.CREATE.{}", jetstream.stream);
+            let mut reply = request(&mut connection, &create, &config)?;
+            if reply["error"]["err_code"].as_u64() == Some(STREAM_NAME_IN_USE) {
+                let update = format!("$JS.API.STREAM.UPDATE.{}", jetstream.stream);
+                reply = request(&mut connection, &update, &config)?;
+            }
+            if let Some(error) = api_error(&reply) {
+                return Err(error);
+            }
+        }
+        println!("Connected to NATS at {}", self.address);
+        Ok(connection)
+    }
+
+    fn publish(&mut self, batch: &RecordBatch) -> io::Result<usize> {
+        let payloads = pmu_payloads(batch)?;
+        if payloads.is_empty() {
+            return Ok(0);
+        }
+        if self.connection.is_none() {
+            self.connection = Some(self.connect()?);
+        }
+        let subjects: Vec<String> = payloads
+            .iter()
+            .map(|pmu| self.subject(&pmu.station, pmu.idcode))
+            .collect();
+        let acknowledged = self.jetstream.is_some();
+        let connection = self.connection.as_mut().unwrap();
+
+        let mut buffer = Vec::new();
+        let first_reply = connection.next_reply;
+        for (pmu, subject) in payloads.iter().zip(&subjects) {
+            let payload = pmu.payload.to_string();
+            let reply = if acknowledged {
+                connection.next_reply += 1;
+                format!(" {}.{}", connection.inbox, connection.next_reply)
+            } else {
+                String::new()
+            };
+            buffer.extend_from_slice(
+                format!("PUB {}{} {}\r\n", subject, reply, payload.len()).as_bytes(),
+            );
+            buffer.extend_from_slice(payload.as_bytes());
+            buffer.extend_from_slice(b"\r\n");
+        }
+        connection.writer.write_all(&buffer)?;
+
+        if !acknowledged {
+            ping(connection)?;
+            return Ok(payloads.len());
+        }
+        // Acknowledgements, the first error is returned after all arrived
+        let mut error = None;
+        let mut pending = payloads.len();
+        while pending > 0 {
+            let (subject, ack) = next_message(connection)?;
+            let number = subject
+                .rsplit('.')
+                .next()
+                .and_then(|n| n.parse::<u64>().ok())
+                .unwrap_or(0);
+            if number <= first_reply || number > connection.next_reply {
+                continue; // Late reply of an earlier batch
+            }
+            pending -= 1;
+            if let Some(e) = serde_json::from_slice::<Value>(&ack)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+                .and_then(|ack| api_error(&ack).map_or(Ok(()), Err))
+                .err()
+            {
+                error.get_or_insert(e);
+            }
+        }
+        error.map_or(Ok(payloads.len()), Err)
+    }
+}
+
+impl BatchSink for NatsPublisher {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        match self.publish(batch) {
+            Ok(messages) => {
+                self.messages += messages;
+                Ok(())
+            }
+            Err(e) => {
+                // A failed connection is opened again, a rejected message says so
+                if e.kind() != io::ErrorKind::InvalidData {
+                    self.connection = None;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.connection.as_mut() {
+            Some(connection) => connection.writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.connection = None;
+        Ok(())
+    }
+}
+
+// Error of a JetStream API reply or publish acknowledgement, as InvalidData.
+fn api_error(reply: &Value) -> Option<io::Error> {
+    let error = reply.get("error")?;
+    Some(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "JetStream: {} ({})",
+            error["description"].as_str().unwrap_or("error"),
+            error["code"]
+        ),
+    ))
+}
+
+// Request on the JetStream API and its JSON reply.
+fn request(connection: &mut Connection, subject: &str, payload: &str) -> io::Result<Value> {
+    connection.next_reply += 1;
+    let reply = format!("{}.{}", connection.inbox, connection.next_reply);
+    connection.writer.write_all(
+        format!(
+            "PUB {} {} {}\r\n{}\r\n",
+            subject,
+            reply,
+            payload.len(),
+            payload
+        )
+        .as_bytes(),
+    )?;
+    loop {
+        let (subject, message) = next_message(connection)?;
+        if subject == reply {
+            return serde_json::from_slice(&message)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+    }
+}
+
+// Wait for the PONG to a PING, so errors of everything sent before show.
+fn ping(connection: &mut Connection) -> io::Result<()> {
+    connection.writer.write_all(b"PING\r\n")?;
+    loop {
+        match read_operation(connection)? {
+            Operation::Pong => return Ok(()),
+            Operation::Message(..) => {}
+        }
+    }
+}
+
+fn next_message(connection: &mut Connection) -> io::Result<(String, Vec<u8>)> {
+    loop {
+        if let Operation::Message(subject, payload) = read_operation(connection)? {
+            return Ok((subject, payload));
+        }
+    }
+}
+
+enum Operation {
+    Pong,
+    Message(String, Vec<u8>),
+}
+
+// Read server operations up to a PONG or MSG, answering PINGs on the way.
+fn read_operation(connection: &mut Connection) -> io::Result<Operation> {
+    loop {
+        let mut line = String::new();
+        if connection.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "NATS closed the connection",
+            ));
+        }
+        let line = line.trim_end();
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("PING") => connection.writer.write_all(b"PONG\r\n")?,
+            Some("PONG") => return Ok(Operation::Pong),
+            Some("+OK") | Some("INFO") => {}
+            Some("-ERR") => return Err(io::Error::other(format!("NATS: {}", &line[5..]))),
+            Some("MSG") => {
+                // MSG <subject> <sid> [reply-to] <bytes>
+                let fields: Vec<&str> = parts.collect();
+                let subject = fields.first().copied().unwrap_or_default().to_string();
+                let length: usize = fields
+                    .last()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| io::Error::other(format!("Invalid NATS message {:?}", line)))?;
+                let mut payload = vec![0; length + 2];
+                connection.reader.read_exact(&mut payload)?;
+                payload.truncate(length);
+                return Ok(Operation::Message(subject, payload));
+            }
+            _ => {
+                return Err(io::Error::other(format!(
+                    "Unexpected NATS operation {:?}",
+                    line
+                )))
+            }
+        }
+    }
+}
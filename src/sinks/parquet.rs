@@ -0,0 +1,640 @@
+// Parquet file sink.
+//
+// Writes batches to numbered files in a directory. A stream's schema can change
+// mid-capture when the PMU sends a new configuration, in that case the current
+// file is closed and a new one started with the new schema. Both files record
+// the transition in their key-value metadata so the archive can be stitched
+// back together later.
+//
+// A file is written as <name>.parquet.tmp and renamed once its footer is on
+// disk, so a crash never leaves a torn file under a final name. The manifest
+// next to the files counts the batches and rows of completed files. Opening
+// the sink again removes leftover .tmp files and resumes the numbering.
+//
+// ParquetOptions tune the files for the queries run on archives: a time range
+// and a few channels or stations. Smaller row groups and pages make the
+// min/max statistics of the timestamp column skip more of a file, bloom
+// filters on the station and idcode columns of long format batches skip the
+// row groups of other PMUs.
+use super::manifest::Manifest;
+use super::{align_to_schema, same_fields, to_io_error, BatchSink};
+use crate::frames::ConfigurationFrame1and2_2011;
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::file::properties::{EnabledStatistics, WriterProperties};
+use ::parquet::format::KeyValue;
+use ::parquet::schema::types::ColumnPath;
+use arrow::array::{BooleanArray, TimestampMicrosecondArray};
+use arrow::compute::filter_record_batch;
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use chrono::{DateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const PREVIOUS_FILE_KEY: &str = "pmu.previous_file";
+pub const NEXT_FILE_KEY: &str = "pmu.next_file";
+pub const CHANGE_REASON_KEY: &str = "pmu.schema_change_reason";
+pub const COLUMNS_ADDED_KEY: &str = "pmu.columns_added";
+pub const COLUMNS_REMOVED_KEY: &str = "pmu.columns_removed";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatisticsLevel {
+    None,
+    Chunk, // Per row group
+    #[default]
+    Page,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParquetOptions {
+    // Rows per row group, the writer's default (1M) when not set
+    #[serde(default)]
+    pub row_group_rows: Option<usize>,
+    // Target size of a data page, the writer's default (1 MiB) when not set
+    #[serde(default)]
+    pub data_page_bytes: Option<usize>,
+    // Dictionary encoding of all columns, and per column overrides of it
+    #[serde(default = "default_dictionary")]
+    pub dictionary: bool,
+    #[serde(default)]
+    pub dictionary_columns: BTreeMap<String, bool>,
+    #[serde(default)]
+    pub statistics: StatisticsLevel,
+    // Columns with a bloom filter, e.g. ["station", "idcode"]
+    #[serde(default)]
+    pub bloom_filter_columns: Vec<String>,
+    // False positive probability and expected distinct values of the filters
+    #[serde(default)]
+    pub bloom_filter_fpp: Option<f64>,
+    #[serde(default)]
+    pub bloom_filter_ndv: Option<u64>,
+}
+
+fn default_dictionary() -> bool {
+    true
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        ParquetOptions {
+            row_group_rows: None,
+            data_page_bytes: None,
+            dictionary: default_dictionary(),
+            dictionary_columns: BTreeMap::new(),
+            statistics: StatisticsLevel::default(),
+            bloom_filter_columns: Vec::new(),
+            bloom_filter_fpp: None,
+            bloom_filter_ndv: None,
+        }
+    }
+}
+
+impl ParquetOptions {
+    pub fn with_row_group_rows(mut self, rows: usize) -> Self {
+        self.row_group_rows = Some(rows);
+        self
+    }
+
+    pub fn with_data_page_bytes(mut self, bytes: usize) -> Self {
+        self.data_page_bytes = Some(bytes);
+        self
+    }
+
+    pub fn with_column_dictionary(mut self, column: &str, enabled: bool) -> Self {
+        self.dictionary_columns.insert(column.to_string(), enabled);
+        self
+    }
+
+    pub fn with_statistics(mut self, statistics: StatisticsLevel) -> Self {
+        self.statistics = statistics;
+        self
+    }
+
+    pub fn with_bloom_filters(mut self, columns: &[&str]) -> Self {
+        self.bloom_filter_columns = columns.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    // Problems with the options, empty when they can be used.
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.row_group_rows == Some(0) {
+            problems.push("row_group_rows must be positive".to_string());
+        }
+        if self.data_page_bytes == Some(0) {
+            problems.push("data_page_bytes must be positive".to_string());
+        }
+        if let Some(fpp) = self.bloom_filter_fpp {
+            if !(fpp > 0.0 && fpp < 1.0) {
+                problems.push(format!("bloom_filter_fpp {} is not between 0 and 1", fpp));
+            }
+        }
+        if self.bloom_filter_ndv == Some(0) {
+            problems.push("bloom_filter_ndv must be positive".to_string());
+        }
+        problems
+    }
+
+    // Options for columns not in a batch's schema are ignored by the writer.
+    pub fn properties(&self) -> WriterProperties {
+        let mut builder = WriterProperties::builder()
+            .set_dictionary_enabled(self.dictionary)
+            .set_statistics_enabled(match self.statistics {
+                StatisticsLevel::None => EnabledStatistics::None,
+                StatisticsLevel::Chunk => EnabledStatistics::Chunk,
+                StatisticsLevel::Page => EnabledStatistics::Page,
+            });
+        if let Some(rows) = self.row_group_rows {
+            builder = builder.set_max_row_group_size(rows);
+        }
+        if let Some(bytes) = self.data_page_bytes {
+            builder = builder.set_data_page_size_limit(bytes);
+        }
+        for (column, enabled) in &self.dictionary_columns {
+            builder = builder.set_column_dictionary_enabled(column_path(column), *enabled);
+        }
+        for column in &self.bloom_filter_columns {
+            let path = column_path(column);
+            builder = builder.set_column_bloom_filter_enabled(path.clone(), true);
+            if let Some(fpp) = self.bloom_filter_fpp {
+                builder = builder.set_column_bloom_filter_fpp(path.clone(), fpp);
+            }
+            if let Some(ndv) = self.bloom_filter_ndv {
+                builder = builder.set_column_bloom_filter_ndv(path, ndv);
+            }
+        }
+        builder.build()
+    }
+}
+
+// Top level columns only, names may contain dots.
+fn column_path(column: &str) -> ColumnPath {
+    ColumnPath::new(vec![column.to_string()])
+}
+
+struct PendingTransition {
+    previous_file: Option<PathBuf>,
+    reason: String,
+    added: Vec<String>,
+    removed: Vec<String>,
+}
+
+pub struct ParquetSink {
+    dir: PathBuf,
+    prefix: String,
+    properties: WriterProperties,
+    writer: Option<ArrowWriter<File>>,
+    schema: Option<SchemaRef>,
+    current_file: Option<PathBuf>,
+    file_index: u32,
+    files: Vec<PathBuf>,
+    transition: Option<PendingTransition>,
+    force_rotate: Option<String>,
+    manifest_path: PathBuf,
+    manifest: Manifest,
+    pending: (u64, u64), // Batches and rows of the open file
+}
+
+impl ParquetSink {
+    pub fn new(dir: impl AsRef<Path>, prefix: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        remove_incomplete(dir.as_ref(), prefix)?;
+        let manifest_path = Manifest::sidecar(dir.as_ref().join(prefix));
+        let manifest = Manifest::load(&manifest_path)?.unwrap_or_default();
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            prefix: prefix.to_string(),
+            properties: WriterProperties::builder().build(),
+            writer: None,
+            schema: None,
+            current_file: None,
+            file_index: 0,
+            files: Vec::new(),
+            transition: None,
+            force_rotate: None,
+            manifest_path,
+            manifest,
+            pending: (0, 0),
+        })
+    }
+
+    pub fn with_properties(mut self, properties: WriterProperties) -> Self {
+        self.properties = properties;
+        self
+    }
+
+    pub fn with_options(self, options: &ParquetOptions) -> Self {
+        self.with_properties(options.properties())
+    }
+
+    // Completed files, in the order they were written.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    // Completed files and their batches and rows, across runs.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    // Start a new file on the next write even if the schema is unchanged,
+    // e.g. when CFGCNT increments but the channel layout stays the same.
+    pub fn mark_config_change(&mut self, reason: &str) {
+        self.force_rotate = Some(reason.to_string());
+    }
+
+    fn next_file_path(&self) -> PathBuf {
+        self.dir
+            .join(format!("{}-{:06}.parquet", self.prefix, self.file_index))
+    }
+
+    fn open(&mut self, schema: SchemaRef) -> io::Result<()> {
+        // A partition reopened for late rows keeps the files it already has
+        while self.next_file_path().exists() {
+            self.file_index += 1;
+        }
+        let path = self.next_file_path();
+        let file = File::create(incomplete_path(&path))?;
+        let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(self.properties.clone()))
+            .map_err(to_io_error)?;
+
+        if let Some(transition) = self.transition.take() {
+            if let Some(previous) = transition.previous_file {
+                writer.append_key_value_metadata(KeyValue::new(
+                    PREVIOUS_FILE_KEY.to_string(),
+                    previous.file_name().unwrap().to_string_lossy().to_string(),
+                ));
+            }
+            writer.append_key_value_metadata(KeyValue::new(
+                CHANGE_REASON_KEY.to_string(),
+                transition.reason,
+            ));
+            writer.append_key_value_metadata(KeyValue::new(
+                COLUMNS_ADDED_KEY.to_string(),
+                transition.added.join(","),
+            ));
+            writer.append_key_value_metadata(KeyValue::new(
+                COLUMNS_REMOVED_KEY.to_string(),
+                transition.removed.join(","),
+            ));
+        }
+
+        println!("Opened parquet file {}", path.display());
+        self.file_index += 1;
+        self.writer = Some(writer);
+        self.schema = Some(schema);
+        self.current_file = Some(path);
+        Ok(())
+    }
+
+    fn close_current(&mut self, next_file: Option<&Path>) -> io::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            if let Some(next) = next_file {
+                writer.append_key_value_metadata(KeyValue::new(
+                    NEXT_FILE_KEY.to_string(),
+                    next.file_name().unwrap().to_string_lossy().to_string(),
+                ));
+            }
+            let file = writer.into_inner().map_err(to_io_error)?;
+            file.sync_all()?;
+            if let Some(path) = self.current_file.take() {
+                fs::rename(incomplete_path(&path), &path)?;
+                let (batches, rows) = std::mem::take(&mut self.pending);
+                self.manifest
+                    .files
+                    .push(path.file_name().unwrap().to_string_lossy().to_string());
+                self.manifest.batches += batches;
+                self.manifest.rows += rows;
+                self.manifest.save(&self.manifest_path)?;
+                self.files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    // Close the current file and prepare the metadata for the next one.
+    fn rotate(&mut self, new_schema: &Schema, reason: String) -> io::Result<()> {
+        let (added, removed) = match &self.schema {
+            Some(old) => (
+                new_schema
+                    .fields()
+                    .iter()
+                    .filter(|f| old.field_with_name(f.name()).is_err())
+                    .map(|f| f.name().clone())
+                    .collect(),
+                old.fields()
+                    .iter()
+                    .filter(|f| new_schema.field_with_name(f.name()).is_err())
+                    .map(|f| f.name().clone())
+                    .collect(),
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+        println!(
+            "Schema change ({}), {} columns added, {} removed",
+            reason,
+            added.len(),
+            removed.len()
+        );
+        let previous_file = self.current_file.clone();
+        let next_file = self.next_file_path();
+        self.close_current(Some(&next_file))?;
+        self.transition = Some(PendingTransition {
+            previous_file,
+            reason,
+            added,
+            removed,
+        });
+        Ok(())
+    }
+}
+
+// Where a file is written until it is complete.
+fn incomplete_path(path: &Path) -> PathBuf {
+    path.with_extension("parquet.tmp")
+}
+
+// Files of an earlier run that crashed before closing them.
+fn remove_incomplete(dir: &Path, prefix: &str) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if name.starts_with(&format!("{}-", prefix)) && name.ends_with(".parquet.tmp") {
+            println!("Removing incomplete file {}", path.display());
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+impl BatchSink for ParquetSink {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let incoming = batch.schema();
+        let batch = match &self.schema {
+            Some(current) if same_fields(current, &incoming) => {
+                if let Some(reason) = self.force_rotate.take() {
+                    let current = current.clone();
+                    self.rotate(&incoming, reason)?;
+                    self.open(current.clone())?;
+                    align_to_schema(batch, &current)?
+                } else if self.writer.is_none() {
+                    let current = current.clone();
+                    self.open(current.clone())?;
+                    align_to_schema(batch, &current)?
+                } else {
+                    align_to_schema(batch, current)?
+                }
+            }
+            Some(_) => {
+                let reason = self
+                    .force_rotate
+                    .take()
+                    .unwrap_or_else(|| "channel map changed".to_string());
+                self.rotate(&incoming, reason)?;
+                self.open(incoming)?;
+                batch.clone()
+            }
+            None => {
+                self.force_rotate = None;
+                self.open(incoming)?;
+                batch.clone()
+            }
+        };
+
+        self.writer
+            .as_mut()
+            .unwrap()
+            .write(&batch)
+            .map_err(to_io_error)?;
+        self.pending.0 += 1;
+        self.pending.1 += batch.num_rows() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.writer.as_mut() {
+            Some(writer) => writer.flush().map_err(to_io_error),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.close_current(None)
+    }
+}
+
+impl Drop for ParquetSink {
+    fn drop(&mut self) {
+        // An ArrowWriter dropped without close leaves a file with no footer.
+        if let Err(e) = self.close_current(None) {
+            println!("Failed to close parquet file: {}", e);
+        }
+    }
+}
+
+// Hive style partitioned dataset:
+//   root/station=<name>/idcode=<id>/date=YYYY-MM-DD/hour=HH/part-NNNNNN.parquet
+// Rows are routed to partitions by the UTC hour of their timestamp column,
+// which comes from SOC. With a timezone the date and hour are local time
+// instead: the hour repeated when DST ends is a single partition holding
+// both, the hour skipped when it starts has none.
+// Each partition directory is written by its own ParquetSink, so schema
+// changes rotate files inside the partition as usual.
+pub struct PartitionedParquetSink {
+    root: PathBuf,
+    station: String,
+    idcode: u16,
+    properties: WriterProperties,
+    timezone: Option<Tz>,
+    partitions: HashMap<i64, ParquetSink>, // Keyed by wall clock hours since the epoch
+    newest_hour: Option<i64>,
+    files: Vec<PathBuf>,
+}
+
+const MICROS_PER_HOUR: i64 = 3_600_000_000;
+
+// Percent-encode anything that is not safe in a path segment.
+pub fn escape_partition_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' || byte == b'.' {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+impl PartitionedParquetSink {
+    pub fn new(root: impl AsRef<Path>, station: &str, idcode: u16) -> io::Result<Self> {
+        fs::create_dir_all(root.as_ref())?;
+        Ok(Self {
+            root: root.as_ref().to_path_buf(),
+            station: station.trim().to_string(),
+            idcode,
+            properties: WriterProperties::builder().build(),
+            timezone: None,
+            partitions: HashMap::new(),
+            newest_hour: None,
+            files: Vec::new(),
+        })
+    }
+
+    // Station and idcode taken from a stream configuration. Multi-PMU streams are
+    // filed under the first PMU's station name and the stream idcode.
+    pub fn from_config(
+        root: impl AsRef<Path>,
+        config: &ConfigurationFrame1and2_2011,
+    ) -> io::Result<Self> {
+        let station = config
+            .pmu_configs
+            .first()
+            .map(|pmu| String::from_utf8_lossy(&pmu.stn).trim().to_string())
+            .unwrap_or_else(|| format!("PDC{}", config.prefix.idcode));
+        Self::new(root, &station, config.prefix.idcode)
+    }
+
+    pub fn with_properties(mut self, properties: WriterProperties) -> Self {
+        self.properties = properties;
+        self
+    }
+
+    pub fn with_options(self, options: &ParquetOptions) -> Self {
+        self.with_properties(options.properties())
+    }
+
+    // Name partitions by local date and hour, e.g. chrono_tz::Europe::Berlin.
+    pub fn with_timezone(mut self, timezone: Tz) -> Self {
+        self.timezone = Some(timezone);
+        self
+    }
+
+    // Wall clock hours since the epoch of a timestamp, UTC unless a timezone is set.
+    pub fn partition_hour(&self, timestamp_us: i64) -> i64 {
+        let local_us = match self.timezone {
+            Some(tz) => {
+                let utc = DateTime::from_timestamp_micros(timestamp_us)
+                    .unwrap_or_default()
+                    .naive_utc();
+                let offset = tz.offset_from_utc_datetime(&utc).fix().local_minus_utc();
+                timestamp_us + offset as i64 * 1_000_000
+            }
+            None => timestamp_us,
+        };
+        local_us.div_euclid(MICROS_PER_HOUR)
+    }
+
+    // Directory of a partition, hour as returned by partition_hour.
+    pub fn partition_dir(&self, hour: i64) -> PathBuf {
+        let start = DateTime::from_timestamp(hour * 3600, 0)
+            .unwrap_or_default()
+            .naive_utc();
+        self.root
+            .join(format!("station={}", escape_partition_value(&self.station)))
+            .join(format!("idcode={}", self.idcode))
+            .join(format!("date={}", start.format("%Y-%m-%d")))
+            .join(format!("hour={}", start.format("%H")))
+    }
+
+    // Completed files across all partitions.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    fn partition(&mut self, hour: i64) -> io::Result<&mut ParquetSink> {
+        if !self.partitions.contains_key(&hour) {
+            let sink = ParquetSink::new(self.partition_dir(hour), "part")?
+                .with_properties(self.properties.clone());
+            self.partitions.insert(hour, sink);
+        }
+        Ok(self.partitions.get_mut(&hour).unwrap())
+    }
+
+    // Close partitions more than an hour older than the newest data seen,
+    // late rows for those hours will start a new file in the same partition.
+    fn close_stale_partitions(&mut self) -> io::Result<()> {
+        let Some(newest) = self.newest_hour else {
+            return Ok(());
+        };
+        let stale: Vec<i64> = self
+            .partitions
+            .keys()
+            .filter(|hour| **hour < newest - 1)
+            .copied()
+            .collect();
+        for hour in stale {
+            if let Some(mut sink) = self.partitions.remove(&hour) {
+                sink.close()?;
+                self.files.extend_from_slice(sink.files());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn timestamp_column(batch: &RecordBatch) -> io::Result<&TimestampMicrosecondArray> {
+    batch
+        .column_by_name("timestamp")
+        .and_then(|col| col.as_any().downcast_ref::<TimestampMicrosecondArray>())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Batch has no microsecond timestamp column",
+            )
+        })
+}
+
+impl BatchSink for PartitionedParquetSink {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let timestamps = timestamp_column(batch)?;
+        let hours: Vec<i64> = timestamps
+            .values()
+            .iter()
+            .map(|ts| self.partition_hour(*ts))
+            .collect();
+
+        let mut distinct = hours.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+
+        for hour in distinct {
+            let part = if hours.iter().all(|h| *h == hour) {
+                batch.clone()
+            } else {
+                let mask = BooleanArray::from_iter(hours.iter().map(|h| Some(*h == hour)));
+                filter_record_batch(batch, &mask).map_err(to_io_error)?
+            };
+            self.partition(hour)?.write_batch(&part)?;
+            self.newest_hour = Some(self.newest_hour.map_or(hour, |newest| newest.max(hour)));
+        }
+        self.close_stale_partitions()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for sink in self.partitions.values_mut() {
+            sink.flush()?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        let mut hours: Vec<i64> = self.partitions.keys().copied().collect();
+        hours.sort_unstable();
+        for hour in hours {
+            if let Some(mut sink) = self.partitions.remove(&hour) {
+                sink.close()?;
+                self.files.extend_from_slice(sink.files());
+            }
+        }
+        Ok(())
+    }
+}
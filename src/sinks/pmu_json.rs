@@ -0,0 +1,94 @@
+// Rows split by PMU as JSON payloads, for the message bus sinks:
+//
+//   {"timestamp_us": 1700000000000000, "idcode": 7734, "station": "Station A",
+//    "values": {"FREQ": 60.0, "VA_X": 7967.4, "DATA_VALID": 1.0, ...}}
+//
+// Values are in engineering units and named after the channel without the
+// station and IDCODE prefix, NULL when missing or not finite. Complex phasor
+// columns are left out, as are columns without a PMU such as the timestamp.
+use crate::arrow_utils::{META_IDCODE, META_OFFSET, META_SCALE, META_STATION};
+use arrow::array::{Array, Float64Array, TimestampMicrosecondArray};
+use arrow::compute::cast;
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::io;
+
+pub(crate) struct PmuPayload {
+    pub idcode: u16,
+    #[cfg_attr(not(feature = "nats"), allow(dead_code))] // Subject of nats
+    pub station: String,
+    pub payload: Value,
+}
+
+// Payloads row by row, the PMUs of a row in IDCODE order.
+pub(crate) fn pmu_payloads(batch: &RecordBatch) -> io::Result<Vec<PmuPayload>> {
+    let timestamps = batch
+        .column_by_name("timestamp")
+        .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "Batch without timestamp column")
+        })?;
+
+    // Columns of each PMU, with the name they are sent with
+    let mut pmus: BTreeMap<u16, (String, Vec<(String, Float64Array)>)> = BTreeMap::new();
+    let schema = batch.schema();
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        let meta = field.metadata();
+        let Some(idcode) = meta.get(META_IDCODE).and_then(|v| v.parse::<u16>().ok()) else {
+            continue;
+        };
+        let Ok(values) = cast(column, &DataType::Float64) else {
+            continue;
+        };
+        let Some(values) = values.as_any().downcast_ref::<Float64Array>() else {
+            continue;
+        };
+        let number = |key: &str, default: f64| {
+            meta.get(key)
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(default)
+        };
+        let (scale, offset) = (number(META_SCALE, 1.0), number(META_OFFSET, 0.0));
+        let values: Float64Array = values.unary(|v| v * scale + offset);
+        let station = meta.get(META_STATION).cloned().unwrap_or_default();
+        let prefix = format!("{}_{}_", station, idcode);
+        let name = field
+            .name()
+            .strip_prefix(&prefix)
+            .unwrap_or(field.name())
+            .to_string();
+        pmus.entry(idcode)
+            .or_insert_with(|| (station, Vec::new()))
+            .1
+            .push((name, values));
+    }
+
+    let mut payloads = Vec::with_capacity(batch.num_rows() * pmus.len());
+    for row in 0..batch.num_rows() {
+        for (idcode, (station, columns)) in &pmus {
+            let mut values = Map::new();
+            for (name, column) in columns {
+                let value = column.value(row);
+                let value = if column.is_valid(row) && value.is_finite() {
+                    json!(value)
+                } else {
+                    Value::Null
+                };
+                values.insert(name.clone(), value);
+            }
+            payloads.push(PmuPayload {
+                idcode: *idcode,
+                station: station.clone(),
+                payload: json!({
+                    "timestamp_us": timestamps.value(row),
+                    "idcode": idcode,
+                    "station": station,
+                    "values": values,
+                }),
+            });
+        }
+    }
+    Ok(payloads)
+}
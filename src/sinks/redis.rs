@@ -0,0 +1,257 @@
+// Redis Streams sink, a low-latency fan-out to other services.
+//
+// Every row is split by PMU and each PMU's part is added with XADD as one
+// JSON payload (see pmu_json) to the stream <prefix>:<PMU IDCODE>. Entry ids
+// are assigned by Redis; trimming keeps about maxlen entries per stream (MAXLEN ~)
+// unless exact trimming is asked for.
+//
+// The commands of a batch are pipelined over one connection, which is
+// opened again on the next batch after an error. The address is host:port or
+// redis://[:password@]host[:port][/db].
+use super::pmu_json::pmu_payloads;
+use super::BatchSink;
+use arrow::record_batch::RecordBatch;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const DEFAULT_PORT: u16 = 6379;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+pub struct RedisStreamSink {
+    address: String, // host:port
+    password: Option<String>,
+    database: Option<u32>,
+    prefix: String,
+    maxlen: Option<usize>,
+    exact_trim: bool,
+    connection: Option<Connection>,
+    entries: usize,
+}
+
+impl RedisStreamSink {
+    // The connection is opened with the first batch.
+    pub fn new(address: &str) -> io::Result<Self> {
+        let invalid = |message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: {}", message, address),
+            )
+        };
+        let (mut password, mut database) = (None, None);
+        let host = match address.strip_prefix("redis://") {
+            Some(rest) => {
+                let (credentials, rest) = match rest.rsplit_once('@') {
+                    Some((credentials, rest)) => (Some(credentials), rest),
+                    None => (None, rest),
+                };
+                // The user name before the colon is not used
+                password = credentials
+                    .map(|c| c.split_once(':').map_or(c, |(_, password)| password))
+                    .filter(|p| !p.is_empty())
+                    .map(str::to_string);
+                let (host, db) = match rest.split_once('/') {
+                    Some((host, db)) if !db.is_empty() => (
+                        host,
+                        Some(db.parse().map_err(|_| invalid("Invalid database"))?),
+                    ),
+                    Some((host, _)) => (host, None),
+                    None => (rest, None),
+                };
+                database = db;
+                host
+            }
+            None => address,
+        };
+        if host.is_empty() {
+            return Err(invalid("No host"));
+        }
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:{}", host, DEFAULT_PORT)
+        };
+        Ok(RedisStreamSink {
+            address,
+            password,
+            database,
+            prefix: "pmu".to_string(),
+            maxlen: None,
+            exact_trim: false,
+            connection: None,
+            entries: 0,
+        })
+    }
+
+    // Streams are named <prefix>:<PMU IDCODE>, pmu by default.
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    // Trim every stream to about maxlen entries.
+    pub fn with_maxlen(mut self, maxlen: usize) -> Self {
+        self.maxlen = Some(maxlen);
+        self
+    }
+
+    // Trim to exactly maxlen, which costs Redis more than trimming whole nodes.
+    pub fn with_exact_trim(mut self, exact: bool) -> Self {
+        self.exact_trim = exact;
+        self
+    }
+
+    // Entries added since the sink was opened.
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        let stream = TcpStream::connect(&self.address)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        let mut connection = Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+        let mut setup = Vec::new();
+        if let Some(password) = &self.password {
+            setup.push(vec!["AUTH".to_string(), password.clone()]);
+        }
+        if let Some(database) = self.database {
+            setup.push(vec!["SELECT".to_string(), database.to_string()]);
+        }
+        if !setup.is_empty() {
+            send(&mut connection, &setup)?;
+        }
+        println!("Connected to Redis at {}", self.address);
+        Ok(connection)
+    }
+
+    // XADD commands for the rows of a batch, one per PMU and row.
+    fn commands(&self, batch: &RecordBatch) -> io::Result<Vec<Vec<String>>> {
+        let commands = pmu_payloads(batch)?
+            .into_iter()
+            .map(|pmu| {
+                let mut command = vec![
+                    "XADD".to_string(),
+                    format!("{}:{}", self.prefix, pmu.idcode),
+                ];
+                if let Some(maxlen) = self.maxlen {
+                    command.push("MAXLEN".to_string());
+                    command.push(if self.exact_trim { "=" } else { "~" }.to_string());
+                    command.push(maxlen.to_string());
+                }
+                command.extend(["*".to_string(), "data".to_string(), pmu.payload.to_string()]);
+                command
+            })
+            .collect();
+        Ok(commands)
+    }
+}
+
+impl BatchSink for RedisStreamSink {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let commands = self.commands(batch)?;
+        if commands.is_empty() {
+            return Ok(());
+        }
+        if self.connection.is_none() {
+            self.connection = Some(self.connect()?);
+        }
+        let connection = self.connection.as_mut().unwrap();
+        let result = send(connection, &commands);
+        if let Err(e) = &result {
+            // A failed connection is opened again, a rejected command says so
+            if e.kind() != io::ErrorKind::InvalidData {
+                self.connection = None;
+            }
+        }
+        result?;
+        self.entries += commands.len();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.connection.as_mut() {
+            Some(connection) => connection.writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.connection = None;
+        Ok(())
+    }
+}
+
+// Pipeline commands and read all replies. The first error reply is returned
+// as InvalidData after the rest have been read.
+fn send(connection: &mut Connection, commands: &[Vec<String>]) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    for command in commands {
+        buffer.extend_from_slice(format!("*{}\r\n", command.len()).as_bytes());
+        for argument in command {
+            buffer.extend_from_slice(format!("${}\r\n", argument.len()).as_bytes());
+            buffer.extend_from_slice(argument.as_bytes());
+            buffer.extend_from_slice(b"\r\n");
+        }
+    }
+    connection.writer.write_all(&buffer)?;
+    let mut error = None;
+    for _ in commands {
+        if let Err(e) = read_reply(&mut connection.reader) {
+            if e.kind() != io::ErrorKind::InvalidData {
+                return Err(e);
+            }
+            error.get_or_insert(e);
+        }
+    }
+    error.map_or(Ok(()), Err)
+}
+
+// Read one RESP reply, error replies as InvalidData.
+fn read_reply(reader: &mut impl BufRead) -> io::Result<()> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Redis closed the connection",
+        ));
+    }
+    let line = line.trim_end();
+    let length = || {
+        line[1..]
+            .parse::<i64>()
+            .map_err(|_| io::Error::other(format!("Invalid Redis reply {:?}", line)))
+    };
+    match line.as_bytes().first() {
+        Some(b'+') | Some(b':') => Ok(()),
+        Some(b'-') => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Redis: {}", &line[1..]),
+        )),
+        Some(b'$') => {
+            let length = length()?;
+            if length >= 0 {
+                // Content and CRLF
+                let mut content = vec![0; length as usize + 2];
+                reader.read_exact(&mut content)?;
+            }
+            Ok(())
+        }
+        Some(b'*') => {
+            for _ in 0..length()?.max(0) {
+                read_reply(reader)?;
+            }
+            Ok(())
+        }
+        _ => Err(io::Error::other(format!("Invalid Redis reply {:?}", line))),
+    }
+}
@@ -0,0 +1,271 @@
+// Disk spool in front of a sink that may go away, e.g. a database.
+//
+// While the sink fails, SpooledSink writes the batches to its spool
+// directory instead, one Arrow IPC file per batch, and tries the sink again
+// every retry_secs with the next live batch. Once a live batch is written
+// the backlog is replayed, oldest first, at no more than
+// catch_up_rows_per_sec alongside the live data, so the outage window is
+// kept without swamping the sink that just came back. Replayed rows reach
+// the sink after newer live rows.
+//
+// The spool is on disk: batches left when closing are replayed after a
+// restart with the same directory. Each sink of each stream needs its own
+// directory.
+use super::{to_io_error, BatchSink};
+use crate::latency::now_micros;
+use arrow::ipc::reader::FileReader;
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpoolConfig {
+    pub dir: PathBuf,
+    #[serde(default = "default_catch_up_rows_per_sec")]
+    pub catch_up_rows_per_sec: f64,
+    #[serde(default = "default_retry_secs")]
+    pub retry_secs: f64,
+}
+
+fn default_catch_up_rows_per_sec() -> f64 {
+    10_000.0
+}
+
+fn default_retry_secs() -> f64 {
+    5.0
+}
+
+impl SpoolConfig {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        SpoolConfig {
+            dir: dir.as_ref().to_path_buf(),
+            catch_up_rows_per_sec: default_catch_up_rows_per_sec(),
+            retry_secs: default_retry_secs(),
+        }
+    }
+
+    pub fn with_catch_up_rate(mut self, rows_per_sec: f64) -> Self {
+        self.catch_up_rows_per_sec = rows_per_sec;
+        self
+    }
+
+    pub fn with_retry_secs(mut self, retry_secs: f64) -> Self {
+        self.retry_secs = retry_secs.max(0.0);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpoolStats {
+    pub spooled: u64,  // Batches written to the spool
+    pub replayed: u64, // Batches of the spool written to the sink
+    pub failures: u64, // Failed writes to the sink
+}
+
+pub struct SpooledSink {
+    sink: Box<dyn BatchSink + Send>,
+    config: SpoolConfig,
+    backlog: VecDeque<(PathBuf, usize)>, // Spool files with their rows, oldest first
+    next_file: u64,
+    down_since_us: Option<i64>, // Last failure while the sink is down
+    allowance: f64,             // Rows that may be replayed now
+    last_us: i64,
+    stats: SpoolStats,
+}
+
+impl SpooledSink {
+    // Takes up the batches a previous run left in the directory.
+    pub fn new(sink: Box<dyn BatchSink + Send>, config: SpoolConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let mut files: Vec<(u64, PathBuf)> = fs::read_dir(&config.dir)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let number = path
+                    .file_name()?
+                    .to_str()?
+                    .strip_suffix(".arrow")?
+                    .parse::<u64>()
+                    .ok()?;
+                Some((number, path))
+            })
+            .collect();
+        files.sort();
+        let next_file = files.last().map_or(0, |(number, _)| number + 1);
+        let mut backlog = VecDeque::new();
+        for (_, path) in files {
+            let rows = read_spool_file(&path)?
+                .iter()
+                .map(RecordBatch::num_rows)
+                .sum();
+            backlog.push_back((path, rows));
+        }
+        if !backlog.is_empty() {
+            println!(
+                "Spool {} holds {} batches of a previous run",
+                config.dir.display(),
+                backlog.len()
+            );
+        }
+        Ok(SpooledSink {
+            sink,
+            config,
+            backlog,
+            next_file,
+            down_since_us: None,
+            allowance: 0.0,
+            last_us: now_micros(),
+            stats: SpoolStats::default(),
+        })
+    }
+
+    pub fn stats(&self) -> SpoolStats {
+        self.stats
+    }
+
+    // Batches waiting in the spool.
+    pub fn backlog(&self) -> usize {
+        self.backlog.len()
+    }
+
+    pub fn is_down(&self) -> bool {
+        self.down_since_us.is_some()
+    }
+
+    pub fn write_batch_at(&mut self, batch: &RecordBatch, now_us: i64) -> io::Result<()> {
+        let retry_us = (self.config.retry_secs * 1e6) as i64;
+        let retry = self
+            .down_since_us
+            .is_none_or(|since| now_us >= since + retry_us);
+        if retry {
+            match self.sink.write_batch(batch) {
+                Ok(()) => {
+                    if self.down_since_us.take().is_some() {
+                        println!(
+                            "Sink back, catching up on {} spooled batches",
+                            self.backlog.len()
+                        );
+                        self.allowance = 0.0;
+                        self.last_us = now_us;
+                    }
+                    return self.poll(now_us).map(|_| ());
+                }
+                Err(e) => self.fail(now_us, &e),
+            }
+        }
+        self.spool(batch)
+    }
+
+    // Replay the backlog for the time passed up to now_us, while the sink is
+    // up. Returns the number of batches replayed.
+    pub fn poll(&mut self, now_us: i64) -> io::Result<usize> {
+        let rate = self.config.catch_up_rows_per_sec;
+        let elapsed_s = (now_us - self.last_us).max(0) as f64 / 1e6;
+        self.last_us = self.last_us.max(now_us);
+        if self.is_down() {
+            return Ok(0);
+        }
+        // At most a second of rows saved up, enough for any one batch
+        let largest = self
+            .backlog
+            .iter()
+            .map(|(_, rows)| *rows)
+            .max()
+            .unwrap_or(0);
+        let cap = rate.max(largest as f64);
+        self.allowance = if rate > 0.0 {
+            (self.allowance + elapsed_s * rate).min(cap)
+        } else {
+            f64::INFINITY // No limit
+        };
+        let mut replayed = 0;
+        while let Some((path, rows)) = self.backlog.front().cloned() {
+            if (rows as f64) > self.allowance {
+                break;
+            }
+            for batch in read_spool_file(&path)? {
+                if let Err(e) = self.sink.write_batch(&batch) {
+                    self.fail(now_us, &e);
+                    return Ok(replayed);
+                }
+            }
+            fs::remove_file(&path)?;
+            self.backlog.pop_front();
+            self.allowance -= rows as f64;
+            self.stats.replayed += 1;
+            replayed += 1;
+        }
+        if replayed > 0 && self.backlog.is_empty() {
+            println!("Spool {} caught up", self.config.dir.display());
+        }
+        Ok(replayed)
+    }
+
+    fn fail(&mut self, now_us: i64, error: &io::Error) {
+        if self.down_since_us.is_none() {
+            println!(
+                "Sink failed, spooling to {}: {}",
+                self.config.dir.display(),
+                error
+            );
+        }
+        self.down_since_us = Some(now_us);
+        self.stats.failures += 1;
+    }
+
+    fn spool(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let path = self
+            .config
+            .dir
+            .join(format!("{:010}.arrow", self.next_file));
+        self.next_file += 1;
+        let mut writer =
+            FileWriter::try_new(File::create(&path)?, &batch.schema()).map_err(to_io_error)?;
+        writer.write(batch).map_err(to_io_error)?;
+        writer.finish().map_err(to_io_error)?;
+        writer.into_inner().map_err(to_io_error)?.sync_all()?;
+        self.backlog.push_back((path, batch.num_rows()));
+        self.stats.spooled += 1;
+        Ok(())
+    }
+}
+
+fn read_spool_file(path: &Path) -> io::Result<Vec<RecordBatch>> {
+    FileReader::try_new(File::open(path)?, None)
+        .map_err(to_io_error)?
+        .collect::<Result<_, _>>()
+        .map_err(to_io_error)
+}
+
+impl BatchSink for SpooledSink {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        self.write_batch_at(batch, now_micros())
+    }
+
+    // Spooled batches are on disk already, nothing to flush while down.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.is_down() {
+            return Ok(());
+        }
+        let result = self.sink.flush();
+        if let Err(e) = &result {
+            self.fail(now_micros(), e);
+        }
+        result
+    }
+
+    // The backlog stays in the spool for the next run.
+    fn close(&mut self) -> io::Result<()> {
+        if !self.backlog.is_empty() {
+            println!(
+                "Spool {} keeps {} batches for the next run",
+                self.config.dir.display(),
+                self.backlog.len()
+            );
+        }
+        self.sink.close()
+    }
+}
@@ -0,0 +1,306 @@
+// SQLite database sink, for storage without any infrastructure.
+//
+// The database is written through the sqlite3 command-line shell, which has
+// to be on the PATH (or given with with_program). Schema:
+//
+//   channels(id INTEGER PRIMARY KEY, name TEXT UNIQUE, stream INTEGER,
+//            station TEXT, kind TEXT, unit TEXT)
+//   samples(channel_id INTEGER, timestamp_us INTEGER, value REAL,
+//           PRIMARY KEY (channel_id, timestamp_us))
+//   events(timestamp_us INTEGER, kind TEXT, severity TEXT, source TEXT,
+//          message TEXT, measured TEXT)
+//
+// Samples are in engineering units, one row per channel and timestamp (UTC
+// microseconds), NULL for missing or non-finite values. Channels are named as
+// the columns of the batches; complex phasor columns are left out. The
+// measured values of events are a JSON object. With a decimation interval
+// only the first row of each interval is kept; rows are picked rather than
+// averaged, which would not work for angles.
+//
+// Every batch is written in one transaction. Rows written again after a
+// restart replace the earlier ones, so a database can be continued.
+use super::{to_io_error, BatchSink};
+use crate::arrow_utils::{META_KIND, META_OFFSET, META_SCALE, META_STATION, META_UNIT};
+use crate::events::Event;
+use arrow::array::{Array, Float64Array, TimestampMicrosecondArray};
+use arrow::compute::cast;
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+CREATE TABLE IF NOT EXISTS channels (
+    id INTEGER PRIMARY KEY, name TEXT UNIQUE NOT NULL, stream INTEGER,
+    station TEXT, kind TEXT, unit TEXT);
+CREATE TABLE IF NOT EXISTS samples (
+    channel_id INTEGER NOT NULL REFERENCES channels(id), timestamp_us INTEGER NOT NULL,
+    value REAL, PRIMARY KEY (channel_id, timestamp_us)) WITHOUT ROWID;
+CREATE INDEX IF NOT EXISTS samples_time ON samples(timestamp_us);
+CREATE TABLE IF NOT EXISTS events (
+    timestamp_us INTEGER NOT NULL, kind TEXT, severity TEXT, source TEXT,
+    message TEXT, measured TEXT);
+CREATE INDEX IF NOT EXISTS events_time ON events(timestamp_us);
+";
+
+pub struct SqliteSink {
+    path: PathBuf,
+    stream: Option<u16>,
+    interval_us: i64,
+    last_interval: Option<i64>,
+    channels: HashMap<String, i64>, // Channel ids by name
+    shell: Option<(Child, ChildStdin)>,
+    rows: usize,
+}
+
+impl SqliteSink {
+    // Open or continue a database with the sqlite3 shell on the PATH.
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::with_program(path, "sqlite3")
+    }
+
+    pub fn with_program(path: impl AsRef<Path>, program: &str) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Create the tables and read back the channels of earlier runs
+        let output = Command::new(program)
+            .arg("-batch")
+            .arg("-bail")
+            .arg(&path)
+            .arg(format!("{} SELECT id, name FROM channels;", SCHEMA))
+            .output()
+            .map_err(|e| io::Error::new(e.kind(), format!("Failed to run {}: {}", program, e)))?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} failed on {}: {}",
+                program,
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let channels = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let (id, name) = line.split_once('|')?;
+                Some((name.to_string(), id.parse().ok()?))
+            })
+            .collect();
+
+        let mut child = Command::new(program)
+            .arg("-batch")
+            .arg("-bail")
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| io::Error::other("No stdin"))?;
+        // Wait for other writers to the same database
+        stdin.write_all(b".timeout 5000\n")?;
+        println!("Opened SQLite database {}", path.display());
+        Ok(SqliteSink {
+            path,
+            stream: None,
+            interval_us: 0,
+            last_interval: None,
+            channels,
+            shell: Some((child, stdin)),
+            rows: 0,
+        })
+    }
+
+    // IDCODE of the stream the channels belong to.
+    pub fn with_stream(mut self, idcode: u16) -> Self {
+        self.stream = Some(idcode);
+        self
+    }
+
+    // Keep only the first row of every interval, 0 keeps all rows.
+    pub fn with_decimation(mut self, interval_us: i64) -> Self {
+        self.interval_us = interval_us.max(0);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Rows (timestamps) written since the sink was opened.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn write_event(&mut self, event: &Event) -> io::Result<()> {
+        let measured = serde_json::to_string(&event.values).map_err(to_io_error)?;
+        let name = |value: serde_json::Value| value.as_str().unwrap_or_default().to_string();
+        let sql = format!(
+            "INSERT INTO events VALUES ({}, {}, {}, {}, {}, {});\n",
+            event.timestamp_us,
+            quote(&name(serde_json::json!(event.kind))),
+            quote(&name(serde_json::json!(event.severity))),
+            quote(&event.source),
+            quote(&event.message),
+            quote(&measured)
+        );
+        self.execute(&sql)
+    }
+
+    fn execute(&mut self, sql: &str) -> io::Result<()> {
+        let (_, stdin) = self
+            .shell
+            .as_mut()
+            .ok_or_else(|| io::Error::other("SQLite sink is closed"))?;
+        if let Err(e) = stdin.write_all(sql.as_bytes()) {
+            // The shell stopped at an error, report that instead
+            let error = self.finish().err().unwrap_or(e);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    // Close the shell and wait for it, with its error output on failure.
+    fn finish(&mut self) -> io::Result<()> {
+        let Some((mut child, stdin)) = self.shell.take() else {
+            return Ok(());
+        };
+        drop(stdin);
+        let status = child.wait()?;
+        if status.success() {
+            return Ok(());
+        }
+        let mut stderr = String::new();
+        if let Some(mut pipe) = child.stderr.take() {
+            pipe.read_to_string(&mut stderr)?;
+        }
+        Err(io::Error::other(format!(
+            "sqlite3 failed on {}: {}",
+            self.path.display(),
+            stderr.trim()
+        )))
+    }
+
+    // Id of a channel, with the statement adding it if it is new.
+    fn channel_id(&mut self, name: &str, meta: &HashMap<String, String>, sql: &mut String) -> i64 {
+        if let Some(id) = self.channels.get(name) {
+            return *id;
+        }
+        let id = self.channels.values().max().copied().unwrap_or(0) + 1;
+        self.channels.insert(name.to_string(), id);
+        let text = |key: &str| meta.get(key).map_or("NULL".to_string(), |v| quote(v));
+        let _ = writeln!(
+            sql,
+            "INSERT OR REPLACE INTO channels VALUES ({}, {}, {}, {}, {}, {});",
+            id,
+            quote(name),
+            self.stream.map_or("NULL".to_string(), |s| s.to_string()),
+            text(META_STATION),
+            text(META_KIND),
+            text(META_UNIT)
+        );
+        id
+    }
+}
+
+impl BatchSink for SqliteSink {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let timestamps = batch
+            .column_by_name("timestamp")
+            .and_then(|c| c.as_any().downcast_ref::<TimestampMicrosecondArray>())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Batch without timestamp column")
+            })?;
+        // Rows kept after decimation
+        let mut rows = Vec::new();
+        for row in 0..batch.num_rows() {
+            let timestamp_us = timestamps.value(row);
+            if self.interval_us > 0 {
+                let interval = timestamp_us.div_euclid(self.interval_us);
+                if self.last_interval == Some(interval) {
+                    continue;
+                }
+                self.last_interval = Some(interval);
+            }
+            rows.push(row);
+        }
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut sql = String::from("BEGIN;\n");
+        let schema = batch.schema();
+        for (field, column) in schema.fields().iter().zip(batch.columns()) {
+            if field.name() == "timestamp" {
+                continue;
+            }
+            let Ok(values) = cast(column, &DataType::Float64) else {
+                continue;
+            };
+            let Some(values) = values.as_any().downcast_ref::<Float64Array>() else {
+                continue;
+            };
+            let meta = field.metadata();
+            let number = |key: &str, default: f64| {
+                meta.get(key)
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .unwrap_or(default)
+            };
+            let (scale, offset) = (number(META_SCALE, 1.0), number(META_OFFSET, 0.0));
+            let id = self.channel_id(field.name(), meta, &mut sql);
+            sql.push_str("INSERT OR REPLACE INTO samples VALUES ");
+            for (n, row) in rows.iter().enumerate() {
+                let value = values.value(*row) * scale + offset;
+                let value = if values.is_valid(*row) && value.is_finite() {
+                    format!("{:e}", value)
+                } else {
+                    "NULL".to_string()
+                };
+                let separator = if n == 0 { "" } else { "," };
+                let _ = write!(
+                    sql,
+                    "{}({},{},{})",
+                    separator,
+                    id,
+                    timestamps.value(*row),
+                    value
+                );
+            }
+            sql.push_str(";\n");
+        }
+        sql.push_str("COMMIT;\n");
+        self.execute(&sql)?;
+        self.rows += rows.len();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.shell.as_mut() {
+            Some((_, stdin)) => stdin.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.finish()
+    }
+}
+
+impl Drop for SqliteSink {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+// SQL string literal.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
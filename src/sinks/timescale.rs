@@ -0,0 +1,336 @@
+// PostgreSQL / TimescaleDB sink with binary COPY.
+//
+// Each batch is sent with COPY ... FROM STDIN (FORMAT binary) through psql,
+// so no rows are rendered as text on the way. The table is created from the
+// channel catalog of the first batch: a time column (timestamptz) and one
+// column per channel with its raw value, like the Delta sink. Channels that
+// appear later are added as columns. The catalog itself, station, IDCODE,
+// unit, scale and offset of every column, is kept in pmu_channels so values
+// can be converted to engineering units (raw * scale + offset) in SQL. It
+// also holds the bus, branch and mRID of channels mapped to a network model.
+//
+// With TimescaleDB the table is made a hypertable on time; with_hypertable
+// (false) leaves it a plain table for PostgreSQL without the extension.
+use super::{to_io_error, BatchSink};
+use crate::arrow_utils::{
+    META_BRANCH, META_BUS, META_CHANNEL, META_COMPONENT, META_IDCODE, META_KIND, META_MRID,
+    META_OFFSET, META_SCALE, META_STATION, META_UNIT,
+};
+use arrow::array::{
+    Array, ArrayRef, BooleanArray, Float32Array, Float64Array, Int16Array, Int32Array, Int64Array,
+    TimestampMicrosecondArray,
+};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+// Microseconds from the Unix epoch to the PostgreSQL epoch, 2000-01-01.
+const POSTGRES_EPOCH_US: i64 = 946_684_800_000_000;
+
+// Longest identifier PostgreSQL keeps without truncating it.
+const MAX_IDENTIFIER: usize = 63;
+
+const CATALOG: &str = "CREATE TABLE IF NOT EXISTS pmu_channels (
+    table_name text NOT NULL, column_name text NOT NULL, station text, idcode integer,
+    channel text, component text, kind text, unit text, scale double precision,
+    \"offset\" double precision, PRIMARY KEY (table_name, column_name));
+ALTER TABLE pmu_channels ADD COLUMN IF NOT EXISTS bus text,
+    ADD COLUMN IF NOT EXISTS branch text, ADD COLUMN IF NOT EXISTS mrid text;";
+
+// Arrow type a column is sent as and its PostgreSQL type.
+fn pg_type(data_type: &DataType) -> io::Result<(DataType, &'static str)> {
+    let mapped = match data_type {
+        DataType::Boolean => (DataType::Boolean, "boolean"),
+        DataType::Int8 | DataType::UInt8 | DataType::Int16 => (DataType::Int16, "smallint"),
+        DataType::Int32 | DataType::UInt16 => (DataType::Int32, "integer"),
+        DataType::Int64 | DataType::UInt32 => (DataType::Int64, "bigint"),
+        DataType::Float32 => (DataType::Float32, "real"),
+        DataType::Float64 => (DataType::Float64, "double precision"),
+        DataType::Timestamp(TimeUnit::Microsecond, _) => (
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            "timestamptz",
+        ),
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("No PostgreSQL type for {:?}", other),
+            ))
+        }
+    };
+    Ok(mapped)
+}
+
+// Name of a batch column in the table, the timestamp is time.
+fn column_name(field: &Field) -> &str {
+    match field.name().as_str() {
+        "timestamp" => "time",
+        name => name,
+    }
+}
+
+fn identifier(name: &str) -> io::Result<String> {
+    if name.len() > MAX_IDENTIFIER {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Name {} longer than {} bytes", name, MAX_IDENTIFIER),
+        ));
+    }
+    Ok(format!("\"{}\"", name.replace('"', "\"\"")))
+}
+
+fn literal(text: Option<&String>) -> String {
+    match text {
+        Some(text) => format!("'{}'", text.replace('\'', "''")),
+        None => "NULL".to_string(),
+    }
+}
+
+// Statements creating the table of a batch, or adding the channels it lacks,
+// and recording the channels in the catalog.
+pub fn create_statements(table: &str, batch: &RecordBatch, hypertable: bool) -> io::Result<String> {
+    let table_name = identifier(table)?;
+    let mut sql = format!(
+        "{}\nCREATE TABLE IF NOT EXISTS {} (time timestamptz NOT NULL);\n",
+        CATALOG, table_name
+    );
+    let schema = batch.schema();
+    for field in schema.fields() {
+        let (_, pg) = pg_type(field.data_type())?;
+        let name = column_name(field);
+        if name == "time" {
+            continue;
+        }
+        let _ = writeln!(
+            sql,
+            "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {};",
+            table_name,
+            identifier(name)?,
+            pg
+        );
+        let meta = field.metadata();
+        let number = |key: &str| meta.get(key).map_or("NULL".to_string(), |v| v.to_string());
+        let _ = writeln!(
+            sql,
+            "INSERT INTO pmu_channels VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {}) \
+             ON CONFLICT (table_name, column_name) DO UPDATE SET station = EXCLUDED.station, \
+             idcode = EXCLUDED.idcode, channel = EXCLUDED.channel, component = EXCLUDED.component, \
+             kind = EXCLUDED.kind, unit = EXCLUDED.unit, scale = EXCLUDED.scale, \
+             \"offset\" = EXCLUDED.\"offset\";",
+            literal(Some(&table.to_string())),
+            literal(Some(&name.to_string())),
+            literal(meta.get(META_STATION)),
+            number(META_IDCODE),
+            literal(meta.get(META_CHANNEL)),
+            literal(meta.get(META_COMPONENT)),
+            literal(meta.get(META_KIND)),
+            literal(meta.get(META_UNIT)),
+            number(META_SCALE),
+            number(META_OFFSET),
+        );
+        // Network model elements, of channels in the topology
+        if [META_BUS, META_BRANCH, META_MRID]
+            .iter()
+            .any(|key| meta.contains_key(*key))
+        {
+            let _ = writeln!(
+                sql,
+                "UPDATE pmu_channels SET bus = {}, branch = {}, mrid = {} \
+                 WHERE table_name = {} AND column_name = {};",
+                literal(meta.get(META_BUS)),
+                literal(meta.get(META_BRANCH)),
+                literal(meta.get(META_MRID)),
+                literal(Some(&table.to_string())),
+                literal(Some(&name.to_string())),
+            );
+        }
+    }
+    if hypertable {
+        let _ = writeln!(
+            sql,
+            "SELECT create_hypertable({}, 'time', if_not_exists => TRUE);",
+            literal(Some(&table_name))
+        );
+    }
+    Ok(sql)
+}
+
+// Column list of the COPY statement for a batch.
+pub fn copy_columns(batch: &RecordBatch) -> io::Result<String> {
+    let names = batch
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| identifier(column_name(field)))
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(names.join(", "))
+}
+
+// A batch in the binary COPY format: signature, flags and header extension,
+// per row the field count and every field as length and big endian value
+// (-1 for NULL), then -1 as the trailer.
+pub fn copy_data(batch: &RecordBatch) -> io::Result<Vec<u8>> {
+    let mut columns: Vec<ArrayRef> = Vec::with_capacity(batch.num_columns());
+    for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+        let (data_type, _) = pg_type(field.data_type())?;
+        columns.push(if &data_type == column.data_type() {
+            column.clone()
+        } else {
+            cast(column, &data_type).map_err(to_io_error)?
+        });
+    }
+
+    let mut data = Vec::with_capacity(19 + batch.num_rows() * (2 + columns.len() * 8));
+    data.extend_from_slice(b"PGCOPY\n\xff\r\n\0");
+    data.extend_from_slice(&0i32.to_be_bytes()); // Flags, no OIDs
+    data.extend_from_slice(&0i32.to_be_bytes()); // Header extension length
+    for row in 0..batch.num_rows() {
+        data.extend_from_slice(&(columns.len() as i16).to_be_bytes());
+        for column in &columns {
+            if column.is_null(row) {
+                data.extend_from_slice(&(-1i32).to_be_bytes());
+                continue;
+            }
+            let any = column.as_any();
+            let value: Vec<u8> = if let Some(c) = any.downcast_ref::<TimestampMicrosecondArray>() {
+                (c.value(row) - POSTGRES_EPOCH_US).to_be_bytes().to_vec()
+            } else if let Some(c) = any.downcast_ref::<Float64Array>() {
+                c.value(row).to_be_bytes().to_vec()
+            } else if let Some(c) = any.downcast_ref::<Float32Array>() {
+                c.value(row).to_be_bytes().to_vec()
+            } else if let Some(c) = any.downcast_ref::<Int64Array>() {
+                c.value(row).to_be_bytes().to_vec()
+            } else if let Some(c) = any.downcast_ref::<Int32Array>() {
+                c.value(row).to_be_bytes().to_vec()
+            } else if let Some(c) = any.downcast_ref::<Int16Array>() {
+                c.value(row).to_be_bytes().to_vec()
+            } else if let Some(c) = any.downcast_ref::<BooleanArray>() {
+                vec![c.value(row) as u8]
+            } else {
+                return Err(io::Error::other("Unexpected column type"));
+            };
+            data.extend_from_slice(&(value.len() as i32).to_be_bytes());
+            data.extend_from_slice(&value);
+        }
+    }
+    data.extend_from_slice(&(-1i16).to_be_bytes());
+    Ok(data)
+}
+
+pub struct TimescaleSink {
+    program: String,
+    connection: String, // libpq connection string or URI
+    table: String,
+    hypertable: bool,
+    columns: HashSet<String>, // Columns known to exist in the table
+    rows: usize,
+}
+
+impl TimescaleSink {
+    // Nothing is sent before the first batch, which creates the table.
+    pub fn new(connection: &str, table: &str) -> io::Result<Self> {
+        identifier(table)?;
+        Ok(TimescaleSink {
+            program: "psql".to_string(),
+            connection: connection.to_string(),
+            table: table.to_string(),
+            hypertable: true,
+            columns: HashSet::new(),
+            rows: 0,
+        })
+    }
+
+    // psql to run, when it is not on the PATH.
+    pub fn with_program(mut self, program: &str) -> Self {
+        self.program = program.to_string();
+        self
+    }
+
+    pub fn with_hypertable(mut self, hypertable: bool) -> Self {
+        self.hypertable = hypertable;
+        self
+    }
+
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    // Rows copied since the sink was opened.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    // Run a command with psql, input on stdin.
+    fn psql(&self, command: &str, input: &[u8]) -> io::Result<()> {
+        let mut child = Command::new(&self.program)
+            .arg("--no-psqlrc")
+            .arg("--quiet")
+            .arg("--set=ON_ERROR_STOP=1")
+            .arg("--dbname")
+            .arg(&self.connection)
+            .arg("--command")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                io::Error::new(e.kind(), format!("Failed to run {}: {}", self.program, e))
+            })?;
+        let written = child
+            .stdin
+            .take()
+            .map_or(Ok(()), |mut stdin| stdin.write_all(input));
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} failed: {}",
+                self.program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        written
+    }
+}
+
+impl BatchSink for TimescaleSink {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+        let new_columns: Vec<String> = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| column_name(field).to_string())
+            .filter(|name| !self.columns.contains(name))
+            .collect();
+        if !new_columns.is_empty() {
+            self.psql(
+                &create_statements(&self.table, batch, self.hypertable)?,
+                &[],
+            )?;
+            self.columns.extend(new_columns);
+        }
+        let command = format!(
+            "COPY {} ({}) FROM STDIN (FORMAT binary)",
+            identifier(&self.table)?,
+            copy_columns(batch)?
+        );
+        self.psql(&command, &copy_data(batch)?)?;
+        self.rows += batch.num_rows();
+        Ok(())
+    }
+
+    // Every batch is committed when write_batch returns.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
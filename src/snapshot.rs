@@ -0,0 +1,394 @@
+// Snapshot-on-trigger capture of raw frames.
+//
+// A SnapshotRecorder keeps the data frames of the last pre window of every
+// stream in a ring, with the latest configuration frame of the stream. When
+// a trigger fires, an event of at least min_severity on the event bus or a
+// manual trigger through a SnapshotTrigger, it writes a new capture file
+// (recorder format) holding the configurations and the buffered frames from
+// the pre window before the trigger, then appends every frame up to the end
+// of the post window. A trigger while a snapshot is open extends its post
+// window instead of starting another file.
+//
+// Windows are in frame time (SOC/FRACSEC), as are the event timestamps;
+// manual triggers are at the wall clock time they are made. A snapshot is
+// finished by the first frame past its post window, by poll once the post
+// window is over, or by finish.
+//
+// Files are <dir>/<name>-<trigger time>-<reason>.pmucap.
+use crate::arrow_utils::frame_timestamp_micros_with;
+use crate::events::{Event, EventBus, Severity};
+use crate::frames::now_micros;
+use crate::recorder::{CaptureCompression, CaptureRecord, CaptureWriter};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::sync::broadcast;
+
+// Frames kept per stream at most, whatever the pre window
+const MAX_PRE_FRAMES: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotConfig {
+    pub dir: PathBuf,
+    #[serde(default = "default_window_secs")]
+    pub pre_secs: f64,
+    #[serde(default = "default_window_secs")]
+    pub post_secs: f64,
+    // Events triggering a snapshot
+    #[serde(default = "default_min_severity")]
+    pub min_severity: Severity,
+    // Compress the snapshots with zstd at this level
+    #[serde(default)]
+    pub zstd_level: Option<i32>,
+}
+
+fn default_window_secs() -> f64 {
+    10.0
+}
+
+fn default_min_severity() -> Severity {
+    Severity::Alarm
+}
+
+impl SnapshotConfig {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        SnapshotConfig {
+            dir: dir.as_ref().to_path_buf(),
+            pre_secs: default_window_secs(),
+            post_secs: default_window_secs(),
+            min_severity: default_min_severity(),
+            zstd_level: None,
+        }
+    }
+
+    pub fn with_windows(mut self, pre_secs: f64, post_secs: f64) -> Self {
+        self.pre_secs = pre_secs.max(0.0);
+        self.post_secs = post_secs.max(0.0);
+        self
+    }
+
+    pub fn with_min_severity(mut self, min_severity: Severity) -> Self {
+        self.min_severity = min_severity;
+        self
+    }
+
+    pub fn with_zstd(mut self, level: i32) -> Self {
+        self.zstd_level = Some(level);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trigger {
+    pub reason: String,
+    pub timestamp_us: i64,
+}
+
+// Manual triggers of every recorder subscribed to it.
+#[derive(Clone)]
+pub struct SnapshotTrigger {
+    sender: broadcast::Sender<Trigger>,
+}
+
+impl Default for SnapshotTrigger {
+    fn default() -> Self {
+        SnapshotTrigger {
+            sender: broadcast::channel(64).0,
+        }
+    }
+}
+
+impl SnapshotTrigger {
+    pub fn new() -> Self {
+        SnapshotTrigger::default()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Trigger> {
+        self.sender.subscribe()
+    }
+
+    // Trigger now. Returns the number of recorders reached.
+    pub fn trigger(&self, reason: &str) -> usize {
+        self.trigger_at(reason, now_micros())
+    }
+
+    pub fn trigger_at(&self, reason: &str, timestamp_us: i64) -> usize {
+        self.sender
+            .send(Trigger {
+                reason: reason.to_string(),
+                timestamp_us,
+            })
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Default)]
+struct StreamBuffer {
+    config: Option<Vec<u8>>, // Latest configuration frame
+    time_base: u32,
+    frames: VecDeque<(i64, CaptureRecord)>, // With their frame time
+}
+
+struct Snapshot {
+    writer: CaptureWriter,
+    path: PathBuf,
+    until_us: i64, // End of the post window
+    frames: u64,
+}
+
+pub struct SnapshotRecorder {
+    config: SnapshotConfig,
+    name: String,
+    streams: BTreeMap<u16, StreamBuffer>,
+    open: Option<Snapshot>,
+    events: Option<broadcast::Receiver<Event>>,
+    triggers: Option<broadcast::Receiver<Trigger>>,
+    written: Vec<PathBuf>,
+}
+
+impl SnapshotRecorder {
+    pub fn new(config: SnapshotConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        Ok(SnapshotRecorder {
+            config,
+            name: "snapshot".to_string(),
+            streams: BTreeMap::new(),
+            open: None,
+            events: None,
+            triggers: None,
+            written: Vec::new(),
+        })
+    }
+
+    // Prefix of the file names, to tell recorders sharing a directory apart.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn with_events(mut self, bus: &EventBus) -> Self {
+        self.events = Some(bus.subscribe());
+        self
+    }
+
+    pub fn with_triggers(mut self, trigger: &SnapshotTrigger) -> Self {
+        self.triggers = Some(trigger.subscribe());
+        self
+    }
+
+    // Snapshots started so far, the last one possibly still open.
+    pub fn snapshots(&self) -> &[PathBuf] {
+        &self.written
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.open.is_some()
+    }
+
+    // Frames in the pre window rings of all streams.
+    pub fn buffered(&self) -> usize {
+        self.streams
+            .values()
+            .map(|stream| stream.frames.len())
+            .sum()
+    }
+
+    // Take a received frame: buffer it, and write it to the open snapshot
+    // while within the post window.
+    pub fn push(&mut self, frame: &[u8], arrival_us: i64) -> io::Result<()> {
+        self.take_triggers()?;
+        if frame.len() < 14 {
+            return Ok(());
+        }
+        let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+        let stream = self.streams.entry(idcode).or_insert_with(|| StreamBuffer {
+            time_base: 1_000_000,
+            ..Default::default()
+        });
+        let mut timestamp = None;
+        match (frame[1] >> 4) & 0x07 {
+            2 | 3 => {
+                if frame.len() >= 18 {
+                    stream.time_base =
+                        u32::from_be_bytes([0, frame[15], frame[16], frame[17]]).max(1);
+                }
+                stream.config = Some(frame.to_vec());
+            }
+            0 => timestamp = frame_timestamp_micros_with(frame, stream.time_base),
+            _ => return Ok(()),
+        }
+        if let Some(timestamp) = timestamp {
+            if self
+                .open
+                .as_ref()
+                .is_some_and(|open| timestamp > open.until_us)
+            {
+                self.close()?;
+            }
+        }
+        if let Some(open) = self.open.as_mut() {
+            open.writer.write_frame_at(frame, arrival_us)?;
+            open.frames += 1;
+        }
+        if let (Some(timestamp), Some(stream)) = (timestamp, self.streams.get_mut(&idcode)) {
+            let pre_us = (self.config.pre_secs * 1e6) as i64;
+            let record = CaptureRecord {
+                arrival_us,
+                frame: frame.to_vec(),
+            };
+            stream.frames.push_back((timestamp, record));
+            while stream.frames.len() > MAX_PRE_FRAMES
+                || stream
+                    .frames
+                    .front()
+                    .is_some_and(|(first, _)| *first < timestamp - pre_us)
+            {
+                stream.frames.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+    // Start a snapshot, or extend the open one, at the trigger time.
+    pub fn trigger(&mut self, trigger: Trigger) -> io::Result<()> {
+        let post_us = (self.config.post_secs * 1e6) as i64;
+        if let Some(open) = self.open.as_mut() {
+            open.until_us = open.until_us.max(trigger.timestamp_us + post_us);
+            println!(
+                "Snapshot {} extended by trigger: {}",
+                open.path.display(),
+                trigger.reason
+            );
+            return Ok(());
+        }
+        let path = self.path_for(&trigger);
+        let compression = match self.config.zstd_level {
+            Some(level) => CaptureCompression::Zstd(level),
+            None => CaptureCompression::None,
+        };
+        let mut writer = CaptureWriter::create(&path, compression)?;
+        let mut frames = 0;
+        for stream in self.streams.values() {
+            if let Some(config) = &stream.config {
+                writer.write_frame_at(config, trigger.timestamp_us)?;
+                frames += 1;
+            }
+        }
+        // The pre windows of the streams, merged in frame time
+        let from_us = trigger.timestamp_us - (self.config.pre_secs * 1e6) as i64;
+        let mut buffered: Vec<&(i64, CaptureRecord)> = self
+            .streams
+            .values()
+            .flat_map(|stream| stream.frames.iter())
+            .filter(|(timestamp, _)| *timestamp >= from_us)
+            .collect();
+        buffered.sort_by_key(|(timestamp, _)| *timestamp);
+        for (_, record) in buffered {
+            writer.write_frame_at(&record.frame, record.arrival_us)?;
+            frames += 1;
+        }
+        println!(
+            "Snapshot triggered by {}: {} ({} frames before)",
+            trigger.reason,
+            path.display(),
+            frames
+        );
+        self.written.push(path.clone());
+        self.open = Some(Snapshot {
+            writer,
+            path,
+            until_us: trigger.timestamp_us + post_us,
+            frames,
+        });
+        Ok(())
+    }
+
+    // Take pending triggers and close the snapshot once its post window is
+    // over at now_us, for streams that went quiet.
+    pub fn poll(&mut self, now_us: i64) -> io::Result<()> {
+        self.take_triggers()?;
+        if self
+            .open
+            .as_ref()
+            .is_some_and(|open| now_us > open.until_us)
+        {
+            self.close()?;
+        }
+        Ok(())
+    }
+
+    // Close the open snapshot, e.g. when stopping.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if let Some(mut open) = self.open.take() {
+            open.writer.finish()?;
+            println!(
+                "Snapshot {} written, {} frames",
+                open.path.display(),
+                open.frames
+            );
+        }
+        Ok(())
+    }
+
+    fn take_triggers(&mut self) -> io::Result<()> {
+        let mut triggers = Vec::new();
+        if let Some(events) = self.events.as_mut() {
+            loop {
+                match events.try_recv() {
+                    Ok(event) if event.severity >= self.config.min_severity => {
+                        triggers.push(Trigger {
+                            reason: format!("{:?} {}", event.kind, event.source),
+                            timestamp_us: event.timestamp_us,
+                        });
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::TryRecvError::Lagged(missed)) => {
+                        println!("Snapshot recorder missed {} events", missed);
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+        if let Some(receiver) = self.triggers.as_mut() {
+            loop {
+                match receiver.try_recv() {
+                    Ok(trigger) => triggers.push(trigger),
+                    Err(broadcast::error::TryRecvError::Lagged(_)) => {}
+                    Err(_) => break,
+                }
+            }
+        }
+        for trigger in triggers {
+            self.trigger(trigger)?;
+        }
+        Ok(())
+    }
+
+    // <dir>/<name>-<time>-<reason>.pmucap, numbered when taken.
+    fn path_for(&self, trigger: &Trigger) -> PathBuf {
+        let time = DateTime::from_timestamp_micros(trigger.timestamp_us)
+            .unwrap_or_default()
+            .format("%Y%m%dT%H%M%S%.3f");
+        let reason: String = trigger
+            .reason
+            .chars()
+            .take(40)
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let stem = format!("{}-{}-{}", self.name, time, reason);
+        let mut path = self.config.dir.join(format!("{}.pmucap", stem));
+        let mut n = 1;
+        while path.exists() {
+            path = self.config.dir.join(format!("{}-{}.pmucap", stem, n));
+            n += 1;
+        }
+        path
+    }
+}
@@ -0,0 +1,279 @@
+// Soak test of the collection pipeline against the simulator, to catch leaks
+// in the streaming subsystems before field deployment.
+//
+// run_soak serves a simulated stream (pdc_server with the configured
+// Scenario) on a local port and collects it with a Pipeline into a sink
+// under the output directory for the configured duration, typically a day.
+// Every sample interval it records the resident memory and open file
+// descriptors of the process, read from /proc/self (Linux only, None
+// elsewhere), and the worst latency of the pipeline's streams
+// (pipeline::LATENCY_METRIC).
+//
+// At the end the samples are checked against the bounds (check). Memory and
+// descriptors are compared to the first sample after the warm-up, once
+// buffers, sinks and connections have settled. Latency drift is the mean
+// latency of the last tenth of the samples against the first tenth after the
+// warm-up. The pipeline must also have collected frames without errors or
+// dropping any. The server and the pipeline run in this process, so the
+// figures cover both.
+use crate::metrics::Metrics;
+use crate::pdc_server::{run_mock_server, Protocol, ServerConfig};
+use crate::pipeline::{Pipeline, PipelineConfig, ShardStats, SinkFormat, LATENCY_METRIC};
+use crate::simulator::Scenario;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoakConfig {
+    pub duration_secs: f64,
+    pub sample_secs: f64,
+    pub warmup_secs: f64, // Samples before are not compared
+    pub port: u16,        // Local port of the simulated server
+    pub scenario: Scenario,
+    pub format: SinkFormat,
+    pub batch_rows: usize,
+    // Bounds checked at the end
+    pub max_rss_growth_mb: f64,
+    pub max_fd_growth: i64,
+    pub max_latency_drift_ms: f64,
+}
+
+impl Default for SoakConfig {
+    fn default() -> Self {
+        SoakConfig {
+            duration_secs: 24.0 * 3600.0,
+            sample_secs: 60.0,
+            warmup_secs: 300.0,
+            port: 4712,
+            scenario: Scenario::default(),
+            format: SinkFormat::Parquet,
+            batch_rows: 1800,
+            max_rss_growth_mb: 64.0,
+            max_fd_growth: 8,
+            max_latency_drift_ms: 100.0,
+        }
+    }
+}
+
+impl SoakConfig {
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        serde_json::from_str(json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SoakSample {
+    pub elapsed_secs: f64,
+    pub rss_bytes: Option<u64>,
+    pub open_fds: Option<usize>,
+    pub latency_us: Option<f64>, // Worst of the streams
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SoakReport {
+    pub samples: Vec<SoakSample>,
+    pub stats: Vec<ShardStats>,
+    pub violations: Vec<String>, // Bounds exceeded
+}
+
+impl SoakReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    pub fn print(&self) {
+        for shard in &self.stats {
+            println!(
+                "{} frames, {} rows in {} batches, {} errors, {} dropped",
+                shard.frames, shard.rows, shard.batches, shard.errors, shard.dropped
+            );
+        }
+        if let (Some(first), Some(last)) = (self.samples.first(), self.samples.last()) {
+            println!(
+                "{} samples over {:.0} s, last: {}",
+                self.samples.len(),
+                last.elapsed_secs - first.elapsed_secs,
+                describe(last)
+            );
+        }
+        for violation in &self.violations {
+            println!("FAILED: {}", violation);
+        }
+        if self.is_ok() {
+            println!("Soak test passed");
+        }
+    }
+}
+
+fn describe(sample: &SoakSample) -> String {
+    let or_na = |value: Option<String>| value.unwrap_or_else(|| "n/a".to_string());
+    format!(
+        "rss {} MB, {} fds, latency {} ms",
+        or_na(sample.rss_bytes.map(|b| format!("{:.1}", b as f64 / 1e6))),
+        or_na(sample.open_fds.map(|n| n.to_string())),
+        or_na(sample.latency_us.map(|us| format!("{:.1}", us / 1000.0)))
+    )
+}
+
+// Resident set size of this process.
+pub fn rss_bytes() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+// File descriptors open in this process, sockets included.
+pub fn open_fds() -> Option<usize> {
+    Some(fs::read_dir("/proc/self/fd").ok()?.count())
+}
+
+fn sample(metrics: &Metrics, elapsed_secs: f64) -> SoakSample {
+    SoakSample {
+        elapsed_secs,
+        rss_bytes: rss_bytes(),
+        open_fds: open_fds(),
+        latency_us: metrics
+            .gauge_series(LATENCY_METRIC)
+            .into_values()
+            .reduce(f64::max),
+    }
+}
+
+// Bounds the samples and pipeline statistics exceed, empty when passing.
+pub fn check(config: &SoakConfig, samples: &[SoakSample], stats: &[ShardStats]) -> Vec<String> {
+    let mut violations = Vec::new();
+    let settled: Vec<&SoakSample> = samples
+        .iter()
+        .filter(|s| s.elapsed_secs >= config.warmup_secs)
+        .collect();
+    if settled.len() < 2 {
+        violations.push(format!(
+            "{} samples after the warm-up, at least 2 needed",
+            settled.len()
+        ));
+        return violations;
+    }
+    let (first, last) = (settled[0], settled[settled.len() - 1]);
+
+    if let (Some(start), Some(end)) = (first.rss_bytes, last.rss_bytes) {
+        let growth_mb = (end as f64 - start as f64) / 1e6;
+        if growth_mb > config.max_rss_growth_mb {
+            violations.push(format!(
+                "Memory grew {:.1} MB, more than {} MB",
+                growth_mb, config.max_rss_growth_mb
+            ));
+        }
+    }
+    if let (Some(start), Some(end)) = (first.open_fds, last.open_fds) {
+        let growth = end as i64 - start as i64;
+        if growth > config.max_fd_growth {
+            violations.push(format!(
+                "Open file descriptors grew by {}, more than {}",
+                growth, config.max_fd_growth
+            ));
+        }
+    }
+    let tenth = (settled.len() / 10).max(1);
+    let mean_latency = |samples: &[&SoakSample]| {
+        let values: Vec<f64> = samples.iter().filter_map(|s| s.latency_us).collect();
+        (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+    };
+    match (
+        mean_latency(&settled[..tenth]),
+        mean_latency(&settled[settled.len() - tenth..]),
+    ) {
+        (Some(start), Some(end)) => {
+            let drift_ms = (end - start) / 1000.0;
+            if drift_ms > config.max_latency_drift_ms {
+                violations.push(format!(
+                    "Latency drifted {:.1} ms, more than {} ms",
+                    drift_ms, config.max_latency_drift_ms
+                ));
+            }
+        }
+        _ => violations.push("No latency measured, no frames reached the pipeline".to_string()),
+    }
+
+    let total = stats
+        .iter()
+        .fold(ShardStats::default(), |mut total, shard| {
+            total.frames += shard.frames;
+            total.errors += shard.errors;
+            total.dropped += shard.dropped;
+            total
+        });
+    if total.frames == 0 {
+        violations.push("No frames collected".to_string());
+    }
+    if total.errors > 0 || total.dropped > 0 {
+        violations.push(format!(
+            "{} errors and {} frames dropped",
+            total.errors, total.dropped
+        ));
+    }
+    violations
+}
+
+// Run the soak test, the sink writing under dir.
+pub async fn run_soak(config: &SoakConfig, dir: &Path) -> io::Result<SoakReport> {
+    if config.sample_secs <= 0.0 || config.duration_secs < config.sample_secs {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The soak test needs a positive sample interval within its duration",
+        ));
+    }
+    let server_config =
+        ServerConfig::new("127.0.0.1".to_string(), config.port, Protocol::TCP, 30.0)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .with_scenario(config.scenario.clone());
+    let server = tokio::spawn(async move {
+        if let Err(e) = run_mock_server(server_config).await {
+            println!("Soak server error: {}", e);
+        }
+    });
+    time::sleep(Duration::from_millis(200)).await;
+
+    let pipeline_config: PipelineConfig = serde_json::from_value(serde_json::json!({
+        "streams": [{"host": "127.0.0.1", "port": config.port}],
+        "sink": {"format": config.format, "dir": dir},
+        "batch_rows": config.batch_rows,
+    }))
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let metrics = Arc::new(Metrics::new());
+    let pipeline = Arc::new(Pipeline::new(pipeline_config).with_metrics(metrics.clone()));
+    let runner = pipeline.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+
+    let start = Instant::now();
+    let mut samples = Vec::new();
+    let mut ticker = time::interval(Duration::from_secs_f64(config.sample_secs));
+    ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    ticker.tick().await;
+    while start.elapsed().as_secs_f64() < config.duration_secs {
+        ticker.tick().await;
+        let sample = sample(&metrics, start.elapsed().as_secs_f64());
+        println!("{:.0} s: {}", sample.elapsed_secs, describe(&sample));
+        samples.push(sample);
+    }
+
+    pipeline.stop();
+    let stats = run.await.map_err(io::Error::other)??;
+    server.abort();
+    let violations = check(config, &samples, &stats);
+    Ok(SoakReport {
+        samples,
+        stats,
+        violations,
+    })
+}
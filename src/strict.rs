@@ -0,0 +1,261 @@
+// Strict checking of reserved and invalid field values.
+//
+// The parser accepts whatever it can make sense of, which is what a running
+// system wants. A device being commissioned should instead be held to IEEE
+// C37.118.2-2011, so its misconfigurations show up before it goes live.
+// StrictChecker reports every field of a frame that uses a reserved or
+// invalid value:
+//
+//   every frame     SYNC leading byte, reserved bit 7 and frame types 6-7,
+//                   versions 0 and 4-15, IDCODE 0 and 65535, reserved bit 7
+//                   and codes 12-14 of the message time quality
+//   configuration   reserved TIME_BASE bits 31-24 or TIME_BASE 0, NUM_PMU 0,
+//                   DATA_RATE 0, PMU IDCODE 0 and 65535, FORMAT bits 15-4,
+//                   PHUNIT types 2-255, ANUNIT types 3-64, FNOM bits 15-1
+//   data            FRACSEC not below TIME_BASE, STAT trigger reason 6,
+//                   against the last configuration of the stream that passed
+//   command         CMD 7 and 0x1000-0xFFFF
+//
+// Frames of version 3 (C37.118.2-2024) are only checked for their prefix.
+use crate::frame_parser::parse_config_frame_1and2;
+use crate::frames::ConfigurationFrame1and2_2011;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub field: String, // e.g. "PMU 2 (Station B) FORMAT"
+    pub value: String,
+    pub rule: &'static str,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {}: {}", self.field, self.value, self.rule)
+    }
+}
+
+fn violation(field: impl Into<String>, value: impl fmt::Display, rule: &'static str) -> Violation {
+    Violation {
+        field: field.into(),
+        value: value.to_string(),
+        rule,
+    }
+}
+
+// Violations of the 14 byte prefix every frame starts with.
+pub fn check_prefix(frame: &[u8]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    if frame.len() < 14 {
+        violations.push(violation(
+            "frame",
+            format!("{} bytes", frame.len()),
+            "shorter than the prefix",
+        ));
+        return violations;
+    }
+    if frame[0] != 0xAA {
+        violations.push(violation(
+            "SYNC",
+            format!("0x{:02X}{:02X}", frame[0], frame[1]),
+            "leading byte is not 0xAA",
+        ));
+    }
+    if frame[1] & 0x80 != 0 {
+        violations.push(violation(
+            "SYNC",
+            format!("0x{:02X}{:02X}", frame[0], frame[1]),
+            "reserved bit 7 set",
+        ));
+    }
+    let frame_type = (frame[1] >> 4) & 0x07;
+    if frame_type >= 6 {
+        violations.push(violation("SYNC frame type", frame_type, "reserved"));
+    }
+    let version = frame[1] & 0x0F;
+    if version == 0 || version > 3 {
+        violations.push(violation("SYNC version", version, "reserved"));
+    }
+    let framesize = u16::from_be_bytes([frame[2], frame[3]]);
+    if framesize as usize != frame.len() {
+        violations.push(violation(
+            "FRAMESIZE",
+            framesize,
+            "differs from the bytes received",
+        ));
+    }
+    let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+    if idcode == 0 || idcode == u16::MAX {
+        violations.push(violation("IDCODE", idcode, "reserved"));
+    }
+    let quality = frame[10];
+    if quality & 0x80 != 0 {
+        violations.push(violation(
+            "FRACSEC time quality",
+            format!("0x{:02X}", quality),
+            "reserved bit 7 set",
+        ));
+    }
+    if (0x0C..=0x0E).contains(&(quality & 0x0F)) {
+        violations.push(violation(
+            "FRACSEC time quality code",
+            quality & 0x0F,
+            "reserved",
+        ));
+    }
+    violations
+}
+
+// Violations of the fields of a configuration frame (1 or 2).
+pub fn check_config(config: &ConfigurationFrame1and2_2011) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    if config.time_base >> 24 != 0 {
+        violations.push(violation(
+            "TIME_BASE",
+            format!("0x{:08X}", config.time_base),
+            "reserved bits 31-24 set",
+        ));
+    }
+    if config.time_base & 0x00FF_FFFF == 0 {
+        violations.push(violation("TIME_BASE", 0, "must be positive"));
+    }
+    if config.pmu_configs.is_empty() {
+        violations.push(violation("NUM_PMU", 0, "must be positive"));
+    }
+    if config.data_rate == 0 {
+        violations.push(violation("DATA_RATE", 0, "must not be 0"));
+    }
+    for (index, pmu) in config.pmu_configs.iter().enumerate() {
+        let station = String::from_utf8_lossy(&pmu.stn).trim().to_string();
+        let field = |name: &str| format!("PMU {} ({}) {}", index + 1, station, name);
+        if pmu.idcode == 0 || pmu.idcode == u16::MAX {
+            violations.push(violation(field("IDCODE"), pmu.idcode, "reserved"));
+        }
+        if pmu.format.reserved != 0 {
+            violations.push(violation(
+                field("FORMAT"),
+                format!("0x{:04X}", u16::from(pmu.format)),
+                "reserved bits 15-4 set",
+            ));
+        }
+        for (channel, unit) in pmu.phunit.iter().enumerate() {
+            if unit >> 24 > 1 {
+                violations.push(violation(
+                    field(&format!("PHUNIT {}", channel + 1)),
+                    format!("0x{:08X}", unit),
+                    "reserved type, 0 (voltage) or 1 (current) expected",
+                ));
+            }
+        }
+        for (channel, unit) in pmu.anunit.iter().enumerate() {
+            if (3..=64).contains(&(unit >> 24)) {
+                violations.push(violation(
+                    field(&format!("ANUNIT {}", channel + 1)),
+                    format!("0x{:08X}", unit),
+                    "reserved type",
+                ));
+            }
+        }
+        if pmu.fnom & 0xFFFE != 0 {
+            violations.push(violation(
+                field("FNOM"),
+                format!("0x{:04X}", pmu.fnom),
+                "reserved bits 15-1 set",
+            ));
+        }
+    }
+    violations
+}
+
+// Violations of a data frame of a configuration.
+pub fn check_data(frame: &[u8], config: &ConfigurationFrame1and2_2011) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let fracsec = u32::from_be_bytes([0, frame[11], frame[12], frame[13]]);
+    let time_base = config.time_base & 0x00FF_FFFF;
+    if time_base > 0 && fracsec >= time_base {
+        violations.push(violation(
+            "FRACSEC",
+            fracsec,
+            "not below TIME_BASE of the configuration",
+        ));
+    }
+    if frame.len() != config.calc_data_frame_size() {
+        // The fields are not where the configuration has them
+        return violations;
+    }
+    let mut offset = 14;
+    for (index, pmu) in config.pmu_configs.iter().enumerate() {
+        let stat = u16::from_be_bytes([frame[offset], frame[offset + 1]]);
+        if stat & 0x000F == 0x0006 {
+            let station = String::from_utf8_lossy(&pmu.stn).trim().to_string();
+            violations.push(violation(
+                format!("PMU {} ({}) STAT trigger reason", index + 1, station),
+                6,
+                "reserved",
+            ));
+        }
+        offset += 2
+            + pmu.phasor_size() * pmu.phnmr as usize
+            + 2 * pmu.freq_dfreq_size()
+            + pmu.analog_size() * pmu.annmr as usize
+            + 2 * pmu.dgnmr as usize;
+    }
+    violations
+}
+
+// Violations of a command frame.
+pub fn check_command(frame: &[u8]) -> Vec<Violation> {
+    let Some(cmd) = frame.get(14..16) else {
+        return vec![violation(
+            "CMD",
+            "missing",
+            "command frame without a command",
+        )];
+    };
+    let cmd = u16::from_be_bytes([cmd[0], cmd[1]]);
+    if cmd == 0x0007 || cmd >= 0x1000 {
+        vec![violation("CMD", format!("0x{:04X}", cmd), "reserved")]
+    } else {
+        Vec::new()
+    }
+}
+
+// Checks frames as they are received, keeping the configuration of every
+// stream to check its data frames against.
+#[derive(Default)]
+pub struct StrictChecker {
+    configs: HashMap<u16, ConfigurationFrame1and2_2011>,
+}
+
+impl StrictChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Violations of a frame, none when it may be used.
+    pub fn check(&mut self, frame: &[u8]) -> Vec<Violation> {
+        let mut violations = check_prefix(frame);
+        if frame.len() < 16 || frame[1] & 0x0F == 3 {
+            return violations;
+        }
+        let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+        match (frame[1] >> 4) & 0x07 {
+            0 => {
+                if let Some(config) = self.configs.get(&idcode) {
+                    violations.extend(check_data(frame, config));
+                }
+            }
+            2 | 3 => {
+                if let Ok(config) = parse_config_frame_1and2(frame) {
+                    violations.extend(check_config(&config));
+                    if violations.is_empty() {
+                        self.configs.insert(idcode, config);
+                    }
+                }
+            }
+            4 => violations.extend(check_command(frame)),
+            _ => {}
+        }
+        violations
+    }
+}
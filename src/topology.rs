@@ -0,0 +1,144 @@
+// Mapping of channels to the elements of a network model.
+//
+// Model-based analytics (state estimation, model validation) need to know
+// which bus or branch of the model a measurement belongs to. A mapping file,
+// a CSV file with one channel per line, tells them:
+//
+//   channel,bus,branch,mrid
+//   Station A_7734_VA,BUS_7,,_5f2c1a4e-0c43-4b8e-9d41-2b6f0b1c7e10
+//   Station A_7734_IA,BUS_7,LINE_7_8,_9d1e3b2a-6f5c-4d7e-8a90-1c2b3d4e5f60
+//
+// Channels are named as in the pmu.channel metadata of their columns, after
+// remapping; derived channels and SCADA points can be mapped too. Any of the
+// elements may be left empty. Lines starting with # and a channel,...
+// header are skipped.
+//
+// The elements are attached to every column of the channel as metadata
+// (pmu.bus, pmu.branch, pmu.mrid), which goes with the batches into the
+// sinks: Parquet keeps it in the schema, HDF5 as attributes and TimescaleDB
+// in the pmu_channels catalog.
+use crate::arrow_utils::{META_BRANCH, META_BUS, META_CHANNEL, META_MRID};
+use arrow::datatypes::Schema;
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModelElement {
+    pub bus: Option<String>,
+    pub branch: Option<String>,
+    pub mrid: Option<String>, // CIM mRID of the measured element
+}
+
+impl ModelElement {
+    // Metadata keys and values of the element, empty ones left out.
+    pub fn metadata(&self) -> Vec<(&'static str, &str)> {
+        [
+            (META_BUS, &self.bus),
+            (META_BRANCH, &self.branch),
+            (META_MRID, &self.mrid),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.as_deref().map(|value| (key, value)))
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Topology {
+    channels: HashMap<String, ModelElement>,
+}
+
+impl Topology {
+    pub fn from_csv(text: &str) -> Result<Self, String> {
+        let mut topology = Topology::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("channel,") {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [channel, bus, branch, mrid] = fields[..] else {
+                return Err(format!(
+                    "Line {}: Expected channel,bus,branch,mrid, got {:?}",
+                    number + 1,
+                    line
+                ));
+            };
+            let text = |field: &str| (!field.is_empty()).then(|| field.to_string());
+            let element = ModelElement {
+                bus: text(bus),
+                branch: text(branch),
+                mrid: text(mrid),
+            };
+            if channel.is_empty() || element == ModelElement::default() {
+                return Err(format!(
+                    "Line {}: Expected a channel and an element, got {:?}",
+                    number + 1,
+                    line
+                ));
+            }
+            if topology
+                .channels
+                .insert(channel.to_string(), element)
+                .is_some()
+            {
+                return Err(format!(
+                    "Line {}: Channel {} mapped twice",
+                    number + 1,
+                    channel
+                ));
+            }
+        }
+        Ok(topology)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_csv(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn element(&self, channel: &str) -> Option<&ModelElement> {
+        self.channels.get(channel)
+    }
+
+    // The batch with the elements in the metadata of the columns of mapped
+    // channels.
+    pub fn apply(&self, batch: &RecordBatch) -> Result<RecordBatch, ArrowError> {
+        let schema = batch.schema();
+        let fields: Vec<_> = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                let element = field
+                    .metadata()
+                    .get(META_CHANNEL)
+                    .and_then(|channel| self.channels.get(channel));
+                let Some(element) = element else {
+                    return field.as_ref().clone();
+                };
+                let mut metadata = field.metadata().clone();
+                for (key, value) in element.metadata() {
+                    metadata.insert(key.to_string(), value.to_string());
+                }
+                field.as_ref().clone().with_metadata(metadata)
+            })
+            .collect();
+        RecordBatch::try_new(
+            Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone())),
+            batch.columns().to_vec(),
+        )
+    }
+}
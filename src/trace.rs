@@ -0,0 +1,437 @@
+// Latency of data frames through the pipeline, by stage.
+//
+// A sampled frame gets a trace when the PDC client receives it, and the
+// shard writer stamps it as it passes the later stages:
+//
+// - receive: from the frame's SOC/FRACSEC to its arrival at the client, the
+//   PMU, PDC and network;
+// - parse: on to the writer having checked the frame and buffered it in the
+//   accumulator, including the wait in the stream's queue;
+// - align: on to the batch holding the frame being built, i.e. the wait for
+//   the batch to fill plus parsing the columns;
+// - flush: on to the batch written to every sink, derived channels, SCADA
+//   and topology columns included.
+//
+// The time spent in every stage is added to a histogram of the stage, and
+// the total to one of its own, so a slow parser, aggregator or sink shows
+// up as the stage where the time goes. Traces not completed, e.g. of frames
+// dropped on the way, are abandoned when too many are open.
+//
+// Frames are known by their stream's idcode and their timestamp: the idcode
+// as received until the writer parses them, the remapped one after.
+use crate::arrow_utils::frame_timestamp_micros_with;
+use crate::metrics::Metrics;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+pub const STAGE_LATENCY_METRIC: &str = "pmu_pipeline_stage_latency_us";
+
+// Upper bounds of the histogram buckets, with one more for anything above.
+pub const BUCKET_BOUNDS_US: [i64; 19] = [
+    100,
+    250,
+    500,
+    1_000,
+    2_500,
+    5_000,
+    10_000,
+    25_000,
+    50_000,
+    100_000,
+    250_000,
+    500_000,
+    1_000_000,
+    2_500_000,
+    5_000_000,
+    10_000_000,
+    30_000_000,
+    60_000_000,
+    120_000_000,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    Receive,
+    Parse,
+    Align,
+    Flush,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [Stage::Receive, Stage::Parse, Stage::Align, Stage::Flush];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Receive => "receive",
+            Stage::Parse => "parse",
+            Stage::Align => "align",
+            Stage::Flush => "flush",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceConfig {
+    // Trace one data frame in this many of each stream, 1 for all
+    #[serde(default = "default_sample_every")]
+    pub sample_every: u64,
+    // Traces kept open at most, the oldest abandoned beyond
+    #[serde(default = "default_max_open")]
+    pub max_open: usize,
+}
+
+fn default_sample_every() -> u64 {
+    30
+}
+
+fn default_max_open() -> usize {
+    100_000
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        TraceConfig {
+            sample_every: default_sample_every(),
+            max_open: default_max_open(),
+        }
+    }
+}
+
+impl TraceConfig {
+    pub fn with_sample_every(mut self, frames: u64) -> Self {
+        self.sample_every = frames;
+        self
+    }
+}
+
+// Times of one frame, microseconds since the epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTrace {
+    pub idcode: u16, // As received
+    pub frame_us: i64,
+    pub stages: [Option<i64>; 4], // When each stage ended, by Stage::ALL
+}
+
+impl FrameTrace {
+    fn new(idcode: u16, frame_us: i64) -> Self {
+        FrameTrace {
+            idcode,
+            frame_us,
+            stages: [None; 4],
+        }
+    }
+
+    fn stamp(&mut self, stage: Stage, at_us: i64) {
+        self.stages[stage as usize] = Some(at_us);
+    }
+
+    // Time spent in a stage, from the end of the one before or the frame's
+    // timestamp.
+    pub fn stage_us(&self, stage: Stage) -> Option<i64> {
+        let index = stage as usize;
+        let start = match index {
+            0 => self.frame_us,
+            _ => self.stages[index - 1]?,
+        };
+        Some(self.stages[index]? - start)
+    }
+
+    pub fn total_us(&self) -> Option<i64> {
+        Some(self.stages[Stage::Flush as usize]? - self.frame_us)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    pub counts: [u64; BUCKET_BOUNDS_US.len() + 1], // By BUCKET_BOUNDS_US, then above
+    pub count: u64,
+    pub sum_us: i64,
+    pub max_us: i64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: [0; BUCKET_BOUNDS_US.len() + 1],
+            count: 0,
+            sum_us: 0,
+            max_us: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, value_us: i64) {
+        let bucket = BUCKET_BOUNDS_US.partition_point(|bound| *bound < value_us);
+        self.counts[bucket] += 1;
+        self.max_us = if self.count == 0 {
+            value_us
+        } else {
+            self.max_us.max(value_us)
+        };
+        self.count += 1;
+        self.sum_us += value_us;
+    }
+
+    pub fn mean_us(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum_us as f64 / self.count as f64)
+    }
+
+    // Upper bound of the bucket holding the quantile, the maximum for the
+    // last bucket. None when empty.
+    pub fn quantile_us(&self, quantile: f64) -> Option<i64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(match BUCKET_BOUNDS_US.get(bucket) {
+                    Some(bound) => (*bound).min(self.max_us),
+                    None => self.max_us,
+                });
+            }
+        }
+        Some(self.max_us)
+    }
+}
+
+// Histograms of the stages and of the total, and the traces counted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceSummary {
+    pub stages: [Histogram; 4], // By Stage::ALL
+    pub total: Histogram,
+    pub completed: u64,
+    pub abandoned: u64,
+}
+
+impl TraceSummary {
+    pub fn stage(&self, stage: Stage) -> &Histogram {
+        &self.stages[stage as usize]
+    }
+
+    // The stage with the largest mean, where the time goes.
+    pub fn slowest(&self) -> Option<Stage> {
+        Stage::ALL
+            .into_iter()
+            .filter_map(|stage| Some((stage, self.stage(stage).mean_us()?)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(stage, _)| stage)
+    }
+}
+
+impl fmt::Display for TraceSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} frames traced, {} abandoned",
+            self.completed, self.abandoned
+        )?;
+        let rows = Stage::ALL
+            .iter()
+            .map(|stage| (stage.name(), self.stage(*stage)))
+            .chain([("total", &self.total)]);
+        for (name, histogram) in rows {
+            let ms = |us: Option<i64>| {
+                us.map_or("-".to_string(), |us| format!("{:.1}", us as f64 / 1000.0))
+            };
+            writeln!(
+                f,
+                "{:<8} mean {} ms, p50 {} ms, p99 {} ms, max {} ms",
+                name,
+                histogram
+                    .mean_us()
+                    .map_or("-".to_string(), |us| format!("{:.1}", us / 1000.0)),
+                ms(histogram.quantile_us(0.5)),
+                ms(histogram.quantile_us(0.99)),
+                ms((histogram.count > 0).then_some(histogram.max_us)),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Traces {
+    received: HashMap<(u16, i64), FrameTrace>, // Waiting for the writer
+    parsed: HashMap<u16, VecDeque<FrameTrace>>, // Waiting for their batch, by timestamp
+    order: VecDeque<(u16, i64)>,               // Received, oldest first
+    open: usize,
+    seen: HashMap<u16, u64>, // Data frames by stream, for sampling
+    summary: TraceSummary,
+}
+
+// Shared by the clients and the shard writers of a pipeline.
+pub struct LatencyTracer {
+    config: TraceConfig,
+    traces: Mutex<Traces>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl LatencyTracer {
+    pub fn new(config: TraceConfig) -> Self {
+        LatencyTracer {
+            config,
+            traces: Mutex::new(Traces::default()),
+            metrics: None,
+        }
+    }
+
+    // Set the p50, p99 and maximum of every stage as gauges,
+    // pmu_pipeline_stage_latency_us{stage="parse",quantile="0.99"}.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        metrics.describe(
+            STAGE_LATENCY_METRIC,
+            "Microseconds spent by traced data frames in each pipeline stage",
+        );
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn config(&self) -> &TraceConfig {
+        &self.config
+    }
+
+    // A data frame arrived at a client, traced when sampled.
+    pub fn received(&self, frame: &[u8], time_base: u32, arrival_us: i64) {
+        let Some(frame_us) = frame_timestamp_micros_with(frame, time_base) else {
+            return;
+        };
+        let idcode = u16::from_be_bytes([frame[4], frame[5]]);
+        let mut traces = self.traces.lock().unwrap();
+        let seen = traces.seen.entry(idcode).or_default();
+        *seen += 1;
+        if !(*seen - 1).is_multiple_of(self.config.sample_every.max(1)) {
+            return;
+        }
+        let mut trace = FrameTrace::new(idcode, frame_us);
+        trace.stamp(Stage::Receive, arrival_us);
+        if traces.received.insert((idcode, frame_us), trace).is_none() {
+            traces.order.push_back((idcode, frame_us));
+            traces.open += 1;
+        }
+        while traces.open > self.config.max_open.max(1) {
+            traces.abandon_oldest();
+        }
+        // Completed traces are only dropped from the order now and then
+        if traces.order.len() > 2 * self.config.max_open.max(1) {
+            let Traces {
+                order,
+                received,
+                parsed,
+                ..
+            } = &mut *traces;
+            order.retain(|key| received.contains_key(key) || is_parsed(parsed, *key));
+        }
+    }
+
+    // The writer buffered a frame in the accumulator, under the stream's
+    // idcode after remapping.
+    pub fn parsed(&self, received_idcode: u16, idcode: u16, frame_us: i64, at_us: i64) {
+        let mut traces = self.traces.lock().unwrap();
+        let Some(mut trace) = traces.received.remove(&(received_idcode, frame_us)) else {
+            return;
+        };
+        trace.stamp(Stage::Parse, at_us);
+        let parsed = traces.parsed.entry(idcode).or_default();
+        let position = parsed.partition_point(|t| t.frame_us <= frame_us);
+        parsed.insert(position, trace);
+    }
+
+    // The batch of a stream up to last_us was built at aligned_us and
+    // written to the sinks at flushed_us.
+    pub fn batch_written(&self, idcode: u16, last_us: i64, aligned_us: i64, flushed_us: i64) {
+        let completed = {
+            let mut traces = self.traces.lock().unwrap();
+            let Some(parsed) = traces.parsed.get_mut(&idcode) else {
+                return;
+            };
+            let count = parsed.partition_point(|t| t.frame_us <= last_us);
+            let done: Vec<FrameTrace> = parsed.drain(..count).collect();
+            for mut trace in done.iter().copied() {
+                trace.stamp(Stage::Align, aligned_us);
+                trace.stamp(Stage::Flush, flushed_us);
+                traces.complete(&trace);
+            }
+            !done.is_empty()
+        };
+        if completed {
+            self.export();
+        }
+    }
+
+    pub fn summary(&self) -> TraceSummary {
+        self.traces.lock().unwrap().summary.clone()
+    }
+
+    fn export(&self) {
+        let Some(metrics) = &self.metrics else {
+            return;
+        };
+        let summary = self.summary();
+        let rows = Stage::ALL
+            .iter()
+            .map(|stage| (stage.name(), summary.stage(*stage)))
+            .chain([("total", &summary.total)]);
+        for (stage, histogram) in rows {
+            for (quantile, value) in [
+                ("0.5", histogram.quantile_us(0.5)),
+                ("0.99", histogram.quantile_us(0.99)),
+                ("1", (histogram.count > 0).then_some(histogram.max_us)),
+            ] {
+                if let Some(value) = value {
+                    metrics.set_gauge(
+                        STAGE_LATENCY_METRIC,
+                        &[("stage", stage), ("quantile", quantile)],
+                        value as f64,
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Traces {
+    fn complete(&mut self, trace: &FrameTrace) {
+        for stage in Stage::ALL {
+            if let Some(us) = trace.stage_us(stage) {
+                self.summary.stages[stage as usize].record(us);
+            }
+        }
+        if let Some(us) = trace.total_us() {
+            self.summary.total.record(us);
+        }
+        self.summary.completed += 1;
+        self.open -= 1;
+    }
+
+    // Drop the oldest open trace, wherever it is.
+    fn abandon_oldest(&mut self) {
+        while let Some(key) = self.order.pop_front() {
+            let removed = self.received.remove(&key).is_some()
+                || self.parsed.values_mut().any(|parsed| {
+                    match parsed.iter().position(|t| (t.idcode, t.frame_us) == key) {
+                        Some(position) => parsed.remove(position).is_some(),
+                        None => false,
+                    }
+                });
+            if removed {
+                self.open -= 1;
+                self.summary.abandoned += 1;
+                return;
+            }
+        }
+    }
+}
+
+fn is_parsed(parsed: &HashMap<u16, VecDeque<FrameTrace>>, (idcode, frame_us): (u16, i64)) -> bool {
+    parsed.values().any(|traces| {
+        traces
+            .iter()
+            .any(|t| (t.idcode, t.frame_us) == (idcode, frame_us))
+    })
+}
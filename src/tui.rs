@@ -0,0 +1,561 @@
+// Terminal monitor of live PDC streams.
+//
+// A quick field tool on top of PDCClient: one worker per stream connects,
+// starts the transmission and reports the client status and the latest data
+// frame. The screen lists every stream with its state, data rate and
+// latency, the latest frequency and voltage magnitudes of the selected
+// stream, and the recent stall/resume events.
+//
+// Keys: Up/Down (or k/j) select a stream, s starts and x stops its
+// transmission, q or Esc quits. Streams start when the monitor starts.
+//
+// The clients log to stdout, so the screen is drawn on stderr: redirect
+// stdout (pmu monitor ... > monitor.log) to keep it clean.
+use crate::events::{Event, EventBus, Severity};
+use crate::frames::{ConfigurationFrame1and2_2011, PMUConfigurationFrame2011};
+use crate::pdc_client::{ClientState, ClientStatus, ControlMessage, PDCClient};
+use crate::queue::Rings;
+use crate::reports::format_time;
+use ratatui::crossterm::event::{self as term, Event as TermEvent, KeyCode, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::prelude::CrosstermBackend;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Row, Table, TableState};
+use ratatui::{Frame, Terminal};
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+
+const MAX_EVENTS: usize = 100; // Kept for the events pane
+const REFRESH: Duration = Duration::from_millis(250);
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+const BUFFER_DURATION: Duration = Duration::from_secs(10); // Of the clients
+
+// A stream to monitor: host:port/idcode, optionally prefixed with name=.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamTarget {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub idcode: u16,
+}
+
+impl StreamTarget {
+    pub fn parse(target: &str) -> io::Result<Self> {
+        let invalid = |message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} in stream {:?}, expected [name=]host:port/idcode",
+                    message, target
+                ),
+            )
+        };
+        let (name, address) = match target.split_once('=') {
+            Some((name, address)) => (Some(name.trim()), address.trim()),
+            None => (None, target.trim()),
+        };
+        let (host_port, idcode) = address
+            .rsplit_once('/')
+            .ok_or_else(|| invalid("Missing idcode"))?;
+        let (host, port) = host_port
+            .rsplit_once(':')
+            .ok_or_else(|| invalid("Missing port"))?;
+        if host.is_empty() {
+            return Err(invalid("Missing host"));
+        }
+        let port = port.parse().map_err(|_| invalid("Invalid port"))?;
+        let idcode = idcode.parse().map_err(|_| invalid("Invalid idcode"))?;
+        Ok(StreamTarget {
+            name: name.unwrap_or(address).to_string(),
+            host: host.to_string(),
+            port,
+            idcode,
+        })
+    }
+}
+
+// Latest values of one PMU of a data frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub station: String,
+    pub idcode: u16,
+    pub frequency: f64,               // Hz
+    pub voltages: Vec<(String, f64)>, // Voltage phasor magnitudes, V
+}
+
+fn channel_name(pmu: &PMUConfigurationFrame2011, index: usize) -> String {
+    pmu.chnam
+        .get(16 * index..16 * index + 16)
+        .map(|name| String::from_utf8_lossy(name).trim().to_string())
+        .unwrap_or_default()
+}
+
+fn be16(bytes: &[u8]) -> i16 {
+    i16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn f32_at(bytes: &[u8]) -> f64 {
+    f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+}
+
+// Frequency and voltage magnitudes of every PMU of a data frame. PMUs that
+// do not fit in the frame are left out.
+pub fn readings(frame: &[u8], config: &ConfigurationFrame1and2_2011) -> Vec<Reading> {
+    let mut readings = Vec::new();
+    let mut offset = 14; // After the prefix
+    for pmu in &config.pmu_configs {
+        let phasors = pmu.phnmr as usize * pmu.phasor_size();
+        let size = 2
+            + phasors
+            + 2 * pmu.freq_dfreq_size()
+            + pmu.annmr as usize * pmu.analog_size()
+            + 2 * pmu.dgnmr as usize;
+        if frame.len() < offset + size + 2 {
+            break;
+        }
+        let mut voltages = Vec::new();
+        let mut at = offset + 2; // After STAT
+        for k in 0..pmu.phnmr as usize {
+            let bytes = &frame[at..at + pmu.phasor_size()];
+            at += pmu.phasor_size();
+            let unit = pmu.phunit.get(k).copied().unwrap_or(0);
+            if unit >> 24 != 0 {
+                continue; // Current
+            }
+            let scale = (unit & 0x00FF_FFFF) as f64 / 100_000.0;
+            let magnitude = match (pmu.format.phasor_float, pmu.is_phasor_polar()) {
+                (true, true) => f32_at(&bytes[0..4]),
+                (true, false) => f32_at(&bytes[0..4]).hypot(f32_at(&bytes[4..8])),
+                (false, true) => be16(&bytes[0..2]) as u16 as f64 * scale,
+                (false, false) => {
+                    (be16(&bytes[0..2]) as f64).hypot(be16(&bytes[2..4]) as f64) * scale
+                }
+            };
+            voltages.push((channel_name(pmu, k), magnitude));
+        }
+        let frequency = if pmu.format.freq_float {
+            f32_at(&frame[at..at + 4])
+        } else {
+            let nominal = if pmu.fnom & 0x1 != 0 { 50.0 } else { 60.0 };
+            nominal + be16(&frame[at..at + 2]) as f64 / 1000.0
+        };
+        readings.push(Reading {
+            station: String::from_utf8_lossy(&pmu.stn).trim().to_string(),
+            idcode: pmu.idcode,
+            frequency,
+            voltages,
+        });
+        offset += size;
+    }
+    readings
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamState {
+    Idle, // Not started, or stopped from the monitor
+    Connecting,
+    Client(ClientState),
+    Failed(String), // Connecting failed
+}
+
+impl StreamState {
+    fn label(&self) -> String {
+        match self {
+            StreamState::Idle => "idle".to_string(),
+            StreamState::Connecting => "connecting".to_string(),
+            StreamState::Client(state) => format!("{:?}", state).to_lowercase(),
+            StreamState::Failed(_) => "failed".to_string(),
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            StreamState::Client(ClientState::Streaming) => Color::Green,
+            StreamState::Client(ClientState::Stalled) | StreamState::Connecting => Color::Yellow,
+            StreamState::Failed(_) => Color::Red,
+            _ => Color::Gray,
+        }
+    }
+}
+
+// What the monitor knows about one stream.
+#[derive(Debug, Clone)]
+pub struct StreamView {
+    pub target: StreamTarget,
+    pub state: StreamState,
+    pub status: Option<ClientStatus>, // Of the current connection
+    pub rate: f64,                    // Frames per second
+    pub readings: Vec<Reading>,
+    rate_from: Option<(Instant, u64)>, // Start of the rate window, frames then
+}
+
+impl StreamView {
+    fn new(target: StreamTarget) -> Self {
+        StreamView {
+            target,
+            state: StreamState::Idle,
+            status: None,
+            rate: 0.0,
+            readings: Vec::new(),
+            rate_from: None,
+        }
+    }
+
+    // The rate is measured over windows of at least a second.
+    pub fn update_status(&mut self, status: ClientStatus, now: Instant) {
+        self.state = StreamState::Client(status.state);
+        match self.rate_from {
+            Some((from, frames)) if status.frames_received >= frames => {
+                let elapsed = now.duration_since(from);
+                if elapsed >= RATE_WINDOW {
+                    self.rate = (status.frames_received - frames) as f64 / elapsed.as_secs_f64();
+                    self.rate_from = Some((now, status.frames_received));
+                }
+            }
+            _ => self.rate_from = Some((now, status.frames_received)),
+        }
+        self.status = Some(status);
+    }
+
+    fn set_state(&mut self, state: StreamState) {
+        if matches!(state, StreamState::Connecting) {
+            self.status = None;
+            self.rate_from = None;
+            self.readings.clear();
+        }
+        if !matches!(state, StreamState::Client(ClientState::Streaming)) {
+            self.rate = 0.0;
+        }
+        self.state = state;
+    }
+}
+
+// From the stream workers to the monitor.
+#[derive(Debug, Clone)]
+pub enum Update {
+    State(usize, StreamState),
+    Status(usize, ClientStatus),
+    Readings(usize, Vec<Reading>),
+}
+
+// What a key asks the monitor to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    None,
+    Start(usize),
+    Stop(usize),
+    Quit,
+}
+
+pub struct MonitorState {
+    pub streams: Vec<StreamView>,
+    pub events: VecDeque<Event>, // Newest first
+    pub selected: usize,
+}
+
+impl MonitorState {
+    pub fn new(targets: Vec<StreamTarget>) -> Self {
+        MonitorState {
+            streams: targets.into_iter().map(StreamView::new).collect(),
+            events: VecDeque::new(),
+            selected: 0,
+        }
+    }
+
+    pub fn handle_key(&mut self, key: KeyCode) -> Action {
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => Action::Quit,
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.selected = self.selected.saturating_sub(1);
+                Action::None
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                if self.selected + 1 < self.streams.len() {
+                    self.selected += 1;
+                }
+                Action::None
+            }
+            KeyCode::Char('s') if !self.streams.is_empty() => Action::Start(self.selected),
+            KeyCode::Char('x') if !self.streams.is_empty() => Action::Stop(self.selected),
+            _ => Action::None,
+        }
+    }
+
+    pub fn apply(&mut self, update: Update, now: Instant) {
+        match update {
+            Update::State(index, state) => self.streams[index].set_state(state),
+            Update::Status(index, status) => self.streams[index].update_status(status, now),
+            Update::Readings(index, readings) => self.streams[index].readings = readings,
+        }
+    }
+
+    pub fn push_event(&mut self, event: Event) {
+        self.events.push_front(event);
+        self.events.truncate(MAX_EVENTS);
+    }
+}
+
+fn latency_ms(status: &Option<ClientStatus>) -> (String, String) {
+    match status {
+        Some(status) if status.latency.frames > 0 => (
+            format!("{:.1}", status.latency.last_us as f64 / 1000.0),
+            format!("{:.1}", status.latency.jitter_us / 1000.0),
+        ),
+        _ => ("-".to_string(), "-".to_string()),
+    }
+}
+
+pub fn render(frame: &mut Frame, state: &MonitorState) {
+    let [streams_area, detail_area, events_area, help_area] = Layout::vertical([
+        Constraint::Length(state.streams.len() as u16 + 3),
+        Constraint::Min(6),
+        Constraint::Length(10),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let rows = state.streams.iter().map(|stream| {
+        let (latency, jitter) = latency_ms(&stream.status);
+        let (frames, stalls) = stream
+            .status
+            .map_or((0, 0), |s| (s.frames_received, s.stalls));
+        let frequency = stream.readings.first().map_or("-".to_string(), |reading| {
+            format!("{:.3}", reading.frequency)
+        });
+        Row::new(vec![
+            stream.target.name.clone(),
+            stream.state.label(),
+            format!("{:.1}", stream.rate),
+            latency,
+            jitter,
+            frames.to_string(),
+            stalls.to_string(),
+            frequency,
+        ])
+        .style(Style::default().fg(stream.state.color()))
+    });
+    let header = Row::new(vec![
+        "Stream", "State", "Rate/s", "Lat ms", "Jit ms", "Frames", "Stalls", "Freq Hz",
+    ])
+    .style(Style::default().add_modifier(Modifier::BOLD));
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Min(16),
+            Constraint::Length(10),
+            Constraint::Length(7),
+            Constraint::Length(8),
+            Constraint::Length(7),
+            Constraint::Length(9),
+            Constraint::Length(7),
+            Constraint::Length(9),
+        ],
+    )
+    .header(header)
+    .block(Block::bordered().title("Streams"))
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut table_state = TableState::default().with_selected(Some(state.selected));
+    frame.render_stateful_widget(table, streams_area, &mut table_state);
+
+    let mut lines = Vec::new();
+    if let Some(stream) = state.streams.get(state.selected) {
+        let target = &stream.target;
+        lines.push(Line::from(format!(
+            "{}:{} idcode {}",
+            target.host, target.port, target.idcode
+        )));
+        if let StreamState::Failed(error) = &stream.state {
+            lines.push(Line::from(format!("Error: {}", error)).style(Color::Red));
+        }
+        if let Some(status) = &stream.status {
+            if status.config_change_pending {
+                lines.push(Line::from("Configuration change pending").style(Color::Yellow));
+            }
+        }
+        for reading in &stream.readings {
+            lines.push(Line::from(format!(
+                "{} ({})  {:.4} Hz",
+                reading.station, reading.idcode, reading.frequency
+            )));
+            for (name, magnitude) in &reading.voltages {
+                lines.push(Line::from(format!("  {:<16} {:>12.1} V", name, magnitude)));
+            }
+        }
+    }
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("Latest values")),
+        detail_area,
+    );
+
+    let events = state.events.iter().map(|event| {
+        let color = match event.severity {
+            Severity::Info => Color::Gray,
+            Severity::Warning => Color::Yellow,
+            Severity::Alarm => Color::Red,
+        };
+        ListItem::new(format!(
+            "{}  {}  {}",
+            format_time(event.timestamp_us),
+            event.source,
+            event.message
+        ))
+        .style(color)
+    });
+    frame.render_widget(
+        List::new(events).block(Block::bordered().title("Events")),
+        events_area,
+    );
+    frame.render_widget(
+        Paragraph::new("Up/Down select  s start  x stop  q quit"),
+        help_area,
+    );
+}
+
+// Connects, streams until stopped, then waits to be started again. true on
+// commands starts, false stops.
+async fn stream_worker(
+    index: usize,
+    target: StreamTarget,
+    events: EventBus,
+    mut commands: mpsc::Receiver<bool>,
+    updates: mpsc::UnboundedSender<Update>,
+) {
+    while let Some(start) = commands.recv().await {
+        if !start {
+            continue;
+        }
+        let _ = updates.send(Update::State(index, StreamState::Connecting));
+        // Connecting panics when the configuration cannot be read
+        let connect = {
+            let target = target.clone();
+            tokio::spawn(async move {
+                PDCClient::new(&target.host, target.port, target.idcode, BUFFER_DURATION).await
+            })
+        };
+        let (client, control, _data) = match connect.await {
+            Ok(Ok(connected)) => connected,
+            Ok(Err(e)) => {
+                let _ = updates.send(Update::State(index, StreamState::Failed(e.to_string())));
+                continue;
+            }
+            Err(_) => {
+                let error = "no configuration from the server".to_string();
+                let _ = updates.send(Update::State(index, StreamState::Failed(error)));
+                continue;
+            }
+        };
+        let config = client.config.clone();
+        let mut frames = Rings::new();
+        let mut client = client
+            .with_frame_queue(frames.add(64))
+            .with_event_bus(events.clone());
+        let mut status = client.status_receiver();
+        let mut stream = tokio::spawn(async move { client.start_stream().await });
+        let mut refresh = tokio::time::interval(REFRESH);
+        loop {
+            tokio::select! {
+                _ = &mut stream => break,
+                command = commands.recv() => {
+                    if command != Some(true) {
+                        let _ = control.send(ControlMessage::Stop).await;
+                    }
+                    if command.is_none() {
+                        let _ = (&mut stream).await;
+                        return;
+                    }
+                }
+                _ = refresh.tick() => {
+                    let status = *status.borrow_and_update();
+                    let _ = updates.send(Update::Status(index, status));
+                    let mut latest = None;
+                    while let Some(frame) = frames.try_pop() {
+                        latest = Some(frame);
+                    }
+                    if let (Some(frame), Some(config)) = (latest, &config) {
+                        let _ = updates.send(Update::Readings(index, readings(&frame, config)));
+                    }
+                }
+            }
+        }
+        let _ = updates.send(Update::Status(index, *status.borrow()));
+        let _ = updates.send(Update::State(index, StreamState::Idle));
+    }
+}
+
+// Monitors the streams until q is pressed.
+pub async fn run(targets: Vec<StreamTarget>) -> io::Result<()> {
+    let events = EventBus::new(256);
+    let mut event_rx = events.subscribe();
+    let (update_tx, mut update_rx) = mpsc::unbounded_channel();
+    let mut workers = Vec::new();
+    for (index, target) in targets.iter().enumerate() {
+        let (command_tx, command_rx) = mpsc::channel(8);
+        let _ = command_tx.try_send(true);
+        let task = tokio::spawn(stream_worker(
+            index,
+            target.clone(),
+            events.clone(),
+            command_rx,
+            update_tx.clone(),
+        ));
+        workers.push((command_tx, task));
+    }
+    let mut state = MonitorState::new(targets);
+
+    enable_raw_mode()?;
+    execute!(io::stderr(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stderr()))?;
+    let result = async {
+        terminal.clear()?;
+        let mut refresh = tokio::time::interval(Duration::from_millis(100));
+        loop {
+            refresh.tick().await;
+            while term::poll(Duration::ZERO)? {
+                let TermEvent::Key(key) = term::read()? else {
+                    continue;
+                };
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match state.handle_key(key.code) {
+                    Action::Quit => return Ok(()),
+                    Action::Start(index) => {
+                        let _ = workers[index].0.try_send(true);
+                    }
+                    Action::Stop(index) => {
+                        let _ = workers[index].0.try_send(false);
+                    }
+                    Action::None => {}
+                }
+            }
+            while let Ok(update) = update_rx.try_recv() {
+                state.apply(update, Instant::now());
+            }
+            loop {
+                match event_rx.try_recv() {
+                    Ok(event) => state.push_event(event),
+                    Err(broadcast::error::TryRecvError::Lagged(_)) => {}
+                    Err(_) => break,
+                }
+            }
+            terminal.draw(|frame| render(frame, &state))?;
+        }
+    }
+    .await;
+    disable_raw_mode()?;
+    execute!(io::stderr(), LeaveAlternateScreen)?;
+
+    // Closing the command channels stops the streams
+    for (commands, task) in workers {
+        drop(commands);
+        let _ = task.await;
+    }
+    result
+}
@@ -0,0 +1,363 @@
+// Virtual PMUs: derived quantities served as a C37.118 stream.
+//
+// A VirtualStreamConfig describes PMUs whose channels are computed from the
+// PMUs of a source stream: sequence components of three phase phasors,
+// differences between phasor angles, and a frequency that is the weighted
+// mean of source frequencies, such as the frequency of an area. VirtualStream
+// resolves the channel names against the source configuration, makes a
+// configuration frame 2 for the virtual PMUs and then one data frame per
+// source data frame, at the same timestamp. Consumers that only speak
+// C37.118 get analytics results like any PMU's measurements.
+//
+// Channels are named as in the channel map, `Station A_7734_VA`, or by the
+// channel name alone when one source PMU has it. Frequencies are weighted by
+// FREQ channel, `Station A_7734_FREQ`, or by station name; without weights a
+// virtual PMU reports the mean frequency of the PMUs its phasors come from.
+//
+// Values are floating point: phasors as magnitude and angle, angle
+// differences as analogs in degrees, wrapped into (-180, 180]. The STAT of a
+// virtual PMU carries the data error and sync bits (15-13) of the source PMUs
+// its channels come from.
+use crate::analytics::accuracy::{frame_measurements, Phasor, PmuMeasurement};
+use crate::frame_parser::parse_config_frame_1and2;
+use crate::frames::{
+    calculate_crc, ConfigurationFrame1and2_2011, FormatFlags, PMUConfigurationFrame2011,
+    PrefixFrame2011,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const STAT_SOURCE_BITS: u16 = 0xE000; // Data error and PMU sync bits taken from the sources
+const FORMAT_FLOAT_POLAR: FormatFlags = FormatFlags {
+    freq_float: true,
+    analog_float: true,
+    phasor_float: true,
+    polar: true,
+    reserved: 0,
+};
+const PHUNIT_CURRENT: u32 = 0x0100_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SequenceComponent {
+    Positive,
+    Negative,
+    Zero,
+}
+
+// A sequence component of phases A, B and C.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SequencePhasor {
+    pub name: String,
+    pub component: SequenceComponent,
+    pub phases: [String; 3],
+    #[serde(default)]
+    pub current: bool,
+}
+
+// Angle of from less the angle of to, degrees.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AngleDifference {
+    pub name: String,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VirtualPmuConfig {
+    pub station: String,
+    pub idcode: u16,
+    #[serde(default)]
+    pub sequences: Vec<SequencePhasor>,
+    #[serde(default)]
+    pub angle_differences: Vec<AngleDifference>,
+    // Weight of each source frequency, by FREQ channel or station
+    #[serde(default)]
+    pub frequency_weights: BTreeMap<String, f64>,
+    #[serde(default)]
+    pub nominal_50hz: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VirtualStreamConfig {
+    pub idcode: u16,
+    pub pmus: Vec<VirtualPmuConfig>,
+}
+
+impl VirtualStreamConfig {
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        serde_json::from_str(json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+// A phasor of the source: PMU and phasor number.
+type PhasorRef = (usize, usize);
+
+struct ResolvedPmu {
+    sequences: Vec<(SequenceComponent, [PhasorRef; 3])>,
+    angle_differences: Vec<(PhasorRef, PhasorRef)>,
+    frequency_weights: Vec<(usize, f64)>, // Source PMU and weight
+    sources: Vec<usize>,                  // Source PMUs whose STAT is carried
+}
+
+pub struct VirtualStream {
+    source: ConfigurationFrame1and2_2011,
+    config: ConfigurationFrame1and2_2011,
+    pmus: Vec<ResolvedPmu>,
+}
+
+impl VirtualStream {
+    pub fn new(
+        config: VirtualStreamConfig,
+        source: &ConfigurationFrame1and2_2011,
+    ) -> io::Result<Self> {
+        if config.pmus.is_empty() {
+            return Err(invalid("No virtual PMUs".to_string()));
+        }
+        let pmus = config
+            .pmus
+            .iter()
+            .map(|pmu| resolve(pmu, source))
+            .collect::<io::Result<Vec<_>>>()?;
+        let stream = ConfigurationFrame1and2_2011 {
+            prefix: PrefixFrame2011 {
+                sync: 0xAA31,
+                framesize: 0,
+                idcode: config.idcode,
+                soc: 0,
+                fracsec: 0,
+            },
+            time_base: source.time_base,
+            num_pmu: config.pmus.len() as u16,
+            pmu_configs: config.pmus.iter().map(pmu_config).collect(),
+            data_rate: source.data_rate,
+            chk: 0,
+        };
+        Ok(VirtualStream {
+            source: source.clone(),
+            // Framesize and CHK filled in
+            config: parse_config_frame_1and2(&stream.to_hex()).unwrap_or(stream),
+            pmus,
+        })
+    }
+
+    // Configuration frame 2 of the virtual PMUs.
+    pub fn config(&self) -> &ConfigurationFrame1and2_2011 {
+        &self.config
+    }
+
+    // The data frame of the virtual PMUs for a data frame of the source.
+    pub fn data_frame(&self, source_frame: &[u8]) -> Result<Vec<u8>, String> {
+        let measurements = frame_measurements(source_frame, &self.source)?;
+        let mut frame = Vec::with_capacity(self.config.calc_data_frame_size());
+        frame.extend_from_slice(&0xAA01u16.to_be_bytes());
+        frame.extend_from_slice(&(self.config.calc_data_frame_size() as u16).to_be_bytes());
+        frame.extend_from_slice(&self.config.prefix.idcode.to_be_bytes());
+        // SOC and FRACSEC, time quality included, of the source
+        frame.extend_from_slice(&source_frame[6..14]);
+        for pmu in &self.pmus {
+            let stat = pmu
+                .sources
+                .iter()
+                .fold(0, |stat, i| stat | measurements[*i].stat & STAT_SOURCE_BITS);
+            frame.extend_from_slice(&stat.to_be_bytes());
+            for (component, phases) in &pmu.sequences {
+                let phases = phases.map(|phasor| source_phasor(&measurements, phasor));
+                let phasor = sequence(*component, phases);
+                put_f32(&mut frame, phasor.magnitude());
+                put_f32(&mut frame, phasor.angle());
+            }
+            let (frequency, rocof) = weighted_frequency(&measurements, &pmu.frequency_weights);
+            put_f32(&mut frame, frequency);
+            put_f32(&mut frame, rocof);
+            for (from, to) in &pmu.angle_differences {
+                let difference = source_phasor(&measurements, *from).angle()
+                    - source_phasor(&measurements, *to).angle();
+                put_f32(&mut frame, wrap_degrees(difference.to_degrees()));
+            }
+        }
+        let crc = calculate_crc(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        Ok(frame)
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn resolve(
+    pmu: &VirtualPmuConfig,
+    source: &ConfigurationFrame1and2_2011,
+) -> io::Result<ResolvedPmu> {
+    let mut sources = Vec::new();
+    let mut phasor = |name: &str| -> io::Result<PhasorRef> {
+        let found = find_phasor(source, name)
+            .ok_or_else(|| invalid(format!("{}: no phasor {}", pmu.station, name)))?;
+        if !sources.contains(&found.0) {
+            sources.push(found.0);
+        }
+        Ok(found)
+    };
+    let mut sequences = Vec::new();
+    for sequence in &pmu.sequences {
+        let phases = [
+            phasor(&sequence.phases[0])?,
+            phasor(&sequence.phases[1])?,
+            phasor(&sequence.phases[2])?,
+        ];
+        sequences.push((sequence.component, phases));
+    }
+    let mut angle_differences = Vec::new();
+    for difference in &pmu.angle_differences {
+        angle_differences.push((phasor(&difference.from)?, phasor(&difference.to)?));
+    }
+    let frequency_weights = if pmu.frequency_weights.is_empty() {
+        sources.iter().map(|i| (*i, 1.0)).collect()
+    } else {
+        let mut weights = Vec::new();
+        for (name, weight) in &pmu.frequency_weights {
+            let i = find_frequency(source, name)
+                .ok_or_else(|| invalid(format!("{}: no frequency {}", pmu.station, name)))?;
+            weights.push((i, *weight));
+            if !sources.contains(&i) {
+                sources.push(i);
+            }
+        }
+        weights
+    };
+    Ok(ResolvedPmu {
+        sequences,
+        angle_differences,
+        frequency_weights,
+        sources,
+    })
+}
+
+// The phasor of that full name, or of that channel name when one PMU has it.
+fn find_phasor(source: &ConfigurationFrame1and2_2011, name: &str) -> Option<PhasorRef> {
+    let mut found = Vec::new();
+    for (i, pmu_config) in source.pmu_configs.iter().enumerate() {
+        let names = pmu_config.get_column_names();
+        let short = names.iter().map(|full| {
+            full.strip_prefix(&format!("{}_{}_", station(pmu_config), pmu_config.idcode))
+                .unwrap_or(full)
+                .to_string()
+        });
+        for (k, (full, short)) in names
+            .iter()
+            .zip(short)
+            .take(pmu_config.phnmr as usize)
+            .enumerate()
+        {
+            if full == name {
+                return Some((i, k));
+            }
+            if short == name {
+                found.push((i, k));
+            }
+        }
+    }
+    (found.len() == 1).then(|| found[0])
+}
+
+// The PMU of a FREQ channel name or station name.
+fn find_frequency(source: &ConfigurationFrame1and2_2011, name: &str) -> Option<usize> {
+    source.pmu_configs.iter().position(|pmu_config| {
+        let station = station(pmu_config);
+        name == format!("{}_{}_FREQ", station, pmu_config.idcode) || name == station
+    })
+}
+
+fn station(pmu_config: &PMUConfigurationFrame2011) -> String {
+    String::from_utf8_lossy(&pmu_config.stn).trim().to_string()
+}
+
+fn pmu_config(pmu: &VirtualPmuConfig) -> PMUConfigurationFrame2011 {
+    let mut chnam = Vec::new();
+    for name in pmu.sequences.iter().map(|sequence| &sequence.name).chain(
+        pmu.angle_differences
+            .iter()
+            .map(|difference| &difference.name),
+    ) {
+        chnam.extend_from_slice(&pad16(name));
+    }
+    PMUConfigurationFrame2011 {
+        stn: pad16(&pmu.station),
+        idcode: pmu.idcode,
+        format: FORMAT_FLOAT_POLAR,
+        phnmr: pmu.sequences.len() as u16,
+        annmr: pmu.angle_differences.len() as u16,
+        dgnmr: 0,
+        chnam,
+        phunit: pmu
+            .sequences
+            .iter()
+            .map(|sequence| if sequence.current { PHUNIT_CURRENT } else { 0 })
+            .collect(),
+        anunit: vec![0; pmu.angle_differences.len()],
+        digunit: Vec::new(),
+        fnom: pmu.nominal_50hz as u16,
+        cfgcnt: 0,
+    }
+}
+
+fn pad16(name: &str) -> [u8; 16] {
+    let mut padded = [b' '; 16];
+    for (dst, src) in padded.iter_mut().zip(name.bytes()) {
+        *dst = src;
+    }
+    padded
+}
+
+fn source_phasor(measurements: &[PmuMeasurement], (i, k): PhasorRef) -> Phasor {
+    measurements[i].phasors.get(k).copied().unwrap_or_default()
+}
+
+// Symmetrical component of phases a, b and c.
+pub fn sequence(component: SequenceComponent, [a, b, c]: [Phasor; 3]) -> Phasor {
+    let turn = |phasor: Phasor, degrees: f64| {
+        Phasor::from_polar(phasor.magnitude(), phasor.angle() + degrees.to_radians())
+    };
+    let (b, c) = match component {
+        SequenceComponent::Positive => (turn(b, 120.0), turn(c, 240.0)),
+        SequenceComponent::Negative => (turn(b, 240.0), turn(c, 120.0)),
+        SequenceComponent::Zero => (b, c),
+    };
+    Phasor::new((a.re + b.re + c.re) / 3.0, (a.im + b.im + c.im) / 3.0)
+}
+
+fn weighted_frequency(measurements: &[PmuMeasurement], weights: &[(usize, f64)]) -> (f64, f64) {
+    let total: f64 = weights.iter().map(|(_, weight)| weight).sum();
+    if total == 0.0 {
+        return (f64::NAN, f64::NAN);
+    }
+    let mean = |value: fn(&PmuMeasurement) -> f64| {
+        weights
+            .iter()
+            .map(|(i, weight)| value(&measurements[*i]) * weight)
+            .sum::<f64>()
+            / total
+    };
+    (mean(|m| m.frequency), mean(|m| m.rocof))
+}
+
+fn wrap_degrees(degrees: f64) -> f64 {
+    let wrapped = (degrees + 180.0).rem_euclid(360.0) - 180.0;
+    if wrapped == -180.0 {
+        180.0
+    } else {
+        wrapped
+    }
+}
+
+fn put_f32(frame: &mut Vec<u8>, value: f64) {
+    frame.extend_from_slice(&(value as f32).to_be_bytes());
+}
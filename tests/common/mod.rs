@@ -0,0 +1,141 @@
+// Fixtures shared by the integration tests: the sample frames in
+// tests/test_data, copies of them under other IDCODEs and times with their
+// CHK recalculated, and small record batches.
+#[cfg(feature = "arrow")]
+use arrow::array::{Array, ArrayRef, Float32Array, TimestampMicrosecondArray, UInt16Array};
+#[cfg(feature = "arrow")]
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+#[cfg(feature = "arrow")]
+use arrow::record_batch::RecordBatch;
+#[cfg(feature = "arrow")]
+use pmu::arrow_utils::build_record_batch;
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::frames::calculate_crc;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+pub fn read_hex_file(file_name: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let path = Path::new("tests/test_data").join(file_name);
+    let content = fs::read_to_string(path)?;
+    let hex_string: String = content.chars().filter(|c| !c.is_whitespace()).collect();
+
+    hex_string
+        .as_bytes()
+        .chunks(2)
+        .map(|chunk| {
+            let hex_byte = std::str::from_utf8(chunk).unwrap();
+            u8::from_str_radix(hex_byte, 16).map_err(|e| e.into())
+        })
+        .collect()
+}
+
+// The frame with its CHK recalculated.
+pub fn with_crc(mut frame: Vec<u8>) -> Vec<u8> {
+    let len = frame.len();
+    let crc = calculate_crc(&frame[..len - 2]);
+    frame[len - 2..].copy_from_slice(&crc.to_be_bytes());
+    frame
+}
+
+// Whether the CHK of the frame matches its other bytes.
+pub fn crc_ok(frame: &[u8]) -> bool {
+    let len = frame.len();
+    calculate_crc(&frame[..len - 2]) == u16::from_be_bytes([frame[len - 2], frame[len - 1]])
+}
+
+// Copy of the sample data frame for another IDCODE and time, index frames
+// into soc. The sample config has 30 frames/s and a time base of 10^6.
+pub fn data_frame(idcode: u16, soc: u32, index: u32) -> Vec<u8> {
+    let fracsec = (index as f64 * 1_000_000.0 / 30.0).round() as u32;
+    let mut frame = read_hex_file("data_message.bin").unwrap();
+    frame[4..6].copy_from_slice(&idcode.to_be_bytes());
+    frame[6..10].copy_from_slice(&soc.to_be_bytes());
+    frame[10..14].copy_from_slice(&fracsec.to_be_bytes());
+    with_crc(frame)
+}
+
+// Start of the streams built with data_frame_us.
+pub const START_US: i64 = 1_700_000_000_000_000;
+
+// Time of frame n from START_US at the 30 frames/s of the sample config.
+pub fn frame_time(n: i64) -> i64 {
+    START_US + (n as f64 * 1e6 / 30.0).round() as i64
+}
+
+// The sample data frame of idcode at timestamp_us (time base 10^6).
+pub fn data_frame_us(idcode: u16, timestamp_us: i64) -> Vec<u8> {
+    let mut frame = read_hex_file("data_message.bin").unwrap();
+    frame[4..6].copy_from_slice(&idcode.to_be_bytes());
+    frame[6..10].copy_from_slice(&((timestamp_us / 1_000_000) as u32).to_be_bytes());
+    frame[10..14].copy_from_slice(&((timestamp_us % 1_000_000) as u32).to_be_bytes());
+    with_crc(frame)
+}
+
+// The sample data frame at SOC and FRACSEC.
+pub fn data_frame_at(soc: u32, fracsec: u32) -> Vec<u8> {
+    let mut frame = read_hex_file("data_message.bin").unwrap();
+    frame[6..10].copy_from_slice(&soc.to_be_bytes());
+    frame[10..14].copy_from_slice(&fracsec.to_be_bytes());
+    with_crc(frame)
+}
+
+// The sample data frame as a one-row batch of the sample configuration.
+#[cfg(feature = "arrow")]
+pub fn sample_batch() -> RecordBatch {
+    repeated_batch(1)
+}
+
+// The sample data frame repeated frames times, one row each.
+#[cfg(feature = "arrow")]
+pub fn repeated_batch(frames: usize) -> RecordBatch {
+    let config = parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+    let frame = read_hex_file("data_message.bin").unwrap();
+    let buffer = frame.repeat(frames);
+    build_record_batch(&buffer, frame.len(), &config.get_channel_map()).unwrap()
+}
+
+// The timestamp column of a batch.
+#[cfg(feature = "arrow")]
+pub fn timestamps(batch: &RecordBatch) -> Vec<i64> {
+    batch
+        .column_by_name("timestamp")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<TimestampMicrosecondArray>()
+        .unwrap()
+        .values()
+        .to_vec()
+}
+
+// Two rows just after 2000-01-01: FREQ with channel metadata, NULL in the
+// second row, and a BREAKER digital if asked for.
+#[cfg(feature = "arrow")]
+pub fn frequency_batch(with_breaker: bool) -> RecordBatch {
+    let freq = Field::new("FREQ", DataType::Float32, true).with_metadata(HashMap::from([
+        ("pmu.station".to_string(), "Station A".to_string()),
+        ("pmu.unit".to_string(), "Hz".to_string()),
+        ("pmu.scale".to_string(), "1".to_string()),
+    ]));
+    let mut fields = vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            false,
+        ),
+        freq,
+    ];
+    let mut arrays: Vec<ArrayRef> = vec![
+        Arc::new(TimestampMicrosecondArray::from(vec![
+            946_684_800_000_001,
+            946_684_800_000_002,
+        ])),
+        Arc::new(Float32Array::from(vec![Some(60.0), None])),
+    ];
+    if with_breaker {
+        fields.push(Field::new("BREAKER", DataType::UInt16, false));
+        arrays.push(Arc::new(UInt16Array::from(vec![1, 0])));
+    }
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays).unwrap()
+}
@@ -0,0 +1,572 @@
+#![cfg(feature = "arrow")]
+#![allow(unused)]
+mod common;
+
+use common::{data_frame_at, read_hex_file, timestamps};
+
+#[cfg(test)]
+mod tests {
+    use super::{data_frame_at, read_hex_file, timestamps};
+    use arrow::array::{
+        Array, Float64Array, Int16Array, StringArray, TimestampMicrosecondArray, UInt16Array,
+        UInt8Array,
+    };
+    use arrow::record_batch::RecordBatch;
+    use pmu::accumulator::{AccumulatorError, BatchAccumulator, CrcStats, FlushPolicy, GapFill};
+    use pmu::arrow_utils::{
+        soc_fracsec_micros, unwrap_soc_rollover, ArrowLayout, ArrowOptions, SparseChannels,
+        QUALITY_BAD_CRC, QUALITY_COLUMN, QUALITY_HELD, QUALITY_INTERPOLATED, SOC_ROLLOVER_US,
+    };
+    use pmu::budget::MemoryBudget;
+    use pmu::frame_parser::parse_config_frame_1and2;
+    use pmu::frames::{ConfigurationFrame1and2_2011, CrcMode};
+    use pmu::simulator::{Scenario, ScenarioEvent, Simulator};
+    use std::time::Duration;
+
+    fn config() -> ConfigurationFrame1and2_2011 {
+        parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap()
+    }
+
+    // Sample config at 30 frames/s, frequency rising 1 Hz/s. Frames 10-12 are
+    // dropped when drop is set.
+    fn frames(drop: bool) -> Vec<Vec<u8>> {
+        let mut events = vec![ScenarioEvent::FrequencyRamp {
+            at: 0.0,
+            duration: 10.0,
+            rate: 1.0,
+        }];
+        if drop {
+            events.push(ScenarioEvent::DropFrames { at: 0.33, count: 3 });
+        }
+        let scenario = Scenario {
+            start_soc: Some(1_700_000_000),
+            events,
+            ..Default::default()
+        };
+        let mut simulator = Simulator::new(config(), scenario);
+        (0..20).flat_map(|_| simulator.next_tick().frames).collect()
+    }
+
+    fn accumulate(accumulator: &mut BatchAccumulator, frames: &[Vec<u8>]) -> RecordBatch {
+        accumulator.add_stream(&config());
+        for frame in frames {
+            assert!(accumulator.push_frame(frame).unwrap().is_none());
+        }
+        accumulator.flush(7734).unwrap().unwrap()
+    }
+
+    fn quality(batch: &RecordBatch) -> Vec<u8> {
+        batch
+            .column_by_name(QUALITY_COLUMN)
+            .unwrap()
+            .as_any()
+            .downcast_ref::<UInt8Array>()
+            .unwrap()
+            .values()
+            .to_vec()
+    }
+
+    fn int16_column(batch: &RecordBatch, name: &str) -> Vec<i16> {
+        batch
+            .column_by_name(name)
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Int16Array>()
+            .unwrap()
+            .values()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_no_quality_column_by_default() {
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited());
+        let batch = accumulate(&mut accumulator, &frames(true));
+        assert_eq!(batch.num_rows(), 17);
+        assert!(batch.column_by_name(QUALITY_COLUMN).is_none());
+    }
+
+    #[test]
+    fn test_gap_hold() {
+        let mut accumulator =
+            BatchAccumulator::new(MemoryBudget::unlimited()).with_gap_fill(GapFill::Hold, 5);
+        let batch = accumulate(&mut accumulator, &frames(true));
+        assert_eq!(batch.num_rows(), 20);
+
+        let flags = quality(&batch);
+        assert_eq!(&flags[10..13], &[QUALITY_HELD; 3]);
+        assert!(flags[..10].iter().chain(&flags[13..]).all(|f| *f == 0));
+
+        let timestamps = batch
+            .column_by_name("timestamp")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<TimestampMicrosecondArray>()
+            .unwrap()
+            .values()
+            .to_vec();
+        for (i, timestamp) in timestamps.iter().enumerate() {
+            let expected = (i as f64 * 1_000_000.0 / 30.0).round() as i64;
+            assert_eq!(timestamp - 1_700_000_000_000_000, expected);
+        }
+
+        let freq = int16_column(&batch, "Station A_7734_FREQ");
+        assert_eq!(freq[10], freq[9]);
+        assert_eq!(freq[12], freq[9]);
+    }
+
+    #[test]
+    fn test_gap_interpolate() {
+        let mut accumulator =
+            BatchAccumulator::new(MemoryBudget::unlimited()).with_gap_fill(GapFill::Interpolate, 5);
+        let batch = accumulate(&mut accumulator, &frames(true));
+        assert_eq!(quality(&batch)[10..13], [QUALITY_INTERPOLATED; 3]);
+
+        // Compare with the frames that were dropped
+        let expected = accumulate(
+            &mut BatchAccumulator::new(MemoryBudget::unlimited()),
+            &frames(false),
+        );
+        let freq = int16_column(&batch, "Station A_7734_FREQ");
+        let actual_freq = int16_column(&expected, "Station A_7734_FREQ");
+        for i in 10..13 {
+            assert!((freq[i] - actual_freq[i]).abs() <= 1, "row {}", i);
+        }
+        let names: Vec<String> = config().pmu_configs[0].get_column_names();
+        let x = int16_column(&batch, &format!("{}_X", names[0]));
+        let actual_x = int16_column(&expected, &format!("{}_X", names[0]));
+        // Phasor components move along a straight line across the gap
+        assert!((x[11] as i32 * 2 - (x[9] as i32 + x[13] as i32)).abs() <= 1);
+        assert!(x[9] > x[10] && x[10] > x[11] && x[11] > x[12] && x[12] > x[13]);
+        assert_eq!(x[9], actual_x[9]);
+        assert_eq!(x[13], actual_x[13]);
+    }
+
+    #[test]
+    fn test_long_gaps_are_not_filled() {
+        let mut accumulator =
+            BatchAccumulator::new(MemoryBudget::unlimited()).with_gap_fill(GapFill::Hold, 2);
+        let batch = accumulate(&mut accumulator, &frames(true));
+        assert_eq!(batch.num_rows(), 17);
+        assert!(quality(&batch).iter().all(|f| *f == 0));
+    }
+
+    #[test]
+    fn test_lenient_crc() {
+        let mut frames = frames(false);
+        let len = frames[4].len();
+        frames[4][len - 1] ^= 0xFF;
+
+        let mut strict =
+            BatchAccumulator::new(MemoryBudget::unlimited()).with_gap_fill(GapFill::Hold, 5);
+        strict.add_stream(&config());
+        match strict.push_frame(&frames[4]) {
+            Err(AccumulatorError::InvalidCrc(7734)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut lenient =
+            BatchAccumulator::new(MemoryBudget::unlimited()).with_lenient_parsing(true);
+        let batch = accumulate(&mut lenient, &frames);
+        let flags = quality(&batch);
+        assert_eq!(flags[4], QUALITY_BAD_CRC);
+        assert_eq!(flags.iter().filter(|f| **f != 0).count(), 1);
+    }
+
+    #[test]
+    fn test_salvage() {
+        let mut frames = frames(false);
+        let len = frames[0].len();
+        let analog = config().get_channel_map()["Station A_7734_ANALOG1"].offset;
+        // Bad CHK only
+        frames[4][len - 1] ^= 0xFF;
+        // FRAMESIZE not of the configuration
+        frames[6][3] ^= 0x01;
+        // FRACSEC past the time base
+        frames[8][11..14].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+        // Analog value not a number
+        frames[10][analog..analog + 4].copy_from_slice(&f32::NAN.to_be_bytes());
+        // SOC an hour off
+        frames[12][8] ^= 0x10;
+
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited()).with_salvage(true);
+        accumulator.add_stream(&config());
+        for (n, frame) in frames.iter().enumerate() {
+            let result = accumulator.push_frame(frame);
+            if [6, 8, 10, 12].contains(&n) {
+                assert!(
+                    matches!(result, Err(AccumulatorError::InvalidCrc(7734))),
+                    "frame {}",
+                    n
+                );
+            } else {
+                assert!(result.unwrap().is_none());
+            }
+        }
+        let batch = accumulator.flush(7734).unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 16);
+        let flags = quality(&batch);
+        assert_eq!(flags[4], QUALITY_BAD_CRC);
+        assert_eq!(flags.iter().filter(|f| **f != 0).count(), 1);
+        assert_eq!(accumulator.crc_stats(7734).unwrap().mismatches, 5);
+    }
+
+    #[test]
+    fn test_crc_modes() {
+        // A device computing CHK without the SYNC word
+        let frames: Vec<Vec<u8>> = frames(false)
+            .into_iter()
+            .map(|mut frame| {
+                let len = frame.len();
+                let chk = CrcMode::ExcludeSync.calculate(&frame).unwrap();
+                frame[len - 2..].copy_from_slice(&chk.to_be_bytes());
+                frame
+            })
+            .collect();
+        assert_eq!(CrcMode::detect(&frames[0]), Some(CrcMode::ExcludeSync));
+        assert!(!CrcMode::Standard.check(&frames[0]));
+        assert!(CrcMode::Skip.check(&frames[0]));
+
+        let mut strict = BatchAccumulator::new(MemoryBudget::unlimited());
+        strict.set_crc_mode(7734, CrcMode::Standard, false);
+        strict.add_stream(&config());
+        assert!(matches!(
+            strict.push_frame(&frames[0]),
+            Err(AccumulatorError::InvalidCrc(7734))
+        ));
+
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited());
+        accumulator.set_crc_mode(7734, CrcMode::ExcludeSync, false);
+        let batch = accumulate(&mut accumulator, &frames);
+        assert_eq!(batch.num_rows(), 20);
+        assert!(quality(&batch).iter().all(|f| *f == 0));
+        assert_eq!(accumulator.crc_stats(7734).unwrap().mismatches, 0);
+
+        // Lenient with the standard check: kept, flagged and counted
+        let mut lenient = BatchAccumulator::new(MemoryBudget::unlimited());
+        lenient.set_crc_mode(7734, CrcMode::Standard, true);
+        let batch = accumulate(&mut lenient, &frames);
+        assert!(quality(&batch).iter().all(|f| *f == QUALITY_BAD_CRC));
+        let stats = lenient.crc_stats(7734).unwrap();
+        assert_eq!(stats.checked, 20);
+        assert_eq!(stats.mismatches, 20);
+        assert_eq!(stats.passed_by.get(&CrcMode::ExcludeSync), Some(&20));
+
+        let mut skip = BatchAccumulator::new(MemoryBudget::unlimited());
+        skip.set_crc_mode(7734, CrcMode::Skip, false);
+        let batch = accumulate(&mut skip, &frames);
+        assert!(quality(&batch).iter().all(|f| *f == 0));
+        assert_eq!(skip.crc_stats(7734), Some(&CrcStats::default()));
+    }
+
+    #[test]
+    fn test_long_format() {
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited())
+            .with_gap_fill(GapFill::Hold, 5)
+            .with_arrow_options(ArrowOptions::default().with_layout(ArrowLayout::Long));
+        let batch = accumulate(&mut accumulator, &frames(true));
+        let names: Vec<_> = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "timestamp",
+                "station",
+                "idcode",
+                "channel",
+                "type",
+                "value",
+                "quality"
+            ]
+        );
+        // 8 phasor components, FREQ, DFREQ, 3 analogs, 1 digital, STAT and 7 decoded flags
+        assert_eq!(batch.num_rows(), 20 * 22);
+
+        let strings = |name: &str| {
+            batch
+                .column_by_name(name)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap()
+                .clone()
+        };
+        let (stations, channels, types) = (strings("station"), strings("channel"), strings("type"));
+        let idcodes = batch.column_by_name("idcode").unwrap();
+        let idcodes = idcodes.as_any().downcast_ref::<UInt16Array>().unwrap();
+        let values = batch.column_by_name("value").unwrap();
+        let values = values.as_any().downcast_ref::<Float64Array>().unwrap();
+        let timestamps = batch.column_by_name("timestamp").unwrap();
+        let timestamps = timestamps
+            .as_any()
+            .downcast_ref::<TimestampMicrosecondArray>()
+            .unwrap();
+        let flags = quality(&batch);
+
+        assert!(stations.iter().all(|s| s == Some("Station A")));
+        assert!(idcodes.values().iter().all(|id| *id == 7734));
+        let rows = |channel: &str| -> Vec<usize> {
+            (0..batch.num_rows())
+                .filter(|i| channels.value(*i) == channel)
+                .collect()
+        };
+
+        // Frequency in Hz, ramping 1 Hz/s from nominal
+        let freq = rows("FREQ");
+        assert_eq!(freq.len(), 20);
+        assert_eq!(types.value(freq[0]), "frequency");
+        assert!((values.value(freq[0]) - 60.0).abs() < 0.002);
+        assert!((values.value(freq[9]) - 60.3).abs() < 0.002);
+        assert_eq!(
+            timestamps.value(freq[1]) - timestamps.value(freq[0]),
+            33_333
+        );
+        assert_eq!(flags[freq[11]], QUALITY_HELD);
+        assert_eq!(flags[freq[14]], 0);
+
+        // Phasor components share the channel name
+        let va = rows("VA");
+        assert_eq!(va.len(), 40);
+        assert_eq!(types.value(va[0]), "voltage_real");
+        assert_eq!(types.value(va[1]), "voltage_imaginary");
+        assert_eq!(rows("PMU_SYNC").len(), 20);
+        assert_eq!(values.value(rows("DATA_VALID")[0]), 1.0);
+    }
+
+    // Frames of the sample config at 30 frames/s, starting on a whole second.
+    fn steady_frames(count: usize) -> Vec<Vec<u8>> {
+        let scenario = Scenario {
+            start_soc: Some(1_700_000_000),
+            ..Default::default()
+        };
+        let mut simulator = Simulator::new(config(), scenario);
+        (0..count)
+            .flat_map(|_| simulator.next_tick().frames)
+            .collect()
+    }
+
+    fn push_all(accumulator: &mut BatchAccumulator, frames: &[Vec<u8>]) -> Vec<RecordBatch> {
+        accumulator.add_stream(&config());
+        let mut batches: Vec<RecordBatch> = frames
+            .iter()
+            .filter_map(|frame| accumulator.push_frame(frame).unwrap())
+            .map(|(_, batch)| batch)
+            .collect();
+        batches.extend(accumulator.flush(7734).unwrap());
+        batches
+    }
+
+    #[test]
+    fn test_flush_every_n_rows() {
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited())
+            .with_flush_policy(FlushPolicy::default().with_max_rows(7));
+        let batches = push_all(&mut accumulator, &steady_frames(20));
+        let rows: Vec<_> = batches.iter().map(|b| b.num_rows()).collect();
+        assert_eq!(rows, [7, 7, 6]);
+    }
+
+    #[test]
+    fn test_flush_on_bytes() {
+        let frame_size = steady_frames(1)[0].len();
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited())
+            .with_flush_policy(FlushPolicy::default().with_max_bytes(frame_size * 10));
+        let batches = push_all(&mut accumulator, &steady_frames(25));
+        let rows: Vec<_> = batches.iter().map(|b| b.num_rows()).collect();
+        assert_eq!(rows, [10, 10, 5]);
+    }
+
+    #[test]
+    fn test_flush_on_timestamp_boundary() {
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited())
+            .with_flush_policy(FlushPolicy::default().with_boundary(Duration::from_secs(1)));
+        // Start a third of the way into the first second
+        let frames = steady_frames(75);
+        let batches = push_all(&mut accumulator, &frames[10..]);
+        let rows: Vec<_> = batches.iter().map(|b| b.num_rows()).collect();
+        assert_eq!(rows, [20, 30, 15]);
+
+        for batch in &batches {
+            let timestamps = batch
+                .column_by_name("timestamp")
+                .unwrap()
+                .as_any()
+                .downcast_ref::<TimestampMicrosecondArray>()
+                .unwrap();
+            let first = timestamps.value(0) / 1_000_000;
+            let last = timestamps.value(batch.num_rows() - 1) / 1_000_000;
+            assert_eq!(first, last);
+        }
+    }
+
+    #[test]
+    fn test_flush_on_wall_time() {
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited())
+            .with_flush_policy(FlushPolicy::default().with_max_age(Duration::from_millis(20)));
+        accumulator.add_stream(&config());
+        let frames = steady_frames(3);
+        assert!(accumulator.push_frame(&frames[0]).unwrap().is_none());
+        assert!(accumulator.push_frame(&frames[1]).unwrap().is_none());
+        assert!(accumulator.flush_expired().unwrap().is_empty());
+
+        std::thread::sleep(Duration::from_millis(30));
+        let expired = accumulator.flush_expired().unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, 7734);
+        assert_eq!(expired[0].1.num_rows(), 2);
+
+        // A frame pushed after the age has passed flushes its batch too
+        assert!(accumulator.push_frame(&frames[2]).unwrap().is_none());
+        std::thread::sleep(Duration::from_millis(30));
+        let frames = steady_frames(4);
+        let (_, batch) = accumulator.push_frame(&frames[3]).unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 2);
+    }
+
+    fn is_increasing(timestamps: &[i64]) -> bool {
+        timestamps.windows(2).all(|pair| pair[0] < pair[1])
+    }
+
+    #[test]
+    fn test_fracsec_to_micros() {
+        // Flags are not part of the time
+        assert_eq!(
+            soc_fracsec_micros(1, 0x0F00_0000 | 500_000, 1_000_000),
+            1_500_000
+        );
+        // Rounded with a time base that is no divisor of 10^6
+        assert_eq!(soc_fracsec_micros(0, 1, 1 << 24), 0);
+        assert_eq!(soc_fracsec_micros(0, 9, 1 << 24), 1);
+        assert_eq!(soc_fracsec_micros(0, (1 << 24) - 1, 1 << 24), 1_000_000);
+        // FRACSEC at the time base is the next second
+        assert_eq!(soc_fracsec_micros(7, 30, 30), 8_000_000);
+        assert_eq!(
+            unwrap_soc_rollover(0, u32::MAX as i64 * 1_000_000),
+            SOC_ROLLOVER_US
+        );
+        assert_eq!(unwrap_soc_rollover(5, 0), 5);
+    }
+
+    #[test]
+    fn test_time_base_not_dividing_a_second() {
+        let mut config = config();
+        let time_base = 1 << 24;
+        config.time_base = time_base;
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited());
+        accumulator.add_stream(&config);
+        // Two seconds at 30 frames/s, then FRACSEC at the time base
+        for n in 0..60u64 {
+            let soc = 1_700_000_000 + (n / 30) as u32;
+            let fracsec = ((n % 30) * time_base as u64 / 30) as u32;
+            accumulator
+                .push_frame(&data_frame_at(soc, fracsec))
+                .unwrap();
+        }
+        accumulator
+            .push_frame(&data_frame_at(1_700_000_001, time_base))
+            .unwrap();
+        let timestamps = timestamps(&accumulator.flush(7734).unwrap().unwrap());
+        assert_eq!(timestamps.len(), 61);
+        assert!(is_increasing(&timestamps));
+        for (n, timestamp) in timestamps.iter().enumerate() {
+            let expected = 1_700_000_000_000_000 + (n as i64 * 1_000_000) / 30;
+            assert!((timestamp - expected).abs() <= 1, "{} {}", n, timestamp);
+        }
+        assert_eq!(timestamps[60], 1_700_000_002_000_000);
+    }
+
+    #[test]
+    fn test_soc_rollover_keeps_timestamps_increasing() {
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited())
+            .with_flush_policy(FlushPolicy::default().with_max_rows(20));
+        accumulator.add_stream(&config());
+        // A second before SOC wraps to a second after, in batches of 20 rows
+        let mut batches = Vec::new();
+        for n in 0..60u32 {
+            let soc = u32::MAX.wrapping_add(n / 30);
+            let fracsec = (n % 30) * 1_000_000 / 30;
+            batches.extend(
+                accumulator
+                    .push_frame(&data_frame_at(soc, fracsec))
+                    .unwrap(),
+            );
+        }
+        let timestamps: Vec<i64> = batches
+            .iter()
+            .flat_map(|(_, batch)| timestamps(batch))
+            .collect();
+        assert_eq!(timestamps.len(), 60);
+        assert!(is_increasing(&timestamps));
+        assert_eq!(timestamps[29] / 1_000_000, u32::MAX as i64);
+        assert_eq!(timestamps[30], SOC_ROLLOVER_US);
+    }
+
+    #[test]
+    fn test_lagging_soc_at_fracsec_wrap() {
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited());
+        accumulator.add_stream(&config());
+        let soc = 1_700_000_000;
+        for fracsec in [900_000, 933_333, 966_667] {
+            accumulator
+                .push_frame(&data_frame_at(soc, fracsec))
+                .unwrap();
+        }
+        // FRACSEC wrapped before SOC was carried
+        accumulator.push_frame(&data_frame_at(soc, 0)).unwrap();
+        accumulator
+            .push_frame(&data_frame_at(soc + 1, 33_333))
+            .unwrap();
+        let timestamps = timestamps(&accumulator.flush(7734).unwrap().unwrap());
+        assert!(is_increasing(&timestamps));
+        assert_eq!(timestamps[3], 1_700_000_001_000_000);
+        assert_eq!(accumulator.time_repairs(7734), 1);
+    }
+
+    #[test]
+    fn test_sparse_channels() {
+        let frames = frames(false);
+        let sparse = SparseChannels::default()
+            .with_kinds(&["rms", "peak", "point_on_wave", "digital"])
+            .with_channels(&["FREQ"])
+            .with_deadband(0.5);
+        let rows = |sparse: SparseChannels| -> Vec<Vec<String>> {
+            let options = ArrowOptions::default()
+                .with_layout(ArrowLayout::Long)
+                .with_sparse(sparse);
+            let mut accumulator =
+                BatchAccumulator::new(MemoryBudget::unlimited()).with_arrow_options(options);
+            accumulator.add_stream(&config());
+            // Two batches, the last values are kept across them
+            frames
+                .chunks(10)
+                .map(|frames| {
+                    for frame in frames {
+                        assert!(accumulator.push_frame(frame).unwrap().is_none());
+                    }
+                    let batch = accumulator.flush(7734).unwrap().unwrap();
+                    let channels = batch.column_by_name("channel").unwrap();
+                    let channels = channels.as_any().downcast_ref::<StringArray>().unwrap();
+                    channels.iter().map(|c| c.unwrap().to_string()).collect()
+                })
+                .collect()
+        };
+        let count = |rows: &[String], channel: &str| rows.iter().filter(|c| *c == channel).count();
+
+        // 3 analogs and the digital once, FREQ when it moved 0.5 Hz, the 17
+        // other values of the 22 every frame
+        let batches = rows(sparse.clone());
+        assert_eq!(batches[0].len(), 10 * 17 + 4 + 1);
+        assert_eq!(count(&batches[0], "ANALOG2"), 1);
+        assert_eq!(count(&batches[0], "BREAKER 1 STATUS"), 1);
+        assert_eq!(count(&batches[1], "ANALOG2"), 0);
+        assert_eq!(count(&batches[0], "FREQ") + count(&batches[1], "FREQ"), 2);
+        assert_eq!(count(&batches[1], "VA"), 20);
+
+        // With a heartbeat the constant values are given every 250 ms
+        let batches = rows(sparse.with_heartbeat(Duration::from_millis(250)));
+        let analogs: Vec<usize> = batches.iter().map(|rows| count(rows, "ANALOG1")).collect();
+        assert_eq!(analogs, vec![2, 1]);
+    }
+}
@@ -0,0 +1,382 @@
+#![cfg(feature = "sinks")]
+#![allow(unused)]
+mod common;
+
+use common::{data_frame, read_hex_file, with_crc};
+use std::fs;
+
+// Microseconds into the second of frame index at 30 frames/s.
+fn data_frame_fraction(index: u32) -> i64 {
+    (index as f64 * 1_000_000.0 / 30.0).round() as i64
+}
+
+// The same frame with different data, CRC recalculated.
+fn altered(mut frame: Vec<u8>) -> Vec<u8> {
+    frame[16] ^= 0x01;
+    with_crc(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{altered, data_frame, data_frame_fraction, read_hex_file};
+    use pmu::aggregator::{Aggregator, AggregatorError, ConflictKind, ConflictPolicy, LateData};
+    use pmu::budget::MemoryBudget;
+    use pmu::frame_parser::parse_config_frame_1and2;
+    use pmu::frames::ConfigurationFrame1and2_2011;
+    use pmu::historian::Historian;
+    use pmu::sinks::csv::CsvSink;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    const SOC: u32 = 1_700_000_000;
+
+    fn config(idcode: u16) -> ConfigurationFrame1and2_2011 {
+        let mut config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        config.prefix.idcode = idcode;
+        config
+    }
+
+    fn aggregator(lateness_ms: u64) -> Aggregator {
+        let mut aggregator = Aggregator::new(Duration::from_millis(lateness_ms));
+        aggregator.add_stream(&config(1));
+        aggregator.add_stream(&config(2));
+        aggregator
+    }
+
+    #[test]
+    fn test_complete_groups_are_emitted_at_once() {
+        let mut aggregator = aggregator(100);
+        assert!(aggregator
+            .push_frame(&data_frame(1, SOC, 0))
+            .unwrap()
+            .is_empty());
+        let groups = aggregator.push_frame(&data_frame(2, SOC, 0)).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].timestamp_us, SOC as i64 * 1_000_000);
+        assert_eq!(groups[0].frames.keys().copied().collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn test_incomplete_groups_wait_for_the_watermark() {
+        let mut aggregator = aggregator(100);
+        // Stream 2 misses frame 0
+        assert!(aggregator
+            .push_frame(&data_frame(1, SOC, 0))
+            .unwrap()
+            .is_empty());
+        for index in 1..3 {
+            assert!(aggregator
+                .push_frame(&data_frame(1, SOC, index))
+                .unwrap()
+                .is_empty());
+        }
+        // 100 ms after frame 0
+        let groups = aggregator.push_frame(&data_frame(1, SOC, 3)).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].missing(aggregator.idcodes()), [2]);
+        assert_eq!(
+            aggregator.watermark(),
+            Some(SOC as i64 * 1_000_000 + 100_000 - 100_000)
+        );
+
+        // Stream 2 catches up on frames 1-3, which completes them in order
+        for index in 1..3 {
+            let groups = aggregator.push_frame(&data_frame(2, SOC, index)).unwrap();
+            assert_eq!(groups.len(), 1);
+            assert_eq!(groups[0].frames.len(), 2);
+        }
+        let flushed = aggregator.flush();
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed[0].missing(aggregator.idcodes()), [2]);
+    }
+
+    #[test]
+    fn test_late_frames_are_dropped() {
+        let mut aggregator = aggregator(50);
+        for index in 0..5 {
+            aggregator.push_frame(&data_frame(1, SOC, index)).unwrap();
+        }
+        // Group 0 was emitted without stream 2
+        let groups = aggregator.push_frame(&data_frame(2, SOC, 0)).unwrap();
+        assert!(groups.is_empty());
+        assert_eq!(aggregator.late_frames(2), Some(1));
+        assert_eq!(aggregator.late_frames(1), Some(0));
+        // Not added to a pending group either
+        assert!(aggregator.flush().iter().all(|g| g.frames.len() == 1));
+    }
+
+    #[test]
+    fn test_late_frames_to_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("late.csv");
+        let mut aggregator = Aggregator::new(Duration::from_millis(50))
+            .with_late_data(LateData::Sink(Box::new(CsvSink::new(&path).unwrap())));
+        aggregator.add_stream(&config(1));
+        aggregator.add_stream(&config(2));
+        for index in 0..5 {
+            aggregator.push_frame(&data_frame(1, SOC, index)).unwrap();
+        }
+        aggregator.push_frame(&data_frame(2, SOC, 0)).unwrap();
+        aggregator.push_frame(&data_frame(2, SOC, 1)).unwrap();
+        drop(aggregator);
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        // Header and one row per late frame
+        assert_eq!(csv.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_late_frames_patch_historian() {
+        let historian = Arc::new(Mutex::new(Historian::new(MemoryBudget::unlimited())));
+        historian.lock().unwrap().add_stream(&config(2));
+        let mut aggregator = Aggregator::new(Duration::from_millis(50))
+            .with_late_data(LateData::Historian(historian.clone()));
+        aggregator.add_stream(&config(1));
+        aggregator.add_stream(&config(2));
+
+        // Stream 2 frames 3 and 4 are on time and stored by the application
+        for index in 0..5 {
+            aggregator.push_frame(&data_frame(1, SOC, index)).unwrap();
+        }
+        for index in 3..5 {
+            let frame = data_frame(2, SOC, index);
+            aggregator.push_frame(&frame).unwrap();
+            historian.lock().unwrap().insert(&frame).unwrap();
+        }
+        // Frames 0 and 1 arrive late and are patched in before them
+        aggregator.push_frame(&data_frame(2, SOC, 1)).unwrap();
+        aggregator.push_frame(&data_frame(2, SOC, 0)).unwrap();
+        assert_eq!(aggregator.late_frames(2), Some(2));
+
+        let historian = historian.lock().unwrap();
+        let start = SOC as i64 * 1_000_000;
+        assert_eq!(historian.time_range(2), Some((start, start + 133_333)));
+        let batch = historian.query(2, start, start + 200_000).unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 4);
+    }
+
+    #[test]
+    fn test_unknown_stream() {
+        let mut aggregator = aggregator(50);
+        assert!(matches!(
+            aggregator.push_frame(&data_frame(3, SOC, 0)),
+            Err(AggregatorError::UnknownStream(3))
+        ));
+    }
+
+    // Stream 1 from source a, stream 2 from source c.
+    fn sourced(policy: ConflictPolicy) -> Aggregator {
+        let mut aggregator =
+            Aggregator::new(Duration::from_millis(100)).with_conflict_policy(policy);
+        assert_eq!(
+            aggregator.add_stream_from("a", &config(1)).unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            aggregator.add_stream_from("c", &config(2)).unwrap(),
+            Some(2)
+        );
+        aggregator
+    }
+
+    fn other_config(idcode: u16) -> ConfigurationFrame1and2_2011 {
+        let mut config = config(idcode);
+        config.data_rate = 60;
+        config
+    }
+
+    #[test]
+    fn test_redundant_sources_are_merged() {
+        let mut aggregator = sourced(ConflictPolicy::Reject);
+        assert_eq!(
+            aggregator.add_stream_from("b", &config(1)).unwrap(),
+            Some(1)
+        );
+        aggregator
+            .push_frame_from("a", &data_frame(1, SOC, 0))
+            .unwrap();
+        assert!(aggregator
+            .push_frame_from("b", &data_frame(1, SOC, 0))
+            .unwrap()
+            .is_empty());
+        let groups = aggregator
+            .push_frame_from("c", &data_frame(2, SOC, 0))
+            .unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].frames[&1], data_frame(1, SOC, 0));
+        assert!(aggregator.conflicts().is_empty());
+    }
+
+    #[test]
+    fn test_config_conflict_prefer_first() {
+        let mut aggregator = sourced(ConflictPolicy::PreferFirst);
+        assert_eq!(
+            aggregator.add_stream_from("b", &other_config(1)).unwrap(),
+            None
+        );
+        assert!(aggregator
+            .push_frame_from("b", &data_frame(1, SOC, 0))
+            .unwrap()
+            .is_empty());
+        aggregator
+            .push_frame_from("a", &data_frame(1, SOC, 0))
+            .unwrap();
+        let groups = aggregator
+            .push_frame_from("c", &data_frame(2, SOC, 0))
+            .unwrap();
+        assert_eq!(groups[0].frames.keys().copied().collect::<Vec<_>>(), [1, 2]);
+
+        let conflict = &aggregator.conflicts()[0];
+        assert_eq!((conflict.idcode, conflict.source.as_str()), (1, "b"));
+        assert_eq!(conflict.kind, ConflictKind::Config);
+        assert_eq!((conflict.assigned, conflict.dropped), (None, 1));
+    }
+
+    #[test]
+    fn test_config_conflict_reject() {
+        let mut aggregator = sourced(ConflictPolicy::Reject);
+        match aggregator.add_stream_from("b", &other_config(1)) {
+            Err(AggregatorError::Conflict(conflict)) => assert_eq!(conflict.source, "b"),
+            other => panic!("Expected a conflict, got {:?}", other),
+        }
+        // The owner keeps the stream
+        assert_eq!(
+            aggregator.add_stream_from("a", &config(1)).unwrap(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_config_conflict_suffix() {
+        let mut aggregator = sourced(ConflictPolicy::Suffix);
+        // 2 is taken, the next free IDCODE is 3
+        assert_eq!(
+            aggregator.add_stream_from("b", &config(1)).unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            aggregator.add_stream_from("d", &other_config(1)).unwrap(),
+            Some(3)
+        );
+        let state = aggregator.checkpoint();
+        let suffixed = state
+            .configs
+            .iter()
+            .map(|frame| frame.config().unwrap())
+            .find(|config| config.prefix.idcode == 3)
+            .unwrap();
+        assert_eq!(&suffixed.pmu_configs[0].stn, b"Station A_2     ");
+        assert_eq!(suffixed.data_rate, 60);
+
+        aggregator
+            .push_frame_from("d", &data_frame(1, SOC, 0))
+            .unwrap();
+        aggregator
+            .push_frame_from("a", &data_frame(1, SOC, 0))
+            .unwrap();
+        let groups = aggregator
+            .push_frame_from("c", &data_frame(2, SOC, 0))
+            .unwrap();
+        assert_eq!(
+            groups[0].frames.keys().copied().collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        assert_eq!(groups[0].frames[&3][4..6], 3u16.to_be_bytes());
+    }
+
+    #[test]
+    fn test_data_conflict_suffix() {
+        let mut aggregator = sourced(ConflictPolicy::Suffix);
+        assert_eq!(
+            aggregator.add_stream_from("b", &config(1)).unwrap(),
+            Some(1)
+        );
+        // The frame of the second source arrives first, the owner's frame wins
+        let frame = data_frame(1, SOC, 0);
+        aggregator
+            .push_frame_from("b", &altered(frame.clone()))
+            .unwrap();
+        aggregator.push_frame_from("a", &frame).unwrap();
+        let conflict = aggregator.conflicts()[0].clone();
+        assert_eq!(
+            (conflict.source.as_str(), conflict.kind),
+            ("b", ConflictKind::Data)
+        );
+        assert_eq!(conflict.assigned, Some(3));
+
+        let groups = aggregator
+            .push_frame_from("c", &data_frame(2, SOC, 0))
+            .unwrap();
+        assert_eq!(groups[0].frames[&1], frame);
+        assert_eq!(groups[0].frames[&3][16], frame[16] ^ 0x01);
+        // Later frames of b go to its own stream
+        aggregator
+            .push_frame_from("b", &data_frame(1, SOC, 1))
+            .unwrap();
+        aggregator
+            .push_frame_from("a", &data_frame(1, SOC, 1))
+            .unwrap();
+        let groups = aggregator
+            .push_frame_from("c", &data_frame(2, SOC, 1))
+            .unwrap();
+        assert_eq!(
+            groups[0].frames.keys().copied().collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn test_stuck_clock_aligned_by_arrival() {
+        let mut aggregator = aggregator(100).with_arrival_fallback(Duration::from_secs(2));
+        let frame_time = |n: u32| {
+            SOC as i64 * 1_000_000 + (n / 30) as i64 * 1_000_000 + data_frame_fraction(n % 30)
+        };
+        let mut groups = Vec::new();
+        // Stream 2 arrives 50 ms after its frame time; its SOC sticks after 1 s
+        for n in 0..150 {
+            groups.extend(
+                aggregator
+                    .push_frame_at(&data_frame(1, SOC + n / 30, n % 30), frame_time(n) + 20_000)
+                    .unwrap(),
+            );
+            let soc = SOC + (n / 30).min(1);
+            groups.extend(
+                aggregator
+                    .push_frame_at(&data_frame(2, soc, n % 30), frame_time(n) + 50_000)
+                    .unwrap(),
+            );
+        }
+        assert_eq!(aggregator.is_by_arrival(2), Some(true));
+        assert_eq!(aggregator.is_by_arrival(1), Some(false));
+        // Found stuck two seconds after the SOC last changed
+        assert_eq!(aggregator.arrival_frames(2), Some(60));
+        // The frames of the stuck second were late
+        assert_eq!(aggregator.late_frames(2), Some(30));
+
+        let group = groups
+            .iter()
+            .find(|group| group.timestamp_us == frame_time(100))
+            .unwrap();
+        assert_eq!(group.frames.len(), 2);
+        let frame = &group.frames[&2];
+        let soc = u32::from_be_bytes([frame[6], frame[7], frame[8], frame[9]]);
+        assert_eq!(soc, SOC + 3);
+        // Clock failure, PMU sync error and sorted by arrival
+        assert_eq!(frame[10] & 0x0F, 0x0F);
+        let stat = u16::from_be_bytes([frame[14], frame[15]]);
+        assert_eq!(stat & 0x3000, 0x3000);
+        let len = frame.len();
+        let crc = pmu::frames::calculate_crc(&frame[..len - 2]);
+        assert_eq!(frame[len - 2..], crc.to_be_bytes());
+        // Stream 1 is untouched
+        assert_eq!(group.frames[&1], data_frame(1, SOC + 3, 10));
+
+        // Once the SOC moves again the frames' own time counts
+        aggregator
+            .push_frame_at(&data_frame(2, SOC + 5, 0), frame_time(150) + 50_000)
+            .unwrap();
+        assert_eq!(aggregator.is_by_arrival(2), Some(false));
+        assert_eq!(aggregator.arrival_frames(2), Some(60));
+    }
+}
@@ -0,0 +1,1622 @@
+#![cfg(feature = "analytics")]
+#![allow(unused)]
+mod common;
+
+use arrow::array::{Float64Array, TimestampMicrosecondArray};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use common::read_hex_file;
+use pmu::accumulator::BatchAccumulator;
+use pmu::analytics::accuracy::{
+    compare, compare_streams, frame_measurements, stream_measurements, summarize, PmuMeasurement,
+};
+use pmu::analytics::angle_reference::{AngleReference, AngleReferencer};
+use pmu::analytics::anomaly::{AnomalyConfig, AnomalyDetector, AnomalyKind};
+use pmu::analytics::coherency::{CoherencyClusterer, CoherencyConfig};
+use pmu::analytics::compliance::{
+    evaluate_capture, run_compliance, standard_conditions, DeviceUnderTest, PerformanceClass,
+    SimulatedDevice,
+};
+use pmu::analytics::frequency_event::{FrequencyEventClassifier, FrequencyEventConfig};
+use pmu::analytics::frequency_response::FrequencyResponse;
+use pmu::analytics::jitter::{phasor_angles, AngleJitter, JitterMonitor, JITTER_GAUGE};
+use pmu::analytics::line_outage::{LineOutageConfig, LineOutageDetector, StationPair};
+use pmu::analytics::magnitude_step::{MagnitudeStepConfig, MagnitudeStepDetector};
+use pmu::analytics::oscillation::{
+    identify_modes, Mode, ModeTracker, OscillationConfig, OscillationDetector,
+};
+use pmu::analytics::reference::{FrequencyRamp, Modulation, ReferenceSignal, Step};
+use pmu::analytics::spectrogram::{
+    frequency_series, spectrogram, spectrogram_batch, SpectrogramConfig, WindowFunction,
+    META_SPECTROGRAM_CHANNEL,
+};
+use pmu::analytics::time_skew::{estimate_skew, TimeSkewConfig};
+use pmu::analytics::trigger::{Comparison, Condition, TriggerDefinition, TriggerEngine};
+use pmu::analytics::ufls::{evaluate_frames, UflsConfig, UflsSimulation};
+use pmu::analytics::voltage_stability::{
+    stability_batch, BusSample, VoltageStabilityConfig, VoltageStabilityMonitor,
+};
+use pmu::analytics::{frequency_error, rocof_error, tve, Phasor};
+use pmu::annotations::{Annotation, AnnotationKind, AnnotationStore};
+use pmu::budget::MemoryBudget;
+use pmu::events::{EventBus, EventKind, Severity};
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::frames::ConfigurationFrame1and2_2011;
+use pmu::metrics::Metrics;
+use pmu::recorder::{CaptureCompression, CaptureWriter};
+use pmu::simulator::{NoiseModel, Scenario, ScenarioEvent, SimulatedPmu, Simulator, StreamLayout};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+fn frames(simulator: &mut Simulator, count: usize) -> Vec<Vec<u8>> {
+    (0..count)
+        .flat_map(|_| simulator.next_tick().frames)
+        .collect()
+}
+
+// Rows of (timestamp, value per channel) as a batch of Float64 columns.
+fn channel_batch(channels: &[&str], rows: &[(i64, Vec<f64>)]) -> RecordBatch {
+    let mut fields = vec![Field::new(
+        "timestamp",
+        DataType::Timestamp(TimeUnit::Microsecond, None),
+        false,
+    )];
+    fields.extend(
+        channels
+            .iter()
+            .map(|channel| Field::new(*channel, DataType::Float64, false)),
+    );
+    let mut columns: Vec<arrow::array::ArrayRef> = vec![Arc::new(TimestampMicrosecondArray::from(
+        rows.iter().map(|(t, _)| *t).collect::<Vec<_>>(),
+    ))];
+    for index in 0..channels.len() {
+        columns.push(Arc::new(Float64Array::from(
+            rows.iter()
+                .map(|(_, values)| values[index])
+                .collect::<Vec<_>>(),
+        )));
+    }
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tve() {
+        let reference = Phasor::from_polar(100.0, 0.3);
+        assert_eq!(tve(reference, reference), 0.0);
+        // 1% magnitude error
+        let measured = Phasor::from_polar(101.0, 0.3);
+        assert!((tve(reference, measured) - 1.0).abs() < 1e-9);
+        // An angle error of 0.573 degrees is about 1% TVE
+        let measured = Phasor::from_polar(100.0, 0.3 + 0.01);
+        assert!((tve(reference, measured) - 1.0).abs() < 1e-3);
+
+        assert_eq!(tve(Phasor::default(), Phasor::default()), 0.0);
+        assert!(tve(Phasor::default(), Phasor::new(1.0, 0.0)).is_infinite());
+    }
+
+    #[test]
+    fn test_frequency_and_rocof_errors() {
+        assert!((frequency_error(60.0, 60.004) - 0.004).abs() < 1e-9);
+        assert!((frequency_error(60.0, 59.996) - 0.004).abs() < 1e-9);
+        assert!((rocof_error(0.1, -0.3) - 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_fixed_point_measurements() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let scenario = Scenario {
+            start_soc: Some(1_700_000_000),
+            ..Default::default()
+        };
+        let mut sim = Simulator::new(config.clone(), scenario);
+        let frame = sim.next_tick().frames.remove(0);
+
+        let measurements = frame_measurements(&frame, &config).unwrap();
+        assert_eq!(measurements.len(), 1);
+        let measurement = &measurements[0];
+        assert_eq!(measurement.timestamp_us, 1_700_000_000_000_000);
+        assert_eq!(measurement.frequency, 60.0);
+        assert_eq!(measurement.rocof, 0.0);
+        assert_eq!(measurement.phasors.len(), 4);
+        let scale = (config.pmu_configs[0].phunit[0] & 0x00FF_FFFF) as f64 * 1e-5;
+        assert!((measurement.phasors[0].magnitude() - 20_000.0 * scale).abs() < scale);
+        assert!(measurement.phasors[0].angle().abs() < 1e-3);
+
+        let mut corrupt = frame.clone();
+        let len = corrupt.len();
+        corrupt[len - 1] ^= 0xFF;
+        assert!(frame_measurements(&corrupt, &config).is_err());
+        assert!(frame_measurements(&frame[..len - 4], &config).is_err());
+    }
+
+    #[test]
+    fn test_compare_streams() {
+        let layout = StreamLayout {
+            idcode: 9,
+            data_rate: 50,
+            time_base: 1_000_000,
+            pmus: vec![SimulatedPmu {
+                station: "DUT".to_string(),
+                idcode: 9,
+                polar: true,
+                float_phasors: true,
+                float_analogs: true,
+                float_freq: true,
+                phasors: 3,
+                analogs: 0,
+                digitals: 0,
+                data_rate: None,
+                nominal_50hz: true,
+                angle: 0.0,
+            }],
+        };
+        let scenario = Scenario {
+            start_soc: Some(1_700_000_000),
+            ..Default::default()
+        };
+        let noisy = Scenario {
+            noise: Some(NoiseModel {
+                seed: 11,
+                tve_percent: Some(0.5),
+                frequency_std: 0.001,
+                ..Default::default()
+            }),
+            ..scenario.clone()
+        };
+        let mut reference = Simulator::from_layout(&layout, scenario);
+        let mut device = Simulator::from_layout(&layout, noisy);
+        let config = reference.config().clone();
+
+        let reference = stream_measurements(&frames(&mut reference, 100), &config, 0);
+        // The device misses its first ten frames
+        let device = stream_measurements(&frames(&mut device, 100)[10..], &config, 0);
+        assert_eq!(reference[0].frequency, 50.0);
+
+        let errors = compare_streams(&reference, &device);
+        assert_eq!(errors.len(), 90);
+        assert_eq!(errors[0].timestamp_us, device[0].timestamp_us);
+        for error in &errors {
+            assert_eq!(error.tve.len(), 3);
+            assert!(error.tve.iter().all(|tve| (tve - 0.5).abs() < 1e-3));
+        }
+
+        let summary = summarize(&errors);
+        assert_eq!(summary.count, 90);
+        assert!((summary.mean_tve - 0.5).abs() < 1e-3);
+        assert!(summary.max_fe > 0.0 && summary.max_fe < 0.01);
+        assert_eq!(summary.max_rfe, 0.0);
+
+        let exact = compare(&reference[3], &reference[3]);
+        assert_eq!(exact.max_tve(), 0.0);
+    }
+
+    fn sample_config() -> ConfigurationFrame1and2_2011 {
+        parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_ideal_device_complies() {
+        for class in [PerformanceClass::P, PerformanceClass::M] {
+            let mut device = SimulatedDevice::new(sample_config());
+            let report = run_compliance(&mut device, class);
+            assert_eq!(report.results.len(), 6);
+            assert!(report.passed(), "{}", report.render());
+            assert!(report.results.iter().all(|result| result.summary.count > 0));
+
+            let steps = report
+                .results
+                .iter()
+                .find(|result| result.name == "phase_step")
+                .unwrap();
+            assert!(steps.excluded > 0);
+
+            let json = report.to_json();
+            assert_eq!(json["class"], class.name());
+            assert_eq!(json["passed"], true);
+            assert_eq!(json["conditions"].as_array().unwrap().len(), 6);
+        }
+    }
+
+    #[test]
+    fn test_noisy_device_fails() {
+        let mut device = SimulatedDevice::new(sample_config()).with_noise(NoiseModel {
+            seed: 5,
+            tve_percent: Some(1.5),
+            frequency_std: 0.01,
+            ..Default::default()
+        });
+        let report = run_compliance(&mut device, PerformanceClass::P);
+        assert!(!report.passed());
+        for result in &report.results {
+            assert!(result.failures.iter().any(|f| f.starts_with("TVE")));
+            assert!(result.failures.iter().any(|f| f.starts_with("FE")));
+            assert!(result.summary.mean_tve > 1.4 && result.summary.mean_tve < 1.6);
+        }
+        let text = report.render();
+        assert!(text.contains("frequency_ramp"));
+        assert!(text.ends_with("FAIL\n"));
+    }
+
+    struct BrokenDevice;
+
+    impl DeviceUnderTest for BrokenDevice {
+        fn measure(
+            &mut self,
+            _scenario: &Scenario,
+        ) -> Result<(ConfigurationFrame1and2_2011, Vec<Vec<u8>>), String> {
+            Err("not connected".to_string())
+        }
+    }
+
+    #[test]
+    fn test_device_errors_fail() {
+        let report = run_compliance(&mut BrokenDevice, PerformanceClass::M);
+        assert!(!report.passed());
+        assert_eq!(
+            report.results[0].failures,
+            vec!["Device failed: not connected".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_capture_compliance() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = sample_config();
+        let condition = standard_conditions(PerformanceClass::P)
+            .into_iter()
+            .find(|condition| condition.name == "magnitude_step")
+            .unwrap();
+
+        let record = |path: &Path, noise: Option<NoiseModel>| {
+            let mut device = SimulatedDevice::new(config.clone());
+            if let Some(noise) = noise {
+                device = device.with_noise(noise);
+            }
+            let (config, frames) = device.measure(&condition.scenario).unwrap();
+            let mut writer = CaptureWriter::create(path, CaptureCompression::None).unwrap();
+            writer.write_frame(&config.to_hex()).unwrap();
+            for frame in &frames {
+                writer.write_frame(frame).unwrap();
+            }
+            writer.finish().unwrap();
+        };
+
+        let clean = dir.path().join("clean.pmucap");
+        record(&clean, None);
+        let result = evaluate_capture(&clean, None, &condition, PerformanceClass::P).unwrap();
+        assert!(result.passed(), "{:?}", result.failures);
+        assert_eq!(result.summary.count + result.excluded, 120);
+
+        let noisy = dir.path().join("noisy.pmucap");
+        record(
+            &noisy,
+            Some(NoiseModel {
+                seed: 2,
+                tve_percent: Some(2.0),
+                ..Default::default()
+            }),
+        );
+        let result =
+            evaluate_capture(&noisy, Some(&config), &condition, PerformanceClass::P).unwrap();
+        assert!(!result.passed());
+        assert!(result.summary.max_tve > 1.9);
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_reference_off_nominal_and_steps() {
+        let signal = ReferenceSignal {
+            phase_deg: 30.0,
+            frequency_offset: 0.5,
+            magnitude_step: Some(Step { at: 1.0, size: 0.1 }),
+            phase_step: Some(Step {
+                at: 1.0,
+                size: -10.0,
+            }),
+            ..Default::default()
+        };
+        let value = signal.at(0.25);
+        assert!(close(value.magnitude, 1.0));
+        assert!(close(value.angle, PI / 6.0 + 2.0 * PI * 0.5 * 0.25));
+        assert!(close(value.frequency_offset, 0.5));
+        assert!(close(value.rocof, 0.0));
+
+        let value = signal.at(1.0);
+        assert!(close(value.magnitude, 1.1));
+        assert!(close(value.angle, PI / 6.0 + PI - 10f64.to_radians()));
+    }
+
+    #[test]
+    fn test_reference_modulation() {
+        let signal = ReferenceSignal {
+            amplitude_modulation: Some(Modulation {
+                depth: 0.1,
+                frequency: 2.0,
+            }),
+            phase_modulation: Some(Modulation {
+                depth: 0.1,
+                frequency: 2.0,
+            }),
+            ..Default::default()
+        };
+        let value = signal.at(0.0);
+        assert!(close(value.magnitude, 1.1));
+        assert!(close(value.angle, -0.1));
+        assert!(close(value.frequency_offset, 0.0));
+        // ROCOF peaks where the angle does, ka w^2 / 2pi
+        let w = 2.0 * PI * 2.0;
+        assert!(close(value.rocof, 0.1 * w * w / (2.0 * PI)));
+
+        // A quarter period later the frequency deviation peaks
+        let value = signal.at(0.125);
+        assert!(close(value.magnitude, 1.0));
+        assert!(close(value.frequency_offset, 0.1 * w / (2.0 * PI)));
+    }
+
+    #[test]
+    fn test_reference_ramp() {
+        let signal = ReferenceSignal {
+            ramp: Some(FrequencyRamp {
+                at: 1.0,
+                rate: 1.0,
+                duration: Some(2.0),
+            }),
+            ..Default::default()
+        };
+        assert!(close(signal.at(0.5).frequency_offset, 0.0));
+        let value = signal.at(2.0);
+        assert!(close(value.frequency_offset, 1.0));
+        assert!(close(value.rocof, 1.0));
+        assert!(close(value.angle, PI));
+
+        // The angle keeps advancing at the final deviation after the ramp
+        let value = signal.at(4.0);
+        assert!(close(value.frequency_offset, 2.0));
+        assert!(close(value.rocof, 0.0));
+        assert!(close(value.angle, 4.0 * PI + 2.0 * PI * 2.0));
+    }
+
+    #[test]
+    fn test_reference_drives_simulator() {
+        let signal = ReferenceSignal {
+            frequency_offset: 0.2,
+            amplitude_modulation: Some(Modulation {
+                depth: 0.1,
+                frequency: 1.0,
+            }),
+            phase_modulation: Some(Modulation {
+                depth: 0.05,
+                frequency: 1.0,
+            }),
+            ..Default::default()
+        };
+        let scenario = Scenario::from_json(
+            r#"{
+                "start_soc": 1700000000,
+                "signal": {
+                    "frequency_offset": 0.2,
+                    "amplitude_modulation": {"depth": 0.1, "frequency": 1.0},
+                    "phase_modulation": {"depth": 0.05, "frequency": 1.0}
+                },
+                "stream": {
+                    "idcode": 5,
+                    "data_rate": 60,
+                    "pmus": [{
+                        "station": "REF",
+                        "idcode": 5,
+                        "float_phasors": true,
+                        "float_freq": true
+                    }]
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(scenario.signal.as_ref(), Some(&signal));
+        let layout = scenario.stream.clone().unwrap();
+        let mut sim = Simulator::from_layout(&layout, scenario);
+        let config = sim.config().clone();
+
+        let simulated = stream_measurements(&frames(&mut sim, 120), &config, 0);
+        let reference = signal.measurements(1_700_000_000_000_000, 60, 120, 60.0, 1.0, 3);
+        assert_eq!(reference[1].timestamp_us, 1_700_000_000_016_667);
+
+        let errors = compare_streams(&reference, &simulated);
+        assert_eq!(errors.len(), 120);
+        let summary = summarize(&errors);
+        assert!(summary.max_tve < 1e-4, "{:?}", summary);
+        assert!(summary.max_fe < 1e-4, "{:?}", summary);
+        assert!(summary.max_rfe < 1e-4, "{:?}", summary);
+    }
+
+    #[test]
+    fn test_spectrogram_of_a_tone() {
+        // 3.75 Hz is bin 8 of a 64 sample window at 30 samples/s
+        let samples: Vec<(i64, f64)> = (0..256)
+            .map(|n| {
+                let t = n as f64 / 30.0;
+                ((t * 1e6) as i64, 5.0 + 2.0 * (2.0 * PI * 3.75 * t).sin())
+            })
+            .collect();
+        let config =
+            SpectrogramConfig::new(64, 32).with_window_function(WindowFunction::Rectangular);
+        let (bins, columns) = spectrogram(&samples, 30.0, config);
+        assert_eq!(bins.len(), 33);
+        assert!(close(bins[8], 3.75));
+        assert_eq!(columns.len(), 7);
+        // Centred on the window, samples 0-63 then 32-95
+        assert_eq!(columns[0].timestamp_us, samples[63].0 / 2);
+        assert_eq!(columns[1].timestamp_us, (samples[32].0 + samples[95].0) / 2);
+        for column in &columns {
+            // Mean square of the sine, the offset is removed
+            assert!(close(column.power[8], 2.0));
+            let rest: f64 = column.power.iter().sum::<f64>() - column.power[8];
+            assert!(rest < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_spectrogram_of_simulated_oscillation() {
+        // Phase modulated at 1.875 Hz, the frequency swings 0.05 x 1.875 Hz
+        let scenario = Scenario::from_json(
+            r#"{
+                "start_soc": 1700000000,
+                "signal": {"phase_modulation": {"depth": 0.05, "frequency": 1.875}},
+                "stream": {
+                    "idcode": 5,
+                    "data_rate": 60,
+                    "pmus": [{"station": "OSC", "idcode": 5, "float_freq": true}]
+                }
+            }"#,
+        )
+        .unwrap();
+        let layout = scenario.stream.clone().unwrap();
+        let mut sim = Simulator::from_layout(&layout, scenario);
+        let config = sim.config().clone();
+        let measurements = stream_measurements(&frames(&mut sim, 600), &config, 0);
+
+        let (bins, columns) = spectrogram(
+            &frequency_series(&measurements),
+            60.0,
+            SpectrogramConfig::new(64, 48),
+        );
+        assert_eq!(columns.len(), 1 + (600 - 64) / 16);
+        let amplitude = 0.05 * 1.875;
+        for column in &columns {
+            let peak = (0..bins.len())
+                .max_by(|a, b| column.power[*a].total_cmp(&column.power[*b]))
+                .unwrap();
+            assert!(close(bins[peak], 1.875));
+            let expected = amplitude * amplitude / 2.0;
+            assert!((column.power[peak] - expected).abs() < 0.05 * expected);
+        }
+
+        let batch = spectrogram_batch("OSC_5_FREQ", &bins, &columns).unwrap();
+        assert_eq!(batch.num_rows(), columns.len() * bins.len());
+        assert_eq!(batch.num_columns(), 3);
+        assert_eq!(
+            batch.schema().metadata().get(META_SPECTROGRAM_CHANNEL),
+            Some(&"OSC_5_FREQ".to_string())
+        );
+    }
+
+    // Damped sinusoid, damping ratio zeta, on top of an offset.
+    fn ringdown(t: f64, amplitude: f64, frequency: f64, zeta: f64) -> f64 {
+        let w = 2.0 * PI * frequency / (1.0 - zeta * zeta).sqrt();
+        amplitude * (-zeta * w * t).exp() * (2.0 * PI * frequency * t).cos()
+    }
+
+    #[test]
+    fn test_identify_modes() {
+        let samples: Vec<f64> = (0..600)
+            .map(|n| {
+                let t = n as f64 / 30.0;
+                60.0 + ringdown(t, 0.2, 0.5, 0.05) + ringdown(t, 0.1, 1.2, 0.1)
+            })
+            .collect();
+        let modes = identify_modes(&samples, 30.0, &OscillationConfig::default());
+        assert_eq!(modes.len(), 2, "{:?}", modes);
+        assert!((modes[0].frequency - 0.5).abs() < 1e-3, "{:?}", modes);
+        assert!((modes[0].damping - 0.05).abs() < 1e-3, "{:?}", modes);
+        assert!((modes[0].amplitude - 0.2).abs() < 1e-3, "{:?}", modes);
+        assert!((modes[1].frequency - 1.2).abs() < 1e-3, "{:?}", modes);
+        assert!((modes[1].damping - 0.1).abs() < 1e-3, "{:?}", modes);
+
+        let config = OscillationConfig {
+            max_frequency: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(identify_modes(&samples, 30.0, &config).len(), 1);
+    }
+
+    #[test]
+    fn test_mode_tracking_and_damping_alarm() {
+        let mode = |frequency, damping| Mode {
+            frequency,
+            damping,
+            amplitude: 0.1,
+        };
+        let mut tracker = ModeTracker::new(0.1, 0.05).with_max_missed(1);
+        assert!(tracker
+            .update(0, &[mode(0.50, 0.10), mode(1.20, 0.08)])
+            .is_empty());
+        // The 0.5 Hz mode drifts and loses damping, the order does not matter
+        let alarms = tracker.update(5, &[mode(1.21, 0.08), mode(0.53, 0.03)]);
+        assert_eq!(alarms.len(), 1);
+        assert_eq!(alarms[0].mode_id, 0);
+        assert!((alarms[0].frequency - 0.53).abs() < 1e-9);
+        // Still low, no new alarm
+        assert!(tracker.update(10, &[mode(0.54, 0.02)]).is_empty());
+        // Recovers, then drops again
+        tracker.update(15, &[mode(0.54, 0.06)]);
+        assert_eq!(tracker.update(20, &[mode(0.55, 0.04)]).len(), 1);
+
+        let ids: Vec<u32> = tracker.tracks().iter().map(|t| t.id).collect();
+        assert_eq!(ids, [0]);
+        // 1.2 Hz went unseen for more than one window
+        assert_eq!(tracker.finished().len(), 1);
+        assert_eq!(tracker.finished()[0].points.len(), 2);
+        let damping: Vec<f64> = tracker.tracks()[0]
+            .points
+            .iter()
+            .map(|p| p.damping)
+            .collect();
+        assert_eq!(damping, [0.10, 0.03, 0.02, 0.06, 0.04]);
+
+        // A mode further than the tolerance starts a new track
+        tracker.update(25, &[mode(0.80, 0.1)]);
+        assert_eq!(tracker.tracks().len(), 2);
+    }
+
+    #[test]
+    fn test_detector_tracks_simulated_ringdowns() {
+        // A 0.7 Hz oscillation whose damping drops from 10% to 2% halfway
+        let config = OscillationConfig {
+            window: 300,
+            step: 300,
+            ..Default::default()
+        };
+        let mut detector = OscillationDetector::new(config, 30.0);
+        let mut tracker = ModeTracker::new(0.05, 0.05);
+        let mut alarms = Vec::new();
+        for n in 0..1800 {
+            let zeta = if n < 900 { 0.10 } else { 0.02 };
+            // A new ringdown starts every window
+            let t = (n % 300) as f64 / 30.0;
+            let value = 60.0 + ringdown(t, 0.05, 0.7, zeta);
+            if let Some((timestamp, modes)) = detector.push(n as i64 * 33_333, value) {
+                alarms.extend(tracker.update(timestamp, &modes[..1]));
+            }
+        }
+        assert_eq!(tracker.tracks().len(), 1);
+        let points = &tracker.tracks()[0].points;
+        assert_eq!(points.len(), 6);
+        assert!(points[..3].iter().all(|p| (p.damping - 0.10).abs() < 1e-3));
+        assert!(points[3..].iter().all(|p| (p.damping - 0.02).abs() < 1e-3));
+        assert_eq!(alarms.len(), 1);
+        assert_eq!(alarms[0].timestamp_us, 1199 * 33_333);
+    }
+
+    // Frequency at 30 samples/s: nominal for 20 s, then an excursion of sign
+    // (-1 for a trip) settling 0.05 Hz away with a nadir near 0.15 Hz.
+    fn excursion(sign: f64) -> Vec<(i64, f64)> {
+        (0..30 * 90)
+            .map(|n| {
+                let t = n as f64 / 30.0;
+                let tau = (t - 20.0).max(0.0);
+                let deviation =
+                    0.05 * (1.0 - (-tau).exp()) + 0.1 * (tau / 5.0) * (1.0 - tau / 5.0).exp();
+                ((t * 1e6).round() as i64, 60.0 + sign * deviation)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_generator_trip_classification() {
+        let bus = EventBus::new(16);
+        let mut events = bus.subscribe();
+        let config = FrequencyEventConfig::new(1000.0).with_alarm(400.0);
+        let mut classifier = FrequencyEventClassifier::new(config, "Station A").with_event_bus(bus);
+
+        let classified: Vec<_> = excursion(-1.0)
+            .into_iter()
+            .filter_map(|(t, f)| classifier.push(t, f))
+            .collect();
+        assert_eq!(classified.len(), 1);
+        let trip = classified[0];
+        assert_eq!(trip.kind, EventKind::GeneratorTrip);
+        assert!(
+            (trip.start_us as f64 / 1e6 - 20.4).abs() < 0.1,
+            "{:?}",
+            trip
+        );
+        assert!((trip.pre_event_hz - 60.0).abs() < 0.002, "{:?}", trip);
+        assert!((trip.nadir_hz - 59.85).abs() < 0.01, "{:?}", trip);
+        assert!(
+            (trip.nadir_us as f64 / 1e6 - 25.0).abs() < 0.5,
+            "{:?}",
+            trip
+        );
+        assert!((trip.settling_hz - 59.95).abs() < 0.005, "{:?}", trip);
+        assert!(trip.initial_rocof < -0.05, "{:?}", trip);
+        // 0.05 Hz at 1000 MW per 0.1 Hz
+        assert!((trip.size_mw - 500.0).abs() < 50.0, "{:?}", trip);
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.kind, EventKind::GeneratorTrip);
+        assert_eq!(event.severity, Severity::Alarm);
+        assert_eq!(event.source, "Station A");
+        assert_eq!(event.values["size_mw"], trip.size_mw);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_frequency_response_of_reported_loss() {
+        let bus = EventBus::new(16);
+        let mut events = bus.subscribe();
+        let config = FrequencyEventConfig::new(1000.0);
+        let mut classifier = FrequencyEventClassifier::new(config, "Area 1").with_event_bus(bus);
+        let mut classified = Vec::new();
+        for (t, f) in excursion(-1.0) {
+            // Too long before the event, then the unit that tripped
+            if t == 5_000_000 {
+                classifier.report_loss(t, 900.0);
+            }
+            if t == 21_000_000 {
+                classifier.report_loss(20_000_000, 600.0);
+            }
+            classified.extend(classifier.push(t, f));
+        }
+        assert_eq!(classified.len(), 1);
+        let response = classified[0].response.unwrap();
+        assert_eq!(response.delta_p_mw, 600.0);
+        // 0.05 Hz settled, 0.15 Hz at the nadir
+        assert!(
+            (response.settling_beta - 1200.0).abs() < 100.0,
+            "{:?}",
+            response
+        );
+        assert!((response.nadir_beta - 400.0).abs() < 30.0, "{:?}", response);
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.values["delta_p_mw"], 600.0);
+        assert_eq!(
+            event.values["settling_beta_mw_per_0_1hz"],
+            response.settling_beta
+        );
+        assert!(
+            event.message.contains("MW/0.1 Hz settling"),
+            "{}",
+            event.message
+        );
+
+        // No loss reported, no response
+        let mut classifier = FrequencyEventClassifier::new(config, "Area 1");
+        let classified: Vec<_> = excursion(-1.0)
+            .into_iter()
+            .filter_map(|(t, f)| classifier.push(t, f))
+            .collect();
+        assert_eq!(classified[0].response, None);
+        assert!(!classified[0]
+            .to_event("Area 1", Severity::Warning)
+            .values
+            .contains_key("delta_p_mw"));
+
+        // Nor without a settled change
+        let mut flat = classified[0];
+        flat.settling_hz = flat.pre_event_hz;
+        assert_eq!(FrequencyResponse::estimate(&flat, 600.0), None);
+    }
+
+    #[test]
+    fn test_load_loss_classification() {
+        let config = FrequencyEventConfig::new(200.0);
+        let mut classifier = FrequencyEventClassifier::new(config, "B");
+        let classified: Vec<_> = excursion(1.0)
+            .into_iter()
+            .filter_map(|(t, f)| classifier.push(t, f))
+            .collect();
+        assert_eq!(classified.len(), 1);
+        assert_eq!(classified[0].kind, EventKind::LoadLoss);
+        assert!((classified[0].nadir_hz - 60.15).abs() < 0.01);
+        assert!((classified[0].size_mw - 100.0).abs() < 10.0);
+
+        // Steady frequency is not an event
+        let mut classifier = FrequencyEventClassifier::new(config, "B");
+        assert!((0..3000).all(|n| classifier.push(n * 33_333, 60.01).is_none()));
+    }
+
+    // Angles of stations A, B and C at 30 samples/s, drifting 1 deg/s apart,
+    // reported in -180..180 degrees. From step_at seconds A leads by step_ab
+    // more and C lags by step_bc more.
+    fn station_angles(n: i64, step_at: f64, step_ab: f64, step_bc: f64) -> HashMap<String, f64> {
+        let t = n as f64 / 30.0;
+        let (ab, bc) = if t >= step_at {
+            (step_ab, step_bc)
+        } else {
+            (0.0, 0.0)
+        };
+        let wrap = |deg: f64| ((deg + 180.0).rem_euclid(360.0) - 180.0).to_radians();
+        HashMap::from([
+            ("A".to_string(), wrap(170.0 + t + ab)),
+            ("B".to_string(), wrap(165.0)),
+            ("C".to_string(), wrap(160.0 - t - bc)),
+        ])
+    }
+
+    #[test]
+    fn test_correlated_angle_steps() {
+        let bus = EventBus::new(4);
+        let mut events = bus.subscribe();
+        let pairs = vec![StationPair::new("A", "B"), StationPair::new("B", "C")];
+        let mut detector =
+            LineOutageDetector::new(LineOutageConfig::default(), pairs).with_event_bus(bus);
+
+        let mut changes = Vec::new();
+        for n in 0..300 {
+            // B-C steps a tenth of a second after A-B
+            let mut angles = station_angles(n, 5.0, 8.0, 0.0);
+            let later = station_angles(n, 5.1, 0.0, 6.0);
+            angles.insert("C".to_string(), later["C"]);
+            changes.extend(detector.push(n * 33_333, &angles));
+        }
+        changes.extend(detector.flush());
+        assert_eq!(changes.len(), 1);
+        let change = &changes[0];
+        assert_eq!(change.timestamp_us, 150 * 33_333);
+        let pairs: Vec<&str> = change.steps.iter().map(|s| s.pair.as_str()).collect();
+        assert_eq!(pairs, ["A-B", "B-C"]);
+        assert!(
+            (change.steps[0].change_deg() - 8.5).abs() < 0.1,
+            "{:?}",
+            change
+        );
+        assert!(
+            (change.steps[1].change_deg() - 6.5).abs() < 0.1,
+            "{:?}",
+            change
+        );
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.kind, EventKind::TopologyChange);
+        assert_eq!(event.values["pairs"], 2.0);
+    }
+
+    #[test]
+    fn test_no_step_on_drift_or_wrap() {
+        let pairs = vec![StationPair::new("A", "B"), StationPair::new("A", "C")];
+        let mut detector = LineOutageDetector::new(LineOutageConfig::default(), pairs);
+        // A's angle wraps to -180 degrees after 10 s, which is not a step
+        for n in 0..600 {
+            assert!(detector
+                .push(n * 33_333, &station_angles(n, f64::MAX, 0.0, 0.0))
+                .is_none());
+        }
+        assert!(detector.flush().is_none());
+
+        // Separate steps more than the correlation time apart are separate changes
+        let pairs = vec![StationPair::new("A", "B"), StationPair::new("B", "C")];
+        let mut detector = LineOutageDetector::new(LineOutageConfig::default(), pairs);
+        let mut changes = Vec::new();
+        for n in 0..300 {
+            let mut angles = station_angles(n, 2.0, 8.0, 0.0);
+            angles.insert("C".to_string(), station_angles(n, 6.0, 0.0, 6.0)["C"]);
+            changes.extend(detector.push(n * 33_333, &angles));
+        }
+        changes.extend(detector.flush());
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|c| c.steps.len() == 1));
+    }
+
+    // Stations whose angles drift together at 0.05 Hz off nominal, B 20 and
+    // C 45 degrees behind A.
+    fn drifting(n: i64) -> HashMap<String, PmuMeasurement> {
+        let drift = 2.0 * PI * 0.05 * n as f64 / 30.0;
+        [("A", 0.0), ("B", -20.0), ("C", -45.0)]
+            .into_iter()
+            .map(|(station, offset): (&str, f64)| {
+                let measurement = PmuMeasurement {
+                    timestamp_us: n * 33_333,
+                    stat: 0,
+                    phasors: vec![Phasor::from_polar(1.0, drift + offset.to_radians())],
+                    frequency: 60.05,
+                    rocof: 0.0,
+                };
+                (station.to_string(), measurement)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_coherent_groups() {
+        let bus = EventBus::new(4);
+        let mut events = bus.subscribe();
+        let mut clusterer = CoherencyClusterer::new(CoherencyConfig::default()).with_event_bus(bus);
+        let mut changes = Vec::new();
+        // A and B swing against C at 0.5 Hz, after 20 s B swings with C.
+        // A sits at 179 degrees, its angle wraps on every swing.
+        for n in 0..30 * 40 {
+            let t = n as f64 / 30.0;
+            let swing = (2.0 * PI * 0.5 * t).sin() * 5f64.to_radians();
+            let b = if t < 20.0 { swing } else { -swing };
+            let angles = HashMap::from([
+                ("A".to_string(), 179f64.to_radians() + swing),
+                ("B".to_string(), 0.3 + b),
+                ("C".to_string(), -0.4 - swing),
+                ("D".to_string(), 1.0),
+            ]);
+            changes.extend(clusterer.push(n * 33_333, &angles));
+            if n == 30 * 15 {
+                let groups = &clusterer.current().unwrap().groups;
+                assert_eq!(groups, &[vec!["A", "B"], vec!["C"], vec!["D"]]);
+            }
+        }
+        // B leaves A's group first, and joins C's once the window holds
+        // mostly swings with C
+        assert_eq!(changes.len(), 2);
+        assert_eq!(clusterer.changes(), 2);
+        assert!(changes[0].timestamp_us > 20_000_000);
+        assert_eq!(
+            changes[0].groups,
+            [vec!["A"], vec!["B"], vec!["C"], vec!["D"]]
+        );
+        let change = &changes[1];
+        assert!(change.timestamp_us < 30_000_000);
+        assert_eq!(change.groups, [vec!["A"], vec!["B", "C"], vec!["D"]]);
+        assert_eq!(change.group_of("C").unwrap(), ["B", "C"]);
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.kind, EventKind::CoherencyChange);
+        assert_eq!(event.source, "A,B");
+        assert_eq!(event.values["groups"], 4.0);
+        assert!(event.message.contains("[A, B] [C] [D] to [A] [B] [C] [D]"));
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.source, "B,C");
+        assert_eq!(event.values["moved"], 2.0);
+    }
+
+    #[test]
+    fn test_angles_relative_to_reference() {
+        let mut referencer = AngleReferencer::new(vec![AngleReference::phasor("A", 0)]);
+        for n in 0..600 {
+            let referenced = referencer.push(n * 33_333, &drifting(n));
+            let angles = referenced.first_phasors();
+            assert!(angles["A"].abs() < 1e-9);
+            assert!(
+                (angles["B"].to_degrees() + 20.0).abs() < 1e-6,
+                "{:?}",
+                angles
+            );
+            assert!(
+                (angles["C"].to_degrees() + 45.0).abs() < 1e-6,
+                "{:?}",
+                angles
+            );
+        }
+        assert_eq!(referencer.switches(), 0);
+    }
+
+    #[test]
+    fn test_reference_falls_back_and_returns() {
+        let bus = EventBus::new(8);
+        let mut events = bus.subscribe();
+        let mut referencer = AngleReferencer::new(vec![
+            AngleReference::phasor("A", 0),
+            AngleReference::phasor("B", 0),
+        ])
+        .with_event_bus(bus);
+
+        let references: Vec<AngleReference> = (0..40)
+            .map(|n| {
+                let mut measurements = drifting(n);
+                match n {
+                    // A drops out, then reports invalid data
+                    10..20 => {
+                        measurements.remove("A");
+                    }
+                    20..30 => measurements.get_mut("A").unwrap().stat = 0x8000,
+                    _ => {}
+                }
+                let referenced = referencer.push(n * 33_333, &measurements);
+                if (10..30).contains(&n) {
+                    let angles = referenced.first_phasors();
+                    assert!(angles["B"].abs() < 1e-9);
+                    assert!((angles["C"].to_degrees() + 25.0).abs() < 1e-6);
+                }
+                referenced.reference
+            })
+            .collect();
+        assert_eq!(references[9], AngleReference::phasor("A", 0));
+        assert_eq!(references[10], AngleReference::phasor("B", 0));
+        assert_eq!(references[30], AngleReference::phasor("A", 0));
+        assert_eq!(referencer.switches(), 2);
+
+        let fallback = events.try_recv().unwrap();
+        assert_eq!(fallback.kind, EventKind::ReferenceChanged);
+        assert_eq!(
+            (fallback.severity, fallback.timestamp_us),
+            (Severity::Warning, 10 * 33_333)
+        );
+        let back = events.try_recv().unwrap();
+        assert_eq!((back.severity, back.source.as_str()), (Severity::Info, "A"));
+
+        // Without any usable reference the angles stay as reported
+        let referenced = referencer.push(40 * 33_333, &HashMap::new());
+        assert_eq!(referenced.reference, AngleReference::Nominal);
+    }
+
+    #[test]
+    fn test_voltage_stability_indices() {
+        let nominal = 100_000.0;
+        let config = VoltageStabilityConfig::new(&["LOAD", "REF"], nominal)
+            .with_reference("REF")
+            .with_window(10.0, 5.0);
+        let mut monitor = VoltageStabilityMonitor::new(config);
+
+        // Load rising from 100 to 220 MW over 60 s, the voltage sagging
+        // 0.0005 pu and the angle falling 0.1 degree per MW
+        let mut rows = Vec::new();
+        for n in 0..1800 {
+            let p = 100.0 + n as f64 / 15.0;
+            let v = (1.0 - 0.0005 * (p - 100.0)) * nominal;
+            let voltage = Phasor::from_polar(v, (-0.1 * p).to_radians());
+            // Unity power factor, Re(V I*) = p MW
+            let scale = p * 1e6 / (v * v);
+            let current = Phasor::new(voltage.re * scale, voltage.im * scale);
+            let samples = HashMap::from([
+                (
+                    "LOAD".to_string(),
+                    BusSample {
+                        voltage,
+                        current: Some(current),
+                    },
+                ),
+                (
+                    "REF".to_string(),
+                    BusSample {
+                        voltage: Phasor::from_polar(nominal, 0.0),
+                        current: None,
+                    },
+                ),
+            ]);
+            rows.extend(monitor.push(n * 33_333, &samples));
+        }
+        // Every 5 s from the first sample, both buses
+        assert_eq!(rows.len(), 24);
+        assert_eq!(rows[0].dv_dp, None);
+        let load: Vec<_> = rows.iter().filter(|r| r.bus == "LOAD").collect();
+        assert_eq!(load[1].timestamp_us, 151 * 33_333);
+        for row in &load[1..] {
+            assert!((row.dv_dp.unwrap() + 0.0005).abs() < 1e-9, "{:?}", row);
+            assert!((row.dv_dangle.unwrap() - 0.005).abs() < 1e-9, "{:?}", row);
+        }
+        assert!(load[11].voltage_pu < load[1].voltage_pu);
+        // The reference has no spread in angle and no current
+        let reference = rows.iter().find(|r| r.bus == "REF").unwrap();
+        assert_eq!(reference.voltage_pu, 1.0);
+        assert_eq!((reference.dv_dangle, reference.dv_dp), (None, None));
+
+        let batch = stability_batch(&rows).unwrap();
+        assert_eq!(batch.num_rows(), 24);
+        assert_eq!(batch.column_by_name("dv_dp").unwrap().null_count(), 13);
+    }
+
+    // Frequency with a little deterministic noise at 30 frames/s.
+    fn noisy_frequency(n: i64) -> f64 {
+        60.0 + 0.002 * (n as f64 * 1.7).sin() + 0.001 * (n as f64 * 0.31).cos()
+    }
+
+    #[test]
+    fn test_anomaly_detection() {
+        let bus = EventBus::default();
+        let mut events = bus.subscribe();
+        let mut detector =
+            AnomalyDetector::new(AnomalyConfig::new(30.0), "Station A").with_event_bus(bus);
+        let mut anomalies = Vec::new();
+        for n in 0..600i64 {
+            let value = match n {
+                100 => 60.5,                           // Spike
+                200..=299 => noisy_frequency(n) - 0.2, // Jump down and back up
+                400..=449 => noisy_frequency(399),     // Stuck
+                500..=503 => continue,                 // Dropout
+                550 => f64::NAN,
+                _ => noisy_frequency(n),
+            };
+            anomalies.extend(detector.push("FREQ", n * 33_333, value));
+        }
+
+        let found: Vec<(AnomalyKind, i64)> =
+            anomalies.iter().map(|a| (a.kind, a.timestamp_us)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (AnomalyKind::Spike, 100 * 33_333),
+                (AnomalyKind::Jump, 200 * 33_333),
+                (AnomalyKind::Jump, 300 * 33_333),
+                (AnomalyKind::Stuck, 399 * 33_333),
+                (AnomalyKind::Dropout, 504 * 33_333),
+                (AnomalyKind::Dropout, 550 * 33_333),
+            ]
+        );
+        assert!(anomalies[0].score > 6.0);
+        assert!(anomalies[1].score < -6.0);
+        assert_eq!(anomalies[3].score, 30.0);
+        assert!((anomalies[4].score - 4.0).abs() < 0.01);
+
+        let quality = detector.report().channels["FREQ"];
+        assert_eq!(quality.samples, 596);
+        assert_eq!(
+            (
+                quality.stuck,
+                quality.spikes,
+                quality.dropouts,
+                quality.jumps
+            ),
+            (1, 1, 2, 2)
+        );
+        assert!(detector.report().render().contains("spikes=1"));
+        assert_eq!(detector.report().to_json()["channels"][0]["anomalies"], 6);
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.kind, EventKind::DataQuality);
+        assert_eq!(event.severity, Severity::Warning);
+        assert_eq!(event.source, "Station A");
+        assert!(event.message.starts_with("spike on FREQ"));
+    }
+
+    #[test]
+    fn test_annotated_anomalies_are_suppressed() {
+        let bus = EventBus::default();
+        let mut events = bus.subscribe();
+        let mut store = AnnotationStore::in_memory();
+        // Maintenance on the station covers the spike, the note suppresses nothing
+        store
+            .add(
+                Annotation::new(AnnotationKind::Maintenance, 90 * 33_333, 110 * 33_333)
+                    .with_source("Station A"),
+            )
+            .unwrap();
+        store
+            .add(Annotation::new(AnnotationKind::Note, 0, 600 * 33_333))
+            .unwrap();
+        let mut detector = AnomalyDetector::new(AnomalyConfig::new(30.0), "7734")
+            .with_event_bus(bus)
+            .with_annotations(Arc::new(Mutex::new(store)));
+        let mut anomalies = Vec::new();
+        for n in 0..400i64 {
+            let value = match n {
+                100 | 300 => 60.5,
+                _ => noisy_frequency(n),
+            };
+            anomalies.extend(detector.push("Station A_7734_FREQ", n * 33_333, value));
+        }
+
+        let found: Vec<(AnomalyKind, i64)> =
+            anomalies.iter().map(|a| (a.kind, a.timestamp_us)).collect();
+        assert_eq!(found, vec![(AnomalyKind::Spike, 300 * 33_333)]);
+        let quality = detector.report().channels["Station A_7734_FREQ"];
+        assert_eq!((quality.spikes, quality.suppressed), (1, 1));
+        assert_eq!(events.try_recv().unwrap().timestamp_us, 300 * 33_333);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_no_anomalies_on_clean_signal() {
+        let mut detector = AnomalyDetector::new(AnomalyConfig::new(30.0), "Station A");
+        for n in 0..3000i64 {
+            // Slow ramp plus noise
+            let value = noisy_frequency(n) + n as f64 * 1e-5;
+            assert_eq!(detector.push("FREQ", n * 33_333, value), vec![], "{}", n);
+        }
+        assert_eq!(detector.report().channels["FREQ"].anomalies(), 0);
+    }
+
+    // 120 kV with +-0.05% of noise.
+    fn noisy_voltage(n: i64) -> f64 {
+        120_000.0 * (1.0 + 0.0005 * ((n * 7919) % 13 - 6) as f64 / 6.0)
+    }
+
+    #[test]
+    fn test_magnitude_steps() {
+        let bus = EventBus::default();
+        let mut events = bus.subscribe();
+        let mut detector =
+            MagnitudeStepDetector::new(MagnitudeStepConfig::new(30.0), "7734").with_event_bus(bus);
+        let mut steps = Vec::new();
+        for n in 0..800i64 {
+            let value = match n {
+                100..=299 => noisy_voltage(n) * 1.015, // Capacitor bank in
+                300 => noisy_voltage(n) * 1.007,       // Halfway back
+                500..=559 => noisy_voltage(n) * (1.0 + 0.02 * (n - 500) as f64 / 60.0), // Ramp
+                560.. => noisy_voltage(n) * 1.02,
+                _ => noisy_voltage(n),
+            };
+            steps.extend(detector.push("VA", n * 33_333, value));
+        }
+        // The ramp is no step
+        let found: Vec<i64> = steps
+            .iter()
+            .map(|step| step.timestamp_us / 33_333)
+            .collect();
+        assert_eq!(found, vec![100, 301]);
+        assert!((steps[0].relative() - 0.015).abs() < 5e-4, "{:?}", steps[0]);
+        assert!((steps[0].before - 120_000.0).abs() < 60.0);
+        assert!((steps[1].change() + 1800.0).abs() < 60.0);
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.kind, EventKind::MagnitudeStep);
+        assert_eq!(event.severity, Severity::Info);
+        assert_eq!(event.source, "7734");
+        assert!(event.message.starts_with("Magnitude step on VA of +1.5"));
+        assert!((event.values["step_percent"] - 1.5).abs() < 0.05);
+        assert_eq!(events.try_recv().unwrap().timestamp_us, 301 * 33_333);
+        assert!(events.try_recv().is_err());
+
+        // Below min_step
+        let mut detector =
+            MagnitudeStepDetector::new(MagnitudeStepConfig::new(30.0).with_min_step(0.02), "7734");
+        assert!((0..300i64).all(|n| {
+            let value = noisy_voltage(n) * if n >= 100 { 1.015 } else { 1.0 };
+            detector.push("VA", n * 33_333, value).is_none()
+        }));
+    }
+
+    #[test]
+    fn test_magnitude_steps_of_batch() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let scenario = Scenario {
+            start_soc: Some(1_700_000_000),
+            events: vec![ScenarioEvent::VoltageSag {
+                at: 1.0,
+                duration: 1.0,
+                depth: 0.02,
+            }],
+            ..Default::default()
+        };
+        let mut sim = Simulator::new(config.clone(), scenario);
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited());
+        accumulator.add_stream(&config);
+        for frame in frames(&mut sim, 90) {
+            accumulator.push_frame(&frame).unwrap();
+        }
+        let (_, batch) = accumulator.flush_all().unwrap().remove(0);
+
+        let mut detector = MagnitudeStepDetector::new(MagnitudeStepConfig::new(30.0), "7734");
+        let steps = detector.push_batch(&batch);
+        // Down and back up on every phasor
+        assert_eq!(steps.len(), 8, "{:?}", steps);
+        let start_us = 1_700_000_000_000_000i64;
+        for step in &steps {
+            let seconds = (step.timestamp_us - start_us) as f64 / 1e6;
+            assert!(
+                (seconds - 1.0).abs() < 0.05 || (seconds - 2.0).abs() < 0.05,
+                "{:?}",
+                step
+            );
+            assert!((step.relative().abs() - 0.02).abs() < 2e-3, "{:?}", step);
+        }
+    }
+
+    fn ufls_settings() -> UflsConfig {
+        UflsConfig::from_json(
+            r#"{"stages": [
+                {"name": "stage 1", "pickup_hz": 59.3, "delay_secs": 0.1, "shed_percent": 5},
+                {"name": "stage 2", "pickup_hz": 58.9, "delay_secs": 0.25, "shed_percent": 10},
+                {"name": "df/dt", "pickup_hz": 59.8, "rocof_hz_per_s": -0.4, "shed_percent": 7.5},
+                {"name": "fast df/dt", "rocof_hz_per_s": -1.0, "shed_percent": 20},
+                {"name": "stage 3", "pickup_hz": 58.0, "delay_secs": 0.1, "shed_percent": 10}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_ufls_stages() {
+        let bus = EventBus::default();
+        let mut events = bus.subscribe();
+        let mut simulation = UflsSimulation::new(ufls_settings(), "7734")
+            .unwrap()
+            .with_event_bus(bus);
+        // 60 Hz, falling at 0.5 Hz/s from 1 s to 4 s, then holding at 58.5 Hz
+        let mut trips = Vec::new();
+        for n in 0..180i64 {
+            let t = n as f64 / 30.0;
+            let frequency = 60.0 - 0.5 * (t - 1.0).clamp(0.0, 3.0);
+            trips.extend(simulation.push(n * 33_333, frequency));
+        }
+        let tripped: Vec<(&str, f64)> = trips
+            .iter()
+            .map(|trip| (trip.stage.as_str(), trip.trip_us as f64 / 1e6))
+            .collect();
+        assert_eq!(tripped.len(), 3, "{:?}", tripped);
+        // Below 59.8 Hz at 1.4 s, already falling fast enough
+        assert_eq!(tripped[0].0, "df/dt");
+        assert!((tripped[0].1 - 1.4).abs() < 0.05, "{:?}", tripped);
+        // Below 59.3 Hz at 2.4 s, after 0.1 s
+        assert_eq!(tripped[1].0, "stage 1");
+        assert!((tripped[1].1 - 2.5).abs() < 0.05, "{:?}", tripped);
+        assert!((trips[1].frequency_hz - 59.25).abs() < 0.02);
+        assert_eq!(tripped[2].0, "stage 2");
+        assert!((tripped[2].1 - 3.45).abs() < 0.05, "{:?}", tripped);
+        assert!((trips[0].rocof.unwrap() + 0.5).abs() < 1e-4);
+
+        let report = simulation.report();
+        assert_eq!(report.samples, 180);
+        assert_eq!(report.shed_percent(), 22.5);
+        assert!((report.nadir.unwrap().1 - 58.5).abs() < 1e-9);
+        assert!((report.min_rocof.unwrap() + 0.5).abs() < 1e-4);
+        assert!(report.trip("stage 3").is_none());
+        let text = report.render();
+        assert!(text.contains("fast df/dt       not tripped"), "{}", text);
+        assert!(text.contains("3 of 5 stages tripped, 22.5% of load shed"));
+        assert_eq!(report.to_json()["stages"][1]["tripped"], true);
+        assert_eq!(report.to_json()["stages"][4]["tripped"], false);
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.kind, EventKind::LoadShed);
+        assert_eq!(event.source, "7734");
+        assert!(event.message.starts_with("UFLS stage df/dt would trip"));
+    }
+
+    #[test]
+    fn test_ufls_delay_resets_on_dropout() {
+        let settings = UflsConfig::from_json(
+            r#"{"stages": [{"name": "stage 1", "pickup_hz": 59.5, "delay_secs": 0.5}]}"#,
+        )
+        .unwrap();
+        let mut simulation = UflsSimulation::new(settings, "7734").unwrap();
+        // Below the pickup for 0.35 s twice, then for 0.75 s, at 20 fps
+        for n in 0..120i64 {
+            let low = matches!(n, 10..=17 | 40..=47 | 70..=85);
+            let frequency = if low { 59.4 } else { 59.9 };
+            simulation.push(n * 50_000, frequency);
+        }
+        let trip = simulation.report().trip("stage 1").unwrap();
+        assert_eq!(trip.pickup_us, 70 * 50_000);
+        assert_eq!(trip.trip_us, 80 * 50_000);
+
+        for json in [
+            r#"{"stages": []}"#,
+            r#"{"stages": [{"name": "stage 1", "delay_secs": 0.5}]}"#,
+            r#"{"stages": [{"name": "stage 1", "pickup_hz": 59.5, "delay_secs": -1}]}"#,
+        ] {
+            let settings = UflsConfig::from_json(json).unwrap();
+            assert!(UflsSimulation::new(settings, "7734").is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_ufls_of_captured_frames() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let scenario = Scenario {
+            start_soc: Some(1_700_000_000),
+            events: vec![ScenarioEvent::FrequencyRamp {
+                at: 1.0,
+                duration: 3.0,
+                rate: -0.5,
+            }],
+            ..Default::default()
+        };
+        let mut sim = Simulator::new(config.clone(), scenario);
+        let frames = frames(&mut sim, 150);
+        assert!(evaluate_frames(ufls_settings(), None, &frames, 0).is_err());
+
+        let report = evaluate_frames(ufls_settings(), Some(config.clone()), &frames, 0).unwrap();
+        assert_eq!(report.samples, 150);
+        let stages: Vec<&str> = report.trips.iter().map(|t| t.stage.as_str()).collect();
+        assert_eq!(stages, vec!["df/dt", "stage 1", "stage 2"]);
+        // The configuration frame among the frames
+        let mut with_config = vec![config.to_hex()];
+        with_config.extend(frames);
+        assert_eq!(
+            evaluate_frames(ufls_settings(), None, &with_config, 0).unwrap(),
+            report
+        );
+    }
+
+    // System frequency wandering around 60 Hz, at t seconds.
+    fn wandering_frequency(t: f64) -> f64 {
+        60.0 + 0.02 * (2.0 * PI * 0.3 * t).sin()
+            + 0.01 * (2.0 * PI * 1.1 * t + 1.0).sin()
+            + 0.005 * (2.0 * PI * 2.3 * t + 2.0).sin()
+    }
+
+    // Samples at rate frames/s over 60 s of a clock late by skew_us.
+    fn frequency_samples(rate: i64, skew_us: i64) -> Vec<(i64, f64)> {
+        (0..60 * rate)
+            .map(|n| {
+                let timestamp_us = n * 1_000_000 / rate;
+                let t = (timestamp_us - skew_us) as f64 / 1e6;
+                (timestamp_us, wandering_frequency(t))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_time_skew() {
+        let config = TimeSkewConfig::default();
+        let reference = frequency_samples(30, 0);
+        let estimate = estimate_skew(&config, &reference, &reference).unwrap();
+        assert!(estimate.skew_us.abs() < 100.0, "{:?}", estimate);
+        assert!(estimate.correlation > 0.99);
+        assert_eq!(estimate.interval_us, 33_333.0);
+
+        // A third of a reporting interval, and more than one
+        for skew_us in [12_000, -50_000] {
+            let late = frequency_samples(30, skew_us);
+            let estimate = estimate_skew(&config, &reference, &late).unwrap();
+            assert!(
+                (estimate.skew_us - skew_us as f64).abs() < 1_000.0,
+                "{} {:?}",
+                skew_us,
+                estimate
+            );
+        }
+
+        // Streams of different rates, on the grid of the faster one
+        let fast = frequency_samples(60, 8_000);
+        let estimate = estimate_skew(&config, &reference, &fast).unwrap();
+        assert_eq!(estimate.interval_us, 16_667.0);
+        assert!(
+            (estimate.skew_us - 8_000.0).abs() < 1_000.0,
+            "{:?}",
+            estimate
+        );
+
+        // Nothing to correlate
+        let flat: Vec<(i64, f64)> = reference.iter().map(|(t, _)| (*t, 60.0)).collect();
+        assert_eq!(estimate_skew(&config, &reference, &flat), None);
+        assert_eq!(estimate_skew(&config, &reference, &reference[..1]), None);
+    }
+
+    // Angle rotating at 0.2 Hz off nominal, wrapped, with +-noise alternating.
+    fn rotating_angle(n: i64, noise: f64) -> f64 {
+        let angle = 2.0 * PI * 0.2 * n as f64 / 30.0 + 3.0;
+        let angle = (angle + PI).rem_euclid(2.0 * PI) - PI;
+        if n % 2 == 0 {
+            angle + noise
+        } else {
+            angle - noise
+        }
+    }
+
+    #[test]
+    fn test_angle_jitter() {
+        let mut clean = AngleJitter::new(30);
+        let mut noisy = AngleJitter::new(30);
+        let (mut clean_jitter, mut noisy_jitter) = (None, None);
+        for n in 0..300i64 {
+            clean_jitter = clean.push(n * 33_333, rotating_angle(n, 0.0));
+            noisy_jitter = noisy.push(n * 33_333, rotating_angle(n, 0.01));
+            if n < 14 {
+                assert_eq!(clean_jitter, None);
+            }
+        }
+        // The rotation, wrapping included, is not jitter
+        assert!(clean_jitter.unwrap() < 1e-6, "{:?}", clean_jitter);
+        assert!(
+            (noisy_jitter.unwrap() - 0.01).abs() < 1e-3,
+            "{:?}",
+            noisy_jitter
+        );
+
+        // A gap starts the window over
+        assert_eq!(noisy.push(400 * 33_333, rotating_angle(400, 0.01)), None);
+        assert_eq!(noisy.jitter(), None);
+        assert_eq!(noisy.push(401 * 33_333, f64::NAN), None);
+    }
+
+    #[test]
+    fn test_angle_jitter_in_quality_report() {
+        let mut detector =
+            AnomalyDetector::new(AnomalyConfig::new(30.0).with_jitter_window(20), "Station A");
+        for n in 0..100i64 {
+            detector.push_angle("Station A_7734_VA", n * 33_333, rotating_angle(n, 0.02));
+        }
+        let jitter = detector.report().angle_jitter["Station A_7734_VA"];
+        assert_eq!(jitter.samples, 100);
+        assert!((jitter.latest.unwrap() - 0.02).abs() < 2e-3);
+        assert!(jitter.max.unwrap() >= jitter.mean().unwrap());
+        assert!(detector
+            .report()
+            .render()
+            .contains("Station A_7734_VA        angle jitter latest=2."));
+        let json = detector.report().to_json();
+        assert_eq!(json["angle_jitter"][0]["name"], "Station A_7734_VA");
+        assert_eq!(json["angle_jitter"][0]["samples"], 100);
+        // Angles are not counted as anomaly samples
+        assert!(detector.report().channels.is_empty());
+    }
+
+    #[test]
+    fn test_jitter_monitor_metrics() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let noisy = Scenario {
+            start_soc: Some(1_700_000_000),
+            noise: Some(NoiseModel {
+                seed: 3,
+                angle_std_deg: 0.5,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut sim = Simulator::new(config.clone(), noisy);
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited());
+        accumulator.add_stream(&config);
+        for frame in frames(&mut sim, 60) {
+            accumulator.push_frame(&frame).unwrap();
+        }
+        let (_, batch) = accumulator.flush_all().unwrap().remove(0);
+        let angles = phasor_angles(&batch);
+        assert_eq!(angles.len(), 4);
+        assert!(angles
+            .iter()
+            .all(|(_, station, values)| { station.trim() == "Station A" && values.len() == 60 }));
+
+        let metrics = Arc::new(Metrics::new());
+        let mut monitor = JitterMonitor::new(30, "7734").with_metrics(metrics.clone());
+        monitor.push_batch(&batch);
+        let (channel, _, _) = &angles[0];
+        let stats = monitor.stats(channel).unwrap();
+        assert_eq!(stats.samples, 60);
+        let jitter = metrics
+            .gauge(JITTER_GAUGE, &[("source", "7734"), ("channel", channel)])
+            .unwrap();
+        assert_eq!(Some(jitter), stats.latest);
+        // 0.5 degrees of noise
+        let expected = 0.5f64.to_radians();
+        assert!(
+            jitter > 0.5 * expected && jitter < 1.5 * expected,
+            "{}",
+            jitter
+        );
+        assert_eq!(monitor.report().len(), 4);
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE pmu_angle_jitter_rad gauge"));
+        assert!(rendered.contains(&format!(
+            "pmu_angle_jitter_rad{{source=\"7734\",channel=\"{}\"}}",
+            channel
+        )));
+    }
+
+    #[test]
+    fn test_trigger_with_time_qualifier() {
+        let rocof = Comparison::channel("DFREQ")
+            .absolute()
+            .above(0.5)
+            .for_ms(100);
+        let unbalance = Comparison::ratio("V2", "V1").above(0.02);
+        let definition = TriggerDefinition::new(
+            "islanding",
+            Condition::all(vec![rocof.into(), unbalance.into()]),
+        )
+        .with_severity(Severity::Alarm);
+        let bus = EventBus::new(16);
+        let mut events = bus.subscribe();
+        let mut engine = TriggerEngine::new(vec![definition])
+            .unwrap()
+            .with_event_bus(bus);
+        assert_eq!(engine.channels(), ["DFREQ", "V2", "V1"]);
+
+        // ROCOF -0.8 Hz/s from row 10, unbalance 3 % except rows 20 to 24
+        let rows: Vec<(i64, Vec<f64>)> = (0..40i64)
+            .map(|n| {
+                let rocof = if n >= 10 { -0.8 } else { 0.1 };
+                let v2 = if (20..25).contains(&n) {
+                    1000.0
+                } else {
+                    3000.0
+                };
+                (n * 33_333, vec![rocof, 100_000.0, v2])
+            })
+            .collect();
+        let batch = channel_batch(&["DFREQ", "V1", "V2"], &rows);
+        let fired = engine.push_batch(&batch).unwrap();
+
+        // Held for 100 ms at row 14, again as soon as the unbalance returns
+        assert_eq!(fired.len(), 2);
+        assert_eq!(fired[0].timestamp_us, 14 * 33_333);
+        assert_eq!(fired[1].timestamp_us, 25 * 33_333);
+        assert_eq!(fired[0].kind, EventKind::Trigger);
+        assert_eq!(fired[0].severity, Severity::Alarm);
+        assert_eq!(fired[0].source, "islanding");
+        assert_eq!(
+            fired[0].message,
+            "islanding: |DFREQ| > 0.5 for 100 ms and V2/V1 > 0.02"
+        );
+        assert_eq!(fired[0].values["DFREQ"], -0.8);
+        assert!((fired[0].values["V2/V1"] - 0.03).abs() < 1e-12);
+        assert!(engine.is_active("islanding"));
+        assert_eq!(engine.fired()["islanding"], 2);
+        assert_eq!(events.try_recv().unwrap(), fired[0]);
+    }
+
+    #[test]
+    fn test_trigger_definitions_from_json() {
+        let definitions: Vec<TriggerDefinition> = serde_json::from_str(
+            r#"[{"name": "low voltage", "condition": {"any": [
+                    {"channel": "VA", "below": 0.9},
+                    {"all": [{"channel": "VB", "below": 0.95}, {"channel": "VC", "below": 0.95}],
+                     "for_ms": 200}
+                ]}}]"#,
+        )
+        .unwrap();
+        assert_eq!(definitions[0].severity, Severity::Warning);
+        assert_eq!(
+            definitions[0].condition.to_string(),
+            "VA < 0.9 or (VB < 0.95 and VC < 0.95, for 200 ms)"
+        );
+
+        let mut engine = TriggerEngine::new(definitions).unwrap();
+        let rows: Vec<(i64, Vec<f64>)> = (0..10i64)
+            .map(|n| (n * 100_000, vec![1.0, 0.93, 0.94]))
+            .collect();
+        let fired = engine
+            .push_batch(&channel_batch(&["VA", "VB", "VC"], &rows))
+            .unwrap();
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].timestamp_us, 200_000);
+
+        let invalid: Vec<TriggerDefinition> =
+            serde_json::from_str(r#"[{"name": "x", "condition": {"channel": "VA"}}]"#).unwrap();
+        assert!(TriggerEngine::new(invalid).is_err());
+        let empty = vec![TriggerDefinition::new("y", Condition::all(vec![]))];
+        assert!(TriggerEngine::new(empty).is_err());
+    }
+
+    #[test]
+    fn test_trigger_values_across_streams_go_stale() {
+        let definition = TriggerDefinition::new(
+            "angle spread",
+            Comparison::ratio("North_1_P", "South_2_P").above(1.5),
+        );
+        let mut engine = TriggerEngine::new(vec![definition]).unwrap();
+        let north = |t: i64| channel_batch(&["North_1_P"], &[(t, vec![200.0])]);
+        let south = |t: i64| channel_batch(&["South_2_P"], &[(t, vec![100.0])]);
+
+        assert!(engine.push_batch(&north(0)).unwrap().is_empty());
+        assert_eq!(engine.push_batch(&south(20_000)).unwrap().len(), 1);
+        // The north value is too old by now
+        assert!(engine.push_batch(&south(2_000_000)).unwrap().is_empty());
+        assert!(!engine.is_active("angle spread"));
+        assert_eq!(engine.push_batch(&north(2_010_000)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_trigger_on_data_frames() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let frame = read_hex_file("data_message.bin").unwrap();
+        let definition = TriggerDefinition::new(
+            "overfrequency",
+            Condition::all(vec![
+                Comparison::channel("Station A_7734_FREQ")
+                    .above(62.0)
+                    .into(),
+                Comparison::channel("Station A_7734_VA")
+                    .above(130_000.0)
+                    .into(),
+            ]),
+        );
+        let mut engine = TriggerEngine::new(vec![definition]).unwrap();
+        assert!(engine.push_frame(&frame).is_err()); // Stream not registered
+        engine.add_stream(&config);
+        let fired = engine.push_frame(&frame).unwrap();
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].values["Station A_7734_FREQ"], 62.5);
+        assert!((fired[0].values["Station A_7734_VA"] - 133_987.4).abs() < 1.0);
+        assert!(engine.push_frame(&frame).unwrap().is_empty()); // Still active
+    }
+}
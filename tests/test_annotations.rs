@@ -0,0 +1,101 @@
+#![allow(unused)]
+
+#[cfg(test)]
+mod tests {
+    use pmu::annotations::{Annotation, AnnotationKind, AnnotationStore};
+
+    const HOUR_US: i64 = 3_600_000_000;
+
+    #[test]
+    fn test_store_persists_annotations_and_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("annotations.json");
+        let mut store = AnnotationStore::open(&path).unwrap();
+        let first = store
+            .add(
+                Annotation::new(AnnotationKind::Maintenance, 0, HOUR_US)
+                    .with_stream(7734)
+                    .with_note("Relay replacement"),
+            )
+            .unwrap();
+        let second = store
+            .add(
+                Annotation::new(AnnotationKind::KnownBad, HOUR_US, 2 * HOUR_US)
+                    .with_source("SUB_A"),
+            )
+            .unwrap();
+        assert_eq!((first, second), (1, 2));
+        store.set_metadata("7734", "owner", Some("north")).unwrap();
+        assert!(store
+            .add(Annotation::new(AnnotationKind::Note, HOUR_US, HOUR_US))
+            .is_err());
+
+        let mut store = AnnotationStore::open(&path).unwrap();
+        assert_eq!(store.annotations().len(), 2);
+        assert_eq!(store.annotations()[0].note, "Relay replacement");
+        assert_eq!(store.metadata("7734").unwrap()["owner"], "north");
+
+        assert!(store.remove(first).unwrap());
+        assert!(!store.remove(first).unwrap());
+        store.set_metadata("7734", "owner", None).unwrap();
+        // Ids are not reused
+        let third = store
+            .add(Annotation::new(AnnotationKind::Note, 0, 1))
+            .unwrap();
+        assert_eq!(third, 3);
+
+        let store = AnnotationStore::open(&path).unwrap();
+        assert_eq!(store.annotations().len(), 2);
+        assert_eq!(store.metadata("7734"), None);
+    }
+
+    #[test]
+    fn test_annotations_match_streams_and_sources() {
+        let mut store = AnnotationStore::in_memory();
+        store
+            .add(Annotation::new(AnnotationKind::Maintenance, 0, HOUR_US).with_stream(7734))
+            .unwrap();
+        store
+            .add(
+                Annotation::new(AnnotationKind::KnownBad, HOUR_US, 2 * HOUR_US)
+                    .with_source("SUB_A"),
+            )
+            .unwrap();
+        store
+            .add(Annotation::new(AnnotationKind::Note, 0, 3 * HOUR_US))
+            .unwrap();
+
+        // Stream annotations only apply where the stream is known
+        assert!(store.suppressing(Some(7734), "SUB_B_VA", 10).is_some());
+        assert!(store.suppressing(Some(1), "SUB_B_VA", 10).is_none());
+        assert!(store.suppressing(None, "SUB_B_VA", 10).is_none());
+
+        // A station covers its channels but not other stations sharing a prefix
+        assert!(store.suppressing(None, "SUB_A", HOUR_US).is_some());
+        assert!(store.suppressing(None, "SUB_A_7734_VA", HOUR_US).is_some());
+        assert!(store.suppressing(None, "SUB_AB_VA", HOUR_US).is_none());
+        assert!(store.suppressing(None, "SUB_A", 2 * HOUR_US).is_none());
+
+        // Notes suppress nothing but are found
+        assert!(store.suppressing(None, "SUB_C", 2 * HOUR_US + 1).is_none());
+        assert_eq!(store.find(None, "SUB_C", 0, 3 * HOUR_US).len(), 1);
+        assert_eq!(store.find(Some(7734), "SUB_A_VA", 0, 3 * HOUR_US).len(), 3);
+    }
+
+    #[test]
+    fn test_suppressed_time_of_a_stream() {
+        let mut store = AnnotationStore::in_memory();
+        for (start, end) in [(10, 40), (30, 50), (80, 90)] {
+            store
+                .add(Annotation::new(AnnotationKind::Maintenance, start, end).with_stream(1))
+                .unwrap();
+        }
+        // Source annotations do not take the whole stream out
+        store
+            .add(Annotation::new(AnnotationKind::KnownBad, 0, 100).with_source("SUB_A"))
+            .unwrap();
+        assert_eq!(store.suppressed_us(1, 0, 100), 50);
+        assert_eq!(store.suppressed_us(1, 35, 85), 20);
+        assert_eq!(store.suppressed_us(2, 0, 100), 0);
+    }
+}
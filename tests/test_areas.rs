@@ -0,0 +1,112 @@
+#![cfg(feature = "analytics")]
+#![allow(unused)]
+mod common;
+
+use common::read_hex_file;
+use pmu::analytics::angle_reference::{AngleReference, ReferencedAngles};
+use pmu::areas::{AreaConfig, AreaMap};
+use pmu::arrow_utils::build_record_batch;
+use pmu::frame_parser::parse_config_frame_1and2;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+const AREAS: &str = r#"{"areas": [
+    {"name": "WEST"},
+    {"name": "NORTH", "parent": "WEST", "stations": ["SUB_A", "SUB_B"]},
+    {"name": "SOUTH", "parent": "WEST", "stations": ["SUB_C"]},
+    {"name": "EAST", "stations": ["SUB_D", "Station A"]}
+]}"#;
+
+fn areas() -> AreaMap {
+    AreaMap::new(AreaConfig::from_json(AREAS).unwrap()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_area_hierarchy() {
+        let areas = areas();
+        assert_eq!(areas.area_of("SUB_A"), Some("NORTH"));
+        assert_eq!(areas.area_of("Station A       "), Some("EAST"));
+        assert_eq!(areas.area_of("SUB_X"), None);
+        assert_eq!(areas.path_of("SUB_C"), ["SOUTH", "WEST"]);
+        assert_eq!(areas.children("WEST"), ["NORTH", "SOUTH"]);
+        assert_eq!(areas.stations("WEST"), ["SUB_A", "SUB_B", "SUB_C"]);
+        assert_eq!(areas.stations("NORTH"), ["SUB_A", "SUB_B"]);
+        assert!(areas.contains("WEST", "SUB_B"));
+        assert!(!areas.contains("EAST", "SUB_B"));
+
+        let groups = areas.group([("SUB_A", 1), ("SUB_C", 2), ("SUB_D", 3), ("SUB_X", 4)]);
+        assert_eq!(groups["WEST"], [1, 2]);
+        assert_eq!(groups["NORTH"], [1]);
+        assert_eq!(groups["EAST"], [3]);
+        assert_eq!(groups.len(), 4);
+
+        let json = areas.to_json();
+        assert_eq!(json[0]["name"], "WEST");
+        assert_eq!(json[0]["children"][1], "SOUTH");
+        assert_eq!(json[0]["stations"].as_array().unwrap().len(), 3);
+        assert_eq!(areas.area_json("SOUTH").unwrap()["parent"], "WEST");
+        assert!(areas.area_json("NOWHERE").is_none());
+    }
+
+    #[test]
+    fn test_invalid_areas() {
+        let map = |json: &str| AreaMap::new(AreaConfig::from_json(json).unwrap());
+        assert!(map(r#"{"areas": [{"name": "A"}, {"name": "A"}]}"#).is_err());
+        assert!(map(r#"{"areas": [{"name": "A", "parent": "B"}]}"#).is_err());
+        assert!(map(
+            r#"{"areas": [{"name": "A", "parent": "B"}, {"name": "B", "parent": "A"}, {"name": "C"}]}"#
+        )
+        .is_err());
+        assert!(map(
+            r#"{"areas": [{"name": "A", "stations": ["S"]}, {"name": "B", "stations": [" S "]}]}"#
+        )
+        .is_err());
+        assert!(map(r#"{"areas": []}"#).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_area_angles() {
+        let degrees = |d: f64| d * PI / 180.0;
+        let referenced = ReferencedAngles {
+            timestamp_us: 0,
+            reference: AngleReference::Nominal,
+            angles: HashMap::from([
+                ("SUB_A".to_string(), vec![degrees(170.0)]),
+                ("SUB_B".to_string(), vec![degrees(-170.0)]),
+                ("SUB_C".to_string(), vec![degrees(150.0)]),
+                ("SUB_D".to_string(), vec![degrees(10.0), degrees(99.0)]),
+            ]),
+        };
+        let angles = referenced.area_angles(&areas());
+        // Either side of 180 degrees averages to 180, not 0
+        assert!((angles["NORTH"].abs() - PI).abs() < 1e-9);
+        assert!((angles["SOUTH"] - degrees(150.0)).abs() < 1e-9);
+        assert!((angles["EAST"] - degrees(10.0)).abs() < 1e-9);
+        assert!((angles["WEST"] - degrees(170.0)).abs() < 1e-9);
+
+        let differences = referenced.area_differences(&areas());
+        assert_eq!(differences.len(), 6);
+        let (a, b, difference) = &differences[0];
+        assert_eq!((a.as_str(), b.as_str()), ("EAST", "NORTH"));
+        assert!((difference - degrees(-170.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_select_area_columns() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let frame = read_hex_file("data_message.bin").unwrap();
+        let batch = build_record_batch(&frame, frame.len(), &config.get_channel_map()).unwrap();
+
+        let east = areas().select(&batch, "EAST").unwrap();
+        assert_eq!(east.num_columns(), batch.num_columns());
+        let west = areas().select(&batch, "WEST").unwrap();
+        assert_eq!(west.num_columns(), 1);
+        assert_eq!(west.schema().field(0).name(), "timestamp");
+        assert_eq!(west.num_rows(), 1);
+    }
+}
@@ -0,0 +1,124 @@
+#![cfg(feature = "server")]
+#![allow(unused)]
+use pmu::audit::{AuditDirection, AuditEntry, AuditLog, AuditOutcome};
+use pmu::frames::CommandFrame2011;
+use pmu::pdc_client::PDCClient;
+use pmu::pdc_server::{run_mock_server, ClientPermission, CommandPolicy, Protocol, ServerConfig};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time;
+
+fn parse_lines(log: &AuditLog) -> Vec<Value> {
+    log.lines()
+        .iter()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_json() {
+        let cmd = CommandFrame2011::new_turn_on_transmission(7734);
+        let entry = AuditEntry::from_command(
+            AuditDirection::Received,
+            &cmd,
+            None,
+            Some("10.0.0.5:5000".parse().unwrap()),
+            AuditOutcome::Rejected("requires Control permission".to_string()),
+        );
+        let json = entry.to_json();
+        assert_eq!(json["direction"], "received");
+        assert_eq!(json["idcode"], 7734);
+        assert_eq!(json["command"], 2);
+        assert_eq!(json["command_name"], "turn_on_transmission");
+        assert_eq!(json["peer"], "10.0.0.5:5000");
+        assert_eq!(json["local"], Value::Null);
+        assert_eq!(json["outcome"], "rejected");
+        assert_eq!(json["reason"], "requires Control permission");
+        assert!(json["timestamp_us"].as_i64().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_file_log_appends_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let cmd = CommandFrame2011::new_send_config_frame1(1);
+        for _ in 0..2 {
+            let log = AuditLog::open(&path).unwrap();
+            log.record(&AuditEntry::from_command(
+                AuditDirection::Sent,
+                &cmd,
+                None,
+                None,
+                AuditOutcome::Sent,
+            ))
+            .unwrap();
+        }
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["command_name"], "send_config_frame1");
+    }
+
+    #[tokio::test]
+    async fn test_client_and_server_commands_are_audited() {
+        let server_log = Arc::new(AuditLog::in_memory());
+        let client_log = Arc::new(AuditLog::in_memory());
+        let policy = CommandPolicy::new(ClientPermission::ReadOnly);
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4723, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_policy(policy)
+            .with_audit(server_log.clone());
+        tokio::spawn(async move {
+            if let Err(e) = run_mock_server(server_config).await {
+                println!("Mock server error: {}", e)
+            };
+        });
+        time::sleep(Duration::from_millis(500)).await;
+
+        let (mut client, control_tx, _data_rx) = PDCClient::new_with_audit(
+            "127.0.0.1",
+            4723,
+            7734,
+            Duration::from_secs(1),
+            Some(client_log.clone()),
+        )
+        .await
+        .unwrap();
+        let client_handle = tokio::spawn(async move {
+            client.start_stream().await;
+        });
+        time::sleep(Duration::from_millis(300)).await;
+        control_tx
+            .send(pmu::pdc_client::ControlMessage::Stop)
+            .await
+            .unwrap();
+        let _ = time::timeout(Duration::from_secs(3), client_handle).await;
+        time::sleep(Duration::from_millis(300)).await;
+
+        let sent = parse_lines(&client_log);
+        let sent_names: Vec<_> = sent.iter().map(|e| e["command_name"].clone()).collect();
+        assert_eq!(
+            sent_names,
+            vec![
+                "send_config_frame1",
+                "turn_on_transmission",
+                "turn_off_transmission"
+            ]
+        );
+        assert!(sent.iter().all(|e| e["outcome"] == "sent"));
+
+        let received = parse_lines(&server_log);
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0]["outcome"], "accepted");
+        assert_eq!(received[1]["outcome"], "rejected");
+        assert_eq!(received[1]["peer"], sent[1]["local"]);
+    }
+}
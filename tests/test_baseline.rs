@@ -0,0 +1,117 @@
+#![cfg(feature = "analytics")]
+#![allow(unused)]
+
+#[cfg(test)]
+mod tests {
+    use pmu::baseline::Baseliner;
+
+    const HOUR_US: i64 = 3_600_000_000;
+    // 2024-06-03 00:00:00 UTC
+    const START_US: i64 = 1_717_372_800_000_000;
+
+    // A sample every 6 s for whole days; the level follows the hour of day
+    // and the day adds an offset, the seconds spread 0..1 over each minute.
+    fn feed(baseliner: &mut Baseliner, days: i64) {
+        for day in 0..days {
+            for hour in 0..24 {
+                for second in (0..3600).step_by(6) {
+                    let timestamp_us = START_US + (day * 24 + hour) * HOUR_US + second * 1_000_000;
+                    let value =
+                        100.0 + hour as f64 + day as f64 * 0.1 + (second % 60) as f64 / 60.0;
+                    baseliner.push("FREQ", timestamp_us, value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_hourly_baseline() {
+        let mut baseliner = Baseliner::in_memory();
+        feed(&mut baseliner, 3);
+        // The last hour is still open
+        assert!(baseliner.hour_summary("FREQ", "2024-06-05", 23).is_none());
+        baseliner.close_open_hours();
+
+        let summary = baseliner.hour_summary("FREQ", "2024-06-04", 7).unwrap();
+        assert_eq!(summary.count, 600);
+        assert!((summary.min - 107.1).abs() < 1e-9);
+        assert!((summary.max - (107.1 + 54.0 / 60.0)).abs() < 1e-9);
+        assert!((summary.p50 - (107.1 + 24.0 / 60.0)).abs() < 1e-9);
+
+        let baseline = baseliner.baseline("FREQ", 7).unwrap();
+        assert_eq!(baseline.days, 3);
+        assert_eq!(baseline.count, 1800);
+        assert!((baseline.min - 107.0).abs() < 1e-9);
+        assert!((baseline.max - (107.2 + 54.0 / 60.0)).abs() < 1e-9);
+        // Median of the daily medians is the middle day's
+        assert!((baseline.p50 - summary.p50).abs() < 1e-9);
+        assert!((baseline.mean - (107.1 + 27.0 / 60.0)).abs() < 1e-9);
+
+        assert_eq!(
+            baseliner.validate("FREQ", START_US + 7 * HOUR_US, 107.5, 0.0),
+            Some(true)
+        );
+        // Normal at 7:00 is not normal at 12:00
+        assert_eq!(
+            baseliner.validate("FREQ", START_US + 12 * HOUR_US, 107.5, 0.5),
+            Some(false)
+        );
+        assert_eq!(
+            baseliner.validate("VA", START_US + 7 * HOUR_US, 107.5, 0.0),
+            None
+        );
+        let (low, high) = baseline.limits(0.5);
+        assert!(low < baseline.p01 && high > baseline.p99);
+    }
+
+    #[test]
+    fn test_saved_and_reloaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("baseline.json");
+        let mut baseliner = Baseliner::open(&path).unwrap();
+        feed(&mut baseliner, 2);
+        baseliner.close_open_hours();
+        baseliner.save().unwrap();
+        let expected = baseliner.baseline("FREQ", 3);
+
+        let reloaded = Baseliner::open(&path).unwrap();
+        assert_eq!(reloaded.channels(), vec!["FREQ"]);
+        assert_eq!(reloaded.baseline("FREQ", 3), expected);
+        assert!(!dir.path().join("baseline.tmp").exists());
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(Baseliner::open(&path).is_err());
+    }
+
+    #[test]
+    fn test_retention_and_subsampling() {
+        let mut baseliner = Baseliner::in_memory()
+            .with_retention_days(2)
+            .with_max_samples(100);
+        feed(&mut baseliner, 4);
+        baseliner.close_open_hours();
+        assert!(baseliner.hour_summary("FREQ", "2024-06-04", 0).is_none());
+        let baseline = baseliner.baseline("FREQ", 0).unwrap();
+        assert_eq!(baseline.days, 2);
+        // All samples are counted, percentiles come from a subsample
+        assert_eq!(baseline.count, 1200);
+        assert!((baseline.p50 - 100.25 - 0.4).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_local_hours() {
+        let mut baseliner = Baseliner::in_memory().with_timezone(chrono_tz::America::New_York);
+        // 12:00 UTC is 8:00 EDT
+        baseliner.push("FREQ", START_US + 12 * HOUR_US, 60.0);
+        baseliner.close_open_hours();
+        assert!(baseliner.hour_summary("FREQ", "2024-06-03", 8).is_some());
+        assert!(baseliner.baseline("FREQ", 12).is_none());
+        assert_eq!(
+            baseliner
+                .baseline_at("FREQ", START_US + 12 * HOUR_US)
+                .unwrap()
+                .p50,
+            60.0
+        );
+    }
+}
@@ -0,0 +1,163 @@
+#![cfg(feature = "analytics")]
+#![allow(unused)]
+mod common;
+
+use common::{data_frame_us, frame_time, read_hex_file, START_US};
+use pmu::budget::MemoryBudget;
+use pmu::bundle::{BundleConfig, EventBundler};
+use pmu::events::{Event, EventBus, EventKind, Severity};
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::historian::Historian;
+use std::fs;
+
+// The sample stream at 30 frames/s for the given seconds.
+fn historian(seconds: i64) -> Historian {
+    let mut historian = Historian::new(MemoryBudget::unlimited());
+    let config = parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+    historian.add_stream(&config);
+    for n in 0..seconds * 30 {
+        historian
+            .insert(&data_frame_us(7734, frame_time(n)))
+            .unwrap();
+    }
+    historian
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_on_classified_event() {
+        let dir = tempfile::tempdir().unwrap();
+        let bus = EventBus::new(16);
+        let mut bundler = EventBundler::new(BundleConfig::new(dir.path()).with_windows(1.0, 1.0))
+            .unwrap()
+            .with_events(&bus);
+        let historian = historian(4);
+
+        let trip = Event::new(
+            START_US + 2_000_000,
+            EventKind::GeneratorTrip,
+            "Station A",
+            "Generator trip of 500 MW".to_string(),
+        )
+        .with_severity(Severity::Alarm)
+        .with_value("mw", 500.0);
+        bus.publish(trip.clone());
+        // Not a classified event
+        bus.publish(Event::new(
+            START_US + 2_000_000,
+            EventKind::DataQuality,
+            "Station A",
+            "Spike".to_string(),
+        ));
+        // Its post window is not held yet
+        bus.publish(Event::new(
+            START_US + 3_500_000,
+            EventKind::LoadLoss,
+            "Station A",
+            "Load loss".to_string(),
+        ));
+
+        let written = bundler.poll(&historian).unwrap();
+        assert_eq!(written.len(), 1);
+        assert_eq!(bundler.pending(), 1);
+        let bundle = &written[0];
+        assert!(bundle
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .ends_with("-generator_trip-Station_A"));
+
+        let description: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(bundle.join("event.json")).unwrap()).unwrap();
+        assert_eq!(description["event"]["kind"], "generator_trip");
+        assert_eq!(description["event"]["values"]["mw"], 500.0);
+        assert_eq!(description["window"]["start_us"], START_US + 1_000_000);
+        assert_eq!(description["streams"][0]["idcode"], 7734);
+        assert_eq!(description["streams"][0]["station"], "Station A");
+        assert_eq!(description["streams"][0]["rows"], 61);
+        assert_eq!(description["record"]["rows"], 61);
+
+        // COMTRADE header and one line per sample
+        let cfg = fs::read_to_string(bundle.join("record.cfg")).unwrap();
+        let lines: Vec<&str> = cfg.lines().collect();
+        assert_eq!(lines[0], "Station A,pmu,1999");
+        let channels = description["record"]["channels"].as_u64().unwrap() as usize;
+        assert_eq!(lines[1], format!("{},{}A,0D", channels, channels));
+        assert!(lines[2..2 + channels]
+            .iter()
+            .any(|line| line.contains("FREQ") && line.contains(",Hz,")));
+        assert_eq!(lines[2 + channels], "60");
+        assert_eq!(lines[4 + channels], "30,61");
+        assert_eq!(lines[5 + channels], "14/11/2023,22:13:21.000000");
+        assert_eq!(lines[6 + channels], "14/11/2023,22:13:22.000000");
+        assert_eq!(lines[7 + channels], "ASCII");
+        let dat = fs::read_to_string(bundle.join("record.dat")).unwrap();
+        let rows: Vec<&str> = dat.lines().collect();
+        assert_eq!(rows.len(), 61);
+        assert!(rows[0].starts_with("1,0,"));
+        assert_eq!(rows[0].split(',').count(), channels + 2);
+        assert!(rows[30].starts_with("31,1000000,"));
+
+        // Only the key channels to plot
+        let plot = fs::read_to_string(bundle.join("plot.csv")).unwrap();
+        let header = plot.lines().next().unwrap();
+        assert!(header.contains("FREQ"), "{}", header);
+        assert!(!header.contains("ANALOG"), "{}", header);
+        assert_eq!(plot.lines().count(), 62);
+        assert!(bundle.join("plot_channels.csv").exists());
+        #[cfg(feature = "plot")]
+        assert!(bundle.join("chart.svg").exists());
+
+        // The rest when stopping
+        let written = bundler.finish(&historian).unwrap();
+        assert_eq!(written.len(), 1);
+        assert_eq!(bundler.bundles().len(), 2);
+        let description: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(written[0].join("event.json")).unwrap())
+                .unwrap();
+        assert_eq!(description["streams"][0]["rows"], 45);
+    }
+
+    #[test]
+    fn test_bundle_config() {
+        let config = BundleConfig::from_json(
+            r#"{"dir": "bundles", "pre_secs": 5, "kinds": ["trigger", "topology_change"],
+                "min_severity": "warning"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.pre_secs, 5.0);
+        assert_eq!(config.post_secs, 10.0);
+        assert_eq!(
+            config.kinds,
+            [EventKind::Trigger, EventKind::TopologyChange]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut bundler = EventBundler::new(BundleConfig {
+            dir: dir.path().to_path_buf(),
+            ..config
+        })
+        .unwrap();
+        let event = Event::new(
+            START_US,
+            EventKind::Trigger,
+            "Station A",
+            "Trigger".to_string(),
+        );
+        assert!(!bundler.offer(&event));
+        assert!(bundler.offer(&event.clone().with_severity(Severity::Warning)));
+
+        // Without data only the description
+        let written = bundler
+            .finish(&Historian::new(MemoryBudget::unlimited()))
+            .unwrap();
+        let files: Vec<String> = fs::read_dir(&written[0])
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(files, ["event.json"]);
+    }
+}
@@ -0,0 +1,88 @@
+#![cfg(feature = "arrow")]
+#![allow(unused)]
+mod common;
+
+use common::read_hex_file;
+use pmu::channels::{ChannelId, ChannelIndex, ChannelRegistry};
+use pmu::filter::{ChannelFilters, FilterChain, MovingAverage};
+use pmu::frame_parser::parse_config_frame_1and2;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_interns_names() {
+        let mut registry = ChannelRegistry::new();
+        assert!(registry.is_empty());
+        let va = registry.intern("Station A_7734_VA");
+        let freq = registry.intern("Station A_7734_FREQ");
+        assert_eq!(va, ChannelId(0));
+        assert_eq!(freq, ChannelId(1));
+        // The same name keeps its ID
+        assert_eq!(registry.intern("Station A_7734_VA"), va);
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.id("Station A_7734_FREQ"), Some(freq));
+        assert_eq!(registry.id("VB"), None);
+        assert_eq!(registry.name(freq), Some("Station A_7734_FREQ"));
+        assert_eq!(registry.name(ChannelId(7)), None);
+        assert_eq!(
+            registry.names().collect::<Vec<_>>(),
+            vec!["Station A_7734_VA", "Station A_7734_FREQ"]
+        );
+    }
+
+    #[test]
+    fn test_index_of_configuration() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let channel_map = config.get_channel_map();
+        let index = config.channel_index();
+        assert_eq!(index.len(), channel_map.len());
+
+        // IDs in offset order, each with the channel map's info
+        let offsets: Vec<usize> = index.iter().map(|(_, _, info)| info.offset).collect();
+        assert!(offsets.windows(2).all(|pair| pair[0] <= pair[1]));
+        for (id, name, info) in index.iter() {
+            assert_eq!(index.id(name), Some(id));
+            assert_eq!(index.offset(id), Some(channel_map[name].offset));
+            assert_eq!(info.size, channel_map[name].size);
+        }
+        let freq = index.id("Station A_7734_FREQ").unwrap();
+        assert_eq!(index.name(freq), Some("Station A_7734_FREQ"));
+        assert_eq!(index.get("Station A_7734_FREQ").unwrap().unit, "Hz");
+        assert!(index.id("Station B_1_FREQ").is_none());
+
+        // The same configuration gives the same IDs
+        let again = ChannelIndex::new(&config.get_channel_map());
+        assert!(index
+            .iter()
+            .zip(again.iter())
+            .all(|(a, b)| a.0 == b.0 && a.1 == b.1));
+
+        let subset = index.channel_map(&[freq]);
+        assert_eq!(subset.len(), 1);
+        assert!(subset.contains_key("Station A_7734_FREQ"));
+    }
+
+    #[test]
+    fn test_filters_by_channel_id() {
+        let chain = || FilterChain::new().with(MovingAverage::new(2));
+        let mut filters = ChannelFilters::new(30.0)
+            .with_chain("FREQ", chain())
+            .with_chain("DFREQ", chain());
+        let freq = filters.channel_id("FREQ").unwrap();
+        assert!(filters.channel_id("VA").is_none());
+        assert_eq!(filters.process_channel(freq, 2.0), 2.0);
+        // By name or ID, the same chain
+        assert_eq!(filters.process("FREQ", 4.0), 3.0);
+        assert_eq!(filters.process_channel(freq, 4.0), 4.0);
+        assert_eq!(filters.process("VA", 5.0), 5.0);
+        assert_eq!(filters.process("DFREQ", 2.0), 2.0);
+        assert_eq!(filters.process("DFREQ", 6.0), 4.0);
+
+        // A chain set again replaces the one before
+        let mut filters = filters.with_chain("FREQ", FilterChain::new());
+        assert_eq!(filters.process("FREQ", 7.0), 7.0);
+    }
+}
@@ -0,0 +1,115 @@
+#![cfg(feature = "sinks")]
+#![allow(unused)]
+mod common;
+
+use common::{data_frame, read_hex_file};
+use std::fs;
+
+#[cfg(test)]
+mod tests {
+    use super::{data_frame, read_hex_file};
+    use pmu::aggregator::Aggregator;
+    use pmu::budget::MemoryBudget;
+    use pmu::checkpoint::{self, Checkpoint, Frame, StreamState};
+    use pmu::frame_parser::parse_config_frame_1and2;
+    use pmu::frames::ConfigurationFrame1and2_2011;
+    use pmu::historian::Historian;
+    use std::fs;
+    use std::time::Duration;
+
+    const SOC: u32 = 1_700_000_000;
+
+    fn config(idcode: u16) -> ConfigurationFrame1and2_2011 {
+        let mut config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        config.prefix.idcode = idcode;
+        config
+    }
+
+    #[test]
+    fn test_checkpoint_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("collector.json");
+        assert_eq!(Checkpoint::load(&path).unwrap(), None);
+
+        let mut checkpoint = Checkpoint {
+            saved_us: 1,
+            ..Default::default()
+        };
+        checkpoint.streams.insert(
+            7,
+            StreamState {
+                source: Some("10.0.0.1:4712".to_string()),
+                config: Frame::from(&config(7)),
+                last_timestamp_us: Some(42),
+            },
+        );
+        checkpoint.save(&path).unwrap();
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(json.contains("\"config\":\"aa31"), "{}", json);
+        let loaded = Checkpoint::load(&path).unwrap().unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(loaded.streams[&7].config.config().unwrap().prefix.idcode, 7);
+        assert!(loaded.stream_from("10.0.0.1:4712").is_some());
+
+        // Shards merged, the newer state of a stream wins
+        checkpoint.saved_us = 2;
+        checkpoint.streams.get_mut(&7).unwrap().last_timestamp_us = Some(43);
+        checkpoint.save(dir.path().join("shard-1.json")).unwrap();
+        let merged = checkpoint::load_dir(dir.path()).unwrap();
+        assert_eq!(merged.streams[&7].last_timestamp_us, Some(43));
+        assert_eq!(
+            checkpoint::load_dir(dir.path().join("none")).unwrap(),
+            Checkpoint::default()
+        );
+    }
+
+    #[test]
+    fn test_aggregator_resumes_pending_groups() {
+        let mut aggregator = Aggregator::new(Duration::from_millis(100));
+        aggregator.add_stream(&config(1));
+        aggregator.add_stream(&config(2));
+        assert!(aggregator
+            .push_frame(&data_frame(1, SOC, 0))
+            .unwrap()
+            .is_empty());
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.aggregator = Some(aggregator.checkpoint());
+        let json = serde_json::to_string(&checkpoint).unwrap();
+        drop(aggregator);
+
+        // After the restart, stream 2 completes the group stream 1 started
+        let checkpoint: Checkpoint = serde_json::from_str(&json).unwrap();
+        let mut aggregator = Aggregator::new(Duration::from_millis(100));
+        aggregator
+            .restore(checkpoint.aggregator.as_ref().unwrap())
+            .unwrap();
+        assert_eq!(
+            aggregator.watermark(),
+            Some(SOC as i64 * 1_000_000 - 100_000)
+        );
+        let groups = aggregator.push_frame(&data_frame(2, SOC, 0)).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].frames.keys().copied().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(groups[0].frames[&1], data_frame(1, SOC, 0));
+    }
+
+    #[test]
+    fn test_historian_restores_frames() {
+        let mut historian = Historian::new(MemoryBudget::unlimited());
+        historian.add_stream(&config(7734));
+        for index in 0..10 {
+            historian.insert(&data_frame(7734, SOC, index)).unwrap();
+        }
+        let state = historian.checkpoint();
+        assert_eq!(state.frames.len(), 10);
+
+        // A smaller budget keeps the newest frames
+        let mut restored = Historian::new(MemoryBudget::unlimited().with_max_rows(4));
+        restored.restore(&state).unwrap();
+        assert_eq!(restored.usage(7734).unwrap().rows, 4);
+        let newest = historian.time_range(7734).unwrap().1;
+        assert_eq!(restored.time_range(7734).unwrap().1, newest);
+        assert_eq!(restored.evicted(7734), Some(6));
+    }
+}
@@ -0,0 +1,162 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+mod common;
+
+use common::read_hex_file;
+use pmu::cim::{catalog_xml, measurements, mrid, write_catalog, CIM_NAMESPACE};
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::pipeline::{CatalogChannel, StreamCheck};
+use pmu::topology::Topology;
+use std::fs;
+
+// The catalog of the sample configuration, as validation lists it.
+fn sample_stream() -> StreamCheck {
+    let config = parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+    let mut channels: Vec<CatalogChannel> = config
+        .get_channel_map()
+        .into_iter()
+        .map(|(name, info)| CatalogChannel {
+            name,
+            station: info.station,
+            idcode: info.idcode,
+            kind: info.kind.to_string(),
+            unit: info.unit.to_string(),
+        })
+        .collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    StreamCheck {
+        source: "127.0.0.1:4712".to_string(),
+        idcode: Some(7734),
+        data_rate: Some(30),
+        channels,
+        error: None,
+    }
+}
+
+fn channel(name: &str, kind: &str, unit: &str) -> CatalogChannel {
+    CatalogChannel {
+        name: name.to_string(),
+        station: "Station A".to_string(),
+        idcode: 7734,
+        kind: kind.to_string(),
+        unit: unit.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mrid_is_stable_uuid() {
+        let id = mrid("Station A_7734_VA_magnitude");
+        assert_eq!(id, mrid("Station A_7734_VA_magnitude"));
+        assert_ne!(id, mrid("Station A_7734_VA_angle"));
+        let groups: Vec<&str> = id.split('-').collect();
+        assert_eq!(
+            groups.iter().map(|g| g.len()).collect::<Vec<_>>(),
+            vec![8, 4, 4, 4, 12]
+        );
+        assert!(groups[2].starts_with('8'));
+        assert!(matches!(
+            groups[3].chars().next(),
+            Some('8' | '9' | 'a' | 'b')
+        ));
+    }
+
+    #[test]
+    fn test_measurements_of_channels() {
+        let topology = Topology::default();
+        let phasor = measurements(&channel("VA", "voltage", "V"), &topology);
+        assert_eq!(phasor.len(), 2);
+        assert_eq!(phasor[0].name, "VA_magnitude");
+        assert_eq!(phasor[0].measurement_type, "PhaseVoltage");
+        assert_eq!(phasor[0].unit, "V");
+        assert_eq!(phasor[1].name, "VA_angle");
+        assert_eq!(phasor[1].unit, "rad");
+        assert_eq!(phasor[0].description, "Station A, PMU 7734");
+        assert_eq!(phasor[0].terminal, None);
+
+        let current = measurements(&channel("I1", "current", "A"), &topology);
+        assert_eq!(current[0].measurement_type, "LineCurrent");
+        let freq = measurements(&channel("FREQ", "frequency", "Hz"), &topology);
+        assert_eq!((freq[0].class, freq[0].unit), ("Analog", "Hz"));
+        let breaker = measurements(&channel("BRK", "digital", ""), &topology);
+        assert_eq!((breaker[0].class, breaker[0].unit), ("Discrete", "none"));
+        assert!(measurements(&channel("STAT", "stat", ""), &topology).is_empty());
+    }
+
+    #[test]
+    fn test_catalog_document() {
+        let stream = sample_stream();
+        let va = stream
+            .channels
+            .iter()
+            .find(|channel| channel.kind == "voltage")
+            .unwrap()
+            .name
+            .clone();
+        let topology =
+            Topology::from_csv(&format!("{},BUS_7,LINE_7_8,_5f2c1a4e-0c43\n", va)).unwrap();
+        let failed = StreamCheck {
+            source: "127.0.0.1:4713".to_string(),
+            idcode: None,
+            data_rate: None,
+            channels: Vec::new(),
+            error: Some("Failed to connect".to_string()),
+        };
+        let xml = catalog_xml(&[stream.clone(), failed], &topology);
+
+        assert!(xml.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?>"#));
+        assert!(xml.contains(&format!(r#"xmlns:cim="{}""#, CIM_NAMESPACE)));
+        assert!(xml.trim_end().ends_with("</rdf:RDF>"));
+        let expected: usize = stream
+            .channels
+            .iter()
+            .map(|channel| measurements(channel, &topology).len())
+            .sum();
+        let opened = xml.matches("<cim:Analog ").count() + xml.matches("<cim:Discrete ").count();
+        assert_eq!(opened, expected);
+        assert_eq!(
+            xml.matches("</cim:Analog>").count() + xml.matches("</cim:Discrete>").count(),
+            expected
+        );
+        assert!(!xml.contains("STAT<"));
+
+        // Both parts of the mapped phasor refer to its terminal, listed once
+        let magnitude = mrid(&format!("{}_magnitude", va));
+        assert!(xml.contains(&format!(r#"<cim:Analog rdf:ID="_{}">"#, magnitude)));
+        assert_eq!(
+            xml.matches(r##"<cim:Measurement.Terminal rdf:resource="#_5f2c1a4e-0c43"/>"##)
+                .count(),
+            2
+        );
+        assert_eq!(
+            xml.matches(r##"<cim:Terminal rdf:about="#_5f2c1a4e-0c43">"##)
+                .count(),
+            1
+        );
+        assert!(xml.contains("bus BUS_7, branch LINE_7_8"));
+
+        // Same catalog, same document
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("catalog.xml");
+        write_catalog(&path, &[stream], &topology).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(written, xml);
+    }
+
+    #[test]
+    fn test_names_are_escaped() {
+        let stream = StreamCheck {
+            source: "pdc".to_string(),
+            idcode: Some(1),
+            data_rate: Some(30),
+            channels: vec![channel("P&Q <1>", "rms", "")],
+            error: None,
+        };
+        let xml = catalog_xml(&[stream], &Topology::default());
+        assert!(xml.contains("<cim:IdentifiedObject.name>P&amp;Q &lt;1&gt;</"));
+        assert!(xml.contains("UnitSymbol.none"));
+    }
+}
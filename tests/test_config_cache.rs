@@ -0,0 +1,123 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+mod common;
+
+use common::read_hex_file;
+use pmu::config_cache::ConfigCache;
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::pdc_server::{run_mock_server, Protocol, ServerConfig};
+use pmu::pipeline::{Pipeline, PipelineConfig};
+use pmu::simulator::{Scenario, SimulatedPmu, StreamLayout};
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time;
+
+fn layout(idcode: u16, phasors: u16) -> StreamLayout {
+    StreamLayout {
+        idcode,
+        data_rate: 30,
+        time_base: 1_000_000,
+        pmus: vec![SimulatedPmu {
+            station: format!("PMU {}", idcode),
+            idcode,
+            polar: false,
+            float_phasors: false,
+            float_analogs: false,
+            float_freq: false,
+            phasors,
+            analogs: 0,
+            digitals: 0,
+            data_rate: None,
+            nominal_50hz: false,
+            angle: 0.0,
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ConfigCache::new(dir.path().join("cache")).unwrap();
+        let frame = read_hex_file("config_message.bin").unwrap();
+        assert_eq!(cache.load("10.0.0.1:4712", 1).unwrap(), None);
+
+        assert!(cache.store("10.0.0.1:4712", 1, &frame).unwrap());
+        assert_eq!(
+            cache.path("10.0.0.1:4712", 1),
+            dir.path().join("cache/10.0.0.1_4712-1.cfg")
+        );
+        assert_eq!(cache.load("10.0.0.1:4712", 1).unwrap(), Some(frame.clone()));
+        let config = cache.load_config("10.0.0.1:4712", 1).unwrap().unwrap();
+        assert_eq!(config, parse_config_frame_1and2(&frame).unwrap());
+        // Unchanged, not written again
+        assert!(!cache.store("10.0.0.1:4712", 1, &frame).unwrap());
+
+        // By source and idcode
+        assert_eq!(cache.load("10.0.0.1:4713", 1).unwrap(), None);
+        assert_eq!(cache.load("10.0.0.1:4712", 2).unwrap(), None);
+
+        // Only whole configuration frames
+        let data = read_hex_file("data_message.bin").unwrap();
+        assert!(cache.store("10.0.0.1:4712", 1, &data).is_err());
+        assert!(cache
+            .store("10.0.0.1:4712", 1, &frame[..frame.len() - 1])
+            .is_err());
+        assert_eq!(cache.load("10.0.0.1:4712", 1).unwrap(), Some(frame));
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_refreshes_cached_config() {
+        let scenario = Scenario {
+            stream: Some(layout(107, 3)),
+            ..Default::default()
+        };
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4746, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_udp_data(4747)
+            .with_scenario(scenario);
+        let server = tokio::spawn(async move {
+            if let Err(e) = run_mock_server(server_config).await {
+                println!("Mock server error: {}", e)
+            };
+        });
+        time::sleep(Duration::from_millis(500)).await;
+
+        // Cached before the PMU got a third phasor
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ConfigCache::new(dir.path().join("cache")).unwrap();
+        let stale = layout(107, 2).to_config();
+        cache.store("127.0.0.1:4746", 1, &stale.to_hex()).unwrap();
+
+        let json = format!(
+            r#"{{"streams": [{{"host": "127.0.0.1", "port": 4746, "udp_port": 4747}}],
+                "sink": {{"format": "csv", "dir": {:?}}},
+                "config_cache": {:?}, "batch_rows": 10}}"#,
+            dir.path().join("out"),
+            dir.path().join("cache")
+        );
+        let pipeline = Arc::new(Pipeline::new(PipelineConfig::from_json(&json).unwrap()));
+        let runner = pipeline.clone();
+        let handle = tokio::spawn(async move { runner.run().await });
+        time::sleep(Duration::from_millis(1000)).await;
+        pipeline.stop();
+        let stats = time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(stats[0].errors, 0);
+        assert!(stats[0].frames > 10, "{:?}", stats[0]);
+
+        // The current configuration replaced the stale one
+        let cached = cache.load_config("127.0.0.1:4746", 1).unwrap().unwrap();
+        assert_eq!(cached.pmu_configs[0].phnmr, 3);
+        let csv = fs::read_to_string(dir.path().join("out/107.csv")).unwrap();
+        assert!(csv.lines().next().unwrap().contains("PMU 107_107_VC_X"));
+        server.abort();
+    }
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "sinks")]
+#![allow(unused)]
+use arrow::array::{ArrayRef, Float32Array, TimestampMicrosecondArray, UInt8Array};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use std::sync::Arc;
+
+// Two rows with a timestamp, the given Float32 columns and a quality column.
+fn batch_with_quality(names: &[&str], quality: [u8; 2]) -> RecordBatch {
+    let mut fields = vec![Field::new(
+        "timestamp",
+        DataType::Timestamp(TimeUnit::Microsecond, None),
+        false,
+    )];
+    let mut arrays: Vec<ArrayRef> = vec![Arc::new(TimestampMicrosecondArray::from(vec![
+        1_700_000_000_000_000,
+        1_700_000_000_033_333,
+    ]))];
+    for name in names {
+        fields.push(Field::new(*name, DataType::Float32, false));
+        arrays.push(Arc::new(Float32Array::from(vec![1.5, 2.5])));
+    }
+    fields.push(Field::new("quality", DataType::UInt8, false));
+    arrays.push(Arc::new(UInt8Array::from(quality.to_vec())));
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::batch_with_quality;
+    use pmu::sinks::csv::CsvSink;
+    use pmu::sinks::BatchSink;
+    use std::fs;
+
+    #[test]
+    fn test_csv_rows_and_quality() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out").join("stream.csv");
+        let mut sink = CsvSink::new(&path).unwrap();
+        sink.write_batch(&batch_with_quality(&["A", "B"], [0, 1]))
+            .unwrap();
+        sink.write_batch(&batch_with_quality(&["A", "B"], [2, 0]))
+            .unwrap();
+        sink.close().unwrap();
+        assert_eq!(sink.rows(), 4);
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "timestamp,A,B,quality");
+        assert!(lines[1].starts_with("2023-11-14T22:13:20"));
+        assert!(lines[1].ends_with(",1.5,1.5,0"));
+        assert!(lines[2].ends_with(",2.5,2.5,1"));
+        assert!(lines[3].ends_with(",1.5,1.5,2"));
+        assert!(sink
+            .write_batch(&batch_with_quality(&["A"], [0, 0]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_csv_header_on_schema_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stream.csv");
+        let mut sink = CsvSink::new(&path).unwrap();
+        sink.write_batch(&batch_with_quality(&["A", "B"], [0, 0]))
+            .unwrap();
+        // Same fields in another order are written in the first order
+        sink.write_batch(&batch_with_quality(&["B", "A"], [0, 0]))
+            .unwrap();
+        sink.write_batch(&batch_with_quality(&["C"], [0, 0]))
+            .unwrap();
+        sink.close().unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let headers: Vec<&str> = text
+            .lines()
+            .filter(|line| line.starts_with("timestamp"))
+            .collect();
+        assert_eq!(
+            headers,
+            vec!["timestamp,A,B,quality", "timestamp,C,quality"]
+        );
+        assert_eq!(text.lines().count(), 8);
+    }
+}
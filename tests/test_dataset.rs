@@ -0,0 +1,124 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+mod common;
+
+use common::timestamps;
+
+#[cfg(test)]
+mod tests {
+    use super::timestamps;
+    use pmu::dataset::{self, DatasetConfig, DatasetGenerator, EventLabel};
+    use std::collections::HashMap;
+    use std::fs;
+
+    const START_US: i64 = 1_700_000_000 * 1_000_000;
+
+    // Two streams of 30 frames/s for 10 s, a grid wide ramp and per stream faults.
+    fn config() -> DatasetConfig {
+        DatasetConfig::from_json(
+            r#"{
+                "start_soc": 1700000000,
+                "duration_secs": 10,
+                "batch_rows": 100,
+                "format": "csv",
+                "noise": {"seed": 7, "magnitude_std": 0.001},
+                "events": [{"type": "frequency_ramp", "at": 2, "duration": 1.5, "rate": 0.1}],
+                "streams": [
+                    {
+                        "layout": {"idcode": 1, "data_rate": 30,
+                            "pmus": [{"station": "A", "idcode": 11, "phasors": 2}]},
+                        "events": [{"type": "drop_frames", "at": 5, "count": 6}]
+                    },
+                    {
+                        "layout": {"idcode": 2, "data_rate": 30,
+                            "pmus": [{"station": "B", "idcode": 21}, {"station": "C", "idcode": 22}]},
+                        "events": [{"type": "corrupt_crc", "at": 4, "count": 3}]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_generator_batches_every_stream() {
+        let config = config();
+        let mut generator = DatasetGenerator::new(&config);
+        let mut rows: HashMap<u16, Vec<i64>> = HashMap::new();
+        for next in generator.by_ref() {
+            let (idcode, batch) = next.unwrap();
+            assert!(batch.num_rows() <= 100);
+            rows.entry(idcode).or_default().extend(timestamps(&batch));
+        }
+        // 300 frames each, minus the dropped and the corrupted ones
+        assert_eq!(rows[&1].len(), 294);
+        assert_eq!(rows[&2].len(), 297);
+        for timestamps in rows.values() {
+            assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+            assert_eq!(timestamps[0], START_US);
+        }
+
+        let stats = generator.stats();
+        assert_eq!(stats.frames, 594);
+        assert_eq!(stats.rejected, 3);
+        assert_eq!(stats.rows, 591);
+    }
+
+    #[test]
+    fn test_generator_is_deterministic() {
+        let config = config();
+        let first: Vec<_> = DatasetGenerator::new(&config)
+            .map(|next| next.unwrap())
+            .collect();
+        let second: Vec<_> = DatasetGenerator::new(&config)
+            .map(|next| next.unwrap())
+            .collect();
+        assert_eq!(first.len(), second.len());
+        for ((first_idcode, first), (second_idcode, second)) in first.iter().zip(&second) {
+            // Column order follows the channel map, compare by name
+            assert_eq!(first_idcode, second_idcode);
+            for field in first.schema().fields() {
+                assert_eq!(
+                    first.column_by_name(field.name()),
+                    second.column_by_name(field.name())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_labels_mark_injected_events() {
+        let labels = config().labels();
+        let label = |idcode: u16, kind: &str, start_s: f64, end_s: f64| EventLabel {
+            idcode,
+            kind: kind.to_string(),
+            start_us: START_US + (start_s * 1e6).round() as i64,
+            end_us: START_US + (end_s * 1e6).round() as i64,
+        };
+        assert_eq!(
+            labels,
+            vec![
+                label(1, "frequency_ramp", 2.0, 3.5),
+                label(2, "frequency_ramp", 2.0, 3.5),
+                label(2, "corrupt_crc", 4.0, 4.1),
+                label(1, "drop_frames", 5.0, 5.2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_dataset_and_labels() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats = dataset::write(&config(), dir.path()).unwrap();
+        assert_eq!(stats.rows, 591);
+
+        let csv = fs::read_to_string(dir.path().join("1.csv")).unwrap();
+        assert_eq!(csv.lines().count(), 294 + 1);
+        assert!(dir.path().join("2.csv").exists());
+
+        let labels: Vec<EventLabel> =
+            serde_json::from_str(&fs::read_to_string(dir.path().join("labels.json")).unwrap())
+                .unwrap();
+        assert_eq!(labels, config().labels());
+    }
+}
@@ -0,0 +1,147 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+mod common;
+
+use common::read_hex_file;
+use pmu::deadletter::{
+    DeadLetter, DeadLetterConfig, DeadLetterQueue, FRAMES_METRIC, WRITTEN_METRIC,
+};
+use pmu::metrics::Metrics;
+use pmu::pipeline::PipelineConfig;
+use pmu::recorder::{CaptureReader, CaptureRecord};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+const START_US: i64 = 1_700_000_000_000_000;
+
+// The sample data frame, its CHK broken, arriving n tenths of a second in.
+fn bad_crc(source: &str, n: i64) -> DeadLetter {
+    let mut frame = read_hex_file("data_message.bin").unwrap();
+    let len = frame.len();
+    frame[len - 1] ^= 0xff;
+    DeadLetter {
+        frame,
+        arrival_us: START_US + n * 100_000,
+        source: source.to_string(),
+        reason: "crc".to_string(),
+        error: "InvalidCrc(7734)".to_string(),
+    }
+}
+
+fn records(path: &Path) -> Vec<CaptureRecord> {
+    CaptureReader::open(path)
+        .unwrap()
+        .records()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+fn context(path: &Path) -> Vec<Value> {
+    fs::read_to_string(path.with_extension("jsonl"))
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_written_once_failing_repeatedly() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DeadLetterConfig::new(dir.path()).with_limits(3, 4);
+        let mut queue = DeadLetterQueue::new(config).unwrap().with_name("shard-0");
+
+        // A single failure is only counted
+        assert_eq!(queue.push(bad_crc("10.0.0.2:4712", 0)).unwrap(), 0);
+        assert_eq!(queue.push(bad_crc("10.0.0.1:4712", 1)).unwrap(), 0);
+        assert_eq!(queue.push(bad_crc("10.0.0.1:4712", 2)).unwrap(), 0);
+        assert!(queue.path().is_none());
+        // The third writes the two held back with it
+        assert_eq!(queue.push(bad_crc("10.0.0.1:4712", 3)).unwrap(), 3);
+        assert_eq!(queue.push(bad_crc("10.0.0.1:4712", 4)).unwrap(), 1);
+        // No more than four of a source and reason
+        assert_eq!(queue.push(bad_crc("10.0.0.1:4712", 5)).unwrap(), 0);
+        assert_eq!(queue.failures("10.0.0.1:4712", "crc"), 5);
+        assert_eq!(queue.failures("10.0.0.2:4712", "crc"), 1);
+        assert_eq!(queue.written(), 4);
+
+        let path = queue.path().unwrap().to_path_buf();
+        assert_eq!(
+            path.file_name().unwrap().to_str().unwrap(),
+            "shard-0-20231114T221320.pmucap"
+        );
+        queue.finish().unwrap();
+        let records = records(&path);
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0], {
+            let letter = bad_crc("10.0.0.1:4712", 1);
+            CaptureRecord {
+                arrival_us: letter.arrival_us,
+                frame: letter.frame,
+            }
+        });
+
+        let context = context(&path);
+        assert_eq!(context.len(), 4);
+        assert_eq!(context[0]["frame"], 0);
+        assert_eq!(context[0]["source"], "10.0.0.1:4712");
+        assert_eq!(context[0]["reason"], "crc");
+        assert_eq!(context[0]["error"], "InvalidCrc(7734)");
+        assert_eq!(context[0]["arrival_us"], START_US + 100_000);
+        assert_eq!(context[0]["time"], "2023-11-14T22:13:20.100+00:00");
+        assert_eq!(context[3]["frame"], 3);
+        assert_eq!(context[3]["size"], records[3].frame.len());
+    }
+
+    #[test]
+    fn test_failures_counted_in_metrics() {
+        let dir = tempfile::tempdir().unwrap();
+        let metrics = Arc::new(Metrics::new());
+        let config = DeadLetterConfig::new(dir.path()).with_limits(2, 100);
+        let mut queue = DeadLetterQueue::new(config)
+            .unwrap()
+            .with_metrics(metrics.clone());
+        for n in 0..3 {
+            queue.push(bad_crc("idcode 7734", n)).unwrap();
+        }
+        let mut config = bad_crc("idcode 7734", 3);
+        config.reason = "config".to_string();
+        queue.push(config).unwrap();
+        queue.finish().unwrap();
+
+        assert_eq!(
+            metrics.counter(
+                FRAMES_METRIC,
+                &[("source", "idcode 7734"), ("reason", "crc")]
+            ),
+            3
+        );
+        assert_eq!(metrics.counter_total(FRAMES_METRIC), 4);
+        assert_eq!(metrics.counter(WRITTEN_METRIC, &[("reason", "crc")]), 3);
+        assert_eq!(metrics.counter_total(WRITTEN_METRIC), 3);
+        assert!(metrics
+            .render()
+            .contains("# HELP pmu_dead_letter_frames_total"));
+    }
+
+    #[test]
+    fn test_pipeline_config() {
+        let config = PipelineConfig::from_json(
+            r#"{
+                "streams": [{"host": "127.0.0.1", "port": 4712}],
+                "sink": {"dir": "out"},
+                "dead_letter": {"dir": "dead-letters"}
+            }"#,
+        )
+        .unwrap();
+        let dead_letter = config.dead_letter.unwrap();
+        assert_eq!(dead_letter, DeadLetterConfig::new("dead-letters"));
+        assert_eq!(dead_letter.min_failures, 3);
+        assert_eq!(dead_letter.max_frames, 100);
+    }
+}
@@ -0,0 +1,163 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+mod common;
+
+use arrow::array::{Array, Float64Array, TimestampMicrosecondArray};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use common::timestamps;
+use pmu::pipeline::{PipelineConfig, SinkConfig, SinkFormat};
+use pmu::sinks::decimate::{DecimatedSink, Decimator};
+use pmu::sinks::BatchSink;
+use std::sync::{Arc, Mutex};
+
+// Rows every period_us from start_us, the value being the row number.
+fn batch(start_us: i64, period_us: i64, rows: usize) -> RecordBatch {
+    let schema = Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            false,
+        ),
+        Field::new("value", DataType::Float64, false),
+    ]);
+    let timestamps: Vec<i64> = (0..rows as i64).map(|n| start_us + n * period_us).collect();
+    let values: Vec<f64> = (0..rows).map(|n| n as f64).collect();
+    RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(TimestampMicrosecondArray::from(timestamps)),
+            Arc::new(Float64Array::from(values)),
+        ],
+    )
+    .unwrap()
+}
+
+// Keeps what it is given, for checking what a wrapper passes on.
+#[derive(Clone, Default)]
+struct Collect(Arc<Mutex<Vec<RecordBatch>>>);
+
+impl BatchSink for Collect {
+    fn write_batch(&mut self, batch: &RecordBatch) -> std::io::Result<()> {
+        self.0.lock().unwrap().push(batch.clone());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decimate_across_batches() {
+        // 30 fps starting mid-second, 5 Hz keeps the first row of every 200 ms
+        let mut decimator = Decimator::from_rate(5.0);
+        assert_eq!(decimator.interval_us(), 200_000);
+        let mut kept = Vec::new();
+        for n in 0..3 {
+            let start = 1_000_150_000 + n * 10 * 33_333;
+            let output = decimator.decimate(&batch(start, 33_333, 10)).unwrap();
+            kept.extend(output.map(|batch| timestamps(&batch)).unwrap_or_default());
+        }
+        let intervals: Vec<i64> = kept.iter().map(|t| t / 200_000).collect();
+        assert_eq!(intervals, (5000..5006).collect::<Vec<i64>>());
+        assert_eq!(kept[0], 1_000_150_000);
+        assert_eq!(kept[1], 1_000_150_000 + 2 * 33_333);
+
+        // Nothing new in the same interval
+        assert!(decimator
+            .decimate(&batch(1_001_150_000 - 1, 1, 1))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_keep_all_rows() {
+        let mut decimator = Decimator::default();
+        let input = batch(0, 33_333, 5);
+        assert_eq!(decimator.decimate(&input).unwrap().unwrap(), input);
+        assert!(Decimator::new(0).keep(1));
+        assert!(decimator.decimate(&batch(0, 1, 0)).unwrap().is_none());
+        assert_eq!(Decimator::from_rate(0.0).interval_us(), 0);
+
+        let mut decimator = Decimator::new(1_000_000);
+        let no_timestamps = RecordBatch::try_new(
+            Arc::new(Schema::new(vec![Field::new(
+                "value",
+                DataType::Float64,
+                false,
+            )])),
+            vec![Arc::new(Float64Array::from(vec![1.0]))],
+        )
+        .unwrap();
+        assert!(decimator.decimate(&no_timestamps).is_err());
+    }
+
+    #[test]
+    fn test_decimated_sink() {
+        let collect = Collect::default();
+        let mut sink = DecimatedSink::new(Box::new(collect.clone()), Decimator::new(1_000_000));
+        sink.write_batch(&batch(0, 100_000, 25)).unwrap();
+        sink.write_batch(&batch(2_500_000, 100_000, 4)).unwrap(); // Within 2 s to 3 s
+        sink.write_batch(&batch(2_900_000, 100_000, 2)).unwrap();
+        let batches = collect.0.lock().unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(timestamps(&batches[0]), [0, 1_000_000, 2_000_000]);
+        assert_eq!(timestamps(&batches[1]), [3_000_000]);
+        // The other columns follow their rows
+        let values = batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(values.values().to_vec(), [0.0, 10.0, 20.0]);
+    }
+
+    #[test]
+    fn test_sink_rates_config() {
+        let config = PipelineConfig::from_json(
+            r#"{
+                "streams": [{"host": "127.0.0.1", "port": 4712}],
+                "sink": {"format": "parquet", "dir": "full"},
+                "sinks": [
+                    {"format": "sqlite", "dir": "history", "rate_hz": 1},
+                    {"format": "json", "dir": "live", "decimation_ms": 200}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let sinks = config.all_sinks();
+        assert_eq!(sinks.len(), 3);
+        assert_eq!(sinks[0].decimator().interval_us(), 0);
+        assert_eq!(sinks[1].format, SinkFormat::Sqlite);
+        assert_eq!(sinks[1].decimator().interval_us(), 1_000_000);
+        assert_eq!(sinks[2].decimator().interval_us(), 200_000);
+    }
+
+    #[test]
+    fn test_open_decimated_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SinkConfig {
+            format: SinkFormat::Csv,
+            dir: dir.path().to_path_buf(),
+            rate_hz: Some(2.0),
+            decimation_ms: None,
+            spool: None,
+            parquet: None,
+            names: Default::default(),
+        };
+        let mut sink = config.open(7).unwrap();
+        sink.write_batch(&batch(0, 100_000, 30)).unwrap();
+        sink.close().unwrap();
+        let csv = std::fs::read_to_string(dir.path().join("7.csv")).unwrap();
+        assert_eq!(csv.lines().count(), 1 + 6);
+    }
+}
@@ -0,0 +1,120 @@
+#![cfg(feature = "client")]
+#![allow(unused)]
+mod common;
+
+use common::{data_frame_us, read_hex_file};
+
+const SOC: u32 = 1_700_000_000;
+
+// The sample data frame n frames into SOC at 30 frames/s.
+fn nth_frame(n: u32) -> Vec<u8> {
+    let fracsec = (n % 30) * 1_000_000 / 30;
+    data_frame_us(7734, (SOC + n / 30) as i64 * 1_000_000 + fracsec as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pmu::dedup::{frame_key, FrameDeduplicator, DUPLICATES_METRIC};
+    use pmu::metrics::Metrics;
+    use pmu::pdc_client::PDCClient;
+    use pmu::queue::Rings;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::time;
+
+    #[test]
+    fn test_frame_key() {
+        let frame = nth_frame(31);
+        assert_eq!(frame_key(&frame), Some((7734, SOC + 1, 33_333)));
+        let config = read_hex_file("config_message.bin").unwrap();
+        assert_eq!(frame_key(&config), None);
+        assert_eq!(frame_key(&frame[..10]), None);
+    }
+
+    #[test]
+    fn test_duplicates_dropped() {
+        let metrics = Arc::new(Metrics::new());
+        let mut dedup = FrameDeduplicator::new(4).with_metrics(metrics.clone());
+        let passed: Vec<u32> = [0, 1, 2, 1, 2, 3, 0]
+            .into_iter()
+            .filter(|n| !dedup.is_duplicate(&nth_frame(*n), true))
+            .collect();
+        assert_eq!(passed, vec![0, 1, 2, 3]);
+        assert_eq!(dedup.duplicates(7734), 3);
+        assert_eq!(metrics.counter(DUPLICATES_METRIC, &[("stream", "7734")]), 3);
+
+        // Late but not seen before
+        assert!(!dedup.is_duplicate(&nth_frame(10), true));
+        assert!(!dedup.is_duplicate(&nth_frame(5), true));
+        assert_eq!(dedup.len(), 4);
+        // The least recently seen are forgotten first: 0 was seen again
+        // after 1 and 2, which went before it
+        assert!(!dedup.is_duplicate(&nth_frame(1), true));
+        assert!(dedup.is_duplicate(&nth_frame(5), true));
+        assert_eq!(dedup.total_duplicates(), 4);
+    }
+
+    #[test]
+    fn test_failed_chk_not_remembered() {
+        let mut dedup = FrameDeduplicator::default();
+        let frame = nth_frame(7);
+        let mut corrupted = frame.clone();
+        corrupted[20] ^= 0xFF;
+        assert!(!dedup.is_duplicate(&corrupted, false));
+        // Sent again intact
+        assert!(!dedup.is_duplicate(&frame, true));
+        assert!(dedup.is_duplicate(&frame, true));
+    }
+
+    // A PDC answering the configuration request, then sending frames 0-4 and
+    // retransmitting 2-4 as after a reconnect.
+    async fn retransmitting_pdc(listener: TcpListener) {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut command = [0u8; 18];
+        socket.read_exact(&mut command).await.unwrap();
+        let config = read_hex_file("config_message.bin").unwrap();
+        socket.write_all(&config).await.unwrap();
+        socket.read_exact(&mut command).await.unwrap();
+        for n in [0, 1, 2, 3, 4, 2, 3, 4, 5] {
+            socket.write_all(&nth_frame(n)).await.unwrap();
+        }
+        time::sleep(Duration::from_secs(5)).await;
+    }
+
+    #[tokio::test]
+    async fn test_client_drops_retransmitted_frames() {
+        let listener = TcpListener::bind("127.0.0.1:4756").await.unwrap();
+        let server = tokio::spawn(retransmitting_pdc(listener));
+
+        let (client, _, _) = PDCClient::new("127.0.0.1", 4756, 1, Duration::from_secs(10))
+            .await
+            .unwrap();
+        let mut rings = Rings::new();
+        let mut client = client
+            .with_frame_queue(rings.add(64))
+            .with_dedup(FrameDeduplicator::default());
+        let stats = client.stats_receiver();
+        let control = client.get_control_sender();
+        let handle = tokio::spawn(async move { client.start_stream().await });
+
+        let mut received = Vec::new();
+        while received.len() < 6 {
+            let frame = time::timeout(Duration::from_secs(3), rings.pop())
+                .await
+                .unwrap()
+                .unwrap();
+            received.push(frame_key(&frame).unwrap().2);
+        }
+        let fracsec = |n: u32| n * 1_000_000 / 30;
+        assert_eq!(received, (0..6).map(fracsec).collect::<Vec<_>>());
+        let stats = *stats.borrow();
+        assert_eq!((stats.data_frames, stats.duplicates), (9, 3));
+
+        let _ = control.send(pmu::pdc_client::ControlMessage::Stop).await;
+        handle.abort();
+        server.abort();
+    }
+}
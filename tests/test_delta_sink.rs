@@ -0,0 +1,114 @@
+#![cfg(feature = "delta")]
+#![allow(unused)]
+mod common;
+
+use common::frequency_batch;
+
+#[cfg(test)]
+mod tests {
+    use super::frequency_batch;
+    use pmu::sinks::delta::DeltaSink;
+    use pmu::sinks::BatchSink;
+    use serde_json::Value;
+    use std::fs;
+
+    fn log_actions(dir: &std::path::Path, version: i64) -> Vec<Value> {
+        let path = dir.join("_delta_log").join(format!("{:020}.json", version));
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_each_batch_is_a_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = DeltaSink::open(dir.path()).unwrap();
+        assert_eq!(sink.version(), None);
+
+        sink.write_batch(&frequency_batch(false)).unwrap();
+        sink.write_batch(&frequency_batch(false)).unwrap();
+        assert_eq!(sink.version(), Some(1));
+
+        let first = log_actions(dir.path(), 0);
+        assert!(first[0].get("protocol").is_some());
+        let schema = first[1]["metaData"]["schemaString"].as_str().unwrap();
+        assert!(schema.contains("\"timestamp\""));
+
+        // Same schema, the second commit only adds a file.
+        let second = log_actions(dir.path(), 1);
+        assert!(second[0].get("add").is_some());
+        let path = second[0]["add"]["path"].as_str().unwrap();
+        assert!(dir.path().join(path).exists());
+    }
+
+    #[test]
+    fn test_reopen_appends_and_updates_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = DeltaSink::open(dir.path()).unwrap();
+        sink.write_batch(&frequency_batch(false)).unwrap();
+        drop(sink);
+
+        let mut sink = DeltaSink::open(dir.path()).unwrap();
+        assert_eq!(sink.version(), Some(0));
+        sink.write_batch(&frequency_batch(true)).unwrap();
+
+        let actions = log_actions(dir.path(), 1);
+        let metadata = &actions[0]["metaData"];
+        assert_eq!(
+            metadata["id"],
+            log_actions(dir.path(), 0)[1]["metaData"]["id"]
+        );
+        assert!(metadata["schemaString"]
+            .as_str()
+            .unwrap()
+            .contains("\"integer\""));
+    }
+
+    #[test]
+    fn test_concurrent_writers() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut first = DeltaSink::open(dir.path()).unwrap();
+        let mut second = DeltaSink::open(dir.path()).unwrap();
+        first.write_batch(&frequency_batch(false)).unwrap();
+
+        // Version 0 is taken, the second writer only adds its file on top.
+        second.write_batch(&frequency_batch(false)).unwrap();
+        assert_eq!(second.version(), Some(1));
+        let actions = log_actions(dir.path(), 1);
+        assert_eq!(actions.len(), 1);
+        assert!(actions[0].get("add").is_some());
+
+        // The first writer is behind the table: its schema change fails, and
+        // goes through once it has taken up the second writer's version.
+        let error = first.write_batch(&frequency_batch(true)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let log_entry = |version: i64| {
+            dir.path()
+                .join("_delta_log")
+                .join(format!("{:020}.json", version))
+        };
+        assert!(!log_entry(2).exists());
+        first.write_batch(&frequency_batch(true)).unwrap();
+        assert!(log_actions(dir.path(), 2)[0].get("metaData").is_some());
+
+        // Now the second writer's batch no longer matches the table.
+        let error = second.write_batch(&frequency_batch(false)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(!log_entry(3).exists());
+
+        // Entries are claimed whole, no temporary files are left behind.
+        let mut names: Vec<String> = fs::read_dir(dir.path().join("_delta_log"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            (0..3)
+                .map(|v| format!("{:020}.json", v))
+                .collect::<Vec<_>>()
+        );
+    }
+}
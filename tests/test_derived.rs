@@ -0,0 +1,261 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+mod common;
+
+use arrow::array::{Array, ArrayRef, AsArray, Float64Array};
+use arrow::datatypes::{DataType, Field, Float64Type, Schema};
+use arrow::record_batch::RecordBatch;
+use common::sample_batch;
+use pmu::arrow_utils::{channel_values, META_CHANNEL, META_EXPRESSION, META_STATION, META_UNIT};
+use pmu::derived::{DerivedChannel, DerivedChannels, Expr, Function, Operator, WeightedMean};
+use pmu::pipeline::PipelineConfig;
+use std::sync::Arc;
+
+fn column(batch: &RecordBatch, name: &str) -> Float64Array {
+    batch
+        .column_by_name(name)
+        .unwrap_or_else(|| panic!("no column {}", name))
+        .as_primitive::<Float64Type>()
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_expressions() {
+        let channel = |name: &str| Box::new(Expr::Channel(name.to_string()));
+        assert_eq!(
+            Expr::parse("a + b * 2").unwrap(),
+            Expr::Binary(
+                Operator::Add,
+                channel("a"),
+                Box::new(Expr::Binary(
+                    Operator::Multiply,
+                    channel("b"),
+                    Box::new(Expr::Number(2.0))
+                ))
+            )
+        );
+        assert_eq!(
+            Expr::parse("-(a - b) / 1e3").unwrap(),
+            Expr::Binary(
+                Operator::Divide,
+                Box::new(Expr::Negate(Box::new(Expr::Binary(
+                    Operator::Subtract,
+                    channel("a"),
+                    channel("b")
+                )))),
+                Box::new(Expr::Number(1000.0))
+            )
+        );
+        assert_eq!(
+            Expr::parse("ABS(`Station A_7734_VA`)").unwrap(),
+            Expr::Call(
+                Function::Abs,
+                vec![Expr::Channel("Station A_7734_VA".into())]
+            )
+        );
+
+        let expr = Expr::parse(r#"max(VA_MAG - "VB MAG", VA_MAG) / VN + VA_MAG"#).unwrap();
+        assert_eq!(expr.channels(), ["VA_MAG", "VB MAG", "VN"]);
+
+        for invalid in [
+            "",
+            "a +",
+            "(a",
+            "a b",
+            "log(a)",
+            "min(a)",
+            "abs(a, b)",
+            "`a",
+            "a % b",
+            "1.2.3",
+        ] {
+            assert!(Expr::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_derive_from_sample_frame() {
+        let derived = DerivedChannels::new(vec![
+            DerivedChannel::new("freq_dev", "FREQ - 60").with_unit("Hz"),
+            DerivedChannel::new("dev_mhz", "freq_dev * 1000"),
+            DerivedChannel::new("unbalance", "(max(VA, VB) - min(VA, VB)) / VC"),
+            DerivedChannel::new("VA_kV", "`Station A_7734_VA` / 1000"),
+            DerivedChannel::new("missing", "VX + 1"),
+        ])
+        .unwrap();
+        let batch = sample_batch();
+        let output = derived.apply(&batch).unwrap();
+        assert_eq!(output.num_columns(), batch.num_columns() + 4);
+        assert!(output.column_by_name("missing").is_none());
+
+        let freq_dev = column(&output, "Station A_7734_freq_dev");
+        assert!((freq_dev.value(0) - 2.5).abs() < 1e-9);
+        let dev_mhz = column(&output, "Station A_7734_dev_mhz");
+        assert!((dev_mhz.value(0) - 2500.0).abs() < 1e-6);
+        let unbalance = column(&output, "Station A_7734_unbalance").value(0);
+        assert!((0.0..1e-3).contains(&unbalance), "{}", unbalance);
+
+        let va = channel_values(&batch, "Station A_7734_VA")
+            .unwrap()
+            .value(0);
+        let va_kv = column(&output, "Station A_7734_VA_kV").value(0);
+        assert!((va_kv - va / 1000.0).abs() < 1e-9);
+        assert!((va - 133_987.4).abs() < 1.0);
+
+        let schema = output.schema();
+        let field = schema.field_with_name("Station A_7734_freq_dev").unwrap();
+        assert_eq!(field.metadata()[META_STATION], "Station A");
+        assert_eq!(field.metadata()[META_CHANNEL], "Station A_7734_freq_dev");
+        assert_eq!(field.metadata()[META_EXPRESSION], "FREQ - 60");
+        assert_eq!(field.metadata()[META_UNIT], "Hz");
+    }
+
+    #[test]
+    fn test_nulls_and_scalars() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Float64, true),
+            Field::new("b", DataType::Float64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Float64Array::from(vec![Some(1.0), None, Some(4.0)])) as ArrayRef,
+                Arc::new(Float64Array::from(vec![Some(-3.0), Some(2.0), Some(0.0)])),
+            ],
+        )
+        .unwrap();
+        let derived = DerivedChannels::new(vec![
+            DerivedChannel::new("c", "min(a, 2) + max(b, -1) * -2"),
+            DerivedChannel::new("d", "sqrt(abs(b)) / a"),
+            DerivedChannel::new("e", "a / b"),
+            DerivedChannel::new("f", "2 * 3"),
+        ])
+        .unwrap();
+        let output = derived.apply(&batch).unwrap();
+
+        // Channels of no station keep the name as given
+        let c = column(&output, "c");
+        assert_eq!(c.value(0), 3.0);
+        assert!(c.is_null(1));
+        assert_eq!(c.value(2), 2.0);
+        assert!((column(&output, "d").value(0) - 3f64.sqrt()).abs() < 1e-12);
+        assert_eq!(column(&output, "e").value(2), f64::INFINITY);
+        assert_eq!(column(&output, "f").values().to_vec(), [6.0, 6.0, 6.0]);
+    }
+
+    #[test]
+    fn test_weighted_area_frequency() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("F1", DataType::Float64, true),
+            Field::new("F2", DataType::Float64, true),
+            Field::new("F3", DataType::Float64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Float64Array::from(vec![Some(60.0), Some(60.0), None, None])) as ArrayRef,
+                Arc::new(Float64Array::from(vec![
+                    Some(60.03),
+                    Some(60.02),
+                    Some(60.1),
+                    None,
+                ])),
+                Arc::new(Float64Array::from(vec![
+                    Some(60.0),
+                    Some(0.0),
+                    Some(f64::NAN),
+                    Some(59.9),
+                ])),
+            ],
+        )
+        .unwrap();
+        let mean = WeightedMean::new(&[("F1", 2.0), ("F2", 1.0), ("F3", 1.0), ("F9", 5.0)])
+            .with_max_deviation(0.05);
+        let derived = DerivedChannels::new(vec![
+            DerivedChannel::weighted_mean("area_freq", mean.clone()).with_unit("Hz"),
+            DerivedChannel::weighted_mean("strict", mean.with_min_inputs(2)),
+        ])
+        .unwrap();
+        let output = derived.apply(&batch).unwrap();
+
+        let area = column(&output, "area_freq");
+        assert!((area.value(0) - (2.0 * 60.0 + 60.03 + 60.0) / 4.0).abs() < 1e-9);
+        // The dropout reading 0 is rejected as an outlier
+        assert!((area.value(1) - (2.0 * 60.0 + 60.02) / 3.0).abs() < 1e-9);
+        // NaN and nulls are not inputs
+        assert_eq!(area.value(2), 60.1);
+        assert_eq!(area.value(3), 59.9);
+        let strict = column(&output, "strict");
+        assert!(strict.is_valid(1));
+        assert!(strict.is_null(2));
+        assert!(strict.is_null(3));
+
+        let schema = output.schema();
+        let field = schema.field_with_name("area_freq").unwrap();
+        assert_eq!(
+            field.metadata()[META_EXPRESSION],
+            "weighted_mean(F1 * 2, F2 * 1, F3 * 1, F9 * 5)"
+        );
+        assert_eq!(field.metadata()[META_UNIT], "Hz");
+
+        // Not enough inputs in the batch
+        let derived = DerivedChannels::new(vec![DerivedChannel::weighted_mean(
+            "elsewhere",
+            WeightedMean::new(&[("F1", 1.0), ("F8", 1.0), ("F9", 1.0)]).with_min_inputs(2),
+        )])
+        .unwrap();
+        assert!(derived
+            .apply(&batch)
+            .unwrap()
+            .column_by_name("elsewhere")
+            .is_none());
+    }
+
+    #[test]
+    fn test_derived_channel_config() {
+        let config = PipelineConfig::from_json(
+            r#"{
+                "streams": [{"host": "127.0.0.1", "port": 4712}],
+                "sink": {"dir": "out"},
+                "derived": [
+                    {"name": "VA_abs", "expression": "abs(VA)", "unit": "V"},
+                    {"name": "ratio", "expression": "(VA_MAG - VB_MAG)/VN"},
+                    {"name": "area_freq", "unit": "Hz", "weighted_mean": {
+                        "weights": {"SUB_A_1_FREQ": 3.5, "SUB_B_2_FREQ": 1.0},
+                        "max_deviation": 0.1
+                    }}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.derived[0],
+            DerivedChannel::new("VA_abs", "abs(VA)").with_unit("V")
+        );
+        assert_eq!(config.derived[1].unit, None);
+        assert_eq!(
+            config.derived[2],
+            DerivedChannel::weighted_mean(
+                "area_freq",
+                WeightedMean::new(&[("SUB_A_1_FREQ", 3.5), ("SUB_B_2_FREQ", 1.0)])
+                    .with_max_deviation(0.1)
+            )
+            .with_unit("Hz")
+        );
+        assert!(DerivedChannels::new(config.derived).is_ok());
+
+        let duplicate = vec![DerivedChannel::new("x", "a"), DerivedChannel::new("x", "b")];
+        assert!(DerivedChannels::new(duplicate).is_err());
+        assert!(DerivedChannels::new(vec![DerivedChannel::new("y", "a +")]).is_err());
+        assert!(DerivedChannels::new(vec![DerivedChannel::new("", "a")]).is_err());
+        let mut both = DerivedChannel::weighted_mean("z", WeightedMean::new(&[("a", 1.0)]));
+        both.expression = "a".to_string();
+        assert!(DerivedChannels::new(vec![both]).is_err());
+        let empty = DerivedChannel::weighted_mean("z", WeightedMean::new(&[]));
+        assert!(DerivedChannels::new(vec![empty]).is_err());
+    }
+}
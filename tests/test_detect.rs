@@ -0,0 +1,95 @@
+#![cfg(feature = "sinks")]
+#![allow(unused)]
+mod common;
+
+use common::{data_frame_us, read_hex_file};
+use pmu::detect::{detect, Encoding, RateSource};
+
+const START_US: i64 = 1_700_000_000_000_000;
+
+// Data frames at rate frames per second for the given seconds.
+fn data_frames(rate: i64, seconds: f64) -> Vec<u8> {
+    let count = (rate as f64 * seconds) as i64;
+    (0..count)
+        .flat_map(|n| data_frame_us(7734, START_US + n * 1_000_000 / rate))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_with_configuration() {
+        let mut bytes = b"garbage".to_vec();
+        bytes.extend(read_hex_file("config_message.bin").unwrap());
+        bytes.extend(data_frames(30, 2.0));
+        let detection = detect(&bytes);
+        assert_eq!(detection.encoding, Encoding::Binary);
+        assert_eq!(detection.offset, Some(7));
+        assert_eq!(detection.version, Some(1));
+        assert_eq!(detection.protocol(), "IEEE C37.118-2005");
+        assert_eq!(detection.frames, 61);
+        assert_eq!(detection.frame_types["data"], 60);
+        assert_eq!(detection.frame_types["configuration 2"], 1);
+        assert_eq!(detection.idcodes, [7734]);
+        assert_eq!(detection.data_frame_size, Some(52));
+        assert_eq!(detection.time_base, Some(1_000_000));
+        assert_eq!(detection.data_rate, Some(30.0));
+        assert_eq!(detection.rate_source, Some(RateSource::Config));
+        assert_eq!(detection.crc_failures, 0);
+        assert!((detection.coverage - (bytes.len() - 7) as f64 / bytes.len() as f64).abs() < 1e-12);
+
+        let report = detection.to_string();
+        assert!(report.contains("First frame at byte 7"), "{}", report);
+        assert!(report.contains("30 frames/s (configuration)"), "{}", report);
+    }
+
+    #[test]
+    fn test_detect_rate_from_timestamps() {
+        // Partial first and last seconds do not count
+        let mut bytes = data_frame_us(7734, START_US - 100_000);
+        bytes.extend(data_frames(50, 3.5));
+        let detection = detect(&bytes);
+        assert_eq!(detection.offset, Some(0));
+        assert_eq!(detection.data_rate, Some(50.0));
+        assert_eq!(detection.rate_source, Some(RateSource::Timestamps));
+        assert_eq!(detection.first_soc, Some((START_US / 1_000_000 - 1) as u32));
+        assert_eq!(detection.last_soc, Some((START_US / 1_000_000 + 3) as u32));
+
+        // The same as hex text
+        let hex: String = bytes
+            .chunks(16)
+            .map(|line| {
+                line.iter()
+                    .map(|b| format!("{:02X} ", b))
+                    .collect::<String>()
+                    + "\n"
+            })
+            .collect();
+        let from_hex = detect(hex.as_bytes());
+        assert_eq!(from_hex.encoding, Encoding::HexText);
+        assert_eq!(from_hex.frames, detection.frames);
+        assert_eq!(from_hex.data_rate, Some(50.0));
+    }
+
+    #[test]
+    fn test_detect_bad_checksums() {
+        let mut bytes = Vec::new();
+        for n in 0..5 {
+            let mut frame = data_frame_us(7734, START_US + n * 33_333);
+            let len = frame.len();
+            frame[len - 2..].copy_from_slice(&[0, 0]);
+            bytes.extend(frame);
+        }
+        let detection = detect(&bytes);
+        assert_eq!(detection.offset, None);
+        assert_eq!(detection.frames, 0);
+        assert_eq!(detection.crc_failures, 5);
+        assert!(detection.to_string().contains("No C37.118 frame found"));
+
+        let detection = detect(b"");
+        assert_eq!(detection.frames, 0);
+        assert_eq!(detection.data_rate, None);
+    }
+}
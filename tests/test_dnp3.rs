@@ -0,0 +1,229 @@
+#![cfg(feature = "dnp3")]
+#![allow(unused)]
+mod common;
+
+use common::repeated_batch;
+
+#[cfg(test)]
+mod tests {
+    use super::repeated_batch;
+    use pmu::dnp3::*;
+    use pmu::sinks::BatchSink;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::watch;
+
+    const MASTER: u16 = 1;
+    const OUTSTATION: u16 = 10;
+
+    // Test master: sends requests and reads back link frames.
+    struct Master {
+        socket: TcpStream,
+        buffer: Vec<u8>,
+        sequence: u8,
+    }
+
+    impl Master {
+        async fn connect(address: std::net::SocketAddr) -> Self {
+            Master {
+                socket: TcpStream::connect(address).await.unwrap(),
+                buffer: Vec::new(),
+                sequence: 0,
+            }
+        }
+
+        async fn send(&mut self, control: u8, data: Vec<u8>) {
+            let frame = LinkFrame {
+                control,
+                destination: OUTSTATION,
+                source: MASTER,
+                data,
+            };
+            self.socket.write_all(&frame.encode()).await.unwrap();
+        }
+
+        async fn receive(&mut self) -> LinkFrame {
+            loop {
+                if let Some((frame, length)) = LinkFrame::decode(&self.buffer).unwrap() {
+                    self.buffer.drain(..length);
+                    return frame;
+                }
+                let mut read = [0u8; 512];
+                let n = self.socket.read(&mut read).await.unwrap();
+                assert!(n > 0, "Outstation closed the connection");
+                self.buffer.extend_from_slice(&read[..n]);
+            }
+        }
+
+        // Application request, returns the response fragment.
+        async fn request(&mut self, function: u8, objects: &[u8]) -> Vec<u8> {
+            let mut data = vec![0xC0 | self.sequence, 0xC0 | self.sequence, function];
+            data.extend_from_slice(objects);
+            self.sequence = (self.sequence + 1) & 0x0F;
+            self.send(0xC4, data).await; // Unconfirmed user data
+            let mut fragment = Vec::new();
+            loop {
+                let frame = self.receive().await;
+                assert_eq!(frame.control, 0x44);
+                assert_eq!((frame.destination, frame.source), (MASTER, OUTSTATION));
+                fragment.extend_from_slice(&frame.data[1..]);
+                if frame.data[0] & 0x80 != 0 {
+                    return fragment;
+                }
+            }
+        }
+    }
+
+    fn config() -> Dnp3Config {
+        Dnp3Config::new(vec![
+            AnalogPoint::new(0, "Station A_7734_FREQ"),
+            AnalogPoint::new(1, "Station A_7734_VA"),
+            AnalogPoint::new(2, "Station A_7734_VB").with_variation(2),
+            AnalogPoint::new(5, "Not there"),
+        ])
+    }
+
+    fn f32_at(data: &[u8], offset: usize) -> f32 {
+        f32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_crc_and_frames() {
+        // Check value of CRC-16/DNP
+        assert_eq!(crc(b"123456789"), 0xEA82);
+        let frame = LinkFrame {
+            control: 0xC4,
+            destination: 10,
+            source: 1,
+            data: (0..40).collect(),
+        };
+        let encoded = frame.encode();
+        // Header and three blocks with their CRCs
+        assert_eq!(encoded.len(), 10 + 40 + 6);
+        assert_eq!(encoded[2], 45);
+        assert_eq!(LinkFrame::decode(&encoded[..30]).unwrap(), None);
+        let (decoded, length) = LinkFrame::decode(&encoded).unwrap().unwrap();
+        assert_eq!((decoded, length), (frame, encoded.len()));
+        let mut corrupt = encoded.clone();
+        corrupt[20] ^= 1;
+        assert!(LinkFrame::decode(&corrupt).is_err());
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let config = Dnp3Config::from_json(
+            r#"{"outstation": 4, "points": [{"index": 0, "channel": "A_1_FREQ"}], "decimation_ms": 1000}"#,
+        )
+        .unwrap();
+        assert_eq!(config.outstation, 4);
+        assert_eq!(config.points[0].variation, 5);
+        assert!(Dnp3Config::from_json(
+            r#"{"points": [{"index": 0, "channel": "A"}, {"index": 0, "channel": "B"}]}"#
+        )
+        .is_err());
+        assert!(Dnp3Config::from_json(
+            r#"{"points": [{"index": 0, "channel": "A", "variation": 7}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_gateway_decimation() {
+        let points = AnalogPoints::new(&config().points);
+        let mut gateway = Dnp3Gateway::new(points.clone()).with_decimation(1_000_000);
+        assert_eq!(points.get(0), Some((0.0, FLAG_RESTART)));
+        gateway.write_batch(&repeated_batch(3)).unwrap();
+        let (frequency, flags) = points.get(0).unwrap();
+        // FREQ of the sample frame is 60 Hz + 2500 mHz
+        assert_eq!((frequency, flags), (62.5, FLAG_ONLINE));
+        assert!(points.get(1).unwrap().0 > 0.0);
+        // Not in the batch
+        assert_eq!(points.get(5), Some((0.0, FLAG_RESTART)));
+
+        // The same second again is left out
+        points.set(0, 50.0);
+        gateway.write_batch(&repeated_batch(1)).unwrap();
+        assert_eq!(points.get(0).unwrap().0, 50.0);
+    }
+
+    #[tokio::test]
+    async fn test_master_polls_analog_inputs() {
+        let outstation = Dnp3Outstation::bind("127.0.0.1:0", config()).await.unwrap();
+        let address = outstation.local_addr().unwrap();
+        let mut gateway = outstation.gateway();
+        gateway.write_batch(&repeated_batch(1)).unwrap();
+        let (stop, stopped) = watch::channel(false);
+        let server = tokio::spawn(outstation.run(stopped));
+
+        let mut master = Master::connect(address).await;
+        master.send(0xC0, Vec::new()).await; // Reset link
+        let ack = master.receive().await;
+        assert_eq!((ack.control, ack.destination), (0x00, MASTER));
+        master.send(0xC9, Vec::new()).await; // Request link status
+        assert_eq!(master.receive().await.control, 11);
+
+        // Class 0: points 0-1 as floats, 2 as 16 bit, 5 as float
+        let response = master.request(1, &[60, 1, 0x06]).await;
+        assert_eq!(response[1], 0x81);
+        assert_eq!(response[2], IIN1_DEVICE_RESTART);
+        assert_eq!(response[3], 0);
+        assert_eq!(response[4..11], [30, 5, 0x01, 0, 0, 1, 0]);
+        assert_eq!(response[11], FLAG_ONLINE);
+        assert_eq!(f32_at(&response, 12), 62.5);
+        assert_eq!(response[16], FLAG_ONLINE);
+        let magnitude = f32_at(&response, 17);
+        assert!(magnitude > 0.0);
+        assert_eq!(response[21..28], [30, 2, 0x01, 2, 0, 2, 0]);
+        // VB is beyond 16 bits
+        assert_eq!(response[28], FLAG_ONLINE | FLAG_OVER_RANGE);
+        assert_eq!(i16::from_le_bytes([response[29], response[30]]), i16::MAX);
+        assert_eq!(response[31..38], [30, 5, 0x01, 5, 0, 5, 0]);
+        assert_eq!(response[38], FLAG_RESTART);
+        assert_eq!(response.len(), 43);
+
+        // Clear the restart bit
+        let response = master.request(2, &[80, 1, 0x00, 7, 7, 0]).await;
+        assert_eq!(response[2..4], [0, 0]);
+
+        // Points 0-1 as 32 bit integers
+        let response = master.request(1, &[30, 1, 0x00, 0, 1]).await;
+        assert_eq!(response[2..4], [0, 0]);
+        assert_eq!(response[4..11], [30, 1, 0x01, 0, 0, 1, 0]);
+        assert_eq!(response[11], FLAG_ONLINE);
+        assert_eq!(i32::from_le_bytes(response[12..16].try_into().unwrap()), 63);
+        assert_eq!(
+            i32::from_le_bytes(response[17..21].try_into().unwrap()),
+            magnitude.round() as i32
+        );
+
+        // Binary inputs and cold restart are not there
+        let response = master.request(1, &[1, 2, 0x06]).await;
+        assert_eq!(response[3], IIN2_OBJECT_UNKNOWN);
+        assert_eq!(response.len(), 4);
+        let response = master.request(13, &[]).await;
+        assert_eq!(response[3], IIN2_NO_FUNCTION);
+
+        stop.send(true).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_other_outstations_are_ignored() {
+        let mut config = config();
+        config.master = Some(3);
+        let outstation = Dnp3Outstation::bind("127.0.0.1:0", config).await.unwrap();
+        let address = outstation.local_addr().unwrap();
+        let (stop, stopped) = watch::channel(false);
+        let server = tokio::spawn(outstation.run(stopped));
+
+        // From master 1, which is not the configured master
+        let mut master = Master::connect(address).await;
+        master.send(0xC9, Vec::new()).await;
+        let reply =
+            tokio::time::timeout(std::time::Duration::from_millis(200), master.receive()).await;
+        assert!(reply.is_err());
+
+        stop.send(true).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+#![allow(unused)]
+mod common;
+
+use common::read_hex_file;
+use pmu::dump::{dissect, dump, dump_all};
+use pmu::frame_parser::{parse_command_frame, parse_config_frame_1and2, parse_data_frames};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dissect_data_frame() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let data = read_hex_file("data_message.bin").unwrap();
+        let fields = dissect(&data, Some(&config));
+
+        assert_eq!(fields[0].name, "SYNC");
+        assert_eq!(fields[0].offset, 0);
+        assert_eq!(fields[0].length, 2);
+        assert_eq!(fields[2].name, "IDCODE");
+        assert_eq!(fields[2].value, "7734");
+
+        let freq = fields.iter().find(|f| f.name == "FREQ").unwrap();
+        assert_eq!(freq.offset, 0x20);
+        assert!(freq.value.contains("62.5000 Hz"));
+        assert!(freq.value.contains("+2500 mHz"));
+
+        let heading = fields.iter().find(|f| f.length == 0).unwrap();
+        assert!(heading.name.contains("Station A"));
+
+        let chk = fields.last().unwrap();
+        assert_eq!(chk.name, "CHK");
+        assert_eq!(chk.offset, data.len() - 2);
+        assert!(chk.value.ends_with("(ok)"));
+    }
+
+    #[test]
+    fn test_dissect_polar_data_frame() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_polar_message.bin").unwrap()).unwrap();
+        let mut data = read_hex_file("data_polar_message.bin").unwrap();
+        let fields = dissect(&data, Some(&config));
+        let vb = fields.iter().find(|f| f.name == "VB").unwrap();
+        assert_eq!(vb.value, "133987.376 V, -120.00 deg");
+
+        // Magnitudes are unsigned
+        data[16..18].copy_from_slice(&50_000u16.to_be_bytes());
+        let fields = dissect(&data, Some(&config));
+        let va = fields.iter().find(|f| f.name == "VA").unwrap();
+        assert_eq!(va.value, "457763.500 V, 0.00 deg");
+    }
+
+    #[test]
+    fn test_dissect_float_data_frame() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_float_message.bin").unwrap()).unwrap();
+        let data = read_hex_file("data_float_message.bin").unwrap();
+        let fields = dissect(&data, Some(&config));
+        let vb = fields.iter().find(|f| f.name == "VB").unwrap();
+        assert_eq!(vb.length, 8);
+        assert_eq!(
+            vb.value,
+            "-66998.266 -116052.203j V = 134003.289 V, -120.00 deg"
+        );
+        let freq = fields.iter().find(|f| f.name == "FREQ").unwrap();
+        assert_eq!((freq.offset, freq.length), (0x30, 4));
+        assert_eq!(freq.value, "62.5000 Hz");
+        let dfreq = fields.iter().find(|f| f.name == "DFREQ").unwrap();
+        assert_eq!(dfreq.value, "0.0000 Hz/s");
+        let analog = fields.iter().find(|f| f.name == "ANALOG2").unwrap();
+        assert_eq!(analog.value, "1000");
+        assert!(fields.last().unwrap().value.ends_with("(ok)"));
+    }
+
+    #[test]
+    fn test_dissect_bad_checksum() {
+        let mut data = read_hex_file("cmd_message.bin").unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xFF;
+        let chk = dissect(&data, None).pop().unwrap();
+        assert!(chk.value.contains("expected"));
+    }
+
+    #[test]
+    fn test_dissect_truncated() {
+        let config = read_hex_file("config_message.bin").unwrap();
+        let fields = dissect(&config[..30], None);
+        assert!(fields.iter().any(|f| f.value.contains("(truncated)")));
+    }
+
+    #[test]
+    fn test_data_frame_without_config() {
+        let data = read_hex_file("data_message.bin").unwrap();
+        let text = dump(&data, None);
+        assert!(text.contains("configuration needed"));
+        assert!(text.contains("CHK"));
+    }
+
+    #[test]
+    fn test_dump_layout() {
+        let cmd = read_hex_file("cmd_message.bin").unwrap();
+        let text = dump(&cmd, None);
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].starts_with("0000  AA 41"));
+        assert!(lines[0].contains("SYNC"));
+        assert!(text.contains("turn_on_transmission"));
+    }
+
+    #[test]
+    fn test_display_and_pretty_print() {
+        let config_bytes = read_hex_file("config_message.bin").unwrap();
+        let config = parse_config_frame_1and2(&config_bytes).unwrap();
+        assert!(config.prefix.to_string().contains("IDCODE 7734"));
+
+        let printed = config.pretty_print();
+        assert!(printed.contains("\"Station A\""));
+        assert!(printed.contains("30 frames/s"));
+
+        let data = parse_data_frames(&read_hex_file("data_message.bin").unwrap(), &config).unwrap();
+        assert!(data.pretty_print(&config).contains("BREAKER 1 STATUS"));
+
+        let command = parse_command_frame(&read_hex_file("cmd_message.bin").unwrap()).unwrap();
+        assert!(command.to_string().contains("turn_on_transmission"));
+    }
+
+    #[test]
+    fn test_dump_all_uses_config_from_capture() {
+        let mut capture = read_hex_file("config_message.bin").unwrap();
+        capture.extend(read_hex_file("data_message.bin").unwrap());
+        capture.extend(read_hex_file("cmd_message.bin").unwrap());
+
+        let text = dump_all(&capture, None);
+        assert!(text.contains("Frame at 0x1C6, 52 bytes"));
+        assert!(text.contains("62.5000 Hz"));
+        assert!(!text.contains("configuration needed"));
+        assert_eq!(text.matches("Frame at").count(), 3);
+    }
+}
@@ -0,0 +1,114 @@
+#![cfg(any(feature = "sinks", feature = "client"))]
+#![allow(unused)]
+
+#[cfg(test)]
+mod tests {
+    use pmu::events::{Event, EventBus, EventKind, Maintenance, MaintenanceWindow, Severity};
+    use tokio::sync::broadcast::error::RecvError;
+
+    fn trip(timestamp_us: i64) -> Event {
+        Event::new(
+            timestamp_us,
+            EventKind::GeneratorTrip,
+            "A",
+            "trip".to_string(),
+        )
+        .with_value("size_mw", 100.0)
+    }
+
+    #[tokio::test]
+    async fn test_every_subscriber_receives_events() {
+        let bus = EventBus::new(8);
+        assert_eq!(bus.publish(trip(0)), 0);
+
+        let mut a = bus.subscribe();
+        let mut b = bus.subscribe();
+        assert_eq!(bus.publish(trip(1)), 2);
+        assert_eq!(a.recv().await.unwrap().timestamp_us, 1);
+        assert_eq!(b.recv().await.unwrap().timestamp_us, 1);
+    }
+
+    #[tokio::test]
+    async fn test_lagging_subscriber_loses_oldest() {
+        let bus = EventBus::new(2);
+        let mut slow = bus.subscribe();
+        for t in 0..4 {
+            bus.publish(trip(t));
+        }
+        assert!(matches!(slow.recv().await, Err(RecvError::Lagged(2))));
+        assert_eq!(slow.recv().await.unwrap().timestamp_us, 2);
+    }
+
+    #[test]
+    fn test_event_json() {
+        let event = trip(5).with_severity(Severity::Alarm);
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["kind"], "generator_trip");
+        assert_eq!(json["severity"], "alarm");
+        assert_eq!(json["values"]["size_mw"], 100.0);
+    }
+
+    #[test]
+    fn test_maintenance_window_suppresses_alarms() {
+        let maintenance = Maintenance::new(vec![MaintenanceWindow {
+            station: "A".to_string(),
+            start_us: 10,
+            end_us: 20,
+        }]);
+        let bus = EventBus::new(8).with_maintenance(maintenance.clone());
+        let mut events = bus.subscribe();
+
+        assert_eq!(bus.publish(trip(5).with_severity(Severity::Alarm)), 1);
+        assert_eq!(bus.publish(trip(10).with_severity(Severity::Alarm)), 0);
+        // Channels of the station are covered, other stations and info events are not
+        let mut channel = trip(15).with_severity(Severity::Warning);
+        channel.source = "A_7734_FREQ".to_string();
+        assert_eq!(bus.publish(channel), 0);
+        let mut other = trip(15).with_severity(Severity::Alarm);
+        other.source = "AB".to_string();
+        assert_eq!(bus.publish(other), 1);
+        assert_eq!(bus.publish(trip(16)), 1);
+        assert_eq!(bus.publish(trip(20).with_severity(Severity::Alarm)), 1);
+
+        let received: Vec<i64> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| event.timestamp_us)
+            .collect();
+        assert_eq!(received, vec![5, 15, 16, 20]);
+
+        let suppressed = maintenance.suppressed();
+        assert_eq!(suppressed.len(), 1);
+        assert_eq!(suppressed["A"].count, 2);
+        assert_eq!(suppressed["A"].by_kind["generator_trip"], 2);
+        assert_eq!(
+            (suppressed["A"].first_us, suppressed["A"].last_us),
+            (Some(10), Some(15))
+        );
+    }
+
+    #[test]
+    fn test_maintenance_live_toggle() {
+        let maintenance = Maintenance::new(vec![MaintenanceWindow {
+            station: "A".to_string(),
+            start_us: 0,
+            end_us: 100,
+        }]);
+        let bus = EventBus::new(8).with_maintenance(maintenance.clone());
+        let _events = bus.subscribe();
+        let alarm = |t| trip(t).with_severity(Severity::Alarm);
+
+        // The toggle wins over the window both ways
+        maintenance.set("A", false);
+        assert_eq!(bus.publish(alarm(50)), 1);
+        maintenance.set("A", true);
+        assert!(maintenance.is_active("A", 500));
+        assert_eq!(bus.publish(alarm(500)), 0);
+        maintenance.set("A", false);
+        assert_eq!(bus.publish(alarm(501)), 1);
+        maintenance.clear("A");
+        assert_eq!(bus.publish(alarm(60)), 0);
+
+        let suppressed = maintenance.take_suppressed();
+        assert_eq!(suppressed["A"].count, 2);
+        assert!(maintenance.suppressed().is_empty());
+    }
+}
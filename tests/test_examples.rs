@@ -0,0 +1,93 @@
+#![cfg(feature = "cli")]
+#![allow(unused)]
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use std::fs;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time;
+
+// Parquet files under a directory.
+fn parquet_files(dir: &Path) -> Vec<String> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).unwrap().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            files.extend(parquet_files(&path));
+        } else if path.extension().is_some_and(|ext| ext == "parquet") {
+            files.push(path.display().to_string());
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_collect_simulated_pmus() {
+        let mut sim = Command::new(env!("CARGO_BIN_EXE_pmu-sim"))
+            .args(["--pmus", "3", "--rate", "30", "--port", "4754"])
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        time::sleep(Duration::from_millis(500)).await;
+
+        // A CSV sink is written as Parquet all the same
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("collector.json");
+        let unused = dir.path().join("unused");
+        let json = format!(
+            r#"{{"streams": [{{"host": "127.0.0.1", "port": 4754}}],
+                "sink": {{"format": "csv", "dir": {:?}}}, "batch_rows": 10}}"#,
+            unused
+        );
+        fs::write(&config, json).unwrap();
+        let out = dir.path().join("data");
+        let mut collector = Command::new(env!("CARGO_BIN_EXE_pdc-collector"))
+            .arg(&config)
+            .arg("--out")
+            .arg(&out)
+            .args(["--metrics-port", "4755", "--duration", "3"])
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        time::sleep(Duration::from_millis(2000)).await;
+
+        let metrics = reqwest::get("http://127.0.0.1:4755/metrics")
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(metrics.contains("pmu_pipeline_latency_us{"), "{}", metrics);
+
+        let status = time::timeout(Duration::from_secs(10), collector.wait())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(status.success());
+        let files = parquet_files(&out);
+        assert!(!files.is_empty());
+        assert!(!unused.exists());
+        // Every PMU of the stream has its columns
+        let file = fs::File::open(&files[0]).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        let schema = reader.schema();
+        for station in ["PMU 1", "PMU 2", "PMU 3"] {
+            assert!(
+                schema
+                    .fields()
+                    .iter()
+                    .any(|field| field.name().starts_with(station)),
+                "{:?}",
+                schema
+            );
+        }
+        sim.kill().await.unwrap();
+    }
+}
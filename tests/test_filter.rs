@@ -0,0 +1,167 @@
+#![cfg(feature = "arrow")]
+#![allow(unused)]
+mod common;
+
+use common::read_hex_file;
+
+#[cfg(test)]
+mod tests {
+    use super::read_hex_file;
+    use arrow::array::{Array, Float64Array};
+    use arrow::datatypes::DataType;
+    use pmu::accumulator::BatchAccumulator;
+    use pmu::arrow_utils::{to_long_format, META_FILTER, META_GROUP_DELAY_US, META_OFFSET};
+    use pmu::budget::MemoryBudget;
+    use pmu::filter::{Biquad, ChannelFilters, Filter, FilterChain, Fir, Median, MovingAverage};
+    use pmu::frame_parser::parse_config_frame_1and2;
+    use pmu::simulator::{Scenario, ScenarioEvent, Simulator};
+    use std::f64::consts::PI;
+
+    fn run(filter: &mut impl Filter, input: &[f64]) -> Vec<f64> {
+        input.iter().map(|x| filter.process(*x)).collect()
+    }
+
+    #[test]
+    fn test_moving_average_and_median() {
+        let mut input = vec![1.0; 10];
+        input[4] = 9.0;
+        input.extend([2.0; 10]);
+
+        let mut median = Median::new(5);
+        let output = run(&mut median, &input);
+        // The spike is gone, the step comes through delayed by 2 samples
+        assert!(output[..12].iter().all(|y| *y == 1.0), "{:?}", output);
+        assert!(output[12..].iter().all(|y| *y == 2.0), "{:?}", output);
+        assert_eq!(median.group_delay(), 2.0);
+
+        let mut average = MovingAverage::new(4);
+        let output = run(&mut average, &[5.0, 5.0, 9.0, 9.0, 9.0, 9.0]);
+        assert_eq!(output, [5.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+        assert_eq!(average.group_delay(), 1.5);
+        assert_eq!(average.describe(), "moving_average(4)");
+
+        average.reset();
+        assert_eq!(average.process(1.0), 1.0);
+    }
+
+    #[test]
+    fn test_fir_delay() {
+        let mut fir = Fir::new(vec![0.25, 0.5, 0.25]);
+        assert_eq!(fir.group_delay(), 1.0);
+        assert_eq!(
+            run(&mut fir, &[4.0, 4.0, 8.0, 8.0, 8.0]),
+            [4.0, 4.0, 5.0, 7.0, 8.0]
+        );
+        // Not linear phase, delay at DC
+        assert_eq!(Fir::new(vec![0.75, 0.25]).group_delay(), 0.25);
+    }
+
+    #[test]
+    fn test_biquad() {
+        let rate = 30.0;
+        let mut lowpass = Biquad::lowpass(1.0, rate, 1.0 / 2f64.sqrt());
+        // Starts settled on the first sample
+        let output = run(&mut lowpass, &[60.0; 50]);
+        assert!(
+            output.iter().all(|y| (y - 60.0).abs() < 1e-9),
+            "{:?}",
+            output
+        );
+
+        // 10 Hz ripple is attenuated by more than 30 dB
+        let ripple: Vec<f64> = (0..300)
+            .map(|n| 60.0 + 0.1 * (2.0 * PI * 10.0 * n as f64 / rate).sin())
+            .collect();
+        let output = run(&mut lowpass, &ripple);
+        let peak = output[150..]
+            .iter()
+            .map(|y| (y - 60.0).abs())
+            .fold(0.0, f64::max);
+        assert!(peak < 0.1 * 10f64.powf(-30.0 / 20.0), "{}", peak);
+
+        // Second order Butterworth: sqrt(2) / (2 pi fc) seconds
+        let expected = 2f64.sqrt() / (2.0 * PI * 1.0) * rate;
+        assert!((lowpass.group_delay() - expected).abs() < 0.15 * expected);
+
+        let mut highpass = Biquad::highpass(0.5, rate, 1.0 / 2f64.sqrt());
+        let output = run(&mut highpass, &[60.0; 10]);
+        assert!(output.iter().all(|y| y.abs() < 1e-9), "{:?}", output);
+        assert_eq!(highpass.group_delay(), 0.0);
+    }
+
+    #[test]
+    fn test_chain() {
+        let mut chain = FilterChain::new()
+            .with(Median::new(3))
+            .with(MovingAverage::new(5));
+        assert_eq!(chain.group_delay(), 3.0);
+        assert_eq!(chain.describe(), "median(3) -> moving_average(5)");
+        assert_eq!(chain.process(2.0), 2.0);
+
+        let filters = ChannelFilters::new(30.0).with_chain("FREQ", chain);
+        assert_eq!(filters.group_delay_us("FREQ"), 100_000.0);
+        assert_eq!(filters.group_delay_us("VA"), 0.0);
+    }
+
+    #[test]
+    fn test_filter_batch() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let scenario = Scenario {
+            start_soc: Some(1_700_000_000),
+            events: vec![ScenarioEvent::FrequencyRamp {
+                at: 0.0,
+                duration: 10.0,
+                rate: 1.0,
+            }],
+            ..Default::default()
+        };
+        let mut simulator = Simulator::new(config.clone(), scenario);
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited());
+        accumulator.add_stream(&config);
+
+        let column = "Station A_7734_FREQ";
+        let mut filters = ChannelFilters::new(30.0)
+            .with_chain(column, FilterChain::new().with(MovingAverage::new(3)));
+        let mut filtered = Vec::new();
+        for _ in 0..2 {
+            for frame in (0..10).flat_map(|_| simulator.next_tick().frames) {
+                assert!(accumulator.push_frame(&frame).unwrap().is_none());
+            }
+            let batch = accumulator.flush(7734).unwrap().unwrap();
+            let batch = filters.filter_batch(&batch).unwrap();
+
+            let schema = batch.schema();
+            let field = schema.field_with_name(column).unwrap();
+            assert_eq!(field.data_type(), &DataType::Float64);
+            assert_eq!(field.metadata()[META_FILTER], "moving_average(3)");
+            let delay_us: f64 = field.metadata()[META_GROUP_DELAY_US].parse().unwrap();
+            assert!((delay_us - 33_333.3).abs() < 0.1);
+            assert!(!field.metadata().contains_key(META_OFFSET));
+            // Unfiltered columns are left as they are
+            assert_eq!(
+                schema
+                    .field_with_name("Station A_7734_DFREQ")
+                    .unwrap()
+                    .data_type(),
+                &DataType::Int16
+            );
+            let values = batch.column_by_name(column).unwrap();
+            let values = values.as_any().downcast_ref::<Float64Array>().unwrap();
+            filtered.extend(values.values().iter().copied());
+
+            // The long layout reads the engineering values as they are
+            let long = to_long_format(&batch).unwrap();
+            let long_values = long.column_by_name("value").unwrap();
+            let long_values = long_values.as_any().downcast_ref::<Float64Array>().unwrap();
+            assert!(long_values.values().contains(&values.value(5)));
+        }
+
+        // A 1 Hz/s ramp lags one sample behind, also across batches
+        assert!((filtered[0] - 60.0).abs() < 0.002);
+        for (n, value) in filtered.iter().enumerate().skip(2) {
+            let expected = 60.0 + (n as f64 - 1.0) / 30.0;
+            assert!((value - expected).abs() < 0.002, "{} {}", n, value);
+        }
+    }
+}
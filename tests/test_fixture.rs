@@ -0,0 +1,162 @@
+#![cfg(feature = "server")]
+#![allow(unused)]
+mod common;
+
+use arrow::array::Array;
+use common::{data_frame_at, read_hex_file, with_crc};
+use pmu::arrow_utils::build_record_batch;
+use pmu::fixture::{capture, to_hex_text, Fixture, FIXTURE_IDCODE, FIXTURE_SOC};
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::frames::CrcMode;
+use pmu::pdc_server::{run_mock_server, Protocol, ServerConfig};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+// A device with its own identity: idcode 60, station and channels renamed.
+fn device_fixture() -> Fixture {
+    let mut config =
+        parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+    config.prefix.idcode = 60;
+    config.pmu_configs[0].idcode = 60;
+    config.pmu_configs[0].stn = *b"SUBSTATION NORTH";
+    for (i, chunk) in config.pmu_configs[0].chnam.chunks_mut(16).enumerate() {
+        if chunk.iter().any(|byte| *byte != b' ') {
+            chunk.copy_from_slice(format!("FEEDER SECRET{:03}", i).as_bytes());
+        }
+    }
+    let frames = (0..3)
+        .map(|i| {
+            let mut frame = data_frame_at(1_700_000_000, i * 33_333);
+            frame[4..6].copy_from_slice(&60u16.to_be_bytes());
+            with_crc(frame)
+        })
+        .collect();
+    Fixture::new(config.to_hex(), frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anonymize() {
+        let device = device_fixture();
+        let fixture = device.anonymize().unwrap();
+
+        let config = parse_config_frame_1and2(&fixture.config).unwrap();
+        assert_eq!(config.prefix.idcode, FIXTURE_IDCODE);
+        assert_eq!(config.pmu_configs[0].idcode, FIXTURE_IDCODE);
+        assert_eq!(&config.pmu_configs[0].stn, b"Station A       ");
+        let text = String::from_utf8_lossy(&fixture.config).to_string();
+        assert!(!text.contains("SECRET") && !text.contains("NORTH"));
+        let names: Vec<String> = config.pmu_configs[0]
+            .chnam
+            .chunks(16)
+            .map(|chunk| String::from_utf8_lossy(chunk).trim().to_string())
+            .collect();
+        assert!(names.iter().any(|name| name == "V1" || name == "I1"));
+        assert!(names.iter().all(|name| !name.contains("FEEDER")));
+
+        assert_eq!(fixture.frames.len(), 3);
+        for (i, frame) in fixture.frames.iter().enumerate() {
+            assert_eq!(u16::from_be_bytes([frame[4], frame[5]]), FIXTURE_IDCODE);
+            assert_eq!(
+                u32::from_be_bytes(frame[6..10].try_into().unwrap()),
+                FIXTURE_SOC
+            );
+            assert_eq!(
+                u32::from_be_bytes(frame[10..14].try_into().unwrap()),
+                i as u32 * 33_333
+            );
+            assert_eq!(CrcMode::detect(frame), Some(CrcMode::Standard));
+        }
+
+        // The values are kept
+        let original = parse_config_frame_1and2(&device.config).unwrap();
+        let before = build_record_batch(
+            &device.frames.concat(),
+            device.frames[0].len(),
+            &original.get_channel_map(),
+        )
+        .unwrap();
+        let after = build_record_batch(
+            &fixture.frames.concat(),
+            fixture.frames[0].len(),
+            &config.get_channel_map(),
+        )
+        .unwrap();
+        assert_eq!(before.num_rows(), 3);
+        assert_eq!(after.num_columns(), before.num_columns());
+        // Values of every column but the timestamp, without their names
+        let sorted = |batch: &arrow::record_batch::RecordBatch| {
+            let mut columns: Vec<Vec<u8>> = batch
+                .columns()
+                .iter()
+                .skip(1)
+                .map(|column| column.to_data().buffers()[0].as_slice().to_vec())
+                .collect();
+            columns.sort();
+            columns
+        };
+        assert_eq!(sorted(&before), sorted(&after));
+    }
+
+    #[test]
+    fn test_write_in_test_data_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let fixture = device_fixture().anonymize().unwrap();
+        let paths = fixture.write(dir.path().join("fixtures"), "relay").unwrap();
+        assert_eq!(paths[0].file_name().unwrap(), "relay_config.bin");
+        assert_eq!(paths[1].file_name().unwrap(), "relay_data.bin");
+
+        let text = fs::read_to_string(&paths[1]).unwrap();
+        assert!(text
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('A'..='F').contains(&c)));
+        assert_eq!(text, to_hex_text(&fixture.frames.concat()));
+        assert_eq!(to_hex_text(&[0xAA, 0x01, 0x0F]), "AA010F");
+
+        // Read back as the tests do
+        let copied = Path::new("tests/test_data").join("relay_roundtrip_config.bin");
+        fs::copy(&paths[0], &copied).unwrap();
+        let config = read_hex_file("relay_roundtrip_config.bin");
+        fs::remove_file(&copied).unwrap();
+        assert_eq!(config.unwrap(), fixture.config);
+    }
+
+    #[tokio::test]
+    async fn test_capture_from_device() {
+        let server_config =
+            ServerConfig::new("127.0.0.1".to_string(), 4741, Protocol::TCP, 30.0).unwrap();
+        let server = tokio::spawn(async move {
+            if let Err(e) = run_mock_server(server_config).await {
+                println!("Mock server error: {}", e)
+            };
+        });
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let captured = capture("127.0.0.1", 4741, 7734, 4, Duration::from_secs(5))
+            .await
+            .unwrap();
+        let config = parse_config_frame_1and2(&captured.config).unwrap();
+        assert_eq!(captured.frames.len(), 4);
+        for frame in &captured.frames {
+            assert_eq!(frame.len(), config.calc_data_frame_size());
+            assert_eq!(frame[1] >> 4 & 0x07, 0);
+        }
+        let fixture = captured.anonymize().unwrap();
+        assert_eq!(
+            u32::from_be_bytes(fixture.frames[0][6..10].try_into().unwrap()),
+            FIXTURE_SOC
+        );
+
+        // Nothing that many frames in time
+        assert!(
+            capture("127.0.0.1", 4741, 7734, 1000, Duration::from_millis(300))
+                .await
+                .is_err()
+        );
+        server.abort();
+    }
+}
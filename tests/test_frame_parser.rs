@@ -1,29 +1,18 @@
 #![allow(unused)]
+mod common;
+
+use common::read_hex_file;
 use std::cmp::min;
-use std::fs;
-use std::path::Path;
-
-fn read_hex_file(file_name: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let path = Path::new("tests/test_data").join(file_name);
-    let content = fs::read_to_string(path)?;
-    let hex_string: String = content.chars().filter(|c| !c.is_whitespace()).collect();
-
-    hex_string
-        .as_bytes()
-        .chunks(2)
-        .map(|chunk| {
-            let hex_byte = std::str::from_utf8(chunk).unwrap();
-            u8::from_str_radix(hex_byte, 16).map_err(|e| e.into())
-        })
-        .collect()
-}
 
 #[cfg(test)]
 mod tests {
-    use pmu::frame_parser::{parse_config_frame_1and2, parse_data_frames};
+    use pmu::frame_parser::{
+        frame_sequence, parse_config_frame_1and2, parse_data_frame_view, parse_data_frames,
+        parse_frame_sequence, ParseError,
+    };
     use pmu::frames::{
         calculate_crc, ConfigurationFrame1and2_2011, DataFrame2011, PMUConfigurationFrame2011,
-        PMUFrameType, PMUValues, PrefixFrame2011,
+        PMUData, PMUDataFrame, PMUFrameType, PMUValues, PrefixFrame2011,
     };
 
     #[test]
@@ -113,7 +102,7 @@ mod tests {
         let pmu_config = &config_frame.pmu_configs[0];
         //assert_eq!(pmu_config.stn, *b"Station A        ");
         assert_eq!(pmu_config.idcode, 7734);
-        assert_eq!(pmu_config.format, 4);
+        assert_eq!(u16::from(pmu_config.format), 4);
         assert_eq!(pmu_config.phnmr, 4);
         assert_eq!(pmu_config.annmr, 3);
         assert_eq!(pmu_config.dgnmr, 1);
@@ -127,6 +116,56 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_format_flags() {
+        use pmu::frames::FormatFlags;
+
+        for bits in 0u16..16 {
+            let format = FormatFlags::from(bits);
+            assert_eq!(u16::from(format), bits);
+            assert_eq!(format.reserved, 0);
+        }
+        let format = FormatFlags::from(0x000E);
+        assert!(format.freq_float && format.analog_float && format.phasor_float);
+        assert!(!format.polar);
+        assert_eq!(
+            (
+                format.freq_dfreq_size(),
+                format.analog_size(),
+                format.phasor_size()
+            ),
+            (4, 4, 8)
+        );
+        assert_eq!(
+            format.to_string(),
+            "FREQ/DFREQ float, analogs float, phasors float rectangular"
+        );
+        assert_eq!(
+            FormatFlags::from(0x0001).to_string(),
+            "FREQ/DFREQ 16 bit, analogs 16 bit, phasors 16 bit polar"
+        );
+
+        // Reserved bits are kept, so frames serialize back as received
+        let format = FormatFlags::from(0x8005);
+        assert_eq!(format.reserved, 0x8000);
+        assert!(format.polar && format.analog_float);
+        assert_eq!(u16::from(format), 0x8005);
+
+        let json = serde_json::to_value(FormatFlags::from(0x0004)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "freq_float": false,
+                "analog_float": true,
+                "phasor_float": false,
+                "polar": false,
+                "reserved": 0
+            })
+        );
+        let parsed: FormatFlags = serde_json::from_str(r#"{"polar": true}"#).unwrap();
+        assert_eq!(u16::from(parsed), 0x0001);
+    }
+
     #[test]
     fn test_calc_data_frame_size() {
         // Parse the configuration frame
@@ -149,6 +188,91 @@ mod tests {
         assert_eq!(actual_size as u16, prefix_size);
     }
 
+    #[test]
+    fn test_data_frame_sizes_for_every_format() {
+        let config_buffer = super::read_hex_file("config_message.bin").unwrap();
+        let sample = parse_config_frame_1and2(&config_buffer).unwrap();
+        let pmu = &sample.pmu_configs[0];
+        // 4 phasors, 3 analogs, 1 digital word
+        for format in 0u16..16 {
+            let mut pmu = pmu.clone();
+            pmu.format = format.into();
+            let phasor = if format & 0x2 != 0 { 8 } else { 4 };
+            let analog = if format & 0x4 != 0 { 4 } else { 2 };
+            let freq = if format & 0x8 != 0 { 4 } else { 2 };
+            assert_eq!(pmu.phasor_size(), phasor);
+            assert_eq!(pmu.analog_size(), analog);
+            assert_eq!(pmu.freq_dfreq_size(), freq);
+
+            let mut config = sample.clone();
+            config.pmu_configs = vec![pmu.clone()];
+            let size = 16 + 2 + 4 * phasor + 2 * freq + 3 * analog + 2;
+            assert_eq!(config.calc_data_frame_size(), size);
+
+            // Two more PMUs of other formats add their own blocks
+            let mut other = pmu.clone();
+            other.format = (15 - format).into();
+            other.phnmr = 1;
+            other.annmr = 0;
+            other.dgnmr = 2;
+            config.pmu_configs.extend([other.clone(), other.clone()]);
+            config.num_pmu = 3;
+            let other_size = 2 + other.phasor_size() + 2 * other.freq_dfreq_size() + 2 * 2;
+            assert_eq!(config.calc_data_frame_size(), size + 2 * other_size);
+        }
+        // No PMUs: prefix and CHK only
+        let mut empty = sample.clone();
+        empty.pmu_configs.clear();
+        assert_eq!(empty.calc_data_frame_size(), 16);
+    }
+
+    #[test]
+    fn test_data_frame_size_checked_against_framesize() {
+        let config =
+            parse_config_frame_1and2(&super::read_hex_file("config_message.bin").unwrap()).unwrap();
+        let data_buffer = super::read_hex_file("data_message.bin").unwrap();
+
+        // FRAMESIZE of another configuration
+        let mut wrong = data_buffer.clone();
+        wrong[2..4].copy_from_slice(&60u16.to_be_bytes());
+        wrong.extend_from_slice(&[0; 8]);
+        assert!(matches!(
+            parse_data_frames(&wrong, &config),
+            Err(ParseError::InvalidFrameSize)
+        ));
+        assert!(matches!(
+            parse_data_frame_view(bytes::Bytes::from(wrong), &config),
+            Err(ParseError::InvalidFrameSize)
+        ));
+
+        // Cut short: an error rather than a panic
+        assert!(matches!(
+            parse_data_frames(&data_buffer[..40], &config),
+            Err(ParseError::InsufficientData)
+        ));
+        assert!(matches!(
+            parse_data_frames(&data_buffer[..10], &config),
+            Err(ParseError::InsufficientData)
+        ));
+
+        // The configuration's FORMAT changed, the frames did not
+        let mut float_config = config.clone();
+        float_config.pmu_configs[0].format = 0x000E.into();
+        assert!(matches!(
+            parse_data_frames(&data_buffer, &float_config),
+            Err(ParseError::InvalidFrameSize)
+        ));
+
+        // Trailing bytes after the frame are not part of it
+        let mut padded = data_buffer.clone();
+        padded.extend_from_slice(&[0xFF; 4]);
+        let frame = parse_data_frames(&padded, &config).unwrap();
+        assert_eq!(
+            frame.chk,
+            calculate_crc(&data_buffer[..data_buffer.len() - 2])
+        );
+    }
+
     #[test]
     fn test_parse_data_frame() {
         // First, parse the configuration frame
@@ -203,7 +327,7 @@ mod tests {
         };
 
         let is_polar = pmu_config.is_phasor_polar();
-        assert_eq!(is_polar, false);
+        assert!(!is_polar);
 
         // Test Phasor values
         assert_eq!(phasor_values[0], PMUValues::Fixed(vec![14635, 0]));
@@ -234,6 +358,206 @@ mod tests {
         let calculated_crc = calculate_crc(&data_buffer[..data_buffer.len() - 2]);
         assert_eq!(calculated_crc, data_frame.chk, "CRC mismatch in data frame");
     }
+
+    #[test]
+    fn test_data_frame_view() {
+        let config_frame =
+            parse_config_frame_1and2(&super::read_hex_file("config_message.bin").unwrap()).unwrap();
+        let data_buffer = super::read_hex_file("data_message.bin").unwrap();
+        let parsed = parse_data_frames(&data_buffer, &config_frame).unwrap();
+        let copied = match &parsed.data[0] {
+            PMUFrameType::Fixed(data) => data,
+            _ => panic!("Expected fixed frequency"),
+        };
+
+        let frame = bytes::Bytes::from(data_buffer.clone());
+        let view = parse_data_frame_view(frame.clone(), &config_frame).unwrap();
+        assert_eq!(view.prefix.idcode, 7734);
+        assert_eq!(view.pmus.len(), 1);
+        let pmu = &view.pmus[0];
+        assert_eq!(pmu.stat, copied.stat);
+        assert_eq!(pmu.phasors[..], copied.phasors[..]);
+        assert_eq!(i16::from_be_bytes([pmu.freq[0], pmu.freq[1]]), copied.freq);
+        assert_eq!(pmu.analogs[..], copied.analog[..]);
+        assert_eq!(pmu.digitals[..], copied.digital[..]);
+        assert_eq!(view.chk, parsed.chk);
+        // The fields point into the frame, nothing was copied
+        let offset = pmu.phasors.as_ptr() as usize - frame.as_ptr() as usize;
+        assert_eq!(offset, 16);
+
+        assert!(parse_data_frame_view(frame.slice(..40), &config_frame).is_err());
+        assert!(parse_data_frame_view(frame.slice(..10), &config_frame).is_err());
+    }
+
+    #[test]
+    fn test_data_frame_view_matches_parser() {
+        let config =
+            parse_config_frame_1and2(&super::read_hex_file("config_message.bin").unwrap()).unwrap();
+        // The next frame's first bytes follow in the buffer
+        let mut buffer = super::read_hex_file("data_message.bin").unwrap();
+        let size = buffer.len();
+        buffer.extend_from_slice(&[0xAA, 0x01, 0x00, 0x34]);
+
+        let parsed = parse_data_frames(&buffer, &config).unwrap();
+        let view = parse_data_frame_view(bytes::Bytes::from(buffer.clone()), &config).unwrap();
+        assert_eq!(view.prefix, parsed.prefix);
+        assert_eq!(view.chk, parsed.chk);
+        assert_eq!(
+            view.chk,
+            u16::from_be_bytes([buffer[size - 2], buffer[size - 1]])
+        );
+        assert_eq!(view.frame.len(), size);
+        let PMUFrameType::Fixed(pmu) = &parsed.data[0] else {
+            panic!("Expected fixed frequency")
+        };
+        let pmu_view = &view.pmus[0];
+        assert_eq!(pmu_view.stat, pmu.stat);
+        assert_eq!(pmu_view.phasors[..], pmu.phasors[..]);
+        assert_eq!(pmu_view.freq[..], pmu.freq.to_be_bytes());
+        assert_eq!(pmu_view.dfreq[..], pmu.dfreq.to_be_bytes());
+        assert_eq!(pmu_view.analogs[..], pmu.analog[..]);
+        assert_eq!(pmu_view.digitals[..], pmu.digital[..]);
+
+        // Cut short of FRAMESIZE, the same error from both
+        let short = &buffer[..size - 4];
+        assert!(matches!(
+            parse_data_frames(short, &config),
+            Err(ParseError::InsufficientData)
+        ));
+        assert!(matches!(
+            parse_data_frame_view(bytes::Bytes::copy_from_slice(short), &config),
+            Err(ParseError::InsufficientData)
+        ));
+    }
+
+    #[test]
+    fn test_pmu_data_values() {
+        let config_frame =
+            parse_config_frame_1and2(&super::read_hex_file("config_message.bin").unwrap()).unwrap();
+        let pmu_config = &config_frame.pmu_configs[0];
+        let data_buffer = super::read_hex_file("data_message.bin").unwrap();
+        let data_frame = parse_data_frames(&data_buffer, &config_frame).unwrap();
+
+        // Fixed point phasors and frequency, floating point analogs
+        let block = &data_frame.data[0];
+        let scale = (pmu_config.phunit[0] & 0x00FF_FFFF) as f64 / 100_000.0;
+        let phasors = block.phasor_values(pmu_config);
+        assert_eq!(phasors.len(), 4);
+        assert_eq!(phasors[0], (14635.0 * scale, 0.0));
+        assert_eq!(block.stat(), 0);
+        assert_eq!(block.frequency(pmu_config), 62.5);
+        assert_eq!(block.rocof(), 0.0);
+        assert_eq!(
+            block.analog_values(pmu_config),
+            vec![100.0, 1000.0, 10000.0]
+        );
+        assert_eq!(block.digital_words(), vec![0b0011110000010010]);
+
+        // The same values as floating point, polar phasors
+        let mut float_config = pmu_config.clone();
+        float_config.format = 0x000F.into();
+        let (magnitude, angle) = (230.5f32, -2.5f32);
+        let mut phasors = Vec::new();
+        for _ in 0..pmu_config.phnmr {
+            phasors.extend_from_slice(&magnitude.to_be_bytes());
+            phasors.extend_from_slice(&angle.to_be_bytes());
+        }
+        let floating = PMUFrameType::Floating(PMUDataFrame {
+            stat: 0x8000,
+            phasors,
+            freq: 59.98,
+            dfreq: -0.25,
+            analog: [100.0f32, 1000.0, 10000.0]
+                .iter()
+                .flat_map(|value| value.to_be_bytes())
+                .collect(),
+            digital: vec![0x3C, 0x12],
+        });
+        let values = floating.phasor_values(&float_config);
+        assert_eq!(values[3], (magnitude as f64, angle as f64));
+        assert_eq!(floating.stat(), 0x8000);
+        assert!((floating.frequency(&float_config) - 59.98).abs() < 1e-5);
+        assert_eq!(floating.rocof(), -0.25);
+        assert_eq!(
+            floating.analog_values(&float_config),
+            block.analog_values(pmu_config)
+        );
+        assert_eq!(floating.digital_words(), block.digital_words());
+
+        // Fixed point polar phasors: unsigned magnitude, angle x 10^4, 50 Hz
+        let mut polar_config = pmu_config.clone();
+        polar_config.format = 0x0001.into();
+        polar_config.fnom = 1;
+        let fixed = PMUDataFrame::<i16> {
+            stat: 0,
+            phasors: [40000u16.to_be_bytes(), (-5000i16).to_be_bytes()].concat(),
+            freq: -20,
+            dfreq: 5,
+            analog: vec![0, 2],
+            digital: Vec::new(),
+        };
+        let values = fixed.phasor_values(&polar_config);
+        assert_eq!(values[0], (40000.0 * scale, -0.5));
+        assert_eq!(fixed.frequency(&polar_config), 49.98);
+        assert_eq!(fixed.rocof(), 0.05);
+        assert_eq!(
+            fixed.analog_values(&polar_config),
+            vec![2.0 * ((polar_config.anunit[0] << 8) as i32 >> 8) as f64]
+        );
+    }
+
+    #[test]
+    fn test_parse_frame_sequence() {
+        let config_buffer = super::read_hex_file("config_message.bin").unwrap();
+        let config_frame = parse_config_frame_1and2(&config_buffer).unwrap();
+        let data_frame = super::read_hex_file("data_message.bin").unwrap();
+
+        // A 30kB buffer of back to back data frames
+        let num_frames = 30 * 1024 / data_frame.len();
+        let buffer = data_frame.repeat(num_frames);
+        let frames: Vec<_> = parse_frame_sequence(&buffer, &config_frame)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(frames.len(), num_frames);
+        assert!(frames.iter().all(|frame| frame.prefix.idcode == 7734));
+
+        // Garbage, a damaged frame and a cut off one: each is one error and
+        // the frames after them are found again
+        let mut damaged = data_frame.clone();
+        damaged[20] ^= 0xFF;
+        let mut buffer = vec![0x00, 0xAA, 0x13];
+        buffer.extend_from_slice(&config_buffer);
+        buffer.extend_from_slice(&data_frame);
+        buffer.extend_from_slice(&damaged);
+        buffer.extend_from_slice(&data_frame);
+        buffer.extend_from_slice(&data_frame[..30]);
+        let results: Vec<_> = parse_frame_sequence(&buffer, &config_frame).collect();
+        assert_eq!(results.len(), 5);
+        assert!(matches!(results[0], Err(ParseError::InvalidHeader)));
+        assert!(results[1].is_ok());
+        assert!(matches!(results[2], Err(ParseError::InvalidCRC)));
+        assert!(results[3].is_ok());
+        assert!(matches!(results[4], Err(ParseError::InvalidFrameSize)));
+
+        let mut sequence = frame_sequence(&buffer);
+        let frames: Vec<&[u8]> = sequence.by_ref().filter_map(Result::ok).collect();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0], &config_buffer[..]);
+        assert_eq!(sequence.skipped(), 3 + damaged.len() + 30);
+        assert_eq!(sequence.offset(), buffer.len());
+
+        // Data frames of another size than the configuration's
+        let mut other = data_frame[..data_frame.len() - 2].to_vec();
+        other.extend_from_slice(&[0, 0, 0, 0]);
+        let size = (other.len() as u16).to_be_bytes();
+        other[2..4].copy_from_slice(&size);
+        let crc = calculate_crc(&other[..other.len() - 2]);
+        let len = other.len();
+        other[len - 2..].copy_from_slice(&crc.to_be_bytes());
+        let results: Vec<_> = parse_frame_sequence(&other, &config_frame).collect();
+        assert!(matches!(results[..], [Err(ParseError::InvalidFrameSize)]));
+    }
+    #[cfg(feature = "arrow")]
     #[test]
     fn test_arrow_frame_creation() {
         use arrow::array::{
@@ -443,4 +767,433 @@ mod tests {
             );
         }
     }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_arrow_field_metadata() {
+        use pmu::arrow_utils::{
+            build_arrow_schema, META_CHANNEL, META_COMPONENT, META_IDCODE, META_KIND,
+            META_NOMINAL_FREQUENCY, META_OFFSET, META_SCALE, META_STATION, META_UNIT,
+        };
+
+        let config =
+            parse_config_frame_1and2(&super::read_hex_file("config_message.bin").unwrap()).unwrap();
+        let schema = build_arrow_schema(&config.get_channel_map());
+        let meta = |column: &str, key: &str| {
+            schema
+                .field_with_name(column)
+                .unwrap()
+                .metadata()
+                .get(key)
+                .cloned()
+        };
+        assert!(schema
+            .field_with_name("timestamp")
+            .unwrap()
+            .metadata()
+            .is_empty());
+
+        let va = "Station A_7734_VA_X";
+        assert_eq!(meta(va, META_STATION).as_deref(), Some("Station A"));
+        assert_eq!(meta(va, META_IDCODE).as_deref(), Some("7734"));
+        assert_eq!(meta(va, META_CHANNEL).as_deref(), Some("Station A_7734_VA"));
+        assert_eq!(meta(va, META_UNIT).as_deref(), Some("V"));
+        assert_eq!(meta(va, META_KIND).as_deref(), Some("voltage"));
+        assert_eq!(meta(va, META_SCALE).as_deref(), Some("9.15527"));
+        assert_eq!(meta(va, META_COMPONENT).as_deref(), Some("real"));
+        assert_eq!(meta(va, META_NOMINAL_FREQUENCY).as_deref(), Some("60"));
+        let va_y = "Station A_7734_VA_Y";
+        assert_eq!(meta(va_y, META_COMPONENT).as_deref(), Some("imaginary"));
+
+        let i1 = "Station A_7734_I1_X";
+        assert_eq!(meta(i1, META_UNIT).as_deref(), Some("A"));
+        assert_eq!(meta(i1, META_KIND).as_deref(), Some("current"));
+        assert_eq!(meta(i1, META_SCALE).as_deref(), Some("0.45776"));
+
+        // Fixed point FREQ is the deviation from nominal in mHz
+        let freq = "Station A_7734_FREQ";
+        assert_eq!(meta(freq, META_UNIT).as_deref(), Some("Hz"));
+        assert_eq!(meta(freq, META_SCALE).as_deref(), Some("0.001"));
+        assert_eq!(meta(freq, META_OFFSET).as_deref(), Some("60"));
+        let dfreq = "Station A_7734_DFREQ";
+        assert_eq!(meta(dfreq, META_UNIT).as_deref(), Some("Hz/s"));
+        assert_eq!(meta(dfreq, META_OFFSET), None);
+
+        assert_eq!(
+            meta("Station A_7734_ANALOG2", META_KIND).as_deref(),
+            Some("rms")
+        );
+        assert_eq!(meta("Station A_7734_ANALOG2", META_UNIT), None);
+        assert_eq!(
+            meta("Station A_7734_BREAKER 1 STATUS", META_KIND).as_deref(),
+            Some("digital")
+        );
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_decoded_stat_columns() {
+        use arrow::array::{Array, BooleanArray, UInt16Array, UInt8Array};
+        use pmu::arrow_utils::{build_record_batch_with, ArrowOptions, StatColumns};
+
+        let config =
+            parse_config_frame_1and2(&super::read_hex_file("config_message.bin").unwrap()).unwrap();
+        let channel_map = config.get_channel_map();
+        let mut frame = super::read_hex_file("data_message.bin").unwrap();
+        let len = frame.len();
+        // Out of sync, config change pending, leap second (time quality 5)
+        let stat: u16 = 0x2000 | 0x0400 | (5 << 6);
+        frame[14..16].copy_from_slice(&stat.to_be_bytes());
+        let crc = calculate_crc(&frame[..len - 2]);
+        frame[len - 2..].copy_from_slice(&crc.to_be_bytes());
+
+        let batch =
+            build_record_batch_with(&frame, len, &channel_map, &ArrowOptions::default()).unwrap();
+        let column = |name: &str| {
+            batch
+                .column_by_name(&format!("Station A_7734_{}", name))
+                .unwrap_or_else(|| panic!("missing {}", name))
+                .clone()
+        };
+        let flag = |name: &str| {
+            column(name)
+                .as_any()
+                .downcast_ref::<BooleanArray>()
+                .unwrap()
+                .value(0)
+        };
+        let raw = column("STAT");
+        assert_eq!(
+            raw.as_any().downcast_ref::<UInt16Array>().unwrap().value(0),
+            stat
+        );
+        assert!(flag("DATA_VALID"));
+        assert!(!flag("PMU_SYNC"));
+        assert!(!flag("SORTED_BY_ARRIVAL"));
+        assert!(!flag("TRIGGER"));
+        assert!(flag("CONFIG_CHANGE"));
+        assert!(!flag("DATA_MODIFIED"));
+        let time_quality = column("TIME_QUALITY");
+        assert_eq!(
+            time_quality
+                .as_any()
+                .downcast_ref::<UInt8Array>()
+                .unwrap()
+                .value(0),
+            5
+        );
+
+        let raw_only = build_record_batch_with(
+            &frame,
+            len,
+            &channel_map,
+            &ArrowOptions::default().with_stat_columns(StatColumns::Raw),
+        )
+        .unwrap();
+        assert!(raw_only.column_by_name("Station A_7734_STAT").is_some());
+        assert!(raw_only.column_by_name("Station A_7734_PMU_SYNC").is_none());
+
+        let decoded = build_record_batch_with(
+            &frame,
+            len,
+            &channel_map,
+            &ArrowOptions::default().with_stat_columns(StatColumns::Decoded),
+        )
+        .unwrap();
+        assert!(decoded.column_by_name("Station A_7734_STAT").is_none());
+        assert!(decoded.column_by_name("Station A_7734_PMU_SYNC").is_some());
+        assert_eq!(decoded.num_columns(), raw_only.num_columns() + 6);
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_complex_phasor_columns() {
+        use arrow::array::{Array, FixedSizeListArray, Float64Array, Int16Array, StringArray};
+        use arrow::datatypes::DataType;
+        use pmu::arrow_utils::{
+            build_record_batch_with, to_long_format, ArrowOptions, PhasorColumns, META_COMPONENT,
+            META_SCALE,
+        };
+
+        let config =
+            parse_config_frame_1and2(&super::read_hex_file("config_message.bin").unwrap()).unwrap();
+        let channel_map = config.get_channel_map();
+        let frame = super::read_hex_file("data_message.bin").unwrap();
+        let options = ArrowOptions::default().with_phasor_columns(PhasorColumns::Complex);
+        let components =
+            build_record_batch_with(&frame, frame.len(), &channel_map, &ArrowOptions::default())
+                .unwrap();
+        let complex = build_record_batch_with(&frame, frame.len(), &channel_map, &options).unwrap();
+
+        // One column per phasor instead of two
+        assert_eq!(complex.num_columns(), components.num_columns() - 4);
+        assert!(complex.column_by_name("Station A_7734_VA_X").is_none());
+        let schema = complex.schema();
+        let field = schema.field_with_name("Station A_7734_VA").unwrap();
+        assert!(matches!(field.data_type(), DataType::FixedSizeList(_, 2)));
+        assert_eq!(field.metadata()[META_COMPONENT], "complex");
+        assert_eq!(field.metadata()[META_SCALE], "1");
+
+        let raw = |name: &str| {
+            components
+                .column_by_name(name)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<Int16Array>()
+                .unwrap()
+                .value(0) as f64
+        };
+        let scale: f64 = components
+            .schema()
+            .field_with_name("Station A_7734_VA_X")
+            .unwrap()
+            .metadata()[META_SCALE]
+            .parse()
+            .unwrap();
+        let va = complex.column_by_name("Station A_7734_VA").unwrap();
+        let va = va.as_any().downcast_ref::<FixedSizeListArray>().unwrap();
+        let pair = va.value(0);
+        let pair = pair.as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(pair.value(0), raw("Station A_7734_VA_X") * scale);
+        assert_eq!(pair.value(1), raw("Station A_7734_VA_Y") * scale);
+
+        // The long layout has the same values either way
+        let long = to_long_format(&complex).unwrap();
+        let expected = to_long_format(&components).unwrap();
+        assert_eq!(long.num_rows(), expected.num_rows());
+        let rows = |batch: &arrow::record_batch::RecordBatch| {
+            let types = batch.column_by_name("type").unwrap();
+            let types = types.as_any().downcast_ref::<StringArray>().unwrap();
+            let values = batch.column_by_name("value").unwrap();
+            let values = values.as_any().downcast_ref::<Float64Array>().unwrap();
+            let mut rows: Vec<(String, f64)> = (0..batch.num_rows())
+                .map(|i| (types.value(i).to_string(), values.value(i)))
+                .collect();
+            rows.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+            rows
+        };
+        let (long, expected) = (rows(&long), rows(&expected));
+        for (row, expected) in long.iter().zip(&expected) {
+            assert_eq!(row.0, expected.0);
+            assert!(
+                (row.1 - expected.1).abs() < 1e-9,
+                "{:?} {:?}",
+                row,
+                expected
+            );
+        }
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_polar_fixed_phasor_frames() {
+        use arrow::array::{Array, FixedSizeListArray, Float64Array, Int16Array, UInt16Array};
+        use arrow::datatypes::DataType;
+        use pmu::arrow_utils::{
+            build_record_batch, build_record_batch_with, channel_values, ArrowOptions,
+            PhasorColumns, META_COMPONENT, META_SCALE, META_UNIT,
+        };
+
+        // The Annex D sample with FORMAT 0x0005: the same phasors as magnitude
+        // (unsigned) and angle (signed, 10^-4 rad)
+        let config =
+            parse_config_frame_1and2(&super::read_hex_file("config_polar_message.bin").unwrap())
+                .unwrap();
+        let pmu_config = &config.pmu_configs[0];
+        assert_eq!(u16::from(pmu_config.format), 0x0005);
+        assert!(pmu_config.is_phasor_polar());
+        assert_eq!(pmu_config.phasor_size(), 4);
+        let mut frame = super::read_hex_file("data_polar_message.bin").unwrap();
+        assert_eq!(frame.len(), config.calc_data_frame_size());
+
+        let rectangular_config =
+            parse_config_frame_1and2(&super::read_hex_file("config_message.bin").unwrap()).unwrap();
+        let rectangular = parse_data_frames(
+            &super::read_hex_file("data_message.bin").unwrap(),
+            &rectangular_config,
+        )
+        .unwrap();
+        let rectangular = rectangular.data[0].phasor_values(&rectangular_config.pmu_configs[0]);
+        let data = parse_data_frames(&frame, &config).unwrap();
+        let polar = data.data[0].phasor_values(pmu_config);
+        for ((magnitude, angle), (re, im)) in polar.iter().zip(&rectangular) {
+            // Within a count of the rectangular sample
+            let scale = (pmu_config.phunit[0] & 0x00FF_FFFF) as f64 / 100_000.0;
+            assert!((magnitude - re.hypot(*im)).abs() < 2.0 * scale);
+            assert!((angle - im.atan2(*re)).abs() < 1e-3);
+        }
+        assert!((polar[1].1.to_degrees() + 120.0).abs() < 0.01);
+        assert!((polar[2].1.to_degrees() - 120.0).abs() < 0.01);
+
+        // Arrow: an unsigned magnitude and an angle in radians
+        let channel_map = config.get_channel_map();
+        let batch = build_record_batch(&frame, frame.len(), &channel_map).unwrap();
+        let magnitude = batch
+            .schema()
+            .field_with_name("Station A_7734_VB_X")
+            .unwrap()
+            .clone();
+        assert_eq!(magnitude.data_type(), &DataType::UInt16);
+        assert_eq!(magnitude.metadata()[META_COMPONENT], "magnitude");
+        let angle = batch
+            .schema()
+            .field_with_name("Station A_7734_VB_Y")
+            .unwrap()
+            .clone();
+        assert_eq!(angle.data_type(), &DataType::Int16);
+        assert_eq!(angle.metadata()[META_COMPONENT], "angle");
+        assert_eq!(angle.metadata()[META_UNIT], "rad");
+        assert_eq!(angle.metadata()[META_SCALE], "0.0001");
+        let vb = channel_values(&batch, "Station A_7734_VB").unwrap();
+        assert!((vb.value(0) - polar[1].0).abs() < 1e-9);
+
+        // Magnitudes past i16::MAX stay positive
+        let scale = (pmu_config.phunit[0] & 0x00FF_FFFF) as f64 / 100_000.0;
+        frame[16..18].copy_from_slice(&50_000u16.to_be_bytes());
+        let len = frame.len();
+        let crc = calculate_crc(&frame[..len - 2]);
+        frame[len - 2..].copy_from_slice(&crc.to_be_bytes());
+        let data = parse_data_frames(&frame, &config).unwrap();
+        assert_eq!(
+            data.data[0].phasor_values(pmu_config)[0].0,
+            50_000.0 * scale
+        );
+        let batch = build_record_batch(&frame, frame.len(), &channel_map).unwrap();
+        let column = batch.column_by_name("Station A_7734_VA_X").unwrap();
+        let column = column.as_any().downcast_ref::<UInt16Array>().unwrap();
+        assert_eq!(column.value(0), 50_000);
+        let va = channel_values(&batch, "Station A_7734_VA").unwrap();
+        assert!((va.value(0) - 50_000.0 * scale).abs() < 1e-6);
+
+        // Complex columns convert to rectangular
+        let options = ArrowOptions::default().with_phasor_columns(PhasorColumns::Complex);
+        let complex = build_record_batch_with(&frame, frame.len(), &channel_map, &options).unwrap();
+        let vb = complex.column_by_name("Station A_7734_VB").unwrap();
+        let vb = vb.as_any().downcast_ref::<FixedSizeListArray>().unwrap();
+        let pair = vb.value(0);
+        let pair = pair.as_any().downcast_ref::<Float64Array>().unwrap();
+        assert!((pair.value(0) - rectangular[1].0).abs() < 2.0 * scale);
+        assert!((pair.value(1) - rectangular[1].1).abs() < 2.0 * scale);
+        let va = complex.column_by_name("Station A_7734_VA").unwrap();
+        let va = va.as_any().downcast_ref::<FixedSizeListArray>().unwrap();
+        let pair = va.value(0);
+        let pair = pair.as_any().downcast_ref::<Float64Array>().unwrap();
+        assert!((pair.value(0) - 50_000.0 * scale).abs() < 1e-6);
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_floating_point_frames() {
+        use arrow::array::{Array, Float32Array, UInt16Array};
+        use arrow::datatypes::DataType;
+        use pmu::arrow_utils::{build_record_batch, channel_values, META_COMPONENT, META_SCALE};
+
+        // The Annex D sample with FORMAT 0x000E: phasors, analogs and
+        // FREQ/DFREQ all 32 bit floats in engineering units
+        let config_buffer = super::read_hex_file("config_float_message.bin").unwrap();
+        let config = parse_config_frame_1and2(&config_buffer).unwrap();
+        assert_eq!(config.to_hex(), config_buffer);
+        let pmu_config = &config.pmu_configs[0];
+        assert_eq!(u16::from(pmu_config.format), 0x000E);
+        assert!(!pmu_config.is_phasor_polar());
+        assert_eq!(pmu_config.phasor_size(), 8);
+        assert_eq!(pmu_config.analog_size(), 4);
+        assert_eq!(pmu_config.freq_dfreq_size(), 4);
+        let frame = super::read_hex_file("data_float_message.bin").unwrap();
+        assert_eq!(frame.len(), config.calc_data_frame_size());
+        assert_eq!(frame.len(), 72);
+
+        let data = parse_data_frames(&frame, &config).unwrap();
+        assert_eq!(data.prefix.framesize, 72);
+        assert_eq!(data.prefix.fracsec, 16817);
+        assert_eq!(data.chk, calculate_crc(&frame[..frame.len() - 2]));
+        let block = match &data.data[0] {
+            PMUFrameType::Floating(block) => block,
+            _ => panic!("Expected floating point FREQ/DFREQ"),
+        };
+        assert_eq!((block.freq, block.dfreq), (62.5, 0.0));
+        let phasors = block.parse_phasors(pmu_config);
+        assert_eq!(phasors.len(), 4);
+        assert_eq!(phasors[0], PMUValues::Float(vec![133_987.38, 0.0]));
+        assert_eq!(
+            block.parse_analogs(pmu_config),
+            PMUValues::Float(vec![100.0, 1000.0, 10000.0])
+        );
+        assert_eq!(block.parse_digitals(), vec![0b0011110000010010]);
+        // Serializes back to the same bytes
+        assert_eq!(data.to_hex(), frame);
+
+        // Scaled: the same values as the fixed point sample, PHUNIT unused
+        let fixed_config =
+            parse_config_frame_1and2(&super::read_hex_file("config_message.bin").unwrap()).unwrap();
+        let fixed = parse_data_frames(
+            &super::read_hex_file("data_message.bin").unwrap(),
+            &fixed_config,
+        )
+        .unwrap();
+        let fixed_block = &fixed.data[0];
+        let fixed_config = &fixed_config.pmu_configs[0];
+        let block = &data.data[0];
+        for ((re, im), (fixed_re, fixed_im)) in block
+            .phasor_values(pmu_config)
+            .iter()
+            .zip(fixed_block.phasor_values(fixed_config))
+        {
+            // f32 holds about 7 digits
+            assert!((re - fixed_re).abs() < 1e-6 * fixed_re.abs().max(1.0));
+            assert!((im - fixed_im).abs() < 1e-6 * fixed_im.abs().max(1.0));
+        }
+        assert_eq!(
+            block.frequency(pmu_config),
+            fixed_block.frequency(fixed_config)
+        );
+        assert_eq!(block.rocof(), fixed_block.rocof());
+        assert_eq!(
+            block.analog_values(pmu_config),
+            fixed_block.analog_values(fixed_config)
+        );
+        assert_eq!(block.digital_words(), fixed_block.digital_words());
+
+        // The zero copy view finds the same fields
+        let view = parse_data_frame_view(bytes::Bytes::from(frame.clone()), &config).unwrap();
+        let pmu = &view.pmus[0];
+        assert_eq!(pmu.phasors.len(), 32);
+        assert_eq!(pmu.freq[..], 62.5f32.to_be_bytes());
+        assert_eq!(pmu.analogs.len(), 12);
+        assert_eq!(pmu.digitals[..], [0x3C, 0x12]);
+
+        // Arrow: Float32 columns taken as they are, scale 1
+        let batch = build_record_batch(&frame, frame.len(), &config.get_channel_map()).unwrap();
+        let schema = batch.schema();
+        let real = schema
+            .field_with_name("Station A_7734_VB_magnitude")
+            .unwrap();
+        assert_eq!(real.data_type(), &DataType::Float32);
+        assert_eq!(real.metadata()[META_COMPONENT], "real");
+        assert_eq!(real.metadata()[META_SCALE], "1");
+        let imaginary = schema.field_with_name("Station A_7734_VB_angle").unwrap();
+        assert_eq!(imaginary.metadata()[META_COMPONENT], "imaginary");
+        for name in ["FREQ", "DFREQ", "ANALOG1"] {
+            let field = schema
+                .field_with_name(&format!("Station A_7734_{}", name))
+                .unwrap();
+            assert_eq!(field.data_type(), &DataType::Float32);
+        }
+        let column = |name: &str| {
+            batch
+                .column_by_name(&format!("Station A_7734_{}", name))
+                .unwrap()
+                .as_any()
+                .downcast_ref::<Float32Array>()
+                .unwrap()
+                .value(0)
+        };
+        assert_eq!(column("VB_magnitude"), -66998.266);
+        assert_eq!(column("VB_angle"), -116052.2);
+        assert_eq!(column("FREQ"), 62.5);
+        assert_eq!(column("ANALOG3"), 10000.0);
+        let vb = channel_values(&batch, "Station A_7734_VB").unwrap();
+        let (fixed_re, fixed_im) = fixed_block.phasor_values(fixed_config)[1];
+        assert!((vb.value(0) - fixed_re.hypot(fixed_im)).abs() < 1e-6 * fixed_re.hypot(fixed_im));
+    }
 }
@@ -0,0 +1,40 @@
+#![allow(unused)]
+
+#[cfg(test)]
+mod tests {
+    use pmu::frame_pool::FramePool;
+
+    #[test]
+    fn test_frames_share_chunks() {
+        let mut pool = FramePool::new().with_chunk_frames(4);
+        let mut frames = Vec::new();
+        for n in 0..8u8 {
+            let buf = pool.buffer(52);
+            assert!(buf.iter().all(|b| *b == 0));
+            buf[0] = 0xAA;
+            buf[1] = n;
+            frames.push(pool.freeze(52));
+        }
+        // Two chunks of four frames while all of them are kept
+        assert_eq!(pool.stats().frames, 8);
+        assert_eq!(pool.stats().allocations, 2);
+        assert_eq!(frames[5][..2], [0xAA, 5]);
+        assert_eq!(frames[5].len(), 52);
+    }
+
+    #[test]
+    fn test_chunk_reclaimed() {
+        let mut pool = FramePool::new().with_chunk_frames(4);
+        for n in 0..40 {
+            let frame = pool.copy(&[n as u8; 52]);
+            assert_eq!(frame[51], n as u8);
+            // Dropped before the next read, like a consumer keeping up
+        }
+        assert_eq!(pool.stats().allocations, 1);
+        assert_eq!(pool.stats().reclaimed, 9);
+
+        // A read shorter than the buffer keeps only what was read
+        pool.buffer(53)[..3].copy_from_slice(&[1, 2, 3]);
+        assert_eq!(pool.freeze(3)[..], [1, 2, 3]);
+    }
+}
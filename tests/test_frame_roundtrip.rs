@@ -0,0 +1,290 @@
+#![allow(unused)]
+use pmu::frames::{
+    ConfigurationFrame1and2_2011, DataFrame2011, PMUConfigurationFrame2011, PMUDataFrame,
+    PMUFrameType, PrefixFrame2011,
+};
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+fn pad16(name: &str) -> [u8; 16] {
+    let mut padded = [b' '; 16];
+    padded[..name.len()].copy_from_slice(name.as_bytes());
+    padded
+}
+
+// One PMU with random channel counts and FORMAT, channel names unique within the PMU.
+fn arb_pmu_config(index: u16) -> impl Strategy<Value = PMUConfigurationFrame2011> {
+    (
+        0u16..16,
+        0u16..=6,
+        0u16..=4,
+        0u16..=2,
+        any::<u16>(),
+        0u16..=1,
+    )
+        .prop_flat_map(move |(format, phnmr, annmr, dgnmr, cfgcnt, fnom)| {
+            let phunit = vec(
+                (0u32..=1, 0u32..0x0100_0000).prop_map(|(kind, scale)| kind << 24 | scale),
+                phnmr as usize,
+            );
+            let anunit = vec(any::<u32>(), annmr as usize);
+            let digunit = vec(any::<u32>(), dgnmr as usize);
+            (phunit, anunit, digunit).prop_map(move |(phunit, anunit, digunit)| {
+                let mut names = Vec::new();
+                names.extend((0..phnmr).map(|k| format!("PH{}", k)));
+                names.extend((0..annmr).map(|k| format!("AN{}", k)));
+                for word in 0..dgnmr {
+                    names.extend((0..16).map(|bit| format!("DG{}_{}", word, bit)));
+                }
+                PMUConfigurationFrame2011 {
+                    stn: pad16(&format!("STN{}", index)),
+                    idcode: 100 + index,
+                    format: format.into(),
+                    phnmr,
+                    annmr,
+                    dgnmr,
+                    chnam: names.iter().flat_map(|name| pad16(name)).collect(),
+                    phunit,
+                    anunit,
+                    digunit,
+                    fnom,
+                    cfgcnt,
+                }
+            })
+        })
+}
+
+// A configuration frame with one to three PMUs. FRAMESIZE and CHK are left
+// at 0, to_hex fills them in.
+fn arb_config() -> impl Strategy<Value = ConfigurationFrame1and2_2011> {
+    (1u16..=3)
+        .prop_flat_map(|num_pmu| {
+            (
+                (0..num_pmu).map(arb_pmu_config).collect::<Vec<_>>(),
+                any::<u16>(),
+                1u32..0x0100_0000,
+                prop::sample::select(vec![10i16, 25, 30, 50, 60, 120, -2]),
+                any::<u32>(),
+            )
+        })
+        .prop_map(
+            |(pmu_configs, idcode, time_base, data_rate, soc)| ConfigurationFrame1and2_2011 {
+                prefix: PrefixFrame2011 {
+                    sync: 0xAA31,
+                    framesize: 0,
+                    idcode,
+                    soc,
+                    fracsec: 0,
+                },
+                time_base,
+                num_pmu: pmu_configs.len() as u16,
+                pmu_configs,
+                data_rate,
+                chk: 0,
+            },
+        )
+}
+
+fn finite_f32() -> impl Strategy<Value = f32> {
+    -1.0e6f32..1.0e6f32
+}
+
+// Values of count i16 or f32 fields, as big endian bytes.
+fn arb_words(count: usize, float: bool) -> BoxedStrategy<Vec<u8>> {
+    if float {
+        vec(finite_f32(), count)
+            .prop_map(|values| values.iter().flat_map(|v| v.to_be_bytes()).collect())
+            .boxed()
+    } else {
+        vec(any::<i16>(), count)
+            .prop_map(|values| values.iter().flat_map(|v| v.to_be_bytes()).collect())
+            .boxed()
+    }
+}
+
+// The data of one PMU, laid out as its configuration says.
+fn arb_pmu_data(config: &PMUConfigurationFrame2011) -> BoxedStrategy<PMUFrameType> {
+    let fields = (
+        any::<u16>(),
+        arb_words(2 * config.phnmr as usize, config.format.phasor_float),
+        arb_words(config.annmr as usize, config.format.analog_float),
+        vec(any::<u16>(), config.dgnmr as usize).prop_map(|words| {
+            words
+                .iter()
+                .flat_map(|w| w.to_be_bytes())
+                .collect::<Vec<u8>>()
+        }),
+    );
+    if config.format.freq_float {
+        (fields, finite_f32(), finite_f32())
+            .prop_map(|((stat, phasors, analog, digital), freq, dfreq)| {
+                PMUFrameType::Floating(PMUDataFrame {
+                    stat,
+                    phasors,
+                    freq,
+                    dfreq,
+                    analog,
+                    digital,
+                })
+            })
+            .boxed()
+    } else {
+        (fields, any::<i16>(), any::<i16>())
+            .prop_map(|((stat, phasors, analog, digital), freq, dfreq)| {
+                PMUFrameType::Fixed(PMUDataFrame {
+                    stat,
+                    phasors,
+                    freq,
+                    dfreq,
+                    analog,
+                    digital,
+                })
+            })
+            .boxed()
+    }
+}
+
+// A configuration and a data frame matching it, FRAMESIZE and CHK left at 0.
+fn arb_config_and_data() -> impl Strategy<Value = (ConfigurationFrame1and2_2011, DataFrame2011)> {
+    arb_config()
+        .prop_flat_map(|config| {
+            let pmus: Vec<_> = config.pmu_configs.iter().map(arb_pmu_data).collect();
+            (Just(config), pmus, any::<u32>(), 0u32..0x0100_0000)
+        })
+        .prop_map(|(config, data, soc, fracsec)| {
+            let frame = DataFrame2011 {
+                prefix: PrefixFrame2011 {
+                    sync: 0xAA01,
+                    framesize: 0,
+                    idcode: config.prefix.idcode,
+                    soc,
+                    fracsec,
+                },
+                data,
+                chk: 0,
+            };
+            (config, frame)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{arb_config, arb_config_and_data};
+    #[cfg(feature = "arrow")]
+    use arrow::array::{Array, Float32Array, Int16Array, UInt16Array};
+    #[cfg(feature = "arrow")]
+    use pmu::arrow_utils::build_record_batch;
+    use pmu::frame_parser::{parse_config_frame_1and2, parse_data_frames};
+    use pmu::frames::{calculate_crc, PMUFrameType, PMUValues};
+    use proptest::prelude::*;
+
+    // FRAMESIZE and CHK as serialized.
+    fn framing(bytes: &[u8]) -> (u16, u16) {
+        (bytes.len() as u16, calculate_crc(&bytes[..bytes.len() - 2]))
+    }
+
+    #[cfg(feature = "arrow")]
+    fn column<'a, T: 'static>(batch: &'a arrow::record_batch::RecordBatch, name: &str) -> &'a T {
+        batch
+            .column_by_name(name)
+            .unwrap_or_else(|| panic!("No column {}", name))
+            .as_any()
+            .downcast_ref::<T>()
+            .unwrap_or_else(|| panic!("Column {} has another type", name))
+    }
+
+    proptest! {
+        #[test]
+        fn test_config_round_trip(config in arb_config()) {
+            let bytes = config.to_hex();
+            let mut expected = config.clone();
+            (expected.prefix.framesize, expected.chk) = framing(&bytes);
+            let parsed = parse_config_frame_1and2(&bytes).unwrap();
+            prop_assert_eq!(&parsed, &expected);
+            prop_assert_eq!(parsed.to_hex(), bytes);
+        }
+
+        #[test]
+        fn test_data_round_trip((config, frame) in arb_config_and_data()) {
+            let bytes = frame.to_hex();
+            prop_assert_eq!(bytes.len(), config.calc_data_frame_size());
+
+            let parsed = parse_data_frames(&bytes, &config).unwrap();
+            let mut expected = frame;
+            (expected.prefix.framesize, expected.chk) = framing(&bytes);
+            prop_assert_eq!(&parsed, &expected);
+            prop_assert_eq!(parsed.to_hex(), bytes);
+        }
+
+        #[cfg(feature = "arrow")]
+        #[test]
+        fn test_arrow_matches_parser((config, frame) in arb_config_and_data()) {
+            let bytes = frame.to_hex();
+            let frame = parse_data_frames(&bytes, &config).unwrap();
+            let batch =
+                build_record_batch(&bytes, bytes.len(), &config.get_channel_map()).unwrap();
+            prop_assert_eq!(batch.num_rows(), 1);
+
+            for (pmu_config, pmu) in config.pmu_configs.iter().zip(&frame.data) {
+                let prefix = format!("STN{}_{}", pmu_config.idcode - 100, pmu_config.idcode);
+                let (stat, phasors, analogs, digitals) = match pmu {
+                    PMUFrameType::Fixed(pmu) => {
+                        let freq: &Int16Array = column(&batch, &format!("{}_FREQ", prefix));
+                        let dfreq: &Int16Array = column(&batch, &format!("{}_DFREQ", prefix));
+                        prop_assert_eq!((freq.value(0), dfreq.value(0)), (pmu.freq, pmu.dfreq));
+                        (pmu.stat, pmu.parse_phasors(pmu_config), pmu.parse_analogs(pmu_config), pmu.parse_digitals())
+                    }
+                    PMUFrameType::Floating(pmu) => {
+                        let freq: &Float32Array = column(&batch, &format!("{}_FREQ", prefix));
+                        let dfreq: &Float32Array = column(&batch, &format!("{}_DFREQ", prefix));
+                        prop_assert_eq!((freq.value(0), dfreq.value(0)), (pmu.freq, pmu.dfreq));
+                        (pmu.stat, pmu.parse_phasors(pmu_config), pmu.parse_analogs(pmu_config), pmu.parse_digitals())
+                    }
+                };
+                let stat_column: &UInt16Array = column(&batch, &format!("{}_STAT", prefix));
+                prop_assert_eq!(stat_column.value(0), stat);
+
+                for (k, phasor) in phasors.iter().enumerate() {
+                    let name = format!("{}_PH{}", prefix, k);
+                    match phasor {
+                        PMUValues::Float(values) => {
+                            let first: &Float32Array = column(&batch, &format!("{}_magnitude", name));
+                            let second: &Float32Array = column(&batch, &format!("{}_angle", name));
+                            prop_assert_eq!(vec![first.value(0), second.value(0)], values.clone());
+                        }
+                        // Polar magnitudes are unsigned
+                        PMUValues::Fixed(values) if pmu_config.is_phasor_polar() => {
+                            let first: &UInt16Array = column(&batch, &format!("{}_X", name));
+                            let second: &Int16Array = column(&batch, &format!("{}_Y", name));
+                            prop_assert_eq!(first.value(0), values[0] as u16);
+                            prop_assert_eq!(second.value(0), values[1]);
+                        }
+                        PMUValues::Fixed(values) => {
+                            let first: &Int16Array = column(&batch, &format!("{}_X", name));
+                            let second: &Int16Array = column(&batch, &format!("{}_Y", name));
+                            prop_assert_eq!(vec![first.value(0), second.value(0)], values.clone());
+                        }
+                    }
+                }
+                match analogs {
+                    PMUValues::Float(values) => {
+                        for (k, value) in values.iter().enumerate() {
+                            let analog: &Float32Array = column(&batch, &format!("{}_AN{}", prefix, k));
+                            prop_assert_eq!(analog.value(0), *value);
+                        }
+                    }
+                    PMUValues::Fixed(values) => {
+                        for (k, value) in values.iter().enumerate() {
+                            let analog: &Int16Array = column(&batch, &format!("{}_AN{}", prefix, k));
+                            prop_assert_eq!(analog.value(0), *value);
+                        }
+                    }
+                }
+                for (word, value) in digitals.iter().enumerate() {
+                    let digital: &UInt16Array = column(&batch, &format!("{}_DG{}_0", prefix, word));
+                    prop_assert_eq!(digital.value(0), *value);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+#![allow(unused)]
+mod common;
+
+use common::{read_hex_file, with_crc};
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::frames::ConfigurationFrame1and2_2011;
+use pmu::simulator::{Scenario, Simulator};
+
+fn config() -> ConfigurationFrame1and2_2011 {
+    parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap()
+}
+
+fn frames(count: usize) -> Vec<Vec<u8>> {
+    let scenario = Scenario {
+        start_soc: Some(1_700_000_000),
+        ..Default::default()
+    };
+    let mut simulator = Simulator::new(config(), scenario);
+    (0..count)
+        .flat_map(|_| simulator.next_tick().frames)
+        .collect()
+}
+
+// A frame with FRAMESIZE set to declared and CHK over its bytes.
+fn declaring(frame: &[u8], declared: u16) -> Vec<u8> {
+    let mut frame = frame.to_vec();
+    frame[2..4].copy_from_slice(&declared.to_be_bytes());
+    with_crc(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "arrow")]
+    use pmu::accumulator::{AccumulatorError, BatchAccumulator};
+    use pmu::budget::MemoryBudget;
+    use pmu::frame_parser::parse_data_frames;
+    use pmu::frames::CrcMode;
+    use pmu::framesize::{
+        check_frame_size, repair_frame_size, FrameSizeIssue, FrameSizePolicy, FrameSizeStats,
+    };
+
+    #[test]
+    fn test_check_frame_size() {
+        let frame = frames(1).remove(0);
+        let size = config().calc_data_frame_size();
+        assert_eq!(frame.len(), size);
+        assert_eq!(check_frame_size(&frame, size), None);
+        assert_eq!(
+            check_frame_size(&frame[..size - 4], size),
+            Some(FrameSizeIssue::TooShort {
+                expected: size,
+                actual: size - 4
+            })
+        );
+        let mut padded = frame.clone();
+        padded.extend_from_slice(&[0, 0, 0]);
+        assert_eq!(
+            check_frame_size(&padded, size),
+            Some(FrameSizeIssue::TrailingBytes {
+                expected: size,
+                actual: size + 3
+            })
+        );
+        let lying = declaring(&frame, size as u16 + 2);
+        let issue = check_frame_size(&lying, size).unwrap();
+        assert_eq!(
+            issue,
+            FrameSizeIssue::DeclaredMismatch {
+                expected: size,
+                declared: size + 2
+            }
+        );
+        assert_eq!(
+            issue.to_string(),
+            format!(
+                "FRAMESIZE {} in a frame of the {} bytes the configuration gives",
+                size + 2,
+                size
+            )
+        );
+    }
+
+    #[test]
+    fn test_repair_frame_size() {
+        let config = config();
+        let frame = frames(1).remove(0);
+        let size = frame.len();
+
+        // Garbage after a frame whose CHK is where the configuration puts it
+        let mut padded = frame.clone();
+        padded.extend_from_slice(&[0xDE, 0xAD]);
+        assert_eq!(
+            repair_frame_size(&padded, size, CrcMode::Standard),
+            Some(frame.clone())
+        );
+        // FRAMESIZE counting the padding, CHK after it
+        let mut counted = frame[..size - 2].to_vec();
+        counted.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        let counted = declaring(&counted, size as u16 + 4);
+        let repaired = repair_frame_size(&counted, size, CrcMode::Standard).unwrap();
+        assert_eq!(repaired.len(), size);
+        assert!(CrcMode::Standard.check(&repaired));
+        assert!(parse_data_frames(&repaired, &config).is_ok());
+        // The right size, FRAMESIZE wrong
+        let lying = declaring(&frame, size as u16 - 2);
+        assert!(parse_data_frames(&lying, &config).is_err());
+        assert_eq!(
+            repair_frame_size(&lying, size, CrcMode::Standard),
+            Some(frame.clone())
+        );
+
+        // CHK checking at a FRAMESIZE short of the configured size, the bytes
+        // after it unverified
+        let mut short = frame[..size - 6].to_vec();
+        short.extend_from_slice(&[0, 0]);
+        let mut short = declaring(&short, size as u16 - 4);
+        short.extend_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(short.len(), size);
+        assert!(CrcMode::Standard.check(&short[..size - 4]));
+        assert_eq!(repair_frame_size(&short, size, CrcMode::Standard), None);
+
+        // Nothing vouching for the bytes, or too few of them
+        let mut damaged = padded.clone();
+        damaged[20] ^= 0xFF;
+        assert_eq!(repair_frame_size(&damaged, size, CrcMode::Standard), None);
+        assert_eq!(
+            repair_frame_size(&frame[..size - 2], size, CrcMode::Standard),
+            None
+        );
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_accumulator_policies() {
+        let config = config();
+        let frames = frames(6);
+        let size = frames[0].len();
+        let lying = declaring(&frames[1], size as u16 + 8);
+        let mut padded = frames[2].clone();
+        padded.extend_from_slice(&[0; 5]);
+        let short = frames[3][..size - 6].to_vec();
+
+        // Strict by default: the declared size is reported for a mismatch
+        let mut strict = BatchAccumulator::new(MemoryBudget::unlimited());
+        strict.add_stream(&config);
+        assert!(matches!(
+            strict.push_frame(&lying),
+            Err(AccumulatorError::InvalidFrameSize { expected, actual })
+                if expected == size && actual == size + 8
+        ));
+        assert!(matches!(
+            strict.push_frame(&padded),
+            Err(AccumulatorError::InvalidFrameSize { actual, .. }) if actual == size + 5
+        ));
+        assert_eq!(
+            strict.frame_size_stats(7734),
+            Some(&FrameSizeStats {
+                trailing_bytes: 1,
+                declared_mismatch: 1,
+                ..Default::default()
+            })
+        );
+
+        let mut lenient = BatchAccumulator::new(MemoryBudget::unlimited());
+        lenient.set_frame_size_policy(7734, FrameSizePolicy::Lenient);
+        lenient.add_stream(&config);
+        let pushed = [&frames[0], &lying, &padded, &short, &frames[4]];
+        let results: Vec<bool> = pushed
+            .iter()
+            .map(|frame| lenient.push_frame(frame).is_ok())
+            .collect();
+        assert_eq!(results, vec![true, true, true, false, true]);
+        let stats = lenient.frame_size_stats(7734).unwrap();
+        assert_eq!((stats.total(), stats.too_short, stats.repaired), (3, 1, 2));
+        let batch = lenient.flush(7734).unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 4);
+    }
+
+    #[test]
+    fn test_policy_from_json() {
+        let policy: FrameSizePolicy = serde_json::from_str(r#""lenient""#).unwrap();
+        assert_eq!(policy, FrameSizePolicy::Lenient);
+        assert_eq!(FrameSizePolicy::default(), FrameSizePolicy::Strict);
+    }
+}
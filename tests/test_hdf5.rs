@@ -0,0 +1,159 @@
+#![cfg(feature = "hdf5")]
+#![allow(unused)]
+mod common;
+
+use common::sample_batch;
+use pmu::sinks::hdf5::{lookup3, Hdf5Sink};
+use pmu::sinks::BatchSink;
+use std::collections::HashMap;
+use std::fs;
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+// The messages of the object header at address, checking its checksum.
+fn messages(file: &[u8], address: u64) -> Vec<(u8, Vec<u8>)> {
+    let at = address as usize;
+    assert_eq!(&file[at..at + 4], b"OHDR");
+    assert_eq!(file[at + 4], 2);
+    let size = u32::from_le_bytes(file[at + 6..at + 10].try_into().unwrap()) as usize;
+    let end = at + 10 + size;
+    let checksum = u32::from_le_bytes(file[end..end + 4].try_into().unwrap());
+    assert_eq!(lookup3(&file[at..end]), checksum);
+    let mut messages = Vec::new();
+    let mut pos = at + 10;
+    while pos < end {
+        let len = u16::from_le_bytes([file[pos + 1], file[pos + 2]]) as usize;
+        messages.push((file[pos], file[pos + 4..pos + 4 + len].to_vec()));
+        pos += 4 + len;
+    }
+    messages
+}
+
+// The hard links of a group by name.
+fn links(file: &[u8], address: u64) -> HashMap<String, u64> {
+    let mut links = HashMap::new();
+    for (kind, body) in messages(file, address) {
+        if kind != 0x06 {
+            continue;
+        }
+        assert_eq!(body[0..3], [1, 0x10, 1]);
+        let len = body[3] as usize;
+        let name = String::from_utf8(body[4..4 + len].to_vec()).unwrap();
+        links.insert(name, u64_at(&body, 4 + len));
+    }
+    links
+}
+
+// The rows of a dataset and its attributes, as raw bytes.
+fn dataset(file: &[u8], address: u64) -> (Vec<u8>, HashMap<String, Vec<u8>>) {
+    let mut data = Vec::new();
+    let mut attributes = HashMap::new();
+    for (kind, body) in messages(file, address) {
+        match kind {
+            0x08 => {
+                assert_eq!(body[0..2], [3, 1]);
+                let at = u64_at(&body, 2) as usize;
+                let len = u64_at(&body, 10) as usize;
+                data = file[at..at + len].to_vec();
+            }
+            0x0C => {
+                let name_len = u16::from_le_bytes([body[2], body[3]]) as usize;
+                let type_len = u16::from_le_bytes([body[4], body[5]]) as usize;
+                let space_len = u16::from_le_bytes([body[6], body[7]]) as usize;
+                let name = String::from_utf8(body[9..8 + name_len].to_vec()).unwrap();
+                let value = body[9 + name_len + type_len + space_len..].to_vec();
+                attributes.insert(name, value);
+            }
+            _ => {}
+        }
+    }
+    (data, attributes)
+}
+
+fn f64s(bytes: &[u8]) -> Vec<f64> {
+    bytes
+        .chunks(8)
+        .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup3_checksum() {
+        assert_eq!(lookup3(b""), 0xdeadbeef);
+        assert_eq!(lookup3(b"Four score and seven years ago"), 0x17770551);
+    }
+
+    #[test]
+    fn test_hdf5_layout() {
+        let batch = sample_batch();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sample.h5");
+        let mut sink = Hdf5Sink::new(&path).unwrap();
+        sink.write_batch(&batch).unwrap();
+        sink.write_batch(&batch).unwrap();
+        assert_eq!(sink.rows(), 2);
+        sink.close().unwrap();
+        assert!(sink.write_batch(&batch).is_err());
+
+        let file = fs::read(&path).unwrap();
+        assert_eq!(&file[..8], b"\x89HDF\r\n\x1a\n");
+        assert_eq!(file[8], 2);
+        assert_eq!(
+            lookup3(&file[..44]),
+            u32::from_le_bytes(file[44..48].try_into().unwrap())
+        );
+        assert_eq!(u64_at(&file, 28), file.len() as u64);
+
+        let root = links(&file, u64_at(&file, 36));
+        assert_eq!(root.keys().collect::<Vec<_>>(), ["Station A"]);
+        let station = links(&file, root["Station A"]);
+        let stream = links(&file, station["7734"]);
+        assert!(stream.contains_key("timestamp"));
+        assert!(stream.contains_key("VA_X"));
+        assert!(!stream.keys().any(|name| name.starts_with("Station A")));
+
+        let (data, attributes) = dataset(&file, stream["FREQ"]);
+        let scale = f64::from_le_bytes(attributes["scale"][..].try_into().unwrap());
+        let offset = f64::from_le_bytes(attributes["offset"][..].try_into().unwrap());
+        let values = f64s(&data);
+        assert_eq!(values.len(), 2);
+        assert!((values[0] * scale + offset - 62.5).abs() < 1e-6);
+        assert_eq!(attributes["channel"], b"Station A_7734_FREQ");
+
+        let (data, _) = dataset(&file, stream["timestamp"]);
+        let timestamps: Vec<i64> = data
+            .chunks(8)
+            .map(|chunk| i64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        assert_eq!(timestamps.len(), 2);
+        assert_eq!(timestamps[0], timestamps[1]);
+    }
+
+    #[test]
+    fn test_hdf5_late_channels() {
+        let batch = sample_batch();
+        let freq = batch.schema().index_of("Station A_7734_FREQ").unwrap();
+        let columns: Vec<usize> = (0..batch.num_columns()).filter(|&i| i != freq).collect();
+        let without_freq = batch.project(&columns).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = Hdf5Sink::new(dir.path().join("late.h5")).unwrap();
+        sink.write_batch(&without_freq).unwrap();
+        sink.write_batch(&batch).unwrap();
+        sink.write_batch(&without_freq).unwrap();
+        let file = sink.to_bytes();
+
+        let root = links(&file, u64_at(&file, 36));
+        let stream = links(&file, links(&file, root["Station A"])["7734"]);
+        let values = f64s(&dataset(&file, stream["FREQ"]).0);
+        assert_eq!(values.len(), 3);
+        assert!(values[0].is_nan() && values[2].is_nan());
+        assert!(!values[1].is_nan());
+    }
+}
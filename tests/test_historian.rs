@@ -0,0 +1,530 @@
+#![cfg(feature = "arrow")]
+#![allow(unused)]
+mod common;
+
+use arrow::array::{Array, TimestampMicrosecondArray};
+use arrow::record_batch::RecordBatch;
+use common::{data_frame_us, read_hex_file, timestamps, with_crc};
+use pmu::budget::MemoryBudget;
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::frames::ConfigurationFrame1and2_2011;
+use pmu::historian::{
+    Continuation, Historian, HistorianError, HistorianQuery, Resolution, DEFAULT_MAX_QUERY_ROWS,
+};
+use pmu::rollup::RollupTier;
+use pmu::segments::{SegmentConfig, SegmentStore};
+use pmu::simulator::{Scenario, ScenarioEvent, Simulator};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+const SOC: u32 = 1_700_000_000;
+const START_US: i64 = SOC as i64 * 1_000_000;
+
+// The sample data frame n frames into SOC at 30 frames/s.
+fn nth_frame(n: u32) -> Vec<u8> {
+    let fracsec = (n % 30) * 1_000_000 / 30;
+    data_frame_us(7734, (SOC + n / 30) as i64 * 1_000_000 + fracsec as i64)
+}
+
+fn config() -> ConfigurationFrame1and2_2011 {
+    parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap()
+}
+
+// Two seconds of the sample stream.
+fn historian() -> Historian {
+    let mut historian = Historian::new(MemoryBudget::unlimited());
+    historian.add_stream(&config());
+    for n in 0..60 {
+        historian.insert(&nth_frame(n)).unwrap();
+    }
+    historian
+}
+
+// Ten frames in memory, the others spilled to segments of 16 frames.
+fn spilling(dir: &Path, max_bytes: u64) -> Historian {
+    let frame_size = config().calc_data_frame_size() as u64;
+    let segments = SegmentConfig::new(dir).with_limits(32 + 16 * (8 + frame_size), max_bytes);
+    let mut historian = Historian::new(MemoryBudget::unlimited().with_max_rows(10))
+        .with_segments(SegmentStore::open(segments).unwrap());
+    historian.add_stream(&config());
+    historian
+}
+
+fn segment_files(dir: &Path) -> usize {
+    fs::read_dir(dir).unwrap().count()
+}
+
+// 25 seconds of the sample stream, frequency rising 1 Hz/s, with 1 and 10
+// second rollups and no more than a second of raw frames.
+fn ramping() -> Historian {
+    let mut historian =
+        Historian::new(MemoryBudget::unlimited().with_max_rows(30)).with_rollups(vec![
+            RollupTier::new(Duration::from_secs(10), Duration::from_secs(3600)),
+            RollupTier::new(Duration::from_secs(1), Duration::from_secs(3600)),
+        ]);
+    historian.add_stream(&config());
+    let scenario = Scenario {
+        start_soc: Some(SOC),
+        events: vec![ScenarioEvent::FrequencyRamp {
+            at: 0.0,
+            duration: 30.0,
+            rate: 1.0,
+        }],
+        ..Default::default()
+    };
+    let mut simulator = Simulator::new(config(), scenario);
+    for _ in 0..25 * 30 {
+        for frame in simulator.next_tick().frames {
+            historian.insert(&frame).unwrap();
+        }
+    }
+    historian
+}
+
+fn float_column(batch: &RecordBatch, name: &str) -> Vec<f64> {
+    batch
+        .column_by_name(name)
+        .unwrap()
+        .as_any()
+        .downcast_ref::<arrow::array::Float64Array>()
+        .unwrap()
+        .values()
+        .to_vec()
+}
+
+// Timestamps of all pages of a query, and the number of pages.
+fn read_pages(historian: &Historian, query: HistorianQuery) -> (Vec<i64>, usize) {
+    let mut rows = Vec::new();
+    let mut pages = 0;
+    let mut query = query;
+    loop {
+        let page = historian.query_page(&query).unwrap();
+        pages += 1;
+        rows.extend(page.batch.as_ref().map(timestamps).unwrap_or_default());
+        match page.next {
+            Some(next) => query = query.after(next),
+            None => return (rows, pages),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_half_open_range() {
+        let historian = historian();
+        let second = historian
+            .query(7734, START_US, START_US + 1_000_000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.num_rows(), 30);
+        assert_eq!(timestamps(&second)[0], START_US);
+        // The frame at the end goes with the next range
+        let page = historian
+            .query_page(&HistorianQuery::new(
+                7734,
+                START_US + 1_000_000,
+                START_US + 2_000_000,
+            ))
+            .unwrap();
+        assert_eq!(page.batch.unwrap().num_rows(), 30);
+        assert!(page.next.is_none());
+        assert!(historian.query(7734, START_US, START_US).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_stream_time_base() {
+        let mut config = config();
+        config.time_base = 30;
+        let mut historian = Historian::new(MemoryBudget::unlimited());
+        historian.add_stream(&config);
+        for n in 0..30u32 {
+            let mut frame = nth_frame(0);
+            frame[10..14].copy_from_slice(&n.to_be_bytes());
+            historian.insert(&with_crc(frame)).unwrap();
+        }
+        // FRACSEC counts thirtieths of a second
+        let batch = historian
+            .query(7734, START_US, START_US + 1_000_000)
+            .unwrap()
+            .unwrap();
+        let times = timestamps(&batch);
+        assert_eq!(times.len(), 30);
+        assert_eq!(times[0], START_US);
+        assert_eq!(*times.last().unwrap(), START_US + 966_667);
+    }
+
+    #[test]
+    fn test_pages() {
+        let mut historian = historian();
+        let all = timestamps(
+            &historian
+                .query(7734, START_US, START_US + 2_000_000)
+                .unwrap()
+                .unwrap(),
+        );
+        let query = HistorianQuery::new(7734, START_US, START_US + 2_000_000);
+        let (rows, pages) = read_pages(&historian, query.clone().with_max_rows(7));
+        assert_eq!(rows, all);
+        assert_eq!(pages, 9);
+
+        // The historian's limit applies whatever the query asks
+        let historian = historian.with_max_query_rows(25);
+        let (rows, pages) = read_pages(&historian, query.with_max_rows(1_000));
+        assert_eq!(rows, all);
+        assert_eq!(pages, 3);
+    }
+
+    #[test]
+    fn test_pages_split_at_a_repeated_timestamp() {
+        let mut historian = historian();
+        // Frames 10 and 11 held three times each
+        for n in [10, 10, 11, 11] {
+            historian.insert(&nth_frame(n)).unwrap();
+        }
+        let query = HistorianQuery::new(7734, START_US, START_US + 1_000_000).with_max_rows(2);
+        let page = historian
+            .query_page(&query.clone().after(Continuation {
+                timestamp_us: START_US + 333_333,
+                skip: 0,
+            }))
+            .unwrap();
+        assert_eq!(
+            page.next,
+            Some(Continuation {
+                timestamp_us: START_US + 333_333,
+                skip: 2
+            })
+        );
+        let (rows, _) = read_pages(&historian, query);
+        assert_eq!(rows.len(), 34);
+        assert!(rows.windows(2).all(|pair| pair[0] <= pair[1]));
+        let repeated = |t: i64| rows.iter().filter(|&&row| row == t).count();
+        assert_eq!(repeated(START_US + 333_333), 3);
+        assert_eq!(repeated(START_US + 366_666), 3);
+    }
+
+    #[test]
+    fn test_channel_projection() {
+        let historian = historian();
+        let query = HistorianQuery::new(7734, START_US, START_US + 1_000_000)
+            .with_channels(&["Station A_7734_FREQ", "Station A_7734_VA"]);
+        let batch = historian.query_page(&query).unwrap().batch.unwrap();
+        let mut names: Vec<String> = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "Station A_7734_FREQ",
+                "Station A_7734_VA_X",
+                "Station A_7734_VA_Y",
+                "timestamp"
+            ]
+        );
+        assert_eq!(batch.num_rows(), 30);
+
+        let query = HistorianQuery::new(7734, START_US, START_US + 1_000_000)
+            .with_channels(&["Station A_7734_XX"]);
+        assert!(matches!(
+            historian.query_page(&query),
+            Err(HistorianError::UnknownChannel(name)) if name == "Station A_7734_XX"
+        ));
+    }
+
+    #[test]
+    fn test_continuation_token() {
+        let next = Continuation {
+            timestamp_us: START_US + 33_333,
+            skip: 2,
+        };
+        assert_eq!(next.to_string(), "1700000000033333.2");
+        assert_eq!(next.to_string().parse::<Continuation>().unwrap(), next);
+        assert!(matches!(
+            "1700000000033333".parse::<Continuation>(),
+            Err(HistorianError::InvalidContinuation(_))
+        ));
+        assert_eq!(DEFAULT_MAX_QUERY_ROWS, 100_000);
+    }
+
+    #[test]
+    fn test_spill_to_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut historian = spilling(dir.path(), u64::MAX);
+        for n in 0..60 {
+            historian.insert(&nth_frame(n)).unwrap();
+        }
+        assert_eq!(historian.usage(7734).unwrap().rows, 10);
+        assert_eq!(historian.disk_frames(7734), 50);
+        assert_eq!(historian.evicted(7734), Some(0));
+        assert_eq!(segment_files(dir.path()), 4);
+        assert_eq!(
+            historian.time_range(7734),
+            Some((START_US, START_US + 1_966_666))
+        );
+
+        // Queries read disk and memory alike
+        let all = historian
+            .query(7734, START_US, START_US + 2_000_000)
+            .unwrap()
+            .unwrap();
+        let expected: Vec<i64> = (0..60)
+            .map(|n| START_US + (n / 30) * 1_000_000 + (n % 30) * 1_000_000 / 30)
+            .collect();
+        assert_eq!(timestamps(&all), expected);
+        let query = HistorianQuery::new(7734, START_US + 900_000, START_US + 2_000_000);
+        let (rows, _) = read_pages(&historian, query.with_max_rows(8));
+        assert_eq!(rows, expected[27..]);
+
+        // A late frame older than the frames in memory is spilled to a
+        // segment of its own, out of order with the last one
+        historian.patch(&nth_frame(5)).unwrap();
+        let batch = historian
+            .query(7734, START_US, START_US + 200_000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(batch.num_rows(), 7);
+        assert_eq!(segment_files(dir.path()), 5);
+    }
+
+    #[test]
+    fn test_segments_taken_up_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut historian = spilling(dir.path(), u64::MAX);
+            for n in 0..60 {
+                historian.insert(&nth_frame(n)).unwrap();
+            }
+        }
+        let historian = spilling(dir.path(), u64::MAX);
+        assert_eq!(historian.disk_frames(7734), 50);
+        assert_eq!(
+            historian.time_range(7734),
+            Some((START_US, START_US + 1_633_333))
+        );
+        let batch = historian
+            .query(7734, START_US, START_US + 2_000_000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(batch.num_rows(), 50);
+
+        // Not those of another configuration
+        let mut historian = spilling(dir.path(), u64::MAX);
+        let mut config = config();
+        config.data_rate = 60;
+        historian.add_stream(&config);
+        assert_eq!(historian.disk_frames(7734), 0);
+        assert_eq!(segment_files(dir.path()), 0);
+    }
+
+    #[test]
+    fn test_oldest_segments_deleted_past_max_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let frame_size = config().calc_data_frame_size() as u64;
+        let segment_size = 32 + 16 * (8 + frame_size);
+        let mut historian = spilling(dir.path(), 2 * segment_size);
+        for n in 0..60 {
+            historian.insert(&nth_frame(n)).unwrap();
+        }
+        // 50 spilled, the first two segments deleted
+        assert_eq!(segment_files(dir.path()), 2);
+        assert_eq!(historian.disk_frames(7734), 18);
+        assert_eq!(historian.evicted(7734), Some(32));
+        assert_eq!(historian.time_range(7734).unwrap().0, START_US + 1_066_666);
+    }
+
+    #[test]
+    fn test_rollup_tiers() {
+        let historian = ramping();
+        let end_us = START_US + 25_000_000;
+        // Raw frames of the last second only
+        assert_eq!(historian.time_range(7734).unwrap().0, START_US + 24_000_000);
+
+        let seconds = historian
+            .query_rollup(7734, Duration::from_secs(1), START_US, end_us)
+            .unwrap()
+            .unwrap();
+        // The last second still open
+        assert_eq!(seconds.num_rows(), 25);
+        assert_eq!(timestamps(&seconds)[3], START_US + 3_000_000);
+        let frames = seconds
+            .column_by_name("frames")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<arrow::array::UInt64Array>()
+            .unwrap();
+        assert!(frames.values().iter().all(|frames| *frames == 30));
+        let min = float_column(&seconds, "Station A_7734_FREQ_min");
+        let mean = float_column(&seconds, "Station A_7734_FREQ_mean");
+        let max = float_column(&seconds, "Station A_7734_FREQ_max");
+        for second in [0, 5, 24] {
+            assert!(min[second] < mean[second] && mean[second] < max[second]);
+            assert!((max[second] - min[second] - 29.0 / 30.0).abs() < 0.01);
+        }
+        assert!((mean[5] - mean[4] - 1.0).abs() < 0.01);
+
+        let tens = historian
+            .query_rollup(7734, Duration::from_secs(10), START_US + 10_000_000, end_us)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            timestamps(&tens),
+            [START_US + 10_000_000, START_US + 20_000_000]
+        );
+        let frames = tens
+            .column_by_name("frames")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<arrow::array::UInt64Array>()
+            .unwrap();
+        // The open row has the closed seconds 20 to 23
+        assert_eq!(frames.values().to_vec(), [300, 120]);
+        let tens_min = float_column(&tens, "Station A_7734_FREQ_min");
+        let tens_max = float_column(&tens, "Station A_7734_FREQ_max");
+        assert_eq!(tens_min[0], min[10]);
+        assert_eq!(tens_max[0], max[19]);
+
+        assert!(matches!(
+            historian.query_rollup(7734, Duration::from_secs(60), START_US, end_us),
+            Err(HistorianError::UnknownRollup(60_000_000))
+        ));
+        assert_eq!(historian.rollup_late(7734), Some(0));
+    }
+
+    #[test]
+    fn test_query_downsampled() {
+        let mut historian = ramping();
+        let end_us = START_US + 25_000_000;
+        assert_eq!(
+            historian.resolution(7734, end_us - 1_000_000, end_us, 100),
+            Resolution::Raw
+        );
+        assert_eq!(
+            historian.resolution(7734, START_US, end_us, 100),
+            Resolution::Rollup(1_000_000)
+        );
+        assert_eq!(
+            historian.resolution(7734, START_US, end_us, 3),
+            Resolution::Rollup(10_000_000)
+        );
+        assert_eq!(
+            historian.resolution(7734, START_US, end_us, 1),
+            Resolution::Rollup(10_000_000)
+        );
+
+        let (resolution, batch) = historian
+            .query_downsampled(7734, START_US, end_us, 3)
+            .unwrap();
+        assert_eq!(resolution, Resolution::Rollup(10_000_000));
+        assert_eq!(batch.unwrap().num_rows(), 3);
+        let (resolution, batch) = historian
+            .query_downsampled(7734, end_us - 1_000_000, end_us, 100)
+            .unwrap();
+        assert_eq!(resolution, Resolution::Raw);
+        assert_eq!(batch.unwrap().num_rows(), 30);
+
+        // A frame of a second already summarized
+        historian.patch(&nth_frame(30)).unwrap();
+        assert_eq!(historian.rollup_late(7734), Some(1));
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let scenario = Scenario {
+            start_soc: Some(SOC),
+            events: vec![
+                ScenarioEvent::PhaseJump {
+                    at: 1.0,
+                    degrees: 200.0,
+                },
+                ScenarioEvent::VoltageSag {
+                    at: 1.0,
+                    duration: 10.0,
+                    depth: 0.25,
+                },
+            ],
+            ..Default::default()
+        };
+        let mut simulator = Simulator::new(config(), scenario);
+        let mut historian = Historian::new(MemoryBudget::unlimited());
+        historian.add_stream(&config());
+        for _ in 0..60 {
+            for frame in simulator.next_tick().frames {
+                historian.insert(&frame).unwrap();
+            }
+        }
+
+        // Between frames, the last ones before each time are compared
+        let t1 = START_US + 500_000 + 10;
+        let t2 = START_US + 1_500_000 + 10;
+        let diff = historian.snapshot_diff(t1, t2).unwrap().unwrap();
+        let strings = |name: &str| -> Vec<String> {
+            let column = diff.column_by_name(name).unwrap();
+            let column = column
+                .as_any()
+                .downcast_ref::<arrow::array::StringArray>()
+                .unwrap();
+            column.iter().map(|v| v.unwrap().to_string()).collect()
+        };
+        let times = |name: &str| {
+            diff.column_by_name(name)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<TimestampMicrosecondArray>()
+                .unwrap()
+                .value(0)
+        };
+        assert_eq!(times("timestamp_1"), START_US + 500_000);
+        assert_eq!(times("timestamp_2"), START_US + 1_500_000);
+        let channels = strings("channel");
+        let quantities = strings("quantity");
+        let rows: Vec<(&str, &str)> = channels
+            .iter()
+            .zip(&quantities)
+            .map(|(c, q)| (c.as_str(), q.as_str()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("Station A_7734_FREQ", "frequency"),
+                ("Station A_7734_I1", "magnitude"),
+                ("Station A_7734_I1", "angle"),
+                ("Station A_7734_VA", "magnitude"),
+                ("Station A_7734_VA", "angle"),
+                ("Station A_7734_VB", "magnitude"),
+                ("Station A_7734_VB", "angle"),
+                ("Station A_7734_VC", "magnitude"),
+                ("Station A_7734_VC", "angle"),
+            ]
+        );
+        assert!(strings("station").iter().all(|s| s == "Station A"));
+
+        let values = |name: &str| float_column(&diff, name);
+        let (value_1, delta) = (values("value_1"), values("delta"));
+        let units = strings("unit");
+        assert_eq!((units[0].as_str(), units[4].as_str()), ("Hz", "deg"));
+        // Steady frequency
+        assert!(delta[0].abs() < 1e-6, "{:?}", delta);
+        for row in [3, 5, 7] {
+            // Magnitudes down by a quarter
+            assert!(
+                (delta[row] / value_1[row] + 0.25).abs() < 0.01,
+                "{:?} {:?}",
+                value_1,
+                delta
+            );
+            // 200 degrees ahead is 160 behind
+            assert!((delta[row + 1] + 160.0).abs() < 0.1, "{:?}", delta);
+        }
+
+        // Nothing held at or before the first time
+        assert!(historian.snapshot_diff(START_US - 1, t2).unwrap().is_none());
+    }
+}
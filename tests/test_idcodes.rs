@@ -0,0 +1,163 @@
+#![allow(unused)]
+#[cfg(all(feature = "analytics", feature = "server"))]
+use pmu::dataset::DatasetConfig;
+use pmu::idcodes::{assign_layouts, parse_range, IdcodeError, IdcodeRange, IdcodeRegistry};
+use pmu::simulator::{SimulatedPmu, StreamLayout};
+
+fn pmu(station: &str, idcode: u16) -> SimulatedPmu {
+    SimulatedPmu {
+        station: station.to_string(),
+        idcode,
+        polar: false,
+        float_phasors: false,
+        float_analogs: false,
+        float_freq: false,
+        phasors: 3,
+        analogs: 0,
+        digitals: 0,
+        data_rate: None,
+        nominal_50hz: false,
+        angle: 0.0,
+    }
+}
+
+fn layout(idcode: u16, pmus: Vec<SimulatedPmu>) -> StreamLayout {
+    StreamLayout {
+        idcode,
+        data_rate: 30,
+        time_base: 1_000_000,
+        pmus,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_and_allocate() {
+        let mut registry = IdcodeRegistry::new().with_range(100..=102);
+        registry.register(101, "PMU A").unwrap();
+        registry.register(7734, "PMU B").unwrap(); // Outside the range
+        assert_eq!(
+            registry.register(101, "PMU C"),
+            Err(IdcodeError::Taken {
+                idcode: 101,
+                owner: "PMU A".to_string()
+            })
+        );
+        assert_eq!(
+            registry.register(0, "PMU C").unwrap_err().to_string(),
+            "IDCODE 0 is reserved"
+        );
+        assert_eq!(
+            registry.register(65535, "PMU C"),
+            Err(IdcodeError::Reserved(65535))
+        );
+
+        assert_eq!(registry.allocate("PMU C"), Ok(100));
+        assert_eq!(registry.allocate("PMU D"), Ok(102));
+        assert_eq!(
+            registry.allocate("PMU E").unwrap_err().to_string(),
+            "No free IDCODE left in 100-102"
+        );
+        assert_eq!(registry.owner(102), Some("PMU D"));
+        assert_eq!(registry.len(), 4);
+        assert_eq!(registry.release(101), Some("PMU A".to_string()));
+        assert_eq!(registry.allocate("PMU E"), Ok(101));
+        assert!(!registry.is_free(101));
+        assert!(!registry.is_free(0));
+    }
+
+    #[test]
+    fn test_range_leaves_out_reserved() {
+        let mut registry = IdcodeRegistry::new().with_range(0..=65535);
+        assert_eq!(registry.range(), &(1..=65534));
+        assert_eq!(registry.allocate("PMU A"), Ok(1));
+        assert_eq!(parse_range("100-199"), Ok(100..=199));
+        assert_eq!(parse_range(" 7 - 7 "), Ok(7..=7));
+        assert!(parse_range("199-100").is_err());
+        assert!(parse_range("100").is_err());
+        assert!(parse_range("1-70000").is_err());
+        assert_eq!(IdcodeRange { first: 5, last: 9 }.range(), 5..=9);
+    }
+
+    #[test]
+    fn test_assign_layouts() {
+        let mut layouts = vec![
+            layout(0, vec![pmu("A", 0)]),
+            layout(0, vec![pmu("B", 0), pmu("C", 10)]),
+            layout(20, vec![pmu("D", 0)]),
+        ];
+        assign_layouts(&mut layouts, 10..=30).unwrap();
+        // Given IDCODEs first, then the lowest free ones in order
+        assert_eq!(layouts[0].pmus[0].idcode, 11);
+        assert_eq!(layouts[0].idcode, 11); // The idcode of its only PMU
+        assert_eq!(layouts[1].pmus[0].idcode, 12);
+        assert_eq!(layouts[1].pmus[1].idcode, 10);
+        assert_eq!(layouts[1].idcode, 10);
+        assert_eq!(layouts[2].idcode, 20);
+        assert_eq!(layouts[2].pmus[0].idcode, 13);
+
+        // Collisions name both holders
+        let mut layouts = vec![layout(1, vec![pmu("A", 11)]), layout(2, vec![pmu("B", 11)])];
+        assert_eq!(
+            assign_layouts(&mut layouts, 1..=65534)
+                .unwrap_err()
+                .to_string(),
+            "IDCODE 11 is already used by PMU A"
+        );
+        let mut layouts = vec![layout(1, vec![pmu("A", 11)]), layout(1, vec![])];
+        assert_eq!(
+            assign_layouts(&mut layouts, 1..=65534)
+                .unwrap_err()
+                .to_string(),
+            "IDCODE 1 is already used by stream 1"
+        );
+        let mut layouts = vec![layout(65535, vec![pmu("A", 11)])];
+        assert_eq!(
+            assign_layouts(&mut layouts, 1..=65534),
+            Err(IdcodeError::Reserved(65535))
+        );
+        let mut layouts = vec![layout(0, vec![pmu("A", 0), pmu("B", 0), pmu("C", 0)])];
+        assert_eq!(
+            assign_layouts(&mut layouts, 5..=6),
+            Err(IdcodeError::Exhausted { first: 5, last: 6 })
+        );
+    }
+
+    #[cfg(all(feature = "analytics", feature = "server"))]
+    #[test]
+    fn test_dataset_assigns_idcodes() {
+        let config = DatasetConfig::from_json(
+            r#"{
+                "start_soc": 1700000000,
+                "duration_secs": 1,
+                "idcodes": {"first": 500, "last": 599},
+                "streams": [
+                    {"layout": {"data_rate": 30, "pmus": [{"station": "A"}]}},
+                    {"layout": {"idcode": 500, "data_rate": 30,
+                        "pmus": [{"station": "B"}, {"station": "C", "idcode": 501}]}}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let stream = |index: usize| &config.streams[index].layout;
+        assert_eq!(stream(0).pmus[0].idcode, 500);
+        assert_eq!(stream(0).idcode, 501);
+        assert_eq!(stream(1).pmus[0].idcode, 502);
+
+        let error = DatasetConfig::from_json(
+            r#"{
+                "start_soc": 1700000000,
+                "duration_secs": 1,
+                "streams": [
+                    {"layout": {"idcode": 1, "data_rate": 30, "pmus": [{"station": "A", "idcode": 5}]}},
+                    {"layout": {"idcode": 1, "data_rate": 30, "pmus": [{"station": "B", "idcode": 6}]}}
+                ]
+            }"#,
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "IDCODE 1 is already used by stream 1");
+    }
+}
@@ -0,0 +1,125 @@
+#![cfg(feature = "server")]
+#![allow(unused)]
+mod common;
+
+use arrow::record_batch::RecordBatch;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use common::sample_batch;
+use pmu::ingest::{router, IngestControl, IngestSettings};
+use tower::ServiceExt;
+
+fn column_names(batch: &RecordBatch) -> Vec<String> {
+    batch
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.name().clone())
+        .collect()
+}
+
+async fn request(control: &IngestControl, method: &str, uri: &str) -> (StatusCode, String) {
+    let response = router(control.clone())
+        .oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_channels() {
+        let batch = sample_batch();
+        let all = column_names(&batch);
+        assert!(
+            all.contains(&"Station A_7734_VA_X".to_string()),
+            "{:?}",
+            all
+        );
+
+        let mut settings = IngestSettings::default();
+        assert_eq!(settings.select(&batch).unwrap(), batch);
+        // A channel by name leaves out all its columns, a column by its own
+        settings
+            .disabled_channels
+            .insert("Station A_7734_VA".to_string());
+        settings
+            .disabled_channels
+            .insert("Station A_7734_FREQ".to_string());
+        settings.disabled_channels.insert("timestamp".to_string());
+        let selected = settings.select(&batch).unwrap();
+        let names = column_names(&selected);
+        assert_eq!(names.len(), all.len() - 3, "{:?}", names);
+        assert!(names.contains(&"timestamp".to_string()));
+        assert!(names.contains(&"Station A_7734_VB_X".to_string()));
+        assert!(!names
+            .iter()
+            .any(|name| name.starts_with("Station A_7734_VA")));
+        assert!(!names.contains(&"Station A_7734_FREQ".to_string()));
+        assert_eq!(selected.num_rows(), 1);
+    }
+
+    #[test]
+    fn test_control_notifies_subscribers() {
+        let control = IngestControl::default();
+        let mut receiver = control.subscribe();
+        assert!(!receiver.has_changed().unwrap());
+        assert!(control.set_stream(7734, false));
+        assert!(!control.set_stream(7734, false));
+        assert!(receiver.has_changed().unwrap());
+        assert!(!receiver.borrow_and_update().is_stream_enabled(7734));
+
+        assert!(control.set_channel("Station A_7734_VA", false));
+        assert!(control.set_stream(7734, true));
+        let settings = receiver.borrow_and_update().clone();
+        assert!(settings.is_stream_enabled(7734));
+        assert!(settings.disabled_channels.contains("Station A_7734_VA"));
+        control.set(IngestSettings::default());
+        assert!(receiver.has_changed().unwrap());
+        assert_eq!(control.settings(), IngestSettings::default());
+    }
+
+    #[tokio::test]
+    async fn test_http_control() {
+        let control = IngestControl::default();
+        let (status, body) = request(&control, "POST", "/ingest/streams/7734/disable").await;
+        assert_eq!(status, StatusCode::OK);
+        let settings: IngestSettings = serde_json::from_str(&body).unwrap();
+        assert!(!settings.is_stream_enabled(7734));
+
+        let (status, _) = request(
+            &control,
+            "POST",
+            "/ingest/channels/Station%20A_7734_VA/disable",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = request(&control, "POST", "/ingest/streams/7734/enable").await;
+        let settings: IngestSettings = serde_json::from_str(&body).unwrap();
+        assert!(settings.disabled_streams.is_empty());
+
+        let (status, body) = request(&control, "GET", "/ingest").await;
+        assert_eq!(status, StatusCode::OK);
+        let settings: IngestSettings = serde_json::from_str(&body).unwrap();
+        assert_eq!(settings, control.settings());
+        assert!(settings.disabled_channels.contains("Station A_7734_VA"));
+
+        let (status, _) = request(&control, "POST", "/ingest/streams/7734/pause").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = request(&control, "POST", "/ingest/streams/pmu/disable").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "sinks")]
+#![allow(unused)]
+use arrow::array::{ArrayRef, Float32Array, TimestampMicrosecondArray, UInt8Array};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use std::sync::Arc;
+
+// Two rows with a timestamp, a FREQ column and a quality column.
+fn batch_with_quality(quality: [u8; 2]) -> RecordBatch {
+    let schema = Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            false,
+        ),
+        Field::new("FREQ", DataType::Float32, false),
+        Field::new("quality", DataType::UInt8, false),
+    ]);
+    let arrays: Vec<ArrayRef> = vec![
+        Arc::new(TimestampMicrosecondArray::from(vec![
+            1_700_000_000_000_000,
+            1_700_000_000_033_333,
+        ])),
+        Arc::new(Float32Array::from(vec![60.0, 60.5])),
+        Arc::new(UInt8Array::from(quality.to_vec())),
+    ];
+    RecordBatch::try_new(Arc::new(schema), arrays).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::batch_with_quality;
+    use pmu::sinks::json::JsonSink;
+    use pmu::sinks::BatchSink;
+    use serde_json::Value;
+    use std::fs;
+
+    #[test]
+    fn test_json_lines_with_quality() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stream.jsonl");
+        let mut sink = JsonSink::new(&path).unwrap();
+        sink.write_batch(&batch_with_quality([0, 2])).unwrap();
+        sink.write_batch(&batch_with_quality([4, 0])).unwrap();
+        sink.flush().unwrap();
+        sink.close().unwrap();
+        assert_eq!(sink.rows(), 4);
+
+        let rows: Vec<Value> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[1]["FREQ"], 60.5);
+        let quality: Vec<u64> = rows
+            .iter()
+            .map(|row| row["quality"].as_u64().unwrap())
+            .collect();
+        assert_eq!(quality, vec![0, 2, 4, 0]);
+        assert!(rows[0]["timestamp"]
+            .as_str()
+            .unwrap()
+            .starts_with("2023-11-14T22:13:20"));
+    }
+}
@@ -0,0 +1,146 @@
+#![cfg(feature = "server")]
+#![allow(unused)]
+
+#[cfg(test)]
+mod tests {
+    use pmu::arrow_utils::frame_timestamp_micros_with;
+    use pmu::latency::{
+        enable_rx_timestamps, now_micros, recv_timestamped, LatencyTracker, TimestampSource,
+    };
+    use pmu::pdc_client::{ClientState, ControlMessage, PDCClient};
+    use pmu::pdc_server::{run_mock_server, Protocol, ServerConfig};
+    use pmu::simulator::Scenario;
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+    use tokio::time;
+
+    #[test]
+    fn test_latency_and_jitter() {
+        let mut tracker = LatencyTracker::new();
+        tracker.record(1_000_000, 1_020_000, TimestampSource::Kernel);
+        assert_eq!(tracker.last_us, 20_000);
+        assert_eq!(tracker.jitter_us, 0.0);
+
+        // Latency alternating between 20 and 36 ms
+        for n in 1..200 {
+            let latency = if n % 2 == 0 { 20_000 } else { 36_000 };
+            tracker.record(
+                1_000_000 + n,
+                1_000_000 + n + latency,
+                TimestampSource::Kernel,
+            );
+        }
+        assert_eq!(tracker.frames, 200);
+        assert_eq!((tracker.min_us, tracker.max_us), (20_000, 36_000));
+        assert!(
+            (tracker.jitter_us - 16_000.0).abs() < 1.0,
+            "{}",
+            tracker.jitter_us
+        );
+        assert_eq!(tracker.source, TimestampSource::Kernel);
+    }
+
+    #[test]
+    fn test_frame_timestamp() {
+        let mut frame = [0u8; 16];
+        frame[6..10].copy_from_slice(&1_700_000_000u32.to_be_bytes());
+        // Quality flags in the top byte are not part of the fraction
+        frame[10..14].copy_from_slice(&(0x0F00_0000u32 | 250_000).to_be_bytes());
+        assert_eq!(
+            frame_timestamp_micros_with(&frame, 1_000_000),
+            Some(1_700_000_000_250_000)
+        );
+        // A frame cut before the end of FRACSEC has no timestamp
+        assert_eq!(frame_timestamp_micros_with(&frame[..13], 1_000_000), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_kernel_timestamps() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        enable_rx_timestamps(&receiver, false).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let before = now_micros();
+        sender
+            .send_to(b"frame", receiver.local_addr().unwrap())
+            .await
+            .unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+        let mut buf = [0u8; 64];
+        let (n, from, arrival_us, source) = recv_timestamped(&receiver, &mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"frame");
+        assert_eq!(from, sender.local_addr().unwrap());
+        assert_eq!(source, TimestampSource::Kernel);
+        // Stamped on arrival, not when the read returned 50 ms later
+        assert!(arrival_us >= before - 1_000, "{} {}", arrival_us, before);
+        assert!(arrival_us < now_micros() - 40_000, "{}", arrival_us);
+    }
+
+    #[tokio::test]
+    async fn test_client_latency() {
+        // Simulated frames count from the start of the second the client
+        // connected in, so they are late by up to a few seconds but steadily
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4729, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_udp_data(4730)
+            .with_scenario(Scenario::default());
+        let server_handle = tokio::spawn(async move {
+            if let Err(e) = run_mock_server(server_config).await {
+                println!("Mock server error: {}", e)
+            };
+        });
+        time::sleep(Duration::from_millis(500)).await;
+
+        let (pdc_client, _, _) = PDCClient::new("127.0.0.1", 4729, 1, Duration::from_secs(10))
+            .await
+            .expect("Failed to create PDC Client");
+        let pdc_client = pdc_client.with_udp_data(4730).await.unwrap();
+        #[cfg(target_os = "linux")]
+        let pdc_client = pdc_client.with_rx_timestamps(false).unwrap();
+        let mut pdc_client = pdc_client;
+        let status = pdc_client.status_receiver();
+        let control_tx = pdc_client.get_control_sender();
+        let client_handle = tokio::spawn(async move {
+            pdc_client.start_stream().await;
+        });
+
+        time::sleep(Duration::from_secs(1)).await;
+        let latency = status.borrow().latency;
+        assert!(latency.frames >= 10);
+        assert!(
+            latency.min_us >= 0 && latency.max_us < 3_000_000,
+            "{:?}",
+            latency
+        );
+        assert!(latency.jitter_us < 50_000.0, "{:?}", latency);
+        #[cfg(target_os = "linux")]
+        assert_eq!(latency.source, TimestampSource::Kernel);
+
+        control_tx.send(ControlMessage::Stop).await.unwrap();
+        time::timeout(Duration::from_secs(3), client_handle)
+            .await
+            .unwrap()
+            .unwrap();
+        server_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_rx_timestamps_need_udp() {
+        let server_config =
+            ServerConfig::new("127.0.0.1".to_string(), 4731, Protocol::TCP, 30.0).unwrap();
+        let server_handle = tokio::spawn(async move {
+            if let Err(e) = run_mock_server(server_config).await {
+                println!("Mock server error: {}", e)
+            };
+        });
+        time::sleep(Duration::from_millis(500)).await;
+
+        let (pdc_client, _, _) = PDCClient::new("127.0.0.1", 4731, 1, Duration::from_secs(10))
+            .await
+            .expect("Failed to create PDC Client");
+        let error = pdc_client.with_rx_timestamps(false).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        server_handle.abort();
+    }
+}
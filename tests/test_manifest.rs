@@ -0,0 +1,108 @@
+#![cfg(feature = "parquet")]
+#![allow(unused)]
+use arrow::array::{ArrayRef, Float32Array, TimestampMicrosecondArray};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use std::sync::Arc;
+
+// Two rows with a timestamp and a Float32 column.
+fn batch() -> RecordBatch {
+    let schema = Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            false,
+        ),
+        Field::new("A", DataType::Float32, false),
+    ]);
+    let arrays: Vec<ArrayRef> = vec![
+        Arc::new(TimestampMicrosecondArray::from(vec![1, 2])),
+        Arc::new(Float32Array::from(vec![1.0, 2.0])),
+    ];
+    RecordBatch::try_new(Arc::new(schema), arrays).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::batch;
+    use pmu::sinks::csv::CsvSink;
+    use pmu::sinks::manifest::Manifest;
+    use pmu::sinks::parquet::ParquetSink;
+    use pmu::sinks::BatchSink;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    #[test]
+    fn test_manifest_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Manifest::sidecar(dir.path().join("7734.csv"));
+        assert_eq!(path, dir.path().join("_7734.csv.manifest.json"));
+        assert_eq!(Manifest::load(&path).unwrap(), None);
+
+        let manifest = Manifest {
+            offset: 120,
+            batches: 3,
+            rows: 90,
+            files: vec!["a-000000.parquet".to_string()],
+        };
+        manifest.save(&path).unwrap();
+        assert_eq!(Manifest::load(&path).unwrap(), Some(manifest));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_csv_resume_drops_torn_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stream.csv");
+        let mut sink = CsvSink::new(&path).unwrap();
+        sink.write_batch(&batch()).unwrap();
+        sink.write_batch(&batch()).unwrap();
+        // Crash halfway through the next batch
+        std::mem::forget(sink);
+        let durable = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"1970-01-01T00:00:00.000003,3.").unwrap();
+
+        let mut sink = CsvSink::resume(&path).unwrap();
+        assert_eq!(sink.rows(), 4);
+        assert_eq!(fs::metadata(&path).unwrap().len(), durable);
+        sink.write_batch(&batch()).unwrap();
+        sink.close().unwrap();
+        assert_eq!(sink.rows(), 6);
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        // A header after resuming, no partial row
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[5], "timestamp,A");
+        assert!(lines.iter().all(|line| !line.ends_with(",3.")));
+        let manifest = Manifest::load(Manifest::sidecar(&path)).unwrap().unwrap();
+        assert_eq!((manifest.batches, manifest.rows), (3, 6));
+        assert_eq!(manifest.offset, text.len() as u64);
+    }
+
+    #[test]
+    fn test_parquet_incomplete_file_not_counted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = ParquetSink::new(dir.path(), "pmu").unwrap();
+        sink.write_batch(&batch()).unwrap();
+        sink.close().unwrap();
+        sink.write_batch(&batch()).unwrap();
+        // Crash before the second file is closed
+        std::mem::forget(sink);
+        assert!(dir.path().join("pmu-000001.parquet.tmp").exists());
+        assert!(!dir.path().join("pmu-000001.parquet").exists());
+
+        let mut sink = ParquetSink::new(dir.path(), "pmu").unwrap();
+        assert!(!dir.path().join("pmu-000001.parquet.tmp").exists());
+        assert_eq!(sink.manifest().files, ["pmu-000000.parquet"]);
+        assert_eq!((sink.manifest().batches, sink.manifest().rows), (1, 2));
+        sink.write_batch(&batch()).unwrap();
+        sink.close().unwrap();
+        assert_eq!(
+            sink.manifest().files,
+            ["pmu-000000.parquet", "pmu-000001.parquet"]
+        );
+        assert_eq!(sink.manifest().rows, 4);
+    }
+}
@@ -0,0 +1,179 @@
+#![cfg(feature = "sinks")]
+#![allow(unused)]
+mod common;
+
+use common::{data_frame_us, frame_time, read_hex_file, with_crc, START_US};
+use pmu::matrix::{sidecar_path, MatrixExport};
+use pmu::recorder::{CaptureCompression, CaptureWriter};
+use std::fs;
+
+// The sample configuration under another idcode.
+fn config_frame(idcode: u16) -> Vec<u8> {
+    let mut frame = read_hex_file("config_message.bin").unwrap();
+    frame[4..6].copy_from_slice(&idcode.to_be_bytes());
+    // The PMU idcode follows the station name
+    frame[36..38].copy_from_slice(&idcode.to_be_bytes());
+    with_crc(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_matrix_with_gaps() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut export = MatrixExport::new();
+        export.push_frame(&config_frame(7734)).unwrap();
+        export.push_frame(&config_frame(7735)).unwrap();
+        for n in 0..30 {
+            export
+                .push_frame(&data_frame_us(7734, frame_time(n)))
+                .unwrap();
+            // The second stream starts later and misses a frame
+            if n >= 3 && n != 10 {
+                // A few hundred microseconds late
+                export
+                    .push_frame(&data_frame_us(7735, frame_time(n) + 400))
+                    .unwrap();
+            }
+        }
+        assert_eq!(export.rate_hz(), Some(30.0));
+        let path = dir.path().join("export.csv");
+        let stats = export.write_csv(&path).unwrap();
+        assert_eq!(stats.rows, 30);
+
+        let csv = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 31);
+        let header: Vec<&str> = lines[0].split(',').collect();
+        assert_eq!(header[..2], ["time_s", "posix_s"]);
+        assert_eq!(header.len(), 2 + stats.columns);
+        let freq = |idcode: u16| {
+            let name = format!("Station_A_{}_FREQ", idcode);
+            header.iter().position(|h| *h == name).unwrap()
+        };
+        assert!(header.contains(&"Station_A_7734_BREAKER_1_STATUS"));
+        assert!(!header.iter().any(|h| h.ends_with("DATA_VALID")));
+
+        let row = |n: usize| -> Vec<&str> { lines[n + 1].split(',').collect() };
+        assert_eq!(row(0)[0], "0.000000");
+        assert_eq!(row(0)[1], "1700000000.000000");
+        assert_eq!(row(3)[0], "0.100000");
+        assert_eq!(row(0)[freq(7734)], "62.5");
+        assert_eq!(row(0)[freq(7735)], "NaN");
+        assert_eq!(row(3)[freq(7735)], "62.5");
+        assert_eq!(row(10)[freq(7735)], "NaN");
+        assert_eq!(row(29)[freq(7735)], "62.5");
+        // 3 missing at the start and one in the middle, in every column of 7735
+        assert_eq!(stats.gaps, 4 * stats.columns / 2);
+
+        let sidecar = fs::read_to_string(sidecar_path(&path)).unwrap();
+        assert_eq!(sidecar_path(&path), dir.path().join("export_channels.csv"));
+        let lines: Vec<&str> = sidecar.lines().collect();
+        assert_eq!(
+            lines[0],
+            "column,variable,channel,station,idcode,kind,unit,component,samples,gaps"
+        );
+        assert_eq!(lines.len(), 1 + stats.columns);
+        let va = lines
+            .iter()
+            .find(|line| line.contains(",Station_A_7735_VA_X,"))
+            .unwrap();
+        let fields: Vec<&str> = va.split(',').collect();
+        assert_eq!(
+            fields[2..],
+            [
+                "Station A_7735_VA",
+                "Station A",
+                "7735",
+                "voltage",
+                "V",
+                "real",
+                "26",
+                "4"
+            ]
+        );
+        let column: usize = fields[0].parse().unwrap();
+        assert_eq!(header[column - 1], "Station_A_7735_VA_X");
+    }
+
+    #[test]
+    fn test_rate_and_captures() {
+        let dir = tempfile::tempdir().unwrap();
+        let capture = dir.path().join("capture.pmucap");
+        let mut writer = CaptureWriter::create(&capture, CaptureCompression::None).unwrap();
+        writer.write_frame(&config_frame(7734)).unwrap();
+        for n in 0..60 {
+            writer
+                .write_frame(&data_frame_us(7734, frame_time(n)))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut export = MatrixExport::new().with_rate(10.0);
+        let mut reader = pmu::recorder::CaptureReader::open(&capture).unwrap();
+        for record in reader.records() {
+            export.push_frame(&record.unwrap().frame).unwrap();
+        }
+        let path = dir.path().join("slow.csv");
+        let stats = export.write_csv(&path).unwrap();
+        // 0 to 1.967 s at 10 Hz
+        assert_eq!(stats.rows, 20);
+        assert_eq!(stats.gaps, 0);
+
+        // Without configuration there is no rate to go by
+        let mut export = MatrixExport::new();
+        assert!(export.push_frame(&data_frame_us(7734, START_US)).is_err());
+        assert!(export.write_csv(dir.path().join("none.csv")).is_err());
+    }
+
+    #[cfg(feature = "mat")]
+    #[test]
+    fn test_mat_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut export = MatrixExport::new();
+        export.push_frame(&config_frame(7734)).unwrap();
+        for n in 0..5 {
+            export
+                .push_frame(&data_frame_us(7734, frame_time(n)))
+                .unwrap();
+        }
+        let path = dir.path().join("export.mat");
+        let stats = export.write_mat(&path).unwrap();
+        assert_eq!(stats.rows, 5);
+        assert!(sidecar_path(&path).exists());
+
+        let bytes = fs::read(&path).unwrap();
+        assert!(bytes.starts_with(b"MATLAB 5.0 MAT-file"));
+        assert_eq!(&bytes[124..128], &[0x00, 0x01, b'I', b'M']);
+        // Elements: tag, then size, padded to 8 bytes
+        let mut names = Vec::new();
+        let mut offset = 128;
+        while offset < bytes.len() {
+            let tag = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+            let size = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap());
+            assert_eq!(tag, 14);
+            // Flags (16), dimensions (16), then the name
+            let name_offset = offset + 8 + 16 + 16;
+            let name_len =
+                u32::from_le_bytes(bytes[name_offset + 4..name_offset + 8].try_into().unwrap())
+                    as usize;
+            names.push(String::from_utf8_lossy(&bytes[name_offset + 8..][..name_len]).to_string());
+            assert_eq!(size % 8, 0);
+            offset += 8 + size as usize;
+        }
+        assert_eq!(offset, bytes.len());
+        assert_eq!(
+            names,
+            [
+                "time_s",
+                "posix_s",
+                "data",
+                "variables",
+                "channels",
+                "units"
+            ]
+        );
+    }
+}
@@ -0,0 +1,114 @@
+#![cfg(feature = "arrow")]
+#![allow(unused)]
+mod common;
+
+use common::{data_frame_at, read_hex_file};
+
+#[cfg(test)]
+mod tests {
+    use super::{data_frame_at, read_hex_file};
+    use pmu::accumulator::{AccumulatorError, BatchAccumulator};
+    use pmu::budget::{MemoryBudget, MemoryUsage};
+    use pmu::frame_parser::parse_config_frame_1and2;
+    use pmu::historian::Historian;
+
+    #[test]
+    fn test_accumulator_flushes_on_row_budget() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited().with_max_rows(3));
+        accumulator.add_stream(&config);
+
+        assert!(accumulator
+            .push_frame(&data_frame_at(1, 0))
+            .unwrap()
+            .is_none());
+        assert!(accumulator
+            .push_frame(&data_frame_at(2, 0))
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            accumulator.usage(7734),
+            Some(MemoryUsage {
+                rows: 2,
+                bytes: 104
+            })
+        );
+
+        let (idcode, batch) = accumulator
+            .push_frame(&data_frame_at(3, 0))
+            .unwrap()
+            .unwrap();
+        assert_eq!(idcode, 7734);
+        assert_eq!(batch.num_rows(), 3);
+        assert_eq!(accumulator.usage(7734), Some(MemoryUsage::default()));
+    }
+
+    #[test]
+    fn test_accumulator_flushes_on_byte_budget() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        // Two 52 byte frames fit under 150 bytes, the third reaches the limit.
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited())
+            .with_total_budget(MemoryBudget::unlimited().with_max_bytes(150));
+        accumulator.add_stream(&config);
+
+        assert!(accumulator
+            .push_frame(&data_frame_at(1, 0))
+            .unwrap()
+            .is_none());
+        assert!(accumulator
+            .push_frame(&data_frame_at(2, 0))
+            .unwrap()
+            .is_none());
+        let flushed = accumulator.push_frame(&data_frame_at(3, 0)).unwrap();
+        assert_eq!(flushed.map(|(_, batch)| batch.num_rows()), Some(3));
+    }
+
+    #[test]
+    fn test_accumulator_rejects_unknown_stream() {
+        let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited());
+        match accumulator.push_frame(&data_frame_at(1, 0)) {
+            Err(AccumulatorError::UnknownStream(7734)) => {}
+            other => panic!("Expected UnknownStream, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_historian_evicts_oldest_frames() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let mut historian = Historian::new(MemoryBudget::unlimited().with_max_rows(5));
+        historian.add_stream(&config);
+
+        for soc in 1..=8 {
+            historian.insert(&data_frame_at(soc, 0)).unwrap();
+        }
+
+        let usage = historian.usage(7734).unwrap();
+        assert_eq!(usage.rows, 5);
+        assert_eq!(historian.evicted(7734), Some(3));
+        assert_eq!(historian.time_range(7734), Some((4_000_000, 8_000_000)));
+
+        let batch = historian.query(7734, 0, i64::MAX).unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 5);
+        assert_eq!(historian.total_usage(), usage);
+    }
+
+    #[test]
+    fn test_historian_byte_budget() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let mut historian = Historian::new(MemoryBudget::unlimited().with_max_bytes(1024));
+        historian.add_stream(&config);
+
+        for soc in 1..=100 {
+            historian.insert(&data_frame_at(soc, 0)).unwrap();
+        }
+
+        let usage = historian.usage(7734).unwrap();
+        assert!(usage.bytes <= 1024);
+        assert!(usage.rows > 0);
+        assert_eq!(historian.evicted(7734), Some(100 - usage.rows as u64));
+    }
+}
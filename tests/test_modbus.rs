@@ -0,0 +1,154 @@
+#![cfg(feature = "modbus")]
+#![allow(unused)]
+mod common;
+
+use common::repeated_batch;
+
+#[cfg(test)]
+mod tests {
+    use super::repeated_batch;
+    use pmu::modbus::*;
+    use pmu::sinks::BatchSink;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::watch;
+
+    fn config() -> ModbusConfig {
+        ModbusConfig::new(vec![
+            // Deviation from 60 Hz in mHz
+            RegisterMap::new(0, "Station A_7734_FREQ", RegisterFormat::I16)
+                .with_offset(60.0)
+                .with_scale(1000.0),
+            RegisterMap::new(1, "Station A_7734_FREQ", RegisterFormat::F32),
+            RegisterMap::new(10, "Station A_7734_VA", RegisterFormat::U32).with_scale(10.0),
+            RegisterMap::new(12, "Not there", RegisterFormat::U16),
+        ])
+    }
+
+    // Request with transaction id 7, the response PDU.
+    async fn request(socket: &mut TcpStream, unit: u8, pdu: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0, 7, 0, 0];
+        frame.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+        frame.push(unit);
+        frame.extend_from_slice(pdu);
+        socket.write_all(&frame).await.unwrap();
+        let mut header = [0u8; 7];
+        socket.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[..4], [0, 7, 0, 0]);
+        assert_eq!(header[6], unit);
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        let mut response = vec![0u8; length - 1];
+        socket.read_exact(&mut response).await.unwrap();
+        response
+    }
+
+    fn words(response: &[u8]) -> Vec<u16> {
+        response[2..]
+            .chunks(2)
+            .map(|w| u16::from_be_bytes([w[0], w[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn test_register_scaling() {
+        let registers = Registers::new(&config());
+        // Nothing received yet
+        let before = registers.read(0, 3).unwrap();
+        assert_eq!(before[0], 0);
+        assert!(f32::from_bits((before[1] as u32) << 16 | before[2] as u32).is_nan());
+
+        assert!(registers.set("Station A_7734_FREQ", 59.95));
+        assert!(!registers.set("Other", 1.0));
+        let values = registers.read(0, 3).unwrap();
+        assert_eq!(values[0] as i16, -50);
+        assert_eq!(
+            f32::from_bits((values[1] as u32) << 16 | values[2] as u32),
+            59.95
+        );
+
+        // Saturated at the range of the format
+        registers.set("Station A_7734_FREQ", 100.0);
+        assert_eq!(registers.read(0, 1).unwrap(), [i16::MAX as u16]);
+        registers.set("Not there", -5.0);
+        assert_eq!(registers.read(12, 1).unwrap(), [0]);
+
+        // Gaps read as 0, ranges without any register are not there
+        assert_eq!(registers.read(3, 8).unwrap(), [0; 8]);
+        assert_eq!(registers.read(20, 5), None);
+        assert_eq!(registers.read(u16::MAX, 2), None);
+    }
+
+    #[test]
+    fn test_word_swap_and_validation() {
+        let mut config = config();
+        config.word_swap = true;
+        let registers = Registers::new(&config);
+        registers.set("Station A_7734_VA", 7000.0);
+        assert_eq!(registers.read(10, 2).unwrap(), [(70000 - 65536) as u16, 1]);
+
+        config
+            .registers
+            .push(RegisterMap::new(11, "Overlapping", RegisterFormat::I16));
+        assert!(config.validate().is_err());
+        let config = ModbusConfig::from_json(
+            r#"{"unit": 3, "registers": [{"address": 0, "channel": "A_1_FREQ", "format": "f32"}], "update_ms": 1000}"#,
+        )
+        .unwrap();
+        assert_eq!(config.registers[0].format, RegisterFormat::F32);
+        assert_eq!(config.registers[0].scale, 1.0);
+        assert_eq!(config.unit, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_client_reads_registers() {
+        let mut config = config();
+        config.unit = Some(1);
+        config.update_ms = Some(1000);
+        let server = ModbusServer::bind("127.0.0.1:0", config).await.unwrap();
+        let address = server.local_addr().unwrap();
+        let mut gateway = server.gateway();
+        gateway.write_batch(&repeated_batch(3)).unwrap();
+        let (stop, stopped) = watch::channel(false);
+        let task = tokio::spawn(server.run(stopped));
+
+        let mut socket = TcpStream::connect(address).await.unwrap();
+        // FREQ of the sample frame is 60 Hz + 2500 mHz
+        let response = request(&mut socket, 1, &[3, 0, 0, 0, 3]).await;
+        assert_eq!(response[..2], [3, 6]);
+        let values = words(&response);
+        assert_eq!(values[0], 2500);
+        assert_eq!(
+            f32::from_bits((values[1] as u32) << 16 | values[2] as u32),
+            62.5
+        );
+        // Input registers read the same map
+        let response = request(&mut socket, 1, &[4, 0, 10, 0, 2]).await;
+        let values = words(&response);
+        assert!((values[0] as u32) << 16 | values[1] as u32 > 0);
+
+        // Exceptions
+        assert_eq!(
+            request(&mut socket, 1, &[3, 0, 50, 0, 2]).await,
+            [0x83, ILLEGAL_DATA_ADDRESS]
+        );
+        assert_eq!(
+            request(&mut socket, 1, &[3, 0, 0, 0, 126]).await,
+            [0x83, ILLEGAL_DATA_VALUE]
+        );
+        assert_eq!(
+            request(&mut socket, 1, &[6, 0, 0, 0, 1]).await,
+            [0x86, ILLEGAL_FUNCTION]
+        );
+
+        // Requests to other units get no answer
+        socket
+            .write_all(&[0, 8, 0, 0, 0, 6, 2, 3, 0, 0, 0, 1])
+            .await
+            .unwrap();
+        let response = request(&mut socket, 1, &[3, 0, 0, 0, 1]).await;
+        assert_eq!(words(&response), [2500]);
+
+        stop.send(true).unwrap();
+        task.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+use pmu::multicast::{self, MulticastConfig};
+use pmu::pdc_server::{run_mock_server, Protocol, ServerConfig};
+use pmu::pipeline::{Pipeline, PipelineConfig};
+use pmu::simulator::Scenario;
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time;
+
+// Address datagrams to the group are sent from, None without a route for it.
+fn sender_address(group: Ipv4Addr) -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect((group, 9)).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multicast_config() {
+        let config: MulticastConfig = serde_json::from_str(
+            r#"{"group": "239.192.0.1", "interface": "10.0.0.5",
+                "sources": ["10.0.0.21", "10.0.0.22"], "recv_buffer_bytes": 4194304}"#,
+        )
+        .unwrap();
+        assert_eq!(config.interface, Some(Ipv4Addr::new(10, 0, 0, 5)));
+        assert!(config.reuse_address);
+        assert!(config.check().is_empty());
+        assert!(config.accepts("10.0.0.22".parse().unwrap()));
+        assert!(!config.accepts("10.0.0.23".parse().unwrap()));
+        assert!(MulticastConfig::new("239.192.0.1".parse().unwrap())
+            .accepts("10.0.0.23".parse().unwrap()));
+
+        let unicast = MulticastConfig::new("10.0.0.1".parse().unwrap());
+        assert_eq!(unicast.check(), vec!["10.0.0.1 is not a multicast group"]);
+        let mixed = MulticastConfig::new("239.192.0.1".parse().unwrap()).with_sources(vec![
+            "fe80::1".parse().unwrap(),
+            "239.0.0.1".parse().unwrap(),
+        ]);
+        assert_eq!(mixed.check().len(), 2);
+        let ssm_v6 = MulticastConfig::new("ff3e::8000:1".parse().unwrap())
+            .with_sources(vec!["2001:db8::1".parse().unwrap()]);
+        assert_eq!(ssm_v6.check().len(), 1);
+        assert_eq!(
+            multicast::bind(&unicast, 4748).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[tokio::test]
+    async fn test_source_filtering() {
+        let group = Ipv4Addr::new(239, 1, 2, 3);
+        let Some(local) = sender_address(group) else {
+            println!("No route for multicast, skipping");
+            return;
+        };
+        // Three streams sharing the port
+        let wanted = MulticastConfig::new(IpAddr::V4(group)).with_sources(vec![local]);
+        let other_source = MulticastConfig::new(IpAddr::V4(group))
+            .with_sources(vec!["192.0.2.99".parse().unwrap()])
+            .with_recv_buffer(1 << 20);
+        let other_group = MulticastConfig::new("239.1.2.4".parse().unwrap());
+        let wanted = multicast::bind(&wanted, 4748).unwrap();
+        let other_source = multicast::bind(&other_source, 4748).unwrap();
+        let other_group = multicast::bind(&other_group, 4748).unwrap();
+
+        let sender = UdpSocket::bind("0.0.0.0:0").unwrap();
+        sender.send_to(b"frame", (group, 4748)).unwrap();
+        let mut buf = [0u8; 16];
+        let (n, from) = time::timeout(Duration::from_secs(1), wanted.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((&buf[..n], from.ip()), (&b"frame"[..], local));
+        for socket in [&other_source, &other_group] {
+            let received =
+                time::timeout(Duration::from_millis(200), socket.recv_from(&mut buf)).await;
+            assert!(received.is_err(), "{:?}", received);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_multicast_stream() {
+        let group = Ipv4Addr::new(239, 1, 2, 5);
+        let Some(local) = sender_address(group) else {
+            println!("No route for multicast, skipping");
+            return;
+        };
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4749, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_multicast_data(IpAddr::V4(group), 4750)
+            .with_scenario(Scenario::default());
+        let server = tokio::spawn(async move {
+            if let Err(e) = run_mock_server(server_config).await {
+                println!("Mock server error: {}", e)
+            };
+        });
+        time::sleep(Duration::from_millis(500)).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let json = format!(
+            r#"{{"streams": [{{"host": "127.0.0.1", "port": 4749, "udp_port": 4750,
+                               "multicast": {{"group": "{}", "sources": ["{}"]}}}}],
+                "sink": {{"format": "csv", "dir": {:?}}}, "batch_rows": 10}}"#,
+            group,
+            local,
+            dir.path()
+        );
+        let config = PipelineConfig::from_json(&json).unwrap();
+        assert_eq!(
+            config.streams[0].multicast.as_ref().unwrap().sources,
+            vec![local]
+        );
+        let pipeline = Arc::new(Pipeline::new(config));
+        let runner = pipeline.clone();
+        let handle = tokio::spawn(async move { runner.run().await });
+        time::sleep(Duration::from_millis(1000)).await;
+        pipeline.stop();
+        let stats = time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(stats[0].errors, 0);
+        assert!(stats[0].frames > 10, "{:?}", stats[0]);
+        assert!(dir.path().join("7734.csv").exists());
+        server.abort();
+    }
+}
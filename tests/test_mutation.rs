@@ -0,0 +1,197 @@
+#![allow(unused)]
+mod common;
+
+use common::read_hex_file;
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::frames::ConfigurationFrame1and2_2011;
+use pmu::mutation::{check_parser, faults, mutations, Fault, Mutation, Outcome, Report, Target};
+
+// Configuration and data frames of each sample stream.
+const SAMPLES: [(&str, &str); 3] = [
+    ("config_message.bin", "data_message.bin"),
+    ("config_float_message.bin", "data_float_message.bin"),
+    ("config_polar_message.bin", "data_polar_message.bin"),
+];
+
+fn assert_no_failures(report: &Report) {
+    assert!(report.failures().is_empty(), "{}", report);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mutations() {
+        let config = read_hex_file("config_message.bin").unwrap();
+        let data = read_hex_file("data_message.bin").unwrap();
+
+        let flipped = Mutation::FlipChk(9).apply(&data).unwrap();
+        assert_eq!(flipped[data.len() - 2], data[data.len() - 2] ^ 0x02);
+        assert_eq!(flipped[..data.len() - 2], data[..data.len() - 2]);
+        assert_eq!(Mutation::FlipChk(16).apply(&data), None);
+        assert_eq!(Mutation::Truncate(data.len()).apply(&data), None);
+        assert_eq!(Mutation::Truncate(10).apply(&data).unwrap(), data[..10]);
+        let framesize = (data.len() as u16 + 1).to_be_bytes();
+        assert_eq!(
+            Mutation::FrameSize(data.len() as u16 + 1)
+                .apply(&data)
+                .unwrap()[2..4],
+            framesize
+        );
+        // FORMAT is only in configuration frames, at 38 for the first PMU
+        let format = Mutation::Format {
+            pmu: 0,
+            format: 0x000F,
+        };
+        assert_eq!(format.apply(&data), None);
+        assert_eq!(format.apply(&config).unwrap()[38..40], [0x00, 0x0F]);
+        assert_eq!(Mutation::Format { pmu: 1, format: 0 }.apply(&config), None);
+
+        // A cut frame resealed declares its new length and checks
+        let resealed = Fault {
+            mutation: Mutation::Truncate(30),
+            resealed: true,
+        }
+        .apply(&data)
+        .unwrap();
+        assert_eq!(resealed.len(), 30);
+        assert_eq!(u16::from_be_bytes([resealed[2], resealed[3]]), 30);
+        assert!(pmu::frames::CrcMode::Standard.check(&resealed));
+
+        let data_mutations = mutations(&data);
+        assert_eq!(data_mutations.len(), 16 + data.len() + 12 + 7);
+        assert!(!data_mutations
+            .iter()
+            .any(|m| matches!(m, Mutation::Format { .. })));
+        let config_mutations = mutations(&config);
+        assert_eq!(
+            config_mutations
+                .iter()
+                .filter(|m| matches!(m, Mutation::Format { .. }))
+                .count(),
+            16
+        );
+        assert_eq!(faults(&data).len(), 2 * data_mutations.len());
+        assert_eq!(
+            Fault {
+                mutation: Mutation::Format { pmu: 0, format: 1 },
+                resealed: true
+            }
+            .to_string(),
+            "FORMAT 0x0001 of PMU 0, resealed"
+        );
+    }
+
+    #[test]
+    fn test_parser_faults() {
+        for (config_file, data_file) in SAMPLES {
+            let config_frame = read_hex_file(config_file).unwrap();
+            let data_frame = read_hex_file(data_file).unwrap();
+            let config = parse_config_frame_1and2(&config_frame).unwrap();
+
+            let report = check_parser(&config_frame, None);
+            assert_no_failures(&report);
+            // On the wire every fault is caught
+            assert!(report
+                .trials
+                .iter()
+                .filter(|t| !t.fault.resealed)
+                .all(|t| matches!(t.outcome, Outcome::Rejected(_))));
+            // A resealed FORMAT is a valid frame saying something else
+            assert!(report.trials.iter().any(|t| t.fault.resealed
+                && matches!(t.fault.mutation, Mutation::Format { .. })
+                && t.outcome == Outcome::Changed));
+
+            let report = check_parser(&data_frame, Some(&config));
+            assert_no_failures(&report);
+            assert!(report.trials.iter().all(|t| t.target == Target::Data));
+            // Resealed as another frame type it may be a valid command
+            assert!(
+                report
+                    .trials
+                    .iter()
+                    .filter(|t| !matches!(t.fault.mutation, Mutation::FrameType(_)))
+                    .all(|t| matches!(t.outcome, Outcome::Rejected(_))),
+                "{}",
+                report
+            );
+        }
+    }
+
+    #[test]
+    fn test_frame_type_faults() {
+        // Header and configuration 3 frames are not parsed, an error either way
+        let config_frame = read_hex_file("config_message.bin").unwrap();
+        let data_frame = read_hex_file("data_message.bin").unwrap();
+        let config = parse_config_frame_1and2(&config_frame).unwrap();
+        for (frame, config) in [(&config_frame, None), (&data_frame, Some(&config))] {
+            let report = check_parser(frame, config);
+            assert_no_failures(&report);
+            let retyped: Vec<_> = report
+                .trials
+                .iter()
+                .filter(|t| {
+                    t.fault.resealed
+                        && matches!(t.fault.mutation, Mutation::FrameType(0b001 | 0b101))
+                })
+                .collect();
+            assert_eq!(retyped.len(), 2);
+            for trial in retyped {
+                assert_eq!(
+                    trial.outcome,
+                    Outcome::Rejected("NotImplemented".to_string()),
+                    "{}",
+                    trial
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated_configuration_frame() {
+        // Cut anywhere, an error rather than a panic
+        let config_frame = read_hex_file("config_message.bin").unwrap();
+        for len in [0, 10, 19, 40, 100, config_frame.len() - 6] {
+            assert!(parse_config_frame_1and2(&config_frame[..len]).is_err());
+        }
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_accumulator_faults() {
+        use pmu::accumulator::BatchAccumulator;
+        use pmu::budget::MemoryBudget;
+        use pmu::frames::CrcMode;
+        use pmu::framesize::FrameSizePolicy;
+        use pmu::mutation::check_accumulator;
+
+        for (config_file, data_file) in SAMPLES {
+            let config_frame = read_hex_file(config_file).unwrap();
+            let data_frame = read_hex_file(data_file).unwrap();
+
+            // Strict, CHK left to the client
+            let report = check_accumulator(&config_frame, &data_frame, || {
+                BatchAccumulator::new(MemoryBudget::unlimited())
+            });
+            assert_no_failures(&report);
+            assert!(report.trials.iter().any(|t| t.target == Target::Config));
+
+            // CHK checked, bad ones kept and flagged
+            let report = check_accumulator(&config_frame, &data_frame, || {
+                BatchAccumulator::new(MemoryBudget::unlimited()).with_lenient_parsing(true)
+            });
+            assert_no_failures(&report);
+            assert!(report.count(|o| *o == Outcome::Flagged) >= 16, "{}", report);
+
+            // Frames with trailing bytes or a wrong FRAMESIZE repaired
+            let report = check_accumulator(&config_frame, &data_frame, || {
+                let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited());
+                accumulator.set_crc_mode(7734, CrcMode::Standard, false);
+                accumulator.set_frame_size_policy(7734, FrameSizePolicy::Lenient);
+                accumulator
+            });
+            assert_no_failures(&report);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+mod common;
+
+use arrow::record_batch::RecordBatch;
+use common::sample_batch;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::datatypes::{DataType, Field};
+    use pmu::pipeline::{SinkConfig, SinkFormat};
+    use pmu::sinks::naming::{NameMapping, NameStyle, RenamedSink};
+    use pmu::sinks::BatchSink;
+    use std::sync::{Arc, Mutex};
+
+    // Keeps what it is given, for checking what a wrapper passes on.
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<RecordBatch>>>);
+
+    impl BatchSink for Collect {
+        fn write_batch(&mut self, batch: &RecordBatch) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(batch.clone());
+            Ok(())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn close(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn names(batch: &RecordBatch) -> Vec<String> {
+        batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_styles() {
+        let batch = sample_batch();
+        let schema = batch.schema();
+        let va = schema.field_with_name("Station A_7734_VA_X").unwrap();
+        let breaker = schema
+            .field_with_name("Station A_7734_BREAKER 1 STATUS")
+            .unwrap();
+        let timestamp = schema.field_with_name("timestamp").unwrap();
+
+        let styled = |style: NameStyle| [va, breaker, timestamp].map(|field| style.apply(field));
+        assert_eq!(
+            styled(NameStyle::Canonical),
+            [
+                "Station A_7734_VA_X",
+                "Station A_7734_BREAKER 1 STATUS",
+                "timestamp"
+            ]
+        );
+        assert_eq!(
+            styled(NameStyle::SnakeCase),
+            [
+                "station_a_7734_va_x",
+                "station_a_7734_breaker_1_status",
+                "timestamp"
+            ]
+        );
+        assert_eq!(
+            styled(NameStyle::Dotted),
+            [
+                "station_a.7734.va_x",
+                "station_a.7734.breaker_1_status",
+                "timestamp"
+            ]
+        );
+        assert_eq!(
+            styled(NameStyle::Prometheus),
+            [
+                "Station_A_7734_VA_X",
+                "Station_A_7734_BREAKER_1_STATUS",
+                "timestamp"
+            ]
+        );
+        // No identifier starts with a digit
+        let numeric = Field::new("50Hz (rms)", DataType::Float64, true);
+        assert_eq!(NameStyle::SnakeCase.apply(&numeric), "_50hz_rms");
+        assert_eq!(NameStyle::Prometheus.apply(&numeric), "_50Hz__rms_");
+    }
+
+    #[test]
+    fn test_collisions_and_reverse() {
+        let fields = [
+            Field::new("VA mag", DataType::Float64, true),
+            Field::new("va_mag", DataType::Float64, true),
+            Field::new("VA-MAG", DataType::Float64, true),
+        ];
+        let mut mapping = NameMapping::new(NameStyle::SnakeCase);
+        let given: Vec<String> = fields
+            .iter()
+            .map(|field| mapping.name_field(field).to_string())
+            .collect();
+        assert_eq!(given, ["va_mag", "va_mag_2", "va_mag_3"]);
+        // Deterministic: the same column keeps its name
+        assert_eq!(mapping.name_field(&fields[1]), "va_mag_2");
+        assert_eq!(mapping.columns().len(), 3);
+        assert_eq!(mapping.canonical("va_mag_3"), Some("VA-MAG"));
+        assert_eq!(mapping.name("VA mag"), Some("va_mag"));
+        assert_eq!(mapping.canonical("va_mag_4"), None);
+    }
+
+    #[test]
+    fn test_mapping_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("_7734.columns.json");
+        let collect = Collect::default();
+        let mut sink =
+            RenamedSink::new(Box::new(collect.clone()), NameStyle::Dotted, &path).unwrap();
+        let batch = sample_batch();
+        sink.write_batch(&batch).unwrap();
+        let written = collect.0.lock().unwrap()[0].clone();
+        assert_eq!(written.num_columns(), batch.num_columns());
+        assert!(names(&written).contains(&"station_a.7734.freq".to_string()));
+        // Metadata goes along with the column
+        let freq = written
+            .schema()
+            .field_with_name("station_a.7734.freq")
+            .unwrap()
+            .clone();
+        assert_eq!(
+            freq.metadata().get("pmu.channel").map(String::as_str),
+            Some("Station A_7734_FREQ")
+        );
+
+        // Loaded again, every name maps back to its column
+        let mapping = NameMapping::load(&path, NameStyle::Dotted).unwrap();
+        assert_eq!(mapping.columns(), sink.mapping().columns());
+        for (canonical, name) in names(&batch).iter().zip(names(&written)) {
+            assert_eq!(mapping.canonical(&name), Some(canonical.as_str()));
+        }
+        // Names given earlier are kept over those a new column would take
+        let clash = Field::new("Station A_7734_Freq", DataType::Float64, true)
+            .with_metadata(freq.metadata().clone());
+        let mut loaded = NameMapping::load(&path, NameStyle::Dotted).unwrap();
+        assert_eq!(loaded.name_field(&clash), "station_a.7734.freq_2");
+
+        // A mapping of another style would rename the columns
+        assert!(NameMapping::load(&path, NameStyle::SnakeCase).is_err());
+        assert!(
+            NameMapping::load(dir.path().join("none.json"), NameStyle::SnakeCase)
+                .unwrap()
+                .columns()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_sink_config_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = format!(
+            r#"{{"format": "csv", "dir": {:?}, "names": "snake_case"}}"#,
+            dir.path()
+        );
+        let config: SinkConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(config.names, NameStyle::SnakeCase);
+        let mut sink = config.open(7734).unwrap();
+        sink.write_batch(&sample_batch()).unwrap();
+        sink.close().unwrap();
+        let mapping =
+            NameMapping::load(dir.path().join("_7734.columns.json"), NameStyle::SnakeCase).unwrap();
+        assert_eq!(
+            mapping.canonical("station_a_7734_dfreq"),
+            Some("Station A_7734_DFREQ")
+        );
+
+        let json = r#"{"format": "csv", "dir": "out"}"#;
+        let config: SinkConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.names, NameStyle::Canonical);
+    }
+}
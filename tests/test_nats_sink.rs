@@ -0,0 +1,179 @@
+#![cfg(feature = "nats")]
+#![allow(unused)]
+mod common;
+
+use common::repeated_batch;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+
+// Minimal NATS server on a local port for one connection. Stream creation
+// fails as in use when the stream exists already, publishes with a reply
+// subject are acknowledged, with an error for subjects starting with reject.
+// Returns the operations received (PUB lines with their payload) once the
+// client disconnects.
+fn fake_nats(stream_exists: bool) -> (String, JoinHandle<Vec<(String, String)>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let handle = thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(socket.try_clone().unwrap());
+        let mut writer = socket;
+        writer
+            .write_all(b"INFO {\"server_id\":\"fake\",\"jetstream\":true}\r\n")
+            .unwrap();
+        let mut operations = Vec::new();
+        let mut sequence = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                break;
+            }
+            let line = line.trim_end().to_string();
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let mut payload = String::new();
+            match fields[0] {
+                "PING" => writer.write_all(b"PONG\r\n").unwrap(),
+                "PUB" => {
+                    let length: usize = fields.last().unwrap().parse().unwrap();
+                    let mut data = vec![0; length + 2];
+                    reader.read_exact(&mut data).unwrap();
+                    data.truncate(length);
+                    payload = String::from_utf8(data).unwrap();
+                    if fields.len() == 4 {
+                        let (subject, reply) = (fields[1], fields[2]);
+                        let answer = if subject.starts_with("$JS.API.STREAM.CREATE")
+                            && stream_exists
+                        {
+                            r#"{"error":{"code":400,"err_code":10058,"description":"stream name already in use"}}"#.to_string()
+                        } else if subject.starts_with("$JS.API.STREAM") {
+                            r#"{"config":{}}"#.to_string()
+                        } else if subject.starts_with("reject") {
+                            r#"{"error":{"code":503,"description":"no responders"}}"#.to_string()
+                        } else {
+                            sequence += 1;
+                            format!(r#"{{"stream":"PMU","seq":{}}}"#, sequence)
+                        };
+                        let message = format!("MSG {} 1 {}\r\n{}\r\n", reply, answer.len(), answer);
+                        writer.write_all(message.as_bytes()).unwrap();
+                    }
+                }
+                _ => {}
+            }
+            operations.push((line, payload));
+        }
+        operations
+    });
+    (address, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fake_nats, repeated_batch};
+    use pmu::sinks::nats::{JetStreamConfig, NatsPublisher, Storage};
+    use pmu::sinks::BatchSink;
+    use serde_json::Value;
+    use std::time::Duration;
+
+    #[test]
+    fn test_core_publish_per_station_subject() {
+        let (address, server) = fake_nats(false);
+        let mut publisher = NatsPublisher::new(&format!("nats://{}", address))
+            .unwrap()
+            .with_prefix("grid")
+            .with_token("secret");
+        publisher.write_batch(&repeated_batch(2)).unwrap();
+        assert_eq!(publisher.messages(), 2);
+        publisher.close().unwrap();
+
+        let operations = server.join().unwrap();
+        let connect: Value = serde_json::from_str(&operations[0].0["CONNECT ".len()..]).unwrap();
+        assert_eq!(connect["auth_token"], "secret");
+        assert_eq!(connect["verbose"], false);
+        let published: Vec<_> = operations
+            .iter()
+            .filter(|(line, _)| line.starts_with("PUB"))
+            .collect();
+        assert_eq!(published.len(), 2);
+        // No reply subject without JetStream
+        assert!(published[0].0.starts_with("PUB grid.Station_A.7734 "));
+        assert_eq!(published[0].0.split_whitespace().count(), 3);
+        let payload: Value = serde_json::from_str(&published[0].1).unwrap();
+        assert_eq!(payload["idcode"], 7734);
+        assert_eq!(payload["values"]["FREQ"], 62.5);
+        // Every batch ends with a PING
+        assert_eq!(operations.last().unwrap().0, "PING");
+    }
+
+    #[test]
+    fn test_jetstream_stream_and_acks() {
+        let (address, server) = fake_nats(true);
+        let jetstream = JetStreamConfig::new("PMU")
+            .with_storage(Storage::Memory)
+            .with_max_age(Duration::from_secs(3600))
+            .with_max_msgs(1000);
+        let mut publisher = NatsPublisher::new(&address)
+            .unwrap()
+            .with_user("pmu", "pw")
+            .with_jetstream(jetstream);
+        publisher.write_batch(&repeated_batch(3)).unwrap();
+        publisher.write_batch(&repeated_batch(1)).unwrap();
+        assert_eq!(publisher.messages(), 4);
+        publisher.close().unwrap();
+
+        let operations = server.join().unwrap();
+        assert!(operations[1].0.starts_with("SUB _INBOX."));
+        // Created, found in use and updated
+        let create = operations
+            .iter()
+            .find(|(line, _)| line.starts_with("PUB $JS.API.STREAM.CREATE.PMU "))
+            .unwrap();
+        let update = operations
+            .iter()
+            .find(|(line, _)| line.starts_with("PUB $JS.API.STREAM.UPDATE.PMU "))
+            .unwrap();
+        let config: Value = serde_json::from_str(&update.1).unwrap();
+        assert_eq!(config, serde_json::from_str::<Value>(&create.1).unwrap());
+        assert_eq!(config["subjects"][0], "pmu.>");
+        assert_eq!(config["storage"], "memory");
+        assert_eq!(config["max_age"], 3_600_000_000_000u64);
+        assert_eq!(config["max_msgs"], 1000);
+        assert_eq!(config["max_bytes"], -1);
+        // Messages carry a reply subject for the acknowledgement
+        let published: Vec<_> = operations
+            .iter()
+            .filter(|(line, _)| line.starts_with("PUB pmu."))
+            .collect();
+        assert_eq!(published.len(), 4);
+        assert!(published[0]
+            .0
+            .split_whitespace()
+            .nth(2)
+            .unwrap()
+            .starts_with("_INBOX."));
+    }
+
+    #[test]
+    fn test_rejected_messages() {
+        let (address, server) = fake_nats(false);
+        let mut publisher = NatsPublisher::new(&address)
+            .unwrap()
+            .with_prefix("reject")
+            .with_jetstream(JetStreamConfig::new("REJECT"));
+        let error = publisher.write_batch(&repeated_batch(2)).unwrap_err();
+        assert!(error.to_string().contains("no responders"), "{}", error);
+        assert_eq!(publisher.messages(), 0);
+        publisher.close().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_subjects_and_addresses() {
+        let publisher = NatsPublisher::new("localhost").unwrap();
+        assert_eq!(publisher.subject("SUB A.1", 7), "pmu.SUB_A_1.7");
+        assert_eq!(publisher.subject("", 7), "pmu._.7");
+        assert!(NatsPublisher::new("nats://").is_err());
+        assert!(NatsPublisher::new("nats://user:pw@host:4222").is_err());
+        assert!(NatsPublisher::new("nats://host:4223").is_ok());
+    }
+}
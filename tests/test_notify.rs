@@ -0,0 +1,256 @@
+#![cfg(feature = "analytics")]
+#![allow(unused)]
+
+#[cfg(test)]
+mod tests {
+    use pmu::events::{Event, EventBus, EventKind, Severity};
+    use pmu::notify::*;
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    const SECOND_US: i64 = 1_000_000;
+    const START_US: i64 = 1_700_000_000_000_000;
+
+    fn event(offset_s: i64, severity: Severity, source: &str) -> Event {
+        Event::new(
+            START_US + offset_s * SECOND_US,
+            EventKind::DataQuality,
+            source,
+            "Oscillation".to_string(),
+        )
+        .with_severity(severity)
+        .with_value("amplitude_mhz", 12.5)
+    }
+
+    fn webhook(format: WebhookFormat) -> NotifyTarget {
+        NotifyTarget::Webhook {
+            url: "http://127.0.0.1:9/alerts".to_string(),
+            format,
+        }
+    }
+
+    #[test]
+    fn test_digest_batching() {
+        let channel = NotifyChannel::new("ops", webhook(WebhookFormat::Slack))
+            .with_digest(Duration::from_secs(60));
+        let mut notifier = Notifier::new(NotifyConfig {
+            channels: vec![channel],
+        })
+        .unwrap();
+
+        // The first event goes out at once
+        notifier.offer(&event(0, Severity::Alarm, "SUB_A"));
+        let messages = notifier.due(START_US);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].subject, "Alarm: data_quality at SUB_A");
+        assert!(messages[0].text.contains("amplitude_mhz=12.5"));
+
+        // Then an event a second, collected for a minute; info is left out
+        for s in 1..600 {
+            notifier.offer(&event(
+                s,
+                Severity::Warning,
+                if s % 2 == 0 { "SUB_A" } else { "SUB_B" },
+            ));
+            notifier.offer(&event(s, Severity::Info, "SUB_C"));
+            assert!(notifier.due(START_US + s * SECOND_US).is_empty() || s >= 60);
+        }
+        let messages = notifier.due(START_US + 600 * SECOND_US);
+        assert_eq!(messages.len(), 1);
+        let digest = &messages[0];
+        assert!(digest.events.len() < 600);
+        assert!(
+            digest.subject.contains("worst Warning, from SUB_A, SUB_B"),
+            "{}",
+            digest.subject
+        );
+        assert!(digest.text.contains("... and"));
+        assert!(notifier.due(START_US + 601 * SECOND_US).is_empty());
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let channel = NotifyChannel::new("sms", webhook(WebhookFormat::Events))
+            .with_digest(Duration::from_secs(0))
+            .with_max_per_hour(2);
+        let mut notifier = Notifier::new(NotifyConfig {
+            channels: vec![channel],
+        })
+        .unwrap();
+        let mut sent = 0;
+        for s in 0..10 {
+            notifier.offer(&event(s, Severity::Alarm, "SUB_A"));
+            sent += notifier.due(START_US + s * SECOND_US).len();
+        }
+        assert_eq!(sent, 2);
+        // An hour after the first the rest goes out as one digest
+        let messages = notifier.due(START_US + 3600 * SECOND_US);
+        assert_eq!(messages[0].events.len(), 8);
+    }
+
+    #[test]
+    fn test_webhook_formats() {
+        let mut notifier = Notifier::new(NotifyConfig {
+            channels: vec![NotifyChannel::new("ops", webhook(WebhookFormat::Slack))],
+        })
+        .unwrap();
+        notifier.offer(&event(0, Severity::Alarm, "SUB_A"));
+        let message = notifier.due(START_US).remove(0);
+
+        let slack = message.webhook_body(&WebhookFormat::Slack);
+        assert!(slack["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("*Alarm: data_quality at SUB_A*\n"));
+        let teams = message.webhook_body(&WebhookFormat::Teams);
+        assert_eq!(teams["@type"], "MessageCard");
+        assert_eq!(teams["title"], message.subject);
+        let events = message.webhook_body(&WebhookFormat::Events);
+        assert_eq!(events["events"][0]["source"], "SUB_A");
+
+        let template = json!({"to": "+15550100", "body": "{severity} ({count}): {subject}", "items": "{events}"});
+        let custom = message.webhook_body(&WebhookFormat::Template(template));
+        assert_eq!(custom["to"], "+15550100");
+        assert_eq!(custom["body"], "Alarm (1): Alarm: data_quality at SUB_A");
+        assert_eq!(custom["items"][0]["kind"], "data_quality");
+    }
+
+    #[test]
+    fn test_config() {
+        let config = NotifyConfig::from_json(
+            r#"{"channels": [
+                {"name": "mail", "target": {"type": "smtp", "server": "localhost:25",
+                 "from": "pmu@example.com", "to": ["ops@example.com"]}, "max_per_hour": 10},
+                {"name": "teams", "target": {"type": "webhook", "url": "http://hooks/x", "format": "teams"},
+                 "min_severity": "alarm", "digest_secs": 300}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.channels[0].min_severity, Severity::Warning);
+        assert_eq!(config.channels[0].digest_secs, 60);
+        assert_eq!(config.channels[1].min_severity, Severity::Alarm);
+        assert!(Notifier::new(config).is_ok());
+
+        let invalid = NotifyConfig {
+            channels: vec![NotifyChannel::new(
+                "https",
+                NotifyTarget::Webhook {
+                    url: "https://hooks/x".to_string(),
+                    format: WebhookFormat::Slack,
+                },
+            )],
+        };
+        assert!(Notifier::new(invalid).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mail_through_smtp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = socket.into_split();
+            let mut reader = BufReader::new(reader);
+            writer.write_all(b"220 relay ESMTP\r\n").await.unwrap();
+            let mut lines = Vec::new();
+            let mut data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                let reply: &[u8] = if data {
+                    if line == ".\r\n" {
+                        data = false;
+                        b"250 queued\r\n"
+                    } else {
+                        b""
+                    }
+                } else if line.starts_with("EHLO") {
+                    b"250-relay\r\n250 8BITMIME\r\n"
+                } else if line.starts_with("DATA") {
+                    data = true;
+                    b"354 go ahead\r\n"
+                } else if line.starts_with("QUIT") {
+                    writer.write_all(b"221 bye\r\n").await.unwrap();
+                    lines.push(line);
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                writer.write_all(reply).await.unwrap();
+                lines.push(line);
+            }
+            lines
+        });
+
+        let bus = EventBus::new(16);
+        let target = NotifyTarget::Smtp(SmtpTarget {
+            server: address.to_string(),
+            from: "pmu@example.com".to_string(),
+            to: vec!["a@example.com".to_string(), "b@example.com".to_string()],
+            subject_prefix: "[GRID]".to_string(),
+        });
+        let mut notifier = Notifier::new(NotifyConfig {
+            channels: vec![NotifyChannel::new("mail", target)],
+        })
+        .unwrap()
+        .with_events(&bus);
+        let mut event = event(0, Severity::Alarm, "SUB_A");
+        event.message = "Two lines\n.the second with a dot".to_string();
+        bus.publish(event);
+        let messages = notifier.deliver_due(START_US).await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!((notifier.sent(), notifier.failures()), (1, 0));
+
+        let lines = server.await.unwrap();
+        assert_eq!(lines[1], "MAIL FROM:<pmu@example.com>\r\n");
+        assert_eq!(lines[2], "RCPT TO:<a@example.com>\r\n");
+        assert_eq!(lines[3], "RCPT TO:<b@example.com>\r\n");
+        assert!(lines.contains(&"Subject: [GRID] Alarm: data_quality at SUB_A\r\n".to_string()));
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("2023-11-14 22:13:20 UTC Alarm")));
+        // Dot stuffed
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("..the second with a dot")));
+        assert_eq!(lines.last().unwrap(), "QUIT\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_failed_webhook_is_counted() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            while !request.ends_with(b"}") {
+                let n = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..n]);
+            }
+            socket
+                .write_all(b"HTTP/1.1 429 Too Many Requests\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        let target = NotifyTarget::Webhook {
+            url: format!("http://{}/hook", address),
+            format: WebhookFormat::Slack,
+        };
+        let mut notifier = Notifier::new(NotifyConfig {
+            channels: vec![NotifyChannel::new("slack", target)],
+        })
+        .unwrap();
+        notifier.offer(&event(0, Severity::Warning, "SUB_A"));
+        notifier.deliver_due(START_US).await;
+        assert_eq!((notifier.sent(), notifier.failures()), (0, 1));
+        let request = server.await.unwrap();
+        let body: Value = serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert!(body["text"].as_str().unwrap().contains("SUB_A"));
+    }
+}
@@ -0,0 +1,175 @@
+#![cfg(feature = "arrow")]
+#![allow(unused)]
+use arrow::array::{Array, AsArray, Float64Array, UInt16Array};
+use arrow::datatypes::{Float64Type, TimestampMicrosecondType};
+use arrow::record_batch::RecordBatch;
+use pmu::arrow_utils::{
+    channel_value, channel_values, META_CHANNEL, META_COMPONENT, META_KIND, META_STATION, META_UNIT,
+};
+use pmu::openpdc::{parse_signal_reference, parse_timestamp, MeasurementMap, OpenPdcReader};
+use std::io::Cursor;
+
+const EXPORT: &str = "\
+Timestamp,SHELBY-FQ,SHELBY-DF,SHELBY-PM1,SHELBY-PA1,SHELBY-SF,CORDOVA-FQ,CORDOVA-AV2,NOTHING
+2024-01-01 06:00:00.000,60.01,0.02,133987.5,90,0,59.99,1.5,1
+2024-01-01 06:00:00.033,60.02,,133990,-90,8192,NaN,1.6,2
+2024-01-01 06:00:00.067,60.03,0.01,133995,180,0,59.98,1.7,3
+";
+
+fn read(reader: &OpenPdcReader, text: &str) -> Vec<(u16, RecordBatch)> {
+    let mut batches = Vec::new();
+    reader
+        .read(Cursor::new(text), |idcode, batch| {
+            batches.push((idcode, batch));
+            Ok(())
+        })
+        .unwrap();
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal_references_and_timestamps() {
+        assert_eq!(
+            parse_signal_reference("SHELBY-PA1"),
+            Some(("SHELBY", "PA", 1))
+        );
+        assert_eq!(
+            parse_signal_reference("TVA-SHELBY-FQ"),
+            Some(("TVA-SHELBY", "FQ", 0))
+        );
+        assert_eq!(parse_signal_reference("PPA:12"), None);
+        assert_eq!(parse_signal_reference("SHELBY-12"), None);
+
+        let expected = 1_704_088_800_033_000;
+        assert_eq!(parse_timestamp("2024-01-01 06:00:00.033"), Some(expected));
+        assert_eq!(parse_timestamp("2024-01-01T06:00:00.033Z"), Some(expected));
+        assert_eq!(parse_timestamp("01/01/2024 06:00:00.033"), Some(expected));
+        assert_eq!(parse_timestamp("638396856000330000"), Some(expected));
+        assert_eq!(parse_timestamp("1704088800.033"), Some(expected));
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_read_signal_references() {
+        let reader = OpenPdcReader::new().with_idcode("CORDOVA", 7);
+        let mut batches = Vec::new();
+        let stats = reader
+            .read(Cursor::new(EXPORT), |idcode, batch| {
+                batches.push((idcode, batch));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(stats.rows, 3);
+        assert_eq!(stats.streams, 2);
+        assert_eq!(stats.skipped_columns, ["NOTHING"]);
+        assert_eq!(batches.len(), 2);
+
+        let (idcode, shelby) = &batches[0];
+        assert_eq!(*idcode, 1);
+        assert_eq!(shelby.num_rows(), 3);
+        let timestamps = shelby.column(0).as_primitive::<TimestampMicrosecondType>();
+        assert_eq!(timestamps.value(1), 1_704_088_800_033_000);
+
+        let freq = channel_values(shelby, "SHELBY_1_FREQ").unwrap();
+        assert_eq!(freq.values().to_vec(), [60.01, 60.02, 60.03]);
+        let dfreq = shelby
+            .column_by_name("SHELBY_1_DFREQ")
+            .unwrap()
+            .as_primitive::<Float64Type>();
+        assert!(dfreq.is_null(1));
+        assert_eq!(channel_value(shelby, "SHELBY_1_PH1", 0), Some(133987.5));
+        let angle = channel_values(shelby, "SHELBY_1_PH1_angle").unwrap();
+        assert!((angle.value(0) - std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+        let stat = shelby
+            .column_by_name("SHELBY_1_STAT")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<UInt16Array>()
+            .unwrap();
+        assert_eq!(stat.value(1), 8192);
+
+        let schema = shelby.schema();
+        let field = schema.field_with_name("SHELBY_1_PH1_angle").unwrap();
+        assert_eq!(field.metadata()[META_STATION], "SHELBY");
+        assert_eq!(field.metadata()[META_CHANNEL], "SHELBY_1_PH1");
+        assert_eq!(field.metadata()[META_COMPONENT], "angle");
+        assert_eq!(field.metadata()[META_UNIT], "rad");
+        let field = schema.field_with_name("SHELBY_1_FREQ").unwrap();
+        assert_eq!(field.metadata()[META_KIND], "frequency");
+
+        let (idcode, cordova) = &batches[1];
+        assert_eq!(*idcode, 7);
+        let freq = cordova
+            .column_by_name("CORDOVA_7_FREQ")
+            .unwrap()
+            .as_primitive::<Float64Type>();
+        assert!(freq.is_null(1));
+        assert_eq!(channel_value(cordova, "CORDOVA_7_AN2", 2), Some(1.7));
+    }
+
+    #[test]
+    fn test_read_measurement_keys() {
+        let measurements = MeasurementMap::from_csv(
+            "\
+ID,SignalID,PointTag,SignalReference,Device,SignalType,EngineeringUnits,Label
+PPA:1,00000000-0000-0000-0000-000000000001,TVA_SHELBY:FREQ,SHELBY-FQ,SHELBY,FREQ,Hz,
+PPA:2,00000000-0000-0000-0000-000000000002,TVA_SHELBY:IPHM1,SHELBY-PM2,SHELBY,IPHM,Amps,IA
+PPA:3,00000000-0000-0000-0000-000000000003,\"TVA_SHELBY:IPHA1\",SHELBY-PA2,SHELBY,IPHA,,IA
+",
+        )
+        .unwrap();
+        assert_eq!(measurements.len(), 3);
+        assert_eq!(
+            measurements
+                .get("TVA_SHELBY:IPHA1")
+                .unwrap()
+                .signal_reference,
+            "SHELBY-PA2"
+        );
+
+        let reader = OpenPdcReader::new()
+            .with_measurements(measurements)
+            .with_batch_rows(2);
+        let text = "\
+\"Timestamp\",\"PPA:1\",\"PPA:2\",\"TVA_SHELBY:IPHA1\",\"PPA:9\"
+2024-01-01 06:00:00.000,60.0,410.5,-30,1
+2024-01-01 06:00:00.033,60.1,411.5,-31,1
+2024-01-01 06:00:00.067,60.2,412.5,-32,1
+";
+        let batches = read(&reader, text);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].1.num_rows(), 2);
+        assert_eq!(batches[1].1.num_rows(), 1);
+
+        let batch = &batches[1].1;
+        assert_eq!(channel_value(batch, "SHELBY_1_FREQ", 0), Some(60.2));
+        assert_eq!(channel_value(batch, "SHELBY_1_IA", 0), Some(412.5));
+        let schema = batch.schema();
+        let field = schema.field_with_name("SHELBY_1_IA_magnitude").unwrap();
+        assert_eq!(field.metadata()[META_KIND], "current");
+        assert_eq!(field.metadata()[META_UNIT], "Amps");
+        assert!(schema.field_with_name("SHELBY_1_IA_angle").is_ok());
+        assert!(!schema.fields().iter().any(|f| f.name().contains("PPA")));
+    }
+
+    #[test]
+    fn test_invalid_exports() {
+        let reader = OpenPdcReader::new();
+        let read = |text: &str| reader.read(Cursor::new(text), |_, _| Ok(()));
+        assert!(read("").is_err());
+        assert!(read("Time,SHELBY-FQ\n").is_err());
+        assert!(read("Timestamp,SHELBY-FQ\nnever,60\n").is_err());
+        assert!(read("Timestamp,SHELBY-FQ\n2024-01-01 06:00:00,sixty\n").is_err());
+        assert_eq!(
+            read("Timestamp,SHELBY-FQ\n\n2024-01-01 06:00:00,60\n")
+                .unwrap()
+                .rows,
+            1
+        );
+        assert!(MeasurementMap::from_csv("Name,Value\nx,1\n").is_err());
+    }
+}
@@ -0,0 +1,459 @@
+#![cfg(feature = "parquet")]
+#![allow(unused)]
+use arrow::array::{ArrayRef, Float32Array, TimestampMicrosecondArray};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use std::sync::Arc;
+
+// Batch with a timestamp column followed by one Float32 column per name.
+fn batch_with_columns(names: &[&str]) -> RecordBatch {
+    let mut fields = vec![Field::new(
+        "timestamp",
+        DataType::Timestamp(TimeUnit::Microsecond, None),
+        false,
+    )];
+    let mut arrays: Vec<ArrayRef> = vec![Arc::new(TimestampMicrosecondArray::from(vec![1, 2]))];
+    for name in names {
+        fields.push(Field::new(*name, DataType::Float32, false));
+        arrays.push(Arc::new(Float32Array::from(vec![1.0, 2.0])));
+    }
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::batch_with_columns;
+    use arrow::array::TimestampMicrosecondArray;
+    use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+    use arrow::record_batch::RecordBatch;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use pmu::sinks::parquet::{
+        ParquetSink, CHANGE_REASON_KEY, COLUMNS_ADDED_KEY, NEXT_FILE_KEY, PREVIOUS_FILE_KEY,
+    };
+    use pmu::sinks::BatchSink;
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn key_value_metadata(path: &Path) -> HashMap<String, String> {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap();
+        builder
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .map(|kvs| {
+                kvs.iter()
+                    .map(|kv| (kv.key.clone(), kv.value.clone().unwrap_or_default()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn row_count(path: &Path) -> usize {
+        ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum()
+    }
+
+    #[test]
+    fn test_reordered_columns_stay_in_one_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = ParquetSink::new(dir.path(), "capture").unwrap();
+
+        sink.write_batch(&batch_with_columns(&["VA", "VB"]))
+            .unwrap();
+        sink.write_batch(&batch_with_columns(&["VB", "VA"]))
+            .unwrap();
+        sink.close().unwrap();
+
+        assert_eq!(sink.files().len(), 1);
+        assert_eq!(row_count(&sink.files()[0]), 4);
+    }
+
+    #[test]
+    fn test_schema_change_starts_new_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = ParquetSink::new(dir.path(), "capture").unwrap();
+
+        sink.write_batch(&batch_with_columns(&["VA"])).unwrap();
+        sink.write_batch(&batch_with_columns(&["VA", "VB"]))
+            .unwrap();
+        sink.close().unwrap();
+
+        let files = sink.files().to_vec();
+        assert_eq!(files.len(), 2);
+
+        let first = key_value_metadata(&files[0]);
+        assert_eq!(
+            first.get(NEXT_FILE_KEY).map(String::as_str),
+            Some("capture-000001.parquet")
+        );
+
+        let second = key_value_metadata(&files[1]);
+        assert_eq!(
+            second.get(PREVIOUS_FILE_KEY).map(String::as_str),
+            Some("capture-000000.parquet")
+        );
+        assert_eq!(
+            second.get(CHANGE_REASON_KEY).map(String::as_str),
+            Some("channel map changed")
+        );
+        assert_eq!(
+            second.get(COLUMNS_ADDED_KEY).map(String::as_str),
+            Some("VB")
+        );
+        assert_eq!(row_count(&files[1]), 2);
+    }
+
+    #[test]
+    fn test_forced_rotation_on_config_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = ParquetSink::new(dir.path(), "capture").unwrap();
+
+        sink.write_batch(&batch_with_columns(&["VA"])).unwrap();
+        sink.mark_config_change("cfgcnt 1 -> 2");
+        sink.write_batch(&batch_with_columns(&["VA"])).unwrap();
+        sink.close().unwrap();
+
+        assert_eq!(sink.files().len(), 2);
+        let second = key_value_metadata(&sink.files()[1]);
+        assert_eq!(
+            second.get(CHANGE_REASON_KEY).map(String::as_str),
+            Some("cfgcnt 1 -> 2")
+        );
+    }
+
+    #[test]
+    fn test_parquet_options() {
+        use arrow::array::{Float64Array, StringArray, UInt16Array};
+        use parquet::basic::Encoding;
+        use parquet::file::properties::ReaderProperties;
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+        use parquet::file::serialized_reader::ReadOptionsBuilder;
+        use pmu::sinks::parquet::{ParquetOptions, StatisticsLevel};
+
+        let options: ParquetOptions = serde_json::from_str(
+            r#"{"row_group_rows": 4, "dictionary_columns": {"value": false},
+                "statistics": "chunk", "bloom_filter_columns": ["station", "idcode"],
+                "bloom_filter_fpp": 0.01}"#,
+        )
+        .unwrap();
+        assert!(options.dictionary);
+        assert!(options.check().is_empty());
+        assert_eq!(
+            ParquetOptions::default()
+                .with_row_group_rows(0)
+                .with_statistics(StatisticsLevel::None)
+                .check(),
+            vec!["row_group_rows must be positive"]
+        );
+
+        // Long format rows of two stations
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Microsecond, None),
+                false,
+            ),
+            Field::new("station", DataType::Utf8, false),
+            Field::new("idcode", DataType::UInt16, false),
+            Field::new("value", DataType::Float64, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(TimestampMicrosecondArray::from_iter_values(0..10)),
+                Arc::new(StringArray::from_iter_values((0..10).map(|i| {
+                    if i < 5 {
+                        "Station A"
+                    } else {
+                        "Station B"
+                    }
+                }))),
+                Arc::new(UInt16Array::from_iter_values((0..10).map(|i| {
+                    if i < 5 {
+                        7734
+                    } else {
+                        7735
+                    }
+                }))),
+                Arc::new(Float64Array::from_iter_values((0..10).map(f64::from))),
+            ],
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = ParquetSink::new(dir.path(), "long")
+            .unwrap()
+            .with_options(&options);
+        sink.write_batch(&batch).unwrap();
+        sink.close().unwrap();
+
+        let read_options = ReadOptionsBuilder::new()
+            .with_reader_properties(
+                ReaderProperties::builder()
+                    .set_read_bloom_filter(true)
+                    .build(),
+            )
+            .build();
+        let reader = SerializedFileReader::new_with_options(
+            File::open(&sink.files()[0]).unwrap(),
+            read_options,
+        )
+        .unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.num_row_groups(), 3);
+        let first = metadata.row_group(0);
+        // Statistics per row group only
+        assert!(first.column(0).statistics().is_some());
+        assert!(first.column(0).column_index_offset().is_none());
+        // Dictionary encoding off for the value column only
+        assert!(first.column(1).dictionary_page_offset().is_some());
+        assert!(first.column(3).dictionary_page_offset().is_none());
+        assert!(!first
+            .column(3)
+            .encodings()
+            .contains(&Encoding::RLE_DICTIONARY));
+
+        let row_group = reader.get_row_group(0).unwrap();
+        let stations = row_group.get_column_bloom_filter(1).unwrap();
+        assert!(stations.check(&"Station A"));
+        let idcodes = row_group.get_column_bloom_filter(2).unwrap();
+        assert!(idcodes.check(&7734i32));
+        assert!(row_group.get_column_bloom_filter(0).is_none());
+        assert!(row_group.get_column_bloom_filter(3).is_none());
+    }
+
+    #[test]
+    fn test_field_metadata_round_trip() {
+        use pmu::arrow_utils::{
+            build_record_batch, META_COMPONENT, META_NOMINAL_FREQUENCY, META_UNIT,
+        };
+        use pmu::simulator::{Scenario, SimulatedPmu, Simulator, StreamLayout};
+
+        let layout = StreamLayout {
+            idcode: 12,
+            data_rate: 25,
+            time_base: 1_000_000,
+            pmus: vec![SimulatedPmu {
+                station: "SUB".to_string(),
+                idcode: 12,
+                polar: true,
+                float_phasors: true,
+                float_analogs: false,
+                float_freq: true,
+                phasors: 1,
+                analogs: 0,
+                digitals: 0,
+                data_rate: None,
+                nominal_50hz: true,
+                angle: 0.0,
+            }],
+        };
+        let mut simulator = Simulator::from_layout(&layout, Scenario::default());
+        let config = simulator.config().clone();
+        let frames: Vec<u8> = (0..5)
+            .flat_map(|_| simulator.next_tick().frames.concat())
+            .collect();
+        let batch = build_record_batch(
+            &frames,
+            config.calc_data_frame_size(),
+            &config.get_channel_map(),
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = ParquetSink::new(dir.path(), "meta").unwrap();
+        sink.write_batch(&batch).unwrap();
+        sink.close().unwrap();
+
+        let builder =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(&sink.files()[0]).unwrap())
+                .unwrap();
+        let schema = builder.schema().clone();
+        let angle = schema
+            .field_with_name("SUB_12_VA_angle")
+            .unwrap()
+            .metadata();
+        assert_eq!(angle.get(META_COMPONENT).map(String::as_str), Some("angle"));
+        assert_eq!(angle.get(META_UNIT).map(String::as_str), Some("rad"));
+        let magnitude = schema
+            .field_with_name("SUB_12_VA_magnitude")
+            .unwrap()
+            .metadata();
+        assert_eq!(magnitude.get(META_UNIT).map(String::as_str), Some("V"));
+        let freq = schema.field_with_name("SUB_12_FREQ").unwrap().metadata();
+        assert_eq!(
+            freq.get(META_NOMINAL_FREQUENCY).map(String::as_str),
+            Some("50")
+        );
+    }
+}
+
+#[cfg(test)]
+mod partition_tests {
+    use arrow::array::{ArrayRef, Float32Array, TimestampMicrosecondArray};
+    use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+    use arrow::record_batch::RecordBatch;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use pmu::sinks::parquet::{escape_partition_value, ParquetOptions, PartitionedParquetSink};
+    use pmu::sinks::BatchSink;
+    use std::fs::File;
+    use std::sync::Arc;
+
+    fn batch_at(timestamps: Vec<i64>) -> RecordBatch {
+        let values: Vec<f32> = timestamps.iter().map(|t| *t as f32).collect();
+        let schema = Schema::new(vec![
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Microsecond, None),
+                false,
+            ),
+            Field::new("FREQ", DataType::Float32, false),
+        ]);
+        let arrays: Vec<ArrayRef> = vec![
+            Arc::new(TimestampMicrosecondArray::from(timestamps)),
+            Arc::new(Float32Array::from(values)),
+        ];
+        RecordBatch::try_new(Arc::new(schema), arrays).unwrap()
+    }
+
+    #[test]
+    fn test_escape_partition_value() {
+        assert_eq!(escape_partition_value("Station A"), "Station%20A");
+        assert_eq!(escape_partition_value("SUB_1-a.b"), "SUB_1-a.b");
+        assert_eq!(escape_partition_value("a/b"), "a%2Fb");
+    }
+
+    #[test]
+    fn test_rows_split_by_hour() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = PartitionedParquetSink::new(dir.path(), "Station A", 7734)
+            .unwrap()
+            .with_options(&ParquetOptions::default().with_row_group_rows(2));
+
+        // 2006-06-05 23:59:59 and 2006-06-06 00:00:00 / 00:00:01 UTC
+        let t0 = 1_149_551_999_000_000;
+        sink.write_batch(&batch_at(vec![t0, t0 + 1_000_000, t0 + 2_000_000]))
+            .unwrap();
+        sink.close().unwrap();
+
+        let files = sink.files().to_vec();
+        assert_eq!(files.len(), 2);
+        let first = files[0].strip_prefix(dir.path()).unwrap();
+        assert_eq!(
+            first.to_str().unwrap(),
+            "station=Station%20A/idcode=7734/date=2006-06-05/hour=23/part-000000.parquet"
+        );
+        let second = files[1].strip_prefix(dir.path()).unwrap();
+        assert_eq!(
+            second.to_str().unwrap(),
+            "station=Station%20A/idcode=7734/date=2006-06-06/hour=00/part-000000.parquet"
+        );
+
+        let reader =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(&files[1]).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        assert_eq!(reader.metadata().num_row_groups(), 1);
+    }
+
+    #[test]
+    fn test_old_partitions_are_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = PartitionedParquetSink::new(dir.path(), "A", 1).unwrap();
+        let hour = 3_600_000_000;
+
+        sink.write_batch(&batch_at(vec![0])).unwrap();
+        sink.write_batch(&batch_at(vec![hour])).unwrap();
+        assert!(sink.files().is_empty());
+
+        // Two hours past the first partition closes it.
+        sink.write_batch(&batch_at(vec![2 * hour])).unwrap();
+        assert_eq!(sink.files().len(), 1);
+        sink.close().unwrap();
+        assert_eq!(sink.files().len(), 3);
+    }
+
+    fn relative_files(sink: &PartitionedParquetSink, root: &std::path::Path) -> Vec<String> {
+        sink.files()
+            .iter()
+            .map(|f| f.strip_prefix(root).unwrap().to_str().unwrap().to_string())
+            .collect()
+    }
+
+    fn rows(file: &std::path::Path) -> i64 {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(file).unwrap()).unwrap();
+        reader.metadata().file_metadata().num_rows()
+    }
+
+    #[test]
+    fn test_local_time_dst_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = PartitionedParquetSink::new(dir.path(), "A", 1)
+            .unwrap()
+            .with_timezone(chrono_tz::Europe::Berlin);
+        // 2024-10-27 00:30, 01:30 and 02:30 UTC are 02:30 CEST, 02:30 CET and 03:30 CET
+        for soc in [1_729_989_000i64, 1_729_992_600, 1_729_996_200] {
+            sink.write_batch(&batch_at(vec![soc * 1_000_000])).unwrap();
+        }
+        sink.close().unwrap();
+
+        assert_eq!(
+            relative_files(&sink, dir.path()),
+            [
+                "station=A/idcode=1/date=2024-10-27/hour=02/part-000000.parquet",
+                "station=A/idcode=1/date=2024-10-27/hour=03/part-000000.parquet",
+            ]
+        );
+        assert_eq!(rows(&sink.files()[0]), 2);
+    }
+
+    #[test]
+    fn test_local_time_dst_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = PartitionedParquetSink::new(dir.path(), "A", 1)
+            .unwrap()
+            .with_timezone(chrono_tz::Europe::Berlin);
+        // 2024-03-31 00:30 and 01:30 UTC are 01:30 CET and 03:30 CEST
+        let soc = 1_711_845_000i64;
+        sink.write_batch(&batch_at(vec![soc * 1_000_000, (soc + 3600) * 1_000_000]))
+            .unwrap();
+        sink.close().unwrap();
+
+        assert_eq!(
+            relative_files(&sink, dir.path()),
+            [
+                "station=A/idcode=1/date=2024-03-31/hour=01/part-000000.parquet",
+                "station=A/idcode=1/date=2024-03-31/hour=03/part-000000.parquet",
+            ]
+        );
+        // UTC partitions are unaffected
+        let utc = PartitionedParquetSink::new(dir.path(), "A", 1).unwrap();
+        assert_eq!(utc.partition_hour(soc * 1_000_000), soc / 3600);
+    }
+
+    #[test]
+    fn test_reopened_partition_keeps_its_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = PartitionedParquetSink::new(dir.path(), "A", 1).unwrap();
+        let hour = 3_600_000_000;
+
+        sink.write_batch(&batch_at(vec![0])).unwrap();
+        sink.write_batch(&batch_at(vec![2 * hour])).unwrap();
+        // Late row for the closed first hour
+        sink.write_batch(&batch_at(vec![1])).unwrap();
+        sink.close().unwrap();
+
+        let files = relative_files(&sink, dir.path());
+        assert!(files.contains(
+            &"station=A/idcode=1/date=1970-01-01/hour=00/part-000000.parquet".to_string()
+        ));
+        assert!(files.contains(
+            &"station=A/idcode=1/date=1970-01-01/hour=00/part-000001.parquet".to_string()
+        ));
+        assert!(sink.files().iter().all(|f| rows(f) == 1));
+    }
+}
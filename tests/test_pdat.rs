@@ -0,0 +1,100 @@
+#![cfg(feature = "arrow")]
+#![allow(unused)]
+mod common;
+
+use arrow::array::AsArray;
+use arrow::datatypes::TimestampMicrosecondType;
+use common::{data_frame_us, read_hex_file};
+use pmu::arrow_utils::channel_value;
+use pmu::pdat::{scan_frames, ArchiveStats, PdatReader};
+use std::fs;
+
+const START_US: i64 = 1_700_000_000_000_000;
+
+// An archive with a vendor header and record headers between the frames.
+fn archive(frames: usize) -> Vec<u8> {
+    let mut bytes = b"PDAT\x00\x02\x00\x10vendor header...".to_vec();
+    bytes.extend(read_hex_file("config_message.bin").unwrap());
+    for n in 0..frames {
+        bytes.extend_from_slice(&(n as u32).to_be_bytes());
+        bytes.extend(data_frame_us(7734, START_US + n as i64 * 33_333));
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_frames() {
+        let config = read_hex_file("config_message.bin").unwrap();
+        let bytes = archive(3);
+        let (frames, skipped) = scan_frames(&bytes);
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0], &config[..]);
+        assert_eq!(frames[2], &data_frame_us(7734, START_US + 33_333)[..]);
+        assert_eq!(skipped, 24 + 3 * 4);
+
+        // A damaged frame is skipped as a whole, the next one is found
+        let mut bytes = archive(3);
+        let second = bytes.len() - 2 * data_frame_us(7734, 0).len() - 4;
+        bytes[second + 20] ^= 0xFF;
+        let (frames, _) = scan_frames(&bytes);
+        assert_eq!(frames.len(), 3);
+
+        let bytes = archive(2);
+        let (frames, skipped) = scan_frames(&bytes[..50]);
+        assert!(frames.is_empty());
+        assert_eq!(skipped, 50);
+    }
+
+    #[test]
+    fn test_read_archive() {
+        let mut rows = Vec::new();
+        let stats = PdatReader::new()
+            .with_batch_rows(4)
+            .read(&archive(10), |idcode, batch| {
+                assert_eq!(idcode, 7734);
+                rows.push(batch.num_rows());
+                let timestamps = batch.column(0).as_primitive::<TimestampMicrosecondType>();
+                assert_eq!(timestamps.value(0) % 33_333, START_US % 33_333);
+                assert!(
+                    (channel_value(&batch, "Station A_7734_FREQ", 0).unwrap() - 62.5).abs() < 1e-9
+                );
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(rows, [4, 4, 2]);
+        assert_eq!(stats.configs, 1);
+        assert_eq!(stats.data_frames, 10);
+        assert_eq!(stats.rows, 10);
+        assert_eq!(stats.unconfigured, 0);
+    }
+
+    #[test]
+    fn test_configuration_order() {
+        // Data frames before the configuration are not turned into rows
+        let mut bytes = data_frame_us(7734, START_US - 33_333);
+        bytes.extend(archive(2));
+        // A repeated configuration flushes the stream
+        bytes.extend(read_hex_file("config_message.bin").unwrap());
+        bytes.extend(data_frame_us(7734, START_US + 100_000));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.pdat");
+        fs::write(&path, &bytes).unwrap();
+        let batches = PdatReader::new().read_file(&path).unwrap();
+        let rows: Vec<usize> = batches.iter().map(|(_, batch)| batch.num_rows()).collect();
+        assert_eq!(rows, [2, 1]);
+
+        let stats = PdatReader::new().read(&bytes, |_, _| Ok(())).unwrap();
+        assert_eq!(stats.configs, 2);
+        assert_eq!(stats.unconfigured, 1);
+
+        // Nothing to describe the data
+        let err = PdatReader::new()
+            .read(&data_frame_us(7734, START_US), |_, _| Ok(()))
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
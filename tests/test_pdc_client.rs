@@ -1,3 +1,4 @@
+#![cfg(feature = "server")]
 #![allow(unused)]
 use arrow::array::{Array, Datum};
 use arrow::ipc::reader::FileReader;
@@ -5,7 +6,6 @@ use bytes::Bytes;
 use pmu::pdc_buffer_server;
 use pmu::pdc_client::{ControlMessage, PDCClient};
 use pmu::pdc_server::{run_mock_server, Protocol, ServerConfig};
-use reqwest;
 use std::io::Cursor;
 use std::time::Duration;
 use tokio::time;
@@ -129,7 +129,7 @@ async fn test_buffer_server_data_endpoint() {
 
     // Print first few rows
     println!("\nFirst few rows:");
-    for batch in reader {
+    if let Some(batch) = reader.into_iter().next() {
         let batch = batch.expect("Failed to read batch");
         println!("Number of rows: {}", batch.num_rows());
 
@@ -141,11 +141,181 @@ async fn test_buffer_server_data_endpoint() {
             );
         }
         println!();
-
-        break; // Just print the first batch
     }
 
     // Clean up
     buffer_server_handle.abort();
     pdc_server_handle.abort();
 }
+
+#[tokio::test]
+async fn test_stalled_stream() {
+    use pmu::events::{EventBus, EventKind};
+    use pmu::pdc_client::{ClientState, StallPolicy};
+    use pmu::simulator::{Scenario, ScenarioEvent};
+
+    // Data for 1 s, then 2 s of silence
+    let scenario = Scenario {
+        events: vec![ScenarioEvent::DropFrames { at: 1.0, count: 60 }],
+        ..Default::default()
+    };
+    let server_config = ServerConfig::new("127.0.0.1".to_string(), 4726, Protocol::TCP, 30.0)
+        .unwrap()
+        .with_scenario(scenario);
+    let server_handle = tokio::spawn(async move {
+        if let Err(e) = run_mock_server(server_config).await {
+            println!("Mock server error: {}", e)
+        };
+    });
+    time::sleep(Duration::from_millis(500)).await;
+
+    let bus = EventBus::default();
+    let mut events = bus.subscribe();
+    let (pdc_client, _, _) = PDCClient::new("127.0.0.1", 4726, 1, Duration::from_secs(10))
+        .await
+        .expect("Failed to create PDC Client");
+    // Half a second of silence at 30 frames/s
+    let mut pdc_client = pdc_client
+        .with_stall_policy(StallPolicy::new(15).with_resend_turn_on(2))
+        .with_event_bus(bus);
+    assert_eq!(pdc_client.status().state, ClientState::Configured);
+    let status = pdc_client.status_receiver();
+    let control_tx = pdc_client.get_control_sender();
+    let client_handle = tokio::spawn(async move {
+        pdc_client.start_stream().await;
+    });
+
+    let stalled = time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stalled.kind, EventKind::StreamStalled);
+    assert_eq!(stalled.source, "Station A");
+    assert!(stalled.values["silence_s"] >= 0.5);
+    assert_eq!(status.borrow().state, ClientState::Stalled);
+
+    let resumed = time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(resumed.kind, EventKind::StreamResumed);
+    let current = *status.borrow();
+    assert_eq!(current.state, ClientState::Streaming);
+    assert_eq!(current.stalls, 1);
+    assert!(current.turn_on_resends >= 1 && current.turn_on_resends <= 2);
+    assert!(current.frames_received >= 30);
+
+    control_tx.send(ControlMessage::Stop).await.unwrap();
+    time::timeout(Duration::from_secs(3), client_handle)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(status.borrow().state, ClientState::Stopped);
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_commanded_udp() {
+    use pmu::pdc_client::ClientState;
+
+    let server_config = ServerConfig::new("127.0.0.1".to_string(), 4727, Protocol::TCP, 30.0)
+        .unwrap()
+        .with_udp_data(4728);
+    let server_handle = tokio::spawn(async move {
+        if let Err(e) = run_mock_server(server_config).await {
+            println!("Mock server error: {}", e)
+        };
+    });
+    time::sleep(Duration::from_millis(500)).await;
+
+    let (pdc_client, _, mut data_rx) =
+        PDCClient::new("127.0.0.1", 4727, 1, Duration::from_secs(10))
+            .await
+            .expect("Failed to create PDC Client");
+    let mut pdc_client = pdc_client.with_udp_data(4728).await.unwrap();
+    let frame_size = pdc_client.get_frame_size();
+    let status = pdc_client.status_receiver();
+    let control_tx = pdc_client.get_control_sender();
+    let client_handle = tokio::spawn(async move {
+        pdc_client.start_stream().await;
+    });
+
+    time::sleep(Duration::from_secs(1)).await;
+    assert!(status.borrow().frames_received >= 10);
+    control_tx.send(ControlMessage::GetBuffer).await.unwrap();
+    let buffer = time::timeout(Duration::from_secs(3), data_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let frame = &buffer[..frame_size];
+    assert_eq!(frame[0], 0xAA);
+    assert_eq!(u16::from_be_bytes([frame[4], frame[5]]), 7734);
+
+    // Stop closes both sockets
+    control_tx.send(ControlMessage::Stop).await.unwrap();
+    time::timeout(Duration::from_secs(3), client_handle)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(status.borrow().state, ClientState::Stopped);
+    assert!(std::net::UdpSocket::bind("127.0.0.1:4728").is_ok());
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_session_stats() {
+    use pmu::pdc_client::ClientState;
+    use pmu::simulator::Scenario;
+
+    let server_config = ServerConfig::new("127.0.0.1".to_string(), 4752, Protocol::TCP, 30.0)
+        .unwrap()
+        .with_scenario(Scenario::default());
+    let server_handle = tokio::spawn(async move {
+        if let Err(e) = run_mock_server(server_config).await {
+            println!("Mock server error: {}", e)
+        };
+    });
+    time::sleep(Duration::from_millis(500)).await;
+
+    let (mut pdc_client, _, _) = PDCClient::new("127.0.0.1", 4752, 1, Duration::from_secs(10))
+        .await
+        .expect("Failed to create PDC Client");
+    let connected = pdc_client.stats();
+    assert_eq!((connected.config_frames, connected.data_frames), (1, 0));
+    assert_eq!((connected.commands_sent, connected.bytes_sent), (1, 18));
+    assert_eq!(connected.crc_failures, 0);
+    assert!(connected.last_command_us.is_some());
+    assert!(connected.last_response_us >= connected.last_command_us);
+
+    let frame_size = pdc_client.get_frame_size() as u64;
+    let stats = pdc_client.stats_receiver();
+    let control_tx = pdc_client.get_control_sender();
+    let client_handle = tokio::spawn(async move {
+        pdc_client.start_stream().await;
+        pdc_client
+    });
+    time::sleep(Duration::from_secs(1)).await;
+    let streaming = *stats.borrow();
+    assert!(streaming.data_frames >= 20, "{:?}", streaming);
+    assert_eq!(streaming.crc_failures, 0);
+    assert!(
+        streaming.bytes_received >= connected.bytes_received + streaming.data_frames * frame_size
+    );
+    assert!(streaming.last_data_us > streaming.last_response_us);
+
+    // The counters carry on over a reconnect
+    control_tx.send(ControlMessage::Stop).await.unwrap();
+    let mut pdc_client = time::timeout(Duration::from_secs(3), client_handle)
+        .await
+        .unwrap()
+        .unwrap();
+    pdc_client.reconnect().await.unwrap();
+    assert_eq!(pdc_client.status().state, ClientState::Configured);
+    let reconnected = pdc_client.stats();
+    assert_eq!(reconnected.reconnects, 1);
+    assert_eq!(reconnected.config_frames, 2);
+    assert!(reconnected.data_frames >= streaming.data_frames);
+    // Turn on, turn off and the configuration request
+    assert_eq!(reconnected.commands_sent, 4);
+    server_handle.abort();
+}
@@ -0,0 +1,414 @@
+#![cfg(feature = "server")]
+#![allow(unused)]
+use pmu::frames::{calculate_crc, CommandFrame2011};
+use pmu::metrics::Metrics;
+use pmu::pdc_server::{
+    run_mock_server, ClientPermission, CommandPolicy, CommandReplayGuard, ExtendedFramePolicy,
+    Protocol, ReplayAction, ReplayFinding, ServerConfig,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
+
+async fn start_server(port: u16, policy: CommandPolicy) {
+    let server_config = ServerConfig::new("127.0.0.1".to_string(), port, Protocol::TCP, 30.0)
+        .unwrap()
+        .with_policy(policy);
+    start_server_with_config(server_config).await;
+}
+
+async fn start_server_with_config(server_config: ServerConfig) {
+    tokio::spawn(async move {
+        if let Err(e) = run_mock_server(server_config).await {
+            println!("Mock server error: {}", e)
+        };
+    });
+    time::sleep(Duration::from_millis(500)).await;
+}
+
+// Bytes received from the server within the timeout.
+async fn read_for(stream: &mut TcpStream, timeout: Duration) -> Vec<u8> {
+    let mut received = Vec::new();
+    let mut buf = vec![0u8; 4096];
+    let deadline = time::Instant::now() + timeout;
+    while let Ok(Ok(n)) = time::timeout_at(deadline, stream.read(&mut buf)).await {
+        if n == 0 {
+            break;
+        }
+        received.extend_from_slice(&buf[..n]);
+    }
+    received
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_permissions() {
+        assert_eq!(ClientPermission::required_for(1), ClientPermission::Control);
+        assert_eq!(ClientPermission::required_for(2), ClientPermission::Control);
+        assert_eq!(
+            ClientPermission::required_for(5),
+            ClientPermission::ReadOnly
+        );
+        assert_eq!(ClientPermission::required_for(8), ClientPermission::Control);
+
+        let local = "127.0.0.1".parse().unwrap();
+        let other = "10.0.0.1".parse().unwrap();
+        let policy = CommandPolicy::new(ClientPermission::Denied)
+            .with_client(local, ClientPermission::ReadOnly);
+        assert!(policy.authorize(&local, 5));
+        assert!(!policy.authorize(&local, 2));
+        assert!(!policy.authorize(&other, 5));
+        assert!(CommandPolicy::default().authorize(&other, 2));
+    }
+
+    #[tokio::test]
+    async fn test_read_only_client_cannot_start_stream() {
+        let policy = CommandPolicy::new(ClientPermission::Denied)
+            .with_client("127.0.0.1".parse().unwrap(), ClientPermission::ReadOnly);
+        start_server(4721, policy).await;
+
+        let mut stream = TcpStream::connect("127.0.0.1:4721").await.unwrap();
+        stream
+            .write_all(&CommandFrame2011::new_turn_on_transmission(7734).to_hex())
+            .await
+            .unwrap();
+        assert!(read_for(&mut stream, Duration::from_millis(300))
+            .await
+            .is_empty());
+
+        // Configuration requests are still answered
+        stream
+            .write_all(&CommandFrame2011::new_send_config_frame1(7734).to_hex())
+            .await
+            .unwrap();
+        let received = read_for(&mut stream, Duration::from_millis(300)).await;
+        assert!(!received.is_empty());
+        assert_eq!(received[0], 0xAA);
+    }
+
+    #[tokio::test]
+    async fn test_denied_client_is_disconnected() {
+        let policy = CommandPolicy::new(ClientPermission::Denied);
+        start_server(4722, policy).await;
+
+        let mut stream = TcpStream::connect("127.0.0.1:4722").await.unwrap();
+        let _ = stream
+            .write_all(&CommandFrame2011::new_send_config_frame2(7734).to_hex())
+            .await;
+        let mut buf = [0u8; 64];
+        let n = time::timeout(Duration::from_secs(1), stream.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap_or(0);
+        assert_eq!(n, 0);
+    }
+
+    #[test]
+    fn test_replay_guard_findings() {
+        let guard = CommandReplayGuard::new(Duration::from_secs(2), ReplayAction::Reject);
+        let source = "127.0.0.1".parse().unwrap();
+        let now = 1_700_000_000_000_000;
+
+        assert_eq!(guard.check(source, now - 500_000, now), None);
+        assert_eq!(
+            guard.check(source, now - 500_000, now),
+            Some(ReplayFinding::Duplicate {
+                last_us: now - 500_000
+            })
+        );
+        assert_eq!(
+            guard.check(source, now - 3_000_000, now),
+            Some(ReplayFinding::Stale { age_us: 3_000_000 })
+        );
+        assert_eq!(
+            guard.check(source, now + 5_000_000, now),
+            Some(ReplayFinding::Future {
+                ahead_us: 5_000_000
+            })
+        );
+        assert_eq!(guard.check(source, now, now), None);
+
+        // Sources are tracked separately
+        let other = "10.0.0.1".parse().unwrap();
+        assert_eq!(guard.check(other, now - 500_000, now), None);
+    }
+
+    #[tokio::test]
+    async fn test_replayed_commands_are_rejected() {
+        let metrics = Arc::new(Metrics::new());
+        let guard = CommandReplayGuard::new(Duration::from_secs(5), ReplayAction::Reject);
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4724, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_replay_guard(Arc::new(guard))
+            .with_metrics(metrics.clone());
+        start_server_with_config(server_config).await;
+
+        let mut stream = TcpStream::connect("127.0.0.1:4724").await.unwrap();
+        let mut cmd = CommandFrame2011::new_send_config_frame1(7734);
+        cmd.stamp_now();
+        let bytes = cmd.to_hex();
+
+        stream.write_all(&bytes).await.unwrap();
+        assert!(!read_for(&mut stream, Duration::from_millis(300))
+            .await
+            .is_empty());
+
+        // Captured command sent again
+        stream.write_all(&bytes).await.unwrap();
+        assert!(read_for(&mut stream, Duration::from_millis(300))
+            .await
+            .is_empty());
+
+        // Unstamped command
+        stream
+            .write_all(&CommandFrame2011::new_send_config_frame1(7734).to_hex())
+            .await
+            .unwrap();
+        assert!(read_for(&mut stream, Duration::from_millis(300))
+            .await
+            .is_empty());
+
+        assert_eq!(
+            metrics.counter(
+                "pmu_server_command_replays_total",
+                &[("reason", "duplicate")]
+            ),
+            1
+        );
+        assert_eq!(
+            metrics.counter("pmu_server_command_replays_total", &[("reason", "stale")]),
+            1
+        );
+        assert_eq!(
+            metrics.counter(
+                "pmu_server_commands_total",
+                &[("command", "send_config_frame1"), ("outcome", "rejected")]
+            ),
+            2
+        );
+        assert!(metrics
+            .render()
+            .contains("# TYPE pmu_server_command_replays_total counter"));
+    }
+
+    #[test]
+    fn test_extended_frame_policy() {
+        let extended =
+            |data: &[u8]| CommandFrame2011::new_extended_frame(7734).with_extframe(data.to_vec());
+        let frame = extended(&[0x00, 0x01, 0xAB, 0xCD]);
+        let bytes = frame.to_hex();
+        assert_eq!(bytes.len(), 22);
+        assert_eq!(u16::from_be_bytes([bytes[2], bytes[3]]), 22);
+
+        // Denied by default, other commands unaffected
+        let policy = ExtendedFramePolicy::default();
+        assert_eq!(
+            policy.check(&frame),
+            Err("extended frames are denied".to_string())
+        );
+        assert!(policy
+            .check(&CommandFrame2011::new_turn_on_transmission(7734))
+            .is_ok());
+
+        let policy =
+            ExtendedFramePolicy::allow(4).with_hook("opcode", |data| match data.get(..2) {
+                Some([0x00, 0x01]) => Ok(()),
+                _ => Err("unknown opcode".to_string()),
+            });
+        assert!(policy.check(&frame).is_ok());
+        assert_eq!(
+            policy.check(&extended(&[0x00, 0x02])),
+            Err("opcode: unknown opcode".to_string())
+        );
+        assert!(policy
+            .check(&extended(&[0x00, 0x01, 0x00, 0x00, 0x00, 0x00]))
+            .unwrap_err()
+            .contains("at most 4"));
+        assert!(policy
+            .check(&extended(&[0x00, 0x01, 0x00]))
+            .unwrap_err()
+            .contains("not whole words"));
+        let smuggled = CommandFrame2011::new_turn_on_transmission(7734).with_extframe(vec![0; 2]);
+        assert!(policy.check(&smuggled).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_extended_frames_are_scrubbed() {
+        let metrics = Arc::new(Metrics::new());
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4751, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_extended_frames(ExtendedFramePolicy::allow(16).with_hook("opcode", |data| {
+                match data.first() {
+                    Some(0x00) => Ok(()),
+                    _ => Err("unknown opcode".to_string()),
+                }
+            }))
+            .with_metrics(metrics.clone());
+        start_server_with_config(server_config).await;
+
+        let mut stream = TcpStream::connect("127.0.0.1:4751").await.unwrap();
+        let extended = |data: &[u8]| {
+            CommandFrame2011::new_extended_frame(7734)
+                .with_extframe(data.to_vec())
+                .to_hex()
+        };
+        for frame in [
+            extended(&[0x00, 0x01]),
+            extended(&[0xFF, 0x01]),
+            extended(&[0x00; 64]),
+            // Truncated and garbage input must not take the connection down
+            extended(&[0x00, 0x01])[..10].to_vec(),
+            vec![0xAA],
+        ] {
+            stream.write_all(&frame).await.unwrap();
+            assert!(read_for(&mut stream, Duration::from_millis(200))
+                .await
+                .is_empty());
+        }
+        stream
+            .write_all(&CommandFrame2011::new_send_config_frame1(7734).to_hex())
+            .await
+            .unwrap();
+        let received = read_for(&mut stream, Duration::from_millis(300)).await;
+        assert_eq!(received.first(), Some(&0xAA));
+
+        let count = |outcome| {
+            metrics.counter(
+                "pmu_server_commands_total",
+                &[("command", "extended_frame"), ("outcome", outcome)],
+            )
+        };
+        assert_eq!((count("accepted"), count("rejected")), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn test_non_command_frames_are_dropped() {
+        start_server(4757, CommandPolicy::default()).await;
+        let mut stream = TcpStream::connect("127.0.0.1:4757").await.unwrap();
+
+        // Header and configuration 3 frames with a valid CHK
+        for sync in [0xAA11u16, 0xAA51] {
+            let mut frame = sync.to_be_bytes().to_vec();
+            frame.extend_from_slice(&16u16.to_be_bytes());
+            frame.extend_from_slice(&7734u16.to_be_bytes());
+            frame.extend_from_slice(&[0; 8]);
+            let crc = calculate_crc(&frame);
+            frame.extend_from_slice(&crc.to_be_bytes());
+            stream.write_all(&frame).await.unwrap();
+            assert!(read_for(&mut stream, Duration::from_millis(200))
+                .await
+                .is_empty());
+        }
+        // The connection is still served
+        stream
+            .write_all(&CommandFrame2011::new_send_config_frame1(7734).to_hex())
+            .await
+            .unwrap();
+        let received = read_for(&mut stream, Duration::from_millis(300)).await;
+        assert_eq!(received.first(), Some(&0xAA));
+    }
+
+    #[tokio::test]
+    async fn test_server_streams_simulated_frames() {
+        let scenario = pmu::simulator::Scenario {
+            start_soc: Some(1_700_000_000),
+            ..Default::default()
+        };
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4725, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_scenario(scenario);
+        start_server_with_config(server_config).await;
+
+        let mut stream = TcpStream::connect("127.0.0.1:4725").await.unwrap();
+        stream
+            .write_all(&CommandFrame2011::new_turn_on_transmission(7734).to_hex())
+            .await
+            .unwrap();
+        let received = read_for(&mut stream, Duration::from_millis(500)).await;
+        let frame_size = u16::from_be_bytes([received[2], received[3]]) as usize;
+        assert!(received.len() >= 2 * frame_size);
+        assert_eq!(
+            pmu::arrow_utils::frame_timestamp_micros(&received[..frame_size]),
+            Some(1_700_000_000_000_000)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_upsamples_simulated_stream() {
+        let scenario = pmu::simulator::Scenario {
+            start_soc: Some(1_700_000_000),
+            ..Default::default()
+        };
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4743, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_scenario(scenario)
+            .with_output_rate(60);
+        start_server_with_config(server_config).await;
+
+        let mut stream = TcpStream::connect("127.0.0.1:4743").await.unwrap();
+        stream
+            .write_all(&CommandFrame2011::new_send_config_frame1(7734).to_hex())
+            .await
+            .unwrap();
+        let received = read_for(&mut stream, Duration::from_millis(300)).await;
+        let config = pmu::frame_parser::parse_config_frame_1and2(&received).unwrap();
+        assert_eq!(config.data_rate, 60);
+
+        stream
+            .write_all(&CommandFrame2011::new_turn_on_transmission(7734).to_hex())
+            .await
+            .unwrap();
+        let received = read_for(&mut stream, Duration::from_millis(500)).await;
+        let frame_size = config.calc_data_frame_size();
+        let frames: Vec<&[u8]> = received.chunks_exact(frame_size).collect();
+        assert!(frames.len() >= 3);
+        for (n, frame) in frames.iter().enumerate() {
+            assert_eq!(
+                pmu::arrow_utils::frame_timestamp_micros(frame),
+                Some(1_700_000_000_000_000 + (n as f64 * 1e6 / 60.0).round() as i64)
+            );
+            // Every other frame is interpolated, STAT bit 9
+            let stat = u16::from_be_bytes([frame[14], frame[15]]);
+            assert_eq!(stat & 0x0200 != 0, n % 2 == 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_serves_virtual_pmus() {
+        let virtual_stream = pmu::virtual_pmu::VirtualStreamConfig::from_json(
+            r#"{"idcode": 900, "pmus": [{"station": "AREA", "idcode": 901,
+                "angle_differences": [{"name": "VA-VB", "from": "VA", "to": "VB"}]}]}"#,
+        )
+        .unwrap();
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4744, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_virtual_stream(virtual_stream);
+        start_server_with_config(server_config).await;
+
+        let mut stream = TcpStream::connect("127.0.0.1:4744").await.unwrap();
+        stream
+            .write_all(&CommandFrame2011::new_send_config_frame1(7734).to_hex())
+            .await
+            .unwrap();
+        let received = read_for(&mut stream, Duration::from_millis(300)).await;
+        let config = pmu::frame_parser::parse_config_frame_1and2(&received).unwrap();
+        assert_eq!(config.prefix.idcode, 900);
+        assert_eq!(config.pmu_configs[0].idcode, 901);
+
+        stream
+            .write_all(&CommandFrame2011::new_turn_on_transmission(7734).to_hex())
+            .await
+            .unwrap();
+        let received = read_for(&mut stream, Duration::from_millis(200)).await;
+        let frame_size = config.calc_data_frame_size();
+        assert!(received.len() >= frame_size);
+        let measurements =
+            pmu::analytics::accuracy::frame_measurements(&received[..frame_size], &config).unwrap();
+        assert_eq!(measurements.len(), 1);
+    }
+}
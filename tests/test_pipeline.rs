@@ -0,0 +1,454 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+
+#[cfg(test)]
+mod tests {
+    use pmu::checkpoint::Checkpoint;
+    use pmu::frames::CrcMode;
+    use pmu::pdc_server::{
+        run_mock_server, ClientPermission, CommandPolicy, Protocol, ServerConfig,
+    };
+    use pmu::pipeline::{ExecutionMode, Pipeline, PipelineConfig, SinkFormat};
+    use pmu::simulator::{Scenario, SimulatedPmu, StreamLayout};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time;
+
+    fn layout(idcode: u16) -> StreamLayout {
+        StreamLayout {
+            idcode,
+            data_rate: 30,
+            time_base: 1_000_000,
+            pmus: vec![SimulatedPmu {
+                station: format!("PMU {}", idcode),
+                idcode,
+                polar: false,
+                float_phasors: false,
+                float_analogs: false,
+                float_freq: false,
+                phasors: 3,
+                analogs: 0,
+                digitals: 0,
+                data_rate: None,
+                nominal_50hz: false,
+                angle: 0.0,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_pipeline_config() {
+        let config = PipelineConfig::from_json(
+            r#"{
+                "streams": [
+                    {"host": "10.0.0.1", "port": 4712},
+                    {"host": "10.0.0.2", "port": 4712, "idcode": 7},
+                    {"host": "10.0.0.3", "port": 4712, "udp_port": 4713,
+                     "crc_mode": "exclude_sync", "lenient": true}
+                ],
+                "sink": {"format": "csv", "dir": "out"},
+                "execution": {"mode": "sharded", "shards": 2},
+                "salvage": true
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.sink.format, SinkFormat::Csv);
+        assert_eq!(config.batch_rows, 1800);
+        assert_eq!(config.streams[1].idcode, 7);
+        assert_eq!(config.streams[2].udp_port, Some(4713));
+        assert_eq!(config.streams[0].crc_mode, CrcMode::Standard);
+        assert_eq!(config.streams[2].crc_mode, CrcMode::ExcludeSync);
+        assert!(config.streams[2].lenient);
+        assert!(config.salvage);
+        let shards = config.shard_streams();
+        let hosts: Vec<Vec<&str>> = shards
+            .iter()
+            .map(|shard| shard.iter().map(|s| s.host.as_str()).collect())
+            .collect();
+        assert_eq!(hosts, vec![vec!["10.0.0.1", "10.0.0.3"], vec!["10.0.0.2"]]);
+
+        // Never more shards than streams, shared is a single one
+        let mut config = config;
+        config.execution = ExecutionMode::Sharded { shards: Some(8) };
+        assert_eq!(config.shards(), 3);
+        config.execution = ExecutionMode::Shared;
+        assert_eq!(config.shard_streams().len(), 1);
+        assert!(PipelineConfig::from_json(r#"{"streams": []}"#).is_err());
+    }
+
+    #[test]
+    fn test_server_permissions() {
+        let config = PipelineConfig::from_json(
+            r#"{
+                "streams": [],
+                "sink": {"dir": "out"},
+                "server": {"permissions": {
+                    "default": "denied",
+                    "clients": {"10.0.0.5": "control", "10.0.0.6": "read_only"}
+                }}
+            }"#,
+        )
+        .unwrap();
+        let policy = config.server.unwrap().permissions;
+        let permission = |ip: &str| policy.permission(&ip.parse().unwrap());
+        assert_eq!(permission("10.0.0.5"), ClientPermission::Control);
+        assert_eq!(permission("10.0.0.6"), ClientPermission::ReadOnly);
+        assert_eq!(permission("10.0.0.7"), ClientPermission::Denied);
+
+        // Without a policy every client has control, as before
+        let config =
+            PipelineConfig::from_json(r#"{"streams": [], "sink": {"dir": "out"}, "server": {}}"#)
+                .unwrap();
+        assert_eq!(config.server.unwrap().permissions, CommandPolicy::default());
+        assert!(PipelineConfig::from_json(
+            r#"{"streams": [], "sink": {"dir": "out"},
+                "server": {"permissions": {"default": "admin"}}}"#,
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sharded_pipeline() {
+        let ports = [4732, 4733, 4734];
+        let mut servers = Vec::new();
+        for (n, port) in ports.iter().enumerate() {
+            let scenario = Scenario {
+                stream: Some(layout(101 + n as u16)),
+                ..Default::default()
+            };
+            let server_config =
+                ServerConfig::new("127.0.0.1".to_string(), *port, Protocol::TCP, 30.0)
+                    .unwrap()
+                    .with_scenario(scenario);
+            servers.push(tokio::spawn(async move {
+                if let Err(e) = run_mock_server(server_config).await {
+                    println!("Mock server error: {}", e)
+                };
+            }));
+        }
+        time::sleep(Duration::from_millis(500)).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let streams: Vec<String> = ports
+            .iter()
+            .map(|port| format!(r#"{{"host": "127.0.0.1", "port": {}}}"#, port))
+            .collect();
+        let config = PipelineConfig::from_json(&format!(
+            r#"{{"streams": [{}], "sink": {{"format": "csv", "dir": {:?}}},
+                "execution": {{"mode": "sharded", "shards": 2}}, "batch_rows": 10}}"#,
+            streams.join(","),
+            dir.path()
+        ))
+        .unwrap();
+        let pipeline = Arc::new(Pipeline::new(config));
+        let runner = pipeline.clone();
+        let handle = tokio::spawn(async move { runner.run().await });
+
+        time::sleep(Duration::from_millis(1500)).await;
+        pipeline.stop();
+        let stats = time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].streams, 2);
+        assert_eq!(stats[1].streams, 1);
+        for shard in &stats {
+            assert_eq!(shard.errors, 0);
+            assert_eq!(shard.dropped, 0);
+            assert!(shard.frames >= 10 * shard.streams as u64, "{:?}", shard);
+            // Buffered rows are written when stopping
+            assert_eq!(shard.rows, shard.frames);
+        }
+        for idcode in [101, 102, 103] {
+            let csv = std::fs::read_to_string(dir.path().join(format!("{}.csv", idcode))).unwrap();
+            assert!(csv.lines().count() > 10, "{}", idcode);
+        }
+        for server in servers {
+            server.abort();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_resumes_from_checkpoint() {
+        let scenario = Scenario {
+            stream: Some(layout(104)),
+            ..Default::default()
+        };
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4735, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_scenario(scenario);
+        let server = tokio::spawn(async move {
+            if let Err(e) = run_mock_server(server_config).await {
+                println!("Mock server error: {}", e)
+            };
+        });
+        time::sleep(Duration::from_millis(500)).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let json = format!(
+            r#"{{"streams": [{{"host": "127.0.0.1", "port": 4735}}],
+                "sink": {{"format": "csv", "dir": {:?}}},
+                "checkpoint": {{"dir": {:?}}}}}"#,
+            dir.path().join("out"),
+            dir.path().join("checkpoints")
+        );
+        let mut last = None;
+        for _ in 0..2 {
+            let pipeline = Arc::new(Pipeline::new(PipelineConfig::from_json(&json).unwrap()));
+            let runner = pipeline.clone();
+            let handle = tokio::spawn(async move { runner.run().await });
+            time::sleep(Duration::from_millis(1000)).await;
+            pipeline.stop();
+            let stats = time::timeout(Duration::from_secs(5), handle)
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert_eq!(stats[0].errors, 0);
+            assert!(stats[0].frames > 0, "{:?}", stats[0]);
+
+            let checkpoint = Checkpoint::load(dir.path().join("checkpoints/shard-0.json"))
+                .unwrap()
+                .unwrap();
+            let stream = checkpoint.stream_from("127.0.0.1:4735").unwrap();
+            assert_eq!(stream.config.config().unwrap().prefix.idcode, 104);
+            assert!(stream.last_timestamp_us > last);
+            last = stream.last_timestamp_us;
+        }
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_remaps_streams() {
+        let scenario = Scenario {
+            stream: Some(layout(105)),
+            ..Default::default()
+        };
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4736, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_scenario(scenario);
+        let server = tokio::spawn(async move {
+            if let Err(e) = run_mock_server(server_config).await {
+                println!("Mock server error: {}", e)
+            };
+        });
+        time::sleep(Duration::from_millis(500)).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("remap.csv"),
+            "kind,stream,from,to\nstream,,105,205\nstation,105,PMU 105,BUS_7\n",
+        )
+        .unwrap();
+        let json = format!(
+            r#"{{"streams": [{{"host": "127.0.0.1", "port": 4736}}],
+                "sink": {{"format": "csv", "dir": {:?}}},
+                "checkpoint": {{"dir": {:?}}},
+                "remap": {:?}}}"#,
+            dir.path().join("out"),
+            dir.path().join("checkpoints"),
+            dir.path().join("remap.csv")
+        );
+        let pipeline = Arc::new(Pipeline::new(PipelineConfig::from_json(&json).unwrap()));
+        let runner = pipeline.clone();
+        let handle = tokio::spawn(async move { runner.run().await });
+        time::sleep(Duration::from_millis(1000)).await;
+        pipeline.stop();
+        let stats = time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(stats[0].errors, 0);
+        assert!(stats[0].frames > 0, "{:?}", stats[0]);
+
+        let csv = std::fs::read_to_string(dir.path().join("out/205.csv")).unwrap();
+        let header = csv.lines().next().unwrap();
+        assert!(header.contains("BUS_7_105_"), "{}", header);
+        assert!(!header.contains("PMU 105"), "{}", header);
+        assert!(!dir.path().join("out/105.csv").exists());
+
+        // The checkpoint keeps the configuration as the PDC sent it
+        let checkpoint = Checkpoint::load(dir.path().join("checkpoints/shard-0.json"))
+            .unwrap()
+            .unwrap();
+        let stream = checkpoint.stream_from("127.0.0.1:4736").unwrap();
+        assert_eq!(stream.config.config().unwrap().prefix.idcode, 105);
+        assert!(checkpoint.streams.contains_key(&205));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_sink_rates() {
+        let scenario = Scenario {
+            stream: Some(layout(106)),
+            ..Default::default()
+        };
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4737, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_scenario(scenario);
+        let server = tokio::spawn(async move {
+            if let Err(e) = run_mock_server(server_config).await {
+                println!("Mock server error: {}", e)
+            };
+        });
+        time::sleep(Duration::from_millis(500)).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let json = format!(
+            r#"{{"streams": [{{"host": "127.0.0.1", "port": 4737}}],
+                "sink": {{"format": "csv", "dir": {:?}}},
+                "sinks": [{{"format": "csv", "dir": {:?}, "rate_hz": 5}}],
+                "batch_rows": 10}}"#,
+            dir.path().join("full"),
+            dir.path().join("slow")
+        );
+        let pipeline = Arc::new(Pipeline::new(PipelineConfig::from_json(&json).unwrap()));
+        let runner = pipeline.clone();
+        let handle = tokio::spawn(async move { runner.run().await });
+        time::sleep(Duration::from_millis(2000)).await;
+        pipeline.stop();
+        let stats = time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(stats[0].errors, 0);
+        assert_eq!(stats[0].rows, stats[0].frames);
+
+        let rows = |sink: &str| {
+            let csv = std::fs::read_to_string(dir.path().join(sink).join("106.csv")).unwrap();
+            csv.lines().count() as u64 - 1
+        };
+        assert_eq!(rows("full"), stats[0].frames);
+        // One row in six at 30 fps, give or take the interval boundaries
+        let slow = rows("slow");
+        assert!(
+            slow * 6 <= stats[0].frames + 12,
+            "{} of {}",
+            slow,
+            stats[0].frames
+        );
+        assert!(
+            slow * 6 + 12 >= stats[0].frames,
+            "{} of {}",
+            slow,
+            stats[0].frames
+        );
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_validate() {
+        let scenario = Scenario {
+            stream: Some(layout(107)),
+            ..Default::default()
+        };
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4738, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_scenario(scenario);
+        let server = tokio::spawn(async move {
+            if let Err(e) = run_mock_server(server_config).await {
+                println!("Mock server error: {}", e)
+            };
+        });
+        time::sleep(Duration::from_millis(500)).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("remap.csv"),
+            "kind,stream,from,to\nstream,,107,207\nstation,107,PMU 107,BUS_8\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("taken"), "").unwrap();
+        // Nothing listens on 4739; a file is in the way of the snapshots
+        let json = format!(
+            r#"{{"streams": [{{"host": "127.0.0.1", "port": 4738}},
+                             {{"host": "127.0.0.1", "port": 4739}}],
+                "sink": {{"format": "csv", "dir": {:?}}},
+                "remap": {:?},
+                "snapshot": {{"dir": {:?}}}}}"#,
+            dir.path().join("out"),
+            dir.path().join("remap.csv"),
+            dir.path().join("taken").join("snapshots")
+        );
+        let config = PipelineConfig::from_json(&json).unwrap();
+        let validation = Pipeline::validate(&config).await;
+        validation.print();
+        assert!(!validation.is_ok());
+        assert_eq!(validation.problems.len(), 2, "{:?}", validation.problems);
+        assert!(validation.problems[0].starts_with("Snapshot directory"));
+        assert!(validation.problems[1].starts_with("Stream 127.0.0.1:4739"));
+
+        let stream = &validation.streams[0];
+        assert_eq!(stream.error, None);
+        assert_eq!(stream.idcode, Some(207));
+        assert_eq!(stream.data_rate, Some(30));
+        // STAT, frequency, ROCOF and three phasors, as remapped
+        assert_eq!(stream.channels.len(), 6, "{:?}", stream.channels);
+        assert!(stream.channels.iter().all(|c| c.station == "BUS_8"));
+        assert!(stream.channels.iter().any(|c| c.kind == "voltage"));
+        assert!(validation.streams[1].error.is_some());
+
+        // The sink directory is there, empty
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("out")).unwrap().count(),
+            0
+        );
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_ingest_control() {
+        let scenario = Scenario {
+            stream: Some(layout(108)),
+            ..Default::default()
+        };
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4740, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_scenario(scenario);
+        let server = tokio::spawn(async move {
+            if let Err(e) = run_mock_server(server_config).await {
+                println!("Mock server error: {}", e)
+            };
+        });
+        time::sleep(Duration::from_millis(500)).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let json = format!(
+            r#"{{"streams": [{{"host": "127.0.0.1", "port": 4740}}],
+                "sink": {{"format": "csv", "dir": {:?}}},
+                "batch_rows": 10}}"#,
+            dir.path()
+        );
+        let pipeline = Arc::new(Pipeline::new(PipelineConfig::from_json(&json).unwrap()));
+        let control = pipeline.ingest_control();
+        control.set_channel("PMU 108_108_VB", false);
+        let runner = pipeline.clone();
+        let handle = tokio::spawn(async move { runner.run().await });
+        time::sleep(Duration::from_millis(1000)).await;
+        control.set_stream(108, false);
+        time::sleep(Duration::from_millis(700)).await;
+        pipeline.stop();
+        let stats = time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(stats[0].errors, 0);
+        assert!(stats[0].frames > 0, "{:?}", stats[0]);
+        assert!(stats[0].disabled > 0, "{:?}", stats[0]);
+        // Every frame taken in is written, those buffered when disabling too
+        assert_eq!(stats[0].rows, stats[0].frames);
+
+        let csv = std::fs::read_to_string(dir.path().join("108.csv")).unwrap();
+        let header = csv.lines().next().unwrap();
+        assert!(header.contains("PMU 108_108_VA"), "{}", header);
+        assert!(!header.contains("PMU 108_108_VB"), "{}", header);
+        assert_eq!(csv.lines().count() as u64 - 1, stats[0].rows);
+        server.abort();
+    }
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "plot")]
+#![allow(unused)]
+mod common;
+
+use arrow::record_batch::RecordBatch;
+use common::{data_frame_us, frame_time, read_hex_file, with_crc, START_US};
+use pmu::accumulator::BatchAccumulator;
+use pmu::budget::MemoryBudget;
+use pmu::events::{Event, EventKind, Severity};
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::plot::QuickLook;
+use std::fs;
+
+// The sample configuration under another idcode and station name.
+fn config_frame(idcode: u16, station: &str) -> Vec<u8> {
+    let mut frame = read_hex_file("config_message.bin").unwrap();
+    frame[4..6].copy_from_slice(&idcode.to_be_bytes());
+    let mut name = [b' '; 16];
+    name[..station.len()].copy_from_slice(station.as_bytes());
+    frame[20..36].copy_from_slice(&name);
+    frame[36..38].copy_from_slice(&idcode.to_be_bytes());
+    with_crc(frame)
+}
+
+// Two seconds of two stations.
+fn batches() -> Vec<RecordBatch> {
+    let mut accumulator = BatchAccumulator::new(MemoryBudget::unlimited());
+    for (idcode, station) in [(1, "Station A"), (2, "Station B")] {
+        let config = parse_config_frame_1and2(&config_frame(idcode, station)).unwrap();
+        accumulator.add_stream(&config);
+        for n in 0..60 {
+            accumulator
+                .push_frame(&data_frame_us(idcode, frame_time(n)))
+                .unwrap();
+        }
+    }
+    accumulator
+        .flush_all()
+        .unwrap()
+        .into_iter()
+        .map(|(_, batch)| batch)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quick_look_svg() {
+        let mut chart = QuickLook::new().with_title("Quick look");
+        for batch in batches() {
+            chart.add_batch(&batch).unwrap();
+        }
+        let svg = chart.render_svg().unwrap();
+        assert!(svg.starts_with("<svg"), "{}", &svg[..100]);
+        assert!(svg.contains("Quick look"));
+        assert!(svg.contains("Frequency"));
+        assert!(svg.contains("Angle difference"));
+        assert!(svg.contains("Station B - Station A"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let stats = chart.render(dir.path().join("chart.svg")).unwrap();
+        assert_eq!(stats.panels, 3);
+        // Frequency and the three voltage magnitudes of both, one angle difference
+        assert_eq!(stats.series, 9, "{:?}", stats);
+        assert_eq!(stats.points, 9 * 60);
+        assert!(chart.render(dir.path().join("chart.txt")).is_err());
+    }
+
+    #[test]
+    fn test_quick_look_event_window() {
+        let event = Event::new(
+            START_US + 1_000_000,
+            EventKind::GeneratorTrip,
+            "Station A",
+            "Trip".to_string(),
+        )
+        .with_severity(Severity::Alarm);
+        let mut chart = QuickLook::for_event(&event, 0.5, 0.5)
+            .with_magnitudes(&["Station A_1_VA"])
+            .with_size(640, 480);
+        for batch in batches() {
+            chart.add_batch(&batch).unwrap();
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("event.png");
+        let stats = chart.render(&path).unwrap();
+        // 0.5 s either side at 30 frames/s of two frequencies, one magnitude
+        // and one angle difference
+        assert_eq!(stats.series, 4, "{:?}", stats);
+        assert_eq!(stats.points, 4 * 31);
+        let png = fs::read(&path).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+    }
+}
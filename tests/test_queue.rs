@@ -0,0 +1,83 @@
+#![cfg(feature = "client")]
+#![allow(unused)]
+
+#[cfg(test)]
+mod tests {
+    use pmu::queue::Rings;
+    use std::time::Duration;
+    use tokio::time;
+
+    #[test]
+    fn test_rings_take_turns() {
+        let mut rings = Rings::new();
+        let mut a = rings.add(8);
+        let mut b = rings.add(8);
+        for n in 0..3 {
+            assert!(a.push(("a", n)));
+        }
+        assert!(b.push(("b", 0)));
+        assert_eq!(rings.len(), 4);
+
+        let popped: Vec<(&str, i32)> = std::iter::from_fn(|| rings.try_pop()).collect();
+        assert_eq!(popped, [("a", 0), ("b", 0), ("a", 1), ("a", 2)]);
+        assert!(rings.is_empty());
+    }
+
+    #[test]
+    fn test_full_ring_drops() {
+        let mut rings = Rings::new();
+        let mut producer = rings.add(2);
+        assert!(producer.push(1));
+        assert!(producer.push(2));
+        assert!(!producer.push(3));
+        assert_eq!(producer.slots(), 0);
+        assert_eq!(rings.dropped(), 1);
+        assert_eq!(rings.try_pop(), Some(1));
+        assert!(producer.push(4));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_pop_waits_for_producers() {
+        let mut rings = Rings::new();
+        let producers: Vec<_> = (0..4).map(|_| rings.add(16)).collect();
+        for (stream, mut producer) in producers.into_iter().enumerate() {
+            tokio::spawn(async move {
+                for n in 0..50 {
+                    while !producer.push((stream, n)) {
+                        tokio::task::yield_now().await;
+                    }
+                    if n % 10 == 0 {
+                        time::sleep(Duration::from_millis(1)).await;
+                    }
+                }
+            });
+        }
+
+        // Every value once, in order per stream, then None when all are done
+        let mut next = [0; 4];
+        while let Some((stream, n)) = time::timeout(Duration::from_secs(5), rings.pop())
+            .await
+            .unwrap()
+        {
+            assert_eq!(n, next[stream]);
+            next[stream] += 1;
+        }
+        assert_eq!(next, [50; 4]);
+        assert_eq!(rings.dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_pop_ends_when_producer_dropped() {
+        let mut rings = Rings::<u32>::new();
+        let mut producer = rings.add(4);
+        producer.push(7);
+        tokio::spawn(async move {
+            time::sleep(Duration::from_millis(50)).await;
+            drop(producer);
+        });
+        assert_eq!(rings.pop().await, Some(7));
+        // Waiting when the producer goes away
+        let end = time::timeout(Duration::from_secs(2), rings.pop()).await;
+        assert_eq!(end, Ok(None));
+    }
+}
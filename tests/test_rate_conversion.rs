@@ -0,0 +1,114 @@
+#![cfg(feature = "arrow")]
+#![allow(unused)]
+mod common;
+
+use common::{crc_ok, read_hex_file};
+use pmu::arrow_utils::frame_timestamp_micros;
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::frames::ConfigurationFrame1and2_2011;
+use pmu::rate_conversion::RateConverter;
+use pmu::simulator::{Scenario, ScenarioEvent, Simulator};
+
+// Sample config: STAT, then 4 fixed point rectangular phasors, fixed FREQ/DFREQ.
+const STAT_OFFSET: usize = 14;
+const FREQ_OFFSET: usize = 14 + 2 + 4 * 4;
+
+fn config() -> ConfigurationFrame1and2_2011 {
+    parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap()
+}
+
+// Frames of the 30 fps sample stream with the frequency ramping.
+fn frames(count: usize) -> Vec<Vec<u8>> {
+    let scenario = Scenario {
+        start_soc: Some(1_700_000_000),
+        events: vec![ScenarioEvent::FrequencyRamp {
+            at: 0.0,
+            duration: 10.0,
+            rate: 0.3,
+        }],
+        ..Default::default()
+    };
+    let mut simulator = Simulator::new(config(), scenario);
+    (0..count)
+        .flat_map(|_| simulator.next_tick().frames)
+        .collect()
+}
+
+fn stat(frame: &[u8]) -> u16 {
+    u16::from_be_bytes([frame[STAT_OFFSET], frame[STAT_OFFSET + 1]])
+}
+
+fn frequency(frame: &[u8]) -> i16 {
+    i16::from_be_bytes([frame[FREQ_OFFSET], frame[FREQ_OFFSET + 1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upsampling_interpolates_flagged_frames() {
+        let mut converter = RateConverter::new(&config(), 60).unwrap();
+        assert_eq!(converter.output_rate(), 60);
+        assert_eq!(converter.output_config().data_rate, 60);
+        let input = frames(31);
+        let output: Vec<Vec<u8>> = input.iter().flat_map(|f| converter.push(f)).collect();
+        assert_eq!(output.len(), 61);
+        assert_eq!(converter.interpolated(), 30);
+
+        let start_us = 1_700_000_000_000_000;
+        for (n, frame) in output.iter().enumerate() {
+            let expected_us = start_us + (n as f64 * 1e6 / 60.0).round() as i64;
+            assert_eq!(frame_timestamp_micros(frame), Some(expected_us));
+            assert!(crc_ok(frame));
+            if n % 2 == 0 {
+                // Measured frames pass as they are
+                assert_eq!(frame, &input[n / 2]);
+                assert_eq!(stat(frame) & 0x0200, 0);
+            } else {
+                assert_ne!(stat(frame) & 0x0200, 0);
+                let (before, after) = (frequency(&input[n / 2]), frequency(&input[n / 2 + 1]));
+                let midpoint = (before as f64 + after as f64) / 2.0;
+                assert!((frequency(frame) as f64 - midpoint).abs() <= 0.5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decimation_keeps_frames_on_the_grid() {
+        let mut converter = RateConverter::new(&config(), 10).unwrap();
+        let input = frames(30);
+        let output: Vec<Vec<u8>> = input.iter().flat_map(|f| converter.push(f)).collect();
+        assert_eq!(output.len(), 10);
+        assert_eq!(converter.interpolated(), 0);
+        for (n, frame) in output.iter().enumerate() {
+            assert_eq!(frame, &input[3 * n]);
+        }
+
+        // The same rate passes everything
+        let mut converter = RateConverter::new(&config(), 30).unwrap();
+        let output: Vec<Vec<u8>> = input.iter().flat_map(|f| converter.push(f)).collect();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_rate_conversion_across_gaps() {
+        let mut converter = RateConverter::new(&config(), 60).unwrap();
+        let input = frames(10);
+        let mut output = Vec::new();
+        // Frames 4 to 6 lost: nothing is made up across the gap
+        for frame in input[..4].iter().chain(&input[7..]) {
+            output.extend(converter.push(frame));
+        }
+        assert_eq!(output.len(), 7 + 3 + 2);
+        assert_eq!(output[7], input[7]);
+        // Repeated and older frames are dropped
+        assert!(converter.push(&input[9]).is_empty());
+        assert!(converter.push(&input[2]).is_empty());
+        // Wrong size
+        assert!(converter.push(&input[0][..20]).is_empty());
+
+        assert!(RateConverter::new(&config(), 0).is_err());
+        assert!(RateConverter::new(&config(), -5).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+#![cfg(feature = "sinks")]
+#![allow(unused)]
+mod common;
+
+use common::data_frame_at;
+
+#[cfg(test)]
+mod tests {
+    use super::data_frame_at;
+    use pmu::recorder::{CaptureCompression, CaptureReader, CaptureWriter};
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    fn write_capture(path: &std::path::Path, compression: CaptureCompression, frames: u32) {
+        let mut writer = CaptureWriter::create(path, compression)
+            .unwrap()
+            .with_block_frames(10);
+        for soc in 0..frames {
+            writer
+                .write_frame_at(&data_frame_at(1_000 + soc, 0), soc as i64)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_capture_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capture.pmucap");
+        write_capture(&path, CaptureCompression::None, 25);
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+        assert!(!reader.is_compressed());
+        assert!(reader.is_indexed());
+        assert_eq!(reader.index().len(), 3);
+        assert_eq!(reader.frame_count(), 25);
+
+        let records: Vec<_> = reader.records().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 25);
+        assert_eq!(records[7].frame, data_frame_at(1_007, 0));
+        assert_eq!(records[7].arrival_us, 7);
+    }
+
+    #[test]
+    fn test_zstd_capture_is_smaller_and_decompresses() {
+        let dir = tempfile::tempdir().unwrap();
+        let raw_path = dir.path().join("raw.pmucap");
+        let zstd_path = dir.path().join("zstd.pmucap");
+        write_capture(&raw_path, CaptureCompression::None, 200);
+        write_capture(&zstd_path, CaptureCompression::Zstd(3), 200);
+
+        let raw_len = fs::metadata(&raw_path).unwrap().len();
+        let zstd_len = fs::metadata(&zstd_path).unwrap().len();
+        assert!(zstd_len * 3 < raw_len, "{} vs {}", zstd_len, raw_len);
+
+        let mut raw = CaptureReader::open(&raw_path).unwrap();
+        let mut compressed = CaptureReader::open(&zstd_path).unwrap();
+        assert!(compressed.is_compressed());
+        let raw_records: Vec<_> = raw.records().map(|r| r.unwrap()).collect();
+        let zstd_records: Vec<_> = compressed.records().map(|r| r.unwrap()).collect();
+        assert_eq!(raw_records, zstd_records);
+    }
+
+    #[test]
+    fn test_seek_by_timestamp() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capture.pmucap");
+        write_capture(&path, CaptureCompression::Zstd(3), 50);
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+        let target = 1_023 * 1_000_000;
+        let first = reader.seek(target).next().unwrap().unwrap();
+        assert_eq!(first.frame, data_frame_at(1_023, 0));
+        assert_eq!(reader.seek(target).count(), 27);
+
+        // Past the end of the capture
+        assert_eq!(reader.seek(2_000 * 1_000_000).count(), 0);
+    }
+
+    #[test]
+    fn test_unfinished_capture_is_scanned() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capture.pmucap");
+        write_capture(&path, CaptureCompression::Zstd(3), 25);
+
+        // Cut off the index and half of the last block, as after a crash.
+        let reader = CaptureReader::open(&path).unwrap();
+        let last_block = reader.index()[2].offset;
+        drop(reader);
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(last_block + 20).unwrap();
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+        assert!(!reader.is_indexed());
+        assert_eq!(reader.index().len(), 2);
+        assert_eq!(reader.records().count(), 20);
+    }
+
+    #[test]
+    fn test_resume_after_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capture.pmucap");
+        let mut writer = CaptureWriter::create(&path, CaptureCompression::None)
+            .unwrap()
+            .with_block_frames(10);
+        for soc in 0..25 {
+            writer
+                .write_frame_at(&data_frame_at(1_000 + soc, 0), soc as i64)
+                .unwrap();
+        }
+        // Crash with a torn block on disk and five frames never written
+        std::mem::forget(writer);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0xAA; 40]).unwrap();
+        drop(file);
+
+        let mut writer = CaptureWriter::resume(&path, CaptureCompression::None)
+            .unwrap()
+            .with_block_frames(10);
+        for soc in 20..23 {
+            writer
+                .write_frame_at(&data_frame_at(1_000 + soc, 0), soc as i64)
+                .unwrap();
+        }
+        assert_eq!(writer.finish().unwrap().len(), 3);
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+        assert!(reader.is_indexed());
+        let arrivals: Vec<i64> = reader.records().map(|r| r.unwrap().arrival_us).collect();
+        assert_eq!(arrivals, (0..23).collect::<Vec<i64>>());
+        assert!(CaptureWriter::resume(&path, CaptureCompression::Zstd(3)).is_err());
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("not_a_capture.bin");
+        fs::write(&path, b"hello world, this is not a capture").unwrap();
+        assert!(CaptureReader::open(&path).is_err());
+    }
+}
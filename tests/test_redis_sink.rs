@@ -0,0 +1,105 @@
+#![cfg(feature = "redis")]
+#![allow(unused)]
+mod common;
+
+use common::repeated_batch;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+
+// Minimal Redis on a local port: answers count commands, with an error for
+// XADDs to the stream named reject, then returns the commands received.
+fn fake_redis(count: usize) -> (String, JoinHandle<Vec<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let handle = thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(socket.try_clone().unwrap());
+        let mut writer = socket;
+        let mut commands = Vec::new();
+        for n in 0..count {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let arguments: usize = line.trim_end()[1..].parse().unwrap();
+            let mut command = Vec::new();
+            for _ in 0..arguments {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                let length: usize = line.trim_end()[1..].parse().unwrap();
+                let mut argument = vec![0; length + 2];
+                reader.read_exact(&mut argument).unwrap();
+                argument.truncate(length);
+                command.push(String::from_utf8(argument).unwrap());
+            }
+            let reply = match command[0].as_str() {
+                "XADD" if command[1].starts_with("reject") => "-ERR rejected\r\n".to_string(),
+                "XADD" => format!("$15\r\n1700000000000-{}\r\n", n % 10),
+                _ => "+OK\r\n".to_string(),
+            };
+            writer.write_all(reply.as_bytes()).unwrap();
+            commands.push(command);
+        }
+        commands
+    });
+    (address, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fake_redis, repeated_batch};
+    use pmu::sinks::redis::RedisStreamSink;
+    use pmu::sinks::BatchSink;
+    use serde_json::Value;
+
+    #[test]
+    fn test_xadd_per_pmu_payloads() {
+        // AUTH, SELECT and two rows of the one PMU
+        let (address, server) = fake_redis(4);
+        let url = format!("redis://:secret@{}/2", address);
+        let mut sink = RedisStreamSink::new(&url)
+            .unwrap()
+            .with_prefix("grid")
+            .with_maxlen(1000);
+        sink.write_batch(&repeated_batch(2)).unwrap();
+        assert_eq!(sink.entries(), 2);
+
+        let commands = server.join().unwrap();
+        assert_eq!(commands[0], ["AUTH", "secret"]);
+        assert_eq!(commands[1], ["SELECT", "2"]);
+        let xadd = &commands[2];
+        assert_eq!(
+            xadd[..7],
+            ["XADD", "grid:7734", "MAXLEN", "~", "1000", "*", "data"]
+        );
+        let payload: Value = serde_json::from_str(&xadd[7]).unwrap();
+        assert_eq!(payload["idcode"], 7734);
+        assert_eq!(payload["station"], "Station A");
+        // FREQ of the sample frame is 60 Hz + 2500 mHz
+        assert_eq!(payload["values"]["FREQ"], 62.5);
+        assert!(payload["values"]["VA_X"].as_f64().unwrap() > 0.0);
+        assert_eq!(payload["values"]["DATA_VALID"], 1.0);
+        assert!(payload["timestamp_us"].as_i64().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_exact_trim_and_rejected_entries() {
+        let (address, server) = fake_redis(2);
+        let mut sink = RedisStreamSink::new(&address)
+            .unwrap()
+            .with_prefix("reject")
+            .with_maxlen(10)
+            .with_exact_trim(true);
+        let error = sink.write_batch(&repeated_batch(2)).unwrap_err();
+        assert!(error.to_string().contains("ERR rejected"), "{}", error);
+        assert_eq!(sink.entries(), 0);
+        let commands = server.join().unwrap();
+        assert_eq!(commands[0][2..5], ["MAXLEN", "=", "10"]);
+    }
+
+    #[test]
+    fn test_invalid_addresses() {
+        assert!(RedisStreamSink::new("redis://").is_err());
+        assert!(RedisStreamSink::new("redis://host/db").is_err());
+        assert!(RedisStreamSink::new("redis://user:pw@host:6380/0").is_ok());
+    }
+}
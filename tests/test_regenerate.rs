@@ -0,0 +1,189 @@
+#![cfg(feature = "arrow")]
+#![allow(unused)]
+mod common;
+
+use common::read_hex_file;
+use pmu::arrow_utils::{
+    build_record_batch, build_record_batch_with, ArrowOptions, PhasorColumns, StatColumns,
+};
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::frames::ConfigurationFrame1and2_2011;
+use pmu::simulator::{Scenario, SimulatedPmu, Simulator, StreamLayout};
+
+fn pmu(idcode: u16, float: bool, polar: bool) -> SimulatedPmu {
+    SimulatedPmu {
+        station: format!("PMU {}", idcode),
+        idcode,
+        polar,
+        float_phasors: float,
+        float_analogs: float,
+        float_freq: float,
+        phasors: 2,
+        analogs: 1,
+        digitals: 1,
+        data_rate: None,
+        nominal_50hz: false,
+        angle: 0.3,
+    }
+}
+
+// Frames of a second of a simulated stream, back to back, and its configuration.
+fn simulated(
+    config: Option<ConfigurationFrame1and2_2011>,
+    layout: Option<StreamLayout>,
+) -> (Vec<Vec<u8>>, ConfigurationFrame1and2_2011) {
+    let scenario = Scenario {
+        start_soc: Some(1_700_000_000),
+        duration: Some(1.0),
+        ..Default::default()
+    };
+    let mut simulator = match (config, layout) {
+        (Some(config), _) => Simulator::new(config, scenario),
+        (None, Some(layout)) => Simulator::from_layout(&layout, scenario),
+        (None, None) => unreachable!(),
+    };
+    let mut frames = Vec::new();
+    while !simulator.is_finished() {
+        frames.extend(simulator.next_tick().frames);
+    }
+    (frames, simulator.config().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{ArrayRef, UInt8Array};
+    use arrow::datatypes::{Field, Schema};
+    use arrow::record_batch::RecordBatch;
+    use pmu::arrow_utils::{QUALITY_COLUMN, QUALITY_HELD};
+    use pmu::frame_parser::parse_data_frames;
+    use pmu::frames::PMUData;
+    use pmu::regenerate::{FrameRegenerator, RegenerateError};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_round_trip_sample_stream() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let (frames, config) = simulated(Some(config), None);
+        let frame_size = config.calc_data_frame_size();
+        let batch =
+            build_record_batch(&frames.concat(), frame_size, &config.get_channel_map()).unwrap();
+
+        let regenerator = FrameRegenerator::new(&config);
+        assert_eq!(regenerator.frames(&batch).unwrap(), frames);
+        let parsed = regenerator.data_frames(&batch).unwrap();
+        assert_eq!(parsed.len(), 30);
+        assert_eq!(parsed[0], parse_data_frames(&frames[0], &config).unwrap());
+    }
+
+    #[test]
+    fn test_round_trip_layouts() {
+        let layout = StreamLayout {
+            idcode: 60,
+            data_rate: 30,
+            time_base: 1_000_000,
+            pmus: vec![
+                pmu(61, false, false),
+                pmu(62, true, true),
+                pmu(63, true, false),
+            ],
+        };
+        let (frames, config) = simulated(None, Some(layout));
+        let frame_size = config.calc_data_frame_size();
+        let regenerator = FrameRegenerator::new(&config);
+        let buffer = frames.concat();
+
+        // Components and raw STAT as received
+        let batch = build_record_batch(&buffer, frame_size, &config.get_channel_map()).unwrap();
+        assert_eq!(regenerator.frames(&batch).unwrap(), frames);
+
+        // Complex phasors and decoded STAT, polar float phasors within rounding
+        let options = ArrowOptions::default()
+            .with_phasor_columns(PhasorColumns::Complex)
+            .with_stat_columns(StatColumns::Decoded);
+        let batch =
+            build_record_batch_with(&buffer, frame_size, &config.get_channel_map(), &options)
+                .unwrap();
+        let regenerated = regenerator.data_frames(&batch).unwrap();
+        for (frame, regenerated) in frames.iter().zip(&regenerated) {
+            let original = parse_data_frames(frame, &config).unwrap();
+            assert_eq!(original.prefix, regenerated.prefix);
+            for (pmu, (original, regenerated)) in config
+                .pmu_configs
+                .iter()
+                .zip(original.data.iter().zip(&regenerated.data))
+            {
+                let original = original.phasor_values(pmu);
+                let regenerated = regenerated.phasor_values(pmu);
+                for (a, b) in original.iter().zip(&regenerated) {
+                    assert!(
+                        (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4,
+                        "{:?} {:?}",
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_columns_by_metadata_and_quality() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let (frames, config) = simulated(Some(config), None);
+        let frame_size = config.calc_data_frame_size();
+        let batch =
+            build_record_batch(&frames.concat(), frame_size, &config.get_channel_map()).unwrap();
+        let regenerator = FrameRegenerator::new(&config);
+
+        // Renamed phasor columns found by their metadata, reversed order and
+        // a quality column holding the first row
+        let schema = batch.schema();
+        let mut fields: Vec<Field> = schema
+            .fields()
+            .iter()
+            .map(|field| match field.name().strip_suffix("_X") {
+                Some(name) => field.as_ref().clone().with_name(format!("{} real", name)),
+                None => field.as_ref().clone(),
+            })
+            .rev()
+            .collect();
+        let mut columns: Vec<ArrayRef> = batch.columns().iter().rev().cloned().collect();
+        let mut quality = vec![0u8; batch.num_rows()];
+        quality[0] = QUALITY_HELD;
+        fields.push(Field::new(
+            QUALITY_COLUMN,
+            arrow::datatypes::DataType::UInt8,
+            false,
+        ));
+        columns.push(Arc::new(UInt8Array::from(quality)));
+        let renamed = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap();
+
+        let regenerated = regenerator.frames(&renamed).unwrap();
+        assert_eq!(regenerated[1..], frames[1..]);
+        let held = parse_data_frames(&regenerated[0], &config).unwrap();
+        assert_eq!(held.data[0].stat() & 0x0200, 0x0200);
+
+        // A channel without a column
+        let name = schema
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .find(|name| name.ends_with("_FREQ"))
+            .unwrap();
+        let mut missing = batch.clone();
+        missing.remove_column(schema.index_of(&name).unwrap());
+        assert_eq!(
+            regenerator.frames(&missing).unwrap_err(),
+            RegenerateError::MissingColumn(name)
+        );
+        let mut no_time = batch.clone();
+        no_time.remove_column(0);
+        assert_eq!(
+            regenerator.frames(&no_time).unwrap_err(),
+            RegenerateError::NoTimestamp
+        );
+    }
+}
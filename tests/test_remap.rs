@@ -0,0 +1,79 @@
+#![allow(unused)]
+mod common;
+
+use common::{crc_ok, read_hex_file};
+
+#[cfg(test)]
+mod tests {
+    use super::{crc_ok, read_hex_file};
+    use pmu::frame_parser::parse_config_frame_1and2;
+    use pmu::remap::Remap;
+    use std::borrow::Cow;
+
+    // Sample config: stream and PMU 7734, "Station A" with VA, VB, VC, I1, ...
+    const TABLE: &str = "kind,stream,from,to
+        # Duplicate name in the field
+        stream,,7734,1001
+        pmu,7734,7734,2001
+        station,7734,Station A,SUB_NORTH
+        station,,Station A,SUB_OTHER
+        channel,,VA,VA_BUS1
+        channel,9999,VB,NOT_THIS_ONE
+    ";
+
+    #[test]
+    fn test_remap_config_frame() {
+        let remap = Remap::from_csv(TABLE).unwrap();
+        let frame = read_hex_file("config_message.bin").unwrap();
+        let remapped = remap.apply(&frame).unwrap();
+        assert!(matches!(remapped, Cow::Owned(_)));
+        assert!(crc_ok(&remapped));
+
+        let config = parse_config_frame_1and2(&remapped).unwrap();
+        assert_eq!(config.prefix.idcode, 1001);
+        let pmu = &config.pmu_configs[0];
+        assert_eq!(pmu.idcode, 2001);
+        // The rule of the stream wins over the general one
+        assert_eq!(&pmu.stn, b"SUB_NORTH       ");
+        let columns = pmu.get_column_names();
+        assert_eq!(columns[0], "SUB_NORTH_2001_VA_BUS1");
+        assert_eq!(columns[1], "SUB_NORTH_2001_VB");
+    }
+
+    #[test]
+    fn test_remap_data_frame() {
+        let remap = Remap::from_csv(TABLE).unwrap();
+        let frame = read_hex_file("data_message.bin").unwrap();
+        let remapped = remap.apply(&frame).unwrap();
+        assert_eq!(u16::from_be_bytes([remapped[4], remapped[5]]), 1001);
+        assert!(crc_ok(&remapped));
+        assert_eq!(remapped[6..remapped.len() - 2], frame[6..frame.len() - 2]);
+
+        // A bad CHK stays bad
+        let mut corrupt = frame.clone();
+        let len = corrupt.len();
+        corrupt[len - 1] ^= 0xFF;
+        assert!(!crc_ok(&remap.apply(&corrupt).unwrap()));
+    }
+
+    #[test]
+    fn test_unmatched_frames_are_borrowed() {
+        let remap = Remap::from_csv("channel,9999,VA,OTHER").unwrap();
+        let config = read_hex_file("config_message.bin").unwrap();
+        let data = read_hex_file("data_message.bin").unwrap();
+        assert!(matches!(remap.apply(&config).unwrap(), Cow::Borrowed(_)));
+        assert!(matches!(remap.apply(&data).unwrap(), Cow::Borrowed(_)));
+        assert_eq!(remap.stream_idcode(7734), 7734);
+        assert!(Remap::default().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_tables() {
+        assert!(Remap::from_csv("station,,A").is_err());
+        assert!(Remap::from_csv("stream,,7734,70000").is_err());
+        assert!(Remap::from_csv("station,,A,NAME_LONGER_THAN_16").is_err());
+        assert!(Remap::from_csv("phasor,,VA,VB").is_err());
+        let error = Remap::from_csv("kind,stream,from,to\nstream,x,1,2").unwrap_err();
+        assert!(error.starts_with("Line 2"), "{}", error);
+    }
+}
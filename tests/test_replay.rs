@@ -0,0 +1,208 @@
+#![cfg(all(feature = "client", feature = "parquet"))]
+#![allow(unused)]
+mod common;
+
+use common::{data_frame_at, read_hex_file};
+
+#[cfg(test)]
+mod tests {
+    use super::{data_frame_at, read_hex_file};
+    use pmu::arrow_utils::frame_timestamp_micros;
+    use pmu::frame_parser::parse_config_frame_1and2;
+    use pmu::recorder::{CaptureCompression, CaptureWriter};
+    use pmu::replay::{
+        clamp_speed, CaptureSource, PlaybackCommand, PlaybackOptions, Player, MAX_SPEED, MIN_SPEED,
+    };
+    use pmu::simulator::{Scenario, Simulator};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use tokio::time::Instant;
+
+    // 20 frames, one second of SOC apart, received 100 ms apart.
+    fn capture(dir: &tempfile::TempDir) -> std::path::PathBuf {
+        let path = dir.path().join("capture.pmucap");
+        let mut writer = CaptureWriter::create(&path, CaptureCompression::Zstd(3))
+            .unwrap()
+            .with_block_frames(4);
+        for i in 0..20u32 {
+            writer
+                .write_frame_at(&data_frame_at(1_000 + i, 0), i as i64 * 100_000)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        path
+    }
+
+    fn soc(frame: &[u8]) -> u32 {
+        u32::from_be_bytes(frame[6..10].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_speed_is_clamped() {
+        assert_eq!(clamp_speed(0.01), MIN_SPEED);
+        assert_eq!(clamp_speed(1_000.0), MAX_SPEED);
+        assert_eq!(clamp_speed(2.5), 2.5);
+        assert_eq!(PlaybackOptions::default().with_speed(500.0).speed, 100.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_capture_playback_speed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = capture(&dir);
+
+        for (speed, expected) in [(1.0, 1_900), (10.0, 190), (0.5, 3_800)] {
+            let source = CaptureSource::open(&path).unwrap();
+            let mut player = Player::new(source, PlaybackOptions::default().with_speed(speed));
+            let (tx, mut rx) = mpsc::channel(100);
+            let start = Instant::now();
+            let sent = player.run(tx).await.unwrap();
+            assert_eq!(sent, 20);
+            assert_eq!(start.elapsed().as_millis(), expected);
+
+            let mut socs = Vec::new();
+            while let Ok(frame) = rx.try_recv() {
+                socs.push(soc(&frame));
+            }
+            assert_eq!(socs, (1_000..1_020).collect::<Vec<_>>());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_seek_and_pause() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = CaptureSource::open(capture(&dir)).unwrap();
+        let mut player = Player::new(source, PlaybackOptions::default());
+        let control = player.control_sender();
+        let (tx, mut rx) = mpsc::channel(100);
+        let handle = tokio::spawn(async move { player.run(tx).await.unwrap() });
+
+        assert_eq!(soc(&rx.recv().await.unwrap()), 1_000);
+        control
+            .send(PlaybackCommand::Seek(1_013 * 1_000_000))
+            .await
+            .unwrap();
+        assert_eq!(soc(&rx.recv().await.unwrap()), 1_013);
+
+        control.send(PlaybackCommand::Pause).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let paused = Instant::now();
+        assert!(tokio::time::timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .is_err());
+        control.send(PlaybackCommand::Resume).await.unwrap();
+        assert_eq!(soc(&rx.recv().await.unwrap()), 1_014);
+
+        control.send(PlaybackCommand::Stop).await.unwrap();
+        assert!(handle.await.unwrap() <= 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_looping_capture() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = CaptureSource::open(capture(&dir)).unwrap();
+        let mut player = Player::new(
+            source,
+            PlaybackOptions::default()
+                .with_speed(100.0)
+                .with_looping(true),
+        );
+        let control = player.control_sender();
+        let (tx, mut rx) = mpsc::channel(100);
+        let handle = tokio::spawn(async move { player.run(tx).await.unwrap() });
+
+        let mut socs = Vec::new();
+        for _ in 0..45 {
+            socs.push(soc(&rx.recv().await.unwrap()));
+        }
+        assert_eq!(socs[19], 1_019);
+        assert_eq!(socs[20], 1_000);
+        assert_eq!(socs[44], 1_004);
+
+        control.send(PlaybackCommand::Stop).await.unwrap();
+        drop(rx);
+        handle.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_simulator_source() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let scenario = Scenario {
+            start_soc: Some(1_700_000_000),
+            duration: Some(2.0),
+            ..Default::default()
+        };
+        let mut player = Player::new(
+            Simulator::new(config, scenario),
+            PlaybackOptions::default().with_speed(2.0),
+        );
+        let (tx, mut rx) = mpsc::channel(100);
+        let start = Instant::now();
+        assert_eq!(player.run(tx).await.unwrap(), 60);
+        assert!((start.elapsed().as_millis() as i64 - 1_000).abs() <= 1);
+
+        // Seeking the simulator skips ahead on its schedule
+        let mut simulator = player.into_source();
+        simulator.rewind();
+        simulator.seek(1_700_000_001_000_000);
+        let tick = simulator.next_tick();
+        assert_eq!(tick.time_us, 1_700_000_001_000_000);
+        assert_eq!(
+            frame_timestamp_micros(&tick.frames[0]),
+            Some(1_700_000_001_000_000)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batch_source_from_parquet() {
+        use pmu::arrow_utils::build_record_batch;
+        use pmu::replay::{BatchSource, FrameSource};
+        use pmu::sinks::parquet::ParquetSink;
+        use pmu::sinks::BatchSink;
+
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let scenario = Scenario {
+            start_soc: Some(1_700_000_000),
+            duration: Some(2.0),
+            ..Default::default()
+        };
+        let mut simulator = Simulator::new(config.clone(), scenario);
+        let mut frames = Vec::new();
+        while !simulator.is_finished() {
+            frames.extend(simulator.next_tick().frames);
+        }
+
+        // Archived in batches of a second
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = ParquetSink::new(dir.path(), "7734").unwrap();
+        let frame_size = config.calc_data_frame_size();
+        for second in frames.chunks(30) {
+            let batch = build_record_batch(&second.concat(), frame_size, &config.get_channel_map())
+                .unwrap();
+            sink.write_batch(&batch).unwrap();
+        }
+        sink.close().unwrap();
+
+        let source = BatchSource::open_parquet(&config, sink.files()).unwrap();
+        assert_eq!(source.len(), 60);
+        let mut player = Player::new(source, PlaybackOptions::default());
+        let (tx, mut rx) = mpsc::channel(100);
+        let start = Instant::now();
+        assert_eq!(player.run(tx).await.unwrap(), 60);
+        // Paced by the timestamps, the first frame sent at once
+        assert!((start.elapsed().as_millis() as i64 - 1_967).abs() <= 1);
+        let mut replayed = Vec::new();
+        while let Ok(frame) = rx.try_recv() {
+            replayed.push(frame);
+        }
+        assert_eq!(replayed, frames);
+
+        let mut source = player.into_source();
+        source.seek(1_700_000_001_000_000).unwrap();
+        let (_, next) = source.next_frames().unwrap().unwrap();
+        assert_eq!(next[0], frames[30]);
+        source.rewind().unwrap();
+        assert_eq!(source.next_frames().unwrap().unwrap().1[0], frames[0]);
+    }
+}
@@ -0,0 +1,267 @@
+#![cfg(feature = "analytics")]
+#![allow(unused)]
+mod common;
+
+use common::{data_frame_at, read_hex_file, with_crc};
+
+// Sample data frame at soc plus fracsec microseconds, with a STAT.
+fn data_frame_with_stat(soc: u32, fracsec: u32, stat: u16) -> Vec<u8> {
+    let mut frame = data_frame_at(soc, fracsec);
+    frame[14..16].copy_from_slice(&stat.to_be_bytes());
+    with_crc(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{data_frame_with_stat, read_hex_file};
+    use pmu::annotations::{Annotation, AnnotationKind, AnnotationStore};
+    use pmu::areas::{AreaConfig, AreaMap};
+    use pmu::budget::MemoryBudget;
+    use pmu::events::{Event, EventBus, EventKind};
+    use pmu::frame_parser::parse_config_frame_1and2;
+    use pmu::historian::Historian;
+    use pmu::reports::{ReportConfig, ReportKind, ReportScheduler, Schedule};
+    use serde_json::Value;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // 2024-01-01 00:00:00 UTC
+    const MIDNIGHT_US: i64 = 1_704_067_200 * 1_000_000;
+    const HOUR_US: i64 = 3_600_000_000;
+
+    // Sample stream 7734 at 30 frames/s for the first minute after midnight,
+    // with 10 frames missing at 20 s and the frames of second 30 flagged bad.
+    fn historian() -> Arc<Mutex<Historian>> {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let mut historian = Historian::new(MemoryBudget::unlimited());
+        historian.add_stream(&config);
+        let soc = (MIDNIGHT_US / 1_000_000) as u32;
+        for second in 0..60 {
+            for frame in 0..30 {
+                if second == 20 && frame < 10 {
+                    continue;
+                }
+                let stat = if second == 30 { 0x8000 } else { 0 };
+                let fracsec = (frame as f64 * 1e6 / 30.0).round() as u32;
+                historian
+                    .insert(&data_frame_with_stat(soc + second, fracsec, stat))
+                    .unwrap();
+            }
+        }
+        Arc::new(Mutex::new(historian))
+    }
+
+    fn config(outputs: &str) -> ReportConfig {
+        ReportConfig::from_json(&format!(
+            r#"{{"jobs": [
+                {{"name": "quality", "kind": "quality", "schedule": {{"every": "hourly"}}, "outputs": {o}}},
+                {{"name": "events", "kind": "events", "schedule": {{"every": "hourly"}}, "outputs": {o}}},
+                {{"name": "compliance", "kind": "compliance", "schedule": {{"every": "daily", "hour": 1}}, "outputs": {o}}}
+            ]}}"#,
+            o = outputs
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_schedule_next_run() {
+        let hourly = Schedule::Hourly { minute: 15 };
+        assert_eq!(
+            hourly.next_after(MIDNIGHT_US, None),
+            MIDNIGHT_US + HOUR_US / 4
+        );
+        assert_eq!(
+            hourly.next_after(MIDNIGHT_US + HOUR_US / 4, None),
+            MIDNIGHT_US + HOUR_US * 5 / 4
+        );
+
+        // 06:00 in Berlin is 05:00 UTC in winter and 04:00 UTC in summer
+        let berlin = Some("Europe/Berlin".parse().unwrap());
+        let daily = Schedule::Daily { hour: 6, minute: 0 };
+        assert_eq!(
+            daily.next_after(MIDNIGHT_US, berlin),
+            MIDNIGHT_US + 5 * HOUR_US
+        );
+        assert_eq!(
+            daily.next_after(MIDNIGHT_US + 5 * HOUR_US, berlin),
+            MIDNIGHT_US + 29 * HOUR_US
+        );
+        let july_us = MIDNIGHT_US + 182 * 24 * HOUR_US;
+        assert_eq!(daily.next_after(july_us, berlin), july_us + 4 * HOUR_US);
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_writes_due_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        let outputs = format!(r#"[{{"dir": {:?}}}]"#, dir.path());
+        let bus = EventBus::new(16);
+        let mut scheduler = ReportScheduler::new(config(&outputs), historian(), MIDNIGHT_US)
+            .unwrap()
+            .with_events(&bus);
+        assert_eq!(scheduler.next_run(), Some(MIDNIGHT_US + HOUR_US));
+
+        bus.publish(Event::new(
+            MIDNIGHT_US + 20_000_000,
+            EventKind::StreamStalled,
+            "7734",
+            "No data".to_string(),
+        ));
+        assert!(scheduler
+            .run_due(MIDNIGHT_US + HOUR_US - 1)
+            .await
+            .is_empty());
+
+        // The hourly jobs are due, the daily one at 01:00 as well
+        let reports = scheduler.run_due(MIDNIGHT_US + HOUR_US).await;
+        assert_eq!(reports.len(), 3);
+        assert_eq!(scheduler.failures(), 0);
+        assert_eq!(scheduler.next_run(), Some(MIDNIGHT_US + 2 * HOUR_US));
+
+        let events = &reports[1];
+        assert_eq!(events.kind, ReportKind::Events);
+        assert_eq!(events.content["count"], 1);
+        assert_eq!(events.content["by_kind"]["stream_stalled"], 1);
+
+        let compliance = &reports[2];
+        assert_eq!(compliance.start_us, MIDNIGHT_US + HOUR_US - 24 * HOUR_US);
+        let stream = &compliance.content["streams"][0];
+        assert_eq!(stream["idcode"], 7734);
+        assert_eq!(stream["expected"], 24 * 3600 * 30);
+        assert_eq!(stream["received"], 1790);
+        assert_eq!(stream["stat_errors"], 30);
+        assert_eq!(stream["off_grid"], 0);
+
+        let quality = &reports[0];
+        assert!(quality.content["channels"].as_array().unwrap().len() > 1);
+        // A single stream has no other to be skewed against
+        assert_eq!(quality.content["time_skew"], serde_json::json!([]));
+
+        let json: Value = serde_json::from_str(
+            &fs::read_to_string(dir.path().join("compliance-20240101T0100Z.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(json["content"]["streams"][0]["received"], 1790);
+        let text = fs::read_to_string(dir.path().join("events-20240101T0100Z.txt")).unwrap();
+        assert!(text.starts_with("events report events (2024-01-01 00:00:00 UTC"));
+    }
+
+    #[tokio::test]
+    async fn test_annotations_in_reports() {
+        let mut store = AnnotationStore::in_memory();
+        // Maintenance of the stream for the 23 hours before midnight
+        store
+            .add(
+                Annotation::new(
+                    AnnotationKind::Maintenance,
+                    MIDNIGHT_US - 23 * HOUR_US,
+                    MIDNIGHT_US,
+                )
+                .with_stream(7734),
+            )
+            .unwrap();
+        store
+            .add(
+                Annotation::new(AnnotationKind::KnownBad, MIDNIGHT_US, MIDNIGHT_US + 1)
+                    .with_source("SUB_A"),
+            )
+            .unwrap();
+        let bus = EventBus::new(16);
+        let mut scheduler = ReportScheduler::new(config("[]"), historian(), MIDNIGHT_US)
+            .unwrap()
+            .with_events(&bus)
+            .with_annotations(Arc::new(Mutex::new(store)));
+        for source in ["SUB_A", "SUB_B"] {
+            bus.publish(Event::new(
+                MIDNIGHT_US,
+                EventKind::StreamStalled,
+                source,
+                String::new(),
+            ));
+        }
+
+        let reports = scheduler.run_due(MIDNIGHT_US + HOUR_US).await;
+        assert_eq!(reports[1].content["count"], 1);
+        assert_eq!(reports[1].content["suppressed"], 1);
+        let stream = &reports[2].content["streams"][0];
+        assert_eq!(stream["expected"], 3600 * 30);
+        assert_eq!(stream["annotated_s"], 23.0 * 3600.0);
+    }
+
+    #[tokio::test]
+    async fn test_areas_in_reports() {
+        let areas = AreaMap::new(
+            AreaConfig::from_json(
+                r#"{"areas": [
+                    {"name": "GRID"},
+                    {"name": "NORTH", "parent": "GRID", "stations": ["Station A"]}
+                ]}"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut scheduler = ReportScheduler::new(config("[]"), historian(), MIDNIGHT_US)
+            .unwrap()
+            .with_areas(Arc::new(areas));
+        let reports = scheduler.run_due(MIDNIGHT_US + HOUR_US).await;
+
+        let quality = &reports[0].content;
+        let channels = quality["channels"].as_array().unwrap();
+        assert!(channels
+            .iter()
+            .all(|c| c["station"] == "Station A" && c["area"] == "NORTH"));
+        let area_totals = quality["areas"].as_array().unwrap();
+        assert_eq!(area_totals.len(), 2);
+        assert_eq!(area_totals[0]["area"], "GRID");
+        assert_eq!(area_totals[0]["channels"], channels.len());
+        assert_eq!(area_totals[0]["anomalies"], quality["anomalies"]);
+
+        let compliance = &reports[2].content;
+        assert_eq!(compliance["streams"][0]["areas"][0], "NORTH");
+        assert_eq!(compliance["areas"][1]["area"], "NORTH");
+        assert_eq!(compliance["areas"][1]["received"], 1790);
+        assert_eq!(compliance["areas"][1]["expected"], 24 * 3600 * 30);
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_posts_to_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for status in ["200 OK", "500 Internal Server Error"] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 4096];
+                // The body ends the request, it is JSON
+                while !request.ends_with(b"}") {
+                    let n = socket.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                }
+                socket
+                    .write_all(format!("HTTP/1.1 {}\r\n\r\n", status).as_bytes())
+                    .await
+                    .unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+
+        let outputs = format!(r#"[{{"webhook": "http://{}/reports"}}]"#, address);
+        let mut config = config(&outputs);
+        config.jobs.truncate(2);
+        let mut scheduler = ReportScheduler::new(config, historian(), MIDNIGHT_US).unwrap();
+        let reports = scheduler.run_due(MIDNIGHT_US + HOUR_US).await;
+        assert_eq!(reports.len(), 2);
+        assert_eq!(scheduler.failures(), 1);
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].starts_with("POST /reports HTTP/1.1\r\n"));
+        let body = requests[0].split("\r\n\r\n").nth(1).unwrap();
+        let json: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(json["job"], "quality");
+        assert_eq!(json["end_us"], MIDNIGHT_US + HOUR_US);
+    }
+}
@@ -0,0 +1,250 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+mod common;
+
+use arrow::array::{Array, AsArray, Float64Array};
+use arrow::datatypes::Float64Type;
+use arrow::record_batch::RecordBatch;
+use common::read_hex_file;
+use pmu::arrow_utils::{build_record_batch_at, ArrowOptions, META_CHANNEL, META_KIND};
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::pipeline::{Pipeline, PipelineConfig};
+use pmu::scada::{
+    parse_csv_line, parse_payload, read_csv_from, start, JoinMode, ScadaConfig, ScadaJoin,
+    ScadaPoint, ScadaSource, ScadaStore,
+};
+use std::fs;
+use std::io::Write;
+use std::time::Duration;
+
+fn point(name: &str, timestamp_us: i64, value: f64) -> ScadaPoint {
+    ScadaPoint {
+        point: name.to_string(),
+        timestamp_us,
+        value,
+    }
+}
+
+// The sample data frame repeated at the given timestamps.
+fn batch_at(timestamps: &[i64]) -> RecordBatch {
+    let config = parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+    let data = read_hex_file("data_message.bin").unwrap();
+    let buffer = data.repeat(timestamps.len());
+    build_record_batch_at(
+        &buffer,
+        data.len(),
+        &config.get_channel_map(),
+        &ArrowOptions::default(),
+        timestamps.to_vec(),
+    )
+    .unwrap()
+}
+
+fn column(batch: &RecordBatch, name: &str) -> Vec<Option<f64>> {
+    batch
+        .column_by_name(name)
+        .unwrap_or_else(|| panic!("no column {}", name))
+        .as_primitive::<Float64Type>()
+        .iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_lookup() {
+        let store = ScadaStore::new(Duration::from_secs(3600));
+        // Out of order on purpose
+        store.push(point("BRK", 2_000_000, 0.0));
+        store.push(point("BRK", 1_000_000, 1.0));
+        store.push(point("BRK", 5_000_000, 1.0));
+        assert_eq!(store.len("BRK"), 3);
+        assert_eq!(store.len("MW"), 0);
+
+        let timestamps = [500_000, 1_400_000, 1_600_000, 2_000_000, 9_000_000];
+        assert_eq!(
+            store.lookup("BRK", &timestamps, JoinMode::Nearest, 1_000_000),
+            vec![Some(1.0), Some(1.0), Some(0.0), Some(0.0), None]
+        );
+        assert_eq!(
+            store.lookup("BRK", &timestamps, JoinMode::Previous, 1_000_000),
+            vec![None, Some(1.0), Some(1.0), Some(0.0), None]
+        );
+        assert_eq!(
+            store.lookup("BRK", &timestamps, JoinMode::Previous, 10_000_000),
+            vec![None, Some(1.0), Some(1.0), Some(0.0), Some(1.0)]
+        );
+        assert_eq!(
+            store.lookup("MW", &timestamps, JoinMode::Nearest, 1_000_000),
+            vec![None; 5]
+        );
+    }
+
+    #[test]
+    fn test_store_retention() {
+        let store = ScadaStore::new(Duration::from_secs(10));
+        for second in 0..30 {
+            store.push(point("MW", second * 1_000_000, second as f64));
+        }
+        assert_eq!(store.len("MW"), 11);
+        assert_eq!(
+            store.lookup("MW", &[0, 25_000_000], JoinMode::Previous, 60_000_000),
+            vec![None, Some(25.0)]
+        );
+    }
+
+    #[test]
+    fn test_parse_csv_and_payloads() {
+        assert_eq!(parse_csv_line("timestamp,point,value"), None);
+        assert_eq!(parse_csv_line(""), None);
+        assert_eq!(
+            parse_csv_line("1700000000000000, BUS7_MW, 12.5"),
+            Some(point("BUS7_MW", 1_700_000_000_000_000, 12.5))
+        );
+        assert_eq!(
+            parse_csv_line("2023-11-14T22:13:20Z,BRK_52,1"),
+            Some(point("BRK_52", 1_700_000_000_000_000, 1.0))
+        );
+
+        assert_eq!(
+            parse_payload("plant/mw", b" 42.5\n", 7),
+            Some(point("plant/mw", 7, 42.5))
+        );
+        assert_eq!(
+            parse_payload(
+                "plant/brk",
+                br#"{"point": "BRK_52", "value": true, "timestamp": "2023-11-14T22:13:20Z"}"#,
+                7
+            ),
+            Some(point("BRK_52", 1_700_000_000_000_000, 1.0))
+        );
+        assert_eq!(
+            parse_payload("plant/mvar", br#"{"value": -3.0, "timestamp": 99}"#, 7),
+            Some(point("plant/mvar", 99, -3.0))
+        );
+        assert_eq!(parse_payload("plant/mw", b"offline", 7), None);
+    }
+
+    #[test]
+    fn test_read_csv_follows_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scada.csv");
+        fs::write(&path, "timestamp,point,value\n1000,MW,1.5\n2000,MW,2").unwrap();
+
+        // The last line is not complete yet
+        let (points, offset) = read_csv_from(&path, 0).unwrap();
+        assert_eq!(points, vec![point("MW", 1000, 1.5)]);
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b".5\n3000,MW,3.5\n").unwrap();
+        let (points, offset) = read_csv_from(&path, offset).unwrap();
+        assert_eq!(points, vec![point("MW", 2000, 2.5), point("MW", 3000, 3.5)]);
+        let (points, offset) = read_csv_from(&path, offset).unwrap();
+        assert!(points.is_empty());
+
+        // Replaced by a shorter file, read from the start
+        fs::write(&path, "4000,MW,4\n").unwrap();
+        let (points, _) = read_csv_from(&path, offset).unwrap();
+        assert_eq!(points, vec![point("MW", 4000, 4.0)]);
+    }
+
+    #[test]
+    fn test_join_adds_point_columns() {
+        let store = ScadaStore::new(Duration::from_secs(3600));
+        store.push(point("MW", 1_000_000, 10.0));
+        store.push(point("MW", 2_000_000, 20.0));
+        store.push(point("BRK", 1_000_000, 1.0));
+        let mut config = ScadaConfig::new(Vec::new(), &["MW", "BRK", "MVAR"]);
+        config.tolerance_ms = 500;
+        let join = ScadaJoin::new(&config, store.clone());
+
+        let batch = batch_at(&[1_100_000, 1_600_000, 2_400_000]);
+        let joined = join.apply(&batch).unwrap();
+        assert_eq!(joined.num_rows(), 3);
+        assert_eq!(joined.num_columns(), batch.num_columns() + 3);
+        assert_eq!(
+            column(&joined, "MW"),
+            vec![Some(10.0), Some(20.0), Some(20.0)]
+        );
+        assert_eq!(column(&joined, "BRK"), vec![Some(1.0), None, None]);
+        assert_eq!(column(&joined, "MVAR"), vec![None; 3]);
+        let field = joined.schema().field_with_name("MW").unwrap().clone();
+        assert_eq!(field.metadata()[META_CHANNEL], "MW");
+        assert_eq!(field.metadata()[META_KIND], "scada");
+
+        config.mode = JoinMode::Previous;
+        let joined = ScadaJoin::new(&config, store).apply(&batch).unwrap();
+        assert_eq!(column(&joined, "MW"), vec![Some(10.0), None, Some(20.0)]);
+    }
+
+    #[tokio::test]
+    async fn test_csv_source_feeds_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scada.csv");
+        fs::write(&path, "timestamp,point,value\n1000,MW,1\n").unwrap();
+        let config = ScadaConfig::new(
+            vec![ScadaSource::Csv {
+                path: path.clone(),
+                poll_ms: 20,
+            }],
+            &["MW"],
+        );
+        let (store, tasks) = start(&config);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(store.len("MW"), 1);
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"2000,MW,2\n3000,MW,3\n").unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(store.len("MW"), 3);
+        tasks.iter().for_each(|task| task.abort());
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_scada_config() {
+        let dir = tempfile::tempdir().unwrap();
+        // Nothing listens on 4797
+        let json = format!(
+            r#"{{"streams": [{{"host": "127.0.0.1", "port": 4797}}],
+                "sink": {{"format": "csv", "dir": {:?}}},
+                "scada": {{"sources": [{{"csv": {{"path": {:?}}}}},
+                                       {{"mqtt": {{"host": "broker", "topics": ["plant/#"]}}}}],
+                           "points": [],
+                           "mode": "previous"}}}}"#,
+            dir.path().join("out"),
+            dir.path().join("missing.csv"),
+        );
+        let config = PipelineConfig::from_json(&json).unwrap();
+        let scada = config.scada.clone().unwrap();
+        assert_eq!(scada.mode, JoinMode::Previous);
+        assert_eq!(scada.tolerance_ms, 10_000);
+        assert_eq!(
+            scada.sources[0],
+            ScadaSource::Csv {
+                path: dir.path().join("missing.csv"),
+                poll_ms: 1000
+            }
+        );
+        assert_eq!(
+            scada.sources[1],
+            ScadaSource::Mqtt {
+                host: "broker".to_string(),
+                port: 1883,
+                topics: vec!["plant/#".to_string()]
+            }
+        );
+
+        let validation = Pipeline::validate(&config).await;
+        assert_eq!(validation.problems.len(), 3, "{:?}", validation.problems);
+        assert!(validation
+            .problems
+            .iter()
+            .any(|p| p == "SCADA: no points to join"));
+        assert!(validation
+            .problems
+            .iter()
+            .any(|p| p.starts_with("SCADA source") && p.ends_with("no such file")));
+    }
+}
@@ -0,0 +1,97 @@
+#![cfg(feature = "mqtt")]
+#![allow(unused)]
+use pmu::scada::mqtt::{connect_packet, parse_publish, subscribe, subscribe_packet};
+use pmu::scada::{JoinMode, ScadaStore};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let header = stream.read_u8().await.unwrap();
+    let length = stream.read_u8().await.unwrap() as usize; // Short packets only
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await.unwrap();
+    (header, body)
+}
+
+fn publish(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = (topic.len() as u16).to_be_bytes().to_vec();
+    body.extend_from_slice(topic.as_bytes());
+    body.extend_from_slice(payload);
+    let mut packet = vec![0x30, body.len() as u8];
+    packet.extend(body);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packets() {
+        let connect = connect_packet("pmu");
+        assert_eq!(connect[0], 0x10);
+        assert_eq!(connect[1] as usize, connect.len() - 2);
+        assert_eq!(&connect[2..8], b"\x00\x04MQTT");
+        assert!(connect.ends_with(b"\x00\x03pmu"));
+
+        let subscribe = subscribe_packet(1, &["plant/mw".to_string()]);
+        assert_eq!(subscribe[0], 0x82);
+        assert_eq!(&subscribe[2..], b"\x00\x01\x00\x08plant/mw\x00");
+
+        let packet = publish("plant/mw", b"1.5");
+        assert_eq!(
+            parse_publish(0, &packet[2..]),
+            Some(("plant/mw".to_string(), &b"1.5"[..]))
+        );
+        // QoS 1 carries a packet identifier after the topic
+        assert_eq!(
+            parse_publish(0x02, b"\x00\x01a\x00\x07on"),
+            Some(("a".to_string(), &b"on"[..]))
+        );
+        assert_eq!(parse_publish(0, b"\x00\x09a"), None);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_feeds_store() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let broker = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (header, _) = read_packet(&mut stream).await;
+            assert_eq!(header, 0x10);
+            stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+            let (header, body) = read_packet(&mut stream).await;
+            assert_eq!(header, 0x82);
+            assert!(body.ends_with(b"\x00\x08plant/mw\x00"));
+            stream
+                .write_all(&publish("plant/mw", b"42.5"))
+                .await
+                .unwrap();
+            stream
+                .write_all(&publish(
+                    "plant/mw",
+                    br#"{"point": "BRK_52", "value": true, "timestamp": 5}"#,
+                ))
+                .await
+                .unwrap();
+            // Hold the connection open
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let store = ScadaStore::new(Duration::from_secs(3600));
+        let topics = vec!["plant/mw".to_string()];
+        let subscriber = tokio::spawn({
+            let store = store.clone();
+            async move { subscribe(&address, &topics, store).await }
+        });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(store.len("plant/mw"), 1);
+        assert_eq!(
+            store.lookup("BRK_52", &[5], JoinMode::Previous, 0),
+            vec![Some(1.0)]
+        );
+        subscriber.abort();
+        broker.abort();
+    }
+}
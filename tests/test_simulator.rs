@@ -0,0 +1,436 @@
+#![cfg(feature = "arrow")]
+#![allow(unused)]
+mod common;
+
+use common::{crc_ok, read_hex_file};
+
+#[cfg(test)]
+mod tests {
+    use super::{crc_ok, read_hex_file};
+    use arrow::array::{Array, Float32Array, Int16Array, UInt16Array};
+    use pmu::arrow_utils::build_record_batch;
+    use pmu::arrow_utils::frame_timestamp_micros;
+    use pmu::frame_parser::parse_config_frame_1and2;
+    use pmu::frame_parser::parse_data_frames;
+    use pmu::frames::PMUFrameType;
+    use pmu::simulator::{
+        NoiseModel, Scenario, ScenarioEvent, SimulatedPmu, Simulator, SimulatorTick, StreamLayout,
+    };
+
+    // Sample config: 4 fixed point rectangular phasors, fixed FREQ/DFREQ.
+    const FREQ_OFFSET: usize = 14 + 2 + 4 * 4;
+
+    fn simulator(events: Vec<ScenarioEvent>) -> Simulator {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let scenario = Scenario {
+            start_soc: Some(1_700_000_000),
+            events,
+            ..Default::default()
+        };
+        Simulator::new(config, scenario)
+    }
+
+    fn ticks(simulator: &mut Simulator, count: usize) -> Vec<SimulatorTick> {
+        (0..count).map(|_| simulator.next_tick()).collect()
+    }
+
+    fn first_phasor(frame: &[u8]) -> (f64, f64) {
+        let re = i16::from_be_bytes([frame[16], frame[17]]) as f64;
+        let im = i16::from_be_bytes([frame[18], frame[19]]) as f64;
+        (re.hypot(im), im.atan2(re).to_degrees())
+    }
+
+    fn freq_mhz(frame: &[u8]) -> i16 {
+        i16::from_be_bytes([frame[FREQ_OFFSET], frame[FREQ_OFFSET + 1]])
+    }
+
+    #[test]
+    fn test_config_frame_round_trip() {
+        let buffer = read_hex_file("config_message.bin").unwrap();
+        let config = parse_config_frame_1and2(&buffer).unwrap();
+        assert_eq!(config.to_hex(), buffer);
+    }
+
+    #[test]
+    fn test_nominal_frames() {
+        let mut sim = simulator(Vec::new());
+        let expected_size = sim.config().calc_data_frame_size();
+        let ticks = ticks(&mut sim, 30);
+
+        for (i, tick) in ticks.iter().enumerate() {
+            assert_eq!(tick.frames.len(), 1);
+            let frame = &tick.frames[0];
+            assert_eq!(frame.len(), expected_size);
+            assert!(crc_ok(frame));
+            assert_eq!(freq_mhz(frame), 0);
+            let timestamp = frame_timestamp_micros(frame).unwrap();
+            let expected = (i as f64 * 1_000_000.0 / 30.0).round() as i64;
+            assert_eq!(timestamp - 1_700_000_000_000_000, expected);
+        }
+        let (magnitude, angle) = first_phasor(&ticks[10].frames[0]);
+        assert!((magnitude - 20_000.0).abs() < 2.0);
+        assert!(angle.abs() < 0.01);
+    }
+
+    #[test]
+    fn test_frequency_ramp_and_phase_jump() {
+        let mut sim = simulator(vec![
+            ScenarioEvent::FrequencyRamp {
+                at: 0.0,
+                duration: 1.0,
+                rate: 0.06,
+            },
+            ScenarioEvent::PhaseJump {
+                at: 3.0,
+                degrees: 30.0,
+            },
+        ]);
+        let ticks = ticks(&mut sim, 120);
+
+        // Ramp holds at 60 mHz, and the phase advances 0.06 cycles per second.
+        assert_eq!(freq_mhz(&ticks[45].frames[0]), 60);
+        let (_, before) = first_phasor(&ticks[89].frames[0]);
+        let (_, after) = first_phasor(&ticks[90].frames[0]);
+        let step = (after - before + 540.0).rem_euclid(360.0) - 180.0;
+        assert!((step - 30.0 - 0.06 * 360.0 / 30.0).abs() < 0.2, "{}", step);
+    }
+
+    #[test]
+    fn test_voltage_sag() {
+        let mut sim = simulator(vec![ScenarioEvent::VoltageSag {
+            at: 1.0,
+            duration: 0.5,
+            depth: 0.3,
+        }]);
+        let ticks = ticks(&mut sim, 60);
+        let (during, _) = first_phasor(&ticks[35].frames[0]);
+        let (after, _) = first_phasor(&ticks[50].frames[0]);
+        assert!((during - 14_000.0).abs() < 2.0);
+        assert!((after - 20_000.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn test_dropped_and_corrupted_frames() {
+        let mut sim = simulator(vec![
+            ScenarioEvent::DropFrames { at: 1.0, count: 3 },
+            ScenarioEvent::CorruptCrc { at: 1.5, count: 2 },
+        ]);
+        let ticks = ticks(&mut sim, 60);
+        let dropped: Vec<usize> = (0..60).filter(|i| ticks[*i].frames.is_empty()).collect();
+        assert_eq!(dropped, vec![30, 31, 32]);
+        let corrupted: Vec<usize> = (0..60)
+            .filter(|i| !ticks[*i].frames.is_empty() && !crc_ok(&ticks[*i].frames[0]))
+            .collect();
+        assert_eq!(corrupted, vec![45, 46]);
+    }
+
+    #[test]
+    fn test_config_change_mid_stream() {
+        let mut sim = simulator(vec![ScenarioEvent::ConfigChange {
+            at: 2.0,
+            data_rate: Some(60),
+        }]);
+        let cfgcnt = sim.config().pmu_configs[0].cfgcnt;
+        let ticks = ticks(&mut sim, 90);
+
+        let stat =
+            |tick: &SimulatorTick| u16::from_be_bytes([tick.frames[0][14], tick.frames[0][15]]);
+        assert_eq!(stat(&ticks[20]) & 0x0400, 0);
+        assert_ne!(stat(&ticks[40]) & 0x0400, 0);
+
+        let change = &ticks[60];
+        assert!(change.config_changed);
+        assert_eq!(change.frames.len(), 2);
+        let new_config = parse_config_frame_1and2(&change.frames[0]).unwrap();
+        assert_eq!(new_config.pmu_configs[0].cfgcnt, cfgcnt + 1);
+        assert_eq!(new_config.data_rate, 60);
+        assert_eq!(stat(&ticks[61]) & 0x0400, 0);
+
+        // Frames after the change follow the new rate
+        assert_eq!(ticks[61].time_us - ticks[60].time_us, 16_667);
+        assert_eq!(sim.period().as_micros(), 16_666);
+    }
+
+    #[test]
+    fn test_clock_jump() {
+        let mut sim = simulator(vec![ScenarioEvent::ClockJump {
+            at: 1.0,
+            offset_ms: -500,
+        }]);
+        let ticks = ticks(&mut sim, 40);
+        let timestamp = |i: usize| frame_timestamp_micros(&ticks[i].frames[0]).unwrap();
+        assert_eq!(timestamp(30) - timestamp(29), 33_333 - 500_000);
+        assert_eq!(ticks[30].time_us - ticks[29].time_us, 33_333);
+    }
+
+    #[test]
+    fn test_scenario_file() {
+        let scenario = Scenario::from_file("tests/test_data/scenario_events.json").unwrap();
+        assert_eq!(scenario.start_soc, Some(1_700_000_000));
+        assert_eq!(scenario.events.len(), 7);
+        assert_eq!(
+            scenario.events[3],
+            ScenarioEvent::DropFrames { at: 6.0, count: 3 }
+        );
+
+        // Same scenario, same frames
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let mut a = Simulator::new(config.clone(), scenario.clone());
+        let mut b = Simulator::new(config, scenario);
+        for _ in 0..400 {
+            assert_eq!(a.next_tick(), b.next_tick());
+        }
+    }
+
+    fn pmu(station: &str, idcode: u16) -> SimulatedPmu {
+        SimulatedPmu {
+            station: station.to_string(),
+            idcode,
+            polar: false,
+            float_phasors: false,
+            float_analogs: false,
+            float_freq: false,
+            phasors: 3,
+            analogs: 0,
+            digitals: 0,
+            data_rate: None,
+            nominal_50hz: false,
+            angle: 0.0,
+        }
+    }
+
+    fn multi_pmu_layout() -> StreamLayout {
+        StreamLayout {
+            idcode: 100,
+            data_rate: 30,
+            time_base: 1_000_000,
+            pmus: vec![
+                pmu("A", 1),
+                SimulatedPmu {
+                    polar: true,
+                    float_phasors: true,
+                    float_analogs: true,
+                    float_freq: true,
+                    phasors: 2,
+                    analogs: 2,
+                    digitals: 1,
+                    data_rate: Some(10),
+                    angle: 30.0,
+                    ..pmu("B", 2)
+                },
+                SimulatedPmu {
+                    polar: true,
+                    phasors: 4,
+                    analogs: 1,
+                    nominal_50hz: true,
+                    ..pmu("C", 3)
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_multi_pmu_config() {
+        let sim = Simulator::from_layout(&multi_pmu_layout(), Scenario::default());
+        let config = parse_config_frame_1and2(&sim.config_frame()).unwrap();
+        assert_eq!(config.prefix.idcode, 100);
+        assert_eq!(config.num_pmu, 3);
+        let formats: Vec<u16> = config.pmu_configs.iter().map(|c| c.format.into()).collect();
+        assert_eq!(formats, vec![0x0000, 0x000F, 0x0001]);
+        assert_eq!(config.pmu_configs[1].get_column_names()[0], "B_2_VA");
+        assert_eq!(config.pmu_configs[2].fnom, 1);
+    }
+
+    #[test]
+    fn test_multi_pmu_frames() {
+        let mut sim = Simulator::from_layout(&multi_pmu_layout(), Scenario::default());
+        let config = sim.config().clone();
+        let ticks = ticks(&mut sim, 6);
+
+        for (i, tick) in ticks.iter().enumerate() {
+            let frame = &tick.frames[0];
+            assert_eq!(frame.len(), config.calc_data_frame_size());
+            assert!(crc_ok(frame));
+            let parsed = parse_data_frames(frame, &config).unwrap();
+            assert_eq!(parsed.data.len(), 3);
+
+            // PMU B reports at 10 of the 30 frames per second
+            match &parsed.data[1] {
+                PMUFrameType::Floating(block) => {
+                    assert_eq!(block.freq, 60.0);
+                    let held = i % 3 != 0;
+                    assert_eq!(block.stat & 0x0200 != 0, held, "frame {}", i);
+                    let magnitude = f32::from_be_bytes(block.phasors[..4].try_into().unwrap());
+                    let angle = f32::from_be_bytes(block.phasors[4..8].try_into().unwrap());
+                    assert_eq!(magnitude, 1.0);
+                    assert!((angle as f64 - 30f64.to_radians()).abs() < 1e-6);
+                }
+                other => panic!("expected floating point block, got {:?}", other),
+            }
+            match &parsed.data[2] {
+                PMUFrameType::Fixed(block) => assert_eq!(block.stat, 0),
+                other => panic!("expected fixed point block, got {:?}", other),
+            }
+        }
+
+        // Channel offsets account for the STAT word of every PMU
+        let mut buffer = Vec::new();
+        for tick in &ticks {
+            buffer.extend_from_slice(&tick.frames[0]);
+        }
+        let batch = build_record_batch(
+            &buffer,
+            config.calc_data_frame_size(),
+            &config.get_channel_map(),
+        )
+        .unwrap();
+        assert_eq!(batch.num_rows(), 6);
+        let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+
+        let b_freq = column("B_2_FREQ");
+        let b_freq = b_freq.as_any().downcast_ref::<Float32Array>().unwrap();
+        assert!(b_freq.values().iter().all(|f| *f == 60.0));
+
+        // Polar: the magnitude is unsigned
+        let c_magnitude = column("C_3_VA_X");
+        let c_magnitude = c_magnitude.as_any().downcast_ref::<UInt16Array>().unwrap();
+        assert!(c_magnitude.values().iter().all(|m| *m == 20_000));
+
+        let a_freq = column("A_1_FREQ");
+        let a_freq = a_freq.as_any().downcast_ref::<Int16Array>().unwrap();
+        assert!(a_freq.values().iter().all(|f| *f == 0));
+
+        let b_digital = column("B_2_DG1_0");
+        let b_digital = b_digital.as_any().downcast_ref::<UInt16Array>().unwrap();
+        assert_eq!(b_digital.len(), 6);
+    }
+
+    fn noisy_simulator(noise: NoiseModel) -> Simulator {
+        let layout = StreamLayout {
+            idcode: 100,
+            data_rate: 50,
+            time_base: 1_000_000,
+            pmus: vec![SimulatedPmu {
+                float_phasors: true,
+                float_freq: true,
+                ..pmu("A", 1)
+            }],
+        };
+        let scenario = Scenario {
+            start_soc: Some(1_700_000_000),
+            noise: Some(noise),
+            ..Default::default()
+        };
+        Simulator::from_layout(&layout, scenario)
+    }
+
+    // Phasors of the single floating point PMU, with FREQ
+    fn float_phasors(frame: &[u8]) -> (Vec<(f64, f64)>, f32) {
+        let value =
+            |offset: usize| f32::from_be_bytes(frame[offset..offset + 4].try_into().unwrap());
+        let phasors = (0..3)
+            .map(|k| (value(16 + 8 * k) as f64, value(20 + 8 * k) as f64))
+            .collect();
+        (phasors, value(40))
+    }
+
+    #[test]
+    fn test_noise_is_repeatable() {
+        let noise = NoiseModel {
+            seed: 7,
+            magnitude_std: 0.01,
+            angle_std_deg: 0.5,
+            frequency_std: 0.005,
+            ..Default::default()
+        };
+        let first = ticks(&mut noisy_simulator(noise.clone()), 20);
+        let second = ticks(&mut noisy_simulator(noise.clone()), 20);
+        assert_eq!(first, second);
+
+        let mut sim = noisy_simulator(noise.clone());
+        ticks(&mut sim, 5);
+        sim.rewind();
+        assert_eq!(ticks(&mut sim, 20), first);
+
+        let other = ticks(&mut noisy_simulator(NoiseModel { seed: 8, ..noise }), 20);
+        assert_ne!(first, other);
+        assert!(first.iter().all(|tick| crc_ok(&tick.frames[0])));
+    }
+
+    #[test]
+    fn test_noise_statistics() {
+        let mut sim = noisy_simulator(NoiseModel {
+            seed: 1,
+            magnitude_std: 0.01,
+            frequency_std: 0.002,
+            ..Default::default()
+        });
+        let samples = ticks(&mut sim, 2000);
+        let magnitudes: Vec<f64> = samples
+            .iter()
+            .map(|tick| {
+                let (phasors, _) = float_phasors(&tick.frames[0]);
+                phasors[0].0.hypot(phasors[0].1)
+            })
+            .collect();
+        let n = magnitudes.len() as f64;
+        let mean = magnitudes.iter().sum::<f64>() / n;
+        let std = (magnitudes.iter().map(|m| (m - mean).powi(2)).sum::<f64>() / n).sqrt();
+        assert!((mean - 1.0).abs() < 0.002, "mean {}", mean);
+        assert!((std - 0.01).abs() < 0.001, "std {}", std);
+
+        let freqs: Vec<f64> = samples
+            .iter()
+            .map(|tick| float_phasors(&tick.frames[0]).1 as f64 - 60.0)
+            .collect();
+        let freq_std = (freqs.iter().map(|f| f * f).sum::<f64>() / n).sqrt();
+        assert!((freq_std - 0.002).abs() < 0.0003, "freq std {}", freq_std);
+    }
+
+    #[test]
+    fn test_tve_target() {
+        let mut sim = noisy_simulator(NoiseModel {
+            seed: 3,
+            tve_percent: Some(1.0),
+            ..Default::default()
+        });
+        for tick in ticks(&mut sim, 50) {
+            let (phasors, _) = float_phasors(&tick.frames[0]);
+            for (k, (re, im)) in phasors.into_iter().enumerate() {
+                let angle = -(k as f64) * 2.0 * std::f64::consts::PI / 3.0;
+                let tve = (re - angle.cos()).hypot(im - angle.sin()) * 100.0;
+                assert!((tve - 1.0).abs() < 1e-4, "tve {}", tve);
+            }
+        }
+    }
+
+    #[test]
+    fn test_quantization() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let scenario = Scenario {
+            start_soc: Some(1_700_000_000),
+            events: vec![ScenarioEvent::FrequencyRamp {
+                at: 0.0,
+                duration: 1.0,
+                rate: 0.5,
+            }],
+            noise: Some(NoiseModel {
+                quantization_bits: Some(12),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut sim = Simulator::new(config, scenario);
+        for tick in ticks(&mut sim, 30) {
+            let frame = &tick.frames[0];
+            let re = i16::from_be_bytes([frame[16], frame[17]]);
+            let im = i16::from_be_bytes([frame[18], frame[19]]);
+            assert_eq!(re % 16, 0);
+            assert_eq!(im % 16, 0);
+            assert_eq!(freq_mhz(frame) % 16, 0);
+        }
+    }
+}
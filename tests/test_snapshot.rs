@@ -0,0 +1,170 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+mod common;
+
+use common::{data_frame_us, read_hex_file};
+use pmu::events::{Event, EventBus, EventKind, Severity};
+use pmu::pipeline::PipelineConfig;
+use pmu::recorder::{CaptureReader, CaptureRecord};
+use pmu::snapshot::{SnapshotConfig, SnapshotRecorder, SnapshotTrigger, Trigger};
+use std::path::Path;
+
+const START_US: i64 = 1_700_000_000_000_000;
+
+fn records(path: &Path) -> Vec<CaptureRecord> {
+    CaptureReader::open(path)
+        .unwrap()
+        .records()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+fn data_times(records: &[CaptureRecord]) -> Vec<i64> {
+    records
+        .iter()
+        .filter(|record| record.frame[1] >> 4 == 0)
+        .map(|record| record.timestamp_us() - START_US)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Frames every 100 ms for five seconds, a trigger at 3 s.
+    #[test]
+    fn test_pre_and_post_windows() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SnapshotConfig::new(dir.path()).with_windows(1.0, 1.0);
+        let mut recorder = SnapshotRecorder::new(config).unwrap();
+        recorder
+            .push(&read_hex_file("config_message.bin").unwrap(), START_US)
+            .unwrap();
+        for n in 0..=50 {
+            let t = START_US + n * 100_000;
+            recorder.push(&data_frame_us(7734, t), t).unwrap();
+            if n == 30 {
+                recorder
+                    .trigger(Trigger {
+                        reason: "manual test".to_string(),
+                        timestamp_us: t,
+                    })
+                    .unwrap();
+                assert!(recorder.is_capturing());
+            }
+        }
+        assert!(!recorder.is_capturing());
+        assert_eq!(recorder.snapshots().len(), 1);
+        assert!(recorder.buffered() <= 11);
+
+        let path = &recorder.snapshots()[0];
+        let name = path.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with("snapshot-20231114T"), "{}", name);
+        assert!(name.ends_with("-manual_test.pmucap"), "{}", name);
+
+        let records = records(path);
+        assert_eq!(records[0].frame[1], 0x31); // Configuration first
+        let times = data_times(&records);
+        let expected: Vec<i64> = (20..=40).map(|n| n * 100_000).collect();
+        assert_eq!(times, expected);
+    }
+
+    #[test]
+    fn test_events_trigger_and_extend() {
+        let dir = tempfile::tempdir().unwrap();
+        let bus = EventBus::new(16);
+        let config = SnapshotConfig::new(dir.path())
+            .with_windows(0.5, 0.5)
+            .with_min_severity(Severity::Warning);
+        let mut recorder = SnapshotRecorder::new(config).unwrap().with_events(&bus);
+
+        for n in 0..30 {
+            let t = START_US + n * 100_000;
+            match n {
+                5 => {
+                    bus.publish(Event::new(t, EventKind::StreamResumed, "A", "info".into()));
+                }
+                10 | 13 => {
+                    bus.publish(
+                        Event::new(t, EventKind::GeneratorTrip, "Station A", "trip".into())
+                            .with_severity(Severity::Alarm),
+                    );
+                }
+                _ => {}
+            }
+            recorder.push(&data_frame_us(7734, t), t).unwrap();
+        }
+        // Info is below the threshold, the second alarm extends the first snapshot
+        assert_eq!(recorder.snapshots().len(), 1);
+        assert!(!recorder.is_capturing());
+        let path = &recorder.snapshots()[0];
+        assert!(path
+            .to_string_lossy()
+            .ends_with("-GeneratorTrip_Station_A.pmucap"));
+        let times = data_times(&records(path));
+        assert_eq!(times.first(), Some(&500_000));
+        assert_eq!(times.last(), Some(&1_800_000));
+        assert_eq!(times.len(), 14);
+    }
+
+    #[test]
+    fn test_manual_trigger_and_poll() {
+        let dir = tempfile::tempdir().unwrap();
+        let trigger = SnapshotTrigger::new();
+        assert_eq!(trigger.trigger("nobody listening"), 0);
+
+        let config = SnapshotConfig::new(dir.path())
+            .with_windows(1.0, 2.0)
+            .with_zstd(3);
+        let mut recorder = SnapshotRecorder::new(config)
+            .unwrap()
+            .with_name("shard-0")
+            .with_triggers(&trigger);
+        for n in 0..5 {
+            let t = START_US + n * 100_000;
+            recorder.push(&data_frame_us(7734, t), t).unwrap();
+        }
+        assert_eq!(trigger.trigger_at("button", START_US + 400_000), 1);
+        recorder.poll(START_US + 500_000).unwrap();
+        assert!(recorder.is_capturing());
+        // The stream went quiet, the post window ends anyway
+        recorder.poll(START_US + 2_400_000).unwrap();
+        assert!(recorder.is_capturing());
+        recorder.poll(START_US + 2_400_001).unwrap();
+        assert!(!recorder.is_capturing());
+
+        let path = &recorder.snapshots()[0];
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("shard-0-"));
+        let reader = CaptureReader::open(path).unwrap();
+        assert!(reader.is_compressed());
+        assert_eq!(reader.frame_count(), 5);
+
+        // Same trigger again: another file next to the first
+        trigger.trigger_at("button", START_US + 400_000);
+        recorder.poll(START_US + 500_000).unwrap();
+        recorder.finish().unwrap();
+        assert_eq!(recorder.snapshots().len(), 2);
+        assert_ne!(recorder.snapshots()[0], recorder.snapshots()[1]);
+    }
+
+    #[test]
+    fn test_pipeline_config() {
+        let config = PipelineConfig::from_json(
+            r#"{
+                "streams": [{"host": "127.0.0.1", "port": 4712}],
+                "sink": {"dir": "out"},
+                "snapshot": {"dir": "snapshots", "pre_secs": 30}
+            }"#,
+        )
+        .unwrap();
+        let snapshot = config.snapshot.unwrap();
+        assert_eq!(snapshot.pre_secs, 30.0);
+        assert_eq!(snapshot.post_secs, 10.0);
+        assert_eq!(snapshot.min_severity, Severity::Alarm);
+        assert_eq!(snapshot.zstd_level, None);
+    }
+}
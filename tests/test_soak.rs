@@ -0,0 +1,126 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+use pmu::pipeline::{ShardStats, SinkFormat};
+use pmu::soak::{check, run_soak, SoakConfig, SoakSample};
+
+fn samples(count: usize, step: impl Fn(usize) -> (u64, usize, f64)) -> Vec<SoakSample> {
+    (0..count)
+        .map(|n| {
+            let (rss_bytes, open_fds, latency_us) = step(n);
+            SoakSample {
+                elapsed_secs: n as f64 * 60.0,
+                rss_bytes: Some(rss_bytes),
+                open_fds: Some(open_fds),
+                latency_us: Some(latency_us),
+            }
+        })
+        .collect()
+}
+
+fn collected() -> Vec<ShardStats> {
+    vec![ShardStats {
+        streams: 1,
+        frames: 1000,
+        rows: 1000,
+        batches: 1,
+        ..Default::default()
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soak_config() {
+        let config = SoakConfig::from_json(
+            r#"{"duration_secs": 3600, "max_fd_growth": 2, "format": "csv",
+                "scenario": {"start_soc": 1700000000}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.duration_secs, 3600.0);
+        assert_eq!(config.max_fd_growth, 2);
+        assert_eq!(config.format, SinkFormat::Csv);
+        assert_eq!(config.scenario.start_soc, Some(1_700_000_000));
+        // The rest as by default
+        assert_eq!(config.sample_secs, 60.0);
+        assert_eq!(config.max_rss_growth_mb, 64.0);
+        assert_eq!(SoakConfig::default().duration_secs, 86_400.0);
+        assert!(SoakConfig::from_json(r#"{"port": "x"}"#).is_err());
+    }
+
+    #[test]
+    fn test_soak_bounds() {
+        let config = SoakConfig::default();
+        // Steady after the warm-up: growth during the first 5 minutes is ignored
+        let steady = samples(60, |n| {
+            let settling = if n < 5 { n as u64 * 20_000_000 } else { 0 };
+            (50_000_000 + settling, 20 + n.min(5), 2_000.0)
+        });
+        assert!(check(&config, &steady, &collected()).is_empty());
+
+        let leaking = samples(60, |n| (50_000_000 + n as u64 * 2_000_000, 20 + n, 2_000.0));
+        let violations = check(&config, &leaking, &collected());
+        assert_eq!(violations.len(), 2, "{:?}", violations);
+        assert!(violations[0].starts_with("Memory grew 108.0 MB"));
+        assert!(violations[1].starts_with("Open file descriptors grew by 54"));
+
+        // A backlog building up: the last tenth 150 ms behind the first
+        let lagging = samples(60, |n| (50_000_000, 20, 2_000.0 + n as f64 * 3_000.0));
+        let violations = check(&config, &lagging, &collected());
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].starts_with("Latency drifted 150.0 ms"));
+
+        // Errors, drops, nothing collected, too short
+        let mut stats = collected();
+        stats[0].dropped = 3;
+        assert_eq!(
+            check(&config, &steady, &stats),
+            vec!["0 errors and 3 frames dropped"]
+        );
+        let mut silent = steady.clone();
+        for sample in &mut silent {
+            sample.latency_us = None;
+        }
+        let violations = check(&config, &silent, &[ShardStats::default()]);
+        assert_eq!(violations.len(), 2);
+        assert_eq!(check(&config, &steady[..5], &collected()).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_short_soak_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SoakConfig {
+            duration_secs: 3.0,
+            sample_secs: 0.5,
+            warmup_secs: 1.0,
+            port: 4745,
+            format: SinkFormat::Csv,
+            batch_rows: 10,
+            ..Default::default()
+        };
+        let report = run_soak(&config, dir.path()).await.unwrap();
+        report.print();
+        assert!(report.is_ok(), "{:?}", report.violations);
+        assert!(report.samples.len() >= 5, "{:?}", report.samples);
+        let last = report.samples.last().unwrap();
+        if cfg!(target_os = "linux") {
+            assert!(last.rss_bytes.unwrap() > 0);
+            assert!(last.open_fds.unwrap() > 0);
+        }
+        // Frames of the simulator are stamped with the current time
+        let latency_us = last.latency_us.unwrap();
+        assert!((0.0..1_000_000.0).contains(&latency_us), "{}", latency_us);
+        let shard = &report.stats[0];
+        assert!(shard.frames >= 60, "{:?}", shard);
+        assert_eq!(shard.rows, shard.frames);
+        // The sample configuration's stream
+        assert!(dir.path().join("7734.csv").exists());
+
+        let bad = SoakConfig {
+            sample_secs: 0.0,
+            ..config
+        };
+        assert!(run_soak(&bad, dir.path()).await.is_err());
+    }
+}
@@ -0,0 +1,191 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+use arrow::array::{Array, Float64Array, TimestampMicrosecondArray};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use pmu::latency::now_micros;
+use pmu::pipeline::{SinkConfig, SinkFormat};
+use pmu::sinks::spool::{SpoolConfig, SpoolStats, SpooledSink};
+use pmu::sinks::BatchSink;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+// Rows every 33 ms from start_us, the value being the row number.
+fn batch(start_us: i64, rows: usize) -> RecordBatch {
+    let schema = Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            false,
+        ),
+        Field::new("value", DataType::Float64, false),
+    ]);
+    let timestamps: Vec<i64> = (0..rows as i64).map(|n| start_us + n * 33_333).collect();
+    let values: Vec<f64> = (0..rows).map(|n| n as f64).collect();
+    RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(TimestampMicrosecondArray::from(timestamps)),
+            Arc::new(Float64Array::from(values)),
+        ],
+    )
+    .unwrap()
+}
+
+fn first_timestamp(batch: &RecordBatch) -> i64 {
+    batch
+        .column_by_name("timestamp")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<TimestampMicrosecondArray>()
+        .unwrap()
+        .value(0)
+}
+
+#[derive(Default)]
+struct State {
+    down: bool,
+    batches: Vec<RecordBatch>,
+}
+
+// Keeps what it is given, failing while down.
+#[derive(Clone, Default)]
+struct Flaky(Arc<Mutex<State>>);
+
+impl Flaky {
+    fn set_down(&self, down: bool) {
+        self.0.lock().unwrap().down = down;
+    }
+
+    // First timestamps of the batches written, in the order written.
+    fn written(&self) -> Vec<i64> {
+        self.0
+            .lock()
+            .unwrap()
+            .batches
+            .iter()
+            .map(first_timestamp)
+            .collect()
+    }
+}
+
+impl BatchSink for Flaky {
+    fn write_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let mut state = self.0.lock().unwrap();
+        if state.down {
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "down"));
+        }
+        state.batches.push(batch.clone());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const SECOND: i64 = 1_000_000;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spool_and_catch_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let flaky = Flaky::default();
+        let config = SpoolConfig::new(dir.path())
+            .with_catch_up_rate(30.0)
+            .with_retry_secs(2.0);
+        let mut sink = SpooledSink::new(Box::new(flaky.clone()), config).unwrap();
+        let t0 = now_micros();
+        let at = |n: i64| t0 + n * SECOND;
+
+        sink.write_batch_at(&batch(at(0), 30), at(0)).unwrap();
+        flaky.set_down(true);
+        sink.write_batch_at(&batch(at(1), 30), at(1)).unwrap();
+        assert!(sink.is_down());
+        // Not tried again before retry_secs
+        sink.write_batch_at(&batch(at(2), 30), at(2)).unwrap();
+        assert_eq!(sink.stats().failures, 1);
+        sink.write_batch_at(&batch(at(3), 30), at(3)).unwrap();
+        assert_eq!(sink.stats().failures, 2);
+        flaky.set_down(false);
+        sink.write_batch_at(&batch(at(4), 30), at(4)).unwrap();
+        assert!(sink.is_down());
+        assert_eq!(sink.backlog(), 4);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 4);
+
+        // Back with the live batch, the backlog follows at 30 rows a second
+        sink.write_batch_at(&batch(at(5), 30), at(5)).unwrap();
+        assert!(!sink.is_down());
+        assert_eq!(flaky.written(), [at(0), at(5)]);
+        assert_eq!(sink.poll(at(6)).unwrap(), 1);
+        assert_eq!(sink.poll(at(6) + SECOND / 2).unwrap(), 0);
+        // No more than a second saved up
+        assert_eq!(sink.poll(at(20)).unwrap(), 1);
+        sink.write_batch_at(&batch(at(21), 30), at(21)).unwrap();
+        assert_eq!(sink.poll(at(22)).unwrap(), 1);
+        assert_eq!(sink.backlog(), 0);
+        assert_eq!(
+            flaky.written(),
+            [at(0), at(5), at(1), at(2), at(21), at(3), at(4)]
+        );
+        assert_eq!(
+            sink.stats(),
+            SpoolStats {
+                spooled: 4,
+                replayed: 4,
+                failures: 2,
+            }
+        );
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_spool_kept_across_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let flaky = Flaky::default();
+        flaky.set_down(true);
+        let config = SpoolConfig::new(dir.path()).with_catch_up_rate(0.0);
+        let mut sink = SpooledSink::new(Box::new(flaky.clone()), config.clone()).unwrap();
+        for n in 0..3 {
+            sink.write_batch(&batch(n * SECOND, 30)).unwrap();
+        }
+        sink.close().unwrap();
+        assert!(flaky.written().is_empty());
+
+        // Without a rate limit the backlog goes with the first live batch
+        flaky.set_down(false);
+        let mut sink = SpooledSink::new(Box::new(flaky.clone()), config).unwrap();
+        assert_eq!(sink.backlog(), 3);
+        sink.write_batch(&batch(3 * SECOND, 30)).unwrap();
+        assert_eq!(sink.backlog(), 0);
+        assert_eq!(flaky.written(), [3 * SECOND, 0, SECOND, 2 * SECOND]);
+    }
+
+    #[test]
+    fn test_spool_in_sink_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = format!(
+            r#"{{"format": "csv", "dir": "{}", "spool": {{"dir": "{}"}}}}"#,
+            dir.path().join("out").display(),
+            dir.path().join("spool").display()
+        );
+        let config: SinkConfig = serde_json::from_str(&json).unwrap();
+        let spool = config.spool.clone().unwrap();
+        assert_eq!(spool.catch_up_rows_per_sec, 10_000.0);
+        assert_eq!(spool.retry_secs, 5.0);
+
+        let mut sink = config.open(7).unwrap();
+        sink.write_batch(&batch(0, 30)).unwrap();
+        sink.close().unwrap();
+        assert!(dir.path().join("spool").join("7").is_dir());
+        let csv = std::fs::read_to_string(dir.path().join("out").join("7.csv")).unwrap();
+        assert_eq!(csv.lines().count(), 31);
+    }
+}
@@ -0,0 +1,149 @@
+#![cfg(feature = "sinks")]
+#![allow(unused)]
+mod common;
+
+use common::read_hex_file;
+use std::path::Path;
+use std::process::Command;
+
+// Result rows of a query, columns separated by |. None without a sqlite3 shell.
+fn query(path: &Path, sql: &str) -> Option<Vec<String>> {
+    let output = Command::new("sqlite3").arg(path).arg(sql).output().ok()?;
+    assert!(output.status.success(), "{:?}", output);
+    Some(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{query, read_hex_file};
+    use arrow::record_batch::RecordBatch;
+    use pmu::arrow_utils::build_record_batch;
+    use pmu::events::{Event, EventKind, Severity};
+    use pmu::frame_parser::parse_config_frame_1and2;
+    use pmu::sinks::sqlite::SqliteSink;
+    use pmu::sinks::BatchSink;
+    use std::process::Command;
+
+    fn sqlite3_available() -> bool {
+        Command::new("sqlite3").arg("-version").output().is_ok()
+    }
+
+    // Sample frames at soc, 10 frames per second.
+    fn batch(soc: u32, frames: u32) -> RecordBatch {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let mut buffer = Vec::new();
+        let mut frame_size = 0;
+        for n in 0..frames {
+            let mut frame = read_hex_file("data_message.bin").unwrap();
+            frame[6..10].copy_from_slice(&(soc + n / 10).to_be_bytes());
+            frame[10..14].copy_from_slice(&(n % 10 * 100_000).to_be_bytes());
+            frame_size = frame.len();
+            buffer.extend_from_slice(&frame);
+        }
+        build_record_batch(&buffer, frame_size, &config.get_channel_map()).unwrap()
+    }
+
+    #[test]
+    fn test_sqlite_samples_and_events() {
+        if !sqlite3_available() {
+            println!("No sqlite3 shell, skipped");
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("7734.sqlite");
+        let mut sink = SqliteSink::new(&path).unwrap().with_stream(7734);
+        let batch = batch(1_700_000_000, 20);
+        sink.write_batch(&batch).unwrap();
+        sink.write_event(
+            &Event::new(
+                1_700_000_000_500_000,
+                EventKind::GeneratorTrip,
+                "Station A",
+                "It's a trip".to_string(),
+            )
+            .with_severity(Severity::Alarm)
+            .with_value("size_mw", 120.0),
+        )
+        .unwrap();
+        sink.close().unwrap();
+        assert_eq!(sink.rows(), 20);
+
+        let channels = query(&path, "SELECT count(*) FROM channels WHERE stream = 7734").unwrap();
+        assert!(channels[0].parse::<usize>().unwrap() > 1);
+        let samples = query(
+            &path,
+            "SELECT count(DISTINCT timestamp_us), count(*) / count(DISTINCT channel_id) FROM samples",
+        )
+        .unwrap();
+        assert_eq!(samples, vec!["20|20"]);
+        // Engineering units, FREQ of the sample frame is 60 Hz + 2500 mHz
+        let frequency = query(
+            &path,
+            "SELECT min(value), max(value) FROM samples JOIN channels ON id = channel_id \
+             WHERE kind = 'frequency'",
+        )
+        .unwrap();
+        let (min, max) = frequency[0].split_once('|').unwrap();
+        assert_eq!(min, max);
+        assert_eq!(min.parse::<f64>().unwrap(), 62.5);
+        let events = query(
+            &path,
+            "SELECT kind, severity, message, measured FROM events",
+        )
+        .unwrap();
+        assert_eq!(
+            events,
+            vec![r#"generator_trip|alarm|It's a trip|{"size_mw":120.0}"#]
+        );
+    }
+
+    #[test]
+    fn test_sqlite_decimation_and_resume() {
+        if !sqlite3_available() {
+            println!("No sqlite3 shell, skipped");
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite");
+        let mut sink = SqliteSink::new(&path).unwrap().with_decimation(1_000_000);
+        sink.write_batch(&batch(1_700_000_000, 25)).unwrap();
+        sink.close().unwrap();
+        assert_eq!(sink.rows(), 3);
+
+        // Rows written again replace the earlier ones, channels keep their ids
+        let before = query(&path, "SELECT id, name FROM channels ORDER BY id").unwrap();
+        let mut sink = SqliteSink::new(&path).unwrap();
+        sink.write_batch(&batch(1_700_000_002, 10)).unwrap();
+        sink.close().unwrap();
+        assert_eq!(
+            query(&path, "SELECT id, name FROM channels ORDER BY id").unwrap(),
+            before
+        );
+        let timestamps = query(
+            &path,
+            "SELECT DISTINCT timestamp_us / 100000 FROM samples ORDER BY 1",
+        )
+        .unwrap();
+        assert_eq!(
+            timestamps[..3],
+            ["17000000000", "17000000010", "17000000020"]
+        );
+        assert_eq!(timestamps.len(), 12);
+    }
+
+    #[test]
+    fn test_sqlite_shell_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = SqliteSink::with_program(dir.path().join("db.sqlite"), "no-such-sqlite3")
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("no-such-sqlite3"));
+    }
+}
@@ -0,0 +1,209 @@
+#![allow(unused)]
+mod common;
+
+use common::{read_hex_file, with_crc};
+use pmu::frame_parser::parse_config_frame_1and2;
+#[cfg(all(feature = "analytics", feature = "server"))]
+use pmu::pdc_server::{run_mock_server, Protocol, ServerConfig};
+#[cfg(all(feature = "analytics", feature = "server"))]
+use pmu::pipeline::{Pipeline, PipelineConfig};
+use pmu::strict::{check_command, check_config, check_prefix, StrictChecker, Violation};
+use std::time::Duration;
+
+// A sample frame changed, with its CHK recalculated.
+fn changed(file_name: &str, change: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+    let mut frame = read_hex_file(file_name).unwrap();
+    change(&mut frame);
+    with_crc(frame)
+}
+
+// Offset of a field of the first PMU in the sample configuration frame,
+// counted from FORMAT.
+fn pmu_offset(field: usize) -> usize {
+    // Prefix, TIME_BASE, NUM_PMU, STN and IDCODE
+    14 + 4 + 2 + 16 + 2 + field
+}
+
+fn fields(violations: &[Violation]) -> Vec<&str> {
+    violations
+        .iter()
+        .map(|violation| violation.field.as_str())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_frames_pass() {
+        let mut checker = StrictChecker::new();
+        let config = read_hex_file("config_message.bin").unwrap();
+        assert_eq!(checker.check(&config), Vec::new());
+        let data = read_hex_file("data_message.bin").unwrap();
+        assert_eq!(checker.check(&data), Vec::new());
+        let command = read_hex_file("cmd_message.bin").unwrap();
+        assert_eq!(checker.check(&command), Vec::new());
+    }
+
+    #[test]
+    fn test_prefix_violations() {
+        let frame = changed("data_message.bin", |frame| {
+            frame[1] = 0x8F; // Reserved bit 7, data frame of version 15
+            frame[4..6].copy_from_slice(&0u16.to_be_bytes());
+            frame[10] = 0x8D; // Reserved bit 7, time quality 13
+        });
+        let violations = check_prefix(&frame);
+        assert_eq!(
+            fields(&violations),
+            vec![
+                "SYNC",
+                "SYNC version",
+                "IDCODE",
+                "FRACSEC time quality",
+                "FRACSEC time quality code"
+            ]
+        );
+        assert_eq!(
+            violations[0].to_string(),
+            "SYNC = 0xAA8F: reserved bit 7 set"
+        );
+        assert_eq!(violations[1].to_string(), "SYNC version = 15: reserved");
+        assert_eq!(violations[2].to_string(), "IDCODE = 0: reserved");
+        assert_eq!(violations[4].value, "13");
+
+        let frame = changed("data_message.bin", |frame| {
+            frame[1] = 0x60 | (frame[1] & 0x0F);
+            frame[4..6].copy_from_slice(&u16::MAX.to_be_bytes());
+        });
+        assert_eq!(
+            fields(&check_prefix(&frame)),
+            vec!["SYNC frame type", "IDCODE"]
+        );
+        assert_eq!(
+            check_prefix(&frame[..10])[0].rule,
+            "shorter than the prefix"
+        );
+        let frame = read_hex_file("data_message.bin").unwrap();
+        assert_eq!(fields(&check_prefix(&frame[..20])), vec!["FRAMESIZE"]);
+    }
+
+    #[test]
+    fn test_config_violations() {
+        let frame = changed("config_message.bin", |frame| {
+            frame[14] = 0x01; // TIME_BASE bits 31-24
+            frame[pmu_offset(0)..pmu_offset(2)].copy_from_slice(&0x0104u16.to_be_bytes());
+            let len = frame.len();
+            // FNOM of the only PMU, before CFGCNT, DATA_RATE and CHK
+            frame[len - 8..len - 6].copy_from_slice(&0x0003u16.to_be_bytes());
+            frame[len - 4..len - 2].copy_from_slice(&0i16.to_be_bytes());
+        });
+        let config = parse_config_frame_1and2(&frame).unwrap();
+        let violations = check_config(&config);
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "TIME_BASE = 0x010F4240: reserved bits 31-24 set",
+                "DATA_RATE = 0: must not be 0",
+                "PMU 1 (Station A) FORMAT = 0x0104: reserved bits 15-4 set",
+                "PMU 1 (Station A) FNOM = 0x0003: reserved bits 15-1 set",
+            ]
+        );
+
+        // Phasor and analog conversion factors of reserved types
+        let mut config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        config.pmu_configs[0].idcode = u16::MAX;
+        config.pmu_configs[0].phunit[0] = 0x0200_0000;
+        config.pmu_configs[0].anunit[0] = 0x0300_0000;
+        let violations = check_config(&config);
+        assert_eq!(violations[0].field, "PMU 1 (Station A) IDCODE");
+        assert_eq!(violations[1].field, "PMU 1 (Station A) PHUNIT 1");
+        assert_eq!(violations[2].field, "PMU 1 (Station A) ANUNIT 1");
+        assert_eq!(violations.len(), 3);
+
+        // Rejected configurations are not kept to check data frames against
+        let mut checker = StrictChecker::new();
+        assert_eq!(checker.check(&frame).len(), 4);
+        let data = changed("data_message.bin", |frame| {
+            frame[11..14].copy_from_slice(&[0x0F, 0x42, 0x40]);
+        });
+        assert!(checker.check(&data).is_empty());
+    }
+
+    #[test]
+    fn test_data_violations() {
+        let mut checker = StrictChecker::new();
+        checker.check(&read_hex_file("config_message.bin").unwrap());
+        // FRACSEC of 1 s at TIME_BASE 1000000, reserved trigger reason
+        let data = changed("data_message.bin", |frame| {
+            frame[11..14].copy_from_slice(&[0x0F, 0x42, 0x40]);
+            frame[15] = (frame[15] & 0xF0) | 0x06;
+        });
+        let violations = checker.check(&data);
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "FRACSEC = 1000000: not below TIME_BASE of the configuration",
+                "PMU 1 (Station A) STAT trigger reason = 6: reserved",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_command_violations() {
+        let command = |cmd: u16| {
+            changed("cmd_message.bin", |frame| {
+                frame[14..16].copy_from_slice(&cmd.to_be_bytes());
+            })
+        };
+        assert!(check_command(&command(0x0002)).is_empty());
+        assert!(check_command(&command(0x0100)).is_empty()); // User designated
+        assert_eq!(
+            check_command(&command(0x0007))[0].to_string(),
+            "CMD = 0x0007: reserved"
+        );
+        assert_eq!(check_command(&command(0x1000))[0].value, "0x1000");
+        assert_eq!(StrictChecker::new().check(&command(0xFFFF)).len(), 1);
+    }
+
+    #[cfg(all(feature = "analytics", feature = "server"))]
+    #[tokio::test]
+    async fn test_pipeline_strict() {
+        let config = PipelineConfig::from_json(
+            r#"{"streams": [{"host": "127.0.0.1", "port": 4742}],
+                "sink": {"dir": "out"}}"#,
+        )
+        .unwrap();
+        assert!(!config.strict);
+
+        let server_config =
+            ServerConfig::new("127.0.0.1".to_string(), 4742, Protocol::TCP, 30.0).unwrap();
+        let server = tokio::spawn(async move {
+            if let Err(e) = run_mock_server(server_config).await {
+                println!("Mock server error: {}", e)
+            };
+        });
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let json = format!(
+            r#"{{"streams": [{{"host": "127.0.0.1", "port": 4742}}],
+                "sink": {{"format": "csv", "dir": {:?}}},
+                "strict": true}}"#,
+            dir.path().join("out")
+        );
+        let config = PipelineConfig::from_json(&json).unwrap();
+        assert!(config.strict);
+        let validation = Pipeline::validate(&config).await;
+        assert!(validation.is_ok(), "{:?}", validation.problems);
+        assert_eq!(validation.streams[0].idcode, Some(7734));
+        server.abort();
+    }
+}
@@ -0,0 +1,126 @@
+#![cfg(feature = "timescale")]
+#![allow(unused)]
+mod common;
+
+use common::frequency_batch;
+
+#[cfg(test)]
+mod tests {
+    use super::frequency_batch;
+    use pmu::sinks::timescale::{copy_columns, copy_data, create_statements, TimescaleSink};
+    use pmu::sinks::BatchSink;
+    use std::process::Command;
+
+    #[test]
+    fn test_binary_copy_encoding() {
+        let data = copy_data(&frequency_batch(true)).unwrap();
+        let mut expected = b"PGCOPY\n\xff\r\n\0".to_vec();
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        // Row 1: 3 fields, 1 us after the PostgreSQL epoch, 60.0, 1
+        expected.extend_from_slice(&3i16.to_be_bytes());
+        expected.extend_from_slice(&8i32.to_be_bytes());
+        expected.extend_from_slice(&1i64.to_be_bytes());
+        expected.extend_from_slice(&4i32.to_be_bytes());
+        expected.extend_from_slice(&60.0f32.to_be_bytes());
+        expected.extend_from_slice(&4i32.to_be_bytes());
+        expected.extend_from_slice(&1i32.to_be_bytes());
+        // Row 2: FREQ is NULL
+        expected.extend_from_slice(&3i16.to_be_bytes());
+        expected.extend_from_slice(&8i32.to_be_bytes());
+        expected.extend_from_slice(&2i64.to_be_bytes());
+        expected.extend_from_slice(&(-1i32).to_be_bytes());
+        expected.extend_from_slice(&4i32.to_be_bytes());
+        expected.extend_from_slice(&0i32.to_be_bytes());
+        expected.extend_from_slice(&(-1i16).to_be_bytes());
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn test_table_from_channel_catalog() {
+        let batch = frequency_batch(true);
+        assert_eq!(
+            copy_columns(&batch).unwrap(),
+            r#""time", "FREQ", "BREAKER""#
+        );
+
+        let sql = create_statements("stream_7734", &batch, true).unwrap();
+        assert!(sql
+            .contains(r#"CREATE TABLE IF NOT EXISTS "stream_7734" (time timestamptz NOT NULL);"#));
+        assert!(sql.contains(r#"ADD COLUMN IF NOT EXISTS "FREQ" real;"#));
+        assert!(sql.contains(r#"ADD COLUMN IF NOT EXISTS "BREAKER" integer;"#));
+        assert!(sql.contains(
+            "VALUES ('stream_7734', 'FREQ', 'Station A', NULL, NULL, NULL, NULL, 'Hz', 1, NULL)"
+        ));
+        assert!(sql.contains("create_hypertable('\"stream_7734\"', 'time'"));
+        assert!(!create_statements("t", &batch, false)
+            .unwrap()
+            .contains("create_hypertable"));
+
+        assert!(TimescaleSink::new("", &"x".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn test_topology_in_channel_catalog() {
+        use arrow::datatypes::{Field, Schema};
+        use arrow::record_batch::RecordBatch;
+        use std::sync::Arc;
+
+        let batch = frequency_batch(true);
+        let sql = create_statements("stream_7734", &batch, false).unwrap();
+        assert!(sql.contains("ADD COLUMN IF NOT EXISTS mrid text"));
+        assert!(!sql.contains("UPDATE pmu_channels"));
+
+        let schema = batch.schema();
+        let mut fields: Vec<Field> = schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+        let mut metadata = fields[1].metadata().clone();
+        metadata.insert("pmu.bus".to_string(), "BUS_7".to_string());
+        metadata.insert("pmu.mrid".to_string(), "_5f2c".to_string());
+        fields[1] = fields[1].clone().with_metadata(metadata);
+        let batch =
+            RecordBatch::try_new(Arc::new(Schema::new(fields)), batch.columns().to_vec()).unwrap();
+        let sql = create_statements("stream_7734", &batch, false).unwrap();
+        assert!(sql.contains(
+            "UPDATE pmu_channels SET bus = 'BUS_7', branch = NULL, mrid = '_5f2c' \
+             WHERE table_name = 'stream_7734' AND column_name = 'FREQ';"
+        ));
+    }
+
+    // Against a database given as PMU_TEST_POSTGRES, plain PostgreSQL will do.
+    #[test]
+    fn test_copy_into_database() {
+        let Ok(connection) = std::env::var("PMU_TEST_POSTGRES") else {
+            println!("PMU_TEST_POSTGRES not set, skipped");
+            return;
+        };
+        let query = |sql: &str| {
+            let output = Command::new("psql")
+                .args(["-X", "-A", "-t", "-d", &connection, "-c", sql])
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{:?}", output);
+            String::from_utf8(output.stdout).unwrap().trim().to_string()
+        };
+        query("DROP TABLE IF EXISTS pmu_test_copy");
+
+        let mut sink = TimescaleSink::new(&connection, "pmu_test_copy")
+            .unwrap()
+            .with_hypertable(false);
+        sink.write_batch(&frequency_batch(true)).unwrap();
+        sink.write_batch(&frequency_batch(true)).unwrap();
+        assert_eq!(sink.rows(), 4);
+
+        assert_eq!(
+            query("SELECT count(*), count(\"FREQ\"), sum(\"BREAKER\") FROM pmu_test_copy"),
+            "4|2|2"
+        );
+        assert_eq!(
+            query("SELECT min(time) AT TIME ZONE 'UTC' FROM pmu_test_copy"),
+            "2000-01-01 00:00:00.000001"
+        );
+        assert_eq!(
+            query("SELECT unit FROM pmu_channels WHERE table_name = 'pmu_test_copy' AND column_name = 'FREQ'"),
+            "Hz"
+        );
+        query("DROP TABLE pmu_test_copy");
+    }
+}
@@ -0,0 +1,126 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+mod common;
+
+use common::sample_batch;
+use pmu::arrow_utils::{META_BRANCH, META_BUS, META_CHANNEL, META_MRID};
+use pmu::pipeline::PipelineConfig;
+use pmu::sinks::parquet::ParquetSink;
+use pmu::sinks::BatchSink;
+use pmu::topology::{ModelElement, Topology};
+use std::fs;
+
+const MAPPING: &str = "channel,bus,branch,mrid
+# Station A feeds bus 7
+Station A_7734_VA,BUS_7,,_5f2c1a4e
+Station A_7734_I1, BUS_7 , LINE_7_8 , _9d1e3b2a
+Station A_7734_FREQ,BUS_7,,
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mapping() {
+        let topology = Topology::from_csv(MAPPING).unwrap();
+        assert_eq!(topology.len(), 3);
+        assert_eq!(
+            topology.element("Station A_7734_I1"),
+            Some(&ModelElement {
+                bus: Some("BUS_7".to_string()),
+                branch: Some("LINE_7_8".to_string()),
+                mrid: Some("_9d1e3b2a".to_string()),
+            })
+        );
+        assert_eq!(
+            topology.element("Station A_7734_FREQ").unwrap().metadata(),
+            vec![(META_BUS, "BUS_7")]
+        );
+        assert_eq!(topology.element("Station A_7734_VB"), None);
+        assert!(Topology::from_csv("").unwrap().is_empty());
+
+        let error = Topology::from_csv("channel,bus,branch,mrid\nVA,BUS_7\n").unwrap_err();
+        assert!(error.starts_with("Line 2:"), "{}", error);
+        assert!(Topology::from_csv("VA,,,\n").is_err());
+        assert!(Topology::from_csv(",BUS_7,,\n").is_err());
+        assert!(Topology::from_csv("VA,BUS_7,,\nVA,BUS_8,,\n")
+            .unwrap_err()
+            .contains("mapped twice"));
+    }
+
+    #[test]
+    fn test_apply_to_columns() {
+        let batch = sample_batch();
+        let topology = Topology::from_csv(MAPPING).unwrap();
+        let mapped = topology.apply(&batch).unwrap();
+        assert_eq!(mapped.num_rows(), batch.num_rows());
+        assert_eq!(mapped.num_columns(), batch.num_columns());
+
+        let schema = mapped.schema();
+        let mut bus = 0;
+        for field in schema.fields() {
+            let meta = field.metadata();
+            let element = meta
+                .get(META_CHANNEL)
+                .and_then(|channel| topology.element(channel));
+            match element {
+                Some(element) => {
+                    bus += 1;
+                    assert_eq!(meta.get(META_BUS), element.bus.as_ref());
+                    assert_eq!(meta.get(META_BRANCH), element.branch.as_ref());
+                    assert_eq!(meta.get(META_MRID), element.mrid.as_ref());
+                }
+                None => assert!(!meta.contains_key(META_BUS), "{}", field.name()),
+            }
+            // Metadata of the channel kept
+            let original = batch
+                .schema()
+                .field_with_name(field.name())
+                .unwrap()
+                .clone();
+            for (key, value) in original.metadata() {
+                assert_eq!(meta.get(key), Some(value));
+            }
+        }
+        // Both components of the phasors, and FREQ
+        assert!(bus >= 5, "{}", bus);
+    }
+
+    #[test]
+    fn test_elements_exported_to_parquet() {
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let dir = tempfile::tempdir().unwrap();
+        let topology = Topology::from_csv(MAPPING).unwrap();
+        let mut sink = ParquetSink::new(dir.path(), "mapped").unwrap();
+        sink.write_batch(&topology.apply(&sample_batch()).unwrap())
+            .unwrap();
+        sink.close().unwrap();
+        let file = fs::File::open(&sink.files()[0]).unwrap();
+        let schema = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .schema()
+            .clone();
+        let freq = schema
+            .fields()
+            .iter()
+            .find(|field| {
+                field.metadata().get(META_CHANNEL).map(String::as_str)
+                    == Some("Station A_7734_FREQ")
+            })
+            .unwrap();
+        assert_eq!(freq.metadata()[META_BUS], "BUS_7");
+    }
+
+    #[test]
+    fn test_pipeline_topology_config() {
+        let config = PipelineConfig::from_json(
+            r#"{"streams": [{"host": "127.0.0.1", "port": 4712}],
+                "sink": {"format": "csv", "dir": "out"},
+                "topology": "topology.csv"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.topology, Some("topology.csv".into()));
+    }
+}
@@ -0,0 +1,162 @@
+#![cfg(all(feature = "analytics", feature = "server"))]
+#![allow(unused)]
+use pmu::metrics::Metrics;
+use pmu::pdc_server::{run_mock_server, Protocol, ServerConfig};
+use pmu::pipeline::{Pipeline, PipelineConfig};
+use pmu::simulator::Scenario;
+use pmu::trace::{
+    Histogram, LatencyTracer, Stage, TraceConfig, BUCKET_BOUNDS_US, STAGE_LATENCY_METRIC,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time;
+
+// Header of a data frame of a stream at a time, with a time base of 1e6.
+fn frame(idcode: u16, frame_us: i64) -> Vec<u8> {
+    let mut frame = vec![0xAA, 0x01, 0x00, 0x10];
+    frame.extend_from_slice(&idcode.to_be_bytes());
+    frame.extend_from_slice(&((frame_us / 1_000_000) as u32).to_be_bytes());
+    frame.extend_from_slice(&((frame_us % 1_000_000) as u32).to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.quantile_us(0.5), None);
+        assert_eq!(histogram.mean_us(), None);
+        for value in [80, 90, 400, 700, 3_000, 4_000, 8_000, 20_000, 90_000] {
+            histogram.record(value);
+        }
+        histogram.record(200_000_000);
+        assert_eq!(histogram.count, 10);
+        assert_eq!(histogram.counts[0], 2);
+        assert_eq!(histogram.counts[BUCKET_BOUNDS_US.len()], 1);
+        assert_eq!(histogram.quantile_us(0.5), Some(5_000));
+        assert_eq!(histogram.quantile_us(0.9), Some(100_000));
+        assert_eq!(histogram.quantile_us(0.99), Some(200_000_000));
+        assert_eq!(histogram.max_us, 200_000_000);
+
+        // A bucket's bound is capped at the largest value recorded
+        let mut small = Histogram::default();
+        small.record(30);
+        assert_eq!(small.quantile_us(1.0), Some(30));
+    }
+
+    #[test]
+    fn test_stages_of_sampled_frames() {
+        let metrics = Arc::new(Metrics::new());
+        let tracer = LatencyTracer::new(TraceConfig::default().with_sample_every(2))
+            .with_metrics(metrics.clone());
+        let start = 1_700_000_000_000_000;
+        for n in 0..4 {
+            let frame_us = start + n * 33_333;
+            tracer.received(&frame(7, frame_us), 1_000_000, frame_us + 20_000);
+            // Remapped from 7 to 70 by the writer
+            tracer.parsed(7, 70, frame_us, frame_us + 21_000);
+        }
+        let last_us = start + 3 * 33_333;
+        tracer.batch_written(70, last_us, last_us + 30_000, last_us + 32_000);
+
+        let summary = tracer.summary();
+        assert_eq!((summary.completed, summary.abandoned), (2, 0));
+        assert_eq!(summary.stage(Stage::Receive).sum_us, 2 * 20_000);
+        assert_eq!(summary.stage(Stage::Parse).sum_us, 2 * 1_000);
+        // The first frame waited for the fourth to fill the batch
+        assert_eq!(summary.stage(Stage::Align).max_us, 3 * 33_333 + 9_000);
+        assert_eq!(summary.stage(Stage::Flush).mean_us(), Some(2_000.0));
+        assert_eq!(summary.total.max_us, 3 * 33_333 + 32_000);
+        assert_eq!(summary.slowest(), Some(Stage::Align));
+        assert_eq!(
+            metrics.gauge(
+                STAGE_LATENCY_METRIC,
+                &[("stage", "flush"), ("quantile", "1")]
+            ),
+            Some(2_000.0)
+        );
+        assert!(metrics
+            .gauge(
+                STAGE_LATENCY_METRIC,
+                &[("stage", "total"), ("quantile", "0.99")]
+            )
+            .is_some());
+        assert!(summary.to_string().starts_with("2 frames traced"));
+    }
+
+    #[test]
+    fn test_abandon_open_traces() {
+        let config = TraceConfig {
+            sample_every: 1,
+            max_open: 3,
+        };
+        let tracer = LatencyTracer::new(config);
+        let start = 1_700_000_000_000_000;
+        for n in 0..5 {
+            let frame_us = start + n * 100_000;
+            tracer.received(&frame(7, frame_us), 1_000_000, frame_us + 1_000);
+        }
+        // The two oldest were dropped on the way
+        tracer.parsed(7, 7, start, start + 2_000);
+        for n in 2..5 {
+            let frame_us = start + n * 100_000;
+            tracer.parsed(7, 7, frame_us, frame_us + 2_000);
+        }
+        tracer.batch_written(7, start + 400_000, start + 500_000, start + 500_000);
+        let summary = tracer.summary();
+        assert_eq!((summary.completed, summary.abandoned), (3, 2));
+        assert_eq!(summary.total.count, 3);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_trace() {
+        let server_config = ServerConfig::new("127.0.0.1".to_string(), 4753, Protocol::TCP, 30.0)
+            .unwrap()
+            .with_scenario(Scenario::default());
+        let server = tokio::spawn(async move {
+            if let Err(e) = run_mock_server(server_config).await {
+                println!("Mock server error: {}", e)
+            };
+        });
+        time::sleep(Duration::from_millis(500)).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let json = format!(
+            r#"{{"streams": [{{"host": "127.0.0.1", "port": 4753}}],
+                "sink": {{"format": "csv", "dir": {:?}}}, "batch_rows": 5,
+                "trace": {{"sample_every": 1}}}}"#,
+            dir.path()
+        );
+        let config = PipelineConfig::from_json(&json).unwrap();
+        let metrics = Arc::new(Metrics::new());
+        let pipeline = Arc::new(Pipeline::new(config).with_metrics(metrics.clone()));
+        let runner = pipeline.clone();
+        let handle = tokio::spawn(async move { runner.run().await });
+        time::sleep(Duration::from_millis(1000)).await;
+        pipeline.stop();
+        let stats = time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(stats[0].frames > 10, "{:?}", stats[0]);
+
+        let summary = pipeline.tracer().unwrap().summary();
+        assert!(summary.completed > 10, "{}", summary);
+        for stage in Stage::ALL {
+            assert_eq!(summary.stage(stage).count, summary.completed);
+            assert!(summary.stage(stage).max_us >= 0, "{}", summary);
+        }
+        assert!(metrics
+            .gauge(
+                STAGE_LATENCY_METRIC,
+                &[("stage", "receive"), ("quantile", "0.5")]
+            )
+            .is_some());
+        server.abort();
+    }
+}
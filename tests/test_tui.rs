@@ -0,0 +1,198 @@
+#![cfg(feature = "tui")]
+#![allow(unused)]
+mod common;
+
+use common::read_hex_file;
+use pmu::events::{Event, EventKind, Severity};
+use pmu::frame_parser::parse_config_frame_1and2;
+use pmu::latency::LatencyTracker;
+use pmu::pdc_client::{ClientState, ClientStatus};
+use pmu::tui::{readings, render, Action, MonitorState, StreamState, StreamTarget, Update};
+use ratatui::backend::TestBackend;
+use ratatui::crossterm::event::KeyCode;
+use ratatui::Terminal;
+use std::time::{Duration, Instant};
+
+fn status(state: ClientState, frames_received: u64) -> ClientStatus {
+    ClientStatus {
+        state,
+        frames_received,
+        stalls: 0,
+        turn_on_resends: 0,
+        config_change_pending: false,
+        latency: LatencyTracker::new(),
+    }
+}
+
+fn targets() -> Vec<StreamTarget> {
+    vec![
+        StreamTarget::parse("north=10.0.0.1:4712/7734").unwrap(),
+        StreamTarget::parse("10.0.0.2:4712/2").unwrap(),
+    ]
+}
+
+fn screen(state: &MonitorState) -> String {
+    let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+    terminal.draw(|frame| render(frame, state)).unwrap();
+    let buffer = terminal.backend().buffer();
+    let mut text = String::new();
+    for y in 0..buffer.area.height {
+        for x in 0..buffer.area.width {
+            text.push_str(buffer[(x, y)].symbol());
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        let target = StreamTarget::parse("north=10.0.0.1:4712/7734").unwrap();
+        assert_eq!(target.name, "north");
+        assert_eq!(target.host, "10.0.0.1");
+        assert_eq!(target.port, 4712);
+        assert_eq!(target.idcode, 7734);
+
+        let target = StreamTarget::parse("localhost:4712/1").unwrap();
+        assert_eq!(target.name, "localhost:4712/1");
+
+        for invalid in [
+            "localhost:4712",
+            "localhost/1",
+            ":4712/1",
+            "host:port/1",
+            "h:1/x",
+        ] {
+            assert!(StreamTarget::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_readings_of_sample_frame() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let data = read_hex_file("data_message.bin").unwrap();
+
+        let readings = readings(&data, &config);
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].station, "Station A");
+        assert_eq!(readings[0].idcode, 7734);
+        assert!((readings[0].frequency - 62.5).abs() < 1e-9);
+
+        // I1 is a current
+        let names: Vec<&str> = readings[0]
+            .voltages
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["VA", "VB", "VC"]);
+        for (_, magnitude) in &readings[0].voltages {
+            assert!((magnitude - 134_000.0).abs() < 20.0, "{}", magnitude);
+        }
+
+        assert!(super::readings(&data[..40], &config).is_empty());
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut state = MonitorState::new(targets());
+        assert_eq!(state.handle_key(KeyCode::Up), Action::None);
+        assert_eq!(state.selected, 0);
+        assert_eq!(state.handle_key(KeyCode::Down), Action::None);
+        assert_eq!(state.handle_key(KeyCode::Char('j')), Action::None);
+        assert_eq!(state.selected, 1);
+        assert_eq!(state.handle_key(KeyCode::Char('x')), Action::Stop(1));
+        assert_eq!(state.handle_key(KeyCode::Char('k')), Action::None);
+        assert_eq!(state.handle_key(KeyCode::Char('s')), Action::Start(0));
+        assert_eq!(state.handle_key(KeyCode::Char('q')), Action::Quit);
+        assert_eq!(state.handle_key(KeyCode::Esc), Action::Quit);
+
+        let mut empty = MonitorState::new(Vec::new());
+        assert_eq!(empty.handle_key(KeyCode::Char('s')), Action::None);
+    }
+
+    #[test]
+    fn test_rate_and_states() {
+        let mut state = MonitorState::new(targets());
+        let start = Instant::now();
+        state.apply(Update::State(0, StreamState::Connecting), start);
+        state.apply(Update::Status(0, status(ClientState::Streaming, 0)), start);
+        state.apply(
+            Update::Status(0, status(ClientState::Streaming, 10)),
+            start + Duration::from_millis(500),
+        );
+        assert_eq!(state.streams[0].rate, 0.0); // Window not complete
+        state.apply(
+            Update::Status(0, status(ClientState::Streaming, 60)),
+            start + Duration::from_secs(2),
+        );
+        assert!((state.streams[0].rate - 30.0).abs() < 1e-9);
+        assert_eq!(
+            state.streams[0].state,
+            StreamState::Client(ClientState::Streaming)
+        );
+
+        state.apply(
+            Update::State(0, StreamState::Idle),
+            start + Duration::from_secs(3),
+        );
+        assert_eq!(state.streams[0].rate, 0.0);
+        assert_eq!(state.streams[1].state, StreamState::Idle);
+    }
+
+    #[test]
+    fn test_events_are_capped() {
+        let mut state = MonitorState::new(targets());
+        for n in 0..150 {
+            state.push_event(Event::new(
+                n,
+                EventKind::StreamStalled,
+                "north",
+                format!("{}", n),
+            ));
+        }
+        assert_eq!(state.events.len(), 100);
+        assert_eq!(state.events[0].message, "149");
+    }
+
+    #[test]
+    fn test_render() {
+        let config =
+            parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap();
+        let data = read_hex_file("data_message.bin").unwrap();
+        let mut state = MonitorState::new(targets());
+        let now = Instant::now();
+        state.apply(Update::Status(0, status(ClientState::Streaming, 42)), now);
+        state.apply(Update::Readings(0, readings(&data, &config)), now);
+        state.apply(
+            Update::State(1, StreamState::Failed("connection refused".to_string())),
+            now,
+        );
+        state.push_event(
+            Event::new(
+                0,
+                EventKind::StreamStalled,
+                "Station A",
+                "No data for 1.0 s".to_string(),
+            )
+            .with_severity(Severity::Warning),
+        );
+
+        let text = screen(&state);
+        assert!(text.contains("north"));
+        assert!(text.contains("streaming"));
+        assert!(text.contains("62.500"));
+        assert!(text.contains("Station A (7734)"));
+        assert!(text.contains("VB"));
+        assert!(text.contains("failed"));
+        assert!(text.contains("No data for 1.0 s"));
+
+        state.handle_key(KeyCode::Down);
+        let text = screen(&state);
+        assert!(text.contains("Error: connection refused"));
+    }
+}
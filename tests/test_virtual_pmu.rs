@@ -0,0 +1,132 @@
+#![cfg(feature = "arrow")]
+#![allow(unused)]
+mod common;
+
+use common::read_hex_file;
+use pmu::analytics::accuracy::{frame_measurements, Phasor};
+use pmu::arrow_utils::frame_timestamp_micros;
+use pmu::frame_parser::{parse_config_frame_1and2, parse_data_frames};
+use pmu::frames::{ConfigurationFrame1and2_2011, PMUData};
+use pmu::simulator::{Scenario, ScenarioEvent, Simulator};
+use pmu::virtual_pmu::{sequence, SequenceComponent, VirtualStream, VirtualStreamConfig};
+
+fn source() -> ConfigurationFrame1and2_2011 {
+    parse_config_frame_1and2(&read_hex_file("config_message.bin").unwrap()).unwrap()
+}
+
+const VIRTUAL_PMUS: &str = r#"{
+    "idcode": 900,
+    "pmus": [{
+        "station": "AREA NORTH",
+        "idcode": 901,
+        "sequences": [
+            {"name": "V1", "component": "positive", "phases": ["VA", "VB", "VC"]},
+            {"name": "V2", "component": "negative", "phases": ["VA", "VB", "VC"]},
+            {"name": "V0", "component": "zero",
+             "phases": ["Station A_7734_VA", "Station A_7734_VB", "Station A_7734_VC"]}
+        ],
+        "angle_differences": [{"name": "VA-VB", "from": "VA", "to": "VB"}],
+        "frequency_weights": {"Station A": 2.0}
+    }]
+}"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_components() {
+        let phase = |degrees: f64| Phasor::from_polar(100.0, degrees.to_radians());
+        let balanced = [phase(10.0), phase(-110.0), phase(130.0)];
+        let positive = sequence(SequenceComponent::Positive, balanced);
+        assert!((positive.magnitude() - 100.0).abs() < 1e-9);
+        assert!((positive.angle().to_degrees() - 10.0).abs() < 1e-9);
+        assert!(sequence(SequenceComponent::Negative, balanced).magnitude() < 1e-9);
+        assert!(sequence(SequenceComponent::Zero, balanced).magnitude() < 1e-9);
+
+        // The same phasor on every phase is all zero sequence
+        let zero = sequence(SequenceComponent::Zero, [phase(30.0); 3]);
+        assert!((zero.magnitude() - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_virtual_configuration() {
+        let config = VirtualStreamConfig::from_json(VIRTUAL_PMUS).unwrap();
+        let stream = VirtualStream::new(config, &source()).unwrap();
+        let virtual_config = stream.config();
+        assert_eq!(virtual_config.prefix.idcode, 900);
+        assert_eq!(virtual_config.data_rate, source().data_rate);
+        assert_eq!(virtual_config.time_base, source().time_base);
+
+        // A CFG-2 legacy consumers can parse
+        let parsed = parse_config_frame_1and2(&virtual_config.to_hex()).unwrap();
+        assert_eq!(&parsed, virtual_config);
+        let pmu = &parsed.pmu_configs[0];
+        assert_eq!(pmu.idcode, 901);
+        assert_eq!((pmu.phnmr, pmu.annmr, pmu.dgnmr), (3, 1, 0));
+        assert_eq!(u16::from(pmu.format), 0x000F);
+        assert_eq!(
+            pmu.get_column_names(),
+            vec![
+                "AREA NORTH_901_V1",
+                "AREA NORTH_901_V2",
+                "AREA NORTH_901_V0",
+                "AREA NORTH_901_VA-VB"
+            ]
+        );
+
+        // Channels the source does not have
+        for json in [
+            VIRTUAL_PMUS.replace(r#""to": "VB""#, r#""to": "VX""#),
+            VIRTUAL_PMUS.replace(r#""Station A": 2.0"#, r#""Station B": 2.0"#),
+            r#"{"idcode": 900, "pmus": []}"#.to_string(),
+        ] {
+            let config = VirtualStreamConfig::from_json(&json).unwrap();
+            assert!(VirtualStream::new(config, &source()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_virtual_data_frames() {
+        let config = VirtualStreamConfig::from_json(VIRTUAL_PMUS).unwrap();
+        let stream = VirtualStream::new(config, &source()).unwrap();
+        let scenario = Scenario {
+            start_soc: Some(1_700_000_000),
+            events: vec![ScenarioEvent::FrequencyRamp {
+                at: 0.0,
+                duration: 1.0,
+                rate: -0.5,
+            }],
+            ..Default::default()
+        };
+        let mut simulator = Simulator::new(source(), scenario);
+        for _ in 0..10 {
+            let source_frame = simulator.next_tick().frames.remove(0);
+            let frame = stream.data_frame(&source_frame).unwrap();
+            assert_eq!(
+                frame_timestamp_micros(&frame),
+                frame_timestamp_micros(&source_frame)
+            );
+
+            let measured = &frame_measurements(&source_frame, &source()).unwrap()[0];
+            let derived = &frame_measurements(&frame, stream.config()).unwrap()[0];
+            let va = measured.phasors[0];
+            // Balanced phases: positive sequence is phase A
+            assert!(
+                (derived.phasors[0].magnitude() - va.magnitude()).abs() < 0.01 * va.magnitude()
+            );
+            assert!((derived.phasors[0].angle() - va.angle()).abs() < 0.01);
+            assert!(derived.phasors[1].magnitude() < 0.01 * va.magnitude());
+            assert!(derived.phasors[2].magnitude() < 0.01 * va.magnitude());
+            assert!((derived.frequency - measured.frequency).abs() < 1e-4);
+            assert!((derived.rocof - measured.rocof).abs() < 1e-4);
+
+            let data = parse_data_frames(&frame, stream.config()).unwrap();
+            let analogs = data.data[0].analog_values(&stream.config().pmu_configs[0]);
+            assert!((analogs[0] - 120.0).abs() < 0.5, "{:?}", analogs);
+        }
+
+        // Frames not of the source configuration
+        assert!(stream.data_frame(&[0xAA, 0x01, 0x00, 0x10]).is_err());
+    }
+}